@@ -0,0 +1,558 @@
+use anyhow::{anyhow, Result};
+use policy_evaluator::callback_requests::{CallbackRequestType, CallbackResponse};
+use serde::Deserialize;
+use serde_json::json;
+use std::{collections::BTreeSet, fs::File, path::Path};
+
+use crate::scaffold::ApiResourceCatalog;
+
+/// The Kubernetes resources served to context-aware policies when running
+/// with the `--replay-context` flag.
+///
+/// The file lists the resources grouped by `apiVersion` and `kind`:
+///
+/// ```yaml
+/// resources:
+///   - apiVersion: v1
+///     kind: Namespace
+///     items:
+///       - metadata:
+///           name: default
+///           labels:
+///             environment: production
+/// ```
+///
+/// The `apiVersion` and `kind` of the items are set automatically when not provided.
+#[derive(Debug, Default, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct ContextFixtures {
+    #[serde(default)]
+    resources: Vec<ResourceFixtures>,
+    /// Used to resolve the API resources without a connection to Kubernetes
+    #[serde(skip)]
+    discovery_snapshot: Option<ApiResourceCatalog>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct ResourceFixtures {
+    api_version: String,
+    kind: String,
+    #[serde(default)]
+    items: Vec<serde_json::Value>,
+}
+
+impl ContextFixtures {
+    pub(crate) fn from_file(path: &Path) -> Result<Self> {
+        let file = File::open(path)
+            .map_err(|e| anyhow!("cannot open context file {}: {}", path.display(), e))?;
+        let mut fixtures: ContextFixtures = serde_yaml::from_reader(file)
+            .map_err(|e| anyhow!("cannot parse context file {}: {}", path.display(), e))?;
+
+        for resource in fixtures.resources.iter_mut() {
+            for item in resource.items.iter_mut() {
+                let item = item.as_object_mut().ok_or_else(|| {
+                    anyhow!(
+                        "invalid {}/{} resource inside of context file: not an object",
+                        resource.api_version,
+                        resource.kind
+                    )
+                })?;
+                item.entry("apiVersion")
+                    .or_insert_with(|| json!(resource.api_version));
+                item.entry("kind").or_insert_with(|| json!(resource.kind));
+            }
+        }
+
+        Ok(fixtures)
+    }
+
+    pub(crate) fn with_discovery_snapshot(
+        mut self,
+        discovery_snapshot: ApiResourceCatalog,
+    ) -> Self {
+        self.discovery_snapshot = Some(discovery_snapshot);
+        self
+    }
+
+    /// Produce the response to the given request. `None` is returned when the request
+    /// cannot be answered using the declared resources, like the ones that are not
+    /// about Kubernetes resources.
+    pub(crate) fn response(
+        &self,
+        request: &CallbackRequestType,
+    ) -> Option<Result<CallbackResponse>> {
+        let response = match request {
+            CallbackRequestType::KubernetesListResourceAll {
+                api_version,
+                kind,
+                label_selector,
+                field_selector,
+            } => self.list(
+                api_version,
+                kind,
+                None,
+                label_selector.as_deref(),
+                field_selector.as_deref(),
+            ),
+            CallbackRequestType::KubernetesListResourceNamespace {
+                api_version,
+                kind,
+                namespace,
+                label_selector,
+                field_selector,
+            } => self.list(
+                api_version,
+                kind,
+                Some(namespace),
+                label_selector.as_deref(),
+                field_selector.as_deref(),
+            ),
+            CallbackRequestType::KubernetesListResourceNamespaces {
+                api_version,
+                kind,
+                namespaces,
+                namespace_selector,
+                label_selector,
+                field_selector,
+            } => self.list_in_namespaces(
+                api_version,
+                kind,
+                namespaces,
+                namespace_selector.as_deref(),
+                label_selector.as_deref(),
+                field_selector.as_deref(),
+            ),
+            CallbackRequestType::KubernetesGetResource {
+                api_version,
+                kind,
+                name,
+                namespace,
+                ..
+            } => self.get(api_version, kind, name, namespace.as_deref()),
+            CallbackRequestType::KubernetesGetResourcePluralName { api_version, kind } => {
+                let discovery_snapshot = self.discovery_snapshot.as_ref()?;
+                discovery_snapshot
+                    .plural_name(api_version, kind)
+                    .map(|plural_name| json!(plural_name))
+                    .ok_or_else(|| {
+                        anyhow!("resource {api_version}/{kind} not found inside of the discovery snapshot")
+                    })
+            }
+            // the declared resources never change
+            CallbackRequestType::HasKubernetesListResourceAllResultChangedSinceInstant {
+                ..
+            } => Ok(json!(false)),
+            // there's nothing to be notified of, the runtime falls back to asking whether
+            // the resources changed
+            CallbackRequestType::SubscribeKubernetesListResourceAllChanges { .. } => Err(anyhow!(
+                "the resources declared inside of the context file never change"
+            )),
+            _ => return None,
+        };
+
+        Some(response.and_then(|value| {
+            Ok(CallbackResponse {
+                payload: serde_json::to_vec(&value)?,
+            })
+        }))
+    }
+
+    fn resources(&self, api_version: &str, kind: &str) -> Result<&[serde_json::Value]> {
+        self.resources
+            .iter()
+            .find(|resource| resource.api_version == api_version && resource.kind == kind)
+            .map(|resource| resource.items.as_slice())
+            .ok_or_else(|| {
+                anyhow!("no {api_version}/{kind} resources are declared inside of the context file")
+            })
+    }
+
+    fn list(
+        &self,
+        api_version: &str,
+        kind: &str,
+        namespace: Option<&str>,
+        label_selector: Option<&str>,
+        field_selector: Option<&str>,
+    ) -> Result<serde_json::Value> {
+        let mut items = Vec::new();
+        for item in self.resources(api_version, kind)? {
+            if namespace.is_some() && metadata_field(item, "namespace") != namespace {
+                continue;
+            }
+            if let Some(selector) = label_selector {
+                if !matches_label_selector(item, selector)? {
+                    continue;
+                }
+            }
+            if let Some(selector) = field_selector {
+                if !matches_field_selector(item, selector)? {
+                    continue;
+                }
+            }
+            items.push(item.clone());
+        }
+
+        Ok(json!({
+            "apiVersion": api_version,
+            "kind": format!("{kind}List"),
+            "metadata": {},
+            "items": items,
+        }))
+    }
+
+    /// List the resources of the given namespaces, plus the ones of the declared
+    /// namespaces matching `namespace_selector`
+    fn list_in_namespaces(
+        &self,
+        api_version: &str,
+        kind: &str,
+        namespaces: &[String],
+        namespace_selector: Option<&str>,
+        label_selector: Option<&str>,
+        field_selector: Option<&str>,
+    ) -> Result<serde_json::Value> {
+        let mut selected_namespaces: BTreeSet<&str> =
+            namespaces.iter().map(String::as_str).collect();
+        if let Some(selector) = namespace_selector {
+            for namespace in self.resources("v1", "Namespace")? {
+                if matches_label_selector(namespace, selector)? {
+                    selected_namespaces.extend(metadata_field(namespace, "name"));
+                }
+            }
+        }
+
+        let mut items = Vec::new();
+        for namespace in selected_namespaces {
+            let list = self.list(
+                api_version,
+                kind,
+                Some(namespace),
+                label_selector,
+                field_selector,
+            )?;
+            if let Some(namespace_items) = list["items"].as_array() {
+                items.extend(namespace_items.iter().cloned());
+            }
+        }
+
+        Ok(json!({
+            "apiVersion": api_version,
+            "kind": format!("{kind}List"),
+            "metadata": {},
+            "items": items,
+        }))
+    }
+
+    fn get(
+        &self,
+        api_version: &str,
+        kind: &str,
+        name: &str,
+        namespace: Option<&str>,
+    ) -> Result<serde_json::Value> {
+        self.resources(api_version, kind)?
+            .iter()
+            .find(|item| {
+                metadata_field(item, "name") == Some(name)
+                    && metadata_field(item, "namespace") == namespace
+            })
+            .cloned()
+            .ok_or_else(|| match namespace {
+                Some(namespace) => anyhow!("{kind} {namespace}/{name} not found"),
+                None => anyhow!("{kind} {name} not found"),
+            })
+    }
+}
+
+fn metadata_field<'a>(item: &'a serde_json::Value, field: &str) -> Option<&'a str> {
+    item.get("metadata")?.get(field)?.as_str()
+}
+
+/// Evaluate an equality based label selector, like `app=nginx,tier!=frontend,!canary`
+fn matches_label_selector(item: &serde_json::Value, selector: &str) -> Result<bool> {
+    let labels = item
+        .get("metadata")
+        .and_then(|metadata| metadata.get("labels"));
+    let label = |key: &str| labels.and_then(|labels| labels.get(key.trim())?.as_str());
+
+    for requirement in selector.split(',').map(str::trim) {
+        if requirement.is_empty() {
+            continue;
+        }
+        if requirement.contains('(') {
+            return Err(anyhow!(
+                "set based label selectors are not supported: {selector}"
+            ));
+        }
+
+        let matches = if let Some((key, value)) = requirement.split_once("!=") {
+            label(key) != Some(value.trim())
+        } else if let Some((key, value)) = requirement
+            .split_once("==")
+            .or_else(|| requirement.split_once('='))
+        {
+            label(key) == Some(value.trim())
+        } else if let Some(key) = requirement.strip_prefix('!') {
+            label(key).is_none()
+        } else {
+            label(requirement).is_some()
+        };
+        if !matches {
+            return Ok(false);
+        }
+    }
+
+    Ok(true)
+}
+
+/// Evaluate a field selector, like `metadata.name=nginx,status.phase!=Running`
+fn matches_field_selector(item: &serde_json::Value, selector: &str) -> Result<bool> {
+    let field = |path: &str| {
+        path.trim()
+            .split('.')
+            .try_fold(item, |value, key| value.get(key))
+            .map(|value| match value {
+                serde_json::Value::String(s) => s.to_owned(),
+                other => other.to_string(),
+            })
+    };
+
+    for requirement in selector.split(',').map(str::trim) {
+        if requirement.is_empty() {
+            continue;
+        }
+
+        let matches = if let Some((path, value)) = requirement.split_once("!=") {
+            field(path).as_deref() != Some(value.trim())
+        } else if let Some((path, value)) = requirement
+            .split_once("==")
+            .or_else(|| requirement.split_once('='))
+        {
+            field(path).as_deref() == Some(value.trim())
+        } else {
+            return Err(anyhow!("invalid field selector: {selector}"));
+        };
+        if !matches {
+            return Ok(false);
+        }
+    }
+
+    Ok(true)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rstest::rstest;
+    use std::io::Write;
+    use tempfile::NamedTempFile;
+
+    fn fixtures() -> ContextFixtures {
+        let contents = r#"
+resources:
+  - apiVersion: v1
+    kind: Namespace
+    items:
+      - metadata:
+          name: default
+          labels:
+            environment: production
+      - metadata:
+          name: kube-system
+  - apiVersion: v1
+    kind: Pod
+    items:
+      - metadata:
+          name: nginx
+          namespace: default
+          labels:
+            app: nginx
+        status:
+          phase: Running
+      - metadata:
+          name: redis
+          namespace: cache
+          labels:
+            app: redis
+        status:
+          phase: Pending
+  - apiVersion: apps/v1
+    kind: Deployment
+"#;
+        let mut file = NamedTempFile::new().unwrap();
+        file.write_all(contents.as_bytes()).unwrap();
+
+        ContextFixtures::from_file(file.path()).unwrap()
+    }
+
+    fn names(response: Option<Result<CallbackResponse>>) -> Vec<String> {
+        let payload = response.unwrap().unwrap().payload;
+        let list: serde_json::Value = serde_json::from_slice(&payload).unwrap();
+        list["items"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|item| item["metadata"]["name"].as_str().unwrap().to_owned())
+            .collect()
+    }
+
+    #[rstest]
+    #[case::all("v1", "Pod", None, None, vec!["nginx", "redis"])]
+    #[case::labels("v1", "Pod", Some("app=nginx"), None, vec!["nginx"])]
+    #[case::not_equal_labels("v1", "Pod", Some("app!=nginx"), None, vec!["redis"])]
+    #[case::label_exists("v1", "Namespace", Some("environment"), None, vec!["default"])]
+    #[case::label_does_not_exist("v1", "Namespace", Some("!environment"), None, vec!["kube-system"])]
+    #[case::fields("v1", "Pod", None, Some("status.phase=Pending"), vec!["redis"])]
+    #[case::labels_and_fields("v1", "Pod", Some("app=nginx"), Some("metadata.name!=nginx"), vec![])]
+    #[case::empty("apps/v1", "Deployment", None, None, vec![])]
+    fn list_all_resources(
+        #[case] api_version: &str,
+        #[case] kind: &str,
+        #[case] label_selector: Option<&str>,
+        #[case] field_selector: Option<&str>,
+        #[case] expected: Vec<&str>,
+    ) {
+        let response = fixtures().response(&CallbackRequestType::KubernetesListResourceAll {
+            api_version: api_version.to_owned(),
+            kind: kind.to_owned(),
+            label_selector: label_selector.map(str::to_owned),
+            field_selector: field_selector.map(str::to_owned),
+        });
+
+        assert_eq!(names(response), expected);
+    }
+
+    #[test]
+    fn list_namespaced_resources() {
+        let response = fixtures().response(&CallbackRequestType::KubernetesListResourceNamespace {
+            api_version: "v1".to_owned(),
+            kind: "Pod".to_owned(),
+            namespace: "cache".to_owned(),
+            label_selector: None,
+            field_selector: None,
+        });
+
+        assert_eq!(names(response), vec!["redis"]);
+    }
+
+    #[rstest]
+    #[case::known("apps/v1", "Deployment", Some("deployments"))]
+    #[case::other_version("apps/v1beta1", "Deployment", Some("deployments"))]
+    #[case::unknown("v1", "Pod", None)]
+    fn plural_name_from_discovery_snapshot(
+        #[case] api_version: &str,
+        #[case] kind: &str,
+        #[case] expected: Option<&str>,
+    ) {
+        let snapshot = r#"{
+  "resources": {
+    "apps|v1|Deployment": {
+      "name": "deployments",
+      "singularName": "deployment",
+      "namespaced": true,
+      "kind": "Deployment",
+      "verbs": ["get", "list", "watch"]
+    }
+  }
+}"#;
+        let mut file = NamedTempFile::new().unwrap();
+        file.write_all(snapshot.as_bytes()).unwrap();
+        let snapshot_fixtures = fixtures()
+            .with_discovery_snapshot(ApiResourceCatalog::from_snapshot(file.path()).unwrap());
+
+        let request = CallbackRequestType::KubernetesGetResourcePluralName {
+            api_version: api_version.to_owned(),
+            kind: kind.to_owned(),
+        };
+        let plural_name = snapshot_fixtures.response(&request).map(|response| {
+            response
+                .ok()
+                .map(|response| serde_json::from_slice::<String>(&response.payload).unwrap())
+        });
+        assert_eq!(plural_name, Some(expected.map(str::to_owned)));
+
+        // without a snapshot, the request is handled by the real callback handler
+        assert!(fixtures().response(&request).is_none());
+    }
+
+    #[rstest]
+    #[case::listed(&["default", "cache"], None, vec!["redis", "nginx"])]
+    #[case::selected(&[], Some("environment=production"), vec!["nginx"])]
+    #[case::listed_and_selected(&["cache"], Some("environment"), vec!["redis", "nginx"])]
+    #[case::no_namespaces(&[], None, vec![])]
+    fn list_resources_across_namespaces(
+        #[case] namespaces: &[&str],
+        #[case] namespace_selector: Option<&str>,
+        #[case] expected: Vec<&str>,
+    ) {
+        let response =
+            fixtures().response(&CallbackRequestType::KubernetesListResourceNamespaces {
+                api_version: "v1".to_owned(),
+                kind: "Pod".to_owned(),
+                namespaces: namespaces.iter().map(|ns| ns.to_string()).collect(),
+                namespace_selector: namespace_selector.map(str::to_owned),
+                label_selector: None,
+                field_selector: None,
+            });
+
+        assert_eq!(names(response), expected);
+    }
+
+    #[rstest]
+    #[case::namespaced("Pod", "nginx", Some("default"), true)]
+    #[case::wrong_namespace("Pod", "nginx", Some("cache"), false)]
+    #[case::cluster_wide("Namespace", "default", None, true)]
+    #[case::not_found("Namespace", "unknown", None, false)]
+    fn get_resource(
+        #[case] kind: &str,
+        #[case] name: &str,
+        #[case] namespace: Option<&str>,
+        #[case] found: bool,
+    ) {
+        let response = fixtures()
+            .response(&CallbackRequestType::KubernetesGetResource {
+                api_version: "v1".to_owned(),
+                kind: kind.to_owned(),
+                name: name.to_owned(),
+                namespace: namespace.map(str::to_owned),
+                disable_cache: false,
+            })
+            .unwrap();
+
+        assert_eq!(response.is_ok(), found);
+        if let Ok(response) = response {
+            let resource: serde_json::Value = serde_json::from_slice(&response.payload).unwrap();
+            // apiVersion and kind are added automatically
+            assert_eq!(resource["apiVersion"], "v1");
+            assert_eq!(resource["kind"], kind);
+        }
+    }
+
+    #[test]
+    fn undeclared_resources() {
+        let response = fixtures()
+            .response(&CallbackRequestType::KubernetesListResourceAll {
+                api_version: "v1".to_owned(),
+                kind: "Service".to_owned(),
+                label_selector: None,
+                field_selector: None,
+            })
+            .unwrap();
+
+        assert!(response
+            .unwrap_err()
+            .to_string()
+            .contains("no v1/Service resources are declared"));
+    }
+
+    #[test]
+    fn other_requests_are_not_handled() {
+        assert!(fixtures()
+            .response(&CallbackRequestType::DNSLookupHost {
+                host: "kubewarden.io".to_owned(),
+            })
+            .is_none());
+    }
+}
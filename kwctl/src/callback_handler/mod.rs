@@ -1,20 +1,33 @@
-use std::path::PathBuf;
+use std::{path::PathBuf, sync::Arc};
 
 use anyhow::Result;
 use policy_evaluator::{callback_requests::CallbackRequest, kube};
 use tokio::sync::{mpsc, oneshot};
 
+pub(crate) mod context;
+pub(crate) mod observer;
 mod proxy;
 
 use crate::{
-    callback_handler::proxy::CallbackHandlerProxy,
+    callback_handler::{context::ContextFixtures, proxy::CallbackHandlerProxy},
     config::{pull_and_run::PullAndRunSettings, HostCapabilitiesMode},
 };
 
 #[derive(Clone)]
 pub(crate) enum ProxyMode {
-    Record { destination: PathBuf },
-    Replay { source: PathBuf },
+    Record {
+        destination: PathBuf,
+    },
+    Replay {
+        source: PathBuf,
+        /// Fail when the policy makes a request that has not been recorded
+        strict: bool,
+    },
+    /// Serve the Kubernetes resources declared by the user, all the other
+    /// requests are handled by the real callback handler
+    Context {
+        fixtures: Arc<ContextFixtures>,
+    },
 }
 
 /// This is an abstraction over the callback_handler provided by the
@@ -22,8 +35,8 @@ pub(crate) enum ProxyMode {
 /// The goal is to allow kwctl to have a proxy handler, that can
 /// record and reply any kind of policy <-> host capability exchange
 pub(crate) enum CallbackHandler {
-    Direct(policy_evaluator::callback_handler::CallbackHandler),
-    Proxy(proxy::CallbackHandlerProxy),
+    Direct(Box<policy_evaluator::callback_handler::CallbackHandler>),
+    Proxy(Box<proxy::CallbackHandlerProxy>),
 }
 
 impl CallbackHandler {
@@ -49,9 +62,15 @@ impl CallbackHandler {
         }
     }
 
-    pub async fn loop_eval(self) {
+    /// Serve the requests of the policy until the handler is shut down. Fails when
+    /// the proxy replays a session in strict mode and the policy made requests that
+    /// have not been recorded
+    pub async fn loop_eval(self) -> Result<()> {
         match self {
-            CallbackHandler::Direct(mut handler) => handler.loop_eval().await,
+            CallbackHandler::Direct(mut handler) => {
+                handler.loop_eval().await;
+                Ok(())
+            }
             CallbackHandler::Proxy(mut handler) => handler.loop_eval().await,
         }
     }
@@ -68,11 +87,12 @@ async fn new_proxy(
         shutdown_channel_rx,
         cfg.sources.clone(),
         cfg.sigstore_trust_root.clone(),
+        cfg.verification_config.clone(),
         kube_client,
     )
     .await?;
 
-    Ok(CallbackHandler::Proxy(proxy))
+    Ok(CallbackHandler::Proxy(Box::new(proxy)))
 }
 
 async fn new_transparent(
@@ -83,12 +103,13 @@ async fn new_transparent(
     let mut callback_handler_builder =
         policy_evaluator::callback_handler::CallbackHandlerBuilder::new(shutdown_channel_rx)
             .registry_config(cfg.sources.clone())
-            .trust_root(cfg.sigstore_trust_root.clone());
+            .trust_root(cfg.sigstore_trust_root.clone())
+            .verification_config(cfg.verification_config.clone());
     if let Some(kc) = kube_client {
         callback_handler_builder = callback_handler_builder.kube_client(kc);
     }
 
     let real_callback_handler = callback_handler_builder.build().await?;
 
-    Ok(CallbackHandler::Direct(real_callback_handler))
+    Ok(CallbackHandler::Direct(Box::new(real_callback_handler)))
 }
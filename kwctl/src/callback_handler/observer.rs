@@ -0,0 +1,113 @@
+use std::{
+    collections::BTreeMap,
+    sync::{Arc, Mutex},
+};
+
+use policy_evaluator::callback_requests::{CallbackRequest, CallbackRequestType};
+use tokio::sync::mpsc;
+use tracing::error;
+
+/// Keeps track of the host capabilities requested by the policies. The requests
+/// are forwarded untouched to the actual callback handler.
+///
+/// The answers given by the host capabilities can change over time: a policy
+/// relying on them might not be deterministic.
+#[derive(Clone, Default)]
+pub(crate) struct HostCapabilitiesObserver {
+    requests: Arc<Mutex<BTreeMap<String, usize>>>,
+}
+
+impl HostCapabilitiesObserver {
+    /// Return a channel that records the requests sent through it, then forwards
+    /// them to `callback_channel`
+    pub fn observe(
+        &self,
+        callback_channel: mpsc::Sender<CallbackRequest>,
+    ) -> mpsc::Sender<CallbackRequest> {
+        let (tx, mut rx) = mpsc::channel::<CallbackRequest>(100);
+        let requests = self.requests.clone();
+
+        tokio::spawn(async move {
+            while let Some(req) = rx.recv().await {
+                *requests
+                    .lock()
+                    .expect("cannot lock the observed host capabilities")
+                    .entry(request_name(&req.request))
+                    .or_default() += 1;
+
+                if callback_channel.send(req).await.is_err() {
+                    error!("cannot forward the request to the callback handler");
+                    break;
+                }
+            }
+        });
+
+        tx
+    }
+
+    /// Return the number of requests made to each host capability, resetting
+    /// the counters
+    pub fn take(&self) -> BTreeMap<String, usize> {
+        std::mem::take(
+            &mut *self
+                .requests
+                .lock()
+                .expect("cannot lock the observed host capabilities"),
+        )
+    }
+}
+
+/// The name of the host capability targeted by the request, like `DNSLookupHost`
+fn request_name(request: &CallbackRequestType) -> String {
+    match serde_json::to_value(request) {
+        Ok(serde_json::Value::Object(object)) => object
+            .keys()
+            .next()
+            .cloned()
+            .unwrap_or_else(|| "unknown".to_owned()),
+        Ok(serde_json::Value::String(name)) => name,
+        _ => "unknown".to_owned(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use policy_evaluator::callback_requests::CallbackResponse;
+    use tokio::sync::oneshot;
+
+    #[tokio::test]
+    async fn record_and_forward_requests() {
+        let (callback_tx, mut callback_rx) = mpsc::channel::<CallbackRequest>(10);
+        let observer = HostCapabilitiesObserver::default();
+        let tx = observer.observe(callback_tx);
+
+        for _ in 0..2 {
+            let (response_tx, _response_rx) =
+                oneshot::channel::<anyhow::Result<CallbackResponse>>();
+            tx.send(CallbackRequest {
+                request: CallbackRequestType::DNSLookupHost {
+                    host: "example.com".to_owned(),
+                },
+                response_channel: response_tx,
+                kubernetes_service_account: None,
+            })
+            .await
+            .unwrap();
+
+            let forwarded = callback_rx.recv().await.expect("request not forwarded");
+            assert_eq!(
+                forwarded.request,
+                CallbackRequestType::DNSLookupHost {
+                    host: "example.com".to_owned(),
+                }
+            );
+        }
+
+        assert_eq!(
+            observer.take(),
+            BTreeMap::from([("DNSLookupHost".to_owned(), 2)])
+        );
+        assert!(observer.take().is_empty());
+    }
+}
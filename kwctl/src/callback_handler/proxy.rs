@@ -4,13 +4,19 @@ use policy_evaluator::{
     callback_handler::CallbackHandlerBuilder,
     callback_requests::{CallbackRequest, CallbackRequestType, CallbackResponse},
     kube,
-    policy_fetcher::{sigstore::trust::ManualTrustRoot, sources::Sources},
+    policy_fetcher::{
+        sigstore::trust::ManualTrustRoot, sources::Sources,
+        verify::config::LatestVerificationConfig,
+    },
 };
 use serde::{Deserialize, Serialize};
-use std::{collections::VecDeque, fs::File, path::PathBuf, sync::Arc};
+use sha2::{Digest, Sha256};
+use std::{collections::HashMap, fs::File, path::PathBuf, sync::Arc};
 use tokio::sync::{mpsc, oneshot};
 use tracing::{error, info, warn};
 
+use crate::errors::{ErrorKind, KwctlError};
+
 #[derive(Debug, Serialize, Deserialize)]
 #[serde(tag = "type")]
 enum Response {
@@ -25,11 +31,141 @@ struct Exchange {
     pub response: Response,
 }
 
+/// The responses recorded for the same request, replayed in the order they
+/// have been recorded
+#[derive(Debug)]
+struct RecordedResponses {
+    responses: Vec<Response>,
+    replayed: usize,
+}
+
+/// The exchanges of a recorded session, indexed by the content hash of their
+/// requests. The requests of the policy are matched regardless of the order in
+/// which they have been recorded.
+///
+/// When the policy makes the same request more times than recorded, the last
+/// recorded response is replayed.
+#[derive(Debug, Default)]
+struct ReplaySession {
+    exchanges: HashMap<String, RecordedResponses>,
+    /// Fail the run when the policy makes a request that has not been recorded
+    strict: bool,
+    /// The requests that have no recorded counterpart
+    unmatched_requests: Vec<CallbackRequestType>,
+}
+
+impl ReplaySession {
+    fn new(exchanges: Vec<Exchange>, strict: bool) -> Result<Self> {
+        let mut session = Self {
+            strict,
+            ..Default::default()
+        };
+        for exchange in exchanges {
+            let request: CallbackRequestType =
+                serde_yaml::from_str(&exchange.request).map_err(|e| {
+                    anyhow!("cannot deserialize recorded request into `CallbackRequestType`: {e}")
+                })?;
+            session
+                .exchanges
+                .entry(request_hash(&request)?)
+                .or_insert_with(|| RecordedResponses {
+                    responses: Vec::new(),
+                    replayed: 0,
+                })
+                .responses
+                .push(exchange.response);
+        }
+        Ok(session)
+    }
+
+    /// Produce the recorded response of the given request
+    fn response(&mut self, request: &CallbackRequestType) -> Result<CallbackResponse> {
+        let recorded = request_hash(request)
+            .ok()
+            .and_then(|hash| self.exchanges.get_mut(&hash));
+        let Some(recorded) = recorded else {
+            if self.strict {
+                error!(?request, "Replay error: the request has not been recorded");
+            } else {
+                warn!(?request, "Replay error: the request has not been recorded");
+            }
+            self.unmatched_requests.push(request.to_owned());
+            return Err(anyhow!(
+                "Replay error: unexpected request, no recorded exchange matches {:?}",
+                request
+            ));
+        };
+
+        let index = recorded.replayed.min(recorded.responses.len() - 1);
+        recorded.replayed += 1;
+        match &recorded.responses[index] {
+            Response::Success { payload } => Ok(CallbackResponse {
+                payload: payload.to_owned().into_bytes(),
+            }),
+            Response::Error { message } => Err(anyhow!("{message}")),
+        }
+    }
+
+    /// Number of recorded responses that have not been replayed
+    fn leftovers(&self) -> usize {
+        self.exchanges
+            .values()
+            .map(|recorded| recorded.responses.len().saturating_sub(recorded.replayed))
+            .sum()
+    }
+
+    /// Ensure all the requests made by the policy have been matched, when running
+    /// in strict mode
+    fn check(&self) -> Result<()> {
+        if !self.strict || self.unmatched_requests.is_empty() {
+            return Ok(());
+        }
+        Err(KwctlError::new(
+            ErrorKind::EvaluationFailed,
+            format!(
+                "the policy made {} host capabilities requests that have not been recorded: {:?}",
+                self.unmatched_requests.len(),
+                self.unmatched_requests
+            ),
+        )
+        .into())
+    }
+}
+
+/// Hash of the canonical JSON representation of the request: the keys of the
+/// objects are sorted, hence the hash doesn't depend on how the request has been
+/// serialized
+fn request_hash(request: &CallbackRequestType) -> Result<String> {
+    let value = serde_json::to_value(request)
+        .map_err(|e| anyhow!("cannot convert request to JSON: {e}"))?;
+    let canonical = serde_json::to_vec(&canonicalize(value))
+        .map_err(|e| anyhow!("cannot serialize request: {e}"))?;
+    Ok(format!("{:x}", Sha256::digest(canonical)))
+}
+
+fn canonicalize(value: serde_json::Value) -> serde_json::Value {
+    match value {
+        serde_json::Value::Object(object) => {
+            let mut entries: Vec<(String, serde_json::Value)> = object
+                .into_iter()
+                .map(|(key, value)| (key, canonicalize(value)))
+                .collect();
+            entries.sort_by(|(a, _), (b, _)| a.cmp(b));
+            serde_json::Value::Object(entries.into_iter().collect())
+        }
+        serde_json::Value::Array(items) => {
+            serde_json::Value::Array(items.into_iter().map(canonicalize).collect())
+        }
+        value => value,
+    }
+}
+
 /// A proxy against a `policy_evaluator::CallbackHandler`
 /// Can record guest requests, save them to file and reply them back
 pub(crate) struct CallbackHandlerProxy {
     sources: Option<Sources>,
     sigstore_trust_root: Option<Arc<ManualTrustRoot<'static>>>,
+    verification_config: Option<LatestVerificationConfig>,
     kube_client: Option<kube::Client>,
     mode: ProxyMode,
 
@@ -54,6 +190,7 @@ impl CallbackHandlerProxy {
         shutdown_channel: oneshot::Receiver<()>,
         sources: Option<Sources>,
         sigstore_trust_root: Option<Arc<ManualTrustRoot<'static>>>,
+        verification_config: Option<LatestVerificationConfig>,
         kube_client: Option<kube::Client>,
     ) -> Result<CallbackHandlerProxy> {
         // the channels used to interact with this callback handler.
@@ -69,6 +206,7 @@ impl CallbackHandlerProxy {
             shutdown_channel,
             sources,
             sigstore_trust_root,
+            verification_config,
             kube_client,
             recorded_exchanges: vec![],
         })
@@ -154,26 +292,109 @@ impl CallbackHandlerProxy {
         }
     }
 
-    pub async fn loop_eval(&mut self) {
+    /// Serve the requests of the policy until the proxy is shut down. Fails when
+    /// replaying in strict mode and the policy made requests that have not been recorded
+    pub async fn loop_eval(&mut self) -> Result<()> {
         match &self.mode {
             ProxyMode::Record { destination: _ } => self.loop_eval_recoder().await,
-            ProxyMode::Replay { source: _ } => self.loop_eval_replay().await,
+            ProxyMode::Replay { .. } => return self.loop_eval_replay().await,
+            ProxyMode::Context { fixtures: _ } => self.loop_eval_context().await,
+        }
+        Ok(())
+    }
+
+    /// Build the real CallbackHandler and spawn the tokio task running it.
+    /// Returns the channel used to send requests to the real CallbackHandler,
+    /// and the one used to shut it down
+    async fn spawn_callback_handler(&self) -> (mpsc::Sender<CallbackRequest>, oneshot::Sender<()>) {
+        // This is a channel used to stop the tokio task that is run
+        // inside of the CallbackHandler
+        let (callback_handler_shutdown_channel_tx, callback_handler_shutdown_channel_rx) =
+            oneshot::channel();
+
+        // Build the real CallbackHandler
+        let mut callback_handler_builder =
+            CallbackHandlerBuilder::new(callback_handler_shutdown_channel_rx)
+                .registry_config(self.sources.clone())
+                .trust_root(self.sigstore_trust_root.clone())
+                .verification_config(self.verification_config.clone());
+        if let Some(kc) = &self.kube_client {
+            callback_handler_builder = callback_handler_builder.kube_client(kc.to_owned());
+        }
+
+        let mut callback_handler = callback_handler_builder
+            .build()
+            .await
+            .expect("cannot build callback handler");
+        let callback_handler_sender = callback_handler.sender_channel();
+
+        // Spawn the tokio task used by the real CallbackHandler
+        tokio::spawn(async move {
+            callback_handler.loop_eval().await;
+        });
+
+        (
+            callback_handler_sender,
+            callback_handler_shutdown_channel_tx,
+        )
+    }
+
+    /// The code used by the handler when running in `context` mode
+    async fn loop_eval_context(&mut self) {
+        let fixtures = if let ProxyMode::Context { fixtures } = &self.mode {
+            fixtures.clone()
+        } else {
+            // this should never happen
+            unreachable!()
+        };
+        let (callback_handler_sender, callback_handler_shutdown_channel_tx) =
+            self.spawn_callback_handler().await;
+
+        loop {
+            tokio::select! {
+                // place the shutdown check before the message evaluation,
+                // as recommended by tokio's documentation about select!
+                _ = &mut self.shutdown_channel => {
+                    if let Err(e) = callback_handler_shutdown_channel_tx.send(()) {
+                        error!(error = ?e, "Cannot shutdown the real callback_handler");
+                    }
+                    return;
+                },
+                maybe_req = self.rx.recv() => {
+                    if let Some(req) = maybe_req {
+                        match fixtures.response(&req.request) {
+                            Some(response) => req
+                                .response_channel
+                                .send(response)
+                                .expect("Cannot send back response to policy"),
+                            // the real CallbackHandler answers using the
+                            // response channel of the original request
+                            None => callback_handler_sender
+                                .send(req)
+                                .await
+                                .expect("cannot forward request to real callback handler"),
+                        }
+                    }
+                }
+            }
         }
     }
 
     /// The code used by the handler when running in `replay` mode
-    async fn loop_eval_replay(&mut self) {
+    async fn loop_eval_replay(&mut self) -> Result<()> {
         // Note: in some cases we use `expect` here to panic at runtime.
         // We want the execution to be aborted if something
         // goes wrong here when dealing with channel message passing,
         // there's no nice way to handle errors here.
 
-        let mut exchanges: VecDeque<Exchange> = if let ProxyMode::Replay { source } = &self.mode {
+        let mut session = if let ProxyMode::Replay { source, strict } = &self.mode {
             let file = File::open(source).unwrap_or_else(|_| {
                 panic!("Cannot open host capabilities interactions file {source:?}")
             });
-            serde_yaml::from_reader(file)
-                .unwrap_or_else(|_| panic!("cannot deserialize contents of {source:?}"))
+            let exchanges: Vec<Exchange> = serde_yaml::from_reader(file)
+                .unwrap_or_else(|_| panic!("cannot deserialize contents of {source:?}"));
+            ReplaySession::new(exchanges, *strict)
+                .unwrap_or_else(|e| panic!("invalid contents of {source:?}: {e}"))
         } else {
             // this should never happen
             unreachable!()
@@ -184,14 +405,15 @@ impl CallbackHandlerProxy {
                 // place the shutdown check before the message evaluation,
                 // as recommended by tokio's documentation about select!
                 _ = &mut self.shutdown_channel => {
-                    if !exchanges.is_empty() {
-                        warn!(leftovers = ?exchanges, "Some of the recorded exchanges have not been replayed");
+                    let leftovers = session.leftovers();
+                    if leftovers > 0 {
+                        warn!(leftovers, "Some of the recorded exchanges have not been replayed");
                     }
-                    return;
+                    return session.check();
                 },
                 maybe_req = self.rx.recv() => {
                     if let Some(req) = maybe_req {
-                        let response = Self::produce_recorded_response(&req, &mut exchanges);
+                        let response = session.response(&req.request);
 
                         req.response_channel.send(response).expect("Cannot send back response to policy");
                     }
@@ -200,59 +422,10 @@ impl CallbackHandlerProxy {
         }
     }
 
-    fn produce_recorded_response(
-        req: &CallbackRequest,
-        exchanges: &mut VecDeque<Exchange>,
-    ) -> Result<CallbackResponse> {
-        match exchanges.pop_front() {
-            None => Err(anyhow!("the list of recorded responses is empty")),
-            Some(exchange) => {
-                let expected_request: CallbackRequestType = serde_yaml::from_str(&exchange.request)
-                    .expect("Cannot deserialize recorded request into `CallbackRequestType`");
-                if expected_request == req.request {
-                    match exchange.response {
-                        Response::Success { payload } => Ok(CallbackResponse {
-                            payload: payload.into_bytes(),
-                        }),
-                        Response::Error { message } => Err(anyhow!("{message}")),
-                    }
-                } else {
-                    Err(anyhow!(
-                        "Replay error: unexpected request. Was expecting {:?}, got {:?} instead",
-                        expected_request,
-                        req.request
-                    ))
-                }
-            }
-        }
-    }
-
     /// The code used by the handler when running in `record` mode
     async fn loop_eval_recoder(&mut self) {
-        // This is a channel used to stop the tokio task that is run
-        // inside of the CallbackHandler
-        let (callback_handler_shutdown_channel_tx, callback_handler_shutdown_channel_rx) =
-            oneshot::channel();
-
-        // Build the real CallbackHandler
-        let mut callback_handler_builder =
-            CallbackHandlerBuilder::new(callback_handler_shutdown_channel_rx)
-                .registry_config(self.sources.clone())
-                .trust_root(self.sigstore_trust_root.clone());
-        if let Some(kc) = &self.kube_client {
-            callback_handler_builder = callback_handler_builder.kube_client(kc.to_owned());
-        }
-
-        let mut callback_handler = callback_handler_builder
-            .build()
-            .await
-            .expect("cannot build callback handler");
-        let callback_handler_sender = callback_handler.sender_channel();
-
-        // Spawn the tokio task used by the real CallbackHandler
-        tokio::spawn(async move {
-            callback_handler.loop_eval().await;
-        });
+        let (callback_handler_sender, callback_handler_shutdown_channel_tx) =
+            self.spawn_callback_handler().await;
 
         // loop of the proxy handler
         loop {
@@ -295,6 +468,7 @@ impl CallbackHandlerProxy {
                         let proxy_req = CallbackRequest {
                             request: req.request,
                             response_channel: response_tx,
+                            kubernetes_service_account: req.kubernetes_service_account,
                         };
 
                         // forward the message to the real CallbackHandler,
@@ -333,112 +507,178 @@ impl CallbackHandlerProxy {
 mod tests {
     use super::*;
 
+    fn exchange(request: &CallbackRequestType, response: Response) -> Exchange {
+        Exchange {
+            request: serde_yaml::to_string(request).expect("cannot serialize request"),
+            response,
+        }
+    }
+
+    fn success(payload: &str) -> Response {
+        Response::Success {
+            payload: payload.to_string(),
+        }
+    }
+
     #[test]
-    fn record_response_no_more_records() {
-        let mut exchanges: VecDeque<Exchange> = VecDeque::new();
-
-        let (response_tx, _) = oneshot::channel::<Result<CallbackResponse>>();
-        let request = CallbackRequest {
-            request: CallbackRequestType::DNSLookupHost {
-                host: "kubewarden.io".to_string(),
-            },
-            response_channel: response_tx,
-        };
+    fn replay_response_no_records() {
+        let mut session = ReplaySession::new(vec![], false).unwrap();
 
-        let response = CallbackHandlerProxy::produce_recorded_response(&request, &mut exchanges);
+        let response = session.response(&CallbackRequestType::DNSLookupHost {
+            host: "kubewarden.io".to_string(),
+        });
         assert!(response.is_err());
         let err = response.unwrap_err();
 
         // we cannot return specialized errors because of the waPC contract
         // hence we have to unfortunately look at the error string
-        assert!(err.to_string().as_str().contains("empty"));
+        assert!(err.to_string().as_str().contains("unexpected request"));
+        assert!(session.check().is_ok());
     }
 
     #[test]
-    fn record_response_unexpected_request() {
-        let expected_request = CallbackRequestType::OciManifestDigest {
+    fn replay_response_unexpected_request() {
+        let recorded_request = CallbackRequestType::OciManifestDigest {
             image: "busybox".to_string(),
         };
-        let expected_exchange = Exchange {
-            request: serde_yaml::to_string(&expected_request)
-                .expect("cannot serialize expected request"),
-            response: Response::Success {
-                payload: "not relevant".to_string(),
-            },
-        };
-
-        let mut exchanges: VecDeque<Exchange> = VecDeque::new();
-        exchanges.push_front(expected_exchange);
-
-        let (response_tx, _) = oneshot::channel::<Result<CallbackResponse>>();
-        let request = CallbackRequest {
-            request: CallbackRequestType::DNSLookupHost {
-                host: "kubewarden.io".to_string(),
-            },
-            response_channel: response_tx,
-        };
-
-        let response = CallbackHandlerProxy::produce_recorded_response(&request, &mut exchanges);
+        let mut session = ReplaySession::new(
+            vec![exchange(&recorded_request, success("not relevant"))],
+            false,
+        )
+        .unwrap();
+
+        let response = session.response(&CallbackRequestType::DNSLookupHost {
+            host: "kubewarden.io".to_string(),
+        });
         assert!(response.is_err());
         let err = response.unwrap_err();
 
         // we cannot return specialized errors because of the waPC contract
         // hence we have to unfortunately look at the error string
         assert!(err.to_string().as_str().contains("unexpected request"));
+        assert_eq!(session.leftovers(), 1);
     }
 
     #[test]
-    fn record_response_replay_successful_response() {
+    fn replay_response_unexpected_request_strict_mode() {
+        let mut session = ReplaySession::new(vec![], true).unwrap();
+
+        let response = session.response(&CallbackRequestType::DNSLookupHost {
+            host: "kubewarden.io".to_string(),
+        });
+        assert!(response.is_err());
+
+        let err = session.check().unwrap_err();
+        assert_eq!(ErrorKind::of(&err), ErrorKind::EvaluationFailed);
+        assert!(err.to_string().contains("kubewarden.io"));
+    }
+
+    #[test]
+    fn replay_response_successful_response() {
         let request = CallbackRequestType::OciManifestDigest {
             image: "busybox".to_string(),
         };
-        let expected_payload = "hello world".to_string();
-        let exchange = Exchange {
-            request: serde_yaml::to_string(&request).expect("cannot serialize request"),
-            response: Response::Success {
-                payload: expected_payload.clone(),
-            },
-        };
+        let mut session =
+            ReplaySession::new(vec![exchange(&request, success("hello world"))], true).unwrap();
 
-        let mut exchanges: VecDeque<Exchange> = VecDeque::new();
-        exchanges.push_front(exchange);
+        let response = session.response(&request).expect("should not be an error");
+        assert_eq!(response.payload, "hello world".as_bytes());
+        assert_eq!(session.leftovers(), 0);
+        assert!(session.check().is_ok());
+    }
 
-        let (response_tx, _) = oneshot::channel::<Result<CallbackResponse>>();
-        let request = CallbackRequest {
-            request,
-            response_channel: response_tx,
+    #[test]
+    fn replay_response_errored_response() {
+        let request = CallbackRequestType::OciManifestDigest {
+            image: "busybox".to_string(),
         };
+        let expected_err_msg = "something went wrong".to_string();
+        let mut session = ReplaySession::new(
+            vec![exchange(
+                &request,
+                Response::Error {
+                    message: expected_err_msg.clone(),
+                },
+            )],
+            false,
+        )
+        .unwrap();
 
-        let response = CallbackHandlerProxy::produce_recorded_response(&request, &mut exchanges)
-            .expect("should not be an error");
-        assert_eq!(response.payload, expected_payload.into_bytes());
+        let response = session.response(&request);
+        assert!(response.is_err());
+        let err = response.unwrap_err();
+        assert_eq!(err.to_string(), expected_err_msg);
     }
 
     #[test]
-    fn record_response_replay_errored_response() {
-        let request = CallbackRequestType::OciManifestDigest {
+    fn replay_response_out_of_order() {
+        let busybox = CallbackRequestType::OciManifestDigest {
             image: "busybox".to_string(),
         };
-        let expected_err_msg = "something went wrong".to_string();
-        let exchange = Exchange {
-            request: serde_yaml::to_string(&request).expect("cannot serialize request"),
-            response: Response::Error {
-                message: expected_err_msg.clone(),
-            },
+        let alpine = CallbackRequestType::OciManifestDigest {
+            image: "alpine".to_string(),
         };
+        let mut session = ReplaySession::new(
+            vec![
+                exchange(&busybox, success("busybox digest")),
+                exchange(&alpine, success("alpine digest")),
+            ],
+            true,
+        )
+        .unwrap();
+
+        assert_eq!(
+            session.response(&alpine).unwrap().payload,
+            "alpine digest".as_bytes()
+        );
+        assert_eq!(
+            session.response(&busybox).unwrap().payload,
+            "busybox digest".as_bytes()
+        );
+        assert!(session.check().is_ok());
+    }
 
-        let mut exchanges: VecDeque<Exchange> = VecDeque::new();
-        exchanges.push_front(exchange);
-
-        let (response_tx, _) = oneshot::channel::<Result<CallbackResponse>>();
-        let request = CallbackRequest {
-            request,
-            response_channel: response_tx,
+    #[test]
+    fn replay_response_same_request_multiple_times() {
+        let request = CallbackRequestType::DNSLookupHost {
+            host: "kubewarden.io".to_string(),
         };
+        let mut session = ReplaySession::new(
+            vec![
+                exchange(&request, success("first")),
+                exchange(&request, success("second")),
+            ],
+            false,
+        )
+        .unwrap();
+
+        // the responses are replayed in the order they have been recorded,
+        // the last one is replayed once all of them have been consumed
+        for expected in ["first", "second", "second"] {
+            assert_eq!(
+                session.response(&request).unwrap().payload,
+                expected.as_bytes()
+            );
+        }
+        assert_eq!(session.leftovers(), 0);
+    }
 
-        let response = CallbackHandlerProxy::produce_recorded_response(&request, &mut exchanges);
-        assert!(response.is_err());
-        let err = response.unwrap_err();
-        assert_eq!(err.to_string(), expected_err_msg);
+    #[test]
+    fn request_hash_does_not_depend_on_the_order_of_the_keys() {
+        let request = CallbackRequestType::OciManifestDigest {
+            image: "busybox".to_string(),
+        };
+        let recorded: CallbackRequestType =
+            serde_yaml::from_str(&serde_yaml::to_string(&request).unwrap()).unwrap();
+
+        assert_eq!(
+            request_hash(&request).unwrap(),
+            request_hash(&recorded).unwrap()
+        );
+        assert_eq!(
+            canonicalize(serde_json::json!({"b": {"d": 1, "c": [{"f": 2, "e": 3}]}, "a": 0}))
+                .to_string(),
+            r#"{"a":0,"b":{"c":[{"e":3,"f":2}],"d":1}}"#
+        );
     }
 }
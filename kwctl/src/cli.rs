@@ -5,6 +5,7 @@ use clap::{
 use lazy_static::lazy_static;
 
 pub(crate) mod bench;
+pub(crate) mod determinism;
 pub(crate) mod run;
 
 lazy_static! {
@@ -104,6 +105,16 @@ fn pull_shared_flags() -> Vec<Arg> {
             .number_of_values(1)
             .value_name("VALUE")
             .help("GitHub repository expected in the certificates generated in CD pipelines"),
+        Arg::new("github-workflow-ref")
+            .long("github-workflow-ref")
+            .number_of_values(1)
+            .value_name("VALUE")
+            .help("Git ref of the GitHub workflow run expected in the certificates generated in CD pipelines (e.g. refs/tags/v1.0.0)"),
+        Arg::new("github-workflow-trigger")
+            .long("github-workflow-trigger")
+            .number_of_values(1)
+            .value_name("VALUE")
+            .help("Event that triggered the GitHub workflow run expected in the certificates generated in CD pipelines (e.g. push)"),
     ]
 }
 
@@ -185,6 +196,16 @@ fn subcommand_verify() -> Command {
             .number_of_values(1)
             .value_name("VALUE")
             .help("GitHub repository expected in the certificates generated in CD pipelines"),
+        Arg::new("github-workflow-ref")
+            .long("github-workflow-ref")
+            .number_of_values(1)
+            .value_name("VALUE")
+            .help("Git ref of the GitHub workflow run expected in the certificates generated in CD pipelines (e.g. refs/tags/v1.0.0)"),
+        Arg::new("github-workflow-trigger")
+            .long("github-workflow-trigger")
+            .number_of_values(1)
+            .value_name("VALUE")
+            .help("Event that triggered the GitHub workflow run expected in the certificates generated in CD pipelines (e.g. push)"),
     ];
     args.sort_by(|a, b| a.get_id().cmp(b.get_id()));
     args.push(
@@ -265,7 +286,7 @@ fn run_args() -> Vec<Arg> {
             .short('r')
             .value_name("PATH")
             .required(true)
-            .help("File containing the Kubernetes admission request object in JSON format"),
+            .help("File containing the Kubernetes admission request object in JSON format. Multiple requests can be provided using JSON Lines or YAML documents"),
         Arg::new("settings-path")
             .long("settings-path")
             .short('s')
@@ -319,6 +340,16 @@ fn run_args() -> Vec<Arg> {
             .number_of_values(1)
             .value_name("VALUE")
             .help("GitHub repository expected in the certificates generated in CD pipelines"),
+        Arg::new("github-workflow-ref")
+            .long("github-workflow-ref")
+            .number_of_values(1)
+            .value_name("VALUE")
+            .help("Git ref of the GitHub workflow run expected in the certificates generated in CD pipelines (e.g. refs/tags/v1.0.0)"),
+        Arg::new("github-workflow-trigger")
+            .long("github-workflow-trigger")
+            .number_of_values(1)
+            .value_name("VALUE")
+            .help("Event that triggered the GitHub workflow run expected in the certificates generated in CD pipelines (e.g. push)"),
         Arg::new("execution-mode")
             .long("execution-mode")
             .short('e')
@@ -351,15 +382,108 @@ Useful to be combined later with '--replay-host-capabilities-interactions' flag"
 the host replays back the answers found inside of the provided file.
 This is useful to test policies in a reproducible way, given no external
 interactions with OCI registries, DNS, Kubernetes are performed."#),
+        Arg::new("strict-replay")
+            .long("strict-replay")
+            .num_args(0)
+            .requires("replay-host-capabilities-interactions")
+            .help("Fail when the policy makes a host capabilities request that is not part of the session given to `--replay-host-capabilities-interactions`. By default the policy receives an error and the evaluation goes on"),
+        Arg::new("replay-context")
+            .long("replay-context")
+            .value_name("FILE")
+            .conflicts_with_all(["record-host-capabilities-interactions", "replay-host-capabilities-interactions"])
+            .long_help(r#"YAML file declaring the Kubernetes resources, grouped by apiVersion
+and kind, served to context-aware policies when they list or get resources.
+No connection to Kubernetes is made. The other host capabilities, like OCI
+and DNS lookups, are not affected."#),
+        Arg::new("discovery-snapshot")
+            .long("discovery-snapshot")
+            .value_name("FILE")
+            .requires("replay-context")
+            .help("Discovery snapshot, generated by `kwctl scaffold discovery-snapshot`, used to resolve the API resources requested by context-aware policies, like their plural names, without a connection to Kubernetes. Requires `--replay-context`"),
+        Arg::new("pull-concurrency")
+            .long("pull-concurrency")
+            .value_name("NUM")
+            .value_parser(clap::builder::RangedU64ValueParser::<usize>::new().range(1..))
+            .default_value("4")
+            .help("Maximum number of policies pulled at the same time"),
+        Arg::new("pull-max-retries")
+            .long("pull-max-retries")
+            .value_name("NUM")
+            .value_parser(clap::value_parser!(u32))
+            .default_value("3")
+            .help("How many times the pull of a policy rate limited by the remote server, or failed because of a transient error like a connection timeout or a HTTP 5xx response, is retried. The delay between two attempts doubles each time, unless the server tells how long to wait"),
+        Arg::new("preopened-dir")
+            .long("preopened-dir")
+            .value_name("GUEST:HOST")
+            .action(ArgAction::Append)
+            .help("Give a WASI policy read-only access to a directory of the host, mounted at the given path. The path must be declared among the `preopenedDirs` of the policy metadata, all the declared ones must be mapped. Can be repeated multiple times"),
      ]
 }
 
 fn subcommand_run() -> Command {
-    let mut args = run_args();
+    let mut args: Vec<Arg> = run_args()
+        .into_iter()
+        .map(|arg| {
+            if arg.get_id() == "request-path" {
+                // the requests are synthesized from the rendered resources, or read
+                // from the files of the request directory
+                arg.required(false)
+                    .required_unless_present_any(["helm-chart", "request-dir"])
+            } else {
+                arg
+            }
+        })
+        .collect();
+    args.push(
+        Arg::new("helm-chart")
+            .long("helm-chart")
+            .value_name("PATH")
+            .conflicts_with_all(["request-path", "raw"])
+            .help("Render the given Helm chart and evaluate the policies against all the resources it defines. Requires the `helm` binary"),
+    );
+    args.push(
+        Arg::new("request-dir")
+            .long("request-dir")
+            .value_name("DIR")
+            .conflicts_with_all(["request-path", "helm-chart"])
+            .help("Directory holding AdmissionReview files in JSON or YAML format. Each request is evaluated, then a summary table is printed. The outcome expected for the requests of a file is declared by the suffix of its name: `<name>.accept.json`, `<name>.mutate.json` or `<name>.reject.json`. The requests of the other files are expected to be allowed. The command fails when an outcome is not the expected one"),
+    );
+    args.push(
+        Arg::new("values")
+            .long("values")
+            .action(ArgAction::Append)
+            .number_of_values(1)
+            .value_name("PATH")
+            .requires("helm-chart")
+            .help("Values file used when rendering the Helm chart. Can be repeated multiple times"),
+    );
+    args.push(
+        Arg::new("output")
+            .long("output")
+            .short('o')
+            .value_name("FORMAT")
+            .value_parser(PossibleValuesParser::new(["json", "policy-report"]))
+            .conflicts_with_all(["request-dir", "simulate-mutations", "raw"])
+            .help("Format of the results of the evaluation of the Helm chart, or of the requests. `policy-report` prints PolicyReport and ClusterPolicyReport resources (wgpolicyk8s.io/v1alpha2) about the objects of the requests. Defaults to `json`"),
+    );
+    args.push(
+        Arg::new("settings-from-manifest")
+            .long("settings-from-manifest")
+            .value_name("PATH")
+            .conflicts_with_all(["settings-path", "settings-json", "allow-context-aware", "execution-mode", "raw"])
+            .help("AdmissionPolicy or ClusterAdmissionPolicy manifest, like the one deployed inside of the cluster. The policy is run with the settings, mode and context-aware resources of the manifest. The module referenced by the manifest is run unless a policy URI is given: in that case, a warning is printed when the two modules differ"),
+    );
+    args.push(
+        Arg::new("simulate-mutations")
+            .long("simulate-mutations")
+            .num_args(0)
+            .conflicts_with_all(["helm-chart", "request-dir", "raw"])
+            .help("Simulate the chain of admission webhooks: the policies allowed to mutate the requests are evaluated first, in the given order, and their patches are applied. The other policies are then evaluated against the mutated requests. A JSON report is printed for each request and policy. The command fails when a request is rejected, is rejected only because of the mutations, or when a patch changes the name, namespace, UID, kind or API version of the object"),
+    );
     args.sort_by(|a, b| a.get_id().cmp(b.get_id()));
     args.push(
         Arg::new("uri_or_sha_prefix_or_yaml_file")
-            .required(true)
+            .required_unless_present("settings-from-manifest")
             .index(1)
             .help("Policy URI, SHA prefix or YAML file containing Kubewarden policy resources. Supported schemes: registry://, https://, file://. If schema is omitted, file:// is assumed, rooted on the current directory.")
     );
@@ -435,6 +559,22 @@ fn subcommand_inspect() -> Command {
             .long("show-signatures")
             .num_args(0)
             .help("Show sigstore signatures"),
+        Arg::new("show-settings-schema")
+            .long("show-settings-schema")
+            .action(ArgAction::SetTrue)
+            .help("Show the JSON Schema of the settings embedded inside of the policy metadata, instead of the metadata"),
+        Arg::new("settings-path")
+            .long("settings-path")
+            .short('s')
+            .value_name("PATH")
+            .requires("show-settings-schema")
+            .conflicts_with("settings-json")
+            .help("File containing the settings to be validated against the settings schema"),
+        Arg::new("settings-json")
+            .long("settings-json")
+            .value_name("VALUE")
+            .requires("show-settings-schema")
+            .help("JSON string containing the settings to be validated against the settings schema"),
     ];
     args.sort_by(|a, b| a.get_id().cmp(b.get_id()));
     args.push(
@@ -512,6 +652,28 @@ fn subcommand_scaffold() -> Command {
             .help("Policy URI or SHA prefix. Supported schemes: registry://, https://, file://. If schema is omitted, file:// is assumed, rooted on the current directory."),
     );
 
+    let mut settings_args = vec![
+        Arg::new("interactive")
+            .long("interactive")
+            .short('i')
+            .action(ArgAction::SetTrue)
+            .help("Ask the value of each setting, validating the answers against the settings schema of the policy"),
+        Arg::new("output")
+            .long("output")
+            .short('o')
+            .value_name("FILE")
+            .help("Path where the settings will be stored. Printed to the standard output when not set"),
+    ];
+    // When scaffolding the settings of a missing policy, we can pull it from a registry
+    settings_args.extend_from_slice(&pull_shared_flags());
+    settings_args.sort_by(|a, b| a.get_id().cmp(b.get_id()));
+    settings_args.push(
+        Arg::new("uri_or_sha_prefix")
+            .required(true)
+            .index(1)
+            .help("Policy URI or SHA prefix. Supported schemes: registry://, https://, file://. If schema is omitted, file:// is assumed, rooted on the current directory."),
+    );
+
     let mut vap_args = vec![
         Arg::new("cel-policy")
             .long("cel-policy")
@@ -552,6 +714,32 @@ fn subcommand_scaffold() -> Command {
     ];
     admission_request_args.sort_by(|a, b| a.get_id().cmp(b.get_id()));
 
+    let mut rbac_args = vec![
+        Arg::new("policies")
+            .long("policies")
+            .short('p')
+            .required(true)
+            .value_name("POLICIES.yml")
+            .help("The policies.yml file used by policy-server"),
+        Arg::new("name")
+            .long("name")
+            .value_name("NAME")
+            .default_value("kubewarden-context-aware-policies")
+            .help("Name of the ClusterRole and of the ClusterRoleBinding"),
+        Arg::new("service-account")
+            .long("service-account")
+            .value_name("NAME")
+            .default_value("policy-server")
+            .help("The ServiceAccount used by policy-server"),
+        Arg::new("namespace")
+            .long("namespace")
+            .short('n')
+            .value_name("NAMESPACE")
+            .default_value("kubewarden")
+            .help("The Namespace of the ServiceAccount used by policy-server"),
+    ];
+    rbac_args.sort_by(|a, b| a.get_id().cmp(b.get_id()));
+
     let mut subcommands = vec![
         Command::new("verification-config")
             .about("Output a default Sigstore verification configuration file"),
@@ -561,12 +749,27 @@ fn subcommand_scaffold() -> Command {
         Command::new("manifest")
             .about("Output a Kubernetes resource manifest")
             .args(manifest_args),
+        Command::new("settings")
+            .about("Output the settings of a policy, generated from the settings schema found inside of its metadata")
+            .args(settings_args),
         Command::new("vap")
             .about("Convert a Kubernetes `ValidatingAdmissionPolicy` into a Kubewarden `ClusterAdmissionPolicy`")
             .args(vap_args),
         Command::new("admission-request")
             .about("Scaffold an AdmissionRequest object")
             .args(admission_request_args),
+        Command::new("rbac")
+            .about("Output the RBAC resources policy-server needs to serve the context aware policies")
+            .args(rbac_args),
+        Command::new("discovery-snapshot")
+            .about("Output a snapshot of the API resources served by the Kubernetes cluster, used to resolve them offline")
+            .arg(
+                Arg::new("output")
+                    .long("output")
+                    .short('o')
+                    .value_name("FILE")
+                    .help("Path where the snapshot will be stored. Printed to the standard output when not set"),
+            ),
     ];
     subcommands.sort_by(|a, b| a.get_name().cmp(b.get_name()));
 
@@ -576,6 +779,336 @@ fn subcommand_scaffold() -> Command {
         .subcommands(subcommands)
 }
 
+fn subcommand_policies() -> Command {
+    let mut verify_store_args = pull_shared_flags();
+    verify_store_args.extend_from_slice(&[
+        Arg::new("output")
+            .long("output")
+            .short('o')
+            .value_name("FORMAT")
+            .value_parser(PossibleValuesParser::new(["table", "json"]))
+            .default_value("table")
+            .help("Output format"),
+        Arg::new("offline")
+            .long("offline")
+            .action(ArgAction::SetTrue)
+            .requires("rekor-public-key-path")
+            .help("Verify the policies without network access, using the Sigstore bundles recorded when they have been pulled and the pinned Rekor public keys"),
+        Arg::new("quarantine")
+            .long("quarantine")
+            .action(ArgAction::SetTrue)
+            .help("Move the policies that fail the verification out of the store, into its quarantine directory"),
+    ]);
+    verify_store_args.sort_by(|a, b| a.get_id().cmp(b.get_id()));
+
+    Command::new("policies")
+        .about("Lists all downloaded policies")
+        .arg(
+            Arg::new("output")
+                .long("output")
+                .short('o')
+                .value_name("FORMAT")
+                .value_parser(PossibleValuesParser::new(["table", "json"]))
+                .default_value("table")
+                .help("Output format. The JSON output includes the provenance recorded when the policies have been pulled"),
+        )
+        .subcommand(
+            Command::new("verify-store")
+                .about("Verify again all the downloaded policies, using the current verification config")
+                .long_about(
+                    r#"Verify again all the downloaded policies, using the current verification config.
+
+The signatures of the policies pulled from OCI registries are verified against the given verification config and Sigstore trust root, then the checksum of the local WebAssembly modules is compared with the verified one.
+The policies that are not pulled from OCI registries are skipped.
+
+With --offline, no network access is made: the Sigstore bundles recorded when the policies have been pulled are verified against the pinned Rekor public keys. Policies without recorded bundles fail the verification.
+
+The command fails when at least one policy does not satisfy the verification config."#,
+                )
+                .args(verify_store_args),
+        )
+        .subcommand(
+            Command::new("prune")
+                .about("Remove the downloaded policies that are no longer used")
+                .long_about(
+                    r#"Remove the downloaded policies that are no longer used.
+
+kwctl records when the policies of the store are used, for example by `kwctl run`, `kwctl inspect` or `kwctl scaffold`. With --unused-for, the policies that have not been used for the given number of days are removed. The policies that have never been used are considered unused since they have been pulled.
+
+With --keep-referenced-by, the policies referenced by the `module` fields of the given YAML files are kept. These can be the policies.yml files of policy-server or the manifests generated by `kwctl scaffold manifest`.
+
+When both are given, only the policies satisfying both the conditions are removed. The policies to be removed are listed, and a confirmation is asked before removing them."#,
+                )
+                .args([
+                    Arg::new("dry-run")
+                        .long("dry-run")
+                        .action(ArgAction::SetTrue)
+                        .help("List the policies that would be removed, without removing them"),
+                    Arg::new("keep-referenced-by")
+                        .long("keep-referenced-by")
+                        .value_name("FILE")
+                        .action(ArgAction::Append)
+                        .required_unless_present("unused-for")
+                        .help("Keep the policies referenced by the `module` fields of this YAML file. Can be repeated"),
+                    Arg::new("output")
+                        .long("output")
+                        .short('o')
+                        .value_name("FORMAT")
+                        .value_parser(PossibleValuesParser::new(["table", "json"]))
+                        .default_value("table")
+                        .help("Output format"),
+                    Arg::new("unused-for")
+                        .long("unused-for")
+                        .value_name("DAYS")
+                        .value_parser(clap::value_parser!(u64))
+                        .help("Remove the policies that have not been used for more than the given number of days"),
+                    Arg::new("yes")
+                        .long("yes")
+                        .short('y')
+                        .action(ArgAction::SetTrue)
+                        .help("Do not ask for confirmation before removing the policies"),
+                ]),
+        )
+}
+
+fn subcommand_store() -> Command {
+    let mut import_args = vec![
+        Arg::new("docker-config-json-path")
+            .long("docker-config-json-path")
+            .value_name("DOCKER_CONFIG")
+            .help("Path to a directory containing the Docker 'config.json' file. Can be used to indicate registry authentication details"),
+        Arg::new("sources-path")
+            .long("sources-path")
+            .value_name("PATH")
+            .help("YAML file holding source information (https, registry insecure hosts, custom CA's...)"),
+        Arg::new("namespace")
+            .long("namespace")
+            .short('n')
+            .value_name("NAMESPACE")
+            .default_value("kubewarden")
+            .help("Namespace of the policy-server Pod"),
+        Arg::new("pod")
+            .long("pod")
+            .value_name("NAME")
+            .required(true)
+            .help("Name of the policy-server Pod"),
+        Arg::new("port")
+            .long("port")
+            .value_name("PORT")
+            .value_parser(clap::value_parser!(u16))
+            .default_value("3000")
+            .help("Port where policy-server serves its API"),
+        Arg::new("insecure")
+            .long("insecure")
+            .action(ArgAction::SetTrue)
+            .help("Reach the API of policy-server over plain HTTP, instead of HTTPS"),
+        Arg::new("output-dir")
+            .long("output-dir")
+            .short('o')
+            .value_name("PATH")
+            .help("Directory where the policies.yml file of policy-server and the settings of the policies are written. Defaults to <NAMESPACE>-<POD>"),
+    ];
+    import_args.sort_by(|a, b| a.get_id().cmp(b.get_id()));
+
+    Command::new("store")
+        .about("Manage the local store of the policies")
+        .subcommand_required(true)
+        .subcommand(
+            Command::new("import-from-server")
+                .about("Download into the store the policies loaded by a running policy-server")
+                .long_about(
+                    r#"Download into the store the policies loaded by a running policy-server.
+
+The policies.yml file is read from the ConfigMaps mounted by the policy-server Pod, the modules of the policies are then pulled into the store. The digests of the pulled modules are compared with the ones reported by the /policies endpoint of policy-server, which is reached through the proxy of the Kubernetes API server.
+
+The policies.yml file, together with the settings of each policy, is written inside of the output directory. The settings can be given to `kwctl run --settings-path` to reproduce locally the behavior of the server.
+
+The command fails when a module doesn't match the one loaded by policy-server. This happens when the tag of the policy has been moved after policy-server pulled it."#,
+                )
+                .args(import_args),
+        )
+}
+
+fn subcommand_diff() -> Command {
+    let mut args = vec![
+        Arg::new("output")
+            .long("output")
+            .short('o')
+            .value_name("FORMAT")
+            .value_parser(PossibleValuesParser::new(["pretty", "json"]))
+            .default_value("pretty")
+            .help("Output format"),
+        Arg::new("fail-on-privilege-expansion")
+            .long("fail-on-privilege-expansion")
+            .num_args(0)
+            .help("Exit with an error when the new version of the policy requires more privileges than the old one"),
+    ];
+    args.sort_by(|a, b| a.get_id().cmp(b.get_id()));
+    args.push(
+        Arg::new("old_uri_or_sha_prefix")
+            .required(true)
+            .index(1)
+            .help("URI or SHA prefix of the old version of the policy. Supported schemes: registry://, https://, file://. If schema is omitted, file:// is assumed, rooted on the current directory."),
+    );
+    args.push(
+        Arg::new("new_uri_or_sha_prefix")
+            .required(true)
+            .index(2)
+            .help("URI or SHA prefix of the new version of the policy. Supported schemes: registry://, https://, file://. If schema is omitted, file:// is assumed, rooted on the current directory."),
+    );
+
+    Command::new("diff")
+        .about("Show the changes to the Kubernetes resources and requests a policy has access to, between two versions of the policy")
+        .args(args)
+}
+
+fn registry_args() -> Vec<Arg> {
+    vec![
+        Arg::new("sources-path")
+            .long("sources-path")
+            .value_name("PATH")
+            .help("YAML file holding source information (https, registry insecure hosts, custom CA's...)"),
+        Arg::new("docker-config-json-path")
+            .long("docker-config-json-path")
+            .value_name("PATH")
+            .help("Path to a directory containing the Docker 'config.json' file. Can be used to indicate registry authentication details"),
+    ]
+}
+
+fn subcommand_registry() -> Command {
+    let mut rm_args = registry_args();
+    rm_args.push(
+        Arg::new("yes")
+            .long("yes")
+            .short('y')
+            .action(ArgAction::SetTrue)
+            .help("Do not ask for confirmation before deleting the policy"),
+    );
+    rm_args.sort_by(|a, b| a.get_id().cmp(b.get_id()));
+    rm_args.push(
+        Arg::new("uri")
+            .required(true)
+            .index(1)
+            .help("Policy URI. Supported schemes: registry://"),
+    );
+
+    let mut tags_args = registry_args();
+    tags_args.push(
+        Arg::new("output")
+            .long("output")
+            .short('o')
+            .value_name("FORMAT")
+            .value_parser(PossibleValuesParser::new(["text", "json"]))
+            .default_value("text")
+            .help("Output format"),
+    );
+    tags_args.sort_by(|a, b| a.get_id().cmp(b.get_id()));
+    tags_args.push(
+        Arg::new("uri")
+            .required(true)
+            .index(1)
+            .help("Policy URI, the tag of the URI is ignored. Supported schemes: registry://"),
+    );
+
+    let mut tag_args = registry_args();
+    tag_args.sort_by(|a, b| a.get_id().cmp(b.get_id()));
+    tag_args.push(
+        Arg::new("uri")
+            .required(true)
+            .index(1)
+            .help("Policy URI. Supported schemes: registry://"),
+    );
+    tag_args.push(
+        Arg::new("new-tag")
+            .required(true)
+            .index(2)
+            .help("The new tag of the policy"),
+    );
+
+    Command::new("registry")
+        .about("Manage the policies stored inside of OCI registries")
+        .subcommand_required(true)
+        .subcommand(
+            Command::new("rm")
+                .about("Delete a policy from an OCI registry")
+                .long_about(
+                    r#"Delete a policy from an OCI registry.
+
+The manifest of the policy is deleted, which removes all the tags pointing to it, not only the one used to reference the policy. A confirmation is asked before deleting the policy, unless the `--yes` flag is used.
+
+Some registries do not allow to delete artifacts, or allow that only to some users."#,
+                )
+                .args(rm_args),
+        )
+        .subcommand(
+            Command::new("tag")
+                .about("Add a tag to a policy stored inside of an OCI registry")
+                .long_about(
+                    r#"Add a tag to a policy stored inside of an OCI registry.
+
+The new tag is created inside of the repository of the policy and points to the same manifest. Existing tags are moved to the policy."#,
+                )
+                .args(tag_args),
+        )
+        .subcommand(
+            Command::new("tags")
+                .about("List the tags of a policy repository")
+                .args(tags_args),
+        )
+}
+
+fn subcommand_verification_config() -> Command {
+    let mut push_args = registry_args();
+    push_args.sort_by(|a, b| a.get_id().cmp(b.get_id()));
+    push_args.push(
+        Arg::new("path")
+            .required(true)
+            .index(1)
+            .help("YAML file holding the verification config"),
+    );
+    push_args.push(
+        Arg::new("uri")
+            .required(true)
+            .index(2)
+            .help("Verification config URI. Supported schemes: registry://"),
+    );
+
+    let mut pull_args = registry_args();
+    pull_args.push(
+        Arg::new("output")
+            .long("output")
+            .short('o')
+            .value_name("PATH")
+            .help("File the verification config is written to. The config is printed on STDOUT when not provided"),
+    );
+    pull_args.sort_by(|a, b| a.get_id().cmp(b.get_id()));
+    pull_args.push(
+        Arg::new("uri")
+            .required(true)
+            .index(1)
+            .help("Verification config URI. Supported schemes: registry://"),
+    );
+
+    Command::new("verification-config")
+        .about("Distribute verification configs using OCI registries")
+        .subcommand_required(true)
+        .subcommand(
+            Command::new("pull")
+                .about("Pull a verification config from an OCI registry")
+                .args(pull_args),
+        )
+        .subcommand(
+            Command::new("push")
+                .about("Push a verification config to an OCI registry")
+                .long_about(
+                    r#"Push a verification config to an OCI registry.
+
+The config is validated and stored as an OCI artifact, allowing to distribute the trust policy the same way the policies are distributed. The config can then be retrieved with `kwctl verification-config pull`."#,
+                )
+                .args(push_args),
+        )
+}
+
 fn subcommand_digest() -> Command {
     let mut args = vec![
         Arg::new("sources-path")
@@ -621,6 +1154,27 @@ fn subcommand_bench() -> Command {
         Arg::new("dump_results_to_disk")
             .long("dump-results-to-disk")
             .help("Puts results in target/tiny-bench/label/.. if target can be found. used for comparing previous runs"),
+        Arg::new("iterations")
+            .long("iterations")
+            .value_name("NUM")
+            .value_parser(clap::builder::RangedU64ValueParser::<usize>::new().range(1..))
+            .conflicts_with_all(["measurement_time", "num_resamples", "num_samples", "warm_up_time", "dump_results_to_disk"])
+            .help("Evaluate the policy the given number of times, then print a report with the latency percentiles, the number of failed evaluations, the instantiation time and the peak memory of the kwctl process, instead of performing the statistical analysis"),
+        Arg::new("warmup_iterations")
+            .long("warmup-iterations")
+            .value_name("NUM")
+            .value_parser(clap::value_parser!(usize))
+            .default_value("10")
+            .requires("iterations")
+            .help("Number of evaluations performed before measuring the iterations"),
+        Arg::new("output")
+            .long("output")
+            .short('o')
+            .value_name("FORMAT")
+            .value_parser(PossibleValuesParser::new(["table", "json"]))
+            .default_value("table")
+            .requires("iterations")
+            .help("Format of the report printed when using --iterations"),
     ];
     let mut run_args = run_args();
     args.append(&mut run_args);
@@ -650,9 +1204,64 @@ fn subcommand_bench() -> Command {
         )
 }
 
+fn subcommand_check_determinism() -> Command {
+    let mut args = vec![
+        Arg::new("repeat")
+            .long("repeat")
+            .value_name("NUM")
+            .value_parser(clap::builder::RangedU64ValueParser::<usize>::new().range(2..))
+            .default_value("10")
+            .help("Number of times the same request is evaluated"),
+        Arg::new("pin-clock")
+            .long("pin-clock")
+            .value_name("RFC3339_TIMESTAMP")
+            .help("Pin the clock of OPA and Gatekeeper policies to the given instant, e.g. `2024-01-01T00:00:00Z`. The `time.now_ns` builtin always returns it"),
+        Arg::new("output")
+            .long("output")
+            .short('o')
+            .value_name("FORMAT")
+            .value_parser(PossibleValuesParser::new(["table", "json"]))
+            .default_value("table")
+            .help("Format of the report"),
+    ];
+    let mut run_args = run_args();
+    args.append(&mut run_args);
+    args.sort_by(|a, b| a.get_id().cmp(b.get_id()));
+    args.push(
+        Arg::new("uri_or_sha_prefix_or_yaml_file")
+            .required(true)
+            .index(1)
+            .help("Policy URI, SHA prefix or YAML file containing Kubewarden policy resources. Supported schemes: registry://, https://, file://. If schema is omitted, file:// is assumed, rooted on the current directory.")
+    );
+
+    Command::new("check-determinism")
+        .about("Looks for non-deterministic Kubewarden policies")
+        .long_about(format!(
+            r#"Looks for non-deterministic Kubewarden policies.
+
+The same request is evaluated multiple times, the command fails when the policy does not always produce the same verdict and patch. The report lists the sources of non-determinism the policy can access: the clock, the random number generator and the host capabilities. They are detected by looking at the WebAssembly module and by recording the host capabilities requested during the evaluations.
+
+{}"#,
+            RUN_AND_BENCH_COMMON_LONG_ABOUT
+        ))
+        .args(args)
+        .group(
+            // these flags cannot be used at the same time
+            ArgGroup::new("host-capabilities-proxy").args([
+                "record-host-capabilities-interactions",
+                "replay-host-capabilities-interactions",
+            ]),
+        )
+}
+
 fn subcommand_save() -> Command {
     Command::new("save")
         .about("save policies to a tar.gz file")
+        .long_about(
+            r#"save policies to a tar.gz file.
+
+The tarball holds the WebAssembly modules of the policies together with their provenance: where they have been pulled from and whether their signatures have been verified. It can be loaded on an air-gapped machine with `kwctl load`."#,
+        )
         .arg(
             Arg::new("output")
                 .long("output")
@@ -670,21 +1279,42 @@ fn subcommand_save() -> Command {
 }
 
 fn subcommand_docs() -> Command {
+    let mut args = vec![
+        Arg::new("output")
+            .long("output")
+            .short('o')
+            .required_unless_present("uri_or_sha_prefix")
+            .value_name("FILE")
+            .help("path where the documentation file will be stored. The documentation of a policy is printed to the standard output when not set"),
+        Arg::new("format")
+            .long("format")
+            .value_name("FORMAT")
+            .value_parser(PossibleValuesParser::new(["markdown", "html"]))
+            .default_value("markdown")
+            .help("Format of the documentation of the policy"),
+    ];
+    // When documenting a missing policy, we can pull it from a registry
+    args.extend_from_slice(&pull_shared_flags());
+    args.sort_by(|a, b| a.get_id().cmp(b.get_id()));
+    args.push(
+        Arg::new("uri_or_sha_prefix")
+            .index(1)
+            .help("Policy URI or SHA prefix. When set, the documentation of the policy is generated from its metadata. Supported schemes: registry://, https://, file://. If schema is omitted, file:// is assumed, rooted on the current directory."),
+    );
+
     Command::new("docs")
-        .about("Generates the markdown documentation for kwctl commands")
-        .arg(
-            Arg::new("output")
-                .long("output")
-                .short('o')
-                .required(true)
-                .value_name("FILE")
-                .help("path where the documentation file will be stored"),
+        .about("Generates the markdown documentation for kwctl commands, or the documentation of a policy")
+        .long_about(
+            r#"Generates the markdown documentation for kwctl commands, or the documentation of a policy.
+
+When a policy is given, its documentation page is generated from its metadata: title, description, usage, rules, settings and the Kubernetes resources it accesses at evaluation time. The page can be rendered as Markdown or HTML, to publish a catalog of policies."#,
         )
+        .args(args)
 }
 
 pub fn build_cli() -> Command {
     let mut subcommands = vec![
-        Command::new("policies").about("Lists all downloaded policies"),
+        subcommand_policies(),
         Command::new("info").about("Display system information"),
         Command::new("rm")
             .about("Removes a Kubewarden policy from the store")
@@ -713,6 +1343,11 @@ pub fn build_cli() -> Command {
             ),
         Command::new("load")
             .about("load policies from a tar.gz file")
+            .long_about(
+                r#"load policies from a tar.gz file.
+
+The policies and their provenance are written inside of the local store. The digests of the policies are checked against the ones recorded by `kwctl save`, the command fails when a policy has been altered."#,
+            )
             .arg(
                 Arg::new("input")
                     .long("input")
@@ -726,10 +1361,15 @@ pub fn build_cli() -> Command {
         subcommand_annotate(),
         subcommand_inspect(),
         subcommand_scaffold(),
+        subcommand_diff(),
         subcommand_digest(),
         subcommand_bench(),
+        subcommand_check_determinism(),
         subcommand_save(),
         subcommand_docs(),
+        subcommand_store(),
+        subcommand_registry(),
+        subcommand_verification_config(),
     ];
     subcommands.sort_by(|a, b| a.get_name().cmp(b.get_name()));
 
@@ -744,6 +1384,14 @@ pub fn build_cli() -> Command {
                 .num_args(0)
                 .help("Increase verbosity"),
         )
+        .arg(
+            Arg::new("error-format")
+                .long("error-format")
+                .value_name("FORMAT")
+                .value_parser(PossibleValuesParser::new(["text", "json"]))
+                .default_value("text")
+                .help("Format of the errors printed on the standard error. The JSON format prints an envelope holding the kind of the error and the exit code"),
+        )
         .arg(
             Arg::new("no-color")
                 .long("no-color")
@@ -2,20 +2,33 @@ use std::time::Duration;
 
 use anyhow::{anyhow, Result};
 use clap::ArgMatches;
+use tracing::warn;
 
-use crate::config::pull_and_run::{parse_policy_definitions, parse_pull_and_run_settings};
+use crate::{
+    command::bench::BenchmarkMode,
+    config::pull_and_run::{parse_policy_definitions, parse_pull_and_run_settings},
+    policies::OutputFormat,
+};
 
 pub(crate) async fn exec(matches: &ArgMatches) -> Result<()> {
     let policy_definitions = parse_policy_definitions(matches)?;
     let pull_and_run_settings = parse_pull_and_run_settings(matches, &policy_definitions).await?;
-    let benchmark_config = create_benchmark_config(matches)?;
+    if pull_and_run_settings.requests.len() > 1 {
+        warn!("Multiple requests defined inside of the request file. Only the first one will be used by the benchmark.");
+    }
+    let benchmark_mode = match matches.get_one::<usize>("iterations") {
+        Some(iterations) => BenchmarkMode::Iterations {
+            warmup_iterations: *matches.get_one::<usize>("warmup_iterations").unwrap(),
+            iterations: *iterations,
+            output: match matches.get_one::<String>("output").map(String::as_str) {
+                Some("json") => OutputFormat::Json,
+                _ => OutputFormat::Table,
+            },
+        },
+        None => BenchmarkMode::Statistical(create_benchmark_config(matches)?),
+    };
 
-    crate::command::bench::exec(
-        &policy_definitions,
-        &pull_and_run_settings,
-        &benchmark_config,
-    )
-    .await
+    crate::command::bench::exec(&policy_definitions, &pull_and_run_settings, &benchmark_mode).await
 }
 
 fn create_benchmark_config(matches: &ArgMatches) -> Result<tiny_bench::BenchmarkConfig> {
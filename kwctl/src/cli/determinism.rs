@@ -0,0 +1,60 @@
+use std::time::SystemTime;
+
+use anyhow::{anyhow, Result};
+use clap::ArgMatches;
+use time::{format_description::well_known::Rfc3339, OffsetDateTime};
+use tracing::warn;
+
+use crate::{
+    callback_handler::observer::HostCapabilitiesObserver,
+    config::pull_and_run::{parse_policy_definitions, parse_pull_and_run_settings},
+    policies::OutputFormat,
+};
+
+pub(crate) async fn exec(matches: &ArgMatches) -> Result<()> {
+    let policy_definitions = parse_policy_definitions(matches)?;
+    let mut pull_and_run_settings =
+        parse_pull_and_run_settings(matches, &policy_definitions).await?;
+    if pull_and_run_settings.requests.len() > 1 {
+        warn!("Multiple requests defined inside of the request file. Only the first one will be used to check the determinism of the policies.");
+    }
+    if let Some(pin_clock) = matches.get_one::<String>("pin-clock") {
+        pull_and_run_settings.pinned_clock = Some(parse_pinned_clock(pin_clock)?);
+    }
+    pull_and_run_settings.host_capabilities_observer = Some(HostCapabilitiesObserver::default());
+
+    let repeat = *matches.get_one::<usize>("repeat").unwrap();
+    let output = match matches.get_one::<String>("output").map(String::as_str) {
+        Some("json") => OutputFormat::Json,
+        _ => OutputFormat::Table,
+    };
+
+    crate::command::determinism::exec(&policy_definitions, &pull_and_run_settings, repeat, &output)
+        .await
+}
+
+fn parse_pinned_clock(value: &str) -> Result<SystemTime> {
+    OffsetDateTime::parse(value, &Rfc3339)
+        .map(SystemTime::from)
+        .map_err(|e| {
+            anyhow!(
+                "Cannot convert 'pin-clock' to a RFC 3339 timestamp: {:?}",
+                e
+            )
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::{Duration, UNIX_EPOCH};
+
+    #[test]
+    fn parse_rfc3339_pinned_clock() {
+        assert_eq!(
+            parse_pinned_clock("2023-11-14T22:13:20Z").unwrap(),
+            UNIX_EPOCH + Duration::from_secs(1_700_000_000)
+        );
+        assert!(parse_pinned_clock("yesterday").is_err());
+    }
+}
@@ -1,11 +1,81 @@
+use std::path::PathBuf;
+
 use anyhow::Result;
 use clap::ArgMatches;
 
-use crate::config::pull_and_run::{parse_policy_definitions, parse_pull_and_run_settings};
+use crate::{
+    command::run::ReportFormat,
+    config::pull_and_run::{
+        parse_manifest_policy_definition, parse_policy_definitions, parse_pull_and_run_settings,
+    },
+};
 
 pub(crate) async fn exec(matches: &ArgMatches) -> Result<()> {
-    let policy_definitions = parse_policy_definitions(matches)?;
+    let (policy_definitions, deployed_module) =
+        match matches.get_one::<String>("settings-from-manifest") {
+            Some(manifest_path) => {
+                let (policy_definition, deployed_module) =
+                    parse_manifest_policy_definition(matches, manifest_path)?;
+                (vec![policy_definition], Some(deployed_module))
+            }
+            None => (parse_policy_definitions(matches)?, None),
+        };
     let pull_and_run_settings = parse_pull_and_run_settings(matches, &policy_definitions).await?;
 
+    if let Some(deployed_module) = deployed_module {
+        for local_module in policy_definitions.iter().flat_map(|policy| policy.uris()) {
+            crate::command::run::manifest::compare_with_deployed_module(
+                &local_module,
+                &deployed_module,
+                pull_and_run_settings.sources.as_ref(),
+            )
+            .await?;
+        }
+    }
+
+    let report_format = match matches.get_one::<String>("output").map(String::as_str) {
+        Some("policy-report") => ReportFormat::PolicyReport,
+        _ => ReportFormat::Json,
+    };
+
+    if let Some(chart) = matches.get_one::<String>("helm-chart") {
+        let values: Vec<PathBuf> = matches
+            .get_many::<String>("values")
+            .map(|values| values.map(PathBuf::from).collect())
+            .unwrap_or_default();
+        return crate::command::run::exec_helm_chart(
+            &policy_definitions,
+            pull_and_run_settings,
+            &PathBuf::from(chart),
+            &values,
+            report_format,
+        )
+        .await;
+    }
+
+    if let Some(dir) = matches.get_one::<String>("request-dir") {
+        return crate::command::run::exec_request_dir(
+            &policy_definitions,
+            pull_and_run_settings,
+            &PathBuf::from(dir),
+        )
+        .await;
+    }
+
+    if report_format == ReportFormat::PolicyReport {
+        return crate::command::run::exec_policy_report(&policy_definitions, pull_and_run_settings)
+            .await;
+    }
+
+    if matches.get_flag("simulate-mutations") {
+        return crate::command::run::simulation::exec(&policy_definitions, pull_and_run_settings)
+            .await;
+    }
+
+    if pull_and_run_settings.requests.len() > 1 {
+        return crate::command::run::exec_requests(&policy_definitions, pull_and_run_settings)
+            .await;
+    }
+
     crate::command::run::exec(&policy_definitions, &pull_and_run_settings).await
 }
@@ -1,2 +1,3 @@
 pub(crate) mod bench;
+pub(crate) mod determinism;
 pub(crate) mod run;
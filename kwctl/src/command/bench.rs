@@ -1,28 +1,97 @@
+use std::time::{Duration, Instant};
+
 use anyhow::{anyhow, Result};
+use policy_evaluator::capability_usage::{self, PolicyCapabilityUsage};
+use prettytable::{format, row, Table};
+use serde::Serialize;
 use tiny_bench::{bench_with_configuration_labeled, BenchmarkConfig};
-use tracing::{debug, error};
+use tracing::{debug, error, warn};
 
 use crate::{
     command::run::{evaluator::Evaluator, local_data::LocalData},
     config::{policy_definition::PolicyDefinition, pull_and_run::PullAndRunSettings},
+    policies::OutputFormat,
 };
 
+/// How the policies are benchmarked
+pub(crate) enum BenchmarkMode {
+    /// The statistical analysis performed by tiny-bench, whose results are printed
+    /// while benchmarking
+    Statistical(BenchmarkConfig),
+    /// A fixed number of evaluations, summarized by a report printed at the end
+    Iterations {
+        warmup_iterations: usize,
+        iterations: usize,
+        output: OutputFormat,
+    },
+}
+
+/// The latencies measured while repeating an operation
+#[derive(Serialize, Debug, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct LatencyReport {
+    pub(crate) p50_us: u64,
+    pub(crate) p95_us: u64,
+    pub(crate) p99_us: u64,
+    pub(crate) max_us: u64,
+}
+
+/// The outcome of the benchmark of one policy
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct BenchReport {
+    policy: String,
+    iterations: usize,
+    /// Not available for policy groups, whose members are instantiated at each
+    /// evaluation
+    instantiation_us: Option<u64>,
+    validate_settings: LatencyReport,
+    validate: LatencyReport,
+    /// The number of measured settings validations that failed
+    validate_settings_errors: usize,
+    /// The number of measured evaluations that failed, because of an error of the
+    /// policy or of its runtime
+    validate_errors: usize,
+    /// The peak resident memory of the whole kwctl process (`VmHWM`), once the policy
+    /// has been benchmarked. The value is cumulative: it also accounts for the policies
+    /// benchmarked before this one. Available only on Linux
+    process_peak_memory_bytes: Option<u64>,
+    /// The host capabilities invoked by the measured evaluations, one entry for each
+    /// evaluated policy, hence for each member of a policy group
+    capability_usage: Vec<PolicyCapabilityUsage>,
+}
+
 pub(crate) async fn exec(
     policy_definitions: &[PolicyDefinition],
     pull_and_run_settings: &PullAndRunSettings,
-    benchmark_config: &BenchmarkConfig,
+    benchmark_mode: &BenchmarkMode,
 ) -> Result<()> {
     let local_data = LocalData::new(policy_definitions, pull_and_run_settings).await?;
+    if matches!(benchmark_mode, BenchmarkMode::Iterations { .. }) {
+        // all the evaluations made while benchmarking are taken into account
+        capability_usage::enable(Duration::MAX);
+    }
 
+    let mut reports = Vec::new();
     for policy_definition in policy_definitions {
-        pull_and_bench(
+        if let Some(report) = pull_and_bench(
             policy_definition,
             pull_and_run_settings,
             &local_data,
-            benchmark_config,
+            benchmark_mode,
         )
         .await
-        .map_err(|e| anyhow!("[{}] - {}", policy_definition, e))?;
+        .map_err(|e| anyhow!("[{}] - {}", policy_definition, e))?
+        {
+            reports.push(report);
+        }
+    }
+
+    if let BenchmarkMode::Iterations { output, .. } = benchmark_mode {
+        match output {
+            OutputFormat::Json => println!("{}", serde_json::to_string_pretty(&reports)?),
+            OutputFormat::Table => print_reports(&reports),
+        }
     }
 
     Ok(())
@@ -32,8 +101,8 @@ pub(crate) async fn pull_and_bench(
     policy_definition: &PolicyDefinition,
     pull_and_run_settings: &PullAndRunSettings,
     local_data: &LocalData,
-    benchmark_config: &BenchmarkConfig,
-) -> Result<()> {
+    benchmark_mode: &BenchmarkMode,
+) -> Result<Option<BenchReport>> {
     let (mut evaluator, callback_handler, shutdown_channel_tx) =
         Evaluator::new(policy_definition, pull_and_run_settings, local_data).await?;
 
@@ -55,6 +124,40 @@ pub(crate) async fn pull_and_bench(
         ));
     }
 
+    let report = match benchmark_mode {
+        BenchmarkMode::Statistical(benchmark_config) => {
+            bench_statistically(&mut evaluator, benchmark_config);
+            None
+        }
+        BenchmarkMode::Iterations {
+            warmup_iterations,
+            iterations,
+            ..
+        } => Some(bench_iterations(
+            &policy_definition.to_string(),
+            &mut evaluator,
+            *warmup_iterations,
+            *iterations,
+        )),
+    };
+
+    if shutdown_channel_tx.send(()).is_err() {
+        error!("Cannot shut down the CallbackHandler task");
+    } else {
+        match handler.await {
+            Err(e) => error!(
+                error = e.to_string().as_str(),
+                "Error waiting for the CallbackHandler task"
+            ),
+            // the policy made host capabilities requests that have not been recorded
+            Ok(result) => result?,
+        }
+    }
+
+    Ok(report)
+}
+
+fn bench_statistically(evaluator: &mut Evaluator, benchmark_config: &BenchmarkConfig) {
     // We have to wrap the settings validation in a `tokio::task::block_in_place` context
     // because if the policy uses context aware functions, this would lead to blocking the
     // tokio runtime. Remember, we're running inside of an async context.
@@ -72,15 +175,233 @@ pub(crate) async fn pull_and_bench(
             let _evaluation_result = evaluator.evaluate();
         });
     });
+}
 
-    if shutdown_channel_tx.send(()).is_err() {
-        error!("Cannot shut down the CallbackHandler task");
-    } else if let Err(e) = handler.await {
-        error!(
-            error = e.to_string().as_str(),
-            "Error waiting for the CallbackHandler task"
+fn bench_iterations(
+    policy: &str,
+    evaluator: &mut Evaluator,
+    warmup_iterations: usize,
+    iterations: usize,
+) -> BenchReport {
+    // See `bench_statistically` about the usage of `tokio::task::block_in_place`
+    let (validate_settings, validate) = tokio::task::block_in_place(|| {
+        for _ in 0..warmup_iterations {
+            let _settings_validation_response = evaluator.validate_settings();
+            let _evaluation_result = evaluator.evaluate();
+        }
+        capability_usage::reset();
+
+        let validate_settings = measure(iterations, || evaluator.validate_settings().valid);
+        let validate = measure(iterations, || {
+            !evaluator.evaluate().is_internal_server_error()
+        });
+        (validate_settings, validate)
+    });
+    let (validate_settings, validate_settings_errors) = validate_settings;
+    let (validate, validate_errors) = validate;
+    if validate_settings_errors + validate_errors > 0 {
+        warn!(
+            policy,
+            validate_settings_errors, validate_errors, "some of the measured iterations failed"
         );
     }
 
-    Ok(())
+    BenchReport {
+        policy: policy.to_owned(),
+        iterations,
+        instantiation_us: evaluator.instantiation_time().map(as_micros),
+        validate_settings: latency_report(validate_settings),
+        validate: latency_report(validate),
+        validate_settings_errors,
+        validate_errors,
+        process_peak_memory_bytes: process_peak_memory_bytes(),
+        capability_usage: capability_usage::report().policies,
+    }
+}
+
+/// Repeat the given operation, which returns whether it succeeded. Return the
+/// duration of each iteration and the number of failed ones
+fn measure(iterations: usize, mut f: impl FnMut() -> bool) -> (Vec<Duration>, usize) {
+    let mut errors = 0;
+    let samples = (0..iterations)
+        .map(|_| {
+            let start = Instant::now();
+            let succeeded = f();
+            let elapsed = start.elapsed();
+            if !succeeded {
+                errors += 1;
+            }
+            elapsed
+        })
+        .collect();
+    (samples, errors)
+}
+
+fn latency_report(mut samples: Vec<Duration>) -> LatencyReport {
+    samples.sort();
+    LatencyReport {
+        p50_us: as_micros(percentile(&samples, 50)),
+        p95_us: as_micros(percentile(&samples, 95)),
+        p99_us: as_micros(percentile(&samples, 99)),
+        max_us: as_micros(samples.last().copied().unwrap_or_default()),
+    }
+}
+
+/// Nearest-rank percentile of the given sorted samples
+fn percentile(sorted_samples: &[Duration], percentile: usize) -> Duration {
+    if sorted_samples.is_empty() {
+        return Duration::ZERO;
+    }
+    let rank = (percentile * sorted_samples.len()).div_ceil(100).max(1);
+    sorted_samples[rank.min(sorted_samples.len()) - 1]
+}
+
+fn as_micros(duration: Duration) -> u64 {
+    u64::try_from(duration.as_micros()).unwrap_or(u64::MAX)
+}
+
+#[cfg(target_os = "linux")]
+fn process_peak_memory_bytes() -> Option<u64> {
+    // the value of `VmHWM` is expressed in kB
+    std::fs::read_to_string("/proc/self/status")
+        .ok()?
+        .lines()
+        .find_map(|line| line.strip_prefix("VmHWM:"))
+        .and_then(|value| {
+            value
+                .trim()
+                .trim_end_matches("kB")
+                .trim()
+                .parse::<u64>()
+                .ok()
+        })
+        .map(|kilobytes| kilobytes * 1024)
+}
+
+#[cfg(not(target_os = "linux"))]
+fn process_peak_memory_bytes() -> Option<u64> {
+    None
+}
+
+fn print_reports(reports: &[BenchReport]) {
+    let mut latencies = Table::new();
+    latencies.set_format(*format::consts::FORMAT_NO_LINESEP_WITH_TITLE);
+    latencies.add_row(
+        row![bl -> "Policy", bl -> "Operation", br -> "p50", br -> "p95", br -> "p99", br -> "Max", br -> "Errors"],
+    );
+    for report in reports {
+        for (operation, latency, errors) in [
+            (
+                "validate_settings",
+                &report.validate_settings,
+                report.validate_settings_errors,
+            ),
+            ("validate", &report.validate, report.validate_errors),
+        ] {
+            latencies.add_row(row![
+                report.policy,
+                operation,
+                r -> format_micros(latency.p50_us),
+                r -> format_micros(latency.p95_us),
+                r -> format_micros(latency.p99_us),
+                r -> format_micros(latency.max_us),
+                r -> errors,
+            ]);
+        }
+    }
+    latencies.printstd();
+    println!();
+
+    let mut resources = Table::new();
+    resources.set_format(*format::consts::FORMAT_NO_LINESEP_WITH_TITLE);
+    resources.add_row(
+        row![bl -> "Policy", br -> "Iterations", br -> "Instantiation", br -> "Process peak memory"],
+    );
+    for report in reports {
+        resources.add_row(row![
+            report.policy,
+            r -> report.iterations,
+            r -> report
+                .instantiation_us
+                .map(format_micros)
+                .unwrap_or_else(|| "n/a".to_owned()),
+            r -> report
+                .process_peak_memory_bytes
+                .map(|bytes| format!("{:.1} MiB", bytes as f64 / (1024.0 * 1024.0)))
+                .unwrap_or_else(|| "n/a".to_owned()),
+        ]);
+    }
+    resources.printstd();
+
+    let findings: Vec<_> = reports
+        .iter()
+        .flat_map(|report| &report.capability_usage)
+        .flat_map(|usage| &usage.findings)
+        .collect();
+    if findings.is_empty() {
+        return;
+    }
+    println!();
+    let mut capabilities = Table::new();
+    capabilities.set_format(*format::consts::FORMAT_NO_LINESEP_WITH_TITLE);
+    capabilities.add_row(row![bl -> "Host capability usage", bl -> "Advice"]);
+    for finding in findings {
+        capabilities.add_row(row![finding.message, finding.advice]);
+    }
+    capabilities.printstd();
+}
+
+fn format_micros(micros: u64) -> String {
+    format!("{:.2?}", Duration::from_micros(micros))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rstest::rstest;
+
+    #[rstest]
+    #[case::median(50, 50)]
+    #[case::p95(95, 95)]
+    #[case::p99(99, 99)]
+    #[case::max(100, 100)]
+    #[case::min(0, 1)]
+    fn nearest_rank_percentile(#[case] percentile_value: usize, #[case] expected_millis: u64) {
+        let samples: Vec<Duration> = (1..=100).map(Duration::from_millis).collect();
+
+        assert_eq!(
+            percentile(&samples, percentile_value),
+            Duration::from_millis(expected_millis)
+        );
+    }
+
+    #[test]
+    fn measure_counts_the_failed_iterations() {
+        let mut iteration = 0;
+        let (samples, errors) = measure(10, || {
+            iteration += 1;
+            iteration % 3 != 0
+        });
+
+        assert_eq!(samples.len(), 10);
+        assert_eq!(errors, 3);
+    }
+
+    #[test]
+    fn latency_report_of_unsorted_samples() {
+        let samples = [3, 1, 4, 1, 5, 9, 2, 6, 5, 3]
+            .into_iter()
+            .map(Duration::from_micros)
+            .collect();
+
+        assert_eq!(
+            latency_report(samples),
+            LatencyReport {
+                p50_us: 3,
+                p95_us: 9,
+                p99_us: 9,
+                max_us: 9,
+            }
+        );
+    }
 }
@@ -0,0 +1,463 @@
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    path::Path,
+};
+
+use anyhow::{anyhow, Result};
+use policy_evaluator::admission_response::AdmissionResponse;
+use prettytable::{format, row, Table};
+use serde::Serialize;
+use tracing::{debug, error};
+
+use crate::{
+    callback_handler::observer::HostCapabilitiesObserver,
+    command::run::{evaluator::Evaluator, local_data::LocalData},
+    config::{policy_definition::PolicyDefinition, pull_and_run::PullAndRunSettings},
+    errors::{ErrorKind, KwctlError},
+    policies::OutputFormat,
+};
+
+/// A source of non-determinism a policy might rely on. Policies using them can
+/// produce different verdicts for the same request
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(rename_all = "camelCase")]
+pub(crate) enum NondeterminismSource {
+    /// The clock of the host
+    Time,
+    /// The random number generator of the host
+    Random,
+    /// The host capabilities: Kubernetes resources, OCI registries, DNS,...
+    HostCapabilities,
+}
+
+impl std::fmt::Display for NondeterminismSource {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            NondeterminismSource::Time => write!(f, "time"),
+            NondeterminismSource::Random => write!(f, "random"),
+            NondeterminismSource::HostCapabilities => write!(f, "host capabilities"),
+        }
+    }
+}
+
+/// The WASI functions that give access to a source of non-determinism
+const WASI_NONDETERMINISM_SOURCES: &[(&str, NondeterminismSource)] = &[
+    ("clock_time_get", NondeterminismSource::Time),
+    ("clock_res_get", NondeterminismSource::Time),
+    ("random_get", NondeterminismSource::Random),
+];
+
+/// The Rego builtins that give access to a source of non-determinism. The names
+/// of the builtins used by a Rego policy are embedded inside of its data segments
+const REGO_NONDETERMINISM_SOURCES: &[(&str, NondeterminismSource)] = &[
+    ("time.now_ns", NondeterminismSource::Time),
+    ("rand.intn", NondeterminismSource::Random),
+    ("uuid.rfc4122", NondeterminismSource::Random),
+    ("http.send", NondeterminismSource::HostCapabilities),
+    (
+        "kubewarden.oci.resolve_digest",
+        NondeterminismSource::HostCapabilities,
+    ),
+];
+
+/// The part of the response that must not change when the same request is
+/// evaluated again
+#[derive(Serialize, Debug, Clone, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct Verdict {
+    allowed: bool,
+    code: Option<u16>,
+    message: Option<String>,
+    patch: Option<String>,
+}
+
+impl From<&AdmissionResponse> for Verdict {
+    fn from(response: &AdmissionResponse) -> Self {
+        Verdict {
+            allowed: response.allowed,
+            code: response.status.as_ref().and_then(|status| status.code),
+            message: response
+                .status
+                .as_ref()
+                .and_then(|status| status.message.clone()),
+            patch: response.patch.clone(),
+        }
+    }
+}
+
+/// A verdict, together with the number of evaluations that produced it
+#[derive(Serialize, Debug, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct VerdictCount {
+    #[serde(flatten)]
+    verdict: Verdict,
+    evaluations: usize,
+}
+
+/// The outcome of the determinism check of one policy
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct DeterminismReport {
+    policy: String,
+    evaluations: usize,
+    /// Whether all the evaluations produced the same verdict
+    deterministic: bool,
+    /// The distinct verdicts, sorted by their first appearance
+    verdicts: Vec<VerdictCount>,
+    /// The sources of non-determinism the Wasm module can access, detected by
+    /// looking at its imports and data segments
+    nondeterminism_sources: BTreeSet<NondeterminismSource>,
+    /// The number of requests made to each host capability during the evaluations
+    host_capabilities: BTreeMap<String, usize>,
+}
+
+pub(crate) async fn exec(
+    policy_definitions: &[PolicyDefinition],
+    pull_and_run_settings: &PullAndRunSettings,
+    repeat: usize,
+    output: &OutputFormat,
+) -> Result<()> {
+    let local_data = LocalData::new(policy_definitions, pull_and_run_settings).await?;
+
+    let mut reports = Vec::new();
+    for policy_definition in policy_definitions {
+        let report = check_determinism(
+            policy_definition,
+            pull_and_run_settings,
+            &local_data,
+            repeat,
+        )
+        .await
+        .map_err(|e| anyhow!("[{}] - {}", policy_definition, e))?;
+        reports.push(report);
+    }
+
+    match output {
+        OutputFormat::Json => println!("{}", serde_json::to_string_pretty(&reports)?),
+        OutputFormat::Table => print_reports(&reports),
+    }
+
+    let non_deterministic: Vec<&str> = reports
+        .iter()
+        .filter(|report| !report.deterministic)
+        .map(|report| report.policy.as_str())
+        .collect();
+    if !non_deterministic.is_empty() {
+        return Err(KwctlError::new(
+            ErrorKind::EvaluationFailed,
+            format!(
+                "non-deterministic policies found: {}",
+                non_deterministic.join(", ")
+            ),
+        )
+        .into());
+    }
+
+    Ok(())
+}
+
+async fn check_determinism(
+    policy_definition: &PolicyDefinition,
+    pull_and_run_settings: &PullAndRunSettings,
+    local_data: &LocalData,
+    repeat: usize,
+) -> Result<DeterminismReport> {
+    let mut nondeterminism_sources = BTreeSet::new();
+    for uri in policy_definition.uris() {
+        nondeterminism_sources.extend(detect_nondeterminism_sources(local_data.local_path(&uri)?)?);
+    }
+
+    let (mut evaluator, callback_handler, shutdown_channel_tx) =
+        Evaluator::new(policy_definition, pull_and_run_settings, local_data).await?;
+
+    // start the callback handler
+    let handler = tokio::spawn(async { callback_handler.loop_eval().await });
+
+    // We have to wrap the evaluation code inside of a `tokio::task::block_in_place` context
+    // because if the policy uses context aware functions, this would lead to blocking the
+    // tokio runtime. Remember, we're running inside of an async context.
+    let responses = tokio::task::block_in_place(|| {
+        let settings_validation_response = evaluator.validate_settings();
+        if !settings_validation_response.valid {
+            debug!(
+                response = serde_json::to_string(&settings_validation_response)
+                    .expect("Failed to serialize response"),
+                "Settings validation response"
+            );
+            return Err(anyhow!(
+                "provided settings are not valid: {:?}",
+                settings_validation_response.message
+            ));
+        }
+
+        Ok((0..repeat)
+            .map(|_| evaluator.evaluate())
+            .collect::<Vec<_>>())
+    });
+
+    if shutdown_channel_tx.send(()).is_err() {
+        error!("Cannot shut down the CallbackHandler task");
+    } else {
+        match handler.await {
+            Err(e) => error!(
+                error = e.to_string().as_str(),
+                "Error waiting for the CallbackHandler task"
+            ),
+            // the policy made host capabilities requests that have not been recorded
+            Ok(result) => result?,
+        }
+    }
+
+    let verdicts = count_verdicts(&responses?);
+    let host_capabilities = pull_and_run_settings
+        .host_capabilities_observer
+        .as_ref()
+        .map(HostCapabilitiesObserver::take)
+        .unwrap_or_default();
+    if !host_capabilities.is_empty() {
+        nondeterminism_sources.insert(NondeterminismSource::HostCapabilities);
+    }
+
+    Ok(DeterminismReport {
+        policy: policy_definition.to_string(),
+        evaluations: repeat,
+        deterministic: verdicts.len() <= 1,
+        verdicts,
+        nondeterminism_sources,
+        host_capabilities,
+    })
+}
+
+/// Group the identical verdicts, keeping the order of their first appearance
+fn count_verdicts(responses: &[AdmissionResponse]) -> Vec<VerdictCount> {
+    let mut verdicts: Vec<VerdictCount> = Vec::new();
+    for verdict in responses.iter().map(Verdict::from) {
+        match verdicts.iter_mut().find(|count| count.verdict == verdict) {
+            Some(count) => count.evaluations += 1,
+            None => verdicts.push(VerdictCount {
+                verdict,
+                evaluations: 1,
+            }),
+        }
+    }
+    verdicts
+}
+
+/// Look at the Wasm module for the sources of non-determinism it can access.
+///
+/// This is a heuristic: a policy importing the WASI clock, or referencing a
+/// Rego builtin that reads it, does not necessarily use it to produce its verdict
+fn detect_nondeterminism_sources(wasm_path: &Path) -> Result<BTreeSet<NondeterminismSource>> {
+    let data: Vec<u8> = std::fs::read(wasm_path)
+        .map_err(|e| anyhow!("cannot access file {:?}: {}", wasm_path, e))?;
+    nondeterminism_sources_of_module(&data)
+}
+
+fn nondeterminism_sources_of_module(data: &[u8]) -> Result<BTreeSet<NondeterminismSource>> {
+    let mut sources = BTreeSet::new();
+
+    for payload in wasmparser::Parser::new(0).parse_all(data) {
+        match payload.map_err(|e| anyhow!("cannot parse WebAssembly file: {}", e))? {
+            wasmparser::Payload::ImportSection(s) => {
+                for import in s {
+                    let import = import
+                        .map_err(|e| anyhow!("cannot parse WebAssembly import section: {}", e))?;
+                    match import.module {
+                        "wasi_snapshot_preview1" | "wasi_unstable" => sources.extend(
+                            WASI_NONDETERMINISM_SOURCES
+                                .iter()
+                                .filter(|(name, _)| *name == import.name)
+                                .map(|(_, source)| *source),
+                        ),
+                        "wapc" if import.name == "__host_call" => {
+                            sources.insert(NondeterminismSource::HostCapabilities);
+                        }
+                        _ => {}
+                    }
+                }
+            }
+            wasmparser::Payload::DataSection(s) => {
+                for data in s {
+                    let data =
+                        data.map_err(|e| anyhow!("cannot parse WebAssembly data section: {}", e))?;
+                    sources.extend(
+                        REGO_NONDETERMINISM_SOURCES
+                            .iter()
+                            .filter(|(builtin, _)| contains(data.data, builtin.as_bytes()))
+                            .map(|(_, source)| *source),
+                    );
+                }
+            }
+            _ => {}
+        }
+    }
+
+    Ok(sources)
+}
+
+fn contains(haystack: &[u8], needle: &[u8]) -> bool {
+    haystack
+        .windows(needle.len())
+        .any(|window| window == needle)
+}
+
+fn print_reports(reports: &[DeterminismReport]) {
+    let mut summary = Table::new();
+    summary.set_format(*format::consts::FORMAT_NO_LINESEP_WITH_TITLE);
+    summary.add_row(row![
+        bl -> "Policy",
+        br -> "Evaluations",
+        br -> "Verdicts",
+        bl -> "Deterministic",
+        bl -> "Non-determinism sources",
+        bl -> "Host capabilities requests",
+    ]);
+    for report in reports {
+        summary.add_row(row![
+            report.policy,
+            r -> report.evaluations,
+            r -> report.verdicts.len(),
+            if report.deterministic { "yes" } else { "no" },
+            report
+                .nondeterminism_sources
+                .iter()
+                .map(ToString::to_string)
+                .collect::<Vec<_>>()
+                .join(", "),
+            report
+                .host_capabilities
+                .iter()
+                .map(|(capability, requests)| format!("{capability}: {requests}"))
+                .collect::<Vec<_>>()
+                .join("\n"),
+        ]);
+    }
+    summary.printstd();
+
+    let divergent: Vec<&DeterminismReport> = reports
+        .iter()
+        .filter(|report| !report.deterministic)
+        .collect();
+    if divergent.is_empty() {
+        return;
+    }
+
+    println!();
+    let mut verdicts = Table::new();
+    verdicts.set_format(*format::consts::FORMAT_NO_LINESEP_WITH_TITLE);
+    verdicts.add_row(row![
+        bl -> "Policy",
+        br -> "Evaluations",
+        bl -> "Allowed",
+        br -> "Code",
+        bl -> "Message",
+        bl -> "Patch",
+    ]);
+    for report in divergent {
+        for count in &report.verdicts {
+            verdicts.add_row(row![
+                report.policy,
+                r -> count.evaluations,
+                count.verdict.allowed,
+                r -> count
+                    .verdict
+                    .code
+                    .map(|code| code.to_string())
+                    .unwrap_or_default(),
+                count.verdict.message.clone().unwrap_or_default(),
+                count.verdict.patch.clone().unwrap_or_default(),
+            ]);
+        }
+    }
+    verdicts.printstd();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use policy_evaluator::admission_response::AdmissionResponseStatus;
+    use rstest::rstest;
+
+    fn response(allowed: bool, message: Option<&str>) -> AdmissionResponse {
+        AdmissionResponse {
+            uid: "uid".to_owned(),
+            allowed,
+            status: message.map(|message| AdmissionResponseStatus {
+                message: Some(message.to_owned()),
+                ..Default::default()
+            }),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn identical_verdicts_are_grouped() {
+        let responses = vec![
+            response(true, None),
+            response(false, Some("expired")),
+            response(true, None),
+        ];
+
+        assert_eq!(
+            count_verdicts(&responses),
+            vec![
+                VerdictCount {
+                    verdict: Verdict::from(&response(true, None)),
+                    evaluations: 2,
+                },
+                VerdictCount {
+                    verdict: Verdict::from(&response(false, Some("expired"))),
+                    evaluations: 1,
+                },
+            ]
+        );
+    }
+
+    /// Build a Wasm module with the given function imports and data segment
+    fn module(imports: &[(&str, &str)], data: &str) -> Vec<u8> {
+        let mut module = walrus::Module::default();
+        let ty = module.types.add(&[], &[]);
+        for (module_name, name) in imports {
+            module.add_import_func(module_name, name, ty);
+        }
+        if !data.is_empty() {
+            module
+                .data
+                .add(walrus::DataKind::Passive, data.as_bytes().to_vec());
+        }
+        module.emit_wasm()
+    }
+
+    #[rstest]
+    #[case::pure(&[], "", &[])]
+    #[case::wasi_clock(
+        &[("wasi_snapshot_preview1", "clock_time_get")],
+        "",
+        &[NondeterminismSource::Time]
+    )]
+    #[case::wasi_random(
+        &[("wasi_snapshot_preview1", "random_get"), ("wasi_snapshot_preview1", "fd_write")],
+        "",
+        &[NondeterminismSource::Random]
+    )]
+    #[case::wapc_host_call(
+        &[("wapc", "__host_call")],
+        "",
+        &[NondeterminismSource::HostCapabilities]
+    )]
+    #[case::rego_builtins(
+        &[("env", "opa_builtin1")],
+        r#"{"time.now_ns":0,"http.send":1}"#,
+        &[NondeterminismSource::Time, NondeterminismSource::HostCapabilities]
+    )]
+    fn detect_sources_of_module(
+        #[case] imports: &[(&str, &str)],
+        #[case] data: &str,
+        #[case] expected: &[NondeterminismSource],
+    ) {
+        assert_eq!(
+            nondeterminism_sources_of_module(&module(imports, data)).unwrap(),
+            expected.iter().copied().collect::<BTreeSet<_>>()
+        );
+    }
+}
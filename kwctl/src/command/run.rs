@@ -1,15 +1,42 @@
-use anyhow::{anyhow, Result};
-use policy_evaluator::admission_response_handler::AdmissionResponseHandler;
-use tracing::{error, warn};
+use std::{
+    fmt,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{anyhow, Context, Result};
+use policy_evaluator::{
+    admission_response::AdmissionResponse, admission_response_handler::AdmissionResponseHandler,
+};
+use prettytable::{format, row, Table};
+use serde::Serialize;
+use tracing::{error, info, warn};
 
 use crate::{
     command::run::{evaluator::Evaluator, local_data::LocalData},
-    config::{policy_definition::PolicyDefinition, pull_and_run::PullAndRunSettings},
+    config::{
+        policy_definition::PolicyDefinition,
+        pull_and_run::{parse_requests, PullAndRunSettings},
+    },
+    errors::{ErrorKind, KwctlError},
 };
 
 pub(crate) mod evaluator;
+pub(crate) mod helm;
 pub(crate) mod local_data;
+pub(crate) mod manifest;
 pub(crate) mod policy_execution_mode;
+pub(crate) mod policy_report;
+pub(crate) mod simulation;
+
+/// How the results of the evaluation of a Helm chart are printed
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub(crate) enum ReportFormat {
+    /// One JSON object for each resource and policy
+    #[default]
+    Json,
+    /// PolicyReport and ClusterPolicyReport resources, as YAML documents
+    PolicyReport,
+}
 
 pub(crate) async fn exec(
     policy_definitions: &[PolicyDefinition],
@@ -21,50 +48,664 @@ pub(crate) async fn exec(
         warn!("Multiple policies defined inside of the CRD file. All of them will run sequentially using the same request.");
     }
 
+    let mut outcome = None;
     for policy_definition in policy_definitions {
-        let (mut evaluator, callback_handler, shutdown_channel_tx) =
-            Evaluator::new(policy_definition, pull_and_run_settings, &local_data).await?;
-
-        // start the callback handler
-        let handler = tokio::spawn(async { callback_handler.loop_eval().await });
-
-        // We have to wrap the evaluation code inside of a `tokio::task::block_in_place` context
-        // because if the policy uses context aware functions, this would lead to blocking the
-        // tokio runtime. Remember, we're running inside of an async context.
-        let evaluation_result = tokio::task::block_in_place(move || {
-            // validate the settings given by the user
-            let settings_validation_response = evaluator.validate_settings();
-            if !settings_validation_response.valid {
-                return Err(anyhow!(
+        let evaluation_result =
+            evaluate(policy_definition, pull_and_run_settings, &local_data).await?;
+
+        // Print the evaluation result back to the user, on STDOUT
+        println!("{}", serde_json::to_string(&evaluation_result)?);
+
+        if evaluation_result.is_internal_server_error() {
+            outcome = Some(KwctlError::new(
+                ErrorKind::EvaluationFailed,
+                format!("policy {policy_definition} failed to evaluate the request"),
+            ));
+        } else if !evaluation_result.allowed && outcome.is_none() {
+            outcome = Some(KwctlError::new(
+                ErrorKind::Rejected,
+                format!("request rejected by policy {policy_definition}"),
+            ));
+        }
+    }
+
+    match outcome {
+        Some(error) => Err(error.into()),
+        None => Ok(()),
+    }
+}
+
+/// The outcome of the evaluation of one of the requests found inside of a request file
+/// holding multiple documents
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct DocumentEvaluationReport {
+    /// Position of the request inside of the file, starting from 1
+    document: usize,
+    policy: String,
+    allowed: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    response: Option<AdmissionResponse>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+}
+
+/// Evaluate, in sequence, all the requests found inside of the request file against the
+/// policies. A report is printed on STDOUT for each request and policy. An error is
+/// returned when at least one of the requests is rejected or cannot be evaluated.
+pub(crate) async fn exec_requests(
+    policy_definitions: &[PolicyDefinition],
+    mut pull_and_run_settings: PullAndRunSettings,
+) -> Result<()> {
+    let local_data = LocalData::new(policy_definitions, &pull_and_run_settings).await?;
+
+    let requests = std::mem::take(&mut pull_and_run_settings.requests);
+    info!(requests = requests.len(), "evaluating requests");
+
+    let mut evaluations = 0;
+    let mut rejections = 0;
+    let mut failures = 0;
+    for (index, request) in requests.into_iter().enumerate() {
+        pull_and_run_settings.request = request;
+        let document = index + 1;
+
+        for policy_definition in policy_definitions {
+            let policy = policy_definition.to_string();
+            evaluations += 1;
+
+            let report =
+                match evaluate(policy_definition, &pull_and_run_settings, &local_data).await {
+                    Ok(response) => DocumentEvaluationReport {
+                        document,
+                        policy,
+                        allowed: response.allowed,
+                        response: Some(response),
+                        error: None,
+                    },
+                    Err(e) => DocumentEvaluationReport {
+                        document,
+                        policy,
+                        allowed: false,
+                        response: None,
+                        error: Some(e.to_string()),
+                    },
+                };
+            if let Some(error) = &report.error {
+                failures += 1;
+                warn!(
+                    document,
+                    policy = report.policy.as_str(),
+                    error = error.as_str(),
+                    "request cannot be evaluated"
+                );
+            } else if !report.allowed {
+                rejections += 1;
+                warn!(
+                    document,
+                    policy = report.policy.as_str(),
+                    "request rejected"
+                );
+            }
+
+            println!("{}", serde_json::to_string(&report)?);
+        }
+    }
+
+    info!(evaluations, rejections, failures, "requests evaluated");
+    if rejections + failures > 0 {
+        let kind = if failures > 0 {
+            ErrorKind::EvaluationFailed
+        } else {
+            ErrorKind::Rejected
+        };
+        return Err(KwctlError::new(
+            kind,
+            format!("{rejections} of {evaluations} evaluations rejected, {failures} failed"),
+        )
+        .into());
+    }
+
+    Ok(())
+}
+
+/// The outcome of the evaluation of a request
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum RequestOutcome {
+    Accept,
+    Mutate,
+    Reject,
+    /// The request cannot be evaluated, or the policy failed during the evaluation
+    Error,
+}
+
+impl RequestOutcome {
+    fn of_response(response: &AdmissionResponse) -> Self {
+        if response.is_internal_server_error() {
+            RequestOutcome::Error
+        } else if !response.allowed {
+            RequestOutcome::Reject
+        } else if response.patch.is_some() {
+            RequestOutcome::Mutate
+        } else {
+            RequestOutcome::Accept
+        }
+    }
+
+    /// The outcome expected for the requests of a file, declared by the suffix of
+    /// its name: `<name>.accept.json`, `<name>.mutate.json` or `<name>.reject.json`.
+    /// The requests of the other files are expected to be allowed, with or without
+    /// mutations
+    fn expected_for_file(path: &Path) -> Option<Self> {
+        let stem = path.file_stem()?.to_str()?;
+        match stem.rsplit_once('.').map(|(_, suffix)| suffix) {
+            Some("accept") => Some(RequestOutcome::Accept),
+            Some("mutate") => Some(RequestOutcome::Mutate),
+            Some("reject") => Some(RequestOutcome::Reject),
+            _ => None,
+        }
+    }
+
+    fn satisfies(self, expected: Option<RequestOutcome>) -> bool {
+        match expected {
+            Some(expected) => self == expected,
+            None => matches!(self, RequestOutcome::Accept | RequestOutcome::Mutate),
+        }
+    }
+}
+
+impl fmt::Display for RequestOutcome {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RequestOutcome::Accept => write!(f, "accept"),
+            RequestOutcome::Mutate => write!(f, "mutate"),
+            RequestOutcome::Reject => write!(f, "reject"),
+            RequestOutcome::Error => write!(f, "error"),
+        }
+    }
+}
+
+/// The outcome of the evaluation of one of the requests found inside of a request
+/// directory
+struct RequestFileReport {
+    /// The file holding the request, followed by the position of the request when
+    /// the file holds multiple documents
+    request: String,
+    policy: String,
+    expected: Option<RequestOutcome>,
+    outcome: RequestOutcome,
+    message: Option<String>,
+}
+
+impl RequestFileReport {
+    fn is_expected(&self) -> bool {
+        self.outcome.satisfies(self.expected)
+    }
+}
+
+/// The files holding the requests inside of the given directory, sorted by name.
+/// Only the JSON and YAML files are taken into account, the subdirectories are ignored
+fn request_files(dir: &Path) -> Result<Vec<PathBuf>> {
+    let mut files = Vec::new();
+    for entry in std::fs::read_dir(dir)
+        .map_err(|e| anyhow!("Error opening request directory {}: {}", dir.display(), e))?
+    {
+        let path = entry?.path();
+        let is_request_file = path
+            .extension()
+            .and_then(|extension| extension.to_str())
+            .is_some_and(|extension| matches!(extension, "json" | "yaml" | "yml"));
+        if path.is_file() && is_request_file {
+            files.push(path);
+        }
+    }
+    files.sort();
+    Ok(files)
+}
+
+/// Evaluate, in sequence, the requests found inside of the files of the given directory
+/// against the policies, then print a summary table. An error is returned when the
+/// outcome of at least one of the evaluations is not the expected one. See
+/// `RequestOutcome::expected_for_file` about how the expected outcomes are declared.
+pub(crate) async fn exec_request_dir(
+    policy_definitions: &[PolicyDefinition],
+    mut pull_and_run_settings: PullAndRunSettings,
+    dir: &Path,
+) -> Result<()> {
+    let local_data = LocalData::new(policy_definitions, &pull_and_run_settings).await?;
+
+    let files = request_files(dir)?;
+    if files.is_empty() {
+        return Err(KwctlError::new(
+            ErrorKind::InvalidInput,
+            format!("no request files found inside of {}", dir.display()),
+        )
+        .into());
+    }
+    info!(files = files.len(), "evaluating request files");
+
+    let mut reports = Vec::new();
+    for file in &files {
+        let name = file.strip_prefix(dir).unwrap_or(file).display().to_string();
+        let expected = RequestOutcome::expected_for_file(file);
+
+        let requests = std::fs::read_to_string(file)
+            .map_err(anyhow::Error::new)
+            .and_then(|raw| parse_requests(&raw));
+        let requests = match requests {
+            Ok(requests) => requests,
+            Err(e) => {
+                warn!(file = name.as_str(), error = %e, "cannot read request file");
+                for policy_definition in policy_definitions {
+                    reports.push(RequestFileReport {
+                        request: name.clone(),
+                        policy: policy_definition.to_string(),
+                        expected,
+                        outcome: RequestOutcome::Error,
+                        message: Some(e.to_string()),
+                    });
+                }
+                continue;
+            }
+        };
+
+        let documents = requests.len();
+        for (index, request) in requests.into_iter().enumerate() {
+            pull_and_run_settings.request = request;
+            let request = if documents > 1 {
+                format!("{name}#{}", index + 1)
+            } else {
+                name.clone()
+            };
+
+            for policy_definition in policy_definitions {
+                let (outcome, message) =
+                    match evaluate(policy_definition, &pull_and_run_settings, &local_data).await {
+                        Ok(response) => (
+                            RequestOutcome::of_response(&response),
+                            response.status.and_then(|status| status.message),
+                        ),
+                        Err(e) => (RequestOutcome::Error, Some(e.to_string())),
+                    };
+                reports.push(RequestFileReport {
+                    request: request.clone(),
+                    policy: policy_definition.to_string(),
+                    expected,
+                    outcome,
+                    message,
+                });
+            }
+        }
+    }
+
+    print_request_file_reports(&reports);
+
+    let unexpected: Vec<&RequestFileReport> = reports
+        .iter()
+        .filter(|report| !report.is_expected())
+        .collect();
+    info!(
+        evaluations = reports.len(),
+        unexpected = unexpected.len(),
+        "request files evaluated"
+    );
+    if !unexpected.is_empty() {
+        let kind = if unexpected
+            .iter()
+            .any(|report| report.outcome == RequestOutcome::Error)
+        {
+            ErrorKind::EvaluationFailed
+        } else {
+            ErrorKind::Rejected
+        };
+        return Err(KwctlError::new(
+            kind,
+            format!(
+                "{} of {} evaluations did not have the expected outcome",
+                unexpected.len(),
+                reports.len()
+            ),
+        )
+        .into());
+    }
+
+    Ok(())
+}
+
+fn print_request_file_reports(reports: &[RequestFileReport]) {
+    let mut table = Table::new();
+    table.set_format(*format::consts::FORMAT_NO_LINESEP_WITH_TITLE);
+    table.add_row(row![
+        bl -> "Request",
+        bl -> "Policy",
+        bl -> "Expected",
+        bl -> "Outcome",
+        bl -> "Result",
+        bl -> "Message",
+    ]);
+    for report in reports {
+        table.add_row(row![
+            report.request,
+            report.policy,
+            report
+                .expected
+                .map(|expected| expected.to_string())
+                .unwrap_or_else(|| "accept/mutate".to_owned()),
+            report.outcome,
+            if report.is_expected() {
+                "ok"
+            } else {
+                "UNEXPECTED"
+            },
+            report.message.clone().unwrap_or_default(),
+        ]);
+    }
+    table.printstd();
+}
+
+/// Render the given Helm chart and evaluate every resource it defines against the policies.
+/// Depending on `report_format`, either a report is printed on STDOUT for each resource and
+/// policy, or all the results are printed at the end as PolicyReport resources.
+pub(crate) async fn exec_helm_chart(
+    policy_definitions: &[PolicyDefinition],
+    mut pull_and_run_settings: PullAndRunSettings,
+    chart: &Path,
+    values: &[PathBuf],
+    report_format: ReportFormat,
+) -> Result<()> {
+    let local_data = LocalData::new(policy_definitions, &pull_and_run_settings).await?;
+
+    let rendered = helm::render_chart(chart, values)?;
+    let resources = helm::parse_rendered_manifests(&rendered)?;
+    info!(resources = resources.len(), "helm chart rendered");
+
+    let mut policy_report_results = Vec::new();
+    let mut rejections = 0;
+    for resource in &resources {
+        pull_and_run_settings.request = resource.admission_request()?;
+
+        for policy_definition in policy_definitions {
+            let response = evaluate(policy_definition, &pull_and_run_settings, &local_data)
+                .await
+                .with_context(|| resource.description())?;
+
+            let report = helm::HelmEvaluationReport {
+                source: resource.source.clone(),
+                kind: resource.kind().map(str::to_owned),
+                name: resource.name().map(str::to_owned),
+                namespace: resource.namespace().map(str::to_owned),
+                policy: policy_definition.to_string(),
+                allowed: response.allowed,
+                message: response.status.and_then(|status| status.message),
+            };
+            if !report.allowed {
+                rejections += 1;
+                warn!(
+                    source = report.source.as_deref(),
+                    policy = report.policy.as_str(),
+                    "resource rejected"
+                );
+            }
+
+            match report_format {
+                ReportFormat::Json => println!("{}", serde_json::to_string(&report)?),
+                ReportFormat::PolicyReport => {
+                    let metadata = match policy_definition {
+                        PolicyDefinition::Policy { uri, .. } => local_data.metadata(uri),
+                        PolicyDefinition::PolicyGroup { .. } => None,
+                    };
+                    policy_report_results.push(policy_report::PolicyReportResult::new(
+                        resource,
+                        &policy_definition.get_policy_id()?.to_string(),
+                        metadata,
+                        report.allowed,
+                        report.message,
+                    ));
+                }
+            }
+        }
+    }
+
+    if report_format == ReportFormat::PolicyReport {
+        let report_name = policy_report_name(chart);
+        for policy_report in
+            policy_report::build_policy_reports(&report_name, policy_report_results)
+        {
+            print!("---\n{}", serde_yaml::to_string(&policy_report)?);
+        }
+    }
+
+    if rejections > 0 {
+        return Err(KwctlError::new(
+            ErrorKind::Rejected,
+            format!("{rejections} resources of the chart have been rejected"),
+        )
+        .into());
+    }
+
+    Ok(())
+}
+
+/// Evaluate all the requests found inside of the request file against the policies, then
+/// print the results as PolicyReport resources about the objects of the requests. An error
+/// is returned when at least one of the requests is rejected.
+pub(crate) async fn exec_policy_report(
+    policy_definitions: &[PolicyDefinition],
+    mut pull_and_run_settings: PullAndRunSettings,
+) -> Result<()> {
+    let local_data = LocalData::new(policy_definitions, &pull_and_run_settings).await?;
+
+    let mut requests = std::mem::take(&mut pull_and_run_settings.requests);
+    if requests.is_empty() {
+        requests.push(pull_and_run_settings.request.clone());
+    }
+
+    let mut policy_report_results = Vec::new();
+    let mut rejections = 0;
+    for (index, request) in requests.into_iter().enumerate() {
+        let resource = policy_report::requested_resource(&request);
+        pull_and_run_settings.request = request;
+
+        for policy_definition in policy_definitions {
+            let response = evaluate(policy_definition, &pull_and_run_settings, &local_data)
+                .await
+                .with_context(|| format!("request #{}", index + 1))?;
+            if !response.allowed {
+                rejections += 1;
+                warn!(
+                    document = index + 1,
+                    policy = policy_definition.to_string().as_str(),
+                    "request rejected"
+                );
+            }
+
+            let metadata = match policy_definition {
+                PolicyDefinition::Policy { uri, .. } => local_data.metadata(uri),
+                PolicyDefinition::PolicyGroup { .. } => None,
+            };
+            policy_report_results.push(policy_report::PolicyReportResult::new(
+                &resource,
+                &policy_definition.get_policy_id()?.to_string(),
+                metadata,
+                response.allowed,
+                response.status.and_then(|status| status.message),
+            ));
+        }
+    }
+
+    for policy_report in policy_report::build_policy_reports("kwctl", policy_report_results) {
+        print!("---\n{}", serde_yaml::to_string(&policy_report)?);
+    }
+
+    if rejections > 0 {
+        return Err(KwctlError::new(
+            ErrorKind::Rejected,
+            format!("{rejections} requests have been rejected"),
+        )
+        .into());
+    }
+
+    Ok(())
+}
+
+/// Name of the PolicyReport resources created for the given chart: the name of the chart
+/// directory, turned into a valid Kubernetes name
+fn policy_report_name(chart: &Path) -> String {
+    let chart_name: String = chart
+        .file_stem()
+        .map(|name| name.to_string_lossy().to_lowercase())
+        .unwrap_or_default()
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c } else { '-' })
+        .collect();
+    let chart_name = chart_name.trim_matches('-');
+
+    if chart_name.is_empty() {
+        "kwctl".to_string()
+    } else {
+        format!("kwctl-{chart_name}")
+    }
+}
+
+/// Evaluate the request defined inside of `pull_and_run_settings` against the given policy
+async fn evaluate(
+    policy_definition: &PolicyDefinition,
+    pull_and_run_settings: &PullAndRunSettings,
+    local_data: &LocalData,
+) -> Result<AdmissionResponse> {
+    let (mut evaluator, callback_handler, shutdown_channel_tx) =
+        Evaluator::new(policy_definition, pull_and_run_settings, local_data).await?;
+
+    // start the callback handler
+    let handler = tokio::spawn(async { callback_handler.loop_eval().await });
+
+    // We have to wrap the evaluation code inside of a `tokio::task::block_in_place` context
+    // because if the policy uses context aware functions, this would lead to blocking the
+    // tokio runtime. Remember, we're running inside of an async context.
+    let evaluation_result = tokio::task::block_in_place(move || {
+        // validate the settings given by the user
+        let settings_validation_response = evaluator.validate_settings();
+        if !settings_validation_response.valid {
+            return Err(KwctlError::new(
+                ErrorKind::EvaluationFailed,
+                format!(
                     "Provided settings are not valid: {:?}",
                     settings_validation_response.message.unwrap_or_default()
-                ));
-            }
-            let vanilla_validation_response = evaluator.evaluate();
-
-            let policy_id = policy_definition.get_policy_id()?;
-            let policy_mode = policy_definition.get_policy_mode();
-            let admission_response_handler = AdmissionResponseHandler::new(
-                &policy_id,
-                &policy_mode,
-                policy_definition.get_policy_allowed_to_mutate(),
-                policy_definition.get_policy_custom_rejection_message(),
-            );
-            Ok(admission_response_handler.process_response(vanilla_validation_response))
-        });
-
-        if shutdown_channel_tx.send(()).is_err() {
-            error!("Cannot shut down the CallbackHandler task");
-        } else if let Err(e) = handler.await {
-            error!(
+                ),
+            )
+            .into());
+        }
+        let vanilla_validation_response = evaluator.evaluate();
+
+        let policy_id = policy_definition.get_policy_id()?;
+        let policy_mode = policy_definition.get_policy_mode();
+        let admission_response_handler = AdmissionResponseHandler::new(
+            &policy_id,
+            &policy_mode,
+            policy_definition.get_policy_allowed_to_mutate(),
+            policy_definition.get_policy_custom_rejection_message(),
+        );
+        Ok(admission_response_handler.process_response(vanilla_validation_response))
+    });
+
+    if shutdown_channel_tx.send(()).is_err() {
+        error!("Cannot shut down the CallbackHandler task");
+    } else {
+        match handler.await {
+            Err(e) => error!(
                 error = e.to_string().as_str(),
                 "Error waiting for the CallbackHandler task"
-            );
+            ),
+            // the policy made host capabilities requests that have not been recorded
+            Ok(result) => result?,
         }
+    }
 
-        // Print the evaluation result back to the user, on STDOUT
-        println!("{}", serde_json::to_string(&evaluation_result?)?);
+    evaluation_result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use policy_evaluator::admission_response::AdmissionResponseStatus;
+    use rstest::rstest;
+
+    #[rstest]
+    #[case::accept("pod.accept.json", Some(RequestOutcome::Accept))]
+    #[case::mutate("pod.mutate.yaml", Some(RequestOutcome::Mutate))]
+    #[case::reject("privileged-pod.reject.json", Some(RequestOutcome::Reject))]
+    #[case::no_suffix("pod.json", None)]
+    #[case::unknown_suffix("pod.v1.json", None)]
+    fn expected_outcome_of_file(#[case] file: &str, #[case] expected: Option<RequestOutcome>) {
+        assert_eq!(RequestOutcome::expected_for_file(Path::new(file)), expected);
     }
 
-    Ok(())
+    #[rstest]
+    #[case::accepted(RequestOutcome::Accept, None, true)]
+    #[case::mutated(RequestOutcome::Mutate, None, true)]
+    #[case::rejected(RequestOutcome::Reject, None, false)]
+    #[case::failed(RequestOutcome::Error, None, false)]
+    #[case::expected_rejection(RequestOutcome::Reject, Some(RequestOutcome::Reject), true)]
+    #[case::unexpected_acceptance(RequestOutcome::Accept, Some(RequestOutcome::Reject), false)]
+    #[case::missing_mutation(RequestOutcome::Accept, Some(RequestOutcome::Mutate), false)]
+    fn outcome_satisfies_expectation(
+        #[case] outcome: RequestOutcome,
+        #[case] expected: Option<RequestOutcome>,
+        #[case] satisfied: bool,
+    ) {
+        assert_eq!(outcome.satisfies(expected), satisfied);
+    }
+
+    #[test]
+    fn outcome_of_response() {
+        let accepted = AdmissionResponse {
+            allowed: true,
+            ..Default::default()
+        };
+        let mutated = AdmissionResponse {
+            allowed: true,
+            patch: Some("W10=".to_owned()),
+            ..Default::default()
+        };
+        let rejected = AdmissionResponse {
+            allowed: false,
+            status: Some(AdmissionResponseStatus {
+                message: Some("privileged containers are not allowed".to_owned()),
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+        let failed =
+            AdmissionResponse::reject_internal_server_error("uid".to_owned(), "boom".to_owned());
+
+        assert_eq!(
+            RequestOutcome::of_response(&accepted),
+            RequestOutcome::Accept
+        );
+        assert_eq!(
+            RequestOutcome::of_response(&mutated),
+            RequestOutcome::Mutate
+        );
+        assert_eq!(
+            RequestOutcome::of_response(&rejected),
+            RequestOutcome::Reject
+        );
+        assert_eq!(RequestOutcome::of_response(&failed), RequestOutcome::Error);
+    }
+
+    #[test]
+    fn request_files_are_sorted_and_filtered() {
+        let dir = tempfile::tempdir().unwrap();
+        for file in ["b.reject.json", "a.json", "c.yaml", "notes.txt"] {
+            std::fs::write(dir.path().join(file), "{}").unwrap();
+        }
+        std::fs::create_dir(dir.path().join("nested.json")).unwrap();
+
+        let files: Vec<String> = request_files(dir.path())
+            .unwrap()
+            .iter()
+            .map(|file| file.file_name().unwrap().to_string_lossy().into_owned())
+            .collect();
+
+        assert_eq!(files, vec!["a.json", "b.reject.json", "c.yaml"]);
+    }
 }
@@ -1,18 +1,23 @@
-use std::{collections::BTreeSet, sync::Arc};
+use std::{
+    collections::BTreeSet,
+    sync::Arc,
+    time::{Duration, Instant},
+};
 
 use anyhow::{anyhow, Result};
 use policy_evaluator::{
     admission_request::AdmissionRequest,
     admission_response::AdmissionResponse,
+    callback_requests::CallbackRequest,
     evaluation_context::EvaluationContext,
     kube,
     kubewarden_policy_sdk::settings::SettingsValidationResponse,
-    policy_evaluator::{PolicyEvaluator, PolicySettings, ValidateRequest},
+    policy_evaluator::{PolicyEvaluator, PolicyExecutionMode, PolicySettings, ValidateRequest},
     policy_evaluator_builder::PolicyEvaluatorBuilder,
     policy_group_evaluator::evaluator::PolicyGroupEvaluator,
     policy_metadata::{ContextAwareResource, Metadata, PolicyType},
 };
-use tokio::sync::oneshot;
+use tokio::sync::{mpsc, oneshot};
 use tracing::{info, warn};
 
 use crate::{
@@ -26,6 +31,7 @@ use crate::{
         pull_and_run::PullAndRunSettings,
         HostCapabilitiesMode,
     },
+    schema_validation::validate_policy_settings,
 };
 
 fn has_raw_policy_type(metadata: Option<&Metadata>) -> bool {
@@ -45,18 +51,35 @@ async fn build_callback_handler(
         None
     } else {
         match &cfg.host_capabilities_mode {
-            HostCapabilitiesMode::Proxy(ProxyMode::Replay { source: _ }) => None,
+            HostCapabilitiesMode::Proxy(ProxyMode::Replay { .. })
+            | HostCapabilitiesMode::Proxy(ProxyMode::Context { fixtures: _ }) => None,
             _ => Some(build_kube_client().await?),
         }
     };
     CallbackHandler::new(cfg, kube_client, shutdown_channel_rx).await
 }
 
+/// The channel used by the policies to reach the callback handler. The requests
+/// are recorded when the host capabilities are observed
+fn callback_channel(
+    callback_handler: &CallbackHandler,
+    cfg: &PullAndRunSettings,
+) -> mpsc::Sender<CallbackRequest> {
+    match &cfg.host_capabilities_observer {
+        Some(observer) => observer.observe(callback_handler.sender_channel()),
+        None => callback_handler.sender_channel(),
+    }
+}
+
 pub(crate) enum Evaluator {
     Policy {
-        policy_evaluator: PolicyEvaluator,
+        policy_evaluator: Box<PolicyEvaluator>,
         settings: PolicySettings,
+        /// The JSON Schema of the settings, as declared by the metadata of the policy
+        settings_schema: Option<serde_json::Value>,
         request: ValidateRequest,
+        /// The time spent instantiating the WebAssembly module
+        instantiation_time: Duration,
     },
     GroupPolicy {
         policy_group_evaluator: Arc<PolicyGroupEvaluator>,
@@ -115,19 +138,64 @@ impl Evaluator {
                 if cfg.enable_wasmtime_cache {
                     policy_evaluator_builder = policy_evaluator_builder.enable_wasmtime_cache();
                 }
+                if let Some(pinned_clock) = cfg.pinned_clock {
+                    if matches!(
+                        execution_mode,
+                        PolicyExecutionMode::Opa | PolicyExecutionMode::OpaGatekeeper
+                    ) {
+                        policy_evaluator_builder =
+                            policy_evaluator_builder.pinned_clock(pinned_clock);
+                    } else {
+                        warn!("the clock can be pinned only for OPA and Gatekeeper policies, the policy reads the clock of the host");
+                    }
+                }
+                // Send the policy the same fields of the request it receives inside of
+                // Policy Server
+                if let Some(paths) = metadata.and_then(|m| m.request_projection.as_deref()) {
+                    if execution_mode == PolicyExecutionMode::KubewardenWapc {
+                        policy_evaluator_builder =
+                            policy_evaluator_builder.request_projection(paths);
+                    }
+                }
+                // Wrap the request inside of the envelope understood by the policy
+                if let Some(request_envelope) = metadata.map(|m| m.request_envelope) {
+                    if execution_mode == PolicyExecutionMode::KubewardenWapc {
+                        policy_evaluator_builder =
+                            policy_evaluator_builder.request_envelope(request_envelope);
+                    }
+                }
+                // Mount only the directories mapped by the user, they must match the
+                // ones declared by the policy
+                if let Some(metadata) = metadata {
+                    policy_evaluator_builder =
+                        policy_evaluator_builder.declared_preopened_dirs(&metadata.preopened_dirs);
+                }
+                for (guest_path, host_path) in &cfg.preopened_dirs {
+                    policy_evaluator_builder =
+                        policy_evaluator_builder.preopened_dir(guest_path, host_path);
+                }
                 let eval_ctx = EvaluationContext {
                     policy_id: uri.to_owned(),
-                    callback_channel: Some(callback_handler.sender_channel()),
+                    policy_stable_id: None,
+                    callback_channel: Some(callback_channel(&callback_handler, cfg)),
                     ctx_aware_resources_allow_list: context_aware_allowed_resources.clone(),
+                    kubernetes_service_account: None,
+                    request_context: Default::default(),
+                    http_policy: None,
+                    key_value_store: false,
                 };
-                let policy_evaluator =
-                    policy_evaluator_builder.build_pre()?.rehydrate(&eval_ctx)?;
+                let policy_evaluator_pre = policy_evaluator_builder.build_pre()?;
+                let instantiation_start = Instant::now();
+                let policy_evaluator = policy_evaluator_pre.rehydrate(&eval_ctx)?;
+                let instantiation_time = instantiation_start.elapsed();
 
                 Ok((
                     Self::Policy {
-                        policy_evaluator,
+                        policy_evaluator: Box::new(policy_evaluator),
                         request,
                         settings: settings.clone(),
+                        settings_schema: metadata.and_then(|m| m.settings_schema.clone()),
+                        instantiation_time,
                     },
                     callback_handler,
                     shutdown_channel_tx,
@@ -150,11 +218,15 @@ impl Evaluator {
                 // group policies cannot be raw right now
                 let request = build_validate_request(&cfg.request, false)?;
 
+                if cfg.pinned_clock.is_some() {
+                    warn!("the clock cannot be pinned for the members of a policy group, they read the clock of the host");
+                }
+
                 let mut policy_group_evaluator = PolicyGroupEvaluator::new(
                     id,
                     message,
                     expression,
-                    Some(callback_handler.sender_channel()),
+                    Some(callback_channel(&callback_handler, cfg)),
                 );
 
                 for (member_id, member) in policy_members {
@@ -163,6 +235,18 @@ impl Evaluator {
                     if cfg.enable_wasmtime_cache {
                         policy_evaluator_builder = policy_evaluator_builder.enable_wasmtime_cache();
                     }
+                    if let Some(metadata) = local_data.metadata(&member.uri) {
+                        policy_evaluator_builder = policy_evaluator_builder
+                            .request_envelope(metadata.request_envelope)
+                            .declared_preopened_dirs(&metadata.preopened_dirs);
+                        // each member receives only the directories it declares
+                        for dir in &metadata.preopened_dirs {
+                            if let Some(host_path) = cfg.preopened_dirs.get(&dir.path) {
+                                policy_evaluator_builder =
+                                    policy_evaluator_builder.preopened_dir(&dir.path, host_path);
+                            }
+                        }
+                    }
 
                     let policy_evaluator_pre = Arc::new(policy_evaluator_builder.build_pre()?);
 
@@ -194,6 +278,7 @@ impl Evaluator {
                 policy_evaluator,
                 settings,
                 request,
+                ..
             } => policy_evaluator.validate(request.clone(), settings),
             Self::GroupPolicy {
                 policy_group_evaluator,
@@ -202,14 +287,44 @@ impl Evaluator {
         }
     }
 
+    /// The time spent instantiating the WebAssembly module of the policy. Policy
+    /// groups instantiate their members at each evaluation, hence this is not
+    /// available for them.
+    pub(crate) fn instantiation_time(&self) -> Option<Duration> {
+        match self {
+            Self::Policy {
+                instantiation_time, ..
+            } => Some(*instantiation_time),
+            Self::GroupPolicy { .. } => None,
+        }
+    }
+
     /// Validates the settings given by the user.
     pub(crate) fn validate_settings(&mut self) -> SettingsValidationResponse {
         match self {
             Self::Policy {
                 policy_evaluator,
                 settings,
+                settings_schema,
                 ..
             } => {
+                // report the settings that do not comply with the schema of the policy
+                // with more details than the policy usually does
+                match validate_policy_settings(settings_schema.as_ref(), settings) {
+                    Ok(None) => {}
+                    Ok(Some(violations)) => {
+                        return SettingsValidationResponse {
+                            valid: false,
+                            message: Some(format!(
+                                "the settings do not comply with the schema of the policy:\n{violations}"
+                            )),
+                        }
+                    }
+                    Err(error) => {
+                        warn!(%error, "cannot validate the settings against the schema of the policy")
+                    }
+                }
+
                 // validate the settings given by the user
                 policy_evaluator.validate_settings(settings)
             }
@@ -279,7 +394,7 @@ fn build_context_aware_allowed_resources(
 /// yet (see https://github.com/kube-rs/kube/issues/1003).
 ///
 /// This function provides a workaround to this limitation.
-async fn build_kube_client() -> Result<kube::Client> {
+pub(crate) async fn build_kube_client() -> Result<kube::Client> {
     // This is the usual way of obtaining a kubeconfig
     let mut kube_config = kube::Config::infer().await.map_err(anyhow::Error::new)?;
 
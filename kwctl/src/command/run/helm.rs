@@ -0,0 +1,244 @@
+use std::{
+    path::{Path, PathBuf},
+    process::Command,
+};
+
+use anyhow::{anyhow, Result};
+use serde::Serialize;
+use serde_json::json;
+
+/// Hard-coded UID used by all the synthesized admission requests
+const ADMISSION_REQUEST_UID: &str = "705ab4f5-6393-11e8-b7cc-42010a800002";
+
+/// A Kubernetes resource produced by the rendering of a Helm chart
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct RenderedResource {
+    /// The template that generated the resource, e.g. `mychart/templates/deployment.yaml`
+    pub source: Option<String>,
+    pub object: serde_json::Value,
+}
+
+impl RenderedResource {
+    pub fn kind(&self) -> Option<&str> {
+        self.object.get("kind").and_then(serde_json::Value::as_str)
+    }
+
+    pub fn name(&self) -> Option<&str> {
+        self.object
+            .pointer("/metadata/name")
+            .and_then(serde_json::Value::as_str)
+    }
+
+    pub fn namespace(&self) -> Option<&str> {
+        self.object
+            .pointer("/metadata/namespace")
+            .and_then(serde_json::Value::as_str)
+    }
+
+    /// Build an admission request asking to create the resource
+    pub fn admission_request(&self) -> Result<serde_json::Value> {
+        let kind = self
+            .kind()
+            .ok_or_else(|| anyhow!("{}: resource without kind", self.description()))?;
+        let api_version = self
+            .object
+            .get("apiVersion")
+            .and_then(serde_json::Value::as_str)
+            .ok_or_else(|| anyhow!("{}: resource without apiVersion", self.description()))?;
+        let (group, version) = match api_version.split_once('/') {
+            Some((group, version)) => (group, version),
+            None => ("", api_version),
+        };
+        // Finding the real plural name requires a connection to the API server. Policies
+        // usually don't rely on it, hence a best effort guess is good enough.
+        let resource = format!("{}s", kind.to_lowercase());
+
+        Ok(json!({
+            "uid": ADMISSION_REQUEST_UID,
+            "kind": { "group": group, "version": version, "kind": kind },
+            "requestKind": { "group": group, "version": version, "kind": kind },
+            "resource": { "group": group, "version": version, "resource": resource },
+            "requestResource": { "group": group, "version": version, "resource": resource },
+            "name": self.name(),
+            "namespace": self.namespace(),
+            "operation": "CREATE",
+            "userInfo": {
+                "username": "kwctl",
+                "groups": ["system:authenticated"],
+            },
+            "object": self.object,
+            "dryRun": false,
+        }))
+    }
+
+    /// Human readable description of the resource, used inside of error messages
+    pub fn description(&self) -> String {
+        format!(
+            "{}/{} ({})",
+            self.kind().unwrap_or("unknown"),
+            self.name().unwrap_or("unknown"),
+            self.source.as_deref().unwrap_or("unknown source"),
+        )
+    }
+}
+
+/// The outcome of the evaluation of a rendered resource against a policy
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct HelmEvaluationReport {
+    pub source: Option<String>,
+    pub kind: Option<String>,
+    pub name: Option<String>,
+    pub namespace: Option<String>,
+    pub policy: String,
+    pub allowed: bool,
+    pub message: Option<String>,
+}
+
+/// Render the given chart by invoking `helm template`.
+/// The `helm` binary must be available inside of the `PATH`.
+pub(crate) fn render_chart(chart: &Path, values: &[PathBuf]) -> Result<String> {
+    let mut command = Command::new("helm");
+    command.arg("template").arg(chart);
+    for values_file in values {
+        command.arg("--values").arg(values_file);
+    }
+
+    let output = command
+        .output()
+        .map_err(|e| anyhow!("cannot run `helm template`, is helm installed? {}", e))?;
+    if !output.status.success() {
+        return Err(anyhow!(
+            "cannot render helm chart {}: {}",
+            chart.display(),
+            String::from_utf8_lossy(&output.stderr).trim()
+        ));
+    }
+
+    String::from_utf8(output.stdout)
+        .map_err(|e| anyhow!("helm template produced invalid UTF-8 output: {}", e))
+}
+
+/// Split the output of `helm template` into the list of resources it defines.
+/// The `# Source:` comment helm puts in front of each document is used to track
+/// which template generated the resource.
+pub(crate) fn parse_rendered_manifests(rendered: &str) -> Result<Vec<RenderedResource>> {
+    let mut documents: Vec<Vec<&str>> = vec![Vec::new()];
+    for line in rendered.lines() {
+        if line.trim_end() == "---" {
+            documents.push(Vec::new());
+        } else {
+            documents.last_mut().unwrap().push(line);
+        }
+    }
+
+    let mut resources = Vec::new();
+    for document in documents {
+        let source = document
+            .iter()
+            .find_map(|line| line.strip_prefix("# Source: "))
+            .map(|source| source.trim().to_owned());
+        let is_empty = document.iter().all(|line| {
+            let line = line.trim();
+            line.is_empty() || line.starts_with('#')
+        });
+        if is_empty {
+            // empty document, e.g. a template disabled by the values
+            continue;
+        }
+
+        let object: serde_json::Value =
+            serde_yaml::from_str(&document.join("\n")).map_err(|e| {
+                anyhow!(
+                    "cannot parse document rendered from {}: {}",
+                    source.as_deref().unwrap_or("unknown source"),
+                    e
+                )
+            })?;
+        if object.is_null() {
+            continue;
+        }
+        resources.push(RenderedResource { source, object });
+    }
+
+    Ok(resources)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const RENDERED: &str = r#"---
+# Source: mychart/templates/serviceaccount.yaml
+apiVersion: v1
+kind: ServiceAccount
+metadata:
+  name: release-name-mychart
+---
+# Source: mychart/templates/disabled.yaml
+---
+# Source: mychart/templates/deployment.yaml
+apiVersion: apps/v1
+kind: Deployment
+metadata:
+  name: release-name-mychart
+  namespace: team-a
+spec:
+  replicas: 1
+"#;
+
+    #[test]
+    fn parse_helm_template_output() {
+        let resources = parse_rendered_manifests(RENDERED).unwrap();
+
+        assert_eq!(resources.len(), 2);
+        assert_eq!(
+            resources[0].source.as_deref(),
+            Some("mychart/templates/serviceaccount.yaml")
+        );
+        assert_eq!(resources[0].kind(), Some("ServiceAccount"));
+        assert_eq!(resources[0].namespace(), None);
+        assert_eq!(
+            resources[1].source.as_deref(),
+            Some("mychart/templates/deployment.yaml")
+        );
+        assert_eq!(resources[1].kind(), Some("Deployment"));
+        assert_eq!(resources[1].namespace(), Some("team-a"));
+    }
+
+    #[test]
+    fn parse_invalid_document() {
+        let err = parse_rendered_manifests("# Source: mychart/templates/broken.yaml\nfoo: [")
+            .unwrap_err();
+        assert!(err.to_string().contains("mychart/templates/broken.yaml"));
+    }
+
+    #[test]
+    fn synthesize_admission_request() {
+        let resources = parse_rendered_manifests(RENDERED).unwrap();
+
+        let request = resources[1].admission_request().unwrap();
+        assert_eq!(request["operation"], "CREATE");
+        assert_eq!(
+            request["kind"],
+            json!({"group": "apps", "version": "v1", "kind": "Deployment"})
+        );
+        assert_eq!(request["resource"]["resource"], "deployments");
+        assert_eq!(request["name"], "release-name-mychart");
+        assert_eq!(request["namespace"], "team-a");
+        assert_eq!(request["object"], resources[1].object);
+
+        let request = resources[0].admission_request().unwrap();
+        assert_eq!(request["kind"]["group"], "");
+        assert_eq!(request["kind"]["version"], "v1");
+    }
+
+    #[test]
+    fn synthesize_admission_request_without_kind() {
+        let resource = RenderedResource {
+            source: Some("mychart/templates/broken.yaml".to_string()),
+            object: json!({"apiVersion": "v1"}),
+        };
+        assert!(resource.admission_request().is_err());
+    }
+}
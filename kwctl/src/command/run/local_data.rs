@@ -1,7 +1,7 @@
 use std::{collections::HashMap, path::PathBuf};
 
 use anyhow::{anyhow, Result};
-use policy_evaluator::{policy_fetcher::PullDestination, policy_metadata::Metadata};
+use policy_evaluator::policy_metadata::Metadata;
 
 use crate::{
     backend::has_minimum_kubewarden_version,
@@ -48,31 +48,30 @@ async fn pull_all(
 ) -> Result<HashMap<String, PathBuf>> {
     let sources = cfg.sources.as_ref();
 
+    let mut uris: Vec<String> = Vec::new();
+    for uri in policy_definitions.iter().flat_map(|policy| policy.uris()) {
+        if !uris.contains(&uri) {
+            uris.push(uri);
+        }
+    }
+    let policies = pull::pull_all(&uris, sources, &cfg.fetcher_options).await?;
+
     let mut local_paths = HashMap::new();
+    for uri in uris {
+        let policy = policies
+            .get(&uri)
+            .ok_or_else(|| anyhow!("Policy {} has not been pulled", uri))?;
 
-    for policy_definition in policy_definitions {
-        for uri in policy_definition.uris() {
-            if local_paths.contains_key(&uri) {
-                continue;
-            }
-            let policy = pull::pull(&uri, sources, PullDestination::MainStore).await?;
-
-            if let Some(digests) = cfg.verified_manifest_digests.as_ref() {
-                let digest = digests
-                    .get(&uri)
-                    .ok_or_else(|| anyhow!("No digest found for {}", uri))?;
-
-                verify::verify_local_checksum(
-                    &policy,
-                    sources,
-                    digest,
-                    cfg.sigstore_trust_root.clone(),
-                )
-                .await?
-            }
+        if let Some(digests) = cfg.verified_manifest_digests.as_ref() {
+            let digest = digests
+                .get(&uri)
+                .ok_or_else(|| anyhow!("No digest found for {}", uri))?;
 
-            local_paths.insert(uri, policy.local_path);
+            verify::verify_local_checksum(policy, sources, digest, cfg.sigstore_trust_root.clone())
+                .await?
         }
+
+        local_paths.insert(uri, policy.local_path.clone());
     }
     Ok(local_paths)
 }
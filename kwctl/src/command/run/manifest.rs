@@ -0,0 +1,54 @@
+use anyhow::Result;
+use policy_evaluator::policy_fetcher::{sources::Sources, PullDestination};
+use tracing::{info, warn};
+
+use crate::pull;
+
+/// Compare the module run locally with the one referenced by the manifest given with
+/// `--settings-from-manifest`, warning when they differ. The deployed module is pulled
+/// to compute its digest: when it cannot be pulled, the comparison is skipped.
+pub(crate) async fn compare_with_deployed_module(
+    local_module: &str,
+    deployed_module: &str,
+    sources: Option<&Sources>,
+) -> Result<()> {
+    if local_module == deployed_module {
+        return Ok(());
+    }
+
+    let local_policy = pull::pull(local_module, sources, PullDestination::MainStore).await?;
+    let deployed_policy = match pull::pull(deployed_module, sources, PullDestination::MainStore)
+        .await
+    {
+        Ok(policy) => policy,
+        Err(e) => {
+            warn!(
+                deployed_module,
+                error = %e,
+                "cannot pull the module referenced by the manifest, the local module cannot be compared with it"
+            );
+            return Ok(());
+        }
+    };
+
+    let local_digest = local_policy.digest()?;
+    let deployed_digest = deployed_policy.digest()?;
+    if local_digest == deployed_digest {
+        info!(
+            local_module,
+            deployed_module,
+            digest = local_digest.as_str(),
+            "the local module is the one referenced by the manifest"
+        );
+    } else {
+        warn!(
+            local_module,
+            local_digest = local_digest.as_str(),
+            deployed_module,
+            deployed_digest = deployed_digest.as_str(),
+            "the local module differs from the one referenced by the manifest, the results may not match the ones of the cluster"
+        );
+    }
+
+    Ok(())
+}
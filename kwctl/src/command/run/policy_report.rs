@@ -0,0 +1,398 @@
+use std::collections::BTreeMap;
+
+use policy_evaluator::{
+    constants::{KUBEWARDEN_ANNOTATION_POLICY_CATEGORY, KUBEWARDEN_ANNOTATION_POLICY_SEVERITY},
+    policy_metadata::Metadata,
+};
+use serde::Serialize;
+use time::OffsetDateTime;
+
+use crate::command::run::helm::RenderedResource;
+
+/// The API version of the PolicyReport and ClusterPolicyReport resources defined by the
+/// Kubernetes Policy working group
+pub(crate) const POLICY_REPORT_API_VERSION: &str = "wgpolicyk8s.io/v1alpha2";
+
+/// The value of the `source` field of all the results produced by kwctl
+const POLICY_REPORT_SOURCE: &str = "kubewarden";
+
+/// The outcome of a policy evaluation, as defined by the PolicyReport CRD
+#[derive(Serialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub(crate) enum PolicyResult {
+    Pass,
+    Fail,
+}
+
+/// Reference to the resource a result is about
+#[derive(Serialize, Debug, Clone, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct ObjectReference {
+    pub api_version: Option<String>,
+    pub kind: Option<String>,
+    pub name: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub namespace: Option<String>,
+}
+
+impl From<&RenderedResource> for ObjectReference {
+    fn from(resource: &RenderedResource) -> Self {
+        Self {
+            api_version: resource
+                .object
+                .get("apiVersion")
+                .and_then(serde_json::Value::as_str)
+                .map(str::to_owned),
+            kind: resource.kind().map(str::to_owned),
+            name: resource.name().map(str::to_owned),
+            namespace: resource.namespace().map(str::to_owned),
+        }
+    }
+}
+
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub(crate) struct Timestamp {
+    pub seconds: i64,
+    pub nanos: i32,
+}
+
+impl From<OffsetDateTime> for Timestamp {
+    fn from(time: OffsetDateTime) -> Self {
+        Self {
+            seconds: time.unix_timestamp(),
+            nanos: time.nanosecond() as i32,
+        }
+    }
+}
+
+/// A single entry of the `results` section of a PolicyReport
+#[derive(Serialize, Debug, Clone, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct PolicyReportResult {
+    pub source: String,
+    pub policy: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub category: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub severity: Option<String>,
+    pub result: PolicyResult,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub message: Option<String>,
+    pub scored: bool,
+    pub timestamp: Timestamp,
+    pub resources: Vec<ObjectReference>,
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub properties: BTreeMap<String, String>,
+}
+
+impl PolicyReportResult {
+    /// Build the result of the evaluation of `resource` against the given policy.
+    /// The category and the severity are taken from the annotations of the policy
+    /// metadata, when available.
+    pub fn new(
+        resource: &RenderedResource,
+        policy: &str,
+        metadata: Option<&Metadata>,
+        allowed: bool,
+        message: Option<String>,
+    ) -> Self {
+        let annotation = |key: &str| {
+            metadata
+                .and_then(|metadata| metadata.annotations.as_ref())
+                .and_then(|annotations| annotations.get(key))
+        };
+
+        let mut properties = BTreeMap::new();
+        if let Some(source) = &resource.source {
+            properties.insert("helmTemplate".to_string(), source.clone());
+        }
+
+        Self {
+            source: POLICY_REPORT_SOURCE.to_string(),
+            policy: policy.to_owned(),
+            category: annotation(KUBEWARDEN_ANNOTATION_POLICY_CATEGORY).cloned(),
+            severity: annotation(KUBEWARDEN_ANNOTATION_POLICY_SEVERITY)
+                .and_then(|severity| policy_report_severity(severity)),
+            result: if allowed {
+                PolicyResult::Pass
+            } else {
+                PolicyResult::Fail
+            },
+            message,
+            scored: true,
+            timestamp: OffsetDateTime::now_utc().into(),
+            resources: vec![resource.into()],
+            properties,
+        }
+    }
+}
+
+/// Map the severity of a Kubewarden policy to one of the values allowed by the
+/// PolicyReport CRD. Unknown severities are dropped, otherwise the report would be
+/// rejected by the API server.
+fn policy_report_severity(severity: &str) -> Option<String> {
+    let severity = match severity.trim().to_lowercase().as_str() {
+        "critical" => "critical",
+        "high" => "high",
+        "medium" | "moderate" => "medium",
+        "low" => "low",
+        "info" | "informational" => "info",
+        _ => return None,
+    };
+    Some(severity.to_string())
+}
+
+#[derive(Serialize, Debug, Clone, Default, PartialEq)]
+pub(crate) struct PolicyReportSummary {
+    pub pass: u32,
+    pub fail: u32,
+    pub warn: u32,
+    pub error: u32,
+    pub skip: u32,
+}
+
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub(crate) struct PolicyReportMetadata {
+    pub name: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub namespace: Option<String>,
+    pub labels: BTreeMap<String, String>,
+}
+
+/// A PolicyReport, or a ClusterPolicyReport when it's about cluster wide resources
+#[derive(Serialize, Debug, Clone, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct PolicyReport {
+    pub api_version: String,
+    pub kind: String,
+    pub metadata: PolicyReportMetadata,
+    pub summary: PolicyReportSummary,
+    pub results: Vec<PolicyReportResult>,
+}
+
+/// The resource an admission request is about: the object being created or updated,
+/// or the one being deleted. Both AdmissionReview and AdmissionRequest objects are
+/// accepted
+pub(crate) fn requested_resource(request: &serde_json::Value) -> RenderedResource {
+    let request = match request.get("kind").and_then(serde_json::Value::as_str) {
+        Some("AdmissionReview") => request.get("request").unwrap_or(request),
+        _ => request,
+    };
+    let object = request
+        .get("object")
+        .filter(|object| !object.is_null())
+        .or_else(|| request.get("oldObject"))
+        .cloned()
+        .unwrap_or_default();
+
+    RenderedResource {
+        source: None,
+        object,
+    }
+}
+
+/// Group the results by the Namespace of the resources they are about. A PolicyReport
+/// is created for each Namespace, while the results about cluster wide resources end up
+/// inside of a ClusterPolicyReport.
+///
+/// All the reports are named after `name`.
+pub(crate) fn build_policy_reports(
+    name: &str,
+    results: Vec<PolicyReportResult>,
+) -> Vec<PolicyReport> {
+    let mut results_by_namespace: BTreeMap<Option<String>, Vec<PolicyReportResult>> =
+        BTreeMap::new();
+    for result in results {
+        let namespace = result
+            .resources
+            .first()
+            .and_then(|resource| resource.namespace.clone());
+        results_by_namespace
+            .entry(namespace)
+            .or_default()
+            .push(result);
+    }
+
+    results_by_namespace
+        .into_iter()
+        .map(|(namespace, results)| {
+            let mut summary = PolicyReportSummary::default();
+            for result in &results {
+                match result.result {
+                    PolicyResult::Pass => summary.pass += 1,
+                    PolicyResult::Fail => summary.fail += 1,
+                }
+            }
+            let kind = if namespace.is_some() {
+                "PolicyReport"
+            } else {
+                "ClusterPolicyReport"
+            };
+
+            PolicyReport {
+                api_version: POLICY_REPORT_API_VERSION.to_string(),
+                kind: kind.to_string(),
+                metadata: PolicyReportMetadata {
+                    name: name.to_owned(),
+                    namespace,
+                    labels: BTreeMap::from([(
+                        "app.kubernetes.io/managed-by".to_string(),
+                        "kubewarden".to_string(),
+                    )]),
+                },
+                summary,
+                results,
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use rstest::rstest;
+    use serde_json::json;
+
+    fn resource(namespace: Option<&str>) -> RenderedResource {
+        let mut object = json!({
+            "apiVersion": "apps/v1",
+            "kind": "Deployment",
+            "metadata": { "name": "nginx" },
+        });
+        if let Some(namespace) = namespace {
+            object["metadata"]["namespace"] = json!(namespace);
+        }
+        RenderedResource {
+            source: Some("mychart/templates/deployment.yaml".to_string()),
+            object,
+        }
+    }
+
+    fn metadata(severity: &str, category: &str) -> Metadata {
+        Metadata {
+            annotations: Some(BTreeMap::from([
+                (
+                    KUBEWARDEN_ANNOTATION_POLICY_SEVERITY.to_string(),
+                    severity.to_string(),
+                ),
+                (
+                    KUBEWARDEN_ANNOTATION_POLICY_CATEGORY.to_string(),
+                    category.to_string(),
+                ),
+            ])),
+            ..Default::default()
+        }
+    }
+
+    #[rstest]
+    #[case::critical("critical", Some("critical"))]
+    #[case::uppercase("HIGH", Some("high"))]
+    #[case::moderate("moderate", Some("medium"))]
+    #[case::informational("informational", Some("info"))]
+    #[case::unknown("whatever", None)]
+    fn map_severity(#[case] severity: &str, #[case] expected: Option<&str>) {
+        assert_eq!(
+            policy_report_severity(severity).as_deref(),
+            expected,
+            "unexpected mapping of {severity}"
+        );
+    }
+
+    #[rstest]
+    #[case::admission_review(json!({
+        "apiVersion": "admission.k8s.io/v1",
+        "kind": "AdmissionReview",
+        "request": {
+            "operation": "CREATE",
+            "object": { "kind": "Pod", "metadata": { "name": "nginx", "namespace": "default" } }
+        }
+    }))]
+    #[case::deletion(json!({
+        "operation": "DELETE",
+        "object": null,
+        "oldObject": { "kind": "Pod", "metadata": { "name": "nginx", "namespace": "default" } }
+    }))]
+    fn resource_of_the_request(#[case] request: serde_json::Value) {
+        let resource = requested_resource(&request);
+
+        assert_eq!(resource.kind(), Some("Pod"));
+        assert_eq!(resource.name(), Some("nginx"));
+        assert_eq!(resource.namespace(), Some("default"));
+        assert_eq!(resource.source, None);
+    }
+
+    #[test]
+    fn result_uses_policy_metadata() {
+        let metadata = metadata("medium", "PSP");
+        let result = PolicyReportResult::new(
+            &resource(Some("team-a")),
+            "privileged-pods",
+            Some(&metadata),
+            false,
+            Some("privileged containers are not allowed".to_string()),
+        );
+
+        assert_eq!(result.source, "kubewarden");
+        assert_eq!(result.result, PolicyResult::Fail);
+        assert_eq!(result.severity.as_deref(), Some("medium"));
+        assert_eq!(result.category.as_deref(), Some("PSP"));
+        assert_eq!(
+            result.resources,
+            vec![ObjectReference {
+                api_version: Some("apps/v1".to_string()),
+                kind: Some("Deployment".to_string()),
+                name: Some("nginx".to_string()),
+                namespace: Some("team-a".to_string()),
+            }]
+        );
+        assert_eq!(
+            result.properties.get("helmTemplate").map(String::as_str),
+            Some("mychart/templates/deployment.yaml")
+        );
+
+        let result = PolicyReportResult::new(&resource(None), "policy-group", None, true, None);
+        assert_eq!(result.result, PolicyResult::Pass);
+        assert!(result.severity.is_none());
+        assert!(result.category.is_none());
+    }
+
+    #[test]
+    fn reports_are_grouped_by_namespace() {
+        let results = vec![
+            PolicyReportResult::new(&resource(Some("team-a")), "policy", None, true, None),
+            PolicyReportResult::new(&resource(Some("team-a")), "policy", None, false, None),
+            PolicyReportResult::new(&resource(Some("team-b")), "policy", None, true, None),
+            PolicyReportResult::new(&resource(None), "policy", None, false, None),
+        ];
+
+        let reports = build_policy_reports("kwctl-mychart", results);
+        assert_eq!(reports.len(), 3);
+
+        let cluster_report = &reports[0];
+        assert_eq!(cluster_report.kind, "ClusterPolicyReport");
+        assert_eq!(cluster_report.metadata.namespace, None);
+        assert_eq!(cluster_report.summary.fail, 1);
+
+        let team_a = &reports[1];
+        assert_eq!(team_a.kind, "PolicyReport");
+        assert_eq!(team_a.api_version, POLICY_REPORT_API_VERSION);
+        assert_eq!(team_a.metadata.name, "kwctl-mychart");
+        assert_eq!(team_a.metadata.namespace.as_deref(), Some("team-a"));
+        assert_eq!(team_a.results.len(), 2);
+        assert_eq!(
+            team_a.summary,
+            PolicyReportSummary {
+                pass: 1,
+                fail: 1,
+                ..Default::default()
+            }
+        );
+
+        let serialized = serde_json::to_value(&reports[2]).unwrap();
+        assert_eq!(serialized["apiVersion"], POLICY_REPORT_API_VERSION);
+        assert_eq!(serialized["results"][0]["result"], "pass");
+        assert_eq!(serialized["results"][0]["scored"], true);
+        assert!(serialized["results"][0]["timestamp"]["seconds"].is_i64());
+    }
+}
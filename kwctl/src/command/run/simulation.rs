@@ -0,0 +1,431 @@
+//! Simulation of the chain of admission webhooks the requests go through.
+//!
+//! The API server invokes the mutating webhooks first, one after the other, each one
+//! receiving the object changed by the previous ones. The validating webhooks are
+//! invoked afterwards, against the final object. A mutation can hence cause the
+//! rejection of a request that would otherwise be accepted.
+//!
+//! The simulation evaluates the policies allowed to mutate the requests, in the order
+//! they are defined, applying their patches. Then the other policies are evaluated
+//! against the mutated request, the rejections that are caused by the mutations are
+//! reported as new violations.
+
+use std::mem;
+
+use anyhow::{anyhow, Result};
+use base64::{engine::general_purpose, Engine as _};
+use policy_evaluator::admission_response::AdmissionResponse;
+use serde::Serialize;
+use tracing::{info, warn};
+
+use crate::{
+    command::run::{evaluate, local_data::LocalData},
+    config::{policy_definition::PolicyDefinition, pull_and_run::PullAndRunSettings},
+    errors::{ErrorKind, KwctlError},
+};
+
+/// The fields of an object the API server doesn't allow the mutating webhooks to change
+const IMMUTABLE_FIELDS: &[&str] = &[
+    "/apiVersion",
+    "/kind",
+    "/metadata/name",
+    "/metadata/namespace",
+    "/metadata/uid",
+];
+
+/// The step of the admission chain a policy takes part in
+#[derive(Serialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "camelCase")]
+enum Phase {
+    Mutation,
+    Validation,
+}
+
+/// The outcome of the evaluation of a request by one of the policies of the chain
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct SimulationReport {
+    /// Position of the request inside of the file, starting from 1
+    document: usize,
+    policy: String,
+    phase: Phase,
+    /// Whether the request is allowed, after the mutations of the previous policies
+    allowed: bool,
+    /// Whether the policy mutated the request
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    mutated: bool,
+    /// Whether the request is allowed without the mutations, for the policies
+    /// evaluated during the validation phase
+    #[serde(skip_serializing_if = "Option::is_none")]
+    allowed_without_mutations: Option<bool>,
+    /// The request is rejected only because of the mutations
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    new_violation: bool,
+    /// The immutable fields changed by the patch of the policy. The patch is not applied
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    unsafe_changes: Vec<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    message: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+}
+
+impl SimulationReport {
+    fn new(document: usize, policy: &PolicyDefinition, phase: Phase) -> Self {
+        Self {
+            document,
+            policy: policy.to_string(),
+            phase,
+            allowed: false,
+            mutated: false,
+            allowed_without_mutations: None,
+            new_violation: false,
+            unsafe_changes: Vec::new(),
+            message: None,
+            error: None,
+        }
+    }
+}
+
+/// The totals of the simulation, used to decide the outcome of the command
+#[derive(Debug, Default)]
+struct SimulationSummary {
+    evaluations: usize,
+    rejections: usize,
+    new_violations: usize,
+    unsafe_mutations: usize,
+    failures: usize,
+}
+
+impl SimulationSummary {
+    fn add(&mut self, report: &SimulationReport) {
+        self.evaluations += 1;
+        if report.error.is_some() {
+            self.failures += 1;
+        } else if !report.allowed {
+            self.rejections += 1;
+        }
+        if report.new_violation {
+            self.new_violations += 1;
+        }
+        if !report.unsafe_changes.is_empty() {
+            self.unsafe_mutations += 1;
+        }
+    }
+}
+
+/// Evaluate all the requests against the chain of policies: the policies allowed to
+/// mutate the requests first, then the other ones against the mutated requests. A
+/// report is printed on STDOUT for each request and policy. An error is returned when
+/// at least one of the requests is rejected, is mutated in an unsafe way or cannot
+/// be evaluated.
+pub(crate) async fn exec(
+    policy_definitions: &[PolicyDefinition],
+    mut pull_and_run_settings: PullAndRunSettings,
+) -> Result<()> {
+    let local_data = LocalData::new(policy_definitions, &pull_and_run_settings).await?;
+
+    let (mutating, validating): (Vec<&PolicyDefinition>, Vec<&PolicyDefinition>) =
+        policy_definitions
+            .iter()
+            .partition(|policy| policy.get_policy_allowed_to_mutate());
+    if mutating.is_empty() {
+        warn!("none of the policies is allowed to mutate the requests, no mutation is going to be simulated");
+    }
+
+    let requests = mem::take(&mut pull_and_run_settings.requests);
+    info!(
+        requests = requests.len(),
+        mutating_policies = mutating.len(),
+        validating_policies = validating.len(),
+        "simulating the admission chain"
+    );
+
+    let mut summary = SimulationSummary::default();
+    for (index, original_request) in requests.into_iter().enumerate() {
+        let document = index + 1;
+        let mut request = original_request.clone();
+        let mut rejected = false;
+
+        for policy in &mutating {
+            pull_and_run_settings.request = request.clone();
+            let mut report = SimulationReport::new(document, policy, Phase::Mutation);
+            match evaluate(policy, &pull_and_run_settings, &local_data).await {
+                Ok(response) => {
+                    report.allowed = response.allowed;
+                    report.message = rejection_message(&response);
+                    if response.allowed {
+                        match patched_request(&request, &response) {
+                            Ok(Some(patched)) => {
+                                report.unsafe_changes = unsafe_changes(&request, &patched);
+                                if report.unsafe_changes.is_empty() {
+                                    report.mutated = true;
+                                    request = patched;
+                                } else {
+                                    warn!(
+                                        document,
+                                        policy = report.policy.as_str(),
+                                        changes = ?report.unsafe_changes,
+                                        "the policy changes immutable fields, the patch is not applied"
+                                    );
+                                }
+                            }
+                            Ok(None) => {}
+                            Err(e) => report.error = Some(e.to_string()),
+                        }
+                    }
+                }
+                Err(e) => report.error = Some(e.to_string()),
+            }
+
+            summary.add(&report);
+            println!("{}", serde_json::to_string(&report)?);
+            if report.error.is_some() || !report.allowed {
+                // the API server stops at the first webhook rejecting the request
+                rejected = true;
+                break;
+            }
+        }
+        if rejected {
+            warn!(
+                document,
+                "request rejected during the mutation phase, the validation phase is skipped"
+            );
+            continue;
+        }
+
+        let mutated = request != original_request;
+        for policy in &validating {
+            let mut report = SimulationReport::new(document, policy, Phase::Validation);
+
+            pull_and_run_settings.request = request.clone();
+            match evaluate(policy, &pull_and_run_settings, &local_data).await {
+                Ok(response) => {
+                    report.allowed = response.allowed;
+                    report.message = rejection_message(&response);
+                }
+                Err(e) => report.error = Some(e.to_string()),
+            }
+
+            if mutated && report.error.is_none() && !report.allowed {
+                pull_and_run_settings.request = original_request.clone();
+                match evaluate(policy, &pull_and_run_settings, &local_data).await {
+                    Ok(response) => {
+                        report.allowed_without_mutations = Some(response.allowed);
+                        report.new_violation = response.allowed;
+                    }
+                    Err(e) => report.error = Some(e.to_string()),
+                }
+            } else if report.error.is_none() {
+                report.allowed_without_mutations = Some(report.allowed);
+            }
+
+            if report.new_violation {
+                warn!(
+                    document,
+                    policy = report.policy.as_str(),
+                    "the request is rejected because of the mutations"
+                );
+            }
+            summary.add(&report);
+            println!("{}", serde_json::to_string(&report)?);
+        }
+    }
+
+    info!(
+        evaluations = summary.evaluations,
+        rejections = summary.rejections,
+        new_violations = summary.new_violations,
+        unsafe_mutations = summary.unsafe_mutations,
+        failures = summary.failures,
+        "admission chain simulated"
+    );
+    if summary.rejections + summary.unsafe_mutations + summary.failures > 0 {
+        let kind = if summary.failures > 0 {
+            ErrorKind::EvaluationFailed
+        } else {
+            ErrorKind::Rejected
+        };
+        return Err(KwctlError::new(
+            kind,
+            format!(
+                "{} of {} evaluations rejected, {} of them because of the mutations, {} unsafe mutations, {} failed",
+                summary.rejections,
+                summary.evaluations,
+                summary.new_violations,
+                summary.unsafe_mutations,
+                summary.failures
+            ),
+        )
+        .into());
+    }
+
+    Ok(())
+}
+
+fn rejection_message(response: &AdmissionResponse) -> Option<String> {
+    if response.allowed {
+        return None;
+    }
+    response
+        .status
+        .as_ref()
+        .and_then(|status| status.message.clone())
+}
+
+/// The object targeted by the request, which can be either an `AdmissionReview` or
+/// an `AdmissionRequest`
+fn request_object(request: &serde_json::Value) -> Option<&serde_json::Value> {
+    if request.get("kind").and_then(serde_json::Value::as_str) == Some("AdmissionReview") {
+        request.pointer("/request/object")
+    } else {
+        request.get("object")
+    }
+}
+
+/// Apply the patch of the response to the object of the request. Returns `None`
+/// when the response doesn't have a patch
+fn patched_request(
+    request: &serde_json::Value,
+    response: &AdmissionResponse,
+) -> Result<Option<serde_json::Value>> {
+    let Some(patch) = &response.patch else {
+        return Ok(None);
+    };
+    let patch = general_purpose::STANDARD
+        .decode(patch)
+        .map_err(|e| anyhow!("the patch is not base64 encoded: {e}"))?;
+    let patch: json_patch::Patch = serde_json::from_slice(&patch)
+        .map_err(|e| anyhow!("the patch is not a valid JSON patch: {e}"))?;
+
+    let mut patched = request.clone();
+    let object =
+        if patched.get("kind").and_then(serde_json::Value::as_str) == Some("AdmissionReview") {
+            patched.pointer_mut("/request/object")
+        } else {
+            patched.get_mut("object")
+        }
+        .ok_or_else(|| anyhow!("the request doesn't have an object to be patched"))?;
+    json_patch::patch(object, &patch).map_err(|e| anyhow!("cannot apply the patch: {e}"))?;
+
+    Ok(Some(patched))
+}
+
+/// The immutable fields of the object of the request that differ after the patch
+fn unsafe_changes(request: &serde_json::Value, patched: &serde_json::Value) -> Vec<String> {
+    let (Some(object), Some(patched_object)) = (request_object(request), request_object(patched))
+    else {
+        return Vec::new();
+    };
+    IMMUTABLE_FIELDS
+        .iter()
+        .filter(|field| object.pointer(field) != patched_object.pointer(field))
+        .map(|field| field.trim_start_matches('/').replace('/', "."))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn request() -> serde_json::Value {
+        json!({
+            "uid": "uid",
+            "operation": "CREATE",
+            "object": {
+                "apiVersion": "v1",
+                "kind": "Pod",
+                "metadata": { "name": "nginx", "namespace": "default" },
+            },
+        })
+    }
+
+    fn response(patch: Option<serde_json::Value>) -> AdmissionResponse {
+        AdmissionResponse {
+            uid: "uid".to_owned(),
+            allowed: true,
+            patch: patch.map(|patch| general_purpose::STANDARD.encode(patch.to_string())),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn patch_admission_request() {
+        let patched = patched_request(
+            &request(),
+            &response(Some(json!([
+                {"op": "add", "path": "/metadata/labels", "value": {"team": "a"}},
+            ]))),
+        )
+        .unwrap()
+        .unwrap();
+
+        assert_eq!(patched["object"]["metadata"]["labels"]["team"], "a");
+        assert!(unsafe_changes(&request(), &patched).is_empty());
+        assert!(patched_request(&request(), &response(None))
+            .unwrap()
+            .is_none());
+    }
+
+    #[test]
+    fn patch_admission_review() {
+        let review = json!({
+            "apiVersion": "admission.k8s.io/v1",
+            "kind": "AdmissionReview",
+            "request": request(),
+        });
+
+        let patched = patched_request(
+            &review,
+            &response(Some(json!([
+                {"op": "replace", "path": "/metadata/name", "value": "apache"},
+            ]))),
+        )
+        .unwrap()
+        .unwrap();
+
+        assert_eq!(patched["request"]["object"]["metadata"]["name"], "apache");
+        assert_eq!(unsafe_changes(&review, &patched), vec!["metadata.name"]);
+    }
+
+    #[test]
+    fn invalid_patch() {
+        let mut invalid = response(None);
+        invalid.patch = Some("not base64!".to_owned());
+        assert!(patched_request(&request(), &invalid).is_err());
+
+        let missing_path = response(Some(json!([
+            {"op": "replace", "path": "/spec/containers/0/image", "value": "nginx"},
+        ])));
+        assert!(patched_request(&request(), &missing_path).is_err());
+    }
+
+    #[test]
+    fn summary() {
+        let policy = PolicyDefinition::PolicyGroup {
+            id: "group".to_owned(),
+            policy_mode: Default::default(),
+            policy_members: Default::default(),
+            expression: "true".to_owned(),
+            message: "rejected".to_owned(),
+        };
+        let mut summary = SimulationSummary::default();
+
+        let mut report = SimulationReport::new(1, &policy, Phase::Validation);
+        report.allowed = true;
+        summary.add(&report);
+        let mut report = SimulationReport::new(1, &policy, Phase::Validation);
+        report.new_violation = true;
+        summary.add(&report);
+        let mut report = SimulationReport::new(2, &policy, Phase::Mutation);
+        report.error = Some("boom".to_owned());
+        summary.add(&report);
+
+        assert_eq!(summary.evaluations, 3);
+        assert_eq!(summary.rejections, 1);
+        assert_eq!(summary.new_violations, 1);
+        assert_eq!(summary.failures, 1);
+        assert_eq!(summary.unsafe_mutations, 0);
+    }
+}
@@ -335,6 +335,39 @@ impl PolicyDefinition {
         Ok(policies)
     }
 
+    /// Reads the AdmissionPolicy or ClusterAdmissionPolicy defined inside of the given
+    /// manifest. The policy keeps its settings, mode and context-aware resources, while
+    /// its module is replaced by the given one, when provided.
+    ///
+    /// Returns the policy, together with the module referenced by the manifest
+    pub fn from_manifest(
+        manifest_path: &str,
+        module: Option<String>,
+    ) -> Result<(PolicyDefinition, String)> {
+        let mut policies = PolicyDefinition::from_yaml_file(manifest_path)?;
+        if policies.len() != 1 {
+            return Err(anyhow!(
+                "the manifest {:?} must define exactly one policy, found {}",
+                manifest_path,
+                policies.len()
+            ));
+        }
+        let mut policy = policies.remove(0);
+
+        let PolicyDefinition::Policy { uri, .. } = &mut policy else {
+            return Err(anyhow!(
+                "the manifest {:?} defines a policy group, run the manifest directly instead",
+                manifest_path
+            ));
+        };
+        let deployed_module = match module {
+            Some(module) => std::mem::replace(uri, module),
+            None => uri.clone(),
+        };
+
+        Ok((policy, deployed_module))
+    }
+
     /// Creates a PolicyDefinition from CLI arguments.
     ///
     /// This will always create an individual PolicyDefinition
@@ -426,6 +459,98 @@ mod tests {
     use policy_evaluator::kubewarden_policy_sdk::crd::policies::common::PolicyMode as PolicyModeSdk;
     use serde_json::json;
 
+    fn manifest(contents: &str) -> tempfile::NamedTempFile {
+        let mut file = tempfile::Builder::new().suffix(".yaml").tempfile().unwrap();
+        std::io::Write::write_all(&mut file, contents.as_bytes()).unwrap();
+        file
+    }
+
+    #[test]
+    fn policy_definition_from_manifest() {
+        let file = manifest(
+            r#"
+apiVersion: policies.kubewarden.io/v1
+kind: ClusterAdmissionPolicy
+metadata:
+  name: safe-labels
+spec:
+  module: registry://ghcr.io/kubewarden/policies/safe-labels:v1.0.0
+  mode: Monitor
+  mutating: false
+  contextAwareResources:
+  - apiVersion: v1
+    kind: Namespace
+  settings:
+    denied_labels:
+    - owner
+"#,
+        );
+        let path = file.path().to_str().unwrap();
+
+        let (policy, deployed_module) =
+            PolicyDefinition::from_manifest(path, Some("file:///tmp/policy.wasm".to_string()))
+                .unwrap();
+
+        assert_eq!(
+            deployed_module,
+            "registry://ghcr.io/kubewarden/policies/safe-labels:v1.0.0"
+        );
+        match policy {
+            PolicyDefinition::Policy {
+                id,
+                uri,
+                settings,
+                policy_mode,
+                ctx_aware_cfg,
+                ..
+            } => {
+                assert_eq!(id, "safe-labels");
+                assert_eq!(uri, "file:///tmp/policy.wasm");
+                assert_eq!(policy_mode, PolicyMode::Monitor);
+                assert_eq!(
+                    settings,
+                    PolicySettings::try_from(&json!({"denied_labels": ["owner"]})).unwrap()
+                );
+                assert_eq!(
+                    ctx_aware_cfg,
+                    ContextAwareConfiguration::AllowList(BTreeSet::from([ContextAwareResource {
+                        api_version: "v1".to_string(),
+                        kind: "Namespace".to_string(),
+                    }]))
+                );
+            }
+            _ => panic!("Expected Individual PolicyDefinition"),
+        }
+
+        // the module of the manifest is run when no other module is given
+        let (policy, _) = PolicyDefinition::from_manifest(path, None).unwrap();
+        assert_eq!(policy.uris(), HashSet::from([deployed_module]));
+    }
+
+    #[test]
+    fn policy_definition_from_manifest_with_policy_group() {
+        let file = manifest(
+            r#"
+apiVersion: policies.kubewarden.io/v1
+kind: AdmissionPolicyGroup
+metadata:
+  name: group
+spec:
+  expression: "a()"
+  message: "rejected"
+  policies:
+    a:
+      module: registry://ghcr.io/kubewarden/policies/safe-labels:v1.0.0
+      settings: {}
+"#,
+        );
+
+        let error = PolicyDefinition::from_manifest(file.path().to_str().unwrap(), None)
+            .unwrap_err()
+            .to_string();
+        assert!(error.contains("defines a policy group"), "{error}");
+    }
+
     #[test]
     fn policy_definition_from_admission_policy() {
         use policy_evaluator::kubewarden_policy_sdk::crd::policies::admission_policy::AdmissionPolicySpec;
@@ -666,6 +791,8 @@ mod tests {
                             settings: PolicySettings::try_from(&pgm_1.settings.0)
                                 .expect("Failed to convert settings for member 1"),
                             ctx_aware_resources_allow_list: pgm_1_expected_context_aware_resources,
+                            kubernetes_service_account: None,
+                            policy_stable_id: None,
                         },
                     },
                 ),
@@ -677,6 +804,8 @@ mod tests {
                             settings: PolicySettings::try_from(&pgm_2.settings.0)
                                 .expect("Failed to convert settings for member 2"),
                             ctx_aware_resources_allow_list: BTreeSet::new(),
+                            kubernetes_service_account: None,
+                            policy_stable_id: None,
                         },
                     },
                 ),
@@ -1,41 +1,63 @@
 use std::{
-    collections::{HashMap, HashSet},
+    collections::{BTreeMap, HashMap, HashSet},
     fs,
     io::{self, Read},
-    path::PathBuf,
+    path::{Path, PathBuf},
     str::FromStr,
     sync::Arc,
+    time::SystemTime,
 };
 
 use anyhow::{anyhow, Result};
 use clap::ArgMatches;
 use policy_evaluator::policy_fetcher::{
-    sigstore::trust::ManualTrustRoot, sources::Sources, verify::config::LatestVerificationConfig,
+    download::FetcherOptions, sigstore::trust::ManualTrustRoot, sources::Sources,
+    verify::config::LatestVerificationConfig,
 };
+use serde::Deserialize;
 use tracing::info;
 
 use crate::{
-    callback_handler,
+    callback_handler::{self, context::ContextFixtures, observer::HostCapabilitiesObserver},
     config::{
         policy_definition::PolicyDefinition,
         sources::remote_server_options,
         verification::{build_sigstore_trust_root, build_verification_options},
         HostCapabilitiesMode,
     },
+    scaffold::ApiResourceCatalog,
     verify,
 };
 
 #[derive(Default)]
 pub(crate) struct PullAndRunSettings {
     pub sources: Option<Sources>,
+    /// How the policies are pulled: how many of them at the same time, and how
+    /// many times a pull throttled by the registry, or failed because of a
+    /// transient error, is retried
+    pub fetcher_options: FetcherOptions,
     pub request: serde_json::Value,
+    /// All the requests found inside of the request file, which can hold multiple
+    /// YAML documents or JSON Lines. `request` is the first one of them
+    pub requests: Vec<serde_json::Value>,
     /// When verification is enabled, the map is populated with:
     /// - key: the policy URI
     /// - value: the digest of the verified manifest
     pub verified_manifest_digests: Option<HashMap<String, String>>,
     pub sigstore_trust_root: Option<Arc<ManualTrustRoot<'static>>>,
+    /// The verification config provided by the user, this is used both to verify
+    /// the policies and by the policies that verify images against the host config
+    pub verification_config: Option<LatestVerificationConfig>,
     pub enable_wasmtime_cache: bool,
     pub host_capabilities_mode: HostCapabilitiesMode,
+    /// When set, the clock of the OPA and Gatekeeper policies is pinned to this instant
+    pub pinned_clock: Option<SystemTime>,
+    /// When set, the host capabilities requested by the policies are recorded
+    pub host_capabilities_observer: Option<HostCapabilitiesObserver>,
+    /// The directories of the host given to the WASI policies:
+    /// - key: the path inside of the policy
+    /// - value: the directory of the host
+    pub preopened_dirs: BTreeMap<String, PathBuf>,
 }
 
 pub(crate) fn parse_policy_definitions(matches: &ArgMatches) -> Result<Vec<PolicyDefinition>> {
@@ -62,50 +84,94 @@ pub(crate) fn parse_policy_definitions(matches: &ArgMatches) -> Result<Vec<Polic
     Ok(vec![PolicyDefinition::from_cli(matches)?])
 }
 
+/// Parse the policy defined inside of the manifest given with `--settings-from-manifest`.
+/// The module given on the command line, when provided, is run instead of the one
+/// referenced by the manifest.
+///
+/// Returns the policy, together with the module referenced by the manifest
+pub(crate) fn parse_manifest_policy_definition(
+    matches: &ArgMatches,
+    manifest_path: &str,
+) -> Result<(PolicyDefinition, String)> {
+    let module = matches
+        .get_one::<String>("uri_or_sha_prefix_or_yaml_file")
+        .map(|uri| {
+            if uri.ends_with(".yaml") || uri.ends_with(".yml") {
+                return Err(anyhow!(
+                    "The --settings-from-manifest option requires a policy module, not a YAML file: {}",
+                    uri
+                ));
+            }
+            crate::utils::map_path_to_uri(uri).map_err(anyhow::Error::new)
+        })
+        .transpose()?;
+
+    PolicyDefinition::from_manifest(manifest_path, module)
+}
+
 pub(crate) async fn parse_pull_and_run_settings(
     matches: &ArgMatches,
     policy_definitions: &[PolicyDefinition],
 ) -> Result<PullAndRunSettings> {
-    let request_raw = match matches
+    // The request is not provided when the requests are synthesized by kwctl, e.g.
+    // when evaluating the resources of a Helm chart
+    let requests = match matches
         .get_one::<String>("request-path")
         .map(|s| s.as_str())
-        .unwrap()
     {
-        "-" => {
+        None => Vec::new(),
+        Some("-") => {
             let mut buffer = String::new();
             io::stdin()
                 .read_to_string(&mut buffer)
                 .map_err(|e| anyhow!("Error reading request from stdin: {}", e))?;
-            buffer
+            parse_requests(&buffer)
+                .map_err(|e| anyhow!("Error parsing request from stdin: {}", e))?
+        }
+        Some(request_path) => {
+            let request_raw = fs::read_to_string(request_path)
+                .map_err(|e| anyhow!("Error opening request file {}; {}", request_path, e))?;
+            parse_requests(&request_raw)
+                .map_err(|e| anyhow!("Error parsing request file {}: {}", request_path, e))?
         }
-        request_path => fs::read_to_string(request_path).map_err(|e| {
-            anyhow!(
-                "Error opening request file {}; {}",
-                matches.get_one::<String>("request-path").unwrap(),
-                e
-            )
-        })?,
     };
-    let request = serde_json::from_str::<serde_json::Value>(&request_raw)?;
+    let request = requests.first().cloned().unwrap_or(serde_json::Value::Null);
 
     let sources = remote_server_options(matches)
         .map_err(|e| anyhow!("Error getting remote server options: {}", e))?;
     let sigstore_trust_root = build_sigstore_trust_root(matches.to_owned()).await?;
+    let fetcher_options = FetcherOptions {
+        concurrency: matches
+            .get_one::<usize>("pull-concurrency")
+            .copied()
+            .unwrap_or(FetcherOptions::default().concurrency),
+        max_retries: matches
+            .get_one::<u32>("pull-max-retries")
+            .copied()
+            .unwrap_or(FetcherOptions::default().max_retries),
+        ..Default::default()
+    };
 
-    let verified_manifest_digests =
-        if let Some(verification_options) = build_verification_options(matches)? {
-            Some(
-                build_verified_manifest_digests(
-                    policy_definitions,
-                    &verification_options,
-                    &sources,
-                    sigstore_trust_root.clone(),
-                )
-                .await?,
+    let verification_config = build_verification_options(matches)?;
+    let verified_manifest_digests = if let Some(verification_options) = &verification_config {
+        Some(
+            build_verified_manifest_digests(
+                policy_definitions,
+                verification_options,
+                &sources,
+                sigstore_trust_root.clone(),
             )
-        } else {
-            None
-        };
+            .await?,
+        )
+    } else {
+        None
+    };
+
+    let preopened_dirs = matches
+        .get_many::<String>("preopened-dir")
+        .unwrap_or_default()
+        .map(|value| parse_preopened_dir(value))
+        .collect::<Result<BTreeMap<_, _>>>()?;
 
     let enable_wasmtime_cache = !matches
         .get_one::<bool>("disable-wasmtime-cache")
@@ -129,21 +195,94 @@ pub(crate) async fn parse_pull_and_run_settings(
             .map(|source| PathBuf::from_str(source).unwrap())
             .ok_or_else(|| anyhow!("Cannot parse 'replay-host-capabilities-interaction' file"))?;
 
-        info!(session_file = ?source, "host capabilities proxy enabled with replay mode");
+        let strict = matches
+            .get_one::<bool>("strict-replay")
+            .unwrap_or(&false)
+            .to_owned();
+
+        info!(session_file = ?source, strict, "host capabilities proxy enabled with replay mode");
+        host_capabilities_mode =
+            HostCapabilitiesMode::Proxy(callback_handler::ProxyMode::Replay { source, strict });
+    }
+    if let Some(source) = matches.get_one::<String>("replay-context") {
+        let mut fixtures = ContextFixtures::from_file(Path::new(source))?;
+        if let Some(snapshot) = matches.get_one::<String>("discovery-snapshot") {
+            info!(discovery_snapshot = ?snapshot, "resolving the API resources using the discovery snapshot");
+            fixtures = fixtures
+                .with_discovery_snapshot(ApiResourceCatalog::from_snapshot(Path::new(snapshot))?);
+        }
+
+        info!(context_file = ?source, "host capabilities serving the Kubernetes resources declared inside of the context file");
         host_capabilities_mode =
-            HostCapabilitiesMode::Proxy(callback_handler::ProxyMode::Replay { source });
+            HostCapabilitiesMode::Proxy(callback_handler::ProxyMode::Context {
+                fixtures: Arc::new(fixtures),
+            });
     }
 
     Ok(PullAndRunSettings {
         sources,
+        fetcher_options,
         request,
+        requests,
         verified_manifest_digests,
         sigstore_trust_root,
+        verification_config,
         enable_wasmtime_cache,
         host_capabilities_mode,
+        pinned_clock: None,
+        host_capabilities_observer: None,
+        preopened_dirs,
     })
 }
 
+/// Parse a `GUEST:HOST` mapping of a preopened directory
+fn parse_preopened_dir(value: &str) -> Result<(String, PathBuf)> {
+    match value.split_once(':') {
+        Some((guest_path, host_path)) if !guest_path.is_empty() && !host_path.is_empty() => {
+            Ok((guest_path.to_owned(), PathBuf::from(host_path)))
+        }
+        _ => Err(anyhow!(
+            "Cannot parse the preopened directory '{}', expected GUEST:HOST",
+            value
+        )),
+    }
+}
+
+/// Parse the contents of a request file. The file can hold a single JSON document,
+/// multiple JSON documents, one per line (JSON Lines), or multiple YAML documents
+pub(crate) fn parse_requests(raw: &str) -> Result<Vec<serde_json::Value>> {
+    if let Ok(request) = serde_json::from_str::<serde_json::Value>(raw) {
+        return Ok(vec![request]);
+    }
+
+    let json_lines: Result<Vec<serde_json::Value>, _> = raw
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty())
+        .map(serde_json::from_str)
+        .collect();
+    if let Ok(requests) = json_lines {
+        if !requests.is_empty() {
+            return Ok(requests);
+        }
+    }
+
+    let mut requests = Vec::new();
+    for document in serde_yaml::Deserializer::from_str(raw) {
+        let request = serde_json::Value::deserialize(document)
+            .map_err(|e| anyhow!("the requests are neither JSON, JSON Lines nor YAML: {}", e))?;
+        // empty documents, e.g. the one following a trailing `---`
+        if !request.is_null() {
+            requests.push(request);
+        }
+    }
+    if requests.is_empty() {
+        return Err(anyhow!("no request found"));
+    }
+
+    Ok(requests)
+}
+
 async fn build_verified_manifest_digests(
     policy_definitions: &[PolicyDefinition],
     verification_options: &LatestVerificationConfig,
@@ -173,3 +312,50 @@ async fn build_verified_manifest_digests(
 
     Ok(verified_manifest_digests)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rstest::rstest;
+    use serde_json::json;
+
+    #[rstest]
+    #[case::json(
+        "{\n  \"uid\": \"1\",\n  \"operation\": \"CREATE\"\n}\n",
+        vec![json!({"uid": "1", "operation": "CREATE"})]
+    )]
+    #[case::json_lines(
+        "{\"uid\": \"1\"}\n\n{\"uid\": \"2\"}\n",
+        vec![json!({"uid": "1"}), json!({"uid": "2"})]
+    )]
+    #[case::yaml_documents(
+        "---\nuid: \"1\"\noperation: CREATE\n---\nuid: \"2\"\noperation: DELETE\n---\n",
+        vec![
+            json!({"uid": "1", "operation": "CREATE"}),
+            json!({"uid": "2", "operation": "DELETE"}),
+        ]
+    )]
+    fn parse_request_file(#[case] raw: &str, #[case] expected: Vec<serde_json::Value>) {
+        assert_eq!(parse_requests(raw).unwrap(), expected);
+    }
+
+    #[rstest]
+    #[case::empty("")]
+    #[case::only_separators("---\n---\n")]
+    #[case::invalid("{\"uid\": ")]
+    fn parse_invalid_request_file(#[case] raw: &str) {
+        assert!(parse_requests(raw).is_err());
+    }
+
+    #[rstest]
+    #[case::mapping("/data:/tmp/data", Some(("/data", "/tmp/data")))]
+    #[case::missing_host_path("/data:", None)]
+    #[case::missing_separator("/data", None)]
+    fn preopened_dir(#[case] value: &str, #[case] expected: Option<(&str, &str)>) {
+        assert_eq!(
+            parse_preopened_dir(value).ok(),
+            expected
+                .map(|(guest_path, host_path)| (guest_path.to_owned(), PathBuf::from(host_path)))
+        );
+    }
+}
@@ -85,6 +85,11 @@ fn build_verification_options_from_flags(
     let github_repo: Option<String> = matches
         .get_many::<String>("github-repo")
         .map(|items| items.into_iter().map(|i| i.to_string()).collect());
+    let github_workflow_ref: Option<String> =
+        matches.get_one::<String>("github-workflow-ref").cloned();
+    let github_workflow_trigger: Option<String> = matches
+        .get_one::<String>("github-workflow-trigger")
+        .cloned();
 
     if key_files.is_none()
         && annotations.is_none()
@@ -92,6 +97,8 @@ fn build_verification_options_from_flags(
         && cert_oidc_issuer.is_none()
         && github_owner.is_none()
         && github_repo.is_none()
+        && github_workflow_ref.is_none()
+        && github_workflow_trigger.is_none()
     {
         // no verification flags were used, don't create a LatestVerificationConfig
         return Ok(None);
@@ -108,7 +115,9 @@ fn build_verification_options_from_flags(
         ));
     }
 
-    if github_repo.is_some() && github_owner.is_none() {
+    if (github_repo.is_some() || github_workflow_ref.is_some() || github_workflow_trigger.is_some())
+        && github_owner.is_none()
+    {
         return Err(anyhow!(
             "Intending to verify GitHub actions signature, but the repository owner is missing."
         ));
@@ -135,6 +144,8 @@ fn build_verification_options_from_flags(
         let sig = Signature::GithubAction {
             owner: repo_owner,
             repo: github_repo,
+            workflow_ref: github_workflow_ref,
+            workflow_trigger: github_workflow_trigger,
             annotations: annotations.clone(),
         };
         signatures.push(sig)
@@ -158,6 +169,7 @@ fn build_verification_options_from_flags(
     let verification_config = LatestVerificationConfig {
         all_of: signatures_all_of,
         any_of: None,
+        trust_roots: None,
     };
     Ok(Some(verification_config))
 }
@@ -0,0 +1,161 @@
+use anyhow::{anyhow, Result};
+use policy_evaluator::policy_metadata::{diff::MetadataDiff, Metadata};
+use prettytable::{format::FormatBuilder, row, Table};
+
+pub(crate) enum OutputType {
+    Json,
+    Pretty,
+}
+
+impl TryFrom<Option<&str>> for OutputType {
+    type Error = anyhow::Error;
+
+    fn try_from(value: Option<&str>) -> Result<Self, Self::Error> {
+        match value {
+            Some("json") => Ok(Self::Json),
+            Some("pretty") | None => Ok(Self::Pretty),
+            Some(unknown) => Err(anyhow!("Invalid output format '{}'", unknown)),
+        }
+    }
+}
+
+/// Show what changed between two versions of a policy, in terms of what they are
+/// allowed to do inside of the cluster. An error is returned when `fail_on_expansion`
+/// is set and the newer version requires more privileges.
+pub(crate) fn diff(
+    old_uri_or_sha_prefix: &str,
+    new_uri_or_sha_prefix: &str,
+    output: OutputType,
+    no_color: bool,
+    fail_on_expansion: bool,
+) -> Result<()> {
+    let old_metadata = read_metadata(old_uri_or_sha_prefix)?;
+    let new_metadata = read_metadata(new_uri_or_sha_prefix)?;
+    let diff = MetadataDiff::new(&old_metadata, &new_metadata);
+
+    match output {
+        OutputType::Json => {
+            let mut json = serde_json::to_value(&diff)?;
+            json["privilegeExpansion"] = serde_json::Value::Bool(diff.is_privilege_expansion());
+            println!("{}", serde_json::to_string_pretty(&json)?);
+        }
+        OutputType::Pretty => print_diff(&diff, no_color),
+    }
+
+    if fail_on_expansion && diff.is_privilege_expansion() {
+        return Err(anyhow!(
+            "{} requires more privileges than {}",
+            new_uri_or_sha_prefix,
+            old_uri_or_sha_prefix
+        ));
+    }
+
+    Ok(())
+}
+
+fn read_metadata(uri_or_sha_prefix: &str) -> Result<Metadata> {
+    let uri = crate::utils::map_path_to_uri(uri_or_sha_prefix)?;
+    let wasm_path = crate::utils::wasm_path(&uri)?;
+
+    Metadata::from_path(&wasm_path)
+        .map_err(|e| anyhow!("Error parsing policy metadata of '{}': {}", uri, e))?
+        .ok_or_else(|| anyhow!(
+            "No Kubewarden metadata found inside of '{}'.\nPolicies can be annotated with the `kwctl annotate` command.",
+            uri
+        ))
+}
+
+fn print_diff(diff: &MetadataDiff, no_color: bool) {
+    if diff.is_privilege_expansion() {
+        let warning = "WARNING: the new version of the policy requires more privileges";
+        if no_color {
+            println!("{warning}");
+        } else {
+            println!("{}", color_print::cformat!("<red,bold>{}</>", warning));
+        }
+        println!();
+    }
+
+    if diff.is_empty() {
+        println!("No changes to what the policy is allowed to do");
+        return;
+    }
+
+    let added = |item: &str| {
+        if no_color {
+            format!("+ {item}")
+        } else {
+            color_print::cformat!("<red>+ {}</>", item)
+        }
+    };
+    let removed = |item: &str| {
+        if no_color {
+            format!("- {item}")
+        } else {
+            color_print::cformat!("<green>- {}</>", item)
+        }
+    };
+
+    if !diff.added_context_aware_resources.is_empty()
+        || !diff.removed_context_aware_resources.is_empty()
+    {
+        print_section_title("Context Aware");
+        for resource in &diff.added_context_aware_resources {
+            println!(
+                "{}",
+                added(&format!("{}/{}", resource.api_version, resource.kind))
+            );
+        }
+        for resource in &diff.removed_context_aware_resources {
+            println!(
+                "{}",
+                removed(&format!("{}/{}", resource.api_version, resource.kind))
+            );
+        }
+        println!();
+    }
+
+    if !diff.added_preopened_dirs.is_empty() || !diff.removed_preopened_dirs.is_empty() {
+        print_section_title("Directories of the host");
+        for path in &diff.added_preopened_dirs {
+            println!("{}", added(path));
+        }
+        for path in &diff.removed_preopened_dirs {
+            println!("{}", removed(path));
+        }
+        println!();
+    }
+
+    if !diff.added_rules.is_empty() || !diff.removed_rules.is_empty() {
+        print_section_title("Rules");
+        for rule in &diff.added_rules {
+            println!("{}", added(rule));
+        }
+        for rule in &diff.removed_rules {
+            println!("{}", removed(rule));
+        }
+        println!();
+    }
+
+    if diff.mutating.is_some() || diff.execution_mode.is_some() {
+        print_section_title("Details");
+        let mut table = Table::new();
+        table.set_format(FormatBuilder::new().padding(0, 1).build());
+        if let Some(mutating) = &diff.mutating {
+            table.add_row(
+                row![Fmbl -> "mutating:", format!("{} -> {}", mutating.from, mutating.to)],
+            );
+        }
+        if let Some(execution_mode) = &diff.execution_mode {
+            table.add_row(row![Fmbl -> "execution mode:", format!("{} -> {}", execution_mode.from, execution_mode.to)]);
+        }
+        table.printstd();
+    }
+}
+
+fn print_section_title(title: &str) {
+    let mut table = Table::new();
+    table.set_format(FormatBuilder::new().padding(0, 1).build());
+    table.add_row(row![Fmbl -> title]);
+    table.printstd();
+}
@@ -0,0 +1,356 @@
+use std::fmt::Write;
+
+use anyhow::{anyhow, Result};
+use policy_evaluator::{
+    constants::*,
+    policy_metadata::{Metadata, Operation, Rule},
+};
+use pulldown_cmark::{html, Options, Parser};
+
+use crate::scaffold::{describe_settings, example_settings};
+
+pub(crate) enum DocsFormat {
+    Markdown,
+    Html,
+}
+
+impl TryFrom<Option<&str>> for DocsFormat {
+    type Error = anyhow::Error;
+
+    fn try_from(value: Option<&str>) -> Result<Self, Self::Error> {
+        match value {
+            Some("markdown") | None => Ok(Self::Markdown),
+            Some("html") => Ok(Self::Html),
+            Some(unknown) => Err(anyhow!("Invalid documentation format '{}'", unknown)),
+        }
+    }
+}
+
+/// Generate the documentation page of a policy from its metadata: the details
+/// found inside of its annotations, the rules, the settings and the Kubernetes
+/// resources it accesses at evaluation time.
+pub(crate) fn policy_docs(uri_or_sha_prefix: &str, format: DocsFormat) -> Result<String> {
+    let uri = crate::utils::get_uri(&uri_or_sha_prefix.to_owned())?;
+    let wasm_path = crate::utils::wasm_path(&uri)?;
+
+    let metadata = Metadata::from_path(&wasm_path)?
+        .ok_or_else(||
+            anyhow!(
+                "No Kubewarden metadata found inside of '{}'.\nPolicies can be annotated with the `kwctl annotate` command.",
+                uri)
+        )?;
+
+    let markdown = render_markdown(&uri, &metadata)?;
+    match format {
+        DocsFormat::Markdown => Ok(markdown),
+        DocsFormat::Html => Ok(render_html(&title(&uri, &metadata), &markdown)),
+    }
+}
+
+fn title(uri: &str, metadata: &Metadata) -> String {
+    annotation(metadata, KUBEWARDEN_ANNOTATION_POLICY_TITLE)
+        .unwrap_or(uri)
+        .to_owned()
+}
+
+fn annotation<'a>(metadata: &'a Metadata, name: &str) -> Option<&'a str> {
+    metadata
+        .annotations
+        .as_ref()?
+        .get(name)
+        .map(|value| value.trim())
+        .filter(|value| !value.is_empty())
+}
+
+fn render_markdown(uri: &str, metadata: &Metadata) -> Result<String> {
+    let mut doc = String::new();
+
+    writeln!(doc, "# {}\n", title(uri, metadata))?;
+    if let Some(description) = annotation(metadata, KUBEWARDEN_ANNOTATION_POLICY_DESCRIPTION) {
+        writeln!(doc, "{description}\n")?;
+    }
+    render_details(&mut doc, uri, metadata)?;
+
+    if let Some(usage) = annotation(metadata, KUBEWARDEN_ANNOTATION_POLICY_USAGE) {
+        writeln!(doc, "## Usage\n\n{usage}\n")?;
+    }
+
+    writeln!(doc, "## Rules\n")?;
+    render_rules(&mut doc, &metadata.rules)?;
+
+    writeln!(doc, "## Settings\n")?;
+    render_settings(&mut doc, metadata.settings_schema.as_ref())?;
+
+    if !metadata.context_aware_resources.is_empty() {
+        writeln!(doc, "## Context aware resources\n")?;
+        writeln!(
+            doc,
+            "The policy requires access to the following Kubernetes resources at evaluation time:\n"
+        )?;
+        writeln!(doc, "| API version | Kind |")?;
+        writeln!(doc, "|---|---|")?;
+        for resource in &metadata.context_aware_resources {
+            writeln!(
+                doc,
+                "| `{}` | `{}` |",
+                table_cell(&resource.api_version),
+                table_cell(&resource.kind)
+            )?;
+        }
+        writeln!(doc)?;
+    }
+
+    Ok(doc)
+}
+
+fn render_details(doc: &mut String, uri: &str, metadata: &Metadata) -> Result<()> {
+    let yes_no = |value: bool| if value { "yes" } else { "no" };
+
+    writeln!(doc, "| | |")?;
+    writeln!(doc, "|---|---|")?;
+    writeln!(doc, "| URI | `{}` |", table_cell(uri))?;
+    for (label, name) in [
+        ("Version", KUBEWARDEN_ANNOTATION_POLICY_VERSION),
+        ("Author", KUBEWARDEN_ANNOTATION_POLICY_AUTHOR),
+        ("Homepage", KUBEWARDEN_ANNOTATION_POLICY_URL),
+        ("Source", KUBEWARDEN_ANNOTATION_POLICY_SOURCE),
+        ("License", KUBEWARDEN_ANNOTATION_POLICY_LICENSE),
+        ("Category", KUBEWARDEN_ANNOTATION_POLICY_CATEGORY),
+        ("Severity", KUBEWARDEN_ANNOTATION_POLICY_SEVERITY),
+    ] {
+        if let Some(value) = annotation(metadata, name) {
+            writeln!(doc, "| {label} | {} |", table_cell(value))?;
+        }
+    }
+    writeln!(doc, "| Mutating | {} |", yes_no(metadata.mutating))?;
+    writeln!(
+        doc,
+        "| Background audit | {} |",
+        yes_no(metadata.background_audit)
+    )?;
+    writeln!(
+        doc,
+        "| Context aware | {} |",
+        yes_no(!metadata.context_aware_resources.is_empty())
+    )?;
+    writeln!(doc, "| Policy type | {} |", metadata.policy_type)?;
+    writeln!(doc, "| Execution mode | {} |", metadata.execution_mode)?;
+    if let Some(version) = &metadata.minimum_kubewarden_version {
+        writeln!(doc, "| Minimum Kubewarden version | {version} |")?;
+    }
+    writeln!(doc)?;
+
+    Ok(())
+}
+
+fn render_rules(doc: &mut String, rules: &[Rule]) -> Result<()> {
+    if rules.is_empty() {
+        writeln!(doc, "The policy does not target any Kubernetes resource.\n")?;
+        return Ok(());
+    }
+
+    writeln!(
+        doc,
+        "The policy is evaluated against the following requests:\n"
+    )?;
+    writeln!(
+        doc,
+        "| API groups | API versions | Resources | Operations |"
+    )?;
+    writeln!(doc, "|---|---|---|---|")?;
+    for rule in rules {
+        let operations: Vec<&str> = rule.operations.iter().map(operation_name).collect();
+        writeln!(
+            doc,
+            "| {} | {} | {} | {} |",
+            code_list(&rule.api_groups),
+            code_list(&rule.api_versions),
+            code_list(&rule.resources),
+            code_list(&operations)
+        )?;
+    }
+    writeln!(doc)?;
+
+    Ok(())
+}
+
+fn render_settings(doc: &mut String, schema: Option<&serde_json::Value>) -> Result<()> {
+    let Some(schema) = schema else {
+        writeln!(
+            doc,
+            "The metadata of the policy does not describe its settings.\n"
+        )?;
+        return Ok(());
+    };
+    let settings = describe_settings(schema)?;
+    if settings.is_empty() {
+        writeln!(doc, "The policy does not have any setting.\n")?;
+        return Ok(());
+    }
+
+    writeln!(doc, "| Setting | Details | Description |")?;
+    writeln!(doc, "|---|---|---|")?;
+    for setting in &settings {
+        writeln!(
+            doc,
+            "| `{}` | {} | {} |",
+            table_cell(&setting.path),
+            table_cell(&setting.details),
+            table_cell(setting.description.as_deref().unwrap_or_default())
+        )?;
+    }
+    writeln!(doc, "\nExample:\n")?;
+    writeln!(doc, "```yaml\n{}```\n", example_settings(schema)?)?;
+
+    Ok(())
+}
+
+fn operation_name(operation: &Operation) -> &'static str {
+    match operation {
+        Operation::Create => "CREATE",
+        Operation::Update => "UPDATE",
+        Operation::Delete => "DELETE",
+        Operation::Connect => "CONNECT",
+        Operation::All => "*",
+    }
+}
+
+/// Render the values as a list of inline code spans. The empty string is the
+/// core API group, hence it's kept visible
+fn code_list<S: AsRef<str>>(values: &[S]) -> String {
+    values
+        .iter()
+        .map(|value| match value.as_ref() {
+            "" => "`\"\"`".to_owned(),
+            value => format!("`{}`", table_cell(value)),
+        })
+        .collect::<Vec<String>>()
+        .join(", ")
+}
+
+/// Markdown tables can't hold multiple lines nor unescaped pipes
+fn table_cell(text: &str) -> String {
+    text.lines()
+        .map(str::trim)
+        .collect::<Vec<&str>>()
+        .join(" ")
+        .replace('|', "\\|")
+}
+
+fn render_html(title: &str, markdown: &str) -> String {
+    let parser = Parser::new_ext(markdown, Options::ENABLE_TABLES);
+    let mut body = String::new();
+    html::push_html(&mut body, parser);
+
+    format!(
+        "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n<title>{}</title>\n</head>\n<body>\n{body}</body>\n</html>\n",
+        escape_html(title)
+    )
+}
+
+fn escape_html(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::collections::{BTreeMap, BTreeSet};
+
+    use policy_evaluator::policy_metadata::ContextAwareResource;
+    use serde_json::json;
+
+    fn metadata() -> Metadata {
+        Metadata {
+            rules: vec![Rule {
+                api_groups: vec!["".to_owned()],
+                api_versions: vec!["v1".to_owned()],
+                resources: vec!["pods".to_owned()],
+                operations: vec![Operation::Create, Operation::Update],
+            }],
+            annotations: Some(BTreeMap::from([
+                (
+                    KUBEWARDEN_ANNOTATION_POLICY_TITLE.to_owned(),
+                    "Pod privileged".to_owned(),
+                ),
+                (
+                    KUBEWARDEN_ANNOTATION_POLICY_DESCRIPTION.to_owned(),
+                    "Reject privileged pods".to_owned(),
+                ),
+                (
+                    KUBEWARDEN_ANNOTATION_POLICY_AUTHOR.to_owned(),
+                    "Kubewarden developers | SUSE".to_owned(),
+                ),
+                (
+                    KUBEWARDEN_ANNOTATION_POLICY_USAGE.to_owned(),
+                    "Deploy the policy with `kwctl scaffold manifest`".to_owned(),
+                ),
+            ])),
+            context_aware_resources: BTreeSet::from([ContextAwareResource {
+                api_version: "v1".to_owned(),
+                kind: "Namespace".to_owned(),
+            }]),
+            settings_schema: Some(json!({
+                "type": "object",
+                "properties": {
+                    "skip_init_containers": {
+                        "type": "boolean",
+                        "description": "Ignore the init containers",
+                        "default": false
+                    }
+                }
+            })),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn render_policy_markdown() {
+        let doc = render_markdown(
+            "registry://ghcr.io/kubewarden/policies/pod-privileged:v1.0.0",
+            &metadata(),
+        )
+        .unwrap();
+
+        assert!(doc.starts_with("# Pod privileged\n\nReject privileged pods\n"));
+        assert!(doc.contains("| Author | Kubewarden developers \\| SUSE |"));
+        assert!(doc.contains("| Context aware | yes |"));
+        assert!(doc.contains("## Usage\n\nDeploy the policy with `kwctl scaffold manifest`\n"));
+        assert!(doc.contains("| `\"\"` | `v1` | `pods` | `CREATE`, `UPDATE` |"));
+        assert!(doc.contains(
+            "| `skip_init_containers` | type: boolean, default: false | Ignore the init containers |"
+        ));
+        assert!(doc.contains("skip_init_containers: false\n```"));
+        assert!(doc.contains("| `v1` | `Namespace` |"));
+    }
+
+    #[test]
+    fn render_policy_without_settings_schema() {
+        let metadata = Metadata {
+            settings_schema: None,
+            context_aware_resources: BTreeSet::new(),
+            ..metadata()
+        };
+        let doc = render_markdown("file:///policy.wasm", &metadata).unwrap();
+
+        assert!(doc.contains("The metadata of the policy does not describe its settings."));
+        assert!(!doc.contains("## Context aware resources"));
+    }
+
+    #[test]
+    fn render_policy_html() {
+        let metadata = metadata();
+        let markdown = render_markdown("file:///policy.wasm", &metadata).unwrap();
+        let html = render_html("Pods <privileged>", &markdown);
+
+        assert!(html.contains("<title>Pods &lt;privileged&gt;</title>"));
+        assert!(html.contains("<h1>Pod privileged</h1>"));
+        assert!(html.contains("<h2>Rules</h2>"));
+        assert!(html.contains("<table>"));
+        assert!(html.contains("<code>kwctl scaffold manifest</code>"));
+    }
+}
@@ -0,0 +1,219 @@
+//! Classification of the errors reported by kwctl.
+//!
+//! Each kind of error is associated with an exit code, this allows scripts to tell
+//! apart a request rejected by a policy from a network failure, without parsing the
+//! output of kwctl. The exit codes are part of the interface of kwctl and are
+//! documented inside of the README, they must never change.
+
+use std::{fmt, process::ExitCode};
+
+use policy_evaluator::policy_fetcher::{
+    errors::FetcherError, registry::errors::RegistryError, verify::errors::VerifyError,
+};
+use serde::Serialize;
+
+use crate::utils::LookupError;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub(crate) enum ErrorKind {
+    /// Any error that doesn't fall into the other categories
+    Internal,
+    /// The command has been invoked with invalid flags or input files. This
+    /// is the same exit code used by the parser of the command line
+    InvalidInput,
+    /// At least one request has been rejected by the policy
+    Rejected,
+    /// The policy cannot be evaluated: the settings are not valid, or the
+    /// policy failed during the evaluation
+    EvaluationFailed,
+    /// The policy cannot be found inside of the local store
+    NotFound,
+    /// The remote registry or server cannot be reached, or it replied with
+    /// an error
+    Network,
+    /// The signatures or the digest of the policy do not satisfy the
+    /// verification config
+    VerificationFailed,
+}
+
+impl ErrorKind {
+    pub(crate) fn exit_code(self) -> u8 {
+        match self {
+            ErrorKind::Internal => 1,
+            ErrorKind::InvalidInput => 2,
+            ErrorKind::Rejected => 3,
+            ErrorKind::EvaluationFailed => 4,
+            ErrorKind::NotFound => 5,
+            ErrorKind::Network => 6,
+            ErrorKind::VerificationFailed => 7,
+        }
+    }
+
+    /// Find the kind of the given error, looking at the errors that caused it
+    pub(crate) fn of(error: &anyhow::Error) -> ErrorKind {
+        error
+            .chain()
+            .find_map(|cause| {
+                if let Some(e) = cause.downcast_ref::<KwctlError>() {
+                    return Some(e.kind);
+                }
+                if let Some(e) = cause.downcast_ref::<FetcherError>() {
+                    return match e {
+                        FetcherError::VerifyError(e) => Some(Self::of_verify_error(e)),
+                        FetcherError::RegistryError(_) | FetcherError::SourceError(_) => {
+                            Some(ErrorKind::Network)
+                        }
+                        FetcherError::InvalidURLError(_) | FetcherError::UrlParserError(_) => {
+                            Some(ErrorKind::InvalidInput)
+                        }
+                        _ => None,
+                    };
+                }
+                if let Some(e) = cause.downcast_ref::<VerifyError>() {
+                    return Some(Self::of_verify_error(e));
+                }
+                if cause.downcast_ref::<RegistryError>().is_some() {
+                    return Some(ErrorKind::Network);
+                }
+                if let Some(LookupError::PolicyMissing(_)) = cause.downcast_ref::<LookupError>() {
+                    return Some(ErrorKind::NotFound);
+                }
+                None
+            })
+            .unwrap_or(ErrorKind::Internal)
+    }
+
+    fn of_verify_error(error: &VerifyError) -> ErrorKind {
+        match error {
+            VerifyError::RegistryError(_) => ErrorKind::Network,
+            VerifyError::InvalidVerifyFileError(_)
+            | VerifyError::VerificationFileReadError(_)
+            | VerifyError::FailedToParseYamlDataError(_) => ErrorKind::InvalidInput,
+            _ => ErrorKind::VerificationFailed,
+        }
+    }
+}
+
+/// An error whose kind is known where it's raised
+#[derive(Debug)]
+pub(crate) struct KwctlError {
+    pub kind: ErrorKind,
+    pub message: String,
+}
+
+impl KwctlError {
+    pub(crate) fn new(kind: ErrorKind, message: impl Into<String>) -> Self {
+        KwctlError {
+            kind,
+            message: message.into(),
+        }
+    }
+}
+
+impl fmt::Display for KwctlError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.message)
+    }
+}
+
+impl std::error::Error for KwctlError {}
+
+/// How the errors are printed on the standard error
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum ErrorFormat {
+    Text,
+    Json,
+}
+
+/// The error printed when `--error-format json` is used
+#[derive(Serialize, Debug, PartialEq)]
+#[serde(rename_all = "camelCase")]
+struct ErrorEnvelope {
+    kind: ErrorKind,
+    exit_code: u8,
+    message: String,
+    /// The errors that caused this one, from the outermost to the innermost
+    causes: Vec<String>,
+}
+
+impl ErrorEnvelope {
+    fn new(error: &anyhow::Error) -> Self {
+        let kind = ErrorKind::of(error);
+        ErrorEnvelope {
+            kind,
+            exit_code: kind.exit_code(),
+            message: error.to_string(),
+            causes: error.chain().skip(1).map(|e| e.to_string()).collect(),
+        }
+    }
+}
+
+/// Print the error on the standard error and return the matching exit code
+pub(crate) fn report(error: &anyhow::Error, format: ErrorFormat) -> ExitCode {
+    let kind = ErrorKind::of(error);
+    match format {
+        ErrorFormat::Text => eprintln!("Error: {error:?}"),
+        ErrorFormat::Json => {
+            let envelope = serde_json::json!({ "error": ErrorEnvelope::new(error) });
+            eprintln!("{envelope}");
+        }
+    }
+
+    ExitCode::from(kind.exit_code())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::anyhow;
+    use rstest::rstest;
+
+    #[rstest]
+    #[case::generic(anyhow!("boom"), ErrorKind::Internal)]
+    #[case::tagged(
+        anyhow::Error::new(KwctlError::new(ErrorKind::Rejected, "rejected")),
+        ErrorKind::Rejected
+    )]
+    #[case::tagged_with_context(
+        anyhow::Error::new(KwctlError::new(ErrorKind::EvaluationFailed, "trap"))
+            .context("cannot evaluate"),
+        ErrorKind::EvaluationFailed
+    )]
+    #[case::policy_missing(
+        anyhow::Error::new(LookupError::PolicyMissing("registry://example.com/policy:v1".to_owned())),
+        ErrorKind::NotFound
+    )]
+    #[case::verification(
+        anyhow::Error::new(VerifyError::ImageVerificationError("no signatures".to_owned())),
+        ErrorKind::VerificationFailed
+    )]
+    #[case::invalid_verification_config(
+        anyhow::Error::new(VerifyError::InvalidVerifyFileError("bad".to_owned())),
+        ErrorKind::InvalidInput
+    )]
+    #[case::fetcher_verification(
+        anyhow::Error::new(FetcherError::VerifyError(VerifyError::ChecksumVerificationError("digest mismatch".to_owned()))),
+        ErrorKind::VerificationFailed
+    )]
+    fn error_kind(#[case] error: anyhow::Error, #[case] expected: ErrorKind) {
+        assert_eq!(ErrorKind::of(&error), expected);
+    }
+
+    #[test]
+    fn error_envelope() {
+        let error = anyhow::Error::new(KwctlError::new(ErrorKind::Rejected, "request rejected"))
+            .context("cannot run policy");
+
+        let envelope = serde_json::to_value(ErrorEnvelope::new(&error)).unwrap();
+        assert_eq!(
+            envelope,
+            serde_json::json!({
+                "kind": "rejected",
+                "exitCode": 3,
+                "message": "cannot run policy",
+                "causes": ["request rejected"],
+            })
+        );
+    }
+}
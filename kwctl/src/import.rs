@@ -0,0 +1,463 @@
+use std::{
+    collections::BTreeMap,
+    fs,
+    future::Future,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{anyhow, Result};
+use k8s_openapi::api::core::v1::{ConfigMap, Pod};
+use policy_evaluator::{
+    kube,
+    policy_fetcher::{sources::Sources, store::Store, PullDestination},
+};
+use prettytable::{format, row, Table};
+use serde::Deserialize;
+use tracing::warn;
+
+use crate::pull;
+
+/// The key of the ConfigMap holding the policies loaded by policy-server
+pub(crate) const POLICIES_FILE: &str = "policies.yml";
+
+/// The policy-server Pod whose policies are imported
+pub(crate) struct PolicyServerPod<'a> {
+    pub namespace: &'a str,
+    pub name: &'a str,
+    /// The port where policy-server serves its API
+    pub port: u16,
+    /// Whether policy-server serves its API over plain HTTP instead of HTTPS
+    pub insecure: bool,
+}
+
+/// The subset of a policy-server `policies.yml` entry required to reproduce it locally.
+/// Both individual policies and policy groups are covered.
+#[derive(Deserialize, Debug, Default)]
+#[serde(rename_all = "camelCase")]
+struct PolicyEntry {
+    module: Option<String>,
+    settings: Option<serde_yaml::Value>,
+    /// The members of a policy group
+    #[serde(default)]
+    policies: BTreeMap<String, PolicyEntry>,
+}
+
+/// An entry of the catalog served by the `/policies` endpoint of policy-server
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct CatalogEntry {
+    id: String,
+    module_digest: Option<String>,
+}
+
+/// What has been read from a running policy-server
+#[derive(Debug)]
+struct ServerSnapshot {
+    /// The contents of the `policies.yml` file used by policy-server
+    policies_file: String,
+    /// The digest of the Wasm module loaded by each policy, by policy ID
+    module_digests: BTreeMap<String, String>,
+}
+
+/// A policy, or the member of a policy group, to be imported
+#[derive(Debug, PartialEq)]
+struct ImportedPolicy {
+    /// The policy ID. Members of policy groups are identified by `<group>/<member>`
+    id: String,
+    module: String,
+    /// The digest of the module loaded by policy-server, when reported
+    expected_sha256: Option<String>,
+    settings: serde_yaml::Value,
+}
+
+#[derive(Debug)]
+enum ImportOutcome {
+    Imported {
+        sha256: String,
+    },
+    /// The module pulled does not match the one loaded by policy-server, this
+    /// happens when the tag of the policy has been moved
+    DigestMismatch {
+        sha256: String,
+    },
+    Skipped {
+        reason: String,
+    },
+}
+
+/// Download into the store the policies loaded by a running policy-server. The
+/// `policies.yml` file of the server and the settings of each policy are written
+/// inside of `output_dir`.
+///
+/// An error is returned when a module does not match the one loaded by the server.
+pub(crate) async fn import_from_server<F, Fut>(
+    kube_client: F,
+    server: &PolicyServerPod<'_>,
+    sources: Option<&Sources>,
+    output_dir: &Path,
+) -> Result<()>
+where
+    F: FnOnce() -> Fut,
+    Fut: Future<Output = Result<kube::Client>>,
+{
+    let snapshot = fetch_snapshot(kube_client().await?, server).await?;
+    let policies = imported_policies(&snapshot)?;
+
+    let settings_dir = output_dir.join("settings");
+    fs::create_dir_all(&settings_dir)
+        .map_err(|e| anyhow!("cannot create {}: {}", settings_dir.display(), e))?;
+    fs::write(output_dir.join(POLICIES_FILE), &snapshot.policies_file)
+        .map_err(|e| anyhow!("cannot write {}: {}", POLICIES_FILE, e))?;
+
+    let store = Store::default();
+    let mut pulled: BTreeMap<String, Result<String, String>> = BTreeMap::new();
+    let mut results = Vec::new();
+    for policy in &policies {
+        let settings_path = settings_dir.join(format!("{}.yml", policy.id.replace('/', "-")));
+        fs::write(&settings_path, serde_yaml::to_string(&policy.settings)?)
+            .map_err(|e| anyhow!("cannot write {}: {}", settings_path.display(), e))?;
+
+        if policy.module.starts_with("file://") {
+            results.push((
+                policy,
+                ImportOutcome::Skipped {
+                    reason: "the module is a file of the policy-server Pod".to_owned(),
+                },
+            ));
+            continue;
+        }
+
+        if !pulled.contains_key(&policy.module) {
+            let sha256 = pull::pull(&policy.module, sources, PullDestination::MainStore)
+                .await
+                .map_err(|e| e.to_string())
+                .and_then(|pulled_policy| {
+                    store
+                        .provenance(&pulled_policy)
+                        .map_err(|e| e.to_string())?
+                        .map(|provenance| provenance.sha256)
+                        .ok_or_else(|| "cannot find the digest of the pulled module".to_owned())
+                });
+            pulled.insert(policy.module.clone(), sha256);
+        }
+
+        let outcome = match &pulled[&policy.module] {
+            Ok(sha256) if policy.expected_sha256.as_ref().is_some_and(|e| e != sha256) => {
+                ImportOutcome::DigestMismatch {
+                    sha256: sha256.clone(),
+                }
+            }
+            Ok(sha256) => ImportOutcome::Imported {
+                sha256: sha256.clone(),
+            },
+            Err(reason) => ImportOutcome::Skipped {
+                reason: reason.clone(),
+            },
+        };
+        results.push((policy, outcome));
+    }
+
+    print_import_table(&results);
+
+    let mismatches = results
+        .iter()
+        .filter(|(_, outcome)| matches!(outcome, ImportOutcome::DigestMismatch { .. }))
+        .count();
+    if mismatches > 0 {
+        return Err(anyhow!(
+            "{} policies do not match the modules loaded by policy-server, their tags have probably been moved. Pin the policies by digest to reproduce the server exactly",
+            mismatches
+        ));
+    }
+    Ok(())
+}
+
+/// Read the `policies.yml` file mounted by the policy-server Pod, together with the
+/// digests of the modules loaded by the server
+async fn fetch_snapshot(
+    client: kube::Client,
+    server: &PolicyServerPod<'_>,
+) -> Result<ServerSnapshot> {
+    let pods = kube::Api::<Pod>::namespaced(client.clone(), server.namespace);
+    let pod = pods
+        .get(server.name)
+        .await
+        .map_err(|e| anyhow!("cannot get Pod {}/{}: {}", server.namespace, server.name, e))?;
+
+    let config_map_names: Vec<String> = pod
+        .spec
+        .iter()
+        .flat_map(|spec| spec.volumes.iter().flatten())
+        .filter_map(|volume| volume.config_map.as_ref())
+        .map(|config_map| config_map.name.clone())
+        .collect();
+
+    let config_maps = kube::Api::<ConfigMap>::namespaced(client.clone(), server.namespace);
+    let mut policies_file = None;
+    for name in config_map_names {
+        let config_map = config_maps
+            .get(&name)
+            .await
+            .map_err(|e| anyhow!("cannot get ConfigMap {}: {}", name, e))?;
+        if let Some(contents) = config_map
+            .data
+            .and_then(|mut data| data.remove(POLICIES_FILE))
+        {
+            policies_file = Some(contents);
+            break;
+        }
+    }
+    let policies_file = policies_file.ok_or_else(|| {
+        anyhow!(
+            "none of the ConfigMaps mounted by Pod {}/{} has a {} key",
+            server.namespace,
+            server.name,
+            POLICIES_FILE
+        )
+    })?;
+
+    let scheme = if server.insecure { "http" } else { "https" };
+    let request = kube::core::Request::new(format!("/api/v1/namespaces/{}/pods", server.namespace))
+        .get(
+            &format!("{}:{}:{}/proxy/policies", scheme, server.name, server.port),
+            &kube::api::GetParams::default(),
+        )?;
+    let catalog: Vec<CatalogEntry> = match client.request_text(request).await {
+        Ok(response) => serde_json::from_str(&response)
+            .map_err(|e| anyhow!("cannot parse the policies reported by policy-server: {}", e))?,
+        Err(e) => {
+            warn!(error = %e, "cannot get the policies loaded by policy-server, the digests of the modules cannot be checked");
+            Vec::new()
+        }
+    };
+
+    Ok(ServerSnapshot {
+        policies_file,
+        module_digests: catalog
+            .into_iter()
+            .filter_map(|entry| entry.module_digest.map(|digest| (entry.id, digest)))
+            .collect(),
+    })
+}
+
+/// The policies defined by the `policies.yml` file of the server, including the members
+/// of the policy groups. The members are matched with the digest reported for the
+/// policies using the same module, since the server doesn't report them.
+fn imported_policies(snapshot: &ServerSnapshot) -> Result<Vec<ImportedPolicy>> {
+    let entries: BTreeMap<String, PolicyEntry> = serde_yaml::from_str(&snapshot.policies_file)
+        .map_err(|e| anyhow!("cannot parse the {} of policy-server: {}", POLICIES_FILE, e))?;
+
+    let mut policies = Vec::new();
+    for (id, entry) in entries {
+        if let Some(module) = entry.module {
+            policies.push(ImportedPolicy {
+                expected_sha256: snapshot.module_digests.get(&id).cloned(),
+                id,
+                module,
+                settings: entry
+                    .settings
+                    .unwrap_or_else(|| serde_yaml::Value::Mapping(Default::default())),
+            });
+            continue;
+        }
+        for (member_id, member) in entry.policies {
+            let Some(module) = member.module else {
+                continue;
+            };
+            policies.push(ImportedPolicy {
+                id: format!("{id}/{member_id}"),
+                module,
+                expected_sha256: None,
+                settings: member
+                    .settings
+                    .unwrap_or_else(|| serde_yaml::Value::Mapping(Default::default())),
+            });
+        }
+    }
+
+    let digests_by_module: BTreeMap<String, String> = policies
+        .iter()
+        .filter_map(|policy| {
+            policy
+                .expected_sha256
+                .clone()
+                .map(|digest| (policy.module.clone(), digest))
+        })
+        .collect();
+    for policy in policies.iter_mut() {
+        if policy.expected_sha256.is_none() {
+            policy.expected_sha256 = digests_by_module.get(&policy.module).cloned();
+        }
+    }
+
+    Ok(policies)
+}
+
+fn print_import_table(results: &[(&ImportedPolicy, ImportOutcome)]) {
+    let mut table = Table::new();
+    table.set_format(*format::consts::FORMAT_NO_LINESEP_WITH_TITLE);
+    table.set_titles(row!["Policy", "Module", "SHA-256", "Outcome"]);
+    for (policy, outcome) in results {
+        let (sha256, outcome) = match outcome {
+            ImportOutcome::Imported { sha256 } => (sha256.clone(), "imported".to_owned()),
+            ImportOutcome::DigestMismatch { sha256 } => (
+                sha256.clone(),
+                format!(
+                    "digest mismatch, policy-server loaded {}",
+                    policy.expected_sha256.as_deref().unwrap_or_default()
+                ),
+            ),
+            ImportOutcome::Skipped { reason } => (String::new(), format!("skipped: {reason}")),
+        };
+        table.add_row(row![policy.id, policy.module, sha256, outcome]);
+    }
+    table.printstd();
+}
+
+/// Where the files describing the imported policies are written by default
+pub(crate) fn default_output_dir(server: &PolicyServerPod<'_>) -> PathBuf {
+    PathBuf::from(format!("{}-{}", server.namespace, server.name))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use hyper::{http, Request, Response};
+    use k8s_openapi::api::core::v1::{ConfigMapVolumeSource, PodSpec, Volume};
+    use k8s_openapi::apimachinery::pkg::apis::meta::v1::ObjectMeta;
+    use kube::client::Body;
+    use serde::Serialize;
+    use serde_json::json;
+    use tower_test::mock::{Handle, SendResponse};
+
+    const POLICIES_YML: &str = r#"
+psp-capabilities:
+  module: registry://ghcr.io/kubewarden/policies/psp-capabilities:v0.1.9
+  settings:
+    allowed_capabilities: ["CHOWN"]
+trusted-images:
+  policies:
+    signed:
+      module: registry://ghcr.io/kubewarden/policies/verify-image-signatures:v0.2.8
+    capabilities:
+      module: registry://ghcr.io/kubewarden/policies/psp-capabilities:v0.1.9
+  expression: "signed() && capabilities()"
+  message: "the image is not trusted"
+"#;
+
+    fn send_response<T: Serialize>(send: SendResponse<Response<Body>>, response: T) {
+        let response = serde_json::to_vec(&response).unwrap();
+        send.send_response(Response::builder().body(Body::from(response)).unwrap());
+    }
+
+    async fn handle_policy_server_pod(handle: Handle<Request<Body>, Response<Body>>) {
+        tokio::spawn(async move {
+            let mut handle = handle;
+
+            loop {
+                let (request, send) = handle.next_request().await.expect("service not called");
+
+                match (request.method(), request.uri().path()) {
+                    (&http::Method::GET, "/api/v1/namespaces/kubewarden/pods/policy-server-xyz") => {
+                        send_response(
+                            send,
+                            Pod {
+                                metadata: ObjectMeta {
+                                    name: Some("policy-server-xyz".to_owned()),
+                                    ..Default::default()
+                                },
+                                spec: Some(PodSpec {
+                                    volumes: Some(vec![Volume {
+                                        name: "policies".to_owned(),
+                                        config_map: Some(ConfigMapVolumeSource {
+                                            name: "policy-server-default".to_owned(),
+                                            ..Default::default()
+                                        }),
+                                        ..Default::default()
+                                    }]),
+                                    ..Default::default()
+                                }),
+                                ..Default::default()
+                            },
+                        );
+                    }
+                    (
+                        &http::Method::GET,
+                        "/api/v1/namespaces/kubewarden/configmaps/policy-server-default",
+                    ) => {
+                        send_response(
+                            send,
+                            ConfigMap {
+                                metadata: ObjectMeta {
+                                    name: Some("policy-server-default".to_owned()),
+                                    ..Default::default()
+                                },
+                                data: Some(BTreeMap::from([(
+                                    POLICIES_FILE.to_owned(),
+                                    POLICIES_YML.to_owned(),
+                                )])),
+                                ..Default::default()
+                            },
+                        );
+                    }
+                    (
+                        &http::Method::GET,
+                        "/api/v1/namespaces/kubewarden/pods/https:policy-server-xyz:3000/proxy/policies",
+                    ) => {
+                        send_response(
+                            send,
+                            json!([
+                                {"id": "psp-capabilities", "moduleDigest": "1234", "policyGroup": false},
+                                {"id": "trusted-images", "moduleDigest": null, "policyGroup": true},
+                            ]),
+                        );
+                    }
+                    _ => {
+                        panic!("unexpected request: {:?}", request);
+                    }
+                }
+            }
+        });
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn read_snapshot_of_policy_server() {
+        let (mocksvc, handle) = tower_test::mock::pair::<Request<Body>, Response<Body>>();
+        handle_policy_server_pod(handle).await;
+        let client = kube::Client::new(mocksvc, "default");
+
+        let server = PolicyServerPod {
+            namespace: "kubewarden",
+            name: "policy-server-xyz",
+            port: 3000,
+            insecure: false,
+        };
+        let snapshot = fetch_snapshot(client, &server).await.unwrap();
+        assert_eq!(snapshot.policies_file, POLICIES_YML);
+        assert_eq!(
+            snapshot.module_digests,
+            BTreeMap::from([("psp-capabilities".to_owned(), "1234".to_owned())])
+        );
+
+        let policies = imported_policies(&snapshot).unwrap();
+        let summary: Vec<(&str, Option<&str>)> = policies
+            .iter()
+            .map(|policy| (policy.id.as_str(), policy.expected_sha256.as_deref()))
+            .collect();
+        assert_eq!(
+            summary,
+            vec![
+                ("psp-capabilities", Some("1234")),
+                // the member uses the same module of psp-capabilities
+                ("trusted-images/capabilities", Some("1234")),
+                ("trusted-images/signed", None),
+            ]
+        );
+        assert_eq!(
+            policies[0].settings,
+            serde_yaml::from_str::<serde_yaml::Value>(r#"allowed_capabilities: ["CHOWN"]"#)
+                .unwrap()
+        );
+    }
+}
@@ -9,7 +9,7 @@ use anyhow::{anyhow, Result};
 use is_terminal::IsTerminal;
 use policy_evaluator::{
     constants::*,
-    policy_evaluator::PolicyExecutionMode,
+    policy_evaluator::{PolicyExecutionMode, PolicySettings},
     policy_fetcher::{
         oci_client::{
             manifest::{OciImageManifest, OciManifest},
@@ -27,6 +27,8 @@ use policy_evaluator::{
 use prettytable::{format::FormatBuilder, row, Table};
 use termimad::{terminal_size, FmtText, MadSkin};
 
+use crate::schema_validation::{describe_violations, validate_settings};
+
 pub(crate) async fn inspect(
     uri_or_sha_prefix: &str,
     output: OutputType,
@@ -78,6 +80,65 @@ pub(crate) async fn inspect(
     Ok(())
 }
 
+/// Print the JSON Schema of the settings embedded inside of the metadata of the
+/// policy. When `settings` are given, they are validated against the schema
+pub(crate) fn inspect_settings_schema(
+    uri_or_sha_prefix: &str,
+    output: OutputType,
+    settings: Option<PolicySettings>,
+) -> Result<()> {
+    let uri = crate::utils::map_path_to_uri(uri_or_sha_prefix)?;
+    let wasm_path = crate::utils::wasm_path(&uri)?;
+
+    let metadata = Metadata::from_path(&wasm_path)
+        .map_err(|e| anyhow!("Error parsing policy metadata: {}", e))?;
+    let schema = metadata
+        .and_then(|metadata| metadata.settings_schema)
+        .ok_or_else(|| anyhow!("The policy '{}' doesn't embed a settings schema", uri))?;
+
+    match output {
+        OutputType::Yaml => print!("{}", serde_yaml::to_string(&schema)?),
+        OutputType::Pretty => println!("{}", serde_json::to_string_pretty(&schema)?),
+    }
+
+    let Some(settings) = settings else {
+        return Ok(());
+    };
+    let violations = validate_settings(&schema, &serde_json::Value::Object(settings.0))?;
+    if !violations.is_empty() {
+        return Err(anyhow!(
+            "The settings are not valid:\n{}",
+            describe_violations(&violations)
+        ));
+    }
+    eprintln!("The settings are valid");
+
+    Ok(())
+}
+
+/// Read the settings given by the user, either as a YAML file or as a JSON string
+pub(crate) fn read_settings(
+    settings_path: Option<&String>,
+    settings_json: Option<&String>,
+) -> Result<Option<PolicySettings>> {
+    let settings: serde_json::Value = if let Some(settings_path) = settings_path {
+        serde_yaml::from_reader(
+            std::fs::File::open(settings_path)
+                .map_err(|e| anyhow!("Cannot open settings file {}: {}", settings_path, e))?,
+        )
+        .map_err(|e| anyhow!("Cannot parse settings file {}: {}", settings_path, e))?
+    } else if let Some(settings_json) = settings_json {
+        serde_json::from_str(settings_json)
+            .map_err(|e| anyhow!("Cannot parse settings JSON: {}", e))?
+    } else {
+        return Ok(None);
+    };
+
+    PolicySettings::try_from(&settings)
+        .map(Some)
+        .map_err(anyhow::Error::msg)
+}
+
 pub(crate) enum OutputType {
     Yaml,
     Pretty,
@@ -1,20 +1,220 @@
 use anyhow::{anyhow, Result};
 use flate2::read::GzDecoder;
-use policy_evaluator::policy_fetcher::store::Store;
+use policy_evaluator::policy_fetcher::{policy::Policy, store::Store};
+use sha2::{Digest, Sha256};
 use std::fs::File;
+use std::io::Read;
+use std::path::{Component, Path, PathBuf};
 use tar::Archive;
 
+use crate::save::{BundleIndex, BUNDLE_INDEX, BUNDLE_INDEX_VERSION};
+
 // load policies inside the tarball provided by source_path into the default store
 pub(crate) fn load(source_path: &str) -> Result<()> {
-    let default_store = Store::default();
-    let destination_path = default_store.root;
+    load_into_store(&Store::default(), source_path)
+}
+
+pub(crate) fn load_into_store(store: &Store, source_path: &str) -> Result<()> {
     let tar_gz =
         File::open(source_path).map_err(|e| anyhow!("cannot open file {}: {}", source_path, e))?;
     let tar = GzDecoder::new(tar_gz);
     let mut archive = Archive::new(tar);
-    archive
-        .unpack(destination_path)
-        .map_err(|e| anyhow!("cannot unpack file {}: {}", source_path, e))?;
+
+    let mut index: Option<BundleIndex> = None;
+    let mut modules: Vec<(PathBuf, Vec<u8>)> = Vec::new();
+    let entries = archive
+        .entries()
+        .map_err(|e| anyhow!("cannot read file {}: {}", source_path, e))?;
+    for entry in entries {
+        let mut entry = entry.map_err(|e| anyhow!("cannot read file {}: {}", source_path, e))?;
+        if !entry.header().entry_type().is_file() {
+            continue;
+        }
+        let path = entry
+            .path()
+            .map_err(|e| anyhow!("cannot read file {}: {}", source_path, e))?
+            .into_owned();
+        if !is_inside_of_store(&path) {
+            return Err(anyhow!(
+                "cannot load file {}: invalid path {}",
+                source_path,
+                path.display()
+            ));
+        }
+        let mut contents = Vec::new();
+        entry
+            .read_to_end(&mut contents)
+            .map_err(|e| anyhow!("cannot read {} from {}: {}", path.display(), source_path, e))?;
+
+        if path == Path::new(BUNDLE_INDEX) {
+            index = Some(
+                serde_json::from_slice(&contents)
+                    .map_err(|e| anyhow!("invalid {} file: {}", BUNDLE_INDEX, e))?,
+            );
+        } else {
+            modules.push((path, contents));
+        }
+    }
+
+    // tarballs created by older versions of kwctl have no index, their
+    // policies are loaded as they are
+    if let Some(index) = &index {
+        check_index(index, &modules)?;
+    }
+
+    for (path, contents) in &modules {
+        let destination = store.root.join(path);
+        if let Some(parent) = destination.parent() {
+            std::fs::create_dir_all(parent)
+                .map_err(|e| anyhow!("cannot create directory {}: {}", parent.display(), e))?;
+        }
+        std::fs::write(&destination, contents)
+            .map_err(|e| anyhow!("cannot write policy {}: {}", destination.display(), e))?;
+    }
+
+    for bundled_policy in index.map(|index| index.policies).unwrap_or_default() {
+        if let Some(provenance) = bundled_policy.provenance {
+            let policy = Policy {
+                uri: bundled_policy.uri.clone(),
+                local_path: store.root.join(&bundled_policy.path),
+            };
+            store.save_provenance(&policy, &provenance).map_err(|e| {
+                anyhow!(
+                    "cannot save provenance of policy {}: {}",
+                    bundled_policy.uri,
+                    e
+                )
+            })?;
+        }
+    }
 
     Ok(())
 }
+
+/// Ensure the policies listed by the index are all inside of the tarball, and
+/// have not been altered
+fn check_index(index: &BundleIndex, modules: &[(PathBuf, Vec<u8>)]) -> Result<()> {
+    if index.version > BUNDLE_INDEX_VERSION {
+        return Err(anyhow!(
+            "unsupported {} version {}, a newer version of kwctl is required",
+            BUNDLE_INDEX,
+            index.version
+        ));
+    }
+
+    for bundled_policy in &index.policies {
+        let contents = modules
+            .iter()
+            .find(|(path, _)| path == &bundled_policy.path)
+            .map(|(_, contents)| contents)
+            .ok_or_else(|| anyhow!("policy {} is missing", bundled_policy.uri))?;
+
+        let sha256 = format!("{:x}", Sha256::digest(contents));
+        if sha256 != bundled_policy.sha256 {
+            return Err(anyhow!(
+                "the digest of policy {} doesn't match: expected {}, got {}",
+                bundled_policy.uri,
+                bundled_policy.sha256,
+                sha256
+            ));
+        }
+    }
+
+    Ok(())
+}
+
+/// Files can be written only inside of the store
+fn is_inside_of_store(path: &Path) -> bool {
+    path.components()
+        .all(|component| matches!(component, Component::Normal(_) | Component::CurDir))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::save::save_from_store;
+    use policy_evaluator::policy_fetcher::store::{
+        provenance::{PolicyProvenance, VerificationStatus},
+        PolicyPath,
+    };
+    use tempfile::tempdir;
+
+    const POLICY_URI: &str = "registry://ghcr.io/kubewarden/policies/pod-privileged:v0.2.5";
+
+    fn store_with_policy(root: &Path) -> Store {
+        let store = Store::new(root);
+        let local_path = store
+            .policy_full_path(POLICY_URI, PolicyPath::PrefixAndFilename)
+            .unwrap();
+        std::fs::create_dir_all(local_path.parent().unwrap()).unwrap();
+        std::fs::write(&local_path, b"\0asm fake module").unwrap();
+
+        let policy = Policy {
+            uri: POLICY_URI.to_owned(),
+            local_path,
+        };
+        store.record_verification(&policy, "sha256:1234").unwrap();
+
+        store
+    }
+
+    #[test]
+    fn save_and_load_preserve_provenance() {
+        let tempdir = tempdir().unwrap();
+        let source = store_with_policy(&tempdir.path().join("source"));
+        let tarball = tempdir.path().join("policies.tar.gz");
+        let tarball = tarball.to_str().unwrap();
+        save_from_store(&source, vec![&POLICY_URI.to_owned()], tarball).unwrap();
+
+        let destination = Store::new(&tempdir.path().join("destination"));
+        load_into_store(&destination, tarball).unwrap();
+
+        let policy = destination.get_policy_by_uri(POLICY_URI).unwrap().unwrap();
+        let source_policy = source.get_policy_by_uri(POLICY_URI).unwrap().unwrap();
+        assert_eq!(policy.digest().unwrap(), source_policy.digest().unwrap());
+
+        let provenance: PolicyProvenance = destination.provenance(&policy).unwrap().unwrap();
+        assert_eq!(
+            provenance,
+            source.provenance(&source_policy).unwrap().unwrap()
+        );
+        assert_eq!(provenance.verification, VerificationStatus::Verified);
+        assert_eq!(provenance.manifest_digest.as_deref(), Some("sha256:1234"));
+    }
+
+    #[test]
+    fn load_rejects_altered_policies() {
+        let index = BundleIndex {
+            version: BUNDLE_INDEX_VERSION,
+            policies: vec![crate::save::BundledPolicy {
+                uri: POLICY_URI.to_owned(),
+                path: PathBuf::from("registry/ghcr.io/policy.wasm"),
+                sha256: format!("{:x}", Sha256::digest(b"original")),
+                provenance: None,
+            }],
+        };
+
+        let modules = vec![(
+            PathBuf::from("registry/ghcr.io/policy.wasm"),
+            b"original".to_vec(),
+        )];
+        assert!(check_index(&index, &modules).is_ok());
+
+        let modules = vec![(
+            PathBuf::from("registry/ghcr.io/policy.wasm"),
+            b"altered".to_vec(),
+        )];
+        assert!(check_index(&index, &modules).is_err());
+
+        assert!(check_index(&index, &[]).is_err());
+    }
+
+    #[test]
+    fn paths_outside_of_the_store() {
+        assert!(is_inside_of_store(Path::new(
+            "registry/ghcr.io/policy.wasm"
+        )));
+        assert!(!is_inside_of_store(Path::new("../policy.wasm")));
+        assert!(!is_inside_of_store(Path::new("/etc/policy.wasm")));
+    }
+}
@@ -1,13 +1,24 @@
 use std::{
-    collections::HashMap, convert::TryFrom, env, fs, io::prelude::*, path::PathBuf, str::FromStr,
+    collections::HashMap,
+    convert::TryFrom,
+    env, fs,
+    io::prelude::*,
+    path::{Path, PathBuf},
+    process::ExitCode,
+    str::FromStr,
+    time::Duration,
 };
 
-use anyhow::{anyhow, Result};
+use anyhow::{anyhow, Context, Result};
 use clap::ArgMatches;
 use itertools::Itertools;
 use lazy_static::lazy_static;
-use policy_evaluator::policy_fetcher::{registry::Registry, store::DEFAULT_ROOT, PullDestination};
-use tracing::{debug, info};
+use policy_evaluator::policy_fetcher::{
+    registry::Registry,
+    store::{Store, DEFAULT_ROOT},
+    PullDestination,
+};
+use tracing::{debug, info, warn};
 use tracing_subscriber::{
     filter::{EnvFilter, LevelFilter},
     fmt,
@@ -19,6 +30,7 @@ use crate::{
         sources::remote_server_options,
         verification::{build_sigstore_trust_root, build_verification_options},
     },
+    errors::ErrorFormat,
     load::load,
     save::save,
     utils::{find_file_matching_file, LookupError},
@@ -31,16 +43,23 @@ mod cli;
 mod command;
 mod completions;
 mod config;
+mod diff;
+mod docs;
+mod errors;
+mod import;
 mod info;
 mod inspect;
 mod load;
 mod policies;
 mod pull;
 mod push;
+mod registry;
 mod rm;
 mod save;
 mod scaffold;
+mod schema_validation;
 mod utils;
+mod verification_config;
 mod verify;
 
 pub(crate) const KWCTL_VERIFICATION_CONFIG: &str = "verification-config.yml";
@@ -56,8 +75,15 @@ lazy_static! {
 }
 
 #[tokio::main]
-async fn main() -> Result<()> {
+async fn main() -> ExitCode {
     let matches = cli::build_cli().get_matches();
+    let error_format = match matches
+        .get_one::<String>("error-format")
+        .map(String::as_str)
+    {
+        Some("json") => ErrorFormat::Json,
+        _ => ErrorFormat::Text,
+    };
     let mut term_color_support = "dumb".to_string();
 
     if let Ok(val) = env::var("TERM") {
@@ -109,8 +135,71 @@ async fn main() -> Result<()> {
         )
         .init();
 
+    match run_command(&matches, no_color).await {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(error) => errors::report(&error, error_format),
+    }
+}
+
+async fn run_command(matches: &ArgMatches, no_color: bool) -> Result<()> {
     match matches.subcommand_name() {
-        Some("policies") => policies::list(),
+        Some("policies") => {
+            if let Some(matches) = matches
+                .subcommand_matches("policies")
+                .and_then(|matches| matches.subcommand_matches("verify-store"))
+            {
+                let sources = remote_server_options(matches)?;
+                let verification_options = build_verification_options(matches)?
+                    .ok_or_else(|| anyhow!("could not retrieve sigstore options"))?;
+                let sigstore_trust_root = build_sigstore_trust_root(matches.to_owned()).await?;
+                let output = match matches.get_one::<String>("output").map(String::as_str) {
+                    Some("json") => policies::OutputFormat::Json,
+                    _ => policies::OutputFormat::Table,
+                };
+                return policies::verify_store(
+                    sources.as_ref(),
+                    &verification_options,
+                    sigstore_trust_root,
+                    output,
+                    matches.get_flag("quarantine"),
+                    matches.get_flag("offline"),
+                )
+                .await;
+            }
+
+            if let Some(matches) = matches
+                .subcommand_matches("policies")
+                .and_then(|matches| matches.subcommand_matches("prune"))
+            {
+                let options = policies::PruneOptions {
+                    unused_for: matches
+                        .get_one::<u64>("unused-for")
+                        .map(|days| Duration::from_secs(days * 24 * 60 * 60)),
+                    keep_referenced_by: matches
+                        .get_many::<String>("keep-referenced-by")
+                        .unwrap_or_default()
+                        .map(PathBuf::from)
+                        .collect(),
+                    dry_run: matches.get_flag("dry-run"),
+                    yes: matches.get_flag("yes"),
+                };
+                let output = match matches.get_one::<String>("output").map(String::as_str) {
+                    Some("json") => policies::OutputFormat::Json,
+                    _ => policies::OutputFormat::Table,
+                };
+                return policies::prune(&options, output);
+            }
+
+            let output = match matches
+                .subcommand_matches("policies")
+                .and_then(|matches| matches.get_one::<String>("output"))
+                .map(String::as_str)
+            {
+                Some("json") => policies::OutputFormat::Json,
+                _ => policies::OutputFormat::Table,
+            };
+            policies::list(output)
+        }
         Some("info") => info::info(),
         Some("pull") => {
             if let Some(matches) = matches.subcommand_matches("pull") {
@@ -140,7 +229,7 @@ async fn main() -> Result<()> {
                     sigstore_trust_root.clone(),
                 )
                 .await
-                .map_err(|e| anyhow!("Policy {} cannot be validated\n{:?}", uri, e))?;
+                .with_context(|| format!("Policy {uri} cannot be validated"))?;
             };
             Ok(())
         }
@@ -203,6 +292,12 @@ async fn main() -> Result<()> {
                 .expect("bench subcommand not found");
             cli::bench::exec(bench_arg).await
         }
+        Some("check-determinism") => {
+            let check_determinism_arg = matches
+                .subcommand_matches("check-determinism")
+                .expect("check-determinism subcommand not found");
+            cli::determinism::exec(check_determinism_arg).await
+        }
         Some("annotate") => {
             if let Some(matches) = matches.subcommand_matches("annotate") {
                 let wasm_path = matches
@@ -231,6 +326,13 @@ async fn main() -> Result<()> {
                     matches.get_one::<String>("output").map(|s| s.as_str()),
                 )?;
                 let sources = remote_server_options(matches)?;
+                if matches.get_flag("show-settings-schema") {
+                    let settings = inspect::read_settings(
+                        matches.get_one::<String>("settings-path"),
+                        matches.get_one::<String>("settings-json"),
+                    )?;
+                    return inspect::inspect_settings_schema(uri_or_sha_prefix, output, settings);
+                }
                 let no_signatures = !matches
                     .get_one::<bool>("show-signatures")
                     .unwrap_or(&false)
@@ -286,6 +388,20 @@ async fn main() -> Result<()> {
                     scaffold_manifest_command(matches).await?;
                 };
             }
+            if let Some(matches) = matches.subcommand_matches("scaffold") {
+                if let Some(matches) = matches.subcommand_matches("settings") {
+                    let uri_or_sha_prefix = matches.get_one::<String>("uri_or_sha_prefix").unwrap();
+                    pull_if_needed(uri_or_sha_prefix, matches).await?;
+
+                    let interactive = matches.get_flag("interactive");
+                    let settings = scaffold::settings(uri_or_sha_prefix, interactive)?;
+                    if let Some(output) = matches.get_one::<String>("output") {
+                        fs::write(output, settings)?;
+                    } else {
+                        print!("{settings}");
+                    }
+                };
+            }
             if let Some(matches) = matches.subcommand_matches("scaffold") {
                 if let Some(matches) = matches.subcommand_matches("vap") {
                     let cel_policy_uri = matches.get_one::<String>("cel-policy").unwrap();
@@ -321,6 +437,29 @@ async fn main() -> Result<()> {
                     scaffold::admission_request(operation, object_path, old_object_path).await?;
                 };
             }
+            if let Some(matches) = matches.subcommand_matches("scaffold") {
+                if let Some(matches) = matches.subcommand_matches("discovery-snapshot") {
+                    let snapshot = scaffold::discovery_snapshot().await?;
+                    if let Some(output) = matches.get_one::<String>("output") {
+                        fs::write(output, snapshot)?;
+                    } else {
+                        println!("{}", snapshot);
+                    }
+                };
+            }
+            if let Some(matches) = matches.subcommand_matches("scaffold") {
+                if let Some(matches) = matches.subcommand_matches("rbac") {
+                    let policies_file: PathBuf =
+                        matches.get_one::<String>("policies").unwrap().into();
+                    let names = scaffold::RbacNames {
+                        name: matches.get_one::<String>("name").unwrap(),
+                        service_account: matches.get_one::<String>("service-account").unwrap(),
+                        namespace: matches.get_one::<String>("namespace").unwrap(),
+                    };
+
+                    scaffold::rbac(policies_file.as_path(), &names).await?;
+                };
+            }
 
             Ok(())
         }
@@ -330,6 +469,29 @@ async fn main() -> Result<()> {
             }
             Ok(())
         }
+        Some("diff") => {
+            if let Some(matches) = matches.subcommand_matches("diff") {
+                let old_uri_or_sha_prefix =
+                    matches.get_one::<String>("old_uri_or_sha_prefix").unwrap();
+                let new_uri_or_sha_prefix =
+                    matches.get_one::<String>("new_uri_or_sha_prefix").unwrap();
+                let output = diff::OutputType::try_from(
+                    matches.get_one::<String>("output").map(|s| s.as_str()),
+                )?;
+                let fail_on_expansion = matches
+                    .get_one::<bool>("fail-on-privilege-expansion")
+                    .unwrap_or(&false)
+                    .to_owned();
+                diff::diff(
+                    old_uri_or_sha_prefix,
+                    new_uri_or_sha_prefix,
+                    output,
+                    no_color,
+                    fail_on_expansion,
+                )?;
+            }
+            Ok(())
+        }
         Some("digest") => {
             if let Some(matches) = matches.subcommand_matches("digest") {
                 let uri = matches.get_one::<String>("uri").unwrap();
@@ -340,6 +502,67 @@ async fn main() -> Result<()> {
             }
             Ok(())
         }
+        Some("registry") => {
+            match matches
+                .subcommand_matches("registry")
+                .and_then(|m| m.subcommand())
+            {
+                Some(("rm", matches)) => {
+                    let uri = matches.get_one::<String>("uri").unwrap();
+                    let sources = remote_server_options(matches)?;
+                    registry::rm(uri, sources.as_ref(), matches.get_flag("yes")).await?;
+                }
+                Some(("tags", matches)) => {
+                    let uri = matches.get_one::<String>("uri").unwrap();
+                    let sources = remote_server_options(matches)?;
+                    let output = match matches.get_one::<String>("output").map(|s| s.as_str()) {
+                        Some("json") => registry::TagsOutputFormat::Json,
+                        _ => registry::TagsOutputFormat::Text,
+                    };
+                    registry::tags(uri, sources.as_ref(), output).await?;
+                }
+                Some(("tag", matches)) => {
+                    let uri = matches.get_one::<String>("uri").unwrap();
+                    let new_tag = matches.get_one::<String>("new-tag").unwrap();
+                    let sources = remote_server_options(matches)?;
+                    registry::tag(uri, new_tag, sources.as_ref()).await?;
+                }
+                _ => {}
+            }
+            Ok(())
+        }
+        Some("verification-config") => {
+            match matches
+                .subcommand_matches("verification-config")
+                .and_then(|m| m.subcommand())
+            {
+                Some(("push", matches)) => {
+                    let path = matches.get_one::<String>("path").unwrap();
+                    let uri = matches
+                        .get_one::<String>("uri")
+                        .map(|u| {
+                            if u.starts_with("registry://") {
+                                u.clone()
+                            } else {
+                                format!("registry://{u}")
+                            }
+                        })
+                        .unwrap();
+                    let sources = remote_server_options(matches)?;
+                    let immutable_ref =
+                        verification_config::push(Path::new(path), &uri, sources.as_ref()).await?;
+                    println!("{immutable_ref}");
+                }
+                Some(("pull", matches)) => {
+                    let uri = matches.get_one::<String>("uri").unwrap();
+                    let output = matches.get_one::<String>("output").map(Path::new);
+                    let sources = remote_server_options(matches)?;
+                    verification_config::pull(uri, output, sources.as_ref()).await?;
+                }
+                _ => {}
+            }
+            Ok(())
+        }
         Some("save") => {
             if let Some(matches) = matches.subcommand_matches("save") {
                 let policies = matches.get_many::<String>("policies").unwrap();
@@ -349,6 +572,32 @@ async fn main() -> Result<()> {
             }
             Ok(())
         }
+        Some("store") => {
+            if let Some(matches) = matches
+                .subcommand_matches("store")
+                .and_then(|matches| matches.subcommand_matches("import-from-server"))
+            {
+                let sources = remote_server_options(matches)?;
+                let server = import::PolicyServerPod {
+                    namespace: matches.get_one::<String>("namespace").unwrap(),
+                    name: matches.get_one::<String>("pod").unwrap(),
+                    port: *matches.get_one::<u16>("port").unwrap(),
+                    insecure: matches.get_flag("insecure"),
+                };
+                let output_dir = matches
+                    .get_one::<String>("output-dir")
+                    .map(PathBuf::from)
+                    .unwrap_or_else(|| import::default_output_dir(&server));
+                import::import_from_server(
+                    command::run::evaluator::build_kube_client,
+                    &server,
+                    sources.as_ref(),
+                    &output_dir,
+                )
+                .await?;
+            }
+            Ok(())
+        }
         Some("load") => {
             if let Some(matches) = matches.subcommand_matches("load") {
                 let input = matches.get_one::<String>("input").unwrap();
@@ -358,6 +607,22 @@ async fn main() -> Result<()> {
         }
         Some("docs") => {
             if let Some(matches) = matches.subcommand_matches("docs") {
+                if let Some(uri_or_sha_prefix) = matches.get_one::<String>("uri_or_sha_prefix") {
+                    pull_if_needed(uri_or_sha_prefix, matches).await?;
+
+                    let format = docs::DocsFormat::try_from(
+                        matches.get_one::<String>("format").map(|s| s.as_str()),
+                    )?;
+                    let content = docs::policy_docs(uri_or_sha_prefix, format)?;
+                    if let Some(output) = matches.get_one::<String>("output") {
+                        fs::write(output, content)
+                            .map_err(|e| anyhow!("cannot write to file {}: {}", output, e))?;
+                    } else {
+                        print!("{content}");
+                    }
+                    return Ok(());
+                }
+
                 let output = matches.get_one::<String>("output").unwrap();
                 let mut file = std::fs::File::create(output)
                     .map_err(|e| anyhow!("cannot create file {}: {}", output, e))?;
@@ -401,33 +666,47 @@ async fn pull_command(
 
     let verification_options = build_verification_options(matches)?;
     let mut verified_manifest_digest: Option<String> = None;
+    let mut recorded_bundles = None;
     if verification_options.is_some() {
         let sigstore_trust_root = build_sigstore_trust_root(matches.to_owned()).await?;
         // verify policy prior to pulling if keys listed, and keep the
         // verified manifest digest:
-        verified_manifest_digest = Some(
-            verify::verify(
-                uri,
-                sources.as_ref(),
-                verification_options.as_ref().unwrap(),
-                sigstore_trust_root.clone(),
-            )
-            .await
-            .map_err(|e| anyhow!("Policy {} cannot be validated\n{:?}", uri, e))?,
-        );
+        let (manifest_digest, bundles) = verify::verify_and_record_bundles(
+            uri,
+            sources.as_ref(),
+            verification_options.as_ref().unwrap(),
+            sigstore_trust_root.clone(),
+        )
+        .await
+        .with_context(|| format!("Policy {uri} cannot be validated"))?;
+        verified_manifest_digest = Some(manifest_digest);
+        recorded_bundles = bundles;
     }
 
+    let pulled_into_store = matches!(destination, PullDestination::MainStore);
     let policy = pull::pull(uri, sources.as_ref(), destination).await?;
 
-    if verification_options.is_some() {
+    if let Some(verified_manifest_digest) = verified_manifest_digest {
         let sigstore_trust_root = build_sigstore_trust_root(matches.to_owned()).await?;
-        return verify::verify_local_checksum(
+        verify::verify_local_checksum(
             &policy,
             sources.as_ref(),
-            &verified_manifest_digest.unwrap(),
+            &verified_manifest_digest,
             sigstore_trust_root.clone(),
         )
-        .await;
+        .await?;
+
+        if pulled_into_store {
+            if let Err(e) = Store::default().record_verification(&policy, &verified_manifest_digest)
+            {
+                warn!(policy = policy.uri.as_str(), error = %e, "cannot record the verification of the policy");
+            }
+            if let Some(recorded_bundles) = recorded_bundles {
+                if let Err(e) = Store::default().save_bundles(&policy, &recorded_bundles) {
+                    warn!(policy = policy.uri.as_str(), error = %e, "cannot record the Sigstore bundles of the policy");
+                }
+            }
+        }
     }
     Ok(())
 }
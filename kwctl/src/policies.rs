@@ -1,14 +1,66 @@
+use std::{
+    collections::HashSet,
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
 use anyhow::{anyhow, Result};
 use policy_evaluator::{
-    policy_fetcher::{policy::Policy, store::Store},
+    policy_fetcher::{
+        sigstore::trust::ManualTrustRoot,
+        sources::Sources,
+        store::{provenance::VerificationStatus, Store, StoreEntry, StoreFilter},
+        verify::{config::LatestVerificationConfig, Verifier},
+    },
     policy_metadata::Metadata as PolicyMetadata,
 };
 use prettytable::{format, row, Table};
+use serde::{Deserialize, Serialize};
+use tracing::{info, warn};
 
-pub(crate) fn list() -> Result<()> {
-    if policy_list()?.is_empty() {
-        return Ok(());
+use crate::{
+    errors::{ErrorKind, KwctlError},
+    registry::confirm,
+};
+
+/// The formats `kwctl policies` can print the contents of the store with
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub(crate) enum OutputFormat {
+    #[default]
+    Table,
+    Json,
+}
+
+/// A policy of the store, enriched with the information read from its metadata
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct PolicyListEntry {
+    #[serde(flatten)]
+    entry: StoreEntry,
+    /// `None` when the policy has no metadata
+    mutating: Option<bool>,
+    context_aware: bool,
+}
+
+pub(crate) fn list(output: OutputFormat) -> Result<()> {
+    let policies = policy_list()?;
+
+    match output {
+        OutputFormat::Json => {
+            println!("{}", serde_json::to_string_pretty(&policies)?);
+        }
+        OutputFormat::Table => {
+            if policies.is_empty() {
+                return Ok(());
+            }
+            print_table(&policies);
+        }
     }
+    Ok(())
+}
+
+fn print_table(policies: &[PolicyListEntry]) {
     let mut table = Table::new();
     table.set_format(*format::consts::FORMAT_NO_LINESEP_WITH_TITLE);
     table.set_titles(row![
@@ -18,45 +70,517 @@ pub(crate) fn list() -> Result<()> {
         "SHA-256",
         "Size"
     ]);
-    for policy in policy_list()? {
-        let (mutating, context_aware) = if let Some(policy_metadata) =
-            PolicyMetadata::from_path(&policy.local_path)
-                .map_err(|e| anyhow!("error processing metadata of policy {}: {:?}", policy, e))?
-        {
-            let mutating = if policy_metadata.mutating {
-                "yes"
-            } else {
-                "no"
-            };
-
-            let context_aware = if policy_metadata.context_aware_resources.is_empty() {
-                "no"
-            } else {
-                "yes"
-            };
-
-            (mutating, context_aware)
-        } else {
-            ("unknown", "no")
+    for policy in policies {
+        let mutating = match policy.mutating {
+            Some(true) => "yes",
+            Some(false) => "no",
+            None => "unknown",
         };
+        let context_aware = if policy.context_aware { "yes" } else { "no" };
 
-        let mut sha256sum = policy.digest()?;
+        let mut sha256sum = policy.entry.sha256.clone();
         sha256sum.truncate(12);
 
-        let policy_filesystem_metadata = std::fs::metadata(&policy.local_path)?;
-
         table.add_row(row![
-            format!("{policy}"),
+            policy.entry.uri,
             mutating,
             context_aware,
             sha256sum,
-            humansize::format_size(policy_filesystem_metadata.len(), humansize::DECIMAL),
+            humansize::format_size(policy.entry.size, humansize::DECIMAL),
         ]);
     }
     table.printstd();
+}
+
+fn policy_list() -> Result<Vec<PolicyListEntry>> {
+    Store::default()
+        .list_entries(&StoreFilter::default())?
+        .into_iter()
+        .map(|entry| {
+            let policy_metadata = PolicyMetadata::from_path(&entry.local_path).map_err(|e| {
+                anyhow!("error processing metadata of policy {}: {:?}", entry.uri, e)
+            })?;
+            let (mutating, context_aware) = match policy_metadata {
+                Some(policy_metadata) => (
+                    Some(policy_metadata.mutating),
+                    !policy_metadata.context_aware_resources.is_empty(),
+                ),
+                None => (None, false),
+            };
+
+            Ok(PolicyListEntry {
+                entry,
+                mutating,
+                context_aware,
+            })
+        })
+        .collect()
+}
+
+/// The outcome of the verification of a policy of the store
+#[derive(Serialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "camelCase")]
+enum VerificationOutcome {
+    Verified,
+    /// Verified without network access, using the recorded Sigstore bundles
+    VerifiedOfflineBundle,
+    Failed,
+    /// Only the policies pulled from OCI registries can be verified
+    Skipped,
+}
+
+/// The result of verifying again a policy of the store
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct StoreVerificationEntry {
+    uri: String,
+    sha256: String,
+    /// The verification status recorded before running the verification again
+    previous_verification: VerificationStatus,
+    outcome: VerificationOutcome,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    manifest_digest: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+    /// Where the policy has been moved, when quarantined
+    #[serde(skip_serializing_if = "Option::is_none")]
+    quarantined_path: Option<PathBuf>,
+}
+
+impl StoreVerificationEntry {
+    /// A policy that was verified once, but no longer satisfies the verification config
+    fn is_drift(&self) -> bool {
+        matches!(
+            self.previous_verification,
+            VerificationStatus::Verified | VerificationStatus::VerifiedOfflineBundle
+        ) && self.outcome == VerificationOutcome::Failed
+    }
+}
+
+/// Verify again the signatures and the local checksum of all the policies of the store,
+/// using the given verification config. The verification status recorded inside
+/// of the store is updated.
+///
+/// When `offline` is set, no network access is made: the policies are verified using
+/// the Sigstore bundles recorded inside of the store when they have been pulled.
+///
+/// An error is returned when at least one policy fails the verification.
+pub(crate) async fn verify_store(
+    sources: Option<&Sources>,
+    verification_config: &LatestVerificationConfig,
+    sigstore_trust_root: Option<Arc<ManualTrustRoot<'static>>>,
+    output: OutputFormat,
+    quarantine: bool,
+    offline: bool,
+) -> Result<()> {
+    let store = Store::default();
+    let mut verifier = Verifier::new(sources.cloned(), sigstore_trust_root).await?;
+
+    let mut results = Vec::new();
+    for entry in store.list_entries(&StoreFilter::default())? {
+        let mut result = StoreVerificationEntry {
+            uri: entry.uri.clone(),
+            sha256: entry.sha256.clone(),
+            previous_verification: entry
+                .provenance
+                .as_ref()
+                .map(|provenance| provenance.verification)
+                .unwrap_or_default(),
+            outcome: VerificationOutcome::Skipped,
+            manifest_digest: None,
+            error: None,
+            quarantined_path: None,
+        };
+        if !entry.uri.starts_with("registry://") {
+            results.push(result);
+            continue;
+        }
+
+        let verification = if offline {
+            verify_entry_offline(&verifier, &store, &entry, verification_config)
+        } else {
+            verify_entry(&mut verifier, &store, &entry, verification_config).await
+        };
+        match verification {
+            Ok(manifest_digest) if offline => {
+                info!(policy = %entry.uri, "policy successfully verified offline");
+                store.record_offline_verification(&entry.policy(), &manifest_digest)?;
+                result.outcome = VerificationOutcome::VerifiedOfflineBundle;
+                result.manifest_digest = Some(manifest_digest);
+            }
+            Ok(manifest_digest) => {
+                info!(policy = %entry.uri, "policy successfully verified");
+                store.record_verification(&entry.policy(), &manifest_digest)?;
+                result.outcome = VerificationOutcome::Verified;
+                result.manifest_digest = Some(manifest_digest);
+            }
+            Err(e) => {
+                warn!(policy = %entry.uri, error = ?e, "policy verification failed");
+                result.outcome = VerificationOutcome::Failed;
+                result.error = Some(e.to_string());
+                if quarantine {
+                    result.quarantined_path = Some(store.quarantine(&entry.policy())?);
+                }
+            }
+        }
+        results.push(result);
+    }
+
+    match output {
+        OutputFormat::Json => {
+            println!("{}", serde_json::to_string_pretty(&results)?);
+        }
+        OutputFormat::Table => {
+            if !results.is_empty() {
+                print_verification_table(&results);
+            }
+        }
+    }
+
+    let failures = results
+        .iter()
+        .filter(|result| result.outcome == VerificationOutcome::Failed)
+        .count();
+    if failures > 0 {
+        return Err(KwctlError::new(
+            ErrorKind::VerificationFailed,
+            format!("{failures} policies do not satisfy the verification config"),
+        )
+        .into());
+    }
+    Ok(())
+}
+
+async fn verify_entry(
+    verifier: &mut Verifier,
+    store: &Store,
+    entry: &StoreEntry,
+    verification_config: &LatestVerificationConfig,
+) -> Result<String> {
+    if entry.is_modified() {
+        return Err(anyhow!(
+            "the WebAssembly module has been changed after being pulled"
+        ));
+    }
+
+    let (manifest_digest, recorded_bundles) = verifier
+        .verify_and_record_bundles(&entry.uri, verification_config)
+        .await?;
+    verifier
+        .verify_local_file_checksum(&entry.policy(), &manifest_digest)
+        .await?;
+    if let Some(recorded_bundles) = recorded_bundles {
+        store.save_bundles(&entry.policy(), &recorded_bundles)?;
+    }
+
+    Ok(manifest_digest)
+}
+
+/// Verify the policy using only the Sigstore bundles recorded inside of the store
+fn verify_entry_offline(
+    verifier: &Verifier,
+    store: &Store,
+    entry: &StoreEntry,
+    verification_config: &LatestVerificationConfig,
+) -> Result<String> {
+    if entry.is_modified() {
+        return Err(anyhow!(
+            "the WebAssembly module has been changed after being pulled"
+        ));
+    }
+
+    let recorded_bundles = store.bundles(&entry.policy())?.ok_or_else(|| {
+        anyhow!("no Sigstore bundles have been recorded, the policy cannot be verified offline")
+    })?;
+    verifier.verify_offline(&entry.policy(), &recorded_bundles, verification_config)?;
+
+    Ok(recorded_bundles.manifest_digest)
+}
+
+fn print_verification_table(results: &[StoreVerificationEntry]) {
+    let mut table = Table::new();
+    table.set_format(*format::consts::FORMAT_NO_LINESEP_WITH_TITLE);
+    table.set_titles(row!["Policy", "Previously", "Now", "Details"]);
+    for result in results {
+        let previously = match result.previous_verification {
+            VerificationStatus::Verified => "verified",
+            VerificationStatus::VerifiedOfflineBundle => "verified offline (bundle)",
+            VerificationStatus::NotVerified => "not verified",
+        };
+        let now = match result.outcome {
+            VerificationOutcome::Verified => "verified",
+            VerificationOutcome::VerifiedOfflineBundle => "verified offline (bundle)",
+            VerificationOutcome::Failed if result.is_drift() => "failed (drift)",
+            VerificationOutcome::Failed => "failed",
+            VerificationOutcome::Skipped => "skipped",
+        };
+        let details = match (&result.error, &result.quarantined_path) {
+            (Some(error), Some(path)) => format!("{error}\nquarantined to {}", path.display()),
+            (Some(error), None) => error.clone(),
+            _ => String::new(),
+        };
+
+        table.add_row(row![result.uri, previously, now, details]);
+    }
+    table.printstd();
+}
+
+/// Which policies of the store are removed by `kwctl policies prune`
+#[derive(Debug, Default)]
+pub(crate) struct PruneOptions {
+    /// Remove the policies that have not been used for longer than this. The
+    /// policies that have never been used are considered unused since they
+    /// have been pulled
+    pub unused_for: Option<Duration>,
+    /// Keep the policies referenced by the `module` fields of these YAML files,
+    /// like the `policies.yml` file of policy-server or the manifests generated
+    /// by `kwctl scaffold manifest`
+    pub keep_referenced_by: Vec<PathBuf>,
+    /// Only list the policies that would be removed
+    pub dry_run: bool,
+    /// Do not ask for confirmation before removing the policies
+    pub yes: bool,
+}
+
+/// A policy of the store that is going to be removed
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct PruneCandidate {
+    uri: String,
+    sha256: String,
+    size: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    last_used_at: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pulled_at: Option<u64>,
+}
+
+/// Remove from the store the policies that are no longer needed. A policy is
+/// removed when it satisfies all the criteria that are set
+pub(crate) fn prune(options: &PruneOptions, output: OutputFormat) -> Result<()> {
+    if options.unused_for.is_none() && options.keep_referenced_by.is_empty() {
+        return Err(anyhow!(
+            "at least one among `--unused-for` and `--keep-referenced-by` must be set"
+        ));
+    }
+
+    let referenced_modules = options
+        .keep_referenced_by
+        .iter()
+        .map(|path| referenced_modules(path))
+        .collect::<Result<Vec<_>>>()?
+        .into_iter()
+        .flatten()
+        .collect::<HashSet<String>>();
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default();
+
+    let store = Store::default();
+    let candidates: Vec<PruneCandidate> = store
+        .list_entries(&StoreFilter::default())?
+        .into_iter()
+        .filter(|entry| {
+            let unused = options
+                .unused_for
+                .is_none_or(|unused_for| is_unused(entry, now, unused_for));
+            unused && !referenced_modules.contains(&entry.uri)
+        })
+        .map(|entry| PruneCandidate {
+            pulled_at: entry.provenance.as_ref().map(|p| p.pulled_at),
+            last_used_at: entry.last_used_at,
+            uri: entry.uri,
+            sha256: entry.sha256,
+            size: entry.size,
+        })
+        .collect();
+
+    match output {
+        OutputFormat::Json => println!("{}", serde_json::to_string_pretty(&candidates)?),
+        OutputFormat::Table => print_prune_table(&candidates, now),
+    }
+
+    let freed_size: u64 = candidates.iter().map(|candidate| candidate.size).sum();
+    let freed_size = humansize::format_size(freed_size, humansize::DECIMAL);
+    if candidates.is_empty() || options.dry_run {
+        info!(
+            policies = candidates.len(),
+            size = freed_size.as_str(),
+            "dry run, no policy has been removed"
+        );
+        return Ok(());
+    }
+    if !options.yes
+        && !confirm(&format!(
+            "Remove {} policies from the store, freeing {freed_size}?",
+            candidates.len()
+        ))?
+    {
+        return Err(anyhow!("the policies have not been removed"));
+    }
+
+    for candidate in &candidates {
+        crate::rm::rm(&candidate.uri)?;
+        info!(policy = candidate.uri.as_str(), "policy removed");
+    }
+    store.compact_usage_journal()?;
+
     Ok(())
 }
 
-fn policy_list() -> Result<Vec<Policy>> {
-    Store::default().list().map_err(anyhow::Error::new)
+/// Whether the policy has not been used for longer than `unused_for`. Policies
+/// that have never been used are considered unused since they have been pulled.
+/// Policies pulled by older versions of kwctl, without a recorded usage, are
+/// always considered unused
+fn is_unused(entry: &StoreEntry, now: u64, unused_for: Duration) -> bool {
+    let last_activity = entry
+        .last_used_at
+        .or_else(|| entry.provenance.as_ref().map(|p| p.pulled_at));
+
+    match last_activity {
+        Some(last_activity) => now.saturating_sub(last_activity) > unused_for.as_secs(),
+        None => true,
+    }
+}
+
+/// The URIs of the policies referenced by the given YAML file. These are the
+/// values of all the `module` fields of all the documents of the file, the
+/// `registry://` scheme is added when missing
+fn referenced_modules(path: &Path) -> Result<HashSet<String>> {
+    let contents = std::fs::read_to_string(path)
+        .map_err(|e| anyhow!("cannot read {}: {e}", path.display()))?;
+
+    let mut modules = HashSet::new();
+    for document in serde_yaml::Deserializer::from_str(&contents) {
+        let document = serde_yaml::Value::deserialize(document)
+            .map_err(|e| anyhow!("cannot parse {}: {e}", path.display()))?;
+        collect_modules(&document, &mut modules);
+    }
+
+    Ok(modules)
+}
+
+fn collect_modules(value: &serde_yaml::Value, modules: &mut HashSet<String>) {
+    match value {
+        serde_yaml::Value::Mapping(mapping) => {
+            for (key, value) in mapping {
+                match (key.as_str(), value.as_str()) {
+                    (Some("module"), Some(module)) if module.contains("://") => {
+                        modules.insert(module.to_owned());
+                    }
+                    (Some("module"), Some(module)) => {
+                        modules.insert(format!("registry://{module}"));
+                    }
+                    _ => collect_modules(value, modules),
+                }
+            }
+        }
+        serde_yaml::Value::Sequence(sequence) => {
+            for value in sequence {
+                collect_modules(value, modules);
+            }
+        }
+        serde_yaml::Value::Tagged(tagged) => collect_modules(&tagged.value, modules),
+        _ => {}
+    }
+}
+
+fn print_prune_table(candidates: &[PruneCandidate], now: u64) {
+    if candidates.is_empty() {
+        return;
+    }
+
+    let days_ago = |timestamp: u64| match now.saturating_sub(timestamp) / (24 * 60 * 60) {
+        0 => "today".to_owned(),
+        1 => "1 day ago".to_owned(),
+        days => format!("{days} days ago"),
+    };
+
+    let mut table = Table::new();
+    table.set_format(*format::consts::FORMAT_NO_LINESEP_WITH_TITLE);
+    table.set_titles(row!["Policy", "Last used", "Pulled", "Size"]);
+    for candidate in candidates {
+        table.add_row(row![
+            candidate.uri,
+            candidate.last_used_at.map_or("never".to_owned(), days_ago),
+            candidate.pulled_at.map_or("unknown".to_owned(), days_ago),
+            humansize::format_size(candidate.size, humansize::DECIMAL),
+        ]);
+    }
+    table.printstd();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use policy_evaluator::policy_fetcher::store::provenance::PolicyProvenance;
+    use rstest::rstest;
+    use std::io::Write;
+
+    const DAY: u64 = 24 * 60 * 60;
+
+    fn entry(last_used_at: Option<u64>, pulled_at: Option<u64>) -> StoreEntry {
+        StoreEntry {
+            uri: "registry://ghcr.io/kubewarden/policies/pod-privileged:v0.2.1".to_owned(),
+            local_path: PathBuf::from("/tmp/pod-privileged.wasm"),
+            sha256: "1234".to_owned(),
+            size: 1024,
+            provenance: pulled_at.map(|pulled_at| PolicyProvenance {
+                pulled_at,
+                ..PolicyProvenance::new("registry://ghcr.io", "1234")
+            }),
+            last_used_at,
+        }
+    }
+
+    #[rstest]
+    #[case::recently_used(Some(99 * DAY), Some(10 * DAY), false)]
+    #[case::used_long_ago(Some(80 * DAY), Some(10 * DAY), true)]
+    #[case::never_used_recently_pulled(None, Some(95 * DAY), false)]
+    #[case::never_used(None, Some(10 * DAY), true)]
+    #[case::no_provenance(None, None, true)]
+    fn unused_policies(
+        #[case] last_used_at: Option<u64>,
+        #[case] pulled_at: Option<u64>,
+        #[case] expected: bool,
+    ) {
+        let unused_for = Duration::from_secs(7 * DAY);
+
+        assert_eq!(
+            is_unused(&entry(last_used_at, pulled_at), 100 * DAY, unused_for),
+            expected
+        );
+    }
+
+    #[test]
+    fn modules_referenced_by_yaml_files() {
+        let mut file = tempfile::NamedTempFile::new().unwrap();
+        write!(
+            file,
+            r#"
+privileged-pods:
+  module: registry://ghcr.io/kubewarden/policies/pod-privileged:v0.2.1
+group:
+  policies:
+    signed:
+      module: ghcr.io/kubewarden/policies/verify-image-signatures:v0.3.0
+---
+apiVersion: policies.kubewarden.io/v1
+kind: ClusterAdmissionPolicy
+spec:
+  module: https://example.com/policies/psp.wasm
+"#
+        )
+        .unwrap();
+
+        assert_eq!(
+            referenced_modules(file.path()).unwrap(),
+            HashSet::from([
+                "registry://ghcr.io/kubewarden/policies/pod-privileged:v0.2.1".to_owned(),
+                "registry://ghcr.io/kubewarden/policies/verify-image-signatures:v0.3.0".to_owned(),
+                "https://example.com/policies/psp.wasm".to_owned(),
+            ])
+        );
+    }
 }
@@ -1,9 +1,13 @@
-use std::time::Duration;
+use std::{collections::HashMap, time::Duration};
 
-use anyhow::Result;
+use anyhow::{anyhow, Result};
 use indicatif::{ProgressBar, ProgressStyle};
 use policy_evaluator::policy_fetcher::{
-    fetch_policy, policy::Policy, sources::Sources, PullDestination,
+    download::{DownloadManager, FetcherOptions},
+    fetch_policy,
+    policy::Policy,
+    sources::Sources,
+    PullDestination,
 };
 
 pub(crate) async fn pull(
@@ -31,3 +35,45 @@ pub(crate) async fn pull(
 
     result
 }
+
+/// Pull all the given policies into the main store, `fetcher_options.concurrency`
+/// of them at the same time. Returns the pulled policies, keyed by URI
+pub(crate) async fn pull_all(
+    uris: &[String],
+    sources: Option<&Sources>,
+    fetcher_options: &FetcherOptions,
+) -> Result<HashMap<String, Policy>> {
+    let pb = ProgressBar::new_spinner();
+    pb.set_style(
+        ProgressStyle::default_spinner()
+            .template("{spinner:.green} {msg}")
+            .expect("cannot set spinner template"),
+    );
+    pb.set_message(format!("Pulling {} policies", uris.len()));
+    pb.enable_steady_tick(Duration::from_millis(100));
+
+    let mut fetched = DownloadManager::new(fetcher_options.clone())
+        .fetch_policies(
+            uris.iter().map(String::as_str),
+            &PullDestination::MainStore,
+            sources,
+        )
+        .await;
+
+    let mut policies = HashMap::new();
+    for uri in uris {
+        match fetched.remove(uri) {
+            Some(Ok(policy)) => {
+                policies.insert(uri.to_owned(), policy);
+            }
+            Some(Err(e)) => {
+                pb.finish_with_message(format!("Failed to pull policy {}: {}", uri, e));
+                return Err(anyhow!("Failed to pull policy {}: {}", uri, e));
+            }
+            None => {}
+        }
+    }
+    pb.finish_with_message(format!("Successfully pulled {} policies", policies.len()));
+
+    Ok(policies)
+}
@@ -11,7 +11,10 @@ use policy_evaluator::{
 };
 use tracing::{debug, warn};
 
-use crate::backend::BackendDetector;
+use crate::{
+    backend::BackendDetector,
+    errors::{ErrorKind, KwctlError},
+};
 
 pub(crate) async fn push(
     wasm_path: PathBuf,
@@ -30,7 +33,11 @@ pub(crate) async fn push(
                 return Err(anyhow!("Rego policies cannot be pushed without metadata"));
             }
         } else {
-            return Err(anyhow!("Cannot push a policy that is not annotated. Use `annotate` command or `push --force`"));
+            return Err(KwctlError::new(
+                ErrorKind::InvalidInput,
+                "Cannot push a policy that is not annotated. Use `annotate` command or `push --force`",
+            )
+            .into());
         }
     }
 
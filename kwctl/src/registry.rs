@@ -0,0 +1,128 @@
+use std::io::{self, BufRead, Write};
+
+use anyhow::{anyhow, Result};
+use is_terminal::IsTerminal;
+use policy_evaluator::policy_fetcher::{registry::Registry, sources::Sources};
+
+/// The formats `kwctl registry tags` can print the tags with
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub(crate) enum TagsOutputFormat {
+    #[default]
+    Text,
+    Json,
+}
+
+pub(crate) async fn rm(uri: &str, sources: Option<&Sources>, yes: bool) -> Result<()> {
+    let registry = Registry::new();
+    let digest = registry.manifest_digest(uri, sources).await?;
+
+    if !yes
+        && !confirm(&format!(
+            "Delete {uri}@{digest}? All the tags pointing to it are going to be removed"
+        ))?
+    {
+        return Err(anyhow!("deletion of {uri} aborted"));
+    }
+
+    // delete by digest, the tag could have been moved meanwhile
+    let deleted_digest = registry
+        .delete(&format!("{}@{}", without_tag(uri), digest), sources)
+        .await?;
+    println!("Deleted {uri}@{deleted_digest}");
+
+    Ok(())
+}
+
+pub(crate) async fn tags(
+    uri: &str,
+    sources: Option<&Sources>,
+    output: TagsOutputFormat,
+) -> Result<()> {
+    let tags = Registry::new().tags(uri, sources).await?;
+
+    match output {
+        TagsOutputFormat::Json => println!("{}", serde_json::to_string_pretty(&tags)?),
+        TagsOutputFormat::Text => {
+            for tag in tags {
+                println!("{tag}");
+            }
+        }
+    }
+    Ok(())
+}
+
+pub(crate) async fn tag(uri: &str, new_tag: &str, sources: Option<&Sources>) -> Result<()> {
+    let immutable_ref = Registry::new().tag(uri, new_tag, sources).await?;
+    println!("{immutable_ref}");
+
+    Ok(())
+}
+
+/// Remove the tag and the digest from the given policy URI
+fn without_tag(uri: &str) -> &str {
+    let uri = uri.split_once('@').map_or(uri, |(uri, _)| uri);
+    // the last `:` is a port when followed by a path
+    match uri.rsplit_once(':') {
+        Some((repository, tag)) if !tag.contains('/') => repository,
+        _ => uri,
+    }
+}
+
+/// Ask the user to confirm an operation. Fails when kwctl is not run
+/// interactively, because nobody could answer
+pub(crate) fn confirm(question: &str) -> Result<bool> {
+    let stdin = io::stdin();
+    if !stdin.is_terminal() {
+        return Err(anyhow!(
+            "cannot ask for confirmation, stdin is not a terminal. Use the `--yes` flag to skip the confirmation"
+        ));
+    }
+
+    print!("{question} [y/N] ");
+    io::stdout().flush()?;
+    let mut answer = String::new();
+    stdin.lock().read_line(&mut answer)?;
+
+    Ok(is_affirmative(&answer))
+}
+
+fn is_affirmative(answer: &str) -> bool {
+    matches!(answer.trim().to_lowercase().as_str(), "y" | "yes")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rstest::rstest;
+
+    #[rstest]
+    #[case("y\n", true)]
+    #[case("Yes", true)]
+    #[case("", false)]
+    #[case("n", false)]
+    #[case("yeah", false)]
+    fn confirmation_answer(#[case] answer: &str, #[case] expected: bool) {
+        assert_eq!(is_affirmative(answer), expected);
+    }
+
+    #[rstest]
+    #[case(
+        "registry://ghcr.io/kubewarden/policies/psp-capabilities:v0.1.3",
+        "registry://ghcr.io/kubewarden/policies/psp-capabilities"
+    )]
+    #[case(
+        "registry://localhost:5000/psp-capabilities:v0.1.3",
+        "registry://localhost:5000/psp-capabilities"
+    )]
+    #[case(
+        "registry://localhost:5000/psp-capabilities",
+        "registry://localhost:5000/psp-capabilities"
+    )]
+    #[case(
+        "registry://ghcr.io/kubewarden/policies/psp-capabilities@sha256:0123456789abcdef",
+        "registry://ghcr.io/kubewarden/policies/psp-capabilities"
+    )]
+    fn uri_without_tag(#[case] uri: &str, #[case] expected: &str) {
+        assert_eq!(without_tag(uri), expected);
+    }
+}
@@ -9,9 +9,13 @@ pub(crate) fn rm(uri_or_sha_prefix: &str) -> Result<()> {
 
     let store = Store::default();
 
-    if store.get_policy_by_uri(&uri)?.is_none() {
-        return Err(anyhow!(LookupError::PolicyMissing(uri)));
-    }
+    let policy = match store.get_policy_by_uri(&uri)? {
+        Some(policy) => policy,
+        None => return Err(anyhow!(LookupError::PolicyMissing(uri))),
+    };
+    store
+        .remove_provenance(&policy)
+        .map_err(|err| anyhow!("could not delete provenance of policy {}: {}", uri, err))?;
 
     let policy_path = store.policy_full_path(&uri, PolicyPath::PrefixAndFilename)?;
     std::fs::remove_file(&policy_path)
@@ -1,30 +1,111 @@
 use anyhow::{anyhow, Result};
 use flate2::write::GzEncoder;
 use flate2::Compression;
-use policy_evaluator::policy_fetcher::store::{PolicyPath, Store};
+use policy_evaluator::policy_fetcher::store::{provenance::PolicyProvenance, PolicyPath, Store};
+use serde::{Deserialize, Serialize};
 use std::fs::File;
+use std::path::PathBuf;
+
+use crate::utils::LookupError;
+
+/// Name of the file, inside of the tarball, describing the policies it holds
+pub(crate) const BUNDLE_INDEX: &str = "kwctl-bundle.json";
+
+/// Version of the format of the bundle index
+pub(crate) const BUNDLE_INDEX_VERSION: u32 = 1;
+
+/// Describes the policies saved inside of a tarball. This is used to restore
+/// the information kept by the store about the policies, like their provenance,
+/// and to ensure the policies have not been altered while being moved around.
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct BundleIndex {
+    pub version: u32,
+    pub policies: Vec<BundledPolicy>,
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct BundledPolicy {
+    pub uri: String,
+    /// Path of the WebAssembly module, relative to the root of the store
+    pub path: PathBuf,
+    /// SHA-256 digest of the WebAssembly module
+    pub sha256: String,
+    /// Where the policy has been pulled from and whether it has been verified.
+    /// The digest of the verified OCI manifest is preserved, this way the
+    /// policy can be checked against it on the machine loading the tarball
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub provenance: Option<PolicyProvenance>,
+}
 
 // saves all policies in a tarball with the name provided as output.
 // policies must be inside the default store.
 pub(crate) fn save(policies: Vec<&String>, output: &str) -> Result<()> {
-    let tar_gz =
-        File::create(output).map_err(|e| anyhow!("cannot create file {}: {}", output, e))?;
-    let enc = GzEncoder::new(tar_gz, Compression::default());
-    let mut tar = tar::Builder::new(enc);
+    save_from_store(&Store::default(), policies, output)
+}
+
+pub(crate) fn save_from_store(store: &Store, policies: Vec<&String>, output: &str) -> Result<()> {
+    let mut index = BundleIndex {
+        version: BUNDLE_INDEX_VERSION,
+        policies: Vec::new(),
+    };
+    let mut files = Vec::new();
 
     for policy in policies {
-        let store = Store::default();
         let uri = crate::utils::map_path_to_uri(policy.as_str())?;
-        let wasm_path = crate::utils::wasm_path(&uri)
-            .map_err(|e| anyhow!("cannot find policy {}: {}", policy, e))?;
-        let mut file = File::open(wasm_path)
-            .map_err(|e| anyhow!("cannot open policy file {}: {}", policy, e))?;
+        let store_policy = store
+            .get_policy_by_uri(&uri)
+            .map_err(|e| anyhow!("cannot find policy {}: {}", policy, e))?
+            .ok_or_else(|| anyhow!(LookupError::PolicyMissing(uri.clone())))?;
         let policy_path = store
             .policy_path(&uri, PolicyPath::PrefixAndFilename)
             .map_err(|e| anyhow!("cannot find path for policy {}: {}", policy, e))?;
-        tar.append_file(policy_path, &mut file)
-            .map_err(|e| anyhow!("cannot append policy {} to tar file: {}", policy, e))?;
+        let sha256 = store_policy
+            .digest()
+            .map_err(|e| anyhow!("cannot compute digest of policy {}: {}", policy, e))?;
+        let provenance = store
+            .provenance(&store_policy)
+            .map_err(|e| anyhow!("cannot read provenance of policy {}: {}", policy, e))?;
+
+        index.policies.push(BundledPolicy {
+            uri,
+            path: policy_path.clone(),
+            sha256,
+            provenance,
+        });
+        files.push((policy_path, store_policy.local_path));
     }
 
+    let tar_gz =
+        File::create(output).map_err(|e| anyhow!("cannot create file {}: {}", output, e))?;
+    let enc = GzEncoder::new(tar_gz, Compression::default());
+    let mut tar = tar::Builder::new(enc);
+
+    // the index is written first, this way it's known before the policies are read
+    let index = serde_json::to_vec_pretty(&index)?;
+    let mut header = tar::Header::new_gnu();
+    header.set_size(index.len() as u64);
+    header.set_mode(0o644);
+    header.set_cksum();
+    tar.append_data(&mut header, BUNDLE_INDEX, index.as_slice())
+        .map_err(|e| anyhow!("cannot append index to tar file: {}", e))?;
+
+    for (policy_path, local_path) in files {
+        let mut file = File::open(&local_path)
+            .map_err(|e| anyhow!("cannot open policy file {}: {}", local_path.display(), e))?;
+        tar.append_file(&policy_path, &mut file).map_err(|e| {
+            anyhow!(
+                "cannot append policy {} to tar file: {}",
+                policy_path.display(),
+                e
+            )
+        })?;
+    }
+
+    tar.into_inner()
+        .and_then(|enc| enc.finish())
+        .map_err(|e| anyhow!("cannot write file {}: {}", output, e))?;
+
     Ok(())
 }
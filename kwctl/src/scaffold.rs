@@ -1,8 +1,17 @@
 mod kubewarden_crds;
 
+mod discovery_snapshot;
+pub(crate) use discovery_snapshot::discovery_snapshot;
+
 mod manifest;
 pub(crate) use manifest::manifest;
 
+mod settings;
+pub(crate) use settings::settings;
+
+mod settings_schema;
+pub(crate) use settings_schema::{describe_settings, example_settings};
+
 mod vap;
 pub(crate) use vap::vap;
 
@@ -12,6 +21,10 @@ pub(crate) use verification_config::verification_config;
 mod artifacthub;
 pub(crate) use artifacthub::artifacthub;
 
+mod rbac;
+pub(crate) use rbac::{rbac, RbacNames};
+
 mod admission_request;
+pub(crate) use admission_request::ApiResourceCatalog;
 pub(crate) use admission_request::Operation as AdmissionRequestOperation;
 pub(crate) use admission_request::{admission_request, DEFAULT_KWCTL_CACHE};
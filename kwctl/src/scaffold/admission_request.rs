@@ -3,7 +3,7 @@ use std::{
     fmt::{self, Display, Formatter},
     fs::File,
     future::Future,
-    path::PathBuf,
+    path::{Path, PathBuf},
     str::FromStr,
 };
 
@@ -68,15 +68,21 @@ enum ApiResourceCatalogRestoredFrom {
     Cache,
     ApiServer,
     Empty,
+    /// Loaded from a discovery snapshot provided by the user, never refreshed
+    Snapshot,
 }
 
 /// A catalog of Kubernetes resources. The catalog is built once by querying a Kubernetes API server.
 ///
-/// This is required because some information about the resource being scaffolded cannot be
+/// This is required because some information about the resources being scaffolded cannot be
 /// inferred from the object itself. For example: knowning if a resource is namespaced or not, or
 /// the plural name of the resource.
+///
+/// The catalog is also used as offline discovery snapshot, generated by
+/// `kwctl scaffold discovery-snapshot`, to resolve the API resources without
+/// a connection to the cluster.
 #[derive(Serialize, Deserialize, Debug)]
-struct ApiResourceCatalog {
+pub(crate) struct ApiResourceCatalog {
     resources: HashMap<String, APIResource>,
     #[serde(skip)]
     restored_from: ApiResourceCatalogRestoredFrom,
@@ -128,7 +134,28 @@ impl ApiResourceCatalog {
         }
     }
 
-    async fn build(client: kube::Client) -> Result<Self> {
+    /// Load a discovery snapshot. Unlike the local cache, the snapshot must exist
+    /// and is never refreshed
+    pub fn from_snapshot(snapshot_path: &Path) -> Result<Self> {
+        let file = File::open(snapshot_path).map_err(|e| {
+            anyhow!(
+                "cannot open discovery snapshot {}: {}",
+                snapshot_path.display(),
+                e
+            )
+        })?;
+        let mut catalog: Self = serde_json::from_reader(file).map_err(|e| {
+            anyhow!(
+                "cannot parse discovery snapshot {}: {}",
+                snapshot_path.display(),
+                e
+            )
+        })?;
+        catalog.restored_from = ApiResourceCatalogRestoredFrom::Snapshot;
+        Ok(catalog)
+    }
+
+    pub(super) async fn build(client: kube::Client) -> Result<Self> {
         let mut resources: HashMap<String, APIResource> = HashMap::new();
 
         // Build knowledge about core resources
@@ -207,6 +234,27 @@ impl ApiResourceCatalog {
         self.resources.get(&Self::gvk_to_string(gvk))
     }
 
+    /// Find the plural name of a resource. The catalog holds only the preferred version
+    /// of each API group, the plural name is the same across the versions of a group,
+    /// hence the other versions are resolved too.
+    pub fn plural_name(&self, api_version: &str, kind: &str) -> Option<&str> {
+        let (group, version) = api_version.split_once('/').unwrap_or(("", api_version));
+        let gvk = kube::api::GroupVersionKind {
+            group: group.to_owned(),
+            version: version.to_owned(),
+            kind: kind.to_owned(),
+        };
+        if let Some(resource) = self.lookup(&gvk) {
+            return Some(resource.name.as_str());
+        }
+
+        let (prefix, suffix) = (format!("{group}|"), format!("|{kind}"));
+        self.resources
+            .iter()
+            .find(|(key, _)| key.starts_with(&prefix) && key.ends_with(&suffix))
+            .map(|(_, resource)| resource.name.as_str())
+    }
+
     /// Refresh the catalog by querying the Kubernetes API server.
     /// This applies only if the catalog was built from the cache.
     pub async fn refresh<F, Fut>(&mut self, build_kubeclient_fn: F) -> Result<()>
@@ -245,7 +293,7 @@ impl ApiResourceCatalog {
 // The scaffold command must be snappy, we don't want it to get stuck
 // waiting for the connection to Kubernetes to be established.
 // Because of that we set a connection timeout of 1 second.
-async fn build_kube_client() -> Result<kube::Client> {
+pub(super) async fn build_kube_client() -> Result<kube::Client> {
     let mut config = kube::Config::infer().await?;
     config.connect_timeout = Some(std::time::Duration::from_secs(1));
     let client = kube::Client::try_from(config)?;
@@ -416,6 +464,7 @@ where
         old_object: None,
         dry_run: None,
         options: None,
+        enrichment: None,
     };
 
     let output = serde_json::to_string_pretty(&request)?;
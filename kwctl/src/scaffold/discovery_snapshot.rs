@@ -0,0 +1,16 @@
+use anyhow::{anyhow, Result};
+
+use crate::scaffold::admission_request::{build_kube_client, ApiResourceCatalog};
+
+/// Query the Kubernetes API server and produce a snapshot of the API resources it
+/// serves. The snapshot can be given to `kwctl run --discovery-snapshot` to resolve
+/// the API resources, like their plural names, without a connection to the cluster.
+pub(crate) async fn discovery_snapshot() -> Result<String> {
+    let client = build_kube_client()
+        .await
+        .map_err(|e| anyhow!("cannot connect to the Kubernetes API server: {e}"))?;
+    let catalog = ApiResourceCatalog::build(client).await?;
+
+    serde_json::to_string_pretty(&catalog)
+        .map_err(|e| anyhow!("cannot serialize discovery snapshot: {e}"))
+}
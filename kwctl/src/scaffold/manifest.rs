@@ -17,8 +17,11 @@ use policy_evaluator::{
 };
 use tracing::warn;
 
-use crate::scaffold::kubewarden_crds::{
-    AdmissionPolicy, AdmissionPolicySpec, ClusterAdmissionPolicy, ClusterAdmissionPolicySpec,
+use crate::scaffold::{
+    kubewarden_crds::{
+        AdmissionPolicy, AdmissionPolicySpec, ClusterAdmissionPolicy, ClusterAdmissionPolicySpec,
+    },
+    settings_schema::commented_settings,
 };
 
 pub(crate) enum ManifestType {
@@ -153,6 +156,12 @@ pub(crate) fn manifest(
         )?;
 
     let settings_yml: serde_yaml::Mapping = serde_yaml::from_str(settings.unwrap_or("{}"))?;
+    // Document the settings only when the user didn't provide any
+    let settings_schema = if settings.is_none() {
+        metadata.settings_schema.clone()
+    } else {
+        None
+    };
 
     let policy_title = get_policy_title_from_cli_or_metadata(policy_title, &metadata);
 
@@ -171,13 +180,37 @@ pub(crate) fn manifest(
     let resource =
         generate_yaml_resource(scaffold_data, resource_type, allow_context_aware_resources)?;
 
-    let stdout = std::io::stdout();
-    let out = stdout.lock();
-    serde_yaml::to_writer(out, &resource)?;
+    let mut manifest = serde_yaml::to_string(&resource)?;
+    if let Some(schema) = settings_schema {
+        manifest = embed_settings_schema(&manifest, &schema)?;
+    }
+    print!("{manifest}");
 
     Ok(())
 }
 
+/// Add a commented block right after the empty `settings` of the manifest,
+/// documenting all the settings described by the policy schema.
+fn embed_settings_schema(manifest: &str, schema: &serde_json::Value) -> Result<String> {
+    const EMPTY_SETTINGS_LINE: &str = "  settings: {}";
+
+    let mut out = String::with_capacity(manifest.len());
+    let mut embedded = false;
+    for line in manifest.lines() {
+        out.push_str(line);
+        out.push('\n');
+        if !embedded && line == EMPTY_SETTINGS_LINE {
+            out.push_str(&commented_settings(schema, 2)?);
+            embedded = true;
+        }
+    }
+
+    if !embedded {
+        warn!("Cannot find the `settings` section of the manifest, the settings schema will not be documented");
+    }
+    Ok(out)
+}
+
 fn get_policy_title_from_cli_or_metadata(
     policy_title: Option<&str>,
     metadata: &Metadata,
@@ -243,6 +276,10 @@ mod tests {
             execution_mode: Default::default(),
             policy_type: Default::default(),
             minimum_kubewarden_version: None,
+            settings_schema: None,
+            request_projection: None,
+            request_envelope: Default::default(),
+            preopened_dirs: vec![],
         }
     }
 
@@ -260,6 +297,10 @@ mod tests {
             execution_mode: Default::default(),
             policy_type: Default::default(),
             minimum_kubewarden_version: None,
+            settings_schema: None,
+            request_projection: None,
+            request_envelope: Default::default(),
+            preopened_dirs: vec![],
         }
     }
 
@@ -287,6 +328,10 @@ mod tests {
             execution_mode: Default::default(),
             policy_type: Default::default(),
             minimum_kubewarden_version: None,
+            settings_schema: None,
+            request_projection: None,
+            request_envelope: Default::default(),
+            preopened_dirs: vec![],
         }
     }
 
@@ -486,6 +531,43 @@ mod tests {
         assert!(context_aware_resources.is_none());
     }
 
+    #[test]
+    fn scaffold_manifest_documents_settings_schema() {
+        let mut metadata = mock_metadata_with_title("test");
+        metadata.protocol_version = Some(policy_evaluator::ProtocolVersion::V1);
+
+        let scaffold_data = ScaffoldPolicyData {
+            uri: "not_relevant".to_string(),
+            policy_title: Some("test".to_string()),
+            metadata,
+            settings: Default::default(),
+        };
+        let resource =
+            generate_yaml_resource(scaffold_data, ManifestType::ClusterAdmissionPolicy, false)
+                .expect("Cannot create yaml resource");
+        let manifest = serde_yaml::to_string(&resource).expect("serialization error");
+
+        let schema = serde_json::json!({
+            "type": "object",
+            "properties": {
+                "allowedRegistries": {
+                    "type": "array",
+                    "items": { "type": "string" },
+                    "description": "Registries images can be pulled from",
+                    "default": []
+                }
+            }
+        });
+        let out = embed_settings_schema(&manifest, &schema).expect("cannot embed schema");
+        assert!(out.contains("  settings: {}\n  # settings:\n"));
+        assert!(out.contains("# Registries images can be pulled from"));
+        assert!(out.contains("allowedRegistries: []"));
+
+        // The comments must not change the manifest
+        let parsed: serde_yaml::Value = serde_yaml::from_str(&out).expect("invalid yaml");
+        assert_eq!(parsed, resource);
+    }
+
     #[test]
     fn test_manifest_with_invalid_policy_title() {
         // Test the validation function directly
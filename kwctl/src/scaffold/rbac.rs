@@ -0,0 +1,395 @@
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fs::File,
+    future::Future,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{anyhow, Result};
+use k8s_openapi::{
+    api::rbac::v1::{ClusterRole, ClusterRoleBinding, PolicyRule, RoleRef, Subject},
+    apimachinery::pkg::apis::meta::v1::ObjectMeta,
+};
+use policy_evaluator::{kube, policy_metadata::ContextAwareResource};
+use serde::Deserialize;
+use tracing::warn;
+
+use crate::scaffold::admission_request::{
+    build_kube_client, ApiResourceCatalog, RESOURCE_CATALOG_FILE,
+};
+
+/// Verbs required by policy-server to serve the context aware requests of the policies
+const CONTEXT_AWARE_VERBS: &[&str] = &["get", "list", "watch"];
+
+/// The names used by the generated RBAC resources
+pub(crate) struct RbacNames<'a> {
+    /// Name of the ClusterRole and of the ClusterRoleBinding
+    pub name: &'a str,
+    /// The ServiceAccount used by policy-server
+    pub service_account: &'a str,
+    /// The Namespace where the ServiceAccount is defined
+    pub namespace: &'a str,
+}
+
+/// The subset of a policy-server `policies.yml` entry that is relevant to build the RBAC rules.
+/// Both individual policies and policy groups are covered.
+#[derive(Deserialize, Debug, Default)]
+#[serde(rename_all = "camelCase")]
+struct PolicyEntry {
+    #[serde(default)]
+    context_aware_resources: BTreeSet<ContextAwareResource>,
+    /// The members of a policy group
+    #[serde(default)]
+    policies: BTreeMap<String, PolicyEntry>,
+}
+
+pub(crate) async fn rbac(policies_path: &Path, names: &RbacNames<'_>) -> Result<()> {
+    let output = scaffold_rbac(
+        RESOURCE_CATALOG_FILE.to_path_buf(),
+        build_kube_client,
+        policies_path,
+        names,
+    )
+    .await?;
+
+    print!("{}", output);
+    Ok(())
+}
+
+async fn scaffold_rbac<F, Fut>(
+    resource_catalog_file: PathBuf,
+    kube_client: F,
+    policies_path: &Path,
+    names: &RbacNames<'_>,
+) -> Result<String>
+where
+    F: FnOnce() -> Fut + Clone,
+    Fut: Future<Output = Result<kube::Client>>,
+{
+    let file = File::open(policies_path).map_err(|err| {
+        anyhow!(
+            "failed to open policies file {}: {}",
+            policies_path.display(),
+            err
+        )
+    })?;
+    let policies: BTreeMap<String, PolicyEntry> = serde_yaml::from_reader(file).map_err(|err| {
+        anyhow!(
+            "failed to parse policies file {}: {}",
+            policies_path.display(),
+            err
+        )
+    })?;
+
+    let context_aware_resources = aggregate_context_aware_resources(&policies);
+    if context_aware_resources.is_empty() {
+        warn!("None of the policies requires access to Kubernetes resources, the ClusterRole does not grant any permission");
+    }
+
+    let resources_by_group =
+        resolve_resources(resource_catalog_file, kube_client, &context_aware_resources).await;
+
+    let (cluster_role, cluster_role_binding) = build_rbac(&resources_by_group, names);
+
+    Ok(format!(
+        "---\n{}---\n{}",
+        serde_yaml::to_string(&cluster_role)?,
+        serde_yaml::to_string(&cluster_role_binding)?
+    ))
+}
+
+/// Collect the Kubernetes resources accessed by all the policies, including the
+/// members of the policy groups
+fn aggregate_context_aware_resources(
+    policies: &BTreeMap<String, PolicyEntry>,
+) -> BTreeSet<ContextAwareResource> {
+    policies
+        .values()
+        .flat_map(|policy| {
+            policy
+                .context_aware_resources
+                .iter()
+                .cloned()
+                .chain(aggregate_context_aware_resources(&policy.policies))
+        })
+        .collect()
+}
+
+/// Map the given resources to the plural names used by the RBAC rules, grouped by API group.
+///
+/// The plural names are read from the resource catalog, which is built by querying the
+/// Kubernetes API server. When the information is not available, the plural name is guessed
+/// from the kind of the resource.
+async fn resolve_resources<F, Fut>(
+    resource_catalog_file: PathBuf,
+    kube_client: F,
+    resources: &BTreeSet<ContextAwareResource>,
+) -> BTreeMap<String, BTreeSet<String>>
+where
+    F: FnOnce() -> Fut + Clone,
+    Fut: Future<Output = Result<kube::Client>>,
+{
+    let mut resources_by_group: BTreeMap<String, BTreeSet<String>> = BTreeMap::new();
+    if resources.is_empty() {
+        // do not try to connect to the API server when there's nothing to look for
+        return resources_by_group;
+    }
+
+    let mut resource_catalog =
+        ApiResourceCatalog::new(resource_catalog_file, kube_client.clone()).await;
+    let mut refreshed = false;
+
+    for resource in resources {
+        let (group, version) = match resource.api_version.split_once('/') {
+            Some((group, version)) => (group, version),
+            None => ("", resource.api_version.as_str()),
+        };
+        let gvk = kube::api::GroupVersionKind {
+            group: group.to_string(),
+            version: version.to_string(),
+            kind: resource.kind.clone(),
+        };
+
+        if resource_catalog.lookup(&gvk).is_none() && !refreshed {
+            // Try to refresh the catalog only once, the API server is not going to
+            // change while we are scaffolding
+            refreshed = true;
+            if let Err(err) = resource_catalog.refresh(kube_client.clone()).await {
+                warn!(?err, "Failed to refresh resource catalog");
+            }
+        }
+
+        let plural = match resource_catalog.lookup(&gvk) {
+            Some(api_resource) => api_resource.name.clone(),
+            None => {
+                let plural = guess_plural_name(&resource.kind);
+                warn!(
+                    api_version = resource.api_version.as_str(),
+                    kind = resource.kind.as_str(),
+                    plural = plural.as_str(),
+                    "Could not find information about the resource, guessing its plural name"
+                );
+                plural
+            }
+        };
+        resources_by_group
+            .entry(group.to_string())
+            .or_default()
+            .insert(plural);
+    }
+
+    resources_by_group
+}
+
+/// Guess the plural name of a resource, following the same rules used by the
+/// Kubernetes API machinery
+fn guess_plural_name(kind: &str) -> String {
+    let singular = kind.to_lowercase();
+    if singular == "endpoints" {
+        return singular;
+    }
+    if let Some(stem) = singular.strip_suffix('y') {
+        if !stem.ends_with(['a', 'e', 'i', 'o', 'u']) {
+            return format!("{stem}ies");
+        }
+    }
+    if singular.ends_with(['s', 'x', 'z']) || singular.ends_with("ch") || singular.ends_with("sh") {
+        return format!("{singular}es");
+    }
+    format!("{singular}s")
+}
+
+/// Build the ClusterRole granting read access to the given resources, plus the
+/// ClusterRoleBinding assigning it to the policy-server ServiceAccount
+fn build_rbac(
+    resources_by_group: &BTreeMap<String, BTreeSet<String>>,
+    names: &RbacNames,
+) -> (ClusterRole, ClusterRoleBinding) {
+    let labels = BTreeMap::from([(
+        "app.kubernetes.io/managed-by".to_string(),
+        "kwctl".to_string(),
+    )]);
+    let metadata = ObjectMeta {
+        name: Some(names.name.to_string()),
+        labels: Some(labels),
+        ..Default::default()
+    };
+
+    let rules = resources_by_group
+        .iter()
+        .map(|(group, resources)| PolicyRule {
+            api_groups: Some(vec![group.clone()]),
+            resources: Some(resources.iter().cloned().collect()),
+            verbs: CONTEXT_AWARE_VERBS.iter().map(|v| v.to_string()).collect(),
+            ..Default::default()
+        })
+        .collect();
+
+    let cluster_role = ClusterRole {
+        metadata: metadata.clone(),
+        rules: Some(rules),
+        ..Default::default()
+    };
+    let cluster_role_binding = ClusterRoleBinding {
+        metadata,
+        role_ref: RoleRef {
+            api_group: "rbac.authorization.k8s.io".to_string(),
+            kind: "ClusterRole".to_string(),
+            name: names.name.to_string(),
+        },
+        subjects: Some(vec![Subject {
+            kind: "ServiceAccount".to_string(),
+            name: names.service_account.to_string(),
+            namespace: Some(names.namespace.to_string()),
+            ..Default::default()
+        }]),
+    };
+
+    (cluster_role, cluster_role_binding)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::io::Write;
+
+    use rstest::rstest;
+
+    const POLICIES_YAML: &str = r#"
+pod-privileged:
+  module: registry://ghcr.io/kubewarden/policies/pod-privileged:v0.2.2
+unique-ingress:
+  module: registry://ghcr.io/kubewarden/policies/unique-ingress:v0.1.0
+  contextAwareResources:
+    - apiVersion: networking.k8s.io/v1
+      kind: Ingress
+group-policy:
+  expression: "a() && b()"
+  message: "denied"
+  policies:
+    a:
+      module: registry://ghcr.io/kubewarden/policies/a:v1.0.0
+      contextAwareResources:
+        - apiVersion: v1
+          kind: Namespace
+        - apiVersion: networking.k8s.io/v1
+          kind: Ingress
+    b:
+      module: registry://ghcr.io/kubewarden/policies/b:v1.0.0
+      contextAwareResources:
+        - apiVersion: v1
+          kind: Service
+"#;
+
+    const NAMES: RbacNames<'static> = RbacNames {
+        name: "kubewarden-context-aware-policies",
+        service_account: "policy-server",
+        namespace: "kubewarden",
+    };
+
+    async fn no_kube_client() -> Result<kube::Client> {
+        Err(anyhow!("no Kubernetes cluster available"))
+    }
+
+    #[rstest]
+    #[case::regular("Deployment", "deployments")]
+    #[case::ending_with_consonant_y("NetworkPolicy", "networkpolicies")]
+    #[case::ending_with_vowel_y("Gateway", "gateways")]
+    #[case::ending_with_s("Ingress", "ingresses")]
+    #[case::ending_with_ch("ResourceClaimPatch", "resourceclaimpatches")]
+    #[case::irregular("Endpoints", "endpoints")]
+    fn guess_plural(#[case] kind: &str, #[case] expected: &str) {
+        assert_eq!(guess_plural_name(kind), expected);
+    }
+
+    #[test]
+    fn aggregate_resources_of_policies_and_groups() {
+        let policies: BTreeMap<String, PolicyEntry> = serde_yaml::from_str(POLICIES_YAML).unwrap();
+
+        let resources = aggregate_context_aware_resources(&policies);
+        let resources: Vec<(&str, &str)> = resources
+            .iter()
+            .map(|r| (r.api_version.as_str(), r.kind.as_str()))
+            .collect();
+        assert_eq!(
+            resources,
+            vec![
+                ("networking.k8s.io/v1", "Ingress"),
+                ("v1", "Namespace"),
+                ("v1", "Service"),
+            ]
+        );
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn scaffold_rbac_without_cluster() {
+        let tempdir = tempfile::tempdir().unwrap();
+        let catalog_file = tempdir.path().join("resource_catalog.json");
+        let policies_file = tempdir.path().join("policies.yml");
+        File::create(&policies_file)
+            .unwrap()
+            .write_all(POLICIES_YAML.as_bytes())
+            .unwrap();
+
+        let output = scaffold_rbac(catalog_file, no_kube_client, &policies_file, &NAMES)
+            .await
+            .unwrap();
+        let documents: Vec<serde_yaml::Value> = output
+            .split("---\n")
+            .filter(|doc| !doc.trim().is_empty())
+            .map(|doc| serde_yaml::from_str(doc).unwrap())
+            .collect();
+        assert_eq!(documents.len(), 2);
+
+        let expected_role: serde_yaml::Value = serde_yaml::from_str(
+            r#"
+apiVersion: rbac.authorization.k8s.io/v1
+kind: ClusterRole
+metadata:
+  name: kubewarden-context-aware-policies
+  labels:
+    app.kubernetes.io/managed-by: kwctl
+rules:
+  - apiGroups: [""]
+    resources: ["namespaces", "services"]
+    verbs: ["get", "list", "watch"]
+  - apiGroups: ["networking.k8s.io"]
+    resources: ["ingresses"]
+    verbs: ["get", "list", "watch"]
+"#,
+        )
+        .unwrap();
+        assert_eq!(documents[0], expected_role);
+
+        let binding = &documents[1];
+        assert_eq!(binding["kind"], "ClusterRoleBinding");
+        assert_eq!(
+            binding["roleRef"]["name"],
+            "kubewarden-context-aware-policies"
+        );
+        assert_eq!(binding["subjects"][0]["kind"], "ServiceAccount");
+        assert_eq!(binding["subjects"][0]["name"], "policy-server");
+        assert_eq!(binding["subjects"][0]["namespace"], "kubewarden");
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn scaffold_rbac_without_context_aware_policies() {
+        let tempdir = tempfile::tempdir().unwrap();
+        let catalog_file = tempdir.path().join("resource_catalog.json");
+        let policies_file = tempdir.path().join("policies.yml");
+        File::create(&policies_file)
+            .unwrap()
+            .write_all(b"pod-privileged:\n  module: registry://ghcr.io/kubewarden/policies/pod-privileged:v0.2.2\n")
+            .unwrap();
+
+        let output = scaffold_rbac(catalog_file.clone(), no_kube_client, &policies_file, &NAMES)
+            .await
+            .unwrap();
+        let role: serde_yaml::Value =
+            serde_yaml::from_str(output.split("---\n").nth(1).unwrap()).unwrap();
+        assert_eq!(role["rules"], serde_yaml::Value::Sequence(vec![]));
+        // the API server is not queried when no resource is needed
+        assert!(!catalog_file.exists());
+    }
+}
@@ -0,0 +1,45 @@
+use std::io;
+
+use anyhow::{anyhow, Result};
+use is_terminal::IsTerminal;
+use policy_evaluator::policy_metadata::Metadata;
+
+use crate::scaffold::settings_schema::{example_settings, prompt_settings};
+
+/// Generate the settings of the policy, using the JSON Schema found inside of its
+/// metadata.
+///
+/// By default an example of the settings is returned, where all the fields are
+/// documented and set to their default value. When `interactive` is set, the user
+/// is asked the value of each setting instead.
+pub(crate) fn settings(uri_or_sha_prefix: &str, interactive: bool) -> Result<String> {
+    let uri = crate::utils::get_uri(&uri_or_sha_prefix.to_owned())?;
+    let wasm_path = crate::utils::wasm_path(&uri)?;
+
+    let metadata = Metadata::from_path(&wasm_path)?
+        .ok_or_else(||
+            anyhow!(
+                "No Kubewarden metadata found inside of '{}'.\nPolicies can be annotated with the `kwctl annotate` command.",
+                uri)
+        )?;
+    let schema = metadata.settings_schema.ok_or_else(|| {
+        anyhow!(
+            "The metadata of '{}' does not describe the settings of the policy, a settings schema is required",
+            uri
+        )
+    })?;
+
+    if !interactive {
+        return example_settings(&schema);
+    }
+
+    let stdin = io::stdin();
+    if !stdin.is_terminal() {
+        return Err(anyhow!(
+            "cannot ask for the settings, stdin is not a terminal"
+        ));
+    }
+    // the questions go to stderr, to keep stdout for the settings
+    let settings = prompt_settings(&schema, &mut stdin.lock(), &mut io::stderr())?;
+    Ok(serde_yaml::to_string(&settings)?)
+}
@@ -0,0 +1,622 @@
+use std::io::{BufRead, Write};
+
+use anyhow::{anyhow, Result};
+use serde_json::Value;
+
+/// Render the settings described by a JSON Schema as a block of commented
+/// YAML. Each property is preceded by its description, type and default value.
+///
+/// The output is made only of comments, hence it can be appended to any
+/// YAML document without changing its meaning.
+pub(crate) fn commented_settings(schema: &Value, indent: usize) -> Result<String> {
+    let prefix = format!("{}#   ", " ".repeat(indent));
+    let mut lines = vec![format!("{}# settings:", " ".repeat(indent))];
+    lines.extend(
+        settings_lines(schema)?
+            .into_iter()
+            .map(|line| format!("{prefix}{line}")),
+    );
+
+    let mut out = lines.join("\n");
+    out.push('\n');
+    Ok(out)
+}
+
+/// Render an example of the settings described by a JSON Schema as a YAML
+/// document. Each property is preceded by a comment with its description, type,
+/// allowed values and default value. The properties are set to their default
+/// value, or to a placeholder matching their type.
+pub(crate) fn example_settings(schema: &Value) -> Result<String> {
+    let mut lines = settings_lines(schema)?;
+    if !has_properties(schema)? {
+        lines.push("{}".to_string());
+    }
+
+    let mut out = lines.join("\n");
+    out.push('\n');
+    Ok(out)
+}
+
+fn has_properties(schema: &Value) -> Result<bool> {
+    Ok(schema_properties(schema)?.is_some_and(|p| !p.is_empty()))
+}
+
+/// The lines of the YAML document describing the settings
+fn settings_lines(schema: &Value) -> Result<Vec<String>> {
+    let mut lines = Vec::new();
+    if !has_properties(schema)? {
+        lines.push("# The policy does not have any setting".to_string());
+    } else {
+        render_properties(schema, 0, &mut lines)?;
+    }
+    Ok(lines)
+}
+
+fn schema_properties(schema: &Value) -> Result<Option<&serde_json::Map<String, Value>>> {
+    let schema = schema
+        .as_object()
+        .ok_or_else(|| anyhow!("the settings schema must be a JSON object"))?;
+    match schema.get("properties") {
+        Some(Value::Object(properties)) => Ok(Some(properties)),
+        Some(_) => Err(anyhow!(
+            "the `properties` of the settings schema must be a JSON object"
+        )),
+        None => Ok(None),
+    }
+}
+
+fn render_properties(schema: &Value, depth: usize, lines: &mut Vec<String>) -> Result<()> {
+    let properties = match schema_properties(schema)? {
+        Some(properties) => properties,
+        None => return Ok(()),
+    };
+    let required = required_properties(schema);
+
+    let prefix = "  ".repeat(depth);
+    for (name, property) in properties {
+        if let Some(description) = property.get("description").and_then(Value::as_str) {
+            for line in description.lines() {
+                lines.push(format!("{prefix}# {}", line.trim_end()));
+            }
+        }
+
+        let details = property_details(property, required.contains(&name.as_str()));
+        lines.push(format!("{prefix}# {details}"));
+
+        let key = yaml_key(name);
+        if has_nested_properties(property) && property.get("default").is_none() {
+            lines.push(format!("{prefix}{key}:"));
+            render_properties(property, depth + 1, lines)?;
+        } else {
+            let value = property
+                .get("default")
+                .map(inline_value)
+                .unwrap_or_else(|| placeholder_value(property).to_string());
+            lines.push(format!("{prefix}{key}: {value}"));
+        }
+    }
+
+    Ok(())
+}
+
+/// A setting described by a JSON Schema. The settings of nested objects are
+/// identified by their dotted path, e.g. `limits.cpu`
+pub(crate) struct SettingDescription {
+    pub path: String,
+    /// Type, allowed values and default value of the setting
+    pub details: String,
+    pub description: Option<String>,
+}
+
+/// Describe each setting of a JSON Schema, the nested objects are flattened
+pub(crate) fn describe_settings(schema: &Value) -> Result<Vec<SettingDescription>> {
+    let mut settings = Vec::new();
+    describe_properties(schema, "", &mut settings)?;
+    Ok(settings)
+}
+
+fn describe_properties(
+    schema: &Value,
+    parent_path: &str,
+    settings: &mut Vec<SettingDescription>,
+) -> Result<()> {
+    let properties = match schema_properties(schema)? {
+        Some(properties) => properties,
+        None => return Ok(()),
+    };
+    let required = required_properties(schema);
+
+    for (name, property) in properties {
+        let path = if parent_path.is_empty() {
+            name.clone()
+        } else {
+            format!("{parent_path}.{name}")
+        };
+        settings.push(SettingDescription {
+            path: path.clone(),
+            details: property_details(property, required.contains(&name.as_str())),
+            description: property
+                .get("description")
+                .and_then(Value::as_str)
+                .map(str::to_owned),
+        });
+        describe_properties(property, &path, settings)?;
+    }
+
+    Ok(())
+}
+
+fn required_properties(schema: &Value) -> Vec<&str> {
+    schema
+        .get("required")
+        .and_then(Value::as_array)
+        .map(|r| r.iter().filter_map(Value::as_str).collect())
+        .unwrap_or_default()
+}
+
+fn has_nested_properties(property: &Value) -> bool {
+    property
+        .get("properties")
+        .and_then(Value::as_object)
+        .is_some_and(|p| !p.is_empty())
+}
+
+/// Ask the value of each setting described by a JSON Schema, writing the questions
+/// to `output` and reading the answers from `input`.
+///
+/// The answers are parsed as YAML and checked against the type and the allowed
+/// values of the property, the question is repeated until a valid answer is given.
+/// An empty answer picks the default value, or leaves an optional setting unset.
+pub(crate) fn prompt_settings(
+    schema: &Value,
+    input: &mut impl BufRead,
+    output: &mut impl Write,
+) -> Result<Value> {
+    let mut settings = serde_json::Map::new();
+    prompt_properties(schema, "", input, output, &mut settings)?;
+    Ok(Value::Object(settings))
+}
+
+fn prompt_properties(
+    schema: &Value,
+    parent_path: &str,
+    input: &mut impl BufRead,
+    output: &mut impl Write,
+    settings: &mut serde_json::Map<String, Value>,
+) -> Result<()> {
+    let properties = match schema_properties(schema)? {
+        Some(properties) => properties,
+        None => return Ok(()),
+    };
+    let required = required_properties(schema);
+
+    for (name, property) in properties {
+        let path = if parent_path.is_empty() {
+            name.clone()
+        } else {
+            format!("{parent_path}.{name}")
+        };
+        let is_required = required.contains(&name.as_str());
+
+        if has_nested_properties(property) && property.get("default").is_none() {
+            let mut nested = serde_json::Map::new();
+            prompt_properties(property, &path, input, output, &mut nested)?;
+            if !nested.is_empty() || is_required {
+                settings.insert(name.clone(), Value::Object(nested));
+            }
+        } else if let Some(value) = prompt_property(&path, property, is_required, input, output)? {
+            settings.insert(name.clone(), value);
+        }
+    }
+
+    Ok(())
+}
+
+fn prompt_property(
+    path: &str,
+    property: &Value,
+    required: bool,
+    input: &mut impl BufRead,
+    output: &mut impl Write,
+) -> Result<Option<Value>> {
+    writeln!(output)?;
+    if let Some(description) = property.get("description").and_then(Value::as_str) {
+        for line in description.lines() {
+            writeln!(output, "# {}", line.trim_end())?;
+        }
+    }
+    writeln!(output, "# {}", property_details(property, required))?;
+
+    loop {
+        write!(output, "{path}: ")?;
+        output.flush()?;
+        let mut answer = String::new();
+        if input.read_line(&mut answer)? == 0 {
+            return Err(anyhow!("no value given for the `{path}` setting"));
+        }
+
+        let answer = answer.trim();
+        if answer.is_empty() {
+            if let Some(default) = property.get("default") {
+                return Ok(Some(default.clone()));
+            }
+            if !required {
+                return Ok(None);
+            }
+            writeln!(output, "The setting is required")?;
+            continue;
+        }
+        match parse_answer(answer, property) {
+            Ok(value) => return Ok(Some(value)),
+            Err(e) => writeln!(output, "Invalid value: {e}")?,
+        }
+    }
+}
+
+/// Parse the value typed by the user. The strings don't need to be quoted
+fn parse_answer(answer: &str, property: &Value) -> Result<Value> {
+    let value = if property.get("type").and_then(Value::as_str) == Some("string") {
+        match serde_yaml::from_str::<Value>(answer) {
+            Ok(Value::String(value)) => Value::String(value),
+            _ => Value::String(answer.to_string()),
+        }
+    } else {
+        serde_yaml::from_str::<Value>(answer)?
+    };
+    validate_value(&value, property)?;
+    Ok(value)
+}
+
+/// Check the value against the type and the allowed values of the property
+fn validate_value(value: &Value, property: &Value) -> Result<()> {
+    if let Some(allowed) = property.get("enum").and_then(Value::as_array) {
+        if !allowed.contains(value) {
+            let allowed: Vec<String> = allowed.iter().map(inline_value).collect();
+            return Err(anyhow!("must be one of {}", allowed.join(", ")));
+        }
+    }
+
+    let types: Vec<&str> = match property.get("type") {
+        Some(Value::String(t)) => vec![t.as_str()],
+        Some(Value::Array(types)) => types.iter().filter_map(Value::as_str).collect(),
+        _ => return Ok(()),
+    };
+    if !types.iter().any(|t| has_type(value, t)) {
+        return Err(anyhow!(
+            "expected a value of type {}",
+            property_type(property)
+        ));
+    }
+    if let (Value::Array(items), Some(items_schema)) = (value, property.get("items")) {
+        for item in items {
+            validate_value(item, items_schema)?;
+        }
+    }
+
+    Ok(())
+}
+
+fn has_type(value: &Value, type_name: &str) -> bool {
+    match type_name {
+        "string" => value.is_string(),
+        "integer" => value.is_i64() || value.is_u64(),
+        "number" => value.is_number(),
+        "boolean" => value.is_boolean(),
+        "array" => value.is_array(),
+        "object" => value.is_object(),
+        "null" => value.is_null(),
+        _ => true,
+    }
+}
+
+/// Type, allowed values and default value of a schema property, e.g.
+/// `type: string, required, allowed values: "a", "b"`
+fn property_details(property: &Value, required: bool) -> String {
+    let mut details = vec![format!("type: {}", property_type(property))];
+    if required {
+        details.push("required".to_string());
+    }
+    if let Some(values) = property.get("enum").and_then(Value::as_array) {
+        let values: Vec<String> = values.iter().map(inline_value).collect();
+        details.push(format!("allowed values: {}", values.join(", ")));
+    }
+    if let Some(default) = property.get("default") {
+        details.push(format!("default: {}", inline_value(default)));
+    }
+    details.join(", ")
+}
+
+/// Human readable type of a schema property, e.g. `array of string`
+fn property_type(property: &Value) -> String {
+    let type_name = match property.get("type") {
+        Some(Value::String(t)) => t.clone(),
+        Some(Value::Array(types)) => types
+            .iter()
+            .filter_map(Value::as_str)
+            .collect::<Vec<&str>>()
+            .join(" | "),
+        _ => "any".to_string(),
+    };
+
+    if type_name == "array" {
+        if let Some(items) = property.get("items") {
+            return format!("array of {}", property_type(items));
+        }
+    }
+    type_name
+}
+
+/// Value used when the schema does not provide a default one
+fn placeholder_value(property: &Value) -> &'static str {
+    if property.get("enum").is_some() {
+        return "null";
+    }
+    match property.get("type").and_then(Value::as_str) {
+        Some("string") => "\"\"",
+        Some("integer") | Some("number") => "0",
+        Some("boolean") => "false",
+        Some("array") => "[]",
+        Some("object") => "{}",
+        _ => "null",
+    }
+}
+
+/// Render a value on a single line. JSON is a subset of YAML, hence this
+/// is always valid YAML.
+fn inline_value(value: &Value) -> String {
+    serde_json::to_string(value).unwrap_or_else(|_| "null".to_string())
+}
+
+/// Quote the key when it contains characters that are not safe inside of a
+/// plain YAML scalar
+fn yaml_key(name: &str) -> String {
+    let is_plain = !name.is_empty()
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-' || c == '.')
+        && !name.starts_with(['-', '.']);
+    if is_plain {
+        name.to_string()
+    } else {
+        inline_value(&Value::String(name.to_string()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use rstest::rstest;
+    use serde_json::json;
+
+    fn uncomment(block: &str) -> String {
+        block
+            .lines()
+            .map(|l| l.trim_start().strip_prefix('#').unwrap_or(l))
+            .filter(|l| !l.trim_start().starts_with('#'))
+            .collect::<Vec<&str>>()
+            .join("\n")
+    }
+
+    #[test]
+    fn render_schema_with_defaults_and_descriptions() {
+        let schema = json!({
+            "type": "object",
+            "required": ["registries"],
+            "properties": {
+                "registries": {
+                    "type": "array",
+                    "items": { "type": "string" },
+                    "description": "List of allowed registries",
+                    "default": ["ghcr.io"]
+                },
+                "mode": {
+                    "type": "string",
+                    "enum": ["strict", "relaxed"]
+                },
+                "limits": {
+                    "type": "object",
+                    "properties": {
+                        "cpu": { "type": "string", "default": "100m" },
+                        "replicas": { "type": "integer" }
+                    }
+                }
+            }
+        });
+
+        let block = commented_settings(&schema, 2).unwrap();
+        assert!(block.lines().all(|l| l.trim_start().starts_with('#')));
+        assert!(block.contains("# List of allowed registries"));
+        assert!(block.contains("# type: array of string, required, default: [\"ghcr.io\"]"));
+        assert!(block.contains("# type: string, allowed values: \"strict\", \"relaxed\""));
+
+        let settings: serde_yaml::Value = serde_yaml::from_str(&uncomment(&block)).unwrap();
+        let expected: serde_yaml::Value = serde_yaml::from_str(
+            r#"
+settings:
+  limits:
+    cpu: "100m"
+    replicas: 0
+  mode: null
+  registries: ["ghcr.io"]
+"#,
+        )
+        .unwrap();
+        assert_eq!(settings, expected);
+    }
+
+    #[test]
+    fn render_schema_without_properties() {
+        let block = commented_settings(&json!({"type": "object"}), 0).unwrap();
+        assert_eq!(
+            block,
+            "# settings:\n#   # The policy does not have any setting\n"
+        );
+    }
+
+    #[test]
+    fn render_invalid_schema() {
+        assert!(commented_settings(&json!("string"), 0).is_err());
+        assert!(commented_settings(&json!({"properties": []}), 0).is_err());
+    }
+
+    #[test]
+    fn render_example_settings() {
+        let schema = json!({
+            "type": "object",
+            "properties": {
+                "limits": {
+                    "type": "object",
+                    "description": "Limits enforced by the policy",
+                    "properties": {
+                        "mode": {
+                            "type": "string",
+                            "enum": ["strict", "relaxed"],
+                            "default": "strict"
+                        }
+                    }
+                }
+            }
+        });
+
+        let example = example_settings(&schema).unwrap();
+        assert_eq!(
+            example,
+            r#"# Limits enforced by the policy
+# type: object
+limits:
+  # type: string, allowed values: "strict", "relaxed", default: "strict"
+  mode: "strict"
+"#
+        );
+        let settings: serde_yaml::Value = serde_yaml::from_str(&example).unwrap();
+        assert_eq!(settings["limits"]["mode"], "strict");
+    }
+
+    #[test]
+    fn render_example_settings_without_properties() {
+        let example = example_settings(&json!({"type": "object"})).unwrap();
+        assert_eq!(example, "# The policy does not have any setting\n{}\n");
+    }
+
+    #[test]
+    fn describe_nested_settings() {
+        let schema = json!({
+            "type": "object",
+            "required": ["limits"],
+            "properties": {
+                "limits": {
+                    "type": "object",
+                    "description": "Limits enforced by the policy",
+                    "properties": {
+                        "replicas": { "type": "integer", "default": 1 }
+                    }
+                }
+            }
+        });
+
+        let settings = describe_settings(&schema).unwrap();
+        let described: Vec<(&str, &str, Option<&str>)> = settings
+            .iter()
+            .map(|s| {
+                (
+                    s.path.as_str(),
+                    s.details.as_str(),
+                    s.description.as_deref(),
+                )
+            })
+            .collect();
+        assert_eq!(
+            described,
+            vec![
+                (
+                    "limits",
+                    "type: object, required",
+                    Some("Limits enforced by the policy")
+                ),
+                ("limits.replicas", "type: integer, default: 1", None),
+            ]
+        );
+    }
+
+    #[rstest]
+    #[case::default_value(
+        json!({"mode": {"type": "string", "default": "strict"}}),
+        "\n",
+        json!({"mode": "strict"}),
+        None
+    )]
+    #[case::optional(json!({"mode": {"type": "string"}}), "\n", json!({}), None)]
+    #[case::unquoted_string(
+        json!({"mode": {"type": "string"}}),
+        "relaxed\n",
+        json!({"mode": "relaxed"}),
+        None
+    )]
+    #[case::invalid_type(
+        json!({"replicas": {"type": "integer"}}),
+        "three\n3\n",
+        json!({"replicas": 3}),
+        Some("Invalid value: expected a value of type integer")
+    )]
+    #[case::not_allowed(
+        json!({"mode": {"type": "string", "enum": ["strict", "relaxed"]}}),
+        "permissive\nrelaxed\n",
+        json!({"mode": "relaxed"}),
+        Some("Invalid value: must be one of \"strict\", \"relaxed\"")
+    )]
+    #[case::invalid_items(
+        json!({"registries": {"type": "array", "items": {"type": "string"}}}),
+        "[1, 2]\n[ghcr.io]\n",
+        json!({"registries": ["ghcr.io"]}),
+        Some("Invalid value: expected a value of type string")
+    )]
+    #[case::nested(
+        json!({"limits": {"type": "object", "properties": {"replicas": {"type": "integer"}}}}),
+        "2\n",
+        json!({"limits": {"replicas": 2}}),
+        Some("limits.replicas: ")
+    )]
+    fn prompt_for_settings(
+        #[case] properties: Value,
+        #[case] answers: &str,
+        #[case] expected: Value,
+        #[case] expected_output: Option<&str>,
+    ) {
+        let schema = json!({"type": "object", "properties": properties});
+        let mut output = Vec::new();
+
+        let settings = prompt_settings(&schema, &mut answers.as_bytes(), &mut output).unwrap();
+        assert_eq!(settings, expected);
+        if let Some(expected_output) = expected_output {
+            assert!(String::from_utf8(output).unwrap().contains(expected_output));
+        }
+    }
+
+    #[test]
+    fn prompt_for_required_settings() {
+        let schema = json!({
+            "type": "object",
+            "required": ["mode"],
+            "properties": { "mode": { "type": "string" } }
+        });
+        let mut output = Vec::new();
+
+        let settings = prompt_settings(&schema, &mut "\nstrict\n".as_bytes(), &mut output).unwrap();
+        assert_eq!(settings, json!({"mode": "strict"}));
+        assert!(String::from_utf8(output)
+            .unwrap()
+            .contains("The setting is required"));
+
+        assert!(prompt_settings(&schema, &mut "\n".as_bytes(), &mut Vec::new()).is_err());
+    }
+
+    #[rstest]
+    #[case::plain("allowedRegistries", "allowedRegistries")]
+    #[case::with_colon("foo: bar", "\"foo: bar\"")]
+    #[case::leading_dash("-foo", "\"-foo\"")]
+    #[case::with_hash("a#b", "\"a#b\"")]
+    fn keys_are_quoted_when_needed(#[case] name: &str, #[case] expected: &str) {
+        assert_eq!(yaml_key(name), expected);
+    }
+}
@@ -1,6 +1,7 @@
 use anyhow::{anyhow, Result};
-use k8s_openapi::api::admissionregistration::v1::{
-    ValidatingAdmissionPolicy, ValidatingAdmissionPolicyBinding,
+use k8s_openapi::{
+    api::admissionregistration::v1::{ValidatingAdmissionPolicy, ValidatingAdmissionPolicyBinding},
+    apimachinery::pkg::apis::meta::v1::{LabelSelector, LabelSelectorRequirement},
 };
 use policy_evaluator::{policy_fetcher::oci_client::Reference, policy_metadata::Rule};
 use std::{collections::BTreeSet, convert::TryFrom, fs::File, path::Path};
@@ -62,6 +63,14 @@ fn convert_vap_to_cluster_admission_policy(
         ));
     }
 
+    let vap_binding_spec = vap_binding.spec.unwrap_or_default();
+    if vap_binding_spec.param_ref.is_some() {
+        return Err(anyhow!(
+            "paramRef is not supported by Kubewarden's CEL policy yet"
+        ));
+    }
+    let mode = policy_mode(vap_binding_spec.validation_actions.as_deref());
+
     let mut settings = serde_yaml::Mapping::new();
 
     // migrate CEL variables
@@ -82,16 +91,30 @@ fn convert_vap_to_cluster_admission_policy(
         settings.insert("validations".into(), kw_cel_validations.into());
     }
 
-    // VAP specifies the namespace selector inside of the binding
-    let namespace_selector = vap_binding
-        .spec
-        .unwrap_or_default()
-        .match_resources
-        .unwrap_or_default()
-        .namespace_selector;
+    // Both the VAP and the binding can restrict the namespaces and the objects,
+    // the policy must match only the ones selected by both of them
+    let vap_match_constraints = vap_spec.match_constraints.unwrap_or_default();
+    let binding_match_resources = vap_binding_spec.match_resources.unwrap_or_default();
+    if vap_match_constraints.exclude_resource_rules.is_some()
+        || binding_match_resources.exclude_resource_rules.is_some()
+    {
+        warn!(
+            "excludeResourceRules are not supported by Kubewarden policies. They will be ignored."
+        );
+    }
+    if binding_match_resources.resource_rules.is_some() {
+        warn!("the resourceRules of the binding are ignored, only the ones of the ValidatingAdmissionPolicy are used.");
+    }
+    let namespace_selector = merge_label_selectors(
+        vap_match_constraints.namespace_selector,
+        binding_match_resources.namespace_selector,
+    );
+    let object_selector = merge_label_selectors(
+        vap_match_constraints.object_selector,
+        binding_match_resources.object_selector,
+    );
 
     // VAP rules are specified inside of the VAP object
-    let vap_match_constraints = vap_spec.match_constraints.unwrap_or_default();
     let match_policy = vap_match_constraints.match_policy;
     let rules = vap_match_constraints
         .resource_rules
@@ -111,12 +134,12 @@ fn convert_vap_to_cluster_admission_policy(
             namespace_selector,
             match_policy,
             rules,
-            object_selector: vap_match_constraints.object_selector,
+            object_selector,
             mutating: false,
             background_audit: true,
             context_aware_resources: BTreeSet::new(),
             failure_policy: vap_spec.failure_policy,
-            mode: None, // VAP policies are always in protect mode, which is the default for KW
+            mode,
             settings,
         },
     };
@@ -124,6 +147,58 @@ fn convert_vap_to_cluster_admission_policy(
     Ok(cluster_admission_policy)
 }
 
+/// Compute the mode of the policy from the `validationActions` of the binding.
+/// Policies that do not deny requests are deployed in monitor mode, otherwise the
+/// default protect mode is used
+fn policy_mode(validation_actions: Option<&[String]>) -> Option<String> {
+    let validation_actions = validation_actions.unwrap_or_default();
+    if validation_actions.is_empty() || validation_actions.iter().any(|a| a == "Deny") {
+        return None;
+    }
+
+    warn!(
+        ?validation_actions,
+        "the binding does not deny requests, the policy is going to be deployed in monitor mode"
+    );
+    Some("monitor".to_string())
+}
+
+/// Build a label selector that matches only the objects selected by both selectors
+fn merge_label_selectors(
+    selector: Option<LabelSelector>,
+    other: Option<LabelSelector>,
+) -> Option<LabelSelector> {
+    let (mut selector, other) = match (selector, other) {
+        (Some(selector), Some(other)) => (selector, other),
+        (selector, other) => return selector.or(other),
+    };
+
+    let mut match_expressions = selector.match_expressions.take().unwrap_or_default();
+    let mut match_labels = selector.match_labels.take().unwrap_or_default();
+    for (key, value) in other.match_labels.unwrap_or_default() {
+        match match_labels.get(&key) {
+            // a label cannot have two values inside of `matchLabels`, express the second
+            // requirement as an expression
+            Some(existing) if *existing != value => {
+                match_expressions.push(LabelSelectorRequirement {
+                    key,
+                    operator: "In".to_string(),
+                    values: Some(vec![value]),
+                })
+            }
+            _ => {
+                match_labels.insert(key, value);
+            }
+        }
+    }
+    match_expressions.extend(other.match_expressions.unwrap_or_default());
+
+    Some(LabelSelector {
+        match_expressions: (!match_expressions.is_empty()).then_some(match_expressions),
+        match_labels: (!match_labels.is_empty()).then_some(match_labels),
+    })
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -189,6 +264,7 @@ mod tests {
             vap.clone().spec.unwrap().failure_policy,
             cluster_admission_policy.spec.failure_policy
         );
+        // the binding denies the requests
         assert!(cluster_admission_policy.spec.mode.is_none());
         assert_eq!(
             vap.clone()
@@ -229,4 +305,74 @@ mod tests {
                 .contains_key("variables"));
         }
     }
+
+    #[test]
+    fn merge_namespace_selectors() {
+        let vap_selector: LabelSelector = serde_yaml::from_str(
+            r#"
+matchLabels:
+  environment: production
+  team: a
+matchExpressions:
+  - key: tier
+    operator: Exists
+"#,
+        )
+        .unwrap();
+        let binding_selector: LabelSelector = serde_yaml::from_str(
+            r#"
+matchLabels:
+  team: b
+  region: eu
+"#,
+        )
+        .unwrap();
+        let expected: LabelSelector = serde_yaml::from_str(
+            r#"
+matchLabels:
+  environment: production
+  region: eu
+  team: a
+matchExpressions:
+  - key: tier
+    operator: Exists
+  - key: team
+    operator: In
+    values: [b]
+"#,
+        )
+        .unwrap();
+
+        assert_eq!(
+            merge_label_selectors(Some(vap_selector.clone()), Some(binding_selector.clone())),
+            Some(expected)
+        );
+        assert_eq!(
+            merge_label_selectors(None, Some(binding_selector.clone())),
+            Some(binding_selector)
+        );
+        assert_eq!(
+            merge_label_selectors(Some(vap_selector.clone()), None),
+            Some(vap_selector)
+        );
+        assert_eq!(merge_label_selectors(None, None), None);
+    }
+
+    #[rstest]
+    #[case::not_set(None, None)]
+    #[case::deny(Some(vec!["Deny"]), None)]
+    #[case::deny_and_audit(Some(vec!["Deny", "Audit"]), None)]
+    #[case::warn(Some(vec!["Warn"]), Some("monitor"))]
+    #[case::warn_and_audit(Some(vec!["Warn", "Audit"]), Some("monitor"))]
+    fn policy_mode_from_validation_actions(
+        #[case] validation_actions: Option<Vec<&str>>,
+        #[case] expected_mode: Option<&str>,
+    ) {
+        let validation_actions: Option<Vec<String>> =
+            validation_actions.map(|actions| actions.into_iter().map(String::from).collect());
+        assert_eq!(
+            policy_mode(validation_actions.as_deref()),
+            expected_mode.map(String::from)
+        );
+    }
 }
@@ -31,9 +31,12 @@ pub(crate) fn verification_config() -> Result<String> {
             all_of: Some(vec![Signature::GithubAction {
                 owner: "kubewarden".to_string(),
                 repo: None,
+                workflow_ref: None,
+                workflow_trigger: None,
                 annotations: None,
             }]),
             any_of: None,
+            trust_roots: None,
         });
 
     Ok(format!(
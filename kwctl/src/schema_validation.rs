@@ -0,0 +1,139 @@
+//! Validation of the settings of a policy against the JSON Schema embedded
+//! inside of its metadata.
+//!
+//! This catches the most common mistakes, like a typo inside of the name of a
+//! setting or a value of the wrong type, without instantiating the policy. The
+//! policy remains the authority on its settings: settings complying with the
+//! schema are still given to the policy for validation.
+
+use std::fmt;
+
+use anyhow::{anyhow, Result};
+use policy_evaluator::policy_evaluator::PolicySettings;
+use serde_json::Value;
+
+/// A value of the settings that doesn't comply with the schema
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct SchemaViolation {
+    /// JSON pointer to the offending value, empty when it's the settings themselves
+    pub(crate) path: String,
+    /// Why the value doesn't comply with the schema
+    pub(crate) message: String,
+}
+
+impl fmt::Display for SchemaViolation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.path.is_empty() {
+            write!(f, "settings: {}", self.message)
+        } else {
+            write!(f, "{}: {}", self.path, self.message)
+        }
+    }
+}
+
+/// Validate the settings against the given JSON Schema. Returns the values that
+/// do not comply with the schema, the list is empty when the settings are valid.
+///
+/// Fails when the schema itself is not valid.
+pub(crate) fn validate_settings(schema: &Value, settings: &Value) -> Result<Vec<SchemaViolation>> {
+    let validator = jsonschema::validator_for(schema)
+        .map_err(|e| anyhow!("the settings schema of the policy is not valid: {e}"))?;
+
+    Ok(validator
+        .iter_errors(settings)
+        .map(|error| SchemaViolation {
+            path: error.instance_path.to_string(),
+            message: error.to_string(),
+        })
+        .collect())
+}
+
+/// Validate the settings against the schema declared by the metadata of the
+/// policy. Nothing is done when the policy doesn't declare a schema.
+///
+/// Returns a description of the values that do not comply with the schema, `None`
+/// when the settings are valid.
+pub(crate) fn validate_policy_settings(
+    schema: Option<&Value>,
+    settings: &PolicySettings,
+) -> Result<Option<String>> {
+    let Some(schema) = schema else {
+        return Ok(None);
+    };
+
+    let violations = validate_settings(schema, &Value::Object(settings.0.clone()))?;
+    if violations.is_empty() {
+        return Ok(None);
+    }
+
+    Ok(Some(describe_violations(&violations)))
+}
+
+/// Describe the violations, one per line
+pub(crate) fn describe_violations(violations: &[SchemaViolation]) -> String {
+    violations
+        .iter()
+        .map(|violation| format!("- {violation}"))
+        .collect::<Vec<String>>()
+        .join("\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use rstest::rstest;
+    use serde_json::json;
+
+    fn schema() -> Value {
+        json!({
+            "type": "object",
+            "properties": {
+                "maxReplicas": {"type": "integer", "minimum": 1},
+                "allowedRegistries": {"type": "array", "items": {"type": "string"}}
+            },
+            "required": ["maxReplicas"],
+            "additionalProperties": false
+        })
+    }
+
+    #[rstest]
+    #[case::valid(json!({"maxReplicas": 3, "allowedRegistries": ["ghcr.io"]}), &[])]
+    #[case::wrong_type(json!({"maxReplicas": "3"}), &["/maxReplicas"])]
+    #[case::nested_value(json!({"maxReplicas": 3, "allowedRegistries": ["ghcr.io", 42]}), &["/allowedRegistries/1"])]
+    #[case::missing_required(json!({}), &[""])]
+    #[case::several_violations(json!({"maxReplicas": 0, "maxReplica": 3}), &["", "/maxReplicas"])]
+    fn settings_validated_against_schema(#[case] settings: Value, #[case] paths: &[&str]) {
+        let violations = validate_settings(&schema(), &settings).unwrap();
+
+        let mut violation_paths: Vec<&str> = violations.iter().map(|v| v.path.as_str()).collect();
+        violation_paths.sort_unstable();
+        assert_eq!(violation_paths, paths);
+    }
+
+    #[test]
+    fn invalid_schema() {
+        let schema = json!({"type": "not-a-type"});
+
+        assert!(validate_settings(&schema, &json!({})).is_err());
+    }
+
+    #[test]
+    fn policy_without_schema() {
+        let settings = PolicySettings::try_from(&json!({"anything": true})).unwrap();
+
+        assert_eq!(validate_policy_settings(None, &settings).unwrap(), None);
+    }
+
+    #[test]
+    fn policy_settings_described() {
+        let settings = PolicySettings::try_from(&json!({"maxReplicas": "3"})).unwrap();
+
+        let description = validate_policy_settings(Some(&schema()), &settings)
+            .unwrap()
+            .expect("the settings are not valid");
+
+        assert!(description.starts_with("- /maxReplicas: "));
+        assert_eq!(description.lines().count(), 1);
+    }
+}
@@ -6,6 +6,7 @@ use regex::Regex;
 use serde_json::json;
 use std::path::PathBuf;
 use std::str::FromStr;
+use tracing::warn;
 use url::Url;
 
 #[derive(Debug, thiserror::Error)]
@@ -69,6 +70,11 @@ pub(crate) fn wasm_path(uri: &str) -> std::result::Result<PathBuf, LookupError>
             let policy = store.get_policy_by_uri(uri)?;
 
             if let Some(policy) = policy {
+                // the usage journal drives `kwctl policies prune`, failing to
+                // update it must not prevent the policy from being used
+                if let Err(e) = store.record_usage(&policy) {
+                    warn!(policy = uri, error = %e, "cannot record the usage of the policy");
+                }
                 Ok(policy.local_path)
             } else {
                 Err(LookupError::PolicyMissing(uri.to_string()))
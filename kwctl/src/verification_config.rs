@@ -0,0 +1,40 @@
+use std::{fs, path::Path};
+
+use anyhow::{anyhow, Result};
+use policy_evaluator::policy_fetcher::{
+    sources::Sources,
+    verify::config::{
+        fetch_from_registry, push_to_registry, read_verification_file,
+        serialize_verification_config,
+    },
+};
+
+/// Push the verification config stored inside of the given file to an OCI registry.
+/// The config is validated before being pushed.
+///
+/// Returns the immutable reference to the pushed config
+pub(crate) async fn push(path: &Path, uri: &str, sources: Option<&Sources>) -> Result<String> {
+    let config = read_verification_file(path)
+        .map_err(|e| anyhow!("Cannot read verification config {}: {}", path.display(), e))?;
+
+    Ok(push_to_registry(&config, uri, sources).await?)
+}
+
+/// Pull the verification config stored inside of an OCI registry. The config is
+/// written to `output`, or printed on STDOUT when no output is given
+pub(crate) async fn pull(
+    uri: &str,
+    output: Option<&Path>,
+    sources: Option<&Sources>,
+) -> Result<()> {
+    let config = fetch_from_registry(uri, sources).await?;
+    let config_yaml = serialize_verification_config(&config)?;
+
+    match output {
+        Some(output) => fs::write(output, config_yaml)
+            .map_err(|e| anyhow!("Cannot write {}: {}", output.display(), e))?,
+        None => print!("{config_yaml}"),
+    }
+
+    Ok(())
+}
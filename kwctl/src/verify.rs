@@ -3,6 +3,7 @@ use policy_evaluator::policy_fetcher::{
     policy::Policy,
     sigstore::trust::ManualTrustRoot,
     sources::Sources,
+    store::provenance::RecordedBundles,
     verify::{config::LatestVerificationConfig, Verifier},
 };
 use std::collections::BTreeMap;
@@ -30,6 +31,29 @@ pub(crate) async fn verify(
     Ok(verified_manifest_digest)
 }
 
+/// Like [`verify`], also returns the Sigstore bundles that verified the policy.
+/// They are recorded inside of the store, to verify the policy again offline
+pub(crate) async fn verify_and_record_bundles(
+    url: &str,
+    sources: Option<&Sources>,
+    verification_config: &LatestVerificationConfig,
+    sigstore_trust_root: Option<Arc<ManualTrustRoot<'static>>>,
+) -> Result<(String, Option<RecordedBundles>)> {
+    debug!(
+        policy = url,
+        ?sources,
+        ?verification_config,
+        "Verifying policy"
+    );
+    let mut verifier = Verifier::new(sources.cloned(), sigstore_trust_root).await?;
+    let verified = verifier
+        .verify_and_record_bundles(url, verification_config)
+        .await?;
+
+    info!("Policy successfully verified");
+    Ok(verified)
+}
+
 pub(crate) async fn verify_local_checksum(
     policy: &Policy,
     sources: Option<&Sources>,
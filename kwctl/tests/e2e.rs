@@ -102,6 +102,37 @@ fn test_policies() {
         .stdout(contains("v0.1.13"));
 }
 
+#[test]
+fn test_policies_json_output() {
+    let tempdir = tempdir().unwrap();
+    pull_policies(tempdir.path(), POLICIES);
+
+    let mut cmd = setup_command(tempdir.path());
+    cmd.arg("policies").arg("--output").arg("json");
+
+    cmd.assert().success();
+    let output = cmd.output().unwrap();
+    let policies: Vec<serde_json::Value> = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(policies.len(), POLICIES.len());
+
+    let pod_privileged = policies
+        .iter()
+        .find(|policy| {
+            policy["uri"]
+                .as_str()
+                .is_some_and(|uri| uri.contains("pod-privileged"))
+        })
+        .expect("pod-privileged policy not listed");
+    assert_eq!(pod_privileged["mutating"], false);
+    assert_eq!(pod_privileged["contextAware"], false);
+    assert_eq!(
+        pod_privileged["provenance"]["sha256"],
+        pod_privileged["sha256"]
+    );
+    assert_eq!(pod_privileged["provenance"]["verification"], "notVerified");
+    assert!(pod_privileged["size"].as_u64().unwrap() > 0);
+}
+
 #[rstest]
 #[case::https(
     "https://github.com/kubewarden/pod-privileged-policy/releases/download/v0.2.5/policy.wasm"
@@ -156,11 +187,51 @@ fn test_run_individual_policy_from_cli(#[case] request: &str, #[case] allowed: b
         .arg(test_data(request))
         .arg("registry://ghcr.io/kubewarden/tests/pod-privileged:v0.2.5");
 
-    cmd.assert().success();
+    cmd.assert().code(if allowed { 0 } else { 3 });
     cmd.assert()
         .stdout(contains(format!("\"allowed\":{}", allowed)));
 }
 
+#[rstest]
+#[case::json_lines("requests.jsonl")]
+#[case::yaml_documents("requests.yaml")]
+fn test_run_multiple_requests(#[case] request_file: &str) {
+    let tempdir = tempdir().unwrap();
+    pull_policies(tempdir.path(), POLICIES);
+
+    let requests: Vec<serde_json::Value> = ["unprivileged-pod.json", "privileged-pod.json"]
+        .iter()
+        .map(|request| {
+            serde_json::from_str(&std::fs::read_to_string(test_data(request)).unwrap()).unwrap()
+        })
+        .collect();
+    let contents = if request_file.ends_with(".jsonl") {
+        requests
+            .iter()
+            .map(|request| format!("{}\n", serde_json::to_string(request).unwrap()))
+            .collect::<String>()
+    } else {
+        requests
+            .iter()
+            .map(|request| format!("---\n{}", serde_yaml::to_string(request).unwrap()))
+            .collect::<String>()
+    };
+    let request_path = tempdir.path().join(request_file);
+    std::fs::write(&request_path, contents).unwrap();
+
+    let mut cmd = setup_command(tempdir.path());
+    cmd.arg("run")
+        .arg("--request-path")
+        .arg(&request_path)
+        .arg("registry://ghcr.io/kubewarden/tests/pod-privileged:v0.2.5");
+
+    // the privileged pod is rejected
+    cmd.assert()
+        .failure()
+        .stdout(contains("\"document\":1").and(contains("\"allowed\":true")))
+        .stdout(contains("\"document\":2").and(contains("\"allowed\":false")));
+}
+
 #[rstest]
 #[case::allowed("unprivileged-pod.json", true)]
 #[case::rejected("privileged-pod.json", false)]
@@ -186,7 +257,7 @@ fn test_run_individual_policy_from_yaml(#[case] request: &str, #[case] allowed:
         .arg(test_data(request))
         .arg(yaml_file.path());
 
-    cmd.assert().success();
+    cmd.assert().code(if allowed { 0 } else { 3 });
     cmd.assert()
         .stdout(contains(format!("\"allowed\":{}", allowed)));
 }
@@ -336,7 +407,7 @@ fn test_run_context(
         .arg(session_path)
         .arg(policy_uri);
 
-    cmd.assert().success();
+    cmd.assert().code(if allowed { 0 } else { 3 });
     cmd.assert()
         .stdout(contains(format!("\"allowed\":{}", allowed)));
 }
@@ -411,7 +482,7 @@ fn test_run_context_from_yaml(
         .arg(session_path)
         .arg(yaml_file.path());
 
-    cmd.assert().success();
+    cmd.assert().code(if allowed { 0 } else { 3 });
     cmd.assert()
         .stdout(contains(format!("\"allowed\":{}", allowed)));
 }
@@ -636,6 +707,11 @@ fn test_save_and_load() {
     for policy in POLICIES {
         cmd.assert().stdout(contains(*policy));
     }
+
+    let mut cmd = setup_command(tempdir.path());
+    cmd.arg("policies").arg("--output").arg("json");
+    cmd.assert().success();
+    cmd.assert().stdout(contains("pulledAt"));
 }
 
 #[test]
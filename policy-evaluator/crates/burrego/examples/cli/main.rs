@@ -1,7 +1,12 @@
 use anyhow::{anyhow, Result};
 
+use policy_evaluator::{
+    callback_handler::{CallbackHandler, CallbackHandlerBuilder},
+    policy_fetcher::sources::read_sources_file,
+};
 use serde_json::json;
 use std::{fs::File, io::BufReader, path::PathBuf, process};
+use tokio::sync::oneshot;
 
 use tracing::debug;
 use tracing_subscriber::prelude::*;
@@ -49,6 +54,21 @@ pub(crate) enum Commands {
         )]
         entrypoint: String,
 
+        /// Back the Kubewarden builtins with the host capabilities, the same
+        /// ones used by kwctl and policy-server
+        #[clap(long, value_parser)]
+        host_capabilities: bool,
+
+        /// YAML file holding source information (https, registry insecure hosts, custom CA's...),
+        /// used by the host capabilities
+        #[clap(
+            long,
+            value_name = "PATH",
+            requires = "host_capabilities",
+            value_parser
+        )]
+        sources_path: Option<PathBuf>,
+
         /// Path to WebAssembly module to load
         #[clap(value_parser, value_name = "WASM_FILE", value_parser)]
         policy: String,
@@ -85,6 +105,8 @@ fn main() -> Result<()> {
             input_path,
             data,
             entrypoint,
+            host_capabilities,
+            sources_path,
             policy,
         } => {
             if input.is_some() && input_path.is_some() {
@@ -104,9 +126,26 @@ fn main() -> Result<()> {
                 json!({})
             };
 
+            // The callback handler runs on its own runtime, while the
+            // evaluation blocks the main thread waiting for its answers
+            let mut host_callbacks = burrego::HostCallbacks::default();
+            let mut callback_handler_runtime = None;
+            if *host_capabilities {
+                let runtime = tokio::runtime::Runtime::new()?;
+                let (shutdown_tx, shutdown_rx) = oneshot::channel();
+                let mut callback_handler =
+                    runtime.block_on(new_callback_handler(sources_path.as_ref(), shutdown_rx))?;
+                host_callbacks.builtins =
+                    policy_evaluator::new_host_builtins(Some(callback_handler.sender_channel()));
+                runtime.spawn(async move {
+                    callback_handler.loop_eval().await;
+                });
+                callback_handler_runtime = Some((runtime, shutdown_tx));
+            }
+
             let mut evaluator = burrego::EvaluatorBuilder::default()
                 .policy_path(&PathBuf::from(policy))
-                .host_callbacks(burrego::HostCallbacks::default())
+                .host_callbacks(host_callbacks)
                 .build()?;
 
             let (major, minor) = evaluator.opa_abi_version()?;
@@ -129,10 +168,27 @@ fn main() -> Result<()> {
                 _ => evaluator.entrypoint_id(&String::from(entrypoint))?,
             };
 
-            let evaluation_res =
-                evaluator.evaluate(entrypoint_id, &input_value, data.as_bytes())?;
-            println!("{}", serde_json::to_string_pretty(&evaluation_res)?);
+            let evaluation_res = evaluator.evaluate(entrypoint_id, &input_value, data.as_bytes());
+            if let Some((_runtime, shutdown_tx)) = callback_handler_runtime {
+                let _ = shutdown_tx.send(());
+            }
+            println!("{}", serde_json::to_string_pretty(&evaluation_res?)?);
             Ok(())
         }
     }
 }
+
+async fn new_callback_handler(
+    sources_path: Option<&PathBuf>,
+    shutdown_channel: oneshot::Receiver<()>,
+) -> Result<CallbackHandler> {
+    let sources = sources_path
+        .map(|path| read_sources_file(path))
+        .transpose()
+        .map_err(|e| anyhow!("Cannot read sources file: {:?}", e))?;
+
+    CallbackHandlerBuilder::new(shutdown_channel)
+        .registry_config(sources)
+        .build()
+        .await
+}
@@ -0,0 +1,540 @@
+use crate::errors::{BurregoError, Result};
+use ::md5::Md5;
+use ::sha1::Sha1;
+use sha2::{Digest, Sha256};
+
+pub fn md5(args: &[serde_json::Value]) -> Result<serde_json::Value> {
+    digest::<Md5>("crypto.md5", args)
+}
+
+pub fn sha1(args: &[serde_json::Value]) -> Result<serde_json::Value> {
+    digest::<Sha1>("crypto.sha1", args)
+}
+
+pub fn sha256(args: &[serde_json::Value]) -> Result<serde_json::Value> {
+    digest::<Sha256>("crypto.sha256", args)
+}
+
+/// Compute the digest of the given string, returned as a lowercase hex string
+fn digest<D: Digest>(name: &str, args: &[serde_json::Value]) -> Result<serde_json::Value> {
+    if args.len() != 1 {
+        return Err(BurregoError::BuiltinError {
+            name: name.to_string(),
+            message: "wrong number of arguments".to_string(),
+        });
+    }
+
+    let input = args[0].as_str().ok_or_else(|| BurregoError::BuiltinError {
+        name: name.to_string(),
+        message: "1st parameter is not a string".to_string(),
+    })?;
+
+    let res: String = D::digest(input.as_bytes())
+        .iter()
+        .map(|b| format!("{b:02x}"))
+        .collect();
+
+    serde_json::to_value(res).map_err(|e| BurregoError::BuiltinError {
+        name: name.to_string(),
+        message: format!("cannot convert value into JSON: {e:?}"),
+    })
+}
+
+pub mod x509 {
+    //! The certificates are given back using the same structure of Go's
+    //! `x509.Certificate`, like OPA does. The differences are:
+    //!
+    //! * `PublicKey` is always `null`
+    //! * serial numbers that do not fit into 64 bits are given as strings
+    //! * `PolicyIdentifiers` and the name constraints are not set
+
+    use crate::errors::{BurregoError, Result};
+    use base64::{engine::general_purpose, Engine as _};
+    use chrono::{DateTime, SecondsFormat};
+    use serde_json::{json, Value};
+    use std::net::IpAddr;
+    use x509_parser::{
+        certificate::X509Certificate,
+        extensions::{DistributionPointName, GeneralName, ParsedExtension},
+        oid_registry::Oid,
+        pem::Pem,
+        x509::X509Name,
+    };
+
+    const NAME: &str = "crypto.x509.parse_certificates";
+
+    /// Parse the certificates given either as a PEM string, or as base64 encoded DER
+    /// or PEM data
+    pub fn parse_certificates(args: &[serde_json::Value]) -> Result<serde_json::Value> {
+        if args.len() != 1 {
+            return Err(builtin_error("wrong number of arguments"));
+        }
+
+        let input = args[0]
+            .as_str()
+            .ok_or_else(|| builtin_error("1st parameter is not a string"))?;
+
+        let data = if input.trim_start().starts_with("-----BEGIN") {
+            input.as_bytes().to_vec()
+        } else {
+            general_purpose::STANDARD
+                .decode(input.trim())
+                .map_err(|e| builtin_error(&format!("cannot decode base64 data: {e}")))?
+        };
+
+        let certificates = if data.starts_with(b"-----BEGIN") {
+            let mut certificates = Vec::new();
+            for pem in Pem::iter_from_buffer(&data) {
+                let pem = pem.map_err(|e| builtin_error(&format!("cannot parse PEM data: {e}")))?;
+                certificates.extend(parse_der(&pem.contents)?);
+            }
+            certificates
+        } else {
+            parse_der(&data)?
+        };
+
+        if certificates.is_empty() {
+            return Err(builtin_error("no certificate found"));
+        }
+        Ok(Value::Array(certificates))
+    }
+
+    /// Parse the DER encoded certificates, concatenated one after the other
+    fn parse_der(mut der: &[u8]) -> Result<Vec<Value>> {
+        let mut certificates = Vec::new();
+        while !der.is_empty() {
+            let (rest, certificate) = x509_parser::parse_x509_certificate(der)
+                .map_err(|e| builtin_error(&format!("cannot parse certificate: {e}")))?;
+            let raw = &der[..der.len() - rest.len()];
+            certificates.push(certificate_to_value(raw, &certificate)?);
+            der = rest;
+        }
+        Ok(certificates)
+    }
+
+    fn certificate_to_value(raw: &[u8], certificate: &X509Certificate) -> Result<Value> {
+        let serial_digits = certificate.serial.to_u64_digits();
+        let serial_number = match serial_digits.as_slice() {
+            [] => json!(0),
+            [serial] => json!(serial),
+            _ => json!(certificate.serial.to_string()),
+        };
+
+        let mut key_usage = 0;
+        let mut ext_key_usage = Vec::new();
+        let mut unknown_ext_key_usage = Vec::new();
+        let mut basic_constraints_valid = false;
+        let mut is_ca = false;
+        let mut max_path_len: i64 = 0;
+        let mut subject_key_id = None;
+        let mut authority_key_id = None;
+        let mut dns_names = Vec::new();
+        let mut email_addresses = Vec::new();
+        let mut ip_addresses = Vec::new();
+        let mut uris = Vec::new();
+        let mut ocsp_servers = Vec::new();
+        let mut issuing_certificate_urls = Vec::new();
+        let mut crl_distribution_points = Vec::new();
+        let mut extensions = Vec::new();
+
+        for extension in certificate.extensions() {
+            extensions.push(json!({
+                "Id": oid_to_value(&extension.oid),
+                "Critical": extension.critical,
+                "Value": base64(extension.value),
+            }));
+
+            match extension.parsed_extension() {
+                ParsedExtension::KeyUsage(usage) => key_usage = usage.flags,
+                ParsedExtension::ExtendedKeyUsage(usage) => {
+                    for (set, id) in [
+                        (usage.any, 0),
+                        (usage.server_auth, 1),
+                        (usage.client_auth, 2),
+                        (usage.code_signing, 3),
+                        (usage.email_protection, 4),
+                        (usage.time_stamping, 8),
+                        (usage.ocsp_signing, 9),
+                    ] {
+                        if set {
+                            ext_key_usage.push(id);
+                        }
+                    }
+                    unknown_ext_key_usage.extend(usage.other.iter().map(oid_to_value));
+                }
+                ParsedExtension::BasicConstraints(constraints) => {
+                    basic_constraints_valid = true;
+                    is_ca = constraints.ca;
+                    max_path_len = constraints.path_len_constraint.map_or(-1, i64::from);
+                }
+                ParsedExtension::SubjectKeyIdentifier(key_id) => {
+                    subject_key_id = Some(base64(key_id.0));
+                }
+                ParsedExtension::AuthorityKeyIdentifier(authority) => {
+                    authority_key_id = authority.key_identifier.as_ref().map(|id| base64(id.0));
+                }
+                ParsedExtension::SubjectAlternativeName(san) => {
+                    for name in &san.general_names {
+                        match name {
+                            GeneralName::DNSName(dns_name) => dns_names.push(dns_name.to_string()),
+                            GeneralName::RFC822Name(email) => {
+                                email_addresses.push(email.to_string())
+                            }
+                            GeneralName::IPAddress(ip) => ip_addresses.push(ip_to_string(ip)?),
+                            GeneralName::URI(uri) => uris.push(uri_to_value(uri)?),
+                            _ => {}
+                        }
+                    }
+                }
+                ParsedExtension::AuthorityInfoAccess(access) => {
+                    for description in &access.accessdescs {
+                        let url = match &description.access_location {
+                            GeneralName::URI(url) => url.to_string(),
+                            _ => continue,
+                        };
+                        match description.access_method.to_id_string().as_str() {
+                            "1.3.6.1.5.5.7.48.1" => ocsp_servers.push(url),
+                            "1.3.6.1.5.5.7.48.2" => issuing_certificate_urls.push(url),
+                            _ => {}
+                        }
+                    }
+                }
+                ParsedExtension::CRLDistributionPoints(points) => {
+                    for point in &points.points {
+                        if let Some(DistributionPointName::FullName(names)) =
+                            &point.distribution_point
+                        {
+                            crl_distribution_points.extend(names.iter().filter_map(
+                                |name| match name {
+                                    GeneralName::URI(uri) => Some(uri.to_string()),
+                                    _ => None,
+                                },
+                            ));
+                        }
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        let validity = certificate.validity();
+        let signature: &[u8] = certificate.signature_value.as_ref();
+        let public_key = certificate.public_key();
+
+        Ok(json!({
+            "Raw": base64(raw),
+            "RawTBSCertificate": base64(certificate.tbs_certificate.as_ref()),
+            "RawSubjectPublicKeyInfo": base64(public_key.raw),
+            "RawSubject": base64(certificate.subject().as_raw()),
+            "RawIssuer": base64(certificate.issuer().as_raw()),
+            "Signature": base64(signature),
+            "SignatureAlgorithm": signature_algorithm(&certificate.signature_algorithm.algorithm),
+            "PublicKeyAlgorithm": public_key_algorithm(&public_key.algorithm.algorithm),
+            "PublicKey": null,
+            "Version": certificate.version().0 + 1,
+            "SerialNumber": serial_number,
+            "Issuer": name_to_value(certificate.issuer())?,
+            "Subject": name_to_value(certificate.subject())?,
+            "NotBefore": timestamp_to_rfc3339(validity.not_before.timestamp())?,
+            "NotAfter": timestamp_to_rfc3339(validity.not_after.timestamp())?,
+            "KeyUsage": key_usage,
+            "Extensions": or_null(extensions),
+            "ExtraExtensions": null,
+            "UnhandledCriticalExtensions": null,
+            "ExtKeyUsage": or_null(ext_key_usage),
+            "UnknownExtKeyUsage": or_null(unknown_ext_key_usage),
+            "BasicConstraintsValid": basic_constraints_valid,
+            "IsCA": is_ca,
+            "MaxPathLen": max_path_len,
+            "MaxPathLenZero": basic_constraints_valid && max_path_len == 0,
+            "SubjectKeyId": subject_key_id,
+            "AuthorityKeyId": authority_key_id,
+            "OCSPServer": or_null(ocsp_servers),
+            "IssuingCertificateURL": or_null(issuing_certificate_urls),
+            "DNSNames": or_null(dns_names),
+            "EmailAddresses": or_null(email_addresses),
+            "IPAddresses": or_null(ip_addresses),
+            "URIs": or_null(uris),
+            "CRLDistributionPoints": or_null(crl_distribution_points),
+            "PolicyIdentifiers": null,
+        }))
+    }
+
+    /// Build the equivalent of Go's `pkix.Name`
+    fn name_to_value(name: &X509Name) -> Result<Value> {
+        let mut country = Vec::new();
+        let mut organization = Vec::new();
+        let mut organizational_unit = Vec::new();
+        let mut locality = Vec::new();
+        let mut province = Vec::new();
+        let mut street_address = Vec::new();
+        let mut postal_code = Vec::new();
+        let mut serial_number = String::new();
+        let mut common_name = String::new();
+        let mut names = Vec::new();
+
+        for attribute in name.iter_attributes() {
+            let value = attribute
+                .as_str()
+                .map_err(|e| builtin_error(&format!("cannot parse name attribute: {e}")))?
+                .to_string();
+            names.push(json!({
+                "Type": oid_to_value(attribute.attr_type()),
+                "Value": value,
+            }));
+            match attribute.attr_type().to_id_string().as_str() {
+                "2.5.4.6" => country.push(value),
+                "2.5.4.10" => organization.push(value),
+                "2.5.4.11" => organizational_unit.push(value),
+                "2.5.4.7" => locality.push(value),
+                "2.5.4.8" => province.push(value),
+                "2.5.4.9" => street_address.push(value),
+                "2.5.4.17" => postal_code.push(value),
+                "2.5.4.5" => serial_number = value,
+                "2.5.4.3" => common_name = value,
+                _ => {}
+            }
+        }
+
+        Ok(json!({
+            "Country": or_null(country),
+            "Organization": or_null(organization),
+            "OrganizationalUnit": or_null(organizational_unit),
+            "Locality": or_null(locality),
+            "Province": or_null(province),
+            "StreetAddress": or_null(street_address),
+            "PostalCode": or_null(postal_code),
+            "SerialNumber": serial_number,
+            "CommonName": common_name,
+            "Names": or_null(names),
+            "ExtraNames": null,
+        }))
+    }
+
+    /// The value of Go's `x509.SignatureAlgorithm`, `0` when unknown
+    fn signature_algorithm(oid: &Oid) -> u8 {
+        match oid.to_id_string().as_str() {
+            "1.2.840.113549.1.1.2" => 1,
+            "1.2.840.113549.1.1.4" => 2,
+            "1.2.840.113549.1.1.5" => 3,
+            "1.2.840.113549.1.1.11" => 4,
+            "1.2.840.113549.1.1.12" => 5,
+            "1.2.840.113549.1.1.13" => 6,
+            "1.2.840.10040.4.3" => 7,
+            "2.16.840.1.101.3.4.3.2" => 8,
+            "1.2.840.10045.4.1" => 9,
+            "1.2.840.10045.4.3.2" => 10,
+            "1.2.840.10045.4.3.3" => 11,
+            "1.2.840.10045.4.3.4" => 12,
+            "1.3.101.112" => 16,
+            _ => 0,
+        }
+    }
+
+    /// The value of Go's `x509.PublicKeyAlgorithm`, `0` when unknown
+    fn public_key_algorithm(oid: &Oid) -> u8 {
+        match oid.to_id_string().as_str() {
+            "1.2.840.113549.1.1.1" => 1,
+            "1.2.840.10040.4.1" => 2,
+            "1.2.840.10045.2.1" => 3,
+            "1.3.101.112" => 4,
+            _ => 0,
+        }
+    }
+
+    /// The equivalent of Go's `url.URL`
+    fn uri_to_value(uri: &str) -> Result<Value> {
+        let url = url::Url::parse(uri)
+            .map_err(|e| builtin_error(&format!("cannot parse URI `{uri}`: {e}")))?;
+        let host = match (url.host_str(), url.port()) {
+            (Some(host), Some(port)) => format!("{host}:{port}"),
+            (Some(host), None) => host.to_string(),
+            (None, _) => String::new(),
+        };
+
+        Ok(json!({
+            "Scheme": url.scheme(),
+            "Opaque": if url.cannot_be_a_base() { url.path() } else { "" },
+            "User": null,
+            "Host": host,
+            "Path": if url.cannot_be_a_base() { "" } else { url.path() },
+            "RawPath": "",
+            "OmitHost": false,
+            "ForceQuery": false,
+            "RawQuery": url.query().unwrap_or_default(),
+            "Fragment": url.fragment().unwrap_or_default(),
+            "RawFragment": "",
+        }))
+    }
+
+    fn ip_to_string(ip: &[u8]) -> Result<String> {
+        let ip = match ip.len() {
+            4 => IpAddr::from(<[u8; 4]>::try_from(ip).expect("4 bytes")),
+            16 => IpAddr::from(<[u8; 16]>::try_from(ip).expect("16 bytes")),
+            _ => return Err(builtin_error("invalid IP address")),
+        };
+        Ok(ip.to_string())
+    }
+
+    fn timestamp_to_rfc3339(timestamp: i64) -> Result<String> {
+        DateTime::from_timestamp(timestamp, 0)
+            .map(|date| date.to_rfc3339_opts(SecondsFormat::Secs, true))
+            .ok_or_else(|| builtin_error(&format!("invalid validity timestamp: {timestamp}")))
+    }
+
+    fn oid_to_value(oid: &Oid) -> Value {
+        json!(oid.iter().map(|arcs| arcs.collect::<Vec<u64>>()))
+    }
+
+    fn base64(data: &[u8]) -> String {
+        general_purpose::STANDARD.encode(data)
+    }
+
+    /// Go encodes the empty slices of the certificate as `null`
+    fn or_null<T: Into<Value>>(values: Vec<T>) -> Value {
+        if values.is_empty() {
+            Value::Null
+        } else {
+            Value::Array(values.into_iter().map(Into::into).collect())
+        }
+    }
+
+    fn builtin_error(message: &str) -> BurregoError {
+        BurregoError::BuiltinError {
+            name: NAME.to_string(),
+            message: message.to_string(),
+        }
+    }
+
+    #[cfg(test)]
+    mod test {
+        use super::*;
+        use serde_json::json;
+
+        const CERTIFICATE: &str = include_str!("../../test_data/certificate.pem");
+
+        fn parse(input: &str) -> Value {
+            let certificates = parse_certificates(&[json!(input)]).unwrap();
+            let certificates = certificates.as_array().unwrap();
+            assert_eq!(certificates.len(), 1);
+            certificates[0].clone()
+        }
+
+        #[test]
+        fn parse_pem() {
+            let certificate = parse(CERTIFICATE);
+
+            assert_eq!(certificate["Version"], json!(3));
+            assert_eq!(certificate["SerialNumber"], json!(4660));
+            assert_eq!(certificate["SignatureAlgorithm"], json!(10));
+            assert_eq!(certificate["PublicKeyAlgorithm"], json!(3));
+            assert_eq!(certificate["NotBefore"], json!("2024-01-01T00:00:00Z"));
+            assert_eq!(certificate["NotAfter"], json!("2034-01-01T00:00:00Z"));
+            assert_eq!(certificate["Subject"]["CommonName"], json!("example.com"));
+            assert_eq!(certificate["Subject"]["Country"], json!(["DE"]));
+            assert_eq!(
+                certificate["Subject"]["Organization"],
+                json!(["Kubewarden"])
+            );
+            assert_eq!(
+                certificate["Subject"]["OrganizationalUnit"],
+                json!(["Testing"])
+            );
+            assert_eq!(certificate["Subject"]["Locality"], Value::Null);
+            assert_eq!(
+                certificate["Subject"]["Names"][0],
+                json!({"Type": [2, 5, 4, 6], "Value": "DE"})
+            );
+            assert_eq!(certificate["Issuer"], certificate["Subject"]);
+            // digitalSignature and keyEncipherment
+            assert_eq!(certificate["KeyUsage"], json!(5));
+            assert_eq!(certificate["ExtKeyUsage"], json!([1, 2]));
+            assert_eq!(certificate["UnknownExtKeyUsage"], Value::Null);
+            assert_eq!(certificate["BasicConstraintsValid"], json!(true));
+            assert_eq!(certificate["IsCA"], json!(false));
+            assert_eq!(certificate["MaxPathLen"], json!(-1));
+            assert_eq!(certificate["MaxPathLenZero"], json!(false));
+            assert_eq!(
+                certificate["SubjectKeyId"],
+                json!("1gaKbwvIyNuRWJixBZPJzM7NarQ=")
+            );
+            assert_eq!(certificate["AuthorityKeyId"], Value::Null);
+            assert_eq!(
+                certificate["DNSNames"],
+                json!(["example.com", "www.example.com"])
+            );
+            assert_eq!(certificate["EmailAddresses"], json!(["admin@example.com"]));
+            assert_eq!(certificate["IPAddresses"], json!(["10.0.0.1"]));
+            assert_eq!(certificate["URIs"][0]["Scheme"], json!("spiffe"));
+            assert_eq!(certificate["URIs"][0]["Host"], json!("example.com"));
+            assert_eq!(
+                certificate["URIs"][0]["Path"],
+                json!("/ns/default/sa/default")
+            );
+            assert_eq!(certificate["Extensions"].as_array().unwrap().len(), 5);
+            assert_eq!(certificate["Extensions"][0]["Id"], json!([2, 5, 29, 19]));
+            assert_eq!(certificate["Extensions"][0]["Critical"], json!(true));
+        }
+
+        #[test]
+        fn parse_base64_encoded_pem_and_der() {
+            let from_pem = parse(CERTIFICATE);
+
+            let encoded_pem = general_purpose::STANDARD.encode(CERTIFICATE);
+            assert_eq!(parse(&encoded_pem), from_pem);
+
+            let der = general_purpose::STANDARD
+                .decode(from_pem["Raw"].as_str().unwrap())
+                .unwrap();
+            let encoded_der = general_purpose::STANDARD.encode(der);
+            assert_eq!(parse(&encoded_der), from_pem);
+        }
+
+        #[test]
+        fn parse_many_certificates() {
+            let bundle = format!("{CERTIFICATE}{CERTIFICATE}");
+
+            let certificates = parse_certificates(&[json!(bundle)]).unwrap();
+
+            assert_eq!(certificates.as_array().unwrap().len(), 2);
+        }
+
+        #[test]
+        fn parse_invalid_certificates() {
+            assert!(parse_certificates(&[json!("not base64!")]).is_err());
+            assert!(parse_certificates(&[json!("aGVsbG8=")]).is_err());
+            assert!(parse_certificates(&[json!(42)]).is_err());
+            assert!(parse_certificates(&[]).is_err());
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn digests() {
+        let args = vec![json!("hello")];
+
+        assert_eq!(
+            md5(&args).unwrap(),
+            json!("5d41402abc4b2a76b9719d911017c592")
+        );
+        assert_eq!(
+            sha1(&args).unwrap(),
+            json!("aaf4c61ddcc5e8a2dabede0f3b482cd9aea9434d")
+        );
+        assert_eq!(
+            sha256(&args).unwrap(),
+            json!("2cf24dba5fb0a30e26e83b2ac5b9e29e1b161e5c1fa7425e73043362938b9824")
+        );
+    }
+
+    #[test]
+    fn digest_of_a_non_string() {
+        assert!(sha256(&[json!(42)]).is_err());
+        assert!(sha256(&[]).is_err());
+    }
+}
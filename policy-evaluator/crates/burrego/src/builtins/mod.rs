@@ -2,10 +2,12 @@ use crate::errors::Result;
 use std::collections::HashMap;
 
 pub(crate) mod builtins_helper;
+mod crypto;
 mod debugging;
 mod encoding;
 mod glob;
 mod json;
+mod net;
 mod regex;
 mod semver;
 mod strings;
@@ -19,6 +21,15 @@ pub(crate) type BuiltinFunctionsMap =
 pub fn get_builtins() -> BuiltinFunctionsMap {
     let mut functions: BuiltinFunctionsMap = HashMap::new();
 
+    // crypto
+    functions.insert("crypto.md5", crypto::md5);
+    functions.insert("crypto.sha1", crypto::sha1);
+    functions.insert("crypto.sha256", crypto::sha256);
+    functions.insert(
+        "crypto.x509.parse_certificates",
+        crypto::x509::parse_certificates,
+    );
+
     // debugging
     functions.insert("trace", debugging::trace);
 
@@ -41,6 +52,12 @@ pub fn get_builtins() -> BuiltinFunctionsMap {
     // glob
     functions.insert("glob.quote_meta", glob::quote_meta);
 
+    // net
+    functions.insert("net.cidr_contains", net::cidr_contains);
+    functions.insert("net.cidr_intersects", net::cidr_intersects);
+    functions.insert("net.cidr_expand", net::cidr_expand);
+    functions.insert("net.cidr_is_valid", net::cidr_is_valid);
+
     // objects
     functions.insert("json.patch", json::patch);
 
@@ -48,6 +65,11 @@ pub fn get_builtins() -> BuiltinFunctionsMap {
     functions.insert("regex.split", regex::split);
     functions.insert("regex.template_match", regex::template_match);
     functions.insert("regex.find_n", regex::find_n);
+    functions.insert(
+        "regex.find_all_string_submatch_n",
+        regex::find_all_string_submatch_n,
+    );
+    functions.insert("regex.globs_match", regex::globs_match);
 
     // semver
     functions.insert("semver.is_valid", semver::is_valid);
@@ -58,8 +80,10 @@ pub fn get_builtins() -> BuiltinFunctionsMap {
 
     // time
     functions.insert("time.now_ns", time::now_ns);
-    functions.insert("parse_rfc3339_ns", time::parse_rfc3339_ns);
-    functions.insert("date", time::date);
+    functions.insert("time.parse_rfc3339_ns", time::parse_rfc3339_ns);
+    functions.insert("time.date", time::date);
+    functions.insert("time.clock", time::clock);
+    functions.insert("time.weekday", time::weekday);
 
     functions
 }
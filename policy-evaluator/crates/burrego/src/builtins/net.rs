@@ -0,0 +1,362 @@
+use crate::errors::{BurregoError, Result};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+
+/// The maximum number of addresses `net.cidr_expand` can produce. Expanding
+/// large networks, like a `/8` IPv4 one, would exhaust the memory of the host
+const MAX_EXPANDED_ADDRESSES: u128 = 1 << 16;
+
+/// A network, defined using the CIDR notation. The address is always stored
+/// using 128 bits, IPv4 networks use only the lowest 32 ones
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Cidr {
+    ipv6: bool,
+    network: u128,
+    prefix_len: u32,
+}
+
+impl Cidr {
+    /// Parse a CIDR like `10.0.0.0/8` or `fd00::/8`. The bits of the address
+    /// that are not part of the prefix are ignored, like Go's `net.ParseCIDR` does
+    fn parse(cidr: &str) -> Option<Cidr> {
+        let (address, prefix_len) = cidr.split_once('/')?;
+        if prefix_len.is_empty() || !prefix_len.chars().all(|c| c.is_ascii_digit()) {
+            return None;
+        }
+        let prefix_len: u32 = prefix_len.parse().ok()?;
+
+        let host = Cidr::from_ip(address.parse().ok()?);
+        if prefix_len > host.bits() {
+            return None;
+        }
+
+        Some(Cidr {
+            network: host.network & host.mask(prefix_len),
+            prefix_len,
+            ..host
+        })
+    }
+
+    /// Parse either a CIDR or a single IP address, which is handled as a network
+    /// made only of that address
+    fn parse_cidr_or_ip(value: &str) -> Option<Cidr> {
+        if value.contains('/') {
+            Cidr::parse(value)
+        } else {
+            value.parse().ok().map(Cidr::from_ip)
+        }
+    }
+
+    fn from_ip(ip: IpAddr) -> Cidr {
+        match ip {
+            IpAddr::V4(ip) => Cidr {
+                ipv6: false,
+                network: u32::from(ip) as u128,
+                prefix_len: 32,
+            },
+            IpAddr::V6(ip) => Cidr {
+                ipv6: true,
+                network: u128::from(ip),
+                prefix_len: 128,
+            },
+        }
+    }
+
+    fn bits(&self) -> u32 {
+        if self.ipv6 {
+            128
+        } else {
+            32
+        }
+    }
+
+    /// The mask selecting the first `prefix_len` bits of an address
+    fn mask(&self, prefix_len: u32) -> u128 {
+        let host_bits = self.bits() - prefix_len;
+        let all_ones = if self.ipv6 {
+            u128::MAX
+        } else {
+            u32::MAX as u128
+        };
+        all_ones.checked_shl(host_bits).unwrap_or(0) & all_ones
+    }
+
+    fn contains(&self, other: &Cidr) -> bool {
+        self.ipv6 == other.ipv6
+            && other.prefix_len >= self.prefix_len
+            && other.network & self.mask(self.prefix_len) == self.network
+    }
+
+    fn intersects(&self, other: &Cidr) -> bool {
+        // two networks intersect only when one of them contains the other one
+        self.contains(other) || other.contains(self)
+    }
+
+    /// The number of addresses inside of the network
+    fn size(&self) -> Option<u128> {
+        1u128.checked_shl(self.bits() - self.prefix_len)
+    }
+
+    fn format_address(&self, address: u128) -> String {
+        if self.ipv6 {
+            Ipv6Addr::from(address).to_string()
+        } else {
+            Ipv4Addr::from(address as u32).to_string()
+        }
+    }
+}
+
+fn string_arg<'a>(name: &str, args: &'a [serde_json::Value], index: usize) -> Result<&'a str> {
+    args[index]
+        .as_str()
+        .ok_or_else(|| BurregoError::BuiltinError {
+            name: name.to_string(),
+            message: format!(
+                "{} parameter is not a string",
+                if index == 0 { "1st" } else { "2nd" }
+            ),
+        })
+}
+
+fn parse_cidr(name: &str, cidr: &str) -> Result<Cidr> {
+    Cidr::parse(cidr).ok_or_else(|| BurregoError::BuiltinError {
+        name: name.to_string(),
+        message: format!("invalid CIDR: {cidr}"),
+    })
+}
+
+pub fn cidr_contains(args: &[serde_json::Value]) -> Result<serde_json::Value> {
+    if args.len() != 2 {
+        return Err(BurregoError::BuiltinError {
+            name: "net.cidr_contains".to_string(),
+            message: "wrong number of arguments".to_string(),
+        });
+    }
+
+    let cidr = parse_cidr(
+        "net.cidr_contains",
+        string_arg("net.cidr_contains", args, 0)?,
+    )?;
+    let cidr_or_ip = string_arg("net.cidr_contains", args, 1)?;
+    let cidr_or_ip =
+        Cidr::parse_cidr_or_ip(cidr_or_ip).ok_or_else(|| BurregoError::BuiltinError {
+            name: "net.cidr_contains".to_string(),
+            message: format!("invalid CIDR or IP address: {cidr_or_ip}"),
+        })?;
+
+    Ok(serde_json::Value::Bool(cidr.contains(&cidr_or_ip)))
+}
+
+pub fn cidr_intersects(args: &[serde_json::Value]) -> Result<serde_json::Value> {
+    if args.len() != 2 {
+        return Err(BurregoError::BuiltinError {
+            name: "net.cidr_intersects".to_string(),
+            message: "wrong number of arguments".to_string(),
+        });
+    }
+
+    let cidr_a = parse_cidr(
+        "net.cidr_intersects",
+        string_arg("net.cidr_intersects", args, 0)?,
+    )?;
+    let cidr_b = parse_cidr(
+        "net.cidr_intersects",
+        string_arg("net.cidr_intersects", args, 1)?,
+    )?;
+
+    Ok(serde_json::Value::Bool(cidr_a.intersects(&cidr_b)))
+}
+
+pub fn cidr_expand(args: &[serde_json::Value]) -> Result<serde_json::Value> {
+    if args.len() != 1 {
+        return Err(BurregoError::BuiltinError {
+            name: "net.cidr_expand".to_string(),
+            message: "wrong number of arguments".to_string(),
+        });
+    }
+
+    let cidr = parse_cidr("net.cidr_expand", string_arg("net.cidr_expand", args, 0)?)?;
+    let size = cidr
+        .size()
+        .filter(|size| *size <= MAX_EXPANDED_ADDRESSES)
+        .ok_or_else(|| BurregoError::BuiltinError {
+            name: "net.cidr_expand".to_string(),
+            message: format!(
+                "the network is too large, at most {MAX_EXPANDED_ADDRESSES} addresses can be expanded"
+            ),
+        })?;
+
+    // the addresses are sorted, like the elements of a Rego set
+    let addresses: Vec<serde_json::Value> = (0..size)
+        .map(|offset| serde_json::Value::String(cidr.format_address(cidr.network + offset)))
+        .collect();
+
+    Ok(serde_json::Value::Array(addresses))
+}
+
+pub fn cidr_is_valid(args: &[serde_json::Value]) -> Result<serde_json::Value> {
+    if args.len() != 1 {
+        return Err(BurregoError::BuiltinError {
+            name: "net.cidr_is_valid".to_string(),
+            message: "wrong number of arguments".to_string(),
+        });
+    }
+
+    let cidr = string_arg("net.cidr_is_valid", args, 0)?;
+
+    Ok(serde_json::Value::Bool(Cidr::parse(cidr).is_some()))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use serde_json::json;
+
+    #[test]
+    fn parse_cidr() {
+        assert_eq!(
+            Cidr::parse("192.168.1.10/24"),
+            Some(Cidr {
+                ipv6: false,
+                network: u32::from(Ipv4Addr::new(192, 168, 1, 0)) as u128,
+                prefix_len: 24,
+            })
+        );
+        assert_eq!(Cidr::parse("0.0.0.0/0").map(|cidr| cidr.network), Some(0));
+        assert_eq!(
+            Cidr::parse("fd00::1/8"),
+            Some(Cidr {
+                ipv6: true,
+                network: u128::from("fd00::".parse::<Ipv6Addr>().unwrap()),
+                prefix_len: 8,
+            })
+        );
+
+        for invalid in [
+            "192.168.1.10",
+            "192.168.1.10/33",
+            "192.168.1.10/",
+            "192.168.1.10/+8",
+            "192.168.1/24",
+            "fd00::/129",
+            "not a cidr/8",
+        ] {
+            assert_eq!(Cidr::parse(invalid), None, "{invalid}");
+        }
+    }
+
+    #[test]
+    fn cidr_contains() -> Result<()> {
+        assert_eq!(
+            super::cidr_contains(&[json!("10.0.0.0/8"), json!("10.1.2.3")])?,
+            true
+        );
+        assert_eq!(
+            super::cidr_contains(&[json!("10.0.0.0/8"), json!("10.1.0.0/16")])?,
+            true
+        );
+        assert_eq!(
+            super::cidr_contains(&[json!("10.0.0.0/8"), json!("10.0.0.0/8")])?,
+            true
+        );
+        assert_eq!(
+            super::cidr_contains(&[json!("10.0.0.0/16"), json!("10.0.0.0/8")])?,
+            false
+        );
+        assert_eq!(
+            super::cidr_contains(&[json!("10.0.0.0/8"), json!("11.0.0.1")])?,
+            false
+        );
+        assert_eq!(
+            super::cidr_contains(&[json!("0.0.0.0/0"), json!("203.0.113.7")])?,
+            true
+        );
+        assert_eq!(
+            super::cidr_contains(&[json!("2001:db8::/32"), json!("2001:db8:1::1")])?,
+            true
+        );
+        assert_eq!(
+            super::cidr_contains(&[json!("2001:db8::/32"), json!("2001:db9::/48")])?,
+            false
+        );
+        // different families never match
+        assert_eq!(
+            super::cidr_contains(&[json!("0.0.0.0/0"), json!("::1")])?,
+            false
+        );
+
+        assert!(super::cidr_contains(&[json!("10.0.0.1"), json!("10.0.0.1")]).is_err());
+        assert!(super::cidr_contains(&[json!("10.0.0.0/8"), json!("10.0.0")]).is_err());
+        assert!(super::cidr_contains(&[json!("10.0.0.0/8"), json!(1)]).is_err());
+        assert!(super::cidr_contains(&[json!("10.0.0.0/8")]).is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn cidr_intersects() -> Result<()> {
+        assert_eq!(
+            super::cidr_intersects(&[json!("192.168.0.0/16"), json!("192.168.1.0/24")])?,
+            true
+        );
+        assert_eq!(
+            super::cidr_intersects(&[json!("192.168.1.0/24"), json!("192.168.0.0/16")])?,
+            true
+        );
+        assert_eq!(
+            super::cidr_intersects(&[json!("192.168.1.0/24"), json!("192.168.2.0/24")])?,
+            false
+        );
+        assert_eq!(
+            super::cidr_intersects(&[json!("fd00::/8"), json!("fd12:3456::/32")])?,
+            true
+        );
+        assert_eq!(
+            super::cidr_intersects(&[json!("fd00::/8"), json!("10.0.0.0/8")])?,
+            false
+        );
+
+        assert!(super::cidr_intersects(&[json!("192.168.0.0/16"), json!("192.168.1.1")]).is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn cidr_expand() -> Result<()> {
+        assert_eq!(
+            super::cidr_expand(&[json!("192.168.0.1/30")])?,
+            json!(["192.168.0.0", "192.168.0.1", "192.168.0.2", "192.168.0.3"])
+        );
+        assert_eq!(
+            super::cidr_expand(&[json!("10.0.0.7/32")])?,
+            json!(["10.0.0.7"])
+        );
+        assert_eq!(
+            super::cidr_expand(&[json!("2001:db8::/127")])?,
+            json!(["2001:db8::", "2001:db8::1"])
+        );
+        assert_eq!(
+            super::cidr_expand(&[json!("10.0.0.0/16")])?
+                .as_array()
+                .map(Vec::len),
+            Some(65536)
+        );
+
+        assert!(super::cidr_expand(&[json!("10.0.0.0/8")]).is_err());
+        assert!(super::cidr_expand(&[json!("::/0")]).is_err());
+        assert!(super::cidr_expand(&[json!("10.0.0.1")]).is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn cidr_is_valid() -> Result<()> {
+        assert_eq!(super::cidr_is_valid(&[json!("10.0.0.0/8")])?, true);
+        assert_eq!(super::cidr_is_valid(&[json!("fd00::/8")])?, true);
+        assert_eq!(super::cidr_is_valid(&[json!("10.0.0.1")])?, false);
+        assert_eq!(super::cidr_is_valid(&[json!("10.0.0.0/33")])?, false);
+        assert!(super::cidr_is_valid(&[json!(false)]).is_err());
+
+        Ok(())
+    }
+}
@@ -1,7 +1,42 @@
 use crate::errors::{BurregoError, Result};
 use core::fmt::Display;
-use regex::{escape as regex_escape, Regex};
-use std::{fmt, str::FromStr};
+use regex::{escape as regex_escape, Regex, RegexBuilder};
+use std::{
+    collections::{HashSet, VecDeque},
+    fmt,
+};
+
+/// Maximum size of a compiled regular expression. The regex crate guarantees linear
+/// time matching, this limit protects against patterns that would require a huge
+/// amount of memory to be compiled.
+const REGEX_SIZE_LIMIT: usize = 1 << 20;
+/// Maximum size of the cache used by the lazy DFA of each regular expression
+const REGEX_DFA_SIZE_LIMIT: usize = 1 << 20;
+
+/// Build a regular expression, enforcing the size limits
+fn new_regex(builtin: &str, pattern: &str) -> Result<Regex> {
+    RegexBuilder::new(pattern)
+        .size_limit(REGEX_SIZE_LIMIT)
+        .dfa_size_limit(REGEX_DFA_SIZE_LIMIT)
+        .build()
+        .map_err(|e| BurregoError::BuiltinError {
+            name: builtin.to_string(),
+            message: format!("cannot build regex from the given pattern string '{pattern}': {e:?}"),
+        })
+}
+
+/// Convert the `number` parameter of the `*_n` builtins into the maximum number of
+/// matches to be returned, `-1` means all of them
+fn matches_limit(builtin: &str, number: &serde_json::Value) -> Result<usize> {
+    match number.as_i64() {
+        Some(-1) => Ok(usize::MAX),
+        Some(n) if n >= 0 => Ok(n as usize),
+        _ => Err(BurregoError::BuiltinError {
+            name: builtin.to_string(),
+            message: "3rd parameter is not a number greater or equal to -1".to_string(),
+        }),
+    }
+}
 
 pub fn split(args: &[serde_json::Value]) -> Result<serde_json::Value> {
     if args.len() != 2 {
@@ -20,18 +55,11 @@ pub fn split(args: &[serde_json::Value]) -> Result<serde_json::Value> {
         message: "2nd parameter is not a string".to_string(),
     })?;
 
-    serde_json::to_value(
-        Regex::new(pattern_str)
-            .map_err(|e| BurregoError::BuiltinError {
-                name: "regex.split".to_string(),
-                message: format!(
-                    "cannot build regex from the given pattern string '{pattern_str}': {e:?}"
-                ),
-            })?
-            .split(string_str)
-            .collect::<String>(),
-    )
-    .map_err(|e| BurregoError::BuiltinError {
+    let parts: Vec<&str> = new_regex("regex.split", pattern_str)?
+        .split(string_str)
+        .collect();
+
+    serde_json::to_value(parts).map_err(|e| BurregoError::BuiltinError {
         name: "regex.split".to_string(),
         message: format!("cannot convert result into JSON: {e:?}"),
     })
@@ -102,32 +130,9 @@ pub fn find_n(args: &[serde_json::Value]) -> Result<serde_json::Value> {
         name: "regex.find_n".to_string(),
         message: "2nd parameter is not a string".to_string(),
     })?;
-    let take_number = args[2].as_i64().ok_or_else(|| BurregoError::BuiltinError {
-        name: "regex.find_n".to_string(),
-        message: "3rd parameter is not a number".to_string(),
-    })?;
-
-    let take_n = if take_number != -1 {
-        take_number as usize
-    } else {
-        Regex::new(pattern_str)
-            .map_err(|e| BurregoError::BuiltinError {
-                name: "regex.find_n".to_string(),
-                message: format!(
-                    "cannot build regex from the given pattern string '{pattern_str}': {e:?}"
-                ),
-            })?
-            .find_iter(string_str)
-            .count()
-    };
+    let take_n = matches_limit("regex.find_n", &args[2])?;
 
-    let matches: Vec<String> = Regex::new(pattern_str)
-        .map_err(|e| BurregoError::BuiltinError {
-            name: "regex.find_n".to_string(),
-            message: format!(
-                "cannot build regex from the given pattern string '{pattern_str}': {e:?}"
-            ),
-        })?
+    let matches: Vec<String> = new_regex("regex.find_n", pattern_str)?
         .find_iter(string_str)
         .take(take_n)
         .map(|match_| String::from(match_.as_str()))
@@ -139,6 +144,298 @@ pub fn find_n(args: &[serde_json::Value]) -> Result<serde_json::Value> {
     })
 }
 
+pub fn find_all_string_submatch_n(args: &[serde_json::Value]) -> Result<serde_json::Value> {
+    if args.len() != 3 {
+        return Err(BurregoError::BuiltinError {
+            name: "regex.find_all_string_submatch_n".to_string(),
+            message: "Wrong number of arguments given".to_string(),
+        });
+    }
+    let pattern_str = args[0].as_str().ok_or_else(|| BurregoError::BuiltinError {
+        name: "regex.find_all_string_submatch_n".to_string(),
+        message: "1st parameter is not a string".to_string(),
+    })?;
+    let string_str = args[1].as_str().ok_or_else(|| BurregoError::BuiltinError {
+        name: "regex.find_all_string_submatch_n".to_string(),
+        message: "2nd parameter is not a string".to_string(),
+    })?;
+    let take_n = matches_limit("regex.find_all_string_submatch_n", &args[2])?;
+
+    // like Go, the groups that did not participate in the match are reported as empty strings
+    let matches: Vec<Vec<&str>> = new_regex("regex.find_all_string_submatch_n", pattern_str)?
+        .captures_iter(string_str)
+        .take(take_n)
+        .map(|captures| {
+            captures
+                .iter()
+                .map(|group| group.map(|group| group.as_str()).unwrap_or_default())
+                .collect()
+        })
+        .collect();
+
+    serde_json::to_value(matches).map_err(|e| BurregoError::BuiltinError {
+        name: "regex.find_all_string_submatch_n".to_string(),
+        message: format!("cannot convert value into JSON: {e:?}"),
+    })
+}
+
+pub fn globs_match(args: &[serde_json::Value]) -> Result<serde_json::Value> {
+    if args.len() != 2 {
+        return Err(BurregoError::BuiltinError {
+            name: "regex.globs_match".to_string(),
+            message: "Wrong number of arguments given".to_string(),
+        });
+    }
+    let glob1 = args[0].as_str().ok_or_else(|| BurregoError::BuiltinError {
+        name: "regex.globs_match".to_string(),
+        message: "1st parameter is not a string".to_string(),
+    })?;
+    let glob2 = args[1].as_str().ok_or_else(|| BurregoError::BuiltinError {
+        name: "regex.globs_match".to_string(),
+        message: "2nd parameter is not a string".to_string(),
+    })?;
+
+    let glob1 = GlobPattern::parse(glob1)?;
+    let glob2 = GlobPattern::parse(glob2)?;
+
+    serde_json::to_value(glob1.intersects(&glob2)).map_err(|e| BurregoError::BuiltinError {
+        name: "regex.globs_match".to_string(),
+        message: format!("cannot convert value into JSON: {e:?}"),
+    })
+}
+
+/// A set of characters, stored as a sorted list of non overlapping ranges
+#[derive(Debug, Clone, PartialEq)]
+struct CharSet(Vec<(char, char)>);
+
+impl CharSet {
+    fn any() -> Self {
+        CharSet(vec![('\0', char::MAX)])
+    }
+
+    fn single(c: char) -> Self {
+        CharSet(vec![(c, c)])
+    }
+
+    fn from_ranges(mut ranges: Vec<(char, char)>) -> Self {
+        ranges.sort();
+        let mut merged: Vec<(char, char)> = Vec::with_capacity(ranges.len());
+        for (start, end) in ranges {
+            match merged.last_mut() {
+                Some(last) if start as u32 <= last.1 as u32 + 1 => {
+                    last.1 = last.1.max(end);
+                }
+                _ => merged.push((start, end)),
+            }
+        }
+        CharSet(merged)
+    }
+
+    fn complement(&self) -> Self {
+        let mut ranges = Vec::new();
+        let mut next: u32 = 0;
+        for (start, end) in &self.0 {
+            if (*start as u32) > next {
+                ranges.extend(char_range(next, *start as u32 - 1));
+            }
+            next = *end as u32 + 1;
+        }
+        if next <= char::MAX as u32 {
+            ranges.extend(char_range(next, char::MAX as u32));
+        }
+        CharSet(ranges)
+    }
+
+    fn intersects(&self, other: &CharSet) -> bool {
+        let (mut i, mut j) = (0, 0);
+        while i < self.0.len() && j < other.0.len() {
+            let (a, b) = (self.0[i], other.0[j]);
+            if a.0 <= b.1 && b.0 <= a.1 {
+                return true;
+            }
+            if a.1 < b.1 {
+                i += 1;
+            } else {
+                j += 1;
+            }
+        }
+        false
+    }
+}
+
+/// Build a range of characters from the given code points, skipping the surrogates
+/// that are not valid characters
+fn char_range(start: u32, end: u32) -> Vec<(char, char)> {
+    const SURROGATES: (u32, u32) = (0xD800, 0xDFFF);
+    let mut ranges = Vec::new();
+    let mut push = |start: u32, end: u32| {
+        if let (Some(start), Some(end)) = (char::from_u32(start), char::from_u32(end)) {
+            if start <= end {
+                ranges.push((start, end));
+            }
+        }
+    };
+    if end < SURROGATES.0 || start > SURROGATES.1 {
+        push(start, end);
+    } else {
+        if start < SURROGATES.0 {
+            push(start, SURROGATES.0 - 1);
+        }
+        if end > SURROGATES.1 {
+            push(SURROGATES.1 + 1, end);
+        }
+    }
+    ranges
+}
+
+/// An element of a glob pattern: a set of characters, that can be repeated
+#[derive(Debug, Clone, PartialEq)]
+struct GlobToken {
+    chars: CharSet,
+    /// When true the token matches zero or more characters (`*`), otherwise
+    /// it matches exactly one character
+    repeated: bool,
+}
+
+/// The glob-style regular expressions accepted by `regex.globs_match`. Like OPA, only
+/// `.`, `*`, `+`, `[`, `-`, `]` and `\` are treated as special symbols.
+#[derive(Debug, PartialEq)]
+struct GlobPattern(Vec<GlobToken>);
+
+impl GlobPattern {
+    fn parse(pattern: &str) -> Result<Self> {
+        let error = |message: String| BurregoError::BuiltinError {
+            name: "regex.globs_match".to_string(),
+            message: format!("invalid glob pattern '{pattern}': {message}"),
+        };
+
+        let mut tokens: Vec<GlobToken> = Vec::new();
+        let mut chars = pattern.chars();
+        while let Some(c) = chars.next() {
+            let (chars_set, quantifier) = match c {
+                '.' => (CharSet::any(), false),
+                '\\' => {
+                    let escaped = chars
+                        .next()
+                        .ok_or_else(|| error("trailing escape character".to_string()))?;
+                    (CharSet::single(escaped), false)
+                }
+                '[' => (Self::parse_class(&mut chars).map_err(error)?, false),
+                '*' | '+' => (CharSet(Vec::new()), true),
+                ']' => return Err(error("unexpected ']'".to_string())),
+                c => (CharSet::single(c), false),
+            };
+
+            if !quantifier {
+                tokens.push(GlobToken {
+                    chars: chars_set,
+                    repeated: false,
+                });
+                continue;
+            }
+
+            // `x*` is a repeated token, `x+` is the same as `xx*`
+            let previous = tokens
+                .last_mut()
+                .filter(|token| !token.repeated)
+                .ok_or_else(|| error(format!("'{c}' must follow a character")))?;
+            if c == '*' {
+                previous.repeated = true;
+            } else {
+                let repeated = GlobToken {
+                    chars: previous.chars.clone(),
+                    repeated: true,
+                };
+                tokens.push(repeated);
+            }
+        }
+
+        Ok(GlobPattern(tokens))
+    }
+
+    /// Parse a character class, the opening `[` has already been consumed
+    fn parse_class(chars: &mut std::str::Chars) -> std::result::Result<CharSet, String> {
+        let mut negated = false;
+        let mut ranges = Vec::new();
+        let mut first = true;
+        loop {
+            let c = chars.next().ok_or_else(|| "unterminated '['".to_string())?;
+            let c = match c {
+                '^' if first => {
+                    negated = true;
+                    first = false;
+                    continue;
+                }
+                ']' if !ranges.is_empty() => break,
+                ']' => return Err("empty character class".to_string()),
+                '\\' => chars.next().ok_or_else(|| "unterminated '['".to_string())?,
+                c => c,
+            };
+            first = false;
+
+            let mut lookahead = chars.clone();
+            if lookahead.next() == Some('-') && !matches!(lookahead.clone().next(), Some(']')) {
+                chars.next();
+                let end = match chars.next() {
+                    Some('\\') => chars.next(),
+                    end => end,
+                }
+                .ok_or_else(|| "unterminated '['".to_string())?;
+                if end < c {
+                    return Err(format!("invalid range '{c}-{end}'"));
+                }
+                ranges.push((c, end));
+            } else {
+                ranges.push((c, c));
+            }
+        }
+
+        let set = CharSet::from_ranges(ranges);
+        Ok(if negated { set.complement() } else { set })
+    }
+
+    /// Check if there's a non-empty string matched by both patterns. The two patterns
+    /// are walked in lockstep, looking for a path that leads both of them to their end
+    /// after consuming at least one character.
+    fn intersects(&self, other: &GlobPattern) -> bool {
+        let (left, right) = (&self.0, &other.0);
+        let mut visited: HashSet<(usize, usize, bool)> = HashSet::new();
+        let mut queue: VecDeque<(usize, usize, bool)> = VecDeque::from([(0, 0, false)]);
+
+        while let Some(state) = queue.pop_front() {
+            if !visited.insert(state) {
+                continue;
+            }
+            let (i, j, consumed) = state;
+            if i == left.len() && j == right.len() {
+                if consumed {
+                    return true;
+                }
+                continue;
+            }
+
+            // repeated tokens can match zero characters
+            if left.get(i).is_some_and(|token| token.repeated) {
+                queue.push_back((i + 1, j, consumed));
+            }
+            if right.get(j).is_some_and(|token| token.repeated) {
+                queue.push_back((i, j + 1, consumed));
+            }
+
+            // consume a character matched by both tokens
+            if let (Some(l), Some(r)) = (left.get(i), right.get(j)) {
+                if l.chars.intersects(&r.chars) {
+                    let next_i = if l.repeated { i } else { i + 1 };
+                    let next_j = if r.repeated { j } else { j + 1 };
+                    queue.push_back((next_i, next_j, true));
+                }
+            }
+        }
+
+        false
+    }
+}
+
 struct Expression {
     is_regexp: bool,
     expression: String,
@@ -212,16 +509,14 @@ impl TemplateMatch {
             expressions.0.push(current_expression);
         }
 
-        Regex::from_str(&format!("{expressions}")).map_err(|e| BurregoError::BuiltinError {
-            name: "regex".to_string(),
-            message: format!("tried to initialize an invalid regular expression: {e:?}"),
-        })
+        new_regex("regex.template_match", &format!("{expressions}"))
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use serde_json::json;
 
     #[test]
     fn regex_from_template() -> Result<()> {
@@ -314,4 +609,108 @@ mod tests {
 
         Ok(())
     }
+
+    // the expected values are the ones produced by OPA
+    #[test]
+    fn split() -> Result<()> {
+        let cases = [
+            ("a", "banana", json!(["b", "n", "n", ""])),
+            ("[,;]\\s*", "a, b;c", json!(["a", "b", "c"])),
+            ("x", "banana", json!(["banana"])),
+        ];
+        for (pattern, value, expected) in cases {
+            assert_eq!(
+                super::split(&[json!(pattern), json!(value)])?,
+                expected,
+                "pattern: {pattern}"
+            );
+        }
+        Ok(())
+    }
+
+    // the expected values are the ones produced by OPA
+    #[test]
+    fn find_all_string_submatch_n() -> Result<()> {
+        let cases = [
+            (
+                "a(x*)b",
+                "-ab-axxb-",
+                -1,
+                json!([["ab", ""], ["axxb", "xx"]]),
+            ),
+            ("a(x*)b", "-ab-axxb-", 1, json!([["ab", ""]])),
+            (
+                "a(x*)b(y)?",
+                "-ab-axxby-",
+                -1,
+                json!([["ab", "", ""], ["axxby", "xx", "y"]]),
+            ),
+            ("a(x*)b", "-ab-axxb-", 0, json!([])),
+            ("nomatch", "-ab-axxb-", -1, json!([])),
+        ];
+        for (pattern, value, number, expected) in cases {
+            assert_eq!(
+                super::find_all_string_submatch_n(&[json!(pattern), json!(value), json!(number)])?,
+                expected,
+                "pattern: {pattern}, number: {number}"
+            );
+        }
+
+        // invalid number
+        assert!(super::find_all_string_submatch_n(&[json!("a"), json!("a"), json!(-2)]).is_err());
+        // invalid pattern
+        assert!(super::find_all_string_submatch_n(&[json!("a("), json!("a"), json!(-1)]).is_err());
+        // not a string
+        assert!(super::find_all_string_submatch_n(&[json!(1), json!("a"), json!(-1)]).is_err());
+
+        Ok(())
+    }
+
+    // the expected values are the ones produced by OPA
+    #[test]
+    fn globs_match() -> Result<()> {
+        let cases = [
+            ("a.a.[0-9]+z", ".b.b2359825792*z", true),
+            ("a.a.[0-9]+z", ".b.b2359825792*", false),
+            ("a*", "b*", false),
+            ("a+", "a*", true),
+            ("[a-c]x", "[^a]x", true),
+            ("[a]x", "[^a]x", false),
+            ("\\.", ".", true),
+            ("\\.", "a", false),
+            (".*", "foo", true),
+            ("foo", "bar", false),
+            ("fo+", "f[a-z]", true),
+            ("fo+", "f[a-z]x", false),
+        ];
+        for (glob1, glob2, expected) in cases {
+            assert_eq!(
+                super::globs_match(&[json!(glob1), json!(glob2)])?,
+                json!(expected),
+                "globs: {glob1} {glob2}"
+            );
+            // the intersection is symmetric
+            assert_eq!(
+                super::globs_match(&[json!(glob2), json!(glob1)])?,
+                json!(expected),
+                "globs: {glob2} {glob1}"
+            );
+        }
+
+        for invalid_glob in ["[a-", "[z-a]", "*a", "a**", "a\\"] {
+            assert!(
+                super::globs_match(&[json!(invalid_glob), json!("a")]).is_err(),
+                "glob: {invalid_glob}"
+            );
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn regex_size_limit() {
+        // the regex crate guarantees linear time matching, but huge patterns are rejected
+        let pattern = "(a{1000}){1000}";
+        assert!(super::find_n(&[json!(pattern), json!("aaaa"), json!(-1)]).is_err());
+    }
 }
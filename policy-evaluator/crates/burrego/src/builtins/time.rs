@@ -1,5 +1,5 @@
 use crate::errors::{BurregoError, Result};
-use chrono::{self, DateTime, Datelike, Duration, Local};
+use chrono::{DateTime, Datelike, Duration, FixedOffset, Local, Timelike, Weekday};
 use std::str::FromStr;
 
 pub fn now_ns(args: &[serde_json::Value]) -> Result<serde_json::Value> {
@@ -41,51 +41,74 @@ pub fn parse_rfc3339_ns(args: &[serde_json::Value]) -> Result<serde_json::Value>
 }
 
 pub fn date(args: &[serde_json::Value]) -> Result<serde_json::Value> {
+    let dt = datetime_arg("time.date", args)?;
+
+    Ok(serde_json::json!([dt.year(), dt.month(), dt.day()]))
+}
+
+pub fn clock(args: &[serde_json::Value]) -> Result<serde_json::Value> {
+    let dt = datetime_arg("time.clock", args)?;
+
+    Ok(serde_json::json!([dt.hour(), dt.minute(), dt.second()]))
+}
+
+pub fn weekday(args: &[serde_json::Value]) -> Result<serde_json::Value> {
+    let dt = datetime_arg("time.weekday", args)?;
+
+    let weekday = match dt.weekday() {
+        Weekday::Mon => "Monday",
+        Weekday::Tue => "Tuesday",
+        Weekday::Wed => "Wednesday",
+        Weekday::Thu => "Thursday",
+        Weekday::Fri => "Friday",
+        Weekday::Sat => "Saturday",
+        Weekday::Sun => "Sunday",
+    };
+    Ok(serde_json::json!(weekday))
+}
+
+/// Parse the argument of the `time.date`, `time.clock` and `time.weekday` builtins.
+/// That's either the number of nanoseconds since the epoch, or an array made of the
+/// nanoseconds and of the name of a timezone, like `Europe/Berlin`.
+///
+/// The UTC timezone is used when no timezone, or an empty one, is given. `Local` is
+/// the timezone of the host
+fn datetime_arg(name: &str, args: &[serde_json::Value]) -> Result<DateTime<FixedOffset>> {
     if args.len() != 1 {
         return Err(BurregoError::BuiltinError {
-            name: "time.date".to_string(),
+            name: name.to_string(),
             message: "wrong number of arguments given".to_string(),
         });
     }
 
-    let nanoseconds: i64;
-    let mut timezone: chrono_tz::Tz = chrono_tz::UTC;
-
-    match args[0].clone() {
+    let (nanoseconds, tz_name) = match &args[0] {
         serde_json::Value::Number(val) => {
-            nanoseconds = val.as_i64().ok_or_else(|| BurregoError::BuiltinError {
-                name: "time.date".to_string(),
+            let nanoseconds = val.as_i64().ok_or_else(|| BurregoError::BuiltinError {
+                name: name.to_string(),
                 message: "1st parameter is not a number".to_string(),
             })?;
+            (nanoseconds, "")
         }
         serde_json::Value::Array(val) => {
             if val.len() != 2 {
                 return Err(BurregoError::BuiltinError {
-                    name: "time.date".to_string(),
+                    name: name.to_string(),
                     message: "wrong number of items inside of input array".to_string(),
                 });
             }
-            nanoseconds = val[0].as_i64().ok_or_else(|| BurregoError::BuiltinError {
-                name: "time.date".to_string(),
+            let nanoseconds = val[0].as_i64().ok_or_else(|| BurregoError::BuiltinError {
+                name: name.to_string(),
                 message: "1st array item is not a number".to_string(),
             })?;
             let tz_name = val[1].as_str().ok_or_else(|| BurregoError::BuiltinError {
-                name: "time.date".to_string(),
+                name: name.to_string(),
                 message: "2nd array item is not a string".to_string(),
             })?;
-            if tz_name == "Local" {
-                return date_local(nanoseconds);
-            } else {
-                timezone =
-                    chrono_tz::Tz::from_str(tz_name).map_err(|e| BurregoError::BuiltinError {
-                        name: "time.date".to_string(),
-                        message: format!("cannot handle given timezone {tz_name}: {e:?}"),
-                    })?;
-            }
+            (nanoseconds, tz_name)
         }
         _ => {
             return Err(BurregoError::BuiltinError {
-                name: "time.date".to_string(),
+                name: name.to_string(),
                 message: "the 1st parameter is neither a number nor an array".to_string(),
             });
         }
@@ -94,25 +117,24 @@ pub fn date(args: &[serde_json::Value]) -> Result<serde_json::Value> {
     let dt = DateTime::UNIX_EPOCH
         .checked_add_signed(Duration::nanoseconds(nanoseconds))
         .ok_or_else(|| BurregoError::BuiltinError {
-            name: "time.date".to_string(),
+            name: name.to_string(),
             message: "overflow when building date".to_string(),
-        })?
-        .with_timezone(&timezone);
+        })?;
 
-    Ok(serde_json::json!([dt.year(), dt.month(), dt.day(),]))
+    match tz_name {
+        "" | "UTC" => Ok(dt.fixed_offset()),
+        "Local" => Ok(dt.with_timezone(&Local).fixed_offset()),
+        _ => {
+            let timezone =
+                chrono_tz::Tz::from_str(tz_name).map_err(|e| BurregoError::BuiltinError {
+                    name: name.to_string(),
+                    message: format!("cannot handle given timezone {tz_name}: {e:?}"),
+                })?;
+            Ok(dt.with_timezone(&timezone).fixed_offset())
+        }
+    }
 }
 
-pub fn date_local(ns: i64) -> Result<serde_json::Value> {
-    let dt = DateTime::UNIX_EPOCH
-        .checked_add_signed(Duration::nanoseconds(ns))
-        .ok_or_else(|| BurregoError::BuiltinError {
-            name: "time.date".to_string(),
-            message: "overflow when building date".to_string(),
-        })?
-        .with_timezone(&chrono::Local);
-
-    Ok(serde_json::json!([dt.year(), dt.month(), dt.day(),]))
-}
 #[cfg(test)]
 mod test {
     use super::*;
@@ -176,4 +198,95 @@ mod test {
             actual.unwrap()
         );
     }
+
+    #[test]
+    fn date_with_empty_tz() {
+        let input_dt = chrono::Utc
+            .with_ymd_and_hms(2024, 12, 31, 23, 30, 0)
+            .unwrap();
+
+        let args: Vec<serde_json::Value> = vec![json!([input_dt.timestamp_nanos_opt(), ""])];
+
+        assert_eq!(json!([2024, 12, 31]), date(&args).unwrap());
+    }
+
+    #[test]
+    fn date_crossing_day_with_tz() {
+        // 23:30 UTC is already the day after in Berlin
+        let input_dt = chrono::Utc
+            .with_ymd_and_hms(2024, 12, 31, 23, 30, 0)
+            .unwrap();
+
+        let args: Vec<serde_json::Value> =
+            vec![json!([input_dt.timestamp_nanos_opt(), "Europe/Berlin"])];
+
+        assert_eq!(json!([2025, 1, 1]), date(&args).unwrap());
+    }
+
+    #[test]
+    fn date_with_invalid_tz() {
+        let args: Vec<serde_json::Value> = vec![json!([0, "Mars/Olympus_Mons"])];
+
+        assert!(date(&args).is_err());
+    }
+
+    #[test]
+    fn clock_with_no_tz() {
+        let input_dt = chrono::Utc.with_ymd_and_hms(2024, 3, 10, 8, 5, 9).unwrap();
+
+        let args: Vec<serde_json::Value> = vec![json!(input_dt.timestamp_nanos_opt())];
+
+        assert_eq!(json!([8, 5, 9]), clock(&args).unwrap());
+    }
+
+    #[test]
+    fn clock_with_tz() {
+        // New York is on daylight saving time in July
+        let input_dt = chrono::Utc.with_ymd_and_hms(2024, 7, 1, 12, 0, 0).unwrap();
+
+        let args: Vec<serde_json::Value> =
+            vec![json!([input_dt.timestamp_nanos_opt(), "America/New_York"])];
+
+        assert_eq!(json!([8, 0, 0]), clock(&args).unwrap());
+    }
+
+    #[test]
+    fn clock_with_wrong_argument() {
+        let args: Vec<serde_json::Value> = vec![json!("2024-07-01T12:00:00Z")];
+
+        assert!(clock(&args).is_err());
+    }
+
+    #[test]
+    fn weekday_with_no_tz() {
+        let input_dt = chrono::Utc.with_ymd_and_hms(2024, 7, 1, 12, 0, 0).unwrap();
+
+        let args: Vec<serde_json::Value> = vec![json!(input_dt.timestamp_nanos_opt())];
+
+        assert_eq!(json!("Monday"), weekday(&args).unwrap());
+    }
+
+    #[test]
+    fn weekday_with_tz() {
+        // Monday 02:00 in UTC is still Sunday in Los Angeles
+        let input_dt = chrono::Utc.with_ymd_and_hms(2024, 7, 1, 2, 0, 0).unwrap();
+
+        let args: Vec<serde_json::Value> = vec![json!([
+            input_dt.timestamp_nanos_opt(),
+            "America/Los_Angeles"
+        ])];
+
+        assert_eq!(json!("Sunday"), weekday(&args).unwrap());
+    }
+
+    #[test]
+    fn weekday_before_epoch() {
+        let input_dt = chrono::Utc
+            .with_ymd_and_hms(1969, 7, 20, 20, 17, 0)
+            .unwrap();
+
+        let args: Vec<serde_json::Value> = vec![json!(input_dt.timestamp_nanos_opt())];
+
+        assert_eq!(json!("Sunday"), weekday(&args).unwrap());
+    }
 }
@@ -29,6 +29,14 @@ pub enum BurregoError {
     #[error("Builtin not implemented: {0}")]
     BuiltinNotImplementedError(String),
 
+    /// The guest raised a trap, the wasmtime error holds its backtrace
+    #[error("{msg}: {source:?}")]
+    WasmTrap {
+        msg: String,
+        #[source]
+        source: wasmtime::Error,
+    },
+
     /// Wasmtime execution deadline exceeded
     #[error("guest code interrupted, execution deadline exceeded")]
     ExecutionDeadlineExceeded,
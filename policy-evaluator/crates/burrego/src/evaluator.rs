@@ -19,6 +19,9 @@ macro_rules! set_epoch_deadline_and_call_guest {
     }};
 }
 
+/// Number of Wasm pages allocated to the memory of the guest at instantiation time
+pub(crate) const INITIAL_MEMORY_PAGES: u32 = 5;
+
 struct EvaluatorStack {
     store: Store<Option<StackHelper>>,
     instance: Instance,
@@ -38,6 +41,8 @@ pub struct Evaluator {
     /// interruption](https://docs.rs/wasmtime/latest/wasmtime/struct.Config.html#method.epoch_interruption)
     /// feature of wasmtime
     epoch_deadline: Option<u64>,
+    /// The maximum number of pages the memory of the guest can grow to
+    max_memory_pages: Option<u32>,
     entrypoints: HashMap<String, i32>,
     used_builtins: HashSet<String>,
 }
@@ -48,12 +53,14 @@ impl Evaluator {
         module: Module,
         host_callbacks: HostCallbacks,
         epoch_deadline: Option<u64>,
+        max_memory_pages: Option<u32>,
     ) -> Result<Evaluator> {
         let stack = Self::setup(
             engine.clone(),
             module.clone(),
             host_callbacks.clone(),
             epoch_deadline,
+            max_memory_pages,
         )?;
         let mut store = stack.store;
         let instance = stack.instance;
@@ -87,6 +94,7 @@ impl Evaluator {
             policy,
             host_callbacks,
             epoch_deadline,
+            max_memory_pages,
             entrypoints,
             used_builtins,
         };
@@ -106,13 +114,14 @@ impl Evaluator {
         module: Module,
         host_callbacks: HostCallbacks,
         epoch_deadline: Option<u64>,
+        max_memory_pages: Option<u32>,
     ) -> Result<EvaluatorStack> {
         let mut linker = Linker::<Option<StackHelper>>::new(&engine);
 
         let opa_data_helper: Option<StackHelper> = None;
         let mut store = Store::new(&engine, opa_data_helper);
 
-        let memory_ty = MemoryType::new(5, None);
+        let memory_ty = MemoryType::new(INITIAL_MEMORY_PAGES, max_memory_pages);
         let memory = Memory::new(&mut store, memory_ty)
             .map_err(|e| BurregoError::WasmEngineError(format!("cannot create memory: {e}")))?;
         linker
@@ -141,6 +150,7 @@ impl Evaluator {
             &mut store,
             host_callbacks.opa_abort,
             host_callbacks.opa_println,
+            host_callbacks.builtins,
         )?;
         let policy = Policy::new(&instance, &mut store, &memory)?;
         _ = store.data_mut().insert(stack_helper);
@@ -159,6 +169,7 @@ impl Evaluator {
             self.module.clone(),
             self.host_callbacks.clone(),
             self.epoch_deadline,
+            self.max_memory_pages,
         )?;
         self.store = stack.store;
         self.instance = stack.instance;
@@ -198,6 +209,7 @@ impl Evaluator {
         let supported_builtins: HashSet<String> = builtins::get_builtins()
             .keys()
             .map(|v| String::from(*v))
+            .chain(self.host_callbacks.builtins.keys().cloned())
             .collect();
         Ok(self
             .used_builtins
@@ -260,3 +272,48 @@ impl Evaluator {
         })
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::{EvaluatorBuilder, HostCallbacks};
+    use serde_json::json;
+    use std::path::Path;
+
+    #[test]
+    fn evaluate_with_opa_eval() {
+        // Built with `opa build -t wasm`, the module implements the OPA Wasm ABI 1.2.
+        // The policy is shared with the tests of policy-evaluator
+        let mut evaluator = EvaluatorBuilder::default()
+            .policy_path(Path::new(
+                "../../tests/data/gatekeeper_always_unhappy_policy.wasm",
+            ))
+            .host_callbacks(HostCallbacks::default())
+            .build()
+            .expect("cannot build evaluator");
+
+        assert_eq!(evaluator.opa_abi_version().unwrap(), (1, 2));
+        assert!(evaluator.policy.one_shot_evaluation());
+
+        let entrypoint_id = evaluator.entrypoint_id("policy/violation").unwrap();
+        let expected = json!([{"result": [{"msg": "failing as expected"}]}]);
+
+        // The input is written into the guest memory right after the data: evaluate
+        // inputs and data of different sizes, one after the other
+        let inputs = [
+            json!({"review": {}}),
+            json!({"review": {"object": {"metadata": {"name": "a".repeat(100_000)}}}}),
+            json!({}),
+        ];
+        let datas: [&[u8]; 3] = [
+            b"{}",
+            br#"{"namespaces": ["default", "kube-system"]}"#,
+            b"{}",
+        ];
+        for (input, data) in inputs.iter().zip(datas) {
+            let result = evaluator
+                .evaluate(entrypoint_id, input, data)
+                .expect("cannot evaluate policy");
+            assert_eq!(result, expected);
+        }
+    }
+}
@@ -1,8 +1,28 @@
 use crate::errors::{BurregoError, Result};
-use std::path::{Path, PathBuf};
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
 use wasmtime::{Engine, Module};
 
-use crate::{host_callbacks::HostCallbacks, Evaluator};
+use crate::{
+    builtins::get_builtins,
+    evaluator::INITIAL_MEMORY_PAGES,
+    host_callbacks::{HostBuiltin, HostCallbacks},
+    http::{self, HttpPolicy},
+    stack_helper::WASM_PAGE_SIZE,
+    Evaluator,
+};
+
+/// Convert a memory limit expressed in bytes to the number of Wasm pages,
+/// rounding up
+fn memory_limit_to_pages(bytes: u64) -> u32 {
+    bytes
+        .div_ceil(WASM_PAGE_SIZE)
+        .try_into()
+        .unwrap_or(u32::MAX)
+}
 
 #[derive(Default)]
 pub struct EvaluatorBuilder {
@@ -10,7 +30,10 @@ pub struct EvaluatorBuilder {
     module: Option<Module>,
     engine: Option<Engine>,
     epoch_deadline: Option<u64>,
+    memory_limit: Option<u64>,
     host_callbacks: Option<HostCallbacks>,
+    builtins: HashMap<String, HostBuiltin>,
+    http_policy: Option<HttpPolicy>,
 }
 
 impl EvaluatorBuilder {
@@ -38,12 +61,58 @@ impl EvaluatorBuilder {
         self
     }
 
+    /// Limit the size of the memory of the Wasm guest, expressed in bytes.
+    /// The limit is rounded up to the next Wasm page
+    #[must_use]
+    pub fn memory_limit(mut self, bytes: u64) -> Self {
+        self.memory_limit = Some(bytes);
+        self
+    }
+
     #[must_use]
     pub fn host_callbacks(mut self, host_callbacks: HostCallbacks) -> Self {
         self.host_callbacks = Some(host_callbacks);
         self
     }
 
+    /// Register a builtin provided by the embedder, like one backed by resources
+    /// owned by the host. The builtin is made available to the policy together
+    /// with the ones implemented by burrego.
+    ///
+    /// # Panics
+    ///
+    /// Panics when a builtin with the same name is implemented by burrego, or
+    /// has already been registered.
+    #[must_use]
+    pub fn builtin<F>(mut self, name: &str, builtin: F) -> Self
+    where
+        F: Fn(&[serde_json::Value]) -> Result<serde_json::Value> + Send + Sync + 'static,
+    {
+        if get_builtins().contains_key(name) {
+            panic!("cannot register builtin {name}: it is already implemented by burrego");
+        }
+        if self
+            .builtins
+            .insert(name.to_string(), Arc::new(builtin))
+            .is_some()
+        {
+            panic!("cannot register builtin {name}: it has already been registered");
+        }
+        self
+    }
+
+    /// Enable the `http.send` builtin. The requests made by the policy are
+    /// checked against the given `HttpPolicy`, then they are performed by the
+    /// `http_send` host callback, which must be set.
+    ///
+    /// The builtin is disabled by default, the policies using it cannot be
+    /// loaded.
+    #[must_use]
+    pub fn enable_http_builtin(mut self, policy: HttpPolicy) -> Self {
+        self.http_policy = Some(policy);
+        self
+    }
+
     fn validate(&self) -> Result<()> {
         if self.policy_path.is_some() && self.module.is_some() {
             return Err(BurregoError::EvaluatorBuilderError(
@@ -56,10 +125,34 @@ impl EvaluatorBuilder {
             ));
         }
 
-        if self.host_callbacks.is_none() {
-            return Err(BurregoError::EvaluatorBuilderError(
-                "host_callbacks must be set".to_string(),
-            ));
+        if let Some(memory_limit) = self.memory_limit {
+            if memory_limit_to_pages(memory_limit) < INITIAL_MEMORY_PAGES {
+                return Err(BurregoError::EvaluatorBuilderError(format!(
+                    "memory_limit must be at least {} bytes",
+                    INITIAL_MEMORY_PAGES as u64 * WASM_PAGE_SIZE
+                )));
+            }
+        }
+
+        let host_callbacks = self.host_callbacks.as_ref().ok_or_else(|| {
+            BurregoError::EvaluatorBuilderError("host_callbacks must be set".to_string())
+        })?;
+
+        if let Some(http_policy) = &self.http_policy {
+            http_policy.validate()?;
+            if host_callbacks.http_send.is_none() {
+                return Err(BurregoError::EvaluatorBuilderError(
+                    "the http_send host callback must be set to enable the http.send builtin"
+                        .to_string(),
+                ));
+            }
+            if self.builtins.contains_key(http::HTTP_SEND)
+                || host_callbacks.builtins.contains_key(http::HTTP_SEND)
+            {
+                return Err(BurregoError::EvaluatorBuilderError(
+                    "http.send cannot be both enabled and provided as a host builtin".to_string(),
+                ));
+            }
         }
 
         Ok(())
@@ -92,11 +185,80 @@ impl EvaluatorBuilder {
             })?,
         };
 
-        let host_callbacks = self
+        let mut host_callbacks = self
             .host_callbacks
             .clone()
             .expect("host callbacks should be set");
+        host_callbacks.builtins.extend(
+            self.builtins
+                .iter()
+                .map(|(name, builtin)| (name.clone(), builtin.clone())),
+        );
+        if let Some(http_policy) = &self.http_policy {
+            let http_policy = http_policy.clone();
+            let http_send = host_callbacks
+                .http_send
+                .clone()
+                .expect("http_send host callback should be set");
+            let builtin: HostBuiltin =
+                Arc::new(move |args| http::send(&http_policy, &http_send, args));
+            host_callbacks
+                .builtins
+                .insert(http::HTTP_SEND.to_string(), builtin);
+        }
+
+        Evaluator::from_engine_and_module(
+            engine,
+            module,
+            host_callbacks,
+            self.epoch_deadline,
+            self.memory_limit.map(memory_limit_to_pages),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+    use std::time::Duration;
+
+    #[test]
+    fn register_builtins() {
+        let builder = EvaluatorBuilder::default()
+            .builtin("kubewarden.first", |_| Ok(json!(1)))
+            .builtin("kubewarden.second", |_| Ok(json!(2)));
+
+        assert_eq!(builder.builtins.len(), 2);
+        let first = builder.builtins.get("kubewarden.first").unwrap();
+        assert_eq!(first(&[]).unwrap(), json!(1));
+    }
+
+    #[test]
+    #[should_panic(expected = "already implemented by burrego")]
+    fn register_builtin_implemented_by_burrego() {
+        let _ = EvaluatorBuilder::default().builtin("sprintf", |_| Ok(json!("")));
+    }
+
+    #[test]
+    #[should_panic(expected = "already been registered")]
+    fn register_builtin_twice() {
+        let _ = EvaluatorBuilder::default()
+            .builtin("kubewarden.first", |_| Ok(json!(1)))
+            .builtin("kubewarden.first", |_| Ok(json!(2)));
+    }
+
+    #[test]
+    fn http_builtin_requires_a_sender() {
+        let builder = EvaluatorBuilder::default()
+            .policy_path(Path::new("policy.wasm"))
+            .host_callbacks(HostCallbacks::default())
+            .enable_http_builtin(HttpPolicy {
+                allowed_origins: vec!["https://example.com".to_string()],
+                timeout: Duration::from_secs(1),
+            });
 
-        Evaluator::from_engine_and_module(engine, module, host_callbacks, self.epoch_deadline)
+        let error = builder.validate().unwrap_err();
+        assert!(error.to_string().contains("http_send host callback"));
     }
 }
@@ -1,8 +1,16 @@
+use crate::{errors::Result, http::HostHttpSend};
+use std::{collections::HashMap, sync::Arc};
+
 /// HostCallback is a type that references a pointer to a function
 /// that can be stored and then invoked by burrego when the Open
 /// Policy Agent Wasm target invokes certain Wasm imports.
 pub type HostCallback = fn(&str);
 
+/// HostBuiltin is a Rego builtin implemented by the host that embeds
+/// burrego. This is useful for the builtins that need access to resources
+/// owned by the host, like a connection to an OCI registry.
+pub type HostBuiltin = Arc<dyn Fn(&[serde_json::Value]) -> Result<serde_json::Value> + Send + Sync>;
+
 /// HostCallbacks defines a set of pluggable host implementations of
 /// OPA documented imports:
 /// <https://www.openpolicyagent.org/docs/latest/wasm/#imports>
@@ -10,6 +18,12 @@ pub type HostCallback = fn(&str);
 pub struct HostCallbacks {
     pub opa_abort: HostCallback,
     pub opa_println: HostCallback,
+    /// Builtins provided by the host, indexed by their name. These take
+    /// precedence over the builtins implemented by burrego
+    pub builtins: HashMap<String, HostBuiltin>,
+    /// Performs the requests of the `http.send` builtin. This is used only
+    /// when the builtin is enabled with `EvaluatorBuilder::enable_http_builtin`
+    pub http_send: Option<HostHttpSend>,
 }
 
 impl Default for HostCallbacks {
@@ -17,6 +31,8 @@ impl Default for HostCallbacks {
         HostCallbacks {
             opa_abort: default_opa_abort,
             opa_println: default_opa_println,
+            builtins: HashMap::new(),
+            http_send: None,
         }
     }
 }
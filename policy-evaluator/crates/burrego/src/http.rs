@@ -0,0 +1,586 @@
+//! Implementation of the `http.send` builtin.
+//!
+//! The builtin is disabled by default, it must be enabled with
+//! [`EvaluatorBuilder::enable_http_builtin`](crate::EvaluatorBuilder::enable_http_builtin).
+//! burrego doesn't perform the requests by itself: they are validated against the
+//! [`HttpPolicy`] and then handed over to the `http_send` host callback. This way the
+//! host keeps full control over the connections made on behalf of the policies.
+//!
+//! The host must not follow the redirects: these are followed by burrego, when the
+//! policy asks for it, so that each location is checked against the [`HttpPolicy`].
+
+use std::{collections::BTreeMap, sync::Arc, time::Duration};
+
+use serde_json::{json, Map, Value};
+use url::Url;
+
+use crate::errors::{BurregoError, Result};
+
+/// Name of the builtin
+pub(crate) const HTTP_SEND: &str = "http.send";
+
+/// Maximum number of redirects followed by a single `http.send` invocation
+const MAX_REDIRECTS: usize = 10;
+
+/// Which requests the policies are allowed to make through `http.send`
+#[derive(Debug, Clone, PartialEq)]
+pub struct HttpPolicy {
+    /// The origins that can be reached, like `https://api.example.com` or
+    /// `http://registry.internal:5000`. When not given, the port is the default one
+    /// of the scheme. A host like `*.example.com` matches all the subdomains of
+    /// `example.com`
+    pub allowed_origins: Vec<String>,
+    /// Maximum duration of a request. Policies can ask for a shorter timeout
+    pub timeout: Duration,
+}
+
+/// An entry of [`HttpPolicy::allowed_origins`]
+#[derive(Debug, PartialEq)]
+struct AllowedOrigin {
+    scheme: String,
+    host: String,
+    port: u16,
+}
+
+impl AllowedOrigin {
+    fn parse(origin: &str) -> Result<Self> {
+        let invalid = |reason: &str| {
+            BurregoError::EvaluatorBuilderError(format!(
+                "invalid http.send allowed origin {origin}: {reason}"
+            ))
+        };
+
+        let (scheme, authority) = origin
+            .split_once("://")
+            .ok_or_else(|| invalid("the scheme is missing"))?;
+        let scheme = scheme.to_ascii_lowercase();
+        let default_port = match scheme.as_str() {
+            "http" => 80,
+            "https" => 443,
+            _ => return Err(invalid("only http and https are supported")),
+        };
+        let authority = authority.strip_suffix('/').unwrap_or(authority);
+        if authority.contains(['/', '?', '#', '@']) {
+            return Err(invalid(
+                "only the scheme, the host and the port can be given",
+            ));
+        }
+
+        // IPv6 addresses are enclosed in brackets, like `[::1]:8080`
+        let port_separator = match authority.rfind(']') {
+            Some(end_of_host) => authority[end_of_host..].find(':').map(|i| end_of_host + i),
+            None => authority.rfind(':'),
+        };
+        let (host, port) = match port_separator {
+            Some(separator) => (
+                &authority[..separator],
+                authority[separator + 1..]
+                    .parse::<u16>()
+                    .map_err(|_| invalid("invalid port"))?,
+            ),
+            None => (authority, default_port),
+        };
+        if host.is_empty() || host == "*." {
+            return Err(invalid("the host is missing"));
+        }
+
+        Ok(AllowedOrigin {
+            scheme,
+            host: host.to_ascii_lowercase(),
+            port,
+        })
+    }
+
+    fn matches(&self, url: &Url) -> bool {
+        let host = url.host_str().unwrap_or_default().to_ascii_lowercase();
+        let host_matches = match self.host.strip_prefix("*.") {
+            Some(domain) => host
+                .strip_suffix(domain)
+                .is_some_and(|subdomain| subdomain.ends_with('.')),
+            None => host == self.host,
+        };
+
+        host_matches
+            && url.scheme() == self.scheme
+            && url.port_or_known_default() == Some(self.port)
+    }
+}
+
+impl HttpPolicy {
+    /// Ensure all the allowed origins are well formed
+    pub fn validate(&self) -> Result<()> {
+        for origin in &self.allowed_origins {
+            AllowedOrigin::parse(origin)?;
+        }
+        Ok(())
+    }
+
+    fn is_url_allowed(&self, url: &Url) -> bool {
+        self.allowed_origins
+            .iter()
+            .any(|origin| AllowedOrigin::parse(origin).is_ok_and(|allowed| allowed.matches(url)))
+    }
+
+    fn check_url(&self, url: &Url) -> Result<()> {
+        if !matches!(url.scheme(), "http" | "https") {
+            return Err(builtin_error(format!(
+                "scheme {} is not supported",
+                url.scheme()
+            )));
+        }
+        if !self.is_url_allowed(url) {
+            return Err(builtin_error(format!(
+                "{} is not allowed",
+                url.origin().ascii_serialization()
+            )));
+        }
+        Ok(())
+    }
+}
+
+/// A request made by a policy through `http.send`, already checked against
+/// the [`HttpPolicy`]
+#[derive(Debug, Clone, PartialEq)]
+pub struct HttpRequest {
+    pub method: String,
+    pub url: String,
+    pub headers: BTreeMap<String, String>,
+    pub body: Option<Vec<u8>>,
+    /// The request must be aborted once this time has elapsed
+    pub timeout: Duration,
+}
+
+/// The response returned by the host to the policy
+#[derive(Debug, Clone, PartialEq)]
+pub struct HttpResponse {
+    pub status_code: u16,
+    pub headers: BTreeMap<String, String>,
+    pub body: Vec<u8>,
+}
+
+/// Performs the requests of the `http.send` builtin on behalf of the policy
+pub type HostHttpSend =
+    Arc<dyn Fn(&HttpRequest) -> std::result::Result<HttpResponse, String> + Send + Sync>;
+
+fn builtin_error(message: impl Into<String>) -> BurregoError {
+    BurregoError::BuiltinError {
+        name: HTTP_SEND.to_string(),
+        message: message.into(),
+    }
+}
+
+/// Parse a duration, given either as a number of nanoseconds or as a string
+/// like `500ms` or `5s`
+fn parse_timeout(value: &Value) -> Result<Duration> {
+    if let Some(nanoseconds) = value.as_u64() {
+        return Ok(Duration::from_nanos(nanoseconds));
+    }
+
+    let timeout = value
+        .as_str()
+        .ok_or_else(|| builtin_error("timeout is neither a number nor a string"))?;
+    let unit_start = timeout
+        .find(|c: char| !c.is_ascii_digit())
+        .ok_or_else(|| builtin_error(format!("timeout {timeout} has no unit")))?;
+    let (amount, unit) = timeout.split_at(unit_start);
+    let amount: u64 = amount
+        .parse()
+        .map_err(|_| builtin_error(format!("invalid timeout {timeout}")))?;
+
+    match unit {
+        "ns" => Ok(Duration::from_nanos(amount)),
+        "us" | "µs" => Ok(Duration::from_micros(amount)),
+        "ms" => Ok(Duration::from_millis(amount)),
+        "s" => Ok(Duration::from_secs(amount)),
+        "m" => Ok(Duration::from_secs(amount.saturating_mul(60))),
+        "h" => Ok(Duration::from_secs(amount.saturating_mul(3600))),
+        _ => Err(builtin_error(format!("invalid timeout unit {unit}"))),
+    }
+}
+
+fn parse_request(policy: &HttpPolicy, request: &Map<String, Value>) -> Result<HttpRequest> {
+    let method = request
+        .get("method")
+        .and_then(Value::as_str)
+        .ok_or_else(|| builtin_error("method is required"))?
+        .to_ascii_uppercase();
+    let url = request
+        .get("url")
+        .and_then(Value::as_str)
+        .ok_or_else(|| builtin_error("url is required"))?;
+
+    let parsed_url = Url::parse(url).map_err(|e| builtin_error(format!("invalid url: {e}")))?;
+    policy.check_url(&parsed_url)?;
+
+    let mut headers = BTreeMap::new();
+    if let Some(request_headers) = request.get("headers") {
+        let request_headers = request_headers
+            .as_object()
+            .ok_or_else(|| builtin_error("headers is not an object"))?;
+        for (name, value) in request_headers {
+            let value = value
+                .as_str()
+                .ok_or_else(|| builtin_error(format!("header {name} is not a string")))?;
+            headers.insert(name.clone(), value.to_string());
+        }
+    }
+
+    let body = match (request.get("raw_body"), request.get("body")) {
+        (Some(raw_body), _) => Some(
+            raw_body
+                .as_str()
+                .ok_or_else(|| builtin_error("raw_body is not a string"))?
+                .as_bytes()
+                .to_vec(),
+        ),
+        (None, Some(body)) => {
+            headers
+                .entry("Content-Type".to_string())
+                .or_insert_with(|| "application/json".to_string());
+            Some(body.to_string().into_bytes())
+        }
+        (None, None) => None,
+    };
+
+    let timeout = match request.get("timeout") {
+        Some(timeout) => parse_timeout(timeout)?.min(policy.timeout),
+        None => policy.timeout,
+    };
+
+    Ok(HttpRequest {
+        method,
+        url: url.to_string(),
+        headers,
+        body,
+        timeout,
+    })
+}
+
+/// Perform the request, following the redirects when `enable_redirect` is set.
+/// Each location is checked against the policy; the `Authorization` header is
+/// not sent to the other origins
+fn perform(
+    policy: &HttpPolicy,
+    http_send: &HostHttpSend,
+    mut request: HttpRequest,
+    enable_redirect: bool,
+) -> Result<HttpResponse> {
+    for _ in 0..=MAX_REDIRECTS {
+        let response = http_send(&request).map_err(builtin_error)?;
+        if !enable_redirect || !matches!(response.status_code, 301 | 302 | 303 | 307 | 308) {
+            return Ok(response);
+        }
+        let Some(location) = response
+            .headers
+            .iter()
+            .find(|(name, _)| name.eq_ignore_ascii_case("location"))
+            .map(|(_, location)| location)
+        else {
+            return Ok(response);
+        };
+
+        let current_url =
+            Url::parse(&request.url).map_err(|e| builtin_error(format!("invalid url: {e}")))?;
+        let next_url = current_url
+            .join(location)
+            .map_err(|e| builtin_error(format!("invalid redirect location {location}: {e}")))?;
+        policy
+            .check_url(&next_url)
+            .map_err(|e| builtin_error(format!("cannot follow the redirect: {e}")))?;
+
+        if next_url.origin() != current_url.origin() {
+            request
+                .headers
+                .retain(|name, _| !name.eq_ignore_ascii_case("authorization"));
+        }
+        if response.status_code == 303
+            || (matches!(response.status_code, 301 | 302) && request.method == "POST")
+        {
+            request.method = "GET".to_string();
+            request.body = None;
+            request
+                .headers
+                .retain(|name, _| !name.eq_ignore_ascii_case("content-type"));
+        }
+        request.url = next_url.to_string();
+    }
+
+    Err(builtin_error(format!(
+        "too many redirects, at most {MAX_REDIRECTS} are followed"
+    )))
+}
+
+fn response_to_value(response: HttpResponse) -> Value {
+    let raw_body = String::from_utf8_lossy(&response.body).to_string();
+    let body: Value = serde_json::from_slice(&response.body).unwrap_or(Value::Null);
+
+    json!({
+        "status_code": response.status_code,
+        "headers": response.headers,
+        "body": body,
+        "raw_body": raw_body,
+    })
+}
+
+/// Evaluate `http.send`. When the request sets `raise_error` to `false`, the
+/// failures are reported inside of the response instead of aborting the
+/// evaluation of the policy, like OPA does
+pub(crate) fn send(policy: &HttpPolicy, http_send: &HostHttpSend, args: &[Value]) -> Result<Value> {
+    if args.len() != 1 {
+        return Err(builtin_error("wrong number of arguments"));
+    }
+    let request = args[0]
+        .as_object()
+        .ok_or_else(|| builtin_error("1st parameter is not an object"))?;
+    let raise_error = request
+        .get("raise_error")
+        .and_then(Value::as_bool)
+        .unwrap_or(true);
+    let enable_redirect = request
+        .get("enable_redirect")
+        .and_then(Value::as_bool)
+        .unwrap_or(false);
+
+    let result = parse_request(policy, request)
+        .and_then(|request| perform(policy, http_send, request, enable_redirect))
+        .map(response_to_value);
+
+    match result {
+        Ok(response) => Ok(response),
+        Err(error) if raise_error => Err(error),
+        Err(error) => {
+            let message = match error {
+                BurregoError::BuiltinError { message, .. } => message,
+                error => error.to_string(),
+            };
+            Ok(json!({
+                "status_code": 0,
+                "error": { "message": message },
+            }))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn policy() -> HttpPolicy {
+        HttpPolicy {
+            allowed_origins: vec![
+                "https://api.example.com".to_string(),
+                "http://*.internal:8080".to_string(),
+            ],
+            timeout: Duration::from_secs(5),
+        }
+    }
+
+    fn echo_sender() -> HostHttpSend {
+        Arc::new(|request: &HttpRequest| {
+            Ok(HttpResponse {
+                status_code: 200,
+                headers: BTreeMap::from([(
+                    "content-type".to_string(),
+                    "application/json".to_string(),
+                )]),
+                body: json!({
+                    "method": request.method,
+                    "timeout_ms": request.timeout.as_millis() as u64,
+                })
+                .to_string()
+                .into_bytes(),
+            })
+        })
+    }
+
+    #[test]
+    fn allowed_origins() {
+        let cases = [
+            ("https://api.example.com/v1", true),
+            ("https://API.example.com", true),
+            ("https://api.example.com:443", true),
+            ("https://api.example.com:8443", false),
+            ("http://api.example.com", false),
+            ("https://other.example.com", false),
+            ("http://registry.internal:8080", true),
+            ("http://a.b.internal:8080/path", true),
+            ("http://registry.internal", false),
+            ("https://registry.internal:8080", false),
+            ("http://internal:8080", false),
+            ("http://notinternal:8080", false),
+        ];
+
+        for (url, allowed) in cases {
+            let url = Url::parse(url).unwrap();
+            assert_eq!(policy().is_url_allowed(&url), allowed, "url: {url}");
+        }
+    }
+
+    #[test]
+    fn invalid_allowed_origins() {
+        let origins = [
+            "api.example.com",
+            "ftp://api.example.com",
+            "https://api.example.com/v1",
+            "https://user@api.example.com",
+            "https://api.example.com:port",
+            "https://",
+        ];
+
+        for origin in origins {
+            let policy = HttpPolicy {
+                allowed_origins: vec![origin.to_string()],
+                timeout: Duration::from_secs(1),
+            };
+            assert!(policy.validate().is_err(), "origin: {origin}");
+        }
+    }
+
+    #[test]
+    fn timeout() {
+        let cases = [
+            (json!(1_000_000), Some(Duration::from_millis(1))),
+            (json!("500ms"), Some(Duration::from_millis(500))),
+            (json!("2s"), Some(Duration::from_secs(2))),
+            (json!("1m"), Some(Duration::from_secs(60))),
+            (json!("10"), None),
+            (json!("10d"), None),
+            (json!(true), None),
+        ];
+
+        for (value, expected) in cases {
+            assert_eq!(parse_timeout(&value).ok(), expected, "timeout: {value}");
+        }
+    }
+
+    #[test]
+    fn send_request() {
+        let response = send(
+            &policy(),
+            &echo_sender(),
+            &[json!({"method": "get", "url": "https://api.example.com/v1", "timeout": "1s"})],
+        )
+        .unwrap();
+
+        assert_eq!(response["status_code"], json!(200));
+        assert_eq!(
+            response["body"],
+            json!({"method": "GET", "timeout_ms": 1000})
+        );
+        assert_eq!(
+            response["headers"]["content-type"],
+            json!("application/json")
+        );
+    }
+
+    #[test]
+    fn timeout_cannot_exceed_the_policy() {
+        let response = send(
+            &policy(),
+            &echo_sender(),
+            &[json!({"method": "GET", "url": "https://api.example.com", "timeout": "1h"})],
+        )
+        .unwrap();
+
+        assert_eq!(response["body"]["timeout_ms"], json!(5000));
+    }
+
+    #[test]
+    fn rejected_requests() {
+        let requests = [
+            json!({"method": "GET", "url": "https://evil.example.org"}),
+            json!({"method": "GET", "url": "file:///etc/passwd"}),
+            json!({"url": "https://api.example.com"}),
+        ];
+
+        for mut request in requests {
+            assert!(send(&policy(), &echo_sender(), &[request.clone()]).is_err());
+
+            request["raise_error"] = json!(false);
+            let response = send(&policy(), &echo_sender(), &[request]).unwrap();
+            assert_eq!(response["status_code"], json!(0));
+            assert!(response["error"]["message"].is_string());
+        }
+    }
+
+    /// Redirects to the given location, serves the other urls
+    fn redirecting_sender(location: &'static str) -> HostHttpSend {
+        Arc::new(move |request: &HttpRequest| {
+            if request.url == "https://api.example.com/old" {
+                return Ok(HttpResponse {
+                    status_code: 302,
+                    headers: BTreeMap::from([("Location".to_string(), location.to_string())]),
+                    body: Vec::new(),
+                });
+            }
+            Ok(HttpResponse {
+                status_code: 200,
+                headers: BTreeMap::new(),
+                body: json!({
+                    "url": request.url,
+                    "authorization": request.headers.get("Authorization"),
+                })
+                .to_string()
+                .into_bytes(),
+            })
+        })
+    }
+
+    #[test]
+    fn redirects() {
+        let request = json!({
+            "method": "GET",
+            "url": "https://api.example.com/old",
+            "headers": {"Authorization": "Bearer secret"},
+            "enable_redirect": true,
+        });
+
+        let response = send(&policy(), &redirecting_sender("/new"), &[request.clone()]).unwrap();
+        assert_eq!(
+            response["body"],
+            json!({"url": "https://api.example.com/new", "authorization": "Bearer secret"})
+        );
+
+        let response = send(
+            &policy(),
+            &redirecting_sender("http://registry.internal:8080/new"),
+            &[request.clone()],
+        )
+        .unwrap();
+        assert_eq!(
+            response["body"],
+            json!({"url": "http://registry.internal:8080/new", "authorization": null})
+        );
+
+        let error = send(
+            &policy(),
+            &redirecting_sender("https://evil.example.org/new"),
+            &[request.clone()],
+        )
+        .unwrap_err();
+        assert!(error.to_string().contains("cannot follow the redirect"));
+
+        let mut request = request;
+        request["enable_redirect"] = json!(false);
+        let response = send(
+            &policy(),
+            &redirecting_sender("https://evil.example.org/new"),
+            &[request],
+        )
+        .unwrap();
+        assert_eq!(response["status_code"], json!(302));
+    }
+
+    #[test]
+    fn host_failures() {
+        let failing_sender: HostHttpSend = Arc::new(|_| Err("connection refused".to_string()));
+        let error = send(
+            &policy(),
+            &failing_sender,
+            &[json!({"method": "GET", "url": "https://api.example.com"})],
+        )
+        .unwrap_err();
+
+        assert!(error.to_string().contains("connection refused"));
+    }
+}
@@ -3,6 +3,7 @@ pub mod errors;
 mod evaluator;
 mod evaluator_builder;
 pub mod host_callbacks;
+pub mod http;
 mod opa_host_functions;
 mod policy;
 mod stack_helper;
@@ -11,3 +12,4 @@ pub use builtins::get_builtins;
 pub use evaluator::Evaluator;
 pub use evaluator_builder::EvaluatorBuilder;
 pub use host_callbacks::HostCallbacks;
+pub use http::HttpPolicy;
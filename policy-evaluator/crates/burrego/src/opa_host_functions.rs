@@ -3,6 +3,7 @@ use tracing::{debug, error};
 use wasmtime::{AsContextMut, Caller, Linker};
 
 use crate::builtins::BUILTINS_HELPER;
+use crate::host_callbacks::HostBuiltin;
 use crate::stack_helper::StackHelper;
 
 /// Add OPA host callbacks to the linker.
@@ -98,17 +99,13 @@ fn register_opa_builtin0_func(
                     error!(builtin_id, builtins =? stack_helper.builtins, "opa_builtin0: cannot find builtin");
                     BurregoError::BuiltinNotImplementedError(format!("opa_builtin0: cannot find builtin {builtin_id}"))
                 })?.clone();
+            let host_builtin = stack_helper.host_builtins.get(&builtin_name).cloned();
             let args = vec![];
 
             let memory_export = caller.get_export("memory").ok_or_else(|| BurregoError::RegoWasmError("cannot find 'memory' export".to_string()))?;
             let memory = memory_export.into_memory().ok_or_else(|| BurregoError::RegoWasmError("'memory' export cannot be converted into a memory object".to_string()))?;
 
-            let builtin_helper = BUILTINS_HELPER
-                .read()
-                .map_err(|e| BurregoError::RegoWasmError(format!("Cannot access global builtin helper: {e:?}")))?;
-
-            let builtin_result = builtin_helper
-                .invoke(&builtin_name, &args)?;
+            let builtin_result = invoke_builtin(host_builtin, &builtin_name, &args)?;
 
             let addr = StackHelper::push_json(
                 caller.as_context_mut(),
@@ -151,6 +148,7 @@ fn register_opa_builtin1_func(
                     BurregoError::BuiltinNotImplementedError(
                     format!("opa_bunltin1: cannot find builtin {builtin_id}"))
                 })?.clone();
+            let host_builtin = stack_helper.host_builtins.get(&builtin_name).cloned();
 
             let memory_export = caller.get_export("memory").ok_or_else(|| BurregoError::RegoWasmError("cannot find 'memory' export".to_string()))?;
             let memory = memory_export.into_memory().ok_or_else(|| BurregoError::RegoWasmError("'memory' export cannot be converted into a memory object".to_string()))?;
@@ -160,12 +158,7 @@ fn register_opa_builtin1_func(
                     StackHelper::pull_json(caller.as_context_mut(), &memory, &opa_json_dump_fn, p1)?;
             let args = vec![p1];
 
-            let builtin_helper = BUILTINS_HELPER
-                .read()
-                .map_err(|e| BurregoError::RegoWasmError(format!("Cannot access global builtin helper: {e:?}")))?;
-
-            let builtin_result = builtin_helper
-                .invoke(&builtin_name, &args)?;
+            let builtin_result = invoke_builtin(host_builtin, &builtin_name, &args)?;
 
             let addr = StackHelper::push_json(
                 caller.as_context_mut(),
@@ -209,6 +202,7 @@ fn register_opa_builtin2_func(
                     error!(builtin_id, builtins =? stack_helper.builtins, "opa_builtin0: cannot find builtin");
                     BurregoError::BuiltinNotImplementedError(format!("opa_builtin2: cannot find builtin {builtin_id}"))
                 })?.clone();
+            let host_builtin = stack_helper.host_builtins.get(&builtin_name).cloned();
 
             let memory_export = caller.get_export("memory").ok_or_else(|| BurregoError::RegoWasmError("cannot find 'memory' export".to_string()))?;
             let memory = memory_export.into_memory().ok_or_else(|| BurregoError::RegoWasmError("'memory' export cannot be converted into a memory object".to_string()))?;
@@ -220,11 +214,7 @@ fn register_opa_builtin2_func(
 
             let args = vec![p1, p2];
 
-            let builtin_helper = BUILTINS_HELPER
-                .read()
-                .map_err(|e| BurregoError::RegoWasmError(format!("Cannot access global builtin helper: {e:?}")))?;
-
-            let builtin_result = builtin_helper.invoke(&builtin_name, &args)?;
+            let builtin_result = invoke_builtin(host_builtin, &builtin_name, &args)?;
 
             let addr = StackHelper::push_json(
                 caller.as_context_mut(),
@@ -269,6 +259,7 @@ fn register_opa_builtin3_func(
                     error!(builtin_id, builtins =? stack_helper.builtins, "opa_builtin0: cannot find builtin");
                     BurregoError::BuiltinNotImplementedError(format!("opa_builtin3: cannot find builtin {builtin_id}"))
                 })?.clone();
+            let host_builtin = stack_helper.host_builtins.get(&builtin_name).cloned();
 
             let memory_export = caller.get_export("memory").ok_or_else(|| BurregoError::RegoWasmError("cannot find 'memory' export".to_string()))?;
             let memory = memory_export.into_memory().ok_or_else(|| BurregoError::RegoWasmError("'memory' export cannot be converted into a memory object".to_string()))?;
@@ -282,11 +273,7 @@ fn register_opa_builtin3_func(
 
             let args = vec![p1, p2, p3];
 
-            let builtin_helper = BUILTINS_HELPER
-                .read()
-                .map_err(|e| BurregoError::RegoWasmError(format!("Cannot access global builtin helper: {e:?}")))?;
-
-            let builtin_result = builtin_helper.invoke(&builtin_name, &args)?;
+            let builtin_result = invoke_builtin(host_builtin, &builtin_name, &args)?;
 
             let addr = StackHelper::push_json(
                 caller.as_context_mut(),
@@ -332,6 +319,7 @@ fn register_opa_builtin4_func(
                     error!(builtin_id, builtins =? stack_helper.builtins, "opa_builtin0: cannot find builtin");
                     BurregoError::BuiltinNotImplementedError(format!("opa_builtin4: cannot find builtin {builtin_id}"))
                 })?.clone();
+            let host_builtin = stack_helper.host_builtins.get(&builtin_name).cloned();
 
             let memory_export = caller.get_export("memory").ok_or_else(|| BurregoError::RegoWasmError("cannot find 'memory' export".to_string()))?;
             let memory = memory_export.into_memory().ok_or_else(|| BurregoError::RegoWasmError("'memory' export cannot be converted into a memory object".to_string()))?;
@@ -347,11 +335,7 @@ fn register_opa_builtin4_func(
 
             let args = vec![p1, p2, p3, p4];
 
-            let builtin_helper = BUILTINS_HELPER
-                .read()
-                .map_err(|e| BurregoError::RegoWasmError(format!("Cannot access global builtin helper: {e:?}")))?;
-
-            let builtin_result = builtin_helper.invoke(&builtin_name, &args)?;
+            let builtin_result = invoke_builtin(host_builtin, &builtin_name, &args)?;
 
             let addr = StackHelper::push_json(
                 caller.as_context_mut(),
@@ -368,3 +352,21 @@ fn register_opa_builtin4_func(
         message: e.to_string(),
     })
 }
+
+/// Invoke the builtin with the given name. The builtins provided by the host
+/// take precedence over the ones implemented by burrego.
+fn invoke_builtin(
+    host_builtin: Option<HostBuiltin>,
+    builtin_name: &str,
+    args: &[serde_json::Value],
+) -> Result<serde_json::Value> {
+    if let Some(host_builtin) = host_builtin {
+        debug!(builtin = builtin_name, "invoking host builtin");
+        return host_builtin(args);
+    }
+
+    let builtin_helper = BUILTINS_HELPER.read().map_err(|e| {
+        BurregoError::RegoWasmError(format!("Cannot access global builtin helper: {e:?}"))
+    })?;
+    builtin_helper.invoke(builtin_name, args)
+}
@@ -3,21 +3,33 @@ use crate::stack_helper::StackHelper;
 use serde_json::json;
 use std::collections::HashMap;
 use std::convert::TryFrom;
+use tracing::debug;
 use wasmtime::{AsContextMut, Instance, Memory, TypedFunc};
 
+/// The `opa_eval` function, introduced by the OPA Wasm ABI 1.2. It performs the whole
+/// evaluation with a single call: the input is parsed inside of the guest and the result
+/// is returned already serialized.
+/// Params: reserved, entrypoint, data address, input address, input length, heap pointer,
+/// result format
+type OpaEvalFn = TypedFunc<(i32, i32, i32, i32, i32, i32, i32), i32>;
+
+/// Ask `opa_eval` to serialize the result as JSON
+const OPA_EVAL_RESULT_FORMAT_JSON: i32 = 0;
+
 /// Handle errors returned when calling a wasmtime function
 /// The macro looks into the error type and, when an epoch interruption
-/// happens, maps the error to BurregoError::ExecutionDeadlineExceeded
+/// happens, maps the error to BurregoError::ExecutionDeadlineExceeded.
+/// The other traps are mapped to BurregoError::WasmTrap, to keep their backtrace
 macro_rules! map_call_error {
     ($err:expr, $msg:expr) => {{
-        if let Some(trap) = $err.downcast_ref::<wasmtime::Trap>() {
-            if matches!(trap, wasmtime::Trap::Interrupt) {
-                BurregoError::ExecutionDeadlineExceeded
-            } else {
-                BurregoError::WasmEngineError(format!("{}: {:?}", $msg, $err))
-            }
-        } else {
-            BurregoError::WasmEngineError(format!("{}: {:?}", $msg, $err))
+        let err = $err;
+        match err.downcast_ref::<wasmtime::Trap>().copied() {
+            Some(wasmtime::Trap::Interrupt) => BurregoError::ExecutionDeadlineExceeded,
+            Some(_) => BurregoError::WasmTrap {
+                msg: $msg.to_string(),
+                source: err,
+            },
+            None => BurregoError::WasmEngineError(format!("{}: {:?}", $msg, err)),
         }
     }};
 }
@@ -36,6 +48,8 @@ pub(crate) struct Policy {
     opa_malloc_fn: TypedFunc<i32, i32>,
     opa_json_parse_fn: TypedFunc<(i32, i32), i32>,
     eval_fn: TypedFunc<i32, i32>,
+    /// Available only when the module implements the OPA Wasm ABI 1.2 or later
+    opa_eval_fn: Option<OpaEvalFn>,
 
     data_addr: i32,
     base_heap_ptr: i32,
@@ -133,6 +147,12 @@ impl Policy {
                 .map_err(|e| {
                     BurregoError::RegoWasmError(format!("cannot get eval function: {e:?}"))
                 })?,
+            opa_eval_fn: instance
+                .get_typed_func::<(i32, i32, i32, i32, i32, i32, i32), i32>(
+                    store.as_context_mut(),
+                    "opa_eval",
+                )
+                .ok(),
             data_addr: 0,
             base_heap_ptr: 0,
             data_heap_ptr: 0,
@@ -154,9 +174,19 @@ impl Policy {
             .map_err(|e| map_call_error!(e, "error invoking opa_heap_ptr_get function"))?;
         policy.data_heap_ptr = policy.base_heap_ptr;
 
+        debug!(
+            one_shot_evaluation = policy.one_shot_evaluation(),
+            "OPA evaluation interface"
+        );
+
         Ok(policy)
     }
 
+    /// Whether the evaluations are done with the `opa_eval` function
+    pub fn one_shot_evaluation(&self) -> bool {
+        self.opa_eval_fn.is_some()
+    }
+
     pub fn builtins(
         &self,
         mut store: impl AsContextMut,
@@ -243,6 +273,10 @@ impl Policy {
         memory: &Memory,
         input: &serde_json::Value,
     ) -> Result<serde_json::Value> {
+        if let Some(opa_eval_fn) = &self.opa_eval_fn {
+            return self.evaluate_one_shot(opa_eval_fn, entrypoint_id, store, memory, input);
+        }
+
         // Reset the heap pointer before each evaluation
         self.opa_heap_ptr_set_fn
             .call(store.as_context_mut(), self.data_heap_ptr)
@@ -292,4 +326,58 @@ impl Policy {
             res_addr,
         )
     }
+
+    /// Evaluate the policy by using the `opa_eval` function. Compared to the evaluation
+    /// done with the `opa_eval_ctx_*` functions, this requires less calls into the guest
+    /// and the input is written straight into the guest memory, without being allocated
+    /// or parsed by a dedicated call.
+    fn evaluate_one_shot(
+        &self,
+        opa_eval_fn: &OpaEvalFn,
+        entrypoint_id: i32,
+        mut store: impl AsContextMut,
+        memory: &Memory,
+        input: &serde_json::Value,
+    ) -> Result<serde_json::Value> {
+        let input = serde_json::to_vec(input).map_err(|e| BurregoError::JSONError {
+            msg: "cannot serialize input".to_string(),
+            source: e,
+        })?;
+        let input_len = i32::try_from(input.len())
+            .map_err(|_| BurregoError::RegoWasmError("input is too big".to_string()))?;
+
+        // The input is written right after the data, the evaluation can then use the
+        // memory that follows it
+        let input_addr = self.data_heap_ptr;
+        let heap_ptr = input_addr
+            .checked_add(input_len)
+            .ok_or_else(|| BurregoError::RegoWasmError("input is too big".to_string()))?;
+        StackHelper::ensure_memory_size(store.as_context_mut(), memory, heap_ptr as usize)?;
+        memory
+            .write(store.as_context_mut(), input_addr as usize, &input)
+            .map_err(|e| {
+                BurregoError::WasmEngineError(format!("cannot write input to memory: {e:?}"))
+            })?;
+
+        let res_addr = opa_eval_fn
+            .call(
+                store.as_context_mut(),
+                (
+                    0,
+                    entrypoint_id,
+                    self.data_addr,
+                    input_addr,
+                    input_len,
+                    heap_ptr,
+                    OPA_EVAL_RESULT_FORMAT_JSON,
+                ),
+            )
+            .map_err(|e| map_call_error!(e, "error invoking opa_eval function"))?;
+
+        let result = StackHelper::read_string(store.as_context_mut(), memory, res_addr)?;
+        serde_json::from_slice(&result).map_err(|e| BurregoError::JSONError {
+            msg: "cannot parse the result of opa_eval".to_string(),
+            source: e,
+        })
+    }
 }
@@ -5,6 +5,9 @@ use std::collections::HashMap;
 use std::convert::TryInto;
 use wasmtime::{AsContext, AsContextMut, Instance, Memory, TypedFunc};
 
+/// Size of a page of the Wasm linear memory
+pub(crate) const WASM_PAGE_SIZE: u64 = 64 * 1024;
+
 /// StackHelper provides a set of helper methods to share data
 /// between the host and the Rego Wasm guest
 #[derive(Clone)]
@@ -17,6 +20,7 @@ pub(crate) struct StackHelper {
     pub(crate) opa_println_host_callback: host_callbacks::HostCallback,
 
     pub(crate) builtins: HashMap<i32, String>,
+    pub(crate) host_builtins: HashMap<String, host_callbacks::HostBuiltin>,
 }
 
 impl StackHelper {
@@ -26,6 +30,7 @@ impl StackHelper {
         mut store: impl AsContextMut,
         opa_abort_host_callback: host_callbacks::HostCallback,
         opa_println_host_callback: host_callbacks::HostCallback,
+        host_builtins: HashMap<String, host_callbacks::HostBuiltin>,
     ) -> Result<StackHelper> {
         let opa_json_dump_fn = instance
             .get_typed_func::<i32, i32>(store.as_context_mut(), "opa_json_dump")
@@ -75,6 +80,7 @@ impl StackHelper {
             builtins,
             opa_abort_host_callback,
             opa_println_host_callback,
+            host_builtins,
         })
     }
 
@@ -86,23 +92,47 @@ impl StackHelper {
     /// # Returns
     /// * The data read
     pub fn read_string(store: impl AsContext, memory: &Memory, addr: i32) -> Result<Vec<u8>> {
-        let mut buffer: [u8; 1] = [0u8];
-        let mut data: Vec<u8> = vec![];
-        let mut raw_addr = addr;
-
-        loop {
-            memory
-                .read(&store, raw_addr.try_into().unwrap(), &mut buffer)
-                .map_err(|e| {
-                    BurregoError::WasmEngineError(format!("cannot read from memory: {e:?}"))
-                })?;
-            if buffer[0] == 0 {
-                break;
-            }
-            data.push(buffer[0]);
-            raw_addr += 1;
+        let start: usize = addr.try_into().map_err(|_| {
+            BurregoError::WasmEngineError(format!(
+                "cannot read from memory: invalid address {addr}"
+            ))
+        })?;
+        let data = memory.data(&store).get(start..).ok_or_else(|| {
+            BurregoError::WasmEngineError(format!(
+                "cannot read from memory: address {addr} is out of bounds"
+            ))
+        })?;
+        let len = data.iter().position(|b| *b == 0).ok_or_else(|| {
+            BurregoError::WasmEngineError(
+                "cannot read from memory: string is not null terminated".to_string(),
+            )
+        })?;
+
+        Ok(data[..len].to_vec())
+    }
+
+    /// Grow the Wasm linear memory, when needed, to ensure it's at least `size` bytes long.
+    /// This must be done before writing data directly into the memory of the guest.
+    /// # Arguments
+    /// * `store` - the Store associated with the Wasm instance
+    /// * `memory` - the Wasm linear memory used by the Wasm Instance
+    /// * `size` - the minimum size of the memory, in bytes
+    pub fn ensure_memory_size(
+        mut store: impl AsContextMut,
+        memory: &Memory,
+        size: usize,
+    ) -> Result<()> {
+        let current_size = memory.data_size(store.as_context()) as u64;
+        let size = size as u64;
+        if size <= current_size {
+            return Ok(());
         }
-        Ok(data)
+
+        let pages = (size - current_size).div_ceil(WASM_PAGE_SIZE);
+        memory
+            .grow(store.as_context_mut(), pages)
+            .map_err(|e| BurregoError::WasmEngineError(format!("cannot grow memory: {e:?}")))?;
+        Ok(())
     }
 
     /// Pull a JSON data from the Wasm guest into the host
@@ -209,3 +239,47 @@ impl StackHelper {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use wasmtime::{Engine, MemoryType, Store};
+
+    fn memory() -> (Store<()>, Memory) {
+        let engine = Engine::default();
+        let mut store = Store::new(&engine, ());
+        let memory = Memory::new(&mut store, MemoryType::new(1, None)).unwrap();
+        (store, memory)
+    }
+
+    #[test]
+    fn read_null_terminated_string() {
+        let (mut store, memory) = memory();
+        memory.write(&mut store, 10, b"{\"a\":1}\0garbage").unwrap();
+
+        let data = StackHelper::read_string(&store, &memory, 10).unwrap();
+        assert_eq!(data, b"{\"a\":1}");
+    }
+
+    #[test]
+    fn read_string_out_of_bounds() {
+        let (store, memory) = memory();
+        let size = memory.data_size(&store) as i32;
+
+        assert!(StackHelper::read_string(&store, &memory, size + 1).is_err());
+        assert!(StackHelper::read_string(&store, &memory, -1).is_err());
+    }
+
+    #[test]
+    fn grow_memory_only_when_needed() {
+        let (mut store, memory) = memory();
+        let page_size = WASM_PAGE_SIZE as usize;
+
+        StackHelper::ensure_memory_size(&mut store, &memory, page_size).unwrap();
+        assert_eq!(memory.data_size(&store), page_size);
+
+        StackHelper::ensure_memory_size(&mut store, &memory, 2 * page_size + 1).unwrap();
+        assert_eq!(memory.data_size(&store), 3 * page_size);
+    }
+}
@@ -1,3 +1,11 @@
+mod builder;
+
+pub use builder::AdmissionRequestBuilder;
+
+/// UID given to the requests built by [`AdmissionRequestBuilder`], unless
+/// a different one is set
+pub(crate) const DEFAULT_ADMISSION_REQUEST_UID: &str = "705ab4f5-6393-11e8-b7cc-42010a800002";
+
 /// This models the admission/v1/AdmissionRequest object of Kubernetes
 #[derive(Clone, Debug, serde::Serialize, serde::Deserialize)]
 #[serde(rename_all = "camelCase")]
@@ -27,6 +35,11 @@ pub struct AdmissionRequest {
     pub dry_run: Option<bool>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub options: Option<k8s_openapi::apimachinery::pkg::runtime::RawExtension>,
+    /// Additional context injected by the host before the evaluation, for example
+    /// by Policy Server's request enrichment. This is never read from the incoming
+    /// request, hence it cannot be forged by whoever sends the request.
+    #[serde(skip_deserializing, skip_serializing_if = "Option::is_none")]
+    pub enrichment: Option<serde_json::Map<String, serde_json::Value>>,
 }
 
 #[derive(Clone, Debug, Default, serde::Serialize, serde::Deserialize, Eq, PartialEq)]
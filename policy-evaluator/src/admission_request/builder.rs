@@ -0,0 +1,505 @@
+use k8s_openapi::{
+    api::authentication::v1::UserInfo,
+    apimachinery::pkg::{apis::meta::v1::ObjectMeta, runtime::RawExtension},
+};
+use serde::Serialize;
+
+use crate::{
+    admission_request::{
+        AdmissionRequest, GroupVersionKind, GroupVersionResource, DEFAULT_ADMISSION_REQUEST_UID,
+    },
+    errors::AdmissionRequestBuilderError,
+    policy_metadata::Operation,
+};
+
+type Result<T> = std::result::Result<T, AdmissionRequestBuilderError>;
+
+/// Helper Struct that creates an `AdmissionRequest` object, mostly useful inside
+/// of tests.
+///
+/// The kind, the resource, the name and the Namespace of the request are taken
+/// from the object, which can be either a k8s-openapi typed object or a plain JSON
+/// document. The request is made by `kubernetes-admin`, a member of the
+/// `system:masters` group, unless a different user is set:
+///
+/// ```ignore
+/// let request = AdmissionRequestBuilder::update(&pod, &old_pod)?
+///     .username("alice")
+///     .dry_run(true)
+///     .build()?;
+/// ```
+///
+/// An existing request can be converted into a builder to change some of its fields.
+#[derive(Clone, Debug)]
+pub struct AdmissionRequestBuilder {
+    operation: String,
+    request: AdmissionRequest,
+}
+
+impl AdmissionRequestBuilder {
+    /// Create a new builder of a request performing `operation` against the given
+    /// kind of resource. The object and the old object must be set by the caller,
+    /// depending on the operation
+    pub fn new(
+        operation: Operation,
+        kind: GroupVersionKind,
+        resource: GroupVersionResource,
+    ) -> Self {
+        Self {
+            operation: operation_name(&operation).to_owned(),
+            request: AdmissionRequest {
+                uid: DEFAULT_ADMISSION_REQUEST_UID.to_owned(),
+                kind: kind.clone(),
+                resource: resource.clone(),
+                sub_resource: None,
+                request_kind: Some(kind),
+                request_resource: Some(resource),
+                request_sub_resource: None,
+                name: None,
+                namespace: None,
+                operation: String::new(),
+                user_info: UserInfo {
+                    username: Some("kubernetes-admin".to_owned()),
+                    groups: Some(vec![
+                        "system:masters".to_owned(),
+                        "system:authenticated".to_owned(),
+                    ]),
+                    ..Default::default()
+                },
+                object: None,
+                old_object: None,
+                dry_run: Some(false),
+                options: None,
+                enrichment: None,
+            },
+        }
+    }
+
+    /// Create a new builder of a request performing `operation` against the given
+    /// JSON object. The object is set as the `object` of the request, or as the
+    /// `oldObject` of `DELETE` requests.
+    ///
+    /// The plural name of the resource is guessed from the kind of the object, which
+    /// is good enough for most of the policies. Use [`AdmissionRequestBuilder::resource`]
+    /// to override it
+    pub fn from_json(operation: Operation, object: serde_json::Value) -> Result<Self> {
+        let kind = object
+            .get("kind")
+            .and_then(serde_json::Value::as_str)
+            .ok_or(AdmissionRequestBuilderError::InvalidObject("kind"))?
+            .to_owned();
+        let api_version = object
+            .get("apiVersion")
+            .and_then(serde_json::Value::as_str)
+            .ok_or(AdmissionRequestBuilderError::InvalidObject("apiVersion"))?;
+        let (group, version) = match api_version.split_once('/') {
+            Some((group, version)) => (group.to_owned(), version.to_owned()),
+            None => (String::new(), api_version.to_owned()),
+        };
+        let metadata_field = |field: &str| {
+            object
+                .pointer(&format!("/metadata/{field}"))
+                .and_then(serde_json::Value::as_str)
+                .map(str::to_owned)
+        };
+        let name = metadata_field("name");
+        let namespace = metadata_field("namespace");
+
+        let resource = GroupVersionResource {
+            group: group.clone(),
+            version: version.clone(),
+            resource: format!("{}s", kind.to_lowercase()),
+        };
+        let kind = GroupVersionKind {
+            group,
+            version,
+            kind,
+        };
+
+        let builder = Self::new(operation.clone(), kind, resource);
+        let builder = match name {
+            Some(name) => builder.name(&name),
+            None => builder,
+        };
+        let builder = match namespace {
+            Some(namespace) => builder.namespace(&namespace),
+            None => builder,
+        };
+        Ok(match operation {
+            Operation::Delete => builder.old_object(object),
+            _ => builder.object(object),
+        })
+    }
+
+    /// Create a new builder of a request performing `operation` against the given
+    /// k8s-openapi typed object. The object is set as the `object` of the request, or
+    /// as the `oldObject` of `DELETE` requests
+    pub fn from_resource<K>(operation: Operation, object: &K) -> Result<Self>
+    where
+        K: k8s_openapi::Resource + k8s_openapi::Metadata<Ty = ObjectMeta> + Serialize,
+    {
+        let kind = GroupVersionKind {
+            group: K::GROUP.to_owned(),
+            version: K::VERSION.to_owned(),
+            kind: K::KIND.to_owned(),
+        };
+        let resource = GroupVersionResource {
+            group: K::GROUP.to_owned(),
+            version: K::VERSION.to_owned(),
+            resource: K::URL_PATH_SEGMENT.to_owned(),
+        };
+        let json = to_json(object)?;
+
+        let mut builder = Self::new(operation.clone(), kind, resource);
+        builder.request.name = object.metadata().name.clone();
+        builder.request.namespace = object.metadata().namespace.clone();
+        Ok(match operation {
+            Operation::Delete => builder.old_object(json),
+            _ => builder.object(json),
+        })
+    }
+
+    /// Create a new builder of a `CREATE` request of the given object
+    pub fn create<K>(object: &K) -> Result<Self>
+    where
+        K: k8s_openapi::Resource + k8s_openapi::Metadata<Ty = ObjectMeta> + Serialize,
+    {
+        Self::from_resource(Operation::Create, object)
+    }
+
+    /// Create a new builder of an `UPDATE` request, changing `old_object` into `object`
+    pub fn update<K>(object: &K, old_object: &K) -> Result<Self>
+    where
+        K: k8s_openapi::Resource + k8s_openapi::Metadata<Ty = ObjectMeta> + Serialize,
+    {
+        Ok(Self::from_resource(Operation::Update, object)?.old_object(to_json(old_object)?))
+    }
+
+    /// Create a new builder of a `DELETE` request of the given object
+    pub fn delete<K>(old_object: &K) -> Result<Self>
+    where
+        K: k8s_openapi::Resource + k8s_openapi::Metadata<Ty = ObjectMeta> + Serialize,
+    {
+        Self::from_resource(Operation::Delete, old_object)
+    }
+
+    /// The operation performed by the request
+    #[must_use]
+    pub fn operation(mut self, operation: Operation) -> Self {
+        self.operation = operation_name(&operation).to_owned();
+        self
+    }
+
+    /// The UID of the request, a hard-coded one is used by default
+    #[must_use]
+    pub fn uid(mut self, uid: &str) -> Self {
+        self.request.uid = uid.to_owned();
+        self
+    }
+
+    /// The resource targeted by the request, like `deployments`
+    #[must_use]
+    pub fn resource(mut self, resource: GroupVersionResource) -> Self {
+        self.request.request_resource = Some(resource.clone());
+        self.request.resource = resource;
+        self
+    }
+
+    /// The sub-resource targeted by the request, like `status` or `scale`
+    #[must_use]
+    pub fn sub_resource(mut self, sub_resource: &str) -> Self {
+        self.request.sub_resource = Some(sub_resource.to_owned());
+        self.request.request_sub_resource = Some(sub_resource.to_owned());
+        self
+    }
+
+    /// The name of the object targeted by the request
+    #[must_use]
+    pub fn name(mut self, name: &str) -> Self {
+        self.request.name = Some(name.to_owned());
+        self
+    }
+
+    /// The Namespace of the object targeted by the request
+    #[must_use]
+    pub fn namespace(mut self, namespace: &str) -> Self {
+        self.request.namespace = Some(namespace.to_owned());
+        self
+    }
+
+    /// The object after the operation, given to `CREATE` and `UPDATE` requests
+    #[must_use]
+    pub fn object(mut self, object: serde_json::Value) -> Self {
+        self.request.object = Some(RawExtension(object));
+        self
+    }
+
+    /// The object before the operation, given to `UPDATE` and `DELETE` requests
+    #[must_use]
+    pub fn old_object(mut self, old_object: serde_json::Value) -> Self {
+        self.request.old_object = Some(RawExtension(old_object));
+        self
+    }
+
+    /// The user making the request
+    #[must_use]
+    pub fn user_info(mut self, user_info: UserInfo) -> Self {
+        self.request.user_info = user_info;
+        self
+    }
+
+    /// The name of the user making the request, the groups of the user are kept
+    #[must_use]
+    pub fn username(mut self, username: &str) -> Self {
+        self.request.user_info.username = Some(username.to_owned());
+        self
+    }
+
+    /// The groups of the user making the request
+    #[must_use]
+    pub fn groups(mut self, groups: &[&str]) -> Self {
+        self.request.user_info.groups = Some(groups.iter().map(|g| g.to_string()).collect());
+        self
+    }
+
+    /// Whether the changes of the request are not going to be persisted
+    #[must_use]
+    pub fn dry_run(mut self, dry_run: bool) -> Self {
+        self.request.dry_run = Some(dry_run);
+        self
+    }
+
+    /// The options of the operation, like a `CreateOptions` or a `DeleteOptions` object
+    #[must_use]
+    pub fn options(mut self, options: serde_json::Value) -> Self {
+        self.request.options = Some(RawExtension(options));
+        self
+    }
+
+    /// Create the `AdmissionRequest`, ensuring it provides the objects required by
+    /// its operation
+    pub fn build(self) -> Result<AdmissionRequest> {
+        let operation = Operation::try_from(self.operation.as_str())
+            .ok()
+            .filter(|operation| *operation != Operation::All)
+            .ok_or_else(|| {
+                AdmissionRequestBuilderError::InvalidOperation(self.operation.clone())
+            })?;
+
+        if matches!(operation, Operation::Create | Operation::Update)
+            && self.request.object.is_none()
+        {
+            return Err(AdmissionRequestBuilderError::MissingObject(self.operation));
+        }
+        if matches!(operation, Operation::Update | Operation::Delete)
+            && self.request.old_object.is_none()
+        {
+            return Err(AdmissionRequestBuilderError::MissingOldObject(
+                self.operation,
+            ));
+        }
+
+        Ok(AdmissionRequest {
+            operation: self.operation,
+            ..self.request
+        })
+    }
+}
+
+impl From<AdmissionRequest> for AdmissionRequestBuilder {
+    fn from(request: AdmissionRequest) -> Self {
+        Self {
+            operation: request.operation.clone(),
+            request,
+        }
+    }
+}
+
+fn operation_name(operation: &Operation) -> &'static str {
+    match operation {
+        Operation::Create => "CREATE",
+        Operation::Update => "UPDATE",
+        Operation::Delete => "DELETE",
+        Operation::Connect => "CONNECT",
+        Operation::All => "*",
+    }
+}
+
+fn to_json(object: &impl Serialize) -> Result<serde_json::Value> {
+    serde_json::to_value(object).map_err(AdmissionRequestBuilderError::Serialize)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use k8s_openapi::api::{apps::v1::Deployment, core::v1::Pod};
+    use serde_json::json;
+
+    fn pod(image: &str) -> Pod {
+        serde_json::from_value(json!({
+            "apiVersion": "v1",
+            "kind": "Pod",
+            "metadata": { "name": "nginx", "namespace": "team-a" },
+            "spec": { "containers": [ { "name": "nginx", "image": image } ] },
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn create_request_of_typed_object() {
+        let request = AdmissionRequestBuilder::create(&pod("nginx:1.27"))
+            .unwrap()
+            .build()
+            .unwrap();
+
+        assert_eq!(request.uid, DEFAULT_ADMISSION_REQUEST_UID);
+        assert_eq!(request.operation, "CREATE");
+        assert_eq!(request.kind.group, "");
+        assert_eq!(request.kind.version, "v1");
+        assert_eq!(request.kind.kind, "Pod");
+        assert_eq!(request.resource.resource, "pods");
+        assert_eq!(request.request_kind, Some(request.kind.clone()));
+        assert_eq!(request.name.as_deref(), Some("nginx"));
+        assert_eq!(request.namespace.as_deref(), Some("team-a"));
+        assert_eq!(request.dry_run, Some(false));
+        assert!(request.old_object.is_none());
+
+        let object = request.object.unwrap().0;
+        assert_eq!(object["apiVersion"], "v1");
+        assert_eq!(object["kind"], "Pod");
+        assert_eq!(object["spec"]["containers"][0]["image"], "nginx:1.27");
+    }
+
+    #[test]
+    fn update_request_of_typed_object() {
+        let request = AdmissionRequestBuilder::update(&pod("nginx:1.27"), &pod("nginx:1.26"))
+            .unwrap()
+            .username("alice")
+            .groups(&["developers"])
+            .dry_run(true)
+            .options(json!({"apiVersion": "meta.k8s.io/v1", "kind": "UpdateOptions"}))
+            .build()
+            .unwrap();
+
+        assert_eq!(request.operation, "UPDATE");
+        assert_eq!(
+            request.object.unwrap().0["spec"]["containers"][0]["image"],
+            "nginx:1.27"
+        );
+        assert_eq!(
+            request.old_object.unwrap().0["spec"]["containers"][0]["image"],
+            "nginx:1.26"
+        );
+        assert_eq!(request.user_info.username.as_deref(), Some("alice"));
+        assert_eq!(
+            request.user_info.groups,
+            Some(vec!["developers".to_owned()])
+        );
+        assert_eq!(request.dry_run, Some(true));
+        assert_eq!(request.options.unwrap().0["kind"], "UpdateOptions");
+    }
+
+    #[test]
+    fn delete_request_of_typed_object() {
+        let deployment = Deployment {
+            metadata: ObjectMeta {
+                name: Some("api".to_owned()),
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+
+        let request = AdmissionRequestBuilder::delete(&deployment)
+            .unwrap()
+            .build()
+            .unwrap();
+
+        assert_eq!(request.operation, "DELETE");
+        assert_eq!(request.kind.group, "apps");
+        assert_eq!(request.resource.resource, "deployments");
+        assert_eq!(request.name.as_deref(), Some("api"));
+        assert!(request.namespace.is_none());
+        assert!(request.object.is_none());
+        assert_eq!(request.old_object.unwrap().0["kind"], "Deployment");
+    }
+
+    #[test]
+    fn request_of_json_object() {
+        let request = AdmissionRequestBuilder::from_json(
+            Operation::Create,
+            json!({
+                "apiVersion": "apps/v1",
+                "kind": "StatefulSet",
+                "metadata": { "name": "web" },
+            }),
+        )
+        .unwrap()
+        .namespace("team-b")
+        .build()
+        .unwrap();
+
+        assert_eq!(request.kind.group, "apps");
+        assert_eq!(request.kind.kind, "StatefulSet");
+        assert_eq!(request.resource.resource, "statefulsets");
+        assert_eq!(request.name.as_deref(), Some("web"));
+        assert_eq!(request.namespace.as_deref(), Some("team-b"));
+
+        assert!(matches!(
+            AdmissionRequestBuilder::from_json(Operation::Create, json!({"kind": "Pod"})),
+            Err(AdmissionRequestBuilderError::InvalidObject("apiVersion"))
+        ));
+    }
+
+    #[test]
+    fn change_existing_request() {
+        let request = AdmissionRequestBuilder::create(&pod("nginx:1.27"))
+            .unwrap()
+            .build()
+            .unwrap();
+
+        let request = AdmissionRequestBuilder::from(request)
+            .operation(Operation::Update)
+            .old_object(serde_json::to_value(pod("nginx:1.26")).unwrap())
+            .sub_resource("status")
+            .uid("1234")
+            .build()
+            .unwrap();
+
+        assert_eq!(request.operation, "UPDATE");
+        assert_eq!(request.uid, "1234");
+        assert_eq!(request.sub_resource.as_deref(), Some("status"));
+        assert_eq!(request.request_sub_resource.as_deref(), Some("status"));
+        assert!(request.object.is_some());
+        assert!(request.old_object.is_some());
+    }
+
+    #[test]
+    fn missing_objects() {
+        let kind = GroupVersionKind {
+            group: String::new(),
+            version: "v1".to_owned(),
+            kind: "Pod".to_owned(),
+        };
+        let resource = GroupVersionResource {
+            group: String::new(),
+            version: "v1".to_owned(),
+            resource: "pods".to_owned(),
+        };
+        let builder =
+            |operation| AdmissionRequestBuilder::new(operation, kind.clone(), resource.clone());
+
+        assert!(matches!(
+            builder(Operation::Create).build(),
+            Err(AdmissionRequestBuilderError::MissingObject(_))
+        ));
+        assert!(matches!(
+            builder(Operation::Update).object(json!({})).build(),
+            Err(AdmissionRequestBuilderError::MissingOldObject(_))
+        ));
+        assert!(matches!(
+            builder(Operation::All).build(),
+            Err(AdmissionRequestBuilderError::InvalidOperation(_))
+        ));
+        assert!(builder(Operation::Connect).build().is_ok());
+    }
+}
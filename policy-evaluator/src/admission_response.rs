@@ -89,6 +89,14 @@ pub struct AdmissionResponseStatus {
     pub code: Option<u16>,
 }
 
+/// The prefix of the message of the responses produced when the evaluation of a
+/// policy fails
+const INTERNAL_SERVER_ERROR_MESSAGE_PREFIX: &str = "internal server error: ";
+
+/// The prefix of the message of the responses produced when the evaluation of a
+/// policy is interrupted because it exceeded its timeout
+const TIMEOUT_MESSAGE_PREFIX: &str = "policy evaluation timed out: ";
+
 impl AdmissionResponse {
     pub fn reject(uid: String, message: String, code: u16) -> AdmissionResponse {
         AdmissionResponse {
@@ -104,7 +112,41 @@ impl AdmissionResponse {
     }
 
     pub fn reject_internal_server_error(uid: String, message: String) -> AdmissionResponse {
-        AdmissionResponse::reject(uid, format!("internal server error: {message}"), 500)
+        AdmissionResponse::reject(
+            uid,
+            format!("{INTERNAL_SERVER_ERROR_MESSAGE_PREFIX}{message}"),
+            500,
+        )
+    }
+
+    /// Reject the request because the evaluation of the policy exceeded its timeout.
+    /// The response uses the `504` code, to tell it apart from the other errors
+    pub fn reject_timeout(uid: String, message: String) -> AdmissionResponse {
+        AdmissionResponse::reject(uid, format!("{TIMEOUT_MESSAGE_PREFIX}{message}"), 504)
+    }
+
+    /// Returns true when the response has been produced because the evaluation of
+    /// the policy failed, for example because the guest trapped or ran out of time,
+    /// instead of being a decision taken by the policy
+    pub fn is_internal_server_error(&self) -> bool {
+        self.has_status(500, INTERNAL_SERVER_ERROR_MESSAGE_PREFIX) || self.is_timeout()
+    }
+
+    /// Returns true when the response has been produced because the evaluation of
+    /// the policy exceeded its timeout
+    pub fn is_timeout(&self) -> bool {
+        self.has_status(504, TIMEOUT_MESSAGE_PREFIX)
+    }
+
+    fn has_status(&self, code: u16, message_prefix: &str) -> bool {
+        !self.allowed
+            && self.status.as_ref().is_some_and(|status| {
+                status.code == Some(code)
+                    && status
+                        .message
+                        .as_deref()
+                        .is_some_and(|message| message.starts_with(message_prefix))
+            })
     }
 
     pub fn from_policy_validation_response(
@@ -420,6 +462,48 @@ mod tests {
         assert_eq!(status.message, Some(message));
     }
 
+    #[test]
+    fn detect_internal_server_error() {
+        let uid = String::from("UID");
+
+        assert!(AdmissionResponse::reject_internal_server_error(
+            uid.clone(),
+            String::from("wasm trap: interrupt")
+        )
+        .is_internal_server_error());
+        // a rejection decided by the policy, even if it uses the same code
+        assert!(
+            !AdmissionResponse::reject(uid.clone(), String::from("boom"), 500)
+                .is_internal_server_error()
+        );
+        assert!(!AdmissionResponse {
+            uid,
+            allowed: true,
+            ..Default::default()
+        }
+        .is_internal_server_error());
+    }
+
+    #[test]
+    fn detect_timeout() {
+        let uid = String::from("UID");
+
+        let response = AdmissionResponse::reject_timeout(
+            uid.clone(),
+            String::from("guest code interrupted, execution deadline exceeded"),
+        );
+        assert!(response.is_timeout());
+        assert!(response.is_internal_server_error());
+        assert_eq!(response.status.unwrap().code, Some(504));
+
+        assert!(!AdmissionResponse::reject_internal_server_error(
+            uid.clone(),
+            String::from("wasm trap: unreachable")
+        )
+        .is_timeout());
+        assert!(!AdmissionResponse::reject(uid, String::from("slow"), 504).is_timeout());
+    }
+
     #[test]
     fn create_from_policy_validation_response_and_mutated_object_is_none() {
         let uid = String::from("UID");
@@ -4,6 +4,7 @@ use crate::admission_response::{
 use tracing::info;
 
 pub mod errors;
+pub mod failure_policy;
 pub mod policy_id;
 pub mod policy_mode;
 
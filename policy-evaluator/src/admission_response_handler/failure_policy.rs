@@ -0,0 +1,21 @@
+use serde::Deserialize;
+
+/// How a policy reacts when its evaluation fails because of a runtime error, like
+/// the guest trapping or exceeding its timeout
+#[derive(Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum FailurePolicy {
+    /// The request is rejected (fail-closed)
+    #[default]
+    Fail,
+    /// The request is accepted, with a warning reporting the error (fail-open)
+    Ignore,
+}
+
+impl From<FailurePolicy> for String {
+    fn from(failure_policy: FailurePolicy) -> String {
+        match failure_policy {
+            FailurePolicy::Fail => String::from("Fail"),
+            FailurePolicy::Ignore => String::from("Ignore"),
+        }
+    }
+}
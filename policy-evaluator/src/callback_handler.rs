@@ -1,25 +1,42 @@
-use std::sync::Arc;
+use std::{collections::HashMap, sync::Arc, time::Duration};
 
 use anyhow::anyhow;
-use kubewarden_policy_sdk::host_capabilities::net::LookupResponse;
 use tokio::sync::{mpsc, oneshot};
-use tracing::{debug, warn};
+use tracing::{debug, error, warn};
 
 use crate::callback_requests::{CallbackRequest, CallbackRequestType, CallbackResponse};
+use crate::evaluation_context::KubernetesServiceAccount;
 
 mod builder;
 mod crypto;
+mod http;
+mod key_value_store;
 mod kubernetes;
+mod net;
 mod oci;
+mod response_size;
 mod sigstore_verification;
 
 pub use builder::CallbackHandlerBuilder;
 pub(crate) use crypto::verify_certificate;
+pub use http::HttpSendResponse;
+pub use key_value_store::{
+    KeyValueDeleteResponse, KeyValueGetResponse, KeyValueIncrementResponse, KeyValueStoreConfig,
+};
+pub(crate) use kubernetes::subscriptions;
+pub use kubernetes::{
+    KubernetesHealth, KubernetesHealthReporter, ReflectorHealth, RequestCoalescingConfig,
+};
+pub use net::DnsCacheConfig;
+pub use policy_fetcher::registry::ClientPoolConfig;
+pub use response_size::{ResponseSizeLimits, ResponseTooLarge};
+
+use response_size::ResponseSizeCheck;
 
 use sigstore_verification::{
     get_sigstore_certificate_verification_cached, get_sigstore_github_actions_verification_cached,
     get_sigstore_keyless_prefix_verification_cached, get_sigstore_keyless_verification_cached,
-    get_sigstore_pub_key_verification_cached,
+    get_sigstore_pub_key_verification_cached, get_sigstore_server_config_verification_cached,
 };
 
 /// Struct that computes request coming from a Wasm guest.
@@ -27,15 +44,27 @@ use sigstore_verification::{
 /// code in order to be fulfilled.
 pub struct CallbackHandler {
     oci_client: Arc<oci::Client>,
+    resolver: Arc<net::Resolver>,
+    http_client: http::Client,
     sigstore_client: sigstore_verification::Client,
     kubernetes_client: Option<kubernetes::Client>,
+    /// The configuration used to create the clients impersonating the
+    /// Service Accounts of the policies
+    kube_impersonation_config: Option<kube::Config>,
+    /// The clients impersonating the Service Accounts of the policies,
+    /// created when the first request of the Service Account is handled
+    impersonating_kubernetes_clients: HashMap<KubernetesServiceAccount, kubernetes::Client>,
+    kubernetes_coalescers: Arc<kubernetes::RequestCoalescers>,
+    /// The key/value store offered to the policies, `None` when not enabled
+    key_value_store: Option<Arc<key_value_store::KeyValueStore>>,
+    response_size_limits: ResponseSizeLimits,
     rx: mpsc::Receiver<CallbackRequest>,
     tx: mpsc::Sender<CallbackRequest>,
     shutdown_channel: oneshot::Receiver<()>,
 }
 
 macro_rules! handle_callback {
-    ($req:expr, $log_value: expr, $log_msg: expr, $code:block) => {{
+    ($req:expr, $size_check:expr, $log_value: expr, $log_msg: expr, $code:block) => {{
         let response = { $code }
             .await
             .map(|response| {
@@ -46,6 +75,7 @@ macro_rules! handle_callback {
                 );
                 let payload = serde_json::to_vec(&response.value)
                     .map_err(|e| anyhow!("error serializing payload: {e:?}"))?;
+                $size_check.check(payload.len())?;
                 Ok(CallbackResponse { payload })
             })
             .and_then(|r| r);
@@ -66,6 +96,14 @@ impl CallbackHandler {
         self.tx.clone()
     }
 
+    /// Returns a handle that reports the health of the Kubernetes integration.
+    /// `None` is returned when the handler has not been given a Kubernetes client.
+    pub fn kubernetes_health_reporter(&self) -> Option<KubernetesHealthReporter> {
+        self.kubernetes_client
+            .clone()
+            .map(KubernetesHealthReporter::new)
+    }
+
     /// Enter an endless loop that:
     ///    1. Waits for requests to be evaluated
     ///    2. Evaluate the request
@@ -90,69 +128,151 @@ impl CallbackHandler {
         }
     }
 
+    /// Returns the client to be used for the requests made with the given Service Account.
+    /// The client of the host is returned when no Service Account is provided.
+    ///
+    /// The client of the host is never used as a fallback: `None` is returned when the
+    /// client impersonating the Service Account cannot be created
+    fn kubernetes_client_for(
+        &mut self,
+        service_account: Option<&KubernetesServiceAccount>,
+    ) -> Option<kubernetes::Client> {
+        let service_account = match service_account {
+            Some(service_account) => service_account,
+            None => return self.kubernetes_client.clone(),
+        };
+
+        if let Some(client) = self.impersonating_kubernetes_clients.get(service_account) {
+            return Some(client.clone());
+        }
+
+        let config = match &self.kube_impersonation_config {
+            Some(config) => config.clone(),
+            None => {
+                error!(
+                    %service_account,
+                    "cannot impersonate Service Account: Kubernetes configuration not provided"
+                );
+                return None;
+            }
+        };
+        match kubernetes::Client::impersonating(config, service_account) {
+            Ok(client) => {
+                self.impersonating_kubernetes_clients
+                    .insert(service_account.to_owned(), client.clone());
+                Some(client)
+            }
+            Err(e) => {
+                error!(%service_account, error = %e, "cannot impersonate Service Account");
+                None
+            }
+        }
+    }
+
     async fn handle_request(&mut self, req: CallbackRequest) {
         let oci_client = self.oci_client.clone();
+        let resolver = self.resolver.clone();
+        let http_client = self.http_client.clone();
         let mut sigstore_client = self.sigstore_client.clone();
-        let mut kubernetes_client = self.kubernetes_client.clone();
+        let mut kubernetes_client =
+            self.kubernetes_client_for(req.kubernetes_service_account.as_ref());
+        // The requests made with different identities are never coalesced together
+        let kubernetes_user = kubernetes_client
+            .as_ref()
+            .and_then(|client| client.impersonated_user().map(str::to_owned));
+        let kubernetes_coalescers = self.kubernetes_coalescers.clone();
+        let key_value_store = self.key_value_store.clone();
+        let response_size_check = ResponseSizeCheck::new(&req.request, &self.response_size_limits);
 
         tokio::spawn(async move {
             match req.request {
                 CallbackRequestType::OciManifestDigest { image } => {
-                    handle_callback!(req, image, "Image digest computed", {
+                    handle_callback!(req, response_size_check, image, "Image digest computed", {
                         oci::get_oci_digest_cached(&oci_client, &image)
                     });
                 }
-                CallbackRequestType::OciManifest { image } => {
-                    handle_callback!(req, image, "Image manifest computed", {
-                        oci::get_oci_manifest_cached(&oci_client, &image)
+                CallbackRequestType::OciResolveDigest { image } => {
+                    handle_callback!(req, response_size_check, image, "Image digest resolved", {
+                        oci::get_oci_resolved_digest_cached(&oci_client, &image)
                     });
                 }
+                CallbackRequestType::OciManifest { image } => {
+                    handle_callback!(
+                        req,
+                        response_size_check,
+                        image,
+                        "Image manifest computed",
+                        { oci::get_oci_manifest_cached(&oci_client, &image) }
+                    );
+                }
                 CallbackRequestType::OciManifestAndConfig { image } => {
-                    handle_callback!(req, image, "Image manifest computed", {
-                        oci::get_oci_manifest_and_config_cached(&oci_client, &image)
-                    });
+                    handle_callback!(
+                        req,
+                        response_size_check,
+                        image,
+                        "Image manifest computed",
+                        { oci::get_oci_manifest_and_config_cached(&oci_client, &image) }
+                    );
                 }
                 CallbackRequestType::SigstorePubKeyVerify {
                     image,
                     pub_keys,
                     annotations,
                 } => {
-                    handle_callback!(req, image, "Sigstore pub key verification done", {
-                        get_sigstore_pub_key_verification_cached(
-                            &mut sigstore_client,
-                            image.clone(),
-                            pub_keys,
-                            annotations,
-                        )
-                    });
+                    handle_callback!(
+                        req,
+                        response_size_check,
+                        image,
+                        "Sigstore pub key verification done",
+                        {
+                            get_sigstore_pub_key_verification_cached(
+                                &mut sigstore_client,
+                                image.clone(),
+                                pub_keys,
+                                annotations,
+                            )
+                        }
+                    );
                 }
                 CallbackRequestType::SigstoreKeylessVerify {
                     image,
                     keyless,
                     annotations,
                 } => {
-                    handle_callback!(req, image, "Sigstore keyless verification done", {
-                        get_sigstore_keyless_verification_cached(
-                            &mut sigstore_client,
-                            image.clone(),
-                            keyless,
-                            annotations,
-                        )
-                    });
+                    handle_callback!(
+                        req,
+                        response_size_check,
+                        image,
+                        "Sigstore keyless verification done",
+                        {
+                            get_sigstore_keyless_verification_cached(
+                                &mut sigstore_client,
+                                image.clone(),
+                                keyless,
+                                annotations,
+                            )
+                        }
+                    );
                 }
                 CallbackRequestType::SigstoreKeylessPrefixVerify {
                     image,
                     keyless_prefix,
                     annotations,
                 } => {
-                    handle_callback!(req, image, "Sigstore keyless prefix verification done", {
-                        get_sigstore_keyless_prefix_verification_cached(
-                            &mut sigstore_client,
-                            image.clone(),
-                            keyless_prefix,
-                            annotations,
-                        )
-                    });
+                    handle_callback!(
+                        req,
+                        response_size_check,
+                        image,
+                        "Sigstore keyless prefix verification done",
+                        {
+                            get_sigstore_keyless_prefix_verification_cached(
+                                &mut sigstore_client,
+                                image.clone(),
+                                keyless_prefix,
+                                annotations,
+                            )
+                        }
+                    );
                 }
                 CallbackRequestType::SigstoreGithubActionsVerify {
                     image,
@@ -160,15 +280,21 @@ impl CallbackHandler {
                     repo,
                     annotations,
                 } => {
-                    handle_callback!(req, image, "Sigstore GitHub Action verification done", {
-                        get_sigstore_github_actions_verification_cached(
-                            &mut sigstore_client,
-                            image.clone(),
-                            owner,
-                            repo,
-                            annotations,
-                        )
-                    });
+                    handle_callback!(
+                        req,
+                        response_size_check,
+                        image,
+                        "Sigstore GitHub Action verification done",
+                        {
+                            get_sigstore_github_actions_verification_cached(
+                                &mut sigstore_client,
+                                image.clone(),
+                                owner,
+                                repo,
+                                annotations,
+                            )
+                        }
+                    );
                 }
                 CallbackRequestType::SigstoreCertificateVerify {
                     image,
@@ -177,32 +303,64 @@ impl CallbackHandler {
                     require_rekor_bundle,
                     annotations,
                 } => {
-                    handle_callback!(req, image, "Sigstore GitHub Action verification done", {
-                        get_sigstore_certificate_verification_cached(
-                            &mut sigstore_client,
-                            &image,
-                            &certificate,
-                            certificate_chain.as_deref(),
-                            require_rekor_bundle,
-                            annotations,
-                        )
-                    })
+                    handle_callback!(
+                        req,
+                        response_size_check,
+                        image,
+                        "Sigstore GitHub Action verification done",
+                        {
+                            get_sigstore_certificate_verification_cached(
+                                &mut sigstore_client,
+                                &image,
+                                &certificate,
+                                certificate_chain.as_deref(),
+                                require_rekor_bundle,
+                                annotations,
+                            )
+                        }
+                    )
+                }
+                CallbackRequestType::SigstoreServerConfigVerify { image } => {
+                    handle_callback!(
+                        req,
+                        response_size_check,
+                        image,
+                        "Sigstore verification against server config done",
+                        {
+                            get_sigstore_server_config_verification_cached(
+                                &mut sigstore_client,
+                                image.clone(),
+                            )
+                        }
+                    )
                 }
                 CallbackRequestType::DNSLookupHost { host } => {
-                    let response = dns_lookup::lookup_host(&host)
-                        .map(|ips| {
-                            let res = LookupResponse {
-                                ips: ips.iter().map(|ip| ip.to_string()).collect(),
-                            };
-                            CallbackResponse {
-                                payload: serde_json::to_vec(&res).unwrap(),
-                            }
-                        })
-                        .map_err(anyhow::Error::new);
-
-                    if let Err(e) = req.response_channel.send(response) {
-                        warn!("callback handler: cannot send response back: {:?}", e);
-                    }
+                    handle_callback!(req, response_size_check, host, "DNS lookup done", {
+                        resolver.lookup_host(&host)
+                    })
+                }
+                CallbackRequestType::HttpSend {
+                    method,
+                    url,
+                    headers,
+                    body,
+                    timeout_ms,
+                } => {
+                    handle_callback!(
+                        req,
+                        response_size_check,
+                        format!("{method} {url}"),
+                        "HTTP request done",
+                        {
+                            http_client.send(
+                                &method,
+                                &url,
+                                &headers,
+                                body,
+                                Duration::from_millis(timeout_ms),
+                            )
+                        }
+                    )
                 }
                 CallbackRequestType::KubernetesListResourceNamespace {
                     api_version,
@@ -213,16 +371,57 @@ impl CallbackHandler {
                 } => {
                     handle_callback!(
                         req,
+                        response_size_check,
                         format!("[{namespace}] {api_version}/{kind}"),
                         "List namespaced Kubernetes resource",
                         {
-                            kubernetes::list_resources_by_namespace(
-                                kubernetes_client.as_mut(),
-                                &api_version,
-                                &kind,
-                                &namespace,
-                                label_selector,
-                                field_selector,
+                            let key = format!(
+                                "list_resources_by_namespace({kubernetes_user:?},{api_version},{kind},{namespace},{label_selector:?},{field_selector:?})"
+                            );
+                            kubernetes_coalescers.lists.run(
+                                key,
+                                "list_resources_by_namespace",
+                                kubernetes::list_resources_by_namespace(
+                                    kubernetes_client.as_mut(),
+                                    &api_version,
+                                    &kind,
+                                    &namespace,
+                                    label_selector,
+                                    field_selector,
+                                ),
+                            )
+                        }
+                    )
+                }
+                CallbackRequestType::KubernetesListResourceNamespaces {
+                    api_version,
+                    kind,
+                    namespaces,
+                    namespace_selector,
+                    label_selector,
+                    field_selector,
+                } => {
+                    handle_callback!(
+                        req,
+                        response_size_check,
+                        format!("[{}] {api_version}/{kind}", namespaces.join(",")),
+                        "List Kubernetes resource across namespaces",
+                        {
+                            let key = format!(
+                                "list_resources_by_namespaces({kubernetes_user:?},{api_version},{kind},{namespaces:?},{namespace_selector:?},{label_selector:?},{field_selector:?})"
+                            );
+                            kubernetes_coalescers.lists.run(
+                                key,
+                                "list_resources_by_namespaces",
+                                kubernetes::list_resources_by_namespaces(
+                                    kubernetes_client.as_mut(),
+                                    &api_version,
+                                    &kind,
+                                    &namespaces,
+                                    namespace_selector,
+                                    label_selector,
+                                    field_selector,
+                                ),
                             )
                         }
                     )
@@ -235,15 +434,23 @@ impl CallbackHandler {
                 } => {
                     handle_callback!(
                         req,
+                        response_size_check,
                         format!("{api_version}/{kind}"),
                         "List Kubernetes resource",
                         {
-                            kubernetes::list_resources_all(
-                                kubernetes_client.as_mut(),
-                                &api_version,
-                                &kind,
-                                label_selector,
-                                field_selector,
+                            let key = format!(
+                                "list_resources_all({kubernetes_user:?},{api_version},{kind},{label_selector:?},{field_selector:?})"
+                            );
+                            kubernetes_coalescers.lists.run(
+                                key,
+                                "list_resources_all",
+                                kubernetes::list_resources_all(
+                                    kubernetes_client.as_mut(),
+                                    &api_version,
+                                    &kind,
+                                    label_selector,
+                                    field_selector,
+                                ),
                             )
                         }
                     )
@@ -258,21 +465,30 @@ impl CallbackHandler {
                     if disable_cache {
                         handle_callback!(
                             req,
+                            response_size_check,
                             format!("{api_version}/{kind}"),
                             "Get Kubernetes resource - no cache",
                             {
-                                kubernetes::get_resource(
-                                    kubernetes_client.as_mut(),
-                                    &api_version,
-                                    &kind,
-                                    &name,
-                                    namespace.as_deref(),
+                                let key = format!(
+                                    "get_resource({kubernetes_user:?},{api_version},{kind},{name},{namespace:?})"
+                                );
+                                kubernetes_coalescers.gets.run(
+                                    key,
+                                    "get_resource",
+                                    kubernetes::get_resource(
+                                        kubernetes_client.as_mut(),
+                                        &api_version,
+                                        &kind,
+                                        &name,
+                                        namespace.as_deref(),
+                                    ),
                                 )
                             }
                         )
                     } else {
                         handle_callback!(
                             req,
+                            response_size_check,
                             format!("{api_version}/{kind}"),
                             "Get Kubernetes resource",
                             {
@@ -290,6 +506,7 @@ impl CallbackHandler {
                 CallbackRequestType::KubernetesGetResourcePluralName { api_version, kind } => {
                     handle_callback!(
                         req,
+                        response_size_check,
                         format!("{api_version}/{kind}"),
                         "Get Kubernetes resource plural name",
                         {
@@ -310,6 +527,7 @@ impl CallbackHandler {
                 } => {
                     handle_callback!(
                         req,
+                        response_size_check,
                         format!("{api_version}/{kind}"),
                         "Has the result of 'Kubernetes list all resources' changed since a given instant",
                         {
@@ -324,6 +542,28 @@ impl CallbackHandler {
                         }
                     )
                 }
+                CallbackRequestType::SubscribeKubernetesListResourceAllChanges {
+                    api_version,
+                    kind,
+                    label_selector,
+                    field_selector,
+                } => {
+                    handle_callback!(
+                        req,
+                        response_size_check,
+                        format!("{api_version}/{kind}"),
+                        "Subscribe to the changes of the result of 'Kubernetes list all resources'",
+                        {
+                            kubernetes::subscribe_to_list_resources_all_changes(
+                                kubernetes_client.as_mut(),
+                                &api_version,
+                                &kind,
+                                label_selector,
+                                field_selector,
+                            )
+                        }
+                    )
+                }
                 CallbackRequestType::KubernetesCanI {
                     request,
                     disable_cache,
@@ -331,6 +571,7 @@ impl CallbackHandler {
                     if disable_cache {
                         handle_callback!(
                             req,
+                            response_size_check,
                             "can_i".to_owned(),
                             "Check if user or service account has permission to perform operation",
                             { kubernetes::can_i(kubernetes_client.as_mut(), request) }
@@ -338,12 +579,97 @@ impl CallbackHandler {
                     } else {
                         handle_callback!(
                             req,
+                            response_size_check,
                             "can_i".to_owned(),
                             "Check if user or service account has permission to perform operation",
                             { kubernetes::can_i_cached(kubernetes_client.as_mut(), request) }
                         )
                     }
                 }
+                CallbackRequestType::KeyValueGet {
+                    policy_id,
+                    namespace,
+                    key,
+                } => {
+                    handle_callback!(
+                        req,
+                        response_size_check,
+                        format!("{policy_id}/{namespace}/{key}"),
+                        "Key/value store lookup done",
+                        {
+                            key_value_store::run(key_value_store.as_deref(), |store| {
+                                store.get(&policy_id, &namespace, &key)
+                            })
+                        }
+                    )
+                }
+                CallbackRequestType::KeyValueSet {
+                    policy_id,
+                    namespace,
+                    key,
+                    value,
+                    ttl_seconds,
+                } => {
+                    handle_callback!(
+                        req,
+                        response_size_check,
+                        format!("{policy_id}/{namespace}/{key}"),
+                        "Key/value store entry set",
+                        {
+                            key_value_store::run(key_value_store.as_deref(), |store| {
+                                store.set(
+                                    &policy_id,
+                                    &namespace,
+                                    &key,
+                                    value,
+                                    ttl_seconds.map(Duration::from_secs),
+                                )
+                            })
+                        }
+                    )
+                }
+                CallbackRequestType::KeyValueDelete {
+                    policy_id,
+                    namespace,
+                    key,
+                } => {
+                    handle_callback!(
+                        req,
+                        response_size_check,
+                        format!("{policy_id}/{namespace}/{key}"),
+                        "Key/value store entry deleted",
+                        {
+                            key_value_store::run(key_value_store.as_deref(), |store| {
+                                store.delete(&policy_id, &namespace, &key)
+                            })
+                        }
+                    )
+                }
+                CallbackRequestType::KeyValueIncrement {
+                    policy_id,
+                    namespace,
+                    key,
+                    delta,
+                    ttl_seconds,
+                } => {
+                    handle_callback!(
+                        req,
+                        response_size_check,
+                        format!("{policy_id}/{namespace}/{key}"),
+                        "Key/value store counter incremented",
+                        {
+                            key_value_store::run(key_value_store.as_deref(), |store| {
+                                store.increment(
+                                    &policy_id,
+                                    &namespace,
+                                    &key,
+                                    delta,
+                                    ttl_seconds.map(Duration::from_secs),
+                                )
+                            })
+                        }
+                    )
+                }
             }
         });
     }
@@ -1,11 +1,13 @@
 use anyhow::Result;
+use policy_fetcher::registry::ClientPoolConfig;
 use policy_fetcher::sigstore::trust::ManualTrustRoot;
 use policy_fetcher::sources::Sources;
-use std::sync::Arc;
+use policy_fetcher::verify::config::LatestVerificationConfig;
+use std::{collections::HashMap, sync::Arc};
 use tokio::sync::{mpsc, oneshot};
 
-use super::CallbackHandler;
-use super::{oci, sigstore_verification};
+use super::{http, key_value_store, kubernetes, net, oci, sigstore_verification};
+use super::{CallbackHandler, ResponseSizeLimits};
 use crate::callback_requests::CallbackRequest;
 
 const DEFAULT_CHANNEL_BUFF_SIZE: usize = 100;
@@ -16,7 +18,14 @@ pub struct CallbackHandlerBuilder {
     channel_buffer_size: usize,
     shutdown_channel: oneshot::Receiver<()>,
     trust_root: Option<Arc<ManualTrustRoot<'static>>>,
+    verification_config: Option<LatestVerificationConfig>,
     kube_client: Option<kube::Client>,
+    kube_impersonation_config: Option<kube::Config>,
+    client_pool_config: ClientPoolConfig,
+    dns_cache_config: net::DnsCacheConfig,
+    request_coalescing_config: kubernetes::RequestCoalescingConfig,
+    response_size_limits: ResponseSizeLimits,
+    key_value_store_config: Option<key_value_store::KeyValueStoreConfig>,
 }
 
 impl CallbackHandlerBuilder {
@@ -26,7 +35,14 @@ impl CallbackHandlerBuilder {
             shutdown_channel,
             channel_buffer_size: DEFAULT_CHANNEL_BUFF_SIZE,
             trust_root: None,
+            verification_config: None,
             kube_client: None,
+            kube_impersonation_config: None,
+            client_pool_config: ClientPoolConfig::default(),
+            dns_cache_config: net::DnsCacheConfig::default(),
+            request_coalescing_config: kubernetes::RequestCoalescingConfig::default(),
+            response_size_limits: ResponseSizeLimits::default(),
+            key_value_store_config: None,
         }
     }
 
@@ -41,6 +57,17 @@ impl CallbackHandlerBuilder {
         self
     }
 
+    /// Set the verification config of the host. This is used by the policies
+    /// that want to verify OCI objects using the same rules adopted by the host
+    /// to verify policies. Optional
+    pub fn verification_config(
+        mut self,
+        verification_config: Option<LatestVerificationConfig>,
+    ) -> Self {
+        self.verification_config = verification_config;
+        self
+    }
+
     /// Set the size of the channel used by the sync world to communicate with
     /// the CallbackHandler. Optional
     pub fn channel_buffer_size(mut self, size: usize) -> Self {
@@ -56,21 +83,91 @@ impl CallbackHandlerBuilder {
         self
     }
 
+    /// Set the configuration used to create the `kube::Client` objects that
+    /// impersonate the Kubernetes Service Accounts of the policies.
+    /// Optional, the policies that have a Service Account cannot interact
+    /// with the Kubernetes API server when not set
+    pub fn kube_impersonation_config(mut self, config: kube::Config) -> Self {
+        self.kube_impersonation_config = Some(config);
+        self
+    }
+
+    /// Set how the clients used to interact with the OCI registries are reused
+    /// across the requests made by the policies. Optional
+    pub fn client_pool_config(mut self, config: ClientPoolConfig) -> Self {
+        self.client_pool_config = config;
+        self
+    }
+
+    /// Set for how long the results of the DNS lookups made by the policies
+    /// are reused. Optional
+    pub fn dns_cache_config(mut self, config: net::DnsCacheConfig) -> Self {
+        self.dns_cache_config = config;
+        self
+    }
+
+    /// Set how the identical requests made by the policies to the Kubernetes
+    /// API server are batched together. Optional
+    pub fn request_coalescing_config(
+        mut self,
+        config: kubernetes::RequestCoalescingConfig,
+    ) -> Self {
+        self.request_coalescing_config = config;
+        self
+    }
+
+    /// Set the maximum size of the responses given back to the policies by the
+    /// host capabilities. Optional, the responses are not limited by default
+    pub fn response_size_limits(mut self, limits: ResponseSizeLimits) -> Self {
+        self.response_size_limits = limits;
+        self
+    }
+
+    /// Enable the key/value store offered to the policies. Optional, the
+    /// policies cannot use the store when not set
+    pub fn key_value_store_config(
+        mut self,
+        config: Option<key_value_store::KeyValueStoreConfig>,
+    ) -> Self {
+        self.key_value_store_config = config;
+        self
+    }
+
     /// Create a CallbackHandler object
     pub async fn build(self) -> Result<CallbackHandler> {
         let (tx, rx) = mpsc::channel::<CallbackRequest>(self.channel_buffer_size);
-        let oci_client = Arc::new(oci::Client::new(self.oci_sources.clone()));
-        let sigstore_client =
-            sigstore_verification::Client::new(self.oci_sources.clone(), self.trust_root.clone())
-                .await?
-                .to_owned();
+        let oci_client = Arc::new(oci::Client::new(
+            self.oci_sources.clone(),
+            self.client_pool_config,
+        ));
+        let resolver = Arc::new(net::Resolver::new(self.dns_cache_config));
+        let http_client = http::Client::new()?;
+        let sigstore_client = sigstore_verification::Client::new(
+            self.oci_sources.clone(),
+            self.trust_root.clone(),
+            self.verification_config,
+        )
+        .await?
+        .to_owned();
 
-        let kubernetes_client = self.kube_client.map(super::kubernetes::Client::new);
+        let kubernetes_client = self.kube_client.map(kubernetes::Client::new);
+        let kubernetes_coalescers = Arc::new(kubernetes::RequestCoalescers::new(
+            self.request_coalescing_config,
+        ));
 
         Ok(CallbackHandler {
             oci_client,
+            resolver,
+            http_client,
             sigstore_client,
             kubernetes_client,
+            kube_impersonation_config: self.kube_impersonation_config,
+            impersonating_kubernetes_clients: HashMap::new(),
+            kubernetes_coalescers,
+            key_value_store: self
+                .key_value_store_config
+                .map(|config| Arc::new(key_value_store::KeyValueStore::new(config))),
+            response_size_limits: self.response_size_limits,
             tx,
             rx,
             shutdown_channel: self.shutdown_channel,
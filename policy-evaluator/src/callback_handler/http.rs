@@ -0,0 +1,77 @@
+use std::{collections::BTreeMap, time::Duration};
+
+use anyhow::{anyhow, Result};
+use serde::{Deserialize, Serialize};
+
+/// The response to a request made through the `http/send` capability
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct HttpSendResponse {
+    pub status_code: u16,
+    pub headers: BTreeMap<String, String>,
+    pub body: Vec<u8>,
+}
+
+/// Performs the HTTP requests made by the policies. The requests have already
+/// been checked against the allowed origins of the policy, hence the redirects
+/// are never followed: these are handled by the policy runtime, which checks
+/// each location
+#[derive(Clone)]
+pub(crate) struct Client {
+    client: reqwest::Client,
+}
+
+impl Client {
+    pub fn new() -> Result<Self> {
+        let client = reqwest::Client::builder()
+            .redirect(reqwest::redirect::Policy::none())
+            .build()
+            .map_err(|e| anyhow!("cannot create the HTTP client: {e}"))?;
+        Ok(Client { client })
+    }
+
+    pub async fn send(
+        &self,
+        method: &str,
+        url: &str,
+        headers: &BTreeMap<String, String>,
+        body: Option<Vec<u8>>,
+        timeout: Duration,
+    ) -> Result<cached::Return<HttpSendResponse>> {
+        let method = reqwest::Method::from_bytes(method.as_bytes())
+            .map_err(|e| anyhow!("invalid HTTP method {method}: {e}"))?;
+        let mut request = self.client.request(method, url).timeout(timeout);
+        for (name, value) in headers {
+            request = request.header(name, value);
+        }
+        if let Some(body) = body {
+            request = request.body(body);
+        }
+
+        let response = request
+            .send()
+            .await
+            .map_err(|e| anyhow!("cannot perform the HTTP request: {e}"))?;
+        let status_code = response.status().as_u16();
+        let headers = response
+            .headers()
+            .iter()
+            .filter_map(|(name, value)| {
+                value
+                    .to_str()
+                    .ok()
+                    .map(|value| (name.to_string(), value.to_string()))
+            })
+            .collect();
+        let body = response
+            .bytes()
+            .await
+            .map_err(|e| anyhow!("cannot read the HTTP response: {e}"))?
+            .to_vec();
+
+        Ok(cached::Return::new(HttpSendResponse {
+            status_code,
+            headers,
+            body,
+        }))
+    }
+}
@@ -0,0 +1,531 @@
+//! In-memory key/value store offered to the policies.
+//!
+//! The store allows lightweight coordination between the evaluations, like counting
+//! the Namespaces created recently or remembering a previous decision. Each policy
+//! has its own entries, which cannot be accessed by the other policies. The entries
+//! of a policy are grouped by namespaces, chosen by the policy.
+//!
+//! Every entry expires, and the number of namespaces of each policy, the number of
+//! entries of each namespace and the size of the values are capped. The writes
+//! exceeding the caps are refused, the existing entries are never evicted to make
+//! room for new ones.
+//!
+//! The store lives inside of the memory of the host: it's not shared with the other
+//! replicas of Policy Server and it's lost on restart.
+
+use std::{
+    collections::HashMap,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use anyhow::{anyhow, Result};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::metrics::{self, KeyValueWriteRejection};
+
+/// Maximum length, in bytes, of the names of the namespaces and of the keys
+const MAX_NAME_LENGTH: usize = 256;
+
+/// Configuration of the key/value store offered to the policies
+#[derive(Clone, Debug, PartialEq)]
+pub struct KeyValueStoreConfig {
+    /// Maximum number of namespaces of each policy
+    pub max_namespaces: usize,
+    /// Maximum number of entries stored inside of each namespace
+    pub max_entries: usize,
+    /// Maximum size, in bytes, of a value once serialized to JSON
+    pub max_value_size: usize,
+    /// Maximum time to live of the entries. Also applied to the entries written
+    /// without a time to live
+    pub max_ttl: Duration,
+}
+
+impl Default for KeyValueStoreConfig {
+    fn default() -> Self {
+        KeyValueStoreConfig {
+            max_namespaces: 64,
+            max_entries: 1024,
+            max_value_size: 4096,
+            max_ttl: Duration::from_secs(3600),
+        }
+    }
+}
+
+/// Response of the `kv/get` capability
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct KeyValueGetResponse {
+    /// The value of the entry, `None` when the key is not set or has expired
+    pub value: Option<Value>,
+}
+
+/// Response of the `kv/delete` capability
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct KeyValueDeleteResponse {
+    /// Whether the key was set
+    pub deleted: bool,
+}
+
+/// Response of the `kv/increment` capability
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct KeyValueIncrementResponse {
+    /// The value of the counter after the increment
+    pub value: i64,
+}
+
+struct Entry {
+    value: Value,
+    expires_at: Instant,
+}
+
+impl Entry {
+    fn is_expired(&self, now: Instant) -> bool {
+        self.expires_at <= now
+    }
+}
+
+/// The namespaces of a policy, each one holding its entries
+type Namespaces = HashMap<String, HashMap<String, Entry>>;
+
+/// The key/value store, see the module documentation
+pub(crate) struct KeyValueStore {
+    config: KeyValueStoreConfig,
+    /// The namespaces of each policy, keyed by the ID of the policy
+    policies: Mutex<HashMap<String, Namespaces>>,
+}
+
+impl KeyValueStore {
+    pub fn new(config: KeyValueStoreConfig) -> Self {
+        KeyValueStore {
+            config,
+            policies: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub fn get(&self, policy_id: &str, namespace: &str, key: &str) -> Result<KeyValueGetResponse> {
+        validate_name("namespace", namespace)?;
+        validate_name("key", key)?;
+
+        let now = Instant::now();
+        let policies = self.lock();
+        let value = policies
+            .get(policy_id)
+            .and_then(|namespaces| namespaces.get(namespace))
+            .and_then(|entries| entries.get(key))
+            .filter(|entry| !entry.is_expired(now))
+            .map(|entry| entry.value.clone());
+        metrics::record_key_value_lookup(policy_id, namespace, value.is_some());
+
+        Ok(KeyValueGetResponse { value })
+    }
+
+    /// Set the value of the key. The time to live is capped by the configuration
+    pub fn set(
+        &self,
+        policy_id: &str,
+        namespace: &str,
+        key: &str,
+        value: Value,
+        ttl: Option<Duration>,
+    ) -> Result<()> {
+        validate_name("namespace", namespace)?;
+        validate_name("key", key)?;
+        let size = serde_json::to_vec(&value)?.len();
+        if size > self.config.max_value_size {
+            metrics::record_key_value_write_rejection(
+                policy_id,
+                namespace,
+                KeyValueWriteRejection::ValueTooLarge,
+            );
+            return Err(anyhow!(
+                "value of key `{key}` is too large: {size} bytes, the limit is {} bytes",
+                self.config.max_value_size
+            ));
+        }
+
+        let now = Instant::now();
+        let expires_at = now + self.ttl(ttl);
+        let mut policies = self.lock();
+        let entries = self.entries_for_write(&mut policies, policy_id, namespace, key, now)?;
+        entries.insert(key.to_owned(), Entry { value, expires_at });
+        metrics::record_key_value_entries(policy_id, namespace, entries.len());
+
+        Ok(())
+    }
+
+    pub fn delete(
+        &self,
+        policy_id: &str,
+        namespace: &str,
+        key: &str,
+    ) -> Result<KeyValueDeleteResponse> {
+        validate_name("namespace", namespace)?;
+        validate_name("key", key)?;
+
+        let now = Instant::now();
+        let mut policies = self.lock();
+        let Some(entries) = policies
+            .get_mut(policy_id)
+            .and_then(|namespaces| namespaces.get_mut(namespace))
+        else {
+            return Ok(KeyValueDeleteResponse { deleted: false });
+        };
+        let deleted = entries
+            .remove(key)
+            .is_some_and(|entry| !entry.is_expired(now));
+        metrics::record_key_value_entries(policy_id, namespace, entries.len());
+
+        Ok(KeyValueDeleteResponse { deleted })
+    }
+
+    /// Add `delta` to the counter stored under the key. A missing, or expired, counter
+    /// starts from `0` and gets the given time to live; the time to live of an existing
+    /// counter is not changed
+    pub fn increment(
+        &self,
+        policy_id: &str,
+        namespace: &str,
+        key: &str,
+        delta: i64,
+        ttl: Option<Duration>,
+    ) -> Result<KeyValueIncrementResponse> {
+        validate_name("namespace", namespace)?;
+        validate_name("key", key)?;
+
+        let now = Instant::now();
+        let ttl = self.ttl(ttl);
+        let mut policies = self.lock();
+        let entries = self.entries_for_write(&mut policies, policy_id, namespace, key, now)?;
+
+        let (current, expires_at) = match entries.get(key) {
+            Some(entry) if !entry.is_expired(now) => {
+                let current = entry
+                    .value
+                    .as_i64()
+                    .ok_or_else(|| anyhow!("value of key `{key}` is not an integer"))?;
+                (current, entry.expires_at)
+            }
+            _ => (0, now + ttl),
+        };
+        let value = current
+            .checked_add(delta)
+            .ok_or_else(|| anyhow!("counter `{key}` overflows"))?;
+        entries.insert(
+            key.to_owned(),
+            Entry {
+                value: Value::from(value),
+                expires_at,
+            },
+        );
+        metrics::record_key_value_entries(policy_id, namespace, entries.len());
+
+        Ok(KeyValueIncrementResponse { value })
+    }
+
+    fn ttl(&self, requested: Option<Duration>) -> Duration {
+        requested.map_or(self.config.max_ttl, |ttl| ttl.min(self.config.max_ttl))
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, HashMap<String, Namespaces>> {
+        self.policies
+            .lock()
+            .expect("cannot lock the key/value store")
+    }
+
+    /// Return the entries of the namespace of the policy, ensuring there's room for
+    /// the key. The expired entries are dropped when the caps are reached
+    fn entries_for_write<'a>(
+        &self,
+        policies: &'a mut HashMap<String, Namespaces>,
+        policy_id: &str,
+        namespace: &str,
+        key: &str,
+        now: Instant,
+    ) -> Result<&'a mut HashMap<String, Entry>> {
+        let namespaces = policies.entry(policy_id.to_owned()).or_default();
+        if !namespaces.contains_key(namespace) && namespaces.len() >= self.config.max_namespaces {
+            for (name, entries) in namespaces.iter_mut() {
+                drop_expired(policy_id, name, entries, now);
+            }
+            namespaces.retain(|_, entries| !entries.is_empty());
+            if namespaces.len() >= self.config.max_namespaces {
+                metrics::record_key_value_write_rejection(
+                    policy_id,
+                    namespace,
+                    KeyValueWriteRejection::TooManyNamespaces,
+                );
+                return Err(anyhow!(
+                    "cannot create namespace `{namespace}`: the limit of {} namespaces has been reached",
+                    self.config.max_namespaces
+                ));
+            }
+        }
+
+        let entries = namespaces.entry(namespace.to_owned()).or_default();
+        if !entries.contains_key(key) && entries.len() >= self.config.max_entries {
+            drop_expired(policy_id, namespace, entries, now);
+            if entries.len() >= self.config.max_entries {
+                metrics::record_key_value_write_rejection(
+                    policy_id,
+                    namespace,
+                    KeyValueWriteRejection::NamespaceFull,
+                );
+                return Err(anyhow!(
+                    "namespace `{namespace}` is full: the limit of {} entries has been reached",
+                    self.config.max_entries
+                ));
+            }
+        }
+
+        Ok(entries)
+    }
+}
+
+/// Run the operation against the store. Fails when the store has not been enabled
+/// by the host
+pub(crate) async fn run<T>(
+    store: Option<&KeyValueStore>,
+    operation: impl FnOnce(&KeyValueStore) -> Result<T>,
+) -> Result<cached::Return<T>> {
+    let store = store.ok_or_else(|| anyhow!("the key/value store is not enabled on this host"))?;
+    operation(store).map(cached::Return::new)
+}
+
+fn drop_expired(
+    policy_id: &str,
+    namespace: &str,
+    entries: &mut HashMap<String, Entry>,
+    now: Instant,
+) {
+    let before = entries.len();
+    entries.retain(|_, entry| !entry.is_expired(now));
+    let expired = before - entries.len();
+    if expired > 0 {
+        metrics::record_key_value_expirations(policy_id, namespace, expired);
+        metrics::record_key_value_entries(policy_id, namespace, entries.len());
+    }
+}
+
+fn validate_name(kind: &str, name: &str) -> Result<()> {
+    if name.is_empty() {
+        return Err(anyhow!("the {kind} cannot be empty"));
+    }
+    if name.len() > MAX_NAME_LENGTH {
+        return Err(anyhow!(
+            "the {kind} is too long: {} bytes, the limit is {MAX_NAME_LENGTH} bytes",
+            name.len()
+        ));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    const POLICY: &str = "policy";
+
+    #[test]
+    fn values_are_namespaced() {
+        let store = KeyValueStore::new(KeyValueStoreConfig::default());
+
+        store
+            .set(POLICY, "quotas", "team-a", json!({"max": 3}), None)
+            .unwrap();
+
+        assert_eq!(
+            store.get(POLICY, "quotas", "team-a").unwrap().value,
+            Some(json!({"max": 3}))
+        );
+        assert_eq!(store.get(POLICY, "other", "team-a").unwrap().value, None);
+
+        assert!(store.delete(POLICY, "quotas", "team-a").unwrap().deleted);
+        assert!(!store.delete(POLICY, "quotas", "team-a").unwrap().deleted);
+        assert_eq!(store.get(POLICY, "quotas", "team-a").unwrap().value, None);
+    }
+
+    #[test]
+    fn values_are_isolated_per_policy() {
+        let store = KeyValueStore::new(KeyValueStoreConfig {
+            max_namespaces: 1,
+            ..Default::default()
+        });
+
+        store.set("a", "quotas", "team-a", json!(1), None).unwrap();
+
+        assert_eq!(store.get("b", "quotas", "team-a").unwrap().value, None);
+        assert!(!store.delete("b", "quotas", "team-a").unwrap().deleted);
+        assert_eq!(
+            store
+                .increment("b", "quotas", "team-a", 1, None)
+                .unwrap()
+                .value,
+            1
+        );
+        assert_eq!(
+            store.get("a", "quotas", "team-a").unwrap().value,
+            Some(json!(1))
+        );
+
+        // the namespaces of a policy do not count against the other policies
+        assert!(store.set("a", "other", "key", json!(1), None).is_err());
+        assert!(store.set("c", "other", "key", json!(1), None).is_ok());
+    }
+
+    #[test]
+    fn expired_entries_are_not_returned() {
+        let store = KeyValueStore::new(KeyValueStoreConfig::default());
+
+        store
+            .set(
+                POLICY,
+                "decisions",
+                "pod-a",
+                json!(true),
+                Some(Duration::ZERO),
+            )
+            .unwrap();
+
+        assert_eq!(store.get(POLICY, "decisions", "pod-a").unwrap().value, None);
+        assert!(!store.delete(POLICY, "decisions", "pod-a").unwrap().deleted);
+    }
+
+    #[test]
+    fn ttl_is_capped() {
+        let store = KeyValueStore::new(KeyValueStoreConfig {
+            max_ttl: Duration::from_secs(10),
+            ..Default::default()
+        });
+
+        assert_eq!(store.ttl(None), Duration::from_secs(10));
+        assert_eq!(
+            store.ttl(Some(Duration::from_secs(60))),
+            Duration::from_secs(10)
+        );
+        assert_eq!(
+            store.ttl(Some(Duration::from_secs(5))),
+            Duration::from_secs(5)
+        );
+    }
+
+    #[test]
+    fn counters() {
+        let store = KeyValueStore::new(KeyValueStoreConfig::default());
+
+        assert_eq!(
+            store
+                .increment(POLICY, "rate-limit", "namespaces", 1, None)
+                .unwrap()
+                .value,
+            1
+        );
+        assert_eq!(
+            store
+                .increment(POLICY, "rate-limit", "namespaces", 2, None)
+                .unwrap()
+                .value,
+            3
+        );
+
+        store
+            .set(POLICY, "rate-limit", "not-a-counter", json!("a"), None)
+            .unwrap();
+        assert!(store
+            .increment(POLICY, "rate-limit", "not-a-counter", 1, None)
+            .is_err());
+
+        store
+            .set(POLICY, "rate-limit", "max", json!(i64::MAX), None)
+            .unwrap();
+        assert!(store
+            .increment(POLICY, "rate-limit", "max", 1, None)
+            .is_err());
+    }
+
+    #[test]
+    fn expired_counters_start_over() {
+        let store = KeyValueStore::new(KeyValueStoreConfig::default());
+
+        store
+            .increment(POLICY, "rate-limit", "namespaces", 5, Some(Duration::ZERO))
+            .unwrap();
+
+        assert_eq!(
+            store
+                .increment(POLICY, "rate-limit", "namespaces", 1, None)
+                .unwrap()
+                .value,
+            1
+        );
+    }
+
+    #[test]
+    fn values_are_capped() {
+        let store = KeyValueStore::new(KeyValueStoreConfig {
+            max_value_size: 4,
+            ..Default::default()
+        });
+
+        assert!(store.set(POLICY, "ns", "small", json!("ab"), None).is_ok());
+        assert!(store
+            .set(POLICY, "ns", "large", json!("abc"), None)
+            .is_err());
+    }
+
+    #[test]
+    fn entries_are_capped() {
+        let store = KeyValueStore::new(KeyValueStoreConfig {
+            max_entries: 2,
+            ..Default::default()
+        });
+
+        store.set(POLICY, "ns", "a", json!(1), None).unwrap();
+        store.set(POLICY, "ns", "b", json!(2), None).unwrap();
+
+        // existing entries can be updated, new ones are refused
+        assert!(store.set(POLICY, "ns", "a", json!(3), None).is_ok());
+        assert!(store.set(POLICY, "ns", "c", json!(4), None).is_err());
+        assert!(store.increment(POLICY, "ns", "c", 1, None).is_err());
+        assert_eq!(store.get(POLICY, "ns", "a").unwrap().value, Some(json!(3)));
+
+        // the expired entries make room for the new ones
+        store
+            .set(POLICY, "ns", "b", json!(2), Some(Duration::ZERO))
+            .unwrap();
+        assert!(store.set(POLICY, "ns", "c", json!(4), None).is_ok());
+    }
+
+    #[test]
+    fn namespaces_are_capped() {
+        let store = KeyValueStore::new(KeyValueStoreConfig {
+            max_namespaces: 1,
+            ..Default::default()
+        });
+
+        store
+            .set(POLICY, "a", "key", json!(1), Some(Duration::ZERO))
+            .unwrap();
+        // the only namespace holds only expired entries
+        assert!(store.set(POLICY, "b", "key", json!(1), None).is_ok());
+        assert!(store.set(POLICY, "c", "key", json!(1), None).is_err());
+    }
+
+    #[test]
+    fn names_are_validated() {
+        let store = KeyValueStore::new(KeyValueStoreConfig::default());
+
+        assert!(store.get(POLICY, "", "key").is_err());
+        assert!(store.get(POLICY, "ns", "").is_err());
+        assert!(store
+            .set(
+                POLICY,
+                "ns",
+                &"k".repeat(MAX_NAME_LENGTH + 1),
+                json!(1),
+                None
+            )
+            .is_err());
+    }
+}
@@ -1,7 +1,10 @@
 use std::time::Duration;
 
 mod client;
+mod coalescer;
+mod health;
 mod reflector;
+pub(crate) mod subscriptions;
 
 use anyhow::{anyhow, Result};
 use cached::proc_macro::cached;
@@ -11,6 +14,9 @@ use kubewarden_policy_sdk::host_capabilities::kubernetes::SubjectAccessReview as
 use serde::Serialize;
 
 pub(crate) use client::Client;
+use coalescer::RequestCoalescer;
+pub use coalescer::RequestCoalescingConfig;
+pub use health::{KubernetesHealth, KubernetesHealthReporter, ReflectorHealth};
 
 #[derive(Eq, Hash, PartialEq)]
 struct ApiVersionKind {
@@ -24,6 +30,21 @@ pub(crate) struct KubeResource {
     pub namespaced: bool,
 }
 
+/// Coalesce the identical requests made by the policies to the Kubernetes API server
+pub(crate) struct RequestCoalescers {
+    pub lists: RequestCoalescer<ObjectList<kube::core::DynamicObject>>,
+    pub gets: RequestCoalescer<kube::core::DynamicObject>,
+}
+
+impl RequestCoalescers {
+    pub fn new(config: RequestCoalescingConfig) -> Self {
+        RequestCoalescers {
+            lists: RequestCoalescer::new(config.clone()),
+            gets: RequestCoalescer::new(config),
+        }
+    }
+}
+
 pub(crate) async fn list_resources_by_namespace(
     client: Option<&mut Client>,
     api_version: &str,
@@ -43,6 +64,33 @@ pub(crate) async fn list_resources_by_namespace(
         .map(cached::Return::new)
 }
 
+pub(crate) async fn list_resources_by_namespaces(
+    client: Option<&mut Client>,
+    api_version: &str,
+    kind: &str,
+    namespaces: &[String],
+    namespace_selector: Option<String>,
+    label_selector: Option<String>,
+    field_selector: Option<String>,
+) -> Result<cached::Return<ObjectList<kube::core::DynamicObject>>> {
+    if client.is_none() {
+        return Err(anyhow!("kube::Client was not initialized properly")).map(cached::Return::new);
+    }
+
+    client
+        .unwrap()
+        .list_resources_by_namespaces(
+            api_version,
+            kind,
+            namespaces,
+            namespace_selector,
+            label_selector,
+            field_selector,
+        )
+        .await
+        .map(cached::Return::new)
+}
+
 pub(crate) async fn list_resources_all(
     client: Option<&mut Client>,
     api_version: &str,
@@ -87,7 +135,7 @@ pub(crate) async fn get_resource(
     result = true,
     sync_writes = "default",
     key = "String",
-    convert = r#"{ format!("get_resource_cached({},{}),{},{:?}", api_version, kind, name, namespace) }"#,
+    convert = r#"{ format!("get_resource_cached({:?},{},{}),{},{:?}", client.as_deref().and_then(Client::impersonated_user), api_version, kind, name, namespace) }"#,
     with_cached_flag = true
 )]
 pub(crate) async fn get_resource_cached(
@@ -148,6 +196,26 @@ pub(crate) async fn has_list_resources_all_result_changed_since_instant(
         .map(cached::Return::new)
 }
 
+/// Subscribe to the changes of the results of the "list all resources" query. The
+/// reflector tracking this query is created when needed
+pub(crate) async fn subscribe_to_list_resources_all_changes(
+    client: Option<&mut Client>,
+    api_version: &str,
+    kind: &str,
+    label_selector: Option<String>,
+    field_selector: Option<String>,
+) -> Result<cached::Return<subscriptions::SubscriptionToken>> {
+    if client.is_none() {
+        return Err(anyhow!("kube::Client was not initialized properly")).map(cached::Return::new);
+    }
+
+    client
+        .unwrap()
+        .subscribe_to_list_resources_all_changes(api_version, kind, label_selector, field_selector)
+        .await
+        .map(cached::Return::new)
+}
+
 pub(crate) async fn can_i(
     client: Option<&mut Client>,
     request: KWSubjectAccessReview,
@@ -172,9 +240,10 @@ pub(crate) async fn can_i(
     // We can use the request type as key because cached requires the key to implement Hash + Eq
     // traits. As we already implement these traits, there is no need to have a custom logic for key
     // generation. If we do that, we will only convert it into a type (e.g. string)  that
-    // implements the traits as well. 
-    key = "KWSubjectAccessReview",
-    convert = r#"{request.clone()}"#,
+    // implements the traits as well. The user impersonated by the client is part of the key,
+    // the same request made with different identities can have different results
+    key = "(Option<String>, KWSubjectAccessReview)",
+    convert = r#"{(client.as_deref().and_then(Client::impersonated_user).map(str::to_owned), request.clone())}"#,
     sync_writes = "default",
     with_cached_flag = true
 )]
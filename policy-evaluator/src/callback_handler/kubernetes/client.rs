@@ -6,16 +6,29 @@ use kube::{
     Api,
 };
 use kubewarden_policy_sdk::host_capabilities::kubernetes::SubjectAccessReview as KWSubjectAccessReview;
-use std::{collections::HashMap, sync::Arc};
+use std::{
+    collections::{BTreeMap, BTreeSet, HashMap},
+    sync::Arc,
+};
 use tokio::{sync::RwLock, time::Instant};
 
-use crate::callback_handler::kubernetes::{reflector::Reflector, ApiVersionKind, KubeResource};
+use crate::callback_handler::kubernetes::{
+    reflector::{Reflector, ReflectorStats},
+    subscriptions::{self, SubscriptionToken},
+    ApiVersionKind, KubeResource, KubernetesHealth,
+};
+use crate::evaluation_context::KubernetesServiceAccount;
 
 #[derive(Clone)]
 pub(crate) struct Client {
     kube_client: kube::Client,
     kube_resources: Arc<RwLock<HashMap<ApiVersionKind, KubeResource>>>,
     reflectors: Arc<RwLock<HashMap<String, Reflector>>>,
+    /// The stats of the reflectors, including the ones that are still performing their
+    /// initial sync. The key is the ID of the reflector
+    reflector_stats: Arc<std::sync::RwLock<BTreeMap<String, Arc<ReflectorStats>>>>,
+    /// The user impersonated by the client, `None` when the identity of the host is used
+    impersonated_user: Option<String>,
 }
 
 impl Client {
@@ -24,9 +37,69 @@ impl Client {
             kube_client: client,
             kube_resources: Arc::new(RwLock::new(HashMap::new())),
             reflectors: Arc::new(RwLock::new(HashMap::new())),
+            reflector_stats: Arc::new(std::sync::RwLock::new(BTreeMap::new())),
+            impersonated_user: None,
+        }
+    }
+
+    /// Create a client that impersonates the given Service Account. The client
+    /// has its own reflectors, this way the resources it can access are never
+    /// shared with the clients using a different identity
+    pub fn impersonating(
+        config: kube::Config,
+        service_account: &KubernetesServiceAccount,
+    ) -> Result<Self> {
+        let username = service_account.username();
+        let mut config = config;
+        config.auth_info.impersonate = Some(username.clone());
+        config.auth_info.impersonate_groups = None;
+
+        let kube_client = kube::Client::try_from(config).map_err(|e| {
+            anyhow!("cannot create Kubernetes client impersonating {service_account}: {e}")
+        })?;
+
+        Ok(Self {
+            impersonated_user: Some(username),
+            ..Self::new(kube_client)
+        })
+    }
+
+    /// The user impersonated by the client, `None` when the identity of the host is used
+    pub fn impersonated_user(&self) -> Option<&str> {
+        self.impersonated_user.as_deref()
+    }
+
+    /// Report the state of the reflectors, and whether the Kubernetes API server can be reached
+    pub async fn health(&self) -> KubernetesHealth {
+        let (server_version, connection_error) = match self.kube_client.apiserver_version().await {
+            Ok(info) => (Some(info.git_version), None),
+            Err(e) => (None, Some(e.to_string())),
+        };
+        let reflectors = self
+            .reflector_stats
+            .read()
+            .map(|stats| stats.values().map(|s| s.health()).collect())
+            .unwrap_or_default();
+
+        KubernetesHealth {
+            connected: connection_error.is_none(),
+            server_version,
+            connection_error,
+            reflectors,
         }
     }
 
+    /// Create the reflector that lists all the instances of the given resource, then wait
+    /// for its initial sync to be completed. Nothing is done when the reflector already exists
+    pub async fn sync_resources_all(&mut self, api_version: &str, kind: &str) -> Result<()> {
+        let resource = self.build_kube_resource(api_version, kind).await?;
+        let reflector_id = Reflector::compute_id(&resource, None, None, None);
+
+        self.get_reflector_reader(&reflector_id, resource, None, None, None)
+            .await
+            .map(|_| ())
+    }
+
     /// Build a KubeResource using the apiVersion and Kind "coordinates" provided.
     /// The result is then cached locally to avoid further interactions with
     /// the Kubernetes API Server
@@ -110,12 +183,23 @@ impl Client {
             return Ok(reader);
         }
 
+        let stats = Arc::new(ReflectorStats::new(
+            &resource,
+            namespace.clone(),
+            label_selector.clone(),
+            field_selector.clone(),
+        ));
+        if let Ok(mut reflector_stats) = self.reflector_stats.write() {
+            reflector_stats.insert(reflector_id.to_string(), stats.clone());
+        }
+
         let reflector = Reflector::create_and_run(
             self.kube_client.clone(),
             resource,
             namespace,
             label_selector,
             field_selector,
+            stats,
         )
         .await?;
         let reader = reflector.reader.clone();
@@ -150,6 +234,60 @@ impl Client {
         .await
     }
 
+    /// List the resources defined inside of the given namespaces, plus the ones
+    /// defined inside of the namespaces matching `namespace_selector`. Each namespace
+    /// is tracked by its own reflector, this way only namespaced permissions are
+    /// required to access the resources.
+    pub async fn list_resources_by_namespaces(
+        &mut self,
+        api_version: &str,
+        kind: &str,
+        namespaces: &[String],
+        namespace_selector: Option<String>,
+        label_selector: Option<String>,
+        field_selector: Option<String>,
+    ) -> Result<ObjectList<kube::core::DynamicObject>> {
+        let resource = self.build_kube_resource(api_version, kind).await?;
+        if !resource.namespaced {
+            return Err(anyhow!("resource {api_version}/{kind} is cluster wide. Cannot search for it inside of namespaces"));
+        }
+
+        let mut selected_namespaces: BTreeSet<String> = namespaces.iter().cloned().collect();
+        if let Some(namespace_selector) = namespace_selector {
+            let namespaces = self
+                .list_resources_all("v1", "Namespace", Some(namespace_selector), None)
+                .await?;
+            selected_namespaces.extend(
+                namespaces
+                    .items
+                    .into_iter()
+                    .filter_map(|namespace| namespace.metadata.name),
+            );
+        }
+
+        let mut items = Vec::new();
+        for namespace in selected_namespaces {
+            let resources = self
+                .list_resources_from_reflector(
+                    resource.clone(),
+                    Some(namespace),
+                    label_selector.clone(),
+                    field_selector.clone(),
+                )
+                .await?;
+            items.extend(resources.items);
+        }
+
+        Ok(ObjectList {
+            types: kube::core::TypeMeta {
+                api_version: resource.resource.api_version,
+                kind: format!("{}List", resource.resource.kind),
+            },
+            metadata: Default::default(),
+            items,
+        })
+    }
+
     pub async fn list_resources_all(
         &mut self,
         api_version: &str,
@@ -184,6 +322,42 @@ impl Client {
             .await)
     }
 
+    pub async fn subscribe_to_list_resources_all_changes(
+        &mut self,
+        api_version: &str,
+        kind: &str,
+        label_selector: Option<String>,
+        field_selector: Option<String>,
+    ) -> Result<SubscriptionToken> {
+        let resource = self.build_kube_resource(api_version, kind).await?;
+        let reflector_id = Reflector::compute_id(
+            &resource,
+            None,
+            label_selector.as_deref(),
+            field_selector.as_deref(),
+        );
+
+        // ensure the reflector exists, the subscription is notified of its changes
+        self.get_reflector_reader(
+            &reflector_id,
+            resource,
+            None,
+            label_selector,
+            field_selector,
+        )
+        .await?;
+
+        let changes = {
+            let reflectors = self.reflectors.read().await;
+            reflectors
+                .get(&reflector_id)
+                .map(|reflector| reflector.changes())
+                .ok_or_else(|| anyhow!("cannot find the reflector {reflector_id}"))?
+        };
+
+        Ok(subscriptions::subscribe(changes))
+    }
+
     async fn list_resources_from_reflector(
         &mut self,
         resource: KubeResource,
@@ -314,3 +488,31 @@ impl Client {
         })
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn impersonating_client() {
+        // Both the ring and the aws-lc-rs providers are enabled inside of the
+        // dependency tree, hence rustls cannot pick the default one by itself.
+        // The policy server does the same inside of its `main` function.
+        let _ = rustls::crypto::ring::default_provider().install_default();
+
+        let config = kube::Config::new("https://127.0.0.1:6443".parse().unwrap());
+        let service_account = KubernetesServiceAccount {
+            namespace: "kubewarden".to_string(),
+            name: "policy".to_string(),
+        };
+
+        let client = Client::impersonating(config.clone(), &service_account).unwrap();
+        assert_eq!(
+            client.impersonated_user(),
+            Some("system:serviceaccount:kubewarden:policy")
+        );
+
+        let client = Client::new(kube::Client::try_from(config).unwrap());
+        assert_eq!(client.impersonated_user(), None);
+    }
+}
@@ -0,0 +1,237 @@
+use std::{collections::HashMap, future::Future, sync::Mutex, time::Duration};
+
+use anyhow::{anyhow, Result};
+use tokio::sync::oneshot;
+
+use crate::metrics::record_coalesced_kubernetes_request;
+
+type Waiter<T> = oneshot::Sender<std::result::Result<T, String>>;
+
+/// Configuration of the coalescing of the identical requests made by the
+/// policies to the Kubernetes API server.
+///
+/// Many policies can ask for the same resources within a few milliseconds,
+/// like during an audit scan. Instead of reading from the reflectors once per
+/// request, the identical requests are batched together and are all served
+/// by a single read.
+#[derive(Clone, Debug, PartialEq)]
+pub struct RequestCoalescingConfig {
+    /// For how long the first request of a batch waits for identical ones
+    /// before being served. Setting this to `0` coalesces only the requests
+    /// made while the first one is in flight
+    pub window: Duration,
+}
+
+impl Default for RequestCoalescingConfig {
+    fn default() -> Self {
+        RequestCoalescingConfig {
+            window: Duration::from_millis(10),
+        }
+    }
+}
+
+/// Serves the identical requests, identified by the same key, with one
+/// single call
+pub(crate) struct RequestCoalescer<T> {
+    config: RequestCoalescingConfig,
+    in_flight: Mutex<HashMap<String, Vec<Waiter<T>>>>,
+}
+
+impl<T: Clone> RequestCoalescer<T> {
+    pub fn new(config: RequestCoalescingConfig) -> Self {
+        RequestCoalescer {
+            config,
+            in_flight: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Run the request, unless an identical one is already being served. In
+    /// that case wait for its result.
+    ///
+    /// The `operation` is used only to report the coalesced requests.
+    pub async fn run<F>(
+        &self,
+        key: String,
+        operation: &str,
+        request: F,
+    ) -> Result<cached::Return<T>>
+    where
+        F: Future<Output = Result<cached::Return<T>>>,
+    {
+        let waiting = {
+            let mut in_flight = self
+                .in_flight
+                .lock()
+                .expect("cannot lock the in-flight Kubernetes requests");
+            match in_flight.get_mut(&key) {
+                Some(waiters) => {
+                    let (tx, rx) = oneshot::channel();
+                    waiters.push(tx);
+                    Some(rx)
+                }
+                None => {
+                    in_flight.insert(key.clone(), Vec::new());
+                    None
+                }
+            }
+        };
+
+        if let Some(rx) = waiting {
+            record_coalesced_kubernetes_request(operation);
+            let value = rx
+                .await
+                .map_err(|_| anyhow!("the coalesced Kubernetes request has been dropped"))?
+                .map_err(|e| anyhow!(e))?;
+            return Ok(cached::Return {
+                was_cached: true,
+                value,
+            });
+        }
+
+        let mut batch = Batch {
+            coalescer: self,
+            key: &key,
+            completed: false,
+        };
+        if !self.config.window.is_zero() {
+            tokio::time::sleep(self.config.window).await;
+        }
+        let result = request.await;
+
+        for waiter in batch.complete() {
+            let shared = match &result {
+                Ok(response) => Ok(response.value.clone()),
+                Err(e) => Err(format!("{e:#}")),
+            };
+            // the waiter might be gone already, there's nothing to do about that
+            let _ = waiter.send(shared);
+        }
+
+        result
+    }
+
+    fn take_waiters(&self, key: &str) -> Vec<Waiter<T>> {
+        self.in_flight
+            .lock()
+            .expect("cannot lock the in-flight Kubernetes requests")
+            .remove(key)
+            .unwrap_or_default()
+    }
+}
+
+/// The requests waiting for the first one of the batch. The batch is removed
+/// also when the first request is cancelled, which makes the waiters fail
+/// instead of hanging forever.
+struct Batch<'a, T: Clone> {
+    coalescer: &'a RequestCoalescer<T>,
+    key: &'a str,
+    completed: bool,
+}
+
+impl<T: Clone> Batch<'_, T> {
+    fn complete(&mut self) -> Vec<Waiter<T>> {
+        self.completed = true;
+        self.coalescer.take_waiters(self.key)
+    }
+}
+
+impl<T: Clone> Drop for Batch<'_, T> {
+    fn drop(&mut self) {
+        if !self.completed {
+            self.coalescer.take_waiters(self.key);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    async fn request(calls: &AtomicUsize, value: &str) -> Result<cached::Return<String>> {
+        calls.fetch_add(1, Ordering::SeqCst);
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        Ok(cached::Return::new(value.to_owned()))
+    }
+
+    #[tokio::test]
+    async fn identical_requests_are_coalesced() {
+        let coalescer = RequestCoalescer::new(RequestCoalescingConfig::default());
+        let calls = AtomicUsize::new(0);
+
+        let (first, second, third) = tokio::join!(
+            coalescer.run("pods".to_owned(), "list", request(&calls, "a")),
+            coalescer.run("pods".to_owned(), "list", request(&calls, "b")),
+            coalescer.run("pods".to_owned(), "list", request(&calls, "c")),
+        );
+
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+        let first = first.unwrap();
+        assert!(!first.was_cached);
+        assert_eq!(first.value, "a");
+        for response in [second.unwrap(), third.unwrap()] {
+            assert!(response.was_cached);
+            assert_eq!(response.value, "a");
+        }
+        assert!(coalescer.in_flight.lock().unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn different_requests_are_not_coalesced() {
+        let coalescer = RequestCoalescer::new(RequestCoalescingConfig {
+            window: Duration::ZERO,
+        });
+        let calls = AtomicUsize::new(0);
+
+        let (pods, services) = tokio::join!(
+            coalescer.run("pods".to_owned(), "list", request(&calls, "pods")),
+            coalescer.run("services".to_owned(), "list", request(&calls, "services")),
+        );
+
+        assert_eq!(calls.load(Ordering::SeqCst), 2);
+        assert_eq!(pods.unwrap().value, "pods");
+        assert_eq!(services.unwrap().value, "services");
+    }
+
+    #[tokio::test]
+    async fn errors_are_shared_with_the_waiters() {
+        let coalescer = RequestCoalescer::<String>::new(RequestCoalescingConfig::default());
+
+        let (first, second) = tokio::join!(
+            coalescer.run("pods".to_owned(), "list", async {
+                Err(anyhow!("forbidden"))
+            }),
+            coalescer.run("pods".to_owned(), "list", async {
+                Ok(cached::Return::new("unexpected".to_owned()))
+            }),
+        );
+
+        assert_eq!(first.err().unwrap().to_string(), "forbidden");
+        assert_eq!(second.err().unwrap().to_string(), "forbidden");
+    }
+
+    #[tokio::test]
+    async fn waiters_are_released_when_the_first_request_is_cancelled() {
+        let coalescer = RequestCoalescer::new(RequestCoalescingConfig::default());
+        let calls = AtomicUsize::new(0);
+
+        let cancelled = tokio::time::timeout(
+            Duration::from_millis(1),
+            coalescer.run("pods".to_owned(), "list", request(&calls, "a")),
+        );
+        let (cancelled, waiter) = tokio::join!(
+            cancelled,
+            coalescer.run("pods".to_owned(), "list", request(&calls, "b")),
+        );
+
+        assert!(cancelled.is_err());
+        assert!(waiter.is_err());
+        assert!(coalescer.in_flight.lock().unwrap().is_empty());
+
+        let response = coalescer
+            .run("pods".to_owned(), "list", request(&calls, "c"))
+            .await
+            .unwrap();
+        assert_eq!(response.value, "c");
+    }
+}
@@ -0,0 +1,135 @@
+use anyhow::Result;
+use serde::Serialize;
+use time::OffsetDateTime;
+
+use crate::{callback_handler::kubernetes::Client, policy_metadata::ContextAwareResource};
+
+/// The health of the integration with the Kubernetes API server
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct KubernetesHealth {
+    /// Whether the Kubernetes API server could be reached
+    pub connected: bool,
+    /// The version of the Kubernetes API server, when it could be reached
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub server_version: Option<String>,
+    /// The error that occurred while reaching the Kubernetes API server
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub connection_error: Option<String>,
+    /// The state of the reflectors keeping the Kubernetes resources cached
+    pub reflectors: Vec<ReflectorHealth>,
+}
+
+impl KubernetesHealth {
+    /// The API server can be reached and all the reflectors completed their initial sync
+    pub fn is_healthy(&self) -> bool {
+        self.connected && self.reflectors.iter().all(|reflector| reflector.synced)
+    }
+}
+
+/// The state of a reflector
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ReflectorHealth {
+    pub api_version: String,
+    pub kind: String,
+    /// The Namespace watched by the reflector, not set when the reflector is cluster wide
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub namespace: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub label_selector: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub field_selector: Option<String>,
+    /// Whether the initial list of the resources has been completed
+    pub synced: bool,
+    /// The last time an event was received from the Kubernetes API server
+    #[serde(with = "time::serde::rfc3339::option")]
+    pub last_sync: Option<OffsetDateTime>,
+    /// How many errors have been reported by the watcher since the reflector was created
+    pub error_count: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub last_error: Option<String>,
+}
+
+/// Gives access to the health of the Kubernetes integration of a `CallbackHandler`.
+/// It can be cloned and used after the `CallbackHandler` has been moved to its own task.
+#[derive(Clone)]
+pub struct KubernetesHealthReporter {
+    client: Client,
+}
+
+impl KubernetesHealthReporter {
+    pub(crate) fn new(client: Client) -> Self {
+        Self { client }
+    }
+
+    /// Report the state of the reflectors, and whether the Kubernetes API server can be reached
+    pub async fn health(&self) -> KubernetesHealth {
+        self.client.health().await
+    }
+
+    /// Start the reflector that lists all the instances of the given resource and wait
+    /// for its initial sync. The reflector is then shared with the policies accessing
+    /// the resource
+    pub async fn sync(&self, resource: &ContextAwareResource) -> Result<()> {
+        self.client
+            .clone()
+            .sync_resources_all(&resource.api_version, &resource.kind)
+            .await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn reflector_health(synced: bool) -> ReflectorHealth {
+        ReflectorHealth {
+            api_version: "v1".to_string(),
+            kind: "Namespace".to_string(),
+            namespace: None,
+            label_selector: Some("app=web".to_string()),
+            field_selector: None,
+            synced,
+            last_sync: None,
+            error_count: 2,
+            last_error: Some("forbidden".to_string()),
+        }
+    }
+
+    #[test]
+    fn health_requires_connection_and_synced_reflectors() {
+        let mut health = KubernetesHealth {
+            connected: true,
+            server_version: Some("v1.30.0".to_string()),
+            connection_error: None,
+            reflectors: vec![reflector_health(true)],
+        };
+        assert!(health.is_healthy());
+
+        health.reflectors.push(reflector_health(false));
+        assert!(!health.is_healthy());
+
+        health.reflectors.clear();
+        health.connected = false;
+        assert!(!health.is_healthy());
+    }
+
+    #[test]
+    fn serialize_reflector_health() {
+        let value = serde_json::to_value(reflector_health(false)).unwrap();
+        assert_eq!(
+            value,
+            json!({
+                "apiVersion": "v1",
+                "kind": "Namespace",
+                "labelSelector": "app=web",
+                "synced": false,
+                "lastSync": null,
+                "errorCount": 2,
+                "lastError": "forbidden",
+            })
+        );
+    }
+}
@@ -5,18 +5,99 @@ use kube::{
     runtime::{reflector::store::Writer, watcher, WatchStreamExt},
     ResourceExt,
 };
-use std::hash::Hash;
+use std::{
+    hash::Hash,
+    sync::{
+        atomic::{AtomicBool, AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+};
+use time::OffsetDateTime;
 use tokio::{sync::watch, time::Instant};
 use tracing::{debug, info, warn};
 
-use crate::callback_handler::kubernetes::KubeResource;
+use crate::callback_handler::kubernetes::{KubeResource, ReflectorHealth};
+
+/// Runtime statistics of a Reflector. They are shared between the background task that keeps
+/// the Reflector updated and the code reporting the health of the Kubernetes integration
+pub(crate) struct ReflectorStats {
+    api_version: String,
+    kind: String,
+    namespace: Option<String>,
+    label_selector: Option<String>,
+    field_selector: Option<String>,
+    synced: AtomicBool,
+    error_count: AtomicU64,
+    last_error: Mutex<Option<String>>,
+    last_sync: Mutex<Option<OffsetDateTime>>,
+}
+
+impl ReflectorStats {
+    pub fn new(
+        resource: &KubeResource,
+        namespace: Option<String>,
+        label_selector: Option<String>,
+        field_selector: Option<String>,
+    ) -> Self {
+        Self {
+            api_version: resource.resource.api_version.clone(),
+            kind: resource.resource.kind.clone(),
+            namespace,
+            label_selector,
+            field_selector,
+            synced: AtomicBool::new(false),
+            error_count: AtomicU64::new(0),
+            last_error: Mutex::new(None),
+            last_sync: Mutex::new(None),
+        }
+    }
+
+    /// Record the reception of an event from the Kubernetes API server
+    fn record_event(&self) {
+        if let Ok(mut last_sync) = self.last_sync.lock() {
+            *last_sync = Some(OffsetDateTime::now_utc());
+        }
+    }
+
+    /// Record an error reported by the watcher
+    fn record_error(&self, error: &watcher::Error) {
+        self.error_count.fetch_add(1, Ordering::Relaxed);
+        if let Ok(mut last_error) = self.last_error.lock() {
+            *last_error = Some(error.to_string());
+        }
+    }
+
+    /// Record the completion of the initial list of the resources
+    fn mark_as_synced(&self) {
+        self.synced.store(true, Ordering::Relaxed);
+    }
+
+    pub fn health(&self) -> ReflectorHealth {
+        ReflectorHealth {
+            api_version: self.api_version.clone(),
+            kind: self.kind.clone(),
+            namespace: self.namespace.clone(),
+            label_selector: self.label_selector.clone(),
+            field_selector: self.field_selector.clone(),
+            synced: self.synced.load(Ordering::Relaxed),
+            last_sync: self.last_sync.lock().map(|t| *t).unwrap_or_default(),
+            error_count: self.error_count.load(Ordering::Relaxed),
+            last_error: self
+                .last_error
+                .lock()
+                .map(|e| e.clone())
+                .unwrap_or_default(),
+        }
+    }
+}
 
 /// Like `kube::runtime::reflector::reflector`, but also sends the time of the last change to a
-/// watch channel
+/// watch channel and records it inside of the stats of the reflector
 pub fn reflector_tracking_changes_instant<K, W>(
     mut writer: store::Writer<K>,
     stream: W,
     last_change_seen_at: watch::Sender<Instant>,
+    stats: Arc<ReflectorStats>,
 ) -> impl Stream<Item = W::Item>
 where
     K: Resource + Clone,
@@ -27,6 +108,7 @@ where
         if let Err(err) = last_change_seen_at.send(Instant::now()) {
             warn!(error = ?err, "failed to set last_change_seen_at");
         }
+        stats.record_event();
         writer.apply_watcher_event(event)
     })
 }
@@ -70,13 +152,15 @@ impl Reflector {
     }
 
     /// Create the reflector and start a tokio task in the background that keeps
-    /// the contents of the Reflector updated
+    /// the contents of the Reflector updated. The errors and the changes seen by the
+    /// watcher are recorded inside of `stats`
     pub async fn create_and_run(
         kube_client: kube::Client,
         resource: KubeResource,
         namespace: Option<String>,
         label_selector: Option<String>,
         field_selector: Option<String>,
+        stats: Arc<ReflectorStats>,
     ) -> Result<Self> {
         let group = resource.resource.group.clone();
         let version = resource.resource.version.clone();
@@ -125,8 +209,10 @@ impl Reflector {
         // this is a watch channel that tracks the last time the reflector saw a change
         let (updated_at_watch_tx, updated_at_watch_rx) = watch::channel(Instant::now());
 
-        let rf = reflector_tracking_changes_instant(writer, stream, updated_at_watch_tx);
+        let rf =
+            reflector_tracking_changes_instant(writer, stream, updated_at_watch_tx, stats.clone());
 
+        let watcher_stats = stats.clone();
         tokio::spawn(async move {
             let infinite_watch = rf.default_backoff().touched_objects().for_each(|obj| {
                 match obj {
@@ -140,16 +226,19 @@ impl Reflector {
                         object=?o,
                         "watcher saw object"
                     ),
-                    Err(e) => warn!(
-                        group,
-                        version,
-                        kind,
-                        ?namespace,
-                        ?label_selector,
-                        ?field_selector,
-                        error=?e,
-                        "watcher error"
-                    ),
+                    Err(e) => {
+                        watcher_stats.record_error(&e);
+                        warn!(
+                            group,
+                            version,
+                            kind,
+                            ?namespace,
+                            ?label_selector,
+                            ?field_selector,
+                            error=?e,
+                            "watcher error"
+                        )
+                    }
                 };
                 ready(())
             });
@@ -157,6 +246,7 @@ impl Reflector {
         });
 
         reader.wait_until_ready().await?;
+        stats.mark_as_synced();
 
         Ok(Reflector {
             reader,
@@ -168,4 +258,9 @@ impl Reflector {
     pub async fn last_change_seen_at(&self) -> Instant {
         *self.last_change_seen_at.borrow()
     }
+
+    /// Get a receiver notified each time the reflector sees a change
+    pub fn changes(&self) -> watch::Receiver<Instant> {
+        self.last_change_seen_at.clone()
+    }
 }
@@ -0,0 +1,101 @@
+//! Push-based notification of the changes seen by the reflectors.
+//!
+//! Checking whether the resources tracked by a reflector changed requires a
+//! round trip over the callback channel. Instead, a runtime can subscribe to the
+//! changes of a reflector: the subscription is identified by a token, which is
+//! then used to look for notifications without involving the callback handler.
+//!
+//! The subscriptions live as long as the process: the runtimes are expected to
+//! subscribe once per set of resources and to keep using the same token.
+
+use lazy_static::lazy_static;
+use std::{
+    collections::HashMap,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Mutex,
+    },
+};
+use tokio::{sync::watch, time::Instant};
+
+/// Identifies a subscription to the changes of a reflector
+pub(crate) type SubscriptionToken = u64;
+
+lazy_static! {
+    static ref SUBSCRIPTIONS: Mutex<HashMap<SubscriptionToken, watch::Receiver<Instant>>> =
+        Mutex::new(HashMap::new());
+}
+
+static NEXT_TOKEN: AtomicU64 = AtomicU64::new(1);
+
+/// Register a subscription to the changes published by the given watch channel.
+/// Only the changes happening after the subscription are notified
+pub(crate) fn subscribe(mut changes: watch::Receiver<Instant>) -> SubscriptionToken {
+    changes.borrow_and_update();
+
+    let token = NEXT_TOKEN.fetch_add(1, Ordering::Relaxed);
+    SUBSCRIPTIONS
+        .lock()
+        .expect("cannot lock the subscriptions")
+        .insert(token, changes);
+    token
+}
+
+/// Whether a change has been notified since the previous call, the notification
+/// is consumed. A subscription whose reflector is gone is always reported as changed.
+///
+/// Returns `None` when the token is unknown, for example when it has been issued
+/// by another process during the recording of a session.
+pub(crate) fn take_notification(token: SubscriptionToken) -> Option<bool> {
+    let mut subscriptions = SUBSCRIPTIONS.lock().expect("cannot lock the subscriptions");
+    let changes = subscriptions.get_mut(&token)?;
+
+    match changes.has_changed() {
+        Ok(true) => {
+            changes.borrow_and_update();
+            Some(true)
+        }
+        Ok(false) => Some(false),
+        Err(_) => Some(true),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn notifications_are_consumed() {
+        let (tx, rx) = watch::channel(Instant::now());
+        tx.send(Instant::now()).unwrap();
+
+        let token = subscribe(rx);
+        assert_eq!(take_notification(token), Some(false));
+
+        tx.send(Instant::now()).unwrap();
+        tx.send(Instant::now()).unwrap();
+        assert_eq!(take_notification(token), Some(true));
+        assert_eq!(take_notification(token), Some(false));
+    }
+
+    #[test]
+    fn reflector_gone() {
+        let (tx, rx) = watch::channel(Instant::now());
+        let token = subscribe(rx);
+
+        drop(tx);
+        assert_eq!(take_notification(token), Some(true));
+    }
+
+    #[test]
+    fn unknown_token() {
+        assert_eq!(take_notification(0), None);
+    }
+
+    #[test]
+    fn tokens_are_unique() {
+        let (_tx, rx) = watch::channel(Instant::now());
+
+        assert_ne!(subscribe(rx.clone()), subscribe(rx));
+    }
+}
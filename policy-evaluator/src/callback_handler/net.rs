@@ -0,0 +1,164 @@
+use std::{
+    collections::HashMap,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use anyhow::{anyhow, Result};
+use kubewarden_policy_sdk::host_capabilities::net::LookupResponse;
+
+/// Configuration of the cache used to store the results of the DNS lookups
+/// performed on behalf of the policies
+#[derive(Clone, Debug, PartialEq)]
+pub struct DnsCacheConfig {
+    /// For how long a successful lookup is reused
+    pub ttl: Duration,
+    /// Maximum number of hosts kept inside of the cache. Setting this to `0`
+    /// disables the cache
+    pub max_entries: usize,
+}
+
+impl Default for DnsCacheConfig {
+    fn default() -> Self {
+        DnsCacheConfig {
+            ttl: Duration::from_secs(30),
+            max_entries: 1024,
+        }
+    }
+}
+
+struct CachedLookup {
+    ips: Vec<String>,
+    expires_at: Instant,
+}
+
+/// Resolves hostnames without blocking the async runtime, caching the
+/// successful results
+pub(crate) struct Resolver {
+    config: DnsCacheConfig,
+    cache: Mutex<HashMap<String, CachedLookup>>,
+}
+
+impl Resolver {
+    pub fn new(config: DnsCacheConfig) -> Self {
+        Resolver {
+            config,
+            cache: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub async fn lookup_host(&self, host: &str) -> Result<cached::Return<LookupResponse>> {
+        if let Some(ips) = self.cached(host) {
+            return Ok(cached::Return {
+                was_cached: true,
+                value: LookupResponse { ips },
+            });
+        }
+
+        let lookup_host = host.to_owned();
+        let ips: Vec<String> =
+            tokio::task::spawn_blocking(move || dns_lookup::lookup_host(&lookup_host))
+                .await
+                .map_err(|e| anyhow!("cannot perform DNS lookup: {e}"))??
+                .iter()
+                .map(|ip| ip.to_string())
+                .collect();
+        self.store(host, &ips);
+
+        Ok(cached::Return::new(LookupResponse { ips }))
+    }
+
+    fn cached(&self, host: &str) -> Option<Vec<String>> {
+        let cache = self.cache.lock().expect("cannot lock the DNS cache");
+        cache
+            .get(host)
+            .filter(|lookup| lookup.expires_at > Instant::now())
+            .map(|lookup| lookup.ips.clone())
+    }
+
+    fn store(&self, host: &str, ips: &[String]) {
+        if self.config.max_entries == 0 {
+            return;
+        }
+
+        let now = Instant::now();
+        let mut cache = self.cache.lock().expect("cannot lock the DNS cache");
+        if cache.len() >= self.config.max_entries && !cache.contains_key(host) {
+            cache.retain(|_, lookup| lookup.expires_at > now);
+        }
+        if cache.len() >= self.config.max_entries && !cache.contains_key(host) {
+            // make room by dropping the lookup closest to its expiration
+            if let Some(oldest) = cache
+                .iter()
+                .min_by_key(|(_, lookup)| lookup.expires_at)
+                .map(|(host, _)| host.to_owned())
+            {
+                cache.remove(&oldest);
+            }
+        }
+        cache.insert(
+            host.to_owned(),
+            CachedLookup {
+                ips: ips.to_vec(),
+                expires_at: now + self.config.ttl,
+            },
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn lookups_are_cached() {
+        let resolver = Resolver::new(DnsCacheConfig::default());
+
+        let response = resolver.lookup_host("localhost").await.unwrap();
+        assert!(!response.was_cached);
+        assert!(!response.value.ips.is_empty());
+
+        let cached_response = resolver.lookup_host("localhost").await.unwrap();
+        assert!(cached_response.was_cached);
+        assert_eq!(cached_response.value.ips, response.value.ips);
+    }
+
+    #[tokio::test]
+    async fn cache_can_be_disabled() {
+        let resolver = Resolver::new(DnsCacheConfig {
+            max_entries: 0,
+            ..Default::default()
+        });
+
+        resolver.lookup_host("localhost").await.unwrap();
+        let response = resolver.lookup_host("localhost").await.unwrap();
+        assert!(!response.was_cached);
+    }
+
+    #[test]
+    fn cache_is_bounded() {
+        let resolver = Resolver::new(DnsCacheConfig {
+            max_entries: 2,
+            ..Default::default()
+        });
+
+        resolver.store("a.example.com", &["10.0.0.1".to_owned()]);
+        resolver.store("b.example.com", &["10.0.0.2".to_owned()]);
+        resolver.store("c.example.com", &["10.0.0.3".to_owned()]);
+
+        assert!(resolver.cached("a.example.com").is_none());
+        assert!(resolver.cached("b.example.com").is_some());
+        assert!(resolver.cached("c.example.com").is_some());
+    }
+
+    #[test]
+    fn expired_lookups_are_not_used() {
+        let resolver = Resolver::new(DnsCacheConfig {
+            ttl: Duration::ZERO,
+            ..Default::default()
+        });
+
+        resolver.store("a.example.com", &["10.0.0.1".to_owned()]);
+        assert!(resolver.cached("a.example.com").is_none());
+    }
+}
@@ -8,11 +8,20 @@ use policy_fetcher::{
         manifest::{OciImageManifest, OciManifest},
         Reference,
     },
-    registry::Registry,
+    registry::{ClientPoolConfig, Registry},
     sources::Sources,
 };
 use serde::{Deserialize, Serialize};
 
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ResolveDigestResponse {
+    /// The fully qualified reference of the image, pinned to its digest.
+    /// E.g.: `docker.io/library/nginx:1.25@sha256:...`
+    pub image: String,
+    /// The manifest digest of the image
+    pub digest: String,
+}
+
 /// Helper struct to interact with an OCI registry
 pub(crate) struct Client {
     sources: Option<Sources>,
@@ -27,8 +36,10 @@ pub struct ManifestAndConfigResponse {
 }
 
 impl Client {
-    pub fn new(sources: Option<Sources>) -> Self {
-        let registry = Registry {};
+    /// Create a client that reuses the connections to the registries, according
+    /// to the given pool configuration
+    pub fn new(sources: Option<Sources>, pool_config: ClientPoolConfig) -> Self {
+        let registry = Registry::with_client_pool(pool_config);
         Client { sources, registry }
     }
 
@@ -47,6 +58,26 @@ impl Client {
         Ok(image_digest)
     }
 
+    /// Resolve the tag of the OCI resource referenced via `image` to its
+    /// manifest digest, returning a reference pinned to the digest
+    pub async fn resolve_digest(&self, image: &str) -> Result<ResolveDigestResponse> {
+        let image_ref: Reference = image.parse()?;
+        let digest = self.digest(image).await?;
+
+        let mut pinned = format!("{}/{}", image_ref.registry(), image_ref.repository());
+        if let Some(tag) = image_ref.tag() {
+            pinned.push(':');
+            pinned.push_str(tag);
+        }
+        pinned.push('@');
+        pinned.push_str(&digest);
+
+        Ok(ResolveDigestResponse {
+            image: pinned,
+            digest,
+        })
+    }
+
     pub async fn manifest(&self, image: &str) -> Result<OciManifest> {
         // this is needed to expand names as `busybox` into
         // fully resolved references like `docker.io/library/busybox`
@@ -105,6 +136,26 @@ pub(crate) async fn get_oci_digest_cached(
         .map(cached::Return::new)
 }
 
+// Resolving a tag requires the same registry interaction done to fetch the digest,
+// hence the results are cached with the same rules used by `get_oci_digest_cached`.
+#[cached(
+    time = 60,
+    result = true,
+    sync_writes = "default",
+    key = "String",
+    convert = r#"{ format!("{}", img) }"#,
+    with_cached_flag = true
+)]
+pub(crate) async fn get_oci_resolved_digest_cached(
+    oci_client: &Client,
+    img: &str,
+) -> Result<cached::Return<ResolveDigestResponse>> {
+    oci_client
+        .resolve_digest(img)
+        .await
+        .map(cached::Return::new)
+}
+
 // Interacting with a remote OCI registry is time expensive, this can cause a massive slow down
 // of policy evaluations, especially inside of PolicyServer.
 // Because of that we will keep a cache of the manifest results.
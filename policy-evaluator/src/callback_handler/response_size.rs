@@ -0,0 +1,182 @@
+use std::{collections::HashMap, fmt};
+
+use serde::Serialize;
+use thiserror::Error;
+use tracing::warn;
+
+use crate::callback_requests::CallbackRequestType;
+
+/// Limits of the size of the responses given back to the policies by the host
+/// capabilities. Responses exceeding them are replaced by a [`ResponseTooLarge`] error
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct ResponseSizeLimits {
+    /// Limit, in bytes, of the capabilities that do not have a dedicated one.
+    /// These responses are not limited when `None`
+    pub default: Option<usize>,
+    /// Limits, in bytes, of some capabilities. The key is made of the namespace
+    /// and of the name of the capability, like `kubernetes/list_resources_all`
+    pub capabilities: HashMap<String, usize>,
+}
+
+impl ResponseSizeLimits {
+    /// The limit applied to the given capability
+    pub fn limit(&self, capability: &str) -> Option<usize> {
+        self.capabilities.get(capability).copied().or(self.default)
+    }
+}
+
+/// Returned to the policy instead of a response that exceeds the size limit of the
+/// capability
+#[derive(Error, Serialize, Debug, Clone, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct ResponseTooLarge {
+    /// The capability, like `kubernetes/list_resources_all`
+    pub capability: String,
+    /// Size of the response, in bytes
+    pub size: usize,
+    /// The limit of the capability, in bytes
+    pub limit: usize,
+    /// How the request can be narrowed
+    pub suggestions: Vec<String>,
+}
+
+impl fmt::Display for ResponseTooLarge {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "response of host capability {} is too large: {} bytes, the limit is {} bytes. Narrow your selector",
+            self.capability, self.size, self.limit
+        )?;
+        if !self.suggestions.is_empty() {
+            write!(f, ": {}", self.suggestions.join("; "))?;
+        }
+        Ok(())
+    }
+}
+
+/// Checks the size of the response given to a request
+pub(crate) struct ResponseSizeCheck {
+    capability: &'static str,
+    limit: Option<usize>,
+    suggestions: Vec<String>,
+}
+
+impl ResponseSizeCheck {
+    pub fn new(request: &CallbackRequestType, limits: &ResponseSizeLimits) -> Self {
+        let capability = request.capability();
+        ResponseSizeCheck {
+            capability,
+            limit: limits.limit(capability),
+            suggestions: narrowing_suggestions(request),
+        }
+    }
+
+    /// Ensure the serialized response fits inside of the limit of the capability
+    pub fn check(&self, size: usize) -> Result<(), ResponseTooLarge> {
+        match self.limit {
+            Some(limit) if size > limit => {
+                warn!(
+                    capability = self.capability,
+                    size, limit, "response of host capability is too large"
+                );
+                Err(ResponseTooLarge {
+                    capability: self.capability.to_owned(),
+                    size,
+                    limit,
+                    suggestions: self.suggestions.clone(),
+                })
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+/// How the given request could be narrowed to get a smaller response. Only the
+/// requests listing Kubernetes resources can be narrowed
+fn narrowing_suggestions(request: &CallbackRequestType) -> Vec<String> {
+    let (label_selector, field_selector, cluster_wide) = match request {
+        CallbackRequestType::KubernetesListResourceNamespace {
+            label_selector,
+            field_selector,
+            ..
+        }
+        | CallbackRequestType::KubernetesListResourceNamespaces {
+            label_selector,
+            field_selector,
+            ..
+        } => (label_selector, field_selector, false),
+        CallbackRequestType::KubernetesListResourceAll {
+            label_selector,
+            field_selector,
+            ..
+        } => (label_selector, field_selector, true),
+        _ => return Vec::new(),
+    };
+
+    let mut suggestions = Vec::new();
+    if label_selector.is_none() {
+        suggestions.push("set a label selector, like `app=frontend`".to_owned());
+    }
+    if field_selector.is_none() {
+        suggestions.push("set a field selector, like `metadata.name=my-resource`".to_owned());
+    }
+    if cluster_wide {
+        suggestions.push(
+            "list the resources of the relevant namespaces only, using list_resources_by_namespace"
+                .to_owned(),
+        );
+    }
+    suggestions
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn list_all(label_selector: Option<&str>) -> CallbackRequestType {
+        CallbackRequestType::KubernetesListResourceAll {
+            api_version: "v1".to_owned(),
+            kind: "Secret".to_owned(),
+            label_selector: label_selector.map(str::to_owned),
+            field_selector: None,
+        }
+    }
+
+    #[test]
+    fn per_capability_limit() {
+        let limits = ResponseSizeLimits {
+            default: Some(100),
+            capabilities: HashMap::from([("kubernetes/list_resources_all".to_owned(), 10)]),
+        };
+
+        assert_eq!(limits.limit("kubernetes/list_resources_all"), Some(10));
+        assert_eq!(limits.limit("oci/verify"), Some(100));
+        assert_eq!(ResponseSizeLimits::default().limit("oci/verify"), None);
+    }
+
+    #[test]
+    fn response_too_large() {
+        let limits = ResponseSizeLimits {
+            default: None,
+            capabilities: HashMap::from([("kubernetes/list_resources_all".to_owned(), 10)]),
+        };
+        let check = ResponseSizeCheck::new(&list_all(Some("app=frontend")), &limits);
+
+        assert!(check.check(10).is_ok());
+        let error = check.check(11).unwrap_err();
+        assert_eq!(error.capability, "kubernetes/list_resources_all");
+        assert_eq!(error.size, 11);
+        assert_eq!(error.limit, 10);
+        assert_eq!(error.suggestions.len(), 2, "{:?}", error.suggestions);
+        assert!(error
+            .to_string()
+            .starts_with("response of host capability kubernetes/list_resources_all is too large: 11 bytes, the limit is 10 bytes. Narrow your selector: set a field selector"));
+    }
+
+    #[test]
+    fn responses_of_unlimited_capabilities() {
+        let check = ResponseSizeCheck::new(&list_all(None), &ResponseSizeLimits::default());
+
+        assert!(check.check(usize::MAX).is_ok());
+    }
+}
@@ -28,12 +28,15 @@ use tracing::warn;
 pub(crate) struct Client {
     cosign_client: Arc<Mutex<sigstore::cosign::Client>>,
     verifier: Verifier,
+    /// The verification config of the host, shared by all the policies
+    server_verification_config: Option<Arc<LatestVerificationConfig>>,
 }
 
 impl Client {
     pub async fn new(
         sources: Option<Sources>,
         trust_root: Option<Arc<ManualTrustRoot<'static>>>,
+        server_verification_config: Option<LatestVerificationConfig>,
     ) -> Result<Self> {
         let cosign_client = Arc::new(Mutex::new(
             Self::build_cosign_client(sources.clone(), trust_root).await?,
@@ -43,6 +46,7 @@ impl Client {
         Ok(Client {
             cosign_client,
             verifier,
+            server_verification_config: server_verification_config.map(Arc::new),
         })
     }
 
@@ -93,6 +97,7 @@ impl Client {
         let verification_config = LatestVerificationConfig {
             all_of: Some(signatures_all_of),
             any_of: None,
+            trust_roots: None,
         };
 
         let result = self.verifier.verify(&image, &verification_config).await;
@@ -128,6 +133,7 @@ impl Client {
         let verification_config = LatestVerificationConfig {
             all_of: Some(signatures_all_of),
             any_of: None,
+            trust_roots: None,
         };
 
         let result = self.verifier.verify(&image, &verification_config).await;
@@ -164,6 +170,7 @@ impl Client {
         let verification_config = LatestVerificationConfig {
             all_of: Some(signatures_all_of),
             any_of: None,
+            trust_roots: None,
         };
 
         let result = self.verifier.verify(&image, &verification_config).await;
@@ -192,12 +199,15 @@ impl Client {
         let signature = Signature::GithubAction {
             owner: owner.clone(),
             repo: repo.clone(),
+            workflow_ref: None,
+            workflow_trigger: None,
             annotations: annotations.clone(),
         };
         signatures_all_of.push(signature);
         let verification_config = LatestVerificationConfig {
             all_of: Some(signatures_all_of),
             any_of: None,
+            trust_roots: None,
         };
 
         let result = self.verifier.verify(&image, &verification_config).await;
@@ -210,6 +220,24 @@ impl Client {
         }
     }
 
+    pub async fn verify_against_server_config(
+        &mut self,
+        image: String,
+    ) -> Result<VerificationResponse> {
+        let verification_config = self.server_verification_config.clone().ok_or_else(|| {
+            anyhow!("the host has not been configured with a verification config")
+        })?;
+
+        let result = self.verifier.verify(&image, &verification_config).await;
+        match result {
+            Ok(digest) => Ok(VerificationResponse {
+                digest,
+                is_trusted: true,
+            }),
+            Err(e) => Err(e.into()),
+        }
+    }
+
     pub async fn verify_certificate(
         &mut self,
         image: &str,
@@ -362,6 +390,33 @@ pub(crate) async fn get_sigstore_github_actions_verification_cached(
         .map(cached::Return::new)
 }
 
+// Sigstore verifications are time expensive, this can cause a massive slow down
+// of policy evaluations, especially inside of PolicyServer.
+// Because of that we will keep a cache of the digests results.
+//
+// Details about this cache:
+//   * the cache is time bound: cached values are purged after 60 seconds
+//   * only successful results are cached
+//   * the verification config is not part of the key: it's the same for
+//     the whole lifetime of the Client
+#[cached(
+    time = 60,
+    result = true,
+    sync_writes = "default",
+    key = "String",
+    convert = r#"{ image.clone() }"#,
+    with_cached_flag = true
+)]
+pub(crate) async fn get_sigstore_server_config_verification_cached(
+    client: &mut Client,
+    image: String,
+) -> Result<cached::Return<VerificationResponse>> {
+    client
+        .verify_against_server_config(image)
+        .await
+        .map(cached::Return::new)
+}
+
 fn get_sigstore_certificate_verification_cache_key(
     image: &str,
     certificate: &[u8],
@@ -424,3 +479,24 @@ pub(crate) async fn get_sigstore_certificate_verification_cached(
         .await
         .map(cached::Return::new)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn verify_against_server_config_requires_a_verification_config() {
+        let mut client = Client::new(None, None, None)
+            .await
+            .expect("cannot create client");
+
+        let error = client
+            .verify_against_server_config("ghcr.io/kubewarden/tests/pod-privileged:v0.2.1".into())
+            .await
+            .err()
+            .expect("verification should fail");
+        assert!(error
+            .to_string()
+            .contains("has not been configured with a verification config"));
+    }
+}
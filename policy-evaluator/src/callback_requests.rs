@@ -9,6 +9,8 @@ use serde::{Deserialize, Serialize};
 use std::collections::BTreeMap;
 use tokio::{sync::oneshot, time::Instant};
 
+use crate::evaluation_context::KubernetesServiceAccount;
+
 /// Holds the response to a waPC evaluation request
 #[derive(Debug, Clone)]
 pub struct CallbackResponse {
@@ -24,11 +26,15 @@ pub struct CallbackRequest {
     pub request: CallbackRequestType,
     /// A tokio oneshot channel over which the evaluation response has to be sent
     pub response_channel: oneshot::Sender<Result<CallbackResponse>>,
+    /// The Kubernetes Service Account to impersonate when the request is made
+    /// against the Kubernetes API server. The identity of the host is used when
+    /// not set
+    pub kubernetes_service_account: Option<KubernetesServiceAccount>,
 }
 
 /// Describes the different kinds of request a waPC guest can make to
 /// our host.
-#[derive(Debug, Eq, PartialEq, Serialize, Deserialize)]
+#[derive(Clone, Debug, Eq, PartialEq, Serialize, Deserialize)]
 pub enum CallbackRequestType {
     /// Require the computation of the manifest digest of an OCI object (be
     /// it an image or anything else that can be stored into an OCI registry)
@@ -37,6 +43,13 @@ pub enum CallbackRequestType {
         image: String,
     },
 
+    /// Require the resolution of the tag of an OCI object to its manifest digest.
+    /// The response contains the reference of the object pinned to the digest
+    OciResolveDigest {
+        /// String pointing to the object (e.g.: `registry.testing.lan/busybox:1.0.0`)
+        image: String,
+    },
+
     /// Require the OCI object manifest returned by the registry (be it an image or anything else
     /// that can be stored into an OCI registry)
     OciManifest {
@@ -121,9 +134,30 @@ pub enum CallbackRequestType {
         annotations: Option<BTreeMap<String, String>>,
     },
 
+    /// Require the verification of the manifest digest of an OCI object
+    /// using the verification config of the host (e.g. the one used by
+    /// policy-server to verify the policies it loads), together with
+    /// its Sigstore trust root
+    SigstoreServerConfigVerify {
+        /// String pointing to the object (e.g.: `registry.testing.lan/busybox:1.0.0`)
+        image: String,
+    },
+
     /// Lookup the addresses for a given hostname via DNS
     DNSLookupHost { host: String },
 
+    /// Perform an HTTP request on behalf of a Rego policy using the `http.send`
+    /// builtin. The request has already been checked against the origins the
+    /// policy is allowed to reach, the redirects are not followed
+    HttpSend {
+        method: String,
+        url: String,
+        headers: BTreeMap<String, String>,
+        body: Option<Vec<u8>>,
+        /// The request is aborted once this number of milliseconds has elapsed
+        timeout_ms: u64,
+    },
+
     /// Get all the Kubernetes resources defined inside of the given
     /// namespace
     /// Note: cannot be used with cluster-wide resources
@@ -142,6 +176,28 @@ pub enum CallbackRequestType {
         field_selector: Option<String>,
     },
 
+    /// Get all the Kubernetes resources defined inside of a set of namespaces.
+    /// The namespaces are either listed explicitly, selected by their labels, or both.
+    /// The resources found inside of each namespace are merged into a single list.
+    /// Note: cannot be used with cluster-wide resources
+    KubernetesListResourceNamespaces {
+        /// apiVersion of the resource (v1 for core group, groupName/groupVersions for other).
+        api_version: String,
+        /// Singular PascalCase name of the resource
+        kind: String,
+        /// Namespaces scoping the search
+        namespaces: Vec<String>,
+        /// A selector picking the namespaces scoping the search by their labels.
+        /// The selected namespaces are added to the ones listed by `namespaces`
+        namespace_selector: Option<String>,
+        /// A selector to restrict the list of returned objects by their labels.
+        /// Defaults to everything if `None`
+        label_selector: Option<String>,
+        /// A selector to restrict the list of returned objects by their fields.
+        /// Defaults to everything if `None`
+        field_selector: Option<String>,
+    },
+
     /// Get all the Kubernetes resources defined inside of the given
     /// cluster
     /// Cluster level resources, or resources viewed across all namespaces.
@@ -204,6 +260,22 @@ pub enum CallbackRequestType {
         since: Instant,
     },
 
+    /// Subscribe to the changes of the data of the reflector tracking this query.
+    /// The response is a token, used by the runtime to look for the notifications
+    /// of changes without making further requests
+    SubscribeKubernetesListResourceAllChanges {
+        /// apiVersion of the resource (v1 for core group, groupName/groupVersions for other).
+        api_version: String,
+        /// Singular PascalCase name of the resource
+        kind: String,
+        /// A selector to restrict the list of returned objects by their labels.
+        /// Defaults to everything if `None`
+        label_selector: Option<String>,
+        /// A selector to restrict the list of returned objects by their fields.
+        /// Defaults to everything if `None`
+        field_selector: Option<String>,
+    },
+
     /// Check if the user can permissions to perform some operations
     KubernetesCanI {
         /// Describe the set of parameters used by the `can_i` function. The values in this struct
@@ -221,7 +293,103 @@ pub enum CallbackRequestType {
         /// the value here to keep the same pattern used by the other Kubernetes requests
         disable_cache: bool,
     },
+
+    /// Get the value of a key of the key/value store of the policy
+    KeyValueGet {
+        /// The policy owning the entries: each policy has its own entries
+        policy_id: String,
+        /// The namespace holding the key
+        namespace: String,
+        /// The key to look up
+        key: String,
+    },
+
+    /// Set the value of a key of the key/value store of the policy
+    KeyValueSet {
+        /// The policy owning the entries: each policy has its own entries
+        policy_id: String,
+        /// The namespace holding the key
+        namespace: String,
+        /// The key to set
+        key: String,
+        /// The value of the key
+        value: serde_json::Value,
+        /// Time to live of the entry, in seconds. The maximum time to live allowed by
+        /// the host is used when `None`
+        ttl_seconds: Option<u64>,
+    },
+
+    /// Delete a key of the key/value store of the policy
+    KeyValueDelete {
+        /// The policy owning the entries: each policy has its own entries
+        policy_id: String,
+        /// The namespace holding the key
+        namespace: String,
+        /// The key to delete
+        key: String,
+    },
+
+    /// Increment a counter stored inside of the key/value store of the policy
+    KeyValueIncrement {
+        /// The policy owning the entries: each policy has its own entries
+        policy_id: String,
+        /// The namespace holding the counter
+        namespace: String,
+        /// The key of the counter
+        key: String,
+        /// The amount added to the counter, can be negative
+        delta: i64,
+        /// Time to live of the counter, in seconds, used only when the counter is
+        /// created. The maximum time to live allowed by the host is used when `None`
+        ttl_seconds: Option<u64>,
+    },
 }
+
+impl CallbackRequestType {
+    /// The host capability serving the request, made of its namespace and of its
+    /// name, like `kubernetes/list_resources_all`
+    pub fn capability(&self) -> &'static str {
+        match self {
+            CallbackRequestType::OciManifestDigest { .. } => "oci/manifest_digest",
+            CallbackRequestType::OciResolveDigest { .. } => "oci/resolve_digest",
+            CallbackRequestType::OciManifest { .. } => "oci/oci_manifest",
+            CallbackRequestType::OciManifestAndConfig { .. } => "oci/oci_manifest_config",
+            CallbackRequestType::SigstorePubKeyVerify { .. }
+            | CallbackRequestType::SigstoreKeylessVerify { .. }
+            | CallbackRequestType::SigstoreKeylessPrefixVerify { .. }
+            | CallbackRequestType::SigstoreGithubActionsVerify { .. }
+            | CallbackRequestType::SigstoreCertificateVerify { .. } => "oci/verify",
+            CallbackRequestType::SigstoreServerConfigVerify { .. } => {
+                "oci/verify_image_against_server_config"
+            }
+            CallbackRequestType::DNSLookupHost { .. } => "net/dns_lookup_host",
+            CallbackRequestType::HttpSend { .. } => "http/send",
+            CallbackRequestType::KubernetesListResourceNamespace { .. } => {
+                "kubernetes/list_resources_by_namespace"
+            }
+            CallbackRequestType::KubernetesListResourceNamespaces { .. } => {
+                "kubernetes/list_resources_by_namespaces"
+            }
+            CallbackRequestType::KubernetesListResourceAll { .. }
+            | CallbackRequestType::HasKubernetesListResourceAllResultChangedSinceInstant {
+                ..
+            }
+            | CallbackRequestType::SubscribeKubernetesListResourceAllChanges { .. } => {
+                "kubernetes/list_resources_all"
+            }
+            CallbackRequestType::KubernetesGetResource { .. }
+            | CallbackRequestType::KubernetesGetResourcePluralName { .. } => {
+                "kubernetes/get_resource"
+            }
+            CallbackRequestType::KubernetesCanI { .. } => "kubernetes/can_i",
+            CallbackRequestType::KeyValueGet { .. } => "kv/get",
+            CallbackRequestType::KeyValueSet { .. } => "kv/set",
+            CallbackRequestType::KeyValueDelete { .. } => "kv/delete",
+            CallbackRequestType::KeyValueIncrement { .. } => "kv/increment",
+        }
+    }
+}
+
 mod tokio_instant_serializer {
     use serde::de::Error;
     use serde::{Deserialize, Deserializer, Serialize, Serializer};
@@ -348,6 +516,86 @@ impl From<kubewarden_policy_sdk::host_capabilities::kubernetes::ListResourcesByN
     }
 }
 
+/// Request sent by the guest to list the resources defined inside of a set of
+/// namespaces, served by the `kubernetes/list_resources_by_namespaces` capability
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct ListResourcesByNamespacesRequest {
+    /// apiVersion of the resource (v1 for core group, groupName/groupVersions for other).
+    pub api_version: String,
+    /// Singular PascalCase name of the resource
+    pub kind: String,
+    /// Namespaces scoping the search
+    #[serde(default)]
+    pub namespaces: Vec<String>,
+    /// A selector picking the namespaces scoping the search by their labels
+    #[serde(default)]
+    pub namespace_selector: Option<String>,
+    /// A selector to restrict the list of returned objects by their labels
+    #[serde(default)]
+    pub label_selector: Option<String>,
+    /// A selector to restrict the list of returned objects by their fields
+    #[serde(default)]
+    pub field_selector: Option<String>,
+}
+
+impl From<ListResourcesByNamespacesRequest> for CallbackRequestType {
+    fn from(req: ListResourcesByNamespacesRequest) -> Self {
+        CallbackRequestType::KubernetesListResourceNamespaces {
+            api_version: req.api_version,
+            kind: req.kind,
+            namespaces: req.namespaces,
+            namespace_selector: req.namespace_selector,
+            label_selector: req.label_selector,
+            field_selector: req.field_selector,
+        }
+    }
+}
+
+/// Request sent by the guest to read or delete a key of the key/value store, served
+/// by the `kv/get` and `kv/delete` capabilities
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct KeyValueKeyRequest {
+    /// The namespace holding the key
+    pub namespace: String,
+    /// The key
+    pub key: String,
+}
+
+/// Request sent by the guest to set a key of the key/value store, served by the
+/// `kv/set` capability
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct KeyValueSetRequest {
+    /// The namespace holding the key
+    pub namespace: String,
+    /// The key to set
+    pub key: String,
+    /// The value of the key
+    pub value: serde_json::Value,
+    /// Time to live of the entry, in seconds
+    #[serde(default)]
+    pub ttl_seconds: Option<u64>,
+}
+
+/// Request sent by the guest to increment a counter of the key/value store, served
+/// by the `kv/increment` capability
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct KeyValueIncrementRequest {
+    /// The namespace holding the counter
+    pub namespace: String,
+    /// The key of the counter
+    pub key: String,
+    /// The amount added to the counter, defaults to `1`
+    #[serde(default = "default_increment_delta")]
+    pub delta: i64,
+    /// Time to live of the counter, in seconds, used only when the counter is created
+    #[serde(default)]
+    pub ttl_seconds: Option<u64>,
+}
+
+fn default_increment_delta() -> i64 {
+    1
+}
+
 impl From<kubewarden_policy_sdk::host_capabilities::kubernetes::ListAllResourcesRequest>
     for CallbackRequestType
 {
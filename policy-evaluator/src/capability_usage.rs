@@ -0,0 +1,557 @@
+//! Analysis of the host capabilities used by the policies.
+//!
+//! When enabled, the calls made by a policy to the host capabilities are grouped by
+//! evaluation and kept for a sliding time window. The usage of each policy is then
+//! summarized by a report, which points out the patterns that are wasteful:
+//!
+//! * the same call, made with identical parameters, is repeated during one evaluation
+//! * the same call, made with identical parameters, is made by almost every evaluation:
+//!   its result is likely better provided through the settings of the policy
+//! * a capability is invoked many times per evaluation: the queries can likely be
+//!   merged, for example by listing the resources with a selector instead of
+//!   getting them one by one
+//!
+//! The calls are attributed to the evaluation running on the current thread, the
+//! ones made outside of an evaluation, like during the validation of the settings,
+//! are ignored.
+
+use lazy_static::lazy_static;
+use serde::Serialize;
+use std::{
+    cell::RefCell,
+    collections::{hash_map::DefaultHasher, BTreeMap, HashMap, VecDeque},
+    hash::{Hash, Hasher},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Mutex,
+    },
+    time::{Duration, Instant},
+};
+
+use crate::callback_requests::CallbackRequestType;
+
+/// Maximum number of evaluations kept for each policy, regardless of the window
+const MAX_EVALUATIONS_PER_POLICY: usize = 1024;
+
+/// The requests shown inside of the report are truncated to this length
+const MAX_REQUEST_LENGTH: usize = 256;
+
+/// Minimum number of evaluations required to tell that a call is made by almost
+/// every evaluation
+const MIN_EVALUATIONS_FOR_IDENTICAL_CALLS: usize = 10;
+
+/// Percentage of the evaluations that must make the same call for it to be reported
+const IDENTICAL_CALLS_PERCENTAGE: usize = 90;
+
+/// Average number of calls to a capability, per evaluation, above which the
+/// usage is reported
+const MANY_CALLS_PER_EVALUATION: f64 = 10.0;
+
+static ENABLED: AtomicBool = AtomicBool::new(false);
+
+lazy_static! {
+    static ref TRACKER: Mutex<CapabilityUsageTracker> =
+        Mutex::new(CapabilityUsageTracker::new(Duration::ZERO));
+}
+
+thread_local! {
+    /// The calls made by the evaluation running on the current thread
+    static CURRENT_EVALUATION: RefCell<Option<Vec<CapabilityCall>>> = const { RefCell::new(None) };
+}
+
+/// Start tracking the usage of the host capabilities, keeping the evaluations made
+/// during the given time window
+pub fn enable(window: Duration) {
+    TRACKER
+        .lock()
+        .expect("cannot lock the capability usage tracker")
+        .window = window;
+    ENABLED.store(true, Ordering::Relaxed);
+}
+
+/// Whether the usage of the host capabilities is tracked
+pub fn is_enabled() -> bool {
+    ENABLED.load(Ordering::Relaxed)
+}
+
+/// Forget the evaluations tracked so far
+pub fn reset() {
+    TRACKER
+        .lock()
+        .expect("cannot lock the capability usage tracker")
+        .policies
+        .clear();
+}
+
+/// Summarize the usage of the host capabilities made by the policies during the
+/// time window
+pub fn report() -> CapabilityUsageReport {
+    let mut tracker = TRACKER
+        .lock()
+        .expect("cannot lock the capability usage tracker");
+    CapabilityUsageReport {
+        window_seconds: tracker.window.as_secs(),
+        policies: tracker.report(Instant::now()),
+    }
+}
+
+/// Start recording the calls made by the evaluation running on the current thread.
+/// Nothing is done when an evaluation is already being recorded, its calls keep
+/// being accumulated
+pub(crate) fn begin_evaluation() {
+    if !is_enabled() {
+        return;
+    }
+    CURRENT_EVALUATION.with(|current| {
+        current.borrow_mut().get_or_insert_with(Vec::new);
+    });
+}
+
+/// Record a call made by the evaluation running on the current thread
+pub(crate) fn record_call(request: &CallbackRequestType) {
+    if !is_enabled() {
+        return;
+    }
+    // These requests are made by the Rego runtime to keep its caches fresh, not by
+    // the policy
+    if matches!(
+        request,
+        CallbackRequestType::HasKubernetesListResourceAllResultChangedSinceInstant { .. }
+            | CallbackRequestType::SubscribeKubernetesListResourceAllChanges { .. }
+    ) {
+        return;
+    }
+
+    CURRENT_EVALUATION.with(|current| {
+        if let Some(calls) = current.borrow_mut().as_mut() {
+            calls.push(CapabilityCall::new(request));
+        }
+    });
+}
+
+/// Stop recording the calls made by the evaluation running on the current thread,
+/// adding them to the usage of the given policy
+pub(crate) fn end_evaluation(policy_id: &str) {
+    let calls = match CURRENT_EVALUATION.with(|current| current.borrow_mut().take()) {
+        Some(calls) => calls,
+        None => return,
+    };
+    TRACKER
+        .lock()
+        .expect("cannot lock the capability usage tracker")
+        .add_evaluation(policy_id, calls, Instant::now());
+}
+
+/// The usage of the host capabilities made by the policies
+#[derive(Serialize, Debug, Clone, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct CapabilityUsageReport {
+    /// The time window covered by the report
+    pub window_seconds: u64,
+    pub policies: Vec<PolicyCapabilityUsage>,
+}
+
+/// The usage of the host capabilities made by one policy
+#[derive(Serialize, Debug, Clone, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct PolicyCapabilityUsage {
+    pub policy_id: String,
+    /// The number of evaluations made during the time window
+    pub evaluations: usize,
+    pub capabilities: Vec<CapabilityUsage>,
+    /// The wasteful patterns found, sorted by the number of calls per evaluation
+    pub findings: Vec<CapabilityUsageFinding>,
+}
+
+/// The usage of one host capability made by a policy
+#[derive(Serialize, Debug, Clone, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct CapabilityUsage {
+    pub capability: String,
+    pub calls: usize,
+    pub calls_per_evaluation: f64,
+    pub max_calls_per_evaluation: usize,
+    /// The calls repeating, with identical parameters, a call already made during
+    /// the same evaluation
+    pub duplicated_calls: usize,
+}
+
+/// The kind of wasteful pattern found
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub enum FindingKind {
+    /// The same call is repeated during one evaluation
+    RepeatedCall,
+    /// The same call is made by almost every evaluation
+    IdenticalAcrossEvaluations,
+    /// The capability is invoked many times per evaluation
+    ManyCalls,
+}
+
+/// A wasteful usage of a host capability, together with the advice to fix it
+#[derive(Serialize, Debug, Clone, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct CapabilityUsageFinding {
+    pub kind: FindingKind,
+    pub capability: String,
+    /// The parameters of the call, not set when the finding is about the capability
+    /// as a whole
+    pub request: Option<String>,
+    pub calls_per_evaluation: f64,
+    pub message: String,
+    pub advice: String,
+}
+
+#[derive(Debug, Clone)]
+struct CapabilityCall {
+    capability: &'static str,
+    /// Identifies the parameters of the call
+    fingerprint: u64,
+    request: String,
+}
+
+impl CapabilityCall {
+    fn new(request: &CallbackRequestType) -> Self {
+        let mut serialized = serde_json::to_string(request).unwrap_or_default();
+        let mut hasher = DefaultHasher::new();
+        serialized.hash(&mut hasher);
+
+        if serialized.len() > MAX_REQUEST_LENGTH {
+            let mut end = MAX_REQUEST_LENGTH;
+            while !serialized.is_char_boundary(end) {
+                end -= 1;
+            }
+            serialized.truncate(end);
+            serialized.push_str("...");
+        }
+
+        CapabilityCall {
+            capability: request.capability(),
+            fingerprint: hasher.finish(),
+            request: serialized,
+        }
+    }
+}
+
+#[derive(Debug)]
+struct EvaluationSample {
+    at: Instant,
+    calls: Vec<CapabilityCall>,
+}
+
+#[derive(Debug)]
+struct CapabilityUsageTracker {
+    window: Duration,
+    policies: HashMap<String, VecDeque<EvaluationSample>>,
+}
+
+/// The calls made with the same parameters
+struct IdenticalCalls<'a> {
+    capability: &'static str,
+    request: &'a str,
+    calls: usize,
+    evaluations: usize,
+}
+
+impl CapabilityUsageTracker {
+    fn new(window: Duration) -> Self {
+        CapabilityUsageTracker {
+            window,
+            policies: HashMap::new(),
+        }
+    }
+
+    fn add_evaluation(&mut self, policy_id: &str, calls: Vec<CapabilityCall>, now: Instant) {
+        let samples = self.policies.entry(policy_id.to_owned()).or_default();
+        samples.push_back(EvaluationSample { at: now, calls });
+        if samples.len() > MAX_EVALUATIONS_PER_POLICY {
+            samples.pop_front();
+        }
+    }
+
+    /// Remove the evaluations made before the time window
+    fn prune(&mut self, now: Instant) {
+        let oldest = match now.checked_sub(self.window) {
+            Some(oldest) => oldest,
+            None => return,
+        };
+        for samples in self.policies.values_mut() {
+            while samples.front().is_some_and(|sample| sample.at < oldest) {
+                samples.pop_front();
+            }
+        }
+        self.policies.retain(|_, samples| !samples.is_empty());
+    }
+
+    fn report(&mut self, now: Instant) -> Vec<PolicyCapabilityUsage> {
+        self.prune(now);
+
+        let mut policies: Vec<PolicyCapabilityUsage> = self
+            .policies
+            .iter()
+            .map(|(policy_id, samples)| policy_usage(policy_id, samples))
+            .collect();
+        policies.sort_by(|a, b| a.policy_id.cmp(&b.policy_id));
+        policies
+    }
+}
+
+fn policy_usage(policy_id: &str, samples: &VecDeque<EvaluationSample>) -> PolicyCapabilityUsage {
+    let evaluations = samples.len();
+    let mut capabilities: BTreeMap<&'static str, CapabilityUsage> = BTreeMap::new();
+    let mut identical_calls: BTreeMap<(&'static str, u64), IdenticalCalls> = BTreeMap::new();
+
+    for sample in samples {
+        let mut calls_of_sample: BTreeMap<(&'static str, u64), usize> = BTreeMap::new();
+        for call in &sample.calls {
+            *calls_of_sample
+                .entry((call.capability, call.fingerprint))
+                .or_default() += 1;
+            identical_calls
+                .entry((call.capability, call.fingerprint))
+                .or_insert_with(|| IdenticalCalls {
+                    capability: call.capability,
+                    request: &call.request,
+                    calls: 0,
+                    evaluations: 0,
+                })
+                .calls += 1;
+        }
+
+        let mut calls_per_capability: BTreeMap<&'static str, usize> = BTreeMap::new();
+        for (&(capability, fingerprint), &calls) in &calls_of_sample {
+            *calls_per_capability.entry(capability).or_default() += calls;
+            let usage = capabilities
+                .entry(capability)
+                .or_insert_with(|| CapabilityUsage {
+                    capability: capability.to_owned(),
+                    calls: 0,
+                    calls_per_evaluation: 0.0,
+                    max_calls_per_evaluation: 0,
+                    duplicated_calls: 0,
+                });
+            usage.calls += calls;
+            usage.duplicated_calls += calls - 1;
+            if let Some(identical) = identical_calls.get_mut(&(capability, fingerprint)) {
+                identical.evaluations += 1;
+            }
+        }
+        for (capability, calls) in calls_per_capability {
+            if let Some(usage) = capabilities.get_mut(capability) {
+                usage.max_calls_per_evaluation = usage.max_calls_per_evaluation.max(calls);
+            }
+        }
+    }
+
+    let mut findings = Vec::new();
+    for identical in identical_calls.values() {
+        let calls_per_evaluation = identical.calls as f64 / identical.evaluations as f64;
+        if identical.calls > identical.evaluations {
+            findings.push(CapabilityUsageFinding {
+                kind: FindingKind::RepeatedCall,
+                capability: identical.capability.to_owned(),
+                request: Some(identical.request.to_owned()),
+                calls_per_evaluation,
+                message: format!(
+                    "policy {policy_id} calls {} {calls_per_evaluation:.1} times per request with identical parameters",
+                    identical.capability
+                ),
+                advice: "reuse the result of the first call during the evaluation".to_owned(),
+            });
+        } else if evaluations >= MIN_EVALUATIONS_FOR_IDENTICAL_CALLS
+            && identical.evaluations * 100 >= evaluations * IDENTICAL_CALLS_PERCENTAGE
+            && !identical.capability.starts_with("kv/")
+        {
+            findings.push(CapabilityUsageFinding {
+                kind: FindingKind::IdenticalAcrossEvaluations,
+                capability: identical.capability.to_owned(),
+                request: Some(identical.request.to_owned()),
+                calls_per_evaluation,
+                message: format!(
+                    "policy {policy_id} calls {} with identical parameters in {} requests out of {evaluations}",
+                    identical.capability, identical.evaluations
+                ),
+                advice: "the result does not depend on the request, provide it through the settings of the policy"
+                    .to_owned(),
+            });
+        }
+    }
+
+    let mut capabilities: Vec<CapabilityUsage> = capabilities.into_values().collect();
+    for usage in &mut capabilities {
+        usage.calls_per_evaluation = usage.calls as f64 / evaluations as f64;
+        if usage.calls_per_evaluation >= MANY_CALLS_PER_EVALUATION {
+            findings.push(CapabilityUsageFinding {
+                kind: FindingKind::ManyCalls,
+                capability: usage.capability.clone(),
+                request: None,
+                calls_per_evaluation: usage.calls_per_evaluation,
+                message: format!(
+                    "policy {policy_id} calls {} {:.1} times per request",
+                    usage.capability, usage.calls_per_evaluation
+                ),
+                advice: "merge the queries, for example by listing the resources with a selector instead of getting them one by one"
+                    .to_owned(),
+            });
+        }
+    }
+    findings.sort_by(|a, b| b.calls_per_evaluation.total_cmp(&a.calls_per_evaluation));
+
+    PolicyCapabilityUsage {
+        policy_id: policy_id.to_owned(),
+        evaluations,
+        capabilities,
+        findings,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn list_pods(namespace: &str) -> CapabilityCall {
+        CapabilityCall::new(&CallbackRequestType::KubernetesListResourceNamespace {
+            api_version: "v1".to_owned(),
+            kind: "Pod".to_owned(),
+            namespace: namespace.to_owned(),
+            label_selector: None,
+            field_selector: None,
+        })
+    }
+
+    fn dns_lookup(host: &str) -> CapabilityCall {
+        CapabilityCall::new(&CallbackRequestType::DNSLookupHost {
+            host: host.to_owned(),
+        })
+    }
+
+    fn tracker_with(evaluations: Vec<Vec<CapabilityCall>>) -> CapabilityUsageTracker {
+        let mut tracker = CapabilityUsageTracker::new(Duration::from_secs(60));
+        let now = Instant::now();
+        for calls in evaluations {
+            tracker.add_evaluation("policy", calls, now);
+        }
+        tracker
+    }
+
+    #[test]
+    fn repeated_calls() {
+        let mut tracker = tracker_with(vec![
+            vec![
+                list_pods("a"),
+                list_pods("a"),
+                list_pods("a"),
+                list_pods("a"),
+            ],
+            vec![
+                list_pods("b"),
+                list_pods("b"),
+                list_pods("b"),
+                list_pods("b"),
+            ],
+        ]);
+
+        let report = tracker.report(Instant::now());
+
+        assert_eq!(report.len(), 1);
+        let usage = &report[0];
+        assert_eq!(usage.evaluations, 2);
+        assert_eq!(
+            usage.capabilities,
+            vec![CapabilityUsage {
+                capability: "kubernetes/list_resources_by_namespace".to_owned(),
+                calls: 8,
+                calls_per_evaluation: 4.0,
+                max_calls_per_evaluation: 4,
+                duplicated_calls: 6,
+            }]
+        );
+        assert_eq!(usage.findings.len(), 2);
+        assert!(usage
+            .findings
+            .iter()
+            .all(|finding| finding.kind == FindingKind::RepeatedCall
+                && finding.calls_per_evaluation == 4.0));
+        assert_eq!(
+            usage.findings[0].message,
+            "policy policy calls kubernetes/list_resources_by_namespace 4.0 times per request with identical parameters"
+        );
+    }
+
+    #[test]
+    fn identical_calls_across_evaluations() {
+        let evaluations = (0..MIN_EVALUATIONS_FOR_IDENTICAL_CALLS)
+            .map(|i| vec![dns_lookup("example.com"), list_pods(&i.to_string())])
+            .collect();
+        let mut tracker = tracker_with(evaluations);
+
+        let report = tracker.report(Instant::now());
+
+        let findings = &report[0].findings;
+        assert_eq!(findings.len(), 1);
+        assert_eq!(findings[0].kind, FindingKind::IdenticalAcrossEvaluations);
+        assert_eq!(findings[0].capability, "net/dns_lookup_host");
+        assert_eq!(
+            findings[0].request.as_deref(),
+            Some(r#"{"DNSLookupHost":{"host":"example.com"}}"#)
+        );
+    }
+
+    #[test]
+    fn identical_calls_require_enough_evaluations() {
+        let mut tracker = tracker_with(vec![vec![dns_lookup("example.com")]; 2]);
+
+        let report = tracker.report(Instant::now());
+
+        assert!(report[0].findings.is_empty());
+    }
+
+    #[test]
+    fn many_calls() {
+        let calls = (0..12).map(|i| list_pods(&i.to_string())).collect();
+        let mut tracker = tracker_with(vec![calls]);
+
+        let report = tracker.report(Instant::now());
+
+        let findings = &report[0].findings;
+        assert_eq!(findings.len(), 1);
+        assert_eq!(findings[0].kind, FindingKind::ManyCalls);
+        assert_eq!(findings[0].request, None);
+        assert_eq!(findings[0].calls_per_evaluation, 12.0);
+    }
+
+    #[test]
+    fn evaluations_outside_of_the_window_are_pruned() {
+        let mut tracker = CapabilityUsageTracker::new(Duration::from_secs(60));
+        let now = Instant::now();
+        tracker.add_evaluation("old", vec![dns_lookup("example.com")], now);
+        tracker.add_evaluation("new", vec![], now + Duration::from_secs(90));
+
+        let report = tracker.report(now + Duration::from_secs(120));
+
+        assert_eq!(report.len(), 1);
+        assert_eq!(report[0].policy_id, "new");
+        assert!(report[0].capabilities.is_empty());
+    }
+
+    #[test]
+    fn evaluations_are_bounded() {
+        let mut tracker = tracker_with(
+            (0..MAX_EVALUATIONS_PER_POLICY + 10)
+                .map(|_| Vec::new())
+                .collect(),
+        );
+
+        let report = tracker.report(Instant::now());
+
+        assert_eq!(report[0].evaluations, MAX_EVALUATIONS_PER_POLICY);
+    }
+
+    #[test]
+    fn long_requests_are_truncated() {
+        let call = dns_lookup(&"a".repeat(2 * MAX_REQUEST_LENGTH));
+
+        assert_eq!(call.request.len(), MAX_REQUEST_LENGTH + 3);
+        assert_ne!(call.fingerprint, dns_lookup("a").fingerprint);
+    }
+}
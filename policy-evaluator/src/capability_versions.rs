@@ -0,0 +1,386 @@
+use lazy_static::lazy_static;
+use serde::Serialize;
+use std::{
+    collections::{BTreeMap, BTreeSet, HashMap},
+    sync::RwLock,
+};
+use thiserror::Error;
+use tracing::warn;
+
+/// The host capabilities implemented by this host, grouped by namespace. Each capability
+/// is followed by the list of versions that are supported.
+///
+/// Operations that do not have an explicit version, like `kubewarden/kubernetes/get_resource`,
+/// are considered to be at version 1.
+const SUPPORTED_CAPABILITIES: &[(&str, &str, &[u32])] = &[
+    ("tracing", "log", &[1]),
+    ("oci", "verify", &[1, 2]),
+    ("oci", "verify_image_against_server_config", &[1]),
+    ("oci", "manifest_digest", &[1]),
+    ("oci", "resolve_digest", &[1]),
+    ("oci", "oci_manifest", &[1]),
+    ("oci", "oci_manifest_config", &[1]),
+    ("net", "dns_lookup_host", &[1]),
+    ("http", "send", &[1]),
+    ("crypto", "is_certificate_trusted", &[1]),
+    ("kubernetes", "list_resources_by_namespace", &[1]),
+    ("kubernetes", "list_resources_by_namespaces", &[1]),
+    ("kubernetes", "list_resources_all", &[1]),
+    ("kubernetes", "get_resource", &[1]),
+    ("kubernetes", "can_i", &[1]),
+    ("kv", "get", &[1]),
+    ("kv", "set", &[1]),
+    ("kv", "delete", &[1]),
+    ("kv", "increment", &[1]),
+];
+
+lazy_static! {
+    /// The unsupported capability versions requested by each policy, the key is the ID
+    /// of the policy
+    static ref UNSUPPORTED_CAPABILITY_VERSIONS: RwLock<HashMap<String, BTreeSet<UnsupportedCapabilityVersion>>> =
+        RwLock::new(HashMap::new());
+}
+
+/// Returned when a policy invokes a version of a host capability that is not
+/// implemented by this host. This usually happens when the policy has been built
+/// with a more recent version of the Kubewarden SDK.
+#[derive(Error, Serialize, Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(rename_all = "camelCase")]
+#[error(
+    "unsupported version v{requested_version} of host capability {namespace}/{capability}, supported versions: {}",
+    .supported_versions.iter().map(|v| format!("v{v}")).collect::<Vec<String>>().join(", ")
+)]
+pub struct UnsupportedCapabilityVersion {
+    /// The namespace of the capability, e.g. `kubernetes`
+    pub namespace: String,
+    /// The name of the capability, e.g. `list_resources_all`
+    pub capability: String,
+    /// The version requested by the policy
+    pub requested_version: u32,
+    /// The versions implemented by this host
+    pub supported_versions: Vec<u32>,
+}
+
+/// The host capabilities implemented by this host, together with their supported
+/// versions. The key is made of the namespace and of the name of the capability,
+/// like `kubernetes/get_resource`
+pub fn supported_capabilities() -> BTreeMap<String, Vec<u32>> {
+    SUPPORTED_CAPABILITIES
+        .iter()
+        .map(|(namespace, capability, versions)| {
+            (format!("{namespace}/{capability}"), versions.to_vec())
+        })
+        .collect()
+}
+
+/// Split a waPC operation into the name of the capability and its version.
+/// Both the `v2/verify` and the `list_resources_all/v2` forms are accepted,
+/// operations without a version are considered to be at version 1.
+pub(crate) fn parse_operation(operation: &str) -> (String, u32) {
+    let mut version = None;
+    let mut name = Vec::new();
+    for part in operation.split('/') {
+        match part.strip_prefix('v').and_then(|v| v.parse::<u32>().ok()) {
+            Some(v) if version.is_none() => version = Some(v),
+            _ => name.push(part),
+        }
+    }
+
+    (name.join("/"), version.unwrap_or(1))
+}
+
+/// Check if an operation that is not handled by the host is a known capability invoked
+/// with an unsupported version. When that happens, the request is recorded and a warning
+/// is emitted the first time the policy makes it.
+pub(crate) fn check_unsupported_version(
+    policy_id: &str,
+    namespace: &str,
+    operation: &str,
+) -> Option<UnsupportedCapabilityVersion> {
+    let (capability, requested_version) = parse_operation(operation);
+    let (_, _, supported_versions) = SUPPORTED_CAPABILITIES
+        .iter()
+        .find(|(ns, name, _)| *ns == namespace && *name == capability)?;
+    if supported_versions.contains(&requested_version) {
+        return None;
+    }
+
+    let unsupported = UnsupportedCapabilityVersion {
+        namespace: namespace.to_owned(),
+        capability,
+        requested_version,
+        supported_versions: supported_versions.to_vec(),
+    };
+
+    let newly_recorded = UNSUPPORTED_CAPABILITY_VERSIONS
+        .write()
+        .map(|mut recorded| {
+            recorded
+                .entry(policy_id.to_owned())
+                .or_default()
+                .insert(unsupported.clone())
+        })
+        .unwrap_or_default();
+    if newly_recorded {
+        warn!(
+            policy_id,
+            namespace,
+            capability = unsupported.capability.as_str(),
+            requested_version,
+            supported_versions = ?unsupported.supported_versions,
+            "policy requires a version of a host capability that is not supported, the policy may have been built with a newer SDK"
+        );
+    }
+
+    Some(unsupported)
+}
+
+/// Return the unsupported capability versions requested so far by the given policy
+pub fn unsupported_capability_versions(policy_id: &str) -> Vec<UnsupportedCapabilityVersion> {
+    UNSUPPORTED_CAPABILITY_VERSIONS
+        .read()
+        .map(|recorded| {
+            recorded
+                .get(policy_id)
+                .map(|unsupported| unsupported.iter().cloned().collect())
+                .unwrap_or_default()
+        })
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::callback_requests::CallbackRequestType;
+    use rstest::rstest;
+
+    #[rstest]
+    #[case("list_resources_all", "list_resources_all", 1)]
+    #[case("v1/verify", "verify", 1)]
+    #[case("v2/verify", "verify", 2)]
+    #[case("list_resources/v2", "list_resources", 2)]
+    #[case(
+        "v1/verify_image_against_server_config",
+        "verify_image_against_server_config",
+        1
+    )]
+    fn parse_capability_operation(
+        #[case] operation: &str,
+        #[case] expected_name: &str,
+        #[case] expected_version: u32,
+    ) {
+        assert_eq!(
+            parse_operation(operation),
+            (expected_name.to_string(), expected_version)
+        );
+    }
+
+    #[test]
+    fn detect_unsupported_versions() {
+        let policy_id = "detect_unsupported_versions";
+
+        assert!(check_unsupported_version(policy_id, "oci", "v2/verify").is_none());
+        assert!(check_unsupported_version(policy_id, "oci", "v1/unknown").is_none());
+
+        let unsupported =
+            check_unsupported_version(policy_id, "kubernetes", "list_resources_all/v2")
+                .expect("the version should not be supported");
+        assert_eq!(
+            unsupported.to_string(),
+            "unsupported version v2 of host capability kubernetes/list_resources_all, supported versions: v1"
+        );
+
+        // requesting the same capability again doesn't lead to duplicates
+        check_unsupported_version(policy_id, "kubernetes", "list_resources_all/v2");
+        check_unsupported_version(policy_id, "oci", "v3/verify");
+        let recorded = unsupported_capability_versions(policy_id);
+        assert_eq!(recorded.len(), 2);
+        assert_eq!(recorded[0], unsupported);
+        assert_eq!(recorded[1].supported_versions, vec![1, 2]);
+
+        assert!(unsupported_capability_versions("unknown-policy").is_empty());
+    }
+
+    /// One request for each variant of `CallbackRequestType`, the `match` fails to
+    /// compile when a new variant is added without being listed here
+    fn callback_requests() -> Vec<CallbackRequestType> {
+        let image = || "ghcr.io/kubewarden/policy:latest".to_owned();
+        let resource = || ("v1".to_owned(), "Pod".to_owned());
+        let requests = vec![
+            CallbackRequestType::OciManifestDigest { image: image() },
+            CallbackRequestType::OciResolveDigest { image: image() },
+            CallbackRequestType::OciManifest { image: image() },
+            CallbackRequestType::OciManifestAndConfig { image: image() },
+            CallbackRequestType::SigstorePubKeyVerify {
+                image: image(),
+                pub_keys: vec![],
+                annotations: None,
+            },
+            CallbackRequestType::SigstoreKeylessVerify {
+                image: image(),
+                keyless: vec![],
+                annotations: None,
+            },
+            CallbackRequestType::SigstoreKeylessPrefixVerify {
+                image: image(),
+                keyless_prefix: vec![],
+                annotations: None,
+            },
+            CallbackRequestType::SigstoreGithubActionsVerify {
+                image: image(),
+                owner: "kubewarden".to_owned(),
+                repo: None,
+                annotations: None,
+            },
+            CallbackRequestType::SigstoreCertificateVerify {
+                image: image(),
+                certificate: vec![],
+                certificate_chain: None,
+                require_rekor_bundle: false,
+                annotations: None,
+            },
+            CallbackRequestType::SigstoreServerConfigVerify { image: image() },
+            CallbackRequestType::DNSLookupHost {
+                host: "kubewarden.io".to_owned(),
+            },
+            CallbackRequestType::HttpSend {
+                method: "GET".to_owned(),
+                url: "https://kubewarden.io".to_owned(),
+                headers: BTreeMap::new(),
+                body: None,
+                timeout_ms: 1000,
+            },
+            CallbackRequestType::KubernetesListResourceNamespace {
+                api_version: resource().0,
+                kind: resource().1,
+                namespace: "default".to_owned(),
+                label_selector: None,
+                field_selector: None,
+            },
+            CallbackRequestType::KubernetesListResourceNamespaces {
+                api_version: resource().0,
+                kind: resource().1,
+                namespaces: vec![],
+                namespace_selector: None,
+                label_selector: None,
+                field_selector: None,
+            },
+            CallbackRequestType::KubernetesListResourceAll {
+                api_version: resource().0,
+                kind: resource().1,
+                label_selector: None,
+                field_selector: None,
+            },
+            CallbackRequestType::KubernetesGetResource {
+                api_version: resource().0,
+                kind: resource().1,
+                name: "nginx".to_owned(),
+                namespace: None,
+                disable_cache: false,
+            },
+            CallbackRequestType::KubernetesGetResourcePluralName {
+                api_version: resource().0,
+                kind: resource().1,
+            },
+            CallbackRequestType::HasKubernetesListResourceAllResultChangedSinceInstant {
+                api_version: resource().0,
+                kind: resource().1,
+                label_selector: None,
+                field_selector: None,
+                since: tokio::time::Instant::now(),
+            },
+            CallbackRequestType::SubscribeKubernetesListResourceAllChanges {
+                api_version: resource().0,
+                kind: resource().1,
+                label_selector: None,
+                field_selector: None,
+            },
+            CallbackRequestType::KubernetesCanI {
+                request: Default::default(),
+                disable_cache: false,
+            },
+            CallbackRequestType::KeyValueGet {
+                policy_id: "policy".to_owned(),
+                namespace: "default".to_owned(),
+                key: "key".to_owned(),
+            },
+            CallbackRequestType::KeyValueSet {
+                policy_id: "policy".to_owned(),
+                namespace: "default".to_owned(),
+                key: "key".to_owned(),
+                value: serde_json::Value::Null,
+                ttl_seconds: None,
+            },
+            CallbackRequestType::KeyValueDelete {
+                policy_id: "policy".to_owned(),
+                namespace: "default".to_owned(),
+                key: "key".to_owned(),
+            },
+            CallbackRequestType::KeyValueIncrement {
+                policy_id: "policy".to_owned(),
+                namespace: "default".to_owned(),
+                key: "key".to_owned(),
+                delta: 1,
+                ttl_seconds: None,
+            },
+        ];
+
+        for request in &requests {
+            match request {
+                CallbackRequestType::OciManifestDigest { .. }
+                | CallbackRequestType::OciResolveDigest { .. }
+                | CallbackRequestType::OciManifest { .. }
+                | CallbackRequestType::OciManifestAndConfig { .. }
+                | CallbackRequestType::SigstorePubKeyVerify { .. }
+                | CallbackRequestType::SigstoreKeylessVerify { .. }
+                | CallbackRequestType::SigstoreKeylessPrefixVerify { .. }
+                | CallbackRequestType::SigstoreGithubActionsVerify { .. }
+                | CallbackRequestType::SigstoreCertificateVerify { .. }
+                | CallbackRequestType::SigstoreServerConfigVerify { .. }
+                | CallbackRequestType::DNSLookupHost { .. }
+                | CallbackRequestType::HttpSend { .. }
+                | CallbackRequestType::KubernetesListResourceNamespace { .. }
+                | CallbackRequestType::KubernetesListResourceNamespaces { .. }
+                | CallbackRequestType::KubernetesListResourceAll { .. }
+                | CallbackRequestType::KubernetesGetResource { .. }
+                | CallbackRequestType::KubernetesGetResourcePluralName { .. }
+                | CallbackRequestType::HasKubernetesListResourceAllResultChangedSinceInstant {
+                    ..
+                }
+                | CallbackRequestType::SubscribeKubernetesListResourceAllChanges { .. }
+                | CallbackRequestType::KubernetesCanI { .. }
+                | CallbackRequestType::KeyValueGet { .. }
+                | CallbackRequestType::KeyValueSet { .. }
+                | CallbackRequestType::KeyValueDelete { .. }
+                | CallbackRequestType::KeyValueIncrement { .. } => {}
+            }
+        }
+        requests
+    }
+
+    #[test]
+    fn supported_capabilities_match_the_callback_requests() {
+        // served by the runtimes, without going through the callback handler
+        let served_by_the_runtimes = ["tracing/log", "crypto/is_certificate_trusted"];
+
+        let served_by_the_callback_handler: BTreeSet<&str> = callback_requests()
+            .iter()
+            .map(CallbackRequestType::capability)
+            .collect();
+        let supported = supported_capabilities();
+
+        for capability in &served_by_the_callback_handler {
+            assert!(
+                supported.contains_key(*capability),
+                "{capability} is not listed among the supported capabilities"
+            );
+        }
+        for capability in supported.keys() {
+            assert!(
+                served_by_the_callback_handler.contains(capability.as_str())
+                    || served_by_the_runtimes.contains(&capability.as_str()),
+                "{capability} is not served by the host"
+            );
+        }
+    }
+}
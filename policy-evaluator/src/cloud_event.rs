@@ -0,0 +1,113 @@
+use std::collections::BTreeMap;
+
+use serde::{Deserialize, Serialize};
+
+/// The only version of the CloudEvents specification that is supported
+pub const SPEC_VERSION: &str = "1.0";
+
+/// This models a CloudEvent, using the attributes defined by version 1.0 of the
+/// specification. The event is serialized using the JSON event format, which is
+/// also what the policies receive when evaluating a CloudEvent.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct CloudEvent {
+    pub specversion: String,
+    pub id: String,
+    pub source: String,
+    #[serde(rename = "type")]
+    pub ty: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub subject: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub time: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub datacontenttype: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub dataschema: Option<String>,
+    /// The payload of the event
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub data: Option<serde_json::Value>,
+    /// The extension attributes of the event
+    #[serde(flatten)]
+    pub extensions: BTreeMap<String, serde_json::Value>,
+}
+
+impl CloudEvent {
+    /// Create a new event, without payload
+    pub fn new(id: &str, source: &str, ty: &str) -> Self {
+        CloudEvent {
+            specversion: SPEC_VERSION.to_owned(),
+            id: id.to_owned(),
+            source: source.to_owned(),
+            ty: ty.to_owned(),
+            subject: None,
+            time: None,
+            datacontenttype: None,
+            dataschema: None,
+            data: None,
+            extensions: BTreeMap::new(),
+        }
+    }
+
+    /// Ensure the event has all the required attributes and is using a supported
+    /// version of the specification
+    pub fn validate(&self) -> Result<(), String> {
+        if self.specversion != SPEC_VERSION {
+            return Err(format!(
+                "unsupported CloudEvents specversion '{}', only '{}' is supported",
+                self.specversion, SPEC_VERSION
+            ));
+        }
+        for (name, value) in [
+            ("id", &self.id),
+            ("source", &self.source),
+            ("type", &self.ty),
+        ] {
+            if value.is_empty() {
+                return Err(format!("the CloudEvent '{name}' attribute cannot be empty"));
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn json_event_format() {
+        let event_json = json!({
+            "specversion": "1.0",
+            "id": "A234-1234-1234",
+            "source": "https://github.com/kubewarden/policy-server",
+            "type": "com.github.push",
+            "datacontenttype": "application/json",
+            "data": {"ref": "refs/heads/main"},
+            "comexampleextension1": "value",
+        });
+
+        let event: CloudEvent = serde_json::from_value(event_json.clone()).unwrap();
+        assert_eq!(event.ty, "com.github.push");
+        assert_eq!(event.data, Some(json!({"ref": "refs/heads/main"})));
+        assert_eq!(
+            event.extensions,
+            BTreeMap::from([("comexampleextension1".to_owned(), json!("value"))])
+        );
+        assert!(event.validate().is_ok());
+
+        assert_eq!(serde_json::to_value(&event).unwrap(), event_json);
+    }
+
+    #[test]
+    fn validate() {
+        let mut event = CloudEvent::new("1", "/ci", "dev.example.build");
+        assert!(event.validate().is_ok());
+
+        event.specversion = "0.3".to_owned();
+        assert!(event.validate().is_err());
+
+        let event = CloudEvent::new("1", "", "dev.example.build");
+        assert!(event.validate().is_err());
+    }
+}
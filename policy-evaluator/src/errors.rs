@@ -66,6 +66,30 @@ pub enum PolicyEvaluatorBuilderError {
 
     #[error("error when building rego precompiled stack")]
     NewRegoStackPre(#[source] wasmtime::Error),
+
+    #[error("cannot select the entrypoint of the rego policy: {0}")]
+    RegoEntrypoint(#[source] crate::runtimes::rego::errors::RegoRuntimeError),
+
+    #[error("cannot link the Rego libraries of the policy: {0}")]
+    RegoLibrary(#[source] crate::runtimes::rego::errors::RegoRuntimeError),
+}
+
+#[derive(Error, Debug)]
+pub enum AdmissionRequestBuilderError {
+    #[error("`{0}` is not the operation of an admission request")]
+    InvalidOperation(String),
+
+    #[error("{0} requests must provide the object")]
+    MissingObject(String),
+
+    #[error("{0} requests must provide the old object")]
+    MissingOldObject(String),
+
+    #[error("the object must have a {0}")]
+    InvalidObject(&'static str),
+
+    #[error("cannot serialize the object: {0}")]
+    Serialize(#[source] serde_json::Error),
 }
 
 #[derive(Error, Debug)]
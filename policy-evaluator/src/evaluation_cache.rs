@@ -0,0 +1,308 @@
+//! Cache of the results of the evaluations.
+//!
+//! Audit scans, and controllers reconciling many identical objects, make the
+//! policies evaluate the same request over and over. The responses are cached
+//! by the digest of the policy, together with the hashes of the request and of
+//! the settings, hence the policy is evaluated only once for all of them.
+//!
+//! The UID of the request is not part of the hash, the cached response is given
+//! the UID of the request it's replying to.
+//!
+//! The responses of the evaluations that invoked a host capability served by the
+//! callback handler, like the OCI, sigstore, DNS and Kubernetes ones, are not
+//! cached: their outcome depends on state that can change at any time.
+
+use std::{cell::Cell, sync::Mutex, time::Duration};
+
+use cached::{Cached, TimedSizedCache};
+use sha2::{Digest, Sha256};
+
+use crate::{
+    admission_response::AdmissionResponse,
+    metrics,
+    policy_evaluator::{PolicySettings, ValidateRequest},
+};
+
+/// Configuration of the cache of the evaluation results
+#[derive(Clone, Debug, PartialEq)]
+pub struct EvaluationCacheConfig {
+    /// For how long a response is reused
+    pub ttl: Duration,
+    /// Maximum number of responses kept inside of the cache. Setting this to `0`
+    /// disables the cache
+    pub max_entries: usize,
+}
+
+impl Default for EvaluationCacheConfig {
+    fn default() -> Self {
+        EvaluationCacheConfig {
+            ttl: Duration::from_secs(30),
+            max_entries: 0,
+        }
+    }
+}
+
+/// Identifies the evaluation of a request by a policy
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct EvaluationCacheKey {
+    /// The digest of the policy, this must change whenever the behaviour of the
+    /// policy changes
+    pub policy_digest: String,
+    /// The hash of the request, computed without its UID
+    pub request_hash: String,
+    /// The hash of the settings of the policy
+    pub settings_hash: String,
+}
+
+impl EvaluationCacheKey {
+    pub fn new(
+        policy_digest: &str,
+        request: &ValidateRequest,
+        settings: &PolicySettings,
+    ) -> serde_json::Result<Self> {
+        let mut request_value = serde_json::to_value(request)?;
+        if let Some(request_object) = request_value.as_object_mut() {
+            let uid_field = match request {
+                ValidateRequest::CloudEvent(_) => "id",
+                ValidateRequest::Raw(_) | ValidateRequest::AdmissionRequest(_) => "uid",
+            };
+            request_object.remove(uid_field);
+        }
+
+        Ok(EvaluationCacheKey {
+            policy_digest: policy_digest.to_owned(),
+            request_hash: sha256(&serde_json::to_vec(&request_value)?),
+            settings_hash: sha256(&serde_json::to_vec(settings)?),
+        })
+    }
+}
+
+fn sha256(data: &[u8]) -> String {
+    format!("{:x}", Sha256::digest(data))
+}
+
+// Tracking the host calls per thread is correct because the host capabilities are
+// served synchronously, on the thread running the evaluation: wasmtime invokes the
+// waPC host callback, and the builtins of the Rego runtime, from the same thread that
+// called into the guest, and they block it until the callback handler replies. An
+// evaluation never moves to another thread, and the thread evaluates nothing else
+// in the meantime.
+thread_local! {
+    /// Set when the evaluation running on the current thread invokes a host
+    /// capability served by the callback handler
+    static HOST_CALLS_MADE: Cell<bool> = const { Cell::new(false) };
+}
+
+/// Run the evaluation of a policy, telling whether its response can be cached:
+/// that's the case only when no host capability served by the callback handler
+/// has been invoked
+pub fn track_cacheability<T>(evaluate: impl FnOnce() -> T) -> (T, bool) {
+    let outer_calls_made = HOST_CALLS_MADE.with(|calls_made| calls_made.replace(false));
+    let result = evaluate();
+    // the calls are attributed to the outer evaluation too, if any
+    let calls_made = HOST_CALLS_MADE.with(|calls_made| {
+        let made = calls_made.get();
+        calls_made.set(outer_calls_made || made);
+        made
+    });
+
+    (result, !calls_made)
+}
+
+/// Record that the evaluation running on the current thread invoked a host
+/// capability served by the callback handler
+pub(crate) fn record_host_call() {
+    HOST_CALLS_MADE.with(|calls_made| calls_made.set(true));
+}
+
+/// LRU cache of the responses produced by the policies, bounded both in size
+/// and in time. It can be shared across threads
+pub struct EvaluationCache {
+    /// Not set when the cache is disabled
+    responses: Option<Mutex<TimedSizedCache<EvaluationCacheKey, AdmissionResponse>>>,
+}
+
+impl EvaluationCache {
+    pub fn new(config: EvaluationCacheConfig) -> Self {
+        EvaluationCache {
+            responses: (config.max_entries > 0).then(|| {
+                Mutex::new(TimedSizedCache::with_size_and_lifespan(
+                    config.max_entries,
+                    config.ttl,
+                ))
+            }),
+        }
+    }
+
+    /// Whether the cache stores any response
+    pub fn is_enabled(&self) -> bool {
+        self.responses.is_some()
+    }
+
+    /// Get the response of the evaluation identified by the key, giving it the UID
+    /// of the request being evaluated
+    pub fn get(
+        &self,
+        policy_id: &str,
+        key: &EvaluationCacheKey,
+        uid: &str,
+    ) -> Option<AdmissionResponse> {
+        let mut responses = self
+            .responses
+            .as_ref()?
+            .lock()
+            .expect("cannot lock the evaluation cache");
+        let response = responses.cache_get(key).map(|cached| AdmissionResponse {
+            uid: uid.to_owned(),
+            ..cached.clone()
+        });
+        metrics::record_evaluation_cache_lookup(policy_id, response.is_some());
+
+        response
+    }
+
+    /// Store the response of the evaluation identified by the key. Responses
+    /// reporting an internal error, like a timeout of the policy, are not stored
+    pub fn insert(&self, key: EvaluationCacheKey, response: &AdmissionResponse) {
+        let Some(responses) = &self.responses else {
+            return;
+        };
+        if response.is_internal_server_error() {
+            return;
+        }
+
+        responses
+            .lock()
+            .expect("cannot lock the evaluation cache")
+            .cache_set(key, response.to_owned());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::admission_request_for_object;
+    use serde_json::json;
+
+    fn request(uid: &str, name: &str) -> ValidateRequest {
+        let mut request = admission_request_for_object(
+            "CREATE",
+            json!({"apiVersion": "v1", "kind": "Pod", "metadata": {"name": name}}),
+        )
+        .expect("cannot build AdmissionRequest");
+        request.uid = uid.to_owned();
+        ValidateRequest::AdmissionRequest(Box::new(request))
+    }
+
+    fn settings(value: serde_json::Value) -> PolicySettings {
+        PolicySettings(value.as_object().unwrap().to_owned())
+    }
+
+    fn cache(max_entries: usize) -> EvaluationCache {
+        EvaluationCache::new(EvaluationCacheConfig {
+            max_entries,
+            ..Default::default()
+        })
+    }
+
+    fn key(name: &str) -> EvaluationCacheKey {
+        EvaluationCacheKey::new("digest", &request("uid", name), &PolicySettings::default())
+            .unwrap()
+    }
+
+    #[test]
+    fn key_does_not_depend_on_the_uid() {
+        let settings = settings(json!({"privileged": false}));
+
+        let first = EvaluationCacheKey::new("digest", &request("1", "nginx"), &settings).unwrap();
+        let second = EvaluationCacheKey::new("digest", &request("2", "nginx"), &settings).unwrap();
+        let other_object =
+            EvaluationCacheKey::new("digest", &request("3", "redis"), &settings).unwrap();
+        let other_settings =
+            EvaluationCacheKey::new("digest", &request("4", "nginx"), &PolicySettings::default())
+                .unwrap();
+
+        assert_eq!(first, second);
+        assert_ne!(first.request_hash, other_object.request_hash);
+        assert_eq!(first.request_hash, other_settings.request_hash);
+        assert_ne!(first.settings_hash, other_settings.settings_hash);
+    }
+
+    #[test]
+    fn cached_responses_get_the_uid_of_the_request() {
+        let cache = cache(10);
+        let response = AdmissionResponse {
+            uid: "first".to_owned(),
+            allowed: true,
+            ..Default::default()
+        };
+
+        assert!(cache.get("policy", &key("nginx"), "first").is_none());
+        cache.insert(key("nginx"), &response);
+
+        let cached = cache.get("policy", &key("nginx"), "second").unwrap();
+        assert_eq!(cached.uid, "second");
+        assert!(cached.allowed);
+    }
+
+    #[test]
+    fn internal_errors_are_not_cached() {
+        let cache = cache(10);
+        let response =
+            AdmissionResponse::reject_internal_server_error("uid".to_owned(), "boom".to_owned());
+
+        cache.insert(key("nginx"), &response);
+
+        assert!(cache.get("policy", &key("nginx"), "uid").is_none());
+    }
+
+    #[test]
+    fn cache_can_be_disabled() {
+        let cache = cache(0);
+
+        cache.insert(key("nginx"), &AdmissionResponse::default());
+
+        assert!(cache.get("policy", &key("nginx"), "uid").is_none());
+    }
+
+    #[test]
+    fn least_recently_used_responses_are_evicted() {
+        let cache = cache(2);
+
+        cache.insert(key("a"), &AdmissionResponse::default());
+        cache.insert(key("b"), &AdmissionResponse::default());
+        // make `a` more recently used than `b`
+        cache.get("policy", &key("a"), "uid").unwrap();
+        cache.insert(key("c"), &AdmissionResponse::default());
+
+        assert!(cache.get("policy", &key("a"), "uid").is_some());
+        assert!(cache.get("policy", &key("b"), "uid").is_none());
+        assert!(cache.get("policy", &key("c"), "uid").is_some());
+    }
+
+    #[test]
+    fn evaluations_making_host_calls_are_not_cacheable() {
+        let (_, cacheable) = track_cacheability(|| ());
+        assert!(cacheable);
+
+        let (_, cacheable) = track_cacheability(record_host_call);
+        assert!(!cacheable);
+
+        let ((_, inner_cacheable), outer_cacheable) =
+            track_cacheability(|| track_cacheability(record_host_call));
+        assert!(!inner_cacheable);
+        assert!(!outer_cacheable);
+    }
+
+    #[test]
+    fn expired_responses_are_not_used() {
+        let cache = EvaluationCache::new(EvaluationCacheConfig {
+            ttl: Duration::ZERO,
+            max_entries: 10,
+        });
+
+        cache.insert(key("nginx"), &AdmissionResponse::default());
+
+        assert!(cache.get("policy", &key("nginx"), "uid").is_none());
+    }
+}
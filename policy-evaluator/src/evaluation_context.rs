@@ -1,9 +1,12 @@
+use burrego::HttpPolicy;
+use serde::{Deserialize, Serialize};
 use std::collections::BTreeSet;
 use std::fmt;
 use tokio::sync::mpsc;
 
 use crate::callback_requests::CallbackRequest;
 use crate::policy_metadata::ContextAwareResource;
+use crate::request_envelope::RequestContext;
 
 /// A struct that holds metadata and other data that are needed when a policy
 /// is being evaluated
@@ -14,6 +17,11 @@ pub struct EvaluationContext {
     /// file
     pub policy_id: String,
 
+    /// Identifier of the policy that, unlike `policy_id`, changes whenever the
+    /// policy is bumped to a different Wasm module. When set, it's added to the
+    /// metrics next to the policy identifier
+    pub policy_stable_id: Option<String>,
+
     /// Channel used by the synchronous world (like the `host_callback` waPC function,
     /// but also Burrego for k8s context aware data),
     /// to request the computation of code that can only be run inside of an
@@ -22,6 +30,45 @@ pub struct EvaluationContext {
 
     /// List of ContextAwareResource the policy is granted access to.
     pub ctx_aware_resources_allow_list: BTreeSet<ContextAwareResource>,
+
+    /// The Kubernetes Service Account impersonated when the policy interacts
+    /// with the Kubernetes API server. When not set, the identity of the host
+    /// is used
+    pub kubernetes_service_account: Option<KubernetesServiceAccount>,
+
+    /// The context of the evaluation given to the policies that use the `v2`
+    /// request envelope
+    pub request_context: RequestContext,
+
+    /// The requests the Rego policies can make with the `http.send` builtin.
+    /// The builtin is disabled when not set
+    pub http_policy: Option<HttpPolicy>,
+
+    /// Whether the policy can use the key/value store offered by the host.
+    /// The policy can access only its own entries
+    pub key_value_store: bool,
+}
+
+/// A Kubernetes Service Account, identified by its namespace and name
+#[derive(Clone, Debug, Eq, Hash, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct KubernetesServiceAccount {
+    pub namespace: String,
+    pub name: String,
+}
+
+impl KubernetesServiceAccount {
+    /// The name of the user the Kubernetes API server associates with the
+    /// Service Account. This is the user to impersonate
+    pub fn username(&self) -> String {
+        format!("system:serviceaccount:{}:{}", self.namespace, self.name)
+    }
+}
+
+impl fmt::Display for KubernetesServiceAccount {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}/{}", self.namespace, self.name)
+    }
 }
 
 impl EvaluationContext {
@@ -47,8 +94,15 @@ impl fmt::Debug for EvaluationContext {
 
         write!(
             f,
-            r#"EvaluationContext {{ policy_id: "{}", callback_channel: {}, allowed_kubernetes_resources: {:?} }}"#,
-            self.policy_id, callback_channel, self.ctx_aware_resources_allow_list,
+            r#"EvaluationContext {{ policy_id: "{}", policy_stable_id: {:?}, callback_channel: {}, allowed_kubernetes_resources: {:?}, kubernetes_service_account: {:?}, request_context: {:?}, http_policy: {:?}, key_value_store: {} }}"#,
+            self.policy_id,
+            self.policy_stable_id,
+            callback_channel,
+            self.ctx_aware_resources_allow_list,
+            self.kubernetes_service_account,
+            self.request_context,
+            self.http_policy,
+            self.key_value_store,
         )
     }
 }
@@ -92,8 +146,13 @@ mod tests {
     ) {
         let ctx = EvaluationContext {
             policy_id: name.to_string(),
+            policy_stable_id: None,
             callback_channel: None,
             ctx_aware_resources_allow_list: allowed_resources,
+            kubernetes_service_account: None,
+            request_context: Default::default(),
+            http_policy: None,
+            key_value_store: false,
         };
 
         let requested_resource = ContextAwareResource {
@@ -109,4 +168,18 @@ mod tests {
             )
         );
     }
+
+    #[test]
+    fn kubernetes_service_account_username() {
+        let service_account: KubernetesServiceAccount = serde_json::from_value(
+            serde_json::json!({"namespace": "kubewarden", "name": "policy"}),
+        )
+        .unwrap();
+
+        assert_eq!(
+            service_account.username(),
+            "system:serviceaccount:kubewarden:policy"
+        );
+        assert_eq!(service_account.to_string(), "kubewarden/policy");
+    }
 }
@@ -6,15 +6,25 @@ pub mod admission_response;
 pub mod admission_response_handler;
 pub mod callback_handler;
 pub mod callback_requests;
+pub mod capability_usage;
+pub mod capability_versions;
+pub mod cloud_event;
 pub mod constants;
 pub mod errors;
+pub mod evaluation_cache;
 pub mod evaluation_context;
+mod metrics;
 pub mod policy_artifacthub;
 pub mod policy_evaluator;
 pub mod policy_group_evaluator;
 pub mod policy_metadata;
 mod policy_tracing;
+pub mod request_envelope;
+mod request_projection;
 pub mod runtimes;
+#[cfg(any(test, feature = "test-utils"))]
+pub mod test_utils;
+pub mod wasm_trap;
 
 // API's that expose other crate types (such as Kubewarden Policy SDK
 // or `policy_fetcher`) can either implement their own exposed types,
@@ -33,3 +43,7 @@ pub use policy_evaluator::policy_evaluator_builder;
 pub use policy_fetcher;
 pub use validator;
 pub use wasmtime_provider::wasmtime;
+
+// Consumers that drive burrego directly can still rely on the Rego builtins
+// backed by the host capabilities
+pub use runtimes::rego::host_builtins::new_host_builtins;
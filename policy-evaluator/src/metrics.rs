@@ -0,0 +1,270 @@
+//! Metrics recorded while evaluating the policies.
+//!
+//! The metrics are recorded using the global OpenTelemetry meter provider. Nothing
+//! is exported unless the program embedding policy-evaluator installs a meter
+//! provider, like Policy Server does when metrics are enabled.
+
+use std::time::Duration;
+
+use lazy_static::lazy_static;
+use opentelemetry::{
+    metrics::{Counter, Gauge, Histogram},
+    KeyValue,
+};
+
+use crate::admission_response::AdmissionResponse;
+
+const METER_NAME: &str = "kubewarden";
+
+lazy_static! {
+    static ref POLICY_EVALUATIONS_TOTAL: Counter<u64> = opentelemetry::global::meter(METER_NAME)
+        .u64_counter("kubewarden_policy_evaluator_evaluations_total")
+        .build();
+    static ref POLICY_EVALUATION_LATENCY: Histogram<u64> = opentelemetry::global::meter(METER_NAME)
+        .u64_histogram("kubewarden_policy_evaluator_evaluation_latency_milliseconds")
+        .build();
+    static ref HOST_CALLBACKS_TOTAL: Counter<u64> = opentelemetry::global::meter(METER_NAME)
+        .u64_counter("kubewarden_policy_evaluator_host_callbacks_total")
+        .build();
+    static ref HOST_CALLBACK_LATENCY: Histogram<u64> = opentelemetry::global::meter(METER_NAME)
+        .u64_histogram("kubewarden_policy_evaluator_host_callback_latency_milliseconds")
+        .build();
+    static ref COALESCED_KUBERNETES_REQUESTS_TOTAL: Counter<u64> =
+        opentelemetry::global::meter(METER_NAME)
+            .u64_counter("kubewarden_policy_evaluator_coalesced_kubernetes_requests_total")
+            .build();
+    static ref EVALUATION_CACHE_LOOKUPS_TOTAL: Counter<u64> =
+        opentelemetry::global::meter(METER_NAME)
+            .u64_counter("kubewarden_policy_evaluator_evaluation_cache_lookups_total")
+            .build();
+    static ref KEY_VALUE_ENTRIES: Gauge<u64> = opentelemetry::global::meter(METER_NAME)
+        .u64_gauge("kubewarden_policy_evaluator_key_value_entries")
+        .build();
+    static ref KEY_VALUE_LOOKUPS_TOTAL: Counter<u64> = opentelemetry::global::meter(METER_NAME)
+        .u64_counter("kubewarden_policy_evaluator_key_value_lookups_total")
+        .build();
+    static ref KEY_VALUE_EXPIRATIONS_TOTAL: Counter<u64> = opentelemetry::global::meter(METER_NAME)
+        .u64_counter("kubewarden_policy_evaluator_key_value_expirations_total")
+        .build();
+    static ref KEY_VALUE_WRITE_REJECTIONS_TOTAL: Counter<u64> =
+        opentelemetry::global::meter(METER_NAME)
+            .u64_counter("kubewarden_policy_evaluator_key_value_write_rejections_total")
+            .build();
+}
+
+/// The outcome of the evaluation of a request
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum EvaluationOutcome {
+    Accepted,
+    Mutated,
+    Rejected,
+    /// The evaluation failed, like when the guest traps or exceeds its timeout
+    Error,
+}
+
+impl EvaluationOutcome {
+    pub(crate) fn of_response(response: &AdmissionResponse) -> Self {
+        if response.allowed {
+            if response.patch.is_some() {
+                EvaluationOutcome::Mutated
+            } else {
+                EvaluationOutcome::Accepted
+            }
+        } else if response.is_internal_server_error() {
+            EvaluationOutcome::Error
+        } else {
+            EvaluationOutcome::Rejected
+        }
+    }
+
+    fn as_str(&self) -> &'static str {
+        match self {
+            EvaluationOutcome::Accepted => "accepted",
+            EvaluationOutcome::Mutated => "mutated",
+            EvaluationOutcome::Rejected => "rejected",
+            EvaluationOutcome::Error => "error",
+        }
+    }
+}
+
+fn milliseconds(duration: Duration) -> u64 {
+    u64::try_from(duration.as_millis()).unwrap_or(u64::MAX)
+}
+
+/// The attributes identifying the policy, the stable ID is added only when known
+fn policy_attributes(policy_name: &str, policy_stable_id: Option<&str>) -> Vec<KeyValue> {
+    let mut attributes = vec![KeyValue::new("policy_name", policy_name.to_owned())];
+    if let Some(policy_stable_id) = policy_stable_id {
+        attributes.push(KeyValue::new(
+            "policy_stable_id",
+            policy_stable_id.to_owned(),
+        ));
+    }
+    attributes
+}
+
+/// Record the evaluation of a request made by a policy
+pub(crate) fn record_policy_evaluation(
+    policy_name: &str,
+    policy_stable_id: Option<&str>,
+    execution_mode: &str,
+    outcome: EvaluationOutcome,
+    duration: Duration,
+) {
+    let mut attributes = policy_attributes(policy_name, policy_stable_id);
+    attributes.extend([
+        KeyValue::new("execution_mode", execution_mode.to_owned()),
+        KeyValue::new("outcome", outcome.as_str()),
+    ]);
+    POLICY_EVALUATIONS_TOTAL.add(1, &attributes);
+    POLICY_EVALUATION_LATENCY.record(milliseconds(duration), &attributes);
+}
+
+/// Record a round-trip made by a policy to a host capability
+pub(crate) fn record_host_callback(
+    policy_name: &str,
+    policy_stable_id: Option<&str>,
+    operation: &str,
+    success: bool,
+    duration: Duration,
+) {
+    let mut attributes = policy_attributes(policy_name, policy_stable_id);
+    attributes.extend([
+        KeyValue::new("operation", operation.to_owned()),
+        KeyValue::new("success", success),
+    ]);
+    HOST_CALLBACKS_TOTAL.add(1, &attributes);
+    HOST_CALLBACK_LATENCY.record(milliseconds(duration), &attributes);
+}
+
+/// Record a request to the Kubernetes API server that has been served by an
+/// identical request made at the same time
+pub(crate) fn record_coalesced_kubernetes_request(operation: &str) {
+    COALESCED_KUBERNETES_REQUESTS_TOTAL.add(1, &[KeyValue::new("operation", operation.to_owned())]);
+}
+
+/// Record a lookup made inside of the cache of the evaluation results
+pub(crate) fn record_evaluation_cache_lookup(policy_name: &str, hit: bool) {
+    EVALUATION_CACHE_LOOKUPS_TOTAL.add(
+        1,
+        &[
+            KeyValue::new("policy_name", policy_name.to_owned()),
+            KeyValue::new("hit", hit),
+        ],
+    );
+}
+
+/// Why a write to the key/value store has been refused
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum KeyValueWriteRejection {
+    ValueTooLarge,
+    NamespaceFull,
+    TooManyNamespaces,
+}
+
+impl KeyValueWriteRejection {
+    fn as_str(&self) -> &'static str {
+        match self {
+            KeyValueWriteRejection::ValueTooLarge => "value_too_large",
+            KeyValueWriteRejection::NamespaceFull => "namespace_full",
+            KeyValueWriteRejection::TooManyNamespaces => "too_many_namespaces",
+        }
+    }
+}
+
+/// Record the number of entries stored inside of a namespace of the key/value store
+pub(crate) fn record_key_value_entries(policy_name: &str, namespace: &str, entries: usize) {
+    KEY_VALUE_ENTRIES.record(
+        u64::try_from(entries).unwrap_or(u64::MAX),
+        &[
+            KeyValue::new("policy_name", policy_name.to_owned()),
+            KeyValue::new("namespace", namespace.to_owned()),
+        ],
+    );
+}
+
+/// Record a lookup made inside of the key/value store
+pub(crate) fn record_key_value_lookup(policy_name: &str, namespace: &str, hit: bool) {
+    KEY_VALUE_LOOKUPS_TOTAL.add(
+        1,
+        &[
+            KeyValue::new("policy_name", policy_name.to_owned()),
+            KeyValue::new("namespace", namespace.to_owned()),
+            KeyValue::new("hit", hit),
+        ],
+    );
+}
+
+/// Record the entries of the key/value store dropped because they expired
+pub(crate) fn record_key_value_expirations(policy_name: &str, namespace: &str, expired: usize) {
+    KEY_VALUE_EXPIRATIONS_TOTAL.add(
+        u64::try_from(expired).unwrap_or(u64::MAX),
+        &[
+            KeyValue::new("policy_name", policy_name.to_owned()),
+            KeyValue::new("namespace", namespace.to_owned()),
+        ],
+    );
+}
+
+/// Record a write refused by the key/value store
+pub(crate) fn record_key_value_write_rejection(
+    policy_name: &str,
+    namespace: &str,
+    reason: KeyValueWriteRejection,
+) {
+    KEY_VALUE_WRITE_REJECTIONS_TOTAL.add(
+        1,
+        &[
+            KeyValue::new("policy_name", policy_name.to_owned()),
+            KeyValue::new("namespace", namespace.to_owned()),
+            KeyValue::new("reason", reason.as_str()),
+        ],
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rstest::rstest;
+
+    #[rstest]
+    #[case::accepted(AdmissionResponse { allowed: true, ..Default::default() }, EvaluationOutcome::Accepted)]
+    #[case::mutated(
+        AdmissionResponse { allowed: true, patch: Some("W10=".to_owned()), ..Default::default() },
+        EvaluationOutcome::Mutated
+    )]
+    #[case::rejected(
+        AdmissionResponse::reject("uid".to_owned(), "denied".to_owned(), 400),
+        EvaluationOutcome::Rejected
+    )]
+    #[case::error(
+        AdmissionResponse::reject_internal_server_error("uid".to_owned(), "boom".to_owned()),
+        EvaluationOutcome::Error
+    )]
+    #[case::timeout(
+        AdmissionResponse::reject_timeout("uid".to_owned(), "too slow".to_owned()),
+        EvaluationOutcome::Error
+    )]
+    fn evaluation_outcome(
+        #[case] response: AdmissionResponse,
+        #[case] expected: EvaluationOutcome,
+    ) {
+        assert_eq!(EvaluationOutcome::of_response(&response), expected);
+    }
+
+    #[rstest]
+    #[case::name_only(None, vec![KeyValue::new("policy_name", "psp")])]
+    #[case::stable_id(
+        Some("psp@3c1f8e2a9b0d"),
+        vec![
+            KeyValue::new("policy_name", "psp"),
+            KeyValue::new("policy_stable_id", "psp@3c1f8e2a9b0d"),
+        ]
+    )]
+    fn attributes_of_the_policy(
+        #[case] policy_stable_id: Option<&str>,
+        #[case] expected: Vec<KeyValue>,
+    ) {
+        assert_eq!(policy_attributes("psp", policy_stable_id), expected);
+    }
+}
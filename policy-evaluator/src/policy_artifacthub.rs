@@ -533,6 +533,10 @@ mod tests {
             execution_mode: Default::default(),
             policy_type: PolicyType::Kubernetes,
             minimum_kubewarden_version: None,
+            settings_schema: None,
+            request_projection: None,
+            request_envelope: Default::default(),
+            preopened_dirs: vec![],
         }
     }
 
@@ -601,6 +605,10 @@ mod tests {
             context_aware_resources,
             execution_mode: Default::default(),
             minimum_kubewarden_version: None,
+            settings_schema: None,
+            request_projection: None,
+            request_envelope: Default::default(),
+            preopened_dirs: vec![],
             policy_type: Default::default(),
         }
     }
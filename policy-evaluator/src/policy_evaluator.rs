@@ -1,10 +1,12 @@
 pub mod errors;
 mod evaluator;
 pub mod policy_evaluator_builder;
+mod policy_evaluator_pool;
 mod policy_evaluator_pre;
 mod stack_pre;
 
 pub use evaluator::PolicyEvaluator;
+pub use policy_evaluator_pool::{PolicyEvaluatorPool, PooledPolicyEvaluator};
 pub use policy_evaluator_pre::PolicyEvaluatorPre;
 
 use anyhow::{anyhow, Result};
@@ -13,7 +15,7 @@ use serde::{Deserialize, Serialize};
 use serde_json::value;
 use std::{convert::TryFrom, fmt};
 
-use crate::admission_request::AdmissionRequest;
+use crate::{admission_request::AdmissionRequest, cloud_event::CloudEvent};
 
 #[derive(Copy, Clone, Default, Debug, Eq, PartialEq, serde::Deserialize, serde::Serialize)]
 pub enum PolicyExecutionMode {
@@ -36,7 +38,7 @@ impl fmt::Display for PolicyExecutionMode {
 }
 
 /// A validation request that can be sent to a policy evaluator.
-/// It can be either a raw JSON object, or a Kubernetes AdmissionRequest.
+/// It can be either a raw JSON object, a Kubernetes AdmissionRequest or a CloudEvent.
 #[derive(Clone, Debug, Serialize)]
 #[serde(untagged)]
 pub enum ValidateRequest {
@@ -44,6 +46,8 @@ pub enum ValidateRequest {
     // This enum uses the `Box` type to avoid the need for a large enum size causing memory layout
     // problems. https://rust-lang.github.io/rust-clippy/master/index.html#large_enum_variant
     AdmissionRequest(Box<AdmissionRequest>),
+    /// The policy receives the event serialized with the JSON event format
+    CloudEvent(Box<CloudEvent>),
 }
 
 impl ValidateRequest {
@@ -54,6 +58,7 @@ impl ValidateRequest {
                 .and_then(value::Value::as_str)
                 .unwrap_or_default(),
             ValidateRequest::AdmissionRequest(adm_req) => &adm_req.uid,
+            ValidateRequest::CloudEvent(event) => &event.id,
         }
     }
 }
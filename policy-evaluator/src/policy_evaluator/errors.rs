@@ -21,4 +21,37 @@ pub enum InvalidUserInputError {
 
     #[error("must specify execution mode")]
     ExecutionMode,
+
+    #[error("the entrypoint can be selected only for OPA and Gatekeeper policies")]
+    EntrypointForNonRegoPolicy,
+
+    #[error("Rego libraries can be linked only to OPA policies")]
+    RegoLibraryForNonOpaPolicy,
+
+    #[error("the memory limit can be set only for OPA and Gatekeeper policies")]
+    MemoryLimitForNonRegoPolicy,
+
+    #[error("the clock can be pinned only for OPA and Gatekeeper policies")]
+    PinnedClockForNonRegoPolicy,
+
+    #[error("the request projection can be set only for waPC policies")]
+    RequestProjectionForNonWapcPolicy,
+
+    #[error("invalid request projection: {0}")]
+    InvalidRequestProjection(String),
+
+    #[error("only waPC policies can be given a request envelope other than v1")]
+    RequestEnvelopeForNonWapcPolicy,
+
+    #[error("only WASI policies can be given preopened directories")]
+    PreopenedDirForNonWasiPolicy,
+
+    #[error("the guest path of a preopened directory must be absolute: {0}")]
+    RelativePreopenedDirGuestPath(String),
+
+    #[error("the directory {0} is not declared among the preopenedDirs of the policy metadata")]
+    UndeclaredPreopenedDir(String),
+
+    #[error("the directory {0} declared by the policy metadata is not mapped to a directory of the host")]
+    UnmappedPreopenedDir(String),
 }
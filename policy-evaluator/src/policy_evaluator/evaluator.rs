@@ -1,11 +1,13 @@
 use kubewarden_policy_sdk::{metadata::ProtocolVersion, settings::SettingsValidationResponse};
-use std::fmt;
+use std::{fmt, time::Instant};
 
 use crate::admission_response::AdmissionResponse;
+use crate::capability_usage;
 use crate::errors::PolicyEvaluatorError;
 use crate::evaluation_context::EvaluationContext;
+use crate::metrics::{self, EvaluationOutcome};
 use crate::policy_evaluator::{PolicySettings, ValidateRequest};
-use crate::runtimes::rego::Runtime as BurregoRuntime;
+use crate::runtimes::rego::{KubernetesContext, Runtime as BurregoRuntime};
 use crate::runtimes::wapc::Runtime as WapcRuntime;
 use crate::runtimes::wasi_cli::Runtime as WasiRuntime;
 use crate::runtimes::Runtime;
@@ -28,24 +30,96 @@ impl PolicyEvaluator {
         &mut self,
         request: ValidateRequest,
         settings: &PolicySettings,
+    ) -> AdmissionResponse {
+        // the resources fetched to build the Kubernetes context are part of the evaluation
+        capability_usage::begin_evaluation();
+        let kube_ctx = self.build_rego_kubernetes_context();
+        self.validate_and_record(&request, settings, kube_ctx.as_ref())
+    }
+
+    /// Validate many requests against the same settings, returning the responses in
+    /// the same order of the requests.
+    ///
+    /// This is cheaper than invoking `validate` for each request: the same Wasm
+    /// instance is reused, and the Kubernetes resources given to context-aware Rego
+    /// policies are fetched only once, before evaluating the first request. Hence all
+    /// the requests are evaluated against the same snapshot of the cluster.
+    #[tracing::instrument(skip(requests), fields(batch_size = requests.len()))]
+    pub fn validate_batch(
+        &mut self,
+        requests: &[ValidateRequest],
+        settings: &PolicySettings,
+    ) -> Vec<AdmissionResponse> {
+        // the usage of the host capabilities made to build the Kubernetes context is
+        // attributed to the first request
+        capability_usage::begin_evaluation();
+        let kube_ctx = self.build_rego_kubernetes_context();
+        requests
+            .iter()
+            .map(|request| self.validate_and_record(request, settings, kube_ctx.as_ref()))
+            .collect()
+    }
+
+    /// Build the Kubernetes context given to Rego policies. `None` is returned
+    /// for the other kinds of policies
+    fn build_rego_kubernetes_context(&self) -> Option<Result<KubernetesContext, String>> {
+        match self.runtime {
+            Runtime::Rego(ref burrego_evaluator) => Some(
+                burrego_evaluator
+                    .build_kubernetes_context(
+                        self.eval_ctx.callback_channel.as_ref(),
+                        &self.eval_ctx.ctx_aware_resources_allow_list,
+                        self.eval_ctx.kubernetes_service_account.as_ref(),
+                    )
+                    .map_err(|e| e.to_string()),
+            ),
+            _ => None,
+        }
+    }
+
+    fn validate_and_record(
+        &mut self,
+        request: &ValidateRequest,
+        settings: &PolicySettings,
+        kube_ctx: Option<&Result<KubernetesContext, String>>,
+    ) -> AdmissionResponse {
+        let start_time = Instant::now();
+        capability_usage::begin_evaluation();
+        let response = self.validate_with_runtime(request, settings, kube_ctx);
+        capability_usage::end_evaluation(&self.eval_ctx.policy_id);
+
+        metrics::record_policy_evaluation(
+            &self.eval_ctx.policy_id,
+            self.eval_ctx.policy_stable_id.as_deref(),
+            &self.runtime.to_string(),
+            EvaluationOutcome::of_response(&response),
+            start_time.elapsed(),
+        );
+
+        response
+    }
+
+    fn validate_with_runtime(
+        &mut self,
+        request: &ValidateRequest,
+        settings: &PolicySettings,
+        kube_ctx: Option<&Result<KubernetesContext, String>>,
     ) -> AdmissionResponse {
         match self.runtime {
             Runtime::Wapc(ref mut wapc_stack) => {
-                WapcRuntime(wapc_stack).validate(settings, &request)
+                WapcRuntime(wapc_stack).validate(settings, request)
             }
-            Runtime::Rego(ref mut burrego_evaluator) => {
-                let kube_ctx = burrego_evaluator.build_kubernetes_context(
-                    self.eval_ctx.callback_channel.as_ref(),
-                    &self.eval_ctx.ctx_aware_resources_allow_list,
-                );
-                match kube_ctx {
-                    Ok(ctx) => BurregoRuntime(burrego_evaluator).validate(settings, &request, &ctx),
-                    Err(e) => {
-                        AdmissionResponse::reject(request.uid().to_string(), e.to_string(), 500)
-                    }
+            Runtime::Rego(ref mut burrego_evaluator) => match kube_ctx {
+                Some(Ok(ctx)) => BurregoRuntime(burrego_evaluator).validate(settings, request, ctx),
+                Some(Err(e)) => {
+                    AdmissionResponse::reject(request.uid().to_string(), e.clone(), 500)
                 }
-            }
-            Runtime::Cli(ref mut cli_stack) => WasiRuntime(cli_stack).validate(settings, &request),
+                None => AdmissionResponse::reject_internal_server_error(
+                    request.uid().to_string(),
+                    "the Kubernetes context of the Rego policy has not been built".to_string(),
+                ),
+            },
+            Runtime::Cli(ref mut cli_stack) => WasiRuntime(cli_stack).validate(settings, request),
         }
     }
 
@@ -93,3 +167,48 @@ impl fmt::Debug for PolicyEvaluator {
             .finish()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::policy_evaluator::PolicyExecutionMode;
+    use crate::policy_evaluator_builder::PolicyEvaluatorBuilder;
+    use crate::test_utils::admission_request_for_object;
+    use serde_json::json;
+
+    #[test]
+    fn validate_batch_preserves_the_order_of_the_requests() {
+        let mut evaluator = PolicyEvaluatorBuilder::new()
+            .execution_mode(PolicyExecutionMode::OpaGatekeeper)
+            .policy_contents(include_bytes!(
+                "../../tests/data/gatekeeper_always_happy_policy.wasm"
+            ))
+            .build_pre()
+            .expect("cannot build PolicyEvaluatorPre")
+            .rehydrate(&EvaluationContext::default())
+            .expect("cannot rehydrate PolicyEvaluator");
+
+        let requests: Vec<ValidateRequest> = ["first", "second", "third"]
+            .into_iter()
+            .map(|uid| {
+                let mut request = admission_request_for_object(
+                    "CREATE",
+                    json!({"apiVersion": "v1", "kind": "Pod", "metadata": {"name": uid}}),
+                )
+                .expect("cannot build AdmissionRequest");
+                request.uid = uid.to_owned();
+                ValidateRequest::AdmissionRequest(Box::new(request))
+            })
+            .collect();
+
+        let responses = evaluator.validate_batch(&requests, &PolicySettings::default());
+
+        assert_eq!(
+            responses
+                .iter()
+                .map(|response| (response.uid.as_str(), response.allowed))
+                .collect::<Vec<_>>(),
+            vec![("first", true), ("second", true), ("third", true)]
+        );
+    }
+}
@@ -1,20 +1,27 @@
-use std::path::Path;
+use std::collections::{BTreeMap, BTreeSet};
+use std::path::{Path, PathBuf};
 use std::result::Result;
+use std::time::SystemTime;
 
 use wasmtime_provider::wasmtime;
 
 use crate::errors::PolicyEvaluatorBuilderError;
 use crate::policy_evaluator::errors::InvalidUserInputError;
 use crate::policy_evaluator::{stack_pre::StackPre, PolicyEvaluatorPre, PolicyExecutionMode};
+use crate::policy_metadata::PreopenedDir;
+use crate::request_envelope::RequestEnvelopeVersion;
+use crate::request_projection::RequestProjection;
 use crate::runtimes::{rego, wapc, wasi_cli};
 
 /// Configure behavior of wasmtime [epoch-based interruptions](https://docs.rs/wasmtime/latest/wasmtime/struct.Config.html#method.epoch_interruption)
 ///
-/// There are two kind of deadlines that apply to waPC modules:
+/// There are three kind of deadlines that apply to waPC modules:
 ///
 /// * waPC initialization code: this is the code defined by the module inside
 ///   of the `wapc_init` or the `_start` functions
 /// * user function: the actual waPC guest function written by an user
+/// * settings validation: the guest function invoked to validate the settings
+///   of the policy
 #[derive(Clone, Copy, Debug, PartialEq)]
 pub(crate) struct EpochDeadlines {
     /// Deadline for waPC initialization code. Expressed in number of epoch ticks
@@ -22,6 +29,27 @@ pub(crate) struct EpochDeadlines {
 
     /// Deadline for user-defined waPC function computation. Expressed in number of epoch ticks
     pub wapc_func: u64,
+
+    /// Deadline for the validation of the policy settings. Expressed in number of epoch ticks
+    pub settings_validation: u64,
+}
+
+/// The kind of guest function invoked by the host, used to pick the
+/// epoch deadline to be enforced
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) enum GuestFunction {
+    Validation,
+    SettingsValidation,
+}
+
+impl EpochDeadlines {
+    /// The deadline to be enforced when invoking the given guest function
+    pub(crate) fn guest_function(&self, function: GuestFunction) -> u64 {
+        match function {
+            GuestFunction::Validation => self.wapc_func,
+            GuestFunction::SettingsValidation => self.settings_validation,
+        }
+    }
 }
 
 /// Helper Struct that creates a `PolicyEvaluator` object
@@ -34,6 +62,15 @@ pub struct PolicyEvaluatorBuilder {
     execution_mode: Option<PolicyExecutionMode>,
     wasmtime_cache: bool,
     epoch_deadlines: Option<EpochDeadlines>,
+    settings_validation_epoch_deadline: Option<u64>,
+    opa_entrypoints: Vec<String>,
+    rego_libraries: rego::RegoLibraries,
+    rego_memory_limit: Option<u64>,
+    pinned_clock: Option<SystemTime>,
+    request_projection: Option<Vec<String>>,
+    request_envelope: RequestEnvelopeVersion,
+    preopened_dirs: BTreeMap<String, PathBuf>,
+    declared_preopened_dirs: Option<BTreeSet<String>>,
 }
 
 impl PolicyEvaluatorBuilder {
@@ -127,10 +164,137 @@ impl PolicyEvaluatorBuilder {
         self.epoch_deadlines = Some(EpochDeadlines {
             wapc_init: wapc_init_deadline,
             wapc_func: wapc_func_deadline,
+            settings_validation: wapc_func_deadline,
         });
         self
     }
 
+    /// Set the number of ticks the validation of the policy settings can take before
+    /// being interrupted. When not set, the `wapc_func_deadline` given to
+    /// [`enable_epoch_interruptions`](PolicyEvaluatorBuilder::enable_epoch_interruptions)
+    /// is used.
+    ///
+    /// This has no effect unless epoch-based interruptions are enabled.
+    #[must_use]
+    pub fn settings_validation_epoch_deadline(mut self, deadline: u64) -> Self {
+        self.settings_validation_epoch_deadline = Some(deadline);
+        self
+    }
+
+    /// Evaluate the given entrypoint of an OPA or Gatekeeper policy. When not set,
+    /// the first entrypoint exported by the Wasm module is used
+    #[must_use]
+    pub fn opa_entrypoint(mut self, entrypoint: &str) -> Self {
+        self.opa_entrypoints = vec![entrypoint.to_owned()];
+        self
+    }
+
+    /// Evaluate all the given entrypoints of an OPA or Gatekeeper policy on each request,
+    /// in the given order, and merge their verdicts:
+    ///
+    /// * the request is rejected when any of the entrypoints rejects it. The messages of the
+    ///   rejecting entrypoints are concatenated, the status code is the one of the first of them
+    /// * otherwise the request is accepted. The patches of the OPA entrypoints are applied
+    ///   one after the other, in the order of the entrypoints. The mutations of the
+    ///   Gatekeeper entrypoints are applied the same way
+    /// * the warnings are concatenated. When the same audit annotation is set by different
+    ///   entrypoints, the value of the first one is kept
+    ///
+    /// This is meant for policies bundling validation and mutation logic under different
+    /// entrypoints: they are evaluated with a single policy evaluation.
+    #[must_use]
+    pub fn opa_entrypoints(mut self, entrypoints: &[&str]) -> Self {
+        self.opa_entrypoints = entrypoints.iter().map(|e| e.to_string()).collect();
+        self
+    }
+
+    /// Link the document of a Rego library into the `data` document of an OPA policy.
+    /// The `path` is made of dot separated identifiers: the library linked at
+    /// `lib.kubernetes` is available to the policy as `data.lib.kubernetes`.
+    ///
+    /// This can be called multiple times to link different libraries. The documents
+    /// are usually fetched with [`policy_fetcher::rego_library::fetch_rego_library`]
+    #[must_use]
+    pub fn rego_library(
+        mut self,
+        path: &str,
+        document: serde_json::Map<String, serde_json::Value>,
+    ) -> Self {
+        self.rego_libraries.insert(path.to_owned(), document);
+        self
+    }
+
+    /// Limit the memory of an OPA or Gatekeeper policy, expressed in bytes.
+    ///
+    /// Before each evaluation, the size of the request and of the Kubernetes context
+    /// given to the policy are checked against this limit. The evaluation fails fast
+    /// when they would not fit into the memory of the policy
+    #[must_use]
+    pub fn rego_memory_limit(mut self, bytes: u64) -> Self {
+        self.rego_memory_limit = Some(bytes);
+        self
+    }
+
+    /// Pin the clock seen by an OPA or Gatekeeper policy: the `time.now_ns` builtin
+    /// always returns the given instant.
+    ///
+    /// This makes the outcome of the policies that depend on the current time
+    /// reproducible, which is useful when looking for non-deterministic policies
+    #[must_use]
+    pub fn pinned_clock(mut self, instant: SystemTime) -> Self {
+        self.pinned_clock = Some(instant);
+        self
+    }
+
+    /// Send to a waPC policy only the given fields of the objects of the `AdmissionRequest`,
+    /// like `object.spec.containers[*].image`. The `apiVersion` and `kind` of the objects,
+    /// together with the other fields of the request, are always sent.
+    ///
+    /// This reduces the cost of serializing and parsing big objects. The paths are usually
+    /// taken from the `requestProjection` of the policy metadata
+    #[must_use]
+    pub fn request_projection(mut self, paths: &[String]) -> Self {
+        self.request_projection = Some(paths.to_vec());
+        self
+    }
+
+    /// The version of the envelope wrapping the requests sent to a waPC policy. The
+    /// `v2` envelope carries also the context of the evaluation, see
+    /// [`EvaluationContext::request_context`](crate::evaluation_context::EvaluationContext::request_context).
+    ///
+    /// The version is usually taken from the `requestEnvelope` of the policy metadata,
+    /// the policies that do not declare it must be given the `v1` envelope
+    #[must_use]
+    pub fn request_envelope(mut self, version: RequestEnvelopeVersion) -> Self {
+        self.request_envelope = version;
+        self
+    }
+
+    /// Give a WASI policy read-only access to the `host_path` directory, mounted
+    /// inside of the guest at `guest_path`. The policy cannot create, modify nor
+    /// remove the files of the directory.
+    ///
+    /// WASI policies cannot access the filesystem of the host by default: only the
+    /// directories explicitly mapped by the operator are available. The guest paths
+    /// are usually taken from the `preopenedDirs` of the policy metadata.
+    /// This can be called multiple times to map different directories
+    #[must_use]
+    pub fn preopened_dir(mut self, guest_path: &str, host_path: &Path) -> Self {
+        self.preopened_dirs
+            .insert(guest_path.to_owned(), host_path.to_owned());
+        self
+    }
+
+    /// The directories the policy expects to find preopened, as declared inside of
+    /// the `preopenedDirs` of its metadata. When set, the directories mapped with
+    /// [`PolicyEvaluatorBuilder::preopened_dir`] must match them exactly: mapping a
+    /// directory that is not declared, or leaving a declared one unmapped, is an error
+    #[must_use]
+    pub fn declared_preopened_dirs(mut self, dirs: &[PreopenedDir]) -> Self {
+        self.declared_preopened_dirs = Some(dirs.iter().map(|dir| dir.path.clone()).collect());
+        self
+    }
+
     /// Ensure the configuration provided to the build is correct
     fn validate_user_input(&self) -> Result<(), InvalidUserInputError> {
         if self.policy_file.is_some() && self.policy_contents.is_some() {
@@ -154,9 +318,105 @@ impl PolicyEvaluatorBuilder {
             return Err(InvalidUserInputError::EngineForModule);
         }
 
+        if !self.opa_entrypoints.is_empty()
+            && !matches!(
+                self.execution_mode,
+                Some(PolicyExecutionMode::Opa) | Some(PolicyExecutionMode::OpaGatekeeper)
+            )
+        {
+            return Err(InvalidUserInputError::EntrypointForNonRegoPolicy);
+        }
+
+        if !self.rego_libraries.is_empty()
+            && !matches!(self.execution_mode, Some(PolicyExecutionMode::Opa))
+        {
+            return Err(InvalidUserInputError::RegoLibraryForNonOpaPolicy);
+        }
+
+        if self.rego_memory_limit.is_some()
+            && !matches!(
+                self.execution_mode,
+                Some(PolicyExecutionMode::Opa) | Some(PolicyExecutionMode::OpaGatekeeper)
+            )
+        {
+            return Err(InvalidUserInputError::MemoryLimitForNonRegoPolicy);
+        }
+
+        if self.pinned_clock.is_some()
+            && !matches!(
+                self.execution_mode,
+                Some(PolicyExecutionMode::Opa) | Some(PolicyExecutionMode::OpaGatekeeper)
+            )
+        {
+            return Err(InvalidUserInputError::PinnedClockForNonRegoPolicy);
+        }
+
+        if self.request_projection.is_some()
+            && !matches!(
+                self.execution_mode,
+                None | Some(PolicyExecutionMode::KubewardenWapc)
+            )
+        {
+            return Err(InvalidUserInputError::RequestProjectionForNonWapcPolicy);
+        }
+
+        if self.request_envelope != RequestEnvelopeVersion::V1
+            && !matches!(
+                self.execution_mode,
+                None | Some(PolicyExecutionMode::KubewardenWapc)
+            )
+        {
+            return Err(InvalidUserInputError::RequestEnvelopeForNonWapcPolicy);
+        }
+
+        if !self.preopened_dirs.is_empty()
+            && !matches!(self.execution_mode, Some(PolicyExecutionMode::Wasi))
+        {
+            return Err(InvalidUserInputError::PreopenedDirForNonWasiPolicy);
+        }
+        if let Some(guest_path) = self
+            .preopened_dirs
+            .keys()
+            .find(|guest_path| !guest_path.starts_with('/'))
+        {
+            return Err(InvalidUserInputError::RelativePreopenedDirGuestPath(
+                guest_path.to_owned(),
+            ));
+        }
+        if let Some(declared) = &self.declared_preopened_dirs {
+            if let Some(guest_path) = self
+                .preopened_dirs
+                .keys()
+                .find(|guest_path| !declared.contains(*guest_path))
+            {
+                return Err(InvalidUserInputError::UndeclaredPreopenedDir(
+                    guest_path.to_owned(),
+                ));
+            }
+            if let Some(guest_path) = declared
+                .iter()
+                .find(|guest_path| !self.preopened_dirs.contains_key(*guest_path))
+            {
+                return Err(InvalidUserInputError::UnmappedPreopenedDir(
+                    guest_path.to_owned(),
+                ));
+            }
+        }
+
         Ok(())
     }
 
+    /// The epoch deadlines to be enforced, taking into account the dedicated deadline
+    /// of the settings validation
+    fn epoch_deadlines(&self) -> Option<EpochDeadlines> {
+        self.epoch_deadlines.map(|deadlines| EpochDeadlines {
+            settings_validation: self
+                .settings_validation_epoch_deadline
+                .unwrap_or(deadlines.settings_validation),
+            ..deadlines
+        })
+    }
+
     /// Create the instance of `PolicyEvaluatorPre` to be used
     pub fn build_pre(&self) -> Result<PolicyEvaluatorPre, PolicyEvaluatorBuilderError> {
         self.validate_user_input()
@@ -164,30 +424,60 @@ impl PolicyEvaluatorBuilder {
 
         let engine = self.build_engine()?;
         let module = self.build_module(&engine)?;
+        let epoch_deadlines = self.epoch_deadlines();
 
         let execution_mode = self.execution_mode.unwrap_or_default();
 
         let stack_pre = match execution_mode {
             PolicyExecutionMode::KubewardenWapc => {
-                let wapc_stack_pre = wapc::StackPre::new(engine, module, self.epoch_deadlines)
-                    .map_err(PolicyEvaluatorBuilderError::NewWapcStackPre)?;
+                let request_projection = self
+                    .request_projection
+                    .as_deref()
+                    .map(RequestProjection::parse)
+                    .transpose()
+                    .map_err(|e| {
+                        PolicyEvaluatorBuilderError::InvalidUserInput(
+                            InvalidUserInputError::InvalidRequestProjection(e),
+                        )
+                    })?;
+                let wapc_stack_pre = wapc::StackPre::new(
+                    engine,
+                    module,
+                    epoch_deadlines,
+                    request_projection,
+                    self.request_envelope,
+                )
+                .map_err(PolicyEvaluatorBuilderError::NewWapcStackPre)?;
                 StackPre::from(wapc_stack_pre)
             }
             PolicyExecutionMode::Wasi => {
-                let wasi_stack_pre = wasi_cli::StackPre::new(engine, module, self.epoch_deadlines)
-                    .map_err(PolicyEvaluatorBuilderError::NewWasiStackPre)?;
+                let wasi_stack_pre =
+                    wasi_cli::StackPre::new(engine, module, epoch_deadlines, &self.preopened_dirs)
+                        .map_err(PolicyEvaluatorBuilderError::NewWasiStackPre)?;
                 StackPre::from(wasi_stack_pre)
             }
             PolicyExecutionMode::Opa | PolicyExecutionMode::OpaGatekeeper => {
-                let rego_stack_pre = rego::StackPre::new(
+                let mut rego_stack_pre = rego::StackPre::new(
                     engine,
                     module,
-                    self.epoch_deadlines,
-                    0, // currently the entrypoint is hard coded to this value
+                    epoch_deadlines,
+                    self.rego_memory_limit,
+                    self.pinned_clock,
+                    0, // the default entrypoint
                     execution_mode
                         .try_into()
                         .map_err(PolicyEvaluatorBuilderError::NewRegoStackPre)?,
                 );
+                if !self.opa_entrypoints.is_empty() {
+                    rego_stack_pre
+                        .select_entrypoints(&self.opa_entrypoints)
+                        .map_err(PolicyEvaluatorBuilderError::RegoEntrypoint)?;
+                }
+                if !self.rego_libraries.is_empty() {
+                    rego_stack_pre
+                        .link_libraries(&self.rego_libraries)
+                        .map_err(PolicyEvaluatorBuilderError::RegoLibrary)?;
+                }
                 StackPre::from(rego_stack_pre)
             }
         };
@@ -201,6 +491,8 @@ impl PolicyEvaluatorBuilder {
             .map_or_else(
                 || {
                     let mut wasmtime_config = wasmtime::Config::new();
+                    // keep the backtrace of the traps, to make policy crashes debuggable
+                    wasmtime_config.wasm_backtrace(true);
                     if self.wasmtime_cache {
                         let cache = wasmtime::Cache::new(wasmtime::CacheConfig::new())?;
                         wasmtime_config.cache(Some(cache));
@@ -254,4 +546,298 @@ mod tests {
 
         _ = policy_evaluator_builder.build_pre().unwrap();
     }
+
+    #[test]
+    fn settings_validation_epoch_deadline() {
+        let builder = PolicyEvaluatorBuilder::new().settings_validation_epoch_deadline(5);
+        assert_eq!(builder.epoch_deadlines(), None);
+
+        let builder = builder.enable_epoch_interruptions(1, 2);
+        assert_eq!(
+            builder.epoch_deadlines(),
+            Some(EpochDeadlines {
+                wapc_init: 1,
+                wapc_func: 2,
+                settings_validation: 5,
+            })
+        );
+
+        let builder = PolicyEvaluatorBuilder::new().enable_epoch_interruptions(1, 2);
+        assert_eq!(
+            builder
+                .epoch_deadlines()
+                .map(|deadlines| deadlines.guest_function(GuestFunction::SettingsValidation)),
+            Some(2)
+        );
+    }
+
+    #[test]
+    fn select_entrypoint_of_non_rego_policy() {
+        let engine = wasmtime::Engine::default();
+        let wat = include_bytes!("../../tests/data/endless_wasm/wapc_endless_loop.wat");
+        let module = wasmtime::Module::new(&engine, wat).expect("cannot compile WAT to wasm");
+
+        let err = PolicyEvaluatorBuilder::new()
+            .execution_mode(PolicyExecutionMode::KubewardenWapc)
+            .policy_module(module)
+            .engine(engine)
+            .opa_entrypoint("policy/main")
+            .build_pre()
+            .unwrap_err();
+
+        assert!(matches!(
+            err,
+            PolicyEvaluatorBuilderError::InvalidUserInput(
+                InvalidUserInputError::EntrypointForNonRegoPolicy
+            )
+        ));
+    }
+
+    #[test]
+    fn memory_limit_of_non_rego_policy() {
+        let engine = wasmtime::Engine::default();
+        let wat = include_bytes!("../../tests/data/endless_wasm/wapc_endless_loop.wat");
+        let module = wasmtime::Module::new(&engine, wat).expect("cannot compile WAT to wasm");
+
+        let err = PolicyEvaluatorBuilder::new()
+            .execution_mode(PolicyExecutionMode::KubewardenWapc)
+            .policy_module(module)
+            .engine(engine)
+            .rego_memory_limit(1024 * 1024)
+            .build_pre()
+            .unwrap_err();
+
+        assert!(matches!(
+            err,
+            PolicyEvaluatorBuilderError::InvalidUserInput(
+                InvalidUserInputError::MemoryLimitForNonRegoPolicy
+            )
+        ));
+    }
+
+    #[test]
+    fn pinned_clock_of_non_rego_policy() {
+        let engine = wasmtime::Engine::default();
+        let wat = include_bytes!("../../tests/data/endless_wasm/wapc_endless_loop.wat");
+        let module = wasmtime::Module::new(&engine, wat).expect("cannot compile WAT to wasm");
+
+        let err = PolicyEvaluatorBuilder::new()
+            .execution_mode(PolicyExecutionMode::KubewardenWapc)
+            .policy_module(module)
+            .engine(engine)
+            .pinned_clock(SystemTime::UNIX_EPOCH)
+            .build_pre()
+            .unwrap_err();
+
+        assert!(matches!(
+            err,
+            PolicyEvaluatorBuilderError::InvalidUserInput(
+                InvalidUserInputError::PinnedClockForNonRegoPolicy
+            )
+        ));
+    }
+
+    #[test]
+    fn request_projection_of_non_wapc_policy() {
+        let err = PolicyEvaluatorBuilder::new()
+            .execution_mode(PolicyExecutionMode::OpaGatekeeper)
+            .policy_contents(include_bytes!(
+                "../../tests/data/gatekeeper_always_happy_policy.wasm"
+            ))
+            .request_projection(&["object.metadata".to_string()])
+            .build_pre()
+            .unwrap_err();
+
+        assert!(matches!(
+            err,
+            PolicyEvaluatorBuilderError::InvalidUserInput(
+                InvalidUserInputError::RequestProjectionForNonWapcPolicy
+            )
+        ));
+    }
+
+    #[test]
+    fn rego_library_of_gatekeeper_policy() {
+        let err = PolicyEvaluatorBuilder::new()
+            .execution_mode(PolicyExecutionMode::OpaGatekeeper)
+            .policy_contents(include_bytes!(
+                "../../tests/data/gatekeeper_always_happy_policy.wasm"
+            ))
+            .rego_library("lib.images", serde_json::Map::new())
+            .build_pre()
+            .unwrap_err();
+
+        assert!(matches!(
+            err,
+            PolicyEvaluatorBuilderError::InvalidUserInput(
+                InvalidUserInputError::RegoLibraryForNonOpaPolicy
+            )
+        ));
+    }
+
+    #[test]
+    fn request_envelope_of_non_wapc_policy() {
+        let err = PolicyEvaluatorBuilder::new()
+            .execution_mode(PolicyExecutionMode::OpaGatekeeper)
+            .policy_contents(include_bytes!(
+                "../../tests/data/gatekeeper_always_happy_policy.wasm"
+            ))
+            .request_envelope(RequestEnvelopeVersion::V2)
+            .build_pre()
+            .unwrap_err();
+
+        assert!(matches!(
+            err,
+            PolicyEvaluatorBuilderError::InvalidUserInput(
+                InvalidUserInputError::RequestEnvelopeForNonWapcPolicy
+            )
+        ));
+    }
+
+    #[test]
+    fn preopened_dir_of_non_wasi_policy() {
+        let err = PolicyEvaluatorBuilder::new()
+            .execution_mode(PolicyExecutionMode::OpaGatekeeper)
+            .policy_contents(include_bytes!(
+                "../../tests/data/gatekeeper_always_happy_policy.wasm"
+            ))
+            .preopened_dir("/data", Path::new("/tmp"))
+            .build_pre()
+            .unwrap_err();
+
+        assert!(matches!(
+            err,
+            PolicyEvaluatorBuilderError::InvalidUserInput(
+                InvalidUserInputError::PreopenedDirForNonWasiPolicy
+            )
+        ));
+    }
+
+    #[test]
+    fn relative_preopened_dir_guest_path() {
+        let err = PolicyEvaluatorBuilder::new()
+            .execution_mode(PolicyExecutionMode::Wasi)
+            .policy_contents(include_bytes!(
+                "../../tests/data/gatekeeper_always_happy_policy.wasm"
+            ))
+            .preopened_dir("data", Path::new("/tmp"))
+            .build_pre()
+            .unwrap_err();
+
+        assert!(matches!(
+            err,
+            PolicyEvaluatorBuilderError::InvalidUserInput(
+                InvalidUserInputError::RelativePreopenedDirGuestPath(path)
+            ) if path == "data"
+        ));
+    }
+
+    #[test]
+    fn undeclared_preopened_dir() {
+        let err = PolicyEvaluatorBuilder::new()
+            .execution_mode(PolicyExecutionMode::Wasi)
+            .policy_contents(include_bytes!(
+                "../../tests/data/gatekeeper_always_happy_policy.wasm"
+            ))
+            .preopened_dir("/data", Path::new("/tmp"))
+            .preopened_dir("/etc", Path::new("/etc"))
+            .declared_preopened_dirs(&[PreopenedDir {
+                path: "/data".to_owned(),
+                description: None,
+            }])
+            .build_pre()
+            .unwrap_err();
+
+        assert!(matches!(
+            err,
+            PolicyEvaluatorBuilderError::InvalidUserInput(
+                InvalidUserInputError::UndeclaredPreopenedDir(path)
+            ) if path == "/etc"
+        ));
+    }
+
+    #[test]
+    fn unmapped_preopened_dir() {
+        let err = PolicyEvaluatorBuilder::new()
+            .execution_mode(PolicyExecutionMode::Wasi)
+            .policy_contents(include_bytes!(
+                "../../tests/data/gatekeeper_always_happy_policy.wasm"
+            ))
+            .declared_preopened_dirs(&[PreopenedDir {
+                path: "/data".to_owned(),
+                description: Some("the allowed registries".to_owned()),
+            }])
+            .build_pre()
+            .unwrap_err();
+
+        assert!(matches!(
+            err,
+            PolicyEvaluatorBuilderError::InvalidUserInput(
+                InvalidUserInputError::UnmappedPreopenedDir(path)
+            ) if path == "/data"
+        ));
+    }
+
+    #[test]
+    fn invalid_request_projection() {
+        let engine = wasmtime::Engine::default();
+        let wat = include_bytes!("../../tests/data/endless_wasm/wapc_endless_loop.wat");
+        let module = wasmtime::Module::new(&engine, wat).expect("cannot compile WAT to wasm");
+
+        let err = PolicyEvaluatorBuilder::new()
+            .execution_mode(PolicyExecutionMode::KubewardenWapc)
+            .policy_module(module)
+            .engine(engine)
+            .request_projection(&["object..metadata".to_string()])
+            .build_pre()
+            .unwrap_err();
+
+        assert!(matches!(
+            err,
+            PolicyEvaluatorBuilderError::InvalidUserInput(
+                InvalidUserInputError::InvalidRequestProjection(_)
+            )
+        ));
+    }
+
+    #[test]
+    fn select_unknown_entrypoint() {
+        let err = PolicyEvaluatorBuilder::new()
+            .execution_mode(PolicyExecutionMode::OpaGatekeeper)
+            .policy_contents(include_bytes!(
+                "../../tests/data/gatekeeper_always_happy_policy.wasm"
+            ))
+            .opa_entrypoint("does/not/exist")
+            .build_pre()
+            .unwrap_err();
+
+        assert!(matches!(
+            err,
+            PolicyEvaluatorBuilderError::RegoEntrypoint(
+                crate::runtimes::rego::errors::RegoRuntimeError::EntrypointNotFound { .. }
+            )
+        ));
+    }
+
+    #[test]
+    fn select_entrypoints_with_an_unknown_one() {
+        let err = PolicyEvaluatorBuilder::new()
+            .execution_mode(PolicyExecutionMode::OpaGatekeeper)
+            .policy_contents(include_bytes!(
+                "../../tests/data/gatekeeper_always_happy_policy.wasm"
+            ))
+            .opa_entrypoints(&["policy/violation", "does/not/exist"])
+            .build_pre()
+            .unwrap_err();
+
+        assert!(matches!(
+            err,
+            PolicyEvaluatorBuilderError::RegoEntrypoint(
+                crate::runtimes::rego::errors::RegoRuntimeError::EntrypointNotFound {
+                    ref entrypoint,
+                    ..
+                }
+            ) if entrypoint == "does/not/exist"
+        ));
+    }
 }
@@ -0,0 +1,217 @@
+use std::ops::{Deref, DerefMut};
+use std::result::Result;
+use std::sync::Mutex;
+
+use crate::errors::PolicyEvaluatorPreError;
+use crate::evaluation_context::EvaluationContext;
+use crate::policy_evaluator::{PolicyEvaluator, PolicyEvaluatorPre};
+
+/// A pool of `PolicyEvaluator` instances of the same policy, evaluated with the
+/// same `EvaluationContext`.
+///
+/// Rehydrating a `PolicyEvaluatorPre` for each evaluation is cheap, but it still
+/// allocates a new Wasm instance every time. The pool keeps up to `size` warm
+/// instances around and reuses them across evaluations, reducing latency and
+/// allocation churn under load.
+///
+/// The instances are taken with [`acquire`](PolicyEvaluatorPool::acquire) and
+/// given back with [`release`](PolicyEvaluatorPool::release) once the evaluation
+/// is done. An instance whose evaluation failed (trap, timeout, internal error)
+/// must not be released, it's enough to drop it. A new instance is created when
+/// the pool is empty, hence `acquire` never waits for other evaluations to complete.
+///
+/// **Warning:** the instances are not reset between evaluations. Policies keeping
+/// global state inside of the guest will see the state left by the previous
+/// evaluations, and the memory of the guest keeps the data of the previous
+/// requests. Use [`with_max_uses`](PolicyEvaluatorPool::with_max_uses) to limit
+/// how long an instance lives.
+pub struct PolicyEvaluatorPool {
+    evaluator_pre: PolicyEvaluatorPre,
+    eval_ctx: EvaluationContext,
+    size: usize,
+    max_uses: usize,
+    idle: Mutex<Vec<PooledPolicyEvaluator>>,
+}
+
+/// A `PolicyEvaluator` taken from a [`PolicyEvaluatorPool`], together with the
+/// number of evaluations it has already performed
+pub struct PooledPolicyEvaluator {
+    evaluator: PolicyEvaluator,
+    uses: usize,
+}
+
+// SAFETY: `PolicyEvaluator` is not `Send` only because the waPC host stores its
+// engine as a `Box<dyn WebAssemblyEngineProvider>`, a trait object without the
+// `Send` bound. The engines created by `PolicyEvaluatorPre::rehydrate` are
+// wasmtime ones, which can be moved across threads. The pool never shares an
+// instance: it's moved out of the pool by `acquire` and used by a single thread
+// until it's given back.
+unsafe impl Send for PooledPolicyEvaluator {}
+
+impl PooledPolicyEvaluator {
+    /// Number of times the instance has been released to the pool
+    pub fn uses(&self) -> usize {
+        self.uses
+    }
+}
+
+impl Deref for PooledPolicyEvaluator {
+    type Target = PolicyEvaluator;
+
+    fn deref(&self) -> &Self::Target {
+        &self.evaluator
+    }
+}
+
+impl DerefMut for PooledPolicyEvaluator {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.evaluator
+    }
+}
+
+impl PolicyEvaluatorPool {
+    /// Create an empty pool that keeps up to `size` idle instances
+    pub fn new(
+        evaluator_pre: PolicyEvaluatorPre,
+        eval_ctx: EvaluationContext,
+        size: usize,
+    ) -> Self {
+        Self {
+            evaluator_pre,
+            eval_ctx,
+            size,
+            max_uses: 0,
+            idle: Mutex::new(Vec::with_capacity(size)),
+        }
+    }
+
+    /// Drop the instances once they have performed `max_uses` evaluations, a new
+    /// instance takes their place. When zero, the instances are reused forever
+    pub fn with_max_uses(mut self, max_uses: usize) -> Self {
+        self.max_uses = max_uses;
+        self
+    }
+
+    /// Fill the pool with instances, so that the first evaluations don't have
+    /// to pay the cost of their creation
+    pub fn warm_up(&self) -> Result<(), PolicyEvaluatorPreError> {
+        while self.idle() < self.size {
+            let evaluator = self.evaluator_pre.rehydrate(&self.eval_ctx)?;
+            let mut idle = self.idle.lock().expect("cannot lock the evaluator pool");
+            if idle.len() < self.size {
+                idle.push(PooledPolicyEvaluator { evaluator, uses: 0 });
+            }
+        }
+        Ok(())
+    }
+
+    /// Take an instance out of the pool. A new one is created when the pool is empty
+    pub fn acquire(&self) -> Result<PooledPolicyEvaluator, PolicyEvaluatorPreError> {
+        let evaluator = self
+            .idle
+            .lock()
+            .expect("cannot lock the evaluator pool")
+            .pop();
+        match evaluator {
+            Some(evaluator) => Ok(evaluator),
+            None => Ok(PooledPolicyEvaluator {
+                evaluator: self.evaluator_pre.rehydrate(&self.eval_ctx)?,
+                uses: 0,
+            }),
+        }
+    }
+
+    /// Give back an instance obtained via [`acquire`](PolicyEvaluatorPool::acquire),
+    /// after a successful evaluation. The instance is dropped when the pool is
+    /// already full, or when it has reached the maximum number of uses
+    pub fn release(&self, mut evaluator: PooledPolicyEvaluator) {
+        evaluator.uses += 1;
+        if self.max_uses > 0 && evaluator.uses >= self.max_uses {
+            return;
+        }
+        let mut idle = self.idle.lock().expect("cannot lock the evaluator pool");
+        if idle.len() < self.size {
+            idle.push(evaluator);
+        }
+    }
+
+    /// Number of idle instances currently held by the pool
+    pub fn idle(&self) -> usize {
+        self.idle
+            .lock()
+            .expect("cannot lock the evaluator pool")
+            .len()
+    }
+
+    /// Maximum number of idle instances held by the pool
+    pub fn size(&self) -> usize {
+        self.size
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::policy_evaluator::PolicyExecutionMode;
+    use crate::policy_evaluator_builder::PolicyEvaluatorBuilder;
+
+    fn build_pool(size: usize) -> PolicyEvaluatorPool {
+        let evaluator_pre = PolicyEvaluatorBuilder::new()
+            .execution_mode(PolicyExecutionMode::OpaGatekeeper)
+            .policy_contents(include_bytes!(
+                "../../tests/data/gatekeeper_always_happy_policy.wasm"
+            ))
+            .build_pre()
+            .expect("cannot build PolicyEvaluatorPre");
+
+        PolicyEvaluatorPool::new(evaluator_pre, EvaluationContext::default(), size)
+    }
+
+    #[test]
+    fn warm_up_fills_the_pool() {
+        let pool = build_pool(3);
+        assert_eq!(pool.idle(), 0);
+
+        pool.warm_up().expect("cannot warm up the pool");
+        assert_eq!(pool.idle(), 3);
+    }
+
+    #[test]
+    fn acquire_and_release() {
+        let pool = build_pool(1);
+
+        // the pool is empty, new instances are created
+        let first = pool.acquire().expect("cannot acquire evaluator");
+        let second = pool.acquire().expect("cannot acquire evaluator");
+        assert_eq!(pool.idle(), 0);
+
+        pool.release(first);
+        assert_eq!(pool.idle(), 1);
+
+        // the pool is full, the instance is dropped
+        pool.release(second);
+        assert_eq!(pool.idle(), 1);
+
+        let reused = pool.acquire().expect("cannot acquire evaluator");
+        assert_eq!(reused.uses(), 1);
+        assert_eq!(pool.idle(), 0);
+    }
+
+    #[test]
+    fn instances_are_recycled_after_max_uses() {
+        let pool = build_pool(1).with_max_uses(2);
+
+        let evaluator = pool.acquire().expect("cannot acquire evaluator");
+        pool.release(evaluator);
+        assert_eq!(pool.idle(), 1);
+
+        // second use: the instance has reached the limit and it's dropped
+        let evaluator = pool.acquire().expect("cannot acquire evaluator");
+        assert_eq!(evaluator.uses(), 1);
+        pool.release(evaluator);
+        assert_eq!(pool.idle(), 0);
+
+        let fresh = pool.acquire().expect("cannot acquire evaluator");
+        assert_eq!(fresh.uses(), 0);
+    }
+}
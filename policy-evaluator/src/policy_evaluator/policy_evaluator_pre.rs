@@ -1,4 +1,4 @@
-use std::result::Result;
+use std::{fmt, result::Result};
 
 use crate::errors::PolicyEvaluatorPreError;
 use crate::evaluation_context::EvaluationContext;
@@ -14,6 +14,20 @@ pub struct PolicyEvaluatorPre {
     stack_pre: StackPre,
 }
 
+impl fmt::Debug for PolicyEvaluatorPre {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let runtime = match &self.stack_pre {
+            StackPre::Wapc(_) => "wapc",
+            StackPre::Wasi(_) => "wasi",
+            StackPre::Rego(_) => "rego",
+        };
+
+        f.debug_struct("PolicyEvaluatorPre")
+            .field("runtime", &runtime)
+            .finish()
+    }
+}
+
 impl PolicyEvaluatorPre {
     pub(crate) fn new(stack_pre: StackPre) -> Self {
         PolicyEvaluatorPre { stack_pre }
@@ -41,7 +55,7 @@ impl PolicyEvaluatorPre {
                 Runtime::Cli(wasi_stack)
             }
             StackPre::Rego(stack_pre) => {
-                let rego_stack = rego::Stack::new_from_pre(stack_pre)
+                let rego_stack = rego::Stack::new_from_pre(stack_pre, eval_ctx)
                     .map_err(PolicyEvaluatorPreError::RehydrateRego)?;
                 Runtime::Rego(Box::new(rego_stack))
             }
@@ -9,6 +9,7 @@ pub mod errors;
 pub mod evaluator;
 
 use crate::admission_response::AdmissionResponse;
+use crate::evaluation_context::KubernetesServiceAccount;
 use crate::policy_evaluator::PolicySettings;
 use crate::policy_metadata::ContextAwareResource;
 
@@ -19,6 +20,12 @@ pub struct PolicyGroupMemberSettings {
     pub settings: PolicySettings,
     /// The list of kubernetes resources that are allowed to be accessed by the policy member
     pub ctx_aware_resources_allow_list: BTreeSet<ContextAwareResource>,
+    /// The Kubernetes Service Account impersonated by the policy member when
+    /// interacting with the Kubernetes API server
+    pub kubernetes_service_account: Option<KubernetesServiceAccount>,
+    /// Identifier of the policy member that changes whenever it's bumped to a different
+    /// Wasm module, see [`EvaluationContext::policy_stable_id`](crate::evaluation_context::EvaluationContext::policy_stable_id)
+    pub policy_stable_id: Option<String>,
 }
 
 /// This holds the a summary of the evaluation results of a policy group member
@@ -67,6 +74,8 @@ impl TryFrom<&PolicyGroupMemberWithContext> for PolicyGroupMemberSettings {
         Ok(Self {
             settings,
             ctx_aware_resources_allow_list,
+            kubernetes_service_account: None,
+            policy_stable_id: None,
         })
     }
 }
@@ -80,6 +89,8 @@ impl TryFrom<&PolicyGroupMember> for PolicyGroupMemberSettings {
         Ok(Self {
             settings,
             ctx_aware_resources_allow_list: BTreeSet::new(),
+            kubernetes_service_account: None,
+            policy_stable_id: None,
         })
     }
 }
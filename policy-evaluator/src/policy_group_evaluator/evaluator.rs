@@ -17,6 +17,7 @@ use crate::policy_group_evaluator::{
     errors::{EvaluationError, Result},
     PolicyGroupMemberEvaluationResult, PolicyGroupMemberSettings,
 };
+use crate::request_envelope::{ClusterInfo, PolicyGroupInfo, RequestContext};
 
 /// PolicyGroupEvaluator is an evaluator that can evaluate a group of policies
 ///
@@ -60,6 +61,10 @@ pub struct PolicyGroupEvaluator {
     /// to request the computation of code that can only be run inside of an
     /// asynchronous block
     callback_channel: Option<mpsc::Sender<CallbackRequest>>,
+
+    /// The cluster served by the host, given to the members that use the `v2`
+    /// request envelope
+    cluster: Option<ClusterInfo>,
 }
 
 impl fmt::Debug for PolicyGroupEvaluator {
@@ -89,9 +94,15 @@ impl PolicyGroupEvaluator {
             policy_members: HashMap::new(),
             policy_members_settings: HashMap::new(),
             callback_channel,
+            cluster: None,
         }
     }
 
+    /// Set the cluster served by the host
+    pub fn set_cluster(&mut self, cluster: Option<ClusterInfo>) {
+        self.cluster = cluster;
+    }
+
     /// Add a policy to the group
     pub fn add_policy_member(
         &mut self,
@@ -255,17 +266,37 @@ impl PolicyGroupEvaluator {
             .get(policy_id)
             .ok_or_else(|| EvaluationError::SettingsNotFound(policy_id.to_owned()))?;
 
-        let eval_ctx = EvaluationContext {
-            policy_id: policy_id.to_owned(),
-            callback_channel: self.callback_channel.clone(),
-            ctx_aware_resources_allow_list: settings.ctx_aware_resources_allow_list.clone(),
-        };
+        let eval_ctx = self.member_evaluation_context(policy_id, settings);
         let mut evaluator = evaluator_pre.rehydrate(&eval_ctx).map_err(|e| {
             EvaluationError::CannotRehydratePolicyGroupMember(policy_id.to_owned(), e)
         })?;
         Ok(evaluator.validate(req.clone(), &settings.settings))
     }
 
+    /// The evaluation context of a member of the group
+    fn member_evaluation_context(
+        &self,
+        policy_id: &str,
+        settings: &PolicyGroupMemberSettings,
+    ) -> EvaluationContext {
+        EvaluationContext {
+            policy_id: policy_id.to_owned(),
+            policy_stable_id: settings.policy_stable_id.clone(),
+            callback_channel: self.callback_channel.clone(),
+            ctx_aware_resources_allow_list: settings.ctx_aware_resources_allow_list.clone(),
+            kubernetes_service_account: settings.kubernetes_service_account.clone(),
+            request_context: RequestContext {
+                cluster: self.cluster.clone(),
+                policy_group: Some(PolicyGroupInfo {
+                    name: self.policy_id.clone(),
+                    member: policy_id.to_owned(),
+                }),
+            },
+            http_policy: None,
+            key_value_store: false,
+        }
+    }
+
     /// Validate the settings of the group of policies
     ///
     /// Each policy is validated individually, and the expression is also validated.
@@ -328,11 +359,7 @@ impl PolicyGroupEvaluator {
             .get(policy_id)
             .ok_or_else(|| EvaluationError::SettingsNotFound(policy_id.to_owned()))?;
 
-        let eval_ctx = EvaluationContext {
-            policy_id: policy_id.to_owned(),
-            callback_channel: self.callback_channel.clone(),
-            ctx_aware_resources_allow_list: settings.ctx_aware_resources_allow_list.clone(),
-        };
+        let eval_ctx = self.member_evaluation_context(policy_id, settings);
         let mut evaluator = evaluator_pre.rehydrate(&eval_ctx).map_err(|e| {
             EvaluationError::CannotRehydratePolicyGroupMember(policy_id.to_owned(), e)
         })?;
@@ -470,6 +497,8 @@ mod tests {
                 PolicyGroupMemberSettings {
                     settings: Default::default(),
                     ctx_aware_resources_allow_list: Default::default(),
+                    kubernetes_service_account: None,
+                    policy_stable_id: None,
                 },
             );
         }
@@ -547,6 +576,8 @@ mod tests {
                 PolicyGroupMemberSettings {
                     settings: Default::default(),
                     ctx_aware_resources_allow_list: Default::default(),
+                    kubernetes_service_account: None,
+                    policy_stable_id: None,
                 },
             );
         }
@@ -11,7 +11,12 @@ use serde::{Deserialize, Serialize};
 use validator::{Validate, ValidationError};
 use wasmparser::{Parser, Payload};
 
-use crate::{errors::MetadataError, policy_evaluator::PolicyExecutionMode};
+use crate::{
+    errors::MetadataError, policy_evaluator::PolicyExecutionMode,
+    request_envelope::RequestEnvelopeVersion, request_projection::RequestProjection,
+};
+
+pub mod diff;
 
 #[derive(Deserialize, Serialize, Debug, Clone, Hash, Eq, PartialEq)]
 pub enum Operation {
@@ -179,6 +184,19 @@ impl From<&kubewarden_policy_sdk::crd::policies::common::ContextAwareResource>
     }
 }
 
+/// A directory of the host the WASI policy expects to find preopened, in read-only
+/// mode, at the given path. The directories are not available to the policy unless
+/// the operator maps them to a directory of the host
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct PreopenedDir {
+    /// Absolute path of the directory inside of the policy, like `/data`
+    pub path: String,
+    /// What the policy expects to find inside of the directory
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+}
+
 #[derive(Deserialize, Serialize, Debug, Clone, Default, PartialEq)]
 pub enum PolicyType {
     #[default]
@@ -217,6 +235,23 @@ pub struct Metadata {
     pub context_aware_resources: BTreeSet<ContextAwareResource>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub minimum_kubewarden_version: Option<Version>,
+    /// JSON Schema describing the settings accepted by the policy
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub settings_schema: Option<serde_json::Value>,
+    /// Paths of the `AdmissionRequest` fields read by the policy, like
+    /// `object.spec.containers[*].image`. When set, the other fields of
+    /// `object` and `oldObject` are not sent to the policy
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub request_projection: Option<Vec<String>>,
+    /// Version of the envelope wrapping the requests given to the policy. Policies
+    /// that do not declare it receive the `v1` envelope
+    #[serde(default, skip_serializing_if = "RequestEnvelopeVersion::is_v1")]
+    pub request_envelope: RequestEnvelopeVersion,
+    /// Directories the WASI policy reads its data files from. They are made
+    /// available to the policy only when the operator maps them to directories
+    /// of the host
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub preopened_dirs: Vec<PreopenedDir>,
 }
 
 const fn _default_true() -> bool {
@@ -235,6 +270,10 @@ impl Default for Metadata {
             policy_type: PolicyType::Kubernetes,
             context_aware_resources: BTreeSet::new(),
             minimum_kubewarden_version: None,
+            settings_schema: None,
+            request_projection: None,
+            request_envelope: RequestEnvelopeVersion::V1,
+            preopened_dirs: vec![],
         }
     }
 }
@@ -269,6 +308,55 @@ fn validate_metadata(metadata: &Metadata) -> Result<(), ValidationError> {
             "Must specify a valid protocol version",
         ));
     }
+    if let Some(schema) = &metadata.settings_schema {
+        if !schema.is_object() {
+            return Err(ValidationError::new(
+                "The settings schema must be a JSON object",
+            ));
+        }
+    }
+    if let Some(paths) = &metadata.request_projection {
+        if metadata.execution_mode != PolicyExecutionMode::KubewardenWapc {
+            return Err(ValidationError::new(
+                "The request projection is supported only by waPC policies",
+            ));
+        }
+        if metadata.mutating {
+            return Err(ValidationError::new(
+                "The request projection cannot be used by mutating policies",
+            ));
+        }
+        if let Err(e) = RequestProjection::parse(paths) {
+            return Err(ValidationError::new("Invalid request projection").with_message(e.into()));
+        }
+    }
+    if metadata.request_envelope != RequestEnvelopeVersion::V1
+        && metadata.execution_mode != PolicyExecutionMode::KubewardenWapc
+    {
+        return Err(ValidationError::new(
+            "Only waPC policies can request a version of the request envelope other than v1",
+        ));
+    }
+    if !metadata.preopened_dirs.is_empty() {
+        if metadata.execution_mode != PolicyExecutionMode::Wasi {
+            return Err(ValidationError::new(
+                "The preopened directories are supported only by WASI policies",
+            ));
+        }
+        let mut paths = HashSet::new();
+        for dir in &metadata.preopened_dirs {
+            if !dir.path.starts_with('/') {
+                return Err(ValidationError::new(
+                    "The path of a preopened directory must be absolute",
+                ));
+            }
+            if !paths.insert(dir.path.as_str()) {
+                return Err(ValidationError::new(
+                    "The same directory cannot be preopened multiple times",
+                ));
+            }
+        }
+    }
     Ok(())
 }
 
@@ -276,6 +364,7 @@ fn validate_metadata(metadata: &Metadata) -> Result<(), ValidationError> {
 mod tests {
     use super::*;
     use assert_json_diff::assert_json_eq;
+    use rstest::rstest;
     use serde_json::json;
 
     #[test]
@@ -454,6 +543,138 @@ mod tests {
         assert_json_eq!(expected, actual);
     }
 
+    #[test]
+    fn metadata_with_settings_schema() {
+        let json_metadata = json!({
+            "protocolVersion": "v1",
+            "rules": [ ],
+            "mutating": false,
+            "settingsSchema": {
+                "type": "object",
+                "properties": {
+                    "allowedRegistries": {
+                        "type": "array",
+                        "items": { "type": "string" },
+                        "default": []
+                    }
+                }
+            }
+        });
+
+        let metadata: Metadata =
+            serde_json::from_value(json_metadata.clone()).expect("cannot deserialize Metadata");
+        assert!(metadata.validate().is_ok());
+        assert_eq!(
+            metadata.settings_schema,
+            Some(json_metadata["settingsSchema"].clone())
+        );
+    }
+
+    #[test]
+    fn metadata_with_invalid_settings_schema() {
+        let metadata = Metadata {
+            protocol_version: Some(ProtocolVersion::V1),
+            settings_schema: Some(json!("not an object")),
+            ..Default::default()
+        };
+        assert!(metadata.validate().is_err());
+    }
+
+    #[test]
+    fn metadata_with_request_projection() {
+        let json_metadata = json!({
+            "protocolVersion": "v1",
+            "rules": [ ],
+            "mutating": false,
+            "requestProjection": [
+                "object.metadata.labels",
+                "object.spec.containers[*].image"
+            ]
+        });
+
+        let metadata: Metadata =
+            serde_json::from_value(json_metadata).expect("cannot deserialize Metadata");
+        assert!(metadata.validate().is_ok());
+        assert_eq!(
+            metadata.request_projection,
+            Some(vec![
+                "object.metadata.labels".to_string(),
+                "object.spec.containers[*].image".to_string()
+            ])
+        );
+    }
+
+    #[rstest]
+    #[case::mutating_policy(true, PolicyExecutionMode::KubewardenWapc, "object.metadata")]
+    #[case::rego_policy(false, PolicyExecutionMode::Opa, "object.metadata")]
+    #[case::invalid_path(false, PolicyExecutionMode::KubewardenWapc, "object..metadata")]
+    fn metadata_with_invalid_request_projection(
+        #[case] mutating: bool,
+        #[case] execution_mode: PolicyExecutionMode,
+        #[case] path: &str,
+    ) {
+        let metadata = Metadata {
+            protocol_version: Some(ProtocolVersion::V1),
+            mutating,
+            execution_mode,
+            request_projection: Some(vec![path.to_string()]),
+            ..Default::default()
+        };
+        assert!(metadata.validate().is_err());
+    }
+
+    #[rstest]
+    #[case::wapc_v2(PolicyExecutionMode::KubewardenWapc, "v2", true)]
+    #[case::rego_v1(PolicyExecutionMode::Opa, "v1", true)]
+    #[case::rego_v2(PolicyExecutionMode::Opa, "v2", false)]
+    #[case::wasi_v2(PolicyExecutionMode::Wasi, "v2", false)]
+    fn metadata_with_request_envelope(
+        #[case] execution_mode: PolicyExecutionMode,
+        #[case] request_envelope: &str,
+        #[case] valid: bool,
+    ) {
+        let json_metadata = json!({
+            "protocolVersion": "v1",
+            "rules": [ ],
+            "mutating": false,
+            "executionMode": execution_mode,
+            "requestEnvelope": request_envelope,
+        });
+
+        let metadata: Metadata =
+            serde_json::from_value(json_metadata).expect("cannot deserialize Metadata");
+        assert_eq!(metadata.request_envelope.to_string(), request_envelope);
+        assert_eq!(metadata.validate().is_ok(), valid);
+    }
+
+    #[rstest]
+    #[case::wasi(PolicyExecutionMode::Wasi, &["/data", "/etc/policy"], true)]
+    #[case::wapc(PolicyExecutionMode::KubewardenWapc, &["/data"], false)]
+    #[case::rego(PolicyExecutionMode::Opa, &["/data"], false)]
+    #[case::relative_path(PolicyExecutionMode::Wasi, &["data"], false)]
+    #[case::duplicated_path(PolicyExecutionMode::Wasi, &["/data", "/data"], false)]
+    fn metadata_with_preopened_dirs(
+        #[case] execution_mode: PolicyExecutionMode,
+        #[case] paths: &[&str],
+        #[case] valid: bool,
+    ) {
+        let json_metadata = json!({
+            "protocolVersion": "v1",
+            "rules": [ ],
+            "mutating": false,
+            "executionMode": execution_mode,
+            "preopenedDirs": paths
+                .iter()
+                .map(|path| json!({"path": path, "description": "allowed registries"}))
+                .collect::<Vec<_>>(),
+        });
+
+        let metadata: Metadata =
+            serde_json::from_value(json_metadata).expect("cannot deserialize Metadata");
+        assert_eq!(metadata.preopened_dirs.len(), paths.len());
+        assert_eq!(metadata.validate().is_ok(), valid);
+    }
+
     #[test]
     fn metadata_init() -> Result<(), ()> {
         let pod_rule = Rule {
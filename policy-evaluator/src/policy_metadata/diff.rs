@@ -0,0 +1,377 @@
+use serde::Serialize;
+use std::collections::BTreeSet;
+
+use crate::policy_metadata::{ContextAwareResource, Metadata, Operation, Rule};
+
+/// A value that changed between two versions of a policy
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct Change<T> {
+    pub from: T,
+    pub to: T,
+}
+
+/// The differences between the metadata of two versions of the same policy,
+/// limited to the ones that affect what the policy can do inside of the cluster
+/// and on the host: the Kubernetes resources accessed through the host
+/// capabilities, the directories of the host and the requests received
+#[derive(Serialize, Debug, Default, Clone, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct MetadataDiff {
+    /// Kubernetes resources the newer version has access to, that were not
+    /// accessible by the older one
+    pub added_context_aware_resources: BTreeSet<ContextAwareResource>,
+    /// Kubernetes resources the newer version no longer has access to
+    pub removed_context_aware_resources: BTreeSet<ContextAwareResource>,
+    /// Paths of the directories of the host the newer version wants to be
+    /// preopened, that were not requested by the older one
+    pub added_preopened_dirs: BTreeSet<String>,
+    /// Paths of the directories of the host the newer version no longer needs
+    pub removed_preopened_dirs: BTreeSet<String>,
+    /// The requests the newer version wants to receive, that were not received by the
+    /// older one. Each request is expressed as `<group>/<version>/<resource> <OPERATION>`.
+    /// Requests already matched by a wildcard of the older version are not reported
+    pub added_rules: BTreeSet<String>,
+    /// The requests the newer version no longer wants to receive. Narrowing a wildcard,
+    /// like replacing `*` with `pods`, reports the wildcard as removed
+    pub removed_rules: BTreeSet<String>,
+    /// Set when the ability of mutating requests changed
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub mutating: Option<Change<bool>>,
+    /// Set when the policy has been built with a different SDK or language
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub execution_mode: Option<Change<String>>,
+}
+
+impl MetadataDiff {
+    /// Compute the differences between the metadata of an older and a newer version of a policy
+    pub fn new(older: &Metadata, newer: &Metadata) -> Self {
+        let older_rules = expand_rules(&older.rules);
+        let newer_rules = expand_rules(&newer.rules);
+        let older_preopened_dirs: BTreeSet<String> = older
+            .preopened_dirs
+            .iter()
+            .map(|d| d.path.clone())
+            .collect();
+        let newer_preopened_dirs: BTreeSet<String> = newer
+            .preopened_dirs
+            .iter()
+            .map(|d| d.path.clone())
+            .collect();
+
+        MetadataDiff {
+            added_context_aware_resources: newer
+                .context_aware_resources
+                .difference(&older.context_aware_resources)
+                .cloned()
+                .collect(),
+            removed_context_aware_resources: older
+                .context_aware_resources
+                .difference(&newer.context_aware_resources)
+                .cloned()
+                .collect(),
+            added_preopened_dirs: newer_preopened_dirs
+                .difference(&older_preopened_dirs)
+                .cloned()
+                .collect(),
+            removed_preopened_dirs: older_preopened_dirs
+                .difference(&newer_preopened_dirs)
+                .cloned()
+                .collect(),
+            added_rules: uncovered_requests(&newer_rules, &older_rules),
+            removed_rules: uncovered_requests(&older_rules, &newer_rules),
+            mutating: (older.mutating != newer.mutating).then_some(Change {
+                from: older.mutating,
+                to: newer.mutating,
+            }),
+            execution_mode: (older.execution_mode != newer.execution_mode).then(|| Change {
+                from: older.execution_mode.to_string(),
+                to: newer.execution_mode.to_string(),
+            }),
+        }
+    }
+
+    /// True when the newer version can do something the older one could not: access
+    /// more Kubernetes resources or directories of the host, receive more requests
+    /// or mutate them
+    pub fn is_privilege_expansion(&self) -> bool {
+        !self.added_context_aware_resources.is_empty()
+            || !self.added_preopened_dirs.is_empty()
+            || !self.added_rules.is_empty()
+            || self.mutating.as_ref().is_some_and(|change| change.to)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        *self == MetadataDiff::default()
+    }
+}
+
+/// A single request matched by a rule, wildcards are kept as they are
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+struct RuleRequest {
+    api_group: String,
+    api_version: String,
+    resource: String,
+    operation: &'static str,
+}
+
+impl RuleRequest {
+    /// True when all the requests matched by `other` are matched by this one too
+    fn covers(&self, other: &RuleRequest) -> bool {
+        let matches = |wide: &str, narrow: &str| wide == "*" || wide == narrow;
+
+        matches(&self.api_group, &other.api_group)
+            && matches(&self.api_version, &other.api_version)
+            && matches(self.operation, other.operation)
+            && resource_covers(&self.resource, &other.resource)
+    }
+}
+
+impl std::fmt::Display for RuleRequest {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{}/{}/{} {}",
+            self.api_group, self.api_version, self.resource, self.operation
+        )
+    }
+}
+
+/// Check if the `wide` resource matches all the resources matched by `narrow`,
+/// following the Kubernetes rules: `*` matches all the resources but not their
+/// subresources, `pods/*` all the subresources of `pods`, `*/scale` the `scale`
+/// subresource of all the resources and `*/*` everything
+fn resource_covers(wide: &str, narrow: &str) -> bool {
+    let matches = |wide: &str, narrow: &str| wide == "*" || wide == narrow;
+
+    match (wide.split_once('/'), narrow.split_once('/')) {
+        (None, None) => matches(wide, narrow),
+        (Some((wide_resource, wide_subresource)), Some((resource, subresource))) => {
+            matches(wide_resource, resource) && matches(wide_subresource, subresource)
+        }
+        (Some(("*", "*")), None) => true,
+        _ => false,
+    }
+}
+
+/// The requests of `requests` that are not matched by any of the `others`
+fn uncovered_requests(
+    requests: &BTreeSet<RuleRequest>,
+    others: &BTreeSet<RuleRequest>,
+) -> BTreeSet<String> {
+    requests
+        .iter()
+        .filter(|request| !others.iter().any(|other| other.covers(request)))
+        .map(|request| request.to_string())
+        .collect()
+}
+
+/// Expand the rules into the list of the requests they match
+fn expand_rules(rules: &[Rule]) -> BTreeSet<RuleRequest> {
+    let mut requests = BTreeSet::new();
+    for rule in rules {
+        for api_group in &rule.api_groups {
+            for api_version in &rule.api_versions {
+                for resource in &rule.resources {
+                    for operation in &rule.operations {
+                        let operation = match operation {
+                            Operation::Create => "CREATE",
+                            Operation::Update => "UPDATE",
+                            Operation::Delete => "DELETE",
+                            Operation::Connect => "CONNECT",
+                            Operation::All => "*",
+                        };
+                        requests.insert(RuleRequest {
+                            api_group: api_group.clone(),
+                            api_version: api_version.clone(),
+                            resource: resource.clone(),
+                            operation,
+                        });
+                    }
+                }
+            }
+        }
+    }
+    requests
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::policy_evaluator::PolicyExecutionMode;
+    use crate::policy_metadata::PreopenedDir;
+    use rstest::rstest;
+
+    fn resource(api_version: &str, kind: &str) -> ContextAwareResource {
+        ContextAwareResource {
+            api_version: api_version.to_string(),
+            kind: kind.to_string(),
+        }
+    }
+
+    fn pod_rule(operations: Vec<Operation>) -> Rule {
+        Rule {
+            api_groups: vec![String::from("")],
+            api_versions: vec![String::from("v1")],
+            resources: vec![String::from("pods")],
+            operations,
+        }
+    }
+
+    #[test]
+    fn identical_versions() {
+        let metadata = Metadata {
+            rules: vec![pod_rule(vec![Operation::Create])],
+            context_aware_resources: BTreeSet::from([resource("v1", "Namespace")]),
+            ..Default::default()
+        };
+
+        let diff = MetadataDiff::new(&metadata, &metadata);
+        assert!(diff.is_empty());
+        assert!(!diff.is_privilege_expansion());
+    }
+
+    #[test]
+    fn privilege_expansion() {
+        let older = Metadata {
+            rules: vec![pod_rule(vec![Operation::Create])],
+            context_aware_resources: BTreeSet::from([
+                resource("v1", "Namespace"),
+                resource("v1", "ConfigMap"),
+            ]),
+            ..Default::default()
+        };
+        let newer = Metadata {
+            rules: vec![pod_rule(vec![Operation::Create, Operation::Update])],
+            context_aware_resources: BTreeSet::from([
+                resource("v1", "Namespace"),
+                resource("v1", "Secret"),
+            ]),
+            preopened_dirs: vec![PreopenedDir {
+                path: "/data".to_string(),
+                description: None,
+            }],
+            mutating: true,
+            execution_mode: PolicyExecutionMode::Opa,
+            ..Default::default()
+        };
+
+        let diff = MetadataDiff::new(&older, &newer);
+        assert!(diff.is_privilege_expansion());
+        assert_eq!(
+            diff,
+            MetadataDiff {
+                added_context_aware_resources: BTreeSet::from([resource("v1", "Secret")]),
+                removed_context_aware_resources: BTreeSet::from([resource("v1", "ConfigMap")]),
+                added_preopened_dirs: BTreeSet::from(["/data".to_string()]),
+                removed_preopened_dirs: BTreeSet::new(),
+                added_rules: BTreeSet::from(["/v1/pods UPDATE".to_string()]),
+                removed_rules: BTreeSet::new(),
+                mutating: Some(Change {
+                    from: false,
+                    to: true
+                }),
+                execution_mode: Some(Change {
+                    from: "kubewarden-wapc".to_string(),
+                    to: "opa".to_string(),
+                }),
+            }
+        );
+
+        // dropping privileges is not an expansion
+        let narrower = Metadata {
+            rules: vec![pod_rule(vec![Operation::Create])],
+            context_aware_resources: BTreeSet::from([resource("v1", "Namespace")]),
+            ..Default::default()
+        };
+        let diff = MetadataDiff::new(&newer, &narrower);
+        assert!(!diff.is_privilege_expansion());
+        assert_eq!(
+            diff.removed_context_aware_resources,
+            BTreeSet::from([resource("v1", "Secret")])
+        );
+        assert_eq!(
+            diff.removed_rules,
+            BTreeSet::from(["/v1/pods UPDATE".to_string()])
+        );
+        assert_eq!(
+            diff.removed_preopened_dirs,
+            BTreeSet::from(["/data".to_string()])
+        );
+    }
+
+    fn rule(api_groups: &[&str], resources: &[&str], operations: Vec<Operation>) -> Rule {
+        Rule {
+            api_groups: api_groups.iter().map(|g| g.to_string()).collect(),
+            api_versions: vec![String::from("v1")],
+            resources: resources.iter().map(|r| r.to_string()).collect(),
+            operations,
+        }
+    }
+
+    #[rstest]
+    #[case::narrower_resource(
+        rule(&[""], &["*"], vec![Operation::Create]),
+        rule(&[""], &["pods"], vec![Operation::Create]),
+        &[],
+        &["/v1/* CREATE"],
+    )]
+    #[case::narrower_operation(
+        rule(&[""], &["pods"], vec![Operation::All]),
+        rule(&[""], &["pods"], vec![Operation::Create, Operation::Update]),
+        &[],
+        &["/v1/pods *"],
+    )]
+    #[case::narrower_api_group(
+        rule(&["*"], &["deployments"], vec![Operation::Create]),
+        rule(&["apps"], &["deployments"], vec![Operation::Create]),
+        &[],
+        &["*/v1/deployments CREATE"],
+    )]
+    #[case::narrower_subresource(
+        rule(&[""], &["*/*"], vec![Operation::Create]),
+        rule(&[""], &["pods/exec", "pods"], vec![Operation::Create]),
+        &[],
+        &["/v1/*/* CREATE"],
+    )]
+    #[case::wider_resource(
+        rule(&[""], &["pods"], vec![Operation::Create]),
+        rule(&[""], &["*"], vec![Operation::Create]),
+        &["/v1/* CREATE"],
+        &[],
+    )]
+    #[case::subresource_not_covered_by_wildcard(
+        rule(&[""], &["*"], vec![Operation::Create]),
+        rule(&[""], &["pods/exec"], vec![Operation::Create]),
+        &["/v1/pods/exec CREATE"],
+        &["/v1/* CREATE"],
+    )]
+    fn wildcard_rules(
+        #[case] older_rule: Rule,
+        #[case] newer_rule: Rule,
+        #[case] added: &[&str],
+        #[case] removed: &[&str],
+    ) {
+        let older = Metadata {
+            rules: vec![older_rule],
+            ..Default::default()
+        };
+        let newer = Metadata {
+            rules: vec![newer_rule],
+            ..Default::default()
+        };
+
+        let diff = MetadataDiff::new(&older, &newer);
+        assert_eq!(
+            diff.added_rules,
+            added.iter().map(|r| r.to_string()).collect::<BTreeSet<_>>()
+        );
+        assert_eq!(
+            diff.removed_rules,
+            removed
+                .iter()
+                .map(|r| r.to_string())
+                .collect::<BTreeSet<_>>()
+        );
+        assert_eq!(diff.is_privilege_expansion(), !added.is_empty());
+    }
+}
@@ -0,0 +1,219 @@
+//! The envelope wrapping the requests given to the waPC policies.
+//!
+//! Historically the policies receive a JSON object made of the `request` and of
+//! the `settings` of the policy. The second version of the envelope declares its
+//! `apiVersion` and carries the context of the evaluation: the cluster the host
+//! is serving, the policy group the policy is a member of and the versions of the
+//! host capabilities implemented by the host.
+//!
+//! Policies opt in to the new envelope through their metadata. All the other ones
+//! keep receiving the first version, hence the hosts can evolve the envelope
+//! without breaking the policies built with older SDKs.
+
+use std::{collections::BTreeMap, fmt};
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::{capability_versions::supported_capabilities, policy_evaluator::PolicySettings};
+
+/// The `apiVersion` of the second version of the envelope
+pub const REQUEST_ENVELOPE_V2_API_VERSION: &str = "kubewarden.io/v2";
+
+/// The versions of the envelope known by this host
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum RequestEnvelopeVersion {
+    /// The request and the settings of the policy, without any context
+    #[default]
+    V1,
+    /// The request and the settings of the policy, together with the context of
+    /// the evaluation
+    V2,
+}
+
+impl RequestEnvelopeVersion {
+    pub(crate) fn is_v1(&self) -> bool {
+        *self == RequestEnvelopeVersion::V1
+    }
+}
+
+impl fmt::Display for RequestEnvelopeVersion {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RequestEnvelopeVersion::V1 => write!(f, "v1"),
+            RequestEnvelopeVersion::V2 => write!(f, "v2"),
+        }
+    }
+}
+
+/// The context of the evaluation provided by the host. It's given only to the
+/// policies that use the `v2` envelope
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RequestContext {
+    /// The cluster served by the host, when known
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub cluster: Option<ClusterInfo>,
+    /// Set when the policy is evaluated as a member of a policy group
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub policy_group: Option<PolicyGroupInfo>,
+}
+
+/// Details about the cluster served by the host
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ClusterInfo {
+    /// The name given to the cluster by the user
+    pub name: String,
+}
+
+/// The policy group being evaluated
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PolicyGroupInfo {
+    /// The identifier of the policy group
+    pub name: String,
+    /// The name of the member of the group that is being evaluated
+    pub member: String,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct RequestEnvelopeV2<'a> {
+    api_version: &'static str,
+    request: &'a Value,
+    settings: &'a PolicySettings,
+    context: RequestContextV2<'a>,
+}
+
+#[derive(Serialize)]
+struct RequestContextV2<'a> {
+    #[serde(flatten)]
+    context: &'a RequestContext,
+    capabilities: BTreeMap<String, Vec<u32>>,
+}
+
+/// Wrap the request and the settings of the policy inside of the envelope of the
+/// given version. The context is dropped when the `v1` envelope is used
+pub(crate) fn build_envelope(
+    version: RequestEnvelopeVersion,
+    request: &Value,
+    settings: &PolicySettings,
+    context: &RequestContext,
+) -> serde_json::Result<Value> {
+    match version {
+        RequestEnvelopeVersion::V1 => Ok(serde_json::json!({
+            "request": request,
+            "settings": settings,
+        })),
+        RequestEnvelopeVersion::V2 => serde_json::to_value(RequestEnvelopeV2 {
+            api_version: REQUEST_ENVELOPE_V2_API_VERSION,
+            request,
+            settings,
+            context: RequestContextV2 {
+                context,
+                capabilities: supported_capabilities(),
+            },
+        }),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn settings() -> PolicySettings {
+        PolicySettings(
+            json!({"allowedRegistries": ["ghcr.io"]})
+                .as_object()
+                .unwrap()
+                .to_owned(),
+        )
+    }
+
+    fn context() -> RequestContext {
+        RequestContext {
+            cluster: Some(ClusterInfo {
+                name: "production".to_string(),
+            }),
+            policy_group: Some(PolicyGroupInfo {
+                name: "trusted-images".to_string(),
+                member: "signed".to_string(),
+            }),
+        }
+    }
+
+    #[test]
+    fn build_v1_envelope() {
+        let request = json!({"uid": "hello"});
+
+        let envelope = build_envelope(
+            RequestEnvelopeVersion::V1,
+            &request,
+            &settings(),
+            &context(),
+        )
+        .unwrap();
+
+        assert_eq!(
+            envelope,
+            json!({
+                "request": {"uid": "hello"},
+                "settings": {"allowedRegistries": ["ghcr.io"]},
+            })
+        );
+    }
+
+    #[test]
+    fn build_v2_envelope() {
+        let request = json!({"uid": "hello"});
+
+        let envelope = build_envelope(
+            RequestEnvelopeVersion::V2,
+            &request,
+            &settings(),
+            &context(),
+        )
+        .unwrap();
+
+        assert_eq!(envelope["apiVersion"], REQUEST_ENVELOPE_V2_API_VERSION);
+        assert_eq!(envelope["request"], request);
+        assert_eq!(
+            envelope["settings"],
+            json!({"allowedRegistries": ["ghcr.io"]})
+        );
+        assert_eq!(
+            envelope["context"]["cluster"],
+            json!({"name": "production"})
+        );
+        assert_eq!(
+            envelope["context"]["policyGroup"],
+            json!({"name": "trusted-images", "member": "signed"})
+        );
+        assert_eq!(
+            envelope["context"]["capabilities"]["oci/verify"],
+            json!([1, 2])
+        );
+    }
+
+    #[test]
+    fn build_v2_envelope_without_context() {
+        let request = json!({"uid": "hello"});
+
+        let envelope = build_envelope(
+            RequestEnvelopeVersion::V2,
+            &request,
+            &settings(),
+            &RequestContext::default(),
+        )
+        .unwrap();
+
+        let context = envelope["context"].as_object().unwrap();
+        assert_eq!(
+            context.keys().collect::<Vec<&String>>(),
+            vec!["capabilities"]
+        );
+    }
+}
@@ -0,0 +1,251 @@
+//! Projection of the `AdmissionRequest` objects given to the policies.
+//!
+//! Most policies read only a handful of fields of the object of the request, but
+//! they receive it entirely. The metadata of a policy can declare the fields it is
+//! interested in, the other fields of `object` and `oldObject` are then stripped
+//! before the request is sent to the policy. This reduces the cost of serializing
+//! the request and of parsing it inside of the guest, which is significant for big
+//! objects, like Deployments with large Pod templates.
+
+use serde_json::{Map, Value};
+
+/// The fields of the request that are subject to the projection. All the other
+/// fields of the request, like `operation` or `userInfo`, are always given to the policy
+const PROJECTED_FIELDS: &[&str] = &["object", "oldObject"];
+
+/// The fields of the projected objects that are always kept, to allow the policy to
+/// tell what kind of object it received
+const ALWAYS_KEPT_OBJECT_FIELDS: &[&str] = &["apiVersion", "kind"];
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+enum Segment {
+    /// The field of an object
+    Field(String),
+    /// All the items of a list
+    AllItems,
+}
+
+/// The fields of the request a policy is interested in
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub(crate) struct RequestProjection {
+    paths: Vec<Vec<Segment>>,
+}
+
+impl RequestProjection {
+    /// Parse the given paths. They are a subset of the JSONPath syntax: the fields are
+    /// separated by dots, `[*]` selects all the items of a list and the leading `$.` is
+    /// optional. For example: `object.metadata.labels` or
+    /// `$.object.spec.containers[*].image`
+    pub(crate) fn parse(paths: &[String]) -> Result<Self, String> {
+        if paths.is_empty() {
+            return Err("the projection must contain at least one path".to_string());
+        }
+
+        let paths = paths
+            .iter()
+            .map(|path| parse_path(path))
+            .collect::<Result<Vec<_>, String>>()?;
+        Ok(Self { paths })
+    }
+
+    /// Return the projection of the given `AdmissionRequest`, serialized as JSON
+    pub(crate) fn apply(&self, request: &Value) -> Value {
+        let Some(request_fields) = request.as_object() else {
+            return request.clone();
+        };
+
+        let mut projected: Map<String, Value> = request_fields
+            .iter()
+            .filter(|(field, _)| !PROJECTED_FIELDS.contains(&field.as_str()))
+            .map(|(field, value)| (field.clone(), value.clone()))
+            .collect();
+        for field in PROJECTED_FIELDS {
+            if let Some(Value::Object(object)) = request_fields.get(*field) {
+                let kept: Map<String, Value> = object
+                    .iter()
+                    .filter(|(field, _)| ALWAYS_KEPT_OBJECT_FIELDS.contains(&field.as_str()))
+                    .map(|(field, value)| (field.clone(), value.clone()))
+                    .collect();
+                projected.insert(field.to_string(), Value::Object(kept));
+            } else if let Some(value) = request_fields.get(*field) {
+                // `null`, like the `object` of DELETE requests
+                projected.insert(field.to_string(), value.clone());
+            }
+        }
+
+        let mut projected = Value::Object(projected);
+        for path in &self.paths {
+            copy_path(request, &mut projected, path);
+        }
+        projected
+    }
+}
+
+fn parse_path(path: &str) -> Result<Vec<Segment>, String> {
+    let trimmed = path.strip_prefix("$.").unwrap_or(path);
+    let mut segments = Vec::new();
+
+    for field in trimmed.split('.') {
+        let (name, all_items) = match field.strip_suffix("[*]") {
+            Some(name) => (name, true),
+            None => (field, false),
+        };
+        if name.is_empty() || name.contains(['[', ']', '*']) {
+            return Err(format!("invalid path `{path}`"));
+        }
+        segments.push(Segment::Field(name.to_string()));
+        if all_items {
+            segments.push(Segment::AllItems);
+        }
+    }
+
+    Ok(segments)
+}
+
+/// Copy the value found at the given path of `source` into `target`, creating the
+/// objects and the lists leading to it. Nothing is done when the path doesn't exist.
+///
+/// Returns whether something has been copied
+fn copy_path(source: &Value, target: &mut Value, path: &[Segment]) -> bool {
+    let Some((segment, rest)) = path.split_first() else {
+        *target = source.clone();
+        return true;
+    };
+
+    match segment {
+        Segment::Field(name) => {
+            let Some(child) = source.as_object().and_then(|object| object.get(name)) else {
+                return false;
+            };
+            if !target.is_object() {
+                *target = Value::Object(Map::new());
+            }
+            let target_object = target
+                .as_object_mut()
+                .expect("target has just been turned into an object");
+            let existed = target_object.contains_key(name);
+            let target_child = target_object.entry(name.clone()).or_insert(Value::Null);
+            let copied = copy_path(child, target_child, rest);
+            if !copied && !existed {
+                // do not leave behind the fields leading to a missing path
+                target_object.remove(name);
+            }
+            copied
+        }
+        Segment::AllItems => {
+            let Some(items) = source.as_array() else {
+                return false;
+            };
+            if target.as_array().map(Vec::len) != Some(items.len()) {
+                *target = Value::Array(vec![Value::Object(Map::new()); items.len()]);
+            }
+            let target_items = target
+                .as_array_mut()
+                .expect("target has just been turned into a list");
+            for (item, target_item) in items.iter().zip(target_items.iter_mut()) {
+                copy_path(item, target_item, rest);
+            }
+            true
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rstest::rstest;
+    use serde_json::json;
+
+    fn request() -> Value {
+        json!({
+            "uid": "705ab4f5",
+            "operation": "CREATE",
+            "object": {
+                "apiVersion": "v1",
+                "kind": "Pod",
+                "metadata": {"name": "nginx", "labels": {"app": "nginx"}, "annotations": {"a": "b"}},
+                "spec": {
+                    "containers": [
+                        {"name": "nginx", "image": "nginx:latest", "ports": [{"containerPort": 80}]},
+                        {"name": "sidecar", "image": "busybox:1.36"}
+                    ]
+                }
+            },
+            "oldObject": null
+        })
+    }
+
+    fn projection(paths: &[&str]) -> RequestProjection {
+        RequestProjection::parse(&paths.iter().map(|p| p.to_string()).collect::<Vec<_>>())
+            .expect("cannot parse projection")
+    }
+
+    #[rstest]
+    #[case::empty(&[])]
+    #[case::empty_field(&["object..metadata"])]
+    #[case::unsupported_index(&["object.spec.containers[0]"])]
+    #[case::wildcard_field(&["object.*"])]
+    fn invalid_projection(#[case] paths: &[&str]) {
+        let paths: Vec<String> = paths.iter().map(|p| p.to_string()).collect();
+        assert!(RequestProjection::parse(&paths).is_err());
+    }
+
+    #[test]
+    fn project_fields() {
+        let projected = projection(&["$.object.metadata.labels"]).apply(&request());
+
+        assert_eq!(
+            projected,
+            json!({
+                "uid": "705ab4f5",
+                "operation": "CREATE",
+                "object": {
+                    "apiVersion": "v1",
+                    "kind": "Pod",
+                    "metadata": {"labels": {"app": "nginx"}}
+                },
+                "oldObject": null
+            })
+        );
+    }
+
+    #[test]
+    fn project_items_of_lists() {
+        let projected = projection(&["object.spec.containers[*].image", "object.metadata.name"])
+            .apply(&request());
+
+        assert_eq!(
+            projected["object"],
+            json!({
+                "apiVersion": "v1",
+                "kind": "Pod",
+                "metadata": {"name": "nginx"},
+                "spec": {
+                    "containers": [
+                        {"image": "nginx:latest"},
+                        {"image": "busybox:1.36"}
+                    ]
+                }
+            })
+        );
+    }
+
+    #[test]
+    fn overlapping_paths() {
+        let projected =
+            projection(&["object.spec.containers[*].image", "object.spec"]).apply(&request());
+
+        assert_eq!(projected["object"]["spec"], request()["object"]["spec"]);
+    }
+
+    #[test]
+    fn missing_paths_are_ignored() {
+        let projected =
+            projection(&["object.spec.initContainers[*].image", "object.status"]).apply(&request());
+
+        assert_eq!(
+            projected["object"],
+            json!({"apiVersion": "v1", "kind": "Pod"})
+        );
+    }
+}
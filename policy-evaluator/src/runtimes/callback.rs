@@ -1,4 +1,4 @@
-use std::sync::Arc;
+use std::{sync::Arc, time::Instant};
 
 use anyhow::{anyhow, Result};
 use kubewarden_policy_sdk::host_capabilities::{
@@ -11,8 +11,16 @@ use kubewarden_policy_sdk::host_capabilities::{
 use tokio::sync::{mpsc, oneshot, oneshot::Receiver};
 use tracing::{debug, error, warn};
 
-use crate::callback_requests::{CallbackRequest, CallbackRequestType, CallbackResponse};
-use crate::{callback_handler::verify_certificate, evaluation_context::EvaluationContext};
+use crate::callback_requests::{
+    CallbackRequest, CallbackRequestType, CallbackResponse, KeyValueIncrementRequest,
+    KeyValueKeyRequest, KeyValueSetRequest, ListResourcesByNamespacesRequest,
+};
+use crate::{
+    callback_handler::{verify_certificate, ResponseTooLarge},
+    capability_usage, capability_versions, evaluation_cache,
+    evaluation_context::EvaluationContext,
+    metrics,
+};
 
 /// The callback function used by waPC and Wasi policies to use host capabilities
 pub(crate) fn host_callback(
@@ -37,10 +45,7 @@ pub(crate) fn host_callback(
                     }
                     Ok(Vec::new())
                 }
-                _ => {
-                    error!(namespace, operation, "unknown operation");
-                    Err(format!("unknown operation: {operation}").into())
-                }
+                _ => unknown_operation(eval_ctx, namespace, operation),
             },
             "oci" => match operation {
                 "v1/verify" => {
@@ -51,6 +56,7 @@ pub(crate) fn host_callback(
                     let req = CallbackRequest {
                         request: req_type,
                         response_channel: tx,
+                        kubernetes_service_account: eval_ctx.kubernetes_service_account.clone(),
                     };
 
                     send_request_and_wait_for_response(
@@ -70,6 +76,7 @@ pub(crate) fn host_callback(
                     let req = CallbackRequest {
                         request: req_type,
                         response_channel: tx,
+                        kubernetes_service_account: eval_ctx.kubernetes_service_account.clone(),
                     };
 
                     send_request_and_wait_for_response(
@@ -81,6 +88,30 @@ pub(crate) fn host_callback(
                         eval_ctx,
                     )
                 }
+                "v1/verify_image_against_server_config" => {
+                    let image: String = serde_json::from_slice(payload.to_vec().as_ref())?;
+                    debug!(
+                        eval_ctx.policy_id,
+                        binding,
+                        operation,
+                        image = image.as_str(),
+                        "Sending request via callback channel"
+                    );
+                    let (tx, rx) = oneshot::channel::<Result<CallbackResponse>>();
+                    let req = CallbackRequest {
+                        request: CallbackRequestType::SigstoreServerConfigVerify { image },
+                        response_channel: tx,
+                        kubernetes_service_account: eval_ctx.kubernetes_service_account.clone(),
+                    };
+                    send_request_and_wait_for_response(
+                        &eval_ctx.policy_id,
+                        binding,
+                        operation,
+                        req,
+                        rx,
+                        eval_ctx,
+                    )
+                }
                 "v1/manifest_digest" => {
                     let image: String = serde_json::from_slice(payload.to_vec().as_ref())?;
                     debug!(
@@ -94,6 +125,31 @@ pub(crate) fn host_callback(
                     let req = CallbackRequest {
                         request: CallbackRequestType::OciManifestDigest { image },
                         response_channel: tx,
+                        kubernetes_service_account: eval_ctx.kubernetes_service_account.clone(),
+                    };
+                    send_request_and_wait_for_response(
+                        &eval_ctx.policy_id,
+                        binding,
+                        operation,
+                        req,
+                        rx,
+                        eval_ctx,
+                    )
+                }
+                "v1/resolve_digest" => {
+                    let image: String = serde_json::from_slice(payload.to_vec().as_ref())?;
+                    debug!(
+                        eval_ctx.policy_id,
+                        binding,
+                        operation,
+                        image = image.as_str(),
+                        "Sending request via callback channel"
+                    );
+                    let (tx, rx) = oneshot::channel::<Result<CallbackResponse>>();
+                    let req = CallbackRequest {
+                        request: CallbackRequestType::OciResolveDigest { image },
+                        response_channel: tx,
+                        kubernetes_service_account: eval_ctx.kubernetes_service_account.clone(),
                     };
                     send_request_and_wait_for_response(
                         &eval_ctx.policy_id,
@@ -117,6 +173,7 @@ pub(crate) fn host_callback(
                     let req = CallbackRequest {
                         request: CallbackRequestType::OciManifest { image },
                         response_channel: tx,
+                        kubernetes_service_account: eval_ctx.kubernetes_service_account.clone(),
                     };
                     send_request_and_wait_for_response(
                         &eval_ctx.policy_id,
@@ -140,6 +197,7 @@ pub(crate) fn host_callback(
                     let req = CallbackRequest {
                         request: CallbackRequestType::OciManifestAndConfig { image },
                         response_channel: tx,
+                        kubernetes_service_account: eval_ctx.kubernetes_service_account.clone(),
                     };
                     send_request_and_wait_for_response(
                         &eval_ctx.policy_id,
@@ -150,10 +208,7 @@ pub(crate) fn host_callback(
                         eval_ctx,
                     )
                 }
-                _ => {
-                    error!("unknown operation: {}", operation);
-                    Err(format!("unknown operation: {operation}").into())
-                }
+                _ => unknown_operation(eval_ctx, namespace, operation),
             },
             "net" => match operation {
                 "v1/dns_lookup_host" => {
@@ -169,6 +224,7 @@ pub(crate) fn host_callback(
                     let req = CallbackRequest {
                         request: CallbackRequestType::DNSLookupHost { host },
                         response_channel: tx,
+                        kubernetes_service_account: eval_ctx.kubernetes_service_account.clone(),
                     };
                     send_request_and_wait_for_response(
                         &eval_ctx.policy_id,
@@ -179,10 +235,7 @@ pub(crate) fn host_callback(
                         eval_ctx,
                     )
                 }
-                _ => {
-                    error!("unknown operation: {}", operation);
-                    Err(format!("unknown operation: {operation}").into())
-                }
+                _ => unknown_operation(eval_ctx, namespace, operation),
             },
             "crypto" => match operation {
                 "v1/is_certificate_trusted" => {
@@ -196,10 +249,7 @@ pub(crate) fn host_callback(
                     };
                     Ok(serde_json::to_vec(&response)?)
                 }
-                _ => {
-                    error!(namespace, operation, "unknown operation");
-                    Err(format!("unknown operation: {operation}").into())
-                }
+                _ => unknown_operation(eval_ctx, namespace, operation),
             },
             "kubernetes" => match operation {
                 "list_resources_by_namespace" => {
@@ -229,6 +279,54 @@ pub(crate) fn host_callback(
                     let req = CallbackRequest {
                         request: CallbackRequestType::from(req),
                         response_channel: tx,
+                        kubernetes_service_account: eval_ctx.kubernetes_service_account.clone(),
+                    };
+                    send_request_and_wait_for_response(
+                        &eval_ctx.policy_id,
+                        binding,
+                        operation,
+                        req,
+                        rx,
+                        eval_ctx,
+                    )
+                }
+                "list_resources_by_namespaces" => {
+                    let req: ListResourcesByNamespacesRequest =
+                        serde_json::from_slice(payload.to_vec().as_ref())?;
+
+                    // The namespaces are selected by looking at their labels, which requires
+                    // access to them too
+                    let mut requested_resources =
+                        vec![(req.api_version.as_str(), req.kind.as_str())];
+                    if req.namespace_selector.is_some() {
+                        requested_resources.push(("v1", "Namespace"));
+                    }
+                    for (api_version, kind) in requested_resources {
+                        if !eval_ctx.can_access_kubernetes_resource(api_version, kind) {
+                            error!(
+                                policy = eval_ctx.policy_id,
+                                resource_requested = format!("{}/{}", api_version, kind),
+                                resources_allowed = ?eval_ctx.ctx_aware_resources_allow_list,
+                                "Policy tried to access a Kubernetes resource it doesn't have access to");
+                            return Err(format!(
+                                    "Policy has not been granted access to Kubernetes {}/{} resources. The violation has been reported.",
+                                    api_version,
+                                    kind).into());
+                        }
+                    }
+
+                    debug!(
+                        eval_ctx.policy_id,
+                        binding,
+                        operation,
+                        ?req,
+                        "Sending request via callback channel"
+                    );
+                    let (tx, rx) = oneshot::channel::<Result<CallbackResponse>>();
+                    let req = CallbackRequest {
+                        request: CallbackRequestType::from(req),
+                        response_channel: tx,
+                        kubernetes_service_account: eval_ctx.kubernetes_service_account.clone(),
                     };
                     send_request_and_wait_for_response(
                         &eval_ctx.policy_id,
@@ -265,6 +363,7 @@ pub(crate) fn host_callback(
                     let req = CallbackRequest {
                         request: CallbackRequestType::from(req),
                         response_channel: tx,
+                        kubernetes_service_account: eval_ctx.kubernetes_service_account.clone(),
                     };
                     send_request_and_wait_for_response(
                         &eval_ctx.policy_id,
@@ -301,6 +400,7 @@ pub(crate) fn host_callback(
                     let req = CallbackRequest {
                         request: CallbackRequestType::from(req),
                         response_channel: tx,
+                        kubernetes_service_account: eval_ctx.kubernetes_service_account.clone(),
                     };
                     send_request_and_wait_for_response(
                         &eval_ctx.policy_id,
@@ -326,6 +426,7 @@ pub(crate) fn host_callback(
                     let req = CallbackRequest {
                         request: CallbackRequestType::from(req),
                         response_channel: tx,
+                        kubernetes_service_account: eval_ctx.kubernetes_service_account.clone(),
                     };
                     send_request_and_wait_for_response(
                         &eval_ctx.policy_id,
@@ -336,11 +437,91 @@ pub(crate) fn host_callback(
                         eval_ctx,
                     )
                 }
-                _ => {
-                    error!(namespace, operation, "unknown operation");
-                    Err(format!("unknown operation: {operation}").into())
-                }
+                _ => unknown_operation(eval_ctx, namespace, operation),
             },
+            "kv" => {
+                if !eval_ctx.key_value_store {
+                    error!(
+                        policy = eval_ctx.policy_id,
+                        operation,
+                        "Policy tried to use the key/value store without being allowed to"
+                    );
+                    return Err(
+                        "Policy has not been granted access to the key/value store. The violation has been reported."
+                            .into(),
+                    );
+                }
+
+                // the entries are always scoped to the policy, the guest can only pick
+                // the namespace among its own ones
+                let policy_id = eval_ctx.policy_id.clone();
+                let req_type = match operation {
+                    "v1/get" => {
+                        let req: KeyValueKeyRequest =
+                            serde_json::from_slice(payload.to_vec().as_ref())?;
+                        CallbackRequestType::KeyValueGet {
+                            policy_id,
+                            namespace: req.namespace,
+                            key: req.key,
+                        }
+                    }
+                    "v1/set" => {
+                        let req: KeyValueSetRequest =
+                            serde_json::from_slice(payload.to_vec().as_ref())?;
+                        CallbackRequestType::KeyValueSet {
+                            policy_id,
+                            namespace: req.namespace,
+                            key: req.key,
+                            value: req.value,
+                            ttl_seconds: req.ttl_seconds,
+                        }
+                    }
+                    "v1/delete" => {
+                        let req: KeyValueKeyRequest =
+                            serde_json::from_slice(payload.to_vec().as_ref())?;
+                        CallbackRequestType::KeyValueDelete {
+                            policy_id,
+                            namespace: req.namespace,
+                            key: req.key,
+                        }
+                    }
+                    "v1/increment" => {
+                        let req: KeyValueIncrementRequest =
+                            serde_json::from_slice(payload.to_vec().as_ref())?;
+                        CallbackRequestType::KeyValueIncrement {
+                            policy_id,
+                            namespace: req.namespace,
+                            key: req.key,
+                            delta: req.delta,
+                            ttl_seconds: req.ttl_seconds,
+                        }
+                    }
+                    _ => return unknown_operation(eval_ctx, namespace, operation),
+                };
+
+                debug!(
+                    eval_ctx.policy_id,
+                    binding,
+                    namespace,
+                    operation,
+                    ?req_type,
+                    "Sending request via callback channel"
+                );
+                let (tx, rx) = oneshot::channel::<Result<CallbackResponse>>();
+                let req = CallbackRequest {
+                    request: req_type,
+                    response_channel: tx,
+                    kubernetes_service_account: eval_ctx.kubernetes_service_account.clone(),
+                };
+                send_request_and_wait_for_response(
+                    &eval_ctx.policy_id,
+                    binding,
+                    operation,
+                    req,
+                    rx,
+                    eval_ctx,
+                )
+            }
             _ => {
                 error!("unknown namespace: {}", namespace);
                 Err(format!("unknown namespace: {namespace}").into())
@@ -364,6 +545,7 @@ pub(crate) fn host_callback(
                 let req = CallbackRequest {
                     request: req,
                     response_channel: tx,
+                    kubernetes_service_account: eval_ctx.kubernetes_service_account.clone(),
                 };
                 send_request_and_wait_for_response(
                     &eval_ctx.policy_id,
@@ -391,6 +573,7 @@ pub(crate) fn host_callback(
                 let req = CallbackRequest {
                     request: req,
                     response_channel: tx,
+                    kubernetes_service_account: eval_ctx.kubernetes_service_account.clone(),
                 };
                 send_request_and_wait_for_response(
                     &eval_ctx.policy_id,
@@ -418,6 +601,7 @@ pub(crate) fn host_callback(
                 let req = CallbackRequest {
                     request: req,
                     response_channel: tx,
+                    kubernetes_service_account: eval_ctx.kubernetes_service_account.clone(),
                 };
                 send_request_and_wait_for_response(
                     &eval_ctx.policy_id,
@@ -440,6 +624,23 @@ pub(crate) fn host_callback(
     }
 }
 
+/// Handle an operation that is not implemented by the host. Known capabilities invoked with
+/// an unsupported version are reported with a dedicated error.
+fn unknown_operation(
+    eval_ctx: &EvaluationContext,
+    namespace: &str,
+    operation: &str,
+) -> Result<Vec<u8>, Box<dyn std::error::Error + Send + Sync>> {
+    if let Some(unsupported) =
+        capability_versions::check_unsupported_version(&eval_ctx.policy_id, namespace, operation)
+    {
+        return Err(unsupported.into());
+    }
+
+    error!(namespace, operation, "unknown operation");
+    Err(format!("unknown operation: {operation}").into())
+}
+
 fn send_request_and_wait_for_response(
     policy_id: &str,
     binding: &str,
@@ -462,13 +663,31 @@ fn send_request_and_wait_for_response(
         ))
     }?;
 
+    capability_usage::record_call(&req.request);
+    evaluation_cache::record_host_call();
+    let start_time = Instant::now();
     let send_result = cb_channel.try_send(req);
     if let Err(e) = send_result {
+        metrics::record_host_callback(
+            policy_id,
+            eval_ctx.policy_stable_id.as_deref(),
+            operation,
+            false,
+            start_time.elapsed(),
+        );
         return Err(format!("Error sending request over callback channel: {e:?}").into());
     }
 
     // wait for the response
-    match rx.blocking_recv() {
+    let response = rx.blocking_recv();
+    metrics::record_host_callback(
+        policy_id,
+        eval_ctx.policy_stable_id.as_deref(),
+        operation,
+        matches!(response, Ok(Ok(_))),
+        start_time.elapsed(),
+    );
+    match response {
         Ok(msg) => match msg {
             Ok(resp) => Ok(resp.payload),
             Err(e) => {
@@ -479,6 +698,10 @@ fn send_request_and_wait_for_response(
                     error = e.to_string().as_str(),
                     "callback evaluation failed"
                 );
+                // Give the policy a clean message telling how to narrow the request
+                if let Some(too_large) = e.downcast_ref::<ResponseTooLarge>() {
+                    return Err(too_large.clone().into());
+                }
                 Err(format!("Callback evaluation failure: {e:?}").into())
             }
         },
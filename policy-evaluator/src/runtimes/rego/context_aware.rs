@@ -3,7 +3,10 @@ use std::collections::{BTreeMap, BTreeSet};
 use tokio::sync::{mpsc, oneshot};
 
 use crate::{
+    callback_handler::subscriptions::{self, SubscriptionToken},
     callback_requests::{CallbackRequest, CallbackRequestType, CallbackResponse},
+    capability_usage, evaluation_cache,
+    evaluation_context::KubernetesServiceAccount,
     policy_metadata::ContextAwareResource,
     runtimes::rego::{
         errors::{RegoRuntimeError, Result},
@@ -11,28 +14,57 @@ use crate::{
     },
 };
 
-#[derive(serde::Serialize)]
-#[serde(untagged)]
+/// The size of the serialized Kubernetes resources, grouped by their type
+pub(crate) type ResourceSizes = BTreeMap<ContextAwareResource, usize>;
+
+/// The Kubernetes data given to the policy, together with the size of the
+/// resources it has been built from
 pub(crate) enum KubernetesContext {
     Empty,
-    Opa(OpaInventory),
-    Gatekeeper(Vec<u8>),
+    Opa(OpaInventory, ResourceSizes),
+    Gatekeeper(Vec<u8>, ResourceSizes),
+}
+
+impl KubernetesContext {
+    pub(crate) fn resource_sizes(&self) -> Option<&ResourceSizes> {
+        match self {
+            KubernetesContext::Empty => None,
+            KubernetesContext::Opa(_, sizes) | KubernetesContext::Gatekeeper(_, sizes) => {
+                Some(sizes)
+            }
+        }
+    }
+}
+
+/// Compute the size of the serialized lists of resources, grouped by their type
+pub(crate) fn resource_sizes(
+    kube_resources: &BTreeMap<ContextAwareResource, ObjectList<kube::core::DynamicObject>>,
+) -> ResourceSizes {
+    kube_resources
+        .iter()
+        .map(|(resource, list)| {
+            let size = serde_json::to_vec(list).map_or(0, |data| data.len());
+            (resource.to_owned(), size)
+        })
+        .collect()
 }
 
 /// Uses the callback channel to get all the Kubernetes resources defined inside of
 /// the cluster whose type is mentioned inside of `allowed_resources`.
 ///
 /// The resources are returned based on the actual RBAC privileges of the client
-/// used by the runtime.
+/// used by the runtime, or of the given Service Account when set.
 pub(crate) fn get_allowed_resources(
     callback_channel: &mpsc::Sender<CallbackRequest>,
     allowed_resources: &BTreeSet<ContextAwareResource>,
+    kubernetes_service_account: Option<&KubernetesServiceAccount>,
 ) -> Result<BTreeMap<ContextAwareResource, ObjectList<kube::core::DynamicObject>>> {
     let mut kube_resources: BTreeMap<ContextAwareResource, ObjectList<kube::core::DynamicObject>> =
         BTreeMap::new();
 
     for resource in allowed_resources {
-        let resource_list = get_all_resources_by_type(callback_channel, resource)?;
+        let resource_list =
+            get_all_resources_by_type(callback_channel, resource, kubernetes_service_account)?;
         kube_resources.insert(resource.to_owned(), resource_list);
     }
 
@@ -42,6 +74,7 @@ pub(crate) fn get_allowed_resources(
 fn get_all_resources_by_type(
     callback_channel: &mpsc::Sender<CallbackRequest>,
     resource_type: &ContextAwareResource,
+    kubernetes_service_account: Option<&KubernetesServiceAccount>,
 ) -> Result<ObjectList<kube::core::DynamicObject>> {
     let req_type = CallbackRequestType::KubernetesListResourceAll {
         api_version: resource_type.api_version.to_owned(),
@@ -50,7 +83,8 @@ fn get_all_resources_by_type(
         field_selector: None,
     };
 
-    let response = make_request_via_callback_channel(req_type, callback_channel)?;
+    let response =
+        make_request_via_callback_channel(req_type, callback_channel, kubernetes_service_account)?;
     serde_json::from_slice::<ObjectList<kube::core::DynamicObject>>(&response.payload)
         .map_err(RegoRuntimeError::CallbackConvertList)
 }
@@ -60,9 +94,15 @@ pub(crate) fn have_allowed_resources_changed_since_instant(
     callback_channel: &mpsc::Sender<CallbackRequest>,
     allowed_resources: &BTreeSet<ContextAwareResource>,
     since: tokio::time::Instant,
+    kubernetes_service_account: Option<&KubernetesServiceAccount>,
 ) -> Result<bool> {
     for resource in allowed_resources {
-        if has_resource_changed_since(callback_channel, resource, since)? {
+        if has_resource_changed_since(
+            callback_channel,
+            resource,
+            since,
+            kubernetes_service_account,
+        )? {
             return Ok(true);
         }
     }
@@ -77,6 +117,7 @@ fn has_resource_changed_since(
     callback_channel: &mpsc::Sender<CallbackRequest>,
     resource_type: &ContextAwareResource,
     since: tokio::time::Instant,
+    kubernetes_service_account: Option<&KubernetesServiceAccount>,
 ) -> Result<bool> {
     let req_type = CallbackRequestType::HasKubernetesListResourceAllResultChangedSinceInstant {
         api_version: resource_type.api_version.to_owned(),
@@ -86,16 +127,61 @@ fn has_resource_changed_since(
         since,
     };
 
-    let response = make_request_via_callback_channel(req_type, callback_channel)?;
+    let response =
+        make_request_via_callback_channel(req_type, callback_channel, kubernetes_service_account)?;
     serde_json::from_slice::<bool>(&response.payload).map_err(RegoRuntimeError::CallbackConvertBool)
 }
 
+/// Subscribe to the changes of the "list all resources" result of each allowed resource.
+/// The tokens of the subscriptions are returned in the same order as the resources
+pub(crate) fn subscribe_to_allowed_resources_changes(
+    callback_channel: &mpsc::Sender<CallbackRequest>,
+    allowed_resources: &BTreeSet<ContextAwareResource>,
+    kubernetes_service_account: Option<&KubernetesServiceAccount>,
+) -> Result<Vec<SubscriptionToken>> {
+    allowed_resources
+        .iter()
+        .map(|resource| {
+            let req_type = CallbackRequestType::SubscribeKubernetesListResourceAllChanges {
+                api_version: resource.api_version.to_owned(),
+                kind: resource.kind.to_owned(),
+                label_selector: None,
+                field_selector: None,
+            };
+
+            let response = make_request_via_callback_channel(
+                req_type,
+                callback_channel,
+                kubernetes_service_account,
+            )?;
+            serde_json::from_slice::<SubscriptionToken>(&response.payload)
+                .map_err(RegoRuntimeError::CallbackConvertSubscriptionToken)
+        })
+        .collect()
+}
+
+/// Check whether a change has been notified to any of the given subscriptions. This
+/// doesn't involve the callback channel: the notifications are pushed by the reflectors.
+///
+/// All the notifications are consumed. Returns `None` when one of the subscriptions is
+/// unknown, the changes must then be checked with `have_allowed_resources_changed_since_instant`
+pub(crate) fn have_subscribed_resources_changed(
+    subscription_tokens: &[SubscriptionToken],
+) -> Option<bool> {
+    subscription_tokens
+        .iter()
+        .map(|token| subscriptions::take_notification(*token))
+        .collect::<Option<Vec<bool>>>()
+        .map(|changes| changes.into_iter().any(|changed| changed))
+}
+
 /// Creates a map that has ContextAwareResource as key, and its plural name as value.
 /// For example, the key for {`apps/v1`, `Deployment`} will have `deployments` as value.
 /// The map is built by making request via the given callback channel.
 pub(crate) fn get_plural_names(
     callback_channel: &mpsc::Sender<CallbackRequest>,
     allowed_resources: &BTreeSet<ContextAwareResource>,
+    kubernetes_service_account: Option<&KubernetesServiceAccount>,
 ) -> Result<BTreeMap<ContextAwareResource, String>> {
     let mut plural_names_by_resource: BTreeMap<ContextAwareResource, String> = BTreeMap::new();
 
@@ -105,7 +191,11 @@ pub(crate) fn get_plural_names(
             kind: resource.kind.to_owned(),
         };
 
-        let response = make_request_via_callback_channel(req_type, callback_channel)?;
+        let response = make_request_via_callback_channel(
+            req_type,
+            callback_channel,
+            kubernetes_service_account,
+        )?;
         let plural_name = serde_json::from_slice::<String>(&response.payload)
             .map_err(RegoRuntimeError::CallbackGetPluralName)?;
 
@@ -116,15 +206,19 @@ pub(crate) fn get_plural_names(
 }
 
 /// Internal helper function that sends a request over the callback channel and returns the
-/// response
-fn make_request_via_callback_channel(
+/// response. Kubernetes requests are made impersonating the given Service Account, when set
+pub(crate) fn make_request_via_callback_channel(
     request_type: CallbackRequestType,
     callback_channel: &mpsc::Sender<CallbackRequest>,
+    kubernetes_service_account: Option<&KubernetesServiceAccount>,
 ) -> Result<CallbackResponse> {
+    capability_usage::record_call(&request_type);
+    evaluation_cache::record_host_call();
     let (tx, rx) = oneshot::channel::<std::result::Result<CallbackResponse, wasmtime::Error>>();
     let req = CallbackRequest {
         request: request_type,
         response_channel: tx,
+        kubernetes_service_account: kubernetes_service_account.cloned(),
     };
     callback_channel
         .try_send(req)
@@ -139,6 +233,7 @@ fn make_request_via_callback_channel(
 #[cfg(test)]
 pub(crate) mod tests {
     use super::*;
+    use crate::test_utils::ScriptedCallbackHandler;
     use anyhow::{anyhow, Result};
     use assert_json_diff::assert_json_eq;
     use rstest::rstest;
@@ -173,61 +268,42 @@ pub(crate) mod tests {
 
     #[tokio::test(flavor = "multi_thread")]
     async fn get_all_resources_success() {
-        let (callback_tx, mut callback_rx) = mpsc::channel::<CallbackRequest>(10);
         let resource = ContextAwareResource {
             api_version: "v1".to_string(),
             kind: "Service".to_string(),
         };
-        let expected_resource = resource.clone();
         let services = [
             dynamic_object_from_fixture("services", Some("kube-system"), "kube-dns").unwrap(),
             dynamic_object_from_fixture("services", Some("kube-system"), "metrics-server").unwrap(),
         ];
         let services_list = object_list_from_dynamic_objects(&services).unwrap();
 
-        tokio::spawn(async move {
-            let req = match callback_rx.recv().await {
-                Some(r) => r,
-                None => return,
-            };
-            match req.request {
+        let (callback_tx, handler) = ScriptedCallbackHandler::new()
+            .respond_to(
                 CallbackRequestType::KubernetesListResourceAll {
-                    api_version,
-                    kind,
-                    label_selector,
-                    field_selector,
-                } => {
-                    assert_eq!(api_version, expected_resource.api_version);
-                    assert_eq!(kind, expected_resource.kind);
-                    assert!(label_selector.is_none());
-                    assert!(field_selector.is_none());
-                }
-                _ => {
-                    panic!("not the expected request type");
-                }
-            };
-
-            let services_list = object_list_from_dynamic_objects(&services).unwrap();
-            let callback_response = CallbackResponse {
-                payload: serde_json::to_vec(&services_list).unwrap(),
-            };
-
-            req.response_channel.send(Ok(callback_response)).unwrap();
-        });
+                    api_version: resource.api_version.clone(),
+                    kind: resource.kind.clone(),
+                    label_selector: None,
+                    field_selector: None,
+                },
+                &services_list,
+            )
+            .spawn();
 
         tokio::task::spawn_blocking(move || {
-            let actual = get_all_resources_by_type(&callback_tx, &resource).unwrap();
+            let actual = get_all_resources_by_type(&callback_tx, &resource, None).unwrap();
             let actual_json = serde_json::to_value(actual).unwrap();
             let expected_json = serde_json::to_value(services_list).unwrap();
             assert_json_eq!(actual_json, expected_json);
         })
         .await
         .unwrap();
+
+        handler.await.unwrap().assert_completed();
     }
 
     #[tokio::test(flavor = "multi_thread")]
     async fn get_resource_plural_name_success() {
-        let (callback_tx, mut callback_rx) = mpsc::channel::<CallbackRequest>(10);
         let resource = ContextAwareResource {
             api_version: "v1".to_string(),
             kind: "Service".to_string(),
@@ -240,37 +316,26 @@ pub(crate) mod tests {
         let mut expected_names: BTreeMap<ContextAwareResource, String> = BTreeMap::new();
         expected_names.insert(resource.clone(), plural_name.to_string());
 
-        let expected_resource = resource.clone();
-
-        tokio::spawn(async move {
-            let req = match callback_rx.recv().await {
-                Some(r) => r,
-                None => return,
-            };
-            match req.request {
-                CallbackRequestType::KubernetesGetResourcePluralName { api_version, kind } => {
-                    assert_eq!(api_version, expected_resource.api_version);
-                    assert_eq!(kind, expected_resource.kind);
-                }
-                _ => {
-                    panic!("not the expected request type");
-                }
-            };
-
-            let callback_response = CallbackResponse {
-                payload: serde_json::to_vec(&plural_name).unwrap(),
-            };
-
-            req.response_channel.send(Ok(callback_response)).unwrap();
-        });
+        let (callback_tx, handler) = ScriptedCallbackHandler::new()
+            .respond_to(
+                CallbackRequestType::KubernetesGetResourcePluralName {
+                    api_version: resource.api_version,
+                    kind: resource.kind,
+                },
+                &plural_name,
+            )
+            .spawn();
 
         tokio::task::spawn_blocking(move || {
-            let actual = get_plural_names(&callback_tx, &resources).unwrap();
+            let actual = get_plural_names(&callback_tx, &resources, None).unwrap();
             assert_eq!(actual, expected_names);
         })
         .await
         .unwrap();
+
+        handler.await.unwrap().assert_completed();
     }
+
     #[rstest]
     #[case(
         HashMap::<ContextAwareResource, bool>::from([(ContextAwareResource{api_version: "v1".to_string(), kind: "Service".to_string()}, true)]),
@@ -299,52 +364,36 @@ pub(crate) mod tests {
         #[case] resources_with_change_status: HashMap<ContextAwareResource, bool>,
         #[case] expected: bool,
     ) {
-        let (callback_tx, mut callback_rx) = mpsc::channel::<CallbackRequest>(10);
         let since = tokio::time::Instant::now();
-        let expected_resources_with_change_status = resources_with_change_status.clone();
-
-        tokio::spawn(async move {
-            loop {
-                let req = match callback_rx.recv().await {
-                    Some(r) => r,
-                    None => return,
-                };
-                let changed = match req.request {
-                CallbackRequestType::HasKubernetesListResourceAllResultChangedSinceInstant {
-                    api_version,
-                    kind,
-                    label_selector,
-                    field_selector,
-                    since: _,
-                } => {
-                    let resource = ContextAwareResource {
-                        api_version: api_version.clone(),
-                        kind: kind.clone(),
-                    };
-                    assert!(label_selector.is_none());
-                    assert!(field_selector.is_none());
-
-                    expected_resources_with_change_status
-                        .get(&resource)
-                        .expect("cannot find resource")
-                }
-                _ => {
-                    panic!("not the expected request type");
-                }
-            };
-
-                let callback_response = CallbackResponse {
-                    payload: serde_json::to_vec(&changed).unwrap(),
-                };
 
-                req.response_channel.send(Ok(callback_response)).unwrap();
-            }
-        });
+        let mut handler = ScriptedCallbackHandler::new();
+        for (resource, changed) in &resources_with_change_status {
+            let resource = resource.clone();
+            handler = handler.respond_to_matching(
+                &format!("{resource:?}"),
+                move |request| {
+                    matches!(
+                        request,
+                        CallbackRequestType::HasKubernetesListResourceAllResultChangedSinceInstant {
+                            api_version,
+                            kind,
+                            label_selector: None,
+                            field_selector: None,
+                            ..
+                        } if *api_version == resource.api_version && *kind == resource.kind
+                    )
+                },
+                changed,
+            );
+        }
+        // The script is not necessarily completed: the lookup stops as soon as one of
+        // the resources has changed
+        let (callback_tx, _handler) = handler.spawn();
 
         tokio::task::spawn_blocking(move || {
             let resources = resources_with_change_status.keys().cloned().collect();
             let actual =
-                have_allowed_resources_changed_since_instant(&callback_tx, &resources, since)
+                have_allowed_resources_changed_since_instant(&callback_tx, &resources, since, None)
                     .unwrap();
             assert_json_eq!(expected, actual);
         })
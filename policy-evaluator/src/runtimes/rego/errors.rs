@@ -25,6 +25,9 @@ pub enum RegoRuntimeError {
     #[error("get plural name failure, cannot convert callback response: {0}")]
     CallbackGetPluralName(#[source] serde_json::Error),
 
+    #[error("cannot convert callback response into a subscription token: {0}")]
+    CallbackConvertSubscriptionToken(#[source] serde_json::Error),
+
     #[error("DynamicObject does not have a name")]
     GatekeeperInventoryMissingName,
 
@@ -55,9 +58,41 @@ pub enum RegoRuntimeError {
     #[error("invalid response from policy: {0}")]
     InvalidResponseWithError(#[source] serde_json::Error),
 
+    #[error("invalid mutation of {location}: {message}")]
+    InvalidMutation { location: String, message: String },
+
+    #[error("invalid mutation: {0}")]
+    InvalidMutationWithError(#[source] serde_json::Error),
+
+    #[error("invalid patch returned by the policy: {0}")]
+    InvalidPatch(String),
+
     #[error("cannot allocate Rego evaluator: {0}")]
     EvaluatorError(String),
 
     #[error("cannot build Rego engine: {0}")]
     RegoEngineBuilder(#[source] burrego::errors::BurregoError),
+
+    #[error(
+        "the policy input does not fit into the memory limit of {memory_limit} bytes: \
+        estimated usage is {estimated_size} bytes, the request is {request_size} bytes, \
+        the Kubernetes context is {context_size} bytes{resources}"
+    )]
+    MemoryLimitExceeded {
+        memory_limit: u64,
+        estimated_size: u64,
+        request_size: usize,
+        context_size: usize,
+        /// The resources of the Kubernetes context, the biggest ones first
+        resources: String,
+    },
+
+    #[error("cannot find entrypoint {entrypoint}, available entrypoints: {}", .available.join(", "))]
+    EntrypointNotFound {
+        entrypoint: String,
+        available: Vec<String>,
+    },
+
+    #[error("invalid Rego library linked at `{path}`: {message}")]
+    InvalidRegoLibrary { path: String, message: String },
 }
@@ -5,12 +5,17 @@ use std::{
     sync::{Arc, RwLock},
 };
 use tokio::{sync::mpsc, time::Instant};
+use tracing::debug;
 
 use crate::runtimes::rego::context_aware::{
     get_allowed_resources, have_allowed_resources_changed_since_instant,
+    have_subscribed_resources_changed, resource_sizes, subscribe_to_allowed_resources_changes,
+    ResourceSizes,
 };
 use crate::{
+    callback_handler::subscriptions::SubscriptionToken,
     callback_requests::CallbackRequest,
+    evaluation_context::KubernetesServiceAccount,
     policy_metadata::ContextAwareResource,
     runtimes::rego::{
         errors::{RegoRuntimeError, Result},
@@ -33,6 +38,11 @@ pub(crate) struct CachedInventory {
     pub data: Vec<u8>,
     /// The instant when the inventory was last computed. This is used to invalidate the cache
     pub cache_time: Instant,
+    /// The size of the serialized resources, grouped by their type
+    pub resource_sizes: ResourceSizes,
+    /// The subscriptions notified of the changes of the resources. When `None`, the
+    /// changes are checked by making a request over the callback channel
+    pub subscriptions: Option<Vec<SubscriptionToken>>,
 }
 
 /// This defines how Gatekeeper policy expects the `input` attribute to be structured.
@@ -42,13 +52,21 @@ struct GatekeeperInput {
     inventory: GatekeeperInventory,
 }
 
+/// The list of resources an inventory is allowed to access, together with the
+/// Service Account impersonated to fetch them
+type InventoryKey = (
+    BTreeSet<ContextAwareResource>,
+    Option<KubernetesServiceAccount>,
+);
+
 /// Hold all the inventories for the Gatekeeper runtime
 ///
 /// The inventories are stored inside of a dictionary that has the list of resources
-/// the inventory is allowed to access as key. The value is the serialized inventory.
+/// the inventory is allowed to access, and the Service Account used to fetch them, as key.
+/// The value is the serialized inventory.
 ///
-/// Two different policies that access the same set of resources will share the same
-/// inventory.
+/// Two different policies that access the same set of resources with the same
+/// Service Account will share the same inventory.
 /// However, two policies sharing an overlapping set of resources will have different
 /// inventories, leading to some duplication of information.
 /// Unfortunately there's nothing we can do to prevent that. We need to keep in cache
@@ -56,7 +74,7 @@ struct GatekeeperInput {
 pub(crate) struct GateKeeperInventoryCache {
     // Note: the Arc is used to make some `clone` invocation faster. The `clone` operations
     // are required because the whole `inventories` variable is located inside of a RwLock
-    inventories: RwLock<HashMap<BTreeSet<ContextAwareResource>, Arc<CachedInventory>>>,
+    inventories: RwLock<HashMap<InventoryKey, Arc<CachedInventory>>>,
 }
 
 impl GateKeeperInventoryCache {
@@ -69,42 +87,103 @@ impl GateKeeperInventoryCache {
     /// This function returns the serialized inventory for the given set of resources.
     /// The inventory is computed and serialized only if it's not already present in the cache.
     /// The inventory is also recreated if the set of resources has changed since the time
-    /// the inventory was computed
+    /// the inventory was computed. The changes are notified to the cache by the reflectors
+    /// tracking the resources, falling back to asking the callback handler when the
+    /// subscription to the changes is not possible
+    #[cfg(test)]
     pub fn get_inventory(
         &self,
         callback_channel: &mpsc::Sender<CallbackRequest>,
         ctx_aware_resources: &BTreeSet<ContextAwareResource>,
+        kubernetes_service_account: Option<&KubernetesServiceAccount>,
     ) -> Result<Vec<u8>> {
+        self.get_cached_inventory(
+            callback_channel,
+            ctx_aware_resources,
+            kubernetes_service_account,
+        )
+        .map(|inventory| inventory.data.clone())
+    }
+
+    /// Like `get_inventory`, but returns the whole cache entry
+    pub fn get_cached_inventory(
+        &self,
+        callback_channel: &mpsc::Sender<CallbackRequest>,
+        ctx_aware_resources: &BTreeSet<ContextAwareResource>,
+        kubernetes_service_account: Option<&KubernetesServiceAccount>,
+    ) -> Result<Arc<CachedInventory>> {
+        let key = (
+            ctx_aware_resources.to_owned(),
+            kubernetes_service_account.cloned(),
+        );
         let inventory = {
             let inventories = self.inventories.read().unwrap();
-            inventories.get(ctx_aware_resources).cloned()
+            inventories.get(&key).cloned()
         };
         let inventory = match inventory {
-            None => self.create_and_register_inventory(ctx_aware_resources, callback_channel),
+            None => self.create_and_register_inventory(key, callback_channel, None),
             Some(cached_inventory) => {
-                if have_allowed_resources_changed_since_instant(
-                    callback_channel,
-                    ctx_aware_resources,
-                    cached_inventory.cache_time,
-                )? {
-                    self.create_and_register_inventory(ctx_aware_resources, callback_channel)
+                let notified_changes = cached_inventory
+                    .subscriptions
+                    .as_deref()
+                    .and_then(have_subscribed_resources_changed);
+                let changed = match notified_changes {
+                    Some(changed) => changed,
+                    None => have_allowed_resources_changed_since_instant(
+                        callback_channel,
+                        ctx_aware_resources,
+                        cached_inventory.cache_time,
+                        kubernetes_service_account,
+                    )?,
+                };
+                if changed {
+                    self.create_and_register_inventory(
+                        key,
+                        callback_channel,
+                        cached_inventory.subscriptions.clone(),
+                    )
                 } else {
                     Ok(cached_inventory)
                 }
             }
         }?;
-        Ok(inventory.data.clone())
+        Ok(inventory)
     }
 
     /// Create the inventory and register it in the cache. A prior entry of the inventory is
     /// automatically removed from the cache.
+    ///
+    /// The subscriptions of the prior entry are kept, otherwise the inventory subscribes to the
+    /// changes of its resources before fetching them: a change happening in between causes
+    /// the inventory to be recreated on the next evaluation, not to be missed.
     fn create_and_register_inventory(
         &self,
-        ctx_aware_resources: &BTreeSet<ContextAwareResource>,
+        key: InventoryKey,
         callback_channel: &mpsc::Sender<CallbackRequest>,
+        subscriptions: Option<Vec<SubscriptionToken>>,
     ) -> Result<Arc<CachedInventory>> {
+        let (ctx_aware_resources, kubernetes_service_account) = &key;
+        let subscriptions = subscriptions.or_else(|| {
+            subscribe_to_allowed_resources_changes(
+                callback_channel,
+                ctx_aware_resources,
+                kubernetes_service_account.as_ref(),
+            )
+            .map_err(|error| {
+                debug!(
+                    %error,
+                    "cannot subscribe to the changes of the resources, they will be polled"
+                )
+            })
+            .ok()
+        });
+
         let now = Instant::now();
-        let cluster_resources = get_allowed_resources(callback_channel, ctx_aware_resources)?;
+        let cluster_resources = get_allowed_resources(
+            callback_channel,
+            ctx_aware_resources,
+            kubernetes_service_account.as_ref(),
+        )?;
         let inventory = GatekeeperInput {
             inventory: GatekeeperInventory::new(&cluster_resources)?,
         };
@@ -112,12 +191,14 @@ impl GateKeeperInventoryCache {
             data: serde_json::to_vec(&inventory)
                 .map_err(RegoRuntimeError::GatekeeperInventorySerializationError)?,
             cache_time: now,
+            resource_sizes: resource_sizes(&cluster_resources),
+            subscriptions,
         });
 
         self.inventories
             .write()
             .unwrap()
-            .insert(ctx_aware_resources.to_owned(), cached_inventory.clone());
+            .insert(key, cached_inventory.clone());
         Ok(cached_inventory)
     }
 }
@@ -125,9 +206,12 @@ impl GateKeeperInventoryCache {
 #[cfg(test)]
 pub(crate) mod tests {
     use super::*;
+    use crate::callback_handler::subscriptions;
     use crate::callback_requests::{CallbackRequestType, CallbackResponse};
     use serial_test::serial;
     use std::collections::BTreeMap;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use tokio::sync::watch;
 
     use crate::runtimes::rego::context_aware::tests::{
         dynamic_object_from_fixture, object_list_from_dynamic_objects,
@@ -149,6 +233,7 @@ pub(crate) mod tests {
         let services_list = object_list_from_dynamic_objects(&services).unwrap();
         let kube_resources = BTreeMap::from([(resource.clone(), services_list.clone())]);
         let expected_inventory = GatekeeperInventory::new(&kube_resources).unwrap();
+        let (_changes_tx, changes_rx) = watch::channel(Instant::now());
 
         tokio::spawn(async move {
             loop {
@@ -171,6 +256,18 @@ pub(crate) mod tests {
                             payload: serde_json::to_vec(&services_list).unwrap(),
                         }
                     }
+                    CallbackRequestType::SubscribeKubernetesListResourceAllChanges {
+                        api_version,
+                        kind,
+                        ..
+                    } => {
+                        assert_eq!(api_version, expected_resource.api_version);
+                        assert_eq!(kind, expected_resource.kind);
+                        let token = subscriptions::subscribe(changes_rx.clone());
+                        CallbackResponse {
+                            payload: serde_json::to_vec(&token).unwrap(),
+                        }
+                    }
                     _ => {
                         panic!("not the expected request type");
                     }
@@ -190,18 +287,22 @@ pub(crate) mod tests {
             let resources: BTreeSet<ContextAwareResource> = BTreeSet::from([resource]);
 
             let cached_inventory = GATEKEEPER_INVENTORY_CACHE
-                .get_inventory(&callback_tx, &resources)
+                .get_inventory(&callback_tx, &resources, None)
                 .unwrap();
             assert!(!cached_inventory.is_empty());
 
             {
                 let inventories = GATEKEEPER_INVENTORY_CACHE.inventories.read().unwrap();
-                let cached_input_json = inventories.get(&resources).unwrap();
+                let cached_input_json = inventories.get(&(resources.clone(), None)).unwrap();
                 let actual_inventory =
                     serde_json::from_slice::<GatekeeperInput>(&cached_input_json.data)
                         .unwrap()
                         .inventory;
                 assert_eq!(expected_inventory, actual_inventory);
+                assert_eq!(
+                    cached_input_json.subscriptions.as_ref().map(Vec::len),
+                    Some(1)
+                );
             }
         })
         .await
@@ -225,11 +326,13 @@ pub(crate) mod tests {
             cache_time: Instant::now()
                 .checked_sub(tokio::time::Duration::from_secs(60))
                 .unwrap(),
+            resource_sizes: ResourceSizes::new(),
+            subscriptions: None,
         };
         {
             let mut inventories = GATEKEEPER_INVENTORY_CACHE.inventories.write().unwrap();
             inventories.insert(
-                resources.clone(),
+                (resources.clone(), None),
                 Arc::new(expected_cached_inventory.clone()),
             );
         }
@@ -268,7 +371,7 @@ pub(crate) mod tests {
 
         tokio::task::spawn_blocking(move || {
             let actual = GATEKEEPER_INVENTORY_CACHE
-                .get_inventory(&callback_tx, &resources)
+                .get_inventory(&callback_tx, &resources, None)
                 .unwrap();
             assert_eq!(expected_cached_inventory.data, actual);
         })
@@ -302,11 +405,16 @@ pub(crate) mod tests {
             cache_time: Instant::now()
                 .checked_sub(tokio::time::Duration::from_secs(60))
                 .unwrap(),
+            resource_sizes: ResourceSizes::new(),
+            subscriptions: None,
         };
 
         {
             let mut inventories = GATEKEEPER_INVENTORY_CACHE.inventories.write().unwrap();
-            inventories.insert(resources.clone(), Arc::new(stale_cached_inventory.clone()));
+            inventories.insert(
+                (resources.clone(), None),
+                Arc::new(stale_cached_inventory.clone()),
+            );
         }
 
         tokio::spawn(async move {
@@ -346,6 +454,13 @@ pub(crate) mod tests {
                             payload: serde_json::to_vec(&true).unwrap(),
                         }
                     }
+                    CallbackRequestType::SubscribeKubernetesListResourceAllChanges { .. } => {
+                        // the changes keep being polled
+                        req.response_channel
+                            .send(Err(anyhow::anyhow!("subscriptions are not supported")))
+                            .unwrap();
+                        continue;
+                    }
                     _ => {
                         panic!("not the expected request type");
                     }
@@ -357,7 +472,7 @@ pub(crate) mod tests {
 
         tokio::task::spawn_blocking(move || {
             let actual = GATEKEEPER_INVENTORY_CACHE
-                .get_inventory(&callback_tx, &resources)
+                .get_inventory(&callback_tx, &resources, None)
                 .unwrap();
             assert!(actual != stale_cached_inventory.data);
             let actual_inventory = serde_json::from_slice::<GatekeeperInput>(&actual).unwrap();
@@ -365,17 +480,93 @@ pub(crate) mod tests {
 
             {
                 let inventories = GATEKEEPER_INVENTORY_CACHE.inventories.read().unwrap();
-                let actual_inventory = inventories.get(&resources).unwrap();
+                let actual_inventory = inventories.get(&(resources.clone(), None)).unwrap();
                 assert!(actual_inventory.cache_time > stale_cached_inventory.cache_time);
             }
 
             {
                 let inventories = GATEKEEPER_INVENTORY_CACHE.inventories.read().unwrap();
-                let actual_inventory = inventories.get(&resources).unwrap();
+                let actual_inventory = inventories.get(&(resources.clone(), None)).unwrap();
                 assert!(actual_inventory.cache_time > stale_cached_inventory.cache_time);
+                assert!(actual_inventory.subscriptions.is_none());
             }
         })
         .await
         .unwrap();
     }
+
+    #[tokio::test(flavor = "multi_thread")]
+    #[serial]
+    async fn test_cached_entry_invalidated_by_notification() {
+        let (callback_tx, mut callback_rx) = mpsc::channel::<CallbackRequest>(10);
+        let resource = ContextAwareResource {
+            api_version: "v1".to_string(),
+            kind: "Service".to_string(),
+        };
+        let resources: BTreeSet<ContextAwareResource> = BTreeSet::from([resource]);
+
+        let services =
+            [dynamic_object_from_fixture("services", Some("kube-system"), "kube-dns").unwrap()];
+        let services_list = object_list_from_dynamic_objects(&services).unwrap();
+        let (changes_tx, changes_rx) = watch::channel(Instant::now());
+        let list_requests = Arc::new(AtomicUsize::new(0));
+        let subscribe_requests = Arc::new(AtomicUsize::new(0));
+
+        let handler_list_requests = list_requests.clone();
+        let handler_subscribe_requests = subscribe_requests.clone();
+        tokio::spawn(async move {
+            while let Some(req) = callback_rx.recv().await {
+                let payload = match req.request {
+                    CallbackRequestType::KubernetesListResourceAll { .. } => {
+                        handler_list_requests.fetch_add(1, Ordering::SeqCst);
+                        serde_json::to_vec(&services_list).unwrap()
+                    }
+                    CallbackRequestType::SubscribeKubernetesListResourceAllChanges { .. } => {
+                        handler_subscribe_requests.fetch_add(1, Ordering::SeqCst);
+                        serde_json::to_vec(&subscriptions::subscribe(changes_rx.clone())).unwrap()
+                    }
+                    _ => {
+                        panic!("not the expected request type");
+                    }
+                };
+
+                req.response_channel
+                    .send(Ok(CallbackResponse { payload }))
+                    .unwrap();
+            }
+        });
+
+        tokio::task::spawn_blocking(move || {
+            {
+                // ensure the cache is empty
+                let mut inventories = GATEKEEPER_INVENTORY_CACHE.inventories.write().unwrap();
+                inventories.clear();
+            }
+
+            let first = GATEKEEPER_INVENTORY_CACHE
+                .get_cached_inventory(&callback_tx, &resources, None)
+                .unwrap();
+            assert_eq!(list_requests.load(Ordering::SeqCst), 1);
+
+            // nothing changed, the changes are not polled
+            let cached = GATEKEEPER_INVENTORY_CACHE
+                .get_cached_inventory(&callback_tx, &resources, None)
+                .unwrap();
+            assert!(Arc::ptr_eq(&first, &cached));
+            assert_eq!(list_requests.load(Ordering::SeqCst), 1);
+
+            changes_tx.send(Instant::now()).unwrap();
+            let rebuilt = GATEKEEPER_INVENTORY_CACHE
+                .get_cached_inventory(&callback_tx, &resources, None)
+                .unwrap();
+            assert!(!Arc::ptr_eq(&first, &rebuilt));
+            assert_eq!(list_requests.load(Ordering::SeqCst), 2);
+
+            // the subscription is kept by the rebuilt inventory
+            assert_eq!(rebuilt.subscriptions, first.subscriptions);
+            assert_eq!(subscribe_requests.load(Ordering::SeqCst), 1);
+        })
+        .await
+        .unwrap();
+    }
 }
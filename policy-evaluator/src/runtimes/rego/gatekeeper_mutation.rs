@@ -0,0 +1,409 @@
+/// Mutations emitted by Gatekeeper policies.
+///
+/// Gatekeeper policies report the violations found inside of the request with
+/// objects like `{"msg": "..."}`. Kubewarden extends this contract: the policy
+/// can also return objects with a `mutation` key, which describe how the
+/// object of the request has to be changed. The mutations mimic the `Assign`
+/// and `AssignMetadata` Custom Resources of Gatekeeper:
+///
+/// ```rego
+/// violation[{"mutation": mutation}] {
+///   mutation := {
+///     "type": "Assign",
+///     "location": "spec.containers[name: *].imagePullPolicy",
+///     "value": "Always",
+///   }
+/// }
+///
+/// violation[{"mutation": mutation}] {
+///   mutation := {
+///     "type": "AssignMetadata",
+///     "location": "metadata.labels.owner",
+///     "value": "team-a",
+///   }
+/// }
+/// ```
+///
+/// Like with Gatekeeper:
+/// * `Assign` sets the value at the given location, creating the missing
+///   objects and list items along the way. It cannot change the metadata
+///   of the object
+/// * `AssignMetadata` can only add labels and annotations, existing ones
+///   are never changed
+///
+/// List items are selected by the value of one of their keys, `[name: nginx]`,
+/// or all the items having the key are selected with `[name: *]`.
+use serde::Deserialize;
+use serde_json::{Map, Value};
+
+use crate::runtimes::rego::errors::{RegoRuntimeError, Result};
+
+#[derive(Debug, Clone, Deserialize, PartialEq)]
+#[serde(tag = "type")]
+pub(crate) enum Mutation {
+    Assign { location: String, value: Value },
+    AssignMetadata { location: String, value: Value },
+}
+
+impl Mutation {
+    /// Apply the mutation to the given object
+    pub(crate) fn apply(&self, object: &mut Value) -> Result<()> {
+        match self {
+            Mutation::Assign { location, value } => {
+                let path = parse_location(location)?;
+                if matches!(path.first(), Some(Segment::Key(key)) if key == "metadata") {
+                    return Err(invalid_mutation(
+                        location,
+                        "Assign cannot change the metadata, use AssignMetadata instead",
+                    ));
+                }
+                assign(object, &path, value, location)
+            }
+            Mutation::AssignMetadata { location, value } => {
+                let path = parse_location(location)?;
+                let field = match path.as_slice() {
+                    [Segment::Key(metadata), Segment::Key(field), Segment::Key(_)]
+                        if metadata == "metadata"
+                            && (field == "labels" || field == "annotations") =>
+                    {
+                        field
+                    }
+                    _ => return Err(invalid_mutation(
+                        location,
+                        "AssignMetadata can only change metadata.labels and metadata.annotations",
+                    )),
+                };
+                if !value.is_string() {
+                    return Err(invalid_mutation(
+                        location,
+                        format!("the value of {field} must be a string"),
+                    ));
+                }
+
+                let target = value_at(object, &path[..2], location)?;
+                if let Segment::Key(key) = &path[2] {
+                    let target = as_object(target, location)?;
+                    target.entry(key.clone()).or_insert_with(|| value.clone());
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Segment {
+    /// A key of an object
+    Key(String),
+    /// The items of a list whose `key` has the given value. All the items
+    /// having the `key` are selected when the value is `None`
+    ListItem { key: String, value: Option<String> },
+}
+
+fn invalid_mutation(location: &str, message: impl Into<String>) -> RegoRuntimeError {
+    RegoRuntimeError::InvalidMutation {
+        location: location.to_string(),
+        message: message.into(),
+    }
+}
+
+/// Parse a location like `spec.containers[name: *].image`
+fn parse_location(location: &str) -> Result<Vec<Segment>> {
+    let mut segments = Vec::new();
+    let mut rest = location;
+
+    while !rest.is_empty() {
+        let end = rest.find(['.', '[']).unwrap_or(rest.len());
+        let key = &rest[..end];
+        if key.is_empty() {
+            return Err(invalid_mutation(location, "empty key"));
+        }
+        segments.push(Segment::Key(key.to_string()));
+        rest = &rest[end..];
+
+        if let Some(selector) = rest.strip_prefix('[') {
+            let close = selector
+                .find(']')
+                .ok_or_else(|| invalid_mutation(location, "unterminated list selector"))?;
+            let (key, value) = selector[..close]
+                .split_once(':')
+                .ok_or_else(|| invalid_mutation(location, "list selectors must be [key: value]"))?;
+            let (key, value) = (key.trim(), value.trim());
+            if key.is_empty() || value.is_empty() {
+                return Err(invalid_mutation(
+                    location,
+                    "list selectors must be [key: value]",
+                ));
+            }
+            segments.push(Segment::ListItem {
+                key: key.to_string(),
+                value: (value != "*").then(|| value.to_string()),
+            });
+            rest = &selector[close + 1..];
+        }
+
+        if let Some(next) = rest.strip_prefix('.') {
+            if next.is_empty() {
+                return Err(invalid_mutation(location, "empty key"));
+            }
+            rest = next;
+        } else if !rest.is_empty() {
+            return Err(invalid_mutation(location, "unexpected characters"));
+        }
+    }
+
+    if segments.is_empty() {
+        return Err(invalid_mutation(location, "empty location"));
+    }
+    if matches!(segments.last(), Some(Segment::ListItem { .. })) {
+        return Err(invalid_mutation(
+            location,
+            "the location cannot end with a list selector",
+        ));
+    }
+
+    Ok(segments)
+}
+
+fn as_object<'a>(value: &'a mut Value, location: &str) -> Result<&'a mut Map<String, Value>> {
+    if value.is_null() {
+        *value = Value::Object(Map::new());
+    }
+    value.as_object_mut().ok_or_else(|| {
+        invalid_mutation(
+            location,
+            "the location crosses a value that is not an object",
+        )
+    })
+}
+
+/// Walk the given path, creating the missing objects
+fn value_at<'a>(object: &'a mut Value, path: &[Segment], location: &str) -> Result<&'a mut Value> {
+    let mut current = object;
+    for segment in path {
+        current = match segment {
+            Segment::Key(key) => as_object(current, location)?
+                .entry(key.clone())
+                .or_insert(Value::Null),
+            Segment::ListItem { .. } => {
+                return Err(invalid_mutation(location, "unexpected list selector"))
+            }
+        };
+    }
+    Ok(current)
+}
+
+fn assign(object: &mut Value, path: &[Segment], value: &Value, location: &str) -> Result<()> {
+    match path {
+        [] => {
+            *object = value.clone();
+            Ok(())
+        }
+        [Segment::Key(key), rest @ ..] => {
+            let child = as_object(object, location)?
+                .entry(key.clone())
+                .or_insert(Value::Null);
+            assign(child, rest, value, location)
+        }
+        [Segment::ListItem {
+            key,
+            value: selector,
+        }, rest @ ..] => {
+            if object.is_null() {
+                *object = Value::Array(Vec::new());
+            }
+            let items = object.as_array_mut().ok_or_else(|| {
+                invalid_mutation(location, "list selector used on a value that is not a list")
+            })?;
+
+            match selector {
+                // all the items having the key
+                None => {
+                    for item in items
+                        .iter_mut()
+                        .filter(|item| item.get(key.as_str()).is_some())
+                    {
+                        assign(item, rest, value, location)?;
+                    }
+                }
+                // the item with the given key, which is created when missing
+                Some(selector) => {
+                    let position = items.iter().position(|item| {
+                        item.get(key.as_str()).and_then(Value::as_str) == Some(selector.as_str())
+                    });
+                    let item = match position {
+                        Some(position) => &mut items[position],
+                        None => {
+                            let mut item = Map::new();
+                            item.insert(key.clone(), Value::String(selector.clone()));
+                            items.push(Value::Object(item));
+                            items.last_mut().expect("an item has just been added")
+                        }
+                    };
+                    assign(item, rest, value, location)?;
+                }
+            }
+            Ok(())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rstest::rstest;
+    use serde_json::json;
+
+    fn pod() -> Value {
+        json!({
+            "apiVersion": "v1",
+            "kind": "Pod",
+            "metadata": {
+                "name": "nginx",
+                "labels": {"owner": "team-b"}
+            },
+            "spec": {
+                "containers": [
+                    {"name": "nginx", "image": "nginx"},
+                    {"name": "sidecar", "image": "envoy", "imagePullPolicy": "IfNotPresent"}
+                ]
+            }
+        })
+    }
+
+    #[rstest]
+    #[case::simple(
+        "spec.containers",
+        Ok(vec![Segment::Key("spec".to_string()), Segment::Key("containers".to_string())])
+    )]
+    #[case::glob(
+        "spec.containers[name: *].image",
+        Ok(vec![
+            Segment::Key("spec".to_string()),
+            Segment::Key("containers".to_string()),
+            Segment::ListItem { key: "name".to_string(), value: None },
+            Segment::Key("image".to_string()),
+        ])
+    )]
+    #[case::selector(
+        "spec.containers[name:nginx].image",
+        Ok(vec![
+            Segment::Key("spec".to_string()),
+            Segment::Key("containers".to_string()),
+            Segment::ListItem { key: "name".to_string(), value: Some("nginx".to_string()) },
+            Segment::Key("image".to_string()),
+        ])
+    )]
+    #[case::empty("", Err(()))]
+    #[case::empty_key("spec..containers", Err(()))]
+    #[case::trailing_dot("spec.", Err(()))]
+    #[case::unterminated_selector("spec.containers[name: *", Err(()))]
+    #[case::invalid_selector("spec.containers[name].image", Err(()))]
+    #[case::ends_with_selector("spec.containers[name: *]", Err(()))]
+    fn parse_locations(
+        #[case] location: &str,
+        #[case] expected: std::result::Result<Vec<Segment>, ()>,
+    ) {
+        assert_eq!(parse_location(location).map_err(|_| ()), expected);
+    }
+
+    #[test]
+    fn assign_to_all_list_items() {
+        let mut object = pod();
+        Mutation::Assign {
+            location: "spec.containers[name: *].imagePullPolicy".to_string(),
+            value: json!("Always"),
+        }
+        .apply(&mut object)
+        .unwrap();
+
+        assert_eq!(
+            object["spec"]["containers"],
+            json!([
+                {"name": "nginx", "image": "nginx", "imagePullPolicy": "Always"},
+                {"name": "sidecar", "image": "envoy", "imagePullPolicy": "Always"}
+            ])
+        );
+    }
+
+    #[test]
+    fn assign_creates_missing_values() {
+        let mut object = pod();
+        Mutation::Assign {
+            location: "spec.containers[name: nginx].securityContext.runAsNonRoot".to_string(),
+            value: json!(true),
+        }
+        .apply(&mut object)
+        .unwrap();
+        Mutation::Assign {
+            location: "spec.initContainers[name: init].image".to_string(),
+            value: json!("busybox"),
+        }
+        .apply(&mut object)
+        .unwrap();
+
+        assert_eq!(
+            object["spec"]["containers"][0]["securityContext"],
+            json!({"runAsNonRoot": true})
+        );
+        assert_eq!(
+            object["spec"]["initContainers"],
+            json!([{"name": "init", "image": "busybox"}])
+        );
+    }
+
+    #[test]
+    fn assign_cannot_change_metadata() {
+        let mut object = pod();
+        let result = Mutation::Assign {
+            location: "metadata.labels.owner".to_string(),
+            value: json!("team-a"),
+        }
+        .apply(&mut object);
+
+        assert!(result.is_err());
+        assert_eq!(object, pod());
+    }
+
+    #[test]
+    fn assign_metadata_only_adds_values() {
+        let mut object = pod();
+        for (location, value) in [
+            ("metadata.labels.owner", "team-a"),
+            ("metadata.labels.tier", "frontend"),
+            ("metadata.annotations.reviewed", "true"),
+        ] {
+            Mutation::AssignMetadata {
+                location: location.to_string(),
+                value: json!(value),
+            }
+            .apply(&mut object)
+            .unwrap();
+        }
+
+        assert_eq!(
+            object["metadata"]["labels"],
+            json!({"owner": "team-b", "tier": "frontend"})
+        );
+        assert_eq!(
+            object["metadata"]["annotations"],
+            json!({"reviewed": "true"})
+        );
+    }
+
+    #[rstest]
+    #[case::not_metadata("spec.nodeName", json!("node"))]
+    #[case::name("metadata.name", json!("other"))]
+    #[case::nested("metadata.labels.owner.name", json!("team-a"))]
+    #[case::not_a_string("metadata.labels.replicas", json!(3))]
+    fn invalid_assign_metadata(#[case] location: &str, #[case] value: Value) {
+        let mut object = pod();
+        let result = Mutation::AssignMetadata {
+            location: location.to_string(),
+            value,
+        }
+        .apply(&mut object);
+
+        assert!(result.is_err());
+    }
+}
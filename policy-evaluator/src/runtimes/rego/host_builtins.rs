@@ -0,0 +1,174 @@
+use burrego::{
+    errors::{BurregoError, Result},
+    host_callbacks::HostBuiltin,
+    http::{HostHttpSend, HttpRequest, HttpResponse},
+};
+use std::{
+    collections::HashMap,
+    sync::Arc,
+    time::{SystemTime, UNIX_EPOCH},
+};
+use tokio::sync::mpsc;
+
+use crate::{
+    callback_handler::HttpSendResponse,
+    callback_requests::{CallbackRequest, CallbackRequestType},
+    runtimes::rego::context_aware::make_request_via_callback_channel,
+};
+
+/// Name of the Rego builtin that resolves the tag of an image to its digest.
+/// Rego policies must declare it inside of the capabilities file used at build time.
+pub(crate) const OCI_RESOLVE_DIGEST: &str = "kubewarden.oci.resolve_digest";
+
+/// Name of the Rego builtin that returns the current time
+pub(crate) const TIME_NOW_NS: &str = "time.now_ns";
+
+/// Build the Rego builtins that are backed by the host capabilities.
+/// The builtins are always registered, the ones invoked when the callback
+/// channel is not set return an error.
+///
+/// The builtins can be given to a burrego `Evaluator` that is not driven by
+/// the policy evaluator, as long as the requests sent over the callback channel
+/// are served by a [`CallbackHandler`](crate::callback_handler::CallbackHandler).
+pub fn new_host_builtins(
+    callback_channel: Option<mpsc::Sender<CallbackRequest>>,
+) -> HashMap<String, HostBuiltin> {
+    let mut builtins: HashMap<String, HostBuiltin> = HashMap::new();
+
+    builtins.insert(
+        OCI_RESOLVE_DIGEST.to_string(),
+        Arc::new(move |args| oci_resolve_digest(callback_channel.as_ref(), args)),
+    );
+
+    builtins
+}
+
+/// Build a `time.now_ns` builtin that always returns the given instant, instead of
+/// reading the clock of the host. Host builtins take precedence over the ones
+/// implemented by burrego, hence this replaces the regular implementation.
+pub(crate) fn pinned_time_now_ns(pinned_clock: SystemTime) -> HostBuiltin {
+    Arc::new(move |args| {
+        if !args.is_empty() {
+            return Err(BurregoError::BuiltinError {
+                name: TIME_NOW_NS.to_string(),
+                message: "wrong number of arguments given".to_string(),
+            });
+        }
+
+        let nanos = pinned_clock
+            .duration_since(UNIX_EPOCH)
+            .map_err(|e| BurregoError::BuiltinError {
+                name: TIME_NOW_NS.to_string(),
+                message: format!("pinned clock is before the UNIX epoch: {e}"),
+            })?
+            .as_nanos();
+        let nanos = i64::try_from(nanos).map_err(|e| BurregoError::BuiltinError {
+            name: TIME_NOW_NS.to_string(),
+            message: format!("pinned clock cannot be expressed in nanoseconds: {e}"),
+        })?;
+
+        Ok(serde_json::Value::from(nanos))
+    })
+}
+
+/// Build the `http_send` host callback used by the `http.send` builtin. The requests,
+/// already checked by burrego against the origins allowed to the policy, are
+/// performed by the `http/send` host capability
+pub(crate) fn new_http_send(callback_channel: mpsc::Sender<CallbackRequest>) -> HostHttpSend {
+    Arc::new(move |request: &HttpRequest| {
+        let response = make_request_via_callback_channel(
+            CallbackRequestType::HttpSend {
+                method: request.method.clone(),
+                url: request.url.clone(),
+                headers: request.headers.clone(),
+                body: request.body.clone(),
+                timeout_ms: u64::try_from(request.timeout.as_millis()).unwrap_or(u64::MAX),
+            },
+            &callback_channel,
+            None,
+        )
+        .map_err(|e| e.to_string())?;
+
+        let response: HttpSendResponse = serde_json::from_slice(&response.payload)
+            .map_err(|e| format!("cannot decode the host response: {e}"))?;
+        Ok(HttpResponse {
+            status_code: response.status_code,
+            headers: response.headers,
+            body: response.body,
+        })
+    })
+}
+
+fn oci_resolve_digest(
+    callback_channel: Option<&mpsc::Sender<CallbackRequest>>,
+    args: &[serde_json::Value],
+) -> Result<serde_json::Value> {
+    if args.len() != 1 {
+        return Err(BurregoError::BuiltinError {
+            name: OCI_RESOLVE_DIGEST.to_string(),
+            message: "wrong number of arguments".to_string(),
+        });
+    }
+
+    let image = args[0]
+        .as_str()
+        .ok_or_else(|| BurregoError::BuiltinError {
+            name: OCI_RESOLVE_DIGEST.to_string(),
+            message: "1st parameter is not a string".to_string(),
+        })?
+        .to_string();
+
+    let callback_channel = callback_channel.ok_or_else(|| BurregoError::BuiltinError {
+        name: OCI_RESOLVE_DIGEST.to_string(),
+        message: "callback channel is not set".to_string(),
+    })?;
+
+    let response = make_request_via_callback_channel(
+        CallbackRequestType::OciResolveDigest { image },
+        callback_channel,
+        None,
+    )
+    .map_err(|e| BurregoError::BuiltinError {
+        name: OCI_RESOLVE_DIGEST.to_string(),
+        message: e.to_string(),
+    })?;
+
+    serde_json::from_slice(&response.payload).map_err(|e| BurregoError::JSONError {
+        msg: "cannot convert host response to JSON".to_string(),
+        source: e,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn oci_resolve_digest_without_callback_channel() {
+        let builtins = new_host_builtins(None);
+        let builtin = builtins.get(OCI_RESOLVE_DIGEST).expect("builtin not found");
+
+        let result = builtin(&[json!("busybox:1.0.0")]);
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn oci_resolve_digest_wrong_arguments() {
+        let builtins = new_host_builtins(None);
+        let builtin = builtins.get(OCI_RESOLVE_DIGEST).expect("builtin not found");
+
+        assert!(builtin(&[]).is_err());
+        assert!(builtin(&[json!(42)]).is_err());
+    }
+
+    #[test]
+    fn pinned_time_now_ns_returns_the_pinned_instant() {
+        let pinned_clock = UNIX_EPOCH + std::time::Duration::from_secs(1_700_000_000);
+        let builtin = pinned_time_now_ns(pinned_clock);
+
+        assert_eq!(builtin(&[]).unwrap(), json!(1_700_000_000_000_000_000_i64));
+        assert_eq!(builtin(&[]).unwrap(), builtin(&[]).unwrap());
+        assert!(builtin(&[json!(1)]).is_err());
+    }
+}
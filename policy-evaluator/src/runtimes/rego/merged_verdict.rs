@@ -0,0 +1,227 @@
+//! Merge of the verdicts of the entrypoints of an OPA policy.
+//!
+//! A policy can be evaluated through several entrypoints, for example one holding
+//! the validation rules and another one holding the mutation rules. Each entrypoint
+//! returns its own `AdmissionResponse`, they are merged into a single one:
+//!
+//! * a rejection takes precedence over an acceptance: the messages of the rejecting
+//!   entrypoints are concatenated, in the order of the entrypoints. The status code is
+//!   the one of the first rejecting entrypoint
+//! * when all the entrypoints accept the request, their JSON patches are concatenated in
+//!   the order of the entrypoints, hence applied one after the other
+//! * the warnings are concatenated, the audit annotations are merged. When the same
+//!   annotation is set by different entrypoints, the value of the first one is kept
+
+use base64::{engine::general_purpose, Engine as _};
+
+use crate::admission_response::{AdmissionResponse, AdmissionResponseStatus, PatchType};
+use crate::runtimes::rego::errors::{RegoRuntimeError, Result};
+
+/// Merge the responses of the entrypoints, given in the order of evaluation
+pub(crate) fn merge_opa_responses(
+    uid: &str,
+    responses: Vec<AdmissionResponse>,
+) -> Result<AdmissionResponse> {
+    if responses.len() == 1 {
+        return Ok(responses.into_iter().next().expect("one response"));
+    }
+
+    let mut merged = AdmissionResponse {
+        uid: uid.to_string(),
+        allowed: responses.iter().all(|response| response.allowed),
+        ..Default::default()
+    };
+
+    for response in &responses {
+        if let Some(warnings) = &response.warnings {
+            merged
+                .warnings
+                .get_or_insert_with(Vec::new)
+                .extend(warnings.iter().cloned());
+        }
+        if let Some(audit_annotations) = &response.audit_annotations {
+            let merged_annotations = merged
+                .audit_annotations
+                .get_or_insert_with(Default::default);
+            for (key, value) in audit_annotations {
+                merged_annotations
+                    .entry(key.to_owned())
+                    .or_insert_with(|| value.to_owned());
+            }
+        }
+    }
+
+    if merged.allowed {
+        merged.patch = merge_patches(&responses)?;
+        if merged.patch.is_some() {
+            merged.patch_type = Some(PatchType::JSONPatch);
+        }
+        return Ok(merged);
+    }
+
+    let rejections: Vec<&AdmissionResponse> = responses
+        .iter()
+        .filter(|response| !response.allowed)
+        .collect();
+    let messages: Vec<String> = rejections
+        .iter()
+        .filter_map(|response| response.status.as_ref()?.message.clone())
+        .collect();
+    merged.status = Some(AdmissionResponseStatus {
+        message: (!messages.is_empty()).then(|| messages.join(", ")),
+        code: rejections
+            .iter()
+            .find_map(|response| response.status.as_ref()?.code),
+        ..Default::default()
+    });
+
+    Ok(merged)
+}
+
+/// Concatenate the JSON patches of the responses, `None` when no response has a patch
+fn merge_patches(responses: &[AdmissionResponse]) -> Result<Option<String>> {
+    let mut operations: Vec<serde_json::Value> = Vec::new();
+    let mut patched = false;
+
+    for patch in responses
+        .iter()
+        .filter_map(|response| response.patch.as_ref())
+    {
+        let decoded = general_purpose::STANDARD
+            .decode(patch)
+            .map_err(|e| RegoRuntimeError::InvalidPatch(e.to_string()))?;
+        let patch_operations: Vec<serde_json::Value> = serde_json::from_slice(&decoded)
+            .map_err(|e| RegoRuntimeError::InvalidPatch(e.to_string()))?;
+        operations.extend(patch_operations);
+        patched = true;
+    }
+
+    if !patched {
+        return Ok(None);
+    }
+    let encoded = serde_json::to_vec(&operations)
+        .map_err(|e| RegoRuntimeError::InvalidPatch(e.to_string()))?;
+    Ok(Some(general_purpose::STANDARD.encode(encoded)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+    use std::collections::HashMap;
+
+    fn accept(patch: Option<serde_json::Value>) -> AdmissionResponse {
+        AdmissionResponse {
+            uid: "uid".to_owned(),
+            allowed: true,
+            patch_type: patch.as_ref().map(|_| PatchType::JSONPatch),
+            patch: patch.map(|patch| general_purpose::STANDARD.encode(patch.to_string())),
+            ..Default::default()
+        }
+    }
+
+    fn reject(message: &str, code: u16) -> AdmissionResponse {
+        AdmissionResponse::reject("uid".to_owned(), message.to_owned(), code)
+    }
+
+    fn decoded_patch(response: &AdmissionResponse) -> serde_json::Value {
+        let decoded = general_purpose::STANDARD
+            .decode(response.patch.as_ref().expect("missing patch"))
+            .unwrap();
+        serde_json::from_slice(&decoded).unwrap()
+    }
+
+    #[test]
+    fn single_response_is_untouched() {
+        let response = reject("nope", 403);
+
+        let merged = merge_opa_responses("uid", vec![response.clone()]).unwrap();
+
+        assert_eq!(merged, response);
+    }
+
+    #[test]
+    fn rejection_takes_precedence() {
+        let responses = vec![
+            accept(Some(json!([{"op": "add", "path": "/a", "value": 1}]))),
+            reject("first", 403),
+            reject("second", 400),
+        ];
+
+        let merged = merge_opa_responses("uid", responses).unwrap();
+
+        assert!(!merged.allowed);
+        assert!(merged.patch.is_none());
+        let status = merged.status.unwrap();
+        assert_eq!(status.message.as_deref(), Some("first, second"));
+        assert_eq!(status.code, Some(403));
+    }
+
+    #[test]
+    fn patches_are_concatenated() {
+        let responses = vec![
+            accept(Some(json!([{"op": "add", "path": "/a", "value": 1}]))),
+            accept(None),
+            accept(Some(json!([{"op": "remove", "path": "/b"}]))),
+        ];
+
+        let merged = merge_opa_responses("uid", responses).unwrap();
+
+        assert!(merged.allowed);
+        assert_eq!(merged.patch_type, Some(PatchType::JSONPatch));
+        assert_eq!(
+            decoded_patch(&merged),
+            json!([
+                {"op": "add", "path": "/a", "value": 1},
+                {"op": "remove", "path": "/b"},
+            ])
+        );
+    }
+
+    #[test]
+    fn accepted_without_patches() {
+        let merged = merge_opa_responses("uid", vec![accept(None), accept(None)]).unwrap();
+
+        assert!(merged.allowed);
+        assert!(merged.patch.is_none());
+        assert!(merged.patch_type.is_none());
+    }
+
+    #[test]
+    fn warnings_and_audit_annotations_are_merged() {
+        let mut first = accept(None);
+        first.warnings = Some(vec!["first warning".to_owned()]);
+        first.audit_annotations = Some(HashMap::from([("key".to_owned(), "first".to_owned())]));
+        let mut second = accept(None);
+        second.warnings = Some(vec!["second warning".to_owned()]);
+        second.audit_annotations = Some(HashMap::from([
+            ("key".to_owned(), "second".to_owned()),
+            ("other".to_owned(), "second".to_owned()),
+        ]));
+
+        let merged = merge_opa_responses("uid", vec![first, second]).unwrap();
+
+        assert_eq!(
+            merged.warnings,
+            Some(vec![
+                "first warning".to_owned(),
+                "second warning".to_owned()
+            ])
+        );
+        assert_eq!(
+            merged.audit_annotations,
+            Some(HashMap::from([
+                ("key".to_owned(), "first".to_owned()),
+                ("other".to_owned(), "second".to_owned()),
+            ]))
+        );
+    }
+
+    #[test]
+    fn invalid_patch() {
+        let mut response = accept(None);
+        response.patch = Some("not base64!".to_owned());
+
+        assert!(merge_opa_responses("uid", vec![accept(None), response]).is_err());
+    }
+}
@@ -2,12 +2,19 @@ mod context_aware;
 pub mod errors;
 mod gatekeeper_inventory;
 mod gatekeeper_inventory_cache;
+mod gatekeeper_mutation;
+pub(crate) mod host_builtins;
+mod merged_verdict;
 mod opa_inventory;
+mod rego_library;
 mod runtime;
+mod size_guard;
 mod stack;
 mod stack_pre;
 
 use burrego::host_callbacks::HostCallbacks;
+pub(crate) use context_aware::KubernetesContext;
+pub(crate) use rego_library::RegoLibraries;
 pub(crate) use runtime::Runtime;
 pub(crate) use stack::Stack;
 pub(crate) use stack_pre::StackPre;
@@ -22,5 +29,6 @@ pub(crate) fn new_host_callbacks() -> HostCallbacks {
     HostCallbacks {
         opa_abort,
         opa_println,
+        ..Default::default()
     }
 }
@@ -0,0 +1,143 @@
+use std::collections::BTreeMap;
+
+use serde_json::{Map, Value};
+
+use crate::runtimes::rego::errors::{RegoRuntimeError, Result};
+
+/// The documents of the Rego libraries, keyed by the path where they are
+/// linked inside of the `data` document of the policy
+pub(crate) type RegoLibraries = BTreeMap<String, Map<String, Value>>;
+
+/// Build the document holding all the Rego libraries used by a policy.
+///
+/// Each library is linked at the given dotted path: the library linked at
+/// `lib.kubernetes` is available to the policy as `data.lib.kubernetes`.
+/// The paths cannot overlap, and cannot start with `kubernetes`, which is
+/// reserved to the context aware data
+pub(crate) fn link_rego_libraries(libraries: &RegoLibraries) -> Result<Map<String, Value>> {
+    let mut linked = Map::new();
+    let paths: Vec<&String> = libraries.keys().collect();
+
+    for (path, document) in libraries {
+        let segments = path_segments(path)?;
+        if let Some(other) = paths
+            .iter()
+            .find(|other| other.starts_with(&format!("{path}.")))
+        {
+            return Err(RegoRuntimeError::InvalidRegoLibrary {
+                path: path.to_owned(),
+                message: format!("it overlaps with the library linked at `{other}`"),
+            });
+        }
+
+        let (name, parents) = segments
+            .split_last()
+            .expect("path segments are never empty");
+        let mut parent = &mut linked;
+        for segment in parents {
+            parent = parent
+                .entry(segment.to_string())
+                .or_insert_with(|| Value::Object(Map::new()))
+                .as_object_mut()
+                .expect("intermediate nodes are always objects");
+        }
+        parent.insert(name.to_string(), Value::Object(document.to_owned()));
+    }
+
+    Ok(linked)
+}
+
+fn path_segments(path: &str) -> Result<Vec<&str>> {
+    let segments: Vec<&str> = path.split('.').collect();
+    if let Some(segment) = segments.iter().find(|segment| !is_rego_identifier(segment)) {
+        return Err(RegoRuntimeError::InvalidRegoLibrary {
+            path: path.to_owned(),
+            message: format!("`{segment}` is not a valid Rego identifier"),
+        });
+    }
+    if segments[0] == "kubernetes" {
+        return Err(RegoRuntimeError::InvalidRegoLibrary {
+            path: path.to_owned(),
+            message: "`data.kubernetes` is reserved to the context aware data".to_owned(),
+        });
+    }
+
+    Ok(segments)
+}
+
+fn is_rego_identifier(segment: &str) -> bool {
+    let mut chars = segment.chars();
+    match chars.next() {
+        Some(c) if c.is_ascii_alphabetic() || c == '_' => {
+            chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+        }
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rstest::rstest;
+    use serde_json::json;
+
+    fn library(document: Value) -> Map<String, Value> {
+        document.as_object().unwrap().to_owned()
+    }
+
+    #[test]
+    fn link_libraries() {
+        let libraries = RegoLibraries::from([
+            (
+                "lib.images".to_owned(),
+                library(json!({"registries": ["ghcr.io"]})),
+            ),
+            ("lib.labels".to_owned(), library(json!({"owner": "team"}))),
+            ("common".to_owned(), library(json!({"enabled": true}))),
+        ]);
+
+        let linked = link_rego_libraries(&libraries).unwrap();
+
+        assert_eq!(
+            Value::Object(linked),
+            json!({
+                "lib": {
+                    "images": {"registries": ["ghcr.io"]},
+                    "labels": {"owner": "team"},
+                },
+                "common": {"enabled": true},
+            })
+        );
+    }
+
+    #[rstest]
+    #[case::empty_segment("lib..images")]
+    #[case::starts_with_digit("lib.1images")]
+    #[case::dash("lib.container-images")]
+    #[case::reserved("kubernetes")]
+    #[case::inside_reserved("kubernetes.helpers")]
+    fn invalid_library_path(#[case] path: &str) {
+        let libraries = RegoLibraries::from([(path.to_owned(), Map::new())]);
+
+        assert!(matches!(
+            link_rego_libraries(&libraries),
+            Err(RegoRuntimeError::InvalidRegoLibrary { .. })
+        ));
+    }
+
+    #[test]
+    fn overlapping_library_paths() {
+        let libraries = RegoLibraries::from([
+            ("lib".to_owned(), Map::new()),
+            ("lib.images".to_owned(), Map::new()),
+        ]);
+
+        let err = link_rego_libraries(&libraries).unwrap_err();
+
+        assert!(
+            err.to_string()
+                .contains("overlaps with the library linked at `lib.images`"),
+            "{err}"
+        );
+    }
+}
@@ -1,16 +1,20 @@
 use burrego::errors::BurregoError;
-use kubewarden_policy_sdk::settings::SettingsValidationResponse;
+use kubewarden_policy_sdk::{
+    response::ValidationResponse as PolicyValidationResponse, settings::SettingsValidationResponse,
+};
 use serde::Deserialize;
 use serde_json::json;
 use tracing::{error, warn};
 
 use crate::runtimes::rego::{
-    context_aware, context_aware::KubernetesContext, errors::RegoRuntimeError, Stack,
+    context_aware, context_aware::KubernetesContext, errors::RegoRuntimeError,
+    gatekeeper_mutation::Mutation, merged_verdict, size_guard, Stack,
 };
 use crate::{
     admission_request,
     admission_response::{AdmissionResponse, AdmissionResponseStatus},
     policy_evaluator::{PolicySettings, RegoPolicyExecutionMode, ValidateRequest},
+    wasm_trap::WasmTrap,
 };
 
 pub(crate) struct Runtime<'a>(pub(crate) &'a mut Stack);
@@ -24,6 +28,18 @@ impl Runtime<'_> {
     ) -> AdmissionResponse {
         let uid = request.uid();
 
+        if let Some(memory_limit) = self.0.memory_limit {
+            if let Err(e) =
+                size_guard::check_memory_limit(memory_limit, request, settings, ctx_data)
+            {
+                error!(error = %e, "policy input exceeds the memory limit");
+                return AdmissionResponse::reject_internal_server_error(
+                    uid.to_string(),
+                    e.to_string(),
+                );
+            }
+        }
+
         // OPA and Gatekeeper expect arguments in different ways
         let burrego_evaluation = match self.0.policy_execution_mode {
             RegoPolicyExecutionMode::Opa => self.evaluate_opa(settings, request, ctx_data),
@@ -37,41 +53,32 @@ impl Runtime<'_> {
                             "Gatekeeper does not support raw validation requests".to_string(),
                         );
                     }
+                    ValidateRequest::CloudEvent(_) => {
+                        return AdmissionResponse::reject_internal_server_error(
+                            uid.to_string(),
+                            "Gatekeeper does not support CloudEvents validation requests"
+                                .to_string(),
+                        );
+                    }
                 };
                 self.evaluate_gatekeeper(settings, request, ctx_data)
             }
         };
 
         match burrego_evaluation {
-            Ok(evaluation_result) => {
+            Ok(evaluation_results) => {
                 match self.0.policy_execution_mode {
                     RegoPolicyExecutionMode::Opa => {
-                        // Open Policy agent policies entrypoint
-                        // return a Kubernetes `AdmissionReview`
-                        // object.
-                        let evaluation_result = evaluation_result
-                            .get(0)
-                            .and_then(|r| r.get("result"))
-                            .and_then(|r| r.get("response"));
-
-                        match evaluation_result {
-                            Some(evaluation_result) => {
-                                match serde_json::from_value(evaluation_result.clone()) {
-                                    Ok(evaluation_result) => AdmissionResponse {
-                                        uid: uid.to_string(),
-                                        ..evaluation_result
-                                    },
-                                    Err(err) => AdmissionResponse::reject_internal_server_error(
-                                        uid.to_string(),
-                                        err.to_string(),
-                                    ),
-                                }
-                            }
-                            None => AdmissionResponse::reject_internal_server_error(
+                        let responses = evaluation_results
+                            .iter()
+                            .map(|evaluation_result| Self::opa_response(uid, evaluation_result))
+                            .collect();
+                        merged_verdict::merge_opa_responses(uid, responses).unwrap_or_else(|e| {
+                            AdmissionResponse::reject_internal_server_error(
                                 uid.to_string(),
-                                "cannot interpret OPA policy result".to_string(),
-                            ),
-                        }
+                                e.to_string(),
+                            )
+                        })
                     }
                     RegoPolicyExecutionMode::Gatekeeper => {
                         // Gatekeeper entrypoint is usually a
@@ -81,30 +88,43 @@ impl Runtime<'_> {
                         // reason. If no violations are reported, the
                         // request is accepted. Otherwise it is
                         // rejected.
+                        // Entries with a `mutation` are not violations,
+                        // they describe how the object of the request
+                        // must be changed when it is accepted.
+                        // When many entrypoints are evaluated, their
+                        // entries are concatenated.
                         #[derive(Debug, Deserialize)]
                         struct Violation {
                             msg: Option<String>,
+                            mutation: Option<serde_json::Value>,
                         }
                         #[derive(Debug, Default, Deserialize)]
                         struct Violations {
                             result: Vec<Violation>,
                         }
 
-                        let violations: Violations = evaluation_result
-                            .get(0)
-                            .ok_or_else(|| RegoRuntimeError::InvalidResponse)
-                            .and_then(|response| {
-                                serde_json::from_value(response.clone())
-                                    .map_err(RegoRuntimeError::InvalidResponseWithError)
-                            })
-                            .unwrap_or_default();
+                        let (mutations, violations): (Vec<Violation>, Vec<Violation>) =
+                            evaluation_results
+                                .iter()
+                                .flat_map(|evaluation_result| {
+                                    let violations: Violations = evaluation_result
+                                        .get(0)
+                                        .ok_or_else(|| RegoRuntimeError::InvalidResponse)
+                                        .and_then(|response| {
+                                            serde_json::from_value(response.clone())
+                                                .map_err(RegoRuntimeError::InvalidResponseWithError)
+                                        })
+                                        .unwrap_or_default();
+                                    violations.result
+                                })
+                                .partition(|violation| violation.mutation.is_some());
 
-                        if violations.result.is_empty() {
-                            AdmissionResponse {
-                                uid: uid.to_string(),
-                                allowed: true,
-                                ..Default::default()
-                            }
+                        if violations.is_empty() {
+                            let mutations = mutations
+                                .into_iter()
+                                .filter_map(|violation| violation.mutation)
+                                .collect::<Vec<_>>();
+                            Self::gatekeeper_mutation_response(uid, request, mutations)
                         } else {
                             AdmissionResponse {
                                 uid: uid.to_string(),
@@ -112,7 +132,6 @@ impl Runtime<'_> {
                                 status: Some(AdmissionResponseStatus {
                                     message: Some(
                                         violations
-                                            .result
                                             .iter()
                                             .filter_map(|violation| violation.msg.clone())
                                             .collect::<Vec<String>>()
@@ -138,18 +157,119 @@ impl Runtime<'_> {
                     if let Err(reset_error) = self.0.evaluator.reset() {
                         error!(?reset_error, "cannot reset burrego evaluator, further invocations might fail or behave not properly");
                     }
+                    return AdmissionResponse::reject_timeout(uid.to_string(), err.to_string());
+                }
+                if let BurregoError::WasmTrap { msg, source } = &err {
+                    if let Some(trap) = WasmTrap::from_error(source) {
+                        trap.log();
+                        return AdmissionResponse::reject_internal_server_error(
+                            uid.to_string(),
+                            format!("{msg}: {trap}"),
+                        );
+                    }
                 }
                 AdmissionResponse::reject_internal_server_error(uid.to_string(), err.to_string())
             }
         }
     }
 
+    /// Interpret the result of an OPA entrypoint, which returns a Kubernetes
+    /// `AdmissionReview` object
+    fn opa_response(uid: &str, evaluation_result: &serde_json::Value) -> AdmissionResponse {
+        let evaluation_result = evaluation_result
+            .get(0)
+            .and_then(|r| r.get("result"))
+            .and_then(|r| r.get("response"));
+
+        match evaluation_result {
+            Some(evaluation_result) => match serde_json::from_value(evaluation_result.clone()) {
+                Ok(evaluation_result) => AdmissionResponse {
+                    uid: uid.to_string(),
+                    ..evaluation_result
+                },
+                Err(err) => AdmissionResponse::reject_internal_server_error(
+                    uid.to_string(),
+                    err.to_string(),
+                ),
+            },
+            None => AdmissionResponse::reject_internal_server_error(
+                uid.to_string(),
+                "cannot interpret OPA policy result".to_string(),
+            ),
+        }
+    }
+
+    /// Accept the request, applying the mutations emitted by the Gatekeeper
+    /// policy to its object
+    fn gatekeeper_mutation_response(
+        uid: &str,
+        request: &ValidateRequest,
+        mutations: Vec<serde_json::Value>,
+    ) -> AdmissionResponse {
+        if mutations.is_empty() {
+            return AdmissionResponse {
+                uid: uid.to_string(),
+                allowed: true,
+                ..Default::default()
+            };
+        }
+
+        let object = match request {
+            ValidateRequest::AdmissionRequest(adm_req) => {
+                adm_req.object.as_ref().map(|object| &object.0)
+            }
+            _ => None,
+        };
+        let mutated_object = object
+            .map(|object| Self::apply_gatekeeper_mutations(object, mutations))
+            .transpose();
+
+        match mutated_object {
+            Ok(mutated_object) => {
+                let response = PolicyValidationResponse {
+                    accepted: true,
+                    // a mutation without an object is rejected below
+                    mutated_object: Some(mutated_object.unwrap_or_default()),
+                    message: None,
+                    code: None,
+                    audit_annotations: None,
+                    warnings: None,
+                };
+                AdmissionResponse::from_policy_validation_response(
+                    uid.to_string(),
+                    object,
+                    &response,
+                )
+                .unwrap_or_else(|e| {
+                    AdmissionResponse::reject_internal_server_error(uid.to_string(), e.to_string())
+                })
+            }
+            Err(e) => {
+                error!(error = %e, "cannot apply the mutations of the Gatekeeper policy");
+                AdmissionResponse::reject_internal_server_error(uid.to_string(), e.to_string())
+            }
+        }
+    }
+
+    fn apply_gatekeeper_mutations(
+        object: &serde_json::Value,
+        mutations: Vec<serde_json::Value>,
+    ) -> Result<serde_json::Value, RegoRuntimeError> {
+        let mut mutated_object = object.clone();
+        for mutation in mutations {
+            let mutation: Mutation = serde_json::from_value(mutation)
+                .map_err(RegoRuntimeError::InvalidMutationWithError)?;
+            mutation.apply(&mut mutated_object)?;
+        }
+        Ok(mutated_object)
+    }
+
     fn evaluate_opa(
         &mut self,
         settings: &PolicySettings,
         request: &ValidateRequest,
         ctx_data: &context_aware::KubernetesContext,
-    ) -> Result<serde_json::Value, BurregoError> {
+    ) -> Result<Vec<serde_json::Value>, BurregoError> {
         let input = json!({
             "request": &request,
         });
@@ -160,29 +280,34 @@ impl Runtime<'_> {
         // We don't know the data that is provided by the users via
         // their settings, hence set the context aware data, to
         // ensure we overwrite what a user might have set.
-        let data = match ctx_data {
-            KubernetesContext::Opa(ctx) => {
-                let mut data = settings.clone();
-                if data
-                    .0
-                    .insert("kubernetes".to_string(), json!(ctx))
-                    .is_some()
-                {
-                    warn!("OPA policy had user provided setting with key `kubernetes`. This value has been overwritten with the actual kubernetes context data");
-                }
-                json!(data)
+        let mut data = settings.clone();
+        if let KubernetesContext::Opa(ctx, _) = ctx_data {
+            if data
+                .0
+                .insert("kubernetes".to_string(), json!(ctx))
+                .is_some()
+            {
+                warn!("OPA policy had user provided setting with key `kubernetes`. This value has been overwritten with the actual kubernetes context data");
             }
-            _ => json!(settings),
-        };
+        }
+        // The Rego libraries are shared by many policies, they take precedence
+        // over the settings of the single policy
+        for (key, library) in self.0.libraries.iter() {
+            if data.0.insert(key.to_owned(), library.clone()).is_some() {
+                warn!(
+                    key,
+                    "OPA policy had user provided setting overwritten by a Rego library"
+                );
+            }
+        }
+        let data = json!(data);
 
         let data_raw = serde_json::to_vec(&data).map_err(|e| BurregoError::JSONError {
             msg: "cannot convert OPA data to JSON".to_string(),
             source: e,
         })?;
 
-        self.0
-            .evaluator
-            .evaluate(self.0.entrypoint_id, &input, &data_raw)
+        self.evaluate_entrypoints(&input, &data_raw)
     }
 
     fn evaluate_gatekeeper(
@@ -190,7 +315,7 @@ impl Runtime<'_> {
         settings: &PolicySettings,
         request: &admission_request::AdmissionRequest,
         ctx_data: &context_aware::KubernetesContext,
-    ) -> Result<serde_json::Value, BurregoError> {
+    ) -> Result<Vec<serde_json::Value>, BurregoError> {
         // Gatekeeper policies include a toplevel `review`
         // object that contains the AdmissionRequest to be
         // evaluated in an `object` attribute, and the
@@ -202,14 +327,27 @@ impl Runtime<'_> {
         });
 
         let data_raw = match ctx_data {
-            KubernetesContext::Gatekeeper(ctx) => ctx,
+            KubernetesContext::Gatekeeper(ctx, _) => ctx,
             KubernetesContext::Empty => "{}".as_bytes(),
-            KubernetesContext::Opa(_) => unreachable!(),
+            KubernetesContext::Opa(..) => unreachable!(),
         };
 
-        self.0
-            .evaluator
-            .evaluate(self.0.entrypoint_id, &input, data_raw)
+        self.evaluate_entrypoints(&input, data_raw)
+    }
+
+    /// Evaluate the entrypoints of the policy, in order. The evaluation stops at the
+    /// first error
+    fn evaluate_entrypoints(
+        &mut self,
+        input: &serde_json::Value,
+        data_raw: &[u8],
+    ) -> Result<Vec<serde_json::Value>, BurregoError> {
+        let stack = &mut *self.0;
+        stack
+            .entrypoint_ids
+            .iter()
+            .map(|entrypoint_id| stack.evaluator.evaluate(*entrypoint_id, input, data_raw))
+            .collect()
     }
 
     pub fn validate_settings(&mut self, _settings: String) -> SettingsValidationResponse {
@@ -0,0 +1,133 @@
+use crate::{
+    policy_evaluator::{PolicySettings, ValidateRequest},
+    runtimes::rego::{
+        context_aware::KubernetesContext,
+        errors::{RegoRuntimeError, Result},
+    },
+};
+
+/// The documents given to a Rego policy are copied into the memory of the guest
+/// in their serialized form, then parsed by OPA into its own data structures. The
+/// parsed documents take at least as much memory as the serialized ones.
+const SERIALIZED_DOCUMENT_MEMORY_FACTOR: u64 = 2;
+
+/// Estimate the memory needed to load the request, the settings and the Kubernetes
+/// context into the guest, and ensure it fits into the memory limit of the policy.
+///
+/// This allows to fail fast, with a descriptive error, instead of having the guest
+/// run out of memory in the middle of the evaluation.
+pub(crate) fn check_memory_limit(
+    memory_limit: u64,
+    request: &ValidateRequest,
+    settings: &PolicySettings,
+    ctx_data: &KubernetesContext,
+) -> Result<()> {
+    let request_size = serialized_size(request);
+    let settings_size = serialized_size(settings);
+    let mut resources: Vec<(String, usize)> = ctx_data
+        .resource_sizes()
+        .map(|sizes| {
+            sizes
+                .iter()
+                .map(|(resource, size)| {
+                    (format!("{}/{}", resource.api_version, resource.kind), *size)
+                })
+                .collect()
+        })
+        .unwrap_or_default();
+    let context_size = match ctx_data {
+        KubernetesContext::Gatekeeper(data, _) => data.len(),
+        _ => resources.iter().map(|(_, size)| size).sum(),
+    };
+
+    let estimated_size =
+        (request_size + settings_size + context_size) as u64 * SERIALIZED_DOCUMENT_MEMORY_FACTOR;
+    if estimated_size <= memory_limit {
+        return Ok(());
+    }
+
+    // list the biggest resources first, they are the ones to look at
+    resources.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+    Err(RegoRuntimeError::MemoryLimitExceeded {
+        memory_limit,
+        estimated_size,
+        request_size,
+        context_size,
+        resources: if resources.is_empty() {
+            String::new()
+        } else {
+            format!(
+                " ({})",
+                resources
+                    .iter()
+                    .map(|(resource, size)| format!("{resource}: {size} bytes"))
+                    .collect::<Vec<String>>()
+                    .join(", ")
+            )
+        },
+    })
+}
+
+fn serialized_size<T: serde::Serialize>(value: &T) -> usize {
+    serde_json::to_vec(value).map_or(0, |data| data.len())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::policy_metadata::ContextAwareResource;
+    use crate::runtimes::rego::context_aware::ResourceSizes;
+    use serde_json::json;
+
+    fn resource(api_version: &str, kind: &str) -> ContextAwareResource {
+        ContextAwareResource {
+            api_version: api_version.to_string(),
+            kind: kind.to_string(),
+        }
+    }
+
+    #[test]
+    fn request_fits_into_memory_limit() {
+        let request = ValidateRequest::Raw(json!({"hello": "world"}));
+        let ctx_data = KubernetesContext::Gatekeeper(
+            vec![0; 1024],
+            ResourceSizes::from([(resource("v1", "Namespace"), 1000)]),
+        );
+
+        assert!(
+            check_memory_limit(1024 * 1024, &request, &PolicySettings::default(), &ctx_data)
+                .is_ok()
+        );
+    }
+
+    #[test]
+    fn context_exceeds_memory_limit() {
+        let request = ValidateRequest::Raw(json!({"hello": "world"}));
+        let ctx_data = KubernetesContext::Gatekeeper(
+            vec![0; 4096],
+            ResourceSizes::from([
+                (resource("v1", "Namespace"), 1000),
+                (resource("apps/v1", "Deployment"), 3000),
+            ]),
+        );
+
+        let err =
+            check_memory_limit(4096, &request, &PolicySettings::default(), &ctx_data).unwrap_err();
+        match err {
+            RegoRuntimeError::MemoryLimitExceeded {
+                memory_limit,
+                context_size,
+                resources,
+                ..
+            } => {
+                assert_eq!(memory_limit, 4096);
+                assert_eq!(context_size, 4096);
+                assert_eq!(
+                    resources,
+                    " (apps/v1/Deployment: 3000 bytes, v1/Namespace: 1000 bytes)"
+                );
+            }
+            _ => panic!("unexpected error: {err:?}"),
+        }
+    }
+}
@@ -1,8 +1,9 @@
-use std::collections::BTreeSet;
+use std::{collections::BTreeSet, sync::Arc};
 use tokio::sync::mpsc;
 
 use crate::{
     callback_requests::CallbackRequest,
+    evaluation_context::{EvaluationContext, KubernetesServiceAccount},
     policy_evaluator::RegoPolicyExecutionMode,
     policy_metadata::ContextAwareResource,
     runtimes::rego::{
@@ -16,20 +17,30 @@ use crate::{
 
 pub(crate) struct Stack {
     pub evaluator: burrego::Evaluator,
-    pub entrypoint_id: i32,
+    /// The entrypoints evaluated on each request, their verdicts are merged
+    pub entrypoint_ids: Vec<i32>,
     pub policy_execution_mode: RegoPolicyExecutionMode,
+    /// The memory limit of the policy, expressed in bytes
+    pub memory_limit: Option<u64>,
+    /// The Rego libraries linked into the `data` document of the policy
+    pub libraries: Arc<serde_json::Map<String, serde_json::Value>>,
 }
 
 impl Stack {
     /// Create a new `Stack` using a `StackPre` object
-    pub fn new_from_pre(stack_pre: &StackPre) -> Result<Self> {
+    pub fn new_from_pre(stack_pre: &StackPre, eval_ctx: &EvaluationContext) -> Result<Self> {
         let evaluator = stack_pre
-            .rehydrate()
+            .rehydrate(
+                eval_ctx.callback_channel.clone(),
+                eval_ctx.http_policy.as_ref(),
+            )
             .map_err(|e| RegoRuntimeError::EvaluatorError(e.to_string()))?;
         Ok(Self {
             evaluator,
-            entrypoint_id: stack_pre.entrypoint_id,
+            entrypoint_ids: stack_pre.entrypoint_ids.clone(),
             policy_execution_mode: stack_pre.policy_execution_mode.clone(),
+            memory_limit: stack_pre.memory_limit,
+            libraries: stack_pre.libraries.clone(),
         })
     }
 
@@ -37,6 +48,7 @@ impl Stack {
         &self,
         callback_channel: Option<&mpsc::Sender<CallbackRequest>>,
         ctx_aware_resources_allow_list: &BTreeSet<ContextAwareResource>,
+        kubernetes_service_account: Option<&KubernetesServiceAccount>,
     ) -> Result<context_aware::KubernetesContext> {
         if ctx_aware_resources_allow_list.is_empty() {
             return Ok(context_aware::KubernetesContext::Empty);
@@ -46,19 +58,39 @@ impl Stack {
             None => Err(RegoRuntimeError::CallbackChannelNotSet),
             Some(chan) => match self.policy_execution_mode {
                 RegoPolicyExecutionMode::Opa => {
-                    let cluster_resources =
-                        context_aware::get_allowed_resources(chan, ctx_aware_resources_allow_list)?;
-                    let plural_names_by_resource =
-                        context_aware::get_plural_names(chan, ctx_aware_resources_allow_list)?;
+                    let cluster_resources = context_aware::get_allowed_resources(
+                        chan,
+                        ctx_aware_resources_allow_list,
+                        kubernetes_service_account,
+                    )?;
+                    let plural_names_by_resource = context_aware::get_plural_names(
+                        chan,
+                        ctx_aware_resources_allow_list,
+                        kubernetes_service_account,
+                    )?;
                     let inventory =
                         OpaInventory::new(&cluster_resources, &plural_names_by_resource)?;
-                    Ok(context_aware::KubernetesContext::Opa(inventory))
+                    // the sizes are needed only to enforce the memory limit, don't pay
+                    // the cost of the serialization otherwise
+                    let resource_sizes = if self.memory_limit.is_some() {
+                        context_aware::resource_sizes(&cluster_resources)
+                    } else {
+                        context_aware::ResourceSizes::new()
+                    };
+                    Ok(context_aware::KubernetesContext::Opa(
+                        inventory,
+                        resource_sizes,
+                    ))
                 }
                 RegoPolicyExecutionMode::Gatekeeper => {
-                    let cached_inventory = GATEKEEPER_INVENTORY_CACHE
-                        .get_inventory(chan, ctx_aware_resources_allow_list)?;
+                    let cached_inventory = GATEKEEPER_INVENTORY_CACHE.get_cached_inventory(
+                        chan,
+                        ctx_aware_resources_allow_list,
+                        kubernetes_service_account,
+                    )?;
                     Ok(context_aware::KubernetesContext::Gatekeeper(
-                        cached_inventory,
+                        cached_inventory.data.clone(),
+                        cached_inventory.resource_sizes.clone(),
                     ))
                 }
             },
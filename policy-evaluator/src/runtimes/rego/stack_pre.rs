@@ -1,6 +1,13 @@
+use std::{sync::Arc, time::SystemTime};
+
+use burrego::HttpPolicy;
+use tokio::sync::mpsc;
+
+use crate::callback_requests::CallbackRequest;
 use crate::policy_evaluator::RegoPolicyExecutionMode;
 use crate::policy_evaluator_builder::EpochDeadlines;
 use crate::runtimes::rego::errors::{RegoRuntimeError, Result};
+use crate::runtimes::rego::rego_library::{link_rego_libraries, RegoLibraries};
 
 /// This struct allows to follow the `StackPre -> Stack`
 /// "pattern" also for Rego policies.
@@ -18,8 +25,14 @@ pub(crate) struct StackPre {
     engine: wasmtime::Engine,
     module: wasmtime::Module,
     epoch_deadlines: Option<EpochDeadlines>,
-    pub entrypoint_id: i32,
+    pub memory_limit: Option<u64>,
+    /// When set, `time.now_ns` always returns this instant
+    pinned_clock: Option<SystemTime>,
+    /// The entrypoints evaluated on each request, their verdicts are merged
+    pub entrypoint_ids: Vec<i32>,
     pub policy_execution_mode: RegoPolicyExecutionMode,
+    /// The Rego libraries linked into the `data` document of the policy
+    pub libraries: Arc<serde_json::Map<String, serde_json::Value>>,
 }
 
 impl StackPre {
@@ -27,6 +40,8 @@ impl StackPre {
         engine: wasmtime::Engine,
         module: wasmtime::Module,
         epoch_deadlines: Option<EpochDeadlines>,
+        memory_limit: Option<u64>,
+        pinned_clock: Option<SystemTime>,
         entrypoint_id: i32,
         policy_execution_mode: RegoPolicyExecutionMode,
     ) -> Self {
@@ -34,24 +49,91 @@ impl StackPre {
             engine,
             module,
             epoch_deadlines,
-            entrypoint_id,
+            memory_limit,
+            pinned_clock,
+            entrypoint_ids: vec![entrypoint_id],
             policy_execution_mode,
+            libraries: Arc::new(serde_json::Map::new()),
         }
     }
 
-    /// Create a fresh `burrego::Evaluator`
-    pub(crate) fn rehydrate(&self) -> Result<burrego::Evaluator> {
+    /// Create a fresh `burrego::Evaluator`. The callback channel is used by the
+    /// Rego builtins that are backed by host capabilities. The `http.send` builtin
+    /// is enabled only when the policy is given an `HttpPolicy`
+    pub(crate) fn rehydrate(
+        &self,
+        callback_channel: Option<mpsc::Sender<CallbackRequest>>,
+        http_policy: Option<&HttpPolicy>,
+    ) -> Result<burrego::Evaluator> {
+        let mut host_callbacks = crate::runtimes::rego::new_host_callbacks();
+        if http_policy.is_some() {
+            let callback_channel = callback_channel
+                .clone()
+                .ok_or(RegoRuntimeError::CallbackChannelNotSet)?;
+            host_callbacks.http_send = Some(super::host_builtins::new_http_send(callback_channel));
+        }
+        if let Some(pinned_clock) = self.pinned_clock {
+            host_callbacks.builtins.insert(
+                super::host_builtins::TIME_NOW_NS.to_string(),
+                super::host_builtins::pinned_time_now_ns(pinned_clock),
+            );
+        }
+
         let mut builder = burrego::EvaluatorBuilder::default()
             .engine(&self.engine)
             .module(self.module.clone())
-            .host_callbacks(crate::runtimes::rego::new_host_callbacks());
+            .host_callbacks(host_callbacks);
+        for (name, builtin) in super::host_builtins::new_host_builtins(callback_channel) {
+            builder = builder.builtin(&name, move |args| builtin(args));
+        }
 
         if let Some(deadlines) = self.epoch_deadlines {
             builder = builder.enable_epoch_interruptions(deadlines.wapc_func);
         }
+        if let Some(memory_limit) = self.memory_limit {
+            builder = builder.memory_limit(memory_limit);
+        }
+        if let Some(http_policy) = http_policy {
+            builder = builder.enable_http_builtin(http_policy.clone());
+        }
         let evaluator = builder
             .build()
             .map_err(RegoRuntimeError::RegoEngineBuilder)?;
         Ok(evaluator)
     }
+
+    /// Evaluate the given entrypoints instead of the default one. The entrypoints must be
+    /// exported by the Wasm module, they are evaluated in the given order.
+    pub(crate) fn select_entrypoints(&mut self, entrypoints: &[String]) -> Result<()> {
+        let evaluator = self.rehydrate(None, None)?;
+        let available_entrypoints = evaluator.entrypoints();
+
+        let entrypoint_ids = entrypoints
+            .iter()
+            .map(|entrypoint| {
+                available_entrypoints
+                    .get(entrypoint)
+                    .copied()
+                    .ok_or_else(|| {
+                        let mut available: Vec<String> =
+                            available_entrypoints.keys().cloned().collect();
+                        available.sort();
+                        RegoRuntimeError::EntrypointNotFound {
+                            entrypoint: entrypoint.to_owned(),
+                            available,
+                        }
+                    })
+            })
+            .collect::<Result<Vec<i32>>>()?;
+        if !entrypoint_ids.is_empty() {
+            self.entrypoint_ids = entrypoint_ids;
+        }
+        Ok(())
+    }
+
+    /// Link the given Rego libraries into the `data` document of the policy
+    pub(crate) fn link_libraries(&mut self, libraries: &RegoLibraries) -> Result<()> {
+        self.libraries = Arc::new(link_rego_libraries(libraries)?);
+        Ok(())
+    }
 }
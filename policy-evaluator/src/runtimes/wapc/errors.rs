@@ -20,6 +20,9 @@ pub enum WapcRuntimeError {
     #[error("cannot invoke 'protocol_version' waPC function : {0}")]
     InvokeProtocolVersion(#[source] wapc::errors::Error),
 
+    #[error("cannot invoke 'validate_settings' waPC function: {0}")]
+    InvokeValidateSettings(#[source] wapc::errors::Error),
+
     #[error("cannot build Wasmtime engine: {0}")]
     WasmtimeEngineBuilder(#[source] wasmtime_provider::errors::Error),
 
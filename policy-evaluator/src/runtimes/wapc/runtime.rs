@@ -2,12 +2,12 @@ use crate::runtimes::wapc::errors::{Result, WapcRuntimeError};
 use kubewarden_policy_sdk::metadata::ProtocolVersion;
 use kubewarden_policy_sdk::response::ValidationResponse as PolicyValidationResponse;
 use kubewarden_policy_sdk::settings::SettingsValidationResponse;
-use serde_json::json;
 use std::convert::TryFrom;
 use tracing::{error, info};
 
 use crate::admission_response::AdmissionResponse;
 use crate::policy_evaluator::{PolicySettings, ValidateRequest};
+use crate::request_envelope::build_envelope;
 use crate::runtimes::wapc::WapcStack;
 
 pub(crate) struct Runtime<'a>(pub(crate) &'a mut WapcStack);
@@ -32,16 +32,29 @@ impl Runtime<'_> {
 
         //NOTE: object is null for DELETE operations
         let req_obj = match request {
-            ValidateRequest::Raw(_) => Some(&req_json_value),
+            ValidateRequest::Raw(_) | ValidateRequest::CloudEvent(_) => Some(&req_json_value),
             ValidateRequest::AdmissionRequest(_) => req_json_value.get("object"),
         };
 
-        let validate_params = json!({
-            "request": request,
-            "settings": settings,
-        });
+        // The policy can receive only a subset of the fields of the request, while
+        // the patch of mutating policies is always computed against the whole object
+        let projected_request = match (request, self.0.request_projection()) {
+            (ValidateRequest::AdmissionRequest(_), Some(projection)) => {
+                Some(projection.apply(&req_json_value))
+            }
+            _ => None,
+        };
 
-        let validate_str = match serde_json::to_string(&validate_params) {
+        // The policies that do not understand the v2 envelope receive the
+        // request without the context of the evaluation
+        let validate_str = match build_envelope(
+            self.0.request_envelope(),
+            projected_request.as_ref().unwrap_or(&req_json_value),
+            settings,
+            self.0.request_context(),
+        )
+        .and_then(|validate_params| serde_json::to_string(&validate_params))
+        {
             Ok(s) => s,
             Err(e) => {
                 error!(
@@ -122,6 +135,7 @@ impl Runtime<'_> {
                     } else {
                         info!("wapc_host reset performed after timeout protection was triggered");
                     }
+                    return AdmissionResponse::reject_timeout(uid.to_string(), e.to_string());
                 }
                 AdmissionResponse::reject_internal_server_error(uid.to_string(), e.to_string())
             }
@@ -129,7 +143,7 @@ impl Runtime<'_> {
     }
 
     pub fn validate_settings(&mut self, settings: String) -> SettingsValidationResponse {
-        match self.0.call("validate_settings", settings.as_bytes()) {
+        match self.0.call_validate_settings(settings.as_bytes()) {
             Ok(res) => {
                 let vr: Result<SettingsValidationResponse> = serde_json::from_slice(&res)
                     .map_err(WapcRuntimeError::InvalidResponseWithError);
@@ -197,8 +211,13 @@ mod tests {
 
         let eval_ctx = EvaluationContext {
             policy_id: "wapc_endless_loop".to_string(),
+            policy_stable_id: None,
             callback_channel: None,
             ctx_aware_resources_allow_list: Default::default(),
+            kubernetes_service_account: None,
+            request_context: Default::default(),
+            http_policy: None,
+            key_value_store: false,
         };
 
         let eval_ctx = Arc::new(eval_ctx);
@@ -1,6 +1,9 @@
 use std::sync::Arc;
 
 use crate::evaluation_context::EvaluationContext;
+use crate::policy_evaluator_builder::GuestFunction;
+use crate::request_envelope::{RequestContext, RequestEnvelopeVersion};
+use crate::request_projection::RequestProjection;
 use crate::runtimes::wapc::{
     callback::new_host_callback,
     errors::{Result, WapcRuntimeError},
@@ -17,7 +20,8 @@ pub(crate) struct WapcStack {
 impl WapcStack {
     pub(crate) fn new_from_pre(stack_pre: &StackPre, eval_ctx: &EvaluationContext) -> Result<Self> {
         let eval_ctx = Arc::new(eval_ctx.to_owned());
-        let wapc_host = Self::wapc_host_from_pre(stack_pre, eval_ctx.clone())?;
+        let wapc_host =
+            Self::wapc_host_from_pre(stack_pre, eval_ctx.clone(), GuestFunction::Validation)?;
 
         Ok(Self {
             wapc_host,
@@ -34,13 +38,33 @@ impl WapcStack {
     /// variable.
     pub(crate) fn reset(&mut self) -> Result<()> {
         // Create a new wapc_host
-        let new_wapc_host = Self::wapc_host_from_pre(&self.stack_pre, self.eval_ctx.clone())?;
+        let new_wapc_host = Self::wapc_host_from_pre(
+            &self.stack_pre,
+            self.eval_ctx.clone(),
+            GuestFunction::Validation,
+        )?;
 
         self.wapc_host = new_wapc_host;
 
         Ok(())
     }
 
+    /// The fields of the `AdmissionRequest` objects to be sent to the policy,
+    /// all of them when `None`
+    pub(crate) fn request_projection(&self) -> Option<&RequestProjection> {
+        self.stack_pre.request_projection()
+    }
+
+    /// The version of the envelope wrapping the requests sent to the policy
+    pub(crate) fn request_envelope(&self) -> RequestEnvelopeVersion {
+        self.stack_pre.request_envelope()
+    }
+
+    /// The context of the evaluation, sent to the policies using the `v2` envelope
+    pub(crate) fn request_context(&self) -> &RequestContext {
+        &self.eval_ctx.request_context
+    }
+
     /// Invokes the given waPC function using the provided payload
     pub(crate) fn call(
         &self,
@@ -50,13 +74,34 @@ impl WapcStack {
         self.wapc_host.call(op, payload)
     }
 
+    /// Invokes the `validate_settings` waPC function. When the settings validation has
+    /// its own epoch deadline, the function is invoked by a dedicated waPC host that is
+    /// discarded afterwards
+    pub(crate) fn call_validate_settings(&self, payload: &[u8]) -> Result<Vec<u8>> {
+        if !self.stack_pre.has_settings_validation_deadline() {
+            return self
+                .call("validate_settings", payload)
+                .map_err(WapcRuntimeError::InvokeValidateSettings);
+        }
+
+        let wapc_host = Self::wapc_host_from_pre(
+            &self.stack_pre,
+            self.eval_ctx.clone(),
+            GuestFunction::SettingsValidation,
+        )?;
+        wapc_host
+            .call("validate_settings", payload)
+            .map_err(WapcRuntimeError::InvokeValidateSettings)
+    }
+
     /// Create a new `WapcHost` by rehydrating the `StackPre`. This is faster than creating the
     /// `WasmtimeEngineProvider` from scratch
     fn wapc_host_from_pre(
         pre: &StackPre,
         eval_ctx: Arc<EvaluationContext>,
+        function: GuestFunction,
     ) -> Result<wapc::WapcHost> {
-        let engine_provider = pre.rehydrate()?;
+        let engine_provider = pre.rehydrate(function)?;
         let wapc_host =
             wapc::WapcHost::new(Box::new(engine_provider), Some(new_host_callback(eval_ctx)))
                 .map_err(WapcRuntimeError::WapcHostBuilder)?;
@@ -1,12 +1,22 @@
 use wasmtime_provider::wasmtime;
 
-use crate::policy_evaluator_builder::EpochDeadlines;
+use crate::policy_evaluator_builder::{EpochDeadlines, GuestFunction};
+use crate::request_envelope::RequestEnvelopeVersion;
+use crate::request_projection::RequestProjection;
 use crate::runtimes::wapc::errors::{Result, WapcRuntimeError};
 
 /// Reduce allocation time of new `WasmtimeProviderEngine`, see the `rehydrate` method
 #[derive(Clone)]
 pub(crate) struct StackPre {
     engine_provider_pre: wasmtime_provider::WasmtimeEngineProviderPre,
+    /// Used to validate the settings when their validation has a dedicated epoch deadline.
+    /// The waPC provider enforces the same deadline on all the guest functions, hence a
+    /// different provider is required
+    settings_validation_engine_provider_pre: Option<wasmtime_provider::WasmtimeEngineProviderPre>,
+    /// The fields of the `AdmissionRequest` objects sent to the policy, all of them when not set
+    request_projection: Option<RequestProjection>,
+    /// The version of the envelope wrapping the requests sent to the policy
+    request_envelope: RequestEnvelopeVersion,
 }
 
 impl StackPre {
@@ -14,26 +24,75 @@ impl StackPre {
         engine: wasmtime::Engine,
         module: wasmtime::Module,
         epoch_deadlines: Option<EpochDeadlines>,
+        request_projection: Option<RequestProjection>,
+        request_envelope: RequestEnvelopeVersion,
     ) -> Result<Self> {
-        let mut builder = wasmtime_provider::WasmtimeEngineProviderBuilder::new()
-            .engine(engine)
-            .module(module);
-        if let Some(deadlines) = epoch_deadlines {
-            builder = builder.enable_epoch_interruptions(deadlines.wapc_init, deadlines.wapc_func);
-        }
+        let engine_provider_pre = Self::build_engine_provider_pre(
+            &engine,
+            &module,
+            epoch_deadlines.map(|deadlines| (deadlines.wapc_init, deadlines.wapc_func)),
+        )?;
+        let settings_validation_engine_provider_pre = epoch_deadlines
+            .filter(|deadlines| deadlines.settings_validation != deadlines.wapc_func)
+            .map(|deadlines| {
+                Self::build_engine_provider_pre(
+                    &engine,
+                    &module,
+                    Some((deadlines.wapc_init, deadlines.settings_validation)),
+                )
+            })
+            .transpose()?;
 
-        let engine_provider_pre = builder
-            .build_pre()
-            .map_err(WapcRuntimeError::WasmtimeEngineBuilder)?;
         Ok(Self {
             engine_provider_pre,
+            settings_validation_engine_provider_pre,
+            request_projection,
+            request_envelope,
         })
     }
 
-    /// Allocate a new `WasmtimeEngineProvider` instance by using a pre-allocated instance
-    pub(crate) fn rehydrate(&self) -> Result<wasmtime_provider::WasmtimeEngineProvider> {
-        let engine = self
-            .engine_provider_pre
+    fn build_engine_provider_pre(
+        engine: &wasmtime::Engine,
+        module: &wasmtime::Module,
+        epoch_deadlines: Option<(u64, u64)>,
+    ) -> Result<wasmtime_provider::WasmtimeEngineProviderPre> {
+        let mut builder = wasmtime_provider::WasmtimeEngineProviderBuilder::new()
+            .engine(engine.clone())
+            .module(module.clone());
+        if let Some((wapc_init, wapc_func)) = epoch_deadlines {
+            builder = builder.enable_epoch_interruptions(wapc_init, wapc_func);
+        }
+
+        builder
+            .build_pre()
+            .map_err(WapcRuntimeError::WasmtimeEngineBuilder)
+    }
+
+    /// Whether the settings validation must be done with a dedicated `WasmtimeEngineProvider`,
+    /// see [`rehydrate`](StackPre::rehydrate)
+    pub(crate) fn has_settings_validation_deadline(&self) -> bool {
+        self.settings_validation_engine_provider_pre.is_some()
+    }
+
+    pub(crate) fn request_projection(&self) -> Option<&RequestProjection> {
+        self.request_projection.as_ref()
+    }
+
+    pub(crate) fn request_envelope(&self) -> RequestEnvelopeVersion {
+        self.request_envelope
+    }
+
+    /// Allocate a new `WasmtimeEngineProvider` instance by using a pre-allocated instance.
+    /// The provider enforces the epoch deadline of the given guest function
+    pub(crate) fn rehydrate(
+        &self,
+        function: GuestFunction,
+    ) -> Result<wasmtime_provider::WasmtimeEngineProvider> {
+        let engine_provider_pre = match (function, &self.settings_validation_engine_provider_pre) {
+            (GuestFunction::SettingsValidation, Some(pre)) => pre,
+            _ => &self.engine_provider_pre,
+        };
+        let engine = engine_provider_pre
             .rehydrate()
             .map_err(WapcRuntimeError::WasmtimeEngineBuilder)?;
         Ok(engine)
@@ -1,5 +1,7 @@
 use thiserror::Error;
 
+use crate::wasm_trap::WasmTrap;
+
 pub type Result<T> = std::result::Result<T, WasiRuntimeError>;
 
 #[derive(Error, Debug)]
@@ -45,4 +47,39 @@ pub enum WasiRuntimeError {
     // corresponds to a PoisonError, whose error message is not particularly useful anyways
     #[error("host_call: cannot get write access to STDIN")]
     WasiWriteAccessStdin(),
+
+    #[error("invalid frame: {0}")]
+    InvalidFrame(String),
+
+    #[error("cannot open directory `{host_path}` preopened at `{guest_path}`: {error}")]
+    PreopenedDir {
+        guest_path: String,
+        host_path: String,
+        #[source]
+        error: std::io::Error,
+    },
+
+    #[error("cannot mount directory preopened at `{guest_path}`: {error}")]
+    PreopenedDirMount { guest_path: String, error: String },
+}
+
+impl WasiRuntimeError {
+    /// Whether the WASI program has been interrupted because it exceeded its
+    /// epoch deadline
+    pub fn is_interrupted(&self) -> bool {
+        match self {
+            WasiRuntimeError::WasiEvaluation { error, .. } => {
+                error.downcast_ref::<wasmtime::Trap>() == Some(&wasmtime::Trap::Interrupt)
+            }
+            _ => false,
+        }
+    }
+
+    /// The trap raised by the WASI program, if any
+    pub fn trap(&self) -> Option<WasmTrap> {
+        match self {
+            WasiRuntimeError::WasiEvaluation { error, .. } => WasmTrap::from_error(error),
+            _ => None,
+        }
+    }
 }
@@ -0,0 +1,166 @@
+//! Version 2 of the protocol used to talk with WASI policies.
+//!
+//! With the first version of the protocol, the guest reads the whole STDIN and
+//! writes its response to STDOUT as plain JSON documents. The second version
+//! streams length-prefixed JSON frames instead, which allows the guest to read
+//! the request and the settings separately, without having to look for the end
+//! of the JSON documents.
+//!
+//! Each frame is made of the size of its payload, encoded as a big-endian
+//! unsigned 32 bit integer, followed by the payload itself:
+//!
+//! * `validate`: the host writes the frame of the request, then the frame of
+//!   the settings. The guest answers with the frame of the validation response
+//! * `validate-settings`: the host writes the frame of the settings, the guest
+//!   answers with the frame of the settings validation response
+//! * host capabilities: the response to each `host.call` invocation is written
+//!   to STDIN as a single frame
+//!
+//! The guest declares it implements this version of the protocol by exporting
+//! a function named [`PROTOCOL_V2_EXPORT`].
+
+use crate::runtimes::wasi_cli::errors::{Result, WasiRuntimeError};
+
+/// The function exported by the guests that implement the version 2 of the protocol
+pub(crate) const PROTOCOL_V2_EXPORT: &str = "kubewarden_wasi_protocol_v2";
+
+/// Size of the header of a frame, holding the size of its payload
+const HEADER_SIZE: usize = std::mem::size_of::<u32>();
+
+/// The protocol used to exchange data with a WASI policy
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub(crate) enum Protocol {
+    /// Plain JSON documents written to STDIN and read from STDOUT
+    #[default]
+    V1,
+    /// Length-prefixed JSON frames
+    V2,
+}
+
+impl Protocol {
+    /// Detect the protocol implemented by the given module
+    pub(crate) fn detect(module: &wasmtime::Module) -> Self {
+        if module
+            .exports()
+            .any(|export| export.name() == PROTOCOL_V2_EXPORT)
+        {
+            Protocol::V2
+        } else {
+            Protocol::V1
+        }
+    }
+
+    /// Encode the given payloads, the ones of the first protocol are concatenated
+    pub(crate) fn encode(&self, payloads: &[&[u8]]) -> Result<Vec<u8>> {
+        match self {
+            Protocol::V1 => Ok(payloads.concat()),
+            Protocol::V2 => {
+                let mut data = Vec::new();
+                for payload in payloads {
+                    encode_frame(payload, &mut data)?;
+                }
+                Ok(data)
+            }
+        }
+    }
+
+    /// Decode the response written by the guest to STDOUT
+    pub(crate) fn decode_response<'a>(&self, data: &'a [u8]) -> Result<&'a [u8]> {
+        match self {
+            Protocol::V1 => Ok(data),
+            Protocol::V2 => decode_frame(data),
+        }
+    }
+}
+
+/// Append the frame of the given payload to `data`
+pub(crate) fn encode_frame(payload: &[u8], data: &mut Vec<u8>) -> Result<()> {
+    let size = u32::try_from(payload.len()).map_err(|_| {
+        WasiRuntimeError::InvalidFrame(format!("payload too big: {} bytes", payload.len()))
+    })?;
+    data.reserve(HEADER_SIZE + payload.len());
+    data.extend_from_slice(&size.to_be_bytes());
+    data.extend_from_slice(payload);
+    Ok(())
+}
+
+/// Return the payload of the only frame contained inside of `data`
+fn decode_frame(data: &[u8]) -> Result<&[u8]> {
+    let (header, payload) = data.split_at_checked(HEADER_SIZE).ok_or_else(|| {
+        WasiRuntimeError::InvalidFrame(format!("frame header too short: {} bytes", data.len()))
+    })?;
+    let size = u32::from_be_bytes(header.try_into().expect("header has the right size")) as usize;
+    if payload.len() != size {
+        return Err(WasiRuntimeError::InvalidFrame(format!(
+            "the frame declares a payload of {size} bytes, {} bytes found",
+            payload.len()
+        )));
+    }
+    Ok(payload)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rstest::rstest;
+
+    #[rstest]
+    #[case::v1(r#"(module (func (export "_start")))"#, Protocol::V1)]
+    #[case::v2(
+        r#"(module (func (export "_start")) (func (export "kubewarden_wasi_protocol_v2")))"#,
+        Protocol::V2
+    )]
+    fn detect_protocol(#[case] wat: &str, #[case] expected: Protocol) {
+        let engine = wasmtime::Engine::default();
+        let module = wasmtime::Module::new(&engine, wat).expect("cannot compile WAT to wasm");
+
+        assert_eq!(Protocol::detect(&module), expected);
+    }
+
+    #[test]
+    fn encode_frames() {
+        let data = Protocol::V2
+            .encode(&[br#"{"uid":"1"}"#, b"{}"])
+            .expect("cannot encode frames");
+
+        let mut expected = vec![0, 0, 0, 11];
+        expected.extend_from_slice(br#"{"uid":"1"}"#);
+        expected.extend_from_slice(&[0, 0, 0, 2]);
+        expected.extend_from_slice(b"{}");
+        assert_eq!(data, expected);
+    }
+
+    #[test]
+    fn encode_v1() {
+        let data = Protocol::V1
+            .encode(&[b"{}"])
+            .expect("cannot encode payload");
+        assert_eq!(data, b"{}");
+    }
+
+    #[test]
+    fn decode_response() {
+        let data = Protocol::V2
+            .encode(&[br#"{"accepted":true}"#])
+            .expect("cannot encode frame");
+
+        assert_eq!(
+            Protocol::V2
+                .decode_response(&data)
+                .expect("cannot decode frame"),
+            br#"{"accepted":true}"#
+        );
+    }
+
+    #[rstest]
+    #[case::empty(&[])]
+    #[case::short_header(&[0, 0])]
+    #[case::truncated_payload(&[0, 0, 0, 4, b'{', b'}'])]
+    #[case::trailing_data(&[0, 0, 0, 2, b'{', b'}', b'{'])]
+    fn decode_invalid_response(#[case] data: &[u8]) {
+        assert!(matches!(
+            Protocol::V2.decode_response(data),
+            Err(WasiRuntimeError::InvalidFrame(_))
+        ));
+    }
+}
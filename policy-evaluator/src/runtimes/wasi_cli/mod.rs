@@ -1,4 +1,6 @@
 pub mod errors;
+mod framing;
+mod read_only_dir;
 mod runtime;
 mod stack;
 mod stack_pre;
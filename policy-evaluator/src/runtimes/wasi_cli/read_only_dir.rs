@@ -0,0 +1,146 @@
+use std::any::Any;
+use std::path::PathBuf;
+
+use wasi_common::{
+    dir::{OpenResult, ReaddirCursor, ReaddirEntity, WasiDir},
+    file::{FdFlags, Filestat, OFlags},
+    Error, ErrorExt,
+};
+
+/// A directory of the host preopened inside of a WASI policy, which can only be read.
+///
+/// The files can be opened only for reading, the operations changing the contents of
+/// the directory (creating files and directories, renaming, removing, linking,...)
+/// are refused. The sub-directories are read-only too.
+pub(crate) struct ReadOnlyDir(Box<dyn WasiDir>);
+
+impl ReadOnlyDir {
+    pub(crate) fn new(dir: wasi_common::sync::Dir) -> Self {
+        Self(Box::new(wasi_common::sync::dir::Dir::from_cap_std(dir)))
+    }
+}
+
+#[async_trait::async_trait]
+impl WasiDir for ReadOnlyDir {
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    async fn open_file(
+        &self,
+        symlink_follow: bool,
+        path: &str,
+        oflags: OFlags,
+        read: bool,
+        write: bool,
+        fdflags: FdFlags,
+    ) -> Result<OpenResult, Error> {
+        if write
+            || oflags.intersects(OFlags::CREATE | OFlags::EXCLUSIVE | OFlags::TRUNCATE)
+            || fdflags.contains(FdFlags::APPEND)
+        {
+            return Err(Error::perm());
+        }
+
+        match self
+            .0
+            .open_file(symlink_follow, path, oflags, read, write, fdflags)
+            .await?
+        {
+            OpenResult::Dir(dir) => Ok(OpenResult::Dir(Box::new(ReadOnlyDir(dir)))),
+            file => Ok(file),
+        }
+    }
+
+    async fn readdir(
+        &self,
+        cursor: ReaddirCursor,
+    ) -> Result<Box<dyn Iterator<Item = Result<ReaddirEntity, Error>> + Send>, Error> {
+        self.0.readdir(cursor).await
+    }
+
+    async fn read_link(&self, path: &str) -> Result<PathBuf, Error> {
+        self.0.read_link(path).await
+    }
+
+    async fn get_filestat(&self) -> Result<Filestat, Error> {
+        self.0.get_filestat().await
+    }
+
+    async fn get_path_filestat(
+        &self,
+        path: &str,
+        follow_symlinks: bool,
+    ) -> Result<Filestat, Error> {
+        self.0.get_path_filestat(path, follow_symlinks).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::executor::block_on;
+
+    fn read_only_dir(path: &std::path::Path) -> ReadOnlyDir {
+        ReadOnlyDir::new(
+            wasi_common::sync::Dir::open_ambient_dir(path, wasi_common::sync::ambient_authority())
+                .expect("cannot open directory"),
+        )
+    }
+
+    #[test]
+    fn files_can_be_read() {
+        let tmp = tempfile::tempdir().unwrap();
+        std::fs::write(tmp.path().join("registries.csv"), "ghcr.io\n").unwrap();
+        std::fs::create_dir(tmp.path().join("nested")).unwrap();
+        let dir = read_only_dir(tmp.path());
+
+        let file = block_on(dir.open_file(
+            false,
+            "registries.csv",
+            OFlags::empty(),
+            true,
+            false,
+            FdFlags::empty(),
+        ));
+        assert!(matches!(file, Ok(OpenResult::File(_))));
+
+        let nested = block_on(dir.open_file(
+            false,
+            "nested",
+            OFlags::DIRECTORY,
+            true,
+            false,
+            FdFlags::empty(),
+        ));
+        assert!(matches!(nested, Ok(OpenResult::Dir(_))));
+    }
+
+    #[test]
+    fn files_cannot_be_written() {
+        let tmp = tempfile::tempdir().unwrap();
+        std::fs::write(tmp.path().join("registries.csv"), "ghcr.io\n").unwrap();
+        let dir = read_only_dir(tmp.path());
+
+        for (path, oflags, write, fdflags) in [
+            ("registries.csv", OFlags::empty(), true, FdFlags::empty()),
+            ("registries.csv", OFlags::TRUNCATE, false, FdFlags::empty()),
+            ("registries.csv", OFlags::empty(), false, FdFlags::APPEND),
+            ("new.csv", OFlags::CREATE, false, FdFlags::empty()),
+        ] {
+            assert!(
+                block_on(dir.open_file(false, path, oflags, true, write, fdflags)).is_err(),
+                "{path} opened with {oflags:?} {fdflags:?}"
+            );
+        }
+
+        assert!(block_on(dir.create_dir("nested")).is_err());
+        assert!(block_on(dir.unlink_file("registries.csv")).is_err());
+        assert_eq!(
+            std::fs::read_to_string(tmp.path().join("registries.csv")).unwrap(),
+            "ghcr.io\n"
+        );
+        assert!(!tmp.path().join("new.csv").exists());
+        assert!(!tmp.path().join("nested").exists());
+    }
+}
@@ -5,6 +5,9 @@ use tracing::{error, warn};
 
 use crate::admission_response::AdmissionResponse;
 use crate::policy_evaluator::{PolicySettings, ValidateRequest};
+use crate::policy_evaluator_builder::GuestFunction;
+use crate::runtimes::wasi_cli::errors::WasiRuntimeError;
+use crate::runtimes::wasi_cli::framing::Protocol;
 use crate::runtimes::wasi_cli::stack::{RunResult, Stack};
 
 pub(crate) struct Runtime<'a>(pub(crate) &'a Stack);
@@ -15,12 +18,8 @@ impl Runtime<'_> {
         settings: &PolicySettings,
         request: &ValidateRequest,
     ) -> AdmissionResponse {
-        let validate_params = json!({
-            "request": request,
-            "settings": settings,
-        });
-
-        let input = match serde_json::to_vec(&validate_params) {
+        let protocol = self.0.protocol();
+        let input = match validation_input(protocol, settings, request) {
             Ok(s) => s,
             Err(e) => {
                 error!(
@@ -35,7 +34,11 @@ impl Runtime<'_> {
         };
         let args = ["policy.wasm", "validate"];
 
-        match self.0.run(&input, &args) {
+        match self
+            .0
+            .run(&input, &args, GuestFunction::Validation)
+            .and_then(|result| decode_stdout(protocol, result))
+        {
             Ok(RunResult { stdout, stderr }) => {
                 if !stderr.is_empty() {
                     warn!(
@@ -45,12 +48,14 @@ impl Runtime<'_> {
                         stderr
                     )
                 }
-                match serde_json::from_slice::<PolicyValidationResponse>(stdout.as_bytes()) {
+                match serde_json::from_slice::<PolicyValidationResponse>(&stdout) {
                     Ok(pvr) => {
                         let req_json_value = serde_json::to_value(request)
                             .expect("cannot convert request to json value");
                         let req_obj = match request {
-                            ValidateRequest::Raw(_) => Some(&req_json_value),
+                            ValidateRequest::Raw(_) | ValidateRequest::CloudEvent(_) => {
+                                Some(&req_json_value)
+                            }
                             ValidateRequest::AdmissionRequest(_) => req_json_value.get("object"),
                         };
 
@@ -72,30 +77,96 @@ impl Runtime<'_> {
                     ),
                 }
             }
-            Err(e) => AdmissionResponse::reject(request.uid().to_string(), e.to_string(), 500),
+            Err(e) if e.is_interrupted() => AdmissionResponse::reject_timeout(
+                request.uid().to_string(),
+                "guest code interrupted, execution deadline exceeded".to_owned(),
+            ),
+            Err(e) => AdmissionResponse::reject(request.uid().to_string(), error_message(&e), 500),
         }
     }
 
     pub fn validate_settings(&self, settings: String) -> SettingsValidationResponse {
         let args = ["policy.wasm", "validate-settings"];
+        let protocol = self.0.protocol();
+        let input = match protocol.encode(&[settings.as_bytes()]) {
+            Ok(input) => input,
+            Err(e) => {
+                return SettingsValidationResponse {
+                    valid: false,
+                    message: Some(e.to_string()),
+                }
+            }
+        };
 
-        match self.0.run(settings.as_bytes(), &args) {
+        match self
+            .0
+            .run(&input, &args, GuestFunction::SettingsValidation)
+            .and_then(|result| decode_stdout(protocol, result))
+        {
             Ok(RunResult { stdout, stderr }) => {
                 if !stderr.is_empty() {
                     warn!(operation = "validate-settings", "stderr: {:?}", stderr)
                 }
-                serde_json::from_slice::<SettingsValidationResponse>(stdout.as_bytes())
-                    .unwrap_or_else(|e| SettingsValidationResponse {
+                serde_json::from_slice::<SettingsValidationResponse>(&stdout).unwrap_or_else(|e| {
+                    SettingsValidationResponse {
                         valid: false,
                         message: Some(format!(
                             "Cannot deserialize settings validation response: {e}"
                         )),
-                    })
+                    }
+                })
             }
             Err(e) => SettingsValidationResponse {
                 valid: false,
-                message: Some(e.to_string()),
+                message: Some(error_message(&e)),
             },
         }
     }
 }
+
+/// The message of the error, followed by the summary of the trap raised by the
+/// guest, if any. The whole backtrace of the trap is logged at debug level
+fn error_message(error: &WasiRuntimeError) -> String {
+    let message = error.to_string();
+    match error.trap() {
+        Some(trap) => {
+            trap.log();
+            if message.is_empty() {
+                trap.to_string()
+            } else {
+                format!("{message}\n{trap}")
+            }
+        }
+        None => message,
+    }
+}
+
+/// The data written to the STDIN of the policy to validate the given request. The first
+/// version of the protocol sends a single JSON document, holding both the request and the
+/// settings, the second one sends them as two distinct frames
+fn validation_input(
+    protocol: Protocol,
+    settings: &PolicySettings,
+    request: &ValidateRequest,
+) -> Result<Vec<u8>, String> {
+    match protocol {
+        Protocol::V1 => serde_json::to_vec(&json!({
+            "request": request,
+            "settings": settings,
+        }))
+        .map_err(|e| e.to_string()),
+        Protocol::V2 => {
+            let request = serde_json::to_vec(request).map_err(|e| e.to_string())?;
+            let settings = serde_json::to_vec(settings).map_err(|e| e.to_string())?;
+            protocol
+                .encode(&[&request, &settings])
+                .map_err(|e| e.to_string())
+        }
+    }
+}
+
+/// Extract the response of the policy from the data it wrote to STDOUT
+fn decode_stdout(protocol: Protocol, mut result: RunResult) -> Result<RunResult, WasiRuntimeError> {
+    result.stdout = protocol.decode_response(&result.stdout)?.to_vec();
+    Ok(result)
+}
@@ -6,8 +6,9 @@ use wasi_common::sync::WasiCtxBuilder;
 use wasi_common::WasiCtx;
 
 use crate::evaluation_context::EvaluationContext;
+use crate::policy_evaluator_builder::GuestFunction;
 use crate::runtimes::wasi_cli::{
-    errors::WasiRuntimeError, stack_pre::StackPre, wasi_pipe::WasiPipe,
+    errors::WasiRuntimeError, framing::Protocol, stack_pre::StackPre, wasi_pipe::WasiPipe,
 };
 
 const EXIT_SUCCESS: i32 = 0;
@@ -16,6 +17,8 @@ pub(crate) struct Context {
     pub(crate) wasi_ctx: WasiCtx,
     pub(crate) stdin_pipe: Arc<RwLock<WasiPipe>>,
     pub(crate) eval_ctx: Arc<EvaluationContext>,
+    /// The protocol used to write the responses of the host capabilities to STDIN
+    pub(crate) protocol: Protocol,
 }
 
 pub(crate) struct Stack {
@@ -24,7 +27,7 @@ pub(crate) struct Stack {
 }
 
 pub(crate) struct RunResult {
-    pub stdout: String,
+    pub stdout: Vec<u8>,
     pub stderr: String,
 }
 
@@ -36,11 +39,18 @@ impl Stack {
         }
     }
 
-    /// Run a WASI program with the given input and args
+    /// The protocol used to exchange data with the policy
+    pub(crate) fn protocol(&self) -> Protocol {
+        self.stack_pre.protocol()
+    }
+
+    /// Run a WASI program with the given input and args. The epoch deadline
+    /// of the given guest function is enforced
     pub(crate) fn run(
         &self,
         input: &[u8],
         args: &[&str],
+        function: GuestFunction,
     ) -> std::result::Result<RunResult, WasiRuntimeError> {
         let stdout_pipe = WritePipe::new_in_memory();
         let stderr_pipe = WritePipe::new_in_memory();
@@ -55,13 +65,15 @@ impl Stack {
             .stdout(Box::new(stdout_pipe.clone()))
             .stderr(Box::new(stderr_pipe.clone()))
             .build();
+        self.stack_pre.mount_preopened_dirs(&wasi_ctx)?;
         let ctx = Context {
             wasi_ctx,
             stdin_pipe,
             eval_ctx: self.eval_ctx.clone(),
+            protocol: self.stack_pre.protocol(),
         };
 
-        let mut store = self.stack_pre.build_store(ctx);
+        let mut store = self.stack_pre.build_store(ctx, function);
         let instance = self.stack_pre.rehydrate(&mut store)?;
         let start_fn = instance
             .get_typed_func::<(), ()>(&mut store, "_start")
@@ -77,7 +89,7 @@ impl Stack {
         if let Err(err) = evaluation_result {
             if let Some(exit_error) = err.downcast_ref::<wasi_common::I32Exit>() {
                 if exit_error.0 == EXIT_SUCCESS {
-                    let stdout = pipe_to_string("stdout", stdout_pipe)?;
+                    let stdout = pipe_to_bytes("stdout", stdout_pipe)?;
                     return Ok(RunResult { stdout, stderr });
                 } else {
                     debug!(
@@ -93,7 +105,7 @@ impl Stack {
             return Err(WasiRuntimeError::WasiEvaluation { stderr, error: err });
         }
 
-        let stdout = pipe_to_string("stdout", stdout_pipe)?;
+        let stdout = pipe_to_bytes("stdout", stdout_pipe)?;
         Ok(RunResult { stdout, stderr })
     }
 }
@@ -102,14 +114,19 @@ fn pipe_to_string(
     name: &str,
     pipe: WritePipe<Cursor<Vec<u8>>>,
 ) -> std::result::Result<String, WasiRuntimeError> {
+    let buf = pipe_to_bytes(name, pipe)?;
+    String::from_utf8(buf).map_err(|e| WasiRuntimeError::PipeConversion {
+        name: name.to_string(),
+        error: format!("Cannot convert buffer to UTF8 string: {e}"),
+    })
+}
+
+fn pipe_to_bytes(
+    name: &str,
+    pipe: WritePipe<Cursor<Vec<u8>>>,
+) -> std::result::Result<Vec<u8>, WasiRuntimeError> {
     match pipe.try_into_inner() {
-        Ok(cursor) => {
-            let buf = cursor.into_inner();
-            String::from_utf8(buf).map_err(|e| WasiRuntimeError::PipeConversion {
-                name: name.to_string(),
-                error: format!("Cannot convert buffer to UTF8 string: {e}"),
-            })
-        }
+        Ok(cursor) => Ok(cursor.into_inner()),
         Err(_) => Err(WasiRuntimeError::PipeConversion {
             name: name.to_string(),
             error: "cannot convert pipe into inner".to_string(),
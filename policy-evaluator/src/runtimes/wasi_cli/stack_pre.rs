@@ -1,11 +1,21 @@
+use std::collections::BTreeMap;
 use std::io::Write;
+use std::path::PathBuf;
+use std::sync::Arc;
 
 use wasmtime::{AsContext, Engine, InstancePre, Linker, Memory, Module, StoreContext};
 
 use crate::runtimes::wasi_cli::errors::{Result, WasiRuntimeError};
 
-use crate::policy_evaluator_builder::EpochDeadlines;
-use crate::runtimes::{callback::host_callback, wasi_cli::stack::Context};
+use crate::policy_evaluator_builder::{EpochDeadlines, GuestFunction};
+use crate::runtimes::{
+    callback::host_callback,
+    wasi_cli::{
+        framing::{self, Protocol},
+        read_only_dir::ReadOnlyDir,
+        stack::Context,
+    },
+};
 
 /// Reduce the allocation time of a Wasi Stack. This is done by leveraging `wasmtime::InstancePre`.
 #[derive(Clone)]
@@ -13,6 +23,10 @@ pub(crate) struct StackPre {
     engine: Engine,
     instance_pre: InstancePre<Context>,
     epoch_deadlines: Option<EpochDeadlines>,
+    protocol: Protocol,
+    /// The directories of the host made available to the policy, indexed by
+    /// their path inside of the guest
+    preopened_dirs: Arc<Vec<(String, wasi_common::sync::Dir)>>,
 }
 
 impl StackPre {
@@ -20,27 +34,73 @@ impl StackPre {
         engine: Engine,
         module: Module,
         epoch_deadlines: Option<EpochDeadlines>,
+        preopened_dirs: &BTreeMap<String, PathBuf>,
     ) -> Result<Self> {
         let mut linker = Linker::<Context>::new(&engine);
         wasi_common::sync::add_to_linker(&mut linker, |c: &mut Context| &mut c.wasi_ctx)
             .map_err(WasiRuntimeError::WasmLinkerError)?;
         add_host_call_to_linker(&mut linker)?;
+        let protocol = Protocol::detect(&module);
 
         let instance_pre = linker
             .instantiate_pre(&module)
             .map_err(WasiRuntimeError::WasmInstantiate)?;
+        let preopened_dirs = preopened_dirs
+            .iter()
+            .map(|(guest_path, host_path)| {
+                wasi_common::sync::Dir::open_ambient_dir(
+                    host_path,
+                    wasi_common::sync::ambient_authority(),
+                )
+                .map(|dir| (guest_path.to_owned(), dir))
+                .map_err(|error| WasiRuntimeError::PreopenedDir {
+                    guest_path: guest_path.to_owned(),
+                    host_path: host_path.display().to_string(),
+                    error,
+                })
+            })
+            .collect::<Result<Vec<_>>>()?;
+
         Ok(Self {
             engine,
             instance_pre,
             epoch_deadlines,
+            protocol,
+            preopened_dirs: Arc::new(preopened_dirs),
         })
     }
 
-    /// Create a brand new `wasmtime::Store` to be used during an evaluation
-    pub(crate) fn build_store(&self, ctx: Context) -> wasmtime::Store<Context> {
+    /// Mount the preopened directories, in read-only mode, inside of the given
+    /// WASI context
+    pub(crate) fn mount_preopened_dirs(&self, wasi_ctx: &wasi_common::WasiCtx) -> Result<()> {
+        for (guest_path, dir) in self.preopened_dirs.iter() {
+            let mount_error = |error: String| WasiRuntimeError::PreopenedDirMount {
+                guest_path: guest_path.to_owned(),
+                error,
+            };
+            let dir = dir.try_clone().map_err(|e| mount_error(e.to_string()))?;
+            wasi_ctx
+                .push_preopened_dir(Box::new(ReadOnlyDir::new(dir)), guest_path)
+                .map_err(|e| mount_error(e.to_string()))?;
+        }
+        Ok(())
+    }
+
+    /// The protocol used to exchange data with the policy
+    pub(crate) fn protocol(&self) -> Protocol {
+        self.protocol
+    }
+
+    /// Create a brand new `wasmtime::Store` to be used during the invocation
+    /// of the given guest function
+    pub(crate) fn build_store(
+        &self,
+        ctx: Context,
+        function: GuestFunction,
+    ) -> wasmtime::Store<Context> {
         let mut store = wasmtime::Store::new(&self.engine, ctx);
-        if let Some(deadline) = self.epoch_deadlines {
-            store.set_epoch_deadline(deadline.wapc_func);
+        if let Some(deadlines) = self.epoch_deadlines {
+            store.set_epoch_deadline(deadlines.guest_function(function));
         }
 
         store
@@ -96,10 +156,15 @@ fn add_host_call_to_linker(linker: &mut wasmtime::Linker<Context>) -> Result<()>
                 // return 1 if the host callback failed, 0 otherwise
                 let func_return_value = host_callback_response.is_err() as i32;
 
-                let response_msg = match host_callback_response {
+                let mut response_msg = match host_callback_response {
                     Ok(r) => r,
                     Err(e) => e.to_string().as_bytes().to_owned(),
                 };
+                if caller.data().protocol == Protocol::V2 {
+                    let mut frame = Vec::new();
+                    framing::encode_frame(&response_msg, &mut frame)?;
+                    response_msg = frame;
+                }
 
                 let mut stdin_pipe = stdin
                     .write()
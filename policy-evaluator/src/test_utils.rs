@@ -0,0 +1,365 @@
+//! Helpers to test code that embeds policy-evaluator.
+//!
+//! This module is available only when the `test-utils` feature is enabled.
+//! It provides:
+//!
+//! * helpers to build `AdmissionRequest` objects from fixtures
+//! * a callback handler replying to the policies with scripted responses,
+//!   removing the need to implement the channel plumbing inside of each test
+//! * assertion helpers to check the patches produced by mutating policies
+
+use std::{collections::VecDeque, fmt, path::Path};
+
+use anyhow::{anyhow, Result};
+use base64::{engine::general_purpose, Engine as _};
+use serde::Serialize;
+use tokio::{sync::mpsc, task::JoinHandle};
+
+use crate::{
+    admission_request::{AdmissionRequest, AdmissionRequestBuilder},
+    admission_response::AdmissionResponse,
+    callback_requests::{CallbackRequest, CallbackRequestType, CallbackResponse},
+    policy_metadata::Operation,
+};
+
+/// Hard-coded UID used by the admission requests built by [`admission_request_for_object`]
+pub const ADMISSION_REQUEST_UID: &str = crate::admission_request::DEFAULT_ADMISSION_REQUEST_UID;
+
+/// Load an `AdmissionRequest` from a JSON file. The file can contain either a
+/// whole `AdmissionReview` object or just its `request` field.
+pub fn admission_request_from_file(path: impl AsRef<Path>) -> Result<AdmissionRequest> {
+    let path = path.as_ref();
+    let contents =
+        std::fs::read(path).map_err(|e| anyhow!("cannot read fixture from path: {path:?}: {e}"))?;
+    let mut value: serde_json::Value = serde_json::from_slice(&contents)
+        .map_err(|e| anyhow!("cannot parse fixture {path:?}: {e}"))?;
+
+    if let Some(request) = value.get_mut("request") {
+        value = request.take();
+    }
+    serde_json::from_value(value)
+        .map_err(|e| anyhow!("fixture {path:?} is not a valid AdmissionRequest: {e}"))
+}
+
+/// Build an `AdmissionRequest` performing the given `operation` (e.g. `CREATE`) against
+/// `object`.
+///
+/// The kind, the name and the Namespace of the request are taken from the object. The
+/// plural name of the resource is guessed from its kind, which is good enough for most
+/// of the policies. The object is always set as the `object` of the request, regardless
+/// of the operation. See [`AdmissionRequestBuilder`] to build requests that provide the
+/// objects expected by their operation.
+pub fn admission_request_for_object(
+    operation: &str,
+    object: serde_json::Value,
+) -> Result<AdmissionRequest> {
+    let request = AdmissionRequestBuilder::from_json(Operation::Create, object)?.build()?;
+    Ok(AdmissionRequest {
+        operation: operation.to_owned(),
+        ..request
+    })
+}
+
+type CallbackRequestMatcher = Box<dyn Fn(&CallbackRequestType) -> bool + Send>;
+
+/// An entry of the script followed by [`ScriptedCallbackHandler`]
+struct ScriptedResponse {
+    description: String,
+    matcher: CallbackRequestMatcher,
+    response: std::result::Result<CallbackResponse, String>,
+}
+
+/// A callback handler that replies to the requests made by the policies with
+/// the responses defined upfront.
+///
+/// Each entry of the script is used once, regardless of the order in which the
+/// requests are received. Requests that do not match any entry of the script are
+/// answered with an error.
+///
+/// ```ignore
+/// let (callback_tx, handler) = ScriptedCallbackHandler::new()
+///     .respond_to(
+///         CallbackRequestType::KubernetesGetResourcePluralName {
+///             api_version: "v1".to_string(),
+///             kind: "Service".to_string(),
+///         },
+///         &"services",
+///     )
+///     .spawn();
+/// // evaluate the policy using `callback_tx`, then drop it
+/// handler.await.unwrap().assert_completed();
+/// ```
+#[derive(Default)]
+pub struct ScriptedCallbackHandler {
+    script: Vec<ScriptedResponse>,
+}
+
+impl ScriptedCallbackHandler {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Reply with `payload`, serialized to JSON, to the given request
+    pub fn respond_to(self, request: CallbackRequestType, payload: &impl Serialize) -> Self {
+        let description = format!("{request:?}");
+        self.respond_to_matching(&description, move |received| received == &request, payload)
+    }
+
+    /// Reply with `payload`, serialized to JSON, to the first request accepted by `matcher`.
+    /// The `description` is used when reporting the entries of the script that have not
+    /// been used.
+    pub fn respond_to_matching<F>(
+        mut self,
+        description: &str,
+        matcher: F,
+        payload: &impl Serialize,
+    ) -> Self
+    where
+        F: Fn(&CallbackRequestType) -> bool + Send + 'static,
+    {
+        let payload = serde_json::to_vec(payload).expect("cannot serialize scripted payload");
+        self.script.push(ScriptedResponse {
+            description: description.to_owned(),
+            matcher: Box::new(matcher),
+            response: Ok(CallbackResponse { payload }),
+        });
+        self
+    }
+
+    /// Reply with an error to the given request
+    pub fn fail(mut self, request: CallbackRequestType, message: &str) -> Self {
+        self.script.push(ScriptedResponse {
+            description: format!("{request:?}"),
+            matcher: Box::new(move |received| received == &request),
+            response: Err(message.to_owned()),
+        });
+        self
+    }
+
+    /// Start serving the requests inside of a tokio task. The task terminates once
+    /// all the senders of the returned channel are dropped, its outcome describes
+    /// how the script has been used.
+    pub fn spawn(
+        self,
+    ) -> (
+        mpsc::Sender<CallbackRequest>,
+        JoinHandle<CallbackScriptReport>,
+    ) {
+        let (tx, mut rx) = mpsc::channel::<CallbackRequest>(10);
+        let mut script: VecDeque<ScriptedResponse> = self.script.into();
+
+        let handle = tokio::spawn(async move {
+            let mut report = CallbackScriptReport::default();
+
+            while let Some(req) = rx.recv().await {
+                let response = match script
+                    .iter()
+                    .position(|entry| (entry.matcher)(&req.request))
+                {
+                    Some(index) => script
+                        .remove(index)
+                        .expect("the entry must exist")
+                        .response
+                        .map_err(|e| anyhow!(e)),
+                    None => {
+                        report.unexpected.push(format!("{:?}", req.request));
+                        Err(anyhow!("unexpected callback request: {:?}", req.request))
+                    }
+                };
+                report.received.push(req.request);
+                // the receiver could be gone, e.g. because the evaluation has been interrupted
+                let _ = req.response_channel.send(response);
+            }
+
+            report.unused = script.into_iter().map(|entry| entry.description).collect();
+            report
+        });
+
+        (tx, handle)
+    }
+}
+
+/// Describes how the script of a [`ScriptedCallbackHandler`] has been used
+#[derive(Debug, Default)]
+pub struct CallbackScriptReport {
+    /// All the requests that have been received, in order
+    pub received: Vec<CallbackRequestType>,
+    /// The requests that did not match any entry of the script
+    pub unexpected: Vec<String>,
+    /// The entries of the script that have not been used
+    pub unused: Vec<String>,
+}
+
+impl CallbackScriptReport {
+    /// Panic unless all the entries of the script have been used, and no unexpected
+    /// request has been received
+    pub fn assert_completed(&self) {
+        assert!(
+            self.unexpected.is_empty() && self.unused.is_empty(),
+            "callback script not completed: {self}"
+        );
+    }
+}
+
+impl fmt::Display for CallbackScriptReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "unexpected requests: [{}], unused responses: [{}]",
+            self.unexpected.join(", "),
+            self.unused.join(", ")
+        )
+    }
+}
+
+/// Apply the JSON patch of the given response to `object`. When the response doesn't
+/// have a patch, the object is returned unchanged.
+pub fn patched_object(
+    object: &serde_json::Value,
+    response: &AdmissionResponse,
+) -> Result<serde_json::Value> {
+    let mut patched = object.clone();
+    if let Some(patch) = &response.patch {
+        let patch = general_purpose::STANDARD
+            .decode(patch)
+            .map_err(|e| anyhow!("patch is not base64 encoded: {e}"))?;
+        let patch: json_patch::Patch = serde_json::from_slice(&patch)
+            .map_err(|e| anyhow!("patch is not a valid JSON patch: {e}"))?;
+        json_patch::patch(&mut patched, &patch).map_err(|e| anyhow!("cannot apply patch: {e}"))?;
+    }
+
+    Ok(patched)
+}
+
+/// Panic unless applying the patch of `response` to `object` produces `expected`
+pub fn assert_patched_object(
+    object: &serde_json::Value,
+    response: &AdmissionResponse,
+    expected: &serde_json::Value,
+) {
+    assert!(
+        response.patch.is_some(),
+        "the response doesn't contain a patch"
+    );
+    let patched = patched_object(object, response).expect("cannot apply patch");
+    assert_eq!(
+        &patched,
+        expected,
+        "unexpected patched object:\n{}\nexpected:\n{}",
+        serde_json::to_string_pretty(&patched).unwrap_or_default(),
+        serde_json::to_string_pretty(expected).unwrap_or_default(),
+    );
+}
+
+/// Panic if the response contains a patch
+pub fn assert_not_mutated(response: &AdmissionResponse) {
+    assert!(
+        response.patch.is_none() && response.patch_type.is_none(),
+        "the response is not expected to mutate the object"
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn build_admission_request_for_object() {
+        let request = admission_request_for_object(
+            "CREATE",
+            json!({
+                "apiVersion": "apps/v1",
+                "kind": "Deployment",
+                "metadata": { "name": "nginx", "namespace": "team-a" },
+            }),
+        )
+        .unwrap();
+
+        assert_eq!(request.operation, "CREATE");
+        assert_eq!(request.kind.group, "apps");
+        assert_eq!(request.kind.version, "v1");
+        assert_eq!(request.resource.resource, "deployments");
+        assert_eq!(request.name.as_deref(), Some("nginx"));
+        assert_eq!(request.namespace.as_deref(), Some("team-a"));
+
+        assert!(admission_request_for_object("CREATE", json!({"apiVersion": "v1"})).is_err());
+
+        let object = json!({"apiVersion": "v1", "kind": "Pod"});
+        for operation in ["UPDATE", "DELETE"] {
+            let request = admission_request_for_object(operation, object.clone()).unwrap();
+            assert_eq!(request.operation, operation);
+            assert_eq!(request.object.unwrap().0, object);
+            assert!(request.old_object.is_none());
+        }
+    }
+
+    #[test]
+    fn load_admission_request_from_file() {
+        let request = admission_request_from_file(
+            Path::new(env!("CARGO_MANIFEST_DIR"))
+                .join("tests/data/pod_with_privileged_containers.json"),
+        )
+        .unwrap();
+        assert_eq!(request.kind.kind, "Pod");
+    }
+
+    #[test]
+    fn check_patches() {
+        let object = json!({"metadata": {"name": "nginx"}});
+        let mutated = json!({"metadata": {"name": "nginx", "labels": {"team": "a"}}});
+        let patch = serde_json::to_string(&json_patch::diff(&object, &mutated)).unwrap();
+        let response = AdmissionResponse {
+            patch: Some(general_purpose::STANDARD.encode(patch)),
+            patch_type: Some(crate::admission_response::PatchType::JSONPatch),
+            ..Default::default()
+        };
+
+        assert_patched_object(&object, &response, &mutated);
+        assert_not_mutated(&AdmissionResponse::default());
+        assert_eq!(
+            patched_object(&object, &AdmissionResponse::default()).unwrap(),
+            object
+        );
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn scripted_callback_handler() {
+        let dns_lookup = || CallbackRequestType::DNSLookupHost {
+            host: "localhost".to_string(),
+        };
+        let (callback_tx, handler) = ScriptedCallbackHandler::new()
+            .respond_to(dns_lookup(), &vec!["127.0.0.1"])
+            .spawn();
+
+        let send = |request: CallbackRequestType| {
+            let callback_tx = callback_tx.clone();
+            async move {
+                let (tx, rx) = tokio::sync::oneshot::channel();
+                callback_tx
+                    .send(CallbackRequest {
+                        request,
+                        response_channel: tx,
+                        kubernetes_service_account: None,
+                    })
+                    .await
+                    .unwrap();
+                rx.await.unwrap()
+            }
+        };
+
+        let response = send(dns_lookup()).await.unwrap();
+        assert_eq!(
+            serde_json::from_slice::<Vec<String>>(&response.payload).unwrap(),
+            vec!["127.0.0.1"]
+        );
+        // the entry of the script has been consumed already
+        assert!(send(dns_lookup()).await.is_err());
+        drop(callback_tx);
+
+        let report = handler.await.unwrap();
+        assert_eq!(report.received.len(), 2);
+        assert_eq!(report.unexpected.len(), 1);
+        assert!(report.unused.is_empty());
+    }
+}
@@ -0,0 +1,198 @@
+//! Details about the traps raised by the policies.
+//!
+//! When the guest traps, wasmtime attaches the kind of the trap and the wasm
+//! backtrace to the error it returns. The functions of the backtrace are named
+//! only when the module has a name section, otherwise just their index is known.
+
+use std::fmt;
+
+use serde::Serialize;
+use tracing::debug;
+
+/// A frame of the backtrace of a trap
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct WasmFrame {
+    /// Index of the function inside of the module
+    pub func_index: u32,
+    /// Name of the function, available only when the module has a name section
+    pub func_name: Option<String>,
+    /// Name of the module, available only when the module has a name section
+    pub module_name: Option<String>,
+    /// Offset of the instruction inside of the module
+    pub module_offset: Option<usize>,
+}
+
+impl WasmFrame {
+    /// The name of the function, falling back to its index when the module
+    /// doesn't have a name section
+    pub fn function(&self) -> String {
+        self.func_name
+            .clone()
+            .unwrap_or_else(|| format!("<wasm function {}>", self.func_index))
+    }
+}
+
+impl From<&wasmtime::FrameInfo> for WasmFrame {
+    fn from(frame: &wasmtime::FrameInfo) -> Self {
+        WasmFrame {
+            func_index: frame.func_index(),
+            func_name: frame.func_name().map(str::to_owned),
+            module_name: frame.module().name().map(str::to_owned),
+            module_offset: frame.module_offset(),
+        }
+    }
+}
+
+impl fmt::Display for WasmFrame {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}!{}",
+            self.module_name.as_deref().unwrap_or("<unknown>"),
+            self.function()
+        )?;
+        if let Some(offset) = self.module_offset {
+            write!(f, " (offset {offset:#x})")?;
+        }
+        Ok(())
+    }
+}
+
+/// A trap raised by the guest while it was being evaluated
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct WasmTrap {
+    /// What caused the trap, like an `unreachable` instruction. This is not set
+    /// when the guest has been stopped by an error raised by a host function
+    pub kind: Option<String>,
+    /// The guest function that was being executed when the trap happened
+    pub function: Option<String>,
+    /// Whether the guest has been interrupted because it exceeded its epoch deadline
+    pub epoch_deadline_exceeded: bool,
+    /// Whether the guest has consumed all its fuel
+    pub out_of_fuel: bool,
+    /// The backtrace of the guest, the innermost frame comes first
+    pub backtrace: Vec<WasmFrame>,
+}
+
+impl WasmTrap {
+    /// Extract the details of the trap from the error returned by wasmtime.
+    /// Returns `None` when the error has not been raised while running the guest
+    pub fn from_error(error: &wasmtime::Error) -> Option<WasmTrap> {
+        let trap = error.downcast_ref::<wasmtime::Trap>().copied();
+        let backtrace = error.downcast_ref::<wasmtime::WasmBacktrace>();
+        if trap.is_none() && backtrace.is_none() {
+            return None;
+        }
+
+        let backtrace: Vec<WasmFrame> = backtrace
+            .map(|backtrace| backtrace.frames().iter().map(WasmFrame::from).collect())
+            .unwrap_or_default();
+
+        Some(WasmTrap {
+            kind: trap.map(|trap| trap.to_string()),
+            function: backtrace.first().map(WasmFrame::function),
+            epoch_deadline_exceeded: trap == Some(wasmtime::Trap::Interrupt),
+            out_of_fuel: trap == Some(wasmtime::Trap::OutOfFuel),
+            backtrace,
+        })
+    }
+
+    /// Log the trap, together with its whole backtrace, at debug level
+    pub(crate) fn log(&self) {
+        debug!(
+            kind = self.kind.as_deref(),
+            function = self.function.as_deref(),
+            epoch_deadline_exceeded = self.epoch_deadline_exceeded,
+            out_of_fuel = self.out_of_fuel,
+            "policy trapped\n{self:#}"
+        );
+    }
+}
+
+/// The trap is written on a single line, the alternate form `{:#}` adds the
+/// backtrace, one frame per line
+impl fmt::Display for WasmTrap {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "wasm trap: {}",
+            self.kind.as_deref().unwrap_or("error raised by the host")
+        )?;
+        if let Some(function) = &self.function {
+            write!(f, ", inside of function {function}")?;
+        }
+
+        if f.alternate() {
+            write!(f, "\nwasm backtrace:")?;
+            for (index, frame) in self.backtrace.iter().enumerate() {
+                write!(f, "\n  {index:>3}: {frame}")?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const WAT: &str = r#"
+        (module $policy
+            (func $validate (export "validate")
+                call $check)
+            (func $check
+                unreachable)
+            (func (export "anonymous")
+                unreachable))
+    "#;
+
+    fn trap(export: &str) -> WasmTrap {
+        let engine = wasmtime::Engine::default();
+        let module = wasmtime::Module::new(&engine, WAT).expect("cannot compile WAT to wasm");
+        let mut store = wasmtime::Store::new(&engine, ());
+        let instance =
+            wasmtime::Instance::new(&mut store, &module, &[]).expect("cannot instantiate module");
+        let func = instance
+            .get_typed_func::<(), ()>(&mut store, export)
+            .expect("cannot find export");
+
+        let error = func
+            .call(&mut store, ())
+            .expect_err("the guest should trap");
+        WasmTrap::from_error(&error).expect("the error should hold a trap")
+    }
+
+    #[test]
+    fn trap_with_symbols() {
+        let trap = trap("validate");
+
+        assert_eq!(
+            trap.kind,
+            Some(wasmtime::Trap::UnreachableCodeReached.to_string())
+        );
+        assert_eq!(trap.function.as_deref(), Some("check"));
+        assert!(!trap.epoch_deadline_exceeded);
+        assert!(!trap.out_of_fuel);
+        let functions: Vec<String> = trap.backtrace.iter().map(WasmFrame::function).collect();
+        assert_eq!(functions, vec!["check", "validate"]);
+        assert_eq!(trap.backtrace[0].module_name.as_deref(), Some("policy"));
+
+        let details = format!("{trap:#}");
+        assert!(details.contains("inside of function check\nwasm backtrace:"));
+        assert!(details.contains("0: policy!check"));
+        assert!(details.contains("1: policy!validate"));
+    }
+
+    #[test]
+    fn trap_without_symbols() {
+        let trap = trap("anonymous");
+
+        assert_eq!(trap.function.as_deref(), Some("<wasm function 2>"));
+        assert!(!format!("{trap}").contains("wasm backtrace"));
+    }
+
+    #[test]
+    fn error_without_trap() {
+        assert!(WasmTrap::from_error(&wasmtime::Error::msg("boom")).is_none());
+    }
+}
@@ -198,8 +198,13 @@ async fn test_policy_evaluator(
 
     let eval_ctx = EvaluationContext {
         policy_id: "test".to_owned(),
+        policy_stable_id: None,
         callback_channel: None,
         ctx_aware_resources_allow_list: Default::default(),
+        kubernetes_service_account: None,
+        request_context: Default::default(),
+        http_policy: None,
+        key_value_store: false,
     };
 
     let mut policy_evaluator = build_policy_evaluator(execution_mode, &policy, &eval_ctx);
@@ -297,6 +302,7 @@ async fn test_runtime_context_aware<F, Fut>(
 
     let eval_ctx = EvaluationContext {
         policy_id: "test".to_owned(),
+        policy_stable_id: None,
         callback_channel: Some(callback_handler_channel),
         ctx_aware_resources_allow_list: BTreeSet::from([
             ContextAwareResource {
@@ -312,6 +318,10 @@ async fn test_runtime_context_aware<F, Fut>(
                 kind: "Service".to_owned(),
             },
         ]),
+        kubernetes_service_account: None,
+        request_context: Default::default(),
+        http_policy: None,
+        key_value_store: false,
     };
 
     let request_data = load_request_data(request_file_path);
@@ -352,12 +362,18 @@ async fn test_oci_manifest_capability(
             image: policy_uri.to_owned(),
         },
         response_channel: tx,
+        kubernetes_service_account: None,
     };
 
     let eval_ctx = EvaluationContext {
         policy_id: "test".to_owned(),
+        policy_stable_id: None,
         callback_channel: Some(callback_handler_channel),
         ctx_aware_resources_allow_list: Default::default(),
+        kubernetes_service_account: None,
+        request_context: Default::default(),
+        http_policy: None,
+        key_value_store: false,
     };
 
     let cb_channel: mpsc::Sender<CallbackRequest> = eval_ctx
@@ -442,12 +458,18 @@ async fn test_oci_manifest_and_config_capability(
             image: policy_uri.to_owned(),
         },
         response_channel: tx,
+        kubernetes_service_account: None,
     };
 
     let eval_ctx = EvaluationContext {
         policy_id: "test".to_owned(),
+        policy_stable_id: None,
         callback_channel: Some(callback_handler_channel),
         ctx_aware_resources_allow_list: Default::default(),
+        kubernetes_service_account: None,
+        request_context: Default::default(),
+        http_policy: None,
+        key_value_store: false,
     };
 
     let cb_channel: mpsc::Sender<CallbackRequest> = eval_ctx
@@ -505,12 +527,18 @@ async fn test_oci_digest_capability() {
             image: "ghcr.io/kubewarden/tests/policy-server:v1.13.0".to_owned(),
         },
         response_channel: tx,
+        kubernetes_service_account: None,
     };
 
     let eval_ctx = EvaluationContext {
         policy_id: "test".to_owned(),
+        policy_stable_id: None,
         callback_channel: Some(callback_handler_channel),
         ctx_aware_resources_allow_list: Default::default(),
+        kubernetes_service_account: None,
+        request_context: Default::default(),
+        http_policy: None,
+        key_value_store: false,
     };
 
     let cb_channel: mpsc::Sender<CallbackRequest> = eval_ctx
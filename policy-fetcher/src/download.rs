@@ -0,0 +1,519 @@
+//! Download of multiple policies at the same time.
+//!
+//! The [`DownloadManager`] bounds the number of operations running at the same
+//! time, spaces out the operations made against the same registry and retries the
+//! ones rate limited by the remote server, or failed because of a transient
+//! error, with an exponential backoff.
+
+use std::{
+    collections::HashMap,
+    str::FromStr,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use futures::{stream, Future, StreamExt};
+use oci_client::Reference;
+use tokio::time::{self, Instant};
+use tracing::{debug, warn};
+
+use crate::{
+    errors::{FetcherError, FetcherResult},
+    fetch_policy,
+    policy::Policy,
+    sources::Sources,
+    verify::errors::VerifyError,
+    PullDestination,
+};
+
+/// Upper bound of the delay between two attempts of a throttled or failed
+/// operation, used when the server doesn't tell how long to wait
+const MAX_RETRY_BACKOFF: Duration = Duration::from_secs(30);
+
+/// Limits applied to the operations made against registries and HTTP servers
+/// while downloading and verifying multiple policies
+#[derive(Clone, Debug, PartialEq)]
+pub struct FetcherOptions {
+    /// Maximum number of policies downloaded and verified at the same time
+    pub concurrency: usize,
+    /// Minimum delay between two operations made against the same host
+    pub registry_delay: Duration,
+    /// How many times an operation rate limited by the remote server, or failed
+    /// because of a transient error, is retried
+    pub max_retries: u32,
+}
+
+impl Default for FetcherOptions {
+    fn default() -> Self {
+        FetcherOptions {
+            concurrency: 4,
+            registry_delay: Duration::ZERO,
+            max_retries: 3,
+        }
+    }
+}
+
+/// Errors of the operations that can be retried: the ones caused by the remote
+/// server rate limiting the requests, and the transient ones, like a connection
+/// that cannot be established, a timeout or a HTTP 5xx response
+pub trait RetryableError {
+    fn is_throttled(&self) -> bool;
+    fn is_transient(&self) -> bool;
+    fn retry_after(&self) -> Option<Duration>;
+}
+
+impl RetryableError for FetcherError {
+    fn is_throttled(&self) -> bool {
+        FetcherError::is_throttled(self)
+    }
+
+    fn is_transient(&self) -> bool {
+        FetcherError::is_transient(self)
+    }
+
+    fn retry_after(&self) -> Option<Duration> {
+        FetcherError::retry_after(self)
+    }
+}
+
+impl RetryableError for VerifyError {
+    fn is_throttled(&self) -> bool {
+        VerifyError::is_throttled(self)
+    }
+
+    fn is_transient(&self) -> bool {
+        VerifyError::is_transient(self)
+    }
+
+    fn retry_after(&self) -> Option<Duration> {
+        None
+    }
+}
+
+/// An operation that has been rate limited by the remote server
+#[derive(Clone, Debug, PartialEq)]
+pub struct ThrottledOperation {
+    /// The registry or HTTP server that throttled the operation
+    pub host: String,
+    /// The kind of operation, e.g. `fetch` or `verify`
+    pub operation: String,
+    /// Whether the operation is going to be retried
+    pub retried: bool,
+}
+
+/// Notified each time an operation is rate limited by the remote server
+pub type ThrottlingObserver = Arc<dyn Fn(&ThrottledOperation) + Send + Sync>;
+
+/// Runs the operations needed to download multiple policies, spacing out the ones
+/// made against the same remote host and retrying the ones that have been
+/// throttled by the remote server or failed because of a transient error
+pub struct DownloadManager {
+    options: FetcherOptions,
+    /// The earliest time a new operation can be started against each host
+    next_slots: Mutex<HashMap<String, Instant>>,
+    observer: Option<ThrottlingObserver>,
+}
+
+impl Default for DownloadManager {
+    fn default() -> Self {
+        DownloadManager::new(FetcherOptions::default())
+    }
+}
+
+impl DownloadManager {
+    pub fn new(options: FetcherOptions) -> Self {
+        DownloadManager {
+            options,
+            next_slots: Mutex::new(HashMap::new()),
+            observer: None,
+        }
+    }
+
+    /// Notify the given observer each time an operation is throttled, e.g. to
+    /// keep track of them with metrics
+    pub fn with_throttling_observer(mut self, observer: ThrottlingObserver) -> Self {
+        self.observer = Some(observer);
+        self
+    }
+
+    pub fn options(&self) -> &FetcherOptions {
+        &self.options
+    }
+
+    /// Download all the given policies, at most `concurrency` of them at the same
+    /// time. The same URL is downloaded only once.
+    ///
+    /// Returns the outcome of the download of each policy, keyed by URL
+    pub async fn fetch_policies<'a>(
+        &self,
+        urls: impl IntoIterator<Item = &'a str>,
+        destination: &PullDestination,
+        sources: Option<&Sources>,
+    ) -> HashMap<String, FetcherResult<Policy>> {
+        let mut urls: Vec<&str> = urls.into_iter().collect();
+        urls.sort_unstable();
+        urls.dedup();
+
+        stream::iter(urls)
+            .map(|url| async move {
+                let result = self.fetch_policy(url, destination, sources).await;
+                (url.to_owned(), result)
+            })
+            .buffer_unordered(self.options.concurrency.max(1))
+            .collect()
+            .await
+    }
+
+    /// Download a single policy, retrying when the remote server throttles the
+    /// requests or a transient error occurs
+    pub async fn fetch_policy(
+        &self,
+        url: &str,
+        destination: &PullDestination,
+        sources: Option<&Sources>,
+    ) -> FetcherResult<Policy> {
+        let host = remote_host(url);
+        self.run(host.as_deref(), "fetch", url, || {
+            fetch_policy(url, destination.clone(), sources)
+        })
+        .await
+    }
+
+    /// Run the operation, retrying it when it's throttled by the remote server or
+    /// it fails because of a transient error. Operations that do not target a
+    /// remote host are run straight away.
+    pub async fn run<T, E, F, Fut>(
+        &self,
+        host: Option<&str>,
+        operation: &str,
+        policy: &str,
+        mut op: F,
+    ) -> std::result::Result<T, E>
+    where
+        E: RetryableError + std::fmt::Display,
+        F: FnMut() -> Fut,
+        Fut: Future<Output = std::result::Result<T, E>>,
+    {
+        let mut attempt = 0;
+        loop {
+            if let Some(host) = host {
+                self.wait(host).await;
+            }
+
+            match op().await {
+                Err(e) if e.is_throttled() || e.is_transient() => {
+                    let retried = attempt < self.options.max_retries;
+                    if e.is_throttled() {
+                        if let Some(observer) = &self.observer {
+                            observer(&ThrottledOperation {
+                                host: host.unwrap_or_default().to_owned(),
+                                operation: operation.to_owned(),
+                                retried,
+                            });
+                        }
+                    }
+                    if !retried {
+                        return Err(e);
+                    }
+
+                    let delay = e.retry_after().unwrap_or_else(|| retry_backoff(attempt));
+                    attempt += 1;
+                    if e.is_throttled() {
+                        warn!(
+                            policy,
+                            host,
+                            operation,
+                            attempt,
+                            delay_ms = delay.as_millis() as u64,
+                            "remote server is rate limiting requests, retrying later"
+                        );
+                    } else {
+                        warn!(
+                            policy,
+                            host,
+                            operation,
+                            attempt,
+                            delay_ms = delay.as_millis() as u64,
+                            error = %e,
+                            "operation failed because of a transient error, retrying later"
+                        );
+                    }
+                    match host {
+                        Some(host) => self.back_off(host, delay),
+                        None => time::sleep(delay).await,
+                    }
+                }
+                result => return result,
+            }
+        }
+    }
+
+    /// Wait until a new operation can be started against the given host
+    async fn wait(&self, host: &str) {
+        let slot = {
+            let mut next_slots = self.next_slots.lock().expect("cannot lock next slots");
+            let now = Instant::now();
+            let slot = next_slots
+                .get(host)
+                .copied()
+                .filter(|slot| *slot > now)
+                .unwrap_or(now);
+            next_slots.insert(host.to_owned(), slot + self.options.registry_delay);
+            slot
+        };
+        if slot > Instant::now() {
+            debug!(host, "waiting before contacting the remote host");
+        }
+        time::sleep_until(slot).await;
+    }
+
+    /// Prevent new operations from being started against the given host for
+    /// the given amount of time
+    fn back_off(&self, host: &str, delay: Duration) {
+        let mut next_slots = self.next_slots.lock().expect("cannot lock next slots");
+        let until = Instant::now() + delay;
+        let slot = next_slots.entry(host.to_owned()).or_insert(until);
+        if *slot < until {
+            *slot = until;
+        }
+    }
+}
+
+/// Delay before the next attempt of a throttled or failed operation: 1 second,
+/// doubled at each attempt
+fn retry_backoff(attempt: u32) -> Duration {
+    Duration::from_secs(1)
+        .saturating_mul(2u32.saturating_pow(attempt))
+        .min(MAX_RETRY_BACKOFF)
+}
+
+/// The remote host a policy is downloaded from, `None` for local policies
+pub fn remote_host(policy_url: &str) -> Option<String> {
+    if let Some(image) = policy_url.strip_prefix("registry://") {
+        return Reference::from_str(image)
+            .ok()
+            .map(|reference| reference.registry().to_owned());
+    }
+
+    match policy_url.split_once("://")? {
+        ("http" | "https", rest) => rest.split('/').next().map(str::to_owned),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sources::SourceError;
+    use rstest::rstest;
+    use std::sync::atomic::{AtomicU32, Ordering};
+
+    #[derive(Debug)]
+    struct FakeError {
+        throttled: bool,
+        transient: bool,
+    }
+
+    impl std::fmt::Display for FakeError {
+        fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+            write!(f, "fake error")
+        }
+    }
+
+    impl RetryableError for FakeError {
+        fn is_throttled(&self) -> bool {
+            self.throttled
+        }
+
+        fn is_transient(&self) -> bool {
+            self.transient
+        }
+
+        fn retry_after(&self) -> Option<Duration> {
+            Some(Duration::from_millis(10))
+        }
+    }
+
+    #[rstest]
+    #[case(
+        "registry://ghcr.io/kubewarden/tests/pod-privileged:v0.1.9",
+        Some("ghcr.io")
+    )]
+    #[case("https://example.com:8443/policy.wasm", Some("example.com:8443"))]
+    #[case("file:///tmp/policy.wasm", None)]
+    fn policy_remote_host(#[case] policy_url: &str, #[case] expected: Option<&str>) {
+        assert_eq!(remote_host(policy_url).as_deref(), expected);
+    }
+
+    #[rstest]
+    #[case(0, Duration::from_secs(1))]
+    #[case(3, Duration::from_secs(8))]
+    #[case(10, MAX_RETRY_BACKOFF)]
+    fn throttled_operation_backoff(#[case] attempt: u32, #[case] expected: Duration) {
+        assert_eq!(retry_backoff(attempt), expected);
+    }
+
+    #[rstest]
+    #[case::recovers(2, 3, true, 3)]
+    #[case::retries_exhausted(5, 2, false, 3)]
+    #[tokio::test]
+    async fn retry_throttled_operations(
+        #[case] throttled_attempts: u32,
+        #[case] max_retries: u32,
+        #[case] expected_success: bool,
+        #[case] expected_attempts: u32,
+    ) {
+        let throttled = Arc::new(Mutex::new(Vec::new()));
+        let observed = throttled.clone();
+        let manager = DownloadManager::new(FetcherOptions {
+            concurrency: 1,
+            registry_delay: Duration::ZERO,
+            max_retries,
+        })
+        .with_throttling_observer(Arc::new(move |operation: &ThrottledOperation| {
+            observed.lock().unwrap().push(operation.retried)
+        }));
+        let attempts = AtomicU32::new(0);
+        let counter = &attempts;
+
+        let result = manager
+            .run(Some("ghcr.io"), "fetch", "policy", move || async move {
+                if counter.fetch_add(1, Ordering::SeqCst) < throttled_attempts {
+                    Err(FakeError {
+                        throttled: true,
+                        transient: false,
+                    })
+                } else {
+                    Ok(())
+                }
+            })
+            .await;
+
+        assert_eq!(result.is_ok(), expected_success);
+        assert_eq!(attempts.load(Ordering::SeqCst), expected_attempts);
+
+        let throttled = throttled.lock().unwrap();
+        assert_eq!(
+            throttled.len() as u32,
+            throttled_attempts.min(max_retries + 1)
+        );
+        assert_eq!(throttled.last() == Some(&true), expected_success);
+    }
+
+    #[tokio::test]
+    async fn retry_transient_errors() {
+        let throttled = Arc::new(Mutex::new(Vec::new()));
+        let observed = throttled.clone();
+        let manager = DownloadManager::new(FetcherOptions {
+            concurrency: 1,
+            registry_delay: Duration::ZERO,
+            max_retries: 3,
+        })
+        .with_throttling_observer(Arc::new(move |operation: &ThrottledOperation| {
+            observed.lock().unwrap().push(operation.retried)
+        }));
+        let attempts = AtomicU32::new(0);
+        let counter = &attempts;
+
+        let result = manager
+            .run(Some("ghcr.io"), "fetch", "policy", move || async move {
+                if counter.fetch_add(1, Ordering::SeqCst) < 2 {
+                    Err(FakeError {
+                        throttled: false,
+                        transient: true,
+                    })
+                } else {
+                    Ok(())
+                }
+            })
+            .await;
+
+        assert!(result.is_ok());
+        assert_eq!(attempts.load(Ordering::SeqCst), 3);
+        // only the operations rate limited by the server are reported as throttled
+        assert!(throttled.lock().unwrap().is_empty());
+    }
+
+    #[rstest]
+    #[case::server_error(reqwest::StatusCode::BAD_GATEWAY, true)]
+    #[case::not_found(reqwest::StatusCode::NOT_FOUND, false)]
+    fn transient_status(#[case] status: reqwest::StatusCode, #[case] transient: bool) {
+        let error = FetcherError::SourceError(SourceError::UnexpectedStatusError { status });
+
+        assert_eq!(error.is_transient(), transient);
+    }
+
+    #[tokio::test]
+    async fn connection_errors_are_transient() {
+        // nothing listens on this port
+        let error = reqwest::get("http://127.0.0.1:1/policy.wasm")
+            .await
+            .unwrap_err();
+
+        assert!(
+            FetcherError::SourceError(SourceError::FailedToCreateHttpClientError(error))
+                .is_transient()
+        );
+    }
+
+    #[tokio::test]
+    async fn do_not_retry_other_errors() {
+        let manager = DownloadManager::default();
+        let attempts = AtomicU32::new(0);
+        let counter = &attempts;
+
+        let result: std::result::Result<(), FakeError> = manager
+            .run(Some("ghcr.io"), "fetch", "policy", move || async move {
+                counter.fetch_add(1, Ordering::SeqCst);
+                Err(FakeError {
+                    throttled: false,
+                    transient: false,
+                })
+            })
+            .await;
+
+        assert!(result.is_err());
+        assert_eq!(attempts.load(Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test]
+    async fn space_out_operations_against_the_same_host() {
+        let manager = DownloadManager::new(FetcherOptions {
+            concurrency: 4,
+            registry_delay: Duration::from_millis(50),
+            max_retries: 0,
+        });
+
+        let start = Instant::now();
+        manager.wait("ghcr.io").await;
+        manager.wait("ghcr.io").await;
+        manager.wait("ghcr.io").await;
+        assert!(start.elapsed() >= Duration::from_millis(100));
+
+        // other hosts are not affected
+        let start = Instant::now();
+        manager.wait("registry.example.com").await;
+        assert!(start.elapsed() < Duration::from_millis(50));
+    }
+
+    #[tokio::test]
+    async fn fetch_each_policy_once() {
+        let tmp = tempfile::tempdir().unwrap();
+        let policy_path = tmp.path().join("policy.wasm");
+        std::fs::write(&policy_path, b"\0asm").unwrap();
+        let url = format!("file://{}", policy_path.display());
+        let missing_url = "ftp://example.com/policy.wasm";
+
+        let fetched = DownloadManager::default()
+            .fetch_policies(
+                [url.as_str(), url.as_str(), missing_url],
+                &PullDestination::LocalFile(tmp.path().to_path_buf()),
+                None,
+            )
+            .await;
+
+        assert_eq!(fetched.len(), 2);
+        assert_eq!(fetched[&url].as_ref().unwrap().local_path, policy_path);
+        assert!(fetched[missing_url].is_err());
+    }
+}
@@ -0,0 +1,144 @@
+//! Connections to hosts resolving to both IPv4 and IPv6 addresses.
+//!
+//! The addresses of the host are sorted according to the address family
+//! preference, interleaving the two families as described by RFC 8305. The
+//! HTTP connector then races the connection attempts: the addresses of the
+//! first family are tried first, and the ones of the other family are tried
+//! too when no connection has been established after a short delay. This way
+//! a broken IPv6, or IPv4, network doesn't make the downloads hang.
+
+use std::{io, net::SocketAddr};
+
+use reqwest::dns::{Addrs, Name, Resolve, Resolving};
+
+use crate::sources::AddressFamily;
+
+/// Resolver of the HTTP clients, it sorts the addresses of the hosts according
+/// to the address family preference
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct AddressFamilyResolver(pub(crate) AddressFamily);
+
+impl Resolve for AddressFamilyResolver {
+    fn resolve(&self, name: Name) -> Resolving {
+        let address_family = self.0;
+        Box::pin(async move {
+            let host = name.as_str();
+            // the port is set by the connector
+            let addrs: Vec<SocketAddr> = tokio::net::lookup_host((host, 0)).await?.collect();
+            let addrs = sort_addresses(addrs, address_family);
+            if addrs.is_empty() {
+                return Err(io::Error::new(
+                    io::ErrorKind::AddrNotAvailable,
+                    format!("{host} has no address of family {address_family}"),
+                )
+                .into());
+            }
+
+            Ok(Box::new(addrs.into_iter()) as Addrs)
+        })
+    }
+}
+
+/// Sort the addresses of a host according to the address family preference.
+///
+/// The addresses of the two families are interleaved, starting with the
+/// preferred family. When there's no preference, the family of the first
+/// address returned by the system resolver is the preferred one. The addresses
+/// of the other family are dropped when only one family is allowed
+pub(crate) fn sort_addresses(
+    addrs: Vec<SocketAddr>,
+    address_family: AddressFamily,
+) -> Vec<SocketAddr> {
+    let (ipv6, ipv4): (Vec<SocketAddr>, Vec<SocketAddr>) =
+        addrs.iter().copied().partition(|addr| addr.is_ipv6());
+
+    let (preferred, fallback) = match address_family {
+        AddressFamily::Ipv4 => return ipv4,
+        AddressFamily::Ipv6 => return ipv6,
+        AddressFamily::PreferIpv4 => (ipv4, ipv6),
+        AddressFamily::PreferIpv6 => (ipv6, ipv4),
+        AddressFamily::Auto => match addrs.first() {
+            Some(addr) if addr.is_ipv4() => (ipv4, ipv6),
+            _ => (ipv6, ipv4),
+        },
+    };
+
+    let mut sorted = Vec::with_capacity(addrs.len());
+    let mut preferred = preferred.into_iter();
+    let mut fallback = fallback.into_iter();
+    loop {
+        match (preferred.next(), fallback.next()) {
+            (None, None) => break,
+            (preferred, fallback) => sorted.extend(preferred.into_iter().chain(fallback)),
+        }
+    }
+
+    sorted
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rstest::rstest;
+
+    fn addrs(addrs: &[&str]) -> Vec<SocketAddr> {
+        addrs.iter().map(|addr| addr.parse().unwrap()).collect()
+    }
+
+    const RESOLVED: &[&str] = &[
+        "[2001:db8::1]:443",
+        "[2001:db8::2]:443",
+        "[2001:db8::3]:443",
+        "192.0.2.1:443",
+        "192.0.2.2:443",
+    ];
+
+    #[rstest]
+    #[case::auto(
+        AddressFamily::Auto,
+        &["[2001:db8::1]:443", "192.0.2.1:443", "[2001:db8::2]:443", "192.0.2.2:443", "[2001:db8::3]:443"]
+    )]
+    #[case::prefer_ipv6(
+        AddressFamily::PreferIpv6,
+        &["[2001:db8::1]:443", "192.0.2.1:443", "[2001:db8::2]:443", "192.0.2.2:443", "[2001:db8::3]:443"]
+    )]
+    #[case::prefer_ipv4(
+        AddressFamily::PreferIpv4,
+        &["192.0.2.1:443", "[2001:db8::1]:443", "192.0.2.2:443", "[2001:db8::2]:443", "[2001:db8::3]:443"]
+    )]
+    #[case::ipv4(AddressFamily::Ipv4, &["192.0.2.1:443", "192.0.2.2:443"])]
+    #[case::ipv6(
+        AddressFamily::Ipv6,
+        &["[2001:db8::1]:443", "[2001:db8::2]:443", "[2001:db8::3]:443"]
+    )]
+    fn addresses_sorted_by_family(
+        #[case] address_family: AddressFamily,
+        #[case] expected: &[&str],
+    ) {
+        assert_eq!(
+            sort_addresses(addrs(RESOLVED), address_family),
+            addrs(expected)
+        );
+    }
+
+    #[test]
+    fn auto_prefers_the_family_of_the_system_resolver() {
+        let resolved = addrs(&["192.0.2.1:443", "[2001:db8::1]:443", "[2001:db8::2]:443"]);
+
+        assert_eq!(
+            sort_addresses(resolved, AddressFamily::Auto),
+            addrs(&["192.0.2.1:443", "[2001:db8::1]:443", "[2001:db8::2]:443"])
+        );
+    }
+
+    #[test]
+    fn single_family_host() {
+        let resolved = addrs(&["192.0.2.1:443"]);
+
+        assert_eq!(
+            sort_addresses(resolved.clone(), AddressFamily::PreferIpv6),
+            resolved
+        );
+        assert!(sort_addresses(resolved, AddressFamily::Ipv6).is_empty());
+    }
+}
@@ -1,5 +1,8 @@
+use std::time::Duration;
 use thiserror::Error;
 
+use crate::{registry::errors::is_throttling_error, sources::SourceError};
+
 pub type FetcherResult<T> = std::result::Result<T, FetcherError>;
 
 #[derive(Error, Debug)]
@@ -28,6 +31,43 @@ pub enum FetcherError {
     CannotCreateStoragePathError(#[from] CannotCreateStoragePathError),
 }
 
+impl FetcherError {
+    /// Whether the operation failed because the remote server is rate limiting
+    /// the requests. The operation can be retried later.
+    pub fn is_throttled(&self) -> bool {
+        match self {
+            FetcherError::SourceError(SourceError::TooManyRequestsError { .. }) => true,
+            FetcherError::SourceError(SourceError::OCIRegistryError(e)) => is_throttling_error(e),
+            FetcherError::RegistryError(e) => e.is_throttled(),
+            FetcherError::VerifyError(e) => e.is_throttled(),
+            _ => false,
+        }
+    }
+
+    /// Whether the operation failed because the remote server could not be
+    /// reached, or because of an error on its side, like a HTTP 5xx response.
+    /// The operation can be retried later.
+    pub fn is_transient(&self) -> bool {
+        match self {
+            FetcherError::SourceError(e) => e.is_transient(),
+            FetcherError::RegistryError(e) => e.is_transient(),
+            FetcherError::VerifyError(e) => e.is_transient(),
+            _ => false,
+        }
+    }
+
+    /// How long to wait before retrying the operation, when this has been
+    /// communicated by the remote server
+    pub fn retry_after(&self) -> Option<Duration> {
+        match self {
+            FetcherError::SourceError(SourceError::TooManyRequestsError { retry_after }) => {
+                *retry_after
+            }
+            _ => None,
+        }
+    }
+}
+
 #[derive(thiserror::Error, Debug)]
 #[error("{0}")]
 pub struct FailedToParseYamlDataError(#[from] pub serde_yaml::Error);
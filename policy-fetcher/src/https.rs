@@ -1,20 +1,96 @@
 #![allow(clippy::upper_case_acronyms)]
 
 use async_trait::async_trait;
+use reqwest::{header, StatusCode};
 use std::{
     boxed::Box,
     convert::{TryFrom, TryInto},
+    net::SocketAddr,
+    sync::Arc,
+    time::Duration,
 };
+use tracing::warn;
 use url::Url;
 
+use crate::dual_stack::{sort_addresses, AddressFamilyResolver};
 use crate::fetcher::{ClientProtocol, PolicyFetcher, TlsVerificationMode};
+use crate::host_and_port;
+use crate::sources::AddressFamily;
 use crate::sources::Certificate;
+use crate::sources::HttpAuth;
+use crate::sources::HttpsTls;
 use crate::sources::SourceError;
 use crate::sources::SourceResult;
+use crate::sources::TlsVersion;
 
 // Struct used to reference a WASM module that is hosted on a HTTP(s) server
 #[derive(Default)]
-pub(crate) struct Https {}
+pub(crate) struct Https {
+    // Credentials sent to the server, if any
+    auth: Option<HttpAuth>,
+    // TLS settings of the server, if any
+    tls: Option<HttpsTls>,
+    // Trust the certificates of the system store
+    system_certificates: bool,
+    // The address family used to connect to the server
+    address_family: AddressFamily,
+}
+
+impl Https {
+    pub(crate) fn new(
+        auth: Option<HttpAuth>,
+        tls: Option<HttpsTls>,
+        system_certificates: bool,
+        address_family: AddressFamily,
+    ) -> Self {
+        Https {
+            auth,
+            tls,
+            system_certificates,
+            address_family,
+        }
+    }
+}
+
+/// The maximum number of redirects followed by the requests carrying credentials,
+/// the same limit of the default redirect policy
+const MAX_REDIRECTS: usize = 10;
+
+/// Follow only the redirects that stay on the origin of the request, the
+/// credentials would otherwise be sent to another server: only the `Authorization`
+/// header is removed by the default redirect policy, not the custom ones
+fn same_origin_redirects() -> reqwest::redirect::Policy {
+    reqwest::redirect::Policy::custom(|attempt| {
+        if attempt.previous().len() > MAX_REDIRECTS {
+            return attempt.error("too many redirects");
+        }
+        let origin = attempt.previous().first().map(|url| url.origin());
+        if origin == Some(attempt.url().origin()) {
+            attempt.follow()
+        } else {
+            let error = format!(
+                "refusing to send the credentials to {}, redirected from another origin",
+                attempt.url()
+            );
+            attempt.error(error)
+        }
+    })
+}
+
+/// Add the credentials to the request
+fn authenticate(
+    request: reqwest::RequestBuilder,
+    auth: Option<&HttpAuth>,
+) -> reqwest::RequestBuilder {
+    match auth {
+        None => request,
+        Some(HttpAuth::Bearer { token }) => request.bearer_auth(token),
+        Some(HttpAuth::Basic { username, password }) => {
+            request.basic_auth(username, Some(password))
+        }
+        Some(HttpAuth::Header { name, value }) => request.header(name.as_str(), value.as_str()),
+    }
+}
 
 impl TryFrom<&Certificate> for reqwest::Certificate {
     type Error = SourceError;
@@ -39,36 +115,316 @@ impl TryFrom<&Certificate> for reqwest::Certificate {
     }
 }
 
+/// Build a HTTP client that connects using the given protocol and address family
+pub(crate) fn build_client(
+    client_protocol: &ClientProtocol,
+    address_family: AddressFamily,
+) -> SourceResult<reqwest::Client> {
+    Ok(client_builder(client_protocol, address_family)?.build()?)
+}
+
+fn client_builder(
+    client_protocol: &ClientProtocol,
+    address_family: AddressFamily,
+) -> SourceResult<reqwest::ClientBuilder> {
+    let mut client_builder =
+        reqwest::Client::builder().dns_resolver(Arc::new(AddressFamilyResolver(address_family)));
+    match client_protocol {
+        ClientProtocol::Http => {}
+        ClientProtocol::Https(ref tls_fetch_mode) => {
+            client_builder = client_builder.https_only(true);
+            match tls_fetch_mode {
+                TlsVerificationMode::SystemCa => {}
+                TlsVerificationMode::CustomCaCertificates(ca_certificates) => {
+                    for certificate in ca_certificates.iter() {
+                        client_builder =
+                            client_builder.add_root_certificate(certificate.try_into()?);
+                    }
+                }
+                TlsVerificationMode::NoTlsVerification => {
+                    client_builder = client_builder.danger_accept_invalid_certs(true);
+                }
+            }
+        }
+    };
+
+    Ok(client_builder)
+}
+
+/// Add the certificates of the system store to the trusted ones
+fn add_system_certificates(
+    mut client_builder: reqwest::ClientBuilder,
+) -> SourceResult<reqwest::ClientBuilder> {
+    let native_certs = rustls_native_certs::load_native_certs();
+    for err in native_certs.errors {
+        warn!(error = %err, "cannot load certificate from the system store");
+    }
+    for certificate in native_certs.certs {
+        client_builder =
+            client_builder.add_root_certificate(reqwest::Certificate::from_der(&certificate)?);
+    }
+    Ok(client_builder)
+}
+
+/// Apply the TLS settings of the server
+fn configure_tls(
+    mut client_builder: reqwest::ClientBuilder,
+    tls: &HttpsTls,
+) -> SourceResult<reqwest::ClientBuilder> {
+    for certificate in &tls.ca_certificates {
+        client_builder = client_builder.add_root_certificate(certificate.try_into()?);
+    }
+    if let Some(version) = tls.min_tls_version {
+        client_builder = client_builder.min_tls_version(match version {
+            TlsVersion::Tls12 => reqwest::tls::Version::TLS_1_2,
+            TlsVersion::Tls13 => reqwest::tls::Version::TLS_1_3,
+        });
+    }
+    Ok(client_builder)
+}
+
+/// Resolve the addresses of the host of the URL
+async fn resolve(url: &Url) -> SourceResult<Vec<SocketAddr>> {
+    let host = url
+        .host_str()
+        .ok_or_else(|| crate::errors::InvalidURLError(url.to_string()))?;
+    let port = url.port_or_known_default().unwrap_or(443);
+    let addrs = tokio::net::lookup_host((host, port)).await.map_err(|e| {
+        SourceError::CannotResolveHostError {
+            host: host.to_owned(),
+            error: e,
+        }
+    })?;
+    Ok(addrs.collect())
+}
+
+/// The URL to connect to when the name of the server is overridden. The overriding
+/// name ends up inside of SNI and is used to verify the certificate of the server,
+/// while the connection is made to the address of the original host
+fn server_name_url(url: &Url, server_name: &str) -> SourceResult<Url> {
+    let mut server_name_url = url.clone();
+    server_name_url
+        .set_host(Some(server_name))
+        .map_err(|_| crate::errors::InvalidURLError(url.to_string()))?;
+    Ok(server_name_url)
+}
+
+impl Https {
+    /// The credentials sent to the server: only the connections to a server whose
+    /// certificate has been verified carry them, they are never sent in cleartext
+    /// nor to a server that could be impersonated
+    fn credentials(&self, url: &Url, client_protocol: &ClientProtocol) -> Option<&HttpAuth> {
+        let auth = self.auth.as_ref()?;
+        match client_protocol {
+            ClientProtocol::Https(
+                TlsVerificationMode::SystemCa | TlsVerificationMode::CustomCaCertificates(_),
+            ) if url.scheme() == "https" => Some(auth),
+            _ => {
+                warn!(%url, protocol = %client_protocol, "not sending the credentials of the server over an insecure connection");
+                None
+            }
+        }
+    }
+}
+
 #[async_trait]
 impl PolicyFetcher for Https {
     async fn fetch(&self, url: &Url, client_protocol: ClientProtocol) -> SourceResult<Vec<u8>> {
-        let mut client_builder = reqwest::Client::builder();
-        match client_protocol {
-            ClientProtocol::Http => {}
-            ClientProtocol::Https(ref tls_fetch_mode) => {
-                client_builder = client_builder.https_only(true);
-                match tls_fetch_mode {
-                    TlsVerificationMode::SystemCa => {}
-                    TlsVerificationMode::CustomCaCertificates(ca_certificates) => {
-                        for certificate in ca_certificates.iter() {
-                            client_builder =
-                                client_builder.add_root_certificate(certificate.try_into()?);
-                        }
-                    }
-                    TlsVerificationMode::NoTlsVerification => {
-                        client_builder = client_builder.danger_accept_invalid_certs(true);
-                    }
+        let auth = self.credentials(url, &client_protocol);
+        let mut client_builder = client_builder(&client_protocol, self.address_family)?;
+        if auth.is_some() {
+            client_builder = client_builder.redirect(same_origin_redirects());
+        }
+        let mut request_url = url.clone();
+        let mut host_header = None;
+        if let ClientProtocol::Https(_) = client_protocol {
+            if self.system_certificates {
+                client_builder = add_system_certificates(client_builder)?;
+            }
+            if let Some(tls) = &self.tls {
+                client_builder = configure_tls(client_builder, tls)?;
+                if let Some(server_name) = &tls.server_name {
+                    let addrs = sort_addresses(resolve(url).await?, self.address_family);
+                    client_builder = client_builder.resolve_to_addrs(server_name, &addrs);
+                    request_url = server_name_url(url, server_name)?;
+                    host_header = Some(host_and_port(url)?);
                 }
             }
-        };
+        }
 
         let client = client_builder.build()?;
-        Ok(client
-            .get(url.as_ref())
+        let mut request = client.get(request_url.as_ref());
+        if let Some(host) = host_header {
+            // the server receives the original host, only the TLS handshake
+            // uses the overriding name
+            request = request.header(header::HOST, host);
+        }
+        let response = authenticate(request, auth).send().await?;
+        if response.status() == StatusCode::TOO_MANY_REQUESTS {
+            return Err(SourceError::TooManyRequestsError {
+                retry_after: response
+                    .headers()
+                    .get(header::RETRY_AFTER)
+                    .and_then(|value| value.to_str().ok())
+                    .and_then(parse_retry_after),
+            });
+        }
+        if !response.status().is_success() {
+            return Err(SourceError::UnexpectedStatusError {
+                status: response.status(),
+            });
+        }
+
+        Ok(response.bytes().await?.to_vec())
+    }
+}
+
+/// Parse the value of the `Retry-After` header. Only the delay in seconds is
+/// supported, HTTP dates are ignored.
+fn parse_retry_after(value: &str) -> Option<Duration> {
+    value.trim().parse::<u64>().ok().map(Duration::from_secs)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rstest::rstest;
+
+    #[rstest]
+    #[case::default_port(
+        "https://files.example.com/policy.wasm",
+        "https://files.internal.example.com/policy.wasm"
+    )]
+    #[case::custom_port(
+        "https://files.example.com:8443/policy.wasm",
+        "https://files.internal.example.com:8443/policy.wasm"
+    )]
+    fn server_name_override(#[case] url: &str, #[case] expected: &str) {
+        let url = Url::parse(url).unwrap();
+        assert_eq!(
+            server_name_url(&url, "files.internal.example.com")
+                .unwrap()
+                .as_str(),
+            expected
+        );
+    }
+
+    #[rstest]
+    #[case("120", Some(Duration::from_secs(120)))]
+    #[case(" 5 ", Some(Duration::from_secs(5)))]
+    #[case("Wed, 21 Oct 2015 07:28:00 GMT", None)]
+    fn retry_after_header(#[case] value: &str, #[case] expected: Option<Duration>) {
+        assert_eq!(parse_retry_after(value), expected);
+    }
+
+    #[rstest]
+    #[case::none(None, None, None)]
+    #[case::bearer(
+        Some(HttpAuth::Bearer { token: "my-token".to_owned() }),
+        Some("authorization"),
+        Some("Bearer my-token")
+    )]
+    #[case::basic(
+        Some(HttpAuth::Basic { username: "kubewarden".to_owned(), password: "secret".to_owned() }),
+        Some("authorization"),
+        Some("Basic a3ViZXdhcmRlbjpzZWNyZXQ=")
+    )]
+    #[case::header(
+        Some(HttpAuth::Header { name: "X-Api-Key".to_owned(), value: "my-key".to_owned() }),
+        Some("x-api-key"),
+        Some("my-key")
+    )]
+    fn request_authentication(
+        #[case] auth: Option<HttpAuth>,
+        #[case] header: Option<&str>,
+        #[case] expected: Option<&str>,
+    ) {
+        let client = reqwest::Client::new();
+        let request = authenticate(client.get("https://example.com/policy.wasm"), auth.as_ref())
+            .build()
+            .unwrap();
+
+        match header {
+            Some(header) => assert_eq!(
+                request.headers().get(header).unwrap().to_str().unwrap(),
+                expected.unwrap()
+            ),
+            None => assert!(request.headers().is_empty()),
+        }
+    }
+
+    #[rstest]
+    #[case::system_ca(
+        "https://files.example.com/policy.wasm",
+        ClientProtocol::Https(TlsVerificationMode::SystemCa),
+        true
+    )]
+    #[case::custom_ca(
+        "https://files.example.com/policy.wasm",
+        ClientProtocol::Https(TlsVerificationMode::CustomCaCertificates(vec![])),
+        true
+    )]
+    #[case::no_tls_verification(
+        "https://files.example.com/policy.wasm",
+        ClientProtocol::Https(TlsVerificationMode::NoTlsVerification),
+        false
+    )]
+    #[case::http_fallback("https://files.example.com/policy.wasm", ClientProtocol::Http, false)]
+    #[case::http_url(
+        "http://files.example.com/policy.wasm",
+        ClientProtocol::Https(TlsVerificationMode::SystemCa),
+        false
+    )]
+    fn credentials_sent_only_over_verified_tls(
+        #[case] url: &str,
+        #[case] client_protocol: ClientProtocol,
+        #[case] sent: bool,
+    ) {
+        let https = Https {
+            auth: Some(HttpAuth::Bearer {
+                token: "my-token".to_owned(),
+            }),
+            ..Default::default()
+        };
+
+        assert_eq!(
+            https
+                .credentials(&Url::parse(url).unwrap(), &client_protocol)
+                .is_some(),
+            sent
+        );
+    }
+
+    /// Serve a single request, redirecting it to the given location
+    fn redirecting_server(location: String) -> SocketAddr {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        std::thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut buffer = [0; 4096];
+            let _ = std::io::Read::read(&mut stream, &mut buffer).unwrap();
+            let response =
+                format!("HTTP/1.1 302 Found\r\nLocation: {location}\r\nContent-Length: 0\r\n\r\n");
+            std::io::Write::write_all(&mut stream, response.as_bytes()).unwrap();
+        });
+        addr
+    }
+
+    #[tokio::test]
+    async fn credentials_do_not_follow_cross_origin_redirects() {
+        let addr = redirecting_server("http://localhost:1/policy.wasm".to_owned());
+        let client = reqwest::Client::builder()
+            .redirect(same_origin_redirects())
+            .build()
+            .unwrap();
+
+        let err = client
+            .get(format!("http://{addr}/policy.wasm"))
+            .header("X-Api-Key", "my-key")
             .send()
-            .await?
-            .bytes()
-            .await?
-            .to_vec())
+            .await
+            .unwrap_err();
+
+        assert!(err.is_redirect());
     }
 }
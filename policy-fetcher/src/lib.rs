@@ -6,14 +6,17 @@ extern crate walkdir;
 use errors::FetcherResult;
 use std::boxed::Box;
 use std::fs;
-use store::errors::{StoreError, StoreResult};
+use store::errors::StoreError;
 use url::Url;
 
+pub mod download;
+mod dual_stack;
 pub mod errors;
 pub mod fetcher;
 mod https;
 pub mod policy;
 pub mod registry;
+pub mod rego_library;
 pub mod sources;
 pub mod store;
 pub mod verify;
@@ -25,16 +28,17 @@ use crate::policy::Policy;
 use crate::registry::build_fully_resolved_reference;
 use crate::registry::Registry;
 use crate::sources::Sources;
-use crate::store::Store;
+use crate::store::{provenance::PolicyProvenance, Store};
 
 #[macro_use]
 extern crate lazy_static;
 
+use sha2::{Digest, Sha256};
 use std::{
     collections::HashSet,
     path::{Path, PathBuf},
 };
-use tracing::debug;
+use tracing::{debug, info, warn};
 use url::ParseError;
 
 // re-export for usage by kwctl, policy-server, policy-evaluator,...
@@ -52,7 +56,7 @@ lazy_static! {
     };
 }
 
-#[derive(Debug)]
+#[derive(Clone, Debug)]
 pub enum PullDestination {
     MainStore,
     Store(PathBuf),
@@ -95,7 +99,7 @@ pub async fn fetch_policy(
         _ => Err(StoreError::UnknownSchemeError(url.scheme().to_owned())),
     }?;
     let (store, mut destination) = pull_destination(&url, &destination)?;
-    if let Some(store) = store {
+    if let Some(store) = &store {
         store
             .ensure(&store.policy_full_path(url.as_str(), store::PolicyPath::PrefixOnly)?)
             .map_err(CannotCreateStoragePathError)?;
@@ -127,35 +131,79 @@ pub async fn fetch_policy(
         _ => unreachable!(),
     }
     debug!(?url, "pulling policy");
-    let policy_fetcher = url_fetcher(url.scheme())?;
     let sources_default = Sources::default();
     let sources = sources.unwrap_or(&sources_default);
+    let policy_fetcher = url_fetcher(&url, sources)?;
 
+    let write_policy = |bytes: &[u8]| -> FetcherResult<Policy> {
+        let policy = create_file_if_valid(bytes, &destination, url.to_string())?;
+        if let Some(store) = &store {
+            let sha256 = format!("{:x}", Sha256::digest(bytes));
+            record_provenance(store, &policy, &sha256);
+        }
+        Ok(policy)
+    };
+
+    // the mirrors of the registry are tried first, the policy keeps the URL of
+    // the registry once pulled
+    for mirror_url in mirror_urls(&url, sources)? {
+        match fetch_with_protocols(policy_fetcher.as_ref(), &mirror_url, sources).await {
+            Ok(bytes) => return write_policy(&bytes),
+            Err(err) => {
+                info!(%err, mirror = %mirror_url, "cannot pull policy from registry mirror");
+            }
+        }
+    }
+
+    let bytes = fetch_with_protocols(policy_fetcher.as_ref(), &url, sources).await?;
+    write_policy(&bytes)
+}
+
+/// Fetch the policy using the protocols allowed for its source: the sources
+/// marked as insecure are tried again without TLS verification, then over HTTP
+async fn fetch_with_protocols(
+    policy_fetcher: &(dyn PolicyFetcher + Send + Sync),
+    url: &Url,
+    sources: &Sources,
+) -> FetcherResult<Vec<u8>> {
     match policy_fetcher
-        .fetch(&url, client_protocol(&url, sources)?)
+        .fetch(url, client_protocol(url, sources)?)
         .await
     {
         Err(err) => {
-            if !sources.is_insecure_source(&host_and_port(&url)?) {
+            if !sources.is_insecure_source(&host_and_port(url)?) {
                 return Err(FetcherError::SourceError(err));
             }
         }
-        Ok(bytes) => return create_file_if_valid(&bytes, &destination, url.to_string()),
+        Ok(bytes) => return Ok(bytes),
     }
     if let Ok(bytes) = policy_fetcher
         .fetch(
-            &url,
+            url,
             ClientProtocol::Https(TlsVerificationMode::NoTlsVerification),
         )
         .await
     {
-        return create_file_if_valid(&bytes, &destination, url.to_string());
+        return Ok(bytes);
     }
 
-    match policy_fetcher.fetch(&url, ClientProtocol::Http).await {
-        Ok(bytes) => create_file_if_valid(&bytes, &destination, url.to_string()),
-        Err(e) => Err(FetcherError::SourceError(e)),
+    policy_fetcher
+        .fetch(url, ClientProtocol::Http)
+        .await
+        .map_err(FetcherError::SourceError)
+}
+
+/// The URLs of the policy on the mirrors of its registry, in the order they
+/// have to be tried. Policies that are not hosted on a registry have no mirror
+fn mirror_urls(url: &Url, sources: &Sources) -> FetcherResult<Vec<Url>> {
+    if url.scheme() != "registry" {
+        return Ok(Vec::new());
     }
+    let reference = build_fully_resolved_reference(url.as_str())?;
+    registry::mirror_references(&reference, sources)?
+        .iter()
+        .map(|mirror| Ok(Url::parse(format!("registry://{mirror}").as_str())?))
+        .collect()
 }
 
 fn client_protocol(
@@ -201,11 +249,22 @@ fn pull_destination(
 // Helper function, takes the URL of the policy and allocates the
 // right struct to interact with it
 #[allow(clippy::box_default)]
-fn url_fetcher(scheme: &str) -> StoreResult<Box<dyn PolicyFetcher + Send>> {
-    match scheme {
-        "http" | "https" => Ok(Box::new(Https::default())),
+fn url_fetcher(
+    url: &Url,
+    sources: &Sources,
+) -> FetcherResult<Box<dyn PolicyFetcher + Send + Sync>> {
+    match url.scheme() {
+        "http" | "https" => {
+            let host = host_and_port(url)?;
+            Ok(Box::new(Https::new(
+                sources.http_auth(&host).cloned(),
+                sources.https_tls(&host).cloned(),
+                sources.https_system_certificates,
+                sources.address_family(&host),
+            )))
+        }
         "registry" => Ok(Box::new(Registry::new())),
-        _ => Err(StoreError::UnknownSchemeError(scheme.to_owned())),
+        scheme => Err(StoreError::UnknownSchemeError(scheme.to_owned()).into()),
     }
 }
 
@@ -227,6 +286,15 @@ pub(crate) fn host_and_port(url: &Url) -> std::result::Result<String, errors::In
 // https://webassembly.github.io/spec/core/bikeshed/#binary-magic
 const WASM_MAGIC_NUMBER: [u8; 4] = [0x00, 0x61, 0x73, 0x6D];
 
+/// Record where the policy pulled into the store comes from. Failing to do that
+/// must not break the pull operation.
+fn record_provenance(store: &Store, policy: &Policy, sha256: &str) {
+    let provenance = PolicyProvenance::new(&policy.uri, sha256);
+    if let Err(e) = store.save_provenance(policy, &provenance) {
+        warn!(policy = policy.uri.as_str(), error = %e, "cannot record policy provenance");
+    }
+}
+
 fn create_file_if_valid(bytes: &[u8], destination: &Path, url: String) -> FetcherResult<Policy> {
     if !bytes.starts_with(&WASM_MAGIC_NUMBER) {
         return Err(FetcherError::InvalidWasmFileError);
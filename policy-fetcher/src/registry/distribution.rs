@@ -0,0 +1,242 @@
+//! Operations of the OCI distribution API that are not provided by `oci_client`
+
+use std::collections::BTreeMap;
+
+use oci_client::{secrets::RegistryAuth, Reference};
+use reqwest::{header, RequestBuilder, Response, StatusCode};
+use serde::Deserialize;
+use tracing::debug;
+
+use crate::{
+    fetcher::ClientProtocol,
+    registry::errors::{RegistryError, RegistryResult},
+    sources::AddressFamily,
+};
+
+/// The token issued by the authorization server of a registry
+#[derive(Deserialize)]
+struct TokenResponse {
+    token: Option<String>,
+    access_token: Option<String>,
+}
+
+/// The authentication challenge returned by a registry through the
+/// `WWW-Authenticate` header
+#[derive(Debug, PartialEq)]
+struct Challenge {
+    scheme: String,
+    params: BTreeMap<String, String>,
+}
+
+/// Delete the manifest with the given digest
+pub(crate) async fn delete_manifest(
+    client_protocol: &ClientProtocol,
+    reference: &Reference,
+    digest: &str,
+    auth: &RegistryAuth,
+    address_family: AddressFamily,
+) -> RegistryResult<()> {
+    let client = crate::https::build_client(client_protocol, address_family)?;
+    let scheme = match client_protocol {
+        ClientProtocol::Http => "http",
+        ClientProtocol::Https(_) => "https",
+    };
+    let url = format!(
+        "{}://{}/v2/{}/manifests/{}",
+        scheme,
+        reference.resolve_registry(),
+        reference.repository(),
+        digest
+    );
+
+    let mut response = client.delete(&url).send().await?;
+    if response.status() == StatusCode::UNAUTHORIZED {
+        let challenge = response
+            .headers()
+            .get(header::WWW_AUTHENTICATE)
+            .and_then(|value| value.to_str().ok())
+            .and_then(parse_challenge)
+            .ok_or_else(|| {
+                RegistryError::AuthenticationError(
+                    "the registry didn't send a valid authentication challenge".to_owned(),
+                )
+            })?;
+        debug!(?challenge, "authenticating the deletion of the manifest");
+        let request = authorize(&client, client.delete(&url), &challenge, reference, auth).await?;
+        response = request.send().await?;
+    }
+
+    let status = response.status();
+    if status.is_success() {
+        return Ok(());
+    }
+    let message = error_message(response).await;
+    let registry = reference.registry().to_owned();
+    let reference = format!("{}/{}@{}", registry, reference.repository(), digest);
+    Err(match status {
+        StatusCode::NOT_FOUND => RegistryError::ManifestNotFoundError(reference),
+        StatusCode::UNAUTHORIZED | StatusCode::FORBIDDEN => {
+            RegistryError::DeletionUnauthorizedError(reference)
+        }
+        // registries with deletion disabled reply either with `405 Method Not Allowed`,
+        // or with the `UNSUPPORTED` error code
+        StatusCode::METHOD_NOT_ALLOWED => RegistryError::DeletionDisabledError(registry),
+        _ if message.contains("UNSUPPORTED") => RegistryError::DeletionDisabledError(registry),
+        _ => RegistryError::DeletionError {
+            reference,
+            status: status.as_u16(),
+            message,
+        },
+    })
+}
+
+/// Add to the request the credentials asked by the challenge
+async fn authorize(
+    client: &reqwest::Client,
+    request: RequestBuilder,
+    challenge: &Challenge,
+    reference: &Reference,
+    auth: &RegistryAuth,
+) -> RegistryResult<RequestBuilder> {
+    match challenge.scheme.to_ascii_lowercase().as_str() {
+        "basic" => match auth {
+            RegistryAuth::Basic(username, password) => {
+                Ok(request.basic_auth(username, Some(password)))
+            }
+            _ => Err(RegistryError::DeletionUnauthorizedError(reference.whole())),
+        },
+        "bearer" => {
+            let realm = challenge.params.get("realm").ok_or_else(|| {
+                RegistryError::AuthenticationError(
+                    "the authentication challenge doesn't have a realm".to_owned(),
+                )
+            })?;
+            let default_scope = format!("repository:{}:delete", reference.repository());
+            let mut query = vec![(
+                "scope",
+                challenge
+                    .params
+                    .get("scope")
+                    .cloned()
+                    .unwrap_or(default_scope),
+            )];
+            if let Some(service) = challenge.params.get("service") {
+                query.push(("service", service.to_owned()));
+            }
+
+            let mut token_request = client.get(realm).query(&query);
+            if let RegistryAuth::Basic(username, password) = auth {
+                token_request = token_request.basic_auth(username, Some(password));
+            }
+            let token_response = token_request.send().await?;
+            if !token_response.status().is_success() {
+                return Err(RegistryError::AuthenticationError(format!(
+                    "the authorization server replied with {}",
+                    token_response.status()
+                )));
+            }
+            let token_response: TokenResponse =
+                serde_json::from_slice(&token_response.bytes().await?)?;
+            let token = token_response
+                .token
+                .or(token_response.access_token)
+                .ok_or_else(|| {
+                    RegistryError::AuthenticationError(
+                        "the authorization server didn't return a token".to_owned(),
+                    )
+                })?;
+
+            Ok(request.bearer_auth(token))
+        }
+        scheme => Err(RegistryError::AuthenticationError(format!(
+            "unsupported authentication scheme '{scheme}'"
+        ))),
+    }
+}
+
+async fn error_message(response: Response) -> String {
+    let status = response.status();
+    response
+        .text()
+        .await
+        .ok()
+        .filter(|body| !body.trim().is_empty())
+        .unwrap_or_else(|| status.to_string())
+}
+
+/// Parse the value of the `WWW-Authenticate` header, e.g.
+/// `Bearer realm="https://auth.docker.io/token",service="registry.docker.io",scope="repository:samalba/my-app:pull,push"`
+fn parse_challenge(value: &str) -> Option<Challenge> {
+    let (scheme, mut rest) = value.trim().split_once(' ').unwrap_or((value.trim(), ""));
+    if scheme.is_empty() {
+        return None;
+    }
+
+    let mut params = BTreeMap::new();
+    loop {
+        rest = rest.trim_start_matches([' ', ',']);
+        if rest.is_empty() {
+            break;
+        }
+        let (key, after_key) = rest.split_once('=')?;
+        let (value, after_value) = match after_key.strip_prefix('"') {
+            Some(quoted) => {
+                let end = quoted.find('"')?;
+                (&quoted[..end], &quoted[end + 1..])
+            }
+            None => after_key.split_once(',').unwrap_or((after_key, "")),
+        };
+        params.insert(key.trim().to_ascii_lowercase(), value.to_owned());
+        rest = after_value;
+    }
+
+    Some(Challenge {
+        scheme: scheme.to_owned(),
+        params,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rstest::rstest;
+
+    #[rstest]
+    #[case::bearer(
+        r#"Bearer realm="https://auth.docker.io/token",service="registry.docker.io",scope="repository:samalba/my-app:pull,push""#,
+        "Bearer",
+        vec![
+            ("realm", "https://auth.docker.io/token"),
+            ("scope", "repository:samalba/my-app:pull,push"),
+            ("service", "registry.docker.io"),
+        ]
+    )]
+    #[case::basic(r#"Basic realm="Registry Realm""#, "Basic", vec![("realm", "Registry Realm")])]
+    #[case::unquoted(
+        "Bearer realm=https://ghcr.io/token, service=ghcr.io",
+        "Bearer",
+        vec![("realm", "https://ghcr.io/token"), ("service", "ghcr.io")]
+    )]
+    fn authentication_challenge(
+        #[case] value: &str,
+        #[case] expected_scheme: &str,
+        #[case] expected_params: Vec<(&str, &str)>,
+    ) {
+        let challenge = parse_challenge(value).expect("cannot parse challenge");
+
+        assert_eq!(challenge.scheme, expected_scheme);
+        assert_eq!(
+            challenge.params,
+            expected_params
+                .into_iter()
+                .map(|(key, value)| (key.to_owned(), value.to_owned()))
+                .collect::<BTreeMap<String, String>>()
+        );
+    }
+
+    #[test]
+    fn invalid_authentication_challenge() {
+        assert!(parse_challenge("").is_none());
+        assert!(parse_challenge(r#"Bearer realm="https://auth.docker.io/token"#).is_none());
+    }
+}
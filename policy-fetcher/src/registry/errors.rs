@@ -20,4 +20,84 @@ pub enum RegistryError {
     InvalidURLError(#[from] InvalidURLError),
     #[error(transparent)]
     JSONParseError(#[from] serde_json::Error),
+    #[error(transparent)]
+    SourceError(#[from] crate::sources::SourceError),
+    #[error("cannot reach the OCI registry: {0}")]
+    HttpError(#[from] reqwest::Error),
+    #[error("cannot authenticate against the OCI registry: {0}")]
+    AuthenticationError(String),
+    #[error("{0} not found")]
+    ManifestNotFoundError(String),
+    #[error("the OCI registry {0} does not allow to delete artifacts")]
+    DeletionDisabledError(String),
+    #[error("not authorized to delete {0}")]
+    DeletionUnauthorizedError(String),
+    #[error("cannot delete {reference}, the OCI registry replied with {status}: {message}")]
+    DeletionError {
+        reference: String,
+        status: u16,
+        message: String,
+    },
+    #[error("cannot tag {0}: {1}")]
+    TagError(String, String),
+    #[error("{0} does not have a layer of type {1}")]
+    MissingLayerError(String, String),
+}
+
+impl RegistryError {
+    /// Whether the registry rejected the request because too many requests
+    /// have been made
+    pub fn is_throttled(&self) -> bool {
+        match self {
+            RegistryError::OCIRegistryError(e) => is_throttling_error(e),
+            _ => false,
+        }
+    }
+
+    /// Whether the registry could not be reached, or failed because of an error
+    /// on its side. The operation can be retried later.
+    pub fn is_transient(&self) -> bool {
+        match self {
+            RegistryError::OCIRegistryError(e) => is_transient_error(e),
+            RegistryError::HttpError(e) => is_transient_request_error(e),
+            RegistryError::SourceError(e) => e.is_transient(),
+            _ => false,
+        }
+    }
+}
+
+/// Whether the registry rejected the request because too many requests
+/// have been made
+pub(crate) fn is_throttling_error(error: &oci_client::errors::OciDistributionError) -> bool {
+    use oci_client::errors::{OciDistributionError, OciErrorCode};
+
+    match error {
+        OciDistributionError::ServerError { code, .. } => *code == 429,
+        OciDistributionError::RegistryError { envelope, .. } => envelope
+            .errors
+            .iter()
+            .any(|e| matches!(e.code, OciErrorCode::Toomanyrequests)),
+        _ => false,
+    }
+}
+
+/// Whether the registry could not be reached, or replied with a server error
+pub(crate) fn is_transient_error(error: &oci_client::errors::OciDistributionError) -> bool {
+    use oci_client::errors::OciDistributionError;
+
+    match error {
+        OciDistributionError::ServerError { code, .. } => *code >= 500,
+        OciDistributionError::RequestError(e) => is_transient_request_error(e),
+        _ => false,
+    }
+}
+
+/// Whether the connection to the server failed or timed out, or the server
+/// replied with a server error
+pub(crate) fn is_transient_request_error(error: &reqwest::Error) -> bool {
+    error.is_connect()
+        || error.is_timeout()
+        || error
+            .status()
+            .is_some_and(|status| status.is_server_error())
 }
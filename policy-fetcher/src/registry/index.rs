@@ -0,0 +1,198 @@
+//! Policies referenced through an image index.
+//!
+//! Some registries wrap the pushed artifacts inside of an image index, and the
+//! same tag can be used by a multi-platform image listing the Wasm module next to
+//! other artifacts. The manifest of the Wasm module is selected among the ones
+//! listed by the index using their `artifactType`, or their platform when the
+//! artifact type is not set.
+
+use oci_client::manifest;
+use serde_json::Value;
+use sha2::{Digest, Sha256, Sha512};
+
+use super::manifest_media_type;
+
+/// The media types of the image indexes
+const IMAGE_INDEX_MEDIA_TYPES: &[&str] = &[
+    manifest::OCI_IMAGE_INDEX_MEDIA_TYPE,
+    manifest::IMAGE_MANIFEST_LIST_MEDIA_TYPE,
+];
+
+/// The media types of the manifests listed by an index that can describe a Wasm module
+const IMAGE_MANIFEST_MEDIA_TYPES: &[&str] = &[
+    manifest::OCI_IMAGE_MEDIA_TYPE,
+    manifest::IMAGE_MANIFEST_MEDIA_TYPE,
+];
+
+/// The `artifactType` of the Wasm modules. When not set explicitly, the artifact
+/// type of a manifest is the media type of its config
+const WASM_ARTIFACT_TYPES: &[&str] = &[
+    manifest::WASM_CONFIG_MEDIA_TYPE,
+    manifest::WASM_LAYER_MEDIA_TYPE,
+];
+
+/// The architecture of the Wasm modules, as declared by the platform of the manifest
+const WASM_ARCHITECTURE: &str = "wasm";
+
+/// Whether the given manifest is an image index. The `mediaType` of an index is
+/// optional, the indexes without it are recognized by their `manifests`
+pub(crate) fn is_image_index(manifest: &[u8]) -> bool {
+    match manifest_media_type(manifest) {
+        Some(media_type) => IMAGE_INDEX_MEDIA_TYPES.contains(&media_type.as_str()),
+        None => serde_json::from_slice::<Value>(manifest)
+            .is_ok_and(|manifest| manifest.get("manifests").is_some_and(Value::is_array)),
+    }
+}
+
+/// Return the digest of the manifest of the Wasm module listed by the given index.
+/// Fails when the index doesn't list exactly one Wasm module
+pub(crate) fn select_wasm_manifest(index: &[u8]) -> Result<String, String> {
+    let index: Value =
+        serde_json::from_slice(index).map_err(|e| format!("cannot parse the index: {e}"))?;
+    let entries = index
+        .get("manifests")
+        .and_then(Value::as_array)
+        .ok_or_else(|| "the index doesn't list any manifest".to_owned())?;
+
+    let mut digests: Vec<&str> = entries
+        .iter()
+        .filter(|entry| is_wasm_entry(entry))
+        .filter_map(|entry| entry.get("digest").and_then(Value::as_str))
+        .collect();
+    digests.sort_unstable();
+    digests.dedup();
+
+    match digests.as_slice() {
+        [digest] => Ok(digest.to_string()),
+        [] => Err("the index doesn't list any Wasm module".to_owned()),
+        _ => Err(format!(
+            "the index lists more than one Wasm module: {}",
+            digests.join(", ")
+        )),
+    }
+}
+
+fn is_wasm_entry(entry: &Value) -> bool {
+    let field = |name: &str| entry.get(name).and_then(Value::as_str);
+
+    let media_type = field("mediaType");
+    if media_type.is_some_and(|media_type| !IMAGE_MANIFEST_MEDIA_TYPES.contains(&media_type)) {
+        return false;
+    }
+
+    match field("artifactType") {
+        Some(artifact_type) => WASM_ARTIFACT_TYPES.contains(&artifact_type),
+        None => {
+            entry
+                .get("platform")
+                .and_then(|platform| platform.get("architecture"))
+                .and_then(Value::as_str)
+                == Some(WASM_ARCHITECTURE)
+        }
+    }
+}
+
+/// Ensure the manifest matches the digest listed by the index
+pub(crate) fn verify_manifest_digest(manifest: &[u8], expected_digest: &str) -> Result<(), String> {
+    let actual_digest = match expected_digest.split_once(':') {
+        Some(("sha256", _)) => format!("sha256:{:x}", Sha256::digest(manifest)),
+        Some(("sha512", _)) => format!("sha512:{:x}", Sha512::digest(manifest)),
+        _ => return Err(format!("unsupported digest {expected_digest}")),
+    };
+
+    if actual_digest != expected_digest {
+        return Err(format!(
+            "the manifest of the Wasm module doesn't match the digest listed by the index. Got {actual_digest} instead of {expected_digest}"
+        ));
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rstest::rstest;
+    use serde_json::json;
+
+    const WASM_DIGEST: &str =
+        "sha256:72b4569c3daee67abeaa64192fb53895d0edb2d44fa6e1d9d4c5d3f8ece09f6e";
+    const IMAGE_DIGEST: &str =
+        "sha256:0a1b2c3d4e5f60718293a4b5c6d7e8f90a1b2c3d4e5f60718293a4b5c6d7e8f9";
+
+    fn index(manifests: Value) -> Vec<u8> {
+        serde_json::to_vec(&json!({
+            "schemaVersion": 2,
+            "mediaType": manifest::OCI_IMAGE_INDEX_MEDIA_TYPE,
+            "manifests": manifests,
+        }))
+        .unwrap()
+    }
+
+    fn image_entry() -> Value {
+        json!({
+            "mediaType": manifest::OCI_IMAGE_MEDIA_TYPE,
+            "digest": IMAGE_DIGEST,
+            "size": 1024,
+            "platform": {"os": "linux", "architecture": "amd64"},
+        })
+    }
+
+    #[rstest]
+    #[case::artifact_type(json!({
+        "mediaType": manifest::OCI_IMAGE_MEDIA_TYPE,
+        "artifactType": manifest::WASM_CONFIG_MEDIA_TYPE,
+        "digest": WASM_DIGEST,
+        "size": 512,
+    }))]
+    #[case::platform(json!({
+        "mediaType": manifest::OCI_IMAGE_MEDIA_TYPE,
+        "digest": WASM_DIGEST,
+        "size": 512,
+        "platform": {"os": "wasip1", "architecture": "wasm"},
+    }))]
+    fn wasm_manifest_selected(#[case] wasm_entry: Value) {
+        let index = index(json!([image_entry(), wasm_entry]));
+
+        assert_eq!(select_wasm_manifest(&index), Ok(WASM_DIGEST.to_owned()));
+    }
+
+    #[rstest]
+    #[case::no_wasm_module(json!([image_entry()]))]
+    #[case::nested_index(json!([{
+        "mediaType": manifest::OCI_IMAGE_INDEX_MEDIA_TYPE,
+        "artifactType": manifest::WASM_CONFIG_MEDIA_TYPE,
+        "digest": WASM_DIGEST,
+        "size": 512,
+    }]))]
+    #[case::several_wasm_modules(json!([
+        {"artifactType": manifest::WASM_CONFIG_MEDIA_TYPE, "digest": WASM_DIGEST},
+        {"artifactType": manifest::WASM_CONFIG_MEDIA_TYPE, "digest": IMAGE_DIGEST},
+    ]))]
+    fn wasm_manifest_not_selected(#[case] manifests: Value) {
+        assert!(select_wasm_manifest(&index(manifests)).is_err());
+    }
+
+    #[rstest]
+    #[case::oci_index(json!({"mediaType": manifest::OCI_IMAGE_INDEX_MEDIA_TYPE, "manifests": []}), true)]
+    #[case::docker_manifest_list(json!({"mediaType": manifest::IMAGE_MANIFEST_LIST_MEDIA_TYPE, "manifests": []}), true)]
+    #[case::index_without_media_type(json!({"schemaVersion": 2, "manifests": []}), true)]
+    #[case::image(json!({"mediaType": manifest::OCI_IMAGE_MEDIA_TYPE, "layers": []}), false)]
+    #[case::image_without_media_type(json!({"schemaVersion": 2, "layers": []}), false)]
+    fn image_index_detected(#[case] manifest: Value, #[case] expected: bool) {
+        assert_eq!(
+            is_image_index(&serde_json::to_vec(&manifest).unwrap()),
+            expected
+        );
+    }
+
+    #[test]
+    fn manifest_digest_verified() {
+        let manifest = br#"{"schemaVersion": 2}"#;
+        let digest = format!("sha256:{:x}", Sha256::digest(manifest));
+
+        assert!(verify_manifest_digest(manifest, &digest).is_ok());
+        assert!(verify_manifest_digest(b"tampered", &digest).is_err());
+        assert!(verify_manifest_digest(manifest, "md5:abcdef").is_err());
+    }
+}
@@ -1,4 +1,4 @@
-use std::{collections::BTreeMap, convert::TryFrom, str::FromStr};
+use std::{collections::BTreeMap, convert::TryFrom, str::FromStr, sync::Arc};
 
 use async_trait::async_trait;
 use docker_credential::DockerCredential;
@@ -12,9 +12,10 @@ use oci_client::{
     },
     manifest,
     secrets::RegistryAuth,
-    Reference,
+    Reference, RegistryOperation,
 };
 use regex::Regex;
+use reqwest::header::HeaderValue;
 use tracing::{debug, info, warn};
 use url::Url;
 
@@ -24,16 +25,53 @@ use crate::{
     sources::{Certificate, SourceError, SourceResult, Sources},
 };
 
+mod distribution;
 pub mod errors;
+mod index;
+mod pool;
+
+use pool::ClientPool;
+pub use pool::ClientPoolConfig;
 
 lazy_static! {
     static ref SHA256_DIGEST_RE: Regex = Regex::new(r"[A-Fa-f0-9]{64}").unwrap();
     static ref SHA512_DIGEST_RE: Regex = Regex::new(r"[A-Fa-f0-9]{128}").unwrap();
 }
 
+/// The number of tags requested to the registry with each page
+const TAGS_PAGE_SIZE: usize = 100;
+
+/// The media types of the manifests that can be tagged
+const TAGGABLE_MEDIA_TYPES: &[&str] = &[
+    manifest::OCI_IMAGE_MEDIA_TYPE,
+    manifest::OCI_IMAGE_INDEX_MEDIA_TYPE,
+    manifest::IMAGE_MANIFEST_MEDIA_TYPE,
+    manifest::IMAGE_MANIFEST_LIST_MEDIA_TYPE,
+];
+
+/// The media types of the manifests that can reference a Wasm module, either
+/// directly or through an image index
+const WASM_MANIFEST_MEDIA_TYPES: &[&str] = &[
+    manifest::OCI_IMAGE_MEDIA_TYPE,
+    manifest::IMAGE_MANIFEST_MEDIA_TYPE,
+    manifest::OCI_IMAGE_INDEX_MEDIA_TYPE,
+    manifest::IMAGE_MANIFEST_LIST_MEDIA_TYPE,
+];
+
+/// The media types of an OCI artifact made of a single layer
+#[derive(Clone, Copy, Debug)]
+pub struct ArtifactMediaTypes<'a> {
+    /// Media type of the layer holding the contents of the artifact
+    pub layer: &'a str,
+    /// Media type of the config of the artifact
+    pub config: &'a str,
+}
+
 // Struct used to reference a WASM module that is hosted on an OCI registry
-#[derive(Default)]
-pub struct Registry {}
+#[derive(Default, Clone)]
+pub struct Registry {
+    clients: Option<Arc<ClientPool>>,
+}
 
 impl From<&Certificate> for OciCertificate {
     fn from(certificate: &Certificate) -> OciCertificate {
@@ -128,13 +166,63 @@ where
     operation(client_protocol).await
 }
 
+/// Perform the given read operation on the mirrors of the registry hosting `reference`,
+/// in the order they are declared inside of the sources. When all of them fail, or when
+/// the registry has no mirror, the operation is performed against the registry itself.
+///
+/// The operation receives the reference of the object on the registry being tried,
+/// together with the credentials of that registry. Each registry is tried with the
+/// protocols chosen by [`try_with_protocols`].
+async fn try_with_mirrors<'a, F, T>(
+    reference: &'a Reference,
+    sources: &'a Sources,
+    operation: F,
+) -> RegistryResult<T>
+where
+    F: Fn(Reference, RegistryAuth, ClientProtocol) -> BoxFuture<'a, RegistryResult<T>>,
+{
+    for mirror in mirror_references(reference, sources)? {
+        let url: Url = Url::parse(format!("registry://{mirror}").as_str())?;
+        let registry_auth = Registry::auth(mirror.registry());
+        let result = try_with_protocols(&url, sources, |client_protocol| {
+            operation(mirror.clone(), registry_auth.clone(), client_protocol)
+        })
+        .await;
+        match result {
+            Ok(result) => return Ok(result),
+            Err(err) => {
+                info!(%err, mirror = mirror.registry(), "operation failed on registry mirror");
+            }
+        }
+    }
+
+    let url: Url = Url::parse(format!("registry://{reference}").as_str())?;
+    let registry_auth = Registry::auth(reference.registry());
+    try_with_protocols(&url, sources, |client_protocol| {
+        operation(reference.clone(), registry_auth.clone(), client_protocol)
+    })
+    .await
+}
+
 impl Registry {
     pub fn new() -> Registry {
-        Registry {}
+        Registry { clients: None }
+    }
+
+    /// Create a Registry that reuses its OCI clients, together with their
+    /// connections, across all the operations. Clones of the returned object
+    /// share the same pool.
+    pub fn with_client_pool(config: ClientPoolConfig) -> Registry {
+        Registry {
+            clients: Some(Arc::new(ClientPool::new(config))),
+        }
     }
 
-    fn client(client_protocol: ClientProtocol) -> Client {
-        Client::new(client_protocol.into())
+    fn client(&self, client_protocol: ClientProtocol) -> Client {
+        match &self.clients {
+            Some(pool) => pool.client(client_protocol),
+            None => Client::new(client_protocol.into()),
+        }
     }
 
     pub fn auth(registry: &str) -> RegistryAuth {
@@ -169,27 +257,56 @@ impl Registry {
         // ensure it contains also the registry. Example: `busybox` ->
         // `docker.io/library/busybox:latest`
         let reference = build_fully_resolved_reference(url)?;
-        let url: Url = Url::parse(format!("registry://{reference}").as_str())?;
-        let registry_auth = Registry::auth(reference.registry());
         let sources: Sources = sources.cloned().unwrap_or_default();
 
-        let (oci_manifest, _) = try_with_protocols(&url, &sources, |client_protocol| {
-            Box::pin({
-                let reference = reference.clone();
-                let registry_auth = registry_auth.clone();
-                async move {
-                    let res = Registry::client(client_protocol)
-                        .pull_manifest(&reference, &registry_auth)
-                        .await?;
-                    Ok(res)
-                }
-            })
-        })
+        let (oci_manifest, _) = try_with_mirrors(
+            &reference,
+            &sources,
+            |reference, registry_auth, client_protocol| {
+                Box::pin({
+                    let client = self.client(client_protocol);
+                    async move {
+                        let res = client.pull_manifest(&reference, &registry_auth).await?;
+                        Ok(res)
+                    }
+                })
+            },
+        )
         .await?;
 
         Ok(oci_manifest)
     }
 
+    /// Fetch the manifest of the Wasm module referenced by the given url. When the url
+    /// references an image index, the manifest of the Wasm module listed by the index
+    /// is returned, see [`resolve_wasm_manifest`].
+    pub async fn wasm_manifest(
+        &self,
+        url: &str,
+        sources: Option<&Sources>,
+    ) -> RegistryResult<manifest::OciImageManifest> {
+        let reference = build_fully_resolved_reference(url)?;
+        let sources: Sources = sources.cloned().unwrap_or_default();
+
+        let (_, wasm_manifest) = try_with_mirrors(
+            &reference,
+            &sources,
+            |reference, registry_auth, client_protocol| {
+                Box::pin({
+                    let client = self.client(client_protocol);
+                    async move {
+                        let res =
+                            resolve_wasm_manifest(&client, &reference, &registry_auth).await?;
+                        Ok(res)
+                    }
+                })
+            },
+        )
+        .await?;
+
+        Ok(serde_json::from_slice(&wasm_manifest)?)
+    }
+
     /// Fetch the manifest's digest of the OCI object referenced by the given url.
     pub async fn manifest_digest(
         &self,
@@ -200,27 +317,100 @@ impl Registry {
         // ensure it contains also the registry. Example: `busybox` ->
         // `docker.io/library/busybox:latest`
         let reference = build_fully_resolved_reference(url)?;
-        let url: Url = Url::parse(format!("registry://{reference}").as_str())?;
-        let registry_auth = Registry::auth(reference.registry());
         let sources: Sources = sources.cloned().unwrap_or_default();
 
-        let digest = try_with_protocols(&url, &sources, |client_protocol| {
-            Box::pin({
-                let reference = reference.clone();
-                let registry_auth = registry_auth.clone();
-                async move {
-                    let res = Registry::client(client_protocol)
-                        .fetch_manifest_digest(&reference, &registry_auth)
-                        .await?;
-                    Ok(res)
-                }
-            })
-        })
+        let digest = try_with_mirrors(
+            &reference,
+            &sources,
+            |reference, registry_auth, client_protocol| {
+                Box::pin({
+                    let client = self.client(client_protocol);
+                    async move {
+                        let res = client
+                            .fetch_manifest_digest(&reference, &registry_auth)
+                            .await?;
+                        Ok(res)
+                    }
+                })
+            },
+        )
         .await?;
 
         Ok(digest)
     }
 
+    /// Fetch the raw contents of the manifest of the OCI object referenced by the given url,
+    /// together with its digest.
+    pub async fn manifest_raw(
+        &self,
+        url: &str,
+        sources: Option<&Sources>,
+    ) -> RegistryResult<(Vec<u8>, String)> {
+        let reference = build_fully_resolved_reference(url)?;
+        let sources: Sources = sources.cloned().unwrap_or_default();
+
+        let (manifest, digest) = try_with_mirrors(
+            &reference,
+            &sources,
+            |reference, registry_auth, client_protocol| {
+                Box::pin({
+                    let client = self.client(client_protocol);
+                    async move {
+                        let res = client
+                            .pull_manifest_raw(
+                                &reference,
+                                &registry_auth,
+                                &[
+                                    manifest::OCI_IMAGE_MEDIA_TYPE,
+                                    manifest::IMAGE_MANIFEST_MEDIA_TYPE,
+                                ],
+                            )
+                            .await?;
+                        Ok(res)
+                    }
+                })
+            },
+        )
+        .await?;
+
+        Ok((manifest.to_vec(), digest))
+    }
+
+    /// List the OCI artifacts referring to the OCI object referenced by the given url,
+    /// which must include the digest. When `artifact_type` is set, only the artifacts
+    /// of that type are returned.
+    pub async fn referrers(
+        &self,
+        url: &str,
+        artifact_type: Option<&str>,
+        sources: Option<&Sources>,
+    ) -> RegistryResult<Vec<manifest::ImageIndexEntry>> {
+        let reference = build_fully_resolved_reference(url)?;
+        let sources: Sources = sources.cloned().unwrap_or_default();
+
+        let index = try_with_mirrors(
+            &reference,
+            &sources,
+            |reference, registry_auth, client_protocol| {
+                Box::pin({
+                    let client = self.client(client_protocol);
+                    async move {
+                        // the referrers API is not covered by the authentication performed
+                        // by the other operations
+                        client
+                            .auth(&reference, &registry_auth, RegistryOperation::Pull)
+                            .await?;
+                        let res = client.pull_referrers(&reference, artifact_type).await?;
+                        Ok(res)
+                    }
+                })
+            },
+        )
+        .await?;
+
+        Ok(index.manifests)
+    }
+
     /// Push the policy to the OCI registry specified by `url`.
     ///
     /// Returns the immutable reference to the policy (i.e.
@@ -231,6 +421,31 @@ impl Registry {
         destination: &str,
         sources: Option<&Sources>,
         annotations: Option<BTreeMap<String, String>>,
+    ) -> RegistryResult<String> {
+        self.push_artifact(
+            policy,
+            &ArtifactMediaTypes {
+                layer: manifest::WASM_LAYER_MEDIA_TYPE,
+                config: manifest::WASM_CONFIG_MEDIA_TYPE,
+            },
+            destination,
+            sources,
+            annotations,
+        )
+        .await
+    }
+
+    /// Push to the OCI registry specified by `url` an artifact made of a single
+    /// layer, holding `data`.
+    ///
+    /// Returns the immutable reference to the artifact
+    pub async fn push_artifact(
+        &self,
+        data: &[u8],
+        media_types: &ArtifactMediaTypes<'_>,
+        destination: &str,
+        sources: Option<&Sources>,
+        annotations: Option<BTreeMap<String, String>>,
     ) -> RegistryResult<String> {
         let url = Url::parse(destination)
             .map_err(|_| crate::errors::InvalidURLError(destination.to_owned()))?;
@@ -245,7 +460,13 @@ impl Registry {
                 let annotations = annotations.clone();
                 async move {
                     let res = self
-                        .do_push(policy, &url, annotations.as_ref(), client_protocol.clone())
+                        .do_push(
+                            data,
+                            media_types,
+                            &url,
+                            annotations.as_ref(),
+                            client_protocol.clone(),
+                        )
                         .await?;
                     Ok(res)
                 }
@@ -257,33 +478,35 @@ impl Registry {
 
     async fn do_push(
         &self,
-        policy: &[u8],
+        data: &[u8],
+        media_types: &ArtifactMediaTypes<'_>,
         url: &Url,
         annotations: Option<&BTreeMap<String, String>>,
         client_protocol: ClientProtocol,
     ) -> RegistryResult<String> {
-        debug!(client_protocol = ?client_protocol, "pushing policy");
+        debug!(client_protocol = ?client_protocol, media_type = media_types.layer, "pushing artifact");
         let reference =
             Reference::from_str(url.as_ref().strip_prefix("registry://").unwrap_or_default())?;
 
         let registry_auth = Registry::auth(reference.registry());
 
         let layers = vec![ImageLayer::new(
-            policy.to_vec(),
-            manifest::WASM_LAYER_MEDIA_TYPE.to_string(),
+            data.to_vec(),
+            media_types.layer.to_string(),
             None,
         )];
 
         let config = Config {
             data: b"{}".to_vec(),
-            media_type: manifest::WASM_CONFIG_MEDIA_TYPE.to_string(),
+            media_type: media_types.config.to_string(),
             annotations: None,
         };
 
         let image_manifest =
             manifest::OciImageManifest::build(&layers, &config, annotations.cloned());
 
-        Ok(Registry::client(client_protocol)
+        Ok(self
+            .client(client_protocol)
             .push(
                 &reference,
                 &layers,
@@ -295,6 +518,42 @@ impl Registry {
             .map(|push_response| push_response.manifest_url)?)
     }
 
+    /// Pull the artifact referenced by `url`, returning the contents of its layer
+    /// of the given media type
+    pub async fn pull_artifact(
+        &self,
+        url: &str,
+        layer_media_type: &str,
+        sources: Option<&Sources>,
+    ) -> RegistryResult<Vec<u8>> {
+        let reference = build_fully_resolved_reference(url)?;
+        let sources: Sources = sources.cloned().unwrap_or_default();
+
+        let layer = try_with_mirrors(
+            &reference,
+            &sources,
+            |reference, registry_auth, client_protocol| {
+                Box::pin({
+                    let client = self.client(client_protocol);
+                    async move {
+                        let image_data = client
+                            .pull(&reference, &registry_auth, vec![layer_media_type])
+                            .await?;
+                        Ok(image_data
+                            .layers
+                            .into_iter()
+                            .find(|layer| layer.media_type == layer_media_type))
+                    }
+                })
+            },
+        )
+        .await?;
+
+        layer.map(|layer| layer.data).ok_or_else(|| {
+            RegistryError::MissingLayerError(reference.whole(), layer_media_type.to_owned())
+        })
+    }
+
     /// Fetch the manifest, its digest and container image configuration of the OCI object referenced by the given url.
     pub async fn manifest_and_config(
         &self,
@@ -306,35 +565,254 @@ impl Registry {
         serde_json::Value,
     )> {
         let reference = build_fully_resolved_reference(url)?;
+        let sources: Sources = sources.cloned().unwrap_or_default();
+
+        let (manifest, digest, config) = try_with_mirrors(
+            &reference,
+            &sources,
+            |reference, registry_auth, client_protocol| {
+                Box::pin({
+                    let client = self.client(client_protocol);
+                    async move {
+                        let res = client
+                            .pull_manifest_and_config(&reference, &registry_auth)
+                            .await?;
+                        Ok(res)
+                    }
+                })
+            },
+        )
+        .await?;
+
+        let config_json = serde_json::from_str(&config)?;
+
+        Ok((manifest, digest, config_json))
+    }
+
+    /// List the tags of the repository referenced by the given url. The tags are
+    /// fetched one page at a time.
+    pub async fn tags(&self, url: &str, sources: Option<&Sources>) -> RegistryResult<Vec<String>> {
+        let reference = build_fully_resolved_reference(url)?;
+        let sources: Sources = sources.cloned().unwrap_or_default();
+
+        try_with_mirrors(
+            &reference,
+            &sources,
+            |reference, registry_auth, client_protocol| {
+                Box::pin({
+                    let client = self.client(client_protocol);
+                    async move {
+                        let mut tags: Vec<String> = Vec::new();
+                        loop {
+                            let page = client
+                                .list_tags(
+                                    &reference,
+                                    &registry_auth,
+                                    Some(TAGS_PAGE_SIZE),
+                                    tags.last().map(String::as_str),
+                                )
+                                .await?;
+                            // some registries ignore the pagination parameters and
+                            // always return the same tags
+                            let last_page = page.tags.len() < TAGS_PAGE_SIZE
+                                || page.tags.last().is_none_or(|last| tags.contains(last));
+                            tags.extend(
+                                page.tags
+                                    .into_iter()
+                                    .filter(|tag| !tags.contains(tag))
+                                    .collect::<Vec<String>>(),
+                            );
+                            if last_page {
+                                break;
+                            }
+                        }
+                        Ok(tags)
+                    }
+                })
+            },
+        )
+        .await
+    }
+
+    /// Add the `new_tag` tag to the OCI artifact referenced by `url`. The new tag
+    /// is created inside of the same repository, hence no blob has to be copied.
+    ///
+    /// Returns the immutable reference to the tagged artifact
+    pub async fn tag(
+        &self,
+        url: &str,
+        new_tag: &str,
+        sources: Option<&Sources>,
+    ) -> RegistryResult<String> {
+        let reference = build_fully_resolved_reference(url)?;
+        let destination = Reference::from_str(&format!(
+            "{}/{}:{}",
+            reference.registry(),
+            reference.repository(),
+            new_tag
+        ))?;
+        if destination.tag() != Some(new_tag) || destination.digest().is_some() {
+            return Err(RegistryError::TagError(
+                reference.whole(),
+                format!("invalid tag '{new_tag}'"),
+            ));
+        }
         let url: Url = Url::parse(format!("registry://{reference}").as_str())?;
         let registry_auth = Registry::auth(reference.registry());
         let sources: Sources = sources.cloned().unwrap_or_default();
 
-        let (manifest, digest, config) = try_with_protocols(&url, &sources, |client_protocol| {
+        let digest = try_with_protocols(&url, &sources, |client_protocol| {
             Box::pin({
                 let reference = reference.clone();
+                let destination = destination.clone();
                 let registry_auth = registry_auth.clone();
+                let client = self.client(client_protocol);
                 async move {
-                    let res = Registry::client(client_protocol)
-                        .pull_manifest_and_config(&reference, &registry_auth)
+                    let (manifest, digest) = client
+                        .pull_manifest_raw(&reference, &registry_auth, TAGGABLE_MEDIA_TYPES)
                         .await?;
-                    Ok(res)
+                    let media_type = manifest_media_type(&manifest).ok_or_else(|| {
+                        RegistryError::TagError(
+                            reference.whole(),
+                            "cannot find the media type of the manifest".to_owned(),
+                        )
+                    })?;
+                    let content_type = HeaderValue::from_str(&media_type)
+                        .map_err(|e| RegistryError::TagError(reference.whole(), e.to_string()))?;
+
+                    client
+                        .auth(&destination, &registry_auth, RegistryOperation::Push)
+                        .await?;
+                    client
+                        .push_manifest_raw(&destination, manifest.to_vec(), content_type)
+                        .await?;
+                    Ok(digest)
                 }
             })
         })
         .await?;
 
-        let config_json = serde_json::from_str(&config)?;
+        build_immutable_ref(&destination.whole(), &digest)
+    }
 
-        Ok((manifest, digest, config_json))
+    /// Delete from the registry the OCI artifact referenced by `url`. The manifest
+    /// is deleted, together with all the tags pointing to it.
+    ///
+    /// Returns the digest of the deleted manifest
+    pub async fn delete(&self, url: &str, sources: Option<&Sources>) -> RegistryResult<String> {
+        let reference = build_fully_resolved_reference(url)?;
+        let url: Url = Url::parse(format!("registry://{reference}").as_str())?;
+        let registry_auth = Registry::auth(reference.registry());
+        let sources: Sources = sources.cloned().unwrap_or_default();
+
+        try_with_protocols(&url, &sources, |client_protocol| {
+            Box::pin({
+                let reference = reference.clone();
+                let registry_auth = registry_auth.clone();
+                let client = self.client(client_protocol.clone());
+                let address_family = sources.address_family(reference.registry());
+                async move {
+                    // manifests can be deleted only by digest
+                    let digest = match reference.digest() {
+                        Some(digest) => digest.to_owned(),
+                        None => {
+                            client
+                                .fetch_manifest_digest(&reference, &registry_auth)
+                                .await?
+                        }
+                    };
+                    distribution::delete_manifest(
+                        &client_protocol,
+                        &reference,
+                        &digest,
+                        &registry_auth,
+                        address_family,
+                    )
+                    .await?;
+                    Ok(digest)
+                }
+            })
+        })
+        .await
     }
 }
 
+/// The media type of the given manifest, as declared by its `mediaType` field
+fn manifest_media_type(manifest: &[u8]) -> Option<String> {
+    serde_json::from_slice::<serde_json::Value>(manifest)
+        .ok()?
+        .get("mediaType")?
+        .as_str()
+        .map(str::to_owned)
+}
+
+/// Return the reference of the Wasm module referenced by `reference`, together with
+/// its manifest. When `reference` points to an image index, the manifest of the Wasm
+/// module is selected among the ones listed by the index, and it's verified against
+/// the digest listed by the index. The returned reference is then pinned by digest
+async fn resolve_wasm_manifest(
+    client: &Client,
+    reference: &Reference,
+    registry_auth: &RegistryAuth,
+) -> SourceResult<(Reference, Vec<u8>)> {
+    let (manifest, _) = client
+        .pull_manifest_raw(reference, registry_auth, WASM_MANIFEST_MEDIA_TYPES)
+        .await?;
+    if !index::is_image_index(&manifest) {
+        return Ok((reference.clone(), manifest.to_vec()));
+    }
+
+    let invalid_index = |message| SourceError::InvalidImageIndexError {
+        reference: reference.whole(),
+        message,
+    };
+    let digest = index::select_wasm_manifest(&manifest).map_err(invalid_index)?;
+    let wasm_reference = reference.clone_with_digest(digest.clone());
+    let (wasm_manifest, _) = client
+        .pull_manifest_raw(
+            &wasm_reference,
+            registry_auth,
+            &[
+                manifest::OCI_IMAGE_MEDIA_TYPE,
+                manifest::IMAGE_MANIFEST_MEDIA_TYPE,
+            ],
+        )
+        .await?;
+    index::verify_manifest_digest(&wasm_manifest, &digest).map_err(invalid_index)?;
+    debug!(image = %reference, %digest, "Wasm module selected from image index");
+
+    Ok((wasm_reference, wasm_manifest.to_vec()))
+}
+
 pub(crate) fn build_fully_resolved_reference(url: &str) -> RegistryResult<Reference> {
     let image = url.strip_prefix("registry://").unwrap_or(url);
     Ok(Reference::try_from(image)?)
 }
 
+/// The references of the given OCI object on the mirrors of its registry, in the
+/// order they have to be tried. The repository, the tag and the digest are kept
+pub(crate) fn mirror_references(
+    reference: &Reference,
+    sources: &Sources,
+) -> RegistryResult<Vec<Reference>> {
+    sources
+        .mirrors(reference.registry())
+        .iter()
+        .map(|mirror| {
+            let mut image = format!("{mirror}/{}", reference.repository());
+            if let Some(tag) = reference.tag() {
+                image.push(':');
+                image.push_str(tag);
+            }
+            if let Some(digest) = reference.digest() {
+                image.push('@');
+                image.push_str(digest);
+            }
+            Ok(Reference::try_from(image)?)
+        })
+        .collect()
+}
+
 #[async_trait]
 impl PolicyFetcher for Registry {
     async fn fetch(&self, url: &Url, client_protocol: ClientProtocol) -> SourceResult<Vec<u8>> {
@@ -342,10 +820,13 @@ impl PolicyFetcher for Registry {
             Reference::from_str(url.as_ref().strip_prefix("registry://").unwrap_or_default())?;
         debug!(image=?reference, ?client_protocol, "fetching policy");
 
-        let image_content = Registry::client(client_protocol)
+        let client = self.client(client_protocol);
+        let registry_auth = Registry::auth(&crate::host_and_port(url)?);
+        let (reference, _) = resolve_wasm_manifest(&client, &reference, &registry_auth).await?;
+        let image_content = client
             .pull(
                 &reference,
-                &Registry::auth(&crate::host_and_port(url)?),
+                &registry_auth,
                 vec![manifest::WASM_LAYER_MEDIA_TYPE],
             )
             .await?
@@ -545,4 +1026,53 @@ mod tests {
             Err(err) => panic!("unknown error: {err:?}"),
         }
     }
+
+    #[rstest]
+    #[case::tag(
+        "ghcr.io/kubewarden/policies/pod-privileged:v0.1.0",
+        vec![
+            "mirror.example.com/kubewarden/policies/pod-privileged:v0.1.0",
+            "localhost:5000/kubewarden/policies/pod-privileged:v0.1.0",
+        ]
+    )]
+    #[case::digest(
+        "ghcr.io/kubewarden/policies/pod-privileged@sha256:72b4569c3daee67abeaa64192fb53895d0edb2d44fa6e1d9d4c5d3f8ece09f6e",
+        vec![
+            "mirror.example.com/kubewarden/policies/pod-privileged@sha256:72b4569c3daee67abeaa64192fb53895d0edb2d44fa6e1d9d4c5d3f8ece09f6e",
+            "localhost:5000/kubewarden/policies/pod-privileged@sha256:72b4569c3daee67abeaa64192fb53895d0edb2d44fa6e1d9d4c5d3f8ece09f6e",
+        ]
+    )]
+    #[case::no_mirror("quay.io/kubewarden/policy:latest", vec![])]
+    fn references_on_mirrors(#[case] image: &str, #[case] expected: Vec<&str>) {
+        let sources = Sources {
+            mirrors: [(
+                "ghcr.io".to_owned(),
+                vec!["mirror.example.com".to_owned(), "localhost:5000".to_owned()],
+            )]
+            .into(),
+            ..Default::default()
+        };
+        let reference = build_fully_resolved_reference(image).unwrap();
+
+        let mirrors: Vec<String> = mirror_references(&reference, &sources)
+            .unwrap()
+            .iter()
+            .map(Reference::whole)
+            .collect();
+        assert_eq!(mirrors, expected);
+    }
+
+    #[rstest]
+    #[case::oci_manifest(
+        r#"{"schemaVersion": 2, "mediaType": "application/vnd.oci.image.manifest.v1+json"}"#,
+        Some("application/vnd.oci.image.manifest.v1+json")
+    )]
+    #[case::no_media_type(r#"{"schemaVersion": 2}"#, None)]
+    #[case::not_json("not a manifest", None)]
+    fn media_type_of_manifest(#[case] manifest: &str, #[case] expected: Option<&str>) {
+        assert_eq!(
+            manifest_media_type(manifest.as_bytes()).as_deref(),
+            expected
+        );
+    }
 }
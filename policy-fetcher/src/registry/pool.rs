@@ -0,0 +1,161 @@
+use std::{
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use oci_client::client::Client;
+
+use crate::fetcher::ClientProtocol;
+
+/// Configuration of the pool of OCI clients shared by the operations made
+/// against the registries
+#[derive(Clone, Debug, PartialEq)]
+pub struct ClientPoolConfig {
+    /// Maximum number of clients kept inside of the pool. Each client has its own
+    /// set of connections, the least recently used client is dropped when the
+    /// pool is full
+    pub max_size: usize,
+    /// Clients that have not been used for longer than this are dropped, closing
+    /// their connections
+    pub idle_timeout: Duration,
+}
+
+impl Default for ClientPoolConfig {
+    fn default() -> Self {
+        ClientPoolConfig {
+            max_size: 16,
+            idle_timeout: Duration::from_secs(90),
+        }
+    }
+}
+
+struct PooledClient {
+    client_protocol: ClientProtocol,
+    client: Client,
+    last_used: Instant,
+}
+
+/// A pool of OCI clients, one for each protocol used to reach the registries.
+///
+/// Reusing the same client allows to reuse its connections, together with the
+/// tokens obtained by the registries.
+pub(crate) struct ClientPool {
+    config: ClientPoolConfig,
+    clients: Mutex<Vec<PooledClient>>,
+}
+
+impl ClientPool {
+    pub(crate) fn new(config: ClientPoolConfig) -> Self {
+        ClientPool {
+            config,
+            clients: Mutex::new(Vec::new()),
+        }
+    }
+
+    /// Get the client to be used with the given protocol, a new one is created
+    /// when the pool doesn't have it yet
+    pub(crate) fn client(&self, client_protocol: ClientProtocol) -> Client {
+        let now = Instant::now();
+        let mut clients = self.clients.lock().expect("cannot lock the client pool");
+
+        clients.retain(|pooled| now.duration_since(pooled.last_used) <= self.config.idle_timeout);
+
+        if let Some(pooled) = clients
+            .iter_mut()
+            .find(|pooled| pooled.client_protocol == client_protocol)
+        {
+            pooled.last_used = now;
+            return pooled.client.clone();
+        }
+
+        let client = Client::new(client_protocol.clone().into());
+        if self.config.max_size == 0 {
+            return client;
+        }
+        if clients.len() >= self.config.max_size {
+            if let Some((lru, _)) = clients
+                .iter()
+                .enumerate()
+                .min_by_key(|(_, pooled)| pooled.last_used)
+            {
+                clients.swap_remove(lru);
+            }
+        }
+        clients.push(PooledClient {
+            client_protocol,
+            client: client.clone(),
+            last_used: now,
+        });
+
+        client
+    }
+
+    #[cfg(test)]
+    fn len(&self) -> usize {
+        self.clients.lock().unwrap().len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fetcher::TlsVerificationMode;
+
+    #[test]
+    fn clients_are_reused() {
+        let pool = ClientPool::new(ClientPoolConfig::default());
+
+        pool.client(ClientProtocol::Http);
+        pool.client(ClientProtocol::Http);
+        assert_eq!(pool.len(), 1);
+
+        pool.client(ClientProtocol::Https(TlsVerificationMode::SystemCa));
+        assert_eq!(pool.len(), 2);
+    }
+
+    #[test]
+    fn least_recently_used_client_is_dropped() {
+        let pool = ClientPool::new(ClientPoolConfig {
+            max_size: 2,
+            ..Default::default()
+        });
+
+        pool.client(ClientProtocol::Http);
+        pool.client(ClientProtocol::Https(TlsVerificationMode::SystemCa));
+        pool.client(ClientProtocol::Http);
+        pool.client(ClientProtocol::Https(
+            TlsVerificationMode::NoTlsVerification,
+        ));
+        assert_eq!(pool.len(), 2);
+
+        let clients = pool.clients.lock().unwrap();
+        assert!(clients
+            .iter()
+            .all(|pooled| pooled.client_protocol
+                != ClientProtocol::Https(TlsVerificationMode::SystemCa)));
+    }
+
+    #[test]
+    fn idle_clients_are_dropped() {
+        let pool = ClientPool::new(ClientPoolConfig {
+            idle_timeout: Duration::ZERO,
+            ..Default::default()
+        });
+
+        pool.client(ClientProtocol::Http);
+        std::thread::sleep(Duration::from_millis(5));
+        pool.client(ClientProtocol::Https(TlsVerificationMode::SystemCa));
+        assert_eq!(pool.len(), 1);
+    }
+
+    #[test]
+    fn pooling_can_be_disabled() {
+        let pool = ClientPool::new(ClientPoolConfig {
+            max_size: 0,
+            ..Default::default()
+        });
+
+        pool.client(ClientProtocol::Http);
+        assert_eq!(pool.len(), 0);
+    }
+}
@@ -0,0 +1,137 @@
+//! Rego libraries are documents shared by multiple OPA policies, like the helper
+//! data used by the rules of an organization. They are stored as OCI artifacts,
+//! next to the policies, and are linked into the `data` document of the policies
+//! referencing them when these are loaded. Only data documents can be shared,
+//! Rego functions and rules cannot be linked into the policies.
+//!
+//! The libraries are always referenced by digest, this ensures all the policies
+//! use the very same version of the library.
+
+use oci_client::Reference;
+use thiserror::Error;
+
+use crate::{
+    registry::{
+        build_fully_resolved_reference, errors::RegistryError, ArtifactMediaTypes, Registry,
+    },
+    sources::Sources,
+};
+
+/// Media type of the layer holding the JSON document of a Rego library
+pub const REGO_LIBRARY_LAYER_MEDIA_TYPE: &str = "application/vnd.kubewarden.rego-library.v1+json";
+/// Media type of the config of the OCI artifacts holding a Rego library
+pub const REGO_LIBRARY_CONFIG_MEDIA_TYPE: &str =
+    "application/vnd.kubewarden.rego-library.config.v1+json";
+
+pub type RegoLibraryResult<T> = std::result::Result<T, RegoLibraryError>;
+
+#[derive(Error, Debug)]
+pub enum RegoLibraryError {
+    #[error("Rego library {0} is not pinned by digest, use a reference like `registry://ghcr.io/org/library@sha256:<digest>`")]
+    NotPinnedError(String),
+    #[error("invalid Rego library {reference}: {message}")]
+    InvalidDocumentError { reference: String, message: String },
+    #[error(transparent)]
+    RegistryError(#[from] RegistryError),
+}
+
+/// The contents of a Rego library
+pub type RegoLibraryDocument = serde_json::Map<String, serde_json::Value>;
+
+/// Store the document of a Rego library as an OCI artifact. `destination` must
+/// use the `registry://` scheme.
+///
+/// Returns the immutable reference to the artifact, that's the one to be used by
+/// the policies
+pub async fn push_rego_library(
+    document: &[u8],
+    destination: &str,
+    sources: Option<&Sources>,
+) -> RegoLibraryResult<String> {
+    parse_rego_library(destination, document)?;
+
+    Ok(Registry::new()
+        .push_artifact(
+            document,
+            &ArtifactMediaTypes {
+                layer: REGO_LIBRARY_LAYER_MEDIA_TYPE,
+                config: REGO_LIBRARY_CONFIG_MEDIA_TYPE,
+            },
+            destination,
+            sources,
+            None,
+        )
+        .await?)
+}
+
+/// Fetch a Rego library that has been stored as an OCI artifact by
+/// [`push_rego_library`]. The `url` must reference the library by digest
+pub async fn fetch_rego_library(
+    url: &str,
+    sources: Option<&Sources>,
+) -> RegoLibraryResult<RegoLibraryDocument> {
+    ensure_pinned(url)?;
+
+    let document = Registry::new()
+        .pull_artifact(url, REGO_LIBRARY_LAYER_MEDIA_TYPE, sources)
+        .await?;
+
+    parse_rego_library(url, &document)
+}
+
+/// Ensure the reference points to the library by digest
+fn ensure_pinned(url: &str) -> RegoLibraryResult<()> {
+    let reference: Reference = build_fully_resolved_reference(url)?;
+    if reference.digest().is_none() {
+        return Err(RegoLibraryError::NotPinnedError(url.to_owned()));
+    }
+
+    Ok(())
+}
+
+/// The document of a library must be a JSON object, its keys become the names
+/// of the documents available to the policies
+fn parse_rego_library(reference: &str, document: &[u8]) -> RegoLibraryResult<RegoLibraryDocument> {
+    let value: serde_json::Value =
+        serde_json::from_slice(document).map_err(|e| RegoLibraryError::InvalidDocumentError {
+            reference: reference.to_owned(),
+            message: format!("the document is not valid JSON: {e}"),
+        })?;
+
+    match value {
+        serde_json::Value::Object(document) => Ok(document),
+        _ => Err(RegoLibraryError::InvalidDocumentError {
+            reference: reference.to_owned(),
+            message: "the document is not a JSON object".to_owned(),
+        }),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rstest::rstest;
+
+    #[rstest]
+    #[case::digest(
+        "registry://ghcr.io/org/library@sha256:9f2c8b0c0c1e0f1d4d5c6b7a8f9e0d1c2b3a4f5e6d7c8b9a0f1e2d3c4b5a6f7e",
+        true
+    )]
+    #[case::tag_and_digest(
+        "registry://ghcr.io/org/library:v1@sha256:9f2c8b0c0c1e0f1d4d5c6b7a8f9e0d1c2b3a4f5e6d7c8b9a0f1e2d3c4b5a6f7e",
+        true
+    )]
+    #[case::tag("registry://ghcr.io/org/library:v1", false)]
+    #[case::latest("registry://ghcr.io/org/library", false)]
+    fn library_must_be_pinned(#[case] url: &str, #[case] pinned: bool) {
+        assert_eq!(ensure_pinned(url).is_ok(), pinned);
+    }
+
+    #[rstest]
+    #[case::object(br#"{"images": {"registries": ["ghcr.io"]}}"#, true)]
+    #[case::array(br#"["ghcr.io"]"#, false)]
+    #[case::not_json(b"package lib", false)]
+    fn library_must_be_an_object(#[case] document: &[u8], #[case] valid: bool) {
+        assert_eq!(parse_rego_library("library", document).is_ok(), valid);
+    }
+}
@@ -3,13 +3,16 @@ use thiserror::Error;
 
 use std::collections::{HashMap, HashSet};
 use std::convert::{TryFrom, TryInto};
+use std::fmt;
 use std::path::{Path, PathBuf};
+use std::time::Duration;
 use std::{fs, fs::File};
 
-use x509_parser::pem::parse_x509_pem;
+use x509_parser::pem::{parse_x509_pem, Pem};
 use x509_parser::prelude::*;
 
 use crate::errors::FailedToParseYamlDataError;
+use crate::registry::errors::{is_transient_error, is_transient_request_error};
 
 pub type SourceResult<T> = std::result::Result<T, SourceError>;
 
@@ -31,6 +34,40 @@ pub enum SourceError {
     FailedToParseYamlDataError(#[from] FailedToParseYamlDataError),
     #[error("failed to create the http client: {0}")]
     FailedToCreateHttpClientError(#[from] reqwest::Error),
+    #[error("Invalid HTTP authentication for {host}: {message}")]
+    InvalidHttpAuthError { host: String, message: String },
+    #[error("Invalid HTTPS TLS settings for {host}: {message}")]
+    InvalidHttpsTlsError { host: String, message: String },
+    #[error("Invalid mirror of {host}: {message}")]
+    InvalidMirrorError { host: String, message: String },
+    #[error("Invalid image index {reference}: {message}")]
+    InvalidImageIndexError { reference: String, message: String },
+    #[error("cannot resolve {host}: {error}")]
+    CannotResolveHostError {
+        host: String,
+        #[source]
+        error: std::io::Error,
+    },
+    #[error("the server is rate limiting requests")]
+    TooManyRequestsError {
+        /// How long to wait before making a new request, as requested by the server
+        retry_after: Option<Duration>,
+    },
+    #[error("the server replied with {status}")]
+    UnexpectedStatusError { status: reqwest::StatusCode },
+}
+
+impl SourceError {
+    /// Whether the server could not be reached, or failed because of an error
+    /// on its side. The operation can be retried later.
+    pub fn is_transient(&self) -> bool {
+        match self {
+            SourceError::OCIRegistryError(e) => is_transient_error(e),
+            SourceError::FailedToCreateHttpClientError(e) => is_transient_request_error(e),
+            SourceError::UnexpectedStatusError { status } => status.is_server_error(),
+            _ => false,
+        }
+    }
 }
 
 #[derive(Clone, Default, Deserialize, Debug)]
@@ -74,6 +111,225 @@ impl TryFrom<RawSourceAuthority> for RawCertificate {
 struct RawSources {
     insecure_sources: HashSet<String>,
     source_authorities: RawSourceAuthorities,
+    http_auth: HashMap<String, HttpAuth>,
+    https_system_certificates: bool,
+    https_tls: HashMap<String, RawHttpsTls>,
+    mirrors: HashMap<String, Vec<String>>,
+    address_family: AddressFamily,
+    address_family_overrides: HashMap<String, AddressFamily>,
+}
+
+/// The IP address family used to connect to the HTTP servers resolving to both
+/// IPv4 and IPv6 addresses. It's set for all the servers, and can be overridden
+/// for specific ones, with the port when it's not the default one:
+///
+/// ```yaml
+/// address_family: prefer_ipv4
+/// address_family_overrides:
+///   files.example.com: ipv4
+///   artifacts.example.com:8443: prefer_ipv6
+/// ```
+///
+/// Unless only one family is allowed, connections are attempted over both the
+/// families: the ones of the preferred family are started first, the other
+/// ones are started shortly after when the preferred family is not responding.
+///
+/// The preference applies only to the downloads from `http://` and `https://`
+/// URLs, and to the deletion of artifacts from OCI registries. It has no effect
+/// on the pulls from OCI registries, nor on the other operations made against
+/// them: these go through the OCI client, which connects to the addresses in the
+/// order returned by the system resolver.
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum AddressFamily {
+    /// Prefer the family of the first address returned by the system resolver
+    #[default]
+    Auto,
+    /// Connect over IPv4 only
+    Ipv4,
+    /// Connect over IPv6 only
+    Ipv6,
+    /// Attempt the connections over IPv4 first
+    PreferIpv4,
+    /// Attempt the connections over IPv6 first
+    PreferIpv6,
+}
+
+impl fmt::Display for AddressFamily {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            AddressFamily::Auto => "auto",
+            AddressFamily::Ipv4 => "ipv4",
+            AddressFamily::Ipv6 => "ipv6",
+            AddressFamily::PreferIpv4 => "prefer_ipv4",
+            AddressFamily::PreferIpv6 => "prefer_ipv6",
+        })
+    }
+}
+
+/// Credentials sent to a HTTP server when downloading policies from `https://`
+/// URLs. They are sent only once the certificate of the server has been verified,
+/// never over plain HTTP nor to the insecure sources, and never follow the
+/// redirects to another origin. This is how they look like:
+///
+/// ```yaml
+/// http_auth:
+///   artifacts.example.com:
+///     type: Bearer
+///     token: my-token
+///   files.example.com:8443:
+///     type: Basic
+///     username: kubewarden
+///     password: secret
+///   internal.example.com:
+///     type: Header
+///     name: X-Api-Key
+///     value: my-key
+/// ```
+#[derive(Clone, Deserialize, PartialEq, Eq)]
+#[serde(tag = "type")]
+pub enum HttpAuth {
+    /// Sent as the `Authorization: Bearer <token>` header
+    Bearer { token: String },
+    /// Sent as the `Authorization: Basic <credentials>` header
+    Basic { username: String, password: String },
+    /// Sent as a custom header
+    Header { name: String, value: String },
+}
+
+// The credentials must never end up inside of the logs
+impl fmt::Debug for HttpAuth {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            HttpAuth::Bearer { .. } => f.write_str("Bearer { token: <redacted> }"),
+            HttpAuth::Basic { username, .. } => write!(
+                f,
+                "Basic {{ username: {username:?}, password: <redacted> }}"
+            ),
+            HttpAuth::Header { name, .. } => {
+                write!(f, "Header {{ name: {name:?}, value: <redacted> }}")
+            }
+        }
+    }
+}
+
+impl HttpAuth {
+    fn validate(&self, host: &str) -> SourceResult<()> {
+        let invalid = |message: String| SourceError::InvalidHttpAuthError {
+            host: host.to_owned(),
+            message,
+        };
+
+        match self {
+            HttpAuth::Bearer { token } => reqwest::header::HeaderValue::from_str(token)
+                .map(|_| ())
+                .map_err(|_| invalid("the token contains invalid characters".to_owned())),
+            HttpAuth::Basic { .. } => Ok(()),
+            HttpAuth::Header { name, value } => {
+                reqwest::header::HeaderName::from_bytes(name.as_bytes())
+                    .map_err(|_| invalid(format!("invalid header name {name}")))?;
+                reqwest::header::HeaderValue::from_str(value)
+                    .map(|_| ())
+                    .map_err(|_| invalid(format!("the value of header {name} is invalid")))
+            }
+        }
+    }
+}
+
+#[derive(Clone, Default, Deserialize, Debug)]
+#[serde(default)]
+struct RawHttpsTls {
+    ca_bundle: Option<PathBuf>,
+    server_name: Option<String>,
+    min_tls_version: Option<TlsVersion>,
+}
+
+/// TLS settings used when downloading policies from `https://` URLs. They are
+/// indexed by host, with the port when it's not the default one, and are distinct
+/// from the `source_authorities` used by OCI registries:
+///
+/// ```yaml
+/// https_system_certificates: true
+/// https_tls:
+///   files.example.com:
+///     ca_bundle: /etc/pki/corporate-ca-bundle.pem
+///     server_name: files.internal.example.com
+///     min_tls_version: "1.3"
+/// ```
+///
+/// `https_system_certificates` makes all the downloads trust the certificates of
+/// the system store too, like the ones of the proxies doing TLS inspection.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct HttpsTls {
+    /// Additional CA certificates trusted when connecting to the host, read
+    /// from the `ca_bundle` file
+    pub ca_certificates: Vec<Certificate>,
+    /// The name sent with SNI and used to verify the certificate of the server,
+    /// instead of the host of the URL
+    pub server_name: Option<String>,
+    /// The minimum TLS version accepted by the client
+    pub min_tls_version: Option<TlsVersion>,
+}
+
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Eq)]
+pub enum TlsVersion {
+    #[serde(rename = "1.2")]
+    Tls12,
+    #[serde(rename = "1.3")]
+    Tls13,
+}
+
+impl HttpsTls {
+    fn try_from_raw(host: &str, raw: RawHttpsTls) -> SourceResult<Self> {
+        let invalid = |message: String| SourceError::InvalidHttpsTlsError {
+            host: host.to_owned(),
+            message,
+        };
+
+        let ca_certificates = match raw.ca_bundle {
+            Some(path) => {
+                let data = fs::read(&path).map_err(|e| {
+                    invalid(format!("cannot read CA bundle {}: {e}", path.display()))
+                })?;
+                read_ca_bundle(&data)
+                    .map_err(|e| invalid(format!("invalid CA bundle {}: {e}", path.display())))?
+            }
+            None => Vec::new(),
+        };
+        if let Some(server_name) = &raw.server_name {
+            rustls_pki_types::ServerName::try_from(server_name.as_str())
+                .map_err(|_| invalid(format!("invalid server name {server_name}")))?;
+        }
+
+        Ok(HttpsTls {
+            ca_certificates,
+            server_name: raw.server_name,
+            min_tls_version: raw.min_tls_version,
+        })
+    }
+}
+
+/// Read the certificates of a CA bundle, which is either a list of PEM encoded
+/// certificates or a single DER encoded one
+fn read_ca_bundle(data: &[u8]) -> std::result::Result<Vec<Certificate>, String> {
+    if X509Certificate::from_der(data).is_ok() {
+        return Ok(vec![Certificate::Der(data.to_vec())]);
+    }
+
+    let mut certificates = Vec::new();
+    for pem in Pem::iter_from_buffer(data) {
+        let pem = pem.map_err(|e| e.to_string())?;
+        if pem.label != "CERTIFICATE" {
+            continue;
+        }
+        pem.parse_x509().map_err(|e| e.to_string())?;
+        certificates.push(Certificate::Der(pem.contents));
+    }
+
+    if certificates.is_empty() {
+        return Err("no certificate found".to_owned());
+    }
+    Ok(certificates)
 }
 
 #[derive(Clone, Debug, Default, Serialize, Deserialize, PartialEq)]
@@ -107,6 +363,32 @@ impl TryFrom<RawSourceAuthorities> for SourceAuthorities {
 pub struct Sources {
     pub insecure_sources: HashSet<String>,
     pub source_authorities: SourceAuthorities,
+    /// Credentials used to download policies from HTTP servers, indexed by
+    /// host, with the port when it's not the default one
+    pub http_auth: HashMap<String, HttpAuth>,
+    /// Trust the certificates of the system store when downloading policies
+    /// from HTTPS servers
+    pub https_system_certificates: bool,
+    /// TLS settings used to download policies from HTTPS servers, indexed
+    /// by host, with the port when it's not the default one
+    pub https_tls: HashMap<String, HttpsTls>,
+    /// Mirrors of the OCI registries, indexed by the host of the registry, with
+    /// the port when it's not the default one. The mirrors are tried in order,
+    /// before falling back to the registry itself:
+    ///
+    /// ```yaml
+    /// mirrors:
+    ///   ghcr.io:
+    ///     - mirror.internal.example.com
+    ///     - registry.example.com:5000
+    /// ```
+    pub mirrors: HashMap<String, Vec<String>>,
+    /// The address family used to connect to the HTTP servers resolving to both
+    /// IPv4 and IPv6 addresses. The pulls from the OCI registries don't use it
+    pub address_family: AddressFamily,
+    /// The address family used to connect to specific HTTP servers, indexed by
+    /// host, with the port when it's not the default one
+    pub address_family_overrides: HashMap<String, AddressFamily>,
 }
 
 #[derive(Clone, Debug, Eq, PartialEq)]
@@ -119,13 +401,55 @@ impl TryFrom<RawSources> for Sources {
     type Error = SourceError;
 
     fn try_from(sources: RawSources) -> SourceResult<Sources> {
+        for (host, auth) in &sources.http_auth {
+            auth.validate(host)?;
+        }
+        let https_tls = sources
+            .https_tls
+            .into_iter()
+            .map(|(host, raw)| HttpsTls::try_from_raw(&host, raw).map(|tls| (host, tls)))
+            .collect::<SourceResult<HashMap<_, _>>>()?;
+        for (host, mirrors) in &sources.mirrors {
+            validate_mirrors(host, mirrors)?;
+        }
+
         Ok(Sources {
             insecure_sources: sources.insecure_sources.clone(),
             source_authorities: sources.source_authorities.try_into()?,
+            http_auth: sources.http_auth,
+            https_system_certificates: sources.https_system_certificates,
+            https_tls,
+            mirrors: sources.mirrors,
+            address_family: sources.address_family,
+            address_family_overrides: sources.address_family_overrides,
         })
     }
 }
 
+/// Each mirror must be the host of a registry, with an optional port
+fn validate_mirrors(host: &str, mirrors: &[String]) -> SourceResult<()> {
+    for mirror in mirrors {
+        // a mirror without a domain, like `mirror`, would be parsed as the first
+        // component of a Docker Hub repository
+        let registry = oci_client::Reference::try_from(format!("{mirror}/kubewarden/policy"))
+            .ok()
+            .map(|reference| reference.registry().to_owned());
+        if registry.as_deref() != Some(mirror.as_str()) {
+            return Err(SourceError::InvalidMirrorError {
+                host: host.to_owned(),
+                message: format!("{mirror} is not the host of a registry"),
+            });
+        }
+        if mirror == host {
+            return Err(SourceError::InvalidMirrorError {
+                host: host.to_owned(),
+                message: "a registry cannot be a mirror of itself".to_owned(),
+            });
+        }
+    }
+    Ok(())
+}
+
 impl TryFrom<RawCertificate> for Certificate {
     type Error = SourceError;
 
@@ -249,6 +573,30 @@ impl Sources {
     pub fn source_authority(&self, host: &str) -> Option<Vec<Certificate>> {
         self.source_authorities.0.get(host).cloned()
     }
+
+    pub fn http_auth(&self, host: &str) -> Option<&HttpAuth> {
+        self.http_auth.get(host)
+    }
+
+    pub fn https_tls(&self, host: &str) -> Option<&HttpsTls> {
+        self.https_tls.get(host)
+    }
+
+    /// The mirrors of the given registry, in the order they have to be tried
+    pub fn mirrors(&self, host: &str) -> &[String] {
+        self.mirrors
+            .get(host)
+            .map(Vec::as_slice)
+            .unwrap_or_default()
+    }
+
+    /// The address family used to connect to the given host
+    pub fn address_family(&self, host: &str) -> AddressFamily {
+        self.address_family_overrides
+            .get(host)
+            .copied()
+            .unwrap_or(self.address_family)
+    }
 }
 
 pub fn read_sources_file(path: &Path) -> SourceResult<Sources> {
@@ -260,6 +608,7 @@ pub fn read_sources_file(path: &Path) -> SourceResult<Sources> {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use rstest::rstest;
     use serde_json::json;
     use std::io::Write;
     use tempfile::NamedTempFile;
@@ -383,4 +732,197 @@ Wm7DCfrPNGVwFWUQOmsPue9rZBgO
             assert_eq!(actual_cert, &expected_cert);
         }
     }
+
+    #[test]
+    fn test_http_auth_deserialization() {
+        let raw = json!({
+            "http_auth": {
+                "artifacts.example.com": {"type": "Bearer", "token": "my-token"},
+                "files.example.com:8443": {"type": "Basic", "username": "kubewarden", "password": "secret"},
+                "internal.example.com": {"type": "Header", "name": "X-Api-Key", "value": "my-key"},
+            }
+        });
+        let raw_sources: RawSources = serde_json::from_value(raw).unwrap();
+        let sources: Sources = raw_sources.try_into().unwrap();
+
+        assert_eq!(
+            sources.http_auth("artifacts.example.com"),
+            Some(&HttpAuth::Bearer {
+                token: "my-token".to_owned()
+            })
+        );
+        assert_eq!(
+            sources.http_auth("files.example.com:8443"),
+            Some(&HttpAuth::Basic {
+                username: "kubewarden".to_owned(),
+                password: "secret".to_owned()
+            })
+        );
+        assert_eq!(
+            sources.http_auth("internal.example.com"),
+            Some(&HttpAuth::Header {
+                name: "X-Api-Key".to_owned(),
+                value: "my-key".to_owned()
+            })
+        );
+        assert_eq!(sources.http_auth("files.example.com"), None);
+    }
+
+    #[test]
+    fn test_invalid_http_auth() {
+        let raw = json!({
+            "http_auth": {
+                "internal.example.com": {"type": "Header", "name": "X Api Key", "value": "my-key"},
+            }
+        });
+        let raw_sources: RawSources = serde_json::from_value(raw).unwrap();
+        let sources: SourceResult<Sources> = raw_sources.try_into();
+
+        assert!(matches!(
+            sources,
+            Err(SourceError::InvalidHttpAuthError { host, .. }) if host == "internal.example.com"
+        ));
+    }
+
+    #[test]
+    fn test_https_tls_deserialization() {
+        let mut ca_bundle = NamedTempFile::new().unwrap();
+        write!(ca_bundle, "{CERT_DATA}{CERT_DATA}").unwrap();
+
+        let raw = json!({
+            "https_system_certificates": true,
+            "https_tls": {
+                "files.example.com": {
+                    "ca_bundle": ca_bundle.path(),
+                    "server_name": "files.internal.example.com",
+                    "min_tls_version": "1.3",
+                },
+                "artifacts.example.com:8443": {
+                    "min_tls_version": "1.2",
+                },
+            }
+        });
+        let raw_sources: RawSources = serde_json::from_value(raw).unwrap();
+        let sources: Sources = raw_sources.try_into().unwrap();
+
+        assert!(sources.https_system_certificates);
+        let tls = sources.https_tls("files.example.com").unwrap();
+        assert_eq!(tls.ca_certificates.len(), 2);
+        assert!(matches!(tls.ca_certificates[0], Certificate::Der(_)));
+        assert_eq!(
+            tls.server_name,
+            Some("files.internal.example.com".to_owned())
+        );
+        assert_eq!(tls.min_tls_version, Some(TlsVersion::Tls13));
+        assert_eq!(
+            sources.https_tls("artifacts.example.com:8443"),
+            Some(&HttpsTls {
+                min_tls_version: Some(TlsVersion::Tls12),
+                ..Default::default()
+            })
+        );
+        assert_eq!(sources.https_tls("artifacts.example.com"), None);
+    }
+
+    #[test]
+    fn test_invalid_https_tls() {
+        let mut ca_bundle = NamedTempFile::new().unwrap();
+        write!(ca_bundle, "not a certificate").unwrap();
+
+        let cases = vec![
+            json!({"ca_bundle": ca_bundle.path()}),
+            json!({"ca_bundle": "/does/not/exist.pem"}),
+            json!({"server_name": "not a valid name"}),
+        ];
+        for case in cases {
+            let raw = json!({ "https_tls": { "files.example.com": case } });
+            let raw_sources: RawSources = serde_json::from_value(raw).unwrap();
+            let sources: SourceResult<Sources> = raw_sources.try_into();
+
+            assert!(
+                matches!(
+                    sources,
+                    Err(SourceError::InvalidHttpsTlsError { ref host, .. }) if host == "files.example.com"
+                ),
+                "expected {case:?} to be rejected"
+            );
+        }
+    }
+
+    #[test]
+    fn test_mirrors_deserialization() {
+        let raw = json!({
+            "mirrors": {
+                "ghcr.io": ["mirror.internal.example.com", "registry.example.com:5000"],
+                "docker.io": ["localhost:5000"],
+            }
+        });
+        let raw_sources: RawSources = serde_json::from_value(raw).unwrap();
+        let sources: Sources = raw_sources.try_into().unwrap();
+
+        assert_eq!(
+            sources.mirrors("ghcr.io"),
+            &["mirror.internal.example.com", "registry.example.com:5000"]
+        );
+        assert_eq!(sources.mirrors("docker.io"), &["localhost:5000"]);
+        assert!(sources.mirrors("quay.io").is_empty());
+    }
+
+    #[rstest]
+    #[case::no_domain("mirror")]
+    #[case::with_path("mirror.example.com/ghcr")]
+    #[case::with_scheme("https://mirror.example.com")]
+    #[case::itself("ghcr.io")]
+    fn test_invalid_mirrors(#[case] mirror: &str) {
+        let raw = json!({ "mirrors": { "ghcr.io": [mirror] } });
+        let raw_sources: RawSources = serde_json::from_value(raw).unwrap();
+        let sources: SourceResult<Sources> = raw_sources.try_into();
+
+        assert!(
+            matches!(
+                sources,
+                Err(SourceError::InvalidMirrorError { ref host, .. }) if host == "ghcr.io"
+            ),
+            "expected {mirror} to be rejected"
+        );
+    }
+
+    #[test]
+    fn test_address_family_deserialization() {
+        let raw = json!({
+            "address_family": "prefer_ipv4",
+            "address_family_overrides": {
+                "files.example.com": "ipv6",
+                "artifacts.example.com:8443": "auto",
+            }
+        });
+        let raw_sources: RawSources = serde_json::from_value(raw).unwrap();
+        let sources: Sources = raw_sources.try_into().unwrap();
+
+        assert_eq!(
+            sources.address_family("files.example.com"),
+            AddressFamily::Ipv6
+        );
+        assert_eq!(
+            sources.address_family("artifacts.example.com:8443"),
+            AddressFamily::Auto
+        );
+        assert_eq!(sources.address_family("quay.io"), AddressFamily::PreferIpv4);
+        assert_eq!(
+            Sources::default().address_family("quay.io"),
+            AddressFamily::Auto
+        );
+    }
+
+    #[test]
+    fn test_http_auth_is_redacted() {
+        let auth = HttpAuth::Basic {
+            username: "kubewarden".to_owned(),
+            password: "secret".to_owned(),
+        };
+
+        let debug = format!("{auth:?}");
+        assert!(debug.contains("kubewarden"));
+        assert!(!debug.contains("secret"));
+    }
 }
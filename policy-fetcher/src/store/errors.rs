@@ -20,4 +20,6 @@ pub enum StoreError {
     DigestError(#[from] crate::policy::DigestError),
     #[error(transparent)]
     DecoderError(#[from] base64::DecodeError),
+    #[error("invalid provenance record: {0}")]
+    ProvenanceError(#[from] serde_json::Error),
 }
@@ -1,18 +1,31 @@
 use directories::ProjectDirs;
 use lazy_static::lazy_static;
 use path_slash::PathExt;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::fs::{File, OpenOptions};
+use std::io::{BufRead, BufReader, Write};
 use std::path::{Path, PathBuf};
 use url::Url;
 use walkdir::WalkDir;
 
 use crate::policy::Policy;
 use errors::StoreError;
+use provenance::{PolicyProvenance, RecordedBundles, VerificationStatus, PROVENANCE_DIR};
+use usage::{UsageRecord, USAGE_JOURNAL_FILE};
 
 use self::errors::StoreResult;
 
 pub mod errors;
 pub mod path;
+pub mod provenance;
 mod scheme;
+pub mod usage;
+
+/// Name of the directory, relative to the root of the store, holding the
+/// policies that have been quarantined. Like the provenance records, it's
+/// skipped when listing the contents of the store.
+pub const QUARANTINE_DIR: &str = ".quarantine";
 
 lazy_static! {
     pub static ref DEFAULT_ROOT: ProjectDirs =
@@ -25,6 +38,101 @@ pub enum PolicyPath {
     PrefixAndFilename,
 }
 
+/// A policy available inside of the store, together with the information
+/// recorded about it
+#[derive(Serialize, Debug, Clone, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct StoreEntry {
+    pub uri: String,
+    pub local_path: PathBuf,
+    /// SHA-256 digest of the WebAssembly module
+    pub sha256: String,
+    /// Size of the WebAssembly module, in bytes
+    pub size: u64,
+    /// Recorded when the policy has been pulled. Not available for the
+    /// policies pulled by older versions
+    pub provenance: Option<PolicyProvenance>,
+    /// When the policy has been used for the last time, in seconds since the
+    /// UNIX epoch. Not available for the policies that have never been used
+    /// since the usage journal has been introduced
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub last_used_at: Option<u64>,
+}
+
+impl StoreEntry {
+    pub fn policy(&self) -> Policy {
+        Policy {
+            uri: self.uri.clone(),
+            local_path: self.local_path.clone(),
+        }
+    }
+
+    /// Returns true when the WebAssembly module has been changed since it
+    /// has been pulled
+    pub fn is_modified(&self) -> bool {
+        self.provenance
+            .as_ref()
+            .is_some_and(|provenance| provenance.sha256 != self.sha256)
+    }
+}
+
+/// Filters used when listing the contents of the store. A policy is listed
+/// only when it satisfies all the filters that are set.
+#[derive(Debug, Clone, Default)]
+pub struct StoreFilter {
+    /// The scheme of the policy URI, e.g. `registry`
+    pub scheme: Option<String>,
+    /// The host, and the port if any, of the policy URI, e.g. `ghcr.io`
+    pub host: Option<String>,
+    /// A substring of the policy URI
+    pub uri_contains: Option<String>,
+    /// The verification status recorded at pull time. Policies without
+    /// provenance information are considered as not verified
+    pub verification: Option<VerificationStatus>,
+}
+
+impl StoreFilter {
+    fn matches_policy(&self, policy: &Policy) -> StoreResult<bool> {
+        let url = Url::parse(&policy.uri)?;
+
+        if self
+            .scheme
+            .as_ref()
+            .is_some_and(|scheme| scheme != url.scheme())
+        {
+            return Ok(false);
+        }
+        if self
+            .host
+            .as_ref()
+            .is_some_and(|host| host != &host_and_port(&url))
+        {
+            return Ok(false);
+        }
+        if self
+            .uri_contains
+            .as_ref()
+            .is_some_and(|needle| !policy.uri.contains(needle.as_str()))
+        {
+            return Ok(false);
+        }
+
+        Ok(true)
+    }
+
+    fn matches_provenance(&self, provenance: Option<&PolicyProvenance>) -> bool {
+        match self.verification {
+            Some(verification) => {
+                verification
+                    == provenance
+                        .map(|provenance| provenance.verification)
+                        .unwrap_or_default()
+            }
+            None => true,
+        }
+    }
+}
+
 /// Store represents a structure that is able to save and retrieve
 /// WebAssembly modules from a central and local location.
 ///
@@ -166,6 +274,249 @@ impl Store {
         Ok(policies)
     }
 
+    /// Lists all the policies in this store that satisfy the given filter,
+    /// sorted by URI. Contrary to `list`, this reads the contents of the
+    /// policies to compute their digests.
+    pub fn list_entries(&self, filter: &StoreFilter) -> StoreResult<Vec<StoreEntry>> {
+        let mut entries = Vec::new();
+        let last_used = self.last_used()?;
+
+        for policy in self.list()? {
+            if !filter.matches_policy(&policy)? {
+                continue;
+            }
+            let provenance = self.provenance(&policy)?;
+            if !filter.matches_provenance(provenance.as_ref()) {
+                continue;
+            }
+
+            let size = std::fs::metadata(&policy.local_path)?.len();
+            entries.push(StoreEntry {
+                sha256: policy.digest()?,
+                size,
+                provenance,
+                last_used_at: last_used.get(&policy.uri).copied(),
+                uri: policy.uri,
+                local_path: policy.local_path,
+            });
+        }
+        entries.sort_by(|a, b| a.uri.cmp(&b.uri));
+
+        Ok(entries)
+    }
+
+    /// Returns the provenance recorded when the given policy has been pulled,
+    /// if any.
+    pub fn provenance(&self, policy: &Policy) -> StoreResult<Option<PolicyProvenance>> {
+        let provenance_path = self.provenance_path(policy)?;
+        if !provenance_path.exists() {
+            return Ok(None);
+        }
+
+        let file = File::open(provenance_path)?;
+        Ok(Some(serde_json::from_reader(file)?))
+    }
+
+    /// Saves the provenance of the given policy, replacing the one recorded
+    /// before.
+    pub fn save_provenance(
+        &self,
+        policy: &Policy,
+        provenance: &PolicyProvenance,
+    ) -> StoreResult<()> {
+        let provenance_path = self.provenance_path(policy)?;
+        if let Some(parent) = provenance_path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        std::fs::write(provenance_path, serde_json::to_vec_pretty(provenance)?)?;
+
+        Ok(())
+    }
+
+    /// Records that the signatures of the given policy have been verified,
+    /// together with the digest of the verified OCI manifest.
+    pub fn record_verification(&self, policy: &Policy, manifest_digest: &str) -> StoreResult<()> {
+        self.record_verification_status(policy, manifest_digest, VerificationStatus::Verified)
+    }
+
+    /// Records that the given policy has been verified without network
+    /// access, using the Sigstore bundles recorded inside of the store.
+    pub fn record_offline_verification(
+        &self,
+        policy: &Policy,
+        manifest_digest: &str,
+    ) -> StoreResult<()> {
+        self.record_verification_status(
+            policy,
+            manifest_digest,
+            VerificationStatus::VerifiedOfflineBundle,
+        )
+    }
+
+    fn record_verification_status(
+        &self,
+        policy: &Policy,
+        manifest_digest: &str,
+        status: VerificationStatus,
+    ) -> StoreResult<()> {
+        let mut provenance = match self.provenance(policy)? {
+            Some(provenance) => provenance,
+            None => PolicyProvenance::new(&policy.uri, &policy.digest()?),
+        };
+        provenance.verification = status;
+        provenance.manifest_digest = Some(manifest_digest.to_owned());
+
+        self.save_provenance(policy, &provenance)
+    }
+
+    /// Returns the Sigstore bundles recorded when the given policy has been
+    /// verified, if any.
+    pub fn bundles(&self, policy: &Policy) -> StoreResult<Option<RecordedBundles>> {
+        let bundles_path = self.bundles_path(policy)?;
+        if !bundles_path.exists() {
+            return Ok(None);
+        }
+
+        let file = File::open(bundles_path)?;
+        Ok(Some(serde_json::from_reader(file)?))
+    }
+
+    /// Saves the Sigstore bundles that verified the given policy, replacing
+    /// the ones recorded before.
+    pub fn save_bundles(&self, policy: &Policy, bundles: &RecordedBundles) -> StoreResult<()> {
+        let bundles_path = self.bundles_path(policy)?;
+        if let Some(parent) = bundles_path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        std::fs::write(bundles_path, serde_json::to_vec_pretty(bundles)?)?;
+
+        Ok(())
+    }
+
+    /// Removes the provenance of the given policy, if any, together with
+    /// its recorded Sigstore bundles. To be invoked when the policy is
+    /// removed from the store.
+    pub fn remove_provenance(&self, policy: &Policy) -> StoreResult<()> {
+        for path in [self.provenance_path(policy)?, self.bundles_path(policy)?] {
+            if path.exists() {
+                std::fs::remove_file(path)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Records that the given policy is being used, appending an entry to the
+    /// usage journal of the store.
+    pub fn record_usage(&self, policy: &Policy) -> StoreResult<()> {
+        std::fs::create_dir_all(&self.root)?;
+        let mut record = serde_json::to_vec(&UsageRecord::now(&policy.uri))?;
+        record.push(b'\n');
+
+        let mut journal = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(self.root.join(USAGE_JOURNAL_FILE))?;
+        journal.write_all(&record)?;
+
+        Ok(())
+    }
+
+    /// Returns when each policy has been used for the last time, in seconds
+    /// since the UNIX epoch, keyed by policy URI. Policies that have never been
+    /// used are not part of the map.
+    ///
+    /// Entries of the journal that cannot be parsed, like the ones truncated by
+    /// a crash, are skipped.
+    pub fn last_used(&self) -> StoreResult<HashMap<String, u64>> {
+        let mut last_used: HashMap<String, u64> = HashMap::new();
+        let journal_path = self.root.join(USAGE_JOURNAL_FILE);
+        if !journal_path.exists() {
+            return Ok(last_used);
+        }
+
+        for line in BufReader::new(File::open(journal_path)?).lines() {
+            let record: UsageRecord = match serde_json::from_str(&line?) {
+                Ok(record) => record,
+                Err(_) => continue,
+            };
+            let used_at = last_used.entry(record.uri).or_default();
+            *used_at = (*used_at).max(record.used_at);
+        }
+
+        Ok(last_used)
+    }
+
+    /// Rewrites the usage journal keeping only the last usage of the policies
+    /// that are still inside of the store. To be invoked after the policies
+    /// have been removed from the store.
+    pub fn compact_usage_journal(&self) -> StoreResult<()> {
+        let journal_path = self.root.join(USAGE_JOURNAL_FILE);
+        if !journal_path.exists() {
+            return Ok(());
+        }
+
+        let last_used = self.last_used()?;
+        let mut journal = Vec::new();
+        for policy in self.list()? {
+            if let Some(used_at) = last_used.get(&policy.uri) {
+                serde_json::to_writer(
+                    &mut journal,
+                    &UsageRecord {
+                        uri: policy.uri,
+                        used_at: *used_at,
+                    },
+                )?;
+                journal.push(b'\n');
+            }
+        }
+
+        // replace the journal atomically, a crash must not lose it
+        let compacted_path = journal_path.with_extension("jsonl.tmp");
+        std::fs::write(&compacted_path, journal)?;
+        std::fs::rename(compacted_path, journal_path)?;
+
+        Ok(())
+    }
+
+    /// Moves the given policy out of the store, into the quarantine directory.
+    /// The quarantined policy can no longer be used, but it's kept around to
+    /// be inspected. Its provenance is removed.
+    ///
+    /// Returns the path of the quarantined WebAssembly module.
+    pub fn quarantine(&self, policy: &Policy) -> StoreResult<PathBuf> {
+        let relative_path = policy.local_path.strip_prefix(&self.root)?;
+        let quarantine_path = self.root.join(QUARANTINE_DIR).join(relative_path);
+        if let Some(parent) = quarantine_path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        std::fs::rename(&policy.local_path, &quarantine_path)?;
+        self.remove_provenance(policy)?;
+
+        Ok(quarantine_path)
+    }
+
+    /// Returns the path of the file holding the provenance of the policy.
+    /// The provenance records mirror the structure of the store, inside of
+    /// a dedicated directory.
+    fn provenance_path(&self, policy: &Policy) -> StoreResult<PathBuf> {
+        let relative_path = policy.local_path.strip_prefix(&self.root)?;
+        let mut provenance_path = self
+            .root
+            .join(PROVENANCE_DIR)
+            .join(relative_path)
+            .into_os_string();
+        provenance_path.push(".json");
+
+        Ok(PathBuf::from(provenance_path))
+    }
+
+    /// Returns the path of the file holding the Sigstore bundles recorded
+    /// for the policy, next to its provenance.
+    fn bundles_path(&self, policy: &Policy) -> StoreResult<PathBuf> {
+        Ok(self.provenance_path(policy)?.with_extension("bundles.json"))
+    }
+
     /// Get a policy by its URI, if it exists.
     pub fn get_policy_by_uri(&self, uri: &str) -> StoreResult<Option<Policy>> {
         let uri = Url::parse(uri)?;
@@ -0,0 +1,87 @@
+use base64::{engine::general_purpose::STANDARD, Engine as _};
+use serde::{Deserialize, Serialize};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Name of the directory, relative to the root of the store, holding the
+/// provenance records of the policies.
+///
+/// The directory is skipped when listing the contents of the store because
+/// it's not a known remote scheme.
+pub(crate) const PROVENANCE_DIR: &str = ".provenance";
+
+/// Whether the signatures of the policy have been checked when it was pulled
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub enum VerificationStatus {
+    #[default]
+    NotVerified,
+    Verified,
+    /// Verified without network access, using the Sigstore bundles recorded
+    /// when the policy has been pulled
+    VerifiedOfflineBundle,
+}
+
+/// Information about the origin of a policy, recorded when the policy is
+/// pulled into the store
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct PolicyProvenance {
+    /// The URL the policy has been pulled from
+    pub source: String,
+    /// When the policy has been pulled, in seconds since the UNIX epoch
+    pub pulled_at: u64,
+    /// SHA-256 digest of the WebAssembly module written to the store
+    pub sha256: String,
+    /// The digest of the OCI manifest that has been verified. Set only when
+    /// the policy has been verified
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub manifest_digest: Option<String>,
+    #[serde(default)]
+    pub verification: VerificationStatus,
+}
+
+impl PolicyProvenance {
+    /// Provenance of a policy that has just been pulled from `source`
+    pub fn new(source: &str, sha256: &str) -> Self {
+        let pulled_at = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or_default();
+
+        PolicyProvenance {
+            source: source.to_owned(),
+            pulled_at,
+            sha256: sha256.to_owned(),
+            manifest_digest: None,
+            verification: VerificationStatus::NotVerified,
+        }
+    }
+}
+
+/// The Sigstore bundles that verified a policy when it has been pulled. They
+/// are recorded next to the provenance of the policy, this way the policy can
+/// be verified again without network access
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct RecordedBundles {
+    /// The digest of the verified OCI manifest
+    pub manifest_digest: String,
+    /// The OCI manifest, base64 encoded. The raw contents are kept to
+    /// check them against the digest
+    pub manifest: String,
+    /// The verified Sigstore bundles, base64 encoded
+    pub bundles: Vec<String>,
+}
+
+impl RecordedBundles {
+    pub fn new(manifest_digest: &str, manifest: &[u8], bundles: &[Vec<u8>]) -> Self {
+        RecordedBundles {
+            manifest_digest: manifest_digest.to_owned(),
+            manifest: STANDARD.encode(manifest),
+            bundles: bundles
+                .iter()
+                .map(|bundle| STANDARD.encode(bundle))
+                .collect(),
+        }
+    }
+}
@@ -0,0 +1,35 @@
+use serde::{Deserialize, Serialize};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Name of the file, relative to the root of the store, holding the usage
+/// journal of the policies.
+///
+/// The file is skipped when listing the contents of the store because it's
+/// not a known remote scheme.
+pub(crate) const USAGE_JOURNAL_FILE: &str = ".usage.jsonl";
+
+/// An entry of the usage journal, appended each time a policy of the store
+/// is used. The journal is a JSON Lines file
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct UsageRecord {
+    /// The URI of the policy
+    pub uri: String,
+    /// When the policy has been used, in seconds since the UNIX epoch
+    pub used_at: u64,
+}
+
+impl UsageRecord {
+    /// Record of a policy that is being used right now
+    pub fn now(uri: &str) -> Self {
+        let used_at = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or_default();
+
+        UsageRecord {
+            uri: uri.to_owned(),
+            used_at,
+        }
+    }
+}
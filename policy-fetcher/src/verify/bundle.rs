@@ -0,0 +1,1133 @@
+//! Verification of the Sigstore bundles attached to the policies.
+//!
+//! Tools like `cosign sign --new-bundle-format` and `sigstore-python` do not push
+//! cosign signature layers. They store a Sigstore bundle (v0.3) inside of an OCI artifact
+//! that refers to the manifest of the policy, using the OCI referrers API.
+//!
+//! A bundle holds the signature of the policy manifest, either as a plain message signature
+//! or as a DSSE envelope wrapping an in-toto statement, together with the verification
+//! material: the signing certificate (or a hint about the public key used) and the entry of
+//! the transparency log. The inclusion proof of the entry is verified against the checkpoint
+//! signed by Rekor.
+//!
+//! Everything needed to verify a bundle is inside of the bundle itself. Once recorded, bundles
+//! can be verified again without network access, using only the pinned Rekor public keys: see
+//! [`Bundle::verify_offline`].
+//!
+//! Verified bundles are turned into `SignatureLayer` objects, this way they can be checked
+//! against the verification config like the cosign signatures.
+
+use base64::{engine::general_purpose::STANDARD, Engine as _};
+use serde::{Deserialize, Deserializer};
+use sha2::{Digest, Sha256};
+use sigstore::{
+    cosign::{
+        payload::simple_signing::SimpleSigning,
+        signature_layers::{CertificateSignature, CertificateSubject, SignatureLayer},
+    },
+    crypto::{CosignVerificationKey, Signature},
+    trust::ManualTrustRoot,
+};
+use tracing::{debug, warn};
+use x509_parser::{
+    certificate::X509Certificate, der_parser::der::parse_der_utf8string, extensions::GeneralName,
+    prelude::FromDer, time::ASN1Time,
+};
+
+use crate::verify::errors::{VerifyError, VerifyResult};
+
+/// Media type of a v0.3 Sigstore bundle. This is both the artifact type of the OCI
+/// artifact holding the bundle, and the media type of its layer
+pub const SIGSTORE_BUNDLE_V03_MEDIA_TYPE: &str = "application/vnd.dev.sigstore.bundle.v0.3+json";
+
+const IN_TOTO_PAYLOAD_TYPE: &str = "application/vnd.in-toto+json";
+
+// Fulcio certificate extensions, see
+// https://github.com/sigstore/fulcio/blob/main/docs/oid-info.md
+const OID_ISSUER_V1: &str = "1.3.6.1.4.1.57264.1.1";
+const OID_GITHUB_WORKFLOW_TRIGGER: &str = "1.3.6.1.4.1.57264.1.2";
+const OID_GITHUB_WORKFLOW_SHA: &str = "1.3.6.1.4.1.57264.1.3";
+const OID_GITHUB_WORKFLOW_NAME: &str = "1.3.6.1.4.1.57264.1.4";
+const OID_GITHUB_WORKFLOW_REPOSITORY: &str = "1.3.6.1.4.1.57264.1.5";
+const OID_GITHUB_WORKFLOW_REF: &str = "1.3.6.1.4.1.57264.1.6";
+const OID_ISSUER_V2: &str = "1.3.6.1.4.1.57264.1.8";
+
+/// A Sigstore bundle, using the JSON encoding of its protobuf definition.
+/// Only the fields used by the verification are deserialized.
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct Bundle {
+    pub media_type: String,
+    pub verification_material: VerificationMaterial,
+    pub message_signature: Option<MessageSignature>,
+    pub dsse_envelope: Option<Envelope>,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct VerificationMaterial {
+    pub certificate: Option<BundleCertificate>,
+    pub x509_certificate_chain: Option<X509CertificateChain>,
+    pub public_key: Option<PublicKeyIdentifier>,
+    #[serde(default)]
+    pub tlog_entries: Vec<TransparencyLogEntry>,
+}
+
+/// A DER encoded X509 certificate
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct BundleCertificate {
+    #[serde(deserialize_with = "base64_bytes")]
+    pub raw_bytes: Vec<u8>,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct X509CertificateChain {
+    pub certificates: Vec<BundleCertificate>,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct PublicKeyIdentifier {
+    #[serde(default)]
+    pub hint: String,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct TransparencyLogEntry {
+    #[serde(deserialize_with = "int64")]
+    pub log_index: i64,
+    #[serde(deserialize_with = "int64")]
+    pub integrated_time: i64,
+    pub log_id: Option<LogId>,
+    pub inclusion_promise: Option<InclusionPromise>,
+    pub inclusion_proof: Option<InclusionProof>,
+    #[serde(deserialize_with = "base64_bytes")]
+    pub canonicalized_body: Vec<u8>,
+}
+
+/// The identifier of the transparency log: the SHA-256 digest of its public key
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct LogId {
+    #[serde(deserialize_with = "base64_bytes")]
+    pub key_id: Vec<u8>,
+}
+
+/// The promise of Rekor to include the entry inside of the log
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct InclusionPromise {
+    /// The Signed Entry Timestamp (SET)
+    #[serde(deserialize_with = "base64_bytes")]
+    pub signed_entry_timestamp: Vec<u8>,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct InclusionProof {
+    #[serde(deserialize_with = "int64")]
+    pub log_index: i64,
+    #[serde(deserialize_with = "base64_bytes")]
+    pub root_hash: Vec<u8>,
+    #[serde(deserialize_with = "int64")]
+    pub tree_size: i64,
+    #[serde(deserialize_with = "base64_bytes_list")]
+    pub hashes: Vec<Vec<u8>>,
+    pub checkpoint: Checkpoint,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct Checkpoint {
+    pub envelope: String,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct MessageSignature {
+    pub message_digest: HashOutput,
+    #[serde(deserialize_with = "base64_bytes")]
+    pub signature: Vec<u8>,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct HashOutput {
+    pub algorithm: String,
+    #[serde(deserialize_with = "base64_bytes")]
+    pub digest: Vec<u8>,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct Envelope {
+    #[serde(deserialize_with = "base64_bytes")]
+    pub payload: Vec<u8>,
+    pub payload_type: String,
+    pub signatures: Vec<EnvelopeSignature>,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct EnvelopeSignature {
+    #[serde(deserialize_with = "base64_bytes")]
+    pub sig: Vec<u8>,
+}
+
+/// The protobuf JSON encoding represents 64 bit integers as strings
+fn int64<'de, D: Deserializer<'de>>(deserializer: D) -> Result<i64, D::Error> {
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum StringOrNumber {
+        String(String),
+        Number(i64),
+    }
+
+    match StringOrNumber::deserialize(deserializer)? {
+        StringOrNumber::String(s) => s.parse().map_err(serde::de::Error::custom),
+        StringOrNumber::Number(n) => Ok(n),
+    }
+}
+
+fn base64_bytes<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<u8>, D::Error> {
+    let encoded = String::deserialize(deserializer)?;
+    STANDARD.decode(encoded).map_err(serde::de::Error::custom)
+}
+
+fn base64_bytes_list<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<Vec<u8>>, D::Error> {
+    Vec::<String>::deserialize(deserializer)?
+        .into_iter()
+        .map(|encoded| STANDARD.decode(encoded).map_err(serde::de::Error::custom))
+        .collect()
+}
+
+fn bundle_error(msg: impl Into<String>) -> VerifyError {
+    VerifyError::BundleVerificationError(msg.into())
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{b:02x}")).collect()
+}
+
+impl Bundle {
+    /// Parse a bundle, only v0.3 bundles are supported
+    pub fn from_slice(data: &[u8]) -> VerifyResult<Self> {
+        let bundle: Bundle = serde_json::from_slice(data)
+            .map_err(|e| bundle_error(format!("cannot parse Sigstore bundle: {e}")))?;
+        if bundle.media_type != SIGSTORE_BUNDLE_V03_MEDIA_TYPE {
+            return Err(bundle_error(format!(
+                "unsupported Sigstore bundle media type: {}",
+                bundle.media_type
+            )));
+        }
+        Ok(bundle)
+    }
+
+    /// Verify the bundle against the manifest of the policy, returning a `SignatureLayer`
+    /// that can be checked against the verification config.
+    ///
+    /// * `image`: the repository of the policy, without tag nor digest
+    /// * `manifest`: the raw contents of the manifest of the policy
+    /// * `manifest_digest`: the digest of the manifest, in the `sha256:<hex>` form
+    ///
+    /// The certificate of keyless signatures must be issued by one of the Fulcio certificates
+    /// of `trust_root`, and the transparency log entry must be included inside of the log
+    /// signed by one of its Rekor keys. The validity of the certificate is checked against the
+    /// time the entry has been integrated into the log, which can be trusted only once the
+    /// entry has been verified: keyless bundles are rejected when `trust_root` has no Rekor
+    /// keys. The transparency log entry of bundles signed with a public key is not verified in
+    /// that case.
+    ///
+    /// Bundles signed with a public key are not verified here: the signature is left inside
+    /// of the layer, it's verified when the layer is checked against the verification config.
+    pub fn verify(
+        &self,
+        image: &str,
+        manifest: &[u8],
+        manifest_digest: &str,
+        trust_root: Option<&ManualTrustRoot<'static>>,
+    ) -> VerifyResult<SignatureLayer> {
+        self.verify_signature(image, manifest, manifest_digest, trust_root, false)
+    }
+
+    /// Verify a recorded bundle without network access, like [`Bundle::verify`].
+    ///
+    /// The transparency log entry cannot be skipped: `trust_root` must have pinned Rekor keys,
+    /// and the entry must have either a Signed Entry Timestamp or an inclusion proof. All the
+    /// ones available must be signed by one of the pinned Rekor keys.
+    pub fn verify_offline(
+        &self,
+        image: &str,
+        manifest: &[u8],
+        manifest_digest: &str,
+        trust_root: &ManualTrustRoot<'static>,
+    ) -> VerifyResult<SignatureLayer> {
+        self.verify_signature(image, manifest, manifest_digest, Some(trust_root), true)
+    }
+
+    fn verify_signature(
+        &self,
+        image: &str,
+        manifest: &[u8],
+        manifest_digest: &str,
+        trust_root: Option<&ManualTrustRoot<'static>>,
+        offline: bool,
+    ) -> VerifyResult<SignatureLayer> {
+        let manifest_hex_digest = manifest_digest
+            .strip_prefix("sha256:")
+            .ok_or_else(|| bundle_error("the manifest digest is not a sha256 one"))?;
+        let (signed_data, signature) = self.signed_data(manifest, manifest_hex_digest)?;
+
+        let tlog_entry = self
+            .verification_material
+            .tlog_entries
+            .first()
+            .ok_or_else(|| bundle_error("the bundle has no transparency log entry"))?;
+        self.verify_tlog_entry_body(tlog_entry, &signed_data, &signature)?;
+        let keyless = self.leaf_certificate().is_some();
+        match trust_root {
+            Some(trust_root) if !trust_root.rekor_keys.is_empty() && offline => {
+                verify_tlog_entry_offline(tlog_entry, &trust_root.rekor_keys)?
+            }
+            Some(trust_root) if !trust_root.rekor_keys.is_empty() => {
+                verify_tlog_entry_inclusion(tlog_entry, &trust_root.rekor_keys)?
+            }
+            _ if offline => {
+                return Err(bundle_error(
+                    "no pinned Rekor public keys, the transparency log entry of the Sigstore bundle cannot be verified offline",
+                ))
+            }
+            // the integrated time of an unverified entry is chosen by whoever built the
+            // bundle, it cannot be used to check the validity of the certificate
+            _ if keyless => {
+                return Err(bundle_error(
+                    "no Rekor public keys available, the certificate of the keyless Sigstore bundle cannot be verified",
+                ))
+            }
+            _ => warn!("no Rekor public keys available, the transparency log entry of the Sigstore bundle is not verified"),
+        }
+
+        let certificate_signature = match self.leaf_certificate() {
+            Some(leaf) => {
+                let fulcio_certs: Vec<&[u8]> = trust_root
+                    .map(|trust_root| {
+                        trust_root
+                            .fulcio_certs
+                            .iter()
+                            .map(|cert| cert.as_ref())
+                            .collect()
+                    })
+                    .unwrap_or_default();
+                Some(verify_certificate(
+                    leaf,
+                    &fulcio_certs,
+                    tlog_entry.integrated_time,
+                    &signed_data,
+                    &signature,
+                )?)
+            }
+            None if self.verification_material.public_key.is_some() => None,
+            None => return Err(bundle_error("the bundle has no verification material")),
+        };
+
+        let simple_signing: SimpleSigning = serde_json::from_value(serde_json::json!({
+            "critical": {
+                "identity": { "docker-reference": image },
+                "image": { "docker-manifest-digest": manifest_digest },
+                "type": "cosign container image signature",
+            },
+            "optional": null,
+        }))
+        .map_err(|e| bundle_error(format!("cannot build signing payload: {e}")))?;
+
+        Ok(SignatureLayer {
+            simple_signing,
+            oci_digest: manifest_digest.to_owned(),
+            certificate_signature,
+            bundle: None,
+            signature: Some(STANDARD.encode(&signature)),
+            raw_data: signed_data,
+        })
+    }
+
+    /// Returns the data signed by the bundle, together with the signature.
+    /// The bundle must be about the given manifest
+    fn signed_data(
+        &self,
+        manifest: &[u8],
+        manifest_hex_digest: &str,
+    ) -> VerifyResult<(Vec<u8>, Vec<u8>)> {
+        match (&self.message_signature, &self.dsse_envelope) {
+            (Some(message_signature), None) => {
+                if message_signature.message_digest.algorithm != "SHA2_256" {
+                    return Err(bundle_error(format!(
+                        "unsupported message digest algorithm: {}",
+                        message_signature.message_digest.algorithm
+                    )));
+                }
+                if to_hex(&message_signature.message_digest.digest) != manifest_hex_digest {
+                    return Err(bundle_error(
+                        "the bundle does not sign the manifest of the policy",
+                    ));
+                }
+                Ok((manifest.to_vec(), message_signature.signature.clone()))
+            }
+            (None, Some(envelope)) => {
+                if envelope.payload_type != IN_TOTO_PAYLOAD_TYPE {
+                    return Err(bundle_error(format!(
+                        "unsupported DSSE payload type: {}",
+                        envelope.payload_type
+                    )));
+                }
+                let [signature] = envelope.signatures.as_slice() else {
+                    return Err(bundle_error(
+                        "the DSSE envelope must have exactly one signature",
+                    ));
+                };
+
+                let statement: serde_json::Value = serde_json::from_slice(&envelope.payload)
+                    .map_err(|e| bundle_error(format!("cannot parse in-toto statement: {e}")))?;
+                let is_subject = statement["subject"]
+                    .as_array()
+                    .into_iter()
+                    .flatten()
+                    .any(|subject| subject["digest"]["sha256"] == manifest_hex_digest);
+                if !is_subject {
+                    return Err(bundle_error(
+                        "the manifest of the policy is not a subject of the in-toto statement",
+                    ));
+                }
+
+                Ok((
+                    dsse_pae(&envelope.payload_type, &envelope.payload),
+                    signature.sig.clone(),
+                ))
+            }
+            _ => Err(bundle_error(
+                "the bundle must have either a message signature or a DSSE envelope",
+            )),
+        }
+    }
+
+    /// Ensure the transparency log entry is about the signature of the bundle
+    fn verify_tlog_entry_body(
+        &self,
+        tlog_entry: &TransparencyLogEntry,
+        signed_data: &[u8],
+        signature: &[u8],
+    ) -> VerifyResult<()> {
+        let body: serde_json::Value = serde_json::from_slice(&tlog_entry.canonicalized_body)
+            .map_err(|e| bundle_error(format!("cannot parse transparency log entry: {e}")))?;
+        let encoded_signature = STANDARD.encode(signature);
+
+        let matches = match (body["kind"].as_str(), &self.dsse_envelope) {
+            (Some("hashedrekord"), None) => {
+                let spec = &body["spec"];
+                spec["signature"]["content"] == encoded_signature.as_str()
+                    && spec["data"]["hash"]["value"]
+                        == format!("{:x}", Sha256::digest(signed_data)).as_str()
+            }
+            (Some("dsse"), Some(envelope)) => {
+                let spec = &body["spec"];
+                spec["payloadHash"]["value"]
+                    == format!("{:x}", Sha256::digest(&envelope.payload)).as_str()
+                    && spec["signatures"]
+                        .as_array()
+                        .into_iter()
+                        .flatten()
+                        .any(|s| s["signature"] == encoded_signature.as_str())
+            }
+            (kind, _) => {
+                return Err(bundle_error(format!(
+                    "unexpected transparency log entry kind: {kind:?}"
+                )))
+            }
+        };
+        if !matches {
+            return Err(bundle_error(
+                "the transparency log entry does not match the signature of the bundle",
+            ));
+        }
+        Ok(())
+    }
+
+    /// The certificate used to sign the bundle, when the signature is keyless
+    fn leaf_certificate(&self) -> Option<&[u8]> {
+        let material = &self.verification_material;
+        material
+            .certificate
+            .as_ref()
+            .or_else(|| {
+                material
+                    .x509_certificate_chain
+                    .as_ref()
+                    .and_then(|chain| chain.certificates.first())
+            })
+            .map(|cert| cert.raw_bytes.as_slice())
+    }
+}
+
+/// Pre-Authentication Encoding of a DSSE envelope, this is what is actually signed
+fn dsse_pae(payload_type: &str, payload: &[u8]) -> Vec<u8> {
+    let mut pae = format!(
+        "DSSEv1 {} {payload_type} {} ",
+        payload_type.len(),
+        payload.len()
+    )
+    .into_bytes();
+    pae.extend_from_slice(payload);
+    pae
+}
+
+/// Returns true when the data has been signed by one of the given Rekor keys
+fn is_signed_by_rekor(rekor_keys: &[Vec<u8>], signature: &[u8], data: &[u8]) -> bool {
+    rekor_keys
+        .iter()
+        .filter_map(|key| CosignVerificationKey::try_from_der(key).ok())
+        .any(|key| {
+            key.verify_signature(Signature::Raw(signature), data)
+                .is_ok()
+        })
+}
+
+/// Verify the transparency log entry using only the data recorded inside of the bundle.
+/// The entry must have either a Signed Entry Timestamp or an inclusion proof, all the
+/// ones available are verified
+fn verify_tlog_entry_offline(
+    tlog_entry: &TransparencyLogEntry,
+    rekor_keys: &[Vec<u8>],
+) -> VerifyResult<()> {
+    if tlog_entry.inclusion_promise.is_none() && tlog_entry.inclusion_proof.is_none() {
+        return Err(bundle_error(
+            "the transparency log entry has neither a Signed Entry Timestamp nor an inclusion proof",
+        ));
+    }
+    if tlog_entry.inclusion_promise.is_some() {
+        verify_signed_entry_timestamp(tlog_entry, rekor_keys)?;
+    }
+    if tlog_entry.inclusion_proof.is_some() {
+        verify_tlog_entry_inclusion(tlog_entry, rekor_keys)?;
+    }
+    debug!("transparency log entry of the Sigstore bundle verified offline");
+    Ok(())
+}
+
+/// Verify the Signed Entry Timestamp, the promise of Rekor to include the entry inside
+/// of the log
+fn verify_signed_entry_timestamp(
+    tlog_entry: &TransparencyLogEntry,
+    rekor_keys: &[Vec<u8>],
+) -> VerifyResult<()> {
+    let promise = tlog_entry
+        .inclusion_promise
+        .as_ref()
+        .ok_or_else(|| bundle_error("the transparency log entry has no Signed Entry Timestamp"))?;
+    let log_id = tlog_entry
+        .log_id
+        .as_ref()
+        .ok_or_else(|| bundle_error("the transparency log entry has no log ID"))?;
+
+    let payload = signed_entry_timestamp_payload(tlog_entry, &log_id.key_id);
+    if is_signed_by_rekor(rekor_keys, &promise.signed_entry_timestamp, &payload) {
+        Ok(())
+    } else {
+        Err(bundle_error(
+            "the Signed Entry Timestamp is not signed by a trusted Rekor key",
+        ))
+    }
+}
+
+/// The data signed by Rekor inside of the Signed Entry Timestamp: the canonical JSON
+/// of the entry, with its keys sorted
+fn signed_entry_timestamp_payload(tlog_entry: &TransparencyLogEntry, log_id: &[u8]) -> Vec<u8> {
+    format!(
+        r#"{{"body":"{}","integratedTime":{},"logID":"{}","logIndex":{}}}"#,
+        STANDARD.encode(&tlog_entry.canonicalized_body),
+        tlog_entry.integrated_time,
+        to_hex(log_id),
+        tlog_entry.log_index,
+    )
+    .into_bytes()
+}
+
+/// Verify the inclusion proof of the transparency log entry, and the signature of the
+/// checkpoint holding the root hash of the log
+fn verify_tlog_entry_inclusion(
+    tlog_entry: &TransparencyLogEntry,
+    rekor_keys: &[Vec<u8>],
+) -> VerifyResult<()> {
+    let proof = tlog_entry
+        .inclusion_proof
+        .as_ref()
+        .ok_or_else(|| bundle_error("the transparency log entry has no inclusion proof"))?;
+
+    let leaf_hash = Sha256::new()
+        .chain_update([0x00])
+        .chain_update(&tlog_entry.canonicalized_body)
+        .finalize();
+    if !verify_inclusion_proof(
+        proof.log_index,
+        proof.tree_size,
+        &leaf_hash,
+        &proof.hashes,
+        &proof.root_hash,
+    ) {
+        return Err(bundle_error("invalid inclusion proof"));
+    }
+
+    let checkpoint = SignedCheckpoint::parse(&proof.checkpoint.envelope)?;
+    if checkpoint.tree_size != proof.tree_size || checkpoint.root_hash != proof.root_hash {
+        return Err(bundle_error(
+            "the checkpoint does not match the inclusion proof",
+        ));
+    }
+    checkpoint.verify(rekor_keys)
+}
+
+/// Verify a Merkle tree inclusion proof, as defined by RFC 9162 (section 2.1.3.2)
+fn verify_inclusion_proof(
+    leaf_index: i64,
+    tree_size: i64,
+    leaf_hash: &[u8],
+    proof: &[Vec<u8>],
+    root_hash: &[u8],
+) -> bool {
+    if leaf_index < 0 || leaf_index >= tree_size {
+        return false;
+    }
+    let hash_children = |left: &[u8], right: &[u8]| -> Vec<u8> {
+        Sha256::new()
+            .chain_update([0x01])
+            .chain_update(left)
+            .chain_update(right)
+            .finalize()
+            .to_vec()
+    };
+
+    let mut fnode = leaf_index;
+    let mut snode = tree_size - 1;
+    let mut hash = leaf_hash.to_vec();
+    for p in proof {
+        if snode == 0 {
+            return false;
+        }
+        if fnode & 1 == 1 || fnode == snode {
+            hash = hash_children(p, &hash);
+            while fnode & 1 == 0 && fnode != 0 {
+                fnode >>= 1;
+                snode >>= 1;
+            }
+        } else {
+            hash = hash_children(&hash, p);
+        }
+        fnode >>= 1;
+        snode >>= 1;
+    }
+
+    snode == 0 && hash == root_hash
+}
+
+/// A checkpoint of the transparency log, encoded as a signed note
+struct SignedCheckpoint {
+    /// The text covered by the signatures
+    note: String,
+    tree_size: i64,
+    root_hash: Vec<u8>,
+    /// The signatures, without the key hint
+    signatures: Vec<Vec<u8>>,
+}
+
+impl SignedCheckpoint {
+    fn parse(envelope: &str) -> VerifyResult<Self> {
+        let (note, signatures) = envelope
+            .split_once("\n\n")
+            .ok_or_else(|| bundle_error("malformed checkpoint"))?;
+        let mut lines = note.lines();
+        let (_origin, tree_size, root_hash) = match (lines.next(), lines.next(), lines.next()) {
+            (Some(origin), Some(tree_size), Some(root_hash)) => (origin, tree_size, root_hash),
+            _ => return Err(bundle_error("malformed checkpoint")),
+        };
+        let tree_size = tree_size
+            .parse()
+            .map_err(|e| bundle_error(format!("malformed checkpoint tree size: {e}")))?;
+        let root_hash = STANDARD
+            .decode(root_hash)
+            .map_err(|e| bundle_error(format!("malformed checkpoint root hash: {e}")))?;
+
+        let signatures = signatures
+            .lines()
+            .filter_map(|line| line.strip_prefix("\u{2014} "))
+            .filter_map(|line| line.rsplit_once(' '))
+            .filter_map(|(_name, signature)| STANDARD.decode(signature).ok())
+            // the first 4 bytes are the hint of the key
+            .filter(|signature| signature.len() > 4)
+            .map(|signature| signature[4..].to_vec())
+            .collect();
+
+        Ok(SignedCheckpoint {
+            note: format!("{note}\n"),
+            tree_size,
+            root_hash,
+            signatures,
+        })
+    }
+
+    /// Ensure the checkpoint has been signed by one of the given Rekor keys
+    fn verify(&self, rekor_keys: &[Vec<u8>]) -> VerifyResult<()> {
+        let verified = self
+            .signatures
+            .iter()
+            .any(|signature| is_signed_by_rekor(rekor_keys, signature, self.note.as_bytes()));
+        if verified {
+            Ok(())
+        } else {
+            Err(bundle_error(
+                "the checkpoint is not signed by a trusted Rekor key",
+            ))
+        }
+    }
+}
+
+/// Verify the certificate of a keyless signature, and the signature made with it.
+/// Returns the identity of the signer
+fn verify_certificate(
+    leaf: &[u8],
+    fulcio_certs: &[&[u8]],
+    integrated_time: i64,
+    signed_data: &[u8],
+    signature: &[u8],
+) -> VerifyResult<CertificateSignature> {
+    let (_, cert) = X509Certificate::from_der(leaf)
+        .map_err(|e| bundle_error(format!("cannot parse signing certificate: {e}")))?;
+
+    let issued_by_fulcio = fulcio_certs
+        .iter()
+        .filter_map(|issuer| X509Certificate::from_der(issuer).ok())
+        .any(|(_, issuer)| {
+            issuer.subject() == cert.issuer()
+                && cert.verify_signature(Some(issuer.public_key())).is_ok()
+        });
+    if !issued_by_fulcio {
+        return Err(bundle_error(
+            "the signing certificate has not been issued by a trusted Fulcio certificate",
+        ));
+    }
+
+    let integrated_time = ASN1Time::from_timestamp(integrated_time)
+        .map_err(|e| bundle_error(format!("invalid integrated time: {e}")))?;
+    if !cert.validity().is_valid_at(integrated_time) {
+        return Err(bundle_error(
+            "the signing certificate was not valid when the entry was added to the transparency log",
+        ));
+    }
+    let code_signing = cert
+        .extended_key_usage()
+        .ok()
+        .flatten()
+        .is_some_and(|eku| eku.value.code_signing);
+    if !code_signing {
+        return Err(bundle_error(
+            "the signing certificate cannot be used for code signing",
+        ));
+    }
+
+    let verification_key = CosignVerificationKey::try_from_der(cert.public_key().raw)
+        .map_err(VerifyError::KeyVerificationError)?;
+    verification_key
+        .verify_signature(Signature::Raw(signature), signed_data)
+        .map_err(VerifyError::KeyVerificationError)?;
+    debug!("Sigstore bundle signature verified");
+
+    let subject = cert
+        .subject_alternative_name()
+        .ok()
+        .flatten()
+        .and_then(|san| {
+            san.value.general_names.iter().find_map(|name| match name {
+                GeneralName::RFC822Name(email) => {
+                    Some(CertificateSubject::Email(email.to_string()))
+                }
+                GeneralName::URI(uri) => Some(CertificateSubject::Uri(uri.to_string())),
+                _ => None,
+            })
+        })
+        .ok_or_else(|| bundle_error("the signing certificate has no subject"))?;
+
+    let extension = |oid: &str| -> Option<String> {
+        cert.extensions()
+            .iter()
+            .find(|ext| ext.oid.to_id_string() == oid)
+            .and_then(|ext| String::from_utf8(ext.value.to_vec()).ok())
+    };
+    let issuer = cert
+        .extensions()
+        .iter()
+        .find(|ext| ext.oid.to_id_string() == OID_ISSUER_V2)
+        .and_then(|ext| parse_der_utf8string(ext.value).ok())
+        .and_then(|(_, value)| value.as_str().ok().map(str::to_owned))
+        .or_else(|| extension(OID_ISSUER_V1));
+
+    Ok(CertificateSignature {
+        verification_key,
+        issuer,
+        subject,
+        github_workflow_trigger: extension(OID_GITHUB_WORKFLOW_TRIGGER),
+        github_workflow_sha: extension(OID_GITHUB_WORKFLOW_SHA),
+        github_workflow_name: extension(OID_GITHUB_WORKFLOW_NAME),
+        github_workflow_repository: extension(OID_GITHUB_WORKFLOW_REPOSITORY),
+        github_workflow_ref: extension(OID_GITHUB_WORKFLOW_REF),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use sigstore::crypto::{signing_key::SigStoreSigner, SigningScheme};
+
+    fn leaf(data: &[u8]) -> Vec<u8> {
+        Sha256::new()
+            .chain_update([0x00])
+            .chain_update(data)
+            .finalize()
+            .to_vec()
+    }
+
+    fn node(left: &[u8], right: &[u8]) -> Vec<u8> {
+        Sha256::new()
+            .chain_update([0x01])
+            .chain_update(left)
+            .chain_update(right)
+            .finalize()
+            .to_vec()
+    }
+
+    #[test]
+    fn inclusion_proof() {
+        // a tree made of 3 leaves: the root is `node(node(a, b), c)`
+        let (a, b, c) = (leaf(b"a"), leaf(b"b"), leaf(b"c"));
+        let ab = node(&a, &b);
+        let root = node(&ab, &c);
+
+        assert!(verify_inclusion_proof(
+            0,
+            3,
+            &a,
+            &[b.clone(), c.clone()],
+            &root
+        ));
+        assert!(verify_inclusion_proof(
+            1,
+            3,
+            &b,
+            &[a.clone(), c.clone()],
+            &root
+        ));
+        assert!(verify_inclusion_proof(2, 3, &c, &[ab.clone()], &root));
+
+        // wrong leaf, index or proof
+        assert!(!verify_inclusion_proof(
+            0,
+            3,
+            &b,
+            &[b.clone(), c.clone()],
+            &root
+        ));
+        assert!(!verify_inclusion_proof(2, 3, &c, &[a.clone()], &root));
+        assert!(!verify_inclusion_proof(3, 3, &c, &[ab.clone()], &root));
+        assert!(!verify_inclusion_proof(2, 3, &c, &[], &root));
+    }
+
+    #[test]
+    fn parse_checkpoint() {
+        let root_hash = STANDARD.encode([1u8; 32]);
+        let signature = STANDARD.encode([[0xAA; 4].as_slice(), b"signature"].concat());
+        let envelope = format!(
+            "rekor.sigstore.dev - 1193050959916656506\n42\n{root_hash}\n\n\u{2014} rekor.sigstore.dev {signature}\n"
+        );
+
+        let checkpoint = SignedCheckpoint::parse(&envelope).unwrap();
+        assert_eq!(checkpoint.tree_size, 42);
+        assert_eq!(checkpoint.root_hash, vec![1u8; 32]);
+        assert_eq!(checkpoint.signatures, vec![b"signature".to_vec()]);
+        assert_eq!(
+            checkpoint.note,
+            format!("rekor.sigstore.dev - 1193050959916656506\n42\n{root_hash}\n")
+        );
+        assert!(checkpoint.verify(&[]).is_err());
+
+        assert!(SignedCheckpoint::parse("rekor.sigstore.dev\n42\n").is_err());
+    }
+
+    #[test]
+    fn dsse_pre_authentication_encoding() {
+        assert_eq!(
+            dsse_pae(IN_TOTO_PAYLOAD_TYPE, b"{}"),
+            b"DSSEv1 28 application/vnd.in-toto+json 2 {}".to_vec()
+        );
+    }
+
+    fn bundle_json(content: serde_json::Value) -> Vec<u8> {
+        let mut bundle = serde_json::json!({
+            "mediaType": SIGSTORE_BUNDLE_V03_MEDIA_TYPE,
+            "verificationMaterial": {
+                "publicKey": { "hint": "key" },
+                "tlogEntries": [{
+                    "logIndex": "10",
+                    "integratedTime": "1700000000",
+                    "canonicalizedBody": STANDARD.encode("{}"),
+                }],
+            },
+        });
+        bundle
+            .as_object_mut()
+            .unwrap()
+            .extend(content.as_object().unwrap().clone());
+        serde_json::to_vec(&bundle).unwrap()
+    }
+
+    #[test]
+    fn parse_bundle() {
+        let manifest_digest = Sha256::digest(b"manifest");
+        let bundle = Bundle::from_slice(&bundle_json(serde_json::json!({
+            "messageSignature": {
+                "messageDigest": {
+                    "algorithm": "SHA2_256",
+                    "digest": STANDARD.encode(manifest_digest),
+                },
+                "signature": STANDARD.encode("signature"),
+            }
+        })))
+        .unwrap();
+
+        assert_eq!(bundle.verification_material.tlog_entries[0].log_index, 10);
+        assert_eq!(
+            bundle.verification_material.tlog_entries[0].integrated_time,
+            1700000000
+        );
+        let (signed_data, signature) = bundle
+            .signed_data(b"manifest", &format!("{manifest_digest:x}"))
+            .unwrap();
+        assert_eq!(signed_data, b"manifest");
+        assert_eq!(signature, b"signature");
+
+        // the bundle is about another manifest
+        assert!(bundle
+            .signed_data(b"other", &format!("{:x}", Sha256::digest(b"other")))
+            .is_err());
+    }
+
+    #[test]
+    fn parse_dsse_bundle() {
+        let manifest_digest = format!("{:x}", Sha256::digest(b"manifest"));
+        let statement = serde_json::json!({
+            "_type": "https://in-toto.io/Statement/v1",
+            "subject": [{ "name": "policy", "digest": { "sha256": manifest_digest } }],
+            "predicateType": "https://sigstore.dev/cosign/sign/v1",
+            "predicate": {},
+        })
+        .to_string();
+        let bundle = Bundle::from_slice(&bundle_json(serde_json::json!({
+            "dsseEnvelope": {
+                "payload": STANDARD.encode(&statement),
+                "payloadType": IN_TOTO_PAYLOAD_TYPE,
+                "signatures": [{ "sig": STANDARD.encode("signature"), "keyid": "" }],
+            }
+        })))
+        .unwrap();
+
+        let (signed_data, signature) = bundle.signed_data(b"manifest", &manifest_digest).unwrap();
+        assert_eq!(
+            signed_data,
+            dsse_pae(IN_TOTO_PAYLOAD_TYPE, statement.as_bytes())
+        );
+        assert_eq!(signature, b"signature");
+        assert!(bundle
+            .signed_data(b"other", &format!("{:x}", Sha256::digest(b"other")))
+            .is_err());
+    }
+
+    fn rekor_key() -> (SigStoreSigner, Vec<u8>) {
+        let signer = SigningScheme::ECDSA_P256_SHA256_ASN1
+            .create_signer()
+            .unwrap();
+        let public_key = signer
+            .to_sigstore_keypair()
+            .unwrap()
+            .public_key_to_der()
+            .unwrap();
+        (signer, public_key)
+    }
+
+    #[test]
+    fn signed_entry_timestamp() {
+        let (rekor_signer, rekor_public_key) = rekor_key();
+        let (_, other_public_key) = rekor_key();
+        let mut tlog_entry = TransparencyLogEntry {
+            log_index: 10,
+            integrated_time: 1700000000,
+            log_id: Some(LogId {
+                key_id: Sha256::digest(&rekor_public_key).to_vec(),
+            }),
+            inclusion_promise: None,
+            inclusion_proof: None,
+            canonicalized_body: b"{}".to_vec(),
+        };
+
+        assert_eq!(
+            signed_entry_timestamp_payload(&tlog_entry, &[0xab, 0xcd]),
+            br#"{"body":"e30=","integratedTime":1700000000,"logID":"abcd","logIndex":10}"#.to_vec()
+        );
+
+        // neither a SET nor an inclusion proof
+        assert!(verify_tlog_entry_offline(&tlog_entry, &[rekor_public_key.clone()]).is_err());
+
+        let payload = signed_entry_timestamp_payload(
+            &tlog_entry,
+            &tlog_entry.log_id.as_ref().unwrap().key_id,
+        );
+        tlog_entry.inclusion_promise = Some(InclusionPromise {
+            signed_entry_timestamp: rekor_signer.sign(&payload).unwrap(),
+        });
+        assert!(verify_tlog_entry_offline(&tlog_entry, &[rekor_public_key.clone()]).is_ok());
+        assert!(verify_tlog_entry_offline(&tlog_entry, &[other_public_key]).is_err());
+
+        // the entry doesn't match the SET anymore
+        tlog_entry.log_index = 11;
+        assert!(verify_tlog_entry_offline(&tlog_entry, &[rekor_public_key]).is_err());
+    }
+
+    #[test]
+    fn verify_offline_requires_pinned_rekor_keys() {
+        let (rekor_signer, rekor_public_key) = rekor_key();
+        let manifest = b"manifest";
+        let manifest_digest = format!("sha256:{:x}", Sha256::digest(manifest));
+        let body = serde_json::json!({
+            "kind": "hashedrekord",
+            "spec": {
+                "signature": { "content": STANDARD.encode("signature") },
+                "data": { "hash": { "value": format!("{:x}", Sha256::digest(manifest)) } },
+            },
+        })
+        .to_string();
+        let key_id = Sha256::digest(&rekor_public_key).to_vec();
+        let payload = format!(
+            r#"{{"body":"{}","integratedTime":1700000000,"logID":"{}","logIndex":10}}"#,
+            STANDARD.encode(&body),
+            to_hex(&key_id),
+        );
+
+        let bundle = Bundle::from_slice(
+            &serde_json::to_vec(&serde_json::json!({
+                "mediaType": SIGSTORE_BUNDLE_V03_MEDIA_TYPE,
+                "verificationMaterial": {
+                    "publicKey": { "hint": "key" },
+                    "tlogEntries": [{
+                        "logIndex": "10",
+                        "logId": { "keyId": STANDARD.encode(&key_id) },
+                        "integratedTime": "1700000000",
+                        "inclusionPromise": {
+                            "signedEntryTimestamp": STANDARD.encode(rekor_signer.sign(payload.as_bytes()).unwrap()),
+                        },
+                        "canonicalizedBody": STANDARD.encode(&body),
+                    }],
+                },
+                "messageSignature": {
+                    "messageDigest": {
+                        "algorithm": "SHA2_256",
+                        "digest": STANDARD.encode(Sha256::digest(manifest)),
+                    },
+                    "signature": STANDARD.encode("signature"),
+                },
+            }))
+            .unwrap(),
+        )
+        .unwrap();
+
+        let pinned = ManualTrustRoot {
+            rekor_keys: vec![rekor_public_key],
+            ..Default::default()
+        };
+        let layer = bundle
+            .verify_offline(
+                "registry.example.com/policy",
+                manifest,
+                &manifest_digest,
+                &pinned,
+            )
+            .unwrap();
+        assert_eq!(layer.oci_digest, manifest_digest);
+        assert!(bundle
+            .verify_offline(
+                "registry.example.com/policy",
+                manifest,
+                &manifest_digest,
+                &ManualTrustRoot::default()
+            )
+            .is_err());
+    }
+
+    #[test]
+    fn keyless_bundles_require_rekor_keys() {
+        let manifest = b"manifest";
+        let manifest_digest = format!("sha256:{:x}", Sha256::digest(manifest));
+        let body = serde_json::json!({
+            "kind": "hashedrekord",
+            "spec": {
+                "signature": { "content": STANDARD.encode("signature") },
+                "data": { "hash": { "value": format!("{:x}", Sha256::digest(manifest)) } },
+            },
+        })
+        .to_string();
+
+        let bundle = Bundle::from_slice(
+            &serde_json::to_vec(&serde_json::json!({
+                "mediaType": SIGSTORE_BUNDLE_V03_MEDIA_TYPE,
+                "verificationMaterial": {
+                    "certificate": { "rawBytes": STANDARD.encode("certificate") },
+                    "tlogEntries": [{
+                        "logIndex": "10",
+                        "logId": { "keyId": STANDARD.encode("key") },
+                        // an expired certificate would be accepted with a forged time
+                        "integratedTime": "1700000000",
+                        "canonicalizedBody": STANDARD.encode(&body),
+                    }],
+                },
+                "messageSignature": {
+                    "messageDigest": {
+                        "algorithm": "SHA2_256",
+                        "digest": STANDARD.encode(Sha256::digest(manifest)),
+                    },
+                    "signature": STANDARD.encode("signature"),
+                },
+            }))
+            .unwrap(),
+        )
+        .unwrap();
+
+        for trust_root in [None, Some(&ManualTrustRoot::default())] {
+            let error = bundle
+                .verify(
+                    "registry.example.com/policy",
+                    manifest,
+                    &manifest_digest,
+                    trust_root,
+                )
+                .unwrap_err();
+            assert!(
+                error.to_string().contains("no Rekor public keys available"),
+                "{error}"
+            );
+        }
+    }
+
+    #[test]
+    fn reject_unsupported_bundle_versions() {
+        let bundle = serde_json::json!({
+            "mediaType": "application/vnd.dev.sigstore.bundle+json;version=0.1",
+            "verificationMaterial": {},
+        });
+        assert!(Bundle::from_slice(&serde_json::to_vec(&bundle).unwrap()).is_err());
+    }
+}
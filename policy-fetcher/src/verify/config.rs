@@ -1,11 +1,20 @@
-use std::{boxed::Box, collections::BTreeMap, fs, path::Path};
+use std::{
+    boxed::Box,
+    collections::{BTreeMap, HashSet},
+    fs,
+    path::Path,
+};
 
+use oci_client::Reference;
+use rustls_pki_types::{pem::PemObject, CertificateDer, SubjectPublicKeyInfoDer};
 use serde::{Deserialize, Deserializer, Serialize};
-use sigstore::cosign::verification_constraint::VerificationConstraint;
+use sigstore::{cosign::verification_constraint::VerificationConstraint, trust::ManualTrustRoot};
 use url::Url;
 
 use crate::{
     errors::FailedToParseYamlDataError,
+    registry::{build_fully_resolved_reference, ArtifactMediaTypes, Registry},
+    sources::Sources,
     verify::{
         errors::{VerifyError, VerifyResult},
         verification_constraints,
@@ -20,11 +29,156 @@ use crate::{
 /// * Implement `TryFrom` that goes from (v - 1) to (v)
 pub type LatestVerificationConfig = VerificationConfigV1;
 
+/// Media type of the layer holding the YAML representation of the verification
+/// config, when the config is stored as an OCI artifact
+pub const VERIFICATION_CONFIG_LAYER_MEDIA_TYPE: &str =
+    "application/vnd.kubewarden.verification-config.v1+yaml";
+/// Media type of the config of the OCI artifacts holding a verification config
+pub const VERIFICATION_CONFIG_CONFIG_MEDIA_TYPE: &str =
+    "application/vnd.kubewarden.verification-config.config.v1+json";
+
 #[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
 #[serde(rename_all = "camelCase", deny_unknown_fields)]
 pub struct VerificationConfigV1 {
     pub all_of: Option<Vec<Signature>>,
     pub any_of: Option<AnyOf>,
+    /// Trust roots used instead of the default one when verifying the images
+    /// they are associated with
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub trust_roots: Option<Vec<NamedTrustRoot>>,
+}
+
+impl VerificationConfigV1 {
+    /// Find the trust root to be used when verifying the given image.
+    /// When the image matches multiple trust roots, the one with the longest
+    /// matching prefix is chosen.
+    ///
+    /// Returns `None` when the default trust root has to be used.
+    pub fn trust_root_for_image(&self, image_url: &str) -> VerifyResult<Option<&NamedTrustRoot>> {
+        let trust_roots = match &self.trust_roots {
+            Some(trust_roots) if !trust_roots.is_empty() => trust_roots,
+            _ => return Ok(None),
+        };
+
+        let reference = build_fully_resolved_reference(image_url)?;
+        Ok(trust_roots
+            .iter()
+            .filter_map(|trust_root| {
+                trust_root
+                    .matching_prefix_len(&reference)
+                    .map(|len| (len, trust_root))
+            })
+            .max_by_key(|(len, _)| *len)
+            .map(|(_, trust_root)| trust_root))
+    }
+}
+
+/// A Sigstore trust root, like a private Fulcio and Rekor instance, used to verify
+/// the images coming from some registries or repositories
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(rename_all = "camelCase", deny_unknown_fields)]
+pub struct NamedTrustRoot {
+    pub name: String,
+    /// Registries or repositories verified with this trust root,
+    /// e.g. `registry.example.com` or `ghcr.io/example/policies`
+    pub images: Vec<String>,
+    /// PEM encoded Fulcio certificates
+    pub fulcio_certificates: Vec<String>,
+    /// PEM encoded Rekor public keys
+    pub rekor_public_keys: Vec<String>,
+}
+
+impl NamedTrustRoot {
+    /// Returns the length of the longest entry of `images` matching the given
+    /// reference, `None` when the trust root cannot be used with it
+    fn matching_prefix_len(&self, reference: &Reference) -> Option<usize> {
+        let repository = format!("{}/{}", reference.registry(), reference.repository());
+        self.images
+            .iter()
+            .map(|image| image.trim_end_matches('/'))
+            .filter(|image| {
+                repository == *image
+                    || repository
+                        .strip_prefix(image)
+                        .is_some_and(|rest| rest.starts_with('/'))
+            })
+            .map(str::len)
+            .max()
+    }
+
+    /// Build the sigstore trust root
+    pub fn manual_trust_root(&self) -> VerifyResult<ManualTrustRoot<'static>> {
+        let fulcio_certs = self
+            .fulcio_certificates
+            .iter()
+            .map(|cert| {
+                CertificateDer::from_pem_slice(cert.as_bytes()).map_err(|e| {
+                    VerifyError::InvalidVerifyFileError(format!(
+                        "trust root '{}': invalid Fulcio certificate: {e}",
+                        self.name
+                    ))
+                })
+            })
+            .collect::<VerifyResult<Vec<CertificateDer<'static>>>>()?;
+        let rekor_keys = self
+            .rekor_public_keys
+            .iter()
+            .map(|key| {
+                SubjectPublicKeyInfoDer::from_pem_slice(key.as_bytes())
+                    .map(|key| key.to_vec())
+                    .map_err(|e| {
+                        VerifyError::InvalidVerifyFileError(format!(
+                            "trust root '{}': invalid Rekor public key: {e}",
+                            self.name
+                        ))
+                    })
+            })
+            .collect::<VerifyResult<Vec<Vec<u8>>>>()?;
+
+        Ok(ManualTrustRoot {
+            fulcio_certs,
+            rekor_keys,
+            ..Default::default()
+        })
+    }
+}
+
+/// Ensure the trust roots are well formed, can be built and are not
+/// associated with the same images
+fn validate_trust_roots(trust_roots: &[NamedTrustRoot]) -> VerifyResult<()> {
+    let mut names: HashSet<&str> = HashSet::new();
+    let mut images: HashSet<&str> = HashSet::new();
+
+    for trust_root in trust_roots {
+        if !names.insert(trust_root.name.as_str()) {
+            return Err(VerifyError::InvalidVerifyFileError(format!(
+                "trust root '{}' is defined multiple times",
+                trust_root.name
+            )));
+        }
+        if trust_root.images.is_empty() {
+            return Err(VerifyError::InvalidVerifyFileError(format!(
+                "trust root '{}' is not associated with any image",
+                trust_root.name
+            )));
+        }
+        if trust_root.fulcio_certificates.is_empty() || trust_root.rekor_public_keys.is_empty() {
+            return Err(VerifyError::InvalidVerifyFileError(format!(
+                "trust root '{}': both a Fulcio certificate and a Rekor public key are required",
+                trust_root.name
+            )));
+        }
+        for image in &trust_root.images {
+            if !images.insert(image.trim_end_matches('/')) {
+                return Err(VerifyError::InvalidVerifyFileError(format!(
+                    "image '{image}' is associated with multiple trust roots"
+                )));
+            }
+        }
+        trust_root.manual_trust_root()?;
+    }
+
+    Ok(())
 }
 
 /// Enum that holds all the known versions of the configuration file
@@ -77,6 +231,37 @@ pub enum Signature {
     GithubAction {
         owner: String,
         repo: Option<String>,
+        /// Git ref the workflow run was started from, e.g. `refs/tags/v1.0.0`
+        #[serde(
+            rename = "workflowRef",
+            default,
+            skip_serializing_if = "Option::is_none"
+        )]
+        workflow_ref: Option<String>,
+        /// Event that triggered the workflow run, e.g. `push`
+        #[serde(
+            rename = "workflowTrigger",
+            default,
+            skip_serializing_if = "Option::is_none"
+        )]
+        workflow_trigger: Option<String>,
+        annotations: Option<BTreeMap<String, String>>,
+    },
+    /// Signature made with a certificate issued by a private PKI
+    Certificate {
+        /// PEM encoded signing certificate
+        certificate: String,
+        /// PEM encoded certificates of the CAs that issued the signing certificate,
+        /// starting from the issuer of the signing certificate up to the root CA
+        #[serde(rename = "certificateChain")]
+        certificate_chain: Option<Vec<String>>,
+        /// Constraint on the emails and URIs of the certificate
+        subject: Option<Subject>,
+        /// Constraint on the OIDC issuer recorded inside of the certificate
+        issuer: Option<String>,
+        /// Require the signature to be recorded inside of Rekor
+        #[serde(rename = "requireRekorBundle", default)]
+        require_rekor_bundle: bool,
         annotations: Option<BTreeMap<String, String>>,
     },
 }
@@ -110,12 +295,33 @@ impl Signature {
             Signature::GithubAction {
                 owner,
                 repo,
+                workflow_ref,
+                workflow_trigger,
                 annotations,
             } => Ok(Box::new(verification_constraints::GitHubVerifier::new(
                 owner,
                 repo.as_ref().map(|r| r.as_str()),
+                workflow_ref.as_deref(),
+                workflow_trigger.as_deref(),
                 annotations.as_ref(),
             ))),
+            Signature::Certificate {
+                certificate,
+                certificate_chain,
+                subject,
+                issuer,
+                require_rekor_bundle,
+                annotations,
+            } => Ok(Box::new(
+                verification_constraints::CertificateVerifier::new(
+                    certificate,
+                    certificate_chain.as_deref(),
+                    subject.as_ref(),
+                    issuer.as_deref(),
+                    *require_rekor_bundle,
+                    annotations.as_ref(),
+                )?,
+            )),
         }
     }
 }
@@ -205,12 +411,284 @@ pub fn build_latest_verification_config(
             "config is missing signatures in both allOf and anyOff list".to_owned(),
         ));
     }
+    if let Some(trust_roots) = &config.trust_roots {
+        validate_trust_roots(trust_roots)?;
+    }
+    // Report broken certificates and chains when loading the config, instead of
+    // failing each verification
+    let signatures = config
+        .all_of
+        .iter()
+        .flatten()
+        .chain(config.any_of.iter().flat_map(|any_of| &any_of.signatures));
+    for signature in signatures {
+        if matches!(signature, Signature::Certificate { .. }) {
+            signature.verifier()?;
+        }
+    }
     Ok(config)
 }
 
+/// Build the YAML representation of the given verification config, including its
+/// `apiVersion`
+pub fn serialize_verification_config(config: &LatestVerificationConfig) -> VerifyResult<String> {
+    let versioned_config = VersionedVerificationConfig::V1(config.clone());
+    // serde_yaml writes the enums, like `Subject`, as YAML tags, which cannot be read
+    // back by the untagged deserializers. Going through JSON writes them as maps
+    let serde_json::Value::Object(fields) =
+        serde_json::to_value(&versioned_config).map_err(|e| {
+            VerifyError::InvalidVerifyFileError(format!(
+                "cannot serialize the verification config: {e}"
+            ))
+        })?
+    else {
+        unreachable!("the verification config is always serialized as a map");
+    };
+
+    // keep `apiVersion` on top, like in the files written by hand
+    let mut document = serde_yaml::Mapping::new();
+    let (api_version, fields): (Vec<_>, Vec<_>) = fields
+        .into_iter()
+        .partition(|(name, _)| name == "apiVersion");
+    for (name, value) in api_version.into_iter().chain(fields) {
+        document.insert(
+            serde_yaml::Value::String(name),
+            serde_yaml::to_value(value).map_err(FailedToParseYamlDataError)?,
+        );
+    }
+    Ok(serde_yaml::to_string(&document).map_err(FailedToParseYamlDataError)?)
+}
+
+/// Store the verification config as an OCI artifact, allowing to distribute it the
+/// same way the policies are distributed. `destination` must use the `registry://`
+/// scheme.
+///
+/// Returns the immutable reference to the artifact
+pub async fn push_to_registry(
+    config: &LatestVerificationConfig,
+    destination: &str,
+    sources: Option<&Sources>,
+) -> VerifyResult<String> {
+    let config_yaml = serialize_verification_config(config)?;
+
+    Ok(Registry::new()
+        .push_artifact(
+            config_yaml.as_bytes(),
+            &ArtifactMediaTypes {
+                layer: VERIFICATION_CONFIG_LAYER_MEDIA_TYPE,
+                config: VERIFICATION_CONFIG_CONFIG_MEDIA_TYPE,
+            },
+            destination,
+            sources,
+            None,
+        )
+        .await?)
+}
+
+/// Fetch a verification config that has been stored as an OCI artifact by
+/// [`push_to_registry`]. The config is validated like the ones read from files.
+pub async fn fetch_from_registry(
+    url: &str,
+    sources: Option<&Sources>,
+) -> VerifyResult<LatestVerificationConfig> {
+    let config_yaml = Registry::new()
+        .pull_artifact(url, VERIFICATION_CONFIG_LAYER_MEDIA_TYPE, sources)
+        .await?;
+    let config_yaml = String::from_utf8(config_yaml).map_err(|e| {
+        VerifyError::InvalidVerifyFileError(format!(
+            "the verification config stored inside of {url} is not valid UTF-8: {e}"
+        ))
+    })?;
+
+    build_latest_verification_config(&config_yaml)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
+    use rstest::rstest;
+
+    // spellchecker:off
+    const FULCIO_CERT: &str = r#"-----BEGIN CERTIFICATE-----
+MIICUTCCAfugAwIBAgIBADANBgkqhkiG9w0BAQQFADBXMQswCQYDVQQGEwJDTjEL
+MAkGA1UECBMCUE4xCzAJBgNVBAcTAkNOMQswCQYDVQQKEwJPTjELMAkGA1UECxMC
+VU4xFDASBgNVBAMTC0hlcm9uZyBZYW5nMB4XDTA1MDcxNTIxMTk0N1oXDTA1MDgx
+NDIxMTk0N1owVzELMAkGA1UEBhMCQ04xCzAJBgNVBAgTAlBOMQswCQYDVQQHEwJD
+TjELMAkGA1UEChMCT04xCzAJBgNVBAsTAlVOMRQwEgYDVQQDEwtIZXJvbmcgWWFu
+ZzBcMA0GCSqGSIb3DQEBAQUAA0sAMEgCQQCp5hnG7ogBhtlynpOS21cBewKE/B7j
+V14qeyslnr26xZUsSVko36ZnhiaO/zbMOoRcKK9vEcgMtcLFuQTWDl3RAgMBAAGj
+gbEwga4wHQYDVR0OBBYEFFXI70krXeQDxZgbaCQoR4jUDncEMH8GA1UdIwR4MHaA
+FFXI70krXeQDxZgbaCQoR4jUDncEoVukWTBXMQswCQYDVQQGEwJDTjELMAkGA1UE
+CBMCUE4xCzAJBgNVBAcTAkNOMQswCQYDVQQKEwJPTjELMAkGA1UECxMCVU4xFDAS
+BgNVBAMTC0hlcm9uZyBZYW5nggEAMAwGA1UdEwQFMAMBAf8wDQYJKoZIhvcNAQEE
+BQADQQA/ugzBrjjK9jcWnDVfGHlk3icNRq0oV7Ri32z/+HQX67aRfgZu7KWdI+Ju
+Wm7DCfrPNGVwFWUQOmsPue9rZBgO
+-----END CERTIFICATE-----
+"#;
+    const REKOR_KEY: &str = r#"-----BEGIN PUBLIC KEY-----
+MFkwEwYHKoZIzj0CAQYIKoZIzj0DAQcDQgAELKhD7F5OKy77Z582Y6h0u1J3GNA+
+kvUsh4eKpd1lwkDAzfFDs7yXEExsEkPPuiQJBelDT68n7PDIWB/QEY7mrA==
+-----END PUBLIC KEY-----
+"#;
+    // spellchecker:on
+
+    fn named_trust_root(name: &str, images: &[&str]) -> NamedTrustRoot {
+        NamedTrustRoot {
+            name: name.to_string(),
+            images: images.iter().map(|i| i.to_string()).collect(),
+            fulcio_certificates: vec![FULCIO_CERT.to_string()],
+            rekor_public_keys: vec![REKOR_KEY.to_string()],
+        }
+    }
+
+    #[rstest]
+    #[case::registry("registry://registry.example.com/team/policy:v1", Some("private"))]
+    #[case::longest_prefix("ghcr.io/example/policies/pod-privileged:v1", Some("team"))]
+    #[case::same_registry_other_repo("ghcr.io/example/other:v1", Some("org"))]
+    #[case::not_a_path_boundary("ghcr.io/example-malicious/policy:v1", None)]
+    #[case::default_trust_root("ghcr.io/kubewarden/policies/pod-privileged:v1", None)]
+    fn select_trust_root_for_image(#[case] image: &str, #[case] expected: Option<&str>) {
+        let config = VerificationConfigV1 {
+            all_of: None,
+            any_of: None,
+            trust_roots: Some(vec![
+                named_trust_root("private", &["registry.example.com"]),
+                named_trust_root("org", &["ghcr.io/example"]),
+                named_trust_root("team", &["ghcr.io/example/policies/"]),
+            ]),
+        };
+
+        let trust_root = config.trust_root_for_image(image).unwrap();
+        assert_eq!(trust_root.map(|t| t.name.as_str()), expected);
+    }
+
+    #[test]
+    fn build_manual_trust_root() {
+        let trust_root = named_trust_root("private", &["registry.example.com"])
+            .manual_trust_root()
+            .unwrap();
+        assert_eq!(trust_root.fulcio_certs.len(), 1);
+        assert_eq!(trust_root.rekor_keys.len(), 1);
+
+        let broken = NamedTrustRoot {
+            fulcio_certificates: vec!["not a certificate".to_string()],
+            ..named_trust_root("broken", &["registry.example.com"])
+        };
+        assert!(broken.manual_trust_root().is_err());
+    }
+
+    #[rstest]
+    #[case::valid(vec![named_trust_root("a", &["ghcr.io/a"]), named_trust_root("b", &["ghcr.io/b"])], true)]
+    #[case::duplicated_name(vec![named_trust_root("a", &["ghcr.io/a"]), named_trust_root("a", &["ghcr.io/b"])], false)]
+    #[case::duplicated_image(vec![named_trust_root("a", &["ghcr.io/a"]), named_trust_root("b", &["ghcr.io/a/"])], false)]
+    #[case::no_images(vec![named_trust_root("a", &[])], false)]
+    #[case::missing_rekor_key(vec![NamedTrustRoot { rekor_public_keys: vec![], ..named_trust_root("a", &["ghcr.io/a"]) }], false)]
+    fn validate_named_trust_roots(#[case] trust_roots: Vec<NamedTrustRoot>, #[case] valid: bool) {
+        assert_eq!(validate_trust_roots(&trust_roots).is_ok(), valid);
+    }
+
+    #[test]
+    fn test_deserialize_trust_roots() {
+        let config = format!(
+            r#"---
+    apiVersion: v1
+
+    allOf:
+      - kind: githubAction
+        owner: example
+    trustRoots:
+      - name: private
+        images:
+          - registry.example.com
+        fulcioCertificates:
+          - |
+{}
+        rekorPublicKeys:
+          - |
+{}
+    "#,
+            indent(FULCIO_CERT, 12),
+            indent(REKOR_KEY, 12)
+        );
+
+        let vc = build_latest_verification_config(&config).unwrap();
+        let trust_roots = vc.trust_roots.unwrap();
+        assert_eq!(trust_roots.len(), 1);
+        assert_eq!(trust_roots[0].name, "private");
+        assert_eq!(trust_roots[0].images, vec!["registry.example.com"]);
+    }
+
+    fn indent(text: &str, spaces: usize) -> String {
+        text.lines()
+            .map(|line| format!("{}{line}", " ".repeat(spaces)))
+            .collect::<Vec<String>>()
+            .join("\n")
+    }
+
+    #[test]
+    fn test_deserialize_certificate_signature() {
+        let config = r#"---
+    apiVersion: v1
+
+    allOf:
+      - kind: certificate
+        certificate: |
+          -----BEGIN CERTIFICATE-----
+          MIIB
+          -----END CERTIFICATE-----
+        certificateChain: []
+        subject:
+          equal: release@example.com
+        requireRekorBundle: true
+    "#;
+        let vc: VerificationConfig = serde_yaml::from_str(config).unwrap();
+        let VerificationConfig::Versioned(VersionedVerificationConfig::V1(v1)) = vc else {
+            panic!("got an invalid config");
+        };
+        assert_eq!(
+            v1.all_of.unwrap()[0],
+            Signature::Certificate {
+                certificate: "-----BEGIN CERTIFICATE-----\nMIIB\n-----END CERTIFICATE-----\n"
+                    .to_string(),
+                certificate_chain: Some(vec![]),
+                subject: Some(Subject::Equal("release@example.com".to_string())),
+                issuer: None,
+                require_rekor_bundle: true,
+                annotations: None,
+            }
+        );
+
+        // the certificate is not valid, this is reported when building the config
+        assert!(matches!(
+            build_latest_verification_config(config),
+            Err(VerifyError::InvalidVerifyFileError(_))
+        ));
+    }
+
+    #[test]
+    fn test_deserialize_github_action_signature() {
+        let config = r#"---
+    apiVersion: v1
+
+    allOf:
+      - kind: githubAction
+        owner: kubewarden
+        repo: policy-secure-pod-images
+        workflowRef: refs/tags/v1.0.0
+        workflowTrigger: push
+    "#;
+        let vc = build_latest_verification_config(config).unwrap();
+        assert_eq!(
+            vc.all_of.unwrap()[0],
+            Signature::GithubAction {
+                owner: "kubewarden".to_string(),
+                repo: Some("policy-secure-pod-images".to_string()),
+                workflow_ref: Some("refs/tags/v1.0.0".to_string()),
+                workflow_trigger: Some("push".to_string()),
+                annotations: None,
+            }
+        );
+    }
 
     #[test]
     fn test_deserialize_on_broken_yaml() {
@@ -278,6 +756,7 @@ mod tests {
                     let expected = VerificationConfigV1 {
                         all_of: Some(signatures),
                         any_of: None,
+                        trust_roots: None,
                     };
                     assert_eq!(v1, expected);
                 }
@@ -287,6 +766,26 @@ mod tests {
         }
     }
 
+    #[test]
+    fn serialize_and_build_verification_config() {
+        let config = VerificationConfigV1 {
+            all_of: Some(vec![Signature::GenericIssuer {
+                issuer: "https://token.actions.githubusercontent.com".to_string(),
+                subject: Subject::UrlPrefix(Url::parse("https://github.com/kubewarden/").unwrap()),
+                annotations: None,
+            }]),
+            any_of: None,
+            trust_roots: Some(vec![named_trust_root("private", &["registry.example.com"])]),
+        };
+
+        let config_yaml = serialize_verification_config(&config).unwrap();
+        assert!(config_yaml.starts_with("apiVersion: v1\n"));
+        assert_eq!(
+            build_latest_verification_config(&config_yaml).unwrap(),
+            config
+        );
+    }
+
     #[test]
     fn test_sanitize_url_prefix() {
         let config = r#"---
@@ -322,6 +821,7 @@ mod tests {
                     let expected = VerificationConfigV1 {
                         all_of: Some(signatures),
                         any_of: None,
+                        trust_roots: None,
                     };
                     assert_eq!(v1, expected);
                 }
@@ -14,6 +14,8 @@ pub enum VerifyError {
     ImageVerificationError(String),
     #[error("{0}")]
     InvalidVerifyFileError(String),
+    #[error("Sigstore bundle verification failure: {0}")]
+    BundleVerificationError(String),
     #[error("Verification only works with OCI images: Not a valid oci image: {0}")]
     InvalidOCIImageReferenceError(#[from] oci_client::ParseError),
     #[error("key verification failure: {0} ")]
@@ -34,3 +36,23 @@ pub enum VerifyError {
     #[error(transparent)]
     FailedToParseYamlDataError(#[from] FailedToParseYamlDataError),
 }
+
+impl VerifyError {
+    /// Whether the verification failed because the registry is rate limiting
+    /// the requests. The verification can be retried later.
+    pub fn is_throttled(&self) -> bool {
+        match self {
+            VerifyError::RegistryError(e) => e.is_throttled(),
+            _ => false,
+        }
+    }
+
+    /// Whether the verification failed because the registry could not be reached,
+    /// or because of an error on its side. The verification can be retried later.
+    pub fn is_transient(&self) -> bool {
+        match self {
+            VerifyError::RegistryError(e) => e.is_transient(),
+            _ => false,
+        }
+    }
+}
@@ -1,11 +1,13 @@
+use base64::{engine::general_purpose::STANDARD, Engine as _};
 use oci_client::{manifest::WASM_LAYER_MEDIA_TYPE, secrets::RegistryAuth, Reference};
+use sha2::{Digest, Sha256};
 use sigstore::{
     cosign::{self, signature_layers::SignatureLayer, ClientBuilder, CosignCapabilities},
     errors::SigstoreError,
     registry::oci_reference::OciReference,
     trust::ManualTrustRoot,
 };
-use std::{convert::TryFrom, str::FromStr, sync::Arc};
+use std::{collections::HashMap, convert::TryFrom, str::FromStr, sync::Arc};
 use tokio::sync::Mutex;
 use tracing::{debug, error, info, warn};
 
@@ -14,13 +16,16 @@ use crate::{
     policy::Policy,
     registry::build_fully_resolved_reference,
     sources::Sources,
+    store::provenance::RecordedBundles,
     verify::{
-        config::Signature,
+        bundle::{Bundle, SIGSTORE_BUNDLE_V03_MEDIA_TYPE},
+        config::{NamedTrustRoot, Signature},
         errors::{VerifyError, VerifyResult},
     },
     Registry,
 };
 
+pub mod bundle;
 pub mod config;
 pub mod errors;
 pub mod verification_constraints;
@@ -31,6 +36,13 @@ pub mod verification_constraints;
 pub struct Verifier {
     cosign_client: Arc<Mutex<sigstore::cosign::Client>>,
     sources: Option<Sources>,
+    /// Cosign clients of the trust roots defined inside of the verification config,
+    /// indexed by the name of the trust root. They are created the first time
+    /// the trust root is used.
+    named_cosign_clients: HashMap<String, (NamedTrustRoot, Arc<Mutex<sigstore::cosign::Client>>)>,
+    /// The trust root used to verify the Sigstore bundles, unless the verification
+    /// config associates the image with one of its trust roots
+    trust_root: Option<Arc<ManualTrustRoot<'static>>>,
 }
 
 impl Verifier {
@@ -43,6 +55,8 @@ impl Verifier {
         Self {
             cosign_client,
             sources,
+            named_cosign_clients: HashMap::new(),
+            trust_root: None,
         }
     }
 
@@ -57,7 +71,7 @@ impl Verifier {
         let mut cosign_client_builder = ClientBuilder::default()
             .with_oci_client_config(client_config)
             .enable_registry_caching();
-        let cosign_client = match trust_root {
+        let cosign_client = match trust_root.as_ref() {
             Some(trust_root) => {
                 cosign_client_builder =
                     cosign_client_builder.with_trust_repository(trust_root.as_ref())?;
@@ -75,12 +89,74 @@ impl Verifier {
         Ok(Verifier {
             cosign_client: Arc::new(Mutex::new(cosign_client)),
             sources,
+            named_cosign_clients: HashMap::new(),
+            trust_root,
         })
     }
 
+    /// Returns the cosign client to be used when verifying the given image.
+    /// This is the default client, unless the verification config associates the
+    /// image with one of its trust roots.
+    fn cosign_client_for_image(
+        &mut self,
+        image_url: &str,
+        verification_config: &config::LatestVerificationConfig,
+    ) -> VerifyResult<Arc<Mutex<sigstore::cosign::Client>>> {
+        let trust_root = match verification_config.trust_root_for_image(image_url)? {
+            Some(trust_root) => trust_root,
+            None => return Ok(self.cosign_client.clone()),
+        };
+
+        if let Some((cached_trust_root, cosign_client)) =
+            self.named_cosign_clients.get(&trust_root.name)
+        {
+            // the same name could be reused by a different verification config
+            if cached_trust_root == trust_root {
+                return Ok(cosign_client.clone());
+            }
+        }
+
+        debug!(
+            image = image_url,
+            trust_root = trust_root.name.as_str(),
+            "creating cosign client for trust root"
+        );
+        let client_config: sigstore::registry::ClientConfig =
+            self.sources.clone().unwrap_or_default().into();
+        let manual_trust_root = trust_root.manual_trust_root()?;
+        let cosign_client = ClientBuilder::default()
+            .with_oci_client_config(client_config)
+            .enable_registry_caching()
+            .with_trust_repository(&manual_trust_root)?
+            .build()?;
+        let cosign_client = Arc::new(Mutex::new(cosign_client));
+
+        self.named_cosign_clients.insert(
+            trust_root.name.clone(),
+            (trust_root.clone(), cosign_client.clone()),
+        );
+        Ok(cosign_client)
+    }
+
+    /// Returns the trust root to be used when verifying the Sigstore bundles of
+    /// the given image
+    fn trust_root_for_image(
+        &self,
+        image_url: &str,
+        verification_config: &config::LatestVerificationConfig,
+    ) -> VerifyResult<Option<Arc<ManualTrustRoot<'static>>>> {
+        match verification_config.trust_root_for_image(image_url)? {
+            Some(trust_root) => Ok(Some(Arc::new(trust_root.manual_trust_root()?))),
+            None => Ok(self.trust_root.clone()),
+        }
+    }
+
     /// Verifies the given policy using the LatestVerificationConfig provided by
     /// the user.
     ///
+    /// Both the cosign signatures and the Sigstore bundles stored as OCI referrers
+    /// of the policy are taken into account.
+    ///
     /// In case of success, returns the manifest digest of the verified policy.
     ///
     /// Note well: this method doesn't compare the checksum of a possible local
@@ -94,8 +170,48 @@ impl Verifier {
         image_url: &str,
         verification_config: &config::LatestVerificationConfig,
     ) -> VerifyResult<String> {
-        let (source_image_digest, trusted_layers) =
-            fetch_sigstore_remote_data(&self.cosign_client, image_url).await?;
+        self.verify_and_record_bundles(image_url, verification_config)
+            .await
+            .map(|(manifest_digest, _)| manifest_digest)
+    }
+
+    /// Like [`Verifier::verify`], also returns the Sigstore bundles that verified the
+    /// policy, if any. Once recorded inside of the store, they allow to verify the policy
+    /// again without network access, see [`Verifier::verify_offline`].
+    pub async fn verify_and_record_bundles(
+        &mut self,
+        image_url: &str,
+        verification_config: &config::LatestVerificationConfig,
+    ) -> VerifyResult<(String, Option<RecordedBundles>)> {
+        let cosign_client = self.cosign_client_for_image(image_url, verification_config)?;
+        let (source_image_digest, mut trusted_layers) =
+            match fetch_sigstore_remote_data(&cosign_client, image_url).await {
+                Ok(remote_data) => remote_data,
+                Err(VerifyError::ImageVerificationError(error)) => {
+                    // the policy could be signed only with Sigstore bundles
+                    debug!(policy = image_url, %error, "no cosign signatures found");
+                    let digest = Registry::new()
+                        .manifest_digest(image_url, self.sources.as_ref())
+                        .await?;
+                    (digest, Vec::new())
+                }
+                Err(e) => return Err(e),
+            };
+
+        let trust_root = self.trust_root_for_image(image_url, verification_config)?;
+        let (bundle_layers, recorded_bundles) = fetch_and_record_sigstore_bundles(
+            image_url,
+            &source_image_digest,
+            trust_root.as_deref(),
+            self.sources.as_ref(),
+        )
+        .await?;
+        trusted_layers.extend(bundle_layers);
+        if trusted_layers.is_empty() {
+            return Err(VerifyError::ImageVerificationError(format!(
+                "no signatures found for image: {image_url}"
+            )));
+        }
 
         // verify signatures against our config:
         //
@@ -106,7 +222,93 @@ impl Verifier {
             policy = image_url.to_string().as_str(),
             "Policy successfully verified"
         );
-        Ok(source_image_digest)
+        Ok((source_image_digest, recorded_bundles))
+    }
+
+    /// Verifies the given policy without network access, using the Sigstore bundles
+    /// recorded when the policy has been pulled.
+    ///
+    /// The recorded manifest must match its digest and the local WebAssembly module,
+    /// the transparency log entry of each bundle is verified against the pinned Rekor
+    /// keys of the trust root. Finally, the bundles are checked against the verification
+    /// config, like [`Verifier::verify`] does.
+    ///
+    /// Note well: the cosign signatures are not recorded, a policy signed only with them
+    /// cannot be verified offline.
+    pub fn verify_offline(
+        &self,
+        policy: &Policy,
+        recorded_bundles: &RecordedBundles,
+        verification_config: &config::LatestVerificationConfig,
+    ) -> VerifyResult<()> {
+        let manifest_digest = recorded_bundles.manifest_digest.as_str();
+        let manifest = STANDARD.decode(&recorded_bundles.manifest).map_err(|e| {
+            VerifyError::BundleVerificationError(format!("cannot decode recorded manifest: {e}"))
+        })?;
+        let actual_digest = format!("sha256:{:x}", Sha256::digest(&manifest));
+        if actual_digest != manifest_digest {
+            return Err(VerifyError::ChecksumVerificationError(format!(
+                "The recorded manifest doesn't match its digest. Got {actual_digest} instead of {manifest_digest}"
+            )));
+        }
+        let oci_manifest: oci_client::manifest::OciImageManifest =
+            serde_json::from_slice(&manifest).map_err(|e| {
+                VerifyError::BundleVerificationError(format!("cannot parse recorded manifest: {e}"))
+            })?;
+        verify_wasm_layer_digest(policy, &oci_manifest)?;
+
+        if !policy.uri.starts_with("registry://") {
+            return Err(VerifyError::ImageVerificationError(format!(
+                "only policies pulled from OCI registries can be verified: {}",
+                policy.uri
+            )));
+        }
+        let image_url = policy.uri.as_str();
+        let reference = build_fully_resolved_reference(image_url)?;
+        let repository = format!("{}/{}", reference.registry(), reference.repository());
+        let trust_root = self
+            .trust_root_for_image(image_url, verification_config)?
+            .ok_or_else(|| {
+                VerifyError::BundleVerificationError(
+                    "a trust root with pinned Rekor keys is required to verify offline".to_owned(),
+                )
+            })?;
+
+        let mut trusted_layers = Vec::new();
+        for (index, bundle) in recorded_bundles.bundles.iter().enumerate() {
+            let layer = STANDARD
+                .decode(bundle)
+                .map_err(|e| {
+                    VerifyError::BundleVerificationError(format!("cannot decode bundle: {e}"))
+                })
+                .and_then(|data| Bundle::from_slice(&data))
+                .and_then(|bundle| {
+                    bundle.verify_offline(&repository, &manifest, manifest_digest, &trust_root)
+                });
+            match layer {
+                Ok(layer) => trusted_layers.push(layer),
+                Err(error) => warn!(
+                    policy = policy.uri.as_str(),
+                    bundle = index,
+                    %error,
+                    "discarding recorded Sigstore bundle that cannot be verified offline"
+                ),
+            }
+        }
+        if trusted_layers.is_empty() {
+            return Err(VerifyError::ImageVerificationError(format!(
+                "no recorded Sigstore bundles can be verified offline for policy: {}",
+                policy.uri
+            )));
+        }
+
+        verify_signatures_against_config(verification_config, &trusted_layers)?;
+
+        debug!(
+            policy = policy.uri.as_str(),
+            "Policy successfully verified offline"
+        );
+        Ok(())
     }
 
     /// Verifies the checksum of the local file by comparing it with the one
@@ -142,40 +344,45 @@ impl Verifier {
             reference.repository(),
             verified_manifest_digest
         );
+        // the signed manifest can be an image index, listing the one of the Wasm module
         let manifest = registry
-            .manifest(&image_immutable_ref, self.sources.as_ref())
+            .wasm_manifest(&image_immutable_ref, self.sources.as_ref())
             .await?;
 
-        let digests: Vec<String> = if let oci_client::manifest::OciManifest::Image(ref image) =
-            manifest
-        {
-            image
-                .layers
-                .iter()
-                .filter_map(|layer| match layer.media_type.as_str() {
-                    WASM_LAYER_MEDIA_TYPE => Some(layer.digest.clone()),
-                    _ => None,
-                })
-                .collect()
-        } else {
-            unreachable!("Expected Image, found ImageIndex manifest. This cannot happen, as oci clientConfig.platform_resolver is None and we will error earlier");
-        };
+        verify_wasm_layer_digest(policy, &manifest)?;
+        info!("Local file checksum verification passed");
+        Ok(())
+    }
+}
 
-        if digests.len() != 1 {
-            error!(manifest = ?manifest, "The manifest is expected to have one WASM layer");
-            return Err(VerifyError::ChecksumVerificationError("Cannot verify local file integrity, the remote manifest doesn't have only one WASM layer".to_owned()));
-        }
-        let expected_digest = digests[0]
-            .strip_prefix("sha256:")
-            .ok_or_else(|| VerifyError::ChecksumVerificationError("The checksum inside of the remote manifest is not using the sha256 hashing algorithm as expected.".to_owned()))?;
-
-        let file_digest = policy.digest()?;
-        if file_digest != expected_digest {
-            Err(VerifyError::ChecksumVerificationError(format!("The digest of the local file doesn't match with the one reported inside of the signed manifest. Got {file_digest} instead of {expected_digest}")))
-        } else {
-            info!("Local file checksum verification passed");
-            Ok(())
-        }
+/// Ensures the local WebAssembly module of the policy matches the only WASM layer
+/// of the given (verified) manifest
+fn verify_wasm_layer_digest(
+    policy: &Policy,
+    manifest: &oci_client::manifest::OciImageManifest,
+) -> VerifyResult<()> {
+    let digests: Vec<String> = manifest
+        .layers
+        .iter()
+        .filter_map(|layer| match layer.media_type.as_str() {
+            WASM_LAYER_MEDIA_TYPE => Some(layer.digest.clone()),
+            _ => None,
+        })
+        .collect();
+
+    if digests.len() != 1 {
+        error!(manifest = ?manifest, "The manifest is expected to have one WASM layer");
+        return Err(VerifyError::ChecksumVerificationError("Cannot verify local file integrity, the remote manifest doesn't have only one WASM layer".to_owned()));
+    }
+    let expected_digest = digests[0]
+        .strip_prefix("sha256:")
+        .ok_or_else(|| VerifyError::ChecksumVerificationError("The checksum inside of the remote manifest is not using the sha256 hashing algorithm as expected.".to_owned()))?;
+
+    let file_digest = policy.digest()?;
+    if file_digest != expected_digest {
+        Err(VerifyError::ChecksumVerificationError(format!("The digest of the local file doesn't match with the one reported inside of the signed manifest. Got {file_digest} instead of {expected_digest}")))
+    } else {
+        Ok(())
     }
 }
 
@@ -320,6 +527,97 @@ pub async fn fetch_sigstore_remote_data(
     Ok((source_image_digest, layers))
 }
 
+/// Fetch the Sigstore bundles stored as OCI referrers of the given policy, and
+/// verify them. Bundles that cannot be verified are discarded.
+/// Returns the signature layers of the verified bundles.
+pub async fn fetch_sigstore_bundles(
+    image_url: &str,
+    manifest_digest: &str,
+    trust_root: Option<&ManualTrustRoot<'static>>,
+    sources: Option<&Sources>,
+) -> VerifyResult<Vec<SignatureLayer>> {
+    fetch_and_record_sigstore_bundles(image_url, manifest_digest, trust_root, sources)
+        .await
+        .map(|(layers, _)| layers)
+}
+
+/// Like [`fetch_sigstore_bundles`], also returns the raw manifest and the raw
+/// verified bundles, when there are any, to verify them again later without
+/// network access
+async fn fetch_and_record_sigstore_bundles(
+    image_url: &str,
+    manifest_digest: &str,
+    trust_root: Option<&ManualTrustRoot<'static>>,
+    sources: Option<&Sources>,
+) -> VerifyResult<(Vec<SignatureLayer>, Option<RecordedBundles>)> {
+    let reference = build_fully_resolved_reference(image_url)?;
+    let repository = format!("{}/{}", reference.registry(), reference.repository());
+    let image_immutable_ref = format!("registry://{repository}@{manifest_digest}");
+    let registry = Registry::new();
+
+    let bundle_digests: Vec<String> = match registry
+        .referrers(
+            &image_immutable_ref,
+            Some(SIGSTORE_BUNDLE_V03_MEDIA_TYPE),
+            sources,
+        )
+        .await
+    {
+        Ok(referrers) => referrers
+            .into_iter()
+            .map(|referrer| referrer.digest)
+            .collect(),
+        Err(error) => {
+            // not all the registries implement the referrers API
+            debug!(policy = image_url, ?error, "cannot list OCI referrers");
+            return Ok((Vec::new(), None));
+        }
+    };
+    if bundle_digests.is_empty() {
+        return Ok((Vec::new(), None));
+    }
+
+    let (manifest, _) = registry.manifest_raw(&image_immutable_ref, sources).await?;
+
+    let mut layers = Vec::new();
+    let mut verified_bundles = Vec::new();
+    for bundle_digest in bundle_digests {
+        let verified = registry
+            .pull_artifact(
+                &format!("registry://{repository}@{bundle_digest}"),
+                SIGSTORE_BUNDLE_V03_MEDIA_TYPE,
+                sources,
+            )
+            .await
+            .map_err(VerifyError::from)
+            .and_then(|data| {
+                let layer = Bundle::from_slice(&data)?.verify(
+                    &repository,
+                    &manifest,
+                    manifest_digest,
+                    trust_root,
+                )?;
+                Ok((layer, data))
+            });
+        match verified {
+            Ok((layer, data)) => {
+                layers.push(layer);
+                verified_bundles.push(data);
+            }
+            Err(error) => warn!(
+                policy = image_url,
+                bundle = bundle_digest.as_str(),
+                %error,
+                "discarding Sigstore bundle that cannot be verified"
+            ),
+        }
+    }
+
+    let recorded_bundles = (!verified_bundles.is_empty())
+        .then(|| RecordedBundles::new(manifest_digest, &manifest, &verified_bundles));
+    Ok((layers, recorded_bundles))
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -401,6 +699,7 @@ kvUsh4eKpd1lwkDAzfFDs7yXEExsEkPPuiQJBelDT68n7PDIWB/QEY7mrA==
                 minimum_matches: 1,
                 signatures: signatures_any_of,
             }),
+            trust_roots: None,
         };
 
         // build trusted layers:
@@ -419,6 +718,7 @@ kvUsh4eKpd1lwkDAzfFDs7yXEExsEkPPuiQJBelDT68n7PDIWB/QEY7mrA==
         let verification_config = LatestVerificationConfig {
             all_of: None,
             any_of: None,
+            trust_roots: None,
         };
 
         // build trusted layers:
@@ -444,6 +744,7 @@ kvUsh4eKpd1lwkDAzfFDs7yXEExsEkPPuiQJBelDT68n7PDIWB/QEY7mrA==
         let verification_config = LatestVerificationConfig {
             all_of: Some(signatures_all_of),
             any_of: None,
+            trust_roots: None,
         };
 
         // build trusted layers:
@@ -477,6 +778,7 @@ annotations: null
         let verification_config = LatestVerificationConfig {
             all_of: Some(signatures_all_of),
             any_of: None,
+            trust_roots: None,
         };
 
         // build trusted layers:
@@ -513,6 +815,7 @@ annotations: null
                 minimum_matches: 2,
                 signatures: signatures_any_of,
             }),
+            trust_roots: None,
         };
 
         // build trusted layers:
@@ -552,6 +855,7 @@ annotations: null
                 minimum_matches: 2,
                 signatures: signatures_any_of,
             }),
+            trust_roots: None,
         };
 
         // build trusted layers:
@@ -1,12 +1,19 @@
-use std::{collections::BTreeMap, convert::TryFrom};
+use std::{
+    collections::BTreeMap,
+    convert::TryFrom,
+    time::{SystemTime, UNIX_EPOCH},
+};
 
+use rustls_pki_types::{pem::PemObject, CertificateDer};
 use sigstore::cosign::signature_layers::CertificateSignature;
 use sigstore::cosign::verification_constraint::{
     AnnotationVerifier, PublicKeyVerifier, VerificationConstraint,
 };
 use sigstore::cosign::{signature_layers::CertificateSubject, SignatureLayer};
+use sigstore::crypto::{CosignVerificationKey, Signature};
 use sigstore::errors::{Result, SigstoreError};
 use tracing::debug;
+use x509_parser::{certificate::X509Certificate, extensions::GeneralName, prelude::FromDer};
 
 use crate::verify::{
     config::Subject,
@@ -78,23 +85,9 @@ impl GenericIssuerSubjectVerifier {
             annotations: a.to_owned(),
         });
 
-        let s = match &subject {
-            Subject::Equal(_) => subject.clone(),
-            Subject::UrlPrefix(url) => {
-                let prefix = url.to_string();
-                if prefix.ends_with('/') {
-                    subject.clone()
-                } else {
-                    let u = url::Url::parse(format!("{prefix}/").as_str())
-                        .expect("This should never fail");
-                    Subject::UrlPrefix(u)
-                }
-            }
-        };
-
         Self {
             issuer: issuer.to_string(),
-            subject: s,
+            subject: normalize_subject(subject),
             annotation_verifier,
         }
     }
@@ -170,14 +163,266 @@ impl VerificationConstraint for GenericIssuerSubjectVerifier {
     }
 }
 
+/// Fulcio certificate extension holding the OIDC issuer
+const OID_ISSUER_V1: &str = "1.3.6.1.4.1.57264.1.1";
+
+/// Verification Constraint for signatures made with a certificate issued by a
+/// private PKI
+///
+/// The signing certificate, and the chain of CAs that issued it, are provided by
+/// the user: Fulcio is not involved. The certificate must be valid when the
+/// signature is verified, or when the signature has been added to Rekor if a
+/// Rekor bundle is attached to the signature.
+#[derive(Debug)]
+pub struct CertificateVerifier {
+    verification_key: CosignVerificationKey,
+    not_before: i64,
+    not_after: i64,
+    require_rekor_bundle: bool,
+    annotation_verifier: Option<AnnotationVerifier>,
+}
+
+impl CertificateVerifier {
+    /// Build the verifier from the PEM encoded certificate and chain. The chain starts
+    /// with the CA that issued the certificate and ends with the root CA.
+    ///
+    /// The `subject` and `issuer` constraints are checked against the identity of
+    /// the certificate
+    pub fn new(
+        certificate: &str,
+        certificate_chain: Option<&[String]>,
+        subject: Option<&Subject>,
+        issuer: Option<&str>,
+        require_rekor_bundle: bool,
+        annotations: Option<&BTreeMap<String, String>>,
+    ) -> VerifyResult<Self> {
+        let certificate_der = pem_to_der(certificate)?;
+        let (_, cert) = X509Certificate::from_der(&certificate_der).map_err(|e| {
+            VerifyError::InvalidVerifyFileError(format!("cannot parse certificate: {e}"))
+        })?;
+
+        let chain_der = certificate_chain
+            .unwrap_or_default()
+            .iter()
+            .map(|pem| pem_to_der(pem))
+            .collect::<VerifyResult<Vec<_>>>()?;
+        let chain = chain_der
+            .iter()
+            .map(|der| {
+                X509Certificate::from_der(der)
+                    .map(|(_, cert)| cert)
+                    .map_err(|e| {
+                        VerifyError::InvalidVerifyFileError(format!(
+                            "cannot parse certificate of the chain: {e}"
+                        ))
+                    })
+            })
+            .collect::<VerifyResult<Vec<_>>>()?;
+        verify_certificate_chain(&cert, &chain)?;
+
+        let code_signing = cert
+            .extended_key_usage()
+            .ok()
+            .flatten()
+            .is_some_and(|eku| eku.value.code_signing);
+        if !code_signing {
+            return Err(VerifyError::InvalidVerifyFileError(
+                "the certificate cannot be used for code signing".to_owned(),
+            ));
+        }
+        verify_certificate_identity(&cert, subject, issuer)?;
+
+        let verification_key = CosignVerificationKey::try_from_der(cert.public_key().raw)
+            .map_err(VerifyError::KeyVerificationError)?;
+        let annotation_verifier = annotations.map(|a| AnnotationVerifier {
+            annotations: a.to_owned(),
+        });
+
+        Ok(Self {
+            verification_key,
+            not_before: cert.validity().not_before.timestamp(),
+            not_after: cert.validity().not_after.timestamp(),
+            require_rekor_bundle,
+            annotation_verifier,
+        })
+    }
+}
+
+impl VerificationConstraint for CertificateVerifier {
+    fn verify(&self, sl: &SignatureLayer) -> Result<bool> {
+        let signature = match &sl.signature {
+            Some(signature) => signature,
+            None => return Ok(false),
+        };
+        if self
+            .verification_key
+            .verify_signature(Signature::Base64Encoded(signature.as_bytes()), &sl.raw_data)
+            .is_err()
+        {
+            debug!("signature not made with the certificate");
+            return Ok(false);
+        }
+
+        let signed_at = match &sl.bundle {
+            Some(bundle) => bundle.payload.integrated_time,
+            None if self.require_rekor_bundle => {
+                debug!("rekor bundle required but not found");
+                return Ok(false);
+            }
+            None => SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|now| now.as_secs() as i64)
+                .unwrap_or_default(),
+        };
+        if signed_at < self.not_before || signed_at > self.not_after {
+            debug!(
+                signed_at,
+                not_before = self.not_before,
+                not_after = self.not_after,
+                "certificate not valid at signing time"
+            );
+            return Ok(false);
+        }
+
+        match &self.annotation_verifier {
+            Some(av) => av.verify(sl),
+            None => Ok(true),
+        }
+    }
+}
+
+fn pem_to_der(pem: &str) -> VerifyResult<CertificateDer<'static>> {
+    CertificateDer::from_pem_slice(pem.as_bytes())
+        .map_err(|e| VerifyError::InvalidVerifyFileError(format!("invalid certificate: {e}")))
+}
+
+/// Ensure each certificate has been issued by the next CA of the chain. The last
+/// certificate of the chain must be a self-signed root CA
+fn verify_certificate_chain<'a>(
+    cert: &'a X509Certificate<'a>,
+    chain: &'a [X509Certificate<'a>],
+) -> VerifyResult<()> {
+    let mut issued = cert;
+    for ca in chain {
+        let is_ca = ca
+            .basic_constraints()
+            .ok()
+            .flatten()
+            .is_some_and(|bc| bc.value.ca);
+        if !is_ca {
+            return Err(VerifyError::InvalidVerifyFileError(format!(
+                "certificate '{}' of the chain is not a CA",
+                ca.subject()
+            )));
+        }
+        if issued.issuer() != ca.subject()
+            || issued.verify_signature(Some(ca.public_key())).is_err()
+        {
+            return Err(VerifyError::InvalidVerifyFileError(format!(
+                "certificate '{}' has not been issued by '{}'",
+                issued.subject(),
+                ca.subject()
+            )));
+        }
+        issued = ca;
+    }
+
+    if let Some(root) = chain.last() {
+        if root.issuer() != root.subject() || root.verify_signature(None).is_err() {
+            return Err(VerifyError::InvalidVerifyFileError(format!(
+                "the last certificate of the chain, '{}', is not a root CA",
+                root.subject()
+            )));
+        }
+    }
+    Ok(())
+}
+
+/// The URL prefixes are given a trailing `/`, otherwise `https://github.com/org`
+/// would match `https://github.com/org-evil` too
+fn normalize_subject(subject: &Subject) -> Subject {
+    match subject {
+        Subject::Equal(_) => subject.clone(),
+        Subject::UrlPrefix(url) => {
+            let prefix = url.to_string();
+            if prefix.ends_with('/') {
+                subject.clone()
+            } else {
+                let u =
+                    url::Url::parse(format!("{prefix}/").as_str()).expect("This should never fail");
+                Subject::UrlPrefix(u)
+            }
+        }
+    }
+}
+
+/// Ensure the identity of the certificate satisfies the given constraints.
+///
+/// The subject is one of the emails or URIs of the Subject Alternative Name
+/// extension, the issuer is the one of the Fulcio extension, which private PKIs
+/// can set as well
+fn verify_certificate_identity(
+    cert: &X509Certificate,
+    subject: Option<&Subject>,
+    issuer: Option<&str>,
+) -> VerifyResult<()> {
+    if let Some(subject) = subject {
+        let identities: Vec<String> = cert
+            .subject_alternative_name()
+            .ok()
+            .flatten()
+            .map(|san| {
+                san.value
+                    .general_names
+                    .iter()
+                    .filter_map(|name| match name {
+                        GeneralName::RFC822Name(email) => Some(email.to_string()),
+                        GeneralName::URI(uri) => Some(uri.to_string()),
+                        _ => None,
+                    })
+                    .collect()
+            })
+            .unwrap_or_default();
+        let satisfied = identities
+            .iter()
+            .any(|identity| match normalize_subject(subject) {
+                Subject::Equal(value) => *identity == value,
+                Subject::UrlPrefix(prefix) => identity.starts_with(prefix.as_str()),
+            });
+        if !satisfied {
+            return Err(VerifyError::InvalidVerifyFileError(format!(
+                "the identities of the certificate, {identities:?}, do not satisfy the subject constraint {subject:?}"
+            )));
+        }
+    }
+
+    if let Some(issuer) = issuer {
+        let certificate_issuer = cert
+            .extensions()
+            .iter()
+            .find(|ext| ext.oid.to_id_string() == OID_ISSUER_V1)
+            .and_then(|ext| std::str::from_utf8(ext.value).ok());
+        if certificate_issuer != Some(issuer) {
+            return Err(VerifyError::InvalidVerifyFileError(format!(
+                "the issuer of the certificate, {certificate_issuer:?}, is not {issuer}"
+            )));
+        }
+    }
+    Ok(())
+}
+
 /// Verification Constraint for Signatures produced by GitHub Actions
 ///
 /// This constraint looks at the signature done in keyless mode by a
-/// Github Action and inspects its Subject.
+/// Github Action and inspects its Subject. The git ref and the trigger of
+/// the workflow run are checked against the extensions of the certificate,
+/// when required.
 #[derive(Debug)]
 pub struct GitHubVerifier {
     owner: String,
     repo: Option<String>,
+    workflow_ref: Option<String>,
+    workflow_trigger: Option<String>,
     annotation_verifier: Option<AnnotationVerifier>,
 }
 
@@ -189,6 +434,8 @@ impl GitHubVerifier {
     pub fn new(
         owner: &str,
         repo: Option<&str>,
+        workflow_ref: Option<&str>,
+        workflow_trigger: Option<&str>,
         annotations: Option<&BTreeMap<String, String>>,
     ) -> Self {
         let annotation_verifier = annotations.map(|a| AnnotationVerifier {
@@ -198,6 +445,8 @@ impl GitHubVerifier {
         Self {
             owner: owner.to_string(),
             repo: repo.map(|r| r.to_owned()),
+            workflow_ref: workflow_ref.map(|r| r.to_owned()),
+            workflow_trigger: workflow_trigger.map(|t| t.to_owned()),
             annotation_verifier,
         }
     }
@@ -268,6 +517,28 @@ impl VerificationConstraint for GitHubVerifier {
             }
         }
 
+        if let Some(workflow_ref) = &self.workflow_ref {
+            if certificate_signature.github_workflow_ref.as_ref() != Some(workflow_ref) {
+                debug!(
+                    expected_value = ?workflow_ref,
+                    current_value = ?certificate_signature.github_workflow_ref,
+                    "workflow ref not satisfied"
+                );
+                return Ok(false);
+            }
+        }
+
+        if let Some(workflow_trigger) = &self.workflow_trigger {
+            if certificate_signature.github_workflow_trigger.as_ref() != Some(workflow_trigger) {
+                debug!(
+                    expected_value = ?workflow_trigger,
+                    current_value = ?certificate_signature.github_workflow_trigger,
+                    "workflow trigger not satisfied"
+                );
+                return Ok(false);
+            }
+        }
+
         let outcome = if let Some(av) = &self.annotation_verifier {
             av.verify(sl)?
         } else {
@@ -317,10 +588,12 @@ impl TryFrom<&str> for GitHubRepo {
 mod tests {
     use super::*;
 
+    use base64::{engine::general_purpose::STANDARD, Engine as _};
     use rstest::rstest;
     use sigstore::{
         cosign::payload::simple_signing::SimpleSigning,
         cosign::signature_layers::CertificateSignature,
+        crypto::{signing_key::SigStoreSigner, SigningScheme},
     };
 
     fn build_signature_layers_pub_key<'a>() -> (&'a str, SignatureLayer) {
@@ -640,7 +913,7 @@ kvUsh4eKpd1lwkDAzfFDs7yXEExsEkPPuiQJBelDT68n7PDIWB/QEY7mrA==
     fn test_github_verifier_reject_because_no_signature() {
         let (_, sl) = build_signature_layers_pub_key();
 
-        let vc = GitHubVerifier::new("kubewarden", Some("policy"), None);
+        let vc = GitHubVerifier::new("kubewarden", Some("policy"), None, None, None);
         let is_verified = vc.verify(&sl).expect("Should have been successful");
         assert!(!is_verified);
     }
@@ -660,12 +933,18 @@ kvUsh4eKpd1lwkDAzfFDs7yXEExsEkPPuiQJBelDT68n7PDIWB/QEY7mrA==
         );
 
         // check specifically this owner/repo
-        let vc = GitHubVerifier::new("octocat", Some("policy-secure-pod-images"), None);
+        let vc = GitHubVerifier::new(
+            "octocat",
+            Some("policy-secure-pod-images"),
+            None,
+            None,
+            None,
+        );
         let is_verified = vc.verify(&sl).expect("Should have been successful");
         assert!(is_verified);
 
         // anything from this owner is fine
-        let vc = GitHubVerifier::new("octocat", None, None);
+        let vc = GitHubVerifier::new("octocat", None, None, None, None);
         let is_verified = vc.verify(&sl).expect("Should have been successful");
         assert!(is_verified);
     }
@@ -688,12 +967,12 @@ kvUsh4eKpd1lwkDAzfFDs7yXEExsEkPPuiQJBelDT68n7PDIWB/QEY7mrA==
         );
 
         // check specifically this owner/repo
-        let vc = GitHubVerifier::new("kubewarden", Some("psp-one"), None);
+        let vc = GitHubVerifier::new("kubewarden", Some("psp-one"), None, None, None);
         let is_verified = vc.verify(&sl).expect("Should have been successful");
         assert!(!is_verified);
 
         // anything from this owner is fine
-        let vc = GitHubVerifier::new("kubewarden-tests", None, None);
+        let vc = GitHubVerifier::new("kubewarden-tests", None, None, None, None);
         let is_verified = vc.verify(&sl).expect("Should have been successful");
         assert!(!is_verified);
     }
@@ -712,11 +991,45 @@ kvUsh4eKpd1lwkDAzfFDs7yXEExsEkPPuiQJBelDT68n7PDIWB/QEY7mrA==
             Some(github_workflow_repository.to_string()),
         );
 
-        let vc = GitHubVerifier::new("kubewarden", None, None);
+        let vc = GitHubVerifier::new("kubewarden", None, None, None, None);
         let is_verified = vc.verify(&sl).expect("Should have been successful");
         assert!(!is_verified);
     }
 
+    #[rstest]
+    #[case::no_workflow_constraints(None, None, true)]
+    #[case::matching_ref(Some("refs/tags/v1.0.0"), None, true)]
+    #[case::matching_ref_and_trigger(Some("refs/tags/v1.0.0"), Some("push"), true)]
+    #[case::different_ref(Some("refs/heads/main"), None, false)]
+    #[case::different_trigger(None, Some("workflow_dispatch"), false)]
+    fn test_github_verifier_workflow(
+        #[case] workflow_ref: Option<&str>,
+        #[case] workflow_trigger: Option<&str>,
+        #[case] expected: bool,
+    ) {
+        let issuer = "https://token.actions.githubusercontent.com";
+        let subject_str = "https://github.com/kubewarden/policy-secure-pod-images/.github/workflows/release.yml@refs/tags/v1.0.0";
+
+        let mut sl = build_signature_layers_keyless(
+            Some(issuer.to_string()),
+            CertificateSubject::Uri(subject_str.to_string()),
+            Some("kubewarden/policy-secure-pod-images".to_string()),
+        );
+        let certificate_signature = sl.certificate_signature.as_mut().unwrap();
+        certificate_signature.github_workflow_ref = Some("refs/tags/v1.0.0".to_string());
+        certificate_signature.github_workflow_trigger = Some("push".to_string());
+
+        let vc = GitHubVerifier::new(
+            "kubewarden",
+            Some("policy-secure-pod-images"),
+            workflow_ref,
+            workflow_trigger,
+            None,
+        );
+        let is_verified = vc.verify(&sl).expect("Should have been successful");
+        assert_eq!(is_verified, expected);
+    }
+
     #[test]
     fn test_github_verifier_reject_because_certificate_subject_does_not_have_url() {
         // it must have URL, as this is a GH Actions signature
@@ -728,8 +1041,164 @@ kvUsh4eKpd1lwkDAzfFDs7yXEExsEkPPuiQJBelDT68n7PDIWB/QEY7mrA==
         let sl =
             build_signature_layers_keyless(Some(issuer.to_string()), certificate_subject, None);
 
-        let vc = GitHubVerifier::new("kubewarden", None, None);
+        let vc = GitHubVerifier::new("kubewarden", None, None, None, None);
         let is_verified = vc.verify(&sl).expect("Should have been successful");
         assert!(!is_verified);
     }
+
+    const SIGNER_EMAIL: &str = "release@example.com";
+    const SIGNER_URI: &str =
+        "https://github.com/org-evil/policy/.github/workflows/release.yml@refs/heads/main";
+
+    fn certificate_authority(name: &str) -> (String, rcgen::Issuer<'static, rcgen::KeyPair>) {
+        let key = rcgen::KeyPair::generate().unwrap();
+        let mut params = rcgen::CertificateParams::new(Vec::<String>::new()).unwrap();
+        params.is_ca = rcgen::IsCa::Ca(rcgen::BasicConstraints::Unconstrained);
+        params
+            .distinguished_name
+            .push(rcgen::DnType::CommonName, name);
+        let cert = params.self_signed(&key).unwrap();
+
+        (cert.pem(), rcgen::Issuer::new(params, key))
+    }
+
+    /// Issue a signing certificate, returning it together with the signer owning its key
+    fn signing_certificate(
+        ca: &rcgen::Issuer<'static, rcgen::KeyPair>,
+        code_signing: bool,
+    ) -> (String, SigStoreSigner) {
+        let signer = SigningScheme::ECDSA_P256_SHA256_ASN1
+            .create_signer()
+            .unwrap();
+        let private_key = signer
+            .to_sigstore_keypair()
+            .unwrap()
+            .private_key_to_pem()
+            .unwrap();
+        let key = rcgen::KeyPair::from_pem(&private_key).unwrap();
+
+        let mut params = rcgen::CertificateParams::new(Vec::<String>::new()).unwrap();
+        params.subject_alt_names = vec![
+            rcgen::SanType::Rfc822Name(SIGNER_EMAIL.try_into().unwrap()),
+            rcgen::SanType::URI(SIGNER_URI.try_into().unwrap()),
+        ];
+        if code_signing {
+            params.extended_key_usages = vec![rcgen::ExtendedKeyUsagePurpose::CodeSigning];
+        }
+        let cert = params.signed_by(&key, ca).unwrap();
+
+        (cert.pem(), signer)
+    }
+
+    fn build_signature_layer_signed_by(signer: &SigStoreSigner) -> SignatureLayer {
+        let raw_data = r#"{"critical":{"identity":{"docker-reference":"registry.example.com/policy"},"image":{"docker-manifest-digest":"sha256:5f481572d088dc4023afb35fced9530ced3d9b03bf7299c6f492163cb9f0452e"},"type":"cosign container image signature"},"optional":null}"#;
+        let raw_data = raw_data.as_bytes().to_vec();
+        let signature = STANDARD.encode(signer.sign(&raw_data).unwrap());
+
+        SignatureLayer {
+            simple_signing: serde_json::from_slice(&raw_data)
+                .expect("Cannot deserialize SimpleSigning"),
+            oci_digest: "not relevant".to_string(),
+            certificate_signature: None,
+            bundle: None,
+            signature: Some(signature),
+            raw_data,
+        }
+    }
+
+    #[test]
+    fn test_certificate_verifier() {
+        let (ca_pem, ca) = certificate_authority("Example CA");
+        let (cert_pem, signer) = signing_certificate(&ca, true);
+        let chain = vec![ca_pem];
+        let sl = build_signature_layer_signed_by(&signer);
+
+        let vc = CertificateVerifier::new(
+            &cert_pem,
+            Some(chain.as_slice()),
+            Some(&Subject::Equal(SIGNER_EMAIL.to_string())),
+            None,
+            false,
+            None,
+        )
+        .expect("Cannot create verification constraint");
+        assert!(vc.verify(&sl).unwrap());
+
+        // the signature has been made by another key
+        let (_, other_signer) = signing_certificate(&ca, true);
+        assert!(!vc
+            .verify(&build_signature_layer_signed_by(&other_signer))
+            .unwrap());
+
+        let annotations = BTreeMap::from([("env".to_string(), "prod".to_string())]);
+        let vc = CertificateVerifier::new(
+            &cert_pem,
+            Some(chain.as_slice()),
+            None,
+            None,
+            false,
+            Some(&annotations),
+        )
+        .expect("Cannot create verification constraint");
+        assert!(!vc.verify(&sl).unwrap());
+
+        let vc =
+            CertificateVerifier::new(&cert_pem, Some(chain.as_slice()), None, None, true, None)
+                .expect("Cannot create verification constraint");
+        assert!(!vc.verify(&sl).unwrap(), "the Rekor bundle is missing");
+    }
+
+    #[test]
+    fn test_certificate_verifier_rejects_certificate_not_issued_by_the_chain() {
+        let (_, ca) = certificate_authority("Example CA");
+        let (other_ca_pem, _) = certificate_authority("Other CA");
+        let (cert_pem, _) = signing_certificate(&ca, true);
+
+        assert!(CertificateVerifier::new(
+            &cert_pem,
+            Some(&[other_ca_pem][..]),
+            None,
+            None,
+            false,
+            None
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn test_certificate_verifier_rejects_certificate_without_code_signing_usage() {
+        let (ca_pem, ca) = certificate_authority("Example CA");
+        let (cert_pem, _) = signing_certificate(&ca, false);
+
+        assert!(
+            CertificateVerifier::new(&cert_pem, Some(&[ca_pem][..]), None, None, false, None)
+                .is_err()
+        );
+    }
+
+    #[rstest]
+    #[case::subject_equal(Some(Subject::Equal(SIGNER_EMAIL.to_string())), None, true)]
+    #[case::subject_not_equal(Some(Subject::Equal("someone@example.com".to_string())), None, false)]
+    #[case::subject_url_prefix(Some(Subject::UrlPrefix(url::Url::parse("https://github.com/kubewarden/").unwrap())), None, false)]
+    #[case::subject_url_prefix_match(Some(Subject::UrlPrefix(url::Url::parse("https://github.com/org-evil").unwrap())), None, true)]
+    #[case::subject_url_prefix_partial_path(Some(Subject::UrlPrefix(url::Url::parse("https://github.com/org").unwrap())), None, false)]
+    #[case::missing_issuer(None, Some("https://accounts.example.com"), false)]
+    fn test_certificate_verifier_identity(
+        #[case] subject: Option<Subject>,
+        #[case] issuer: Option<&str>,
+        #[case] valid: bool,
+    ) {
+        let (ca_pem, ca) = certificate_authority("Example CA");
+        let (cert_pem, _) = signing_certificate(&ca, true);
+
+        let vc = CertificateVerifier::new(
+            &cert_pem,
+            Some(&[ca_pem][..]),
+            subject.as_ref(),
+            issuer,
+            false,
+            None,
+        );
+        assert_eq!(vc.is_ok(), valid, "{:?}", vc.err());
+    }
 }
@@ -430,6 +430,67 @@ mod e2e {
             .await;
     }
 
+    #[cfg(not(target_os = "windows"))]
+    #[tokio::test]
+    async fn test_pull_from_registry_mirror() {
+        let registry_details = setup_registry_image(RegistryConfiguration {
+            enable_auth: false,
+            enable_tls: false,
+        });
+        let container = registry_details
+            .container_request
+            .start()
+            .await
+            .expect("failed to start registry container");
+
+        let mirror_fqdn = format!(
+            "localhost:{}",
+            container.get_host_port_ipv4(REGISTRY_PORT).await.unwrap()
+        );
+        // nothing listens on this port, the next mirror has to be used
+        let unreachable_mirror = "localhost:1".to_owned();
+        let sources = Sources {
+            insecure_sources: HashSet::from([mirror_fqdn.clone(), unreachable_mirror.clone()]),
+            mirrors: HashMap::from([(
+                "ghcr.io".to_owned(),
+                vec![unreachable_mirror, mirror_fqdn.clone()],
+            )]),
+            ..Default::default()
+        };
+
+        // the policy exists only on the mirror
+        let policy = b"\x00asm\x01\x00\x00\x00";
+        let registry = Registry::new();
+        let immutable_ref = registry
+            .push(
+                policy,
+                &format!("registry://{mirror_fqdn}/kubewarden/tests/mirrored-policy:v1"),
+                Some(&sources),
+                None,
+            )
+            .await
+            .expect("cannot push policy to the mirror");
+        let (_, pushed_digest) = immutable_ref.split_once('@').unwrap();
+
+        let url = "registry://ghcr.io/kubewarden/tests/mirrored-policy:v1";
+        let digest = registry
+            .manifest_digest(url, Some(&sources))
+            .await
+            .expect("cannot fetch the manifest digest from the mirror");
+        assert_eq!(digest, pushed_digest);
+
+        let destination = TempDir::new().unwrap();
+        let pulled = policy_fetcher::fetch_policy(
+            url,
+            policy_fetcher::PullDestination::LocalFile(destination.path().to_path_buf()),
+            Some(&sources),
+        )
+        .await
+        .expect("cannot pull policy from the mirror");
+        assert_eq!(pulled.uri, url);
+        assert_eq!(fs::read(pulled.local_path).unwrap(), policy);
+    }
+
     async fn push_to_registry_and_perform_common_operations(
         policy: &[u8],
         registry: &Registry,
@@ -1,7 +1,8 @@
 use std::path::Path;
 
 use policy_fetcher::policy::Policy;
-use policy_fetcher::store::{path, Store};
+use policy_fetcher::store::provenance::{PolicyProvenance, RecordedBundles, VerificationStatus};
+use policy_fetcher::store::{path, Store, StoreFilter, QUARANTINE_DIR};
 use tempfile::tempdir;
 
 #[test]
@@ -159,6 +160,241 @@ fn test_get_policy_by_sha_prefix_duplicate() {
     assert!(result.is_err());
 }
 
+#[test]
+fn test_list_entries() {
+    let store_root = tempdir().unwrap();
+
+    let https_policy = Policy {
+        uri: "https://internal.host.company/some/path/to/1.0.0/wasm-module.wasm".to_owned(),
+        local_path: store_root.path().join(path::encode_path(
+            "https/internal.host.company/some/path/to/1.0.0/wasm-module.wasm",
+        )),
+    };
+    let registry_policy = Policy {
+        uri: "registry://ghcr.io/some/path/to/wasm-module.wasm:1.0.0".to_owned(),
+        local_path: store_root.path().join(path::encode_path(
+            "registry/ghcr.io/some/path/to/wasm-module.wasm:1.0.0",
+        )),
+    };
+    setup_store(&[https_policy.clone(), registry_policy.clone()]).unwrap();
+
+    let store = Store::new(store_root.path());
+    let sha256 = registry_policy.digest().unwrap();
+    store
+        .save_provenance(
+            &registry_policy,
+            &PolicyProvenance::new(&registry_policy.uri, &sha256),
+        )
+        .unwrap();
+
+    // the provenance records must not be listed as policies
+    assert_eq!(store.list().unwrap().len(), 2);
+
+    let entries = store.list_entries(&StoreFilter::default()).unwrap();
+    assert_eq!(entries.len(), 2);
+    assert_eq!(entries[0].uri, https_policy.uri);
+    assert!(entries[0].provenance.is_none());
+    assert_eq!(entries[1].policy(), registry_policy);
+    assert_eq!(entries[1].sha256, sha256);
+    assert_eq!(
+        entries[1].size,
+        std::fs::metadata(&registry_policy.local_path)
+            .unwrap()
+            .len()
+    );
+    let provenance = entries[1].provenance.as_ref().unwrap();
+    assert_eq!(provenance.source, registry_policy.uri);
+    assert_eq!(provenance.verification, VerificationStatus::NotVerified);
+    assert!(!entries[1].is_modified());
+
+    let entries = store
+        .list_entries(&StoreFilter {
+            scheme: Some("registry".to_owned()),
+            ..Default::default()
+        })
+        .unwrap();
+    assert_eq!(entries.len(), 1);
+    assert_eq!(entries[0].uri, registry_policy.uri);
+
+    let entries = store
+        .list_entries(&StoreFilter {
+            host: Some("internal.host.company".to_owned()),
+            uri_contains: Some("1.0.0".to_owned()),
+            ..Default::default()
+        })
+        .unwrap();
+    assert_eq!(entries.len(), 1);
+    assert_eq!(entries[0].uri, https_policy.uri);
+}
+
+#[test]
+fn test_record_verification() {
+    let store_root = tempdir().unwrap();
+
+    let policy = Policy {
+        uri: "registry://ghcr.io/some/path/to/wasm-module.wasm:1.0.0".to_owned(),
+        local_path: store_root.path().join(path::encode_path(
+            "registry/ghcr.io/some/path/to/wasm-module.wasm:1.0.0",
+        )),
+    };
+    setup_store(&[policy.clone()]).unwrap();
+
+    let store = Store::new(store_root.path());
+    let verified_filter = StoreFilter {
+        verification: Some(VerificationStatus::Verified),
+        ..Default::default()
+    };
+    assert!(store.list_entries(&verified_filter).unwrap().is_empty());
+
+    store
+        .record_verification(
+            &policy,
+            "sha256:72b4569c3daee67abeaa64192fb53895d0edb2d44fa6e1d9d4c5d3f8ece09f6e",
+        )
+        .unwrap();
+
+    let entries = store.list_entries(&verified_filter).unwrap();
+    assert_eq!(entries.len(), 1);
+    let provenance = entries[0].provenance.as_ref().unwrap();
+    assert_eq!(provenance.verification, VerificationStatus::Verified);
+    assert_eq!(
+        provenance.manifest_digest.as_deref(),
+        Some("sha256:72b4569c3daee67abeaa64192fb53895d0edb2d44fa6e1d9d4c5d3f8ece09f6e")
+    );
+
+    store.remove_provenance(&policy).unwrap();
+    assert!(store.provenance(&policy).unwrap().is_none());
+}
+
+#[test]
+fn test_record_offline_verification() {
+    let store_root = tempdir().unwrap();
+
+    let policy = Policy {
+        uri: "registry://ghcr.io/some/path/to/wasm-module.wasm:1.0.0".to_owned(),
+        local_path: store_root.path().join(path::encode_path(
+            "registry/ghcr.io/some/path/to/wasm-module.wasm:1.0.0",
+        )),
+    };
+    setup_store(&[policy.clone()]).unwrap();
+
+    let store = Store::new(store_root.path());
+    assert!(store.bundles(&policy).unwrap().is_none());
+
+    let manifest_digest = "sha256:72b4569c3daee67abeaa64192fb53895d0edb2d44fa6e1d9d4c5d3f8ece09f6e";
+    let bundles = RecordedBundles::new(manifest_digest, b"manifest", &[b"bundle".to_vec()]);
+    store.save_bundles(&policy, &bundles).unwrap();
+    assert_eq!(store.bundles(&policy).unwrap(), Some(bundles));
+
+    store
+        .record_offline_verification(&policy, manifest_digest)
+        .unwrap();
+    let provenance = store.provenance(&policy).unwrap().unwrap();
+    assert_eq!(
+        provenance.verification,
+        VerificationStatus::VerifiedOfflineBundle
+    );
+    assert_eq!(provenance.manifest_digest.as_deref(), Some(manifest_digest));
+
+    // the recorded bundles are not mistaken for policies
+    assert_eq!(store.list().unwrap(), vec![policy.clone()]);
+
+    store.remove_provenance(&policy).unwrap();
+    assert!(store.provenance(&policy).unwrap().is_none());
+    assert!(store.bundles(&policy).unwrap().is_none());
+}
+
+#[test]
+fn test_quarantine() {
+    let store_root = tempdir().unwrap();
+
+    let policy = Policy {
+        uri: "registry://ghcr.io/some/path/to/wasm-module.wasm:1.0.0".to_owned(),
+        local_path: store_root.path().join(path::encode_path(
+            "registry/ghcr.io/some/path/to/wasm-module.wasm:1.0.0",
+        )),
+    };
+    setup_store(&[policy.clone()]).unwrap();
+
+    let store = Store::new(store_root.path());
+    store
+        .record_verification(
+            &policy,
+            "sha256:72b4569c3daee67abeaa64192fb53895d0edb2d44fa6e1d9d4c5d3f8ece09f6e",
+        )
+        .unwrap();
+
+    let quarantine_path = store.quarantine(&policy).unwrap();
+    assert!(quarantine_path.starts_with(store_root.path().join(QUARANTINE_DIR)));
+    assert!(quarantine_path.exists());
+    assert!(!policy.local_path.exists());
+    assert!(store.provenance(&policy).unwrap().is_none());
+
+    // quarantined policies are not part of the store anymore
+    assert!(store.list().unwrap().is_empty());
+    assert!(store.get_policy_by_uri(&policy.uri).unwrap().is_none());
+}
+
+#[test]
+fn test_usage_journal() {
+    let store_root = tempdir().unwrap();
+
+    let used_policy = Policy {
+        uri: "registry://ghcr.io/some/path/to/used.wasm:1.0.0".to_owned(),
+        local_path: store_root.path().join(path::encode_path(
+            "registry/ghcr.io/some/path/to/used.wasm:1.0.0",
+        )),
+    };
+    let unused_policy = Policy {
+        uri: "registry://ghcr.io/some/path/to/unused.wasm:1.0.0".to_owned(),
+        local_path: store_root.path().join(path::encode_path(
+            "registry/ghcr.io/some/path/to/unused.wasm:1.0.0",
+        )),
+    };
+    let removed_policy = Policy {
+        uri: "registry://ghcr.io/some/path/to/removed.wasm:1.0.0".to_owned(),
+        local_path: store_root.path().join(path::encode_path(
+            "registry/ghcr.io/some/path/to/removed.wasm:1.0.0",
+        )),
+    };
+    setup_store(&[
+        used_policy.clone(),
+        unused_policy.clone(),
+        removed_policy.clone(),
+    ])
+    .unwrap();
+
+    let store = Store::new(store_root.path());
+    assert!(store.last_used().unwrap().is_empty());
+
+    store.record_usage(&used_policy).unwrap();
+    store.record_usage(&removed_policy).unwrap();
+    store.record_usage(&used_policy).unwrap();
+
+    // the usage journal must not be listed as a policy
+    assert_eq!(store.list().unwrap().len(), 3);
+
+    let last_used = store.last_used().unwrap();
+    assert_eq!(last_used.len(), 2);
+    let entries = store.list_entries(&StoreFilter::default()).unwrap();
+    let entry = |uri: &str| entries.iter().find(|entry| entry.uri == uri).unwrap();
+    assert_eq!(
+        entry(&used_policy.uri).last_used_at,
+        last_used.get(&used_policy.uri).copied()
+    );
+    assert!(entry(&unused_policy.uri).last_used_at.is_none());
+
+    std::fs::remove_file(&removed_policy.local_path).unwrap();
+    store.compact_usage_journal().unwrap();
+
+    let compacted = store.last_used().unwrap();
+    assert_eq!(compacted.len(), 1);
+    assert_eq!(
+        compacted.get(&used_policy.uri),
+        last_used.get(&used_policy.uri)
+    );
+}
+
 fn setup_store(policies: &[Policy]) -> std::result::Result<(), std::io::Error> {
     for policy in policies {
         std::fs::create_dir_all(policy.local_path.parent().unwrap())?;
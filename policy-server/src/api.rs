@@ -1,6 +1,11 @@
 pub mod admission_review;
 mod api_error;
+pub(crate) mod body_limit;
+mod cloud_event;
+pub(crate) mod dispatcher;
 pub(crate) mod handlers;
+pub(crate) mod json_body;
+pub mod protobuf;
 mod raw_review;
 mod service;
 pub(crate) mod state;
@@ -1,6 +1,21 @@
+use std::sync::Arc;
+
+use axum::{
+    extract::{FromRequest, Request},
+    http::{header, HeaderMap, StatusCode},
+    response::{IntoResponse, Response},
+    Json,
+};
 use policy_evaluator::admission_request::AdmissionRequest;
 use policy_evaluator::admission_response::AdmissionResponse;
 
+use crate::api::{
+    api_error::ApiError,
+    json_body::{NestingScanner, StreamingJson},
+    protobuf::{self, PROTOBUF_CONTENT_TYPE},
+    state::ApiServerState,
+};
+
 #[derive(Clone, Debug, serde::Serialize, serde::Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub struct AdmissionReviewRequest {
@@ -34,3 +49,211 @@ impl AdmissionReviewResponse {
         }
     }
 }
+
+/// The encodings of the AdmissionReview objects exchanged with the Kubernetes API server
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum ReviewEncoding {
+    Json,
+    Protobuf,
+}
+
+impl ReviewEncoding {
+    /// The encoding of the request body, given by its `Content-Type`
+    pub(crate) fn of_request(headers: &HeaderMap) -> Self {
+        match media_types(headers, header::CONTENT_TYPE).next() {
+            Some(media_type) if media_type == PROTOBUF_CONTENT_TYPE => ReviewEncoding::Protobuf,
+            _ => ReviewEncoding::Json,
+        }
+    }
+
+    /// The encoding of the response body: the first supported media type listed by the
+    /// `Accept` header. The response is encoded like the request when the client
+    /// accepts any media type
+    pub(crate) fn of_response(headers: &HeaderMap) -> Self {
+        let request_encoding = ReviewEncoding::of_request(headers);
+        media_types(headers, header::ACCEPT)
+            .find_map(|media_type| match media_type.as_str() {
+                PROTOBUF_CONTENT_TYPE => Some(ReviewEncoding::Protobuf),
+                "application/json" => Some(ReviewEncoding::Json),
+                "*/*" | "application/*" => Some(request_encoding),
+                _ => None,
+            })
+            .unwrap_or(request_encoding)
+    }
+}
+
+/// The media types listed by the header, without their parameters
+fn media_types(headers: &HeaderMap, name: header::HeaderName) -> impl Iterator<Item = String> + '_ {
+    headers
+        .get_all(name)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .filter_map(|media_type| media_type.split(';').next())
+        .map(|media_type| media_type.trim().to_ascii_lowercase())
+        .filter(|media_type| !media_type.is_empty())
+}
+
+/// Extractor of the AdmissionReview requests, which are encoded either as JSON or with
+/// protobuf, depending on their `Content-Type`
+pub(crate) struct NegotiatedAdmissionReview(pub(crate) AdmissionReviewRequest);
+
+impl FromRequest<Arc<ApiServerState>> for NegotiatedAdmissionReview {
+    type Rejection = ApiError;
+
+    async fn from_request(
+        request: Request,
+        state: &Arc<ApiServerState>,
+    ) -> Result<Self, Self::Rejection> {
+        if ReviewEncoding::of_request(request.headers()) == ReviewEncoding::Json {
+            let StreamingJson(admission_review) =
+                StreamingJson::from_request(request, state).await?;
+            return Ok(NegotiatedAdmissionReview(admission_review));
+        }
+
+        let max_size = state.max_request_body_size;
+        let body = axum::body::to_bytes(request.into_body(), max_size)
+            .await
+            .map_err(|_| ApiError {
+                status: StatusCode::PAYLOAD_TOO_LARGE,
+                message: format!(
+                    "request body exceeds the maximum allowed size of {max_size} bytes"
+                ),
+            })?;
+
+        let bad_request = |e: anyhow::Error| ApiError {
+            status: StatusCode::BAD_REQUEST,
+            message: format!("Failed to decode the protobuf body: {e}"),
+        };
+        let review = protobuf::decode_admission_review(&body).map_err(bad_request)?;
+        check_embedded_objects(&review, state.max_request_body_depth)?;
+
+        AdmissionReviewRequest::try_from(review)
+            .map(NegotiatedAdmissionReview)
+            .map_err(bad_request)
+    }
+}
+
+/// The objects embedded inside of a protobuf request are JSON documents: they are
+/// subject to the same nesting limit of the JSON requests. The ones encoded with
+/// protobuf cannot be decoded, hence their media type is not supported
+fn check_embedded_objects(
+    review: &protobuf::AdmissionReview,
+    max_depth: usize,
+) -> Result<(), ApiError> {
+    for (field, object) in protobuf::embedded_objects(review) {
+        if protobuf::is_protobuf_encoded(object) {
+            return Err(ApiError {
+                status: StatusCode::UNSUPPORTED_MEDIA_TYPE,
+                message: format!(
+                    "the {field} is encoded with protobuf, only the objects encoded as JSON are supported"
+                ),
+            });
+        }
+        NestingScanner::new(max_depth)
+            .scan(object)
+            .map_err(|max_depth| ApiError {
+                status: StatusCode::BAD_REQUEST,
+                message: format!(
+                    "the {field} exceeds the maximum allowed nesting depth of {max_depth}"
+                ),
+            })?;
+    }
+
+    Ok(())
+}
+
+/// An AdmissionReview response, encoded as negotiated with the client
+pub(crate) struct NegotiatedAdmissionReviewResponse {
+    pub(crate) review: AdmissionReviewResponse,
+    pub(crate) encoding: ReviewEncoding,
+}
+
+impl IntoResponse for NegotiatedAdmissionReviewResponse {
+    fn into_response(self) -> Response {
+        match self.encoding {
+            ReviewEncoding::Json => Json(self.review).into_response(),
+            ReviewEncoding::Protobuf => match protobuf::AdmissionReview::try_from(&self.review) {
+                Ok(review) => (
+                    [(header::CONTENT_TYPE, PROTOBUF_CONTENT_TYPE)],
+                    protobuf::encode_admission_review(&review),
+                )
+                    .into_response(),
+                Err(e) => ApiError {
+                    status: StatusCode::INTERNAL_SERVER_ERROR,
+                    message: format!("Failed to encode the response with protobuf: {e}"),
+                }
+                .into_response(),
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::http::HeaderValue;
+    use rstest::rstest;
+
+    #[rstest]
+    #[case::json(Some("application/json"), None, ReviewEncoding::Json)]
+    #[case::protobuf(Some(PROTOBUF_CONTENT_TYPE), None, ReviewEncoding::Protobuf)]
+    #[case::no_headers(None, None, ReviewEncoding::Json)]
+    #[case::protobuf_accept_json(
+        Some(PROTOBUF_CONTENT_TYPE),
+        Some("application/json"),
+        ReviewEncoding::Json
+    )]
+    #[case::json_accept_protobuf(
+        Some("application/json"),
+        Some("application/vnd.kubernetes.protobuf, application/json"),
+        ReviewEncoding::Protobuf
+    )]
+    #[case::accept_order(
+        Some(PROTOBUF_CONTENT_TYPE),
+        Some("application/json;q=0.9, application/vnd.kubernetes.protobuf"),
+        ReviewEncoding::Json
+    )]
+    #[case::accept_any(Some(PROTOBUF_CONTENT_TYPE), Some("*/*"), ReviewEncoding::Protobuf)]
+    #[case::accept_unsupported(
+        Some(PROTOBUF_CONTENT_TYPE),
+        Some("text/plain"),
+        ReviewEncoding::Protobuf
+    )]
+    fn response_encoding(
+        #[case] content_type: Option<&'static str>,
+        #[case] accept: Option<&'static str>,
+        #[case] expected: ReviewEncoding,
+    ) {
+        let mut headers = HeaderMap::new();
+        if let Some(content_type) = content_type {
+            headers.insert(header::CONTENT_TYPE, HeaderValue::from_static(content_type));
+        }
+        if let Some(accept) = accept {
+            headers.insert(header::ACCEPT, HeaderValue::from_static(accept));
+        }
+
+        assert_eq!(ReviewEncoding::of_response(&headers), expected);
+    }
+
+    #[rstest]
+    #[case::json(br#"{"metadata": {"labels": {}}}"#.to_vec(), None)]
+    #[case::too_deep(br#"{"a": {"b": {"c": [1]}}}"#.to_vec(), Some(StatusCode::BAD_REQUEST))]
+    #[case::protobuf(
+        b"k8s\x00\x0a\x02\x0a\x00".to_vec(),
+        Some(StatusCode::UNSUPPORTED_MEDIA_TYPE)
+    )]
+    fn embedded_objects(#[case] object: Vec<u8>, #[case] expected_status: Option<StatusCode>) {
+        let review = protobuf::AdmissionReview {
+            request: Some(protobuf::AdmissionRequest {
+                old_object: Some(protobuf::RawExtension { raw: Some(object) }),
+                ..Default::default()
+            }),
+            response: None,
+        };
+
+        let result = check_embedded_objects(&review, 3);
+
+        assert_eq!(result.err().map(|e| e.status), expected_status);
+    }
+}
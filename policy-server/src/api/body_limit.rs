@@ -0,0 +1,45 @@
+use axum::{
+    extract::{MatchedPath, Request, State},
+    http::StatusCode,
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use tracing::warn;
+
+use crate::{
+    api::api_error::ApiError,
+    metrics::{self, OversizedRequest},
+};
+
+/// Replace the response produced when the body of a request exceeds the maximum
+/// allowed size with a structured JSON error, and keep track of these requests.
+///
+/// The limit is enforced by the `DefaultBodyLimit` layer when the body is extracted,
+/// this middleware deals only with the outcome.
+pub(crate) async fn handle_oversized_requests(
+    State(max_request_body_size): State<usize>,
+    matched_path: MatchedPath,
+    request: Request,
+    next: Next,
+) -> Response {
+    let response = next.run(request).await;
+    if response.status() != StatusCode::PAYLOAD_TOO_LARGE {
+        return response;
+    }
+
+    let route = matched_path.as_str().to_owned();
+    warn!(
+        route = route.as_str(),
+        max_request_body_size, "request body exceeds the maximum allowed size"
+    );
+    metrics::add_oversized_request(&OversizedRequest { route });
+
+    ApiError {
+        status: StatusCode::PAYLOAD_TOO_LARGE,
+        message: format!(
+            "request body exceeds the maximum allowed size of {} bytes",
+            max_request_body_size
+        ),
+    }
+    .into_response()
+}
@@ -0,0 +1,251 @@
+use axum::http::{header, HeaderMap, StatusCode};
+use policy_evaluator::{admission_response::AdmissionResponse, cloud_event::CloudEvent};
+use sha2::{Digest, Sha256};
+
+use crate::api::api_error::ApiError;
+
+/// The media type of the CloudEvents delivered using the structured content mode
+pub(crate) const CLOUDEVENTS_JSON: &str = "application/cloudevents+json";
+
+/// The type of the event sent back when the policy accepts the event
+pub(crate) const ALLOWED_EVENT_TYPE: &str = "io.kubewarden.policy.allowed";
+/// The type of the event sent back when the policy rejects the event
+pub(crate) const DENIED_EVENT_TYPE: &str = "io.kubewarden.policy.denied";
+
+/// The prefix of the HTTP headers holding the attributes of the events delivered
+/// using the binary content mode
+const ATTRIBUTE_HEADER_PREFIX: &str = "ce-";
+
+/// Decode a CloudEvent delivered over HTTP, using either the structured or the
+/// binary content mode
+pub(crate) fn decode(headers: &HeaderMap, body: &[u8]) -> Result<CloudEvent, ApiError> {
+    let content_type = headers
+        .get(header::CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.parse::<mime::Mime>().ok());
+
+    let event = if content_type
+        .as_ref()
+        .is_some_and(|content_type| content_type.essence_str() == CLOUDEVENTS_JSON)
+    {
+        serde_json::from_slice::<CloudEvent>(body)
+            .map_err(|e| bad_request(format!("cannot decode CloudEvent: {e}")))?
+    } else {
+        decode_binary(headers, content_type.as_ref(), body)?
+    };
+
+    event.validate().map_err(bad_request)?;
+    Ok(event)
+}
+
+fn decode_binary(
+    headers: &HeaderMap,
+    content_type: Option<&mime::Mime>,
+    body: &[u8],
+) -> Result<CloudEvent, ApiError> {
+    let attribute = |name: &str| -> Result<Option<String>, ApiError> {
+        headers
+            .get(format!("{ATTRIBUTE_HEADER_PREFIX}{name}"))
+            .map(|value| {
+                value
+                    .to_str()
+                    .map(str::to_owned)
+                    .map_err(|_| bad_request(format!("invalid value of the ce-{name} header")))
+            })
+            .transpose()
+    };
+    let required_attribute = |name: &str| -> Result<String, ApiError> {
+        attribute(name)?.ok_or_else(|| bad_request(format!("missing ce-{name} header")))
+    };
+
+    let mut event = CloudEvent::new(
+        &required_attribute("id")?,
+        &required_attribute("source")?,
+        &required_attribute("type")?,
+    );
+    event.specversion = required_attribute("specversion")?;
+    event.subject = attribute("subject")?;
+    event.time = attribute("time")?;
+    event.dataschema = attribute("dataschema")?;
+    event.datacontenttype = content_type.map(|content_type| content_type.to_string());
+
+    for (name, value) in headers {
+        let Some(extension) = name.as_str().strip_prefix(ATTRIBUTE_HEADER_PREFIX) else {
+            continue;
+        };
+        if matches!(
+            extension,
+            "id" | "source" | "type" | "specversion" | "subject" | "time" | "dataschema"
+        ) {
+            continue;
+        }
+        let value = value
+            .to_str()
+            .map_err(|_| bad_request(format!("invalid value of the {name} header")))?;
+        event
+            .extensions
+            .insert(extension.to_owned(), serde_json::Value::from(value));
+    }
+
+    if !body.is_empty() {
+        let is_json = content_type.is_none_or(|content_type| {
+            content_type.subtype() == mime::JSON || content_type.suffix() == Some(mime::JSON)
+        });
+        event.data = Some(if is_json {
+            serde_json::from_slice(body)
+                .map_err(|e| bad_request(format!("cannot decode the CloudEvent data: {e}")))?
+        } else {
+            let data = std::str::from_utf8(body).map_err(|_| ApiError {
+                status: StatusCode::UNSUPPORTED_MEDIA_TYPE,
+                message: "only textual CloudEvent data is supported".to_owned(),
+            })?;
+            serde_json::Value::from(data)
+        });
+    }
+
+    Ok(event)
+}
+
+/// Build the event sent back to the producer of `request`, holding the outcome of
+/// the evaluation made by the given policy
+pub(crate) fn response_event(
+    request: &CloudEvent,
+    policy_id: &str,
+    response: &AdmissionResponse,
+) -> CloudEvent {
+    let ty = if response.allowed {
+        ALLOWED_EVENT_TYPE
+    } else {
+        DENIED_EVENT_TYPE
+    };
+    // the id must be unique for each source, while the ids of the requests are
+    // unique only for their own source
+    let id = format!(
+        "{:x}",
+        Sha256::digest(format!("{}\n{}", request.source, request.id))
+    );
+
+    let mut event = CloudEvent::new(&id, &format!("/validate_cloudevent/{policy_id}"), ty);
+    event.subject = Some(request.id.clone());
+    event.datacontenttype = Some(mime::APPLICATION_JSON.to_string());
+    event.data = Some(serde_json::to_value(response).expect("cannot serialize the response"));
+    event
+}
+
+fn bad_request(message: String) -> ApiError {
+    ApiError {
+        status: StatusCode::BAD_REQUEST,
+        message,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::http::HeaderValue;
+    use rstest::rstest;
+    use serde_json::json;
+
+    fn binary_headers(content_type: Option<&'static str>) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert("ce-specversion", HeaderValue::from_static("1.0"));
+        headers.insert("ce-id", HeaderValue::from_static("1234"));
+        headers.insert("ce-source", HeaderValue::from_static("/ci/pipeline"));
+        headers.insert("ce-type", HeaderValue::from_static("dev.example.build"));
+        headers.insert("ce-pipelinerun", HeaderValue::from_static("42"));
+        if let Some(content_type) = content_type {
+            headers.insert(header::CONTENT_TYPE, HeaderValue::from_static(content_type));
+        }
+        headers
+    }
+
+    #[test]
+    fn decode_structured_event() {
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            header::CONTENT_TYPE,
+            HeaderValue::from_static("application/cloudevents+json; charset=utf-8"),
+        );
+        let body = json!({
+            "specversion": "1.0",
+            "id": "1234",
+            "source": "/ci/pipeline",
+            "type": "dev.example.build",
+            "data": {"image": "ghcr.io/kubewarden/policy-server:latest"},
+        });
+
+        let event = decode(&headers, body.to_string().as_bytes()).unwrap();
+        assert_eq!(event.id, "1234");
+        assert_eq!(
+            event.data,
+            Some(json!({"image": "ghcr.io/kubewarden/policy-server:latest"}))
+        );
+    }
+
+    #[rstest]
+    #[case::json(Some("application/json"), r#"{"image": "nginx"}"#, json!({"image": "nginx"}))]
+    #[case::json_suffix(Some("application/vnd.example+json"), r#"{"image": "nginx"}"#, json!({"image": "nginx"}))]
+    #[case::no_content_type(None, r#"{"image": "nginx"}"#, json!({"image": "nginx"}))]
+    #[case::text(Some("text/plain"), "nginx", json!("nginx"))]
+    fn decode_binary_event(
+        #[case] content_type: Option<&'static str>,
+        #[case] body: &str,
+        #[case] expected_data: serde_json::Value,
+    ) {
+        let event = decode(&binary_headers(content_type), body.as_bytes()).unwrap();
+
+        assert_eq!(event.specversion, "1.0");
+        assert_eq!(event.source, "/ci/pipeline");
+        assert_eq!(event.ty, "dev.example.build");
+        assert_eq!(event.datacontenttype.as_deref(), content_type);
+        assert_eq!(event.extensions.get("pipelinerun"), Some(&json!("42")));
+        assert_eq!(event.data, Some(expected_data));
+    }
+
+    #[rstest]
+    #[case::missing_attribute("ce-type", None, StatusCode::BAD_REQUEST)]
+    #[case::unsupported_version("ce-specversion", Some("0.3"), StatusCode::BAD_REQUEST)]
+    fn decode_invalid_binary_event(
+        #[case] header_name: &'static str,
+        #[case] header_value: Option<&'static str>,
+        #[case] expected_status: StatusCode,
+    ) {
+        let mut headers = binary_headers(Some("application/json"));
+        match header_value {
+            Some(value) => headers.insert(header_name, HeaderValue::from_static(value)),
+            None => headers.remove(header_name),
+        };
+
+        let error = decode(&headers, b"{}").unwrap_err();
+        assert_eq!(error.status, expected_status);
+    }
+
+    #[test]
+    fn decode_binary_data() {
+        let error = decode(
+            &binary_headers(Some("application/octet-stream")),
+            &[0xff, 0xfe],
+        )
+        .unwrap_err();
+        assert_eq!(error.status, StatusCode::UNSUPPORTED_MEDIA_TYPE);
+    }
+
+    #[rstest]
+    #[case::allowed(true, ALLOWED_EVENT_TYPE)]
+    #[case::denied(false, DENIED_EVENT_TYPE)]
+    fn build_response_event(#[case] allowed: bool, #[case] expected_type: &str) {
+        let request = CloudEvent::new("1234", "/ci/pipeline", "dev.example.build");
+        let response = AdmissionResponse {
+            uid: "1234".to_owned(),
+            allowed,
+            ..Default::default()
+        };
+
+        let event = response_event(&request, "trusted-images", &response);
+        assert_eq!(event.ty, expected_type);
+        assert_eq!(event.source, "/validate_cloudevent/trusted-images");
+        assert_eq!(event.subject.as_deref(), Some("1234"));
+        assert_eq!(event.data.as_ref().unwrap()["allowed"], json!(allowed));
+        assert!(event.validate().is_ok());
+    }
+}
@@ -0,0 +1,445 @@
+use axum::http::HeaderMap;
+use policy_evaluator::policy_evaluator::ValidateRequest;
+use std::{
+    collections::VecDeque,
+    fmt,
+    str::FromStr,
+    sync::{Arc, Mutex},
+};
+use tokio::sync::oneshot;
+
+use crate::{api::service::RequestOrigin, config::PriorityConfig};
+
+/// Header that can be used to lower the priority class of a request, for example
+/// by the audit scanner. It cannot raise it, the elevation is decided only by the
+/// configuration of the server
+pub(crate) const PRIORITY_CLASS_HEADER: &str = "kubewarden-priority-class";
+
+/// The priority classes of the requests waiting for a worker. Requests of a class
+/// are dispatched before the ones of the classes that follow it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum PriorityClass {
+    /// Requests about the objects required to keep the cluster running, like the ones
+    /// inside of `kube-system` or the nodes
+    Critical,
+    /// Regular admission requests
+    Normal,
+    /// Bulk traffic, like the requests made by the audit scanner
+    Bulk,
+}
+
+impl PriorityClass {
+    const ALL: [PriorityClass; 3] = [
+        PriorityClass::Critical,
+        PriorityClass::Normal,
+        PriorityClass::Bulk,
+    ];
+
+    fn index(&self) -> usize {
+        match self {
+            PriorityClass::Critical => 0,
+            PriorityClass::Normal => 1,
+            PriorityClass::Bulk => 2,
+        }
+    }
+
+    /// Assign a priority class to a request. The class is determined by the origin
+    /// of the request and by the object being reviewed, the
+    /// `kubewarden-priority-class` header can only lower it: any client can set the
+    /// header, which would otherwise let its requests overtake the critical ones.
+    pub(crate) fn of_request(
+        config: &PriorityConfig,
+        headers: &HeaderMap,
+        request_origin: &RequestOrigin,
+        validate_request: &ValidateRequest,
+    ) -> Self {
+        let priority_class = Self::of_request_origin(config, request_origin, validate_request);
+        match headers
+            .get(PRIORITY_CLASS_HEADER)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.parse::<PriorityClass>().ok())
+        {
+            Some(requested) if requested.index() > priority_class.index() => requested,
+            _ => priority_class,
+        }
+    }
+
+    /// The priority class given by the configuration of the server
+    fn of_request_origin(
+        config: &PriorityConfig,
+        request_origin: &RequestOrigin,
+        validate_request: &ValidateRequest,
+    ) -> Self {
+        if let RequestOrigin::Audit = request_origin {
+            return PriorityClass::Bulk;
+        }
+
+        match validate_request {
+            ValidateRequest::AdmissionRequest(admission_request) => {
+                let critical_namespace = admission_request
+                    .namespace
+                    .as_ref()
+                    .is_some_and(|namespace| config.critical_namespaces.contains(namespace));
+                let node = admission_request.kind.group.is_empty()
+                    && admission_request.kind.kind == "Node";
+                if critical_namespace || node {
+                    PriorityClass::Critical
+                } else {
+                    PriorityClass::Normal
+                }
+            }
+            ValidateRequest::Raw(_) | ValidateRequest::CloudEvent(_) => PriorityClass::Normal,
+        }
+    }
+}
+
+impl fmt::Display for PriorityClass {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            PriorityClass::Critical => write!(f, "critical"),
+            PriorityClass::Normal => write!(f, "normal"),
+            PriorityClass::Bulk => write!(f, "bulk"),
+        }
+    }
+}
+
+impl FromStr for PriorityClass {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_lowercase().as_str() {
+            "critical" => Ok(PriorityClass::Critical),
+            "normal" => Ok(PriorityClass::Normal),
+            "bulk" => Ok(PriorityClass::Bulk),
+            other => Err(format!("unknown priority class: {other}")),
+        }
+    }
+}
+
+/// Hands out the permits to use the workers, serving the requests with the
+/// highest priority class first.
+///
+/// To prevent starvation, once the requests of a class have been overtaken
+/// `starvation_threshold` times by the ones of higher classes, the oldest
+/// request of that class is served next.
+#[derive(Clone)]
+pub(crate) struct PriorityDispatcher {
+    inner: Arc<Mutex<DispatcherState>>,
+}
+
+struct DispatcherState {
+    available_permits: usize,
+    starvation_threshold: u32,
+    queues: [VecDeque<oneshot::Sender<DispatchPermit>>; 3],
+    /// How many times the waiting requests of each class have been overtaken
+    overtaken: [u32; 3],
+}
+
+/// Grants the usage of a worker, the worker is released once the permit is dropped
+pub(crate) struct DispatchPermit {
+    dispatcher: Option<PriorityDispatcher>,
+}
+
+impl Drop for DispatchPermit {
+    fn drop(&mut self) {
+        if let Some(dispatcher) = self.dispatcher.take() {
+            dispatcher.release();
+        }
+    }
+}
+
+impl PriorityDispatcher {
+    pub(crate) fn new(permits: usize, starvation_threshold: u32) -> Self {
+        Self {
+            inner: Arc::new(Mutex::new(DispatcherState {
+                available_permits: permits,
+                starvation_threshold,
+                queues: Default::default(),
+                overtaken: [0; 3],
+            })),
+        }
+    }
+
+    /// Wait for a worker to be available
+    pub(crate) async fn acquire(&self, priority_class: PriorityClass) -> DispatchPermit {
+        let receiver = {
+            let mut state = self.inner.lock().expect("dispatcher lock is poisoned");
+            if state.available_permits > 0 && state.queues.iter().all(VecDeque::is_empty) {
+                state.available_permits -= 1;
+                return self.permit();
+            }
+
+            let (sender, receiver) = oneshot::channel();
+            state.queues[priority_class.index()].push_back(sender);
+            receiver
+        };
+
+        // When the request is cancelled while waiting, the permit it may have been
+        // granted is dropped together with the channel, hence it's released
+        receiver
+            .await
+            .expect("the dispatcher never drops waiting requests")
+    }
+
+    fn permit(&self) -> DispatchPermit {
+        DispatchPermit {
+            dispatcher: Some(self.clone()),
+        }
+    }
+
+    /// Hand the permit that has been released to the next request in line
+    fn release(&self) {
+        loop {
+            let sender = {
+                let mut state = self.inner.lock().expect("dispatcher lock is poisoned");
+                match state.next_in_line() {
+                    Some(sender) => sender,
+                    None => {
+                        state.available_permits += 1;
+                        return;
+                    }
+                }
+            };
+
+            match sender.send(self.permit()) {
+                Ok(()) => return,
+                // the request has been cancelled, try with the next one
+                Err(mut permit) => {
+                    permit.dispatcher = None;
+                }
+            }
+        }
+    }
+
+    /// Return the amount of requests waiting for a worker, for each priority class
+    #[cfg(test)]
+    fn queued(&self) -> Vec<usize> {
+        let state = self.inner.lock().unwrap();
+        state.queues.iter().map(VecDeque::len).collect()
+    }
+}
+
+impl DispatcherState {
+    fn next_in_line(&mut self) -> Option<oneshot::Sender<DispatchPermit>> {
+        let starving = PriorityClass::ALL.iter().find(|class| {
+            let index = class.index();
+            !self.queues[index].is_empty() && self.overtaken[index] >= self.starvation_threshold
+        });
+        let selected = starving
+            .or_else(|| {
+                PriorityClass::ALL
+                    .iter()
+                    .find(|class| !self.queues[class.index()].is_empty())
+            })?
+            .index();
+
+        self.overtaken[selected] = 0;
+        for (queue, overtaken) in self
+            .queues
+            .iter()
+            .zip(self.overtaken.iter_mut())
+            .skip(selected + 1)
+        {
+            if !queue.is_empty() {
+                *overtaken += 1;
+            }
+        }
+
+        self.queues[selected].pop_front()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::build_admission_review_request;
+    use policy_evaluator::admission_request::GroupVersionKind;
+    use rstest::rstest;
+    use std::{collections::HashSet, time::Duration};
+    use tokio::{sync::mpsc, time::timeout};
+
+    fn admission_request(kind: &str, namespace: Option<&str>) -> ValidateRequest {
+        let mut admission_request = build_admission_review_request().request;
+        admission_request.kind = GroupVersionKind {
+            group: String::new(),
+            version: "v1".to_owned(),
+            kind: kind.to_owned(),
+        };
+        admission_request.namespace = namespace.map(str::to_owned);
+
+        ValidateRequest::AdmissionRequest(Box::new(admission_request))
+    }
+
+    #[rstest]
+    #[case::regular(
+        admission_request("Pod", Some("default")),
+        RequestOrigin::Validate,
+        None,
+        PriorityClass::Normal
+    )]
+    #[case::critical_namespace(
+        admission_request("Pod", Some("kube-system")),
+        RequestOrigin::Validate,
+        None,
+        PriorityClass::Critical
+    )]
+    #[case::node(
+        admission_request("Node", None),
+        RequestOrigin::Validate,
+        None,
+        PriorityClass::Critical
+    )]
+    #[case::audit(
+        admission_request("Pod", Some("kube-system")),
+        RequestOrigin::Audit,
+        None,
+        PriorityClass::Bulk
+    )]
+    #[case::header(
+        admission_request("Pod", Some("default")),
+        RequestOrigin::Validate,
+        Some("bulk"),
+        PriorityClass::Bulk
+    )]
+    #[case::header_cannot_raise(
+        admission_request("Pod", Some("default")),
+        RequestOrigin::Validate,
+        Some("critical"),
+        PriorityClass::Normal
+    )]
+    #[case::header_cannot_raise_audit(
+        admission_request("Pod", Some("kube-system")),
+        RequestOrigin::Audit,
+        Some("normal"),
+        PriorityClass::Bulk
+    )]
+    #[case::header_lowers_critical(
+        admission_request("Node", None),
+        RequestOrigin::Validate,
+        Some("normal"),
+        PriorityClass::Normal
+    )]
+    #[case::invalid_header(
+        admission_request("Pod", Some("default")),
+        RequestOrigin::Validate,
+        Some("urgent"),
+        PriorityClass::Normal
+    )]
+    #[case::raw(ValidateRequest::Raw(serde_json::json!({})), RequestOrigin::Validate, None, PriorityClass::Normal)]
+    fn assign_priority_class(
+        #[case] validate_request: ValidateRequest,
+        #[case] request_origin: RequestOrigin,
+        #[case] header: Option<&str>,
+        #[case] expected: PriorityClass,
+    ) {
+        let config = PriorityConfig {
+            critical_namespaces: HashSet::from(["kube-system".to_owned()]),
+            starvation_threshold: 8,
+        };
+        let mut headers = HeaderMap::new();
+        if let Some(header) = header {
+            headers.insert(PRIORITY_CLASS_HEADER, header.parse().unwrap());
+        }
+
+        assert_eq!(
+            PriorityClass::of_request(&config, &headers, &request_origin, &validate_request),
+            expected
+        );
+    }
+
+    const TEST_TIMEOUT: Duration = Duration::from_secs(5);
+
+    type Served = (&'static str, oneshot::Sender<()>);
+
+    /// Queue a request of the given class. Once served, the request reports its label
+    /// over `served_tx`, together with the channel to be used to release its permit.
+    async fn queue_request(
+        dispatcher: &PriorityDispatcher,
+        priority_class: PriorityClass,
+        label: &'static str,
+        served_tx: &mpsc::UnboundedSender<Served>,
+    ) {
+        let dispatcher = dispatcher.clone();
+        let served_tx = served_tx.clone();
+        tokio::spawn(async move {
+            let _permit = dispatcher.acquire(priority_class).await;
+            let (release_tx, release_rx) = oneshot::channel();
+            served_tx.send((label, release_tx)).unwrap();
+            let _ = release_rx.await;
+        });
+        // let the task reach the queue
+        tokio::task::yield_now().await;
+    }
+
+    /// Serve the given amount of requests, one after the other
+    async fn serve(served_rx: &mut mpsc::UnboundedReceiver<Served>, count: usize) -> Vec<&str> {
+        let mut served = Vec::new();
+        for _ in 0..count {
+            let (label, release_tx) = timeout(TEST_TIMEOUT, served_rx.recv())
+                .await
+                .unwrap()
+                .unwrap();
+            served.push(label);
+            release_tx.send(()).unwrap();
+        }
+        served
+    }
+
+    #[tokio::test]
+    async fn higher_priority_classes_are_served_first() {
+        let dispatcher = PriorityDispatcher::new(1, 100);
+        let (served_tx, mut served_rx) = mpsc::unbounded_channel();
+
+        queue_request(&dispatcher, PriorityClass::Bulk, "busy", &served_tx).await;
+        let (_, busy) = served_rx.recv().await.unwrap();
+
+        queue_request(&dispatcher, PriorityClass::Bulk, "bulk", &served_tx).await;
+        queue_request(&dispatcher, PriorityClass::Normal, "normal", &served_tx).await;
+        queue_request(&dispatcher, PriorityClass::Critical, "critical", &served_tx).await;
+        assert_eq!(dispatcher.queued(), vec![1, 1, 1]);
+
+        busy.send(()).unwrap();
+        assert_eq!(
+            serve(&mut served_rx, 3).await,
+            vec!["critical", "normal", "bulk"]
+        );
+    }
+
+    #[tokio::test]
+    async fn lower_priority_classes_do_not_starve() {
+        let dispatcher = PriorityDispatcher::new(1, 2);
+        let (served_tx, mut served_rx) = mpsc::unbounded_channel();
+
+        queue_request(&dispatcher, PriorityClass::Critical, "busy", &served_tx).await;
+        let (_, busy) = served_rx.recv().await.unwrap();
+
+        queue_request(&dispatcher, PriorityClass::Bulk, "bulk", &served_tx).await;
+        for _ in 0..4 {
+            queue_request(&dispatcher, PriorityClass::Critical, "critical", &served_tx).await;
+        }
+
+        busy.send(()).unwrap();
+        assert_eq!(
+            serve(&mut served_rx, 5).await,
+            vec!["critical", "critical", "bulk", "critical", "critical"]
+        );
+    }
+
+    #[tokio::test]
+    async fn cancelled_requests_release_their_permit() {
+        let dispatcher = PriorityDispatcher::new(1, 8);
+
+        let permit = dispatcher.acquire(PriorityClass::Normal).await;
+        let cancelled = timeout(
+            Duration::from_millis(10),
+            dispatcher.acquire(PriorityClass::Critical),
+        )
+        .await;
+        assert!(cancelled.is_err());
+
+        drop(permit);
+        let permit = timeout(TEST_TIMEOUT, dispatcher.acquire(PriorityClass::Bulk)).await;
+        assert!(permit.is_ok());
+        assert_eq!(dispatcher.queued(), vec![0, 0, 0]);
+    }
+}
@@ -1,43 +1,57 @@
 use axum::{
-    extract::{self, FromRequest, Query},
-    http::{header, StatusCode},
+    body::Bytes,
+    extract::{self, ConnectInfo, Query},
+    http::{header, HeaderMap, StatusCode},
     response::IntoResponse,
     Json,
 };
 use policy_evaluator::{
-    admission_request::AdmissionRequest, admission_response::AdmissionResponse,
-    admission_response_handler::errors::EvaluationError, policy_evaluator::ValidateRequest,
+    admission_request::AdmissionRequest,
+    admission_response::AdmissionResponse,
+    admission_response_handler::errors::EvaluationError,
+    callback_handler::KubernetesHealth,
+    capability_usage::{self, CapabilityUsageReport},
+    policy_evaluator::ValidateRequest,
 };
 
 use serde::{Deserialize, Serialize};
-use std::sync::Arc;
-use tokio::task;
+use std::{
+    collections::{BTreeMap, HashSet},
+    net::SocketAddr,
+    sync::Arc,
+    time::Duration,
+};
+use tokio::{task, time::Instant};
 use tracing::{debug, error, Span};
 
+use crate::evaluation::{
+    module_cache::{ModuleCache, PeerEntryError},
+    PolicyCatalogEntry, PolicyState,
+};
 use crate::profiling::ReportGenerationError;
+use crate::tracing::{
+    log_filter,
+    log_filter::{LogFilterRequest, LogFilterStatus},
+};
 use crate::{
     api::{
-        admission_review::{AdmissionReviewRequest, AdmissionReviewResponse},
+        admission_review::{
+            AdmissionReviewResponse, NegotiatedAdmissionReview, NegotiatedAdmissionReviewResponse,
+            ReviewEncoding,
+        },
         api_error::ApiError,
+        cloud_event::{self, CLOUDEVENTS_JSON},
+        dispatcher::PriorityClass,
+        json_body::StreamingJson,
         raw_review::{RawReviewRequest, RawReviewResponse},
         service::{evaluate, RequestOrigin},
         state::ApiServerState,
     },
-    profiling,
+    decision_log::DecisionRecord,
+    journal::JournalEntry,
+    metrics, profiling,
 };
 
-// create an extractor that internally uses `axum::Json` but has a custom rejection
-#[derive(FromRequest)]
-#[from_request(via(axum::Json), rejection(ApiError))]
-pub(crate) struct JsonExtractor<T>(T);
-
-impl<T: Serialize> IntoResponse for JsonExtractor<T> {
-    fn into_response(self) -> axum::response::Response {
-        let Self(value) = self;
-        axum::Json(value).into_response()
-    }
-}
-
 // note about tracing: we are manually adding the `policy_id` field
 // because otherwise the automatic "export" would cause the string to be
 // double quoted. This would make searching by tag inside of Jaeger ugly.
@@ -49,6 +63,7 @@ impl<T: Serialize> IntoResponse for JsonExtractor<T> {
         request_uid=tracing::field::Empty,
         host=crate::config::HOSTNAME.as_str(),
         policy_id=policy_id.as_str(),
+        policy_stable_id=tracing::field::Empty,
         name=tracing::field::Empty,
         namespace=tracing::field::Empty,
         operation=tracing::field::Empty,
@@ -69,14 +84,16 @@ impl<T: Serialize> IntoResponse for JsonExtractor<T> {
 pub(crate) async fn audit_handler(
     extract::State(state): extract::State<Arc<ApiServerState>>,
     extract::Path(policy_id): extract::Path<String>,
-    extract::Json(admission_review): extract::Json<AdmissionReviewRequest>,
-) -> Result<Json<AdmissionReviewResponse>, (StatusCode, ApiError)> {
+    headers: HeaderMap,
+    NegotiatedAdmissionReview(admission_review): NegotiatedAdmissionReview,
+) -> Result<NegotiatedAdmissionReviewResponse, (StatusCode, ApiError)> {
     debug!(admission_review = %serde_json::to_string(&admission_review).unwrap().as_str());
 
     populate_span_with_admission_request_data(&admission_review.request);
 
-    let response = acquire_semaphore_and_evaluate(
+    let response = dispatch_and_evaluate(
         state,
+        &headers,
         policy_id,
         ValidateRequest::AdmissionRequest(Box::new(admission_review.request)),
         RequestOrigin::Audit,
@@ -86,7 +103,10 @@ pub(crate) async fn audit_handler(
 
     populate_span_with_policy_evaluation_results(&response);
 
-    Ok(Json(AdmissionReviewResponse::new(response)))
+    Ok(NegotiatedAdmissionReviewResponse {
+        review: AdmissionReviewResponse::new(response),
+        encoding: ReviewEncoding::of_response(&headers),
+    })
 }
 
 // note about tracing: we are manually adding the `policy_id` field
@@ -100,6 +120,7 @@ pub(crate) async fn audit_handler(
         request_uid=tracing::field::Empty,
         host=crate::config::HOSTNAME.as_str(),
         policy_id=policy_id.as_str(),
+        policy_stable_id=tracing::field::Empty,
         name=tracing::field::Empty,
         namespace=tracing::field::Empty,
         operation=tracing::field::Empty,
@@ -120,14 +141,16 @@ pub(crate) async fn audit_handler(
 pub(crate) async fn validate_handler(
     extract::State(state): extract::State<Arc<ApiServerState>>,
     extract::Path(policy_id): extract::Path<String>,
-    JsonExtractor(admission_review): JsonExtractor<AdmissionReviewRequest>,
-) -> Result<Json<AdmissionReviewResponse>, (StatusCode, ApiError)> {
+    headers: HeaderMap,
+    NegotiatedAdmissionReview(admission_review): NegotiatedAdmissionReview,
+) -> Result<NegotiatedAdmissionReviewResponse, (StatusCode, ApiError)> {
     debug!(admission_review = %serde_json::to_string(&admission_review).unwrap().as_str());
 
     populate_span_with_admission_request_data(&admission_review.request);
 
-    let response = acquire_semaphore_and_evaluate(
+    let response = dispatch_and_evaluate(
         state,
+        &headers,
         policy_id,
         ValidateRequest::AdmissionRequest(Box::new(admission_review.request)),
         RequestOrigin::Validate,
@@ -137,7 +160,10 @@ pub(crate) async fn validate_handler(
 
     populate_span_with_policy_evaluation_results(&response);
 
-    Ok(Json(AdmissionReviewResponse::new(response)))
+    Ok(NegotiatedAdmissionReviewResponse {
+        review: AdmissionReviewResponse::new(response),
+        encoding: ReviewEncoding::of_response(&headers),
+    })
 }
 
 #[tracing::instrument(
@@ -146,6 +172,7 @@ pub(crate) async fn validate_handler(
         request_uid=tracing::field::Empty,
         host=crate::config::HOSTNAME.as_str(),
         policy_id=policy_id.as_str(),
+        policy_stable_id=tracing::field::Empty,
         allowed=tracing::field::Empty,
         mutated=tracing::field::Empty,
         response_code=tracing::field::Empty,
@@ -155,12 +182,14 @@ pub(crate) async fn validate_handler(
 pub(crate) async fn validate_raw_handler(
     extract::State(state): extract::State<Arc<ApiServerState>>,
     extract::Path(policy_id): extract::Path<String>,
-    extract::Json(raw_review): extract::Json<RawReviewRequest>,
+    headers: HeaderMap,
+    StreamingJson(raw_review): StreamingJson<RawReviewRequest>,
 ) -> Result<Json<RawReviewResponse>, (StatusCode, ApiError)> {
     debug!(raw_review = %serde_json::to_string(&raw_review).unwrap().as_str());
 
-    let response = acquire_semaphore_and_evaluate(
+    let response = dispatch_and_evaluate(
         state,
+        &headers,
         policy_id,
         ValidateRequest::Raw(raw_review.request),
         RequestOrigin::Validate,
@@ -173,10 +202,230 @@ pub(crate) async fn validate_raw_handler(
     Ok(Json(RawReviewResponse::new(response)))
 }
 
+#[tracing::instrument(
+    name = "validation_cloudevent",
+    fields(
+        request_uid=tracing::field::Empty,
+        host=crate::config::HOSTNAME.as_str(),
+        policy_id=policy_id.as_str(),
+        policy_stable_id=tracing::field::Empty,
+        event_source=tracing::field::Empty,
+        event_type=tracing::field::Empty,
+        allowed=tracing::field::Empty,
+        mutated=tracing::field::Empty,
+        response_code=tracing::field::Empty,
+        response_message=tracing::field::Empty,
+    ),
+    skip_all)]
+/// Validate a CloudEvent delivered using the HTTP protocol binding. The outcome of the
+/// evaluation is sent back as a CloudEvent, using the structured content mode.
+pub(crate) async fn validate_cloudevent_handler(
+    extract::State(state): extract::State<Arc<ApiServerState>>,
+    extract::Path(policy_id): extract::Path<String>,
+    headers: HeaderMap,
+    body: Bytes,
+) -> Result<impl IntoResponse, (StatusCode, ApiError)> {
+    let event = cloud_event::decode(&headers, &body).map_err(|e| (e.status, e))?;
+    debug!(cloud_event = %serde_json::to_string(&event).unwrap().as_str());
+
+    let span = Span::current();
+    span.record("request_uid", event.id.as_str());
+    span.record("event_source", event.source.as_str());
+    span.record("event_type", event.ty.as_str());
+
+    let response = dispatch_and_evaluate(
+        state,
+        &headers,
+        policy_id.clone(),
+        ValidateRequest::CloudEvent(Box::new(event.clone())),
+        RequestOrigin::Validate,
+    )
+    .await
+    .map_err(handle_evaluation_error)?;
+
+    populate_span_with_policy_evaluation_results(&response);
+
+    Ok((
+        [(header::CONTENT_TYPE, CLOUDEVENTS_JSON)],
+        Json(cloud_event::response_event(&event, &policy_id, &response)),
+    ))
+}
+
+/// List the policies loaded by Policy Server, together with their stable IDs
+pub(crate) async fn policies_handler(
+    extract::State(state): extract::State<Arc<ApiServerState>>,
+) -> Json<Vec<PolicyCatalogEntry>> {
+    Json(state.evaluation_environment.policies_catalog())
+}
+
+/// Report the usage of the host capabilities made by the policies, pointing out the
+/// wasteful patterns found
+pub(crate) async fn capability_usage_handler() -> Json<CapabilityUsageReport> {
+    Json(capability_usage::report())
+}
+
+/// Serve an entry of the module cache to a peer replica, the entry is returned as it
+/// is stored
+pub(crate) async fn module_cache_peer_handler(
+    extract::State(module_cache): extract::State<Arc<ModuleCache>>,
+    extract::Path((engine, module_digest)): extract::Path<(String, String)>,
+    headers: HeaderMap,
+) -> Result<impl IntoResponse, (StatusCode, ApiError)> {
+    let token = headers
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "))
+        .unwrap_or_default();
+
+    let entry = module_cache
+        .peer_entry(&engine, &module_digest, token)
+        .map_err(|e| {
+            let (status, message) = match e {
+                PeerEntryError::Unauthorized => (StatusCode::UNAUTHORIZED, "Invalid token"),
+                PeerEntryError::NotFound => (StatusCode::NOT_FOUND, "Entry not found"),
+            };
+            (
+                status,
+                ApiError {
+                    status,
+                    message: message.to_owned(),
+                },
+            )
+        })?;
+
+    Ok((
+        [(
+            header::CONTENT_TYPE,
+            mime::APPLICATION_OCTET_STREAM.to_string(),
+        )],
+        entry,
+    ))
+}
+
 pub(crate) async fn readiness_handler() -> StatusCode {
     StatusCode::OK
 }
 
+/// The state the policies must reach before Policy Server is considered ready
+#[derive(Deserialize, Debug, Default, Clone, Copy, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub(crate) enum RequiredPolicyState {
+    /// The Wasm modules of the policies have been compiled
+    #[default]
+    Compiled,
+    /// The policies have been instantiated and their Kubernetes resources synced
+    Warm,
+}
+
+impl RequiredPolicyState {
+    fn is_satisfied_by(&self, state: PolicyState) -> bool {
+        match state {
+            PolicyState::Warm => true,
+            PolicyState::Compiled => *self == RequiredPolicyState::Compiled,
+            PolicyState::Cold | PolicyState::Failed | PolicyState::Syncing => false,
+        }
+    }
+}
+
+#[derive(Deserialize)]
+pub(crate) struct ReadyzParams {
+    /// The state the policies must have reached
+    #[serde(default)]
+    pub require: RequiredPolicyState,
+
+    /// Comma separated list of the policies to be checked. When not set, all the
+    /// policies are checked, except the ones that failed to initialize.
+    pub policies: Option<String>,
+}
+
+#[derive(Serialize, Debug)]
+pub(crate) struct ReadyzResponse {
+    pub ready: bool,
+    pub policies: BTreeMap<String, PolicyState>,
+}
+
+/// Report whether the policies reached the required state, together with the
+/// state of each one of them
+pub(crate) async fn readyz_handler(
+    extract::State(state): extract::State<Arc<ApiServerState>>,
+    params: Query<ReadyzParams>,
+) -> Result<(StatusCode, Json<ReadyzResponse>), (StatusCode, ApiError)> {
+    let catalog = state.evaluation_environment.policies_catalog();
+
+    let policies: BTreeMap<String, PolicyState> = match &params.policies {
+        Some(requested) => {
+            let requested: HashSet<&str> = requested
+                .split(',')
+                .map(str::trim)
+                .filter(|id| !id.is_empty())
+                .collect();
+            let policies: BTreeMap<String, PolicyState> = catalog
+                .into_iter()
+                .filter(|entry| requested.contains(entry.id.as_str()))
+                .map(|entry| (entry.id, entry.state))
+                .collect();
+
+            let mut unknown: Vec<&str> = requested
+                .into_iter()
+                .filter(|id| !policies.contains_key(*id))
+                .collect();
+            if !unknown.is_empty() {
+                unknown.sort();
+                return Err((
+                    StatusCode::BAD_REQUEST,
+                    ApiError {
+                        status: StatusCode::BAD_REQUEST,
+                        message: format!("unknown policies: {}", unknown.join(", ")),
+                    },
+                ));
+            }
+            policies
+        }
+        None => catalog
+            .into_iter()
+            .filter(|entry| entry.state != PolicyState::Failed)
+            .map(|entry| (entry.id, entry.state))
+            .collect(),
+    };
+
+    let ready = policies
+        .values()
+        .all(|policy_state| params.require.is_satisfied_by(*policy_state));
+    let status = if ready {
+        StatusCode::OK
+    } else {
+        StatusCode::SERVICE_UNAVAILABLE
+    };
+
+    Ok((status, Json(ReadyzResponse { ready, policies })))
+}
+
+/// Report the state of the reflectors used by the context aware policies, and whether
+/// the Kubernetes API server can be reached. This allows to tell apart the issues of the
+/// policies from the ones of the Kubernetes API server
+pub(crate) async fn readyz_kubernetes_handler(
+    extract::State(state): extract::State<Arc<ApiServerState>>,
+) -> Result<(StatusCode, Json<KubernetesHealth>), (StatusCode, ApiError)> {
+    let reporter = state.kubernetes_health_reporter.as_ref().ok_or_else(|| {
+        (
+            StatusCode::SERVICE_UNAVAILABLE,
+            ApiError {
+                status: StatusCode::SERVICE_UNAVAILABLE,
+                message: "Policy Server is not connected to Kubernetes".to_owned(),
+            },
+        )
+    })?;
+
+    let health = reporter.health().await;
+    let status = if health.is_healthy() {
+        StatusCode::OK
+    } else {
+        StatusCode::SERVICE_UNAVAILABLE
+    };
+
+    Ok((status, Json(health)))
+}
+
 #[derive(Deserialize)]
 pub(crate) struct ProfileParams {
     /// profiling frequency (Hz)
@@ -253,38 +502,212 @@ pub(crate) async fn pprof_get_heap(
     Ok((headers, pprof))
 }
 
-async fn acquire_semaphore_and_evaluate(
+/// Return the tracing filter currently in use
+pub(crate) async fn log_filter_get_handler() -> Result<Json<LogFilterStatus>, (StatusCode, ApiError)>
+{
+    let log_filter = log_filter().ok_or_else(log_filter_not_initialized_error)?;
+
+    Ok(Json(log_filter.status()))
+}
+
+/// Change the tracing filter at runtime, optionally for a limited amount of time
+pub(crate) async fn log_filter_put_handler(
+    ConnectInfo(remote_addr): ConnectInfo<SocketAddr>,
+    extract::Json(request): extract::Json<LogFilterRequest>,
+) -> Result<Json<LogFilterStatus>, (StatusCode, ApiError)> {
+    let log_filter = log_filter().ok_or_else(log_filter_not_initialized_error)?;
+
+    let status = log_filter
+        .set(
+            &request.directives,
+            request.ttl_seconds.map(Duration::from_secs),
+            &remote_addr.to_string(),
+        )
+        .map_err(|e| {
+            (
+                StatusCode::BAD_REQUEST,
+                ApiError {
+                    status: StatusCode::BAD_REQUEST,
+                    message: e.to_string(),
+                },
+            )
+        })?;
+
+    Ok(Json(status))
+}
+
+/// Restore the tracing filter policy-server has been started with
+pub(crate) async fn log_filter_delete_handler(
+    ConnectInfo(remote_addr): ConnectInfo<SocketAddr>,
+) -> Result<Json<LogFilterStatus>, (StatusCode, ApiError)> {
+    let log_filter = log_filter().ok_or_else(log_filter_not_initialized_error)?;
+
+    let status = log_filter.reset(&remote_addr.to_string()).map_err(|e| {
+        error!("cannot restore tracing filter: {}", e);
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            ApiError {
+                status: StatusCode::INTERNAL_SERVER_ERROR,
+                message: "Something went wrong".to_owned(),
+            },
+        )
+    })?;
+
+    Ok(Json(status))
+}
+
+/// Wait for a worker, according to the priority class of the request, then evaluate it
+async fn dispatch_and_evaluate(
     state: Arc<ApiServerState>,
+    headers: &HeaderMap,
     policy_id: String,
     validate_request: ValidateRequest,
     request_origin: RequestOrigin,
 ) -> Result<AdmissionResponse, EvaluationError> {
-    let _permit = state
-        .semaphore
-        .acquire()
-        .await
-        .expect("semaphore acquire failed");
+    let mut validate_request = validate_request;
+    let origin = request_origin.to_string();
 
-    let state = state.clone();
-    let span = Span::current();
-    let response = task::spawn_blocking(move || {
-        let _enter = span.enter();
-
-        evaluate(
-            state.evaluation_environment.clone(),
-            &policy_id,
-            &validate_request,
-            request_origin,
-        )
-    })
-    .await
-    .expect("task::spawn_blocking failed")?;
+    // The enrichment happens before taking a worker, it's made of I/O operations
+    let enrichment_error = match &mut validate_request {
+        ValidateRequest::AdmissionRequest(admission_request) => {
+            state.enrichment.enrich(admission_request).await.err()
+        }
+        ValidateRequest::Raw(_) | ValidateRequest::CloudEvent(_) => None,
+    };
+
+    let (response, validate_request, evaluation_time) = match enrichment_error {
+        Some(error) => {
+            error!(%error, "cannot enrich the request, rejecting it");
+            let response = AdmissionResponse::reject_internal_server_error(
+                validate_request.uid().to_owned(),
+                error.to_string(),
+            );
+            (Ok(response), validate_request, Duration::ZERO)
+        }
+        None => {
+            let start_time = Instant::now();
+            let priority_class = PriorityClass::of_request(
+                &state.priority_config,
+                headers,
+                &request_origin,
+                &validate_request,
+            );
+            let permit = state.dispatcher.acquire(priority_class).await;
+            let queue_latency = start_time.elapsed();
+
+            let state = state.clone();
+            let policy_id = policy_id.clone();
+            let span = Span::current();
+            let evaluated = task::spawn_blocking(move || {
+                let _enter = span.enter();
+
+                let evaluation_start = Instant::now();
+                let response = evaluate(
+                    state.evaluation_environment.clone(),
+                    &policy_id,
+                    &validate_request,
+                    request_origin,
+                );
+
+                (response, validate_request, evaluation_start.elapsed())
+            })
+            .await
+            .expect("task::spawn_blocking failed");
+            // the worker is not needed while recording the decision
+            drop(permit);
+
+            metrics::record_dispatch_latency(
+                queue_latency,
+                start_time.elapsed(),
+                &metrics::DispatchLatency {
+                    priority_class: priority_class.to_string(),
+                },
+            );
+            evaluated
+        }
+    };
+
+    let response = record_decision(
+        &state,
+        &policy_id,
+        &origin,
+        &validate_request,
+        response,
+        evaluation_time,
+    )
+    .await?;
 
     debug!(response =? &response, "policy evaluated");
 
     Ok(response)
 }
 
+/// Write the decision taken about the request to the decision journal and to the
+/// decision log, returning the response to be sent back.
+///
+/// The decision must be on disk before the response is sent back. When it cannot be
+/// written and the journal is fail-closed, the request is rejected instead: the decision
+/// log records this final response.
+async fn record_decision(
+    state: &ApiServerState,
+    policy_id: &str,
+    origin: &str,
+    validate_request: &ValidateRequest,
+    response: Result<AdmissionResponse, EvaluationError>,
+    evaluation_time: Duration,
+) -> Result<AdmissionResponse, EvaluationError> {
+    if state.decision_journal.is_none() && state.decision_log.is_none() {
+        return response;
+    }
+
+    let policy_stable_id = policy_id
+        .parse()
+        .ok()
+        .and_then(|id| state.evaluation_environment.get_policy_stable_id(&id));
+    let entry_of = |response: &Result<AdmissionResponse, EvaluationError>| match response {
+        Ok(response) => JournalEntry::new(
+            policy_id,
+            policy_stable_id.as_deref(),
+            origin,
+            validate_request,
+            response,
+        ),
+        Err(error) => JournalEntry::from_error(
+            policy_id,
+            policy_stable_id.as_deref(),
+            origin,
+            validate_request,
+            error,
+        ),
+    };
+
+    let mut entry = entry_of(&response);
+    let mut response = response;
+    if let Some(decision_journal) = &state.decision_journal {
+        if let Err(error) = decision_journal.append(&entry).await {
+            error!(?error, "cannot write decision to the journal");
+            if decision_journal.fail_closed() {
+                response = Ok(AdmissionResponse::reject_internal_server_error(
+                    entry.request_uid.clone(),
+                    "cannot write decision to the journal".to_owned(),
+                ));
+                entry = entry_of(&response);
+            }
+        }
+    }
+
+    if let Some(decision_log) = &state.decision_log {
+        decision_log.record(DecisionRecord::new(
+            entry,
+            validate_request,
+            response.as_ref().ok(),
+            evaluation_time,
+        ));
+    }
+
+    response
+}
+
 fn populate_span_with_admission_request_data(adm_req: &AdmissionRequest) {
     Span::current().record("kind", adm_req.kind.kind.as_str());
     Span::current().record("kind_group", adm_req.kind.group.as_str());
@@ -341,6 +764,16 @@ fn handle_evaluation_error(error: EvaluationError) -> (StatusCode, ApiError) {
     }
 }
 
+fn log_filter_not_initialized_error() -> (StatusCode, ApiError) {
+    (
+        StatusCode::SERVICE_UNAVAILABLE,
+        ApiError {
+            status: StatusCode::SERVICE_UNAVAILABLE,
+            message: "tracing system not initialized".to_owned(),
+        },
+    )
+}
+
 fn handle_pprof_error(error: ReportGenerationError) -> (StatusCode, ApiError) {
     error!("pprof error: {}", error);
 
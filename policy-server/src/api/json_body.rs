@@ -0,0 +1,191 @@
+use std::sync::Arc;
+
+use axum::{
+    extract::{FromRequest, Request},
+    http::{header, HeaderMap, StatusCode},
+};
+use futures::StreamExt;
+use serde::de::DeserializeOwned;
+
+use crate::api::{api_error::ApiError, state::ApiServerState};
+
+/// Maximum value of `--max-request-body-depth`, that's the recursion limit of the
+/// JSON deserializer
+pub(crate) const MAX_REQUEST_BODY_DEPTH_LIMIT: usize = 128;
+
+/// Extractor of JSON request bodies, protecting the server from the oversized and
+/// the deeply nested ones.
+///
+/// The body is consumed chunk by chunk: the request is rejected as soon as the
+/// chunks received exceed the maximum allowed size, or open more nested objects and
+/// arrays than allowed, without waiting for the rest of the body. The body is
+/// deserialized only once it has been entirely received
+pub(crate) struct StreamingJson<T>(pub(crate) T);
+
+impl<T> FromRequest<Arc<ApiServerState>> for StreamingJson<T>
+where
+    T: DeserializeOwned,
+{
+    type Rejection = ApiError;
+
+    async fn from_request(
+        request: Request,
+        state: &Arc<ApiServerState>,
+    ) -> Result<Self, Self::Rejection> {
+        if !has_json_content_type(request.headers()) {
+            return Err(ApiError {
+                status: StatusCode::UNSUPPORTED_MEDIA_TYPE,
+                message: "Expected request with `Content-Type: application/json`".to_owned(),
+            });
+        }
+
+        let max_size = state.max_request_body_size;
+        let too_large = || ApiError {
+            status: StatusCode::PAYLOAD_TOO_LARGE,
+            message: format!("request body exceeds the maximum allowed size of {max_size} bytes"),
+        };
+        let content_length = request
+            .headers()
+            .get(header::CONTENT_LENGTH)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.parse::<usize>().ok());
+        if content_length.is_some_and(|length| length > max_size) {
+            return Err(too_large());
+        }
+
+        let mut scanner = NestingScanner::new(state.max_request_body_depth);
+        let mut body = Vec::with_capacity(content_length.unwrap_or_default());
+        let mut chunks = request.into_body().into_data_stream();
+        while let Some(chunk) = chunks.next().await {
+            let chunk = chunk.map_err(|e| ApiError {
+                status: StatusCode::BAD_REQUEST,
+                message: format!("Failed to read the request body: {e}"),
+            })?;
+            if body.len() + chunk.len() > max_size {
+                return Err(too_large());
+            }
+            scanner.scan(&chunk).map_err(|max_depth| ApiError {
+                status: StatusCode::BAD_REQUEST,
+                message: format!(
+                    "request body exceeds the maximum allowed nesting depth of {max_depth}"
+                ),
+            })?;
+            body.extend_from_slice(&chunk);
+        }
+
+        serde_json::from_slice(&body)
+            .map(StreamingJson)
+            .map_err(|e| match e.classify() {
+                serde_json::error::Category::Data => ApiError {
+                    status: StatusCode::UNPROCESSABLE_ENTITY,
+                    message: format!(
+                        "Failed to deserialize the JSON body into the target type: {e}"
+                    ),
+                },
+                _ => ApiError {
+                    status: StatusCode::BAD_REQUEST,
+                    message: format!("Failed to parse the request body as JSON: {e}"),
+                },
+            })
+    }
+}
+
+fn has_json_content_type(headers: &HeaderMap) -> bool {
+    headers
+        .get(header::CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.parse::<mime::Mime>().ok())
+        .is_some_and(|content_type| {
+            content_type.type_() == mime::APPLICATION
+                && (content_type.subtype() == mime::JSON
+                    || content_type.suffix() == Some(mime::JSON))
+        })
+}
+
+/// Keeps track of the nesting depth of a JSON document, which is received in
+/// chunks. The document is not validated, this is left to the deserializer
+pub(crate) struct NestingScanner {
+    max_depth: usize,
+    depth: usize,
+    in_string: bool,
+    escaped: bool,
+}
+
+impl NestingScanner {
+    pub(crate) fn new(max_depth: usize) -> Self {
+        NestingScanner {
+            max_depth,
+            depth: 0,
+            in_string: false,
+            escaped: false,
+        }
+    }
+
+    /// Scan the next chunk of the document. Fails with the maximum allowed depth
+    /// when the chunk goes deeper than that
+    pub(crate) fn scan(&mut self, chunk: &[u8]) -> Result<(), usize> {
+        for byte in chunk {
+            if self.in_string {
+                match byte {
+                    _ if self.escaped => self.escaped = false,
+                    b'\\' => self.escaped = true,
+                    b'"' => self.in_string = false,
+                    _ => {}
+                }
+                continue;
+            }
+
+            match byte {
+                b'"' => self.in_string = true,
+                b'{' | b'[' => {
+                    self.depth += 1;
+                    if self.depth > self.max_depth {
+                        return Err(self.max_depth);
+                    }
+                }
+                b'}' | b']' => self.depth = self.depth.saturating_sub(1),
+                _ => {}
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::http::HeaderValue;
+    use rstest::rstest;
+
+    #[rstest]
+    #[case::flat(&[r#"{"a": 1, "b": [1, 2]}"#], 2, true)]
+    #[case::too_deep(&[r#"{"a": {"b": [1]}}"#], 2, false)]
+    #[case::brackets_inside_strings(&[r#"{"a": "{[{[{[", "b": "\"{["}"#], 1, true)]
+    #[case::split_across_chunks(&[r#"{"a": [[1], "#, r#"[[2]]]}"#], 3, false)]
+    #[case::escape_split_across_chunks(&[r#"{"a": "\"#, r#""{{{{"}"#], 1, true)]
+    fn nesting_depth(#[case] chunks: &[&str], #[case] max_depth: usize, #[case] valid: bool) {
+        let mut scanner = NestingScanner::new(max_depth);
+
+        let result = chunks
+            .iter()
+            .try_for_each(|chunk| scanner.scan(chunk.as_bytes()));
+
+        assert_eq!(result.is_ok(), valid);
+    }
+
+    #[rstest]
+    #[case::json(Some("application/json"), true)]
+    #[case::json_with_charset(Some("application/json; charset=utf-8"), true)]
+    #[case::json_suffix(Some("application/merge-patch+json"), true)]
+    #[case::text(Some("text/plain"), false)]
+    #[case::missing(None, false)]
+    fn json_content_type(#[case] content_type: Option<&'static str>, #[case] expected: bool) {
+        let mut headers = HeaderMap::new();
+        if let Some(content_type) = content_type {
+            headers.insert(header::CONTENT_TYPE, HeaderValue::from_static(content_type));
+        }
+
+        assert_eq!(has_json_content_type(&headers), expected);
+    }
+}
@@ -0,0 +1,632 @@
+//! Protobuf encoding of the `AdmissionReview` objects, as sent by the Kubernetes API
+//! server when it talks `application/vnd.kubernetes.protobuf`.
+//!
+//! Kubernetes prefixes the encoded objects with a magic number, followed by a
+//! `runtime.Unknown` message that holds the type of the object and its protobuf
+//! encoding. Only the messages, and the fields, that are part of an `AdmissionReview`
+//! are defined here, their tags match the ones of the upstream `generated.proto` files.
+//!
+//! The objects embedded inside of the requests, like the one being admitted, must be
+//! encoded as JSON: decoding the protobuf encoding of every Kubernetes type is not
+//! supported, the requests holding them are refused with `415 Unsupported Media Type`.
+
+use std::collections::{BTreeMap, HashMap};
+
+use anyhow::{anyhow, Result};
+use base64::{engine::general_purpose, Engine as _};
+use k8s_openapi::{
+    api::authentication::v1::UserInfo as JsonUserInfo,
+    apimachinery::pkg::runtime::RawExtension as JsonRawExtension,
+};
+use policy_evaluator::{admission_request, admission_response::AdmissionResponseStatus};
+use prost::Message;
+use serde::Serialize;
+use serde_json::json;
+
+use crate::api::admission_review::{AdmissionReviewRequest, AdmissionReviewResponse};
+
+/// The content type of the objects encoded with protobuf by Kubernetes
+pub const PROTOBUF_CONTENT_TYPE: &str = "application/vnd.kubernetes.protobuf";
+
+/// The prefix of the objects encoded with protobuf by Kubernetes
+const MAGIC_NUMBER: &[u8] = b"k8s\x00";
+
+const API_VERSION: &str = "admission.k8s.io/v1";
+const KIND: &str = "AdmissionReview";
+
+/// `k8s.io.apimachinery.pkg.runtime.Unknown`
+#[derive(Clone, PartialEq, Message)]
+pub struct Unknown {
+    #[prost(message, optional, tag = "1")]
+    pub type_meta: Option<TypeMeta>,
+    #[prost(bytes = "vec", optional, tag = "2")]
+    pub raw: Option<Vec<u8>>,
+    #[prost(string, optional, tag = "3")]
+    pub content_encoding: Option<String>,
+    #[prost(string, optional, tag = "4")]
+    pub content_type: Option<String>,
+}
+
+/// `k8s.io.apimachinery.pkg.runtime.TypeMeta`
+#[derive(Clone, PartialEq, Message)]
+pub struct TypeMeta {
+    #[prost(string, optional, tag = "1")]
+    pub api_version: Option<String>,
+    #[prost(string, optional, tag = "2")]
+    pub kind: Option<String>,
+}
+
+/// `k8s.io.apimachinery.pkg.runtime.RawExtension`
+#[derive(Clone, PartialEq, Message)]
+pub struct RawExtension {
+    #[prost(bytes = "vec", optional, tag = "1")]
+    pub raw: Option<Vec<u8>>,
+}
+
+/// `k8s.io.api.admission.v1.AdmissionReview`
+#[derive(Clone, PartialEq, Message)]
+pub struct AdmissionReview {
+    #[prost(message, optional, tag = "1")]
+    pub request: Option<AdmissionRequest>,
+    #[prost(message, optional, tag = "2")]
+    pub response: Option<AdmissionResponse>,
+}
+
+/// `k8s.io.api.admission.v1.AdmissionRequest`
+#[derive(Clone, PartialEq, Message)]
+pub struct AdmissionRequest {
+    #[prost(string, optional, tag = "1")]
+    pub uid: Option<String>,
+    #[prost(message, optional, tag = "2")]
+    pub kind: Option<GroupVersionKind>,
+    #[prost(message, optional, tag = "3")]
+    pub resource: Option<GroupVersionResource>,
+    #[prost(string, optional, tag = "4")]
+    pub sub_resource: Option<String>,
+    #[prost(string, optional, tag = "5")]
+    pub name: Option<String>,
+    #[prost(string, optional, tag = "6")]
+    pub namespace: Option<String>,
+    #[prost(string, optional, tag = "7")]
+    pub operation: Option<String>,
+    #[prost(message, optional, tag = "8")]
+    pub user_info: Option<UserInfo>,
+    #[prost(message, optional, tag = "9")]
+    pub object: Option<RawExtension>,
+    #[prost(message, optional, tag = "10")]
+    pub old_object: Option<RawExtension>,
+    #[prost(bool, optional, tag = "11")]
+    pub dry_run: Option<bool>,
+    #[prost(message, optional, tag = "12")]
+    pub options: Option<RawExtension>,
+    #[prost(message, optional, tag = "13")]
+    pub request_kind: Option<GroupVersionKind>,
+    #[prost(message, optional, tag = "14")]
+    pub request_resource: Option<GroupVersionResource>,
+    #[prost(string, optional, tag = "15")]
+    pub request_sub_resource: Option<String>,
+}
+
+/// `k8s.io.api.admission.v1.AdmissionResponse`
+#[derive(Clone, PartialEq, Message)]
+pub struct AdmissionResponse {
+    #[prost(string, optional, tag = "1")]
+    pub uid: Option<String>,
+    #[prost(bool, optional, tag = "2")]
+    pub allowed: Option<bool>,
+    #[prost(message, optional, tag = "3")]
+    pub status: Option<Status>,
+    #[prost(bytes = "vec", optional, tag = "4")]
+    pub patch: Option<Vec<u8>>,
+    #[prost(string, optional, tag = "5")]
+    pub patch_type: Option<String>,
+    #[prost(map = "string, string", tag = "6")]
+    pub audit_annotations: HashMap<String, String>,
+    #[prost(string, repeated, tag = "7")]
+    pub warnings: Vec<String>,
+}
+
+/// `k8s.io.apimachinery.pkg.apis.meta.v1.GroupVersionKind`
+#[derive(Clone, PartialEq, Message)]
+pub struct GroupVersionKind {
+    #[prost(string, optional, tag = "1")]
+    pub group: Option<String>,
+    #[prost(string, optional, tag = "2")]
+    pub version: Option<String>,
+    #[prost(string, optional, tag = "3")]
+    pub kind: Option<String>,
+}
+
+/// `k8s.io.apimachinery.pkg.apis.meta.v1.GroupVersionResource`
+#[derive(Clone, PartialEq, Message)]
+pub struct GroupVersionResource {
+    #[prost(string, optional, tag = "1")]
+    pub group: Option<String>,
+    #[prost(string, optional, tag = "2")]
+    pub version: Option<String>,
+    #[prost(string, optional, tag = "3")]
+    pub resource: Option<String>,
+}
+
+/// `k8s.io.api.authentication.v1.UserInfo`
+#[derive(Clone, PartialEq, Message)]
+pub struct UserInfo {
+    #[prost(string, optional, tag = "1")]
+    pub username: Option<String>,
+    #[prost(string, optional, tag = "2")]
+    pub uid: Option<String>,
+    #[prost(string, repeated, tag = "3")]
+    pub groups: Vec<String>,
+    #[prost(map = "string, message", tag = "4")]
+    pub extra: HashMap<String, ExtraValue>,
+}
+
+/// `k8s.io.api.authentication.v1.ExtraValue`
+#[derive(Clone, PartialEq, Message)]
+pub struct ExtraValue {
+    #[prost(string, repeated, tag = "1")]
+    pub items: Vec<String>,
+}
+
+/// `k8s.io.apimachinery.pkg.apis.meta.v1.Status`
+#[derive(Clone, PartialEq, Message)]
+pub struct Status {
+    #[prost(string, optional, tag = "2")]
+    pub status: Option<String>,
+    #[prost(string, optional, tag = "3")]
+    pub message: Option<String>,
+    #[prost(string, optional, tag = "4")]
+    pub reason: Option<String>,
+    #[prost(message, optional, tag = "5")]
+    pub details: Option<StatusDetails>,
+    #[prost(int32, optional, tag = "6")]
+    pub code: Option<i32>,
+}
+
+/// `k8s.io.apimachinery.pkg.apis.meta.v1.StatusDetails`
+#[derive(Clone, PartialEq, Message)]
+pub struct StatusDetails {
+    #[prost(string, optional, tag = "1")]
+    pub name: Option<String>,
+    #[prost(string, optional, tag = "2")]
+    pub group: Option<String>,
+    #[prost(string, optional, tag = "3")]
+    pub kind: Option<String>,
+    #[prost(message, repeated, tag = "4")]
+    pub causes: Vec<StatusCause>,
+    #[prost(int32, optional, tag = "5")]
+    pub retry_after_seconds: Option<i32>,
+    #[prost(string, optional, tag = "6")]
+    pub uid: Option<String>,
+}
+
+/// `k8s.io.apimachinery.pkg.apis.meta.v1.StatusCause`
+#[derive(Clone, PartialEq, Message)]
+pub struct StatusCause {
+    #[prost(string, optional, tag = "1")]
+    pub reason: Option<String>,
+    #[prost(string, optional, tag = "2")]
+    pub message: Option<String>,
+    #[prost(string, optional, tag = "3")]
+    pub field: Option<String>,
+}
+
+/// Decode an `AdmissionReview` encoded with protobuf, together with its Kubernetes
+/// envelope
+pub fn decode_admission_review(body: &[u8]) -> Result<AdmissionReview> {
+    let envelope = body
+        .strip_prefix(MAGIC_NUMBER)
+        .ok_or_else(|| anyhow!("the body does not start with the Kubernetes protobuf prefix"))?;
+    let unknown = Unknown::decode(envelope)?;
+
+    if let Some(type_meta) = &unknown.type_meta {
+        let kind = type_meta.kind.as_deref().unwrap_or_default();
+        if !kind.is_empty() && kind != KIND {
+            return Err(anyhow!("expected an {KIND} object, got {kind}"));
+        }
+    }
+
+    Ok(AdmissionReview::decode(
+        unknown.raw.unwrap_or_default().as_slice(),
+    )?)
+}
+
+/// The objects embedded inside of the request, together with the name of their field
+pub fn embedded_objects(review: &AdmissionReview) -> Vec<(&'static str, &[u8])> {
+    let Some(request) = &review.request else {
+        return Vec::new();
+    };
+
+    [
+        ("object", &request.object),
+        ("oldObject", &request.old_object),
+        ("options", &request.options),
+    ]
+    .into_iter()
+    .filter_map(|(field, raw_extension)| {
+        raw_extension
+            .as_ref()
+            .and_then(|raw_extension| raw_extension.raw.as_deref())
+            .map(|raw| (field, raw))
+    })
+    .collect()
+}
+
+/// True when the embedded object is encoded with protobuf instead of JSON
+pub fn is_protobuf_encoded(object: &[u8]) -> bool {
+    object.starts_with(MAGIC_NUMBER)
+}
+
+/// Encode an `AdmissionReview` with protobuf, together with its Kubernetes envelope
+pub fn encode_admission_review(review: &AdmissionReview) -> Vec<u8> {
+    let unknown = Unknown {
+        type_meta: Some(TypeMeta {
+            api_version: Some(API_VERSION.to_owned()),
+            kind: Some(KIND.to_owned()),
+        }),
+        raw: Some(review.encode_to_vec()),
+        content_encoding: Some(String::new()),
+        content_type: Some(String::new()),
+    };
+
+    let mut body = MAGIC_NUMBER.to_vec();
+    unknown
+        .encode(&mut body)
+        .expect("a Vec grows to hold the encoded message");
+    body
+}
+
+impl TryFrom<AdmissionReview> for AdmissionReviewRequest {
+    type Error = anyhow::Error;
+
+    fn try_from(review: AdmissionReview) -> Result<Self> {
+        let request = review
+            .request
+            .ok_or_else(|| anyhow!("the AdmissionReview does not contain a request"))?;
+
+        let user_info = request.user_info.unwrap_or_default();
+        let user_info = JsonUserInfo {
+            username: user_info.username,
+            uid: user_info.uid,
+            groups: (!user_info.groups.is_empty()).then_some(user_info.groups),
+            extra: (!user_info.extra.is_empty()).then(|| {
+                user_info
+                    .extra
+                    .into_iter()
+                    .map(|(key, value)| (key, value.items))
+                    .collect::<BTreeMap<_, _>>()
+            }),
+        };
+
+        let request = json!({
+            "uid": request.uid.unwrap_or_default(),
+            "kind": group_version_kind_to_json(request.kind.unwrap_or_default()),
+            "resource": group_version_resource_to_json(request.resource.unwrap_or_default()),
+            "subResource": request.sub_resource,
+            "requestKind": request.request_kind.map(group_version_kind_to_json),
+            "requestResource": request.request_resource.map(group_version_resource_to_json),
+            "requestSubResource": request.request_sub_resource,
+            "name": request.name,
+            "namespace": request.namespace,
+            "operation": request.operation.unwrap_or_default(),
+            "userInfo": user_info,
+            "object": raw_extension_to_json("object", request.object)?,
+            "oldObject": raw_extension_to_json("oldObject", request.old_object)?,
+            "dryRun": request.dry_run,
+            "options": raw_extension_to_json("options", request.options)?,
+        });
+
+        Ok(AdmissionReviewRequest {
+            kind: Some(KIND.to_owned()),
+            api_version: Some(API_VERSION.to_owned()),
+            request: serde_json::from_value(request)?,
+        })
+    }
+}
+
+impl TryFrom<&AdmissionReviewRequest> for AdmissionReview {
+    type Error = anyhow::Error;
+
+    fn try_from(review: &AdmissionReviewRequest) -> Result<Self> {
+        let request = &review.request;
+
+        Ok(AdmissionReview {
+            request: Some(AdmissionRequest {
+                uid: Some(request.uid.clone()),
+                kind: Some(group_version_kind_to_protobuf(&request.kind)),
+                resource: Some(group_version_resource_to_protobuf(&request.resource)),
+                sub_resource: request.sub_resource.clone(),
+                name: request.name.clone(),
+                namespace: request.namespace.clone(),
+                operation: Some(request.operation.clone()),
+                user_info: Some(UserInfo {
+                    username: request.user_info.username.clone(),
+                    uid: request.user_info.uid.clone(),
+                    groups: request.user_info.groups.clone().unwrap_or_default(),
+                    extra: request
+                        .user_info
+                        .extra
+                        .iter()
+                        .flatten()
+                        .map(|(key, items)| {
+                            (
+                                key.clone(),
+                                ExtraValue {
+                                    items: items.clone(),
+                                },
+                            )
+                        })
+                        .collect(),
+                }),
+                object: raw_extension_to_protobuf(request.object.as_ref())?,
+                old_object: raw_extension_to_protobuf(request.old_object.as_ref())?,
+                dry_run: request.dry_run,
+                options: raw_extension_to_protobuf(request.options.as_ref())?,
+                request_kind: request
+                    .request_kind
+                    .as_ref()
+                    .map(group_version_kind_to_protobuf),
+                request_resource: request
+                    .request_resource
+                    .as_ref()
+                    .map(group_version_resource_to_protobuf),
+                request_sub_resource: request.request_sub_resource.clone(),
+            }),
+            response: None,
+        })
+    }
+}
+
+impl TryFrom<&AdmissionReviewResponse> for AdmissionReview {
+    type Error = anyhow::Error;
+
+    fn try_from(review: &AdmissionReviewResponse) -> Result<Self> {
+        let response = &review.response;
+        let patch = response
+            .patch
+            .as_ref()
+            .map(|patch| general_purpose::STANDARD.decode(patch))
+            .transpose()
+            .map_err(|e| anyhow!("cannot decode the patch of the response: {e}"))?;
+
+        Ok(AdmissionReview {
+            request: None,
+            response: Some(AdmissionResponse {
+                uid: Some(response.uid.clone()),
+                allowed: Some(response.allowed),
+                status: response.status.as_ref().map(status_to_protobuf),
+                patch,
+                patch_type: response.patch_type.as_ref().and_then(enum_to_string),
+                audit_annotations: response.audit_annotations.clone().unwrap_or_default(),
+                warnings: response.warnings.clone().unwrap_or_default(),
+            }),
+        })
+    }
+}
+
+fn group_version_kind_to_json(gvk: GroupVersionKind) -> serde_json::Value {
+    json!({
+        "group": gvk.group.unwrap_or_default(),
+        "version": gvk.version.unwrap_or_default(),
+        "kind": gvk.kind.unwrap_or_default(),
+    })
+}
+
+fn group_version_resource_to_json(gvr: GroupVersionResource) -> serde_json::Value {
+    json!({
+        "group": gvr.group.unwrap_or_default(),
+        "version": gvr.version.unwrap_or_default(),
+        "resource": gvr.resource.unwrap_or_default(),
+    })
+}
+
+fn group_version_kind_to_protobuf(gvk: &admission_request::GroupVersionKind) -> GroupVersionKind {
+    GroupVersionKind {
+        group: Some(gvk.group.clone()),
+        version: Some(gvk.version.clone()),
+        kind: Some(gvk.kind.clone()),
+    }
+}
+
+fn group_version_resource_to_protobuf(
+    gvr: &admission_request::GroupVersionResource,
+) -> GroupVersionResource {
+    GroupVersionResource {
+        group: Some(gvr.group.clone()),
+        version: Some(gvr.version.clone()),
+        resource: Some(gvr.resource.clone()),
+    }
+}
+
+/// The embedded objects must be encoded as JSON
+fn raw_extension_to_json(
+    field: &str,
+    raw_extension: Option<RawExtension>,
+) -> Result<Option<serde_json::Value>> {
+    let raw = match raw_extension.and_then(|raw_extension| raw_extension.raw) {
+        Some(raw) if !raw.is_empty() => raw,
+        _ => return Ok(None),
+    };
+    if is_protobuf_encoded(&raw) {
+        return Err(anyhow!(
+            "the {field} is encoded with protobuf, only the objects encoded as JSON are supported"
+        ));
+    }
+
+    serde_json::from_slice(&raw)
+        .map(Some)
+        .map_err(|e| anyhow!("the {field} is not a valid JSON object: {e}"))
+}
+
+fn raw_extension_to_protobuf(
+    raw_extension: Option<&JsonRawExtension>,
+) -> Result<Option<RawExtension>> {
+    raw_extension
+        .map(|raw_extension| {
+            Ok(RawExtension {
+                raw: Some(serde_json::to_vec(&raw_extension.0)?),
+            })
+        })
+        .transpose()
+}
+
+fn status_to_protobuf(status: &AdmissionResponseStatus) -> Status {
+    Status {
+        status: status.status.as_ref().and_then(enum_to_string),
+        message: status.message.clone(),
+        reason: status.reason.as_ref().and_then(enum_to_string),
+        details: status.details.as_ref().map(|details| StatusDetails {
+            name: details.name.clone(),
+            group: details.group.clone(),
+            kind: details.kind.clone(),
+            causes: details
+                .causes
+                .iter()
+                .map(|cause| StatusCause {
+                    reason: cause.reason.as_ref().and_then(enum_to_string),
+                    message: cause.message.clone(),
+                    field: cause.field.clone(),
+                })
+                .collect(),
+            retry_after_seconds: details.retry_after_seconds,
+            uid: details.uid.clone(),
+        }),
+        code: status.code.map(i32::from),
+    }
+}
+
+/// The string the enum is serialized to when encoded as JSON
+fn enum_to_string<T: Serialize>(value: &T) -> Option<String> {
+    serde_json::to_value(value)
+        .ok()
+        .and_then(|value| value.as_str().map(str::to_owned))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use policy_evaluator::admission_response::{
+        self, AdmissionResponseStatusValue, PatchType, StatusReason,
+    };
+
+    fn admission_review_request() -> AdmissionReviewRequest {
+        serde_json::from_value(json!({
+            "apiVersion": "admission.k8s.io/v1",
+            "kind": "AdmissionReview",
+            "request": {
+                "uid": "705ab4f5-6393-11e8-b7cc-42010a800002",
+                "kind": {"group": "", "version": "v1", "kind": "Pod"},
+                "resource": {"group": "", "version": "v1", "resource": "pods"},
+                "requestKind": {"group": "", "version": "v1", "kind": "Pod"},
+                "name": "nginx",
+                "namespace": "default",
+                "operation": "CREATE",
+                "userInfo": {
+                    "username": "alice",
+                    "groups": ["developers"],
+                    "extra": {"scopes": ["read", "write"]}
+                },
+                "object": {"apiVersion": "v1", "kind": "Pod", "metadata": {"name": "nginx"}},
+                "dryRun": false
+            }
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn admission_review_request_round_trip() {
+        let review = admission_review_request();
+
+        let encoded = encode_admission_review(&AdmissionReview::try_from(&review).unwrap());
+        assert!(encoded.starts_with(MAGIC_NUMBER));
+
+        let decoded =
+            AdmissionReviewRequest::try_from(decode_admission_review(&encoded).unwrap()).unwrap();
+        assert_eq!(
+            serde_json::to_value(&decoded).unwrap(),
+            serde_json::to_value(&review).unwrap()
+        );
+    }
+
+    #[test]
+    fn admission_review_response() {
+        let review = AdmissionReviewResponse::new(admission_response::AdmissionResponse {
+            uid: "uid".to_owned(),
+            allowed: false,
+            patch_type: Some(PatchType::JSONPatch),
+            patch: Some(general_purpose::STANDARD.encode(r#"[{"op":"remove","path":"/a"}]"#)),
+            status: Some(AdmissionResponseStatus {
+                status: Some(AdmissionResponseStatusValue::Failure),
+                message: Some("not allowed".to_owned()),
+                reason: Some(StatusReason::Forbidden),
+                code: Some(403),
+                ..Default::default()
+            }),
+            audit_annotations: None,
+            warnings: Some(vec!["careful".to_owned()]),
+        });
+
+        let encoded = encode_admission_review(&AdmissionReview::try_from(&review).unwrap());
+        let response = decode_admission_review(&encoded).unwrap().response.unwrap();
+
+        assert_eq!(
+            response,
+            AdmissionResponse {
+                uid: Some("uid".to_owned()),
+                allowed: Some(false),
+                status: Some(Status {
+                    status: Some("Failure".to_owned()),
+                    message: Some("not allowed".to_owned()),
+                    reason: Some("Forbidden".to_owned()),
+                    details: None,
+                    code: Some(403),
+                }),
+                patch: Some(br#"[{"op":"remove","path":"/a"}]"#.to_vec()),
+                patch_type: Some("JSONPatch".to_owned()),
+                audit_annotations: HashMap::new(),
+                warnings: vec!["careful".to_owned()],
+            }
+        );
+    }
+
+    #[test]
+    fn missing_magic_number() {
+        let review = AdmissionReview::try_from(&admission_review_request()).unwrap();
+
+        assert!(decode_admission_review(&review.encode_to_vec()).is_err());
+    }
+
+    #[test]
+    fn unexpected_kind() {
+        let unknown = Unknown {
+            type_meta: Some(TypeMeta {
+                api_version: Some("v1".to_owned()),
+                kind: Some("Pod".to_owned()),
+            }),
+            ..Default::default()
+        };
+        let mut body = MAGIC_NUMBER.to_vec();
+        body.extend(unknown.encode_to_vec());
+
+        assert!(decode_admission_review(&body).is_err());
+    }
+
+    #[test]
+    fn objects_encoded_with_protobuf_are_rejected() {
+        let mut review = AdmissionReview::try_from(&admission_review_request()).unwrap();
+        let mut object = MAGIC_NUMBER.to_vec();
+        object.extend(Unknown::default().encode_to_vec());
+        review.request.as_mut().unwrap().object = Some(RawExtension { raw: Some(object) });
+
+        let error = AdmissionReviewRequest::try_from(review).unwrap_err();
+
+        assert!(error
+            .to_string()
+            .contains("object is encoded with protobuf"));
+    }
+
+    #[test]
+    fn missing_request() {
+        assert!(AdmissionReviewRequest::try_from(AdmissionReview::default()).is_err());
+    }
+}
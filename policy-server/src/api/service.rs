@@ -3,11 +3,13 @@ use std::{fmt, sync::Arc};
 use policy_evaluator::{
     admission_response::AdmissionResponse,
     admission_response_handler::{
-        errors::EvaluationError, policy_id::PolicyID, AdmissionResponseHandler,
+        errors::EvaluationError, failure_policy::FailurePolicy, policy_id::PolicyID,
+        AdmissionResponseHandler,
     },
     policy_evaluator::ValidateRequest,
 };
 use tokio::time::Instant;
+use tracing::{warn, Span};
 
 use crate::{evaluation::EvaluationEnvironment, metrics};
 
@@ -33,6 +35,10 @@ pub(crate) fn evaluate(
 ) -> Result<AdmissionResponse, EvaluationError> {
     let start_time = Instant::now();
     let policy_id: PolicyID = policy_id.parse()?;
+    let policy_stable_id = evaluation_environment.get_policy_stable_id(&policy_id);
+    if let Some(policy_stable_id) = &policy_stable_id {
+        Span::current().record("policy_stable_id", policy_stable_id.as_str());
+    }
 
     // Early check for requests from special namespaces
     if let ValidateRequest::AdmissionRequest(adm_req) = validate_request {
@@ -43,6 +49,7 @@ pub(crate) fn evaluate(
                 // Record metrics for requests from special namespaces
                 let policy_evaluation_metric = metrics::PolicyEvaluation {
                     policy_name: policy_id.to_string(),
+                    policy_stable_id: policy_stable_id.clone(),
                     policy_mode: evaluation_environment.get_policy_mode(&policy_id)?.into(),
                     resource_namespace: adm_req.clone().namespace,
                     resource_kind: adm_req.clone().request_kind.unwrap_or_default().kind,
@@ -91,6 +98,12 @@ pub(crate) fn evaluate(
         Err(error) => return Err(error),
     };
 
+    // The response must not be altered by the failure policy when reporting to the
+    // audit scanner, which needs to know the actual outcome of the evaluation
+    let failed_open = matches!(request_origin, RequestOrigin::Validate)
+        && vanilla_validation_response.is_internal_server_error()
+        && evaluation_environment.get_policy_failure_policy(&policy_id)? == FailurePolicy::Ignore;
+
     let policy_mode = evaluation_environment.get_policy_mode(&policy_id)?;
     let allowed_to_mutate = evaluation_environment.get_policy_allowed_to_mutate(&policy_id)?;
     let custom_rejection_message =
@@ -113,6 +126,14 @@ pub(crate) fn evaluate(
     );
 
     let validation_response = match request_origin {
+        RequestOrigin::Validate if failed_open => {
+            metrics::add_failed_open_evaluation(&metrics::FailedOpenEvaluation {
+                policy_name: policy_id.to_string(),
+                policy_stable_id: policy_stable_id.clone(),
+            });
+            admission_response_handler
+                .process_response(fail_open(&policy_id, vanilla_validation_response))
+        }
         RequestOrigin::Validate => {
             admission_response_handler.process_response(vanilla_validation_response)
         }
@@ -123,6 +144,7 @@ pub(crate) fn evaluate(
         ValidateRequest::AdmissionRequest(adm_req) => {
             let policy_evaluation_metric = metrics::PolicyEvaluation {
                 policy_name: policy_id.to_string(),
+                policy_stable_id: policy_stable_id.clone(),
                 policy_mode: policy_mode.into(),
                 resource_namespace: adm_req.clone().namespace,
                 resource_kind: adm_req.clone().request_kind.unwrap_or_default().kind,
@@ -135,9 +157,10 @@ pub(crate) fn evaluate(
             metrics::record_policy_latency(policy_evaluation_duration, &policy_evaluation_metric);
             metrics::add_policy_evaluation(&policy_evaluation_metric);
         }
-        ValidateRequest::Raw(_) => {
+        ValidateRequest::Raw(_) | ValidateRequest::CloudEvent(_) => {
             let raw_policy_evaluation_metric = metrics::RawPolicyEvaluation {
                 policy_name: policy_id.to_string(),
+                policy_stable_id: policy_stable_id.clone(),
                 policy_mode: policy_mode.into(),
                 accepted,
                 mutated,
@@ -153,13 +176,35 @@ pub(crate) fn evaluate(
     Ok(validation_response)
 }
 
+/// Accept a request the policy failed to evaluate, the error is reported to the
+/// user as a warning
+fn fail_open(policy_id: &PolicyID, failed_response: AdmissionResponse) -> AdmissionResponse {
+    let error = failed_response
+        .status
+        .and_then(|status| status.message)
+        .unwrap_or_default();
+    warn!(
+        policy_id = policy_id.to_string(),
+        error, "policy evaluation failed, accepting the request because of its failure policy"
+    );
+
+    AdmissionResponse {
+        uid: failed_response.uid,
+        allowed: true,
+        warnings: Some(vec![format!(
+            "{policy_id}: evaluation failed, request accepted by the Ignore failure policy: {error}"
+        )]),
+        ..Default::default()
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
 
     use crate::test_utils::build_admission_review_request;
     use policy_evaluator::admission_response_handler::{
-        policy_id::PolicyID, policy_mode::PolicyMode,
+        failure_policy::FailurePolicy, policy_id::PolicyID, policy_mode::PolicyMode,
     };
 
     use lazy_static::lazy_static;
@@ -195,6 +240,9 @@ mod tests {
         mock_evaluation_environment
             .expect_get_policy_custom_rejection_message()
             .returning(|_policy_id| Ok(None));
+        mock_evaluation_environment
+            .expect_get_policy_stable_id()
+            .returning(|policy_id| Some(format!("{policy_id}@0123456789ab")));
 
         mock_evaluation_environment
     }
@@ -232,6 +280,43 @@ mod tests {
         mock_evaluation_environment
             .expect_get_policy_custom_rejection_message()
             .returning(|_policy_id| Ok(None));
+        mock_evaluation_environment
+            .expect_get_policy_stable_id()
+            .returning(|policy_id| Some(format!("{policy_id}@0123456789ab")));
+
+        mock_evaluation_environment
+    }
+
+    fn create_evaluation_environment_that_fails_evaluation(
+        failure_policy: FailurePolicy,
+    ) -> EvaluationEnvironment {
+        let mut mock_evaluation_environment = EvaluationEnvironment::default();
+        mock_evaluation_environment
+            .expect_validate()
+            .returning(|_policy_id, request| {
+                Ok(AdmissionResponse::reject_internal_server_error(
+                    request.uid().to_owned(),
+                    "wasm trap: interrupt".to_owned(),
+                ))
+            });
+        mock_evaluation_environment
+            .expect_get_policy_failure_policy()
+            .returning(move |_policy_id| Ok(failure_policy));
+        mock_evaluation_environment
+            .expect_get_policy_mode()
+            .returning(|_policy_id| Ok(PolicyMode::Protect));
+        mock_evaluation_environment
+            .expect_get_policy_allowed_to_mutate()
+            .returning(|_policy_id| Ok(false));
+        mock_evaluation_environment
+            .expect_should_always_accept_requests_made_inside_of_namespace()
+            .returning(|_namespace| false);
+        mock_evaluation_environment
+            .expect_get_policy_custom_rejection_message()
+            .returning(|_policy_id| Ok(None));
+        mock_evaluation_environment
+            .expect_get_policy_stable_id()
+            .returning(|policy_id| Some(format!("{policy_id}@0123456789ab")));
 
         mock_evaluation_environment
     }
@@ -387,4 +472,36 @@ mod tests {
         assert!(response.allowed);
         assert!(response.status.is_none());
     }
+
+    #[rstest]
+    #[case::fail_closed(FailurePolicy::Fail, RequestOrigin::Validate, false)]
+    #[case::fail_open(FailurePolicy::Ignore, RequestOrigin::Validate, true)]
+    #[case::audit_is_not_affected(FailurePolicy::Ignore, RequestOrigin::Audit, false)]
+    fn evaluate_policy_evaluator_fails(
+        #[case] failure_policy: FailurePolicy,
+        #[case] request_origin: RequestOrigin,
+        #[case] accept: bool,
+    ) {
+        let evaluation_environment =
+            create_evaluation_environment_that_fails_evaluation(failure_policy);
+        let validate_request =
+            ValidateRequest::AdmissionRequest(Box::new(build_admission_review_request().request));
+
+        let response = evaluate(
+            Arc::new(evaluation_environment),
+            "test_policy1",
+            &validate_request,
+            request_origin,
+        )
+        .unwrap();
+
+        assert_eq!(response.allowed, accept);
+        if accept {
+            assert!(response.status.is_none());
+            let warnings = response.warnings.expect("should warn about the failure");
+            assert!(warnings[0].contains("wasm trap: interrupt"));
+        } else {
+            assert_eq!(response.status.expect("should be set").code, Some(500));
+        }
+    }
 }
@@ -1,9 +1,23 @@
-use tokio::sync::Semaphore;
-
+use crate::api::dispatcher::PriorityDispatcher;
+use crate::config::PriorityConfig;
+use crate::decision_log::DecisionLog;
+use crate::enrichment::EnrichmentConfig;
 use crate::evaluation::EvaluationEnvironment;
+use crate::journal::DecisionJournal;
+use policy_evaluator::callback_handler::KubernetesHealthReporter;
 use std::sync::Arc;
 
 pub(crate) struct ApiServerState {
-    pub(crate) semaphore: Semaphore,
+    pub(crate) dispatcher: PriorityDispatcher,
+    pub(crate) priority_config: PriorityConfig,
     pub(crate) evaluation_environment: Arc<EvaluationEnvironment>,
+    pub(crate) decision_journal: Option<Arc<DecisionJournal>>,
+    pub(crate) decision_log: Option<DecisionLog>,
+    pub(crate) enrichment: EnrichmentConfig,
+    /// Maximum size of the body of the validation requests, in bytes
+    pub(crate) max_request_body_size: usize,
+    /// Maximum nesting depth of the JSON body of the validation requests
+    pub(crate) max_request_body_depth: usize,
+    /// Not set when Policy Server is not connected to Kubernetes
+    pub(crate) kubernetes_health_reporter: Option<KubernetesHealthReporter>,
 }
@@ -68,6 +68,12 @@ pub(crate) fn build_cli() -> Command {
             .env("KUBEWARDEN_PORT")
             .help("Listen on PORT"),
 
+        Arg::new("readiness-probe-address")
+            .long("readiness-probe-addr")
+            .value_name("BIND_ADDRESS")
+            .env("KUBEWARDEN_READINESS_PROBE_BIND_ADDRESS")
+            .help("Bind the readiness endpoint against ADDRESS. Defaults to the address of the API server"),
+
         Arg::new("readiness-probe-port")
             .long("readiness-probe-port")
             .value_name("READINESS_PROBE_PORT")
@@ -81,6 +87,58 @@ pub(crate) fn build_cli() -> Command {
             .env("KUBEWARDEN_WORKERS")
             .help("Number of worker threads to create"),
 
+        Arg::new("evaluator-pool-size")
+            .long("evaluator-pool-size")
+            .value_name("INSTANCES")
+            .env("KUBEWARDEN_EVALUATOR_POOL_SIZE")
+            .default_value("0")
+            .help("Number of warm instances kept for each policy and reused across evaluations. When 0, a new instance is created for each evaluation"),
+
+        Arg::new("evaluator-pool-max-uses")
+            .long("evaluator-pool-max-uses")
+            .value_name("EVALUATIONS")
+            .env("KUBEWARDEN_EVALUATOR_POOL_MAX_USES")
+            .default_value("100")
+            .help("Number of evaluations performed by a pooled instance before it's replaced by a new one. When 0, the instances are reused forever"),
+
+        Arg::new("evaluation-cache-size")
+            .long("evaluation-cache-size")
+            .value_name("ENTRIES")
+            .env("KUBEWARDEN_EVALUATION_CACHE_SIZE")
+            .default_value("0")
+            .help("Maximum number of evaluation results cached and reused for identical requests. The results of the context aware policies, and of the evaluations that invoked a host capability, are never cached. When 0, the cache is disabled"),
+
+        Arg::new("evaluation-cache-ttl")
+            .long("evaluation-cache-ttl")
+            .value_name("SECONDS")
+            .env("KUBEWARDEN_EVALUATION_CACHE_TTL")
+            .default_value("30")
+            .help("For how long a cached evaluation result is reused"),
+
+        Arg::new("module-cache-dir")
+            .long("module-cache-dir")
+            .value_name("DIR")
+            .env("KUBEWARDEN_MODULE_CACHE_DIR")
+            .value_parser(clap::builder::PathBufValueParser::new())
+            .requires("module-cache-key-file")
+            .help("Directory where the compiled Wasm modules are cached. It can be a volume shared by all the replicas, which then skip the compilation of the modules already compiled by the others"),
+
+        Arg::new("module-cache-key-file")
+            .long("module-cache-key-file")
+            .value_name("KEY_FILE")
+            .env("KUBEWARDEN_MODULE_CACHE_KEY_FILE")
+            .value_parser(clap::builder::PathBufValueParser::new())
+            .requires("module-cache-dir")
+            .help("File holding the secret key used to authenticate the entries of the module cache. All the replicas sharing the cache must use the same key, the entries that cannot be authenticated are compiled again"),
+
+        Arg::new("module-cache-peers")
+            .long("module-cache-peers")
+            .value_name("URLS")
+            .env("KUBEWARDEN_MODULE_CACHE_PEERS")
+            .value_delimiter(',')
+            .requires("module-cache-dir")
+            .help("Comma separated list of the URLs of the replicas the compiled modules missing from the module cache are fetched from, e.g. the one of a headless service. All the addresses a host resolves to are tried"),
+
         Arg::new("cert-file")
             .long("cert-file")
             .value_name("CERT_FILE")
@@ -103,6 +161,35 @@ pub(crate) fn build_cli() -> Command {
             .value_parser(clap::builder::PathBufValueParser::new())
             .help("Path to an CA certificate file that issued the client certificate. Required to enable mTLS"),
 
+        Arg::new("readiness-probe-cert-file")
+            .long("readiness-probe-cert-file")
+            .value_name("CERT_FILE")
+            .env("KUBEWARDEN_READINESS_PROBE_CERT_FILE")
+            .value_parser(clap::builder::PathBufValueParser::new())
+            .help("Path to an X.509 certificate file used to serve the readiness endpoint over HTTPS"),
+
+        Arg::new("readiness-probe-key-file")
+            .long("readiness-probe-key-file")
+            .value_name("KEY_FILE")
+            .env("KUBEWARDEN_READINESS_PROBE_KEY_FILE")
+            .value_parser(clap::builder::PathBufValueParser::new())
+            .help("Path to an X.509 private key file used to serve the readiness endpoint over HTTPS"),
+
+        Arg::new("readiness-probe-client-ca-file")
+            .long("readiness-probe-client-ca-file")
+            .value_delimiter(',')
+            .value_name("CLIENT_CA_FILE")
+            .env("KUBEWARDEN_READINESS_PROBE_CLIENT_CA_FILE")
+            .value_parser(clap::builder::PathBufValueParser::new())
+            .help("Path to an CA certificate file that issued the client certificate of the readiness endpoint. Required to enable mTLS on the readiness endpoint"),
+
+        Arg::new("readiness-probe-reuse-tls")
+            .long("readiness-probe-reuse-tls")
+            .env("KUBEWARDEN_READINESS_PROBE_REUSE_TLS")
+            .action(ArgAction::SetTrue)
+            .conflicts_with_all(["readiness-probe-cert-file", "readiness-probe-key-file"])
+            .help("Serve the readiness endpoint over HTTPS using the certificate and key given with --cert-file and --key-file"),
+
         Arg::new("policies")
             .long("policies")
             .value_name("POLICIES_FILE")
@@ -117,6 +204,21 @@ pub(crate) fn build_cli() -> Command {
             .env("KUBEWARDEN_POLICIES_DOWNLOAD_DIR")
             .help("Download path for the policies"),
 
+        Arg::new("policies-signature")
+            .long("policies-signature")
+            .value_name("POLICIES_SIGNATURE_FILE")
+            .env("KUBEWARDEN_POLICIES_SIGNATURE")
+            .value_parser(clap::builder::PathBufValueParser::new())
+            .requires("policies-verification-key")
+            .help("Path to the signature of the policies file, as produced by `cosign sign-blob`. Defaults to the policies file path with the `.sig` suffix"),
+
+        Arg::new("policies-verification-key")
+            .long("policies-verification-key")
+            .value_name("POLICIES_VERIFICATION_KEY_FILE")
+            .env("KUBEWARDEN_POLICIES_VERIFICATION_KEY")
+            .value_parser(clap::builder::PathBufValueParser::new())
+            .help("Path to the PEM encoded public key used to verify the signature of the policies file. When set, Policy Server refuses to start if the policies file is not signed with the matching private key"),
+
         Arg::new("sigstore-cache-dir")
             .long("sigstore-cache-dir")
             .value_name("SIGSTORE_CACHE_DIR")
@@ -136,6 +238,13 @@ pub(crate) fn build_cli() -> Command {
             .env("KUBEWARDEN_VERIFICATION_CONFIG_PATH")
             .help("YAML file holding verification information (URIs, keys, annotations...)"),
 
+        Arg::new("policy-reverification-interval")
+            .long("policy-reverification-interval")
+            .env("KUBEWARDEN_POLICY_REVERIFICATION_INTERVAL")
+            .value_name("SECONDS")
+            .requires("verification-path")
+            .help("Periodically verify again the policies against the verification config. The policies failing the verification stop accepting requests. Disabled by default"),
+
         Arg::new("docker-config-json-path")
             .long("docker-config-json-path")
             .value_name("DOCKER_CONFIG")
@@ -168,6 +277,64 @@ pub(crate) fn build_cli() -> Command {
             .default_value("2")
             .help("Interrupt policy evaluation after the given time"),
 
+        Arg::new("policy-settings-validation-timeout")
+            .long("policy-settings-validation-timeout")
+            .env("KUBEWARDEN_POLICY_SETTINGS_VALIDATION_TIMEOUT")
+            .value_name("MAXIMUM_EXECUTION_TIME_SECONDS")
+            .help("Interrupt the validation of the policy settings after the given time. Defaults to the value of --policy-timeout"),
+
+        Arg::new("policy-timeout-tick-interval")
+            .long("policy-timeout-tick-interval")
+            .env("KUBEWARDEN_POLICY_TIMEOUT_TICK_INTERVAL")
+            .value_name("MILLISECONDS")
+            .default_value("1000")
+            .help("How often the time spent by the policies is checked against their timeout. Smaller values make the timeouts more accurate, at the cost of some overhead"),
+
+        Arg::new("rego-policy-memory-limit")
+            .long("rego-policy-memory-limit")
+            .env("KUBEWARDEN_REGO_POLICY_MEMORY_LIMIT")
+            .value_name("BYTES")
+            .help("Limit the memory of OPA and Gatekeeper policies. The requests whose input would not fit into the memory of the policy are rejected before being evaluated"),
+
+        Arg::new("max-request-body-depth")
+            .long("max-request-body-depth")
+            .env("KUBEWARDEN_MAX_REQUEST_BODY_DEPTH")
+            .value_name("DEPTH")
+            .default_value("128")
+            .help("Maximum nesting depth of the objects and arrays of the JSON body of validation requests, up to 128. Deeper requests are rejected with a 400 response, before the whole body is received"),
+
+        Arg::new("max-request-body-size")
+            .long("max-request-body-size")
+            .env("KUBEWARDEN_MAX_REQUEST_BODY_SIZE")
+            .value_name("BYTES")
+            .default_value("8388608")
+            .help("Maximum size of the body of validation requests. Bigger requests are rejected with a 413 response"),
+
+        Arg::new("disable-response-compression")
+            .long("disable-response-compression")
+            .env("KUBEWARDEN_DISABLE_RESPONSE_COMPRESSION")
+            .action(ArgAction::SetTrue)
+            .help("Do not compress the responses, even when the client accepts gzip or deflate encoded ones"),
+
+        Arg::new("disable-request-projection")
+            .long("disable-request-projection")
+            .env("KUBEWARDEN_DISABLE_REQUEST_PROJECTION")
+            .action(ArgAction::SetTrue)
+            .help("Send the whole AdmissionRequest to all the policies, ignoring the request projection declared inside of their metadata"),
+
+        Arg::new("redact-secret-data")
+            .long("redact-secret-data")
+            .env("KUBEWARDEN_REDACT_SECRET_DATA")
+            .action(ArgAction::SetTrue)
+            .help("Mask the values of the Secrets given to the policies, keeping their keys and lengths. Policies can opt out by setting `secretData: revealed`"),
+
+        Arg::new("cluster-name")
+            .long("cluster-name")
+            .value_name("NAME")
+            .env("KUBEWARDEN_CLUSTER_NAME")
+            .required(false)
+            .help("Name of the cluster served by Policy Server, given to the policies that use the v2 request envelope"),
+
         Arg::new("daemon")
             .long("daemon")
             .env("KUBEWARDEN_DAEMON")
@@ -204,6 +371,260 @@ pub(crate) fn build_cli() -> Command {
             .action(ArgAction::SetTrue)
             .help("Enable pprof profiling"),
 
+        Arg::new("enable-log-filter-admin")
+            .long("enable-log-filter-admin")
+            .env("KUBEWARDEN_ENABLE_LOG_FILTER_ADMIN")
+            .action(ArgAction::SetTrue)
+            .help("Enable the /admin/log-filter endpoint, used to change the tracing filter at runtime. The endpoint is served on the readiness probe port"),
+
+        Arg::new("log-filter-file")
+            .long("log-filter-file")
+            .value_name("LOG_FILTER_FILE")
+            .env("KUBEWARDEN_LOG_FILTER_FILE")
+            .required(false)
+            .help("Path to a YAML file holding extra tracing directives (`directives` and `ttlSeconds` keys). The file is loaded each time the process receives SIGUSR1"),
+
+        Arg::new("lazy-policy-loading")
+            .long("lazy-policy-loading")
+            .env("KUBEWARDEN_LAZY_POLICY_LOADING")
+            .action(ArgAction::SetTrue)
+            .help("Compile policies the first time they are evaluated, instead of doing that at bootstrap time. Policies are still downloaded and verified at bootstrap time. This reduces the startup time when many policies are defined, at the cost of a slower first evaluation. The members of policy groups are always compiled at bootstrap time"),
+
+        Arg::new("lazy-policy-warm-up")
+            .long("lazy-policy-warm-up")
+            .value_delimiter(',')
+            .value_name("POLICY_IDS")
+            .env("KUBEWARDEN_LAZY_POLICY_WARM_UP")
+            .requires("lazy-policy-loading")
+            .help("Comma separated list of policies to be compiled and instantiated in the background right after startup, in the given order. Used only when lazy policy loading is enabled"),
+
+        Arg::new("readiness-requires-kubernetes-sync")
+            .long("readiness-requires-kubernetes-sync")
+            .env("KUBEWARDEN_READINESS_REQUIRES_KUBERNETES_SYNC")
+            .action(ArgAction::SetTrue)
+            .help("Report context aware policies as `syncing` on the `/readyz` endpoint until the Kubernetes resources they are allowed to access have been listed for the first time. The resources are listed right after startup"),
+
+        Arg::new("decision-journal-dir")
+            .long("decision-journal-dir")
+            .value_name("DIR")
+            .env("KUBEWARDEN_DECISION_JOURNAL_DIR")
+            .help("Record each admission decision inside of a write-ahead journal stored in the given directory. Decisions are flushed to disk before the response is sent"),
+
+        Arg::new("decision-journal-fail-closed")
+            .long("decision-journal-fail-closed")
+            .env("KUBEWARDEN_DECISION_JOURNAL_FAIL_CLOSED")
+            .action(ArgAction::SetTrue)
+            .requires("decision-journal-dir")
+            .help("Reject the requests whose decision cannot be written to the decision journal. By default the failure is only logged"),
+
+        Arg::new("decision-journal-segment-size")
+            .long("decision-journal-segment-size")
+            .value_name("BYTES")
+            .env("KUBEWARDEN_DECISION_JOURNAL_SEGMENT_SIZE")
+            .default_value("67108864")
+            .help("Size after which a new segment of the decision journal is started"),
+
+        Arg::new("decision-journal-max-segments")
+            .long("decision-journal-max-segments")
+            .value_name("SEGMENTS")
+            .env("KUBEWARDEN_DECISION_JOURNAL_MAX_SEGMENTS")
+            .help("Number of segments of the decision journal to be retained, the oldest ones are removed. All the segments are kept when not set"),
+
+        Arg::new("decision-log-file")
+            .long("decision-log-file")
+            .value_name("PATH")
+            .env("KUBEWARDEN_DECISION_LOG_FILE")
+            .conflicts_with("decision-log-url")
+            .help("Write a structured JSON record for each evaluation to the given file. The records are written in the background, the evaluations never wait for them"),
+
+        Arg::new("decision-log-url")
+            .long("decision-log-url")
+            .value_name("URL")
+            .env("KUBEWARDEN_DECISION_LOG_URL")
+            .help("Send a structured JSON record for each evaluation to the given HTTP endpoint. The records are sent in batches, with POST requests using the JSON Lines format"),
+
+        Arg::new("decision-log-max-file-size")
+            .long("decision-log-max-file-size")
+            .value_name("BYTES")
+            .env("KUBEWARDEN_DECISION_LOG_MAX_FILE_SIZE")
+            .default_value("104857600")
+            .help("Size after which the decision log file is rotated"),
+
+        Arg::new("decision-log-max-files")
+            .long("decision-log-max-files")
+            .value_name("FILES")
+            .env("KUBEWARDEN_DECISION_LOG_MAX_FILES")
+            .default_value("5")
+            .help("Number of rotated decision log files to be retained, the oldest ones are removed"),
+
+        Arg::new("decision-log-queue-size")
+            .long("decision-log-queue-size")
+            .value_name("RECORDS")
+            .env("KUBEWARDEN_DECISION_LOG_QUEUE_SIZE")
+            .default_value("10000")
+            .help("Number of decision log records waiting to be written. Once reached, the new records are dropped and counted by the `kubewarden_decision_log_records_dropped_total` metric"),
+
+        Arg::new("decision-log-batch-size")
+            .long("decision-log-batch-size")
+            .value_name("RECORDS")
+            .env("KUBEWARDEN_DECISION_LOG_BATCH_SIZE")
+            .default_value("100")
+            .help("Maximum number of decision log records written, or sent, at once"),
+
+        Arg::new("policy-fetch-concurrency")
+            .long("policy-fetch-concurrency")
+            .value_name("OPERATIONS")
+            .env("KUBEWARDEN_POLICY_FETCH_CONCURRENCY")
+            .default_value("4")
+            .help("Maximum number of policies downloaded and verified at the same time during bootstrap"),
+
+        Arg::new("registry-politeness-delay")
+            .long("registry-politeness-delay")
+            .value_name("MILLISECONDS")
+            .env("KUBEWARDEN_REGISTRY_POLITENESS_DELAY")
+            .default_value("0")
+            .help("Minimum delay between two operations made against the same registry during bootstrap"),
+
+        Arg::new("policy-fetch-max-retries")
+            .long("policy-fetch-max-retries")
+            .value_name("RETRIES")
+            .env("KUBEWARDEN_POLICY_FETCH_MAX_RETRIES")
+            .default_value("3")
+            .help("How many times a download or verification rate limited by the registry, or failed because of a transient error like a connection timeout or a HTTP 5xx response, is retried. The delay requested by the Retry-After header is honored, otherwise an exponential backoff is used"),
+
+        Arg::new("priority-critical-namespaces")
+            .long("priority-critical-namespaces")
+            .value_name("NAMESPACES")
+            .env("KUBEWARDEN_PRIORITY_CRITICAL_NAMESPACES")
+            .default_value("kube-system,kube-node-lease")
+            .help("Comma separated list of namespaces whose admission requests are evaluated before the other ones. Requests about Nodes are always evaluated first, audit requests last"),
+
+        Arg::new("priority-starvation-threshold")
+            .long("priority-starvation-threshold")
+            .value_name("REQUESTS")
+            .env("KUBEWARDEN_PRIORITY_STARVATION_THRESHOLD")
+            .default_value("8")
+            .help("How many times a waiting request can be overtaken by requests with a higher priority, before being evaluated"),
+
+        Arg::new("capabilities-client-pool-size")
+            .long("capabilities-client-pool-size")
+            .value_name("CLIENTS")
+            .env("KUBEWARDEN_CAPABILITIES_CLIENT_POOL_SIZE")
+            .default_value("16")
+            .help("Maximum number of registry clients reused by the host capabilities, each one keeping its own connections. Set to 0 to disable the reuse"),
+
+        Arg::new("capabilities-client-idle-timeout")
+            .long("capabilities-client-idle-timeout")
+            .value_name("SECONDS")
+            .env("KUBEWARDEN_CAPABILITIES_CLIENT_IDLE_TIMEOUT")
+            .default_value("90")
+            .help("Registry clients of the host capabilities that have not been used for this long are closed"),
+
+        Arg::new("capabilities-dns-cache-ttl")
+            .long("capabilities-dns-cache-ttl")
+            .value_name("SECONDS")
+            .env("KUBEWARDEN_CAPABILITIES_DNS_CACHE_TTL")
+            .default_value("30")
+            .help("For how long the DNS lookups performed by the policies are cached"),
+
+        Arg::new("capabilities-dns-cache-size")
+            .long("capabilities-dns-cache-size")
+            .value_name("HOSTS")
+            .env("KUBEWARDEN_CAPABILITIES_DNS_CACHE_SIZE")
+            .default_value("1024")
+            .help("Maximum number of hosts kept inside of the DNS cache. Set to 0 to disable the cache"),
+
+        Arg::new("capabilities-kubernetes-coalescing-window")
+            .long("capabilities-kubernetes-coalescing-window")
+            .value_name("MILLISECONDS")
+            .env("KUBEWARDEN_CAPABILITIES_KUBERNETES_COALESCING_WINDOW")
+            .default_value("10")
+            .help("For how long the identical Kubernetes requests made by the policies are batched together, to be served by a single read. Set to 0 to batch only the requests made while an identical one is in flight"),
+
+        Arg::new("capabilities-response-size-limit")
+            .long("capabilities-response-size-limit")
+            .value_name("BYTES")
+            .env("KUBEWARDEN_CAPABILITIES_RESPONSE_SIZE_LIMIT")
+            .help("Maximum size of the responses given back to the policies by the host capabilities. Larger responses are replaced by an error asking the policy to narrow its request. Not limited when not set"),
+
+        Arg::new("capabilities-response-size-limits")
+            .long("capabilities-response-size-limits")
+            .value_name("CAPABILITY=BYTES,...")
+            .env("KUBEWARDEN_CAPABILITIES_RESPONSE_SIZE_LIMITS")
+            .help("Maximum size of the responses of some host capabilities, overriding --capabilities-response-size-limit. For example: `kubernetes/list_resources_all=1048576,oci/oci_manifest=65536`"),
+
+        Arg::new("capabilities-key-value-store")
+            .long("capabilities-key-value-store")
+            .env("KUBEWARDEN_CAPABILITIES_KEY_VALUE_STORE")
+            .action(ArgAction::SetTrue)
+            .help("Enable the in-memory key/value store offered to the policies that opt in. The store is not shared with the other replicas and is lost on restart"),
+
+        Arg::new("capabilities-key-value-store-max-namespaces")
+            .long("capabilities-key-value-store-max-namespaces")
+            .value_name("NAMESPACES")
+            .env("KUBEWARDEN_CAPABILITIES_KEY_VALUE_STORE_MAX_NAMESPACES")
+            .default_value("64")
+            .help("Maximum number of namespaces of each policy inside of the key/value store"),
+
+        Arg::new("capabilities-key-value-store-max-entries")
+            .long("capabilities-key-value-store-max-entries")
+            .value_name("ENTRIES")
+            .env("KUBEWARDEN_CAPABILITIES_KEY_VALUE_STORE_MAX_ENTRIES")
+            .default_value("1024")
+            .help("Maximum number of entries of each namespace of the key/value store"),
+
+        Arg::new("capabilities-key-value-store-max-value-size")
+            .long("capabilities-key-value-store-max-value-size")
+            .value_name("BYTES")
+            .env("KUBEWARDEN_CAPABILITIES_KEY_VALUE_STORE_MAX_VALUE_SIZE")
+            .default_value("4096")
+            .help("Maximum size of the values of the key/value store, once serialized to JSON"),
+
+        Arg::new("capabilities-key-value-store-max-ttl")
+            .long("capabilities-key-value-store-max-ttl")
+            .value_name("SECONDS")
+            .env("KUBEWARDEN_CAPABILITIES_KEY_VALUE_STORE_MAX_TTL")
+            .default_value("3600")
+            .help("Maximum time to live of the entries of the key/value store, also given to the entries written without one"),
+
+        Arg::new("capabilities-usage-analysis-window")
+            .long("capabilities-usage-analysis-window")
+            .value_name("SECONDS")
+            .env("KUBEWARDEN_CAPABILITIES_USAGE_ANALYSIS_WINDOW")
+            .help("Track the host capabilities invoked by each policy evaluation, reporting the wasteful usages found during the given time window through the /debug/capability-usage endpoint. Not tracked when not set"),
+
+        Arg::new("request-enrichment-url")
+            .long("request-enrichment-url")
+            .value_name("URL")
+            .env("KUBEWARDEN_REQUEST_ENRICHMENT_URL")
+            .help("HTTP endpoint receiving the admission requests before they are evaluated. The JSON object it replies with is exposed to the policies under the `enrichment` field of the request"),
+
+        Arg::new("request-enrichment-timeout")
+            .long("request-enrichment-timeout")
+            .value_name("MILLISECONDS")
+            .env("KUBEWARDEN_REQUEST_ENRICHMENT_TIMEOUT")
+            .default_value("1000")
+            .help("Maximum time the enrichment of a request can take"),
+
+        Arg::new("request-enrichment-failure-policy")
+            .long("request-enrichment-failure-policy")
+            .value_name("FAILURE_POLICY")
+            .env("KUBEWARDEN_REQUEST_ENRICHMENT_FAILURE_POLICY")
+            .default_value("Fail")
+            .value_parser([
+                PossibleValue::new("Fail"),
+                PossibleValue::new("Ignore"),
+            ])
+            .help("How failures of the request enrichment are handled: `Fail` rejects the request, `Ignore` evaluates it without the missing data"),
+
+        Arg::new("state-dir")
+            .long("state-dir")
+            .env("KUBEWARDEN_STATE_DIR")
+            .value_name("DIR")
+            .value_parser(clap::builder::PathBufValueParser::new())
+            .conflicts_with_all(["policies-verification-key", "verification-path"])
+            .help("Serve the state restored by `state import` from the given directory. The policies are loaded from there, no network access is performed to fetch or verify them"),
+
         Arg::new("continue-on-errors")
             .long("continue-on-errors")
             .env("KUBEWARDEN_CONTINUE_ON_ERRORS")
@@ -231,4 +652,70 @@ pub(crate) fn build_cli() -> Command {
                         .help("path where the documentation file will be stored"),
                 ),
         )
+        .subcommand(
+            Command::new("journal")
+                .about("Inspect the decision journal")
+                .subcommand_required(true)
+                .subcommand(
+                    Command::new("export")
+                        .about("Export the decisions recorded inside of the journal using the JSON Lines format")
+                        .arg(
+                            Arg::new("dir")
+                                .long("dir")
+                                .env("KUBEWARDEN_DECISION_JOURNAL_DIR")
+                                .required(true)
+                                .value_name("DIR")
+                                .help("Directory holding the decision journal"),
+                        )
+                        .arg(
+                            Arg::new("since")
+                                .long("since")
+                                .value_name("SINCE")
+                                .help("Export only the decisions taken after the given time. Either a UNIX timestamp, in seconds, or a duration relative to now, like 30m, 12h or 7d"),
+                        )
+                        .arg(
+                            Arg::new("output")
+                                .long("output")
+                                .short('o')
+                                .value_name("FILE")
+                                .help("File where the decisions are written, the standard output is used when not set"),
+                        ),
+                ),
+        )
+        .subcommand(
+            Command::new("state")
+                .about("Export and import the state of Policy Server, used to recover from disasters")
+                .subcommand_required(true)
+                .subcommand(
+                    Command::new("export")
+                        .about("Download, verify and compile the policies, then write them inside of an archive together with the configuration")
+                        .arg(
+                            Arg::new("output")
+                                .long("output")
+                                .short('o')
+                                .required(true)
+                                .value_name("FILE")
+                                .help("File where the archive is written"),
+                        ),
+                )
+                .subcommand(
+                    Command::new("import")
+                        .about("Restore the state stored inside of an archive, to be served with --state-dir")
+                        .arg(
+                            Arg::new("archive")
+                                .long("archive")
+                                .required(true)
+                                .value_name("FILE")
+                                .help("Archive produced by `state export`"),
+                        )
+                        .arg(
+                            Arg::new("dir")
+                                .long("dir")
+                                .env("KUBEWARDEN_STATE_DIR")
+                                .required(true)
+                                .value_name("DIR")
+                                .help("Directory where the state is restored"),
+                        ),
+                ),
+        )
 }
@@ -2,9 +2,19 @@ use anyhow::{anyhow, Result};
 use clap::ArgMatches;
 use lazy_static::lazy_static;
 use policy_evaluator::{
-    admission_response_handler::policy_mode::PolicyMode,
+    admission_response_handler::{failure_policy::FailurePolicy, policy_mode::PolicyMode},
+    burrego::HttpPolicy,
+    callback_handler::{
+        ClientPoolConfig, DnsCacheConfig, KeyValueStoreConfig, RequestCoalescingConfig,
+        ResponseSizeLimits,
+    },
+    capability_versions::supported_capabilities,
+    evaluation_cache::EvaluationCacheConfig,
+    evaluation_context::KubernetesServiceAccount,
     policy_evaluator::PolicySettings,
     policy_fetcher::{
+        download::FetcherOptions,
+        sigstore::crypto::{CosignVerificationKey, Signature},
         sources::{read_sources_file, Sources},
         verify::config::{read_verification_file, LatestVerificationConfig, VerificationConfigV1},
     },
@@ -12,14 +22,24 @@ use policy_evaluator::{
 };
 use serde::Deserialize;
 use std::{
-    collections::{BTreeSet, HashMap},
-    env,
-    fs::{self, File},
+    collections::{BTreeMap, BTreeSet, HashMap, HashSet},
+    env, fs,
     net::SocketAddr,
     path::{Path, PathBuf},
+    sync::Arc,
+    time::Duration,
 };
 use tonic::transport::{Certificate, ClientTlsConfig, Identity};
 
+use crate::{
+    api::json_body::MAX_REQUEST_BODY_DEPTH_LIMIT,
+    decision_log::{DecisionLogConfig, DecisionLogSink},
+    enrichment::{EnrichmentConfig, HttpRequestEnricher, RequestEnricher},
+    evaluation::MatchConditions,
+    journal::JournalConfig,
+    state,
+};
+
 pub static SERVICE_NAME: &str = "kubewarden-policy-server";
 const DOCKER_CONFIG_ENV_VAR: &str = "DOCKER_CONFIG";
 
@@ -28,6 +48,29 @@ lazy_static! {
         std::env::var("HOSTNAME").unwrap_or_else(|_| String::from("unknown"));
 }
 
+/// Configuration of the cache of the compiled Wasm modules
+#[derive(Clone)]
+pub struct ModuleCacheConfig {
+    /// Directory holding the compiled modules
+    pub dir: PathBuf,
+    /// Key used to authenticate the entries of the cache. It must be the same for
+    /// all the replicas sharing the directory
+    pub key: Vec<u8>,
+    /// Replicas the missing entries are fetched from
+    pub peers: Vec<reqwest::Url>,
+}
+
+// The key must never end up inside of the logs
+impl std::fmt::Debug for ModuleCacheConfig {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ModuleCacheConfig")
+            .field("dir", &self.dir)
+            .field("key", &"<redacted>")
+            .field("peers", &self.peers)
+            .finish()
+    }
+}
+
 pub struct Config {
     pub addr: SocketAddr,
     pub readiness_probe_addr: SocketAddr,
@@ -37,22 +80,91 @@ pub struct Config {
     pub ignore_kubernetes_connection_failure: bool,
     pub always_accept_admission_reviews_on_namespace: Option<String>,
     pub policy_evaluation_limit_seconds: Option<u64>,
+    pub policy_settings_validation_limit_seconds: Option<u64>,
+    pub policy_timeout_tick_interval: Duration,
     pub tls_config: Option<TlsConfig>,
+    pub readiness_probe_tls_config: Option<TlsConfig>,
     pub pool_size: usize,
+    pub evaluator_pool_size: usize,
+    pub evaluator_pool_max_uses: usize,
+    pub evaluation_cache: EvaluationCacheConfig,
+    pub module_cache: Option<ModuleCacheConfig>,
     pub metrics_enabled: bool,
     pub sigstore_cache_dir: PathBuf,
     pub verification_config: Option<VerificationConfigV1>,
+    pub policy_reverification_interval: Option<Duration>,
     pub log_level: String,
     pub log_fmt: String,
     pub log_no_color: bool,
     pub daemon: bool,
     pub enable_pprof: bool,
+    pub enable_log_filter_admin: bool,
+    pub log_filter_file: Option<PathBuf>,
     pub daemon_pid_file: String,
     pub daemon_stdout_file: Option<String>,
     pub daemon_stderr_file: Option<String>,
     pub continue_on_errors: bool,
+    pub lazy_policy_loading: bool,
+    pub lazy_policy_warm_up: Vec<String>,
+    pub readiness_requires_kubernetes_sync: bool,
+    pub max_request_body_size: usize,
+    pub max_request_body_depth: usize,
+    pub rego_policy_memory_limit: Option<u64>,
+    pub response_compression: bool,
+    pub request_projection: bool,
+    pub redact_secret_data: bool,
+    pub cluster_name: Option<String>,
+    pub decision_journal: Option<JournalConfig>,
+    pub decision_log: Option<DecisionLogConfig>,
+    pub state_dir: Option<PathBuf>,
+    pub policy_fetch: FetcherOptions,
+    pub priority: PriorityConfig,
+    pub capabilities: CapabilitiesConfig,
+    pub enrichment: EnrichmentConfig,
+}
+
+/// How requests are assigned to the priority classes used to dispatch them to the workers
+#[derive(Clone, Debug, PartialEq)]
+pub struct PriorityConfig {
+    /// The requests about objects inside of these namespaces have the highest priority
+    pub critical_namespaces: HashSet<String>,
+    /// How many times the waiting requests of a priority class can be overtaken by the ones
+    /// of higher classes, before being served
+    pub starvation_threshold: u32,
+}
+
+impl Default for PriorityConfig {
+    fn default() -> Self {
+        PriorityConfig {
+            critical_namespaces: HashSet::from([
+                "kube-system".to_owned(),
+                "kube-node-lease".to_owned(),
+            ]),
+            starvation_threshold: 8,
+        }
+    }
+}
+
+/// How the connections opened by the host capabilities on behalf of the policies
+/// are reused
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct CapabilitiesConfig {
+    /// The pool of clients used to interact with the OCI registries
+    pub client_pool: ClientPoolConfig,
+    /// The cache of the DNS lookups
+    pub dns_cache: DnsCacheConfig,
+    /// The batching of the identical requests made to the Kubernetes API server
+    pub request_coalescing: RequestCoalescingConfig,
+    /// The maximum size of the responses given back to the policies
+    pub response_size_limits: ResponseSizeLimits,
+    /// The key/value store offered to the policies, `None` when not enabled
+    pub key_value_store: Option<KeyValueStoreConfig>,
+    /// The time window of the analysis of the host capabilities used by the
+    /// policies, `None` when the usage is not tracked
+    pub usage_analysis_window: Option<Duration>,
 }
 
+#[derive(Clone, Debug, PartialEq)]
 pub struct TlsConfig {
     pub cert_file: PathBuf,
     pub key_file: PathBuf,
@@ -65,24 +177,28 @@ impl Config {
         let addr = api_bind_address(matches)?;
         let readiness_probe_addr = readiness_probe_bind_address(matches)?;
 
-        let policies = policies(matches)?;
+        let state_dir = matches.get_one::<PathBuf>("state-dir").cloned();
+        let policies = policies(matches, state_dir.as_deref())?;
         let policies_download_dir = matches
             .get_one::<String>("policies-download-dir")
             .map(PathBuf::from)
             .expect("This should not happen, there's a default value for policies-download-dir");
-        let policy_evaluation_limit_seconds = if *matches
-            .get_one::<bool>("disable-timeout-protection")
-            .expect("clap should have set a default value")
-        {
-            None
-        } else {
-            Some(
-                matches
-                    .get_one::<String>("policy-timeout")
-                    .expect("policy-timeout should always be set")
-                    .parse::<u64>()?,
-            )
-        };
+        let (policy_evaluation_limit_seconds, policy_settings_validation_limit_seconds) =
+            policy_timeouts(matches)?;
+        validate_policy_timeouts(&policies, policy_evaluation_limit_seconds.is_some())?;
+        validate_match_conditions(&policies)?;
+        validate_http_send(&policies)?;
+        let policy_timeout_tick_interval = matches
+            .get_one::<String>("policy-timeout-tick-interval")
+            .expect("policy-timeout-tick-interval should always be set")
+            .parse::<u64>()
+            .map(Duration::from_millis)
+            .map_err(|e| anyhow!("invalid policy-timeout-tick-interval: {}", e))?;
+        if policy_timeout_tick_interval.is_zero() {
+            return Err(anyhow!(
+                "policy-timeout-tick-interval must be greater than 0"
+            ));
+        }
         let sources = remote_server_options(matches)?;
         let pool_size = matches
             .get_one::<String>("workers")
@@ -90,6 +206,18 @@ impl Config {
                 v.parse::<usize>()
                     .expect("error parsing the number of workers")
             });
+        let evaluator_pool_size = matches
+            .get_one::<String>("evaluator-pool-size")
+            .expect("evaluator-pool-size should always be set")
+            .parse::<usize>()
+            .map_err(|e| anyhow!("invalid evaluator-pool-size: {}", e))?;
+        let evaluator_pool_max_uses = matches
+            .get_one::<String>("evaluator-pool-max-uses")
+            .expect("evaluator-pool-max-uses should always be set")
+            .parse::<usize>()
+            .map_err(|e| anyhow!("invalid evaluator-pool-max-uses: {}", e))?;
+        let evaluation_cache = evaluation_cache_config(matches)?;
+        let module_cache = module_cache_config(matches)?;
         let always_accept_admission_reviews_on_namespace = matches
             .get_one::<String>("always-accept-admission-reviews-on-namespace")
             .map(|s| s.to_owned());
@@ -103,6 +231,7 @@ impl Config {
             .expect("clap should have set a default value")
             .to_owned();
         let verification_config = verification_config(matches)?;
+        let policy_reverification_interval = policy_reverification_interval(matches)?;
         let sigstore_cache_dir = matches
             .get_one::<String>("sigstore-cache-dir")
             .map(PathBuf::from)
@@ -132,18 +261,86 @@ impl Config {
             .expect("clap should have assigned a default value")
             .to_owned();
 
-        let tls_config = build_tls_config(matches)?;
+        let tls_config = build_tls_config(matches, &API_TLS_ARGS)?;
+        let readiness_probe_tls_config =
+            build_readiness_probe_tls_config(matches, tls_config.as_ref())?;
 
         let enable_pprof = matches
             .get_one::<bool>("enable-pprof")
             .expect("clap should have assigned a default value")
             .to_owned();
 
+        let enable_log_filter_admin = matches
+            .get_one::<bool>("enable-log-filter-admin")
+            .expect("clap should have assigned a default value")
+            .to_owned();
+        let log_filter_file = matches
+            .get_one::<String>("log-filter-file")
+            .map(PathBuf::from);
+
         let continue_on_errors = matches
             .get_one::<bool>("continue-on-errors")
             .expect("clap should have assigned a default value")
             .to_owned();
 
+        let lazy_policy_loading = matches
+            .get_one::<bool>("lazy-policy-loading")
+            .expect("clap should have assigned a default value")
+            .to_owned();
+        let readiness_requires_kubernetes_sync = matches
+            .get_one::<bool>("readiness-requires-kubernetes-sync")
+            .expect("clap should have assigned a default value")
+            .to_owned();
+        let lazy_policy_warm_up = matches
+            .get_many::<String>("lazy-policy-warm-up")
+            .unwrap_or_default()
+            .map(|policy_id| policy_id.trim().to_owned())
+            .filter(|policy_id| !policy_id.is_empty())
+            .collect();
+
+        let max_request_body_size = matches
+            .get_one::<String>("max-request-body-size")
+            .expect("max-request-body-size should always be set")
+            .parse::<usize>()
+            .map_err(|e| anyhow!("invalid max-request-body-size: {}", e))?;
+        let max_request_body_depth = matches
+            .get_one::<String>("max-request-body-depth")
+            .expect("max-request-body-depth should always be set")
+            .parse::<usize>()
+            .map_err(|e| anyhow!("invalid max-request-body-depth: {}", e))?;
+        if !(1..=MAX_REQUEST_BODY_DEPTH_LIMIT).contains(&max_request_body_depth) {
+            return Err(anyhow!(
+                "invalid max-request-body-depth: must be between 1 and {}",
+                MAX_REQUEST_BODY_DEPTH_LIMIT
+            ));
+        }
+        let rego_policy_memory_limit = matches
+            .get_one::<String>("rego-policy-memory-limit")
+            .map(|limit| {
+                limit
+                    .parse::<u64>()
+                    .map_err(|e| anyhow!("invalid rego-policy-memory-limit: {}", e))
+            })
+            .transpose()?;
+        let response_compression = !matches
+            .get_one::<bool>("disable-response-compression")
+            .expect("clap should have assigned a default value");
+        let request_projection = !matches
+            .get_one::<bool>("disable-request-projection")
+            .expect("clap should have assigned a default value");
+        let redact_secret_data = *matches
+            .get_one::<bool>("redact-secret-data")
+            .expect("clap should have assigned a default value");
+        let cluster_name = matches.get_one::<String>("cluster-name").cloned();
+
+        let decision_journal = decision_journal_config(matches)?;
+        let decision_log = decision_log_config(matches)?;
+        let policy_fetch = policy_fetch_config(matches)?;
+        let priority = priority_config(matches)?;
+        let capabilities = capabilities_config(matches)?;
+        validate_key_value_store(&policies, capabilities.key_value_store.is_some())?;
+        let enrichment = enrichment_config(matches)?;
+
         Ok(Self {
             addr,
             readiness_probe_addr,
@@ -152,12 +349,20 @@ impl Config {
             policies_download_dir,
             ignore_kubernetes_connection_failure,
             tls_config,
+            readiness_probe_tls_config,
             always_accept_admission_reviews_on_namespace,
             policy_evaluation_limit_seconds,
+            policy_settings_validation_limit_seconds,
+            policy_timeout_tick_interval,
             pool_size,
+            evaluator_pool_size,
+            evaluator_pool_max_uses,
+            evaluation_cache,
+            module_cache,
             metrics_enabled,
             sigstore_cache_dir,
             verification_config,
+            policy_reverification_interval,
             log_level,
             log_fmt,
             log_no_color,
@@ -166,11 +371,367 @@ impl Config {
             daemon_stdout_file,
             daemon_stderr_file,
             enable_pprof,
+            enable_log_filter_admin,
+            log_filter_file,
             continue_on_errors,
+            lazy_policy_loading,
+            lazy_policy_warm_up,
+            readiness_requires_kubernetes_sync,
+            max_request_body_size,
+            max_request_body_depth,
+            rego_policy_memory_limit,
+            response_compression,
+            request_projection,
+            redact_secret_data,
+            cluster_name,
+            decision_journal,
+            decision_log,
+            state_dir,
+            policy_fetch,
+            priority,
+            capabilities,
+            enrichment,
         })
     }
 }
 
+/// The limits of the evaluation of a request and of the validation of the settings,
+/// expressed in seconds. Both are `None` when the timeout protection is disabled
+fn policy_timeouts(matches: &clap::ArgMatches) -> Result<(Option<u64>, Option<u64>)> {
+    if *matches
+        .get_one::<bool>("disable-timeout-protection")
+        .expect("clap should have set a default value")
+    {
+        return Ok((None, None));
+    }
+
+    let evaluation_limit = matches
+        .get_one::<String>("policy-timeout")
+        .expect("policy-timeout should always be set")
+        .parse::<u64>()?;
+    let settings_validation_limit = matches
+        .get_one::<String>("policy-settings-validation-timeout")
+        .map(|limit| limit.parse::<u64>())
+        .transpose()
+        .map_err(|e| anyhow!("invalid policy-settings-validation-timeout: {}", e))?
+        .unwrap_or(evaluation_limit);
+
+    Ok((Some(evaluation_limit), Some(settings_validation_limit)))
+}
+
+fn policy_fetch_config(matches: &clap::ArgMatches) -> Result<FetcherOptions> {
+    let concurrency = matches
+        .get_one::<String>("policy-fetch-concurrency")
+        .expect("policy-fetch-concurrency should always be set")
+        .parse::<usize>()
+        .map_err(|e| anyhow!("invalid policy-fetch-concurrency: {}", e))?;
+    if concurrency == 0 {
+        return Err(anyhow!("policy-fetch-concurrency must be greater than 0"));
+    }
+    let registry_delay = matches
+        .get_one::<String>("registry-politeness-delay")
+        .expect("registry-politeness-delay should always be set")
+        .parse::<u64>()
+        .map(Duration::from_millis)
+        .map_err(|e| anyhow!("invalid registry-politeness-delay: {}", e))?;
+    let max_retries = matches
+        .get_one::<String>("policy-fetch-max-retries")
+        .expect("policy-fetch-max-retries should always be set")
+        .parse::<u32>()
+        .map_err(|e| anyhow!("invalid policy-fetch-max-retries: {}", e))?;
+
+    Ok(FetcherOptions {
+        concurrency,
+        registry_delay,
+        max_retries,
+    })
+}
+
+fn priority_config(matches: &clap::ArgMatches) -> Result<PriorityConfig> {
+    let critical_namespaces = matches
+        .get_one::<String>("priority-critical-namespaces")
+        .expect("priority-critical-namespaces should always be set")
+        .split(',')
+        .map(|namespace| namespace.trim().to_owned())
+        .filter(|namespace| !namespace.is_empty())
+        .collect();
+    let starvation_threshold = matches
+        .get_one::<String>("priority-starvation-threshold")
+        .expect("priority-starvation-threshold should always be set")
+        .parse::<u32>()
+        .map_err(|e| anyhow!("invalid priority-starvation-threshold: {}", e))?;
+
+    Ok(PriorityConfig {
+        critical_namespaces,
+        starvation_threshold,
+    })
+}
+
+fn module_cache_config(matches: &clap::ArgMatches) -> Result<Option<ModuleCacheConfig>> {
+    let Some(dir) = matches.get_one::<PathBuf>("module-cache-dir") else {
+        return Ok(None);
+    };
+    let key_file = matches
+        .get_one::<PathBuf>("module-cache-key-file")
+        .expect("module-cache-key-file is required by module-cache-dir");
+    let key = std::fs::read(key_file).map_err(|e| {
+        anyhow!(
+            "cannot read module-cache-key-file {}: {}",
+            key_file.display(),
+            e
+        )
+    })?;
+    let key = key.trim_ascii().to_vec();
+    if key.is_empty() {
+        return Err(anyhow!(
+            "invalid module-cache-key-file {}: the key is empty",
+            key_file.display()
+        ));
+    }
+
+    let peers = matches
+        .get_many::<String>("module-cache-peers")
+        .into_iter()
+        .flatten()
+        .map(|peer| {
+            reqwest::Url::parse(peer)
+                .ok()
+                .filter(|url| matches!(url.scheme(), "http" | "https") && url.has_host())
+                .ok_or_else(|| anyhow!("invalid module-cache-peers entry: {peer}"))
+        })
+        .collect::<Result<Vec<_>>>()?;
+
+    Ok(Some(ModuleCacheConfig {
+        dir: dir.to_owned(),
+        key,
+        peers,
+    }))
+}
+
+fn evaluation_cache_config(matches: &clap::ArgMatches) -> Result<EvaluationCacheConfig> {
+    let max_entries = matches
+        .get_one::<String>("evaluation-cache-size")
+        .expect("evaluation-cache-size should always be set")
+        .parse::<usize>()
+        .map_err(|e| anyhow!("invalid evaluation-cache-size: {}", e))?;
+    let ttl = matches
+        .get_one::<String>("evaluation-cache-ttl")
+        .expect("evaluation-cache-ttl should always be set")
+        .parse::<u64>()
+        .map(Duration::from_secs)
+        .map_err(|e| anyhow!("invalid evaluation-cache-ttl: {}", e))?;
+
+    Ok(EvaluationCacheConfig { ttl, max_entries })
+}
+
+fn capabilities_config(matches: &clap::ArgMatches) -> Result<CapabilitiesConfig> {
+    let max_size = matches
+        .get_one::<String>("capabilities-client-pool-size")
+        .expect("capabilities-client-pool-size should always be set")
+        .parse::<usize>()
+        .map_err(|e| anyhow!("invalid capabilities-client-pool-size: {}", e))?;
+    let idle_timeout = matches
+        .get_one::<String>("capabilities-client-idle-timeout")
+        .expect("capabilities-client-idle-timeout should always be set")
+        .parse::<u64>()
+        .map(Duration::from_secs)
+        .map_err(|e| anyhow!("invalid capabilities-client-idle-timeout: {}", e))?;
+    let ttl = matches
+        .get_one::<String>("capabilities-dns-cache-ttl")
+        .expect("capabilities-dns-cache-ttl should always be set")
+        .parse::<u64>()
+        .map(Duration::from_secs)
+        .map_err(|e| anyhow!("invalid capabilities-dns-cache-ttl: {}", e))?;
+    let max_entries = matches
+        .get_one::<String>("capabilities-dns-cache-size")
+        .expect("capabilities-dns-cache-size should always be set")
+        .parse::<usize>()
+        .map_err(|e| anyhow!("invalid capabilities-dns-cache-size: {}", e))?;
+    let window = matches
+        .get_one::<String>("capabilities-kubernetes-coalescing-window")
+        .expect("capabilities-kubernetes-coalescing-window should always be set")
+        .parse::<u64>()
+        .map(Duration::from_millis)
+        .map_err(|e| anyhow!("invalid capabilities-kubernetes-coalescing-window: {}", e))?;
+    let response_size_limits = response_size_limits(matches)?;
+    let key_value_store = key_value_store_config(matches)?;
+    let usage_analysis_window = matches
+        .get_one::<String>("capabilities-usage-analysis-window")
+        .map(|window| window.parse::<u64>().map(Duration::from_secs))
+        .transpose()
+        .map_err(|e| anyhow!("invalid capabilities-usage-analysis-window: {}", e))?;
+
+    Ok(CapabilitiesConfig {
+        client_pool: ClientPoolConfig {
+            max_size,
+            idle_timeout,
+        },
+        dns_cache: DnsCacheConfig { ttl, max_entries },
+        request_coalescing: RequestCoalescingConfig { window },
+        response_size_limits,
+        key_value_store,
+        usage_analysis_window,
+    })
+}
+
+fn key_value_store_config(matches: &clap::ArgMatches) -> Result<Option<KeyValueStoreConfig>> {
+    if !matches.get_flag("capabilities-key-value-store") {
+        return Ok(None);
+    }
+
+    let max_namespaces = matches
+        .get_one::<String>("capabilities-key-value-store-max-namespaces")
+        .expect("capabilities-key-value-store-max-namespaces should always be set")
+        .parse::<usize>()
+        .map_err(|e| anyhow!("invalid capabilities-key-value-store-max-namespaces: {}", e))?;
+    let max_entries = matches
+        .get_one::<String>("capabilities-key-value-store-max-entries")
+        .expect("capabilities-key-value-store-max-entries should always be set")
+        .parse::<usize>()
+        .map_err(|e| anyhow!("invalid capabilities-key-value-store-max-entries: {}", e))?;
+    let max_value_size = matches
+        .get_one::<String>("capabilities-key-value-store-max-value-size")
+        .expect("capabilities-key-value-store-max-value-size should always be set")
+        .parse::<usize>()
+        .map_err(|e| anyhow!("invalid capabilities-key-value-store-max-value-size: {}", e))?;
+    let max_ttl = matches
+        .get_one::<String>("capabilities-key-value-store-max-ttl")
+        .expect("capabilities-key-value-store-max-ttl should always be set")
+        .parse::<u64>()
+        .map(Duration::from_secs)
+        .map_err(|e| anyhow!("invalid capabilities-key-value-store-max-ttl: {}", e))?;
+
+    Ok(Some(KeyValueStoreConfig {
+        max_namespaces,
+        max_entries,
+        max_value_size,
+        max_ttl,
+    }))
+}
+
+fn response_size_limits(matches: &clap::ArgMatches) -> Result<ResponseSizeLimits> {
+    let default = matches
+        .get_one::<String>("capabilities-response-size-limit")
+        .map(|limit| limit.parse::<usize>())
+        .transpose()
+        .map_err(|e| anyhow!("invalid capabilities-response-size-limit: {}", e))?;
+
+    let mut capabilities = HashMap::new();
+    if let Some(limits) = matches.get_one::<String>("capabilities-response-size-limits") {
+        let supported_capabilities = supported_capabilities();
+        for entry in limits.split(',').map(str::trim).filter(|e| !e.is_empty()) {
+            let (capability, limit) = entry.split_once('=').ok_or_else(|| {
+                anyhow!(
+                    "invalid capabilities-response-size-limits: `{}` is not in the CAPABILITY=BYTES format",
+                    entry
+                )
+            })?;
+            if !supported_capabilities.contains_key(capability) {
+                return Err(anyhow!(
+                    "invalid capabilities-response-size-limits: unknown host capability `{}`",
+                    capability
+                ));
+            }
+            let limit = limit.parse::<usize>().map_err(|e| {
+                anyhow!(
+                    "invalid capabilities-response-size-limits: limit of `{}`: {}",
+                    capability,
+                    e
+                )
+            })?;
+            capabilities.insert(capability.to_owned(), limit);
+        }
+    }
+
+    Ok(ResponseSizeLimits {
+        default,
+        capabilities,
+    })
+}
+
+fn enrichment_config(matches: &clap::ArgMatches) -> Result<EnrichmentConfig> {
+    let timeout = matches
+        .get_one::<String>("request-enrichment-timeout")
+        .expect("request-enrichment-timeout should always be set")
+        .parse::<u64>()
+        .map(Duration::from_millis)
+        .map_err(|e| anyhow!("invalid request-enrichment-timeout: {}", e))?;
+    let failure_policy = match matches
+        .get_one::<String>("request-enrichment-failure-policy")
+        .expect("request-enrichment-failure-policy should always be set")
+        .as_str()
+    {
+        "Ignore" => FailurePolicy::Ignore,
+        _ => FailurePolicy::Fail,
+    };
+
+    let mut enrichers: Vec<Arc<dyn RequestEnricher>> = Vec::new();
+    if let Some(url) = matches.get_one::<String>("request-enrichment-url") {
+        enrichers.push(Arc::new(HttpRequestEnricher::new(url)?));
+    }
+
+    Ok(EnrichmentConfig {
+        enrichers,
+        timeout,
+        failure_policy,
+    })
+}
+
+fn decision_journal_config(matches: &clap::ArgMatches) -> Result<Option<JournalConfig>> {
+    let dir = match matches.get_one::<String>("decision-journal-dir") {
+        Some(dir) => PathBuf::from(dir),
+        None => return Ok(None),
+    };
+    let segment_size = matches
+        .get_one::<String>("decision-journal-segment-size")
+        .expect("decision-journal-segment-size should always be set")
+        .parse::<u64>()
+        .map_err(|e| anyhow!("invalid decision-journal-segment-size: {}", e))?;
+    let max_segments = matches
+        .get_one::<String>("decision-journal-max-segments")
+        .map(|v| v.parse::<usize>())
+        .transpose()
+        .map_err(|e| anyhow!("invalid decision-journal-max-segments: {}", e))?;
+    let fail_closed = matches.get_flag("decision-journal-fail-closed");
+
+    Ok(Some(JournalConfig {
+        dir,
+        segment_size,
+        max_segments,
+        fail_closed,
+    }))
+}
+
+fn decision_log_config(matches: &clap::ArgMatches) -> Result<Option<DecisionLogConfig>> {
+    let parse = |name: &str| -> Result<u64> {
+        matches
+            .get_one::<String>(name)
+            .unwrap_or_else(|| panic!("{name} should always be set"))
+            .parse::<u64>()
+            .map_err(|e| anyhow!("invalid {name}: {e}"))
+    };
+
+    let sink = if let Some(path) = matches.get_one::<String>("decision-log-file") {
+        DecisionLogSink::File {
+            path: PathBuf::from(path),
+            max_size: parse("decision-log-max-file-size")?,
+            max_files: parse("decision-log-max-files")? as usize,
+        }
+    } else if let Some(url) = matches.get_one::<String>("decision-log-url") {
+        DecisionLogSink::Http {
+            url: url.to_owned(),
+        }
+    } else {
+        return Ok(None);
+    };
+
+    Ok(Some(DecisionLogConfig {
+        sink,
+        queue_size: parse("decision-log-queue-size")? as usize,
+        batch_size: parse("decision-log-batch-size")? as usize,
+    }))
+}
+
 fn api_bind_address(matches: &clap::ArgMatches) -> Result<SocketAddr> {
     format!(
         "{}:{}",
@@ -182,45 +743,129 @@ fn api_bind_address(matches: &clap::ArgMatches) -> Result<SocketAddr> {
 }
 
 fn readiness_probe_bind_address(matches: &clap::ArgMatches) -> Result<SocketAddr> {
+    let address = matches
+        .get_one::<String>("readiness-probe-address")
+        .or_else(|| matches.get_one::<String>("address"))
+        .unwrap();
     format!(
         "{}:{}",
-        matches.get_one::<String>("address").unwrap(),
+        address,
         matches.get_one::<String>("readiness-probe-port").unwrap()
     )
     .parse()
     .map_err(|e| anyhow!("error parsing arguments: {}", e))
 }
 
-fn build_tls_config(matches: &clap::ArgMatches) -> Result<Option<TlsConfig>> {
-    let cert_file = matches.get_one::<PathBuf>("cert-file").cloned();
-    let key_file = matches.get_one::<PathBuf>("key-file").cloned();
-    let client_ca_file = matches.get_many::<PathBuf>("client-ca-file");
+/// Names of the flags configuring the TLS of a server
+struct TlsArgs {
+    cert_file: &'static str,
+    key_file: &'static str,
+    client_ca_file: &'static str,
+}
 
-    match (cert_file, key_file, &client_ca_file) {
+const API_TLS_ARGS: TlsArgs = TlsArgs {
+    cert_file: "cert-file",
+    key_file: "key-file",
+    client_ca_file: "client-ca-file",
+};
+
+const READINESS_PROBE_TLS_ARGS: TlsArgs = TlsArgs {
+    cert_file: "readiness-probe-cert-file",
+    key_file: "readiness-probe-key-file",
+    client_ca_file: "readiness-probe-client-ca-file",
+};
+
+fn client_ca_files(matches: &clap::ArgMatches, args: &TlsArgs) -> Vec<PathBuf> {
+    matches
+        .get_many::<PathBuf>(args.client_ca_file)
+        .unwrap_or_default()
+        .map(|p| p.to_owned())
+        .collect()
+}
+
+fn build_tls_config(matches: &clap::ArgMatches, args: &TlsArgs) -> Result<Option<TlsConfig>> {
+    let cert_file = matches.get_one::<PathBuf>(args.cert_file).cloned();
+    let key_file = matches.get_one::<PathBuf>(args.key_file).cloned();
+    let client_ca_file = client_ca_files(matches, args);
+
+    match (cert_file, key_file, client_ca_file.is_empty()) {
         (Some(cert_file), Some(key_file), _) => Ok(Some(TlsConfig {
             cert_file,
             key_file,
-            client_ca_file: client_ca_file
-                .unwrap_or_default()
-                .map(|p| p.to_owned())
-                .collect::<Vec<PathBuf>>(),
+            client_ca_file,
         })),
         // No TLS configuration provided
-        (None, None, None) => Ok(None),
+        (None, None, true) => Ok(None),
         // Client CA certificate provided without server certificate and key
-        (None, None, Some(_)) => Err(anyhow!(
-            "client CA certificate requires server certificate and key to be specified"
+        (None, None, false) => Err(anyhow!(
+            "{} requires {} and {} to be specified",
+            args.client_ca_file,
+            args.cert_file,
+            args.key_file
         )),
         // Server certificate or key provided without the other
         (Some(_), None, _) | (None, Some(_), _) => Err(anyhow!(
-            "both certificate and key must be provided together"
+            "{} and {} must be provided together",
+            args.cert_file,
+            args.key_file
         )),
     }
 }
 
-fn policies(matches: &clap::ArgMatches) -> Result<HashMap<String, PolicyOrPolicyGroup>> {
-    let policies_file = Path::new(matches.get_one::<String>("policies").unwrap());
-    let policies = read_policies_file(policies_file).map_err(|e| {
+/// The readiness probe server is plain HTTP unless it's given its own certificate,
+/// or it's asked to reuse the one of the API server. The client CA certificates
+/// are never shared with the API server: the clients of the readiness endpoint,
+/// like the kubelet, usually cannot present the certificates accepted by the API
+/// server
+fn build_readiness_probe_tls_config(
+    matches: &clap::ArgMatches,
+    api_tls_config: Option<&TlsConfig>,
+) -> Result<Option<TlsConfig>> {
+    let reuse_api_tls = matches
+        .get_one::<bool>("readiness-probe-reuse-tls")
+        .expect("clap should have assigned a default value")
+        .to_owned();
+    if !reuse_api_tls {
+        return build_tls_config(matches, &READINESS_PROBE_TLS_ARGS);
+    }
+
+    let api_tls_config = api_tls_config.ok_or_else(|| {
+        anyhow!("readiness-probe-reuse-tls requires cert-file and key-file to be specified")
+    })?;
+    Ok(Some(TlsConfig {
+        cert_file: api_tls_config.cert_file.clone(),
+        key_file: api_tls_config.key_file.clone(),
+        client_ca_file: client_ca_files(matches, &READINESS_PROBE_TLS_ARGS),
+    }))
+}
+
+fn policies(
+    matches: &clap::ArgMatches,
+    state_dir: Option<&Path>,
+) -> Result<HashMap<String, PolicyOrPolicyGroup>> {
+    let policies_file = match state_dir {
+        Some(state_dir) => state::policies_file(state_dir),
+        None => PathBuf::from(matches.get_one::<String>("policies").unwrap()),
+    };
+    let policies_file = policies_file.as_path();
+
+    // The file is read only once: the bytes that are verified are the ones
+    // being parsed, even if the file is replaced in the meantime
+    let data = fs::read(policies_file)
+        .map_err(|e| anyhow!("cannot read policies file {:?}: {}", policies_file, e))?;
+
+    // The policies file of a state directory has been checked when the state was exported
+    if state_dir.is_none() {
+        if let Some(verification_key) = matches.get_one::<PathBuf>("policies-verification-key") {
+            let signature_file = matches
+                .get_one::<PathBuf>("policies-signature")
+                .cloned()
+                .unwrap_or_else(|| default_policies_signature_path(policies_file));
+            verify_policies_file(policies_file, &data, &signature_file, verification_key)?;
+        }
+    }
+
+    let policies = parse_policies_file(&data).map_err(|e| {
         anyhow!(
             "error while loading policies from {:?}: {}",
             policies_file,
@@ -233,6 +878,51 @@ fn policies(matches: &clap::ArgMatches) -> Result<HashMap<String, PolicyOrPolicy
     Ok(policies)
 }
 
+/// The signature produced by `cosign sign-blob` is looked up next to the policies
+/// file when no explicit path is given, e.g. `policies.yml.sig`
+fn default_policies_signature_path(policies_file: &Path) -> PathBuf {
+    let mut signature_file = policies_file.as_os_str().to_owned();
+    signature_file.push(".sig");
+    PathBuf::from(signature_file)
+}
+
+/// Verifies the contents of the policies file against the signature produced by
+/// `cosign sign-blob --key <key> <policies file>`. The signature is
+/// expected to be base64 encoded, which is cosign's default output format.
+fn verify_policies_file(
+    policies_file: &Path,
+    data: &[u8],
+    signature_file: &Path,
+    verification_key: &Path,
+) -> Result<()> {
+    let signature = fs::read_to_string(signature_file).map_err(|e| {
+        anyhow!(
+            "cannot read signature of the policies file {:?}: {}",
+            signature_file,
+            e
+        )
+    })?;
+    let key = fs::read(verification_key).map_err(|e| {
+        anyhow!(
+            "cannot read policies verification key {:?}: {}",
+            verification_key,
+            e
+        )
+    })?;
+
+    let verification_key = CosignVerificationKey::try_from_pem(&key)
+        .map_err(|e| anyhow!("invalid policies verification key: {}", e))?;
+    verification_key
+        .verify_signature(Signature::Base64Encoded(signature.trim().as_bytes()), data)
+        .map_err(|e| {
+            anyhow!(
+                "cannot verify the signature of the policies file {:?}, refusing to load it: {}",
+                policies_file,
+                e
+            )
+        })
+}
+
 // Validate the policies and policy groups:
 //  - ensure policy names do not contain a '/' character
 //  - ensure names of policy group's policies do not contain a '/' character
@@ -259,6 +949,98 @@ fn validate_policies(policies: &HashMap<String, PolicyOrPolicyGroup>) -> Result<
     Ok(())
 }
 
+// Validate the timeouts declared by the policies and policy groups:
+//  - ensure the timeout protection is enabled, it cannot be enforced otherwise
+//  - ensure the timeouts are greater than 0
+fn validate_policy_timeouts(
+    policies: &HashMap<String, PolicyOrPolicyGroup>,
+    timeout_protection_enabled: bool,
+) -> Result<()> {
+    for (name, policy) in policies.iter() {
+        let timeout_seconds = match policy {
+            PolicyOrPolicyGroup::Policy {
+                timeout_seconds, ..
+            }
+            | PolicyOrPolicyGroup::PolicyGroup {
+                timeout_seconds, ..
+            } => timeout_seconds,
+        };
+        match timeout_seconds {
+            Some(_) if !timeout_protection_enabled => {
+                return Err(anyhow!(
+                    "policy '{}' sets timeoutSeconds, but the timeout protection is disabled",
+                    name
+                ));
+            }
+            Some(0) => {
+                return Err(anyhow!(
+                    "policy '{}' timeoutSeconds must be greater than 0",
+                    name
+                ));
+            }
+            _ => {}
+        }
+    }
+    Ok(())
+}
+
+// Validate the matchConditions of the policies, to report the invalid CEL
+// expressions at startup time
+fn validate_match_conditions(policies: &HashMap<String, PolicyOrPolicyGroup>) -> Result<()> {
+    for (name, policy) in policies.iter() {
+        MatchConditions::compile(policy.match_conditions())
+            .map_err(|e| anyhow!("policy '{}' has invalid matchConditions: {}", name, e))?;
+    }
+    Ok(())
+}
+
+// Ensure the policies using the key/value store can reach it
+fn validate_key_value_store(
+    policies: &HashMap<String, PolicyOrPolicyGroup>,
+    key_value_store_enabled: bool,
+) -> Result<()> {
+    if key_value_store_enabled {
+        return Ok(());
+    }
+    for (name, policy) in policies.iter() {
+        if let PolicyOrPolicyGroup::Policy {
+            key_value_store: true,
+            ..
+        } = policy
+        {
+            return Err(anyhow!(
+                "policy '{}' uses the key/value store, which has not been enabled with --capabilities-key-value-store",
+                name
+            ));
+        }
+    }
+    Ok(())
+}
+
+// Validate the origins the policies are allowed to reach with the `http.send`
+// Rego builtin
+fn validate_http_send(policies: &HashMap<String, PolicyOrPolicyGroup>) -> Result<()> {
+    for (name, policy) in policies.iter() {
+        if let PolicyOrPolicyGroup::Policy {
+            http_send: Some(http_send),
+            ..
+        } = policy
+        {
+            if http_send.timeout_seconds == 0 {
+                return Err(anyhow!(
+                    "policy '{}' httpSend.timeoutSeconds must be greater than 0",
+                    name
+                ));
+            }
+            http_send
+                .http_policy()
+                .validate()
+                .map_err(|e| anyhow!("policy '{}' has an invalid httpSend: {}", name, e))?;
+        }
+    }
+    Ok(())
+}
+
 fn verification_config(matches: &clap::ArgMatches) -> Result<Option<LatestVerificationConfig>> {
     match matches.get_one::<String>("verification-path") {
         None => Ok(None),
@@ -269,6 +1051,25 @@ fn verification_config(matches: &clap::ArgMatches) -> Result<Option<LatestVerifi
     }
 }
 
+/// How often the policies are verified again, `None` when the periodic re-verification
+/// is disabled
+fn policy_reverification_interval(matches: &clap::ArgMatches) -> Result<Option<Duration>> {
+    let Some(interval) = matches.get_one::<String>("policy-reverification-interval") else {
+        return Ok(None);
+    };
+    let interval = interval
+        .parse::<u64>()
+        .map(Duration::from_secs)
+        .map_err(|e| anyhow!("invalid policy-reverification-interval: {}", e))?;
+    if interval.is_zero() {
+        return Err(anyhow!(
+            "policy-reverification-interval must be greater than 0"
+        ));
+    }
+
+    Ok(Some(interval))
+}
+
 fn remote_server_options(matches: &clap::ArgMatches) -> Result<Option<Sources>> {
     let sources = match matches.get_one::<String>("sources-path") {
         Some(sources_file) => Some(
@@ -296,6 +1097,18 @@ pub enum PolicyOrPolicyGroupSettings {
     },
 }
 
+/// Whether a policy receives the values of the Secrets, see `--redact-secret-data`
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub enum SecretData {
+    /// The values of the Secrets are masked, even when the redaction is not
+    /// enabled globally
+    Redacted,
+    /// The policy receives the values of the Secrets, even when the redaction
+    /// is enabled globally
+    Revealed,
+}
+
 /// `PolicyGroupMember` represents a single policy that is part of a policy group.
 #[derive(Deserialize, Debug, Clone, PartialEq)]
 #[serde(deny_unknown_fields, rename_all = "camelCase")]
@@ -307,6 +1120,13 @@ pub struct PolicyGroupMember {
     /// The list of Kubernetes resources the policy is allowed to access
     #[serde(default)]
     pub context_aware_resources: BTreeSet<ContextAwareResource>,
+    /// The Kubernetes Service Account impersonated by the policy when
+    /// interacting with the Kubernetes API server
+    pub service_account: Option<KubernetesServiceAccount>,
+    /// Directories of the host given to a WASI policy, in read-only mode. See the
+    /// `preopenedDirs` of the individual policies
+    #[serde(default)]
+    pub preopened_dirs: BTreeMap<String, PathBuf>,
 }
 
 impl PolicyGroupMember {
@@ -317,9 +1137,50 @@ impl PolicyGroupMember {
     }
 }
 
+/// The requests an OPA or Gatekeeper policy can make with the `http.send` builtin.
+/// The builtin is disabled when not set
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+#[serde(deny_unknown_fields, rename_all = "camelCase")]
+pub struct HttpSend {
+    /// The origins the policy can reach, like `https://api.example.com` or
+    /// `http://registry.internal:5000`. A host like `*.example.com` matches all the
+    /// subdomains of `example.com`. The redirects leaving these origins are refused
+    pub allowed_origins: Vec<String>,
+    /// Maximum duration of a request, in seconds. Policies can ask for a shorter timeout
+    #[serde(default = "default_http_send_timeout_seconds")]
+    pub timeout_seconds: u64,
+}
+
+fn default_http_send_timeout_seconds() -> u64 {
+    5
+}
+
+impl HttpSend {
+    pub fn http_policy(&self) -> HttpPolicy {
+        HttpPolicy {
+            allowed_origins: self.allowed_origins.clone(),
+            timeout: Duration::from_secs(self.timeout_seconds),
+        }
+    }
+}
+
+/// A CEL expression that must evaluate to `true` for an admission request to be
+/// evaluated by the policy. This mirrors the `matchConditions` of the Kubernetes
+/// admission webhooks
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct MatchCondition {
+    /// Identifies the condition, must be unique among the ones of the policy
+    pub name: String,
+    /// The CEL expression, which can access the `object`, `oldObject` and `request`
+    /// variables
+    pub expression: String,
+}
+
 /// Describes a policy that can be either an individual policy or a group policy.
 #[derive(Deserialize, Debug, Clone, PartialEq)]
 #[serde(untagged)]
+#[allow(clippy::large_enum_variant)]
 pub enum PolicyOrPolicyGroup {
     /// An individual policy
     #[serde(rename_all = "camelCase")]
@@ -329,15 +1190,60 @@ pub enum PolicyOrPolicyGroup {
         #[serde(default)]
         /// The mode of the policy
         policy_mode: PolicyMode,
+        /// How the policy reacts to the runtime errors raised while evaluating a request
+        #[serde(default)]
+        failure_policy: FailurePolicy,
+        /// Interrupt the evaluation of a request after the given number of seconds,
+        /// overriding the value of `--policy-timeout`
+        timeout_seconds: Option<u64>,
         /// Whether the policy is allowed to mutate the request
         allowed_to_mutate: Option<bool>,
         /// The settings for the policy, as provided by the user
         settings: Option<PolicySettings>,
+        /// Settings overriding the base ones when the request is made inside of the
+        /// given namespace. The top-level keys of an override replace the ones of
+        /// the base settings
+        #[serde(default)]
+        namespace_settings: HashMap<String, PolicySettings>,
         #[serde(default)]
         /// The list of Kubernetes resources the policy is allowed to access
         context_aware_resources: BTreeSet<ContextAwareResource>,
+        /// The Kubernetes Service Account impersonated by the policy when interacting
+        /// with the Kubernetes API server. The Service Account of Policy Server is
+        /// used when not set
+        service_account: Option<KubernetesServiceAccount>,
         /// The message that is returned when the policy evaluates to false
         message: Option<String>,
+        /// The entrypoint to be evaluated, applies only to OPA and Gatekeeper policies.
+        /// This allows the same Wasm module to serve different rules. When not set, the
+        /// default entrypoint of the module is used
+        entrypoint: Option<String>,
+        /// Rego libraries linked into the `data` document of the policy, applies only to
+        /// OPA policies. The key is the path where the library is linked, like `lib.images`,
+        /// the value is the OCI artifact holding the library, which must be pinned by digest
+        #[serde(default)]
+        rego_libraries: BTreeMap<String, String>,
+        /// The admission requests are evaluated by the policy only when all these
+        /// conditions are met, the other ones are accepted
+        #[serde(default)]
+        match_conditions: Vec<MatchCondition>,
+        /// Whether the policy receives the values of the Secrets. When not set, the
+        /// values are masked only when `--redact-secret-data` is enabled
+        secret_data: Option<SecretData>,
+        /// The requests the policy can make with the `http.send` builtin, applies only
+        /// to OPA and Gatekeeper policies. The builtin is disabled when not set
+        http_send: Option<HttpSend>,
+        /// Whether the policy can use the key/value store offered by Policy Server,
+        /// which must be enabled with `--capabilities-key-value-store`. The policy
+        /// can access only its own entries
+        #[serde(default)]
+        key_value_store: bool,
+        /// Directories of the host given to a WASI policy, in read-only mode. The key is
+        /// the path inside of the policy, which must be declared among the `preopenedDirs`
+        /// of the policy metadata, the value is the directory of the host. All the
+        /// directories declared by the metadata must be mapped
+        #[serde(default)]
+        preopened_dirs: BTreeMap<String, PathBuf>,
     },
     /// A group of policies that are evaluated together using a given expression
     #[serde(rename_all = "camelCase")]
@@ -345,6 +1251,12 @@ pub enum PolicyOrPolicyGroup {
         /// The mode of the policy
         #[serde(default)]
         policy_mode: PolicyMode,
+        /// How the policy group reacts to the runtime errors raised while evaluating a request
+        #[serde(default)]
+        failure_policy: FailurePolicy,
+        /// Interrupt the evaluation of each member of the group after the given number of
+        /// seconds, overriding the value of `--policy-timeout`
+        timeout_seconds: Option<u64>,
         /// The policies that make up for this group
         /// Key is a unique identifier
         policies: HashMap<String, PolicyGroupMember>,
@@ -352,6 +1264,13 @@ pub enum PolicyOrPolicyGroup {
         expression: String,
         /// The message that is returned when the group of policies evaluates to false
         message: String,
+        /// The admission requests are evaluated by the policy group only when all
+        /// these conditions are met, the other ones are accepted
+        #[serde(default)]
+        match_conditions: Vec<MatchCondition>,
+        /// Whether the members of the group receive the values of the Secrets. When
+        /// not set, the values are masked only when `--redact-secret-data` is enabled
+        secret_data: Option<SecretData>,
     },
 }
 
@@ -373,15 +1292,35 @@ impl PolicyOrPolicyGroup {
             }),
         }
     }
+
+    /// The conditions the admission requests must meet to be evaluated
+    pub fn match_conditions(&self) -> &[MatchCondition] {
+        match self {
+            PolicyOrPolicyGroup::Policy {
+                match_conditions, ..
+            }
+            | PolicyOrPolicyGroup::PolicyGroup {
+                match_conditions, ..
+            } => match_conditions,
+        }
+    }
+
+    /// Whether the policy receives the values of the Secrets, `None` when the
+    /// global behaviour applies
+    pub fn secret_data(&self) -> Option<SecretData> {
+        match self {
+            PolicyOrPolicyGroup::Policy { secret_data, .. }
+            | PolicyOrPolicyGroup::PolicyGroup { secret_data, .. } => *secret_data,
+        }
+    }
 }
 
-/// Reads the policies configuration file, returns a HashMap with String as value
-/// and Policy as values. The key is the name of the policy as provided by the user
-/// inside of the configuration file. This name is used to build the API path
-/// exposing the policy.
-fn read_policies_file(path: &Path) -> Result<HashMap<String, PolicyOrPolicyGroup>> {
-    let settings_file = File::open(path)?;
-    let ps: HashMap<String, PolicyOrPolicyGroup> = serde_yaml::from_reader(&settings_file)?;
+/// Parses the contents of the policies configuration file, returns a HashMap with
+/// String as value and Policy as values. The key is the name of the policy as provided
+/// by the user inside of the configuration file. This name is used to build the API
+/// path exposing the policy.
+fn parse_policies_file(data: &[u8]) -> Result<HashMap<String, PolicyOrPolicyGroup>> {
+    let ps: HashMap<String, PolicyOrPolicyGroup> = serde_yaml::from_slice(data)?;
     Ok(ps)
 }
 
@@ -438,7 +1377,7 @@ mod tests {
     use tempfile::NamedTempFile;
 
     #[test]
-    fn read_policies_file_test() {
+    fn parse_policies_file_test() {
         let policies_yaml = r#"
 ---
 example:
@@ -453,6 +1392,7 @@ example:
           kind: Pod
 group_policy:
     policyMode: monitor
+    failurePolicy: Ignore
     expression: "true"
     message: "group policy message"
     policies:
@@ -464,11 +1404,7 @@ group_policy:
             settings: {}
 "#;
 
-        let mut temp_file = NamedTempFile::new().unwrap();
-        temp_file.write_all(policies_yaml.as_bytes()).unwrap();
-        let file_path = temp_file.into_temp_path();
-
-        let policies = read_policies_file(file_path.as_ref()).unwrap();
+        let policies = parse_policies_file(policies_yaml.as_bytes()).unwrap();
 
         let expected_policies = HashMap::from([
             (
@@ -476,8 +1412,11 @@ group_policy:
                 PolicyOrPolicyGroup::Policy {
                     module: "ghcr.io/kubewarden/policies/context-aware-policy:0.1.0".to_owned(),
                     policy_mode: PolicyMode::Protect,
+                    failure_policy: FailurePolicy::Fail,
+                    timeout_seconds: None,
                     allowed_to_mutate: Some(true),
                     settings: Some(PolicySettings::default()),
+                    namespace_settings: HashMap::new(),
                     context_aware_resources: BTreeSet::from([
                         ContextAwareResource {
                             api_version: "v1".to_owned(),
@@ -488,13 +1427,23 @@ group_policy:
                             kind: "Pod".to_owned(),
                         },
                     ]),
+                    service_account: None,
                     message: Some("my custom error message".to_owned()),
+                    entrypoint: None,
+                    rego_libraries: BTreeMap::new(),
+                    match_conditions: Vec::new(),
+                    secret_data: None,
+                    http_send: None,
+                    key_value_store: false,
+                    preopened_dirs: BTreeMap::new(),
                 },
             ),
             (
                 "group_policy".to_owned(),
                 PolicyOrPolicyGroup::PolicyGroup {
                     policy_mode: PolicyMode::Monitor,
+                    failure_policy: FailurePolicy::Ignore,
+                    timeout_seconds: None,
                     expression: "true".to_owned(),
                     message: "group policy message".to_owned(),
                     policies: HashMap::from([
@@ -504,6 +1453,8 @@ group_policy:
                                 module: "ghcr.io/kubewarden/policies/policy1:0.1.0".to_owned(),
                                 settings: Some(PolicySettings::default()),
                                 context_aware_resources: BTreeSet::new(),
+                                service_account: None,
+                                preopened_dirs: BTreeMap::new(),
                             },
                         ),
                         (
@@ -512,9 +1463,13 @@ group_policy:
                                 module: "ghcr.io/kubewarden/policies/policy2:0.1.0".to_owned(),
                                 settings: Some(PolicySettings::default()),
                                 context_aware_resources: BTreeSet::new(),
+                                service_account: None,
+                                preopened_dirs: BTreeMap::new(),
                             },
                         ),
                     ]),
+                    match_conditions: Vec::new(),
+                    secret_data: None,
                 },
             ),
         ]);
@@ -522,6 +1477,25 @@ group_policy:
         assert_eq!(expected_policies, policies);
     }
 
+    #[rstest]
+    #[case::not_set("", None)]
+    #[case::redacted("secretData: redacted", Some(SecretData::Redacted))]
+    #[case::revealed("secretData: revealed", Some(SecretData::Revealed))]
+    fn policy_secret_data(#[case] secret_data: &str, #[case] expected: Option<SecretData>) {
+        let policies_yaml = format!(
+            r#"
+---
+example:
+  module: ghcr.io/kubewarden/policies/read-secrets:0.1.0
+  {secret_data}
+"#
+        );
+
+        let policies = parse_policies_file(policies_yaml.as_bytes()).unwrap();
+
+        assert_eq!(policies["example"].secret_data(), expected);
+    }
+
     #[rstest]
     #[case::settings_empty(
         r#"
@@ -571,6 +1545,362 @@ example:
         }
     }
 
+    #[test]
+    fn parse_namespace_settings() {
+        let input = r#"
+---
+example:
+  module: file:///tmp/namespace-validate-policy.wasm
+  settings:
+    allowLatestTag: false
+    maxReplicas: 3
+  namespaceSettings:
+    dev:
+      allowLatestTag: true
+"#;
+        let policies: HashMap<String, PolicyOrPolicyGroup> = serde_yaml::from_str(input).unwrap();
+
+        match policies.get("example").unwrap() {
+            PolicyOrPolicyGroup::Policy {
+                namespace_settings, ..
+            } => {
+                assert_eq!(
+                    namespace_settings,
+                    &HashMap::from([(
+                        "dev".to_owned(),
+                        PolicySettings::try_from(&json!({"allowLatestTag": true})).unwrap()
+                    )])
+                );
+            }
+            _ => panic!("Expected an Individual policy"),
+        }
+    }
+
+    #[test]
+    fn parse_service_account() {
+        let input = r#"
+---
+example:
+  module: file:///tmp/context-aware-policy.wasm
+  serviceAccount:
+    namespace: kubewarden
+    name: example-policy
+group_policy:
+  expression: "member()"
+  message: "denied"
+  policies:
+    member:
+      module: file:///tmp/context-aware-policy.wasm
+      serviceAccount:
+        namespace: kubewarden
+        name: member-policy
+"#;
+        let policies: HashMap<String, PolicyOrPolicyGroup> = serde_yaml::from_str(input).unwrap();
+
+        match policies.get("example").unwrap() {
+            PolicyOrPolicyGroup::Policy {
+                service_account, ..
+            } => {
+                assert_eq!(
+                    service_account,
+                    &Some(KubernetesServiceAccount {
+                        namespace: "kubewarden".to_owned(),
+                        name: "example-policy".to_owned(),
+                    })
+                );
+            }
+            _ => panic!("Expected an Individual policy"),
+        }
+        match policies.get("group_policy").unwrap() {
+            PolicyOrPolicyGroup::PolicyGroup { policies, .. } => {
+                assert_eq!(
+                    policies.get("member").unwrap().service_account,
+                    Some(KubernetesServiceAccount {
+                        namespace: "kubewarden".to_owned(),
+                        name: "member-policy".to_owned(),
+                    })
+                );
+            }
+            _ => panic!("Expected a Group policy"),
+        }
+    }
+
+    #[test]
+    fn parse_opa_entrypoint() {
+        let input = r#"
+---
+example:
+  module: file:///tmp/opa-policies.wasm
+  entrypoint: policies/disallow_host_network
+"#;
+        let policies: HashMap<String, PolicyOrPolicyGroup> = serde_yaml::from_str(input).unwrap();
+
+        match policies.get("example").unwrap() {
+            PolicyOrPolicyGroup::Policy { entrypoint, .. } => {
+                assert_eq!(
+                    entrypoint.as_deref(),
+                    Some("policies/disallow_host_network")
+                );
+            }
+            _ => panic!("Expected an Individual policy"),
+        }
+    }
+
+    #[test]
+    fn parse_rego_libraries() {
+        let input = r#"
+---
+example:
+  module: file:///tmp/opa-policies.wasm
+  regoLibraries:
+    lib.images: registry://ghcr.io/org/images-library@sha256:9f2c8b0c0c1e0f1d4d5c6b7a8f9e0d1c2b3a4f5e6d7c8b9a0f1e2d3c4b5a6f7e
+"#;
+        let policies: HashMap<String, PolicyOrPolicyGroup> = serde_yaml::from_str(input).unwrap();
+
+        match policies.get("example").unwrap() {
+            PolicyOrPolicyGroup::Policy { rego_libraries, .. } => {
+                assert_eq!(
+                    rego_libraries.get("lib.images").map(String::as_str),
+                    Some("registry://ghcr.io/org/images-library@sha256:9f2c8b0c0c1e0f1d4d5c6b7a8f9e0d1c2b3a4f5e6d7c8b9a0f1e2d3c4b5a6f7e")
+                );
+            }
+            _ => panic!("Expected an Individual policy"),
+        }
+    }
+
+    #[test]
+    fn parse_http_send() {
+        let input = r#"
+example:
+  module: ghcr.io/kubewarden/tests/opa-policy:v0.1.0
+  httpSend:
+    allowedOrigins:
+    - https://api.example.com
+    - http://*.internal:8080
+"#;
+        let policies: HashMap<String, PolicyOrPolicyGroup> = serde_yaml::from_str(input).unwrap();
+        validate_http_send(&policies).unwrap();
+
+        match policies.get("example").unwrap() {
+            PolicyOrPolicyGroup::Policy { http_send, .. } => {
+                assert_eq!(
+                    http_send.as_ref().map(HttpSend::http_policy),
+                    Some(HttpPolicy {
+                        allowed_origins: vec![
+                            "https://api.example.com".to_owned(),
+                            "http://*.internal:8080".to_owned()
+                        ],
+                        timeout: Duration::from_secs(5),
+                    })
+                );
+            }
+            _ => panic!("Expected an Individual policy"),
+        }
+    }
+
+    #[rstest]
+    #[case::no_scheme("api.example.com", 5)]
+    #[case::path("https://api.example.com/v1", 5)]
+    #[case::zero_timeout("https://api.example.com", 0)]
+    fn invalid_http_send(#[case] origin: &str, #[case] timeout_seconds: u64) {
+        let input = format!(
+            r#"
+example:
+  module: ghcr.io/kubewarden/tests/opa-policy:v0.1.0
+  httpSend:
+    allowedOrigins:
+    - {origin}
+    timeoutSeconds: {timeout_seconds}
+"#
+        );
+        let policies: HashMap<String, PolicyOrPolicyGroup> = serde_yaml::from_str(&input).unwrap();
+
+        assert!(validate_http_send(&policies).is_err());
+    }
+
+    #[rstest]
+    #[case::store_enabled(true, true, true)]
+    #[case::store_not_used(false, false, true)]
+    #[case::store_disabled(true, false, false)]
+    fn key_value_store_opt_in(
+        #[case] opt_in: bool,
+        #[case] key_value_store_enabled: bool,
+        #[case] valid: bool,
+    ) {
+        let input = format!(
+            r#"
+example:
+  module: ghcr.io/kubewarden/tests/rate-limit:v0.1.0
+  keyValueStore: {opt_in}
+"#
+        );
+        let policies: HashMap<String, PolicyOrPolicyGroup> = serde_yaml::from_str(&input).unwrap();
+
+        assert_eq!(
+            validate_key_value_store(&policies, key_value_store_enabled).is_ok(),
+            valid
+        );
+    }
+
+    #[test]
+    fn preopened_dirs() {
+        let input = r#"
+example:
+  module: ghcr.io/kubewarden/tests/wasi-policy:v0.1.0
+  preopenedDirs:
+    /data: /var/lib/kubewarden/data
+"#;
+        let policies: HashMap<String, PolicyOrPolicyGroup> = serde_yaml::from_str(input).unwrap();
+
+        match policies.get("example").unwrap() {
+            PolicyOrPolicyGroup::Policy { preopened_dirs, .. } => {
+                assert_eq!(
+                    preopened_dirs,
+                    &BTreeMap::from([(
+                        "/data".to_owned(),
+                        PathBuf::from("/var/lib/kubewarden/data")
+                    )])
+                );
+            }
+            _ => panic!("Expected an Individual policy"),
+        }
+    }
+
+    #[rstest]
+    #[case::valid_signature("policies.yml", None, true)]
+    #[case::explicit_signature("policies.yml", Some("policies.yml.sig"), true)]
+    #[case::tampered_policies("tampered-policies.yml", Some("policies.yml.sig"), false)]
+    #[case::missing_signature("tampered-policies.yml", None, false)]
+    fn policies_signature_verification(
+        #[case] policies_file: &str,
+        #[case] signature_file: Option<&str>,
+        #[case] is_valid: bool,
+    ) {
+        let data_dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/data/signed-policies");
+        let mut args = vec![
+            "policy-server".to_owned(),
+            format!("--policies={}", data_dir.join(policies_file).display()),
+            format!(
+                "--policies-verification-key={}",
+                data_dir.join("cosign.pub").display()
+            ),
+        ];
+        if let Some(signature_file) = signature_file {
+            args.push(format!(
+                "--policies-signature={}",
+                data_dir.join(signature_file).display()
+            ));
+        }
+
+        let matches = cli::build_cli().try_get_matches_from(args).unwrap();
+        let result = policies(&matches, None);
+        assert_eq!(is_valid, result.is_ok(), "{:?}", result.err());
+        if is_valid {
+            assert!(result.unwrap().contains_key("pod-privileged"));
+        }
+    }
+
+    #[test]
+    fn policies_from_state_dir() {
+        let state_dir = tempfile::TempDir::new().unwrap();
+        fs::write(
+            state::policies_file(state_dir.path()),
+            r#"
+---
+example:
+  module: ghcr.io/kubewarden/policies/context-aware-policy:0.1.0
+"#,
+        )
+        .unwrap();
+
+        let matches = cli::build_cli()
+            .try_get_matches_from([
+                "policy-server",
+                "--policies=does-not-exist.yml",
+                &format!("--state-dir={}", state_dir.path().display()),
+            ])
+            .unwrap();
+        let policies = policies(&matches, Some(state_dir.path())).unwrap();
+
+        assert!(policies.contains_key("example"));
+    }
+
+    #[rstest]
+    #[case::key("secret\n", Some(b"secret".as_slice()))]
+    #[case::empty_key(" \n", None)]
+    fn module_cache_key(#[case] key: &str, #[case] expected: Option<&[u8]>) {
+        let dir = tempfile::TempDir::new().unwrap();
+        let key_file = dir.path().join("key");
+        fs::write(&key_file, key).unwrap();
+
+        let matches = cli::build_cli()
+            .try_get_matches_from([
+                "policy-server",
+                &format!("--module-cache-dir={}", dir.path().join("cache").display()),
+                &format!("--module-cache-key-file={}", key_file.display()),
+            ])
+            .unwrap();
+        let module_cache = module_cache_config(&matches);
+
+        match expected {
+            Some(expected) => {
+                let module_cache = module_cache.unwrap().unwrap();
+                assert_eq!(module_cache.dir, dir.path().join("cache"));
+                assert_eq!(module_cache.key, expected);
+            }
+            None => assert!(module_cache.is_err()),
+        }
+    }
+
+    #[rstest]
+    #[case::none(None, Some(vec![]))]
+    #[case::many(
+        Some("https://policy-server-peers.kubewarden.svc:8443,http://10.0.0.1:3000"),
+        Some(vec!["https://policy-server-peers.kubewarden.svc:8443/", "http://10.0.0.1:3000/"])
+    )]
+    #[case::not_http(Some("ftp://example.com"), None)]
+    #[case::not_url(Some("policy-server"), None)]
+    fn module_cache_peers(#[case] peers: Option<&str>, #[case] expected: Option<Vec<&str>>) {
+        let dir = tempfile::TempDir::new().unwrap();
+        let key_file = dir.path().join("key");
+        fs::write(&key_file, "secret").unwrap();
+
+        let mut args = vec![
+            "policy-server".to_owned(),
+            format!("--module-cache-dir={}", dir.path().join("cache").display()),
+            format!("--module-cache-key-file={}", key_file.display()),
+        ];
+        if let Some(peers) = peers {
+            args.push(format!("--module-cache-peers={peers}"));
+        }
+        let matches = cli::build_cli().try_get_matches_from(args).unwrap();
+        let module_cache = module_cache_config(&matches);
+
+        match expected {
+            Some(expected) => {
+                let peers: Vec<String> = module_cache
+                    .unwrap()
+                    .unwrap()
+                    .peers
+                    .iter()
+                    .map(|peer| peer.to_string())
+                    .collect();
+                assert_eq!(peers, expected);
+            }
+            None => assert!(module_cache.is_err()),
+        }
+    }
+
+    #[test]
+    fn module_cache_requires_a_key() {
+        let result = cli::build_cli().try_get_matches_from([
+            "policy-server",
+            "--module-cache-dir=/var/cache/policy-server",
+        ]);
+
+        assert!(result.is_err());
+    }
+
     #[test]
     fn boolean_flags() {
         let policies_yaml = r#"
@@ -589,6 +1919,10 @@ example:
             "--log-no-color",
             "--daemon",
             "--enable-metrics",
+            "--enable-log-filter-admin",
+            "--lazy-policy-loading",
+            "--disable-response-compression",
+            "--readiness-requires-kubernetes-sync",
         ];
 
         for provide_flag in [true, false] {
@@ -605,9 +1939,505 @@ example:
             assert_eq!(provide_flag, config.log_no_color);
             assert_eq!(provide_flag, config.daemon);
             assert_eq!(provide_flag, config.metrics_enabled);
+            assert_eq!(provide_flag, config.enable_log_filter_admin);
+            assert_eq!(provide_flag, config.lazy_policy_loading);
+            assert_eq!(provide_flag, !config.response_compression);
+            assert_eq!(provide_flag, config.readiness_requires_kubernetes_sync);
         }
     }
 
+    #[rstest]
+    #[case::plain_http(&[], Ok(None))]
+    #[case::own_certificate(
+        &["--readiness-probe-cert-file=probe.crt", "--readiness-probe-key-file=probe.key"],
+        Ok(Some(TlsConfig {
+            cert_file: "probe.crt".into(),
+            key_file: "probe.key".into(),
+            client_ca_file: vec![],
+        }))
+    )]
+    #[case::reuse_api_certificate(
+        &[
+            "--cert-file=api.crt",
+            "--key-file=api.key",
+            "--client-ca-file=api-ca.crt",
+            "--readiness-probe-reuse-tls",
+            "--readiness-probe-client-ca-file=probe-ca.crt",
+        ],
+        Ok(Some(TlsConfig {
+            cert_file: "api.crt".into(),
+            key_file: "api.key".into(),
+            client_ca_file: vec!["probe-ca.crt".into()],
+        }))
+    )]
+    #[case::reuse_without_api_certificate(&["--readiness-probe-reuse-tls"], Err(()))]
+    #[case::missing_key(&["--readiness-probe-cert-file=probe.crt"], Err(()))]
+    #[case::client_ca_without_certificate(
+        &["--readiness-probe-client-ca-file=probe-ca.crt"],
+        Err(())
+    )]
+    fn readiness_probe_tls(
+        #[case] args: &[&str],
+        #[case] expected: std::result::Result<Option<TlsConfig>, ()>,
+    ) {
+        let mut flags = vec!["policy-server"];
+        flags.extend(args);
+        let matches = cli::build_cli().try_get_matches_from(flags).unwrap();
+        let api_tls_config = build_tls_config(&matches, &API_TLS_ARGS).unwrap();
+
+        let tls_config = build_readiness_probe_tls_config(&matches, api_tls_config.as_ref());
+        assert_eq!(tls_config.map_err(|_| ()), expected);
+    }
+
+    #[rstest]
+    #[case::api_address(&["--addr=127.0.0.1"], "127.0.0.1:8081")]
+    #[case::own_address(
+        &["--addr=127.0.0.1", "--readiness-probe-addr=10.0.0.1", "--readiness-probe-port=9000"],
+        "10.0.0.1:9000"
+    )]
+    fn readiness_probe_address(#[case] args: &[&str], #[case] expected: &str) {
+        let mut flags = vec!["policy-server"];
+        flags.extend(args);
+        let matches = cli::build_cli().try_get_matches_from(flags).unwrap();
+
+        assert_eq!(
+            readiness_probe_bind_address(&matches).unwrap(),
+            expected.parse::<SocketAddr>().unwrap()
+        );
+    }
+
+    #[test]
+    fn lazy_policy_warm_up_list() {
+        let policies_yaml = r#"
+---
+example:
+  module: file:///tmp/namespace-validate-policy.wasm
+  settings: {}
+"#;
+        let mut temp_file = NamedTempFile::new().unwrap();
+        temp_file.write_all(policies_yaml.as_bytes()).unwrap();
+        let file_path = temp_file.into_temp_path();
+        let policies_flag = format!("--policies={}", file_path.to_str().unwrap());
+
+        let matches = cli::build_cli()
+            .try_get_matches_from(vec![
+                "policy-server",
+                &policies_flag,
+                "--lazy-policy-loading",
+                "--lazy-policy-warm-up=policy-b,policy-a",
+            ])
+            .unwrap();
+        let config = Config::from_args(&matches).unwrap();
+        assert!(config.lazy_policy_loading);
+        assert_eq!(config.lazy_policy_warm_up, vec!["policy-b", "policy-a"]);
+
+        // the warm-up list is meaningless without lazy loading
+        assert!(cli::build_cli()
+            .try_get_matches_from(vec![
+                "policy-server",
+                &policies_flag,
+                "--lazy-policy-warm-up=policy-a",
+            ])
+            .is_err());
+    }
+
+    #[rstest]
+    #[case::defaults(&[], Some(FetcherOptions::default()))]
+    #[case::custom(
+        &["--policy-fetch-concurrency=1", "--registry-politeness-delay=250", "--policy-fetch-max-retries=0"],
+        Some(FetcherOptions {
+            concurrency: 1,
+            registry_delay: Duration::from_millis(250),
+            max_retries: 0,
+        })
+    )]
+    #[case::no_concurrency(&["--policy-fetch-concurrency=0"], None)]
+    fn policy_fetch_flags(#[case] flags: &[&str], #[case] expected: Option<FetcherOptions>) {
+        let policies_yaml = r#"
+---
+example:
+  module: file:///tmp/namespace-validate-policy.wasm
+  settings: {}
+"#;
+        let mut temp_file = NamedTempFile::new().unwrap();
+        temp_file.write_all(policies_yaml.as_bytes()).unwrap();
+        let file_path = temp_file.into_temp_path();
+        let policies_flag = format!("--policies={}", file_path.to_str().unwrap());
+
+        let mut args = vec!["policy-server", &policies_flag];
+        args.extend(flags);
+        let matches = cli::build_cli().try_get_matches_from(args).unwrap();
+        let config = Config::from_args(&matches);
+        assert_eq!(config.ok().map(|config| config.policy_fetch), expected);
+    }
+
+    #[rstest]
+    #[case::defaults(&[], Some(PriorityConfig::default()))]
+    #[case::custom(
+        &["--priority-critical-namespaces=kube-system, ,infra", "--priority-starvation-threshold=2"],
+        Some(PriorityConfig {
+            critical_namespaces: HashSet::from(["kube-system".to_owned(), "infra".to_owned()]),
+            starvation_threshold: 2,
+        })
+    )]
+    #[case::no_critical_namespaces(
+        &["--priority-critical-namespaces="],
+        Some(PriorityConfig {
+            critical_namespaces: HashSet::new(),
+            starvation_threshold: 8,
+        })
+    )]
+    #[case::invalid_threshold(&["--priority-starvation-threshold=-1"], None)]
+    fn priority_flags(#[case] flags: &[&str], #[case] expected: Option<PriorityConfig>) {
+        let policies_yaml = r#"
+---
+example:
+  module: file:///tmp/namespace-validate-policy.wasm
+  settings: {}
+"#;
+        let mut temp_file = NamedTempFile::new().unwrap();
+        temp_file.write_all(policies_yaml.as_bytes()).unwrap();
+        let file_path = temp_file.into_temp_path();
+        let policies_flag = format!("--policies={}", file_path.to_str().unwrap());
+
+        let mut args = vec!["policy-server", &policies_flag];
+        args.extend(flags);
+        let matches = cli::build_cli().try_get_matches_from(args).unwrap();
+        let config = Config::from_args(&matches);
+        assert_eq!(config.ok().map(|config| config.priority), expected);
+    }
+
+    #[rstest]
+    #[case::defaults(&[], Some((Some(2), Some(2), Duration::from_secs(1))))]
+    #[case::custom(
+        &[
+            "--policy-timeout=1",
+            "--policy-settings-validation-timeout=10",
+            "--policy-timeout-tick-interval=10",
+        ],
+        Some((Some(1), Some(10), Duration::from_millis(10)))
+    )]
+    #[case::settings_validation_defaults_to_policy_timeout(
+        &["--policy-timeout=5"],
+        Some((Some(5), Some(5), Duration::from_secs(1)))
+    )]
+    #[case::disabled(
+        &["--disable-timeout-protection", "--policy-settings-validation-timeout=10"],
+        Some((None, None, Duration::from_secs(1)))
+    )]
+    #[case::invalid_tick_interval(&["--policy-timeout-tick-interval=0"], None)]
+    fn policy_timeout_flags(
+        #[case] flags: &[&str],
+        #[case] expected: Option<(Option<u64>, Option<u64>, Duration)>,
+    ) {
+        let policies_yaml = r#"
+---
+example:
+  module: file:///tmp/namespace-validate-policy.wasm
+  settings: {}
+"#;
+        let mut temp_file = NamedTempFile::new().unwrap();
+        temp_file.write_all(policies_yaml.as_bytes()).unwrap();
+        let file_path = temp_file.into_temp_path();
+        let policies_flag = format!("--policies={}", file_path.to_str().unwrap());
+
+        let mut args = vec!["policy-server", &policies_flag];
+        args.extend(flags);
+        let matches = cli::build_cli().try_get_matches_from(args).unwrap();
+        let config = Config::from_args(&matches);
+        assert_eq!(
+            config.ok().map(|config| (
+                config.policy_evaluation_limit_seconds,
+                config.policy_settings_validation_limit_seconds,
+                config.policy_timeout_tick_interval,
+            )),
+            expected
+        );
+    }
+
+    #[rstest]
+    #[case::defaults(&[], Some(CapabilitiesConfig::default()))]
+    #[case::custom(
+        &[
+            "--capabilities-client-pool-size=4",
+            "--capabilities-client-idle-timeout=10",
+            "--capabilities-dns-cache-ttl=0",
+            "--capabilities-dns-cache-size=0",
+            "--capabilities-kubernetes-coalescing-window=0",
+        ],
+        Some(CapabilitiesConfig {
+            client_pool: ClientPoolConfig {
+                max_size: 4,
+                idle_timeout: Duration::from_secs(10),
+            },
+            dns_cache: DnsCacheConfig {
+                ttl: Duration::ZERO,
+                max_entries: 0,
+            },
+            request_coalescing: RequestCoalescingConfig {
+                window: Duration::ZERO,
+            },
+            response_size_limits: ResponseSizeLimits::default(),
+            key_value_store: None,
+            usage_analysis_window: None,
+        })
+    )]
+    #[case::response_size_limits(
+        &[
+            "--capabilities-response-size-limit=1048576",
+            "--capabilities-response-size-limits=kubernetes/list_resources_all=4096, oci/oci_manifest=512",
+        ],
+        Some(CapabilitiesConfig {
+            response_size_limits: ResponseSizeLimits {
+                default: Some(1048576),
+                capabilities: HashMap::from([
+                    ("kubernetes/list_resources_all".to_owned(), 4096),
+                    ("oci/oci_manifest".to_owned(), 512),
+                ]),
+            },
+            ..Default::default()
+        })
+    )]
+    #[case::key_value_store(
+        &[
+            "--capabilities-key-value-store",
+            "--capabilities-key-value-store-max-entries=10",
+            "--capabilities-key-value-store-max-ttl=60",
+        ],
+        Some(CapabilitiesConfig {
+            key_value_store: Some(KeyValueStoreConfig {
+                max_entries: 10,
+                max_ttl: Duration::from_secs(60),
+                ..Default::default()
+            }),
+            ..Default::default()
+        })
+    )]
+    #[case::key_value_store_limits_without_store(
+        &["--capabilities-key-value-store-max-entries=10"],
+        Some(CapabilitiesConfig::default())
+    )]
+    #[case::usage_analysis_window(
+        &["--capabilities-usage-analysis-window=300"],
+        Some(CapabilitiesConfig {
+            usage_analysis_window: Some(Duration::from_secs(300)),
+            ..Default::default()
+        })
+    )]
+    #[case::invalid_idle_timeout(&["--capabilities-client-idle-timeout=-1"], None)]
+    #[case::invalid_usage_analysis_window(&["--capabilities-usage-analysis-window=5m"], None)]
+    #[case::invalid_key_value_store_max_ttl(
+        &["--capabilities-key-value-store", "--capabilities-key-value-store-max-ttl=-1"],
+        None
+    )]
+    #[case::unknown_response_size_capability(
+        &["--capabilities-response-size-limits=kubernetes/list_everything=4096"],
+        None
+    )]
+    #[case::invalid_response_size_limit(
+        &["--capabilities-response-size-limits=kubernetes/list_resources_all"],
+        None
+    )]
+    fn capabilities_flags(#[case] flags: &[&str], #[case] expected: Option<CapabilitiesConfig>) {
+        let policies_yaml = r#"
+---
+example:
+  module: file:///tmp/namespace-validate-policy.wasm
+  settings: {}
+"#;
+        let mut temp_file = NamedTempFile::new().unwrap();
+        temp_file.write_all(policies_yaml.as_bytes()).unwrap();
+        let file_path = temp_file.into_temp_path();
+        let policies_flag = format!("--policies={}", file_path.to_str().unwrap());
+
+        let mut args = vec!["policy-server", &policies_flag];
+        args.extend(flags);
+        let matches = cli::build_cli().try_get_matches_from(args).unwrap();
+        let config = Config::from_args(&matches);
+        assert_eq!(config.ok().map(|config| config.capabilities), expected);
+    }
+
+    #[rstest]
+    #[case::defaults(&[], Some((vec![], 1000, FailurePolicy::Fail)))]
+    #[case::custom(
+        &[
+            "--request-enrichment-url=http://enricher.local/enrich",
+            "--request-enrichment-timeout=250",
+            "--request-enrichment-failure-policy=Ignore",
+        ],
+        Some((vec!["http://enricher.local/enrich"], 250, FailurePolicy::Ignore))
+    )]
+    #[case::invalid_timeout(&["--request-enrichment-timeout=-1"], None)]
+    fn enrichment_flags(
+        #[case] flags: &[&str],
+        #[case] expected: Option<(Vec<&str>, u64, FailurePolicy)>,
+    ) {
+        let mut args = vec!["policy-server"];
+        args.extend(flags);
+        let matches = cli::build_cli().try_get_matches_from(args).unwrap();
+
+        let enrichment = enrichment_config(&matches).ok();
+        assert_eq!(
+            enrichment.as_ref().map(|enrichment| (
+                enrichment
+                    .enrichers
+                    .iter()
+                    .map(|enricher| enricher.name())
+                    .collect::<Vec<_>>(),
+                enrichment.timeout.as_millis() as u64,
+                enrichment.failure_policy
+            )),
+            expected
+        );
+    }
+
+    #[rstest]
+    #[case::disabled(&[], Some(None))]
+    #[case::enabled(
+        &["--policy-reverification-interval=3600"],
+        Some(Some(Duration::from_secs(3600)))
+    )]
+    #[case::zero_interval(&["--policy-reverification-interval=0"], None)]
+    #[case::invalid_interval(&["--policy-reverification-interval=1h"], None)]
+    fn policy_reverification_flags(
+        #[case] flags: &[&str],
+        #[case] expected: Option<Option<Duration>>,
+    ) {
+        let policies_yaml = r#"
+---
+example:
+  module: registry://ghcr.io/kubewarden/tests/pod-privileged:v0.2.1
+  settings: {}
+"#;
+        let verification_yaml = r#"
+---
+apiVersion: v1
+allOf:
+  - kind: githubAction
+    owner: kubewarden
+"#;
+        let mut policies_file = NamedTempFile::new().unwrap();
+        policies_file.write_all(policies_yaml.as_bytes()).unwrap();
+        let policies_path = policies_file.into_temp_path();
+        let policies_flag = format!("--policies={}", policies_path.to_str().unwrap());
+        let mut verification_file = NamedTempFile::new().unwrap();
+        verification_file
+            .write_all(verification_yaml.as_bytes())
+            .unwrap();
+        let verification_path = verification_file.into_temp_path();
+        let verification_flag = format!(
+            "--verification-path={}",
+            verification_path.to_str().unwrap()
+        );
+
+        let mut args = vec!["policy-server", &policies_flag, &verification_flag];
+        args.extend(flags);
+        let matches = cli::build_cli().try_get_matches_from(args).unwrap();
+        let config = Config::from_args(&matches);
+        assert_eq!(
+            config
+                .ok()
+                .map(|config| config.policy_reverification_interval),
+            expected
+        );
+    }
+
+    #[test]
+    fn decision_journal_flags() {
+        let policies_yaml = r#"
+---
+example:
+  module: file:///tmp/namespace-validate-policy.wasm
+  settings: {}
+"#;
+        let mut temp_file = NamedTempFile::new().unwrap();
+        temp_file.write_all(policies_yaml.as_bytes()).unwrap();
+        let file_path = temp_file.into_temp_path();
+        let policies_flag = format!("--policies={}", file_path.to_str().unwrap());
+
+        let matches = cli::build_cli()
+            .try_get_matches_from(vec!["policy-server", &policies_flag])
+            .unwrap();
+        let config = Config::from_args(&matches).unwrap();
+        assert!(config.decision_journal.is_none());
+
+        let matches = cli::build_cli()
+            .try_get_matches_from(vec![
+                "policy-server",
+                &policies_flag,
+                "--decision-journal-dir=/var/lib/kubewarden/journal",
+                "--decision-journal-segment-size=1024",
+                "--decision-journal-max-segments=10",
+                "--decision-journal-fail-closed",
+            ])
+            .unwrap();
+        let config = Config::from_args(&matches).unwrap();
+        assert_eq!(
+            config.decision_journal,
+            Some(JournalConfig {
+                dir: PathBuf::from("/var/lib/kubewarden/journal"),
+                segment_size: 1024,
+                max_segments: Some(10),
+                fail_closed: true,
+            })
+        );
+    }
+
+    #[rstest]
+    #[case::disabled(&[], None)]
+    #[case::file(
+        &["--decision-log-file=/var/log/kubewarden/decisions.log", "--decision-log-max-files=3"],
+        Some(DecisionLogConfig {
+            sink: DecisionLogSink::File {
+                path: PathBuf::from("/var/log/kubewarden/decisions.log"),
+                max_size: 104857600,
+                max_files: 3,
+            },
+            queue_size: 10000,
+            batch_size: 100,
+        })
+    )]
+    #[case::http(
+        &["--decision-log-url=https://audit.example.com/decisions", "--decision-log-queue-size=50", "--decision-log-batch-size=10"],
+        Some(DecisionLogConfig {
+            sink: DecisionLogSink::Http {
+                url: "https://audit.example.com/decisions".to_owned(),
+            },
+            queue_size: 50,
+            batch_size: 10,
+        })
+    )]
+    fn decision_log_flags(#[case] flags: &[&str], #[case] expected: Option<DecisionLogConfig>) {
+        let policies_yaml = r#"
+---
+example:
+  module: file:///tmp/namespace-validate-policy.wasm
+  settings: {}
+"#;
+        let mut temp_file = NamedTempFile::new().unwrap();
+        temp_file.write_all(policies_yaml.as_bytes()).unwrap();
+        let file_path = temp_file.into_temp_path();
+        let policies_flag = format!("--policies={}", file_path.to_str().unwrap());
+
+        let mut args = vec!["policy-server", &policies_flag];
+        args.extend_from_slice(flags);
+        let matches = cli::build_cli().try_get_matches_from(args).unwrap();
+        let config = Config::from_args(&matches).unwrap();
+
+        assert_eq!(config.decision_log, expected);
+    }
+
+    #[test]
+    fn decision_log_file_and_url_conflict() {
+        let result = cli::build_cli().try_get_matches_from(vec![
+            "policy-server",
+            "--decision-log-file=/var/log/kubewarden/decisions.log",
+            "--decision-log-url=https://audit.example.com/decisions",
+        ]);
+
+        assert!(result.is_err());
+    }
+
     #[rstest]
     #[case::all_good(
         r#"
@@ -663,4 +2493,30 @@ group_policy:
         let validation_result = validate_policies(&policies);
         assert_eq!(is_valid, validation_result.is_ok());
     }
+
+    #[rstest]
+    #[case::no_timeout(None, false, true)]
+    #[case::timeout(Some(5), true, true)]
+    #[case::timeout_protection_disabled(Some(5), false, false)]
+    #[case::zero_timeout(Some(0), true, false)]
+    fn policy_timeout_validation(
+        #[case] timeout_seconds: Option<u64>,
+        #[case] timeout_protection_enabled: bool,
+        #[case] is_valid: bool,
+    ) {
+        let mut policies_yaml = r#"
+example:
+  module: file:///tmp/namespace-validate-policy.wasm
+  settings: {}
+"#
+        .to_owned();
+        if let Some(timeout_seconds) = timeout_seconds {
+            policies_yaml.push_str(&format!("  timeoutSeconds: {timeout_seconds}\n"));
+        }
+        let policies: HashMap<String, PolicyOrPolicyGroup> =
+            serde_yaml::from_str(&policies_yaml).unwrap();
+
+        let validation_result = validate_policy_timeouts(&policies, timeout_protection_enabled);
+        assert_eq!(is_valid, validation_result.is_ok());
+    }
 }
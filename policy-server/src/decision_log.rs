@@ -0,0 +1,506 @@
+//! Structured log of the admission decisions, meant for compliance audits.
+//!
+//! One JSON record is written for each evaluation, including the ones that failed. The
+//! record is the entry of the decision journal describing the decision, extended with
+//! the user who made the request, the patch and the time spent evaluating it. The
+//! records are written either to a file, rotated once it grows beyond the configured
+//! size, or sent to a remote HTTP endpoint in batches, using the JSON Lines format.
+//!
+//! Unlike the decision journal, the evaluations never wait for the records to be
+//! written: the records are queued and written by a background task. When the sink can't
+//! keep up and the queue is full, the new records are dropped and counted by the
+//! `kubewarden_decision_log_records_dropped_total` metric.
+
+use std::{
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use anyhow::{anyhow, Result};
+use base64::{engine::general_purpose::STANDARD, Engine};
+use k8s_openapi::api::authentication::v1::UserInfo;
+use policy_evaluator::{admission_response::AdmissionResponse, policy_evaluator::ValidateRequest};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use tokio::{
+    fs::{self, File, OpenOptions},
+    io::AsyncWriteExt,
+    sync::mpsc,
+};
+use tracing::{info, warn};
+
+use crate::{
+    journal::JournalEntry,
+    metrics::{self, DroppedDecisionRecords},
+};
+
+/// How long the remote endpoint has to accept a batch of records
+const HTTP_SINK_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Where the records are written
+#[derive(Clone, Debug, PartialEq)]
+pub enum DecisionLogSink {
+    /// A file, rotated once it grows beyond `max_size` bytes. At most `max_files`
+    /// rotated files are kept, next to the active one
+    File {
+        path: PathBuf,
+        max_size: u64,
+        max_files: usize,
+    },
+    /// A remote endpoint, receiving the records with POST requests
+    Http { url: String },
+}
+
+/// Configuration of the decision log
+#[derive(Clone, Debug, PartialEq)]
+pub struct DecisionLogConfig {
+    pub sink: DecisionLogSink,
+    /// Number of records waiting to be written, the new records are dropped
+    /// once it's reached
+    pub queue_size: usize,
+    /// Maximum number of records written at once
+    pub batch_size: usize,
+}
+
+/// An admission decision, as written inside of the decision log
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct DecisionRecord {
+    /// The decision, described like inside of the decision journal
+    #[serde(flatten)]
+    pub entry: JournalEntry,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub user_info: Option<UserInfo>,
+    /// The JSON patch applied to the object, when the request has been mutated
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub patch: Option<Value>,
+    /// Time spent evaluating the request, in milliseconds
+    pub duration_ms: f64,
+}
+
+impl DecisionRecord {
+    /// Build the record of the decision described by `entry`. The response is not
+    /// available when the request could not be evaluated
+    pub(crate) fn new(
+        entry: JournalEntry,
+        validate_request: &ValidateRequest,
+        response: Option<&AdmissionResponse>,
+        duration: Duration,
+    ) -> Self {
+        let user_info = match validate_request {
+            ValidateRequest::AdmissionRequest(adm_req) => Some(adm_req.user_info.clone()),
+            ValidateRequest::Raw(_) | ValidateRequest::CloudEvent(_) => None,
+        };
+
+        DecisionRecord {
+            entry,
+            user_info,
+            patch: response
+                .and_then(|response| response.patch.as_deref())
+                .map(decode_patch),
+            duration_ms: duration.as_secs_f64() * 1000.0,
+        }
+    }
+}
+
+/// The patches are base64 encoded inside of the response, they are logged as JSON.
+/// The encoded patch is kept when it cannot be decoded
+fn decode_patch(patch: &str) -> Value {
+    STANDARD
+        .decode(patch)
+        .ok()
+        .and_then(|decoded| serde_json::from_slice(&decoded).ok())
+        .unwrap_or_else(|| Value::String(patch.to_owned()))
+}
+
+/// The queue of the records to be written to the decision log
+#[derive(Clone)]
+pub struct DecisionLog {
+    tx: mpsc::Sender<DecisionRecord>,
+}
+
+impl DecisionLog {
+    /// Open the sink and start the background task writing the records to it.
+    /// Must be called from within a tokio runtime
+    pub async fn start(config: DecisionLogConfig) -> Result<Self> {
+        let sink = match &config.sink {
+            DecisionLogSink::File {
+                path,
+                max_size,
+                max_files,
+            } => Sink::File(FileSink::open(path, *max_size, *max_files).await?),
+            DecisionLogSink::Http { url } => Sink::Http(HttpSink::new(url)?),
+        };
+        info!(sink = ?config.sink, "decision log started");
+
+        let (tx, rx) = mpsc::channel(config.queue_size.max(1));
+        tokio::spawn(write_records(rx, sink, config.batch_size.max(1)));
+
+        Ok(DecisionLog { tx })
+    }
+
+    /// Queue the record, the record is dropped when the queue is full
+    pub(crate) fn record(&self, record: DecisionRecord) {
+        let reason = match self.tx.try_send(record) {
+            Ok(()) => return,
+            Err(mpsc::error::TrySendError::Full(_)) => "queue-full",
+            Err(mpsc::error::TrySendError::Closed(_)) => "closed",
+        };
+        metrics::add_dropped_decision_records(
+            1,
+            &DroppedDecisionRecords {
+                reason: reason.to_owned(),
+            },
+        );
+    }
+}
+
+/// Write the queued records to the sink, in batches, until all the senders are gone
+async fn write_records(mut rx: mpsc::Receiver<DecisionRecord>, mut sink: Sink, batch_size: usize) {
+    let mut batch = Vec::with_capacity(batch_size);
+    while rx.recv_many(&mut batch, batch_size).await > 0 {
+        if let Err(error) = sink.write(&batch).await {
+            warn!(%error, records = batch.len(), "cannot write the decision log records, dropping them");
+            metrics::add_dropped_decision_records(
+                batch.len() as u64,
+                &DroppedDecisionRecords {
+                    reason: "sink-error".to_owned(),
+                },
+            );
+        }
+        batch.clear();
+    }
+}
+
+/// Encode the records using the JSON Lines format
+fn encode_records(records: &[DecisionRecord]) -> Result<Vec<u8>> {
+    let mut encoded = Vec::new();
+    for record in records {
+        serde_json::to_writer(&mut encoded, record)?;
+        encoded.push(b'\n');
+    }
+    Ok(encoded)
+}
+
+enum Sink {
+    File(FileSink),
+    Http(HttpSink),
+}
+
+impl Sink {
+    async fn write(&mut self, records: &[DecisionRecord]) -> Result<()> {
+        let encoded = encode_records(records)?;
+        match self {
+            Sink::File(sink) => sink.write(&encoded).await,
+            Sink::Http(sink) => sink.write(encoded).await,
+        }
+    }
+}
+
+struct FileSink {
+    path: PathBuf,
+    max_size: u64,
+    max_files: usize,
+    file: File,
+    size: u64,
+}
+
+impl FileSink {
+    async fn open(path: &Path, max_size: u64, max_files: usize) -> Result<Self> {
+        let file = open_log_file(path).await?;
+        let size = file.metadata().await?.len();
+
+        Ok(FileSink {
+            path: path.to_owned(),
+            max_size,
+            max_files,
+            file,
+            size,
+        })
+    }
+
+    async fn write(&mut self, encoded: &[u8]) -> Result<()> {
+        if self.size > 0 && self.size + encoded.len() as u64 > self.max_size {
+            self.rotate().await?;
+        }
+
+        self.file.write_all(encoded).await?;
+        self.file.flush().await?;
+        self.size += encoded.len() as u64;
+
+        Ok(())
+    }
+
+    /// Rename the active file to `<path>.1`, shifting the already rotated files, and
+    /// start a new one. The files beyond `max_files` are removed
+    async fn rotate(&mut self) -> Result<()> {
+        if self.max_files == 0 {
+            fs::remove_file(&self.path).await?;
+        } else {
+            let _ = fs::remove_file(rotated_path(&self.path, self.max_files)).await;
+            for index in (1..self.max_files).rev() {
+                let from = rotated_path(&self.path, index);
+                if fs::try_exists(&from).await? {
+                    fs::rename(&from, rotated_path(&self.path, index + 1)).await?;
+                }
+            }
+            fs::rename(&self.path, rotated_path(&self.path, 1)).await?;
+        }
+
+        self.file = open_log_file(&self.path).await?;
+        self.size = 0;
+
+        Ok(())
+    }
+}
+
+async fn open_log_file(path: &Path) -> Result<File> {
+    OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .await
+        .map_err(|e| anyhow!("cannot open decision log {}: {e}", path.display()))
+}
+
+fn rotated_path(path: &Path, index: usize) -> PathBuf {
+    let mut rotated = path.as_os_str().to_owned();
+    rotated.push(format!(".{index}"));
+    PathBuf::from(rotated)
+}
+
+struct HttpSink {
+    url: String,
+    client: reqwest::Client,
+}
+
+impl HttpSink {
+    fn new(url: &str) -> Result<Self> {
+        let client = reqwest::Client::builder()
+            .timeout(HTTP_SINK_TIMEOUT)
+            .build()
+            .map_err(|e| anyhow!("cannot create HTTP client for the decision log: {e}"))?;
+        Ok(Self {
+            url: url.to_owned(),
+            client,
+        })
+    }
+
+    async fn write(&self, encoded: Vec<u8>) -> Result<()> {
+        self.client
+            .post(&self.url)
+            .header(reqwest::header::CONTENT_TYPE, "application/x-ndjson")
+            .body(encoded)
+            .send()
+            .await?
+            .error_for_status()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use policy_evaluator::{
+        admission_request::AdmissionRequest, admission_response_handler::errors::EvaluationError,
+    };
+    use serde_json::json;
+
+    fn record(policy_id: &str) -> DecisionRecord {
+        DecisionRecord {
+            entry: JournalEntry {
+                timestamp: 1000,
+                policy_id: policy_id.to_owned(),
+                policy_stable_id: Some(format!("{policy_id}@3c1f8e2a9b0d")),
+                request_uid: "705ab4f5-6393-11e8-b7cc-42010a800002".to_owned(),
+                origin: "validate".to_owned(),
+                operation: Some("CREATE".to_owned()),
+                kind: Some("Pod".to_owned()),
+                namespace: Some("default".to_owned()),
+                name: Some("nginx".to_owned()),
+                allowed: true,
+                mutated: false,
+                message: None,
+                code: None,
+                error: None,
+            },
+            user_info: None,
+            patch: None,
+            duration_ms: 1.5,
+        }
+    }
+
+    fn read_records(path: &Path) -> Vec<DecisionRecord> {
+        std::fs::read_to_string(path)
+            .unwrap()
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn record_of_a_mutated_admission_request() {
+        let adm_req: AdmissionRequest = serde_json::from_value(json!({
+            "uid": "705ab4f5-6393-11e8-b7cc-42010a800002",
+            "kind": {"group": "", "version": "v1", "kind": "Pod"},
+            "resource": {"group": "", "version": "v1", "resource": "pods"},
+            "operation": "CREATE",
+            "namespace": "default",
+            "name": "nginx",
+            "userInfo": {"username": "alice", "groups": ["developers"]},
+        }))
+        .unwrap();
+        let patch = json!([{"op": "add", "path": "/metadata/labels/team", "value": "a"}]);
+        let response = AdmissionResponse {
+            uid: adm_req.uid.clone(),
+            allowed: true,
+            patch: Some(STANDARD.encode(patch.to_string())),
+            ..Default::default()
+        };
+
+        let validate_request = ValidateRequest::AdmissionRequest(Box::new(adm_req));
+        let record = DecisionRecord::new(
+            JournalEntry::new(
+                "add-team-label",
+                Some("add-team-label@3c1f8e2a9b0d"),
+                "validate",
+                &validate_request,
+                &response,
+            ),
+            &validate_request,
+            Some(&response),
+            Duration::from_micros(2500),
+        );
+
+        assert_eq!(record.entry.namespace.as_deref(), Some("default"));
+        assert!(record.entry.mutated);
+        assert_eq!(
+            record
+                .user_info
+                .as_ref()
+                .and_then(|user_info| user_info.username.as_deref()),
+            Some("alice")
+        );
+        assert_eq!(record.patch, Some(patch));
+        assert_eq!(record.duration_ms, 2.5);
+
+        let encoded = serde_json::to_value(&record).unwrap();
+        assert_eq!(encoded["policyId"], json!("add-team-label"));
+        assert_eq!(
+            encoded["policyStableId"],
+            json!("add-team-label@3c1f8e2a9b0d")
+        );
+        assert_eq!(encoded["userInfo"]["username"], json!("alice"));
+        assert_eq!(
+            serde_json::from_value::<DecisionRecord>(encoded).unwrap(),
+            record
+        );
+    }
+
+    #[test]
+    fn record_of_a_request_that_could_not_be_evaluated() {
+        let validate_request = ValidateRequest::Raw(json!({"uid": "raw-request"}));
+        let error = EvaluationError::PolicyNotFound("missing-policy".to_owned());
+        let record = DecisionRecord::new(
+            JournalEntry::from_error(
+                "missing-policy",
+                None,
+                "validate",
+                &validate_request,
+                &error,
+            ),
+            &validate_request,
+            None,
+            Duration::from_millis(1),
+        );
+
+        assert_eq!(record.entry.error, Some(error.to_string()));
+        assert!(!record.entry.allowed);
+        assert_eq!(record.user_info, None);
+        assert_eq!(record.patch, None);
+
+        let encoded = serde_json::to_value(&record).unwrap();
+        assert_eq!(encoded["error"], json!(error.to_string()));
+        assert!(encoded.get("policyStableId").is_none());
+    }
+
+    #[test]
+    fn invalid_patch_is_kept_encoded() {
+        assert_eq!(decode_patch("not base64!"), json!("not base64!"));
+    }
+
+    #[tokio::test]
+    async fn file_sink_rotation() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("decisions.log");
+        let line_size = encode_records(&[record("policy-0")]).unwrap().len() as u64;
+        // two records fit inside of each file
+        let mut sink = FileSink::open(&path, line_size * 2, 2).await.unwrap();
+
+        for i in 0..7 {
+            let encoded = encode_records(&[record(&format!("policy-{i}"))]).unwrap();
+            sink.write(&encoded).await.unwrap();
+        }
+
+        let policy_ids = |path: &Path| -> Vec<String> {
+            read_records(path)
+                .into_iter()
+                .map(|record| record.entry.policy_id)
+                .collect()
+        };
+        assert_eq!(policy_ids(&path), vec!["policy-6"]);
+        assert_eq!(
+            policy_ids(&rotated_path(&path, 1)),
+            vec!["policy-4", "policy-5"]
+        );
+        assert_eq!(
+            policy_ids(&rotated_path(&path, 2)),
+            vec!["policy-2", "policy-3"]
+        );
+        assert!(!rotated_path(&path, 3).exists());
+    }
+
+    #[tokio::test]
+    async fn records_written_in_background() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("decisions.log");
+        let decision_log = DecisionLog::start(DecisionLogConfig {
+            sink: DecisionLogSink::File {
+                path: path.clone(),
+                max_size: 1024 * 1024,
+                max_files: 1,
+            },
+            queue_size: 10,
+            batch_size: 10,
+        })
+        .await
+        .unwrap();
+
+        decision_log.record(record("policy-1"));
+        decision_log.record(record("policy-2"));
+        drop(decision_log);
+
+        // the background task terminates once all the records have been written
+        let mut records = Vec::new();
+        for _ in 0..50 {
+            records = read_records(&path);
+            if records.len() == 2 {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+        assert_eq!(records, vec![record("policy-1"), record("policy-2")]);
+    }
+
+    #[tokio::test]
+    async fn records_dropped_when_queue_is_full() {
+        let (tx, mut rx) = mpsc::channel(1);
+        let decision_log = DecisionLog { tx };
+
+        decision_log.record(record("policy-1"));
+        decision_log.record(record("policy-2"));
+
+        assert_eq!(rx.recv().await, Some(record("policy-1")));
+        assert!(rx.try_recv().is_err());
+    }
+}
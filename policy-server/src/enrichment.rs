@@ -0,0 +1,307 @@
+//! Enrichment of the admission requests before they are evaluated by the policies.
+//!
+//! The enrichers inject additional context into the request, like the cost center
+//! of a namespace kept inside of an internal system. The data is exposed to the
+//! policies under the `enrichment` field of the admission request.
+//!
+//! Embedders of Policy Server can provide their own enrichers by implementing the
+//! [`RequestEnricher`] trait, an enricher calling an external HTTP endpoint is
+//! provided out of the box.
+
+use std::{fmt, sync::Arc, time::Duration};
+
+use anyhow::{anyhow, Result};
+use futures::future::BoxFuture;
+use policy_evaluator::{
+    admission_request::AdmissionRequest, admission_response_handler::failure_policy::FailurePolicy,
+};
+use serde_json::{Map, Value};
+use tracing::warn;
+
+/// The data injected into the request by the enrichers
+pub type Enrichment = Map<String, Value>;
+
+/// A step run before the evaluation of an admission request, which provides
+/// additional context to the policies
+pub trait RequestEnricher: Send + Sync {
+    /// Name of the enricher, used when reporting errors
+    fn name(&self) -> &str;
+
+    /// Provide the data to be injected into the request. The keys returned by
+    /// an enricher overwrite the ones returned by the enrichers run before it.
+    fn enrich<'a>(&'a self, request: &'a AdmissionRequest) -> BoxFuture<'a, Result<Enrichment>>;
+}
+
+/// Enricher that sends the admission request to an external HTTP endpoint, using
+/// a POST request. The endpoint must reply with a JSON object.
+pub struct HttpRequestEnricher {
+    url: String,
+    client: reqwest::Client,
+}
+
+impl HttpRequestEnricher {
+    pub fn new(url: &str) -> Result<Self> {
+        let client = reqwest::Client::builder()
+            .build()
+            .map_err(|e| anyhow!("cannot create HTTP client for request enrichment: {e}"))?;
+        Ok(Self {
+            url: url.to_owned(),
+            client,
+        })
+    }
+}
+
+impl RequestEnricher for HttpRequestEnricher {
+    fn name(&self) -> &str {
+        &self.url
+    }
+
+    fn enrich<'a>(&'a self, request: &'a AdmissionRequest) -> BoxFuture<'a, Result<Enrichment>> {
+        Box::pin(async move {
+            let response = self
+                .client
+                .post(&self.url)
+                .json(request)
+                .send()
+                .await?
+                .error_for_status()?;
+            let enrichment: Enrichment = response
+                .json()
+                .await
+                .map_err(|e| anyhow!("the endpoint did not reply with a JSON object: {e}"))?;
+            Ok(enrichment)
+        })
+    }
+}
+
+/// The enrichers to be run and how they are run
+#[derive(Clone)]
+pub struct EnrichmentConfig {
+    /// The enrichers, run in this order
+    pub enrichers: Vec<Arc<dyn RequestEnricher>>,
+    /// Maximum time an enricher can take
+    pub timeout: Duration,
+    /// What happens to the request when an enricher fails or times out
+    pub failure_policy: FailurePolicy,
+}
+
+impl Default for EnrichmentConfig {
+    fn default() -> Self {
+        Self {
+            enrichers: Vec::new(),
+            timeout: Duration::from_millis(1000),
+            failure_policy: FailurePolicy::Fail,
+        }
+    }
+}
+
+impl fmt::Debug for EnrichmentConfig {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("EnrichmentConfig")
+            .field(
+                "enrichers",
+                &self
+                    .enrichers
+                    .iter()
+                    .map(|enricher| enricher.name())
+                    .collect::<Vec<_>>(),
+            )
+            .field("timeout", &self.timeout)
+            .field("failure_policy", &self.failure_policy)
+            .finish()
+    }
+}
+
+impl EnrichmentConfig {
+    /// Run the enrichers against the request, and set the data they provided into it.
+    ///
+    /// With the `Fail` failure policy, an error is returned as soon as one of the
+    /// enrichers fails. With the `Ignore` failure policy the error is logged and
+    /// the data of the failing enricher is left out.
+    pub(crate) async fn enrich(&self, request: &mut AdmissionRequest) -> Result<()> {
+        if self.enrichers.is_empty() {
+            return Ok(());
+        }
+
+        let mut enrichment = Enrichment::new();
+        for enricher in &self.enrichers {
+            let result = match tokio::time::timeout(self.timeout, enricher.enrich(request)).await {
+                Ok(result) => result,
+                Err(_) => Err(anyhow!("timed out after {:?}", self.timeout)),
+            };
+
+            match result {
+                Ok(data) => enrichment.extend(data),
+                Err(error) if self.failure_policy == FailurePolicy::Ignore => {
+                    warn!(
+                        enricher = enricher.name(),
+                        %error,
+                        "request enrichment failed, ignoring it because of the failure policy"
+                    );
+                }
+                Err(error) => {
+                    return Err(anyhow!(
+                        "request enrichment by {} failed: {}",
+                        enricher.name(),
+                        error
+                    ));
+                }
+            }
+        }
+
+        request.enrichment = Some(enrichment);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use policy_evaluator::{
+        admission_request::AdmissionRequestBuilder, policy_metadata::Operation,
+    };
+    use rstest::*;
+    use serde_json::json;
+
+    struct StaticEnricher(&'static str, Value);
+
+    impl RequestEnricher for StaticEnricher {
+        fn name(&self) -> &str {
+            self.0
+        }
+
+        fn enrich<'a>(
+            &'a self,
+            _request: &'a AdmissionRequest,
+        ) -> BoxFuture<'a, Result<Enrichment>> {
+            Box::pin(async move { Ok(self.1.as_object().unwrap().clone()) })
+        }
+    }
+
+    struct FailingEnricher;
+
+    impl RequestEnricher for FailingEnricher {
+        fn name(&self) -> &str {
+            "failing"
+        }
+
+        fn enrich<'a>(
+            &'a self,
+            _request: &'a AdmissionRequest,
+        ) -> BoxFuture<'a, Result<Enrichment>> {
+            Box::pin(async move { Err(anyhow!("boom")) })
+        }
+    }
+
+    struct SlowEnricher;
+
+    impl RequestEnricher for SlowEnricher {
+        fn name(&self) -> &str {
+            "slow"
+        }
+
+        fn enrich<'a>(
+            &'a self,
+            _request: &'a AdmissionRequest,
+        ) -> BoxFuture<'a, Result<Enrichment>> {
+            Box::pin(async move {
+                tokio::time::sleep(Duration::from_secs(5)).await;
+                Ok(Enrichment::new())
+            })
+        }
+    }
+
+    fn admission_request() -> AdmissionRequest {
+        AdmissionRequestBuilder::from_json(
+            Operation::Create,
+            json!({
+                "apiVersion": "v1",
+                "kind": "Pod",
+                "metadata": { "name": "nginx", "namespace": "default" },
+            }),
+        )
+        .and_then(AdmissionRequestBuilder::build)
+        .expect("cannot build AdmissionRequest")
+    }
+
+    fn config(
+        enrichers: Vec<Arc<dyn RequestEnricher>>,
+        failure_policy: FailurePolicy,
+    ) -> EnrichmentConfig {
+        EnrichmentConfig {
+            enrichers,
+            timeout: Duration::from_millis(100),
+            failure_policy,
+        }
+    }
+
+    #[tokio::test]
+    async fn no_enrichers() {
+        let mut request = admission_request();
+        config(vec![], FailurePolicy::Fail)
+            .enrich(&mut request)
+            .await
+            .unwrap();
+        assert!(request.enrichment.is_none());
+    }
+
+    #[tokio::test]
+    async fn enrichers_are_merged_in_order() {
+        let mut request = admission_request();
+        config(
+            vec![
+                Arc::new(StaticEnricher(
+                    "first",
+                    json!({"costCenter": "1234", "team": "a"}),
+                )),
+                Arc::new(StaticEnricher("second", json!({"team": "b"}))),
+            ],
+            FailurePolicy::Fail,
+        )
+        .enrich(&mut request)
+        .await
+        .unwrap();
+
+        assert_eq!(
+            json!({"costCenter": "1234", "team": "b"}),
+            Value::Object(request.enrichment.unwrap())
+        );
+    }
+
+    #[rstest]
+    #[case::failure(Arc::new(FailingEnricher))]
+    #[case::timeout(Arc::new(SlowEnricher))]
+    #[tokio::test]
+    async fn failure_policy(#[case] broken_enricher: Arc<dyn RequestEnricher>) {
+        let enrichers: Vec<Arc<dyn RequestEnricher>> = vec![
+            Arc::new(StaticEnricher("static", json!({"costCenter": "1234"}))),
+            broken_enricher,
+        ];
+
+        let mut request = admission_request();
+        let result = config(enrichers.clone(), FailurePolicy::Fail)
+            .enrich(&mut request)
+            .await;
+        assert!(result.is_err());
+        assert!(request.enrichment.is_none());
+
+        let mut request = admission_request();
+        config(enrichers, FailurePolicy::Ignore)
+            .enrich(&mut request)
+            .await
+            .unwrap();
+        assert_eq!(
+            json!({"costCenter": "1234"}),
+            Value::Object(request.enrichment.unwrap())
+        );
+    }
+
+    #[test]
+    fn enrichment_is_not_read_from_the_incoming_request() {
+        let mut request = serde_json::to_value(admission_request()).unwrap();
+        request["enrichment"] = json!({"costCenter": "forged"});
+
+        let request: AdmissionRequest = serde_json::from_value(request).unwrap();
+        assert!(request.enrichment.is_none());
+    }
+}
@@ -1,9 +1,17 @@
+mod epoch_ticker;
 mod evaluation_environment;
+mod match_conditions;
+pub(crate) mod module_cache;
 mod policy_evaluation_settings;
 pub(crate) mod precompiled_policy;
+mod secret_redaction;
 
 // This is required to mock the `EvaluationEnvironment` inside of our tests
 #[mockall_double::double]
 pub(crate) use evaluation_environment::EvaluationEnvironment;
 
-pub(crate) use evaluation_environment::EvaluationEnvironmentBuilder;
+pub(crate) use epoch_ticker::{EpochDeadlines, EpochTicker};
+pub(crate) use evaluation_environment::{
+    EvaluationEnvironmentBuilder, PolicyCatalogEntry, PolicyState,
+};
+pub(crate) use match_conditions::MatchConditions;
@@ -0,0 +1,108 @@
+use std::{
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
+use policy_evaluator::wasmtime;
+use tokio::time;
+
+/// The number of epoch ticks the policies can consume before being interrupted
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) struct EpochDeadlines {
+    /// Deadline of the evaluation of a request
+    pub(crate) evaluation: u64,
+    /// Deadline of the validation of the policy settings
+    pub(crate) settings_validation: u64,
+}
+
+/// Increments the epoch of the `wasmtime::Engine` at a fixed interval, which is what
+/// drives the interruption of the policies exceeding their deadline.
+///
+/// The ticks are counted, allowing to measure how many of them have been consumed
+/// by a guest.
+#[derive(Clone, Debug)]
+pub(crate) struct EpochTicker {
+    tick_interval: Duration,
+    ticks: Arc<AtomicU64>,
+}
+
+impl EpochTicker {
+    pub(crate) fn new(tick_interval: Duration) -> Self {
+        EpochTicker {
+            tick_interval,
+            ticks: Arc::new(AtomicU64::new(0)),
+        }
+    }
+
+    /// Start ticking the epoch of the given engine. Must be invoked from within a
+    /// tokio runtime
+    pub(crate) fn start(&self, engine: wasmtime::Engine) {
+        let tick_interval = self.tick_interval;
+        let ticks = self.ticks.clone();
+        tokio::spawn(async move {
+            let mut interval = time::interval(tick_interval);
+            loop {
+                interval.tick().await;
+                engine.increment_epoch();
+                ticks.fetch_add(1, Ordering::Relaxed);
+            }
+        });
+    }
+
+    /// The number of ticks granted to a guest that can run for the given amount
+    /// of time. The guest is always granted at least one tick
+    pub(crate) fn deadline(&self, limit: Duration) -> u64 {
+        let ticks = limit
+            .as_nanos()
+            .div_ceil(self.tick_interval.as_nanos().max(1));
+        u64::try_from(ticks).unwrap_or(u64::MAX).max(1)
+    }
+
+    /// Invoke `f`, returning its result together with the number of ticks that
+    /// happened meanwhile
+    pub(crate) fn measure<T>(&self, f: impl FnOnce() -> T) -> (T, u64) {
+        let start = self.ticks.load(Ordering::Relaxed);
+        let result = f();
+        let consumed = self.ticks.load(Ordering::Relaxed) - start;
+
+        (result, consumed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rstest::rstest;
+
+    #[rstest]
+    #[case::seconds(Duration::from_secs(1), Duration::from_secs(2), 2)]
+    #[case::milliseconds(Duration::from_millis(10), Duration::from_secs(2), 200)]
+    #[case::rounded_up(Duration::from_millis(300), Duration::from_secs(1), 4)]
+    #[case::at_least_one_tick(Duration::from_secs(5), Duration::from_millis(1), 1)]
+    fn deadline(
+        #[case] tick_interval: Duration,
+        #[case] limit: Duration,
+        #[case] expected_ticks: u64,
+    ) {
+        let ticker = EpochTicker::new(tick_interval);
+        assert_eq!(ticker.deadline(limit), expected_ticks);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn measure_consumed_ticks() {
+        let mut wasmtime_config = wasmtime::Config::new();
+        wasmtime_config.epoch_interruption(true);
+        let engine = wasmtime::Engine::new(&wasmtime_config).unwrap();
+
+        let ticker = EpochTicker::new(Duration::from_millis(5));
+        let (_, idle_ticks) = ticker.measure(|| ());
+        assert_eq!(idle_ticks, 0);
+
+        ticker.start(engine);
+        let (_, consumed) = ticker.measure(|| std::thread::sleep(Duration::from_millis(50)));
+        assert!(consumed > 0);
+    }
+}
@@ -1,34 +1,53 @@
 use std::{
-    collections::{BTreeSet, HashMap, HashSet},
-    fmt,
-    sync::Arc,
+    collections::{BTreeMap, BTreeSet, HashMap, HashSet},
+    fmt, fs,
+    path::{Path, PathBuf},
+    sync::{Arc, OnceLock, RwLock},
+    time::{Duration, Instant},
 };
 
 use policy_evaluator::{
     admission_response::AdmissionResponse,
     admission_response_handler::{
         errors::{EvaluationError, Result},
+        failure_policy::FailurePolicy,
         policy_id::PolicyID,
         policy_mode::PolicyMode,
     },
+    burrego::HttpPolicy,
     callback_requests::CallbackRequest,
-    evaluation_context::EvaluationContext,
+    capability_versions::{unsupported_capability_versions, UnsupportedCapabilityVersion},
+    evaluation_cache::{self, EvaluationCache, EvaluationCacheConfig, EvaluationCacheKey},
+    evaluation_context::{EvaluationContext, KubernetesServiceAccount},
     kubewarden_policy_sdk::settings::SettingsValidationResponse,
-    policy_evaluator::{PolicyEvaluator, PolicyEvaluatorPre, PolicyExecutionMode, ValidateRequest},
+    policy_evaluator::{
+        PolicyEvaluator, PolicyEvaluatorPool, PolicyEvaluatorPre, PolicyExecutionMode,
+        PolicySettings, ValidateRequest,
+    },
     policy_evaluator_builder::PolicyEvaluatorBuilder,
+    policy_fetcher::rego_library::RegoLibraryDocument,
     policy_group_evaluator::{evaluator::PolicyGroupEvaluator, PolicyGroupMemberSettings},
     policy_metadata::ContextAwareResource,
+    request_envelope::{ClusterInfo, PolicyGroupInfo, RequestContext},
     wasmtime,
 };
+use serde::Serialize;
+use sha2::{Digest, Sha256};
 use tokio::sync::mpsc;
-use tracing::debug;
+use tracing::{debug, info, warn};
 
 use crate::{
-    config::{PolicyOrPolicyGroup, PolicyOrPolicyGroupSettings},
+    config::{HttpSend, PolicyOrPolicyGroup, PolicyOrPolicyGroupSettings},
     evaluation::{
-        policy_evaluation_settings::PolicyEvaluationSettings,
+        epoch_ticker::{EpochDeadlines, EpochTicker},
+        match_conditions::MatchConditions,
+        module_cache::ModuleCache,
+        policy_evaluation_settings::{merge_namespace_settings, PolicyEvaluationSettings},
         precompiled_policy::{PrecompiledPolicies, PrecompiledPolicy},
+        secret_redaction::SecretRedaction,
     },
+    metrics,
+    policy_downloader::FetchedRegoLibraries,
 };
 
 #[cfg(test)]
@@ -69,6 +88,129 @@ impl fmt::Display for PolicyGroupMemberEvaluationResult {
 /// The digest of a WebAssembly module
 type ModuleDigest = String;
 
+/// Number of hex characters of the module digest used inside of the stable policy ID
+const STABLE_ID_DIGEST_LENGTH: usize = 12;
+
+/// Build the stable ID of a policy: the name of the policy followed by the short form
+/// of the digest of its Wasm module, e.g. `pod-privileged@3c1f8e2a9b0d`.
+/// Unlike the policy name, this changes whenever the policy is bumped to a different module.
+fn stable_policy_id(policy_id: &PolicyID, digest: &str) -> String {
+    let short_digest = &digest[..digest.len().min(STABLE_ID_DIGEST_LENGTH)];
+    format!("{policy_id}@{short_digest}")
+}
+
+/// Build the key used to deduplicate the `PolicyEvaluatorPre` instances. Policies using the
+/// same Wasm module with a different OPA entrypoint, with different Rego libraries, or with a
+/// different timeout, cannot share the same instance.
+fn policy_evaluator_pre_key(
+    digest: &str,
+    entrypoint: Option<&str>,
+    rego_libraries_digest: Option<&str>,
+    timeout_seconds: Option<u64>,
+    preopened_dirs: &BTreeMap<String, PathBuf>,
+) -> String {
+    let mut key = match entrypoint {
+        Some(entrypoint) => format!("{digest}#{entrypoint}"),
+        None => digest.to_owned(),
+    };
+    if let Some(rego_libraries_digest) = rego_libraries_digest {
+        key.push_str(&format!("+{rego_libraries_digest}"));
+    }
+    if let Some(timeout_seconds) = timeout_seconds {
+        key.push_str(&format!("~{timeout_seconds}s"));
+    }
+    for (guest_path, host_path) in preopened_dirs {
+        key.push_str(&format!("|{guest_path}={}", host_path.display()));
+    }
+    key
+}
+
+/// The Rego libraries linked into the `data` document of a policy
+#[derive(Clone, Debug, Default)]
+struct LinkedRegoLibraries {
+    /// Identifies the libraries, computed over their paths and their references. These
+    /// are pinned by digest, hence this changes whenever the libraries change. `None` when
+    /// the policy doesn't use any library
+    digest: Option<String>,
+    /// The documents of the libraries, keyed by the path where they are linked
+    documents: BTreeMap<String, RegoLibraryDocument>,
+}
+
+/// What caused the compilation of a policy that is loaded lazily
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum CompilationTrigger {
+    /// The policy has been compiled to evaluate a request or to validate its settings
+    OnDemand,
+    /// The policy has been compiled because it's part of the warm-up list
+    WarmUp,
+}
+
+impl fmt::Display for CompilationTrigger {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CompilationTrigger::OnDemand => write!(f, "on-demand"),
+            CompilationTrigger::WarmUp => write!(f, "warm-up"),
+        }
+    }
+}
+
+/// A Wasm module that has been downloaded at bootstrap time, but that is compiled
+/// only when one of the policies using it is needed for the first time.
+struct LazyModule {
+    /// Path to the Wasm module on disk
+    wasm_module_path: PathBuf,
+    /// The OPA entrypoint to be evaluated, the default one is used when not set
+    entrypoint: Option<String>,
+    /// The Rego libraries linked into the `data` document of the policies using the module
+    rego_libraries: BTreeMap<String, RegoLibraryDocument>,
+    /// The evaluation timeout of the policies using the module, the global one is used
+    /// when not set
+    timeout_seconds: Option<u64>,
+    /// The directories of the host preopened inside of the policies using the module
+    preopened_dirs: BTreeMap<String, PathBuf>,
+    /// The outcome of the compilation, set once the module has been compiled.
+    /// The `OnceLock` ensures the module is compiled only once, even when multiple
+    /// requests targeting it are received at the same time.
+    policy_evaluator_pre: OnceLock<std::result::Result<Arc<PolicyEvaluatorPre>, String>>,
+}
+
+/// How ready a policy is to evaluate requests
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub(crate) enum PolicyState {
+    /// The policy is loaded lazily and has not been compiled yet
+    Cold,
+    /// The Wasm module of the policy has been compiled
+    Compiled,
+    /// The module of the policy has been instantiated and the Kubernetes resources it is
+    /// allowed to access are being tracked by synced reflectors. This happens at bootstrap
+    /// time, or with the first evaluation of the lazily loaded policies
+    Warm,
+    /// The policy failed to initialize, it will never be able to evaluate requests
+    Failed,
+    /// The policy is context aware and the Kubernetes resources it is allowed to access
+    /// have not been listed for the first time yet
+    Syncing,
+}
+
+/// An entry of the catalog of the policies loaded by Policy Server
+#[derive(Serialize, Debug, Clone, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct PolicyCatalogEntry {
+    /// The ID of the policy, as defined inside of `policies.yml`
+    pub id: String,
+    /// The stable ID of the policy, not available when the policy failed to initialize
+    pub stable_id: Option<String>,
+    /// The digest of the Wasm module, not available for policy groups
+    pub module_digest: Option<String>,
+    pub policy_group: bool,
+    pub initialization_error: Option<String>,
+    /// The versions of the host capabilities requested by the policy that are not
+    /// supported by Policy Server
+    pub unsupported_capabilities: Vec<UnsupportedCapabilityVersion>,
+    pub state: PolicyState,
+}
+
 /// This structure contains all the policies defined by the user inside of the `policies.yml`.
 /// It also provides helper methods to perform the validation of a request and the validation
 /// of the settings provided by the user.
@@ -94,7 +236,8 @@ pub(crate) struct EvaluationEnvironment {
     always_accept_admission_reviews_on_namespace: Option<String>,
 
     /// A map with the module digest as key, and the associated `PolicyEvaluatorPre`
-    /// as value
+    /// as value. The digest is followed by the OPA entrypoint when the policy selects one,
+    /// see `policy_evaluator_pre_key`
     ///
     /// Note: the `PolicyEvaluatorPre` is wrapped into an `Arc` to allow cheap cloning
     /// when it's being used inside of a `GroupPolicyEvaluator`.
@@ -102,14 +245,68 @@ pub(crate) struct EvaluationEnvironment {
     /// requires `+send` and `+sync`.
     module_digest_to_policy_evaluator_pre: HashMap<ModuleDigest, Arc<PolicyEvaluatorPre>>,
 
+    /// A map with the module digest as key, and the associated `LazyModule` as value.
+    /// This is populated only when lazy policy loading is enabled. The digest of a lazy
+    /// module is computed over the contents of the Wasm file. Like for
+    /// `module_digest_to_policy_evaluator_pre`, the digest is followed by the OPA entrypoint
+    /// when the policy selects one.
+    module_digest_to_lazy_module: HashMap<ModuleDigest, Arc<LazyModule>>,
+
+    /// Map the ID of a lazily loaded policy to the outcome of the validation of its settings.
+    /// The validation is done only once, right after the module has been compiled.
+    lazy_policy_initializations: HashMap<PolicyID, OnceLock<std::result::Result<(), String>>>,
+
+    /// The engine used to compile the lazily loaded policies
+    engine: Option<wasmtime::Engine>,
+
+    /// The cache of the compiled Wasm modules, used by the lazily loaded policies
+    module_cache: Option<Arc<ModuleCache>>,
+
+    /// When set, defines after how many epoch ticks the evaluation of a policy is interrupted
+    epoch_deadlines: Option<EpochDeadlines>,
+
+    /// Used to measure the epoch ticks consumed by the evaluations, set only when
+    /// the timeout protection is enabled
+    epoch_ticker: Option<EpochTicker>,
+
+    /// When set, the memory limit of the OPA and Gatekeeper policies, expressed in bytes
+    rego_policy_memory_limit: Option<u64>,
+
+    /// Whether the waPC policies receive only the fields of the request declared by the
+    /// `requestProjection` of their metadata
+    request_projection: bool,
+
+    /// The cluster served by Policy Server, given to the policies that use the `v2`
+    /// request envelope
+    cluster: Option<ClusterInfo>,
+
     /// A map with the ID of the policy as value, and the list of ContextAwareResource the
     /// policy is allowed to access.
     policy_id_to_ctx_aware_allowed_resources: HashMap<PolicyID, BTreeSet<ContextAwareResource>>,
 
+    /// Map a `policy_id` to the Kubernetes Service Account the policy impersonates. Policies
+    /// using the Service Account of Policy Server are not part of this map.
+    policy_id_to_kubernetes_service_account: HashMap<PolicyID, KubernetesServiceAccount>,
+
+    /// Map a `policy_id` to the requests the policy can make with the `http.send` Rego
+    /// builtin. Policies that cannot use the builtin are not part of this map.
+    policy_id_to_http_policy: HashMap<PolicyID, HttpPolicy>,
+
+    /// The policies allowed to use the key/value store offered by Policy Server.
+    policies_with_key_value_store: HashSet<PolicyID>,
+
     /// Map a `policy_id` to the module's digest.
     /// This allows us to deduplicate the Wasm modules defined by the user.
     policy_id_to_module_digest: HashMap<PolicyID, ModuleDigest>,
 
+    /// Map a `policy_id` to the OPA entrypoint it evaluates. Policies using the default
+    /// entrypoint are not part of this map.
+    policy_id_to_opa_entrypoint: HashMap<PolicyID, String>,
+
+    /// Map a `policy_id` to the digest of the Rego libraries it uses, see
+    /// `LinkedRegoLibraries`. Policies that do not use any library are not part of this map.
+    policy_id_to_rego_libraries_digest: HashMap<PolicyID, String>,
+
     /// Map a `policy_id` to the `PolicyEvaluationSettings` instance. This allows us to obtain
     /// the list of settings to be used when evaluating a given policy.
     policy_id_to_settings: HashMap<PolicyID, PolicyEvaluationSettings>,
@@ -122,6 +319,38 @@ pub(crate) struct EvaluationEnvironment {
     /// A Set containing the IDs of the policy groups.
     policy_groups: HashSet<PolicyID>,
 
+    /// Map a `policy_id` to its stable ID. The stable ID is computed at bootstrap time and
+    /// is used inside of logs, traces and metrics.
+    policy_id_to_stable_id: HashMap<PolicyID, String>,
+
+    /// The IDs of the policies that have been instantiated
+    warm_policies: RwLock<HashSet<PolicyID>>,
+
+    /// The policies that failed their periodic re-verification, with the reason of the
+    /// failure. These policies reject all the requests they receive
+    revoked_policies: RwLock<HashMap<PolicyID, String>>,
+
+    /// How many warm `PolicyEvaluator` instances are kept around for each policy.
+    /// When zero, a new instance is created for each evaluation
+    evaluator_pool_size: usize,
+
+    /// How many evaluations a pooled `PolicyEvaluator` instance performs before being
+    /// replaced by a new one. When zero, the instances are reused forever
+    evaluator_pool_max_uses: usize,
+
+    /// The pools of `PolicyEvaluator` instances, created the first time a policy is
+    /// evaluated. Used only when `evaluator_pool_size` is greater than zero
+    evaluator_pools: RwLock<HashMap<PolicyID, Arc<PolicyEvaluatorPool>>>,
+
+    /// The results of the evaluations, reused for identical requests. Set only when
+    /// the cache is enabled
+    evaluation_cache: Option<EvaluationCache>,
+
+    /// The context aware policies waiting for the initial sync of the Kubernetes resources
+    /// they are allowed to access, together with the resources that have not been synced yet.
+    /// Populated only when the readiness of the policies depends on the Kubernetes sync
+    kubernetes_sync_pending: RwLock<HashMap<PolicyID, BTreeSet<ContextAwareResource>>>,
+
     /// Channel used by the synchronous world (like the `host_callback` waPC function,
     /// but also Burrego for k8s context aware data),
     /// to request the computation of code that can only be run inside of an
@@ -135,8 +364,19 @@ pub(crate) struct EvaluationEnvironmentBuilder<'engine, 'precompiled_policies> {
     precompiled_policies: &'precompiled_policies PrecompiledPolicies,
     callback_handler_tx: mpsc::Sender<CallbackRequest>,
     continue_on_errors: bool,
-    policy_evaluation_limit_seconds: Option<u64>,
+    timeout_protection: Option<(EpochTicker, EpochDeadlines)>,
     always_accept_admission_reviews_on_namespace: Option<String>,
+    lazy_policies: HashMap<String, PathBuf>,
+    rego_libraries: FetchedRegoLibraries,
+    rego_policy_memory_limit: Option<u64>,
+    request_projection: bool,
+    redact_secret_data: bool,
+    cluster_name: Option<String>,
+    evaluator_pool_size: usize,
+    evaluator_pool_max_uses: usize,
+    evaluation_cache: EvaluationCacheConfig,
+    module_cache: Option<Arc<ModuleCache>>,
+    kubernetes_sync_readiness: bool,
 }
 
 impl<'engine, 'precompiled_policies> EvaluationEnvironmentBuilder<'engine, 'precompiled_policies> {
@@ -151,17 +391,30 @@ impl<'engine, 'precompiled_policies> EvaluationEnvironmentBuilder<'engine, 'prec
             precompiled_policies,
             callback_handler_tx,
             continue_on_errors: false,
-            policy_evaluation_limit_seconds: None,
+            timeout_protection: None,
             always_accept_admission_reviews_on_namespace: None,
+            lazy_policies: HashMap::new(),
+            rego_libraries: FetchedRegoLibraries::new(),
+            rego_policy_memory_limit: None,
+            request_projection: true,
+            redact_secret_data: false,
+            cluster_name: None,
+            evaluator_pool_size: 0,
+            evaluator_pool_max_uses: 0,
+            evaluation_cache: EvaluationCacheConfig::default(),
+            module_cache: None,
+            kubernetes_sync_readiness: false,
         }
     }
 
-    /// Enable policy evaluatation timeout feature
-    pub fn with_policy_evaluation_limit_seconds(
+    /// Enable policy evaluatation timeout feature. The `epoch_ticker` must be the one
+    /// driving the epoch of the engine
+    pub fn with_timeout_protection(
         mut self,
-        policy_evaluation_limit_seconds: u64,
+        epoch_ticker: EpochTicker,
+        epoch_deadlines: EpochDeadlines,
     ) -> Self {
-        self.policy_evaluation_limit_seconds = Some(policy_evaluation_limit_seconds);
+        self.timeout_protection = Some((epoch_ticker, epoch_deadlines));
         self
     }
 
@@ -177,6 +430,86 @@ impl<'engine, 'precompiled_policies> EvaluationEnvironmentBuilder<'engine, 'prec
         self
     }
 
+    /// Set the policies that are compiled the first time they are used, instead of
+    /// doing that at bootstrap time.
+    ///
+    /// The key is the URL of the Wasm module, the value is the path where the module has
+    /// been downloaded. These modules must not be part of the precompiled policies.
+    pub fn with_lazy_policies(mut self, lazy_policies: HashMap<String, PathBuf>) -> Self {
+        self.lazy_policies = lazy_policies;
+        self
+    }
+
+    /// Set the Rego libraries referenced by the policies, keyed by their URL
+    pub fn with_rego_libraries(mut self, rego_libraries: FetchedRegoLibraries) -> Self {
+        self.rego_libraries = rego_libraries;
+        self
+    }
+
+    /// Limit the memory of the OPA and Gatekeeper policies, expressed in bytes
+    pub fn with_rego_policy_memory_limit(mut self, limit: Option<u64>) -> Self {
+        self.rego_policy_memory_limit = limit;
+        self
+    }
+
+    /// Honor the request projection declared by the metadata of the waPC policies.
+    /// When disabled, the policies always receive the whole request
+    pub fn with_request_projection(mut self, enabled: bool) -> Self {
+        self.request_projection = enabled;
+        self
+    }
+
+    /// Mask the values of the Secrets given to the policies, unless they opt out of it
+    pub fn with_secret_data_redaction(mut self, enabled: bool) -> Self {
+        self.redact_secret_data = enabled;
+        self
+    }
+
+    /// Set the name of the cluster served by Policy Server. It's part of the context
+    /// given to the policies that use the `v2` request envelope
+    pub fn with_cluster_name(mut self, name: String) -> Self {
+        self.cluster_name = Some(name);
+        self
+    }
+
+    /// Keep up to `size` warm instances of each policy, reusing them across the
+    /// evaluations. When zero, a new instance is created for each evaluation
+    pub fn with_evaluator_pool_size(mut self, size: usize) -> Self {
+        self.evaluator_pool_size = size;
+        self
+    }
+
+    /// Replace the pooled instances once they have performed `max_uses` evaluations,
+    /// so that the memory of the guest doesn't keep the data of the old requests
+    /// around forever. When zero, the instances are reused forever
+    pub fn with_evaluator_pool_max_uses(mut self, max_uses: usize) -> Self {
+        self.evaluator_pool_max_uses = max_uses;
+        self
+    }
+
+    /// Reuse the results of the evaluations for identical requests, see
+    /// `EvaluationCacheConfig`. The results of the context aware policies, and of the
+    /// evaluations that invoked a host capability, are never cached
+    pub fn with_evaluation_cache(mut self, config: EvaluationCacheConfig) -> Self {
+        self.evaluation_cache = config;
+        self
+    }
+
+    /// Compile the lazily loaded policies through the given cache of the compiled
+    /// Wasm modules
+    pub fn with_module_cache(mut self, module_cache: Option<Arc<ModuleCache>>) -> Self {
+        self.module_cache = module_cache;
+        self
+    }
+
+    /// Consider the context aware policies as `Syncing` until the Kubernetes resources
+    /// they are allowed to access have been synced, see
+    /// `EvaluationEnvironment::mark_kubernetes_resource_as_synced`
+    pub fn with_kubernetes_sync_readiness(mut self, enabled: bool) -> Self {
+        self.kubernetes_sync_readiness = enabled;
+        self
+    }
+
     // Because of automock, we have to provide a tailored build method between test and production
     // code
     #[cfg(test)]
@@ -207,6 +540,23 @@ impl<'engine, 'precompiled_policies> EvaluationEnvironmentBuilder<'engine, 'prec
                 .always_accept_admission_reviews_on_namespace
                 .clone(),
             callback_handler_tx: Some(self.callback_handler_tx.clone()),
+            engine: Some(self.engine.clone()),
+            module_cache: self.module_cache.clone(),
+            epoch_deadlines: self
+                .timeout_protection
+                .as_ref()
+                .map(|(_, deadlines)| *deadlines),
+            epoch_ticker: self
+                .timeout_protection
+                .as_ref()
+                .map(|(ticker, _)| ticker.clone()),
+            rego_policy_memory_limit: self.rego_policy_memory_limit,
+            request_projection: self.request_projection,
+            cluster: self.cluster_name.clone().map(|name| ClusterInfo { name }),
+            evaluator_pool_size: self.evaluator_pool_size,
+            evaluator_pool_max_uses: self.evaluator_pool_max_uses,
+            evaluation_cache: (self.evaluation_cache.max_entries > 0)
+                .then(|| EvaluationCache::new(self.evaluation_cache.clone())),
             ..Default::default()
         };
 
@@ -228,33 +578,79 @@ impl<'engine, 'precompiled_policies> EvaluationEnvironmentBuilder<'engine, 'prec
                     continue;
                 }
             };
+            let match_conditions = match MatchConditions::compile(policy.match_conditions()) {
+                Ok(match_conditions) => match_conditions,
+                Err(e) => {
+                    if !self.continue_on_errors {
+                        return Err(EvaluationError::BootstrapFailure(format!(
+                            "cannot compile the matchConditions of the policy: {e}"
+                        )));
+                    }
+                    eval_env
+                        .policy_initialization_errors
+                        .insert(id.to_owned(), e.to_string());
+                    continue;
+                }
+            };
 
             match policy {
                 PolicyOrPolicyGroup::Policy {
                     module: url,
                     policy_mode,
+                    failure_policy,
+                    timeout_seconds,
                     message,
                     allowed_to_mutate,
+                    namespace_settings,
                     context_aware_resources,
+                    service_account,
+                    entrypoint,
+                    rego_libraries,
+                    http_send,
+                    key_value_store,
+                    preopened_dirs,
                     ..
                 } => {
+                    let namespace_settings = match &settings {
+                        PolicyOrPolicyGroupSettings::Policy(base_settings) => {
+                            merge_namespace_settings(base_settings, namespace_settings)
+                        }
+                        PolicyOrPolicyGroupSettings::PolicyGroup { .. } => HashMap::new(),
+                    };
                     let policy_evaluation_settings = PolicyEvaluationSettings {
                         policy_mode: policy_mode.to_owned(),
+                        failure_policy: *failure_policy,
+                        timeout_seconds: *timeout_seconds,
                         allowed_to_mutate: allowed_to_mutate.unwrap_or(false),
                         settings,
+                        namespace_settings,
                         custom_rejection_message: message.clone(),
+                        match_conditions,
+                        secret_redaction: SecretRedaction::resolve(
+                            &id,
+                            self.redact_secret_data,
+                            policy.secret_data(),
+                        ),
+                        preopened_dirs: preopened_dirs.to_owned(),
                     };
 
                     let eval_ctx = EvaluationContext {
                         policy_id: id.to_string(),
+                        policy_stable_id: None,
                         callback_channel: Some(self.callback_handler_tx.clone()),
                         ctx_aware_resources_allow_list: context_aware_resources.to_owned(),
+                        kubernetes_service_account: service_account.to_owned(),
+                        request_context: eval_env.request_context(&id),
+                        http_policy: http_send.as_ref().map(HttpSend::http_policy),
+                        key_value_store: *key_value_store,
                     };
 
                     if let Err(e) = self.bootstrap_policy(
                         &mut eval_env,
                         id.clone(),
                         url,
+                        entrypoint.as_deref(),
+                        rego_libraries,
                         policy_evaluation_settings,
                         eval_ctx,
                     ) {
@@ -269,14 +665,27 @@ impl<'engine, 'precompiled_policies> EvaluationEnvironmentBuilder<'engine, 'prec
                 }
                 PolicyOrPolicyGroup::PolicyGroup {
                     policy_mode,
+                    failure_policy,
+                    timeout_seconds,
                     policies,
                     ..
                 } => {
                     let policy_evaluation_settings = PolicyEvaluationSettings {
                         policy_mode: policy_mode.to_owned(),
+                        failure_policy: *failure_policy,
+                        timeout_seconds: *timeout_seconds,
                         allowed_to_mutate: false, // Group policies are not allowed to mutate
                         custom_rejection_message: None,
                         settings,
+                        namespace_settings: HashMap::new(),
+                        match_conditions,
+                        // the request is redacted before being given to the members
+                        secret_redaction: SecretRedaction::resolve(
+                            &id,
+                            self.redact_secret_data,
+                            policy.secret_data(),
+                        ),
+                        preopened_dirs: BTreeMap::new(),
                     };
                     eval_env.register_policy_group(&id, policy_evaluation_settings);
 
@@ -302,23 +711,39 @@ impl<'engine, 'precompiled_policies> EvaluationEnvironmentBuilder<'engine, 'prec
 
                         let policy_evaluation_settings = PolicyEvaluationSettings {
                             policy_mode: PolicyMode::Protect,
+                            failure_policy: FailurePolicy::Fail,
+                            // the members share the timeout of their group
+                            timeout_seconds: *timeout_seconds,
                             allowed_to_mutate: false,
                             settings,
+                            namespace_settings: HashMap::new(),
                             custom_rejection_message: None,
+                            // the requests are filtered by the conditions of the group
+                            match_conditions: MatchConditions::default(),
+                            // the requests are redacted by the group
+                            secret_redaction: SecretRedaction::Disabled,
+                            preopened_dirs: policy.preopened_dirs.to_owned(),
                         };
 
                         let eval_ctx = EvaluationContext {
                             policy_id: policy_id.to_string(),
+                            policy_stable_id: None,
                             callback_channel: Some(self.callback_handler_tx.clone()),
                             ctx_aware_resources_allow_list: policy
                                 .context_aware_resources
                                 .to_owned(),
+                            kubernetes_service_account: policy.service_account.to_owned(),
+                            request_context: eval_env.request_context(&policy_id),
+                            http_policy: None,
+                            key_value_store: false,
                         };
 
                         if let Err(e) = self.bootstrap_policy(
                             &mut eval_env,
                             policy_id.clone(),
                             &policy.module,
+                            None,
+                            &BTreeMap::new(),
                             policy_evaluation_settings,
                             eval_ctx,
                         ) {
@@ -331,23 +756,54 @@ impl<'engine, 'precompiled_policies> EvaluationEnvironmentBuilder<'engine, 'prec
                             continue;
                         }
                     }
+                    eval_env.register_policy_group_stable_id(&id);
                 }
             }
         }
 
+        if self.kubernetes_sync_readiness {
+            let pending = eval_env
+                .policy_id_to_ctx_aware_allowed_resources
+                .iter()
+                .filter(|(_, resources)| !resources.is_empty())
+                .map(|(policy_id, resources)| (policy_id.to_owned(), resources.to_owned()))
+                .collect();
+            eval_env.kubernetes_sync_pending = RwLock::new(pending);
+        }
+
         Ok(eval_env)
     }
 
     /// Internal method used to bootstrap a policy. The policy is either a single policy or a
     /// children of a policy group.
+    #[allow(clippy::too_many_arguments)]
     fn bootstrap_policy(
         &self,
         eval_env: &mut EvaluationEnvironment,
         id: PolicyID,
         url: &str,
+        entrypoint: Option<&str>,
+        rego_libraries: &BTreeMap<String, String>,
         policy_evaluation_settings: PolicyEvaluationSettings,
         eval_ctx: EvaluationContext,
     ) -> Result<()> {
+        let rego_libraries = self.link_rego_libraries(rego_libraries)?;
+
+        if let Some(wasm_module_path) = self.lazy_policies.get(url) {
+            // The settings, the entrypoint and the libraries are validated once the
+            // policy is compiled
+            return eval_env
+                .register_lazy(
+                    &id,
+                    policy_evaluation_settings,
+                    eval_ctx,
+                    wasm_module_path,
+                    entrypoint,
+                    &rego_libraries,
+                )
+                .map_err(|e| EvaluationError::BootstrapFailure(e.to_string()));
+        }
+
         let precompiled_policy = self
             .precompiled_policies
             .get(url)
@@ -364,12 +820,44 @@ impl<'engine, 'precompiled_policies> EvaluationEnvironmentBuilder<'engine, 'prec
                 policy_evaluation_settings,
                 eval_ctx,
                 precompiled_policy,
-                self.policy_evaluation_limit_seconds,
+                entrypoint,
+                &rego_libraries,
             )
             .map_err(|e| EvaluationError::BootstrapFailure(e.to_string()))?;
 
         eval_env.validate_settings(&id)
     }
+
+    /// Collect the documents of the Rego libraries referenced by a policy. The key of
+    /// `references` is the path where the library is linked, the value is its URL
+    fn link_rego_libraries(
+        &self,
+        references: &BTreeMap<String, String>,
+    ) -> Result<LinkedRegoLibraries> {
+        if references.is_empty() {
+            return Ok(LinkedRegoLibraries::default());
+        }
+
+        let mut documents = BTreeMap::new();
+        let mut hasher = Sha256::new();
+        for (path, url) in references {
+            let document = self
+                .rego_libraries
+                .get(url)
+                .ok_or_else(|| {
+                    EvaluationError::BootstrapFailure(format!("cannot find Rego library {url}"))
+                })?
+                .as_ref()
+                .map_err(|e| EvaluationError::BootstrapFailure(e.to_string()))?;
+            documents.insert(path.to_owned(), document.to_owned());
+            hasher.update(format!("{path}={url}\n"));
+        }
+
+        Ok(LinkedRegoLibraries {
+            digest: Some(format!("{:x}", hasher.finalize())),
+            documents,
+        })
+    }
 }
 
 #[cfg_attr(test, automock)]
@@ -384,59 +872,66 @@ impl EvaluationEnvironment {
         self.always_accept_admission_reviews_on_namespace.as_deref() == Some(namespace)
     }
 
-    /// Register a new policy. It takes care of creating a new `PolicyEvaluator` (when needed).
-    /// This is used to register both individual policies and the ones that are part of a group
-    /// policy.
-    ///
-    /// Params:
-    /// - `engine`: the `wasmtime::Engine` to be used when creating the `PolicyEvaluator`
-    /// - `policy_id`: the unique identifier of the policy
-    /// - `policy_evaluation_settings`: the settings associated with the policy
-    /// - `precompiled_policy`: the `PrecompiledPolicy` associated with the Wasm module referenced by the policy
-    /// - `callback_handler_tx`: the transmission end of a channel that connects the worker with the asynchronous world
-    /// - `policy_evaluation_limit_seconds`: when set, defines after how many seconds the policy evaluation is interrupted
-    fn register(
-        &mut self,
-        engine: &wasmtime::Engine,
-        policy_id: &PolicyID,
-        policy_evaluation_settings: PolicyEvaluationSettings,
-        eval_ctx: EvaluationContext,
-        precompiled_policy: &PrecompiledPolicy,
-        policy_evaluation_limit_seconds: Option<u64>,
-    ) -> Result<()> {
-        let module_digest = &precompiled_policy.digest;
-
-        if !self
-            .module_digest_to_policy_evaluator_pre
-            .contains_key(module_digest)
-        {
-            debug!(?policy_id, "create wasmtime::Module");
-            let module = create_wasmtime_module(policy_id, engine, precompiled_policy)?;
-            debug!(?policy_id, "create PolicyEvaluatorPre");
-            let pol_eval_pre = create_policy_evaluator_pre(
-                engine,
-                &module,
-                precompiled_policy.execution_mode,
-                policy_evaluation_limit_seconds,
-            )?;
-
-            self.module_digest_to_policy_evaluator_pre
-                .insert(module_digest.to_owned(), Arc::new(pol_eval_pre));
+    /// Compile the given lazily loaded policy and validate its settings, without waiting for
+    /// the first request targeting it. Policies compiled at bootstrap time are left untouched.
+    pub(crate) fn warm_up(&self, policy_id: &PolicyID) -> Result<()> {
+        if !self.policy_id_to_settings.contains_key(policy_id) {
+            return Err(EvaluationError::PolicyNotFound(policy_id.to_string()));
         }
-        self.policy_id_to_module_digest
-            .insert(policy_id.to_owned(), module_digest.to_owned());
+        self.initialize_lazy_policy(policy_id, CompilationTrigger::WarmUp)
+    }
 
-        self.policy_id_to_settings
-            .insert(policy_id.to_owned(), policy_evaluation_settings);
+    /// Instantiate the given policy, or all the members of the policy group, and mark it as
+    /// warm without waiting for the first request targeting it. Lazily loaded policies are
+    /// compiled first. The Kubernetes resources returned by `kubernetes_resources_accessed_by`
+    /// must have been synced before invoking this method.
+    pub(crate) fn instantiate(&self, policy_id: &PolicyID) -> Result<()> {
+        if !self.policy_id_to_settings.contains_key(policy_id) {
+            return Err(EvaluationError::PolicyNotFound(policy_id.to_string()));
+        }
+        if let Some(error) = self.policy_initialization_errors.get(policy_id) {
+            return Err(EvaluationError::PolicyInitialization(error.to_string()));
+        }
 
-        self.policy_id_to_ctx_aware_allowed_resources.insert(
-            policy_id.to_owned(),
-            eval_ctx.ctx_aware_resources_allow_list,
-        );
+        if self.policy_groups.contains(policy_id) {
+            // the members of the groups are not pooled, they are instantiated by each evaluation
+            for member_id in self.policy_group_members(policy_id) {
+                self.rehydrate(&member_id)?;
+            }
+        } else {
+            self.warm_up(policy_id)?;
+            if self.evaluator_pool_size == 0 {
+                self.rehydrate(policy_id)?;
+            } else {
+                self.evaluator_pool(policy_id)?.warm_up().map_err(|e| {
+                    EvaluationError::WebAssemblyError(format!(
+                        "cannot rehydrate PolicyEvaluatorPre: {e}"
+                    ))
+                })?;
+            }
+        }
+        self.mark_as_warm(policy_id);
 
         Ok(())
     }
 
+    /// Return the Kubernetes resources the given policy, or the members of the policy group,
+    /// are allowed to access
+    pub(crate) fn kubernetes_resources_accessed_by(
+        &self,
+        policy_id: &PolicyID,
+    ) -> BTreeSet<ContextAwareResource> {
+        let group = policy_id.to_string();
+        self.policy_id_to_ctx_aware_allowed_resources
+            .iter()
+            .filter(|(id, _)| match id {
+                PolicyID::PolicyGroupPolicy { group: g, .. } => *g == group,
+                _ => *id == policy_id,
+            })
+            .flat_map(|(_, resources)| resources.iter().cloned())
+            .collect()
+    }
+
     /// Register a policy group
     fn register_policy_group(
         &mut self,
@@ -448,6 +943,110 @@ impl EvaluationEnvironment {
         self.policy_groups.insert(policy_id.to_owned());
     }
 
+    /// Compute the stable ID of a policy group. This must be invoked once all the members of
+    /// the group have been registered: the digest is computed over the sorted digests of the
+    /// members, hence it changes whenever one of them is changed.
+    fn register_policy_group_stable_id(&mut self, policy_id: &PolicyID) {
+        let mut members: Vec<(&String, &ModuleDigest)> = self
+            .policy_id_to_module_digest
+            .iter()
+            .filter_map(|(member_id, digest)| match member_id {
+                PolicyID::PolicyGroupPolicy { group, name } if *group == policy_id.to_string() => {
+                    Some((name, digest))
+                }
+                _ => None,
+            })
+            .collect();
+        members.sort();
+
+        let mut hasher = Sha256::new();
+        for (name, digest) in members {
+            hasher.update(name.as_bytes());
+            hasher.update(digest.as_bytes());
+        }
+        let digest = format!("{:x}", hasher.finalize());
+
+        self.policy_id_to_stable_id
+            .insert(policy_id.to_owned(), stable_policy_id(policy_id, &digest));
+    }
+
+    /// Given a policy ID, return its stable ID. Policies that failed to initialize
+    /// do not have one.
+    pub(crate) fn get_policy_stable_id(&self, policy_id: &PolicyID) -> Option<String> {
+        self.policy_id_to_stable_id.get(policy_id).cloned()
+    }
+
+    /// Return the list of all the policies known by Policy Server, including the ones that
+    /// failed to initialize. The list is sorted by policy ID.
+    pub(crate) fn policies_catalog(&self) -> Vec<PolicyCatalogEntry> {
+        let mut policy_ids: Vec<&PolicyID> = self
+            .policy_id_to_settings
+            .keys()
+            .chain(self.policy_initialization_errors.keys())
+            .collect::<HashSet<&PolicyID>>()
+            .into_iter()
+            .collect();
+        policy_ids.sort_by_key(|policy_id| policy_id.to_string());
+
+        policy_ids
+            .into_iter()
+            .map(|policy_id| PolicyCatalogEntry {
+                id: policy_id.to_string(),
+                stable_id: self.get_policy_stable_id(policy_id),
+                module_digest: self.policy_id_to_module_digest.get(policy_id).cloned(),
+                policy_group: self.policy_groups.contains(policy_id),
+                initialization_error: self
+                    .policy_initialization_errors
+                    .get(policy_id)
+                    .cloned()
+                    .or_else(|| self.lazy_policy_initialization_error(policy_id))
+                    .or_else(|| self.revocation_error(policy_id)),
+                unsupported_capabilities: unsupported_capability_versions(&policy_id.to_string()),
+                state: self.policy_state(policy_id),
+            })
+            .collect()
+    }
+
+    /// Return the Kubernetes resources that must be synced before the context aware
+    /// policies are considered ready
+    pub(crate) fn kubernetes_resources_pending_sync(&self) -> BTreeSet<ContextAwareResource> {
+        self.kubernetes_sync_pending
+            .read()
+            .map(|pending| pending.values().flatten().cloned().collect())
+            .unwrap_or_default()
+    }
+
+    /// Record the completion of the initial sync of the given Kubernetes resource. The
+    /// policies that have all their resources synced are no longer reported as `Syncing`
+    pub(crate) fn mark_kubernetes_resource_as_synced(&self, resource: &ContextAwareResource) {
+        if let Ok(mut pending) = self.kubernetes_sync_pending.write() {
+            pending.retain(|policy_id, resources| {
+                resources.remove(resource);
+                if resources.is_empty() {
+                    info!(%policy_id, "kubernetes resources accessed by the policy are synced");
+                }
+                !resources.is_empty()
+            });
+        }
+    }
+
+    /// Stop serving the given policy because it failed its periodic re-verification.
+    /// All the requests targeting it are going to be rejected from now on.
+    pub(crate) fn revoke_policy(&self, policy_id: &PolicyID, reason: &str) {
+        if let Ok(mut revoked_policies) = self.revoked_policies.write() {
+            revoked_policies.insert(policy_id.to_owned(), reason.to_owned());
+        }
+    }
+
+    /// Serve again the given policy, because it passed its periodic re-verification.
+    /// Returns `true` when the policy was revoked.
+    pub(crate) fn restore_policy(&self, policy_id: &PolicyID) -> bool {
+        self.revoked_policies
+            .write()
+            .map(|mut revoked_policies| revoked_policies.remove(policy_id).is_some())
+            .unwrap_or_default()
+    }
+
     /// Given a policy ID, return how the policy operates
     pub(crate) fn get_policy_mode(&self, policy_id: &PolicyID) -> Result<PolicyMode> {
         self.policy_id_to_settings
@@ -456,6 +1055,15 @@ impl EvaluationEnvironment {
             .ok_or(EvaluationError::PolicyNotFound(policy_id.to_string()))
     }
 
+    /// Given a policy ID, return how the policy reacts to the errors raised while
+    /// evaluating a request
+    pub(crate) fn get_policy_failure_policy(&self, policy_id: &PolicyID) -> Result<FailurePolicy> {
+        self.policy_id_to_settings
+            .get(policy_id)
+            .map(|settings| settings.failure_policy)
+            .ok_or(EvaluationError::PolicyNotFound(policy_id.to_string()))
+    }
+
     /// Given a policy ID, return how the policy custom reject message
     pub(crate) fn get_policy_custom_rejection_message(
         &self,
@@ -487,29 +1095,46 @@ impl EvaluationEnvironment {
     }
 
     /// Validate the settings the user provided for the given policy
-    fn validate_settings(&mut self, policy_id: &PolicyID) -> Result<()> {
+    fn validate_settings(&self, policy_id: &PolicyID) -> Result<()> {
         let settings = self.get_policy_settings(policy_id)?;
 
         match &settings.settings {
-            PolicyOrPolicyGroupSettings::Policy(settings) => {
-                let mut evaluator = self.rehydrate(policy_id)?;
-                match evaluator.validate_settings(settings) {
-                    SettingsValidationResponse {
-                        valid: true,
-                        message: _,
-                    } => {}
-                    SettingsValidationResponse {
-                        valid: false,
-                        message,
-                    } => {
-                        let error_message = format!(
-                            "Policy settings are invalid: {}",
-                            message.unwrap_or("no message".to_owned())
-                        );
-
-                        return Err(EvaluationError::PolicyInitialization(error_message));
-                    }
-                };
+            PolicyOrPolicyGroupSettings::Policy(base_settings) => {
+                // each variant of the settings is validated, a policy whose settings are
+                // not valid for one namespace cannot be loaded
+                let variants = std::iter::once((None, base_settings)).chain(
+                    settings
+                        .namespace_settings
+                        .iter()
+                        .map(|(namespace, settings)| (Some(namespace), settings)),
+                );
+                for (namespace, settings) in variants {
+                    let mut evaluator = self.rehydrate(policy_id)?;
+                    let validation_response =
+                        self.measure_epochs(policy_id, "validate_settings", || {
+                            evaluator.validate_settings(settings)
+                        });
+                    match validation_response {
+                        SettingsValidationResponse {
+                            valid: true,
+                            message: _,
+                        } => {}
+                        SettingsValidationResponse {
+                            valid: false,
+                            message,
+                        } => {
+                            let message = message.unwrap_or("no message".to_owned());
+                            let error_message = match namespace {
+                                Some(namespace) => format!(
+                                    "Policy settings for namespace {namespace} are invalid: {message}"
+                                ),
+                                None => format!("Policy settings are invalid: {message}"),
+                            };
+
+                            return Err(EvaluationError::PolicyInitialization(error_message));
+                        }
+                    };
+                }
             }
             PolicyOrPolicyGroupSettings::PolicyGroup { .. } => {
                 let group_evaluator = self.build_policy_group_evaluator(policy_id)?;
@@ -529,20 +1154,59 @@ impl EvaluationEnvironment {
 
     /// Internal method, create a `PolicyEvaluator` by using a pre-initialized instance
     fn rehydrate(&self, policy_id: &PolicyID) -> Result<PolicyEvaluator> {
+        let (policy_evaluator_pre, eval_ctx) = self.policy_evaluator_pre_and_context(policy_id)?;
+
+        policy_evaluator_pre.rehydrate(&eval_ctx).map_err(|e| {
+            EvaluationError::WebAssemblyError(format!("cannot rehydrate PolicyEvaluatorPre: {e}"))
+        })
+    }
+
+    /// Internal method, get the pool of `PolicyEvaluator` instances of the given policy,
+    /// creating it the first time
+    fn evaluator_pool(&self, policy_id: &PolicyID) -> Result<Arc<PolicyEvaluatorPool>> {
+        if let Some(pool) = self
+            .evaluator_pools
+            .read()
+            .expect("cannot lock the evaluator pools")
+            .get(policy_id)
+        {
+            return Ok(pool.clone());
+        }
+
+        let (policy_evaluator_pre, eval_ctx) = self.policy_evaluator_pre_and_context(policy_id)?;
+        let pool = Arc::new(
+            PolicyEvaluatorPool::new(
+                policy_evaluator_pre.as_ref().clone(),
+                eval_ctx,
+                self.evaluator_pool_size,
+            )
+            .with_max_uses(self.evaluator_pool_max_uses),
+        );
+
+        // another worker might have created the pool in the meantime, keep the first one
+        Ok(self
+            .evaluator_pools
+            .write()
+            .expect("cannot lock the evaluator pools")
+            .entry(policy_id.to_owned())
+            .or_insert(pool)
+            .clone())
+    }
+
+    /// Internal method, get the pre-initialized instance of the policy and the context
+    /// its evaluators must use
+    fn policy_evaluator_pre_and_context(
+        &self,
+        policy_id: &PolicyID,
+    ) -> Result<(Arc<PolicyEvaluatorPre>, EvaluationContext)> {
         if self.policy_groups.contains(policy_id) {
             return Err(EvaluationError::CannotRehydratePolicyGroup(
                 policy_id.to_string(),
             ));
         }
 
-        let module_digest = self
-            .policy_id_to_module_digest
-            .get(policy_id)
-            .ok_or(EvaluationError::PolicyNotFound(policy_id.to_string()))?;
-        let policy_evaluator_pre = self
-            .module_digest_to_policy_evaluator_pre
-            .get(module_digest)
-            .ok_or(EvaluationError::PolicyNotFound(policy_id.to_string()))?;
+        let policy_evaluator_pre =
+            self.policy_evaluator_pre(policy_id, CompilationTrigger::OnDemand)?;
 
         let ctx_aware_resources_allow_list = self
             .policy_id_to_ctx_aware_allowed_resources
@@ -551,13 +1215,19 @@ impl EvaluationEnvironment {
 
         let eval_ctx = EvaluationContext {
             policy_id: policy_id.to_string(),
+            policy_stable_id: self.get_policy_stable_id(policy_id),
             callback_channel: self.callback_handler_tx.clone(),
             ctx_aware_resources_allow_list: ctx_aware_resources_allow_list.clone(),
+            kubernetes_service_account: self
+                .policy_id_to_kubernetes_service_account
+                .get(policy_id)
+                .cloned(),
+            request_context: self.request_context(policy_id),
+            http_policy: self.policy_id_to_http_policy.get(policy_id).cloned(),
+            key_value_store: self.policies_with_key_value_store.contains(policy_id),
         };
 
-        policy_evaluator_pre.rehydrate(&eval_ctx).map_err(|e| {
-            EvaluationError::WebAssemblyError(format!("cannot rehydrate PolicyEvaluatorPre: {e}"))
-        })
+        Ok((policy_evaluator_pre, eval_ctx))
     }
 
     /// Perform a request validation
@@ -566,11 +1236,46 @@ impl EvaluationEnvironment {
         policy_id: &PolicyID,
         req: &ValidateRequest,
     ) -> Result<AdmissionResponse> {
-        if self.policy_groups.contains(policy_id) {
+        if let Some(error) = self.revocation_error(policy_id) {
+            return Err(EvaluationError::PolicyInitialization(error));
+        }
+        if let Some(settings) = self.policy_id_to_settings.get(policy_id) {
+            match settings.match_conditions.matches(req) {
+                Ok(true) => {}
+                Ok(false) => {
+                    debug!(
+                        ?policy_id,
+                        "request skipped, the matchConditions are not met"
+                    );
+                    return Ok(AdmissionResponse {
+                        uid: req.uid().to_owned(),
+                        allowed: true,
+                        ..Default::default()
+                    });
+                }
+                // the failure policy decides whether the request is accepted
+                Err(e) => {
+                    return Ok(AdmissionResponse::reject_internal_server_error(
+                        req.uid().to_owned(),
+                        e.to_string(),
+                    ))
+                }
+            }
+        }
+        let redacted_req = self
+            .policy_id_to_settings
+            .get(policy_id)
+            .and_then(|settings| settings.secret_redaction.apply(policy_id, req));
+        let req = redacted_req.as_ref().unwrap_or(req);
+
+        let response = if self.policy_groups.contains(policy_id) {
             self.validate_policy_group(policy_id, req)
         } else {
             self.validate_policy(policy_id, req)
-        }
+        }?;
+        self.mark_as_warm(policy_id);
+
+        Ok(response)
     }
 
     /// Validate a policy.
@@ -587,14 +1292,86 @@ impl EvaluationEnvironment {
         if let Some(error) = self.policy_initialization_errors.get(policy_id) {
             return Err(EvaluationError::PolicyInitialization(error.to_string()));
         }
+        self.initialize_lazy_policy(policy_id, CompilationTrigger::OnDemand)?;
+
+        let policy_settings = self.get_policy_settings(policy_id)?;
+        let settings = policy_settings
+            .policy_settings_for_request(req)
+            .expect("individual policies have policy settings");
+
+        let cache_key = self.evaluation_cache_key(policy_id, req, settings);
+        if let (Some(cache), Some(key)) = (&self.evaluation_cache, &cache_key) {
+            if let Some(response) = cache.get(&policy_id.to_string(), key, req.uid()) {
+                debug!(?policy_id, "evaluation result served from the cache");
+                return Ok(response);
+            }
+        }
 
-        let settings = match self.get_policy_settings(policy_id)?.settings {
-            PolicyOrPolicyGroupSettings::Policy(settings) => settings,
-            _ => unreachable!(),
+        let (response, cacheable) = if self.evaluator_pool_size == 0 {
+            let mut evaluator = self.rehydrate(policy_id)?;
+            evaluation_cache::track_cacheability(|| {
+                self.measure_epochs(policy_id, "validate", || {
+                    evaluator.validate(req.clone(), settings)
+                })
+            })
+        } else {
+            let pool = self.evaluator_pool(policy_id)?;
+            let mut evaluator = pool.acquire().map_err(|e| {
+                EvaluationError::WebAssemblyError(format!(
+                    "cannot rehydrate PolicyEvaluatorPre: {e}"
+                ))
+            })?;
+            let (response, cacheable) = evaluation_cache::track_cacheability(|| {
+                self.measure_epochs(policy_id, "validate", || {
+                    evaluator.validate(req.clone(), settings)
+                })
+            });
+            // the guest might be left in an inconsistent state by a trap, a timeout
+            // or an internal error: the instance is not reused
+            if !response.is_internal_server_error() {
+                pool.release(evaluator);
+            }
+            (response, cacheable)
         };
-        let mut evaluator = self.rehydrate(policy_id)?;
 
-        Ok(evaluator.validate(req.clone(), &settings))
+        // the responses of the policies that used the OCI, sigstore, DNS or any
+        // other host capability served by the callback handler can change at any time
+        if let (Some(cache), Some(key), true) = (&self.evaluation_cache, cache_key, cacheable) {
+            cache.insert(key, &response);
+        }
+
+        Ok(response)
+    }
+
+    /// The key identifying the evaluation of the request inside of the evaluation cache.
+    /// `None` when the cache is disabled, or when the policy is context aware: the
+    /// outcome of its evaluation depends on the state of the cluster
+    fn evaluation_cache_key(
+        &self,
+        policy_id: &PolicyID,
+        req: &ValidateRequest,
+        settings: &PolicySettings,
+    ) -> Option<EvaluationCacheKey> {
+        self.evaluation_cache.as_ref()?;
+        let context_aware = self
+            .policy_id_to_ctx_aware_allowed_resources
+            .get(policy_id)
+            .is_some_and(|resources| !resources.is_empty());
+        // the responses of the remote services, and the entries of the key/value
+        // store, can change at any time
+        if context_aware
+            || self.policy_id_to_http_policy.contains_key(policy_id)
+            || self.policies_with_key_value_store.contains(policy_id)
+        {
+            return None;
+        }
+
+        // the key of the `PolicyEvaluatorPre` covers the module, the OPA entrypoint,
+        // the Rego libraries and the timeout of the policy
+        let policy_digest = self.policy_evaluator_pre_key(policy_id).ok()?;
+        EvaluationCacheKey::new(&policy_digest, req, settings)
+            .inspect_err(|e| warn!(?policy_id, error = %e, "cannot compute evaluation cache key"))
+            .ok()
     }
 
     /// Validate a policy group
@@ -626,19 +1403,17 @@ impl EvaluationEnvironment {
             &expression,
             self.callback_handler_tx.clone(),
         );
+        evaluator.set_cluster(self.cluster.clone());
 
         for sub_policy_name in policies {
             let policy_id = PolicyID::PolicyGroupPolicy {
                 group: policy_id.to_string(),
                 name: sub_policy_name.clone(),
             };
-            let module_digest = self
-                .policy_id_to_module_digest
-                .get(&policy_id)
-                .ok_or(EvaluationError::PolicyNotFound(policy_id.to_string()))?;
+            let pre_key = self.policy_evaluator_pre_key(&policy_id)?;
             let policy_evaluator_pre = self
                 .module_digest_to_policy_evaluator_pre
-                .get(module_digest)
+                .get(&pre_key)
                 .ok_or(EvaluationError::PolicyNotFound(policy_id.to_string()))?;
 
             let ctx_aware_resources_allow_list = self
@@ -654,6 +1429,11 @@ impl EvaluationEnvironment {
             let policy_group_member_settings = PolicyGroupMemberSettings {
                 settings,
                 ctx_aware_resources_allow_list: ctx_aware_resources_allow_list.clone(),
+                kubernetes_service_account: self
+                    .policy_id_to_kubernetes_service_account
+                    .get(&policy_id)
+                    .cloned(),
+                policy_stable_id: self.get_policy_stable_id(&policy_id),
             };
 
             evaluator.add_policy_member(
@@ -667,9 +1447,485 @@ impl EvaluationEnvironment {
     }
 }
 
-fn create_wasmtime_module(
-    policy_id: &PolicyID,
-    engine: &wasmtime::Engine,
+/// Internal helpers, including the ones used to handle the policies that are loaded lazily.
+/// These are kept outside of the mocked `impl` block because they are never invoked by the
+/// code using the environment.
+impl EvaluationEnvironment {
+    /// Register a new policy. It takes care of creating a new `PolicyEvaluator` (when needed).
+    /// This is used to register both individual policies and the ones that are part of a group
+    /// policy.
+    ///
+    /// Params:
+    /// - `engine`: the `wasmtime::Engine` to be used when creating the `PolicyEvaluator`
+    /// - `policy_id`: the unique identifier of the policy
+    /// - `policy_evaluation_settings`: the settings associated with the policy
+    /// - `precompiled_policy`: the `PrecompiledPolicy` associated with the Wasm module referenced by the policy
+    /// - `callback_handler_tx`: the transmission end of a channel that connects the worker with the asynchronous world
+    /// - `entrypoint`: the OPA entrypoint to be evaluated, the default one is used when not set
+    /// - `rego_libraries`: the Rego libraries linked into the `data` document of the policy
+    #[allow(clippy::too_many_arguments)]
+    fn register(
+        &mut self,
+        engine: &wasmtime::Engine,
+        policy_id: &PolicyID,
+        policy_evaluation_settings: PolicyEvaluationSettings,
+        eval_ctx: EvaluationContext,
+        precompiled_policy: &PrecompiledPolicy,
+        entrypoint: Option<&str>,
+        rego_libraries: &LinkedRegoLibraries,
+    ) -> Result<()> {
+        let module_digest = &precompiled_policy.digest;
+        let timeout_seconds = policy_evaluation_settings.timeout_seconds;
+        let preopened_dirs = &policy_evaluation_settings.preopened_dirs;
+        let pre_key = policy_evaluator_pre_key(
+            module_digest,
+            entrypoint,
+            rego_libraries.digest.as_deref(),
+            timeout_seconds,
+            preopened_dirs,
+        );
+
+        if !self
+            .module_digest_to_policy_evaluator_pre
+            .contains_key(&pre_key)
+        {
+            debug!(?policy_id, "create wasmtime::Module");
+            let module = create_wasmtime_module(policy_id, engine, precompiled_policy)?;
+            debug!(?policy_id, "create PolicyEvaluatorPre");
+            let pol_eval_pre = create_policy_evaluator_pre(
+                engine,
+                &module,
+                precompiled_policy,
+                entrypoint,
+                &rego_libraries.documents,
+                self.policy_epoch_deadlines(timeout_seconds),
+                self.rego_policy_memory_limit,
+                self.policy_request_projection(precompiled_policy),
+                preopened_dirs,
+            )?;
+
+            self.module_digest_to_policy_evaluator_pre
+                .insert(pre_key, Arc::new(pol_eval_pre));
+        }
+        self.register_opa_entrypoint(policy_id, entrypoint);
+        self.register_rego_libraries(policy_id, rego_libraries);
+        self.policy_id_to_module_digest
+            .insert(policy_id.to_owned(), module_digest.to_owned());
+        self.policy_id_to_stable_id.insert(
+            policy_id.to_owned(),
+            stable_policy_id(policy_id, module_digest),
+        );
+
+        self.policy_id_to_settings
+            .insert(policy_id.to_owned(), policy_evaluation_settings);
+
+        self.policy_id_to_ctx_aware_allowed_resources.insert(
+            policy_id.to_owned(),
+            eval_ctx.ctx_aware_resources_allow_list,
+        );
+        if let Some(service_account) = eval_ctx.kubernetes_service_account {
+            self.policy_id_to_kubernetes_service_account
+                .insert(policy_id.to_owned(), service_account);
+        }
+        if let Some(http_policy) = eval_ctx.http_policy {
+            self.policy_id_to_http_policy
+                .insert(policy_id.to_owned(), http_policy);
+        }
+        if eval_ctx.key_value_store {
+            self.policies_with_key_value_store
+                .insert(policy_id.to_owned());
+        }
+
+        Ok(())
+    }
+
+    /// Register a policy whose Wasm module is compiled the first time it is used.
+    /// Policies using the same Wasm module share the same `LazyModule`, hence the module
+    /// is compiled only once.
+    fn register_lazy(
+        &mut self,
+        policy_id: &PolicyID,
+        policy_evaluation_settings: PolicyEvaluationSettings,
+        eval_ctx: EvaluationContext,
+        wasm_module_path: &Path,
+        entrypoint: Option<&str>,
+        rego_libraries: &LinkedRegoLibraries,
+    ) -> Result<()> {
+        let wasm_module = fs::read(wasm_module_path).map_err(|e| {
+            EvaluationError::WebAssemblyError(format!(
+                "cannot read Wasm module of {policy_id} from {}: {e}",
+                wasm_module_path.display()
+            ))
+        })?;
+        let module_digest = format!("{:x}", Sha256::digest(&wasm_module));
+
+        self.module_digest_to_lazy_module
+            .entry(policy_evaluator_pre_key(
+                &module_digest,
+                entrypoint,
+                rego_libraries.digest.as_deref(),
+                policy_evaluation_settings.timeout_seconds,
+                &policy_evaluation_settings.preopened_dirs,
+            ))
+            .or_insert_with(|| {
+                Arc::new(LazyModule {
+                    wasm_module_path: wasm_module_path.to_owned(),
+                    entrypoint: entrypoint.map(str::to_owned),
+                    rego_libraries: rego_libraries.documents.clone(),
+                    timeout_seconds: policy_evaluation_settings.timeout_seconds,
+                    preopened_dirs: policy_evaluation_settings.preopened_dirs.clone(),
+                    policy_evaluator_pre: OnceLock::new(),
+                })
+            });
+        self.register_opa_entrypoint(policy_id, entrypoint);
+        self.register_rego_libraries(policy_id, rego_libraries);
+        self.lazy_policy_initializations
+            .insert(policy_id.to_owned(), OnceLock::new());
+        self.policy_id_to_stable_id.insert(
+            policy_id.to_owned(),
+            stable_policy_id(policy_id, &module_digest),
+        );
+        self.policy_id_to_module_digest
+            .insert(policy_id.to_owned(), module_digest);
+
+        self.policy_id_to_settings
+            .insert(policy_id.to_owned(), policy_evaluation_settings);
+
+        self.policy_id_to_ctx_aware_allowed_resources.insert(
+            policy_id.to_owned(),
+            eval_ctx.ctx_aware_resources_allow_list,
+        );
+        if let Some(service_account) = eval_ctx.kubernetes_service_account {
+            self.policy_id_to_kubernetes_service_account
+                .insert(policy_id.to_owned(), service_account);
+        }
+        if let Some(http_policy) = eval_ctx.http_policy {
+            self.policy_id_to_http_policy
+                .insert(policy_id.to_owned(), http_policy);
+        }
+        if eval_ctx.key_value_store {
+            self.policies_with_key_value_store
+                .insert(policy_id.to_owned());
+        }
+
+        Ok(())
+    }
+
+    /// Invoke `f`, which runs the given operation of the policy, recording the epoch ticks
+    /// consumed by the guest. Nothing is recorded when the timeout protection is disabled.
+    fn measure_epochs<T>(&self, policy_id: &PolicyID, operation: &str, f: impl FnOnce() -> T) -> T {
+        let Some(epoch_ticker) = &self.epoch_ticker else {
+            return f();
+        };
+
+        let (result, epochs) = epoch_ticker.measure(f);
+        debug!(?policy_id, operation, epochs, "epoch ticks consumed");
+        metrics::record_policy_epochs(
+            epochs,
+            &metrics::PolicyEpochs {
+                policy_name: policy_id.to_string(),
+                policy_stable_id: self.get_policy_stable_id(policy_id),
+                operation: operation.to_owned(),
+            },
+        );
+
+        result
+    }
+
+    /// Keep track of the OPA entrypoint selected by the given policy
+    fn register_opa_entrypoint(&mut self, policy_id: &PolicyID, entrypoint: Option<&str>) {
+        if let Some(entrypoint) = entrypoint {
+            self.policy_id_to_opa_entrypoint
+                .insert(policy_id.to_owned(), entrypoint.to_owned());
+        }
+    }
+
+    /// Keep track of the Rego libraries used by the given policy
+    fn register_rego_libraries(
+        &mut self,
+        policy_id: &PolicyID,
+        rego_libraries: &LinkedRegoLibraries,
+    ) {
+        if let Some(digest) = &rego_libraries.digest {
+            self.policy_id_to_rego_libraries_digest
+                .insert(policy_id.to_owned(), digest.to_owned());
+        }
+    }
+
+    /// Return the key of the `PolicyEvaluatorPre` used by the given policy
+    fn policy_evaluator_pre_key(&self, policy_id: &PolicyID) -> Result<String> {
+        let module_digest = self
+            .policy_id_to_module_digest
+            .get(policy_id)
+            .ok_or(EvaluationError::PolicyNotFound(policy_id.to_string()))?;
+        let settings = self.policy_id_to_settings.get(policy_id);
+        Ok(policy_evaluator_pre_key(
+            module_digest,
+            self.policy_id_to_opa_entrypoint
+                .get(policy_id)
+                .map(String::as_str),
+            self.policy_id_to_rego_libraries_digest
+                .get(policy_id)
+                .map(String::as_str),
+            settings.and_then(|settings| settings.timeout_seconds),
+            settings
+                .map(|settings| &settings.preopened_dirs)
+                .unwrap_or(&BTreeMap::new()),
+        ))
+    }
+
+    /// The epoch deadlines of a policy, the evaluation deadline is computed from the
+    /// timeout of the policy when it overrides the global one. `None` when the timeout
+    /// protection is disabled
+    fn policy_epoch_deadlines(&self, timeout_seconds: Option<u64>) -> Option<EpochDeadlines> {
+        let deadlines = self.epoch_deadlines?;
+        match (timeout_seconds, &self.epoch_ticker) {
+            (Some(timeout_seconds), Some(epoch_ticker)) => Some(EpochDeadlines {
+                evaluation: epoch_ticker.deadline(Duration::from_secs(timeout_seconds)),
+                ..deadlines
+            }),
+            _ => Some(deadlines),
+        }
+    }
+
+    /// The context of the evaluation given to the policies that use the `v2` request envelope
+    fn request_context(&self, policy_id: &PolicyID) -> RequestContext {
+        let policy_group = match policy_id {
+            PolicyID::PolicyGroupPolicy { group, name } => Some(PolicyGroupInfo {
+                name: group.to_owned(),
+                member: name.to_owned(),
+            }),
+            _ => None,
+        };
+
+        RequestContext {
+            cluster: self.cluster.clone(),
+            policy_group,
+        }
+    }
+
+    /// The request projection declared by the metadata of the policy, `None` when
+    /// the policy receives the whole request
+    fn policy_request_projection<'a>(
+        &self,
+        precompiled_policy: &'a PrecompiledPolicy,
+    ) -> Option<&'a [String]> {
+        precompiled_policy
+            .request_projection
+            .as_deref()
+            .filter(|_| self.request_projection)
+    }
+
+    /// Return the `PolicyEvaluatorPre` of the given policy. When the policy is loaded lazily,
+    /// its Wasm module is compiled the first time this method is invoked.
+    fn policy_evaluator_pre(
+        &self,
+        policy_id: &PolicyID,
+        trigger: CompilationTrigger,
+    ) -> Result<Arc<PolicyEvaluatorPre>> {
+        let pre_key = self.policy_evaluator_pre_key(policy_id)?;
+        if let Some(policy_evaluator_pre) = self.module_digest_to_policy_evaluator_pre.get(&pre_key)
+        {
+            return Ok(policy_evaluator_pre.clone());
+        }
+
+        let lazy_module = self
+            .module_digest_to_lazy_module
+            .get(&pre_key)
+            .ok_or(EvaluationError::PolicyNotFound(policy_id.to_string()))?;
+        lazy_module
+            .policy_evaluator_pre
+            .get_or_init(|| {
+                let start_time = Instant::now();
+                let result = self.compile_lazy_module(policy_id, lazy_module);
+                let elapsed = start_time.elapsed();
+
+                metrics::record_policy_compilation(
+                    elapsed,
+                    &metrics::PolicyCompilation {
+                        policy_name: policy_id.to_string(),
+                        trigger: trigger.to_string(),
+                        success: result.is_ok(),
+                    },
+                );
+                info!(
+                    %policy_id,
+                    %trigger,
+                    elapsed_milliseconds = elapsed.as_millis() as u64,
+                    success = result.is_ok(),
+                    "lazily loaded policy compiled"
+                );
+
+                result.map(Arc::new).map_err(|e| e.to_string())
+            })
+            .clone()
+            .map_err(EvaluationError::WebAssemblyError)
+    }
+
+    /// Compile the Wasm module of a lazily loaded policy
+    fn compile_lazy_module(
+        &self,
+        policy_id: &PolicyID,
+        lazy_module: &LazyModule,
+    ) -> Result<PolicyEvaluatorPre> {
+        let engine = self.engine.as_ref().ok_or_else(|| {
+            EvaluationError::WebAssemblyError(format!(
+                "cannot compile {policy_id}: wasmtime engine not available"
+            ))
+        })?;
+
+        let precompiled_policy = PrecompiledPolicy::new(
+            engine,
+            &lazy_module.wasm_module_path,
+            self.module_cache.as_deref(),
+        )
+        .map_err(|e| EvaluationError::WebAssemblyError(format!("{policy_id}: {e}")))?;
+        let module = create_wasmtime_module(policy_id, engine, &precompiled_policy)?;
+        create_policy_evaluator_pre(
+            engine,
+            &module,
+            &precompiled_policy,
+            lazy_module.entrypoint.as_deref(),
+            &lazy_module.rego_libraries,
+            self.policy_epoch_deadlines(lazy_module.timeout_seconds),
+            self.rego_policy_memory_limit,
+            self.policy_request_projection(&precompiled_policy),
+            &lazy_module.preopened_dirs,
+        )
+    }
+
+    /// Ensure a lazily loaded policy is compiled and that its settings are valid.
+    /// This is done only once, the outcome is reused by all the subsequent invocations.
+    /// Policies compiled at bootstrap time are always considered initialized.
+    fn initialize_lazy_policy(
+        &self,
+        policy_id: &PolicyID,
+        trigger: CompilationTrigger,
+    ) -> Result<()> {
+        let initialization = match self.lazy_policy_initializations.get(policy_id) {
+            Some(initialization) => initialization,
+            None => return Ok(()),
+        };
+
+        initialization
+            .get_or_init(|| {
+                self.policy_evaluator_pre(policy_id, trigger)
+                    .and_then(|_| self.validate_settings(policy_id))
+                    .map_err(|e| e.to_string())
+            })
+            .clone()
+            .map_err(EvaluationError::PolicyInitialization)
+    }
+
+    /// Return the IDs of the members of the given policy group
+    fn policy_group_members(&self, policy_id: &PolicyID) -> Vec<PolicyID> {
+        let group = policy_id.to_string();
+        self.policy_id_to_ctx_aware_allowed_resources
+            .keys()
+            .filter(|id| matches!(id, PolicyID::PolicyGroupPolicy { group: g, .. } if *g == group))
+            .cloned()
+            .collect()
+    }
+
+    /// Keep track of the policies that have been instantiated, either at bootstrap time
+    /// or by their first evaluation
+    fn mark_as_warm(&self, policy_id: &PolicyID) {
+        let already_warm = self
+            .warm_policies
+            .read()
+            .map(|warm_policies| warm_policies.contains(policy_id))
+            .unwrap_or(true);
+        if already_warm {
+            return;
+        }
+
+        if let Ok(mut warm_policies) = self.warm_policies.write() {
+            if warm_policies.insert(policy_id.to_owned()) {
+                info!(%policy_id, "policy is warm");
+            }
+        }
+    }
+
+    /// Return how ready the given policy is to evaluate requests
+    fn policy_state(&self, policy_id: &PolicyID) -> PolicyState {
+        if self.policy_initialization_errors.contains_key(policy_id)
+            || self.lazy_policy_initialization_error(policy_id).is_some()
+            || self.revocation_error(policy_id).is_some()
+        {
+            return PolicyState::Failed;
+        }
+        if self.is_waiting_for_kubernetes_sync(policy_id) {
+            return PolicyState::Syncing;
+        }
+        let warm = self
+            .warm_policies
+            .read()
+            .map(|warm_policies| warm_policies.contains(policy_id))
+            .unwrap_or_default();
+        if warm {
+            return PolicyState::Warm;
+        }
+        // the members of policy groups are always compiled at bootstrap time
+        if self.policy_groups.contains(policy_id) {
+            return PolicyState::Compiled;
+        }
+
+        let compiled = self
+            .policy_evaluator_pre_key(policy_id)
+            .map(|pre_key| {
+                self.module_digest_to_policy_evaluator_pre
+                    .contains_key(&pre_key)
+                    || self
+                        .module_digest_to_lazy_module
+                        .get(&pre_key)
+                        .and_then(|lazy_module| lazy_module.policy_evaluator_pre.get())
+                        .is_some_and(|result| result.is_ok())
+            })
+            .unwrap_or_default();
+        if compiled {
+            PolicyState::Compiled
+        } else {
+            PolicyState::Cold
+        }
+    }
+
+    /// Returns `true` when the policy, or one of the members of the policy group, is
+    /// waiting for the initial sync of the Kubernetes resources it can access
+    fn is_waiting_for_kubernetes_sync(&self, policy_id: &PolicyID) -> bool {
+        let group = policy_id.to_string();
+        self.kubernetes_sync_pending
+            .read()
+            .map(|pending| {
+                pending.keys().any(|pending_id| match pending_id {
+                    PolicyID::PolicyGroupPolicy { group: g, .. } if *g == group => true,
+                    _ => pending_id == policy_id,
+                })
+            })
+            .unwrap_or_default()
+    }
+
+    /// Return the error that occurred while initializing a lazily loaded policy, if any
+    fn lazy_policy_initialization_error(&self, policy_id: &PolicyID) -> Option<String> {
+        self.lazy_policy_initializations
+            .get(policy_id)
+            .and_then(OnceLock::get)
+            .and_then(|initialization| initialization.clone().err())
+    }
+
+    /// Return the reason why the policy has been revoked, if it failed its periodic
+    /// re-verification
+    fn revocation_error(&self, policy_id: &PolicyID) -> Option<String> {
+        self.revoked_policies
+            .read()
+            .ok()
+            .and_then(|revoked_policies| revoked_policies.get(policy_id).cloned())
+            .map(|reason| format!("policy failed re-verification: {reason}"))
+    }
+}
+
+fn create_wasmtime_module(
+    policy_id: &PolicyID,
+    engine: &wasmtime::Engine,
     precompiled_policy: &PrecompiledPolicy,
 ) -> Result<wasmtime::Module> {
     // See `wasmtime::Module::deserialize` to know why this method is `unsafe`.
@@ -684,21 +1940,65 @@ fn create_wasmtime_module(
         })
 }
 
-/// Internal function, takes care of creating the `PolicyEvaluator` instance for the given policy
+/// Internal function, takes care of creating the `PolicyEvaluator` instance for the given policy.
+/// The `request_projection` is the one to be honored, see `policy_request_projection`
+#[allow(clippy::too_many_arguments)]
 fn create_policy_evaluator_pre(
     engine: &wasmtime::Engine,
     module: &wasmtime::Module,
-    mode: PolicyExecutionMode,
-    policy_evaluation_limit_seconds: Option<u64>,
+    precompiled_policy: &PrecompiledPolicy,
+    entrypoint: Option<&str>,
+    rego_libraries: &BTreeMap<String, RegoLibraryDocument>,
+    epoch_deadlines: Option<EpochDeadlines>,
+    rego_memory_limit: Option<u64>,
+    request_projection: Option<&[String]>,
+    preopened_dirs: &BTreeMap<String, PathBuf>,
 ) -> Result<PolicyEvaluatorPre> {
+    let mode = precompiled_policy.execution_mode;
     let mut policy_evaluator_builder = PolicyEvaluatorBuilder::new()
         .engine(engine.to_owned())
         .policy_module(module.to_owned())
         .execution_mode(mode);
 
-    if let Some(limit) = policy_evaluation_limit_seconds {
+    if let Some(entrypoint) = entrypoint {
+        policy_evaluator_builder = policy_evaluator_builder.opa_entrypoint(entrypoint);
+    }
+
+    for (path, document) in rego_libraries {
+        policy_evaluator_builder = policy_evaluator_builder.rego_library(path, document.to_owned());
+    }
+
+    if let Some(deadlines) = epoch_deadlines {
+        policy_evaluator_builder = policy_evaluator_builder
+            .enable_epoch_interruptions(deadlines.evaluation, deadlines.evaluation)
+            .settings_validation_epoch_deadline(deadlines.settings_validation);
+    }
+
+    if let Some(limit) = rego_memory_limit {
+        if matches!(
+            mode,
+            PolicyExecutionMode::Opa | PolicyExecutionMode::OpaGatekeeper
+        ) {
+            policy_evaluator_builder = policy_evaluator_builder.rego_memory_limit(limit);
+        }
+    }
+
+    if let Some(paths) = request_projection {
+        if mode == PolicyExecutionMode::KubewardenWapc {
+            policy_evaluator_builder = policy_evaluator_builder.request_projection(paths);
+        }
+    }
+
+    if mode == PolicyExecutionMode::KubewardenWapc {
         policy_evaluator_builder =
-            policy_evaluator_builder.enable_epoch_interruptions(limit, limit);
+            policy_evaluator_builder.request_envelope(precompiled_policy.request_envelope);
+    }
+
+    // the directories mapped by the operator must be the ones declared by the policy
+    policy_evaluator_builder =
+        policy_evaluator_builder.declared_preopened_dirs(&precompiled_policy.preopened_dirs);
+    for (guest_path, host_path) in preopened_dirs {
+        policy_evaluator_builder = policy_evaluator_builder.preopened_dir(guest_path, host_path);
     }
 
     policy_evaluator_builder.build_pre().map_err(|e| {
@@ -732,9 +2032,12 @@ mod tests {
         let digest = hasher.finalize();
 
         PrecompiledPolicy {
-            precompiled_module: module.serialize().unwrap(),
+            precompiled_module: module.serialize().unwrap().into(),
             execution_mode: policy_evaluator::policy_evaluator::PolicyExecutionMode::OpaGatekeeper,
             digest: format!("{digest:x}"),
+            request_projection: None,
+            request_envelope: Default::default(),
+            preopened_dirs: Vec::new(),
         }
     }
 
@@ -778,10 +2081,21 @@ mod tests {
                 PolicyOrPolicyGroup::Policy {
                     module: policy_url.clone(),
                     policy_mode: PolicyMode::Protect,
+                    failure_policy: FailurePolicy::Fail,
+                    timeout_seconds: None,
                     allowed_to_mutate: None,
                     settings: None,
+                    namespace_settings: HashMap::new(),
                     context_aware_resources: BTreeSet::new(),
+                    service_account: None,
                     message: None,
+                    entrypoint: None,
+                    rego_libraries: BTreeMap::new(),
+                    match_conditions: Vec::new(),
+                    secret_data: None,
+                    http_send: None,
+                    key_value_store: false,
+                    preopened_dirs: BTreeMap::new(),
                 },
             );
             precompiled_policies.insert(policy_url, Ok(precompiled_policy.clone()));
@@ -792,63 +2106,87 @@ mod tests {
             "group_policy_valid_expression_with_single_member".to_string(),
             PolicyOrPolicyGroup::PolicyGroup {
                 policy_mode: PolicyMode::Protect,
+                failure_policy: FailurePolicy::Fail,
+                timeout_seconds: None,
                 policies: vec![(
                     "happy_policy_1".to_string(),
                     PolicyGroupMember {
                         module: "file:///tmp/happy_policy_1.wasm".to_string(),
                         settings: None,
                         context_aware_resources: BTreeSet::new(),
+                        service_account: None,
+                        preopened_dirs: BTreeMap::new(),
                     },
                 )]
                 .into_iter()
                 .collect(),
                 expression: "true || happy_policy_1()".to_string(),
                 message: "something went wrong".to_string(),
+                match_conditions: Vec::new(),
+                secret_data: None,
             },
         );
         policies.insert(
             "group_policy_valid_expression_just_rhai".to_string(),
             PolicyOrPolicyGroup::PolicyGroup {
                 policy_mode: PolicyMode::Protect,
+                failure_policy: FailurePolicy::Fail,
+                timeout_seconds: None,
                 expression: "2 > 1".to_string(),
                 message: "something went wrong".to_string(),
                 policies: HashMap::new(),
+                match_conditions: Vec::new(),
+                secret_data: None,
             },
         );
         policies.insert(
             "group_policy_not_valid_expression_because_of_unregistered_function".to_string(),
             PolicyOrPolicyGroup::PolicyGroup {
                 policy_mode: PolicyMode::Protect,
+                failure_policy: FailurePolicy::Fail,
+                timeout_seconds: None,
                 policies: vec![(
                     "happy_policy_1".to_string(),
                     PolicyGroupMember {
                         module: "file:///tmp/happy_policy_1.wasm".to_string(),
                         settings: None,
                         context_aware_resources: BTreeSet::new(),
+                        service_account: None,
+                        preopened_dirs: BTreeMap::new(),
                     },
                 )]
                 .into_iter()
                 .collect(),
                 expression: "unknown_policy() || happy_policy_1()".to_string(),
                 message: "something went wrong".to_string(),
+                match_conditions: Vec::new(),
+                secret_data: None,
             },
         );
         policies.insert(
             "group_policy_not_valid_expression_because_of_typos".to_string(),
             PolicyOrPolicyGroup::PolicyGroup {
                 policy_mode: PolicyMode::Protect,
+                failure_policy: FailurePolicy::Fail,
+                timeout_seconds: None,
                 expression: "something that doesn't make sense".to_string(),
                 message: "something went wrong".to_string(),
                 policies: HashMap::new(),
+                match_conditions: Vec::new(),
+                secret_data: None,
             },
         );
         policies.insert(
             "group_policy_not_valid_expression_because_of_does_not_return_boolean".to_string(),
             PolicyOrPolicyGroup::PolicyGroup {
                 policy_mode: PolicyMode::Protect,
+                failure_policy: FailurePolicy::Fail,
+                timeout_seconds: None,
                 expression: "1 + 1".to_string(),
                 message: "something went wrong".to_string(),
                 policies: HashMap::new(),
+                match_conditions: Vec::new(),
+                secret_data: None,
             },
         );
         policies.insert(
@@ -856,24 +2194,32 @@ mod tests {
                 .to_string(),
             PolicyOrPolicyGroup::PolicyGroup {
                 policy_mode: PolicyMode::Protect,
+                failure_policy: FailurePolicy::Fail,
+                timeout_seconds: None,
                 policies: vec![(
                     "happy_policy_1".to_string(),
                     PolicyGroupMember {
                         module: "file:///tmp/happy_policy_1.wasm".to_string(),
                         settings: None,
                         context_aware_resources: BTreeSet::new(),
+                        service_account: None,
+                        preopened_dirs: BTreeMap::new(),
                     },
                 )]
                 .into_iter()
                 .collect(),
                 expression: "happy_policy_1() + 1".to_string(),
                 message: "something went wrong".to_string(),
+                match_conditions: Vec::new(),
+                secret_data: None,
             },
         );
         policies.insert(
             "group_policy_with_unhappy_or_bracket_happy_and_unhappy_bracket".to_string(),
             PolicyOrPolicyGroup::PolicyGroup {
                 policy_mode: PolicyMode::Protect,
+                failure_policy: FailurePolicy::Fail,
+                timeout_seconds: None,
                 policies: vec![
                     (
                         "happy_policy_1".to_string(),
@@ -881,6 +2227,8 @@ mod tests {
                             module: "file:///tmp/happy_policy_1.wasm".to_string(),
                             settings: None,
                             context_aware_resources: BTreeSet::new(),
+                            service_account: None,
+                            preopened_dirs: BTreeMap::new(),
                         },
                     ),
                     (
@@ -889,6 +2237,8 @@ mod tests {
                             module: "file:///tmp/unhappy_policy_1.wasm".to_string(),
                             settings: None,
                             context_aware_resources: BTreeSet::new(),
+                            service_account: None,
+                            preopened_dirs: BTreeMap::new(),
                         },
                     ),
                     (
@@ -897,6 +2247,8 @@ mod tests {
                             module: "file:///tmp/unhappy_policy_1.wasm".to_string(),
                             settings: None,
                             context_aware_resources: BTreeSet::new(),
+                            service_account: None,
+                            preopened_dirs: BTreeMap::new(),
                         },
                     ),
                 ]
@@ -905,6 +2257,8 @@ mod tests {
                 expression: "unhappy_policy_1() || (happy_policy_1() && unhappy_policy_2())"
                     .to_string(),
                 message: "something went wrong".to_string(),
+                match_conditions: Vec::new(),
+                secret_data: None,
             },
         );
 
@@ -912,6 +2266,8 @@ mod tests {
             "group_policy_with_unhappy_or_happy_or_unhappy".to_string(),
             PolicyOrPolicyGroup::PolicyGroup {
                 policy_mode: PolicyMode::Protect,
+                failure_policy: FailurePolicy::Fail,
+                timeout_seconds: None,
                 policies: vec![
                     (
                         "happy_policy_1".to_string(),
@@ -919,6 +2275,8 @@ mod tests {
                             module: "file:///tmp/happy_policy_1.wasm".to_string(),
                             settings: None,
                             context_aware_resources: BTreeSet::new(),
+                            service_account: None,
+                            preopened_dirs: BTreeMap::new(),
                         },
                     ),
                     (
@@ -927,6 +2285,8 @@ mod tests {
                             module: "file:///tmp/unhappy_policy_1.wasm".to_string(),
                             settings: None,
                             context_aware_resources: BTreeSet::new(),
+                            service_account: None,
+                            preopened_dirs: BTreeMap::new(),
                         },
                     ),
                     (
@@ -935,6 +2295,8 @@ mod tests {
                             module: "file:///tmp/unhappy_policy_1.wasm".to_string(),
                             settings: None,
                             context_aware_resources: BTreeSet::new(),
+                            service_account: None,
+                            preopened_dirs: BTreeMap::new(),
                         },
                     ),
                 ]
@@ -943,6 +2305,8 @@ mod tests {
                 expression: "unhappy_policy_1() || happy_policy_1() || unhappy_policy_2()"
                     .to_string(),
                 message: "something went wrong".to_string(),
+                match_conditions: Vec::new(),
+                secret_data: None,
             },
         );
 
@@ -1072,6 +2436,768 @@ mod tests {
         );
     }
 
+    /// Policies sharing the same Wasm module, but evaluating different entrypoints, must not
+    /// share the same `PolicyEvaluatorPre`. Unknown entrypoints are reported at bootstrap time.
+    #[test]
+    fn opa_entrypoint_selection() {
+        let engine = wasmtime::Engine::default();
+        let (callback_handler_tx, _) = mpsc::channel(10);
+        let precompiled_policy = build_precompiled_policy(
+            &engine,
+            include_bytes!("../../tests/data/gatekeeper_always_happy_policy.wasm"),
+        );
+        let policy_url = "file:///tmp/happy_policy.wasm".to_string();
+        let precompiled_policies: PrecompiledPolicies =
+            HashMap::from([(policy_url.clone(), Ok(precompiled_policy))]);
+
+        let policy = |entrypoint: Option<&str>| PolicyOrPolicyGroup::Policy {
+            module: policy_url.clone(),
+            policy_mode: PolicyMode::Protect,
+            failure_policy: FailurePolicy::Fail,
+            timeout_seconds: None,
+            allowed_to_mutate: None,
+            settings: None,
+            namespace_settings: HashMap::new(),
+            context_aware_resources: BTreeSet::new(),
+            service_account: None,
+            message: None,
+            entrypoint: entrypoint.map(str::to_owned),
+            rego_libraries: BTreeMap::new(),
+            match_conditions: Vec::new(),
+            secret_data: None,
+            http_send: None,
+            key_value_store: false,
+            preopened_dirs: BTreeMap::new(),
+        };
+        let policies = HashMap::from([
+            ("default_entrypoint".to_string(), policy(None)),
+            (
+                "unknown_entrypoint".to_string(),
+                policy(Some("does/not/exist")),
+            ),
+        ]);
+
+        let evaluation_environment =
+            EvaluationEnvironmentBuilder::new(&engine, &precompiled_policies, callback_handler_tx)
+                .with_continue_on_errors(true)
+                .build_evaluation_environment(&policies)
+                .unwrap();
+
+        assert_eq!(
+            evaluation_environment
+                .module_digest_to_policy_evaluator_pre
+                .len(),
+            1
+        );
+        let error = evaluation_environment
+            .policy_initialization_errors
+            .get(&PolicyID::Policy("unknown_entrypoint".to_string()))
+            .expect("the policy should have failed to initialize");
+        assert!(
+            error.contains("cannot find entrypoint does/not/exist"),
+            "unexpected error: {error}"
+        );
+        assert!(!evaluation_environment
+            .policy_initialization_errors
+            .contains_key(&PolicyID::Policy("default_entrypoint".to_string())));
+    }
+
+    /// The Rego libraries are resolved at bootstrap time: policies referencing a library
+    /// that cannot be fetched, or linking libraries to a non OPA module, fail to initialize
+    #[test]
+    fn rego_libraries_initialization() {
+        let engine = wasmtime::Engine::default();
+        let (callback_handler_tx, _) = mpsc::channel(10);
+        let precompiled_policy = build_precompiled_policy(
+            &engine,
+            include_bytes!("../../tests/data/gatekeeper_always_happy_policy.wasm"),
+        );
+        let policy_url = "file:///tmp/happy_policy.wasm".to_string();
+        let precompiled_policies: PrecompiledPolicies =
+            HashMap::from([(policy_url.clone(), Ok(precompiled_policy))]);
+
+        let available_library = "registry://ghcr.io/org/available@sha256:1234".to_string();
+        let missing_library = "registry://ghcr.io/org/missing@sha256:5678".to_string();
+        let rego_libraries: FetchedRegoLibraries = HashMap::from([
+            (
+                available_library.clone(),
+                Ok(RegoLibraryDocument::from_iter([(
+                    "registries".to_string(),
+                    serde_json::json!(["ghcr.io"]),
+                )])),
+            ),
+            (
+                missing_library.clone(),
+                Err(anyhow::anyhow!(
+                    "cannot fetch Rego library {missing_library}"
+                )),
+            ),
+        ]);
+
+        let policy = |library: Option<&str>| PolicyOrPolicyGroup::Policy {
+            module: policy_url.clone(),
+            policy_mode: PolicyMode::Protect,
+            failure_policy: FailurePolicy::Fail,
+            timeout_seconds: None,
+            allowed_to_mutate: None,
+            settings: None,
+            namespace_settings: HashMap::new(),
+            context_aware_resources: BTreeSet::new(),
+            service_account: None,
+            message: None,
+            entrypoint: None,
+            rego_libraries: library
+                .map(|url| BTreeMap::from([("lib.images".to_string(), url.to_string())]))
+                .unwrap_or_default(),
+            match_conditions: Vec::new(),
+            secret_data: None,
+            http_send: None,
+            key_value_store: false,
+            preopened_dirs: BTreeMap::new(),
+        };
+        let policies = HashMap::from([
+            ("no_libraries".to_string(), policy(None)),
+            (
+                "missing_library".to_string(),
+                policy(Some(&missing_library)),
+            ),
+            (
+                "gatekeeper_with_library".to_string(),
+                policy(Some(&available_library)),
+            ),
+        ]);
+
+        let evaluation_environment =
+            EvaluationEnvironmentBuilder::new(&engine, &precompiled_policies, callback_handler_tx)
+                .with_continue_on_errors(true)
+                .with_rego_libraries(rego_libraries)
+                .build_evaluation_environment(&policies)
+                .unwrap();
+
+        let error = |name: &str| {
+            evaluation_environment
+                .policy_initialization_errors
+                .get(&PolicyID::Policy(name.to_string()))
+                .cloned()
+        };
+        assert_eq!(error("no_libraries"), None);
+        let missing_library_error = error("missing_library").unwrap();
+        assert!(
+            missing_library_error.contains("cannot fetch Rego library"),
+            "unexpected error: {missing_library_error}"
+        );
+        let gatekeeper_error = error("gatekeeper_with_library").unwrap();
+        assert!(
+            gatekeeper_error.contains("Rego libraries can be linked only to OPA policies"),
+            "unexpected error: {gatekeeper_error}"
+        );
+    }
+
+    #[test]
+    fn policy_timeout_overrides() {
+        let mut wasmtime_config = wasmtime::Config::new();
+        wasmtime_config.epoch_interruption(true);
+        let engine = wasmtime::Engine::new(&wasmtime_config).unwrap();
+        let (callback_handler_tx, _) = mpsc::channel(10);
+        let precompiled_policy = build_precompiled_policy(
+            &engine,
+            include_bytes!("../../tests/data/gatekeeper_always_happy_policy.wasm"),
+        );
+        let policy_url = "file:///tmp/happy_policy.wasm".to_string();
+        let precompiled_policies: PrecompiledPolicies =
+            HashMap::from([(policy_url.clone(), Ok(precompiled_policy))]);
+
+        let policy = |timeout_seconds: Option<u64>| PolicyOrPolicyGroup::Policy {
+            module: policy_url.clone(),
+            policy_mode: PolicyMode::Protect,
+            failure_policy: FailurePolicy::Fail,
+            timeout_seconds,
+            allowed_to_mutate: None,
+            settings: None,
+            namespace_settings: HashMap::new(),
+            context_aware_resources: BTreeSet::new(),
+            service_account: None,
+            message: None,
+            entrypoint: None,
+            rego_libraries: BTreeMap::new(),
+            match_conditions: Vec::new(),
+            secret_data: None,
+            http_send: None,
+            key_value_store: false,
+            preopened_dirs: BTreeMap::new(),
+        };
+        let policies = HashMap::from([
+            ("global_timeout".to_string(), policy(None)),
+            ("long_timeout".to_string(), policy(Some(10))),
+            ("another_long_timeout".to_string(), policy(Some(10))),
+        ]);
+        let global_deadlines = EpochDeadlines {
+            evaluation: 2,
+            settings_validation: 1,
+        };
+
+        let evaluation_environment =
+            EvaluationEnvironmentBuilder::new(&engine, &precompiled_policies, callback_handler_tx)
+                .with_timeout_protection(EpochTicker::new(Duration::from_secs(1)), global_deadlines)
+                .build_evaluation_environment(&policies)
+                .unwrap();
+
+        // the policies sharing the same timeout share the same `PolicyEvaluatorPre`
+        assert_eq!(
+            evaluation_environment
+                .module_digest_to_policy_evaluator_pre
+                .len(),
+            2
+        );
+        assert_eq!(
+            evaluation_environment.policy_epoch_deadlines(None),
+            Some(global_deadlines)
+        );
+        assert_eq!(
+            evaluation_environment.policy_epoch_deadlines(Some(10)),
+            Some(EpochDeadlines {
+                evaluation: 10,
+                settings_validation: 1,
+            })
+        );
+    }
+
+    #[test]
+    fn stable_policy_ids() {
+        let evaluation_environment = build_evaluation_environment();
+
+        let happy_1 = evaluation_environment
+            .get_policy_stable_id(&PolicyID::Policy("happy_policy_1".to_string()))
+            .expect("should have a stable ID");
+        let happy_2 = evaluation_environment
+            .get_policy_stable_id(&PolicyID::Policy("happy_policy_2".to_string()))
+            .expect("should have a stable ID");
+        let unhappy_1 = evaluation_environment
+            .get_policy_stable_id(&PolicyID::Policy("unhappy_policy_1".to_string()))
+            .expect("should have a stable ID");
+
+        let (name, digest) = happy_1.split_once('@').unwrap();
+        assert_eq!(name, "happy_policy_1");
+        assert_eq!(digest.len(), STABLE_ID_DIGEST_LENGTH);
+        // same module, same digest
+        assert_eq!(happy_2, format!("happy_policy_2@{digest}"));
+        assert_ne!(unhappy_1, format!("unhappy_policy_1@{digest}"));
+
+        // the evaluators report the stable ID inside of their metrics
+        let (_, eval_ctx) = evaluation_environment
+            .policy_evaluator_pre_and_context(&PolicyID::Policy("happy_policy_1".to_string()))
+            .unwrap();
+        assert_eq!(eval_ctx.policy_stable_id, Some(happy_1.clone()));
+
+        let group_id =
+            PolicyID::Policy("group_policy_with_unhappy_or_happy_or_unhappy".to_string());
+        let group_stable_id = evaluation_environment
+            .get_policy_stable_id(&group_id)
+            .expect("policy groups should have a stable ID");
+        assert!(group_stable_id.starts_with("group_policy_with_unhappy_or_happy_or_unhappy@"));
+
+        // the stable ID is computed at bootstrap, it does not change over time
+        assert_eq!(
+            build_evaluation_environment().get_policy_stable_id(&group_id),
+            Some(group_stable_id)
+        );
+    }
+
+    #[test]
+    fn policies_catalog() {
+        let mut evaluation_environment = build_evaluation_environment();
+        evaluation_environment
+            .policy_initialization_errors
+            .insert(PolicyID::Policy("broken".to_string()), "error".to_string());
+
+        let catalog = evaluation_environment.policies_catalog();
+
+        let ids: Vec<&str> = catalog.iter().map(|entry| entry.id.as_str()).collect();
+        let mut sorted_ids = ids.clone();
+        sorted_ids.sort();
+        assert_eq!(ids, sorted_ids);
+
+        let broken = catalog.iter().find(|entry| entry.id == "broken").unwrap();
+        assert_eq!(
+            broken,
+            &PolicyCatalogEntry {
+                id: "broken".to_string(),
+                stable_id: None,
+                module_digest: None,
+                policy_group: false,
+                initialization_error: Some("error".to_string()),
+                unsupported_capabilities: Vec::new(),
+                state: PolicyState::Failed,
+            }
+        );
+
+        let happy = catalog
+            .iter()
+            .find(|entry| entry.id == "happy_policy_1")
+            .unwrap();
+        assert!(!happy.policy_group);
+        assert!(happy.module_digest.is_some());
+        assert!(happy.stable_id.is_some());
+        assert_eq!(happy.state, PolicyState::Compiled);
+
+        let group = catalog
+            .iter()
+            .find(|entry| entry.id == "group_policy_valid_expression_just_rhai")
+            .unwrap();
+        assert!(group.policy_group);
+        assert!(group.module_digest.is_none());
+        assert_eq!(group.state, PolicyState::Compiled);
+    }
+
+    #[test]
+    fn instantiated_policies_are_warm() {
+        let evaluation_environment = build_evaluation_environment();
+        let happy = PolicyID::Policy("happy_policy_1".to_string());
+        let group = PolicyID::Policy("group_policy_valid_expression_just_rhai".to_string());
+
+        for policy_id in [&happy, &group] {
+            evaluation_environment
+                .instantiate(policy_id)
+                .expect("should instantiate the policy");
+            assert_eq!(
+                evaluation_environment.policy_state(policy_id),
+                PolicyState::Warm
+            );
+        }
+        assert!(evaluation_environment
+            .kubernetes_resources_accessed_by(&happy)
+            .is_empty());
+
+        assert!(matches!(
+            evaluation_environment.instantiate(&PolicyID::Policy("unknown".to_string())),
+            Err(EvaluationError::PolicyNotFound(_))
+        ));
+    }
+
+    #[test]
+    fn policy_state_transitions() {
+        let evaluation_environment = build_lazy_evaluation_environment();
+        let unhappy_1 = PolicyID::Policy("unhappy_policy_1".to_string());
+        let unhappy_2 = PolicyID::Policy("unhappy_policy_2".to_string());
+        let not_annotated = PolicyID::Policy("not_annotated".to_string());
+        let validate_request =
+            ValidateRequest::AdmissionRequest(Box::new(build_admission_review_request().request));
+
+        assert_eq!(
+            evaluation_environment.policy_state(&unhappy_1),
+            PolicyState::Cold
+        );
+
+        evaluation_environment.warm_up(&unhappy_1).unwrap();
+        assert_eq!(
+            evaluation_environment.policy_state(&unhappy_1),
+            PolicyState::Compiled
+        );
+
+        evaluation_environment
+            .validate(&unhappy_1, &validate_request)
+            .unwrap();
+        assert_eq!(
+            evaluation_environment.policy_state(&unhappy_1),
+            PolicyState::Warm
+        );
+        // the module is shared, but the other policy has not been evaluated yet
+        assert_eq!(
+            evaluation_environment.policy_state(&unhappy_2),
+            PolicyState::Compiled
+        );
+
+        assert!(evaluation_environment
+            .validate(&not_annotated, &validate_request)
+            .is_err());
+        assert_eq!(
+            evaluation_environment.policy_state(&not_annotated),
+            PolicyState::Failed
+        );
+    }
+
+    #[test]
+    fn context_aware_policies_wait_for_kubernetes_sync() {
+        let engine = wasmtime::Engine::default();
+        let (callback_handler_tx, _) = mpsc::channel(10);
+        let data_dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/data");
+        let module = "gatekeeper_always_happy_policy.wasm";
+        let policy_url = format!("file:///tmp/{module}");
+
+        let namespaces = ContextAwareResource {
+            api_version: "v1".to_string(),
+            kind: "Namespace".to_string(),
+        };
+        let services = ContextAwareResource {
+            api_version: "v1".to_string(),
+            kind: "Service".to_string(),
+        };
+        let mut policies: HashMap<String, PolicyOrPolicyGroup> = HashMap::new();
+        for (policy_id, context_aware_resources) in [
+            (
+                "context_aware",
+                BTreeSet::from([namespaces.clone(), services.clone()]),
+            ),
+            ("not_context_aware", BTreeSet::new()),
+        ] {
+            policies.insert(
+                policy_id.to_string(),
+                PolicyOrPolicyGroup::Policy {
+                    module: policy_url.clone(),
+                    policy_mode: PolicyMode::Protect,
+                    failure_policy: FailurePolicy::Fail,
+                    timeout_seconds: None,
+                    allowed_to_mutate: None,
+                    settings: None,
+                    namespace_settings: HashMap::new(),
+                    context_aware_resources,
+                    service_account: None,
+                    message: None,
+                    entrypoint: None,
+                    rego_libraries: BTreeMap::new(),
+                    match_conditions: Vec::new(),
+                    secret_data: None,
+                    http_send: None,
+                    key_value_store: false,
+                    preopened_dirs: BTreeMap::new(),
+                },
+            );
+        }
+
+        let precompiled_policies = PrecompiledPolicies::new();
+        let evaluation_environment =
+            EvaluationEnvironmentBuilder::new(&engine, &precompiled_policies, callback_handler_tx)
+                .with_lazy_policies(HashMap::from([(policy_url, data_dir.join(module))]))
+                .with_kubernetes_sync_readiness(true)
+                .build_evaluation_environment(&policies)
+                .unwrap();
+        let context_aware = PolicyID::Policy("context_aware".to_string());
+        let not_context_aware = PolicyID::Policy("not_context_aware".to_string());
+
+        assert_eq!(
+            evaluation_environment.kubernetes_resources_pending_sync(),
+            BTreeSet::from([namespaces.clone(), services.clone()])
+        );
+        assert_eq!(
+            evaluation_environment.policy_state(&context_aware),
+            PolicyState::Syncing
+        );
+        assert_eq!(
+            evaluation_environment.policy_state(&not_context_aware),
+            PolicyState::Cold
+        );
+
+        evaluation_environment.mark_kubernetes_resource_as_synced(&namespaces);
+        assert_eq!(
+            evaluation_environment.policy_state(&context_aware),
+            PolicyState::Syncing
+        );
+
+        evaluation_environment.mark_kubernetes_resource_as_synced(&services);
+        assert!(evaluation_environment
+            .kubernetes_resources_pending_sync()
+            .is_empty());
+        assert_eq!(
+            evaluation_environment.policy_state(&context_aware),
+            PolicyState::Cold
+        );
+    }
+
+    /// Build an environment where all the policies are loaded lazily. The `not_annotated`
+    /// policy cannot be compiled because it lacks the metadata required by Policy Server.
+    fn build_lazy_evaluation_environment() -> EvaluationEnvironment {
+        let engine = wasmtime::Engine::default();
+        let (callback_handler_tx, _) = mpsc::channel(10);
+        let data_dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/data");
+
+        let modules = [
+            ("unhappy_policy_1", "gatekeeper_always_unhappy_policy.wasm"),
+            ("unhappy_policy_2", "gatekeeper_always_unhappy_policy.wasm"),
+            ("not_annotated", "gatekeeper_always_happy_policy.wasm"),
+        ];
+        let mut policies: HashMap<String, PolicyOrPolicyGroup> = HashMap::new();
+        let mut lazy_policies: HashMap<String, PathBuf> = HashMap::new();
+        for (policy_id, module) in modules {
+            let policy_url = format!("file:///tmp/{module}");
+            policies.insert(
+                policy_id.to_string(),
+                PolicyOrPolicyGroup::Policy {
+                    module: policy_url.clone(),
+                    policy_mode: PolicyMode::Protect,
+                    failure_policy: FailurePolicy::Fail,
+                    timeout_seconds: None,
+                    allowed_to_mutate: None,
+                    settings: None,
+                    namespace_settings: HashMap::new(),
+                    context_aware_resources: BTreeSet::new(),
+                    service_account: None,
+                    message: None,
+                    entrypoint: None,
+                    rego_libraries: BTreeMap::new(),
+                    match_conditions: Vec::new(),
+                    secret_data: None,
+                    http_send: None,
+                    key_value_store: false,
+                    preopened_dirs: BTreeMap::new(),
+                },
+            );
+            lazy_policies.insert(policy_url, data_dir.join(module));
+        }
+
+        let precompiled_policies = PrecompiledPolicies::new();
+        EvaluationEnvironmentBuilder::new(&engine, &precompiled_policies, callback_handler_tx)
+            .with_lazy_policies(lazy_policies)
+            .build_evaluation_environment(&policies)
+            .unwrap()
+    }
+
+    #[test]
+    fn lazy_policies_are_compiled_on_first_use() {
+        let evaluation_environment = build_lazy_evaluation_environment();
+        let unhappy_1 = PolicyID::Policy("unhappy_policy_1".to_string());
+        let unhappy_2 = PolicyID::Policy("unhappy_policy_2".to_string());
+        let not_annotated = PolicyID::Policy("not_annotated".to_string());
+        let validate_request =
+            ValidateRequest::AdmissionRequest(Box::new(build_admission_review_request().request));
+
+        // nothing is compiled at bootstrap time, but modules are still deduplicated
+        assert!(evaluation_environment
+            .module_digest_to_policy_evaluator_pre
+            .is_empty());
+        assert_eq!(evaluation_environment.module_digest_to_lazy_module.len(), 2);
+        assert!(evaluation_environment
+            .module_digest_to_lazy_module
+            .values()
+            .all(|module| module.policy_evaluator_pre.get().is_none()));
+        assert!(evaluation_environment
+            .get_policy_stable_id(&unhappy_1)
+            .is_some());
+
+        let response = evaluation_environment
+            .validate(&unhappy_1, &validate_request)
+            .expect("should compile the policy on demand");
+        assert!(!response.allowed);
+
+        // the module is shared with the other policy using it
+        let module_digest = evaluation_environment
+            .policy_id_to_module_digest
+            .get(&unhappy_2)
+            .unwrap();
+        assert!(
+            evaluation_environment.module_digest_to_lazy_module[module_digest]
+                .policy_evaluator_pre
+                .get()
+                .is_some()
+        );
+
+        // compilation errors are reported once the policy is used
+        assert!(matches!(
+            evaluation_environment.validate(&not_annotated, &validate_request),
+            Err(EvaluationError::PolicyInitialization(_))
+        ));
+        let catalog_entry = evaluation_environment
+            .policies_catalog()
+            .into_iter()
+            .find(|entry| entry.id == "not_annotated")
+            .unwrap();
+        assert!(catalog_entry.initialization_error.is_some());
+    }
+
+    #[test]
+    fn warm_up_lazy_policies() {
+        let evaluation_environment = build_lazy_evaluation_environment();
+        let unhappy_1 = PolicyID::Policy("unhappy_policy_1".to_string());
+
+        evaluation_environment
+            .warm_up(&unhappy_1)
+            .expect("should compile the policy");
+        let module_digest = evaluation_environment
+            .policy_id_to_module_digest
+            .get(&unhappy_1)
+            .unwrap();
+        assert!(
+            evaluation_environment.module_digest_to_lazy_module[module_digest]
+                .policy_evaluator_pre
+                .get()
+                .is_some()
+        );
+
+        assert!(evaluation_environment
+            .warm_up(&PolicyID::Policy("not_annotated".to_string()))
+            .is_err());
+        assert!(matches!(
+            evaluation_environment.warm_up(&PolicyID::Policy("unknown".to_string())),
+            Err(EvaluationError::PolicyNotFound(_))
+        ));
+    }
+
+    #[test]
+    fn evaluation_results_are_cached() {
+        let mut evaluation_environment = build_evaluation_environment();
+        evaluation_environment.evaluation_cache =
+            Some(EvaluationCache::new(EvaluationCacheConfig {
+                max_entries: 10,
+                ..Default::default()
+            }));
+        let policy_id = PolicyID::Policy("unhappy_policy_1".to_string());
+        let mut admission_request = build_admission_review_request().request;
+        let first_request = ValidateRequest::AdmissionRequest(Box::new(admission_request.clone()));
+        admission_request.uid = "second".to_owned();
+        let second_request = ValidateRequest::AdmissionRequest(Box::new(admission_request));
+
+        let first_response = evaluation_environment
+            .validate(&policy_id, &first_request)
+            .unwrap();
+
+        let settings = evaluation_environment
+            .get_policy_settings(&policy_id)
+            .unwrap()
+            .policy_settings_for_request(&second_request)
+            .unwrap()
+            .to_owned();
+        let key = evaluation_environment
+            .evaluation_cache_key(&policy_id, &second_request, &settings)
+            .unwrap();
+        assert!(evaluation_environment
+            .evaluation_cache
+            .as_ref()
+            .unwrap()
+            .get(&policy_id.to_string(), &key, "second")
+            .is_some());
+
+        let second_response = evaluation_environment
+            .validate(&policy_id, &second_request)
+            .unwrap();
+        assert_eq!(second_response.uid, "second");
+        assert_eq!(
+            AdmissionResponse {
+                uid: first_response.uid.clone(),
+                ..second_response
+            },
+            first_response
+        );
+    }
+
+    fn set_match_conditions(
+        evaluation_environment: &mut EvaluationEnvironment,
+        policy_id: &PolicyID,
+        expression: &str,
+    ) {
+        evaluation_environment
+            .policy_id_to_settings
+            .get_mut(policy_id)
+            .unwrap()
+            .match_conditions = MatchConditions::compile(&[crate::config::MatchCondition {
+            name: "condition".to_owned(),
+            expression: expression.to_owned(),
+        }])
+        .unwrap();
+    }
+
+    #[test]
+    fn requests_not_meeting_match_conditions_are_skipped() {
+        let mut evaluation_environment = build_evaluation_environment();
+        let policy_id = PolicyID::Policy("unhappy_policy_1".to_string());
+        let validate_request =
+            ValidateRequest::AdmissionRequest(Box::new(build_admission_review_request().request));
+
+        // the request is an UPDATE one
+        set_match_conditions(
+            &mut evaluation_environment,
+            &policy_id,
+            "request.operation == 'CREATE'",
+        );
+        let response = evaluation_environment
+            .validate(&policy_id, &validate_request)
+            .unwrap();
+        assert_eq!(
+            response,
+            AdmissionResponse {
+                uid: "hello".to_owned(),
+                allowed: true,
+                ..Default::default()
+            }
+        );
+
+        // the policy rejects all the requests it evaluates
+        set_match_conditions(
+            &mut evaluation_environment,
+            &policy_id,
+            "request.operation == 'UPDATE'",
+        );
+        let response = evaluation_environment
+            .validate(&policy_id, &validate_request)
+            .unwrap();
+        assert!(!response.allowed);
+        assert!(!response.is_internal_server_error());
+    }
+
+    #[test]
+    fn match_conditions_runtime_errors_go_through_the_failure_policy() {
+        let mut evaluation_environment = build_evaluation_environment();
+        let policy_id = PolicyID::Policy("happy_policy_1".to_string());
+        let validate_request =
+            ValidateRequest::AdmissionRequest(Box::new(build_admission_review_request().request));
+
+        // the object of the request has no `spec`
+        set_match_conditions(
+            &mut evaluation_environment,
+            &policy_id,
+            "object.spec.replicas > 1",
+        );
+        let response = evaluation_environment
+            .validate(&policy_id, &validate_request)
+            .unwrap();
+
+        // internal server errors are accepted or rejected according to the failure policy
+        assert!(response.is_internal_server_error());
+        assert!(!response.allowed);
+        assert!(response
+            .status
+            .and_then(|status| status.message)
+            .unwrap()
+            .contains("cannot evaluate matchConditions"));
+        assert_eq!(
+            evaluation_environment
+                .get_policy_failure_policy(&policy_id)
+                .unwrap(),
+            FailurePolicy::Fail
+        );
+    }
+
+    #[test]
+    fn revoked_policies_reject_requests() {
+        let evaluation_environment = Arc::new(build_evaluation_environment());
+        let policy_id = PolicyID::Policy("happy_policy_1".to_string());
+        let validate_request =
+            ValidateRequest::AdmissionRequest(Box::new(build_admission_review_request().request));
+        assert!(evaluation_environment
+            .validate(&policy_id, &validate_request)
+            .is_ok());
+
+        evaluation_environment.revoke_policy(&policy_id, "signature not trusted");
+
+        assert!(matches!(
+            evaluation_environment.validate(&policy_id, &validate_request).unwrap_err(),
+            EvaluationError::PolicyInitialization(error)
+                if error == "policy failed re-verification: signature not trusted"
+        ));
+        let catalog_entry = evaluation_environment
+            .policies_catalog()
+            .into_iter()
+            .find(|entry| entry.id == "happy_policy_1")
+            .unwrap();
+        assert_eq!(catalog_entry.state, PolicyState::Failed);
+        assert!(catalog_entry.initialization_error.is_some());
+
+        // a later successful re-verification restores the policy
+        assert!(evaluation_environment.restore_policy(&policy_id));
+        assert!(!evaluation_environment.restore_policy(&policy_id));
+        assert!(evaluation_environment
+            .validate(&policy_id, &validate_request)
+            .is_ok());
+    }
+
     #[test]
     fn validate_policy_with_initialization_error() {
         let mut evaluation_environment = build_evaluation_environment();
@@ -1122,7 +3248,7 @@ mod tests {
         // However we ignore these errors because we are only interested in the validation of the
         // expression of the group policy
 
-        let mut evaluation_environment = build_evaluation_environment();
+        let evaluation_environment = build_evaluation_environment();
         let validation_result = evaluation_environment.validate_settings(&policy_id);
 
         assert_eq!(expression_is_valid, validation_result.is_ok());
@@ -0,0 +1,188 @@
+use std::{collections::HashSet, sync::Arc};
+
+use anyhow::{anyhow, Result};
+use cel_interpreter::{Context, Program, Value};
+use policy_evaluator::policy_evaluator::ValidateRequest;
+
+use crate::config::MatchCondition;
+
+/// The `matchConditions` of a policy, compiled once at bootstrap time.
+///
+/// They work like the ones of the Kubernetes admission webhooks: the policy
+/// evaluates an admission request only when all the CEL expressions evaluate
+/// to `true`. The expressions can access the `object`, `oldObject` and `request`
+/// variables. Raw requests and CloudEvents are always evaluated.
+#[derive(Clone, Default)]
+pub(crate) struct MatchConditions(Vec<CompiledMatchCondition>);
+
+#[derive(Clone)]
+struct CompiledMatchCondition {
+    name: String,
+    program: Arc<Program>,
+}
+
+impl MatchConditions {
+    /// Compile the CEL expressions of the given conditions. Each condition must
+    /// have a unique, non empty, name
+    pub(crate) fn compile(conditions: &[MatchCondition]) -> Result<Self> {
+        let mut names = HashSet::new();
+        let conditions = conditions
+            .iter()
+            .map(|condition| {
+                if condition.name.is_empty() {
+                    return Err(anyhow!("matchConditions must have a name"));
+                }
+                if !names.insert(condition.name.as_str()) {
+                    return Err(anyhow!(
+                        "matchConditions names must be unique, '{}' is repeated",
+                        condition.name
+                    ));
+                }
+                let program = Program::compile(&condition.expression).map_err(|e| {
+                    anyhow!(
+                        "cannot compile the expression of matchCondition '{}': {}",
+                        condition.name,
+                        e
+                    )
+                })?;
+
+                Ok(CompiledMatchCondition {
+                    name: condition.name.clone(),
+                    program: Arc::new(program),
+                })
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        Ok(MatchConditions(conditions))
+    }
+
+    /// Whether the policy has to evaluate the given request.
+    ///
+    /// The request is skipped as soon as one of the conditions evaluates to `false`,
+    /// even when the other ones fail. An error is returned when none of the conditions
+    /// evaluates to `false` but some of them cannot be evaluated.
+    pub(crate) fn matches(&self, req: &ValidateRequest) -> Result<bool> {
+        let adm_req = match req {
+            ValidateRequest::AdmissionRequest(adm_req) if !self.0.is_empty() => adm_req,
+            _ => return Ok(true),
+        };
+
+        let mut context = Context::default();
+        context.add_variable("object", adm_req.object.as_ref().map(|object| &object.0))?;
+        context.add_variable(
+            "oldObject",
+            adm_req.old_object.as_ref().map(|object| &object.0),
+        )?;
+        context.add_variable("request", adm_req.as_ref())?;
+
+        let mut errors = Vec::new();
+        for condition in &self.0 {
+            match condition.program.execute(&context) {
+                Ok(Value::Bool(true)) => {}
+                Ok(Value::Bool(false)) => return Ok(false),
+                Ok(value) => errors.push(format!(
+                    "'{}' must evaluate to a boolean, got {:?}",
+                    condition.name, value
+                )),
+                Err(e) => errors.push(format!("'{}': {}", condition.name, e)),
+            }
+        }
+
+        if errors.is_empty() {
+            Ok(true)
+        } else {
+            Err(anyhow!(
+                "cannot evaluate matchConditions: {}",
+                errors.join(", ")
+            ))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use policy_evaluator::admission_request::AdmissionRequest;
+    use rstest::rstest;
+    use serde_json::json;
+
+    fn conditions(expressions: &[&str]) -> Vec<MatchCondition> {
+        expressions
+            .iter()
+            .enumerate()
+            .map(|(index, expression)| MatchCondition {
+                name: format!("condition-{index}"),
+                expression: expression.to_string(),
+            })
+            .collect()
+    }
+
+    fn request() -> ValidateRequest {
+        let adm_req: AdmissionRequest = serde_json::from_value(json!({
+            "uid": "hello",
+            "kind": {"group": "", "version": "v1", "kind": "Pod"},
+            "resource": {"group": "", "version": "v1", "resource": "pods"},
+            "namespace": "default",
+            "operation": "CREATE",
+            "userInfo": {"username": "alice", "groups": ["developers"]},
+            "object": {
+                "metadata": {"name": "nginx", "labels": {"app": "web"}},
+            },
+        }))
+        .expect("cannot build the admission request");
+
+        ValidateRequest::AdmissionRequest(Box::new(adm_req))
+    }
+
+    #[rstest]
+    #[case::no_conditions(&[], true)]
+    #[case::request(&["request.operation == 'CREATE'"], true)]
+    #[case::object(&["object.metadata.labels.app == 'web'"], true)]
+    #[case::no_old_object(&["oldObject == null"], true)]
+    #[case::all_true(&["request.namespace == 'default'", "'developers' in request.userInfo.groups"], true)]
+    #[case::one_false(&["request.namespace == 'default'", "request.userInfo.username == 'bob'"], false)]
+    #[case::false_wins_over_errors(&["object.spec.containers.size() > 0", "false"], false)]
+    fn evaluate_match_conditions(#[case] expressions: &[&str], #[case] expected: bool) {
+        let match_conditions =
+            MatchConditions::compile(&conditions(expressions)).expect("cannot compile conditions");
+
+        assert_eq!(
+            match_conditions
+                .matches(&request())
+                .expect("cannot evaluate conditions"),
+            expected
+        );
+    }
+
+    #[rstest]
+    #[case::missing_field(&["object.spec.containers.size() > 0"])]
+    #[case::not_a_boolean(&["request.operation"])]
+    fn evaluate_invalid_match_conditions(#[case] expressions: &[&str]) {
+        let match_conditions =
+            MatchConditions::compile(&conditions(expressions)).expect("cannot compile conditions");
+
+        assert!(match_conditions.matches(&request()).is_err());
+    }
+
+    #[test]
+    fn raw_requests_are_always_evaluated() {
+        let match_conditions =
+            MatchConditions::compile(&conditions(&["false"])).expect("cannot compile conditions");
+
+        assert!(match_conditions
+            .matches(&ValidateRequest::Raw(json!({"hello": "world"})))
+            .expect("cannot evaluate conditions"));
+    }
+
+    #[rstest]
+    #[case::invalid_expression(conditions(&["request.operation =="]))]
+    #[case::empty_name(vec![MatchCondition { name: String::new(), expression: "true".to_owned() }])]
+    #[case::duplicated_name(vec![
+        MatchCondition { name: "same".to_owned(), expression: "true".to_owned() },
+        MatchCondition { name: "same".to_owned(), expression: "false".to_owned() },
+    ])]
+    fn compile_invalid_match_conditions(#[case] conditions: Vec<MatchCondition>) {
+        assert!(MatchConditions::compile(&conditions).is_err());
+    }
+}
@@ -0,0 +1,536 @@
+//! Persistent cache of the compiled Wasm modules.
+//!
+//! Compiling the Wasm modules is the most expensive operation done at bootstrap.
+//! The compiled modules are stored inside of a directory, which can be a volume
+//! shared by all the replicas of Policy Server: the restarts, and the other
+//! replicas, load the compiled modules from there instead of compiling them again.
+//!
+//! The entries are keyed by the digest of the Wasm module and by the fingerprint
+//! of the engine that compiled it. Loading a compiled module means running native
+//! code produced outside of this process, hence each entry is authenticated with
+//! a HMAC computed with a key known only by the replicas. The entries that cannot
+//! be authenticated are refused, and the module is compiled again.
+//!
+//! When peers are configured, a replica that starts fetches the entries it misses
+//! from the replicas already running, before compiling the modules. The entries
+//! are served as they are stored, the replica fetching them authenticates them
+//! with the HMAC, like the entries read from the directory. The requests carry
+//! a token derived from the same key, the replicas don't serve the entries to
+//! whoever doesn't know it.
+
+use std::{
+    collections::HashSet,
+    fs,
+    net::SocketAddr,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use anyhow::{anyhow, Result};
+use base64::{engine::general_purpose, Engine as _};
+use hmac::{Hmac, Mac};
+use policy_evaluator::wasmtime;
+use sha2::{Digest, Sha256};
+use tracing::{debug, info, warn};
+
+use crate::{
+    config::ModuleCacheConfig, policy_downloader::FetchedPolicies, state::engine_fingerprint,
+};
+
+type HmacSha256 = Hmac<Sha256>;
+
+/// Size of the HMAC stored at the beginning of each entry
+const TAG_SIZE: usize = 32;
+
+/// Path of the endpoint serving the entries of the cache to the peers
+pub(crate) const PEER_ENDPOINT_PATH: &str = "/module-cache/{engine}/{module_digest}";
+
+/// Maximum time spent fetching an entry from a peer
+const PEER_REQUEST_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Reasons why an entry is not served to a peer
+#[derive(Debug, PartialEq, Eq)]
+pub(crate) enum PeerEntryError {
+    /// The token of the request doesn't match the requested entry
+    Unauthorized,
+    /// The entry is not inside of the cache
+    NotFound,
+}
+
+/// Cache of the compiled Wasm modules, see the module documentation
+pub(crate) struct ModuleCache {
+    config: ModuleCacheConfig,
+}
+
+impl ModuleCache {
+    pub(crate) fn new(config: ModuleCacheConfig) -> Self {
+        ModuleCache { config }
+    }
+
+    /// Whether the entries are replicated between the peers
+    pub(crate) fn has_peers(&self) -> bool {
+        !self.config.peers.is_empty()
+    }
+
+    /// Compile the given Wasm module, unless it's already inside of the cache
+    pub(crate) fn precompile_module(
+        &self,
+        engine: &wasmtime::Engine,
+        policy_contents: &[u8],
+    ) -> Result<Vec<u8>> {
+        let engine_fingerprint = engine_fingerprint(engine);
+        let module_digest = format!("{:x}", Sha256::digest(policy_contents));
+        let path = self.entry_path(&engine_fingerprint, &module_digest);
+
+        if let Some(precompiled_module) = self.load(&path, &engine_fingerprint, &module_digest) {
+            debug!(module_digest, "compiled module loaded from the cache");
+            return Ok(precompiled_module);
+        }
+
+        let precompiled_module = engine.precompile_module(policy_contents)?;
+        if let Err(error) = self.store(
+            &path,
+            &engine_fingerprint,
+            &module_digest,
+            &precompiled_module,
+        ) {
+            warn!(module_digest, %error, "cannot store the compiled module inside of the cache");
+        }
+
+        Ok(precompiled_module)
+    }
+
+    /// Fetch from the peers the compiled modules of the given policies that are not
+    /// inside of the cache yet. The peers that cannot be reached, and the entries that
+    /// cannot be authenticated, are skipped: the modules are compiled as usual
+    pub(crate) async fn replicate_from_peers(
+        &self,
+        engine: &wasmtime::Engine,
+        fetched_policies: &FetchedPolicies,
+    ) {
+        if self.config.peers.is_empty() {
+            return;
+        }
+
+        let engine_fingerprint = engine_fingerprint(engine);
+        let module_digests: HashSet<String> = fetched_policies
+            .values()
+            .filter_map(|fetched_policy| fetched_policy.as_ref().ok())
+            .filter_map(|path| fs::read(path).ok())
+            .map(|policy_contents| format!("{:x}", Sha256::digest(policy_contents)))
+            .filter(|module_digest| !self.entry_path(&engine_fingerprint, module_digest).exists())
+            .collect();
+        if module_digests.is_empty() {
+            return;
+        }
+
+        let peers = self.resolve_peers().await;
+        let replicated =
+            futures::future::join_all(module_digests.iter().map(|module_digest| {
+                self.replicate_entry(&peers, &engine_fingerprint, module_digest)
+            }))
+            .await
+            .into_iter()
+            .filter(|replicated| *replicated)
+            .count();
+
+        info!(
+            replicated,
+            missing = module_digests.len(),
+            peers = peers.len(),
+            "compiled modules fetched from the peers"
+        );
+    }
+
+    /// Build a HTTP client for each one of the addresses the peers resolve to, this
+    /// way all the replicas behind a headless service are reached.
+    ///
+    /// The certificates of the peers are not verified: the entries are authenticated
+    /// with the HMAC, and the requests with the token
+    async fn resolve_peers(&self) -> Vec<(reqwest::Url, reqwest::Client)> {
+        let mut peers = Vec::new();
+        for peer in &self.config.peers {
+            let (Some(host), Some(port)) = (peer.host_str(), peer.port_or_known_default()) else {
+                warn!(%peer, "invalid module cache peer");
+                continue;
+            };
+            let addrs: Vec<SocketAddr> = match tokio::net::lookup_host((host, port)).await {
+                Ok(addrs) => addrs.collect(),
+                Err(error) => {
+                    warn!(%peer, %error, "cannot resolve module cache peer");
+                    continue;
+                }
+            };
+            for addr in addrs {
+                let client = reqwest::Client::builder()
+                    .resolve(host, addr)
+                    .danger_accept_invalid_certs(true)
+                    .timeout(PEER_REQUEST_TIMEOUT)
+                    .build();
+                match client {
+                    Ok(client) => peers.push((peer.clone(), client)),
+                    Err(error) => warn!(%peer, %addr, %error, "cannot create HTTP client"),
+                }
+            }
+        }
+        peers
+    }
+
+    /// Fetch the entry from the first peer having it. Returns whether the entry has
+    /// been stored inside of the cache
+    async fn replicate_entry(
+        &self,
+        peers: &[(reqwest::Url, reqwest::Client)],
+        engine: &str,
+        module_digest: &str,
+    ) -> bool {
+        let path = self.entry_path(engine, module_digest);
+        let token = self.peer_token(engine, module_digest);
+
+        for (peer, client) in peers {
+            let Ok(url) = peer.join(&format!("module-cache/{engine}/{module_digest}")) else {
+                continue;
+            };
+            let response = client.get(url).bearer_auth(&token).send().await;
+            let entry = match response {
+                Ok(response) if response.status().is_success() => response.bytes().await,
+                Ok(response) => {
+                    debug!(%peer, module_digest, status = %response.status(), "module not served by peer");
+                    continue;
+                }
+                Err(error) => {
+                    debug!(%peer, module_digest, %error, "cannot reach module cache peer");
+                    continue;
+                }
+            };
+            let precompiled_module = match entry
+                .map_err(|e| anyhow!(e))
+                .and_then(|entry| self.authenticate(entry.to_vec(), engine, module_digest))
+            {
+                Ok(precompiled_module) => precompiled_module,
+                Err(error) => {
+                    warn!(%peer, module_digest, %error, "refusing module cache entry served by peer");
+                    continue;
+                }
+            };
+
+            match self.store(&path, engine, module_digest, &precompiled_module) {
+                Ok(()) => {
+                    debug!(%peer, module_digest, "compiled module fetched from peer");
+                    return true;
+                }
+                Err(error) => {
+                    warn!(module_digest, %error, "cannot store the compiled module inside of the cache");
+                    return false;
+                }
+            }
+        }
+
+        false
+    }
+
+    /// The entry requested by a peer, as it is stored
+    pub(crate) fn peer_entry(
+        &self,
+        engine: &str,
+        module_digest: &str,
+        token: &str,
+    ) -> std::result::Result<Vec<u8>, PeerEntryError> {
+        let token = general_purpose::URL_SAFE_NO_PAD
+            .decode(token)
+            .map_err(|_| PeerEntryError::Unauthorized)?;
+        self.peer_mac(engine, module_digest)
+            .verify_slice(&token)
+            .map_err(|_| PeerEntryError::Unauthorized)?;
+
+        let is_valid =
+            |value: &str| !value.is_empty() && value.chars().all(|c| c.is_ascii_alphanumeric());
+        if !is_valid(engine) || !is_valid(module_digest) {
+            return Err(PeerEntryError::NotFound);
+        }
+
+        fs::read(self.entry_path(engine, module_digest)).map_err(|_| PeerEntryError::NotFound)
+    }
+
+    /// Token sent by the peers requesting an entry, it proves they know the key
+    fn peer_token(&self, engine: &str, module_digest: &str) -> String {
+        general_purpose::URL_SAFE_NO_PAD
+            .encode(self.peer_mac(engine, module_digest).finalize().into_bytes())
+    }
+
+    fn peer_mac(&self, engine: &str, module_digest: &str) -> HmacSha256 {
+        let mut mac =
+            HmacSha256::new_from_slice(&self.config.key).expect("HMAC accepts keys of any size");
+        mac.update(b"peer\n");
+        mac.update(engine.as_bytes());
+        mac.update(b"\n");
+        mac.update(module_digest.as_bytes());
+        mac
+    }
+
+    fn entry_path(&self, engine: &str, module_digest: &str) -> PathBuf {
+        self.config
+            .dir
+            .join(engine)
+            .join(format!("{module_digest}.cwasm"))
+    }
+
+    /// Load the compiled module stored inside of the entry, provided the entry is
+    /// authentic
+    fn load(&self, path: &Path, engine: &str, module_digest: &str) -> Option<Vec<u8>> {
+        let entry = fs::read(path).ok()?;
+        self.authenticate(entry, engine, module_digest)
+            .inspect_err(
+                |error| warn!(path = %path.display(), %error, "refusing entry of the module cache"),
+            )
+            .ok()
+    }
+
+    /// Return the compiled module stored inside of the entry, provided the entry
+    /// is authentic
+    fn authenticate(
+        &self,
+        mut entry: Vec<u8>,
+        engine: &str,
+        module_digest: &str,
+    ) -> Result<Vec<u8>> {
+        if entry.len() < TAG_SIZE {
+            return Err(anyhow!("the entry is truncated"));
+        }
+
+        let precompiled_module = entry.split_off(TAG_SIZE);
+        self.mac(engine, module_digest, &precompiled_module)
+            .verify_slice(&entry)
+            .map_err(|_| anyhow!("the entry cannot be authenticated"))?;
+
+        Ok(precompiled_module)
+    }
+
+    /// Store the compiled module. The entry is written to a temporary file first, this
+    /// way the other replicas never read a partially written entry
+    fn store(
+        &self,
+        path: &Path,
+        engine: &str,
+        module_digest: &str,
+        precompiled_module: &[u8],
+    ) -> Result<()> {
+        let dir = path
+            .parent()
+            .ok_or_else(|| anyhow!("invalid module cache entry {}", path.display()))?;
+        fs::create_dir_all(dir)?;
+
+        let tag = self
+            .mac(engine, module_digest, precompiled_module)
+            .finalize()
+            .into_bytes();
+        let tmp_path = dir.join(format!(
+            ".{module_digest}.{}.{}.tmp",
+            crate::config::HOSTNAME.as_str(),
+            std::process::id()
+        ));
+        fs::write(&tmp_path, [tag.as_slice(), precompiled_module].concat())?;
+        fs::rename(&tmp_path, path).inspect_err(|_| {
+            let _ = fs::remove_file(&tmp_path);
+        })?;
+
+        Ok(())
+    }
+
+    /// The HMAC of an entry covers the engine and the Wasm module it has been
+    /// compiled from, this way an entry cannot be moved under another key
+    fn mac(&self, engine: &str, module_digest: &str, precompiled_module: &[u8]) -> HmacSha256 {
+        let mut mac =
+            HmacSha256::new_from_slice(&self.config.key).expect("HMAC accepts keys of any size");
+        mac.update(engine.as_bytes());
+        mac.update(b"\n");
+        mac.update(module_digest.as_bytes());
+        mac.update(b"\n");
+        mac.update(precompiled_module);
+        mac
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+
+    fn policy_contents() -> Vec<u8> {
+        fs::read(
+            Path::new(env!("CARGO_MANIFEST_DIR"))
+                .join("tests/data/gatekeeper_always_happy_policy.wasm"),
+        )
+        .unwrap()
+    }
+
+    fn module_cache(dir: &Path, key: &[u8]) -> ModuleCache {
+        ModuleCache::new(ModuleCacheConfig {
+            dir: dir.to_owned(),
+            key: key.to_vec(),
+            peers: Vec::new(),
+        })
+    }
+
+    #[test]
+    fn compiled_modules_are_reused() {
+        let engine = wasmtime::Engine::default();
+        let dir = tempfile::tempdir().unwrap();
+        let cache = module_cache(dir.path(), b"secret");
+
+        let engine_fp = engine_fingerprint(&engine);
+        let module_digest = format!("{:x}", Sha256::digest(policy_contents()));
+
+        let compiled = cache
+            .precompile_module(&engine, &policy_contents())
+            .unwrap();
+        let path = cache.entry_path(&engine_fp, &module_digest);
+        assert!(path.exists());
+
+        // another replica, sharing the same directory and key
+        let other_replica = module_cache(dir.path(), b"secret");
+        assert_eq!(
+            other_replica.load(&path, &engine_fp, &module_digest),
+            Some(compiled)
+        );
+    }
+
+    #[test]
+    fn hijacked_entries_are_refused() {
+        let engine = wasmtime::Engine::default();
+        let dir = tempfile::tempdir().unwrap();
+        let cache = module_cache(dir.path(), b"secret");
+        let engine_fp = engine_fingerprint(&engine);
+        let module_digest = format!("{:x}", Sha256::digest(policy_contents()));
+
+        cache
+            .precompile_module(&engine, &policy_contents())
+            .unwrap();
+        let path = cache.entry_path(&engine_fp, &module_digest);
+
+        // an entry written with another key
+        let attacker = module_cache(dir.path(), b"guessed");
+        attacker
+            .store(&path, &engine_fp, &module_digest, b"malicious code")
+            .unwrap();
+        assert!(cache.load(&path, &engine_fp, &module_digest).is_none());
+
+        // an entry whose contents have been tampered with
+        let mut tampered = fs::read(&path).unwrap();
+        tampered.truncate(TAG_SIZE);
+        tampered.extend_from_slice(b"malicious code");
+        fs::write(&path, tampered).unwrap();
+        assert!(cache.load(&path, &engine_fp, &module_digest).is_none());
+
+        // the module is compiled again, replacing the entry
+        let compiled = cache
+            .precompile_module(&engine, &policy_contents())
+            .unwrap();
+        assert_eq!(
+            cache.load(&path, &engine_fp, &module_digest),
+            Some(compiled)
+        );
+    }
+
+    #[test]
+    fn peer_entries_require_a_valid_token() {
+        let engine = wasmtime::Engine::default();
+        let dir = tempfile::tempdir().unwrap();
+        let cache = module_cache(dir.path(), b"secret");
+        let engine_fp = engine_fingerprint(&engine);
+        let module_digest = format!("{:x}", Sha256::digest(policy_contents()));
+
+        cache
+            .precompile_module(&engine, &policy_contents())
+            .unwrap();
+
+        let token = cache.peer_token(&engine_fp, &module_digest);
+        let entry = cache
+            .peer_entry(&engine_fp, &module_digest, &token)
+            .unwrap();
+        assert_eq!(
+            entry,
+            fs::read(cache.entry_path(&engine_fp, &module_digest)).unwrap()
+        );
+
+        // a token derived from another key
+        let attacker = module_cache(dir.path(), b"guessed");
+        let token = attacker.peer_token(&engine_fp, &module_digest);
+        assert_eq!(
+            cache.peer_entry(&engine_fp, &module_digest, &token),
+            Err(PeerEntryError::Unauthorized)
+        );
+
+        // a token issued for another entry
+        let token = cache.peer_token(&engine_fp, "other");
+        assert_eq!(
+            cache.peer_entry(&engine_fp, &module_digest, &token),
+            Err(PeerEntryError::Unauthorized)
+        );
+
+        let token = cache.peer_token(&engine_fp, "missing");
+        assert_eq!(
+            cache.peer_entry(&engine_fp, "missing", &token),
+            Err(PeerEntryError::NotFound)
+        );
+    }
+
+    #[tokio::test]
+    async fn entries_are_replicated_from_peers() {
+        let engine = wasmtime::Engine::default();
+        let policy_dir = tempfile::tempdir().unwrap();
+        let policy_path = policy_dir.path().join("policy.wasm");
+        fs::write(&policy_path, policy_contents()).unwrap();
+        let fetched_policies =
+            FetchedPolicies::from([("file:///policy.wasm".to_owned(), Ok(policy_path))]);
+
+        // the warm replica, serving its entries
+        let warm_dir = tempfile::tempdir().unwrap();
+        let warm_replica = Arc::new(module_cache(warm_dir.path(), b"secret"));
+        let compiled = warm_replica
+            .precompile_module(&engine, &policy_contents())
+            .unwrap();
+        let router = axum::Router::new()
+            .route(
+                PEER_ENDPOINT_PATH,
+                axum::routing::get(crate::api::handlers::module_cache_peer_handler),
+            )
+            .with_state(warm_replica);
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, router).await });
+
+        let peers = vec![reqwest::Url::parse(&format!("http://{addr}")).unwrap()];
+        let replica = |dir: &Path, key: &[u8]| {
+            ModuleCache::new(ModuleCacheConfig {
+                dir: dir.to_owned(),
+                key: key.to_vec(),
+                peers: peers.clone(),
+            })
+        };
+        let engine_fp = engine_fingerprint(&engine);
+        let module_digest = format!("{:x}", Sha256::digest(policy_contents()));
+
+        // a new replica, sharing the key
+        let dir = tempfile::tempdir().unwrap();
+        let new_replica = replica(dir.path(), b"secret");
+        new_replica
+            .replicate_from_peers(&engine, &fetched_policies)
+            .await;
+        let path = new_replica.entry_path(&engine_fp, &module_digest);
+        assert_eq!(
+            new_replica.load(&path, &engine_fp, &module_digest),
+            Some(compiled)
+        );
+
+        // a replica with another key is not served
+        let dir = tempfile::tempdir().unwrap();
+        let other_replica = replica(dir.path(), b"guessed");
+        other_replica
+            .replicate_from_peers(&engine, &fetched_policies)
+            .await;
+        assert!(!other_replica
+            .entry_path(&engine_fp, &module_digest)
+            .exists());
+    }
+}
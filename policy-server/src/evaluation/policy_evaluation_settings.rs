@@ -1,5 +1,16 @@
-use crate::config::PolicyOrPolicyGroupSettings;
-use policy_evaluator::admission_response_handler::policy_mode::PolicyMode;
+use std::{
+    collections::{BTreeMap, HashMap},
+    path::PathBuf,
+};
+
+use crate::{
+    config::PolicyOrPolicyGroupSettings,
+    evaluation::{secret_redaction::SecretRedaction, MatchConditions},
+};
+use policy_evaluator::{
+    admission_response_handler::{failure_policy::FailurePolicy, policy_mode::PolicyMode},
+    policy_evaluator::{PolicySettings, ValidateRequest},
+};
 
 /// Holds the evaluation settings of loaded Policy. These settings are taken straight from the
 /// `policies.yml` file provided by the user
@@ -8,10 +19,125 @@ use policy_evaluator::admission_response_handler::policy_mode::PolicyMode;
 pub(crate) struct PolicyEvaluationSettings {
     /// Whether the policy is operating in `protect` or `monitor` mode
     pub(crate) policy_mode: PolicyMode,
+    /// Whether the requests are rejected or accepted when the evaluation fails
+    pub(crate) failure_policy: FailurePolicy,
+    /// The evaluation timeout of the policy, in seconds. The global one is used when not set
+    pub(crate) timeout_seconds: Option<u64>,
     /// Determines if a mutating policy is actually allowed to mutate
     pub(crate) allowed_to_mutate: bool,
     /// The policy-specific settings provided by the user
     pub(crate) settings: PolicyOrPolicyGroupSettings,
+    /// The settings used for the requests made inside of the given namespaces: the
+    /// overrides provided by the user, already merged over the base settings
+    pub(crate) namespace_settings: HashMap<String, PolicySettings>,
     /// Determines a custom rejection message for the policy
     pub(crate) custom_rejection_message: Option<String>,
+    /// The conditions the admission requests must meet to be evaluated by the policy
+    pub(crate) match_conditions: MatchConditions,
+    /// Whether the values of the Secrets are masked before being given to the policy
+    pub(crate) secret_redaction: SecretRedaction,
+    /// The directories of the host preopened inside of the policy, keyed by their path
+    /// inside of the policy. Always empty for policy groups
+    pub(crate) preopened_dirs: BTreeMap<String, PathBuf>,
+}
+
+impl PolicyEvaluationSettings {
+    /// The settings of the policy to be used when evaluating the given request. The
+    /// overrides of the namespace of the request take precedence over the base settings.
+    ///
+    /// Returns `None` when the settings belong to a policy group
+    pub(crate) fn policy_settings_for_request(
+        &self,
+        req: &ValidateRequest,
+    ) -> Option<&PolicySettings> {
+        let namespace_settings = match req {
+            ValidateRequest::AdmissionRequest(adm_req) => adm_req
+                .namespace
+                .as_ref()
+                .and_then(|namespace| self.namespace_settings.get(namespace)),
+            _ => None,
+        };
+
+        match &self.settings {
+            PolicyOrPolicyGroupSettings::Policy(settings) => {
+                Some(namespace_settings.unwrap_or(settings))
+            }
+            PolicyOrPolicyGroupSettings::PolicyGroup { .. } => None,
+        }
+    }
+}
+
+/// Merge the overrides of each namespace over the base settings of the policy. The
+/// top-level keys of an override replace the ones of the base settings
+pub(crate) fn merge_namespace_settings(
+    base_settings: &PolicySettings,
+    overrides: &HashMap<String, PolicySettings>,
+) -> HashMap<String, PolicySettings> {
+    overrides
+        .iter()
+        .map(|(namespace, namespace_override)| {
+            let mut settings = base_settings.clone();
+            settings.0.extend(namespace_override.0.clone());
+            (namespace.to_owned(), settings)
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::build_admission_review_request;
+    use rstest::rstest;
+    use serde_json::json;
+
+    fn settings(value: serde_json::Value) -> PolicySettings {
+        PolicySettings::try_from(&value).unwrap()
+    }
+
+    fn policy_evaluation_settings() -> PolicyEvaluationSettings {
+        let base_settings = settings(json!({"maxReplicas": 3, "allowLatestTag": false}));
+        let overrides =
+            HashMap::from([("dev".to_owned(), settings(json!({"allowLatestTag": true})))]);
+
+        PolicyEvaluationSettings {
+            policy_mode: PolicyMode::Protect,
+            failure_policy: FailurePolicy::Fail,
+            timeout_seconds: None,
+            allowed_to_mutate: false,
+            namespace_settings: merge_namespace_settings(&base_settings, &overrides),
+            settings: PolicyOrPolicyGroupSettings::Policy(base_settings),
+            custom_rejection_message: None,
+            match_conditions: MatchConditions::default(),
+            secret_redaction: SecretRedaction::Disabled,
+            preopened_dirs: BTreeMap::new(),
+        }
+    }
+
+    #[test]
+    fn merge_overrides_over_base_settings() {
+        let merged = policy_evaluation_settings().namespace_settings;
+
+        assert_eq!(
+            merged,
+            HashMap::from([(
+                "dev".to_owned(),
+                settings(json!({"maxReplicas": 3, "allowLatestTag": true}))
+            )])
+        );
+    }
+
+    #[rstest]
+    #[case::overridden_namespace(Some("dev"), json!({"maxReplicas": 3, "allowLatestTag": true}))]
+    #[case::other_namespace(Some("prod"), json!({"maxReplicas": 3, "allowLatestTag": false}))]
+    #[case::cluster_wide(None, json!({"maxReplicas": 3, "allowLatestTag": false}))]
+    fn settings_for_request(#[case] namespace: Option<&str>, #[case] expected: serde_json::Value) {
+        let mut adm_req = build_admission_review_request().request;
+        adm_req.namespace = namespace.map(str::to_owned);
+        let req = ValidateRequest::AdmissionRequest(Box::new(adm_req));
+
+        assert_eq!(
+            policy_evaluation_settings().policy_settings_for_request(&req),
+            Some(&settings(expected))
+        );
+    }
 }
@@ -1,11 +1,18 @@
 use anyhow::{anyhow, Result};
 use lazy_static::lazy_static;
 use policy_evaluator::{
-    policy_evaluator::PolicyExecutionMode, policy_metadata::Metadata, wasmtime, ProtocolVersion,
+    policy_evaluator::PolicyExecutionMode,
+    policy_metadata::{Metadata, PreopenedDir},
+    request_envelope::RequestEnvelopeVersion,
+    wasmtime, ProtocolVersion,
 };
+use rayon::prelude::*;
 use semver::{BuildMetadata, Prerelease, Version};
 use sha2::{Digest, Sha256};
-use std::{collections::HashMap, fs, path::Path, vec::Vec};
+use std::{collections::HashMap, fs, path::Path, sync::Arc, vec::Vec};
+use tracing::debug;
+
+use crate::{evaluation::module_cache::ModuleCache, policy_downloader::FetchedPolicies};
 
 lazy_static! {
     static ref KUBEWARDEN_VERSION: Version = {
@@ -31,41 +38,94 @@ lazy_static! {
 /// that has been created with the same `wasmtime::Config` used at compilation time.
 #[derive(Clone)]
 pub(crate) struct PrecompiledPolicy {
-    /// A precompiled [`wasmtime::Module`]
-    pub precompiled_module: Vec<u8>,
+    /// A precompiled [`wasmtime::Module`]. It is shared by all the policies
+    /// using the same Wasm module.
+    pub precompiled_module: Arc<[u8]>,
 
     /// The execution mode of the policy
     pub execution_mode: PolicyExecutionMode,
 
     /// sha256 digest of the precompiled module
     pub digest: String,
+
+    /// The fields of the `AdmissionRequest` objects read by the policy, as declared
+    /// inside of its metadata
+    pub request_projection: Option<Vec<String>>,
+
+    /// The version of the request envelope understood by the policy, as declared
+    /// inside of its metadata
+    pub request_envelope: RequestEnvelopeVersion,
+
+    /// The directories the policy expects to find preopened, as declared inside of
+    /// its metadata
+    pub preopened_dirs: Vec<PreopenedDir>,
 }
 
 impl PrecompiledPolicy {
     /// Load a WebAssembly module from the disk and compiles it
-    pub fn new(engine: &wasmtime::Engine, wasm_module_path: &Path) -> Result<Self> {
+    pub fn new(
+        engine: &wasmtime::Engine,
+        wasm_module_path: &Path,
+        module_cache: Option<&ModuleCache>,
+    ) -> Result<Self> {
         let policy_contents = fs::read(wasm_module_path)?;
-        let policy_metadata = Metadata::from_contents(&policy_contents)?;
-        let metadata = policy_metadata.unwrap_or_default();
-        let execution_mode = metadata.execution_mode;
-        has_minimum_kubewarden_version(&metadata)?;
+        Self::from_contents(engine, &policy_contents, module_cache)
+    }
 
-        has_valid_protocol_version(&metadata)?;
+    /// Compile the given WebAssembly module. When a module cache is given, the
+    /// module is loaded from there, provided it has already been compiled
+    pub fn from_contents(
+        engine: &wasmtime::Engine,
+        policy_contents: &[u8],
+        module_cache: Option<&ModuleCache>,
+    ) -> Result<Self> {
+        let metadata = policy_metadata(policy_contents)?;
+        let precompiled_module = match module_cache {
+            Some(module_cache) => module_cache.precompile_module(engine, policy_contents)?,
+            None => engine.precompile_module(policy_contents)?,
+        };
 
-        let precompiled_module = engine.precompile_module(&policy_contents)?;
+        Ok(Self::from_metadata(&metadata, precompiled_module))
+    }
+
+    /// Use a WebAssembly module that has already been compiled, like the ones stored
+    /// inside of a state directory.
+    ///
+    /// **Warning:** the module must have been compiled from `policy_contents`, by an
+    /// engine compatible with the one that is going to load it
+    pub fn from_precompiled(policy_contents: &[u8], precompiled_module: Vec<u8>) -> Result<Self> {
+        let metadata = policy_metadata(policy_contents)?;
 
+        Ok(Self::from_metadata(&metadata, precompiled_module))
+    }
+
+    fn from_metadata(metadata: &Metadata, precompiled_module: Vec<u8>) -> Self {
         let mut hasher = Sha256::new();
         hasher.update(&precompiled_module);
         let digest = hasher.finalize();
 
-        Ok(Self {
-            precompiled_module,
-            execution_mode,
+        Self {
+            precompiled_module: precompiled_module.into(),
+            execution_mode: metadata.execution_mode,
             digest: format!("{digest:x}"),
-        })
+            request_projection: metadata.request_projection.clone(),
+            request_envelope: metadata.request_envelope,
+            preopened_dirs: metadata.preopened_dirs.clone(),
+        }
     }
 }
 
+/// Read the metadata of the policy, ensuring it can be run by this version of
+/// Policy Server
+fn policy_metadata(policy_contents: &[u8]) -> Result<Metadata> {
+    let metadata = Metadata::from_contents(policy_contents)?.unwrap_or_default();
+    has_minimum_kubewarden_version(&metadata)?;
+
+    has_valid_protocol_version(&metadata)?;
+
+    Ok(metadata)
+}
+
 /// A dictionary with:
 /// * Key: the URL of the WebAssembly module
 /// * Value: a Result containing the precompiled policy or an error.
@@ -73,6 +133,72 @@ impl PrecompiledPolicy {
 /// Errors are stored and will be reported to the user in the API response.
 pub(crate) type PrecompiledPolicies = HashMap<String, Result<PrecompiledPolicy>>;
 
+/// Compile the Wasm modules that have been fetched.
+///
+/// The same Wasm module can be referenced by different URLs, for example when it's
+/// pulled both by tag and by digest, or from different registries. Modules are
+/// grouped by the digest of their contents: each one of them is compiled only once,
+/// the result is shared by all the URLs referencing it.
+pub(crate) fn precompile_policies(
+    engine: &wasmtime::Engine,
+    fetched_policies: &FetchedPolicies,
+    module_cache: Option<&ModuleCache>,
+) -> PrecompiledPolicies {
+    let mut precompiled_policies = PrecompiledPolicies::new();
+    let mut modules: HashMap<String, (Vec<u8>, Vec<String>)> = HashMap::new();
+    for (policy_url, fetched_policy) in fetched_policies {
+        let policy_contents = match fetched_policy {
+            Ok(path) => fs::read(path).map_err(|e| anyhow!(e)),
+            Err(error) => Err(anyhow!(error.to_string())),
+        };
+        match policy_contents {
+            Ok(policy_contents) => {
+                let module_digest = format!("{:x}", Sha256::digest(&policy_contents));
+                modules
+                    .entry(module_digest)
+                    .or_insert_with(|| (policy_contents, Vec::new()))
+                    .1
+                    .push(policy_url.clone());
+            }
+            Err(error) => {
+                precompiled_policies.insert(policy_url.clone(), Err(error));
+            }
+        }
+    }
+
+    debug!(
+        wasm_modules_count = modules.len(),
+        policy_urls_count = fetched_policies.len(),
+        "instantiating wasmtime::Module objects"
+    );
+
+    let compiled: Vec<(Vec<String>, Result<PrecompiledPolicy>)> = modules
+        .into_par_iter()
+        .map(|(module_digest, (policy_contents, policy_urls))| {
+            let precompiled_policy =
+                PrecompiledPolicy::from_contents(engine, &policy_contents, module_cache);
+            debug!(module_digest, ?policy_urls, "module compiled");
+            (policy_urls, precompiled_policy)
+        })
+        .collect();
+
+    for (mut policy_urls, precompiled_policy) in compiled {
+        let Some(last_policy_url) = policy_urls.pop() else {
+            continue;
+        };
+        for policy_url in policy_urls {
+            let shared = match &precompiled_policy {
+                Ok(precompiled_policy) => Ok(precompiled_policy.clone()),
+                Err(error) => Err(anyhow!(error.to_string())),
+            };
+            precompiled_policies.insert(policy_url, shared);
+        }
+        precompiled_policies.insert(last_policy_url, precompiled_policy);
+    }
+
+    precompiled_policies
+}
+
 /// Check if policy server version is compatible with  minimum kubewarden
 /// version required by the policy
 fn has_minimum_kubewarden_version(metadata: &Metadata) -> Result<()> {
@@ -112,6 +238,7 @@ fn has_valid_protocol_version(metadata: &Metadata) -> Result<()> {
 mod tests {
     use super::*;
     use rstest::rstest;
+    use std::path::PathBuf;
 
     fn generate_metadata(major: u64, minor: u64, patch: u64) -> Metadata {
         let minimum_kubewarden_version = Version {
@@ -175,4 +302,90 @@ mod tests {
     fn validate_protocol_version_test(#[case] metadata: Metadata, #[case] is_valid: bool) {
         assert_eq!(is_valid, has_valid_protocol_version(&metadata).is_ok())
     }
+
+    /// Write a copy of the given Gatekeeper policy, annotated with its execution mode
+    fn annotated_gatekeeper_policy(source: &Path, destination: &Path) {
+        fn leb128(mut value: usize, buffer: &mut Vec<u8>) {
+            loop {
+                let byte = (value & 0x7f) as u8;
+                value >>= 7;
+                if value == 0 {
+                    buffer.push(byte);
+                    return;
+                }
+                buffer.push(byte | 0x80);
+            }
+        }
+
+        let metadata = serde_json::to_vec(&Metadata {
+            execution_mode: PolicyExecutionMode::OpaGatekeeper,
+            ..Default::default()
+        })
+        .unwrap();
+        let name = policy_evaluator::constants::KUBEWARDEN_CUSTOM_SECTION_METADATA.as_bytes();
+        let mut section = Vec::new();
+        leb128(name.len(), &mut section);
+        section.extend_from_slice(name);
+        section.extend_from_slice(&metadata);
+
+        let mut contents = fs::read(source).unwrap();
+        contents.push(0); // custom section id
+        leb128(section.len(), &mut contents);
+        contents.extend_from_slice(&section);
+        fs::write(destination, contents).unwrap();
+    }
+
+    #[test]
+    fn identical_modules_are_compiled_once() {
+        let engine = wasmtime::Engine::default();
+        let data_dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/data");
+        let tempdir = tempfile::tempdir().unwrap();
+        let happy_policy = tempdir.path().join("happy.wasm");
+        annotated_gatekeeper_policy(
+            &data_dir.join("gatekeeper_always_happy_policy.wasm"),
+            &happy_policy,
+        );
+        let unhappy_policy = tempdir.path().join("unhappy.wasm");
+        annotated_gatekeeper_policy(
+            &data_dir.join("gatekeeper_always_unhappy_policy.wasm"),
+            &unhappy_policy,
+        );
+
+        // the same module, stored under a different path
+        let happy_policy_copy = tempdir.path().join("copy.wasm");
+        fs::copy(&happy_policy, &happy_policy_copy).unwrap();
+
+        let fetched_policies: FetchedPolicies = [
+            ("registry://ghcr.io/happy:v1", Ok(happy_policy.clone())),
+            ("registry://mirror.local/happy:v1", Ok(happy_policy_copy)),
+            ("registry://ghcr.io/unhappy:v1", Ok(unhappy_policy)),
+            (
+                "registry://ghcr.io/missing:v1",
+                Ok(PathBuf::from("/does/not/exist.wasm")),
+            ),
+        ]
+        .into_iter()
+        .map(|(url, path)| (url.to_owned(), path))
+        .collect();
+
+        let precompiled_policies = precompile_policies(&engine, &fetched_policies, None);
+        assert_eq!(precompiled_policies.len(), 4);
+
+        let happy = precompiled_policies["registry://ghcr.io/happy:v1"]
+            .as_ref()
+            .unwrap();
+        let happy_mirror = precompiled_policies["registry://mirror.local/happy:v1"]
+            .as_ref()
+            .unwrap();
+        let unhappy = precompiled_policies["registry://ghcr.io/unhappy:v1"]
+            .as_ref()
+            .unwrap();
+        assert!(Arc::ptr_eq(
+            &happy.precompiled_module,
+            &happy_mirror.precompiled_module
+        ));
+        assert_eq!(happy.digest, happy_mirror.digest);
+        assert_ne!(happy.digest, unhappy.digest);
+        assert!(precompiled_policies["registry://ghcr.io/missing:v1"].is_err());
+    }
 }
@@ -0,0 +1,261 @@
+//! Redaction of the Secrets given to the policies.
+//!
+//! Most of the policies targeting Secrets look only at their metadata, or at the
+//! keys they hold, but they receive the values too. When the redaction is enabled,
+//! the values of the `data` and `stringData` fields of the Secrets found inside of
+//! `object` and `oldObject` are masked before the request is sent to the policy.
+//! The keys are kept, and so is the length of the values: policies checking for
+//! empty or short values keep working.
+//!
+//! The policies that genuinely need the values can opt out of the redaction. Each
+//! time one of them receives a Secret, an audit record is logged.
+
+use base64::{engine::general_purpose::STANDARD, Engine};
+use policy_evaluator::{
+    admission_response_handler::policy_id::PolicyID, policy_evaluator::ValidateRequest,
+};
+use serde_json::Value;
+use tracing::{info, warn};
+
+use crate::config::SecretData;
+
+/// The character replacing the contents of the Secrets
+const MASK: char = '*';
+
+/// How the Secrets are given to a policy
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub(crate) enum SecretRedaction {
+    /// The policy receives the values of the Secrets
+    #[default]
+    Disabled,
+    /// The values of the Secrets are masked
+    Enabled,
+    /// The redaction is enabled globally, but the policy opted out of it
+    OptedOut,
+}
+
+impl SecretRedaction {
+    /// Combine the global setting with the `secretData` of the policy. The opt-outs
+    /// are reported, to keep track of the policies that can read the Secrets
+    pub(crate) fn resolve(
+        policy_id: &PolicyID,
+        enabled_globally: bool,
+        secret_data: Option<SecretData>,
+    ) -> Self {
+        match (enabled_globally, secret_data) {
+            (_, Some(SecretData::Redacted)) | (true, None) => SecretRedaction::Enabled,
+            (true, Some(SecretData::Revealed)) => {
+                warn!(
+                    ?policy_id,
+                    "the policy opted out of the redaction of the Secrets, it receives their values"
+                );
+                SecretRedaction::OptedOut
+            }
+            (false, _) => SecretRedaction::Disabled,
+        }
+    }
+
+    /// Return the request to be given to the policy, `None` when it's the original one
+    pub(crate) fn apply(
+        &self,
+        policy_id: &PolicyID,
+        req: &ValidateRequest,
+    ) -> Option<ValidateRequest> {
+        match self {
+            SecretRedaction::Disabled => None,
+            SecretRedaction::Enabled => redact_secret_data(req),
+            SecretRedaction::OptedOut => {
+                if concerns_secret(req) {
+                    info!(
+                        ?policy_id,
+                        request_uid = req.uid(),
+                        "audit: the values of the Secret are given to the policy, which opted out of the redaction"
+                    );
+                }
+                None
+            }
+        }
+    }
+}
+
+/// Return the request with the values of the Secrets masked, `None` when the request
+/// doesn't concern a Secret.
+///
+/// Only admission requests are redacted, the raw requests are given to the policies
+/// untouched.
+fn redact_secret_data(req: &ValidateRequest) -> Option<ValidateRequest> {
+    let ValidateRequest::AdmissionRequest(adm_req) = req else {
+        return None;
+    };
+    if !concerns_secret(req) {
+        return None;
+    }
+
+    let mut redacted = adm_req.clone();
+    for object in [&mut redacted.object, &mut redacted.old_object]
+        .into_iter()
+        .flatten()
+    {
+        redact_secret(&mut object.0);
+    }
+
+    Some(ValidateRequest::AdmissionRequest(redacted))
+}
+
+/// Whether `object` or `oldObject` of the admission request is a Secret
+fn concerns_secret(req: &ValidateRequest) -> bool {
+    let ValidateRequest::AdmissionRequest(adm_req) = req else {
+        return false;
+    };
+    [&adm_req.object, &adm_req.old_object]
+        .into_iter()
+        .flatten()
+        .any(|raw| is_secret(&raw.0))
+}
+
+fn is_secret(object: &Value) -> bool {
+    object.get("apiVersion").and_then(Value::as_str) == Some("v1")
+        && object.get("kind").and_then(Value::as_str) == Some("Secret")
+}
+
+/// Mask the values of the given Secret. Nothing is done when the object is not a Secret
+fn redact_secret(object: &mut Value) {
+    if !is_secret(object) {
+        return;
+    }
+
+    if let Some(Value::Object(data)) = object.get_mut("data") {
+        for value in data.values_mut() {
+            if let Value::String(encoded) = value {
+                *encoded = mask_encoded_value(encoded);
+            }
+        }
+    }
+    if let Some(Value::Object(string_data)) = object.get_mut("stringData") {
+        for value in string_data.values_mut() {
+            if let Value::String(plain) = value {
+                *plain = mask(plain.chars().count());
+            }
+        }
+    }
+}
+
+/// The values of `data` are base64 encoded: the mask is encoded too, this way the
+/// policies decoding the values keep working and see a value of the original length
+fn mask_encoded_value(encoded: &str) -> String {
+    match STANDARD.decode(encoded) {
+        Ok(decoded) => STANDARD.encode(mask(decoded.len())),
+        Err(_) => mask(encoded.chars().count()),
+    }
+}
+
+fn mask(length: usize) -> String {
+    std::iter::repeat_n(MASK, length).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use policy_evaluator::admission_request::AdmissionRequest;
+    use rstest::rstest;
+    use serde_json::json;
+
+    fn admission_request(object: Value, old_object: Value) -> ValidateRequest {
+        let request = json!({
+            "uid": "705ab4f5",
+            "kind": {"group": "", "version": "v1", "kind": "Secret"},
+            "resource": {"group": "", "version": "v1", "resource": "secrets"},
+            "operation": "UPDATE",
+            "userInfo": {"username": "alice"},
+            "object": object,
+            "oldObject": old_object,
+        });
+        let adm_req: AdmissionRequest = serde_json::from_value(request).unwrap();
+        ValidateRequest::AdmissionRequest(Box::new(adm_req))
+    }
+
+    fn objects(req: &ValidateRequest) -> (Value, Value) {
+        let ValidateRequest::AdmissionRequest(adm_req) = req else {
+            unreachable!()
+        };
+        let object = adm_req.object.as_ref().map(|raw| raw.0.clone());
+        let old_object = adm_req.old_object.as_ref().map(|raw| raw.0.clone());
+        (
+            object.unwrap_or(Value::Null),
+            old_object.unwrap_or(Value::Null),
+        )
+    }
+
+    #[test]
+    fn secret_values_are_masked() {
+        let secret = |password: &str, token: &str| {
+            json!({
+                "apiVersion": "v1",
+                "kind": "Secret",
+                "metadata": {"name": "db", "labels": {"app": "db"}},
+                "type": "Opaque",
+                "data": {"password": STANDARD.encode(password), "empty": ""},
+                "stringData": {"token": token},
+            })
+        };
+        let req = admission_request(secret("hunter2", "abc"), secret("s3cr3t", "abcdef"));
+
+        let redacted = redact_secret_data(&req).expect("the request concerns a Secret");
+
+        let (object, old_object) = objects(&redacted);
+        assert_eq!(object, secret("*******", "***"));
+        assert_eq!(old_object, secret("******", "******"));
+    }
+
+    #[test]
+    fn invalid_base64_values_are_masked() {
+        let secret = json!({
+            "apiVersion": "v1",
+            "kind": "Secret",
+            "data": {"password": "not base64!"},
+        });
+        let req = admission_request(secret, Value::Null);
+
+        let redacted = redact_secret_data(&req).unwrap();
+
+        let (object, _) = objects(&redacted);
+        assert_eq!(object["data"]["password"], json!("***********"));
+    }
+
+    #[rstest]
+    #[case::config_map(json!({"apiVersion": "v1", "kind": "ConfigMap", "data": {"a": "b"}}))]
+    #[case::custom_resource_named_secret(
+        json!({"apiVersion": "example.com/v1", "kind": "Secret", "data": {"a": "b"}})
+    )]
+    fn other_objects_are_untouched(#[case] object: Value) {
+        let req = admission_request(object, Value::Null);
+
+        assert!(redact_secret_data(&req).is_none());
+    }
+
+    #[rstest]
+    #[case::global_default(false, None, SecretRedaction::Disabled)]
+    #[case::global_enabled(true, None, SecretRedaction::Enabled)]
+    #[case::policy_redacted(false, Some(SecretData::Redacted), SecretRedaction::Enabled)]
+    #[case::policy_opt_out(true, Some(SecretData::Revealed), SecretRedaction::OptedOut)]
+    #[case::policy_revealed(false, Some(SecretData::Revealed), SecretRedaction::Disabled)]
+    fn resolve_redaction(
+        #[case] enabled_globally: bool,
+        #[case] secret_data: Option<SecretData>,
+        #[case] expected: SecretRedaction,
+    ) {
+        let policy_id = PolicyID::Policy("read-secrets".to_owned());
+
+        assert_eq!(
+            SecretRedaction::resolve(&policy_id, enabled_globally, secret_data),
+            expected
+        );
+    }
+
+    #[test]
+    fn raw_requests_are_untouched() {
+        let req = ValidateRequest::Raw(json!({"apiVersion": "v1", "kind": "Secret"}));
+
+        assert!(redact_secret_data(&req).is_none());
+    }
+}
@@ -0,0 +1,666 @@
+//! Write-ahead journal of the admission decisions taken by Policy Server.
+//!
+//! Each decision is appended to the active segment and flushed to disk before the
+//! response is sent back to the API server. The segments are written by a dedicated
+//! thread: the decisions taken while a flush is in progress are queued and then flushed
+//! together, hence concurrent requests share the cost of the same `fsync`.
+//!
+//! Segments are plain text files, each line holds a checksum followed by the JSON
+//! representation of the decision:
+//!
+//! ```text
+//! 5f2b9c0e1d3a4b6c {"timestamp":1718000000000,"policyId":"pod-privileged","policyStableId":"pod-privileged@3c1f8e2a9b0d",...}
+//! ```
+//!
+//! A new segment is started each time Policy Server starts and when the active segment
+//! grows beyond the configured size. Lines that have been partially written because of a
+//! crash, or that do not match their checksum, are skipped by the reader.
+
+use std::{
+    fs::{self, File, OpenOptions},
+    io::{BufRead, BufReader, Write},
+    path::{Path, PathBuf},
+    thread,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use anyhow::{anyhow, Result};
+use policy_evaluator::{
+    admission_response::AdmissionResponse, admission_response_handler::errors::EvaluationError,
+    policy_evaluator::ValidateRequest,
+};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use tokio::sync::{mpsc, oneshot};
+use tracing::{error, info, warn};
+
+const SEGMENT_PREFIX: &str = "decisions-";
+const SEGMENT_EXTENSION: &str = "journal";
+
+/// Number of bytes of the SHA-256 digest of the entry used as checksum
+const CHECKSUM_LENGTH: usize = 8;
+
+/// Number of entries waiting to be written after which the evaluations wait before
+/// queueing new ones
+const PENDING_ENTRIES: usize = 1024;
+
+/// Configuration of the decision journal
+#[derive(Clone, Debug, PartialEq)]
+pub struct JournalConfig {
+    /// Directory holding the segments of the journal
+    pub dir: PathBuf,
+    /// Size, in bytes, after which a new segment is started
+    pub segment_size: u64,
+    /// When set, the oldest segments are removed to retain at most this number of segments
+    pub max_segments: Option<usize>,
+    /// Reject the requests whose decision cannot be written to the journal, instead of
+    /// only reporting the failure
+    pub fail_closed: bool,
+}
+
+/// An admission decision, as recorded inside of the journal
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct JournalEntry {
+    /// When the decision has been taken, in milliseconds since the UNIX epoch
+    pub timestamp: u64,
+    pub policy_id: String,
+    /// The stable ID of the policy, which changes whenever the policy is bumped to a
+    /// different Wasm module. Not known for the policies that do not exist
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub policy_stable_id: Option<String>,
+    pub request_uid: String,
+    /// Either `validate` or `audit`
+    pub origin: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub operation: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub kind: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub namespace: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    pub allowed: bool,
+    pub mutated: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub message: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub code: Option<u16>,
+    /// The error that prevented the evaluation of the request, no response has been
+    /// produced by the policy in this case
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+impl JournalEntry {
+    /// Build the entry describing the response given to a request
+    pub(crate) fn new(
+        policy_id: &str,
+        policy_stable_id: Option<&str>,
+        origin: &str,
+        validate_request: &ValidateRequest,
+        response: &AdmissionResponse,
+    ) -> Self {
+        let (message, code) = response
+            .status
+            .as_ref()
+            .map(|status| (status.message.clone(), status.code))
+            .unwrap_or_default();
+
+        JournalEntry {
+            allowed: response.allowed,
+            mutated: response.patch.is_some(),
+            message,
+            code,
+            ..JournalEntry::for_request(policy_id, policy_stable_id, origin, validate_request)
+        }
+    }
+
+    /// Build the entry describing a request that could not be evaluated
+    pub(crate) fn from_error(
+        policy_id: &str,
+        policy_stable_id: Option<&str>,
+        origin: &str,
+        validate_request: &ValidateRequest,
+        error: &EvaluationError,
+    ) -> Self {
+        JournalEntry {
+            error: Some(error.to_string()),
+            ..JournalEntry::for_request(policy_id, policy_stable_id, origin, validate_request)
+        }
+    }
+
+    fn for_request(
+        policy_id: &str,
+        policy_stable_id: Option<&str>,
+        origin: &str,
+        validate_request: &ValidateRequest,
+    ) -> Self {
+        let (operation, kind, namespace, name) = match validate_request {
+            ValidateRequest::AdmissionRequest(adm_req) => (
+                Some(adm_req.operation.clone()),
+                Some(adm_req.kind.kind.clone()),
+                adm_req.namespace.clone(),
+                adm_req.name.clone(),
+            ),
+            ValidateRequest::Raw(_) | ValidateRequest::CloudEvent(_) => (None, None, None, None),
+        };
+
+        JournalEntry {
+            timestamp: now_millis(),
+            policy_id: policy_id.to_owned(),
+            policy_stable_id: policy_stable_id.map(str::to_owned),
+            request_uid: validate_request.uid().to_owned(),
+            origin: origin.to_owned(),
+            operation,
+            kind,
+            namespace,
+            name,
+            allowed: false,
+            mutated: false,
+            message: None,
+            code: None,
+            error: None,
+        }
+    }
+}
+
+fn now_millis() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or_default()
+}
+
+/// The segment entries are currently appended to
+struct ActiveSegment {
+    index: u64,
+    file: File,
+    size: u64,
+}
+
+/// An entry waiting to be written, together with the channel used to report the
+/// outcome of the write
+struct PendingEntry {
+    line: String,
+    written: oneshot::Sender<std::result::Result<(), String>>,
+}
+
+/// The writing end of the decision journal
+pub struct DecisionJournal {
+    fail_closed: bool,
+    pending_entries: mpsc::Sender<PendingEntry>,
+}
+
+impl DecisionJournal {
+    /// Open the journal stored inside of the configured directory, the directory is
+    /// created when it doesn't exist. Entries are appended to a new segment by a
+    /// dedicated thread, which stops when the journal is dropped.
+    pub fn open(config: JournalConfig) -> Result<Self> {
+        fs::create_dir_all(&config.dir).map_err(|e| {
+            anyhow!(
+                "cannot create decision journal directory {}: {e}",
+                config.dir.display()
+            )
+        })?;
+
+        let index = segments(&config.dir)?
+            .last()
+            .map(|(index, _)| index + 1)
+            .unwrap_or_default();
+        let active_segment = create_segment(&config.dir, index)?;
+        info!(
+            dir = %config.dir.display(),
+            segment = index,
+            "decision journal opened"
+        );
+
+        let fail_closed = config.fail_closed;
+        let writer = JournalWriter {
+            config,
+            active_segment,
+        };
+        writer.remove_old_segments()?;
+
+        let (pending_entries, pending_entries_rx) = mpsc::channel(PENDING_ENTRIES);
+        thread::Builder::new()
+            .name("decision-journal".to_owned())
+            .spawn(move || writer.run(pending_entries_rx))
+            .map_err(|e| anyhow!("cannot start the decision journal writer: {e}"))?;
+
+        Ok(DecisionJournal {
+            fail_closed,
+            pending_entries,
+        })
+    }
+
+    /// Whether the requests whose decision cannot be written must be rejected
+    pub fn fail_closed(&self) -> bool {
+        self.fail_closed
+    }
+
+    /// Append the entry to the journal. Returns once the entry has been flushed to disk.
+    pub async fn append(&self, entry: &JournalEntry) -> Result<()> {
+        let line = encode_entry(entry)?;
+
+        let (written, written_rx) = oneshot::channel();
+        self.pending_entries
+            .send(PendingEntry { line, written })
+            .await
+            .map_err(|_| anyhow!("decision journal writer is not running"))?;
+        written_rx
+            .await
+            .map_err(|_| anyhow!("decision journal writer is not running"))?
+            .map_err(|e| anyhow!("cannot write decision journal entry: {e}"))
+    }
+}
+
+/// Owns the active segment, all the writes are done by this object
+struct JournalWriter {
+    config: JournalConfig,
+    active_segment: ActiveSegment,
+}
+
+impl JournalWriter {
+    /// Write the entries until all the senders are dropped. The entries queued while
+    /// a flush is in progress are written together, and flushed once
+    fn run(mut self, mut pending_entries: mpsc::Receiver<PendingEntry>) {
+        let mut batch = Vec::new();
+        while let Some(entry) = pending_entries.blocking_recv() {
+            batch.push(entry);
+            while let Ok(entry) = pending_entries.try_recv() {
+                batch.push(entry);
+            }
+
+            let result = self.write(&batch).map_err(|e| {
+                error!(error = %e, entries = batch.len(), "cannot write decision journal entries");
+                e.to_string()
+            });
+            for entry in batch.drain(..) {
+                // the evaluation might have been cancelled in the meantime
+                let _ = entry.written.send(result.clone());
+            }
+        }
+    }
+
+    fn write(&mut self, batch: &[PendingEntry]) -> Result<()> {
+        for entry in batch {
+            let size = entry.line.len() as u64;
+            if self.active_segment.size > 0
+                && self.active_segment.size + size > self.config.segment_size
+            {
+                // the entries already written to the segment must be on disk too
+                self.active_segment.file.sync_data()?;
+                self.active_segment =
+                    create_segment(&self.config.dir, self.active_segment.index + 1)?;
+                self.remove_old_segments()?;
+            }
+
+            self.active_segment.file.write_all(entry.line.as_bytes())?;
+            self.active_segment.size += size;
+        }
+        self.active_segment.file.sync_data()?;
+
+        Ok(())
+    }
+
+    /// Remove the oldest segments, according to the retention configured by the user
+    fn remove_old_segments(&self) -> Result<()> {
+        let max_segments = match self.config.max_segments {
+            Some(max_segments) => max_segments.max(1),
+            None => return Ok(()),
+        };
+
+        let segments = segments(&self.config.dir)?;
+        let exceeding = segments.len().saturating_sub(max_segments);
+        for (_, path) in segments.into_iter().take(exceeding) {
+            info!(segment = %path.display(), "removing old decision journal segment");
+            fs::remove_file(&path)
+                .map_err(|e| anyhow!("cannot remove segment {}: {e}", path.display()))?;
+        }
+
+        Ok(())
+    }
+}
+
+fn segment_path(dir: &Path, index: u64) -> PathBuf {
+    dir.join(format!("{SEGMENT_PREFIX}{index:020}.{SEGMENT_EXTENSION}"))
+}
+
+fn create_segment(dir: &Path, index: u64) -> Result<ActiveSegment> {
+    let path = segment_path(dir, index);
+    let file = OpenOptions::new()
+        .create_new(true)
+        .append(true)
+        .open(&path)
+        .map_err(|e| anyhow!("cannot create segment {}: {e}", path.display()))?;
+    // ensure the new segment survives a crash
+    File::open(dir)?.sync_all()?;
+
+    Ok(ActiveSegment {
+        index,
+        file,
+        size: 0,
+    })
+}
+
+/// Return the segments stored inside of the given directory, sorted from the oldest
+/// to the most recent one
+pub fn segments(dir: &Path) -> Result<Vec<(u64, PathBuf)>> {
+    let mut segments: Vec<(u64, PathBuf)> = fs::read_dir(dir)
+        .map_err(|e| anyhow!("cannot read decision journal {}: {e}", dir.display()))?
+        .filter_map(|entry| entry.ok())
+        .filter_map(|entry| {
+            let path = entry.path();
+            let index = path
+                .file_name()?
+                .to_str()?
+                .strip_prefix(SEGMENT_PREFIX)?
+                .strip_suffix(&format!(".{SEGMENT_EXTENSION}"))?
+                .parse::<u64>()
+                .ok()?;
+            Some((index, path))
+        })
+        .collect();
+    segments.sort();
+
+    Ok(segments)
+}
+
+fn checksum(data: &[u8]) -> String {
+    Sha256::digest(data)[..CHECKSUM_LENGTH]
+        .iter()
+        .map(|b| format!("{b:02x}"))
+        .collect()
+}
+
+fn encode_entry(entry: &JournalEntry) -> Result<String> {
+    let json = serde_json::to_string(entry)?;
+    Ok(format!("{} {json}\n", checksum(json.as_bytes())))
+}
+
+fn decode_entry(line: &str) -> Option<JournalEntry> {
+    let (expected_checksum, json) = line.split_once(' ')?;
+    if checksum(json.as_bytes()) != expected_checksum {
+        return None;
+    }
+    serde_json::from_str(json).ok()
+}
+
+/// The entries read from a segment
+#[derive(Debug, Default)]
+pub struct SegmentContents {
+    pub entries: Vec<JournalEntry>,
+    /// Number of lines that have been skipped because they are truncated or corrupted
+    pub corrupted: usize,
+}
+
+/// Read all the valid entries of a segment
+pub fn read_segment(path: &Path) -> Result<SegmentContents> {
+    let file =
+        File::open(path).map_err(|e| anyhow!("cannot open segment {}: {e}", path.display()))?;
+
+    let mut contents = SegmentContents::default();
+    for line in BufReader::new(file).lines() {
+        let line = match line {
+            Ok(line) => line,
+            Err(_) => {
+                contents.corrupted += 1;
+                continue;
+            }
+        };
+        if line.is_empty() {
+            continue;
+        }
+        match decode_entry(&line) {
+            Some(entry) => contents.entries.push(entry),
+            None => contents.corrupted += 1,
+        }
+    }
+
+    Ok(contents)
+}
+
+/// Summary of an export operation
+#[derive(Debug, Default, PartialEq)]
+pub struct ExportSummary {
+    pub exported: usize,
+    pub corrupted: usize,
+}
+
+/// Write the entries of the journal taken after `since` (expressed in milliseconds since
+/// the UNIX epoch) to `output`, using the JSON Lines format
+pub fn export(dir: &Path, since: Option<u64>, output: &mut impl Write) -> Result<ExportSummary> {
+    let mut summary = ExportSummary::default();
+
+    for (_, path) in segments(dir)? {
+        let contents = read_segment(&path)?;
+        if contents.corrupted > 0 {
+            warn!(
+                segment = %path.display(),
+                corrupted = contents.corrupted,
+                "skipped corrupted decision journal entries"
+            );
+        }
+        summary.corrupted += contents.corrupted;
+
+        for entry in contents.entries {
+            if since.is_some_and(|since| entry.timestamp < since) {
+                continue;
+            }
+            serde_json::to_writer(&mut *output, &entry)?;
+            output.write_all(b"\n")?;
+            summary.exported += 1;
+        }
+    }
+    output.flush()?;
+
+    Ok(summary)
+}
+
+/// Parse the value of the `--since` flag of the export command. This is either
+/// a UNIX timestamp expressed in seconds, or a duration relative to now, like
+/// `30m`, `12h` or `7d`. The result is expressed in milliseconds since the UNIX epoch.
+pub fn parse_since(since: &str) -> Result<u64> {
+    let too_large = || anyhow!("value for since is too large: {since}");
+
+    if let Ok(timestamp) = since.parse::<u64>() {
+        return timestamp.checked_mul(1000).ok_or_else(too_large);
+    }
+
+    let unit_position = since
+        .find(|c: char| !c.is_ascii_digit())
+        .ok_or_else(|| anyhow!("invalid value for since: {since}"))?;
+    let (amount, unit) = since.split_at(unit_position);
+    let amount = amount
+        .parse::<u64>()
+        .map_err(|_| anyhow!("invalid value for since: {since}"))?;
+    let unit_seconds = match unit {
+        "s" => 1,
+        "m" => 60,
+        "h" => 60 * 60,
+        "d" => 60 * 60 * 24,
+        _ => {
+            return Err(anyhow!(
+                "invalid unit for since: {unit}, use one of s, m, h, d"
+            ))
+        }
+    };
+    let millis = amount
+        .checked_mul(unit_seconds)
+        .and_then(|seconds| seconds.checked_mul(1000))
+        .ok_or_else(too_large)?;
+
+    Ok(now_millis().saturating_sub(millis))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rstest::rstest;
+    use std::sync::Arc;
+    use tokio::task::JoinSet;
+
+    fn entry(timestamp: u64, policy_id: &str) -> JournalEntry {
+        JournalEntry {
+            timestamp,
+            policy_id: policy_id.to_owned(),
+            policy_stable_id: Some(format!("{policy_id}@3c1f8e2a9b0d")),
+            request_uid: "705ab4f5-6393-11e8-b7cc-42010a800002".to_owned(),
+            origin: "validate".to_owned(),
+            operation: Some("CREATE".to_owned()),
+            kind: Some("Pod".to_owned()),
+            namespace: Some("default".to_owned()),
+            name: Some("nginx".to_owned()),
+            allowed: false,
+            mutated: false,
+            message: Some("privileged containers are not allowed".to_owned()),
+            code: None,
+            error: None,
+        }
+    }
+
+    fn journal_config(dir: &Path) -> JournalConfig {
+        JournalConfig {
+            dir: dir.to_owned(),
+            segment_size: 64 * 1024 * 1024,
+            max_segments: None,
+            fail_closed: false,
+        }
+    }
+
+    #[tokio::test]
+    async fn append_and_export() {
+        let dir = tempfile::tempdir().unwrap();
+        let journal = DecisionJournal::open(journal_config(dir.path())).unwrap();
+        journal.append(&entry(1000, "policy-1")).await.unwrap();
+        journal.append(&entry(2000, "policy-2")).await.unwrap();
+
+        let mut output = Vec::new();
+        let summary = export(dir.path(), None, &mut output).unwrap();
+        assert_eq!(
+            summary,
+            ExportSummary {
+                exported: 2,
+                corrupted: 0
+            }
+        );
+        let lines: Vec<JournalEntry> = String::from_utf8(output)
+            .unwrap()
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        assert_eq!(
+            lines,
+            vec![entry(1000, "policy-1"), entry(2000, "policy-2")]
+        );
+
+        let mut output = Vec::new();
+        let summary = export(dir.path(), Some(1500), &mut output).unwrap();
+        assert_eq!(summary.exported, 1);
+    }
+
+    #[tokio::test]
+    async fn each_start_uses_a_new_segment() {
+        let dir = tempfile::tempdir().unwrap();
+        for i in 0..3 {
+            let journal = DecisionJournal::open(journal_config(dir.path())).unwrap();
+            journal.append(&entry(i, "policy")).await.unwrap();
+        }
+
+        assert_eq!(segments(dir.path()).unwrap().len(), 3);
+        let mut output = Vec::new();
+        assert_eq!(export(dir.path(), None, &mut output).unwrap().exported, 3);
+    }
+
+    #[tokio::test]
+    async fn rotate_segments() {
+        let dir = tempfile::tempdir().unwrap();
+        let journal = DecisionJournal::open(JournalConfig {
+            dir: dir.path().to_owned(),
+            // each entry ends up in its own segment
+            segment_size: 10,
+            max_segments: Some(2),
+            fail_closed: false,
+        })
+        .unwrap();
+        for i in 0..5 {
+            journal.append(&entry(i, "policy")).await.unwrap();
+        }
+
+        let segments = segments(dir.path()).unwrap();
+        assert_eq!(
+            segments.iter().map(|(index, _)| *index).collect::<Vec<_>>(),
+            vec![3, 4]
+        );
+    }
+
+    #[tokio::test]
+    async fn concurrent_appends() {
+        let dir = tempfile::tempdir().unwrap();
+        let journal = Arc::new(DecisionJournal::open(journal_config(dir.path())).unwrap());
+
+        let mut appends = JoinSet::new();
+        for i in 0..100 {
+            let journal = journal.clone();
+            appends.spawn(async move { journal.append(&entry(i, "policy")).await });
+        }
+        while let Some(result) = appends.join_next().await {
+            result.unwrap().unwrap();
+        }
+
+        let contents = read_segment(&segments(dir.path()).unwrap().pop().unwrap().1).unwrap();
+        assert_eq!(contents.entries.len(), 100);
+        assert_eq!(contents.corrupted, 0);
+    }
+
+    #[test]
+    fn error_outcomes_are_recorded() {
+        let entry = JournalEntry {
+            error: Some("policy not found".to_owned()),
+            ..entry(1000, "unknown")
+        };
+        let line = encode_entry(&entry).unwrap();
+        assert!(line.contains(r#""error":"policy not found""#));
+        assert_eq!(decode_entry(line.trim_end()), Some(entry));
+    }
+
+    #[tokio::test]
+    async fn skip_corrupted_entries() {
+        let dir = tempfile::tempdir().unwrap();
+        let journal = DecisionJournal::open(journal_config(dir.path())).unwrap();
+        journal.append(&entry(1000, "policy-1")).await.unwrap();
+        drop(journal);
+
+        let (_, path) = segments(dir.path()).unwrap().pop().unwrap();
+        let mut file = OpenOptions::new().append(true).open(&path).unwrap();
+        // a line with a wrong checksum, followed by a truncated one
+        let tampered = encode_entry(&entry(2000, "policy-2"))
+            .unwrap()
+            .replace("policy-2", "policy-3");
+        file.write_all(tampered.as_bytes()).unwrap();
+        file.write_all(b"0011223344556677 {\"timestamp\":30")
+            .unwrap();
+
+        let contents = read_segment(&path).unwrap();
+        assert_eq!(contents.entries, vec![entry(1000, "policy-1")]);
+        assert_eq!(contents.corrupted, 2);
+    }
+
+    #[rstest]
+    #[case::timestamp("1718000000", Some(1_718_000_000_000))]
+    #[case::invalid_unit("10y", None)]
+    #[case::no_unit("abc", None)]
+    #[case::timestamp_overflow("18446744073709551615", None)]
+    #[case::relative_overflow("18446744073709551615d", None)]
+    fn parse_since_flag(#[case] since: &str, #[case] expected: Option<u64>) {
+        assert_eq!(parse_since(since).ok(), expected);
+    }
+
+    #[test]
+    fn parse_relative_since() {
+        let since = parse_since("1h").unwrap();
+        let expected = now_millis() - 60 * 60 * 1000;
+        assert!(since <= expected && expected - since < 60 * 1000);
+    }
+}
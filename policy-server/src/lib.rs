@@ -1,6 +1,7 @@
 mod certs;
 mod evaluation;
 mod policy_downloader;
+mod policy_reverifier;
 
 #[cfg(test)]
 mod test_utils;
@@ -10,22 +11,29 @@ mod cli;
 
 pub mod api;
 pub mod config;
+pub mod decision_log;
+pub mod enrichment;
+pub mod journal;
 pub mod metrics;
 pub mod profiling;
+pub mod state;
 pub mod tracing;
 
-use ::tracing::{debug, info, warn, Level};
+use ::tracing::{info, warn, Level};
 use anyhow::{anyhow, Result};
 use axum::{
-    routing::{get, post},
+    extract::DefaultBodyLimit,
+    middleware,
+    routing::{get, post, put},
     Router,
 };
 use axum_server::tls_rustls::RustlsConfig;
 use certs::create_tls_config_and_watch_certificate_changes;
-use evaluation::EvaluationEnvironmentBuilder;
+use evaluation::{EpochDeadlines, EpochTicker, EvaluationEnvironmentBuilder};
 use policy_evaluator::{
-    callback_handler::{CallbackHandler, CallbackHandlerBuilder},
-    kube,
+    admission_response_handler::policy_id::PolicyID,
+    callback_handler::{CallbackHandler, CallbackHandlerBuilder, KubernetesHealthReporter},
+    capability_usage, kube,
     policy_fetcher::sigstore::trust::{
         sigstore::{ManualTrustRoot, SigstoreTrustRoot},
         TrustRoot,
@@ -33,25 +41,44 @@ use policy_evaluator::{
     wasmtime,
 };
 use profiling::activate_memory_profiling;
-use rayon::prelude::*;
-use std::{fs, net::SocketAddr, sync::Arc};
+use std::{
+    collections::{BTreeSet, HashMap, HashSet},
+    fs,
+    net::SocketAddr,
+    path::PathBuf,
+    sync::Arc,
+};
 use tokio::{
-    sync::{oneshot, Notify, Semaphore},
+    sync::{oneshot, Notify},
     time,
 };
-use tower_http::trace::{self, TraceLayer};
+use tower_http::{
+    compression::CompressionLayer,
+    trace::{self, TraceLayer},
+};
 
+use crate::api::body_limit::handle_oversized_requests;
 use crate::api::handlers::{
-    audit_handler, pprof_get_cpu, pprof_get_heap, readiness_handler, validate_handler,
-    validate_raw_handler,
+    audit_handler, capability_usage_handler, log_filter_delete_handler, log_filter_get_handler,
+    log_filter_put_handler, module_cache_peer_handler, policies_handler, pprof_get_cpu,
+    pprof_get_heap, readiness_handler, readyz_handler, readyz_kubernetes_handler,
+    validate_cloudevent_handler, validate_handler, validate_raw_handler,
 };
-use crate::api::state::ApiServerState;
-use crate::evaluation::precompiled_policy::{PrecompiledPolicies, PrecompiledPolicy};
-use crate::policy_downloader::{Downloader, FetchedPolicies};
-use config::Config;
+use crate::api::{dispatcher::PriorityDispatcher, state::ApiServerState};
+use crate::evaluation::module_cache::{ModuleCache, PEER_ENDPOINT_PATH};
+use crate::evaluation::precompiled_policy::precompile_policies;
+use crate::evaluation::{EvaluationEnvironment, PolicyState};
+use crate::policy_downloader::{download_rego_libraries, Downloader, FetchedPolicies};
+use crate::policy_reverifier::PolicyReverifier;
+use config::{Config, PolicyOrPolicyGroup};
+use decision_log::DecisionLog;
+use journal::DecisionJournal;
 
 use tikv_jemallocator::Jemalloc;
 
+/// How long to wait before retrying the initial sync of the Kubernetes resources that failed
+const KUBERNETES_SYNC_RETRY_INTERVAL: time::Duration = time::Duration::from_secs(5);
+
 #[global_allocator]
 static GLOBAL: Jemalloc = Jemalloc;
 
@@ -69,6 +96,7 @@ pub struct PolicyServer {
     addr: SocketAddr,
     tls_config: Option<RustlsConfig>,
     readiness_probe_addr: SocketAddr,
+    readiness_probe_tls_config: Option<RustlsConfig>,
 }
 
 impl PolicyServer {
@@ -78,22 +106,41 @@ impl PolicyServer {
         let (callback_handler_shutdown_channel_tx, callback_handler_shutdown_channel_rx) =
             oneshot::channel();
 
-        let sigstore_trust_root = match create_sigstore_trustroot(&config).await {
-            Ok(trust_root) => Some(trust_root),
-            Err(e) => {
-                // Do not exit, only policies making use of sigstore's keyless/certificate based signatures will fail
-                // There are good chances everything is going to work fine in the majority of cases
-                warn!(?e, "Cannot create Sigstore trust root, verification relying on Rekor and Fulcio will fail");
-                None
-            }
+        // When serving an imported state the policies are neither downloaded nor
+        // verified, hence Sigstore is not needed
+        let offline_state = config
+            .state_dir
+            .as_deref()
+            .map(state::State::open)
+            .transpose()?;
+
+        let sigstore_trust_root = match offline_state {
+            Some(_) => None,
+            None => match create_sigstore_trustroot(&config).await {
+                Ok(trust_root) => Some(trust_root),
+                Err(e) => {
+                    // Do not exit, only policies making use of sigstore's keyless/certificate based signatures will fail
+                    // There are good chances everything is going to work fine in the majority of cases
+                    warn!(?e, "Cannot create Sigstore trust root, verification relying on Rekor and Fulcio will fail");
+                    None
+                }
+            },
         };
 
         let mut callback_handler_builder =
             CallbackHandlerBuilder::new(callback_handler_shutdown_channel_rx)
                 .registry_config(config.sources.clone())
-                .trust_root(sigstore_trust_root.clone());
-
-        let kube_client: Option<kube::Client> = match kube::Client::try_default().await {
+                .trust_root(sigstore_trust_root.clone())
+                .verification_config(config.verification_config.clone())
+                .client_pool_config(config.capabilities.client_pool.clone())
+                .dns_cache_config(config.capabilities.dns_cache.clone())
+                .request_coalescing_config(config.capabilities.request_coalescing.clone())
+                .response_size_limits(config.capabilities.response_size_limits.clone())
+                .key_value_store_config(config.capabilities.key_value_store.clone());
+
+        // The configuration is kept around to create the clients impersonating the
+        // Service Accounts of the policies
+        let kube_client: Option<(kube::Client, kube::Config)> = match build_kube_client().await {
             Ok(client) => Some(client),
             Err(e) => {
                 // We cannot rely on `tracing` yet, because the tracing system has not
@@ -104,8 +151,10 @@ impl PolicyServer {
         };
 
         match kube_client {
-            Some(client) => {
-                callback_handler_builder = callback_handler_builder.kube_client(client);
+            Some((client, config)) => {
+                callback_handler_builder = callback_handler_builder
+                    .kube_client(client)
+                    .kube_impersonation_config(config);
             }
             None => {
                 if config.ignore_kubernetes_connection_failure {
@@ -124,30 +173,79 @@ impl PolicyServer {
 
         let callback_handler = callback_handler_builder.build().await?;
         let callback_sender_channel = callback_handler.sender_channel();
+        let kubernetes_health_reporter = callback_handler.kubernetes_health_reporter();
 
         // Download policies
-        let downloader_sigstore_trust_root = if config.verification_config.is_some() {
-            sigstore_trust_root.clone()
-        } else {
-            None
+        let (fetched_policies, downloader) = match &offline_state {
+            Some(offline_state) => {
+                info!(
+                    state_dir = ?config.state_dir,
+                    "loading the policies from the state directory"
+                );
+                (offline_state.fetched_policies(), None)
+            }
+            None => {
+                let downloader_sigstore_trust_root = if config.verification_config.is_some() {
+                    sigstore_trust_root.clone()
+                } else {
+                    None
+                };
+                let mut downloader =
+                    Downloader::new(config.sources.clone(), downloader_sigstore_trust_root)
+                        .await?
+                        .with_fetch_config(config.policy_fetch.clone());
+
+                let fetched_policies = downloader
+                    .download_policies(
+                        &config.policies,
+                        &config.policies_download_dir,
+                        config.verification_config.as_ref(),
+                    )
+                    .await;
+                (fetched_policies, Some(downloader))
+            }
         };
-        let mut downloader =
-            Downloader::new(config.sources.clone(), downloader_sigstore_trust_root).await?;
-
-        let fetched_policies = downloader
-            .download_policies(
-                &config.policies,
-                &config.policies_download_dir,
-                config.verification_config.as_ref(),
-            )
-            .await;
 
-        let mut wasmtime_config = wasmtime::Config::new();
-        if config.policy_evaluation_limit_seconds.is_some() {
-            wasmtime_config.epoch_interruption(true);
-        }
-        let engine = wasmtime::Engine::new(&wasmtime_config)?;
-        let precompiled_policies = precompile_policies(&engine, &fetched_policies);
+        let rego_libraries = match &offline_state {
+            Some(offline_state) => offline_state.rego_libraries(),
+            None => {
+                download_rego_libraries(
+                    &config.policies,
+                    config.sources.as_ref(),
+                    config.policy_fetch.concurrency,
+                )
+                .await
+            }
+        };
+
+        let engine = create_wasmtime_engine(&config)?;
+        let (fetched_policies, lazy_policies) = if config.lazy_policy_loading {
+            let (fetched_policies, lazy_policies) =
+                split_lazy_policies(&config.policies, fetched_policies);
+            info!(
+                lazy_policies = lazy_policies.len(),
+                precompiled_policies = fetched_policies.len(),
+                "lazy policy loading is enabled"
+            );
+            (fetched_policies, lazy_policies)
+        } else {
+            (fetched_policies, HashMap::new())
+        };
+        let module_cache = config
+            .module_cache
+            .clone()
+            .map(|module_cache| Arc::new(ModuleCache::new(module_cache)));
+        let precompiled_policies = match &offline_state {
+            Some(offline_state) => offline_state.precompiled_policies(&engine, &fetched_policies),
+            None => {
+                if let Some(module_cache) = &module_cache {
+                    module_cache
+                        .replicate_from_peers(&engine, &fetched_policies)
+                        .await;
+                }
+                precompile_policies(&engine, &fetched_policies, module_cache.as_deref())
+            }
+        };
 
         if !config.continue_on_errors {
             for result in precompiled_policies.values() {
@@ -157,43 +255,158 @@ impl PolicyServer {
             }
         }
 
+        // the peers are served the entries of the module cache
+        let module_cache_peer_router = module_cache
+            .clone()
+            .filter(|module_cache| module_cache.has_peers())
+            .map(|module_cache| {
+                Router::new()
+                    .route(PEER_ENDPOINT_PATH, get(module_cache_peer_handler))
+                    .with_state(module_cache)
+            });
+
         let mut evaluation_environment_builder = EvaluationEnvironmentBuilder::new(
             &engine,
             &precompiled_policies,
             callback_sender_channel.clone(),
         )
-        .with_continue_on_errors(config.continue_on_errors);
+        .with_continue_on_errors(config.continue_on_errors)
+        .with_lazy_policies(lazy_policies)
+        .with_rego_libraries(rego_libraries)
+        .with_rego_policy_memory_limit(config.rego_policy_memory_limit)
+        .with_request_projection(config.request_projection)
+        .with_secret_data_redaction(config.redact_secret_data)
+        .with_evaluator_pool_size(config.evaluator_pool_size)
+        .with_evaluator_pool_max_uses(config.evaluator_pool_max_uses)
+        .with_evaluation_cache(config.evaluation_cache.clone())
+        .with_module_cache(module_cache)
+        .with_kubernetes_sync_readiness(config.readiness_requires_kubernetes_sync);
+        if let Some(cluster_name) = config.cluster_name {
+            evaluation_environment_builder =
+                evaluation_environment_builder.with_cluster_name(cluster_name);
+        }
         if let Some(namespace) = config.always_accept_admission_reviews_on_namespace {
             evaluation_environment_builder = evaluation_environment_builder
                 .with_always_accept_admission_reviews_on_namespace(namespace);
         }
-        if let Some(limit) = config.policy_evaluation_limit_seconds {
-            evaluation_environment_builder =
-                evaluation_environment_builder.with_policy_evaluation_limit_seconds(limit);
-        }
-        let evaluation_environment = evaluation_environment_builder.build(&config.policies)?;
-
-        if let Some(limit) = config.policy_evaluation_limit_seconds {
+        if let (Some(limit), Some(settings_validation_limit)) = (
+            config.policy_evaluation_limit_seconds,
+            config.policy_settings_validation_limit_seconds,
+        ) {
+            let epoch_ticker = EpochTicker::new(config.policy_timeout_tick_interval);
+            let epoch_deadlines = EpochDeadlines {
+                evaluation: epoch_ticker.deadline(time::Duration::from_secs(limit)),
+                settings_validation: epoch_ticker
+                    .deadline(time::Duration::from_secs(settings_validation_limit)),
+            };
             info!(
                 execution_limit_seconds = limit,
+                settings_validation_limit_seconds = settings_validation_limit,
+                tick_interval_milliseconds = config.policy_timeout_tick_interval.as_millis() as u64,
+                evaluation_deadline_ticks = epoch_deadlines.evaluation,
+                settings_validation_deadline_ticks = epoch_deadlines.settings_validation,
                 "policy timeout protection is enabled"
             );
 
-            let engine = engine.clone();
-            tokio::spawn(async move {
-                let mut interval = time::interval(time::Duration::from_secs(1));
-                loop {
-                    interval.tick().await;
-                    engine.increment_epoch();
-                }
-            });
+            // the ticker must be running before the environment is built, that's
+            // when the settings of the policies are validated
+            epoch_ticker.start(engine.clone());
+            evaluation_environment_builder = evaluation_environment_builder
+                .with_timeout_protection(epoch_ticker, epoch_deadlines);
         } else {
             info!("policy timeout protection is disabled");
         }
+        let evaluation_environment =
+            Arc::new(evaluation_environment_builder.build(&config.policies)?);
+        for policy in evaluation_environment.policies_catalog() {
+            info!(
+                policy_id = policy.id.as_str(),
+                policy_stable_id = policy.stable_id.as_deref(),
+                "policy loaded"
+            );
+        }
+
+        if let Some(interval) = config.policy_reverification_interval {
+            match (
+                downloader.and_then(|downloader| downloader.into_verified_policies()),
+                config.verification_config.clone(),
+            ) {
+                (Some((verifier, verified_manifest_digests)), Some(verification_config)) => {
+                    PolicyReverifier::new(
+                        verifier,
+                        verification_config,
+                        verified_manifest_digests,
+                        &config.policies,
+                        interval,
+                    )
+                    .start(evaluation_environment.clone());
+                }
+                _ => warn!(
+                    "policy verification is disabled, the policies are not going to be re-verified"
+                ),
+            }
+        }
+
+        if config.readiness_requires_kubernetes_sync {
+            match kubernetes_health_reporter.clone() {
+                Some(reporter) => {
+                    sync_kubernetes_resources(reporter, evaluation_environment.clone())
+                }
+                None => warn!(
+                    "not connected to Kubernetes, context aware policies are going to be reported as syncing"
+                ),
+            }
+        }
+
+        // the policies compiled at bootstrap time come first, followed by the lazily
+        // loaded ones that are part of the warm-up list
+        let mut warm_up_list: Vec<PolicyID> = evaluation_environment
+            .policies_catalog()
+            .into_iter()
+            .filter(|policy| {
+                policy.state != PolicyState::Failed && policy.state != PolicyState::Cold
+            })
+            .filter_map(|policy| policy.id.parse().ok())
+            .collect();
+        for policy_id in &config.lazy_policy_warm_up {
+            match policy_id.parse() {
+                Ok(id) if !warm_up_list.contains(&id) => warm_up_list.push(id),
+                Ok(_) => {}
+                Err(e) => warn!(
+                    policy_id = policy_id.as_str(),
+                    error = %e,
+                    "cannot warm up policy"
+                ),
+            }
+        }
+        warm_up_policies(
+            evaluation_environment.clone(),
+            kubernetes_health_reporter.clone(),
+            warm_up_list,
+        );
+
+        let decision_journal = config
+            .decision_journal
+            .map(|journal_config| DecisionJournal::open(journal_config).map(Arc::new))
+            .transpose()?;
+        let decision_log = match config.decision_log {
+            Some(decision_log_config) => Some(DecisionLog::start(decision_log_config).await?),
+            None => None,
+        };
 
         let state = Arc::new(ApiServerState {
-            semaphore: Semaphore::new(config.pool_size),
-            evaluation_environment: Arc::new(evaluation_environment),
+            dispatcher: PriorityDispatcher::new(
+                config.pool_size,
+                config.priority.starvation_threshold,
+            ),
+            priority_config: config.priority.clone(),
+            evaluation_environment: evaluation_environment.clone(),
+            decision_journal,
+            decision_log,
+            enrichment: config.enrichment.clone(),
+            max_request_body_size: config.max_request_body_size,
+            max_request_body_depth: config.max_request_body_depth,
+            kubernetes_health_reporter,
         });
 
         let tls_config = if let Some(tls_config) = config.tls_config {
@@ -201,18 +414,38 @@ impl PolicyServer {
         } else {
             None
         };
+        let readiness_probe_tls_config = if let Some(tls_config) = config.readiness_probe_tls_config
+        {
+            Some(create_tls_config_and_watch_certificate_changes(tls_config).await?)
+        } else {
+            None
+        };
 
         let mut router = Router::new()
             .route("/audit/{policy_id}", post(audit_handler))
             .route("/validate/{policy_id}", post(validate_handler))
             .route("/validate_raw/{policy_id}", post(validate_raw_handler))
+            .route(
+                "/validate_cloudevent/{policy_id}",
+                post(validate_cloudevent_handler),
+            )
+            .route("/policies", get(policies_handler))
             .with_state(state.clone())
+            .route_layer(middleware::from_fn_with_state(
+                config.max_request_body_size,
+                handle_oversized_requests,
+            ))
+            .layer(DefaultBodyLimit::max(config.max_request_body_size))
             .layer(
                 TraceLayer::new_for_http()
                     .make_span_with(trace::DefaultMakeSpan::new().level(Level::INFO))
                     .on_response(trace::DefaultOnResponse::new().level(Level::INFO)),
             );
 
+        if let Some(module_cache_peer_router) = module_cache_peer_router {
+            router = Router::new().merge(router).merge(module_cache_peer_router);
+        }
+
         if config.enable_pprof {
             activate_memory_profiling().await?;
 
@@ -222,7 +455,49 @@ impl PolicyServer {
             router = Router::new().merge(router).merge(pprof_router);
         }
 
-        let readiness_probe_router = Router::new().route("/readiness", get(readiness_handler));
+        if let Some(window) = config.capabilities.usage_analysis_window {
+            capability_usage::enable(window);
+
+            let capability_usage_router =
+                Router::new().route("/debug/capability-usage", get(capability_usage_handler));
+            router = Router::new().merge(router).merge(capability_usage_router);
+        }
+
+        if let Some(log_filter_file) = config.log_filter_file {
+            match tracing::log_filter() {
+                Some(log_filter) => {
+                    tracing::log_filter::reload_on_sigusr1(log_filter.clone(), log_filter_file)?
+                }
+                None => warn!(
+                    "tracing system not initialized, SIGUSR1 will not reload the tracing filter"
+                ),
+            }
+        }
+
+        if config.response_compression {
+            // gzip and deflate encodings are used only when accepted by the client
+            router = router.layer(CompressionLayer::new());
+        }
+
+        let mut readiness_probe_router = Router::new()
+            .route("/readiness", get(readiness_handler))
+            .route("/readyz", get(readyz_handler))
+            .route("/readyz/kubernetes", get(readyz_kubernetes_handler))
+            .with_state(state);
+
+        // The admin endpoint is not exposed on the webhook port, which is
+        // reachable by everything that can talk with the API server
+        if config.enable_log_filter_admin {
+            let log_filter_router = Router::new().route(
+                "/admin/log-filter",
+                put(log_filter_put_handler)
+                    .get(log_filter_get_handler)
+                    .delete(log_filter_delete_handler),
+            );
+            readiness_probe_router = Router::new()
+                .merge(readiness_probe_router)
+                .merge(log_filter_router);
+        }
 
         Ok(Self {
             router,
@@ -232,6 +507,7 @@ impl PolicyServer {
             addr: config.addr,
             tls_config,
             readiness_probe_addr: config.readiness_probe_addr,
+            readiness_probe_tls_config,
         })
     }
 
@@ -250,21 +526,43 @@ impl PolicyServer {
                 let server_with_tls = axum_server::bind_rustls(self.addr, tls_config);
                 notify.notify_one();
 
-                server_with_tls.serve(self.router.into_make_service()).await
+                server_with_tls
+                    .serve(
+                        self.router
+                            .into_make_service_with_connect_info::<SocketAddr>(),
+                    )
+                    .await
             } else {
                 let server = axum_server::bind(self.addr);
                 notify.notify_one();
 
-                server.serve(self.router.into_make_service()).await
+                server
+                    .serve(
+                        self.router
+                            .into_make_service_with_connect_info::<SocketAddr>(),
+                    )
+                    .await
             }
         };
 
         let readiness_probe_server = async {
             notify.notified().await;
 
-            axum_server::bind(self.readiness_probe_addr)
-                .serve(self.readiness_probe_router.into_make_service())
-                .await
+            if let Some(tls_config) = self.readiness_probe_tls_config {
+                axum_server::bind_rustls(self.readiness_probe_addr, tls_config)
+                    .serve(
+                        self.readiness_probe_router
+                            .into_make_service_with_connect_info::<SocketAddr>(),
+                    )
+                    .await
+            } else {
+                axum_server::bind(self.readiness_probe_addr)
+                    .serve(
+                        self.readiness_probe_router
+                            .into_make_service_with_connect_info::<SocketAddr>(),
+                    )
+                    .await
+            }
         };
 
         tokio::try_join!(api_server, readiness_probe_server)?;
@@ -282,28 +580,159 @@ impl PolicyServer {
     pub fn router(&self) -> Router {
         self.router.clone()
     }
+
+    pub fn readiness_probe_router(&self) -> Router {
+        self.readiness_probe_router.clone()
+    }
 }
 
-fn precompile_policies(
-    engine: &wasmtime::Engine,
-    fetched_policies: &FetchedPolicies,
-) -> PrecompiledPolicies {
-    debug!(
-        wasm_modules_count = fetched_policies.len(),
-        "instantiating wasmtime::Module objects"
+/// Split the fetched policies between the ones that must be precompiled at bootstrap time,
+/// and the ones that can be compiled the first time they are used.
+///
+/// The members of policy groups are always precompiled, because the settings of the group
+/// are validated at bootstrap time. Policies that could not be fetched are kept among
+/// the precompiled ones, this way the error is reported as usual.
+fn split_lazy_policies(
+    policies: &HashMap<String, PolicyOrPolicyGroup>,
+    fetched_policies: FetchedPolicies,
+) -> (FetchedPolicies, HashMap<String, PathBuf>) {
+    let policy_group_modules: HashSet<&str> = policies
+        .values()
+        .filter_map(|policy| match policy {
+            PolicyOrPolicyGroup::PolicyGroup { policies, .. } => Some(policies),
+            PolicyOrPolicyGroup::Policy { .. } => None,
+        })
+        .flat_map(|members| members.values().map(|member| member.module.as_str()))
+        .collect();
+
+    let mut precompiled_policies = FetchedPolicies::new();
+    let mut lazy_policies = HashMap::new();
+    for (policy_url, fetched_policy) in fetched_policies {
+        match fetched_policy {
+            Ok(path) if !policy_group_modules.contains(policy_url.as_str()) => {
+                lazy_policies.insert(policy_url, path);
+            }
+            fetched_policy => {
+                precompiled_policies.insert(policy_url, fetched_policy);
+            }
+        }
+    }
+
+    (precompiled_policies, lazy_policies)
+}
+
+/// Start a background task that performs the initial sync of the Kubernetes resources
+/// accessed by the context aware policies. Resources that cannot be synced are retried
+/// until they succeed. Must be invoked from within a tokio runtime
+fn sync_kubernetes_resources(
+    reporter: KubernetesHealthReporter,
+    evaluation_environment: Arc<EvaluationEnvironment>,
+) {
+    let mut pending = evaluation_environment.kubernetes_resources_pending_sync();
+    if pending.is_empty() {
+        return;
+    }
+    info!(
+        resources = pending.len(),
+        "syncing the Kubernetes resources of the context aware policies"
     );
 
-    fetched_policies
-        .par_iter()
-        .map(|(policy_url, fetched_policy)| match fetched_policy {
-            Ok(policy) => {
-                let precompiled_policy = PrecompiledPolicy::new(engine, policy);
-                debug!(?policy_url, "module compiled");
-                (policy_url.clone(), precompiled_policy)
+    tokio::spawn(async move {
+        loop {
+            let mut failed = BTreeSet::new();
+            for resource in pending {
+                match reporter.sync(&resource).await {
+                    Ok(()) => evaluation_environment.mark_kubernetes_resource_as_synced(&resource),
+                    Err(e) => {
+                        warn!(
+                            api_version = resource.api_version.as_str(),
+                            kind = resource.kind.as_str(),
+                            error = %e,
+                            "cannot sync Kubernetes resource, retrying"
+                        );
+                        failed.insert(resource);
+                    }
+                }
             }
-            Err(error) => (policy_url.clone(), Err(anyhow!(error.to_string()))),
-        })
-        .collect()
+            if failed.is_empty() {
+                info!("Kubernetes resources of the context aware policies are synced");
+                return;
+            }
+            pending = failed;
+            time::sleep(KUBERNETES_SYNC_RETRY_INTERVAL).await;
+        }
+    });
+}
+
+/// Start a background task that instantiates the given policies, in the given order, so
+/// that they become warm without waiting for live traffic. The Kubernetes resources accessed
+/// by the context aware policies are synced first: the policies whose resources cannot be
+/// synced are retried until they succeed. Must be invoked from within a tokio runtime
+fn warm_up_policies(
+    evaluation_environment: Arc<EvaluationEnvironment>,
+    reporter: Option<KubernetesHealthReporter>,
+    mut pending: Vec<PolicyID>,
+) {
+    tokio::spawn(async move {
+        loop {
+            let mut failed = Vec::new();
+            for policy_id in pending {
+                let resources = evaluation_environment.kubernetes_resources_accessed_by(&policy_id);
+                if !resources.is_empty() {
+                    let Some(reporter) = &reporter else {
+                        warn!(
+                            %policy_id,
+                            "not connected to Kubernetes, the context aware policy cannot be warmed up"
+                        );
+                        continue;
+                    };
+                    let mut synced = true;
+                    for resource in &resources {
+                        if let Err(e) = reporter.sync(resource).await {
+                            warn!(
+                                %policy_id,
+                                api_version = resource.api_version.as_str(),
+                                kind = resource.kind.as_str(),
+                                error = %e,
+                                "cannot sync Kubernetes resource, retrying the warm-up of the policy"
+                            );
+                            synced = false;
+                            break;
+                        }
+                    }
+                    if !synced {
+                        failed.push(policy_id);
+                        continue;
+                    }
+                }
+
+                let environment = evaluation_environment.clone();
+                let id = policy_id.clone();
+                let result = tokio::task::spawn_blocking(move || environment.instantiate(&id))
+                    .await
+                    .map_err(|e| e.to_string())
+                    .and_then(|result| result.map_err(|e| e.to_string()));
+                if let Err(e) = result {
+                    warn!(%policy_id, error = e, "cannot warm up policy");
+                }
+            }
+            if failed.is_empty() {
+                info!("policies warm-up completed");
+                return;
+            }
+            pending = failed;
+            time::sleep(KUBERNETES_SYNC_RETRY_INTERVAL).await;
+        }
+    });
+}
+
+/// Create the engine used to compile and run the policies
+fn create_wasmtime_engine(config: &Config) -> Result<wasmtime::Engine> {
+    let mut wasmtime_config = wasmtime::Config::new();
+    if config.policy_evaluation_limit_seconds.is_some() {
+        wasmtime_config.epoch_interruption(true);
+    }
+    wasmtime::Engine::new(&wasmtime_config)
 }
 
 async fn create_sigstore_trustroot(config: &Config) -> Result<Arc<ManualTrustRoot<'static>>> {
@@ -334,3 +763,11 @@ async fn create_sigstore_trustroot(config: &Config) -> Result<Arc<ManualTrustRoo
 
     Ok(Arc::new(manual_root))
 }
+
+/// Create the client used to interact with Kubernetes, returning also the
+/// configuration it has been created from
+async fn build_kube_client() -> Result<(kube::Client, kube::Config)> {
+    let config = kube::Config::infer().await?;
+    let client = kube::Client::try_from(config.clone())?;
+    Ok((client, config))
+}
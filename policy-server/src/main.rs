@@ -2,12 +2,15 @@ mod cli;
 
 use std::fs;
 use std::io::prelude::*;
+use std::path::{Path, PathBuf};
 
 use ::tracing::info;
 use anyhow::anyhow;
 use anyhow::Result;
 use clap::ArgMatches;
+use policy_server::journal;
 use policy_server::metrics::setup_metrics;
+use policy_server::state;
 use policy_server::tracing::setup_tracing;
 use policy_server::PolicyServer;
 
@@ -23,6 +26,12 @@ async fn main() -> Result<()> {
     if matches.subcommand_name() == Some("docs") {
         return run_docs_subcommand(matches.subcommand_matches("docs"));
     }
+    if let Some(("journal", journal_matches)) = matches.subcommand() {
+        return run_journal_subcommand(journal_matches);
+    }
+    if let Some(("state", state_matches)) = matches.subcommand() {
+        return run_state_subcommand(&matches, state_matches).await;
+    }
 
     let config = policy_server::config::Config::from_args(&matches)?;
 
@@ -76,3 +85,95 @@ fn run_docs_subcommand(matches: Option<&ArgMatches>) -> Result<()> {
     }
     Ok(())
 }
+
+/// Handle the journal subcommand, used to inspect the decision journal
+fn run_journal_subcommand(matches: &ArgMatches) -> Result<()> {
+    if let Some(("export", matches)) = matches.subcommand() {
+        let dir = matches.get_one::<String>("dir").unwrap();
+        let since = matches
+            .get_one::<String>("since")
+            .map(|since| journal::parse_since(since))
+            .transpose()?;
+
+        let summary = match matches.get_one::<String>("output") {
+            Some(output) => {
+                let mut file = std::fs::File::create(output)
+                    .map_err(|e| anyhow!("cannot create file {}: {}", output, e))?;
+                journal::export(Path::new(dir), since, &mut file)?
+            }
+            None => journal::export(Path::new(dir), since, &mut std::io::stdout().lock())?,
+        };
+        if summary.corrupted > 0 {
+            eprintln!(
+                "skipped {} corrupted or truncated entries",
+                summary.corrupted
+            );
+        }
+    }
+    Ok(())
+}
+
+/// Handle the state subcommand, used to export and import the state of Policy Server.
+/// The export relies on the configuration given to the root command
+async fn run_state_subcommand(root_matches: &ArgMatches, matches: &ArgMatches) -> Result<()> {
+    match matches.subcommand() {
+        Some(("export", matches)) => {
+            if root_matches.contains_id("state-dir") {
+                return Err(anyhow!(
+                    "the state cannot be exported from a state directory"
+                ));
+            }
+            let config = policy_server::config::Config::from_args(root_matches)?;
+            let tracer_provider =
+                setup_tracing(&config.log_level, &config.log_fmt, config.log_no_color)?;
+
+            let config_files = state::ConfigFiles {
+                policies: root_matches
+                    .get_one::<String>("policies")
+                    .map(PathBuf::from)
+                    .expect("This should not happen, there's a default value for policies"),
+                sources: root_matches
+                    .get_one::<String>("sources-path")
+                    .map(PathBuf::from),
+                verification_config: root_matches
+                    .get_one::<String>("verification-path")
+                    .map(PathBuf::from),
+            };
+            let output = matches.get_one::<String>("output").unwrap();
+            let manifest = state::export(&config, &config_files, Path::new(output)).await?;
+
+            let failed = manifest
+                .modules
+                .values()
+                .filter(|module| module.error.is_some())
+                .count()
+                + manifest
+                    .rego_libraries
+                    .values()
+                    .filter(|library| library.error.is_some())
+                    .count();
+            info!(
+                output,
+                modules = manifest.modules.len(),
+                rego_libraries = manifest.rego_libraries.len(),
+                failed,
+                "state exported"
+            );
+
+            if let Some(trace_provider) = tracer_provider {
+                trace_provider.shutdown()?;
+            }
+        }
+        Some(("import", matches)) => {
+            let archive = matches.get_one::<String>("archive").unwrap();
+            let dir = matches.get_one::<String>("dir").unwrap();
+            let manifest = state::import(Path::new(archive), Path::new(dir))?;
+            println!(
+                "state exported by Policy Server {} restored inside of {}, start Policy Server with --state-dir={}",
+                manifest.policy_server_version, dir, dir
+            );
+        }
+        _ => {}
+    }
+    Ok(())
+}
@@ -6,6 +6,22 @@ mod policy_evaluations_total;
 pub use policy_evaluations_total::add_policy_evaluation;
 mod policy_evaluations_latency;
 pub use policy_evaluations_latency::record_policy_latency;
+mod policy_compilations;
+pub(crate) use policy_compilations::record_policy_compilation;
+mod oversized_requests;
+pub(crate) use oversized_requests::add_oversized_request;
+mod throttled_operations;
+pub(crate) use throttled_operations::add_throttled_operation;
+mod dispatch_latency;
+pub(crate) use dispatch_latency::record_dispatch_latency;
+mod policy_evaluation_epochs;
+pub(crate) use policy_evaluation_epochs::record_policy_epochs;
+mod policy_evaluations_failed_open;
+pub(crate) use policy_evaluations_failed_open::add_failed_open_evaluation;
+mod policy_reverification_failures;
+pub(crate) use policy_reverification_failures::add_policy_reverification_failure;
+mod decision_log_records_dropped;
+pub(crate) use decision_log_records_dropped::add_dropped_decision_records;
 
 use crate::config::build_client_tls_config_from_env;
 
@@ -33,6 +49,7 @@ pub trait PolicyEvaluationMetric: Into<Vec<KeyValue>> {}
 #[derive(Clone)]
 pub(crate) struct PolicyEvaluation {
     pub(crate) policy_name: String,
+    pub(crate) policy_stable_id: Option<String>,
     pub(crate) policy_mode: String,
     pub(crate) resource_kind: String,
     pub(crate) resource_namespace: Option<String>,
@@ -60,6 +77,12 @@ impl Into<Vec<KeyValue>> for &PolicyEvaluation {
             KeyValue::new("mutated", self.mutated),
             KeyValue::new("request_origin", self.request_origin.clone()),
         ];
+        if let Some(policy_stable_id) = &self.policy_stable_id {
+            baggage.append(&mut vec![KeyValue::new(
+                "policy_stable_id",
+                policy_stable_id.clone(),
+            )]);
+        }
         if let Some(resource_namespace) = &self.resource_namespace {
             baggage.append(&mut vec![KeyValue::new(
                 "resource_namespace",
@@ -76,6 +99,7 @@ impl Into<Vec<KeyValue>> for &PolicyEvaluation {
 #[derive(Clone)]
 pub(crate) struct RawPolicyEvaluation {
     pub(crate) policy_name: String,
+    pub(crate) policy_stable_id: Option<String>,
     pub(crate) policy_mode: String,
     pub(crate) accepted: bool,
     pub(crate) mutated: bool,
@@ -94,6 +118,12 @@ impl Into<Vec<KeyValue>> for &RawPolicyEvaluation {
             KeyValue::new("mutated", self.mutated),
         ];
 
+        if let Some(policy_stable_id) = &self.policy_stable_id {
+            baggage.append(&mut vec![KeyValue::new(
+                "policy_stable_id",
+                policy_stable_id.clone(),
+            )]);
+        }
         if let Some(error_code) = self.error_code {
             baggage.append(&mut vec![KeyValue::new("error_code", error_code as i64)]);
         }
@@ -118,3 +148,148 @@ impl Into<Vec<KeyValue>> for &PolicyInitializationError {
         ]
     }
 }
+
+/// The compilation of a policy that has been loaded lazily
+#[derive(Clone)]
+pub(crate) struct PolicyCompilation {
+    pub(crate) policy_name: String,
+    /// What caused the compilation: `on-demand` or `warm-up`
+    pub(crate) trigger: String,
+    pub(crate) success: bool,
+}
+
+#[allow(clippy::from_over_into)]
+impl Into<Vec<KeyValue>> for &PolicyCompilation {
+    fn into(self) -> Vec<KeyValue> {
+        vec![
+            KeyValue::new("policy_name", self.policy_name.clone()),
+            KeyValue::new("trigger", self.trigger.clone()),
+            KeyValue::new("success", self.success),
+        ]
+    }
+}
+
+/// A request rejected because its body exceeds the maximum allowed size
+#[derive(Clone)]
+pub(crate) struct OversizedRequest {
+    /// The route targeted by the request, e.g. `/validate/{policy_id}`
+    pub(crate) route: String,
+}
+
+#[allow(clippy::from_over_into)]
+impl Into<Vec<KeyValue>> for &OversizedRequest {
+    fn into(self) -> Vec<KeyValue> {
+        vec![KeyValue::new("route", self.route.clone())]
+    }
+}
+
+/// An operation made while downloading or verifying a policy that has been
+/// rate limited by the remote server
+#[derive(Clone)]
+pub(crate) struct ThrottledOperation {
+    /// The registry or HTTP server that throttled the operation
+    pub(crate) host: String,
+    /// The kind of operation: `verify`, `fetch` or `verify-checksum`
+    pub(crate) operation: String,
+    /// Whether the operation is going to be retried
+    pub(crate) retried: bool,
+}
+
+#[allow(clippy::from_over_into)]
+impl Into<Vec<KeyValue>> for &ThrottledOperation {
+    fn into(self) -> Vec<KeyValue> {
+        vec![
+            KeyValue::new("host", self.host.clone()),
+            KeyValue::new("operation", self.operation.clone()),
+            KeyValue::new("retried", self.retried),
+        ]
+    }
+}
+
+/// A request dispatched to the workers according to its priority class
+#[derive(Clone)]
+pub(crate) struct DispatchLatency {
+    /// The priority class of the request: `critical`, `normal` or `bulk`
+    pub(crate) priority_class: String,
+}
+
+#[allow(clippy::from_over_into)]
+impl Into<Vec<KeyValue>> for &DispatchLatency {
+    fn into(self) -> Vec<KeyValue> {
+        vec![KeyValue::new("priority_class", self.priority_class.clone())]
+    }
+}
+
+/// The epoch ticks consumed by a policy while running its guest code
+#[derive(Clone)]
+pub(crate) struct PolicyEpochs {
+    pub(crate) policy_name: String,
+    pub(crate) policy_stable_id: Option<String>,
+    /// The operation performed by the policy: `validate` or `validate_settings`
+    pub(crate) operation: String,
+}
+
+#[allow(clippy::from_over_into)]
+impl Into<Vec<KeyValue>> for &PolicyEpochs {
+    fn into(self) -> Vec<KeyValue> {
+        let mut baggage = vec![
+            KeyValue::new("policy_name", self.policy_name.clone()),
+            KeyValue::new("operation", self.operation.clone()),
+        ];
+        if let Some(policy_stable_id) = &self.policy_stable_id {
+            baggage.push(KeyValue::new("policy_stable_id", policy_stable_id.clone()));
+        }
+        baggage
+    }
+}
+
+/// A request accepted because the policy failed to evaluate it and its failure
+/// policy is `Ignore`
+#[derive(Clone)]
+pub(crate) struct FailedOpenEvaluation {
+    pub(crate) policy_name: String,
+    pub(crate) policy_stable_id: Option<String>,
+}
+
+#[allow(clippy::from_over_into)]
+impl Into<Vec<KeyValue>> for &FailedOpenEvaluation {
+    fn into(self) -> Vec<KeyValue> {
+        let mut baggage = vec![KeyValue::new("policy_name", self.policy_name.clone())];
+        if let Some(policy_stable_id) = &self.policy_stable_id {
+            baggage.push(KeyValue::new("policy_stable_id", policy_stable_id.clone()));
+        }
+        baggage
+    }
+}
+
+/// A policy that failed its periodic re-verification
+#[derive(Clone)]
+pub(crate) struct PolicyReverificationFailure {
+    pub(crate) policy_name: String,
+    pub(crate) policy_stable_id: Option<String>,
+}
+
+#[allow(clippy::from_over_into)]
+impl Into<Vec<KeyValue>> for &PolicyReverificationFailure {
+    fn into(self) -> Vec<KeyValue> {
+        let mut baggage = vec![KeyValue::new("policy_name", self.policy_name.clone())];
+        if let Some(policy_stable_id) = &self.policy_stable_id {
+            baggage.push(KeyValue::new("policy_stable_id", policy_stable_id.clone()));
+        }
+        baggage
+    }
+}
+
+/// Records of the decision log that have been dropped instead of being written
+#[derive(Clone)]
+pub(crate) struct DroppedDecisionRecords {
+    /// Why the records have been dropped: `queue-full`, `sink-error` or `closed`
+    pub(crate) reason: String,
+}
+
+#[allow(clippy::from_over_into)]
+impl Into<Vec<KeyValue>> for &DroppedDecisionRecords {
+    fn into(self) -> Vec<KeyValue> {
+        vec![KeyValue::new("reason", self.reason.clone())]
+    }
+}
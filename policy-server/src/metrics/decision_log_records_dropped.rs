@@ -0,0 +1,16 @@
+use lazy_static::lazy_static;
+use opentelemetry::{metrics::Counter, KeyValue};
+
+use crate::metrics::DroppedDecisionRecords;
+
+lazy_static! {
+    static ref DECISION_LOG_RECORDS_DROPPED_TOTAL: Counter<u64> =
+        opentelemetry::global::meter(super::METER_NAME)
+            .u64_counter("kubewarden_decision_log_records_dropped_total")
+            .build();
+}
+
+pub(crate) fn add_dropped_decision_records(count: u64, dropped_records: &DroppedDecisionRecords) {
+    let attributes: Vec<KeyValue> = dropped_records.into();
+    DECISION_LOG_RECORDS_DROPPED_TOTAL.add(count, &attributes);
+}
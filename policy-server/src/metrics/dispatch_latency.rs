@@ -0,0 +1,31 @@
+use lazy_static::lazy_static;
+use opentelemetry::{metrics::Histogram, KeyValue};
+use std::convert::TryFrom;
+use std::time::Duration;
+
+use crate::metrics::DispatchLatency;
+
+lazy_static! {
+    static ref DISPATCH_QUEUE_LATENCY: Histogram<u64> =
+        opentelemetry::global::meter(super::METER_NAME)
+            .u64_histogram("kubewarden_dispatch_queue_latency_milliseconds")
+            .build();
+    static ref DISPATCH_TOTAL_LATENCY: Histogram<u64> =
+        opentelemetry::global::meter(super::METER_NAME)
+            .u64_histogram("kubewarden_dispatch_total_latency_milliseconds")
+            .build();
+}
+
+/// Record the time spent by a request waiting for a worker, and the time it took
+/// to be waited and evaluated
+pub(crate) fn record_dispatch_latency(
+    queue_latency: Duration,
+    total_latency: Duration,
+    dispatch_latency: &DispatchLatency,
+) {
+    let attributes: Vec<KeyValue> = dispatch_latency.into();
+    let millis = |latency: Duration| u64::try_from(latency.as_millis()).unwrap_or(u64::MAX);
+
+    DISPATCH_QUEUE_LATENCY.record(millis(queue_latency), &attributes);
+    DISPATCH_TOTAL_LATENCY.record(millis(total_latency), &attributes);
+}
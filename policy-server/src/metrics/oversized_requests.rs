@@ -0,0 +1,16 @@
+use lazy_static::lazy_static;
+use opentelemetry::{metrics::Counter, KeyValue};
+
+use crate::metrics::OversizedRequest;
+
+lazy_static! {
+    static ref OVERSIZED_REQUESTS_TOTAL: Counter<u64> =
+        opentelemetry::global::meter(super::METER_NAME)
+            .u64_counter("kubewarden_oversized_requests_total")
+            .build();
+}
+
+pub(crate) fn add_oversized_request(oversized_request: &OversizedRequest) {
+    let attributes: Vec<KeyValue> = oversized_request.into();
+    OVERSIZED_REQUESTS_TOTAL.add(1, &attributes);
+}
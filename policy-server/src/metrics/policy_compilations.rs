@@ -0,0 +1,28 @@
+use lazy_static::lazy_static;
+use opentelemetry::{
+    metrics::{Counter, Histogram},
+    KeyValue,
+};
+use std::convert::TryFrom;
+use std::time::Duration;
+
+use crate::metrics::PolicyCompilation;
+
+lazy_static! {
+    static ref POLICY_COMPILATIONS_TOTAL: Counter<u64> =
+        opentelemetry::global::meter(super::METER_NAME)
+            .u64_counter("kubewarden_policy_lazy_compilations_total")
+            .build();
+    static ref POLICY_COMPILATION_LATENCY: Histogram<u64> =
+        opentelemetry::global::meter(super::METER_NAME)
+            .u64_histogram("kubewarden_policy_lazy_compilation_latency_milliseconds")
+            .build();
+}
+
+pub(crate) fn record_policy_compilation(latency: Duration, policy_compilation: &PolicyCompilation) {
+    let attributes: Vec<KeyValue> = policy_compilation.into();
+    let millis_latency = u64::try_from(latency.as_millis()).unwrap_or(u64::MAX);
+
+    POLICY_COMPILATIONS_TOTAL.add(1, &attributes);
+    POLICY_COMPILATION_LATENCY.record(millis_latency, &attributes);
+}
@@ -0,0 +1,17 @@
+use lazy_static::lazy_static;
+use opentelemetry::{metrics::Histogram, KeyValue};
+
+use crate::metrics::PolicyEpochs;
+
+lazy_static! {
+    static ref POLICY_EVALUATION_EPOCHS: Histogram<u64> =
+        opentelemetry::global::meter(super::METER_NAME)
+            .u64_histogram("kubewarden_policy_evaluation_epoch_ticks")
+            .build();
+}
+
+/// Record the number of epoch ticks consumed by a policy. Comparing this value with
+/// the deadline shows how close the policy is to being interrupted
+pub(crate) fn record_policy_epochs(epochs: u64, policy_epochs: &PolicyEpochs) {
+    POLICY_EVALUATION_EPOCHS.record(epochs, &Into::<Vec<KeyValue>>::into(policy_epochs));
+}
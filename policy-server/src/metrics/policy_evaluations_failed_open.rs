@@ -0,0 +1,16 @@
+use lazy_static::lazy_static;
+use opentelemetry::{metrics::Counter, KeyValue};
+
+use crate::metrics::FailedOpenEvaluation;
+
+lazy_static! {
+    static ref POLICY_EVALUATIONS_FAILED_OPEN_TOTAL: Counter<u64> =
+        opentelemetry::global::meter(super::METER_NAME)
+            .u64_counter("kubewarden_policy_evaluations_failed_open_total")
+            .build();
+}
+
+pub(crate) fn add_failed_open_evaluation(failed_open_evaluation: &FailedOpenEvaluation) {
+    let attributes: Vec<KeyValue> = failed_open_evaluation.into();
+    POLICY_EVALUATIONS_FAILED_OPEN_TOTAL.add(1, &attributes);
+}
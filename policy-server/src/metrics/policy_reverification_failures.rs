@@ -0,0 +1,16 @@
+use lazy_static::lazy_static;
+use opentelemetry::{metrics::Counter, KeyValue};
+
+use crate::metrics::PolicyReverificationFailure;
+
+lazy_static! {
+    static ref POLICY_REVERIFICATION_FAILURES_TOTAL: Counter<u64> =
+        opentelemetry::global::meter(super::METER_NAME)
+            .u64_counter("kubewarden_policy_reverification_failures_total")
+            .build();
+}
+
+pub(crate) fn add_policy_reverification_failure(failure: &PolicyReverificationFailure) {
+    let attributes: Vec<KeyValue> = failure.into();
+    POLICY_REVERIFICATION_FAILURES_TOTAL.add(1, &attributes);
+}
@@ -0,0 +1,16 @@
+use lazy_static::lazy_static;
+use opentelemetry::{metrics::Counter, KeyValue};
+
+use crate::metrics::ThrottledOperation;
+
+lazy_static! {
+    static ref THROTTLED_OPERATIONS_TOTAL: Counter<u64> =
+        opentelemetry::global::meter(super::METER_NAME)
+            .u64_counter("kubewarden_policy_fetch_throttled_operations_total")
+            .build();
+}
+
+pub(crate) fn add_throttled_operation(throttled_operation: &ThrottledOperation) {
+    let attributes: Vec<KeyValue> = throttled_operation.into();
+    THROTTLED_OPERATIONS_TOTAL.add(1, &attributes);
+}
@@ -1,7 +1,10 @@
 use anyhow::{anyhow, Result};
+use futures::{stream, StreamExt};
 use policy_evaluator::{
     policy_fetcher,
     policy_fetcher::{
+        download::{remote_host, DownloadManager, FetcherOptions, ThrottledOperation},
+        rego_library::{fetch_rego_library, RegoLibraryDocument},
         sigstore,
         sources::Sources,
         verify::{config::LatestVerificationConfig, Verifier},
@@ -12,21 +15,30 @@ use sigstore::trust::ManualTrustRoot;
 use std::{
     collections::{HashMap, HashSet},
     path::{Path, PathBuf},
-    sync::Arc,
+    sync::{Arc, Mutex},
 };
 use tracing::{debug, error, info};
 
 use crate::config::PolicyOrPolicyGroup;
+use crate::metrics;
 
 /// A Map with the `policy.url` as key,
 /// and a `PathBuf` as value. The `PathBuf` points to the location where
 /// the WebAssembly module has been downloaded.
 pub(crate) type FetchedPolicies = HashMap<String, Result<PathBuf>>;
 
+/// A Map with the URL of a Rego library as key, and the document of the
+/// library as value
+pub(crate) type FetchedRegoLibraries = HashMap<String, Result<RegoLibraryDocument>>;
+
 /// Handles download and verification of policies
 pub(crate) struct Downloader {
     verifier: Option<Verifier>,
     sources: Option<Sources>,
+    download_manager: DownloadManager,
+    /// The manifest digest of the policies that have been verified, keyed by
+    /// policy URL
+    verified_manifest_digests: Mutex<HashMap<String, String>>,
 }
 
 impl Downloader {
@@ -46,7 +58,31 @@ impl Downloader {
             None
         };
 
-        Ok(Downloader { verifier, sources })
+        Ok(Downloader {
+            verifier,
+            sources,
+            download_manager: download_manager(FetcherOptions::default()),
+            verified_manifest_digests: Mutex::new(HashMap::new()),
+        })
+    }
+
+    /// Limit the number of concurrent operations and space out the requests
+    /// made against the same registry
+    pub fn with_fetch_config(mut self, fetch_config: FetcherOptions) -> Self {
+        self.download_manager = download_manager(fetch_config);
+        self
+    }
+
+    /// Consume the downloader, returning the verifier used to check the policies
+    /// together with the manifest digest of each verified policy, keyed by policy URL.
+    /// `None` when the verification of the policies is disabled
+    pub fn into_verified_policies(self) -> Option<(Verifier, HashMap<String, String>)> {
+        let verified_manifest_digests = self
+            .verified_manifest_digests
+            .into_inner()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        self.verifier
+            .map(|verifier| (verifier, verified_manifest_digests))
     }
 
     /// Download all the policies to the given destination
@@ -64,6 +100,7 @@ impl Downloader {
                 .to_str()
                 .expect("cannot convert path to string"),
             policies_count = policies_total,
+            concurrency = self.download_manager.options().concurrency,
             status = "init",
             "policies download",
         );
@@ -71,6 +108,7 @@ impl Downloader {
         let verification_config = verification_config.unwrap_or(&LatestVerificationConfig {
             all_of: None,
             any_of: None,
+            trust_roots: None,
         });
 
         // The same WebAssembly module can be referenced by multiple policies,
@@ -80,10 +118,7 @@ impl Downloader {
         // Note: this Set includes both successful downloads and ones that
         // failed.
         let mut processed_policies: HashSet<&str> = HashSet::new();
-
-        // List of policies that have been successfully fetched.
-        // This can be a subset of `processed_policies`
-        let mut fetched_policies: FetchedPolicies = HashMap::new();
+        let mut policies_to_process: Vec<(String, String)> = Vec::new();
 
         for (name, policy_url) in policies.iter() {
             debug!(policy = name.as_str(), "download");
@@ -95,127 +130,161 @@ impl Downloader {
 
                 continue;
             }
+            policies_to_process.push((name.to_owned(), policy_url.to_owned()));
+        }
 
-            let mut verified_manifest_digest: Option<String> = None;
+        let downloader = &*self;
+        let destination = destination.as_ref();
+
+        stream::iter(policies_to_process)
+            // the futures own the name and the url of the policy: borrowing them
+            // would prevent the server from being spawned on the tokio runtime
+            .map(|(name, policy_url)| async move {
+                let result = downloader
+                    .download_policy(&name, &policy_url, destination, verification_config)
+                    .await;
+                (policy_url, result)
+            })
+            .buffer_unordered(self.download_manager.options().concurrency.max(1))
+            .collect()
+            .await
+    }
 
-            if let Some(ver) = self.verifier.as_mut() {
-                info!(
-                    policy = name.as_str(),
-                    "verifying policy authenticity and integrity using sigstore"
-                );
-                verified_manifest_digest = match ver.verify(policy_url, verification_config).await {
-                    Ok(d) => Some(d),
-                    Err(e) => {
-                        error!(policy = name.as_str(), error =?e, "policy cannot be verified");
-                        fetched_policies.insert(
-                            policy_url.to_owned(),
-                            Err(anyhow!("Policy '{}' cannot be verified: {}", name, e)),
-                        );
-
-                        continue;
-                    }
-                };
-                info!(
-                    name = name.as_str(),
-                    sha256sum = verified_manifest_digest
-                        .as_ref()
-                        .unwrap_or(&"unknown".to_string())
-                        .as_str(),
-                    status = "verified-signatures",
-                    "policy download",
-                );
-            }
+    /// Download and verify a single policy
+    async fn download_policy(
+        &self,
+        name: &str,
+        policy_url: &str,
+        destination: &Path,
+        verification_config: &LatestVerificationConfig,
+    ) -> Result<PathBuf> {
+        let host = remote_host(policy_url);
+        let host = host.as_deref();
+        let mut verified_manifest_digest: Option<String> = None;
+
+        if let Some(verifier) = self.verifier.as_ref() {
+            info!(
+                policy = name,
+                "verifying policy authenticity and integrity using sigstore"
+            );
+            let verification = self
+                .download_manager
+                .run(host, "verify", name, || {
+                    let mut verifier = verifier.clone();
+                    async move { verifier.verify(policy_url, verification_config).await }
+                })
+                .await;
+            verified_manifest_digest = match verification {
+                Ok(d) => Some(d),
+                Err(e) => {
+                    error!(policy = name, error =?e, "policy cannot be verified");
+                    return Err(anyhow!("Policy '{}' cannot be verified: {}", name, e));
+                }
+            };
+            info!(
+                name,
+                sha256sum = verified_manifest_digest
+                    .as_ref()
+                    .unwrap_or(&"unknown".to_string())
+                    .as_str(),
+                status = "verified-signatures",
+                "policy download",
+            );
+        }
 
-            let fetched_policy = match policy_fetcher::fetch_policy(
+        let fetched_policy = match self
+            .download_manager
+            .fetch_policy(
                 policy_url,
-                policy_fetcher::PullDestination::Store(destination.as_ref().to_path_buf()),
+                &policy_fetcher::PullDestination::Store(destination.to_path_buf()),
                 self.sources.as_ref(),
             )
             .await
-            {
-                Ok(fetched_policy) => fetched_policy,
-                Err(e) => {
-                    error!(
-                        policy = name.as_str(),
-                        error =? e,
-                        "policy download failed"
-                    );
-                    fetched_policies.insert(
-                        policy_url.to_owned(),
-                        Err(anyhow!(
-                            "Error while downloading policy '{}' from {}: {}",
-                            name,
-                            policy_url,
-                            e
-                        )),
-                    );
-
-                    continue;
-                }
-            };
-
-            if let Some(ver) = self.verifier.as_mut() {
-                if let Err(e) = ver
-                    .verify_local_file_checksum(
-                        &fetched_policy,
-                        verified_manifest_digest.as_ref().unwrap(),
-                    )
-                    .await
-                {
-                    error!(
-                        policy = name.as_str(),
-                        error =? e,
-                        "verification failed"
-                    );
-
-                    fetched_policies.insert(
-                        policy_url.to_owned(),
-                        Err(anyhow!("Verification of policy {} failed: {}", name, e)),
-                    );
-                    continue;
-                }
+        {
+            Ok(fetched_policy) => fetched_policy,
+            Err(e) => {
+                error!(policy = name, error =? e, "policy download failed");
+                return Err(anyhow!(
+                    "Error while downloading policy '{}' from {}: {}",
+                    name,
+                    policy_url,
+                    e
+                ));
+            }
+        };
 
-                info!(
-                    name = name.as_str(),
-                    sha256sum = verified_manifest_digest
-                        .as_ref()
-                        .unwrap_or(&"unknown".to_string())
-                        .as_str(),
-                    status = "verified-local-checksum",
-                    "policy download",
-                );
+        if let Some(verifier) = self.verifier.as_ref() {
+            let verified_manifest_digest = verified_manifest_digest.as_ref().unwrap();
+            if let Err(e) = self
+                .download_manager
+                .run(host, "verify-checksum", name, || {
+                    let mut verifier = verifier.clone();
+                    let fetched_policy = &fetched_policy;
+                    async move {
+                        verifier
+                            .verify_local_file_checksum(fetched_policy, verified_manifest_digest)
+                            .await
+                    }
+                })
+                .await
+            {
+                error!(policy = name, error =? e, "verification failed");
+                return Err(anyhow!("Verification of policy {} failed: {}", name, e));
             }
 
-            if let Ok(Some(policy_metadata)) = Metadata::from_path(&fetched_policy.local_path) {
-                info!(
-                    name = name.as_str(),
-                    path = fetched_policy.local_path.clone().into_os_string().to_str(),
-                    sha256sum = fetched_policy
-                        .digest()
-                        .unwrap_or_else(|_| "unknown".to_string())
-                        .as_str(),
-                    mutating = policy_metadata.mutating,
-                    "policy download",
-                );
-            } else {
-                info!(
-                    name = name.as_str(),
-                    path = fetched_policy.local_path.clone().into_os_string().to_str(),
-                    sha256sum = fetched_policy
-                        .digest()
-                        .unwrap_or_else(|_| "unknown".to_string())
-                        .as_str(),
-                    "policy download",
-                );
+            info!(
+                name,
+                sha256sum = verified_manifest_digest.as_str(),
+                status = "verified-local-checksum",
+                "policy download",
+            );
+            if let Ok(mut verified_manifest_digests) = self.verified_manifest_digests.lock() {
+                verified_manifest_digests
+                    .insert(policy_url.to_owned(), verified_manifest_digest.to_owned());
             }
+        }
 
-            fetched_policies.insert(policy_url.to_owned(), Ok(fetched_policy.local_path));
+        if let Ok(Some(policy_metadata)) = Metadata::from_path(&fetched_policy.local_path) {
+            info!(
+                name,
+                path = fetched_policy.local_path.clone().into_os_string().to_str(),
+                sha256sum = fetched_policy
+                    .digest()
+                    .unwrap_or_else(|_| "unknown".to_string())
+                    .as_str(),
+                mutating = policy_metadata.mutating,
+                "policy download",
+            );
+        } else {
+            info!(
+                name,
+                path = fetched_policy.local_path.clone().into_os_string().to_str(),
+                sha256sum = fetched_policy
+                    .digest()
+                    .unwrap_or_else(|_| "unknown".to_string())
+                    .as_str(),
+                "policy download",
+            );
         }
 
-        fetched_policies
+        Ok(fetched_policy.local_path)
     }
 }
 
+/// Create the manager of the downloads, recording the throttled operations
+/// inside of the metrics
+fn download_manager(fetch_config: FetcherOptions) -> DownloadManager {
+    DownloadManager::new(fetch_config).with_throttling_observer(Arc::new(
+        |throttled_operation: &ThrottledOperation| {
+            metrics::add_throttled_operation(&metrics::ThrottledOperation {
+                host: throttled_operation.host.clone(),
+                operation: throttled_operation.operation.clone(),
+                retried: throttled_operation.retried,
+            })
+        },
+    ))
+}
+
 /// Creates a new Verifier that fetches Fulcio and Rekor data from the official
 /// TUF repository of the sigstore project
 async fn create_verifier(
@@ -227,6 +296,46 @@ async fn create_verifier(
     Ok(verifier)
 }
 
+/// Download the Rego libraries referenced by the policies. The libraries are small
+/// JSON documents, they are kept in memory
+pub(crate) async fn download_rego_libraries(
+    policies: &HashMap<String, PolicyOrPolicyGroup>,
+    sources: Option<&Sources>,
+    concurrency: usize,
+) -> FetchedRegoLibraries {
+    let library_urls: HashSet<&str> = policies
+        .values()
+        .filter_map(|policy| match policy {
+            PolicyOrPolicyGroup::Policy { rego_libraries, .. } => Some(rego_libraries.values()),
+            PolicyOrPolicyGroup::PolicyGroup { .. } => None,
+        })
+        .flatten()
+        .map(String::as_str)
+        .collect();
+    if library_urls.is_empty() {
+        return FetchedRegoLibraries::new();
+    }
+    info!(
+        libraries_count = library_urls.len(),
+        status = "init",
+        "rego libraries download"
+    );
+
+    stream::iter(library_urls.into_iter().map(str::to_owned))
+        .map(|url| async move {
+            let result = fetch_rego_library(&url, sources).await;
+            match &result {
+                Ok(_) => info!(url, status = "done", "rego library download"),
+                Err(e) => error!(url, error = %e, "rego library download failed"),
+            }
+            let result = result.map_err(|e| anyhow!("cannot fetch Rego library {url}: {e}"));
+            (url, result)
+        })
+        .buffer_unordered(concurrency.max(1))
+        .collect()
+        .await
+}
+
 /// Group policies need to be flattened into a single list of policies to download
 ///
 /// Return a map with the name of the policy as key, and the its download url as value.
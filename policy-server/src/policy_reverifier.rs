@@ -0,0 +1,272 @@
+use std::{collections::HashMap, sync::Arc, time::Duration};
+
+use policy_evaluator::{
+    admission_response_handler::policy_id::PolicyID,
+    policy_fetcher::verify::{config::LatestVerificationConfig, errors::VerifyError, Verifier},
+};
+use tokio::time::{self, Instant};
+use tracing::{debug, error, info, warn};
+
+use crate::config::PolicyOrPolicyGroup;
+use crate::evaluation::EvaluationEnvironment;
+use crate::metrics::{self, PolicyReverificationFailure};
+
+/// Periodically verifies again the policies that have been verified at bootstrap time.
+///
+/// The manifests verified at bootstrap time are the ones being verified again, by digest:
+/// moving the tag of a policy to a different manifest has no effect on the Wasm modules
+/// being served.
+///
+/// The policies that are no longer trusted are revoked: they start rejecting all the
+/// requests they receive. This happens when the signatures of their manifest no longer
+/// satisfy the verification config, for example because they have been removed or because
+/// the certificate used to produce them is no longer trusted. A revoked policy is served
+/// again once it passes a later re-verification.
+pub(crate) struct PolicyReverifier {
+    verifier: Verifier,
+    verification_config: LatestVerificationConfig,
+    /// The manifest digest verified at bootstrap time, keyed by policy URL
+    verified_manifest_digests: HashMap<String, String>,
+    /// The policies making use of each Wasm module, keyed by policy URL
+    policies_by_url: HashMap<String, Vec<PolicyID>>,
+    interval: Duration,
+}
+
+impl PolicyReverifier {
+    pub(crate) fn new(
+        verifier: Verifier,
+        verification_config: LatestVerificationConfig,
+        verified_manifest_digests: HashMap<String, String>,
+        policies: &HashMap<String, PolicyOrPolicyGroup>,
+        interval: Duration,
+    ) -> Self {
+        PolicyReverifier {
+            verifier,
+            verification_config,
+            verified_manifest_digests,
+            policies_by_url: policies_by_url(policies),
+            interval,
+        }
+    }
+
+    /// Start verifying the policies at each interval. Must be invoked from within a
+    /// tokio runtime
+    pub(crate) fn start(mut self, evaluation_environment: Arc<EvaluationEnvironment>) {
+        info!(
+            interval_seconds = self.interval.as_secs(),
+            policies = self.verified_manifest_digests.len(),
+            "periodic re-verification of the policies is enabled"
+        );
+        tokio::spawn(async move {
+            // the policies have just been verified at bootstrap time
+            let mut interval = time::interval_at(Instant::now() + self.interval, self.interval);
+            loop {
+                interval.tick().await;
+                self.reverify(&evaluation_environment).await;
+            }
+        });
+    }
+
+    /// Verify all the policies once, revoking the ones that are no longer trusted and
+    /// restoring the revoked ones that are trusted again
+    async fn reverify(&mut self, evaluation_environment: &EvaluationEnvironment) {
+        debug!("re-verifying policies");
+        for (policy_url, verified_manifest_digest) in &self.verified_manifest_digests {
+            let pinned_url = pinned_url(policy_url, verified_manifest_digest);
+            let reason = match self
+                .verifier
+                .verify(&pinned_url, &self.verification_config)
+                .await
+            {
+                Ok(manifest_digest) if &manifest_digest == verified_manifest_digest => {
+                    debug!(policy_url, "policy re-verified");
+                    for policy_id in self.policies_by_url.get(policy_url).into_iter().flatten() {
+                        if evaluation_environment.restore_policy(policy_id) {
+                            info!(
+                                policy_id = policy_id.to_string().as_str(),
+                                policy_url,
+                                "policy passed re-verification, serving its requests again"
+                            );
+                        }
+                    }
+                    continue;
+                }
+                // the registry served a different manifest than the one requested
+                Ok(manifest_digest) => format!(
+                    "the registry returned the manifest {manifest_digest} instead of {verified_manifest_digest}"
+                ),
+                Err(e) if is_untrusted(&e) => e.to_string(),
+                Err(e) => {
+                    // keep serving the policy, the verification is attempted again
+                    // at the next interval
+                    warn!(policy_url, error = %e, "cannot re-verify policy");
+                    continue;
+                }
+            };
+
+            for policy_id in self.policies_by_url.get(policy_url).into_iter().flatten() {
+                error!(
+                    policy_id = policy_id.to_string().as_str(),
+                    policy_url,
+                    reason = reason.as_str(),
+                    "policy failed re-verification, rejecting all its requests"
+                );
+                metrics::add_policy_reverification_failure(&PolicyReverificationFailure {
+                    policy_name: policy_id.to_string(),
+                    policy_stable_id: evaluation_environment.get_policy_stable_id(policy_id),
+                });
+                evaluation_environment.revoke_policy(policy_id, &reason);
+            }
+        }
+    }
+}
+
+/// Build the URL of the manifest verified at bootstrap time, by replacing the tag of
+/// the given policy URL with the digest of the manifest
+fn pinned_url(policy_url: &str, manifest_digest: &str) -> String {
+    let url = policy_url
+        .split_once('@')
+        .map_or(policy_url, |(url, _)| url);
+    // the registry host can have a port, the tag comes after the last `/`
+    let name_start = url.rfind('/').map_or(0, |position| position + 1);
+    let url = match url[name_start..].rfind(':') {
+        Some(tag_start) => &url[..name_start + tag_start],
+        None => url,
+    };
+
+    format!("{url}@{manifest_digest}")
+}
+
+/// Whether the verification failed because the policy is no longer trusted, as
+/// opposed to a transient error like the registry not being reachable
+fn is_untrusted(error: &VerifyError) -> bool {
+    matches!(
+        error,
+        VerifyError::ImageVerificationError(_)
+            | VerifyError::KeyVerificationError(_)
+            | VerifyError::ChecksumVerificationError(_)
+    )
+}
+
+/// Map each policy URL to the policies making use of it. Policy groups are revoked as
+/// a whole when one of their members fails the verification
+fn policies_by_url(
+    policies: &HashMap<String, PolicyOrPolicyGroup>,
+) -> HashMap<String, Vec<PolicyID>> {
+    let mut policies_by_url: HashMap<String, Vec<PolicyID>> = HashMap::new();
+
+    for (name, policy) in policies {
+        let policy_id = PolicyID::Policy(name.to_owned());
+        match policy {
+            PolicyOrPolicyGroup::Policy { module, .. } => {
+                policies_by_url
+                    .entry(module.to_owned())
+                    .or_default()
+                    .push(policy_id);
+            }
+            PolicyOrPolicyGroup::PolicyGroup { policies, .. } => {
+                for member in policies.values() {
+                    let policy_ids = policies_by_url.entry(member.module.to_owned()).or_default();
+                    if !policy_ids.contains(&policy_id) {
+                        policy_ids.push(policy_id.clone());
+                    }
+                }
+            }
+        }
+    }
+    for policy_ids in policies_by_url.values_mut() {
+        policy_ids.sort_by_key(|policy_id| policy_id.to_string());
+    }
+
+    policies_by_url
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rstest::rstest;
+
+    #[test]
+    fn map_policies_to_their_url() {
+        let policies_yaml = r#"
+pod-privileged:
+  module: registry://ghcr.io/kubewarden/tests/pod-privileged:v0.2.1
+privileged-pods-in-kube-system:
+  module: registry://ghcr.io/kubewarden/tests/pod-privileged:v0.2.1
+  namespaceSelector:
+    matchLabels:
+      kubernetes.io/metadata.name: kube-system
+group:
+  expression: "privileged() && sleeping()"
+  message: "the pod is privileged and sleeping"
+  policies:
+    privileged:
+      module: registry://ghcr.io/kubewarden/tests/pod-privileged:v0.2.1
+    sleeping:
+      module: registry://ghcr.io/kubewarden/tests/sleeping-policy:v0.1.0
+    also_sleeping:
+      module: registry://ghcr.io/kubewarden/tests/sleeping-policy:v0.1.0
+"#;
+        let policies: HashMap<String, PolicyOrPolicyGroup> =
+            serde_yaml::from_str(policies_yaml).unwrap();
+
+        let policies_by_url = policies_by_url(&policies);
+
+        assert_eq!(
+            policies_by_url,
+            HashMap::from([
+                (
+                    "registry://ghcr.io/kubewarden/tests/pod-privileged:v0.2.1".to_owned(),
+                    vec![
+                        PolicyID::Policy("group".to_owned()),
+                        PolicyID::Policy("pod-privileged".to_owned()),
+                        PolicyID::Policy("privileged-pods-in-kube-system".to_owned()),
+                    ]
+                ),
+                (
+                    "registry://ghcr.io/kubewarden/tests/sleeping-policy:v0.1.0".to_owned(),
+                    vec![PolicyID::Policy("group".to_owned())]
+                ),
+            ])
+        );
+    }
+
+    #[rstest]
+    #[case::no_signatures(
+        VerifyError::ImageVerificationError("no signatures found for image".to_owned()),
+        true
+    )]
+    #[case::checksum_mismatch(VerifyError::ChecksumVerificationError("mismatch".to_owned()), true)]
+    #[case::io_error(
+        VerifyError::VerificationFileReadError(std::io::ErrorKind::TimedOut.into()),
+        false
+    )]
+    fn untrusted_policies(#[case] error: VerifyError, #[case] expected: bool) {
+        assert_eq!(is_untrusted(&error), expected);
+    }
+
+    #[rstest]
+    #[case::tag(
+        "registry://ghcr.io/kubewarden/tests/pod-privileged:v0.2.1",
+        "registry://ghcr.io/kubewarden/tests/pod-privileged@sha256:1234"
+    )]
+    #[case::no_tag(
+        "registry://ghcr.io/kubewarden/tests/pod-privileged",
+        "registry://ghcr.io/kubewarden/tests/pod-privileged@sha256:1234"
+    )]
+    #[case::registry_port(
+        "registry://localhost:5000/pod-privileged:v0.2.1",
+        "registry://localhost:5000/pod-privileged@sha256:1234"
+    )]
+    #[case::registry_port_no_tag(
+        "registry://localhost:5000/pod-privileged",
+        "registry://localhost:5000/pod-privileged@sha256:1234"
+    )]
+    #[case::tag_and_digest(
+        "registry://ghcr.io/kubewarden/tests/pod-privileged:v0.2.1@sha256:1234",
+        "registry://ghcr.io/kubewarden/tests/pod-privileged@sha256:1234"
+    )]
+    fn pin_the_verified_manifest(#[case] policy_url: &str, #[case] expected: &str) {
+        assert_eq!(pinned_url(policy_url, "sha256:1234"), expected);
+    }
+}
@@ -0,0 +1,654 @@
+//! Export and import of the state of Policy Server, used by disaster recovery drills.
+//!
+//! The state is a tar archive holding everything Policy Server needs to start serving
+//! without reaching any registry, any HTTP server or the Sigstore infrastructure:
+//!
+//! ```text
+//! manifest.json                   resolved digests, verification reports and errors
+//! policies.yml                    the effective configuration of Policy Server
+//! sources.yml                     optional
+//! verification-config.yml         optional
+//! modules/<sha256>.wasm           the Wasm modules of the policies
+//! precompiled/<sha256>.cwasm      the modules compiled by the engine of Policy Server
+//! rego-libraries/<sha256>.json    the documents of the Rego libraries of the policies
+//! ```
+//!
+//! Once imported, the state directory is given to Policy Server via `--state-dir`. The
+//! precompiled modules are used only when they have been produced by a compatible
+//! engine, otherwise the Wasm modules are compiled again.
+
+use std::{
+    collections::{hash_map::DefaultHasher, BTreeMap, HashSet},
+    fs::{self, File},
+    hash::{Hash, Hasher},
+    path::{Path, PathBuf},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use anyhow::{anyhow, Result};
+use policy_evaluator::wasmtime;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use tracing::info;
+
+use crate::{
+    config::Config,
+    evaluation::precompiled_policy::{precompile_policies, PrecompiledPolicies, PrecompiledPolicy},
+    policy_downloader::{
+        download_rego_libraries, Downloader, FetchedPolicies, FetchedRegoLibraries,
+    },
+};
+
+/// Version of the layout of the archive, bumped on each breaking change
+pub const STATE_FORMAT_VERSION: u32 = 1;
+
+const MANIFEST_FILE: &str = "manifest.json";
+const POLICIES_FILE: &str = "policies.yml";
+const SOURCES_FILE: &str = "sources.yml";
+const VERIFICATION_CONFIG_FILE: &str = "verification-config.yml";
+const MODULES_DIR: &str = "modules";
+const PRECOMPILED_DIR: &str = "precompiled";
+const REGO_LIBRARIES_DIR: &str = "rego-libraries";
+
+/// The configuration files of Policy Server, copied verbatim inside of the archive
+pub struct ConfigFiles {
+    pub policies: PathBuf,
+    pub sources: Option<PathBuf>,
+    pub verification_config: Option<PathBuf>,
+}
+
+/// Describes the contents of an archive
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct StateManifest {
+    pub format_version: u32,
+    /// Version of Policy Server that produced the archive
+    pub policy_server_version: String,
+    /// When the archive has been created, in seconds since the UNIX epoch
+    pub created_at: u64,
+    /// Fingerprint of the engine used to precompile the modules
+    pub engine: String,
+    /// The state of the modules, indexed by the URL of the policy
+    pub modules: BTreeMap<String, ModuleState>,
+    /// The state of the Rego libraries, indexed by their URL
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub rego_libraries: BTreeMap<String, RegoLibraryState>,
+}
+
+/// The state of the Wasm module of a policy
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct ModuleState {
+    /// sha256 digest of the Wasm module, not set when it could not be downloaded
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sha256: Option<String>,
+    /// Digest of the manifest of the OCI artifact, set when the policy has been verified
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub manifest_digest: Option<String>,
+    /// Whether the module has been verified against the verification config
+    pub verified: bool,
+    /// sha256 digest of the precompiled module, not set when the compilation failed
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub precompiled_sha256: Option<String>,
+    /// Why the module could not be downloaded, verified or compiled
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+/// The state of the document of a Rego library
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct RegoLibraryState {
+    /// sha256 digest of the document, not set when it could not be downloaded
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sha256: Option<String>,
+    /// Why the library could not be downloaded
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+/// Download, verify and compile the policies referenced by the configuration, then
+/// write all of them, together with the configuration files, inside of the archive
+pub async fn export(
+    config: &Config,
+    config_files: &ConfigFiles,
+    output: &Path,
+) -> Result<StateManifest> {
+    let sigstore_trust_root = if config.verification_config.is_some() {
+        Some(crate::create_sigstore_trustroot(config).await?)
+    } else {
+        None
+    };
+    let mut downloader = Downloader::new(config.sources.clone(), sigstore_trust_root)
+        .await?
+        .with_fetch_config(config.policy_fetch.clone());
+    let fetched_policies = downloader
+        .download_policies(
+            &config.policies,
+            &config.policies_download_dir,
+            config.verification_config.as_ref(),
+        )
+        .await;
+    let verified_manifest_digests = downloader
+        .into_verified_policies()
+        .map(|(_, digests)| digests)
+        .unwrap_or_default();
+
+    let rego_libraries = download_rego_libraries(
+        &config.policies,
+        config.sources.as_ref(),
+        config.policy_fetch.concurrency,
+    )
+    .await;
+
+    let engine = crate::create_wasmtime_engine(config)?;
+    let precompiled_policies = precompile_policies(&engine, &fetched_policies, None);
+
+    let created_at = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs();
+    let mut manifest = StateManifest {
+        format_version: STATE_FORMAT_VERSION,
+        policy_server_version: env!("CARGO_PKG_VERSION").to_string(),
+        created_at,
+        engine: engine_fingerprint(&engine),
+        modules: BTreeMap::new(),
+        rego_libraries: BTreeMap::new(),
+    };
+
+    let file = File::create(output)
+        .map_err(|e| anyhow!("cannot create file {}: {}", output.display(), e))?;
+    let mut archive = tar::Builder::new(file);
+    let mut archived_modules = HashSet::new();
+
+    for (policy_url, fetched_policy) in &fetched_policies {
+        let module_state = match fetched_policy {
+            Err(error) => ModuleState {
+                error: Some(error.to_string()),
+                ..Default::default()
+            },
+            Ok(path) => {
+                let contents = fs::read(path)
+                    .map_err(|e| anyhow!("cannot read module {}: {}", path.display(), e))?;
+                let sha256 = format!("{:x}", Sha256::digest(&contents));
+                let new_module = archived_modules.insert(sha256.clone());
+                if new_module {
+                    append_file(
+                        &mut archive,
+                        &module_path(Path::new(""), &sha256),
+                        &contents,
+                        created_at,
+                    )?;
+                }
+
+                let (precompiled_sha256, error) = match precompiled_policies.get(policy_url) {
+                    Some(Ok(precompiled_policy)) => {
+                        if new_module {
+                            append_file(
+                                &mut archive,
+                                &precompiled_path(Path::new(""), &sha256),
+                                &precompiled_policy.precompiled_module,
+                                created_at,
+                            )?;
+                        }
+                        (Some(precompiled_policy.digest.clone()), None)
+                    }
+                    Some(Err(error)) => (None, Some(error.to_string())),
+                    None => (None, Some("the module has not been compiled".to_string())),
+                };
+
+                ModuleState {
+                    sha256: Some(sha256),
+                    manifest_digest: verified_manifest_digests.get(policy_url).cloned(),
+                    // the download fails when the policy does not satisfy the verification config
+                    verified: config.verification_config.is_some(),
+                    precompiled_sha256,
+                    error,
+                }
+            }
+        };
+        manifest.modules.insert(policy_url.clone(), module_state);
+    }
+
+    let mut archived_libraries = HashSet::new();
+    for (library_url, rego_library) in &rego_libraries {
+        let library_state = match rego_library {
+            Err(error) => RegoLibraryState {
+                sha256: None,
+                error: Some(error.to_string()),
+            },
+            Ok(document) => {
+                let contents = serde_json::to_vec(document)?;
+                let sha256 = format!("{:x}", Sha256::digest(&contents));
+                if archived_libraries.insert(sha256.clone()) {
+                    append_file(
+                        &mut archive,
+                        &rego_library_path(Path::new(""), &sha256),
+                        &contents,
+                        created_at,
+                    )?;
+                }
+                RegoLibraryState {
+                    sha256: Some(sha256),
+                    error: None,
+                }
+            }
+        };
+        manifest
+            .rego_libraries
+            .insert(library_url.clone(), library_state);
+    }
+
+    let config_files = [
+        (Some(&config_files.policies), POLICIES_FILE),
+        (config_files.sources.as_ref(), SOURCES_FILE),
+        (
+            config_files.verification_config.as_ref(),
+            VERIFICATION_CONFIG_FILE,
+        ),
+    ];
+    for (path, name) in config_files
+        .into_iter()
+        .filter_map(|(path, name)| path.map(|path| (path, name)))
+    {
+        let contents =
+            fs::read(path).map_err(|e| anyhow!("cannot read file {}: {}", path.display(), e))?;
+        append_file(&mut archive, Path::new(name), &contents, created_at)?;
+    }
+
+    append_file(
+        &mut archive,
+        Path::new(MANIFEST_FILE),
+        &serde_json::to_vec_pretty(&manifest)?,
+        created_at,
+    )?;
+    archive
+        .into_inner()
+        .and_then(|file| file.sync_all())
+        .map_err(|e| anyhow!("cannot write archive {}: {}", output.display(), e))?;
+
+    Ok(manifest)
+}
+
+/// Unpack the archive inside of the given directory, then ensure the state is intact
+pub fn import(archive: &Path, dir: &Path) -> Result<StateManifest> {
+    let file = File::open(archive)
+        .map_err(|e| anyhow!("cannot open archive {}: {}", archive.display(), e))?;
+    fs::create_dir_all(dir)
+        .map_err(|e| anyhow!("cannot create directory {}: {}", dir.display(), e))?;
+    tar::Archive::new(file)
+        .unpack(dir)
+        .map_err(|e| anyhow!("cannot unpack archive {}: {}", archive.display(), e))?;
+
+    Ok(State::open(dir)?.manifest)
+}
+
+/// Path of the policies file stored inside of the state directory
+pub fn policies_file(dir: &Path) -> PathBuf {
+    dir.join(POLICIES_FILE)
+}
+
+/// A state directory, produced by [`import`]
+pub struct State {
+    dir: PathBuf,
+    manifest: StateManifest,
+}
+
+impl State {
+    /// Open the state directory, ensuring its modules have not been altered
+    pub fn open(dir: &Path) -> Result<Self> {
+        let manifest_path = dir.join(MANIFEST_FILE);
+        let manifest: StateManifest = serde_json::from_slice(
+            &fs::read(&manifest_path)
+                .map_err(|e| anyhow!("cannot read {}: {}", manifest_path.display(), e))?,
+        )
+        .map_err(|e| anyhow!("cannot parse {}: {}", manifest_path.display(), e))?;
+
+        if manifest.format_version != STATE_FORMAT_VERSION {
+            return Err(anyhow!(
+                "unsupported state format version {}, expected {}",
+                manifest.format_version,
+                STATE_FORMAT_VERSION
+            ));
+        }
+        if !policies_file(dir).exists() {
+            return Err(anyhow!(
+                "the state directory {} does not contain {}",
+                dir.display(),
+                POLICIES_FILE
+            ));
+        }
+
+        for (policy_url, module_state) in &manifest.modules {
+            if let Some(sha256) = &module_state.sha256 {
+                let owner = format!("policy {policy_url}");
+                verify_file_digest(&owner, &module_path(dir, sha256), sha256)?;
+                if let Some(precompiled_sha256) = &module_state.precompiled_sha256 {
+                    verify_file_digest(&owner, &precompiled_path(dir, sha256), precompiled_sha256)?;
+                }
+            }
+        }
+        for (library_url, library_state) in &manifest.rego_libraries {
+            if let Some(sha256) = &library_state.sha256 {
+                verify_file_digest(
+                    &format!("Rego library {library_url}"),
+                    &rego_library_path(dir, sha256),
+                    sha256,
+                )?;
+            }
+        }
+
+        Ok(Self {
+            dir: dir.to_path_buf(),
+            manifest,
+        })
+    }
+
+    pub fn manifest(&self) -> &StateManifest {
+        &self.manifest
+    }
+
+    /// The Wasm modules of the policies, as if they had just been downloaded
+    pub(crate) fn fetched_policies(&self) -> FetchedPolicies {
+        self.manifest
+            .modules
+            .iter()
+            .map(|(policy_url, module_state)| {
+                let fetched_policy = match &module_state.sha256 {
+                    Some(sha256) => Ok(module_path(&self.dir, sha256)),
+                    None => Err(anyhow!(module_state
+                        .error
+                        .clone()
+                        .unwrap_or_else(|| "the module has not been exported".to_string()))),
+                };
+                (policy_url.clone(), fetched_policy)
+            })
+            .collect()
+    }
+
+    /// The documents of the Rego libraries, as if they had just been downloaded. The
+    /// libraries that are not part of the state are reported as missing by the
+    /// policies linking them
+    pub(crate) fn rego_libraries(&self) -> FetchedRegoLibraries {
+        self.manifest
+            .rego_libraries
+            .iter()
+            .map(|(library_url, library_state)| {
+                let rego_library = match &library_state.sha256 {
+                    Some(sha256) => {
+                        let path = rego_library_path(&self.dir, sha256);
+                        fs::read(&path)
+                            .map_err(|e| anyhow!("cannot read {}: {}", path.display(), e))
+                            .and_then(|contents| {
+                                serde_json::from_slice(&contents)
+                                    .map_err(|e| anyhow!("cannot parse {}: {}", path.display(), e))
+                            })
+                    }
+                    None => Err(anyhow!(library_state.error.clone().unwrap_or_else(|| {
+                        "the Rego library has not been exported".to_string()
+                    }))),
+                };
+                (library_url.clone(), rego_library)
+            })
+            .collect()
+    }
+
+    /// Load the precompiled modules of the given policies. They are compiled again
+    /// when the state has been exported by an engine that is not compatible with the
+    /// given one
+    pub(crate) fn precompiled_policies(
+        &self,
+        engine: &wasmtime::Engine,
+        fetched_policies: &FetchedPolicies,
+    ) -> PrecompiledPolicies {
+        if self.manifest.engine != engine_fingerprint(engine) {
+            info!("the precompiled modules of the state have been produced by a different engine, compiling them again");
+            return precompile_policies(engine, fetched_policies, None);
+        }
+
+        fetched_policies
+            .iter()
+            .map(|(policy_url, fetched_policy)| {
+                let precompiled_policy = match fetched_policy {
+                    Ok(path) => self.load_precompiled_policy(policy_url, path),
+                    Err(error) => Err(anyhow!(error.to_string())),
+                };
+                (policy_url.clone(), precompiled_policy)
+            })
+            .collect()
+    }
+
+    fn load_precompiled_policy(&self, policy_url: &str, path: &Path) -> Result<PrecompiledPolicy> {
+        let module_state = self
+            .manifest
+            .modules
+            .get(policy_url)
+            .ok_or_else(|| anyhow!("policy {} is not part of the state", policy_url))?;
+        let sha256 = module_state
+            .sha256
+            .as_ref()
+            .ok_or_else(|| anyhow!("policy {} has not been exported", policy_url))?;
+        if module_state.precompiled_sha256.is_none() {
+            return Err(anyhow!(module_state.error.clone().unwrap_or_else(
+                || format!("policy {policy_url} has not been compiled")
+            )));
+        }
+
+        let policy_contents = fs::read(path)?;
+        let precompiled_module = fs::read(precompiled_path(&self.dir, sha256))?;
+        PrecompiledPolicy::from_precompiled(&policy_contents, precompiled_module)
+    }
+}
+
+/// Fingerprint of the settings of the engine that affect the compiled modules
+pub(crate) fn engine_fingerprint(engine: &wasmtime::Engine) -> String {
+    let mut hasher = DefaultHasher::new();
+    engine.precompile_compatibility_hash().hash(&mut hasher);
+    format!("{:016x}", hasher.finish())
+}
+
+fn module_path(dir: &Path, sha256: &str) -> PathBuf {
+    dir.join(MODULES_DIR).join(format!("{sha256}.wasm"))
+}
+
+fn precompiled_path(dir: &Path, sha256: &str) -> PathBuf {
+    dir.join(PRECOMPILED_DIR).join(format!("{sha256}.cwasm"))
+}
+
+fn rego_library_path(dir: &Path, sha256: &str) -> PathBuf {
+    dir.join(REGO_LIBRARIES_DIR).join(format!("{sha256}.json"))
+}
+
+/// Ensure the file has the expected digest. `owner` describes what the file belongs
+/// to, e.g. `policy <url>`
+fn verify_file_digest(owner: &str, path: &Path, expected: &str) -> Result<()> {
+    let contents = fs::read(path)
+        .map_err(|e| anyhow!("cannot read {} of {}: {}", path.display(), owner, e))?;
+    let digest = format!("{:x}", Sha256::digest(&contents));
+    if digest != expected {
+        return Err(anyhow!(
+            "digest mismatch of {} of {}: expected {}, got {}",
+            path.display(),
+            owner,
+            expected,
+            digest
+        ));
+    }
+    Ok(())
+}
+
+fn append_file(
+    archive: &mut tar::Builder<File>,
+    path: &Path,
+    contents: &[u8],
+    mtime: u64,
+) -> Result<()> {
+    let mut header = tar::Header::new_gnu();
+    header.set_size(contents.len() as u64);
+    header.set_mode(0o644);
+    header.set_mtime(mtime);
+    archive
+        .append_data(&mut header, path, contents)
+        .map_err(|e| anyhow!("cannot add {} to the archive: {}", path.display(), e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    const POLICY_URL: &str = "registry://ghcr.io/kubewarden/tests/pod-privileged:v0.2.1";
+
+    /// Write an archive holding a single module, without any precompiled artifact
+    fn write_archive(dir: &Path, module: &[u8], manifest_sha256: &str) -> PathBuf {
+        let mut manifest = StateManifest {
+            format_version: STATE_FORMAT_VERSION,
+            policy_server_version: env!("CARGO_PKG_VERSION").to_string(),
+            created_at: 0,
+            engine: "0".to_string(),
+            modules: BTreeMap::new(),
+            rego_libraries: BTreeMap::new(),
+        };
+        manifest.modules.insert(
+            POLICY_URL.to_string(),
+            ModuleState {
+                sha256: Some(manifest_sha256.to_string()),
+                ..Default::default()
+            },
+        );
+
+        let archive_path = dir.join("state.tar");
+        let mut archive = tar::Builder::new(File::create(&archive_path).unwrap());
+        append_file(
+            &mut archive,
+            &module_path(Path::new(""), manifest_sha256),
+            module,
+            0,
+        )
+        .unwrap();
+        append_file(&mut archive, Path::new(POLICIES_FILE), b"{}", 0).unwrap();
+        append_file(
+            &mut archive,
+            Path::new(MANIFEST_FILE),
+            &serde_json::to_vec(&manifest).unwrap(),
+            0,
+        )
+        .unwrap();
+        archive.finish().unwrap();
+
+        archive_path
+    }
+
+    #[test]
+    fn import_state() {
+        let tmp = TempDir::new().unwrap();
+        let module = b"\0asm";
+        let sha256 = format!("{:x}", Sha256::digest(module));
+        let archive = write_archive(tmp.path(), module, &sha256);
+        let state_dir = tmp.path().join("state");
+
+        let manifest = import(&archive, &state_dir).unwrap();
+
+        assert_eq!(manifest.modules[POLICY_URL].sha256, Some(sha256.clone()));
+        let state = State::open(&state_dir).unwrap();
+        let fetched_policies = state.fetched_policies();
+        assert_eq!(
+            fetched_policies[POLICY_URL].as_ref().unwrap(),
+            &module_path(&state_dir, &sha256)
+        );
+    }
+
+    #[test]
+    fn import_state_with_altered_module() {
+        let tmp = TempDir::new().unwrap();
+        let sha256 = format!("{:x}", Sha256::digest(b"\0asm"));
+        let archive = write_archive(tmp.path(), b"\0asm altered", &sha256);
+
+        let error = import(&archive, &tmp.path().join("state")).unwrap_err();
+
+        assert!(error.to_string().contains("digest mismatch"), "{error}");
+    }
+
+    #[test]
+    fn open_state_without_manifest() {
+        let tmp = TempDir::new().unwrap();
+
+        assert!(State::open(tmp.path()).is_err());
+    }
+
+    #[test]
+    fn fetched_policies_report_export_errors() {
+        let tmp = TempDir::new().unwrap();
+        let manifest = StateManifest {
+            format_version: STATE_FORMAT_VERSION,
+            policy_server_version: env!("CARGO_PKG_VERSION").to_string(),
+            created_at: 0,
+            engine: "0".to_string(),
+            modules: BTreeMap::from([(
+                POLICY_URL.to_string(),
+                ModuleState {
+                    error: Some("verification failed".to_string()),
+                    ..Default::default()
+                },
+            )]),
+            rego_libraries: BTreeMap::new(),
+        };
+        fs::write(
+            tmp.path().join(MANIFEST_FILE),
+            serde_json::to_vec(&manifest).unwrap(),
+        )
+        .unwrap();
+        fs::write(policies_file(tmp.path()), "{}").unwrap();
+
+        let state = State::open(tmp.path()).unwrap();
+        let fetched_policies = state.fetched_policies();
+
+        assert_eq!(
+            fetched_policies[POLICY_URL]
+                .as_ref()
+                .unwrap_err()
+                .to_string(),
+            "verification failed"
+        );
+    }
+
+    #[test]
+    fn rego_libraries_are_read_from_the_state() {
+        const LIBRARY_URL: &str = "registry://ghcr.io/acme/rego-libraries/images@sha256:9f2c8b0c0c1e0f1d4d5c6b7a8f9e0d1c2b3a4f5e6d7c8b9a0f1e2d3c4b5a6f7e";
+        let tmp = TempDir::new().unwrap();
+        let contents = br#"{"registries":["ghcr.io"]}"#;
+        let sha256 = format!("{:x}", Sha256::digest(contents));
+        let manifest = StateManifest {
+            format_version: STATE_FORMAT_VERSION,
+            policy_server_version: env!("CARGO_PKG_VERSION").to_string(),
+            created_at: 0,
+            engine: "0".to_string(),
+            modules: BTreeMap::new(),
+            rego_libraries: BTreeMap::from([(
+                LIBRARY_URL.to_string(),
+                RegoLibraryState {
+                    sha256: Some(sha256.clone()),
+                    error: None,
+                },
+            )]),
+        };
+        fs::write(
+            tmp.path().join(MANIFEST_FILE),
+            serde_json::to_vec(&manifest).unwrap(),
+        )
+        .unwrap();
+        fs::write(policies_file(tmp.path()), "{}").unwrap();
+        let library_path = rego_library_path(tmp.path(), &sha256);
+        fs::create_dir_all(library_path.parent().unwrap()).unwrap();
+        fs::write(&library_path, contents).unwrap();
+
+        let state = State::open(tmp.path()).unwrap();
+        let rego_libraries = state.rego_libraries();
+        assert_eq!(
+            rego_libraries[LIBRARY_URL].as_ref().unwrap()["registries"],
+            serde_json::json!(["ghcr.io"])
+        );
+
+        fs::write(&library_path, br#"{"registries":["evil.example.com"]}"#).unwrap();
+        let error = State::open(tmp.path()).err().unwrap();
+        assert!(error.to_string().contains("digest mismatch"), "{error}");
+    }
+}
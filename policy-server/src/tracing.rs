@@ -3,11 +3,24 @@ use opentelemetry::trace::TracerProvider;
 use opentelemetry_otlp::WithTonicConfig;
 
 use opentelemetry_sdk::Resource;
+use std::sync::OnceLock;
 use tracing_subscriber::prelude::*;
-use tracing_subscriber::{fmt, EnvFilter};
+use tracing_subscriber::{fmt, reload};
 
 use crate::config::{self, build_client_tls_config_from_env};
 
+pub mod log_filter;
+
+use log_filter::{build_env_filter, LogFilter};
+
+static LOG_FILTER: OnceLock<LogFilter> = OnceLock::new();
+
+/// Returns the handle that can be used to change the tracing filter at runtime.
+/// This is `None` when the tracing system has not been initialized via `setup_tracing`.
+pub fn log_filter() -> Option<&'static LogFilter> {
+    LOG_FILTER.get()
+}
+
 // Setup the tracing system. This MUST be done inside of a tokio Runtime
 // because some collectors rely on it and would panic otherwise.
 //
@@ -18,17 +31,10 @@ pub fn setup_tracing(
     log_fmt: &str,
     log_no_color: bool,
 ) -> Result<Option<opentelemetry_sdk::trace::SdkTracerProvider>> {
-    // setup logging
-    let filter_layer = EnvFilter::new(log_level)
-        // some of our dependencies generate trace events too, but we don't care about them ->
-        // let's filter them
-        .add_directive("cranelift_codegen=off".parse().unwrap())
-        .add_directive("cranelift_wasm=off".parse().unwrap())
-        .add_directive("h2=off".parse().unwrap())
-        .add_directive("hyper=off".parse().unwrap())
-        .add_directive("regalloc=off".parse().unwrap())
-        .add_directive("wasmtime_cranelift=off".parse().unwrap())
-        .add_directive("wasmtime_jit=off".parse().unwrap());
+    // setup logging, the filter can be changed at runtime via the reload handle
+    let (filter_layer, filter_handle) = reload::Layer::new(build_env_filter(log_level, &[]));
+    // the tracing system can be initialized only once, ignore any further attempt
+    let _ = LOG_FILTER.set(LogFilter::new(log_level, filter_handle));
 
     let tracer = match log_fmt {
         "json" => {
@@ -0,0 +1,384 @@
+use std::{
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex, MutexGuard},
+    time::{Duration, Instant},
+};
+
+use anyhow::{anyhow, Result};
+use serde::{Deserialize, Serialize};
+use tokio::task::JoinHandle;
+use tracing::{info, warn};
+use tracing_subscriber::{filter::Directive, reload, EnvFilter, Registry};
+
+/// Some of our dependencies generate trace events too, but we don't care about them.
+/// These directives are always part of the filter, regardless of what is set at runtime.
+const DEPENDENCIES_DIRECTIVES: &[&str] = &[
+    "cranelift_codegen=off",
+    "cranelift_wasm=off",
+    "h2=off",
+    "hyper=off",
+    "regalloc=off",
+    "wasmtime_cranelift=off",
+    "wasmtime_jit=off",
+];
+
+/// Build the `EnvFilter` used by policy-server. The `extra_directives` are
+/// added on top of the given log level and of the directives silencing our
+/// dependencies.
+pub(crate) fn build_env_filter(log_level: &str, extra_directives: &[Directive]) -> EnvFilter {
+    let mut filter = EnvFilter::new(log_level);
+    for directive in DEPENDENCIES_DIRECTIVES {
+        filter = filter.add_directive(directive.parse().unwrap());
+    }
+    for directive in extra_directives {
+        filter = filter.add_directive(directive.clone());
+    }
+    filter
+}
+
+/// Parse a comma separated list of tracing directives, e.g.
+/// `policy_evaluator::runtimes=debug,policy_server::api=trace`
+fn parse_directives(directives: &str) -> Result<Vec<Directive>> {
+    directives
+        .split(',')
+        .map(str::trim)
+        .filter(|d| !d.is_empty())
+        .map(|d| {
+            d.parse::<Directive>()
+                .map_err(|e| anyhow!("invalid tracing directive '{}': {}", d, e))
+        })
+        .collect()
+}
+
+/// Request to change the tracing filter at runtime. This is both the body of
+/// the admin endpoint and the contents of the file loaded on SIGUSR1.
+#[derive(Deserialize, Debug, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct LogFilterRequest {
+    /// Comma separated list of directives, using the `RUST_LOG` syntax
+    #[serde(default)]
+    pub directives: String,
+    /// When set, the default filter is restored after the given amount of seconds
+    pub ttl_seconds: Option<u64>,
+}
+
+/// The tracing filter currently in use
+#[derive(Serialize, Debug, Clone, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct LogFilterStatus {
+    /// The log level policy-server has been started with
+    pub log_level: String,
+    /// The directives added at runtime, empty when the default filter is in use
+    pub directives: String,
+    /// Who changed the filter the last time
+    pub changed_by: Option<String>,
+    /// Seconds left before the default filter is restored
+    pub expires_in_seconds: Option<u64>,
+}
+
+#[derive(Default)]
+struct LogFilterState {
+    directives: String,
+    changed_by: Option<String>,
+    expires_at: Option<Instant>,
+    revert_task: Option<JoinHandle<()>>,
+    /// Bumped on every change of the filter. The revert task restores the
+    /// default filter only when no other change happened in the meantime,
+    /// even if it could not be aborted in time.
+    generation: u64,
+}
+
+/// Handle used to change the tracing filter of policy-server while it is running
+#[derive(Clone)]
+pub struct LogFilter {
+    log_level: String,
+    handle: reload::Handle<EnvFilter, Registry>,
+    state: Arc<Mutex<LogFilterState>>,
+}
+
+impl LogFilter {
+    pub(crate) fn new(log_level: &str, handle: reload::Handle<EnvFilter, Registry>) -> Self {
+        Self {
+            log_level: log_level.to_owned(),
+            handle,
+            state: Arc::new(Mutex::new(LogFilterState::default())),
+        }
+    }
+
+    /// Return the filter currently in use
+    pub fn status(&self) -> LogFilterStatus {
+        let state = self.state.lock().unwrap();
+        LogFilterStatus {
+            log_level: self.log_level.clone(),
+            directives: state.directives.clone(),
+            changed_by: state.changed_by.clone(),
+            expires_in_seconds: state.expires_at.map(|expires_at| {
+                expires_at
+                    .saturating_duration_since(Instant::now())
+                    .as_secs()
+            }),
+        }
+    }
+
+    /// Add the given directives on top of the default filter. When a `ttl` is
+    /// provided, the default filter is restored once it expires.
+    ///
+    /// Must be called from within a tokio runtime when `ttl` is set.
+    pub fn set(
+        &self,
+        directives: &str,
+        ttl: Option<Duration>,
+        changed_by: &str,
+    ) -> Result<LogFilterStatus> {
+        let parsed_directives = parse_directives(directives)?;
+        if parsed_directives.is_empty() {
+            return self.reset(changed_by);
+        }
+
+        let mut state = self.state.lock().unwrap();
+        self.handle
+            .reload(build_env_filter(&self.log_level, &parsed_directives))
+            .map_err(|e| anyhow!("cannot reload tracing filter: {}", e))?;
+
+        if let Some(task) = state.revert_task.take() {
+            task.abort();
+        }
+        state.generation += 1;
+        state.directives = directives.to_owned();
+        state.changed_by = Some(changed_by.to_owned());
+        state.expires_at = ttl.map(|ttl| Instant::now() + ttl);
+        if let Some(ttl) = ttl {
+            let log_filter = self.clone();
+            let generation = state.generation;
+            state.revert_task = Some(tokio::spawn(async move {
+                tokio::time::sleep(ttl).await;
+                if let Err(e) = log_filter.expire(generation) {
+                    warn!(error = %e, "cannot restore default tracing filter");
+                }
+            }));
+        }
+        drop(state);
+
+        info!(
+            changed_by,
+            directives,
+            ttl_seconds = ttl.map(|ttl| ttl.as_secs()),
+            "tracing filter changed"
+        );
+
+        Ok(self.status())
+    }
+
+    /// Restore the filter policy-server has been started with
+    pub fn reset(&self, changed_by: &str) -> Result<LogFilterStatus> {
+        let state = self.state.lock().unwrap();
+        self.restore_default(state, changed_by)?;
+
+        Ok(self.status())
+    }
+
+    /// Invoked by the revert task once the ttl of the change identified by
+    /// `generation` expires. Nothing is done when the filter has been changed
+    /// again in the meantime.
+    fn expire(&self, generation: u64) -> Result<()> {
+        let state = self.state.lock().unwrap();
+        if state.generation != generation {
+            return Ok(());
+        }
+        self.restore_default(state, "ttl-expired")
+    }
+
+    fn restore_default(
+        &self,
+        mut state: MutexGuard<'_, LogFilterState>,
+        changed_by: &str,
+    ) -> Result<()> {
+        self.handle
+            .reload(build_env_filter(&self.log_level, &[]))
+            .map_err(|e| anyhow!("cannot reload tracing filter: {}", e))?;
+
+        if let Some(task) = state.revert_task.take() {
+            // the default filter can be restored by the revert task itself, aborting it is harmless
+            task.abort();
+        }
+        state.generation += 1;
+        state.directives = String::new();
+        state.changed_by = Some(changed_by.to_owned());
+        state.expires_at = None;
+        drop(state);
+
+        info!(changed_by, "tracing filter restored to default");
+
+        Ok(())
+    }
+
+    /// Apply the filter defined inside of the given file. A missing or empty
+    /// file restores the default filter.
+    pub fn load_from_file(&self, path: &Path, changed_by: &str) -> Result<LogFilterStatus> {
+        if !path.exists() {
+            return self.reset(changed_by);
+        }
+        let contents = std::fs::read_to_string(path)
+            .map_err(|e| anyhow!("cannot read tracing filter file {:?}: {}", path, e))?;
+        let request: LogFilterRequest = if contents.trim().is_empty() {
+            LogFilterRequest::default()
+        } else {
+            serde_yaml::from_str(&contents)
+                .map_err(|e| anyhow!("cannot parse tracing filter file {:?}: {}", path, e))?
+        };
+
+        self.set(
+            &request.directives,
+            request.ttl_seconds.map(Duration::from_secs),
+            changed_by,
+        )
+    }
+}
+
+/// Reload the tracing filter from `path` every time the process receives SIGUSR1
+#[cfg(unix)]
+pub fn reload_on_sigusr1(log_filter: LogFilter, path: PathBuf) -> Result<()> {
+    use tokio::signal::unix::{signal, SignalKind};
+
+    let mut sigusr1 = signal(SignalKind::user_defined1())
+        .map_err(|e| anyhow!("cannot register SIGUSR1 handler: {}", e))?;
+
+    tokio::spawn(async move {
+        while sigusr1.recv().await.is_some() {
+            if let Err(e) = log_filter.load_from_file(&path, "SIGUSR1") {
+                warn!(error = %e, ?path, "cannot load tracing filter from file");
+            }
+        }
+    });
+
+    Ok(())
+}
+
+#[cfg(not(unix))]
+pub fn reload_on_sigusr1(_log_filter: LogFilter, _path: PathBuf) -> Result<()> {
+    Err(anyhow!(
+        "reloading the tracing filter on SIGUSR1 is not supported on this platform"
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use rstest::rstest;
+    use std::io::Write;
+    use tempfile::NamedTempFile;
+    use tracing_subscriber::prelude::*;
+
+    // The subscriber must be kept alive, otherwise the reload handle fails
+    fn log_filter() -> (LogFilter, impl tracing::Subscriber) {
+        let (filter_layer, handle) = reload::Layer::new(build_env_filter("info", &[]));
+        let subscriber = tracing_subscriber::registry().with(filter_layer);
+
+        (LogFilter::new("info", handle), subscriber)
+    }
+
+    #[rstest]
+    #[case::single("policy_evaluator::runtimes=debug", 1)]
+    #[case::multiple("policy_evaluator::runtimes=debug, policy_server=trace", 2)]
+    #[case::trailing_comma("policy_server=trace,", 1)]
+    #[case::empty("", 0)]
+    fn parse_valid_directives(#[case] directives: &str, #[case] expected: usize) {
+        assert_eq!(parse_directives(directives).unwrap().len(), expected);
+    }
+
+    #[test]
+    fn parse_invalid_directives() {
+        assert!(parse_directives("policy_server=not-a-level").is_err());
+    }
+
+    #[tokio::test]
+    async fn set_and_reset_filter() {
+        let (log_filter, _subscriber) = log_filter();
+
+        let status = log_filter
+            .set("policy_evaluator::runtimes=debug", None, "127.0.0.1:1234")
+            .unwrap();
+        assert_eq!(status.directives, "policy_evaluator::runtimes=debug");
+        assert_eq!(status.changed_by.as_deref(), Some("127.0.0.1:1234"));
+        assert_eq!(status.expires_in_seconds, None);
+
+        let status = log_filter.reset("127.0.0.1:1234").unwrap();
+        assert!(status.directives.is_empty());
+        assert_eq!(status.log_level, "info");
+    }
+
+    #[tokio::test]
+    async fn invalid_directives_do_not_change_filter() {
+        let (log_filter, _subscriber) = log_filter();
+
+        log_filter.set("policy_server=debug", None, "test").unwrap();
+        assert!(log_filter
+            .set("policy_server=not-a-level", None, "test")
+            .is_err());
+        assert_eq!(log_filter.status().directives, "policy_server=debug");
+    }
+
+    #[tokio::test]
+    async fn filter_is_restored_after_ttl() {
+        let (log_filter, _subscriber) = log_filter();
+
+        let status = log_filter
+            .set(
+                "policy_server=debug",
+                Some(Duration::from_millis(100)),
+                "test",
+            )
+            .unwrap();
+        assert!(status.expires_in_seconds.is_some());
+
+        tokio::time::sleep(Duration::from_millis(500)).await;
+
+        let status = log_filter.status();
+        assert!(status.directives.is_empty());
+        assert_eq!(status.changed_by.as_deref(), Some("ttl-expired"));
+        assert_eq!(status.expires_in_seconds, None);
+    }
+
+    #[tokio::test]
+    async fn stale_ttl_does_not_restore_newer_filter() {
+        let (log_filter, _subscriber) = log_filter();
+
+        log_filter
+            .set(
+                "policy_server=debug",
+                Some(Duration::from_secs(600)),
+                "test",
+            )
+            .unwrap();
+        let stale_generation = log_filter.state.lock().unwrap().generation;
+
+        log_filter.set("policy_server=trace", None, "test").unwrap();
+
+        // Simulate a revert task that woke up before being aborted
+        log_filter.expire(stale_generation).unwrap();
+
+        let status = log_filter.status();
+        assert_eq!(status.directives, "policy_server=trace");
+        assert_eq!(status.changed_by.as_deref(), Some("test"));
+    }
+
+    #[tokio::test]
+    async fn load_filter_from_file() {
+        let (log_filter, _subscriber) = log_filter();
+
+        let mut file = NamedTempFile::new().unwrap();
+        file.write_all(b"directives: policy_server=trace\nttlSeconds: 600\n")
+            .unwrap();
+
+        let status = log_filter.load_from_file(file.path(), "SIGUSR1").unwrap();
+        assert_eq!(status.directives, "policy_server=trace");
+        assert!(status.expires_in_seconds.unwrap() > 0);
+
+        let empty_file = NamedTempFile::new().unwrap();
+        let status = log_filter
+            .load_from_file(empty_file.path(), "SIGUSR1")
+            .unwrap();
+        assert!(status.directives.is_empty());
+    }
+}
@@ -1,14 +1,20 @@
 use std::{
-    collections::{BTreeSet, HashMap},
+    collections::{BTreeMap, BTreeSet, HashMap},
     net::{Ipv4Addr, SocketAddr, SocketAddrV4, TcpListener},
     sync::Once,
+    time::Duration,
 };
 
 use axum::Router;
-use policy_evaluator::admission_response_handler::policy_mode::PolicyMode;
+use policy_evaluator::admission_response_handler::{
+    failure_policy::FailurePolicy, policy_mode::PolicyMode,
+};
+use policy_evaluator::evaluation_cache::EvaluationCacheConfig;
 use policy_evaluator::policy_evaluator::PolicySettings;
+use policy_evaluator::policy_fetcher::download::FetcherOptions;
 use policy_server::{
-    config::{Config, PolicyGroupMember, PolicyOrPolicyGroup},
+    config::{CapabilitiesConfig, Config, PolicyGroupMember, PolicyOrPolicyGroup, PriorityConfig},
+    enrichment::EnrichmentConfig,
     PolicyServer,
 };
 use serde_json::json;
@@ -37,10 +43,21 @@ pub(crate) fn default_test_config() -> Config {
             PolicyOrPolicyGroup::Policy {
                 module: "ghcr.io/kubewarden/tests/pod-privileged:v0.2.1".to_owned(),
                 policy_mode: PolicyMode::Protect,
+                failure_policy: FailurePolicy::Fail,
+                timeout_seconds: None,
                 allowed_to_mutate: None,
                 settings: None,
+                namespace_settings: HashMap::new(),
                 context_aware_resources: BTreeSet::new(),
+                service_account: None,
                 message: None,
+                entrypoint: None,
+                rego_libraries: BTreeMap::new(),
+                match_conditions: Vec::new(),
+                secret_data: None,
+                http_send: None,
+                key_value_store: false,
+                preopened_dirs: BTreeMap::new(),
             },
         ),
         (
@@ -48,6 +65,8 @@ pub(crate) fn default_test_config() -> Config {
             PolicyOrPolicyGroup::Policy {
                 module: "ghcr.io/kubewarden/tests/raw-mutation-policy:v0.1.0".to_owned(),
                 policy_mode: PolicyMode::Protect,
+                failure_policy: FailurePolicy::Fail,
+                timeout_seconds: None,
                 allowed_to_mutate: Some(true),
                 settings: Some(
                     PolicySettings::try_from(&json!({
@@ -56,8 +75,17 @@ pub(crate) fn default_test_config() -> Config {
                     }))
                     .unwrap(),
                 ),
+                namespace_settings: HashMap::new(),
                 context_aware_resources: BTreeSet::new(),
+                service_account: None,
                 message: None,
+                entrypoint: None,
+                rego_libraries: BTreeMap::new(),
+                match_conditions: Vec::new(),
+                secret_data: None,
+                http_send: None,
+                key_value_store: false,
+                preopened_dirs: BTreeMap::new(),
             },
         ),
         (
@@ -65,6 +93,8 @@ pub(crate) fn default_test_config() -> Config {
             PolicyOrPolicyGroup::Policy {
                 module: "ghcr.io/kubewarden/tests/sleeping-policy:v0.1.0".to_owned(),
                 policy_mode: PolicyMode::Protect,
+                failure_policy: FailurePolicy::Fail,
+                timeout_seconds: None,
                 allowed_to_mutate: None,
                 settings: Some(
                     PolicySettings::try_from(&json!({
@@ -72,8 +102,17 @@ pub(crate) fn default_test_config() -> Config {
                     }))
                     .unwrap(),
                 ),
+                namespace_settings: HashMap::new(),
                 context_aware_resources: BTreeSet::new(),
+                service_account: None,
                 message: None,
+                entrypoint: None,
+                rego_libraries: BTreeMap::new(),
+                match_conditions: Vec::new(),
+                secret_data: None,
+                http_send: None,
+                key_value_store: false,
+                preopened_dirs: BTreeMap::new(),
             },
         ),
         (
@@ -82,14 +121,20 @@ pub(crate) fn default_test_config() -> Config {
                 expression: "pod_privileged() && true".to_string(),
                 message: "The group policy rejected your request".to_string(),
                 policy_mode: PolicyMode::Protect,
+                failure_policy: FailurePolicy::Fail,
+                timeout_seconds: None,
                 policies: HashMap::from([(
                     "pod_privileged".to_string(),
                     PolicyGroupMember {
                         module: "ghcr.io/kubewarden/tests/pod-privileged:v0.2.1".to_owned(),
                         settings: None,
                         context_aware_resources: BTreeSet::new(),
+                        service_account: None,
+                        preopened_dirs: BTreeMap::new(),
                     },
                 )]),
+                match_conditions: Vec::new(),
+                secret_data: None,
             },
         ),
         (
@@ -98,6 +143,8 @@ pub(crate) fn default_test_config() -> Config {
                 expression: "raw_mutation() && true".to_string(),
                 message: "The group policy rejected your request".to_string(),
                 policy_mode: PolicyMode::Protect,
+                failure_policy: FailurePolicy::Fail,
+                timeout_seconds: None,
                 policies: HashMap::from([(
                     "raw_mutation".to_string(),
                     PolicyGroupMember {
@@ -110,8 +157,12 @@ pub(crate) fn default_test_config() -> Config {
                             .unwrap(),
                         ),
                         context_aware_resources: BTreeSet::new(),
+                        service_account: None,
+                        preopened_dirs: BTreeMap::new(),
                     },
                 )]),
+                match_conditions: Vec::new(),
+                secret_data: None,
             },
         ),
     ]);
@@ -125,11 +176,19 @@ pub(crate) fn default_test_config() -> Config {
         ignore_kubernetes_connection_failure: true,
         always_accept_admission_reviews_on_namespace: None,
         policy_evaluation_limit_seconds: Some(2),
+        policy_settings_validation_limit_seconds: Some(2),
+        policy_timeout_tick_interval: Duration::from_secs(1),
         tls_config: None,
+        readiness_probe_tls_config: None,
         pool_size: 2,
+        evaluator_pool_size: 0,
+        evaluator_pool_max_uses: 0,
+        module_cache: None,
+        evaluation_cache: EvaluationCacheConfig::default(),
         metrics_enabled: false,
         sigstore_cache_dir: tempdir().unwrap().keep(),
         verification_config: None,
+        policy_reverification_interval: None,
         log_level: "info".to_owned(),
         log_fmt: "json".to_owned(),
         log_no_color: false,
@@ -138,7 +197,26 @@ pub(crate) fn default_test_config() -> Config {
         daemon_stdout_file: None,
         daemon_stderr_file: None,
         enable_pprof: false,
+        enable_log_filter_admin: false,
+        log_filter_file: None,
         continue_on_errors: false,
+        lazy_policy_loading: false,
+        lazy_policy_warm_up: Vec::new(),
+        readiness_requires_kubernetes_sync: false,
+        max_request_body_size: 8 * 1024 * 1024,
+        max_request_body_depth: 128,
+        rego_policy_memory_limit: None,
+        response_compression: true,
+        request_projection: true,
+        redact_secret_data: false,
+        cluster_name: None,
+        decision_journal: None,
+        decision_log: None,
+        state_dir: None,
+        policy_fetch: FetcherOptions::default(),
+        priority: PriorityConfig::default(),
+        capabilities: CapabilitiesConfig::default(),
+        enrichment: EnrichmentConfig::default(),
     }
 }
 
@@ -2,7 +2,7 @@ mod common;
 
 use std::path::PathBuf;
 use std::{
-    collections::{BTreeSet, HashMap},
+    collections::{BTreeMap, BTreeSet, HashMap},
     time::Duration,
 };
 #[cfg(feature = "otel_tests")]
@@ -19,10 +19,17 @@ use http_body_util::BodyExt;
 use policy_evaluator::admission_response::{self, StatusCause, StatusDetails};
 use policy_evaluator::{
     admission_response::AdmissionResponseStatus,
-    admission_response_handler::policy_mode::PolicyMode, policy_evaluator::PolicySettings,
+    admission_response_handler::{failure_policy::FailurePolicy, policy_mode::PolicyMode},
+    policy_evaluator::PolicySettings,
     policy_fetcher::verify::config::VerificationConfigV1,
 };
-use policy_server::{api::admission_review::AdmissionReviewResponse, config::PolicyOrPolicyGroup};
+use policy_server::{
+    api::{
+        admission_review::{AdmissionReviewRequest, AdmissionReviewResponse},
+        protobuf,
+    },
+    config::PolicyOrPolicyGroup,
+};
 use regex::Regex;
 use rstest::*;
 use serde_json::json;
@@ -67,6 +74,79 @@ async fn test_validate() {
     )
 }
 
+#[rstest]
+#[case::protobuf_response(protobuf::PROTOBUF_CONTENT_TYPE, protobuf::PROTOBUF_CONTENT_TYPE)]
+#[case::json_response("application/json", "application/json")]
+#[tokio::test]
+async fn test_validate_protobuf(#[case] accept: &str, #[case] expected_content_type: &str) {
+    setup();
+
+    let config = default_test_config();
+    let app = app(config).await;
+
+    let admission_review: AdmissionReviewRequest =
+        serde_json::from_str(include_str!("data/pod_with_privileged_containers.json")).unwrap();
+    let body = protobuf::encode_admission_review(
+        &protobuf::AdmissionReview::try_from(&admission_review).unwrap(),
+    );
+
+    let request = Request::builder()
+        .method(http::Method::POST)
+        .header(header::CONTENT_TYPE, protobuf::PROTOBUF_CONTENT_TYPE)
+        .header(header::ACCEPT, accept)
+        .uri("/validate/pod-privileged")
+        .body(Body::from(body))
+        .unwrap();
+
+    let response = app.oneshot(request).await.unwrap();
+
+    assert_eq!(response.status(), 200);
+    assert_eq!(
+        response.headers().get(header::CONTENT_TYPE).unwrap(),
+        expected_content_type
+    );
+
+    let body = response.into_body().collect().await.unwrap().to_bytes();
+    let (allowed, message) = if expected_content_type == protobuf::PROTOBUF_CONTENT_TYPE {
+        let response = protobuf::decode_admission_review(&body)
+            .unwrap()
+            .response
+            .unwrap();
+        (response.allowed.unwrap(), response.status.unwrap().message)
+    } else {
+        let response: AdmissionReviewResponse = serde_json::from_slice(&body).unwrap();
+        (
+            response.response.allowed,
+            response.response.status.unwrap().message,
+        )
+    };
+
+    assert!(!allowed);
+    assert_eq!(
+        message.as_deref(),
+        Some("Privileged container is not allowed")
+    );
+}
+
+#[tokio::test]
+async fn test_validate_invalid_protobuf() {
+    setup();
+
+    let config = default_test_config();
+    let app = app(config).await;
+
+    let request = Request::builder()
+        .method(http::Method::POST)
+        .header(header::CONTENT_TYPE, protobuf::PROTOBUF_CONTENT_TYPE)
+        .uri("/validate/pod-privileged")
+        .body(Body::from("not protobuf"))
+        .unwrap();
+
+    let response = app.oneshot(request).await.unwrap();
+
+    assert_eq!(response.status(), 400);
+}
+
 #[tokio::test]
 async fn test_validate_custom_rejection_message() {
     setup();
@@ -77,10 +157,21 @@ async fn test_validate_custom_rejection_message() {
         PolicyOrPolicyGroup::Policy {
             module: "ghcr.io/kubewarden/tests/pod-privileged:v0.2.1".to_owned(),
             policy_mode: PolicyMode::Protect,
+            failure_policy: FailurePolicy::Fail,
+            timeout_seconds: None,
             allowed_to_mutate: None,
             settings: None,
+            namespace_settings: HashMap::new(),
             context_aware_resources: BTreeSet::new(),
+            service_account: None,
             message: Some("Custom error message".to_owned()),
+            entrypoint: None,
+            rego_libraries: BTreeMap::new(),
+            match_conditions: Vec::new(),
+            secret_data: None,
+            http_send: None,
+            key_value_store: false,
+            preopened_dirs: BTreeMap::new(),
         },
     );
     let app = app(config).await;
@@ -223,6 +314,115 @@ async fn test_validate_invalid_payload() {
     assert_eq!(response.status(), 422);
 }
 
+#[rstest]
+#[case::validate("/validate/pod-privileged")]
+#[case::validate_raw("/validate_raw/raw-mutation")]
+#[case::audit("/audit/pod-privileged")]
+#[tokio::test]
+async fn test_request_body_too_large(#[case] uri: &str) {
+    setup();
+
+    let mut config = default_test_config();
+    config.max_request_body_size = 1024;
+    let app = app(config).await;
+
+    let request = Request::builder()
+        .method(http::Method::POST)
+        .header(header::CONTENT_TYPE, "application/json")
+        .uri(uri)
+        .body(Body::from(include_str!(
+            "data/pod_with_privileged_containers.json"
+        )))
+        .unwrap();
+
+    let response = app.oneshot(request).await.unwrap();
+
+    assert_eq!(response.status(), 413);
+    let body: serde_json::Value =
+        serde_json::from_slice(&response.into_body().collect().await.unwrap().to_bytes()).unwrap();
+    assert_eq!(
+        body,
+        json!({
+            "message": "request body exceeds the maximum allowed size of 1024 bytes",
+            "status": 413,
+        })
+    );
+}
+
+#[rstest]
+#[case::validate("/validate/pod-privileged")]
+#[case::validate_raw("/validate_raw/raw-mutation")]
+#[case::audit("/audit/pod-privileged")]
+#[tokio::test]
+async fn test_request_body_too_deep(#[case] uri: &str) {
+    setup();
+
+    let mut config = default_test_config();
+    config.max_request_body_depth = 4;
+    let app = app(config).await;
+
+    let request = Request::builder()
+        .method(http::Method::POST)
+        .header(header::CONTENT_TYPE, "application/json")
+        .uri(uri)
+        .body(Body::from(include_str!(
+            "data/pod_with_privileged_containers.json"
+        )))
+        .unwrap();
+
+    let response = app.oneshot(request).await.unwrap();
+
+    assert_eq!(response.status(), 400);
+    let body: serde_json::Value =
+        serde_json::from_slice(&response.into_body().collect().await.unwrap().to_bytes()).unwrap();
+    assert_eq!(
+        body,
+        json!({
+            "message": "request body exceeds the maximum allowed nesting depth of 4",
+            "status": 400,
+        })
+    );
+}
+
+#[rstest]
+#[case::gzip("gzip", true, Some("gzip"))]
+#[case::deflate("deflate", true, Some("deflate"))]
+#[case::identity("identity", true, None)]
+#[case::compression_disabled("gzip", false, None)]
+#[tokio::test]
+async fn test_response_compression(
+    #[case] accept_encoding: &str,
+    #[case] response_compression: bool,
+    #[case] expected_encoding: Option<&str>,
+) {
+    setup();
+
+    let mut config = default_test_config();
+    config.response_compression = response_compression;
+    let app = app(config).await;
+
+    let request = Request::builder()
+        .method(http::Method::POST)
+        .header(header::CONTENT_TYPE, "application/json")
+        .header(header::ACCEPT_ENCODING, accept_encoding)
+        .uri("/validate/pod-privileged")
+        .body(Body::from(include_str!(
+            "data/pod_with_privileged_containers.json"
+        )))
+        .unwrap();
+
+    let response = app.oneshot(request).await.unwrap();
+
+    assert_eq!(response.status(), 200);
+    assert_eq!(
+        response
+            .headers()
+            .get(header::CONTENT_ENCODING)
+            .map(|value| value.to_str().unwrap()),
+        expected_encoding
+    );
+}
+
 #[tokio::test]
 async fn test_validate_raw() {
     setup();
@@ -253,6 +453,86 @@ async fn test_validate_raw() {
     );
 }
 
+#[rstest]
+#[case::structured(
+    vec![(header::CONTENT_TYPE.as_str(), "application/cloudevents+json")],
+    json!({
+        "specversion": "1.0",
+        "id": "1234",
+        "source": "/farm",
+        "type": "io.example.meal",
+        "user": "tonio",
+        "action": "eats",
+        "resource": "banana",
+    }).to_string(),
+)]
+#[case::binary(
+    vec![
+        ("ce-specversion", "1.0"),
+        ("ce-id", "1234"),
+        ("ce-source", "/farm"),
+        ("ce-type", "io.example.meal"),
+        ("ce-user", "tonio"),
+        ("ce-action", "eats"),
+        ("ce-resource", "banana"),
+        (header::CONTENT_TYPE.as_str(), "application/json"),
+    ],
+    json!({"meal": "lunch"}).to_string(),
+)]
+#[tokio::test]
+async fn test_validate_cloudevent(#[case] headers: Vec<(&str, &str)>, #[case] body: String) {
+    setup();
+
+    let config = default_test_config();
+    let app = app(config).await;
+
+    let mut request = Request::builder()
+        .method(http::Method::POST)
+        .uri("/validate_cloudevent/raw-mutation");
+    for (name, value) in headers {
+        request = request.header(name, value);
+    }
+    let request = request.body(Body::from(body)).unwrap();
+
+    let response = app.oneshot(request).await.unwrap();
+
+    assert_eq!(response.status(), 200);
+    assert_eq!(
+        response.headers().get(header::CONTENT_TYPE).unwrap(),
+        "application/cloudevents+json"
+    );
+
+    let event: serde_json::Value =
+        serde_json::from_slice(&response.into_body().collect().await.unwrap().to_bytes()).unwrap();
+    assert_eq!(event["specversion"], "1.0");
+    assert_eq!(event["type"], "io.kubewarden.policy.allowed");
+    assert_eq!(event["source"], "/validate_cloudevent/raw-mutation");
+    assert_eq!(event["subject"], "1234");
+    assert_eq!(event["data"]["uid"], "1234");
+    assert_eq!(event["data"]["allowed"], true);
+}
+
+#[tokio::test]
+async fn test_validate_invalid_cloudevent() {
+    setup();
+
+    let config = default_test_config();
+    let app = app(config).await;
+
+    let request = Request::builder()
+        .method(http::Method::POST)
+        .header(header::CONTENT_TYPE, "application/json")
+        .header("ce-specversion", "1.0")
+        .header("ce-id", "1234")
+        .uri("/validate_cloudevent/raw-mutation")
+        .body(Body::from("{}"))
+        .unwrap();
+
+    let response = app.oneshot(request).await.unwrap();
+
+    assert_eq!(response.status(), 400);
+}
+
 #[tokio::test]
 async fn test_validate_policy_group_does_not_do_mutation() {
     setup();
@@ -466,14 +746,45 @@ async fn test_timeout_protection_reject() {
         admission_review_response.response.status,
         Some(
             AdmissionResponseStatus {
-                message: Some("internal server error: Guest call failure: guest code interrupted, execution deadline exceeded".to_owned()),
-                code: Some(500),
+                message: Some("policy evaluation timed out: Guest call failure: guest code interrupted, execution deadline exceeded".to_owned()),
+                code: Some(504),
                 ..Default::default()
             }
         )
     );
 }
 
+#[tokio::test]
+async fn test_timeout_protection_policy_override() {
+    setup();
+
+    let mut config = default_test_config();
+    if let Some(PolicyOrPolicyGroup::Policy {
+        timeout_seconds, ..
+    }) = config.policies.get_mut("sleep")
+    {
+        *timeout_seconds = Some(6);
+    }
+    let app = app(config).await;
+
+    let request = Request::builder()
+        .method(http::Method::POST)
+        .header(header::CONTENT_TYPE, "application/json")
+        .uri("/validate/sleep")
+        .body(Body::from(include_str!("data/pod_sleep_4s.json")))
+        .unwrap();
+
+    let response = app.oneshot(request).await.unwrap();
+
+    assert_eq!(response.status(), 200);
+
+    let admission_review_response: AdmissionReviewResponse =
+        serde_json::from_slice(&response.into_body().collect().await.unwrap().to_bytes()).unwrap();
+
+    // the global timeout would have interrupted the evaluation
+    assert!(admission_review_response.response.allowed);
+}
+
 #[tokio::test]
 async fn test_verified_policy() {
     setup();
@@ -509,10 +820,21 @@ async fn test_verified_policy() {
         PolicyOrPolicyGroup::Policy {
             module: "ghcr.io/kubewarden/tests/pod-privileged:v0.2.1".to_owned(),
             policy_mode: PolicyMode::Protect,
+            failure_policy: FailurePolicy::Fail,
+            timeout_seconds: None,
             allowed_to_mutate: None,
             settings: None,
+            namespace_settings: HashMap::new(),
             context_aware_resources: BTreeSet::new(),
+            service_account: None,
             message: None,
+            entrypoint: None,
+            rego_libraries: BTreeMap::new(),
+            match_conditions: Vec::new(),
+            secret_data: None,
+            http_send: None,
+            key_value_store: false,
+            preopened_dirs: BTreeMap::new(),
         },
     )]);
     config.verification_config = Some(verification_config);
@@ -542,6 +864,8 @@ async fn test_policy_with_invalid_settings() {
         PolicyOrPolicyGroup::Policy {
             module: "ghcr.io/kubewarden/tests/sleeping-policy:v0.1.0".to_owned(),
             policy_mode: PolicyMode::Protect,
+            failure_policy: FailurePolicy::Fail,
+            timeout_seconds: None,
             allowed_to_mutate: None,
             settings: Some(
                 PolicySettings::try_from(&json!({
@@ -549,8 +873,17 @@ async fn test_policy_with_invalid_settings() {
                 }))
                 .unwrap(),
             ),
+            namespace_settings: HashMap::new(),
             context_aware_resources: BTreeSet::new(),
+            service_account: None,
             message: None,
+            entrypoint: None,
+            rego_libraries: BTreeMap::new(),
+            match_conditions: Vec::new(),
+            secret_data: None,
+            http_send: None,
+            key_value_store: false,
+            preopened_dirs: BTreeMap::new(),
         },
     );
     config.continue_on_errors = true;
@@ -593,10 +926,21 @@ async fn test_policy_with_wrong_url() {
         PolicyOrPolicyGroup::Policy {
             module: "ghcr.io/kubewarden/tests/not_existing:v0.1.0".to_owned(),
             policy_mode: PolicyMode::Protect,
+            failure_policy: FailurePolicy::Fail,
+            timeout_seconds: None,
             allowed_to_mutate: None,
             settings: None,
+            namespace_settings: HashMap::new(),
             context_aware_resources: BTreeSet::new(),
+            service_account: None,
             message: None,
+            entrypoint: None,
+            rego_libraries: BTreeMap::new(),
+            match_conditions: Vec::new(),
+            secret_data: None,
+            http_send: None,
+            key_value_store: false,
+            preopened_dirs: BTreeMap::new(),
         },
     );
     config.continue_on_errors = true;
@@ -629,6 +973,98 @@ async fn test_policy_with_wrong_url() {
     assert!(pattern.is_match(&status.message.unwrap()));
 }
 
+#[tokio::test]
+async fn test_policies_catalog() {
+    setup();
+
+    let config = default_test_config();
+    let app = app(config).await;
+
+    let request = Request::builder()
+        .method(http::Method::GET)
+        .uri("/policies")
+        .body(Body::empty())
+        .unwrap();
+
+    let response = app.oneshot(request).await.unwrap();
+
+    assert_eq!(response.status(), 200);
+
+    let catalog: Vec<serde_json::Value> =
+        serde_json::from_slice(&response.into_body().collect().await.unwrap().to_bytes()).unwrap();
+
+    let pod_privileged = catalog
+        .iter()
+        .find(|entry| entry["id"] == "pod-privileged")
+        .expect("pod-privileged should be part of the catalog");
+    let stable_id = pod_privileged["stableId"].as_str().unwrap();
+    let module_digest = pod_privileged["moduleDigest"].as_str().unwrap();
+    assert_eq!(
+        stable_id,
+        format!("pod-privileged@{}", &module_digest[..12])
+    );
+    assert_eq!(pod_privileged["policyGroup"], false);
+}
+
+#[tokio::test]
+async fn test_readyz() {
+    setup();
+
+    let config = default_test_config();
+    let server = policy_server::PolicyServer::new_from_config(config)
+        .await
+        .unwrap();
+    let readiness_probe_router = server.readiness_probe_router();
+
+    let readyz = |uri: &'static str| {
+        let readiness_probe_router = readiness_probe_router.clone();
+        async move {
+            let request = Request::builder()
+                .method(http::Method::GET)
+                .uri(uri)
+                .body(Body::empty())
+                .unwrap();
+            let response = readiness_probe_router.oneshot(request).await.unwrap();
+            let status = response.status();
+            let body: serde_json::Value =
+                serde_json::from_slice(&response.into_body().collect().await.unwrap().to_bytes())
+                    .unwrap();
+            (status, body)
+        }
+    };
+
+    // all the policies are compiled at bootstrap time
+    let (status, body) = readyz("/readyz").await;
+    assert_eq!(status, 200);
+    assert_eq!(body["ready"], true);
+
+    // the policies are instantiated in the background right after startup, without
+    // waiting for the first request targeting them
+    let mut warm = false;
+    for _ in 0..50 {
+        let (status, body) = readyz("/readyz?require=warm&policies=pod-privileged").await;
+        if status == 200 {
+            assert_eq!(body["policies"], json!({"pod-privileged": "warm"}));
+            warm = true;
+            break;
+        }
+        assert_eq!(body["policies"], json!({"pod-privileged": "compiled"}));
+        tokio::time::sleep(Duration::from_millis(200)).await;
+    }
+    assert!(warm, "the policy should be warm before serving any request");
+
+    let (status, _) = readyz("/readyz?policies=pod-privileged,unknown").await;
+    assert_eq!(status, 400);
+
+    // the tests are not connected to a Kubernetes cluster
+    let (status, body) = readyz("/readyz/kubernetes").await;
+    assert_eq!(status, 503);
+    assert_eq!(
+        body["message"],
+        "Policy Server is not connected to Kubernetes"
+    );
+}
+
 // helper functions for certificate rotation test, which is a feature supported only on Linux
 #[cfg(target_os = "linux")]
 mod certificate_reload_helpers {